@@ -0,0 +1,190 @@
+//! The Fixed ACPI Description Table (FADT, signature `FACP`).
+//!
+//! The FADT locates the fixed-function power management hardware and carries
+//! assorted platform flags. Later ACPI revisions only ever append to it, so
+//! fields past the end of an older, shorter table read as zero.
+
+use super::{SDT_HEADER_LEN, read_u8, read_u16, read_u32, read_u64};
+
+/// A Generic Address Structure: a register in some address space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GenericAddress {
+    /// 0 for system memory, 1 for system I/O, and so on.
+    pub address_space: u8,
+    /// The register's width in bits.
+    pub bit_width: u8,
+    /// The register's offset in bits.
+    pub bit_offset: u8,
+    /// 1 to 4 for byte to qword accesses, or 0 if undefined.
+    pub access_size: u8,
+    /// The register's address.
+    pub address: u64,
+}
+
+impl GenericAddress {
+    /// The system memory address space.
+    pub const SYSTEM_MEMORY: u8 = 0;
+    /// The system I/O (port) address space.
+    pub const SYSTEM_IO: u8 = 1;
+
+    fn parse(bytes: &[u8], offset: usize) -> Option<Self> {
+        let gas = Self {
+            address_space: read_u8(bytes, offset)?,
+            bit_width: read_u8(bytes, offset + 1)?,
+            bit_offset: read_u8(bytes, offset + 2)?,
+            access_size: read_u8(bytes, offset + 3)?,
+            address: read_u64(bytes, offset + 4)?,
+        };
+
+        // An all-zero structure means the register isn't implemented.
+        (gas.address != 0).then_some(gas)
+    }
+}
+
+bitflags::bitflags! {
+    /// How Arm processors are brought up.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct ArmBootArch: u16 {
+        /// PSCI is implemented.
+        const PSCI_COMPLIANT = 1 << 0;
+        /// PSCI is called with `HVC` rather than `SMC`.
+        const PSCI_USE_HVC = 1 << 1;
+    }
+}
+
+/// The FADT fields of interest to the kernel.
+#[derive(Debug, Clone, Copy)]
+pub struct Fadt {
+    /// The physical address of the DSDT.
+    pub dsdt: u64,
+    /// The interrupt the SCI is wired to.
+    pub sci_interrupt: u16,
+    /// The PM1a event register block's I/O port.
+    pub pm1a_event_block: u32,
+    /// The PM1a control register block's I/O port.
+    pub pm1a_control_block: u32,
+    /// The ACPI PM timer's I/O port, or 0 if there is none.
+    pub pm_timer_block: u32,
+    /// The CMOS RAM index of the century, or 0 if the RTC doesn't keep one.
+    pub century: u8,
+    /// IA-PC boot architecture flags, e.g. whether there is an i8042.
+    pub iapc_boot_arch: u16,
+    /// Fixed feature flags.
+    pub flags: u32,
+    reset_reg: Option<GenericAddress>,
+    /// The value to write to the reset register to reset the system.
+    pub reset_value: u8,
+    /// Arm boot architecture flags.
+    pub arm_boot_arch: ArmBootArch,
+    /// The sleep control register, on hardware-reduced platforms.
+    pub sleep_control: Option<GenericAddress>,
+    /// The sleep status register, on hardware-reduced platforms.
+    pub sleep_status: Option<GenericAddress>,
+}
+
+impl Fadt {
+    /// The reset register is implemented.
+    pub const RESET_REG_SUP: u32 = 1 << 10;
+    /// There is no fixed-function hardware: everything is described by
+    /// other means, as on Arm servers.
+    pub const HW_REDUCED_ACPI: u32 = 1 << 20;
+
+    /// An ACPI 1.0 FADT, which has every field up to the flags.
+    const MIN_LEN: usize = 116;
+
+    /// Parses a FADT, header included.
+    pub fn parse(table: &[u8]) -> Option<Self> {
+        if table.len() < Self::MIN_LEN {
+            return None;
+        }
+
+        let x_dsdt = read_u64(table, 140).unwrap_or(0);
+
+        Some(Self {
+            dsdt: match x_dsdt {
+                0 => read_u32(table, SDT_HEADER_LEN + 4)? as u64,
+                addr => addr,
+            },
+            sci_interrupt: read_u16(table, 46)?,
+            pm1a_event_block: read_u32(table, 56)?,
+            pm1a_control_block: read_u32(table, 64)?,
+            pm_timer_block: read_u32(table, 76)?,
+            century: read_u8(table, 108)?,
+            iapc_boot_arch: read_u16(table, 109)?,
+            flags: read_u32(table, 112)?,
+            reset_reg: GenericAddress::parse(table, 116),
+            reset_value: read_u8(table, 128).unwrap_or(0),
+            arm_boot_arch: ArmBootArch::from_bits_truncate(read_u16(table, 129).unwrap_or(0)),
+            sleep_control: GenericAddress::parse(table, 244),
+            sleep_status: GenericAddress::parse(table, 256),
+        })
+    }
+
+    /// Returns whether the platform is hardware-reduced.
+    pub fn is_hardware_reduced(&self) -> bool {
+        self.flags & Self::HW_REDUCED_ACPI != 0
+    }
+
+    /// Returns the reset register, if the platform supports resetting
+    /// through it.
+    pub fn reset_register(&self) -> Option<GenericAddress> {
+        self.reset_reg
+            .filter(|_| self.flags & Self::RESET_REG_SUP != 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::table;
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn parses_fields() {
+        let mut body = vec![0; 276 - SDT_HEADER_LEN];
+        let mut put = |offset: usize, bytes: &[u8]| {
+            body[offset - SDT_HEADER_LEN..][..bytes.len()].copy_from_slice(bytes);
+        };
+
+        put(40, &0x1000u32.to_le_bytes());
+        put(108, &[0x32]);
+        put(
+            112,
+            &(Fadt::RESET_REG_SUP | Fadt::HW_REDUCED_ACPI).to_le_bytes(),
+        );
+        put(116, &[GenericAddress::SYSTEM_IO, 8, 0, 1]);
+        put(120, &0xcf9u64.to_le_bytes());
+        put(128, &[0x6]);
+        put(129, &3u16.to_le_bytes());
+        put(140, &0x2000u64.to_le_bytes());
+
+        let table = table(b"FACP", 6, &body);
+        let fadt = Fadt::parse(&table).unwrap();
+
+        assert_eq!(fadt.dsdt, 0x2000);
+        assert_eq!(fadt.century, 0x32);
+        assert!(fadt.is_hardware_reduced());
+        assert_eq!(fadt.reset_register().unwrap().address, 0xcf9);
+        assert_eq!(fadt.reset_value, 6);
+        assert_eq!(
+            fadt.arm_boot_arch,
+            ArmBootArch::PSCI_COMPLIANT | ArmBootArch::PSCI_USE_HVC
+        );
+        assert_eq!(fadt.sleep_control, None);
+    }
+
+    #[test]
+    fn acpi_1_table_is_short() {
+        let mut body = vec![0; Fadt::MIN_LEN - SDT_HEADER_LEN];
+        body[40 - SDT_HEADER_LEN..44 - SDT_HEADER_LEN].copy_from_slice(&0x1000u32.to_le_bytes());
+
+        let table = table(b"FACP", 1, &body);
+        let fadt = Fadt::parse(&table).unwrap();
+
+        assert_eq!(fadt.dsdt, 0x1000);
+        assert_eq!(fadt.reset_register(), None);
+        assert_eq!(fadt.arm_boot_arch, ArmBootArch::empty());
+
+        assert!(Fadt::parse(&table[..Fadt::MIN_LEN - 1]).is_none());
+    }
+}
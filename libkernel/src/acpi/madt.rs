@@ -0,0 +1,316 @@
+//! The Multiple APIC Description Table (MADT, signature `APIC`).
+//!
+//! Despite its name, the MADT describes every kind of interrupt controller:
+//! local APICs and I/O APICs on x86, and the GIC's CPU interfaces,
+//! distributor, redistributors and ITSes on Arm. There is one CPU interface
+//! entry per processor, so the MADT also enumerates the CPUs.
+
+use super::{SDT_HEADER_LEN, read_u8, read_u16, read_u32, read_u64};
+
+/// The MADT.
+#[derive(Debug, Clone, Copy)]
+pub struct Madt<'a> {
+    /// The 32-bit physical address of each CPU's local APIC (x86 only).
+    pub local_apic_address: u32,
+    /// `PCAT_COMPAT` and friends.
+    pub flags: u32,
+    entries: &'a [u8],
+}
+
+/// An interrupt controller structure within the MADT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MadtEntry {
+    /// A processor's local APIC.
+    LocalApic {
+        /// The processor's ACPI UID.
+        processor_uid: u8,
+        /// The local APIC ID.
+        apic_id: u8,
+        /// `ENABLED` and `ONLINE_CAPABLE`.
+        flags: u32,
+    },
+    /// An I/O APIC.
+    IoApic {
+        /// The I/O APIC ID.
+        id: u8,
+        /// The physical address of the I/O APIC's registers.
+        address: u32,
+        /// The first global system interrupt the I/O APIC handles.
+        gsi_base: u32,
+    },
+    /// A remapping of an ISA interrupt to a global system interrupt.
+    InterruptSourceOverride {
+        /// The ISA IRQ.
+        source: u8,
+        /// The global system interrupt it is wired to.
+        gsi: u32,
+        /// Polarity and trigger mode.
+        flags: u16,
+    },
+    /// A processor's x2APIC.
+    LocalX2Apic {
+        /// The x2APIC ID.
+        x2apic_id: u32,
+        /// As for [`MadtEntry::LocalApic`].
+        flags: u32,
+        /// The processor's ACPI UID.
+        processor_uid: u32,
+    },
+    /// A GIC CPU interface, one per processor.
+    Gicc {
+        /// The GICv2 CPU interface number.
+        cpu_interface_number: u32,
+        /// The processor's ACPI UID.
+        processor_uid: u32,
+        /// `ENABLED` and `ONLINE_CAPABLE`.
+        flags: u32,
+        /// The parking protocol version, or 0 if PSCI is used instead.
+        parking_version: u32,
+        /// The parking protocol mailbox address.
+        parked_address: u64,
+        /// The GICv2 CPU interface registers, if memory-mapped.
+        base_address: u64,
+        /// The processor's GICv3 redistributor, if not given by a
+        /// [`MadtEntry::Gicr`] range.
+        gicr_base_address: u64,
+        /// The processor's affinity, as in `MPIDR_EL1`.
+        mpidr: u64,
+    },
+    /// The GIC distributor.
+    Gicd {
+        /// The distributor's physical base address.
+        base_address: u64,
+        /// The first global system interrupt it handles.
+        gsi_base: u32,
+        /// The GIC version, or 0 if it must be probed.
+        version: u8,
+    },
+    /// A range of GICv3 redistributors.
+    Gicr {
+        /// The physical base address of the range.
+        base_address: u64,
+        /// The range's length.
+        length: u32,
+    },
+    /// A GICv3 Interrupt Translation Service.
+    GicIts {
+        /// The ITS's ID.
+        id: u32,
+        /// The ITS's physical base address.
+        base_address: u64,
+    },
+    /// Any other structure, which isn't parsed.
+    Other {
+        /// The structure type.
+        kind: u8,
+    },
+}
+
+impl MadtEntry {
+    /// The processor is usable.
+    pub const ENABLED: u32 = 1 << 0;
+    /// The processor isn't usable yet, but can be brought online later.
+    pub const ONLINE_CAPABLE: u32 = 1 << 1;
+    /// As [`MadtEntry::ONLINE_CAPABLE`], for GICC structures.
+    pub const GICC_ONLINE_CAPABLE: u32 = 1 << 3;
+
+    fn parse(kind: u8, s: &[u8]) -> Option<Self> {
+        Some(match kind {
+            0 => Self::LocalApic {
+                processor_uid: read_u8(s, 2)?,
+                apic_id: read_u8(s, 3)?,
+                flags: read_u32(s, 4)?,
+            },
+            1 => Self::IoApic {
+                id: read_u8(s, 2)?,
+                address: read_u32(s, 4)?,
+                gsi_base: read_u32(s, 8)?,
+            },
+            2 => Self::InterruptSourceOverride {
+                source: read_u8(s, 3)?,
+                gsi: read_u32(s, 4)?,
+                flags: read_u16(s, 8)?,
+            },
+            9 => Self::LocalX2Apic {
+                x2apic_id: read_u32(s, 4)?,
+                flags: read_u32(s, 8)?,
+                processor_uid: read_u32(s, 12)?,
+            },
+            0xb => Self::Gicc {
+                cpu_interface_number: read_u32(s, 4)?,
+                processor_uid: read_u32(s, 8)?,
+                flags: read_u32(s, 12)?,
+                parking_version: read_u32(s, 16)?,
+                parked_address: read_u64(s, 24)?,
+                base_address: read_u64(s, 32)?,
+                gicr_base_address: read_u64(s, 60)?,
+                mpidr: read_u64(s, 68)?,
+            },
+            0xc => Self::Gicd {
+                base_address: read_u64(s, 8)?,
+                gsi_base: read_u32(s, 16)?,
+                version: read_u8(s, 20)?,
+            },
+            0xe => Self::Gicr {
+                base_address: read_u64(s, 4)?,
+                length: read_u32(s, 12)?,
+            },
+            0xf => Self::GicIts {
+                id: read_u32(s, 4)?,
+                base_address: read_u64(s, 8)?,
+            },
+            kind => Self::Other { kind },
+        })
+    }
+
+    /// For processor entries, returns whether the processor can be used,
+    /// either now or once brought online.
+    pub fn is_usable_cpu(&self) -> bool {
+        match *self {
+            Self::LocalApic { flags, .. } | Self::LocalX2Apic { flags, .. } => {
+                flags & (Self::ENABLED | Self::ONLINE_CAPABLE) != 0
+            }
+            Self::Gicc { flags, .. } => flags & (Self::ENABLED | Self::GICC_ONLINE_CAPABLE) != 0,
+            _ => false,
+        }
+    }
+}
+
+impl<'a> Madt<'a> {
+    /// Parses a MADT, header included.
+    pub fn parse(table: &'a [u8]) -> Option<Self> {
+        Some(Self {
+            local_apic_address: read_u32(table, SDT_HEADER_LEN)?,
+            flags: read_u32(table, SDT_HEADER_LEN + 4)?,
+            entries: table.get(SDT_HEADER_LEN + 8..)?,
+        })
+    }
+
+    /// Iterates over the interrupt controller structures. A malformed
+    /// structure ends the iteration.
+    pub fn entries(&self) -> impl Iterator<Item = MadtEntry> + 'a {
+        let mut rest = self.entries;
+
+        core::iter::from_fn(move || {
+            let kind = read_u8(rest, 0)?;
+            let len = read_u8(rest, 1)? as usize;
+
+            if len < 2 || len > rest.len() {
+                return None;
+            }
+
+            let (entry, tail) = rest.split_at(len);
+            rest = tail;
+
+            MadtEntry::parse(kind, entry)
+        })
+    }
+
+    /// Iterates over the usable processors' entries.
+    pub fn cpus(&self) -> impl Iterator<Item = MadtEntry> + 'a {
+        self.entries().filter(MadtEntry::is_usable_cpu)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::table;
+    use super::*;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    fn gicc(uid: u32, flags: u32, mpidr: u64) -> Vec<u8> {
+        let mut s = vec![0; 80];
+
+        s[0] = 0xb;
+        s[1] = 80;
+        s[8..12].copy_from_slice(&uid.to_le_bytes());
+        s[12..16].copy_from_slice(&flags.to_le_bytes());
+        s[68..76].copy_from_slice(&mpidr.to_le_bytes());
+
+        s
+    }
+
+    #[test]
+    fn parses_gic_entries() {
+        let mut body = vec![0; 8];
+
+        body.extend(gicc(0, MadtEntry::ENABLED, 0x0));
+        body.extend(gicc(1, MadtEntry::ENABLED, 0x1));
+        body.extend(gicc(2, 0, 0x2));
+
+        let mut gicd = vec![0; 24];
+        gicd[0] = 0xc;
+        gicd[1] = 24;
+        gicd[8..16].copy_from_slice(&0x0800_0000u64.to_le_bytes());
+        gicd[20] = 3;
+        body.extend(gicd);
+
+        let table = table(b"APIC", 4, &body);
+        let madt = Madt::parse(&table).unwrap();
+
+        let mpidrs: Vec<u64> = madt
+            .cpus()
+            .map(|cpu| match cpu {
+                MadtEntry::Gicc { mpidr, .. } => mpidr,
+                _ => unreachable!(),
+            })
+            .collect();
+
+        assert_eq!(mpidrs, [0, 1]);
+        assert!(madt.entries().any(|e| e
+            == MadtEntry::Gicd {
+                base_address: 0x0800_0000,
+                gsi_base: 0,
+                version: 3,
+            }));
+    }
+
+    #[test]
+    fn parses_apic_entries() {
+        let mut body = vec![0; 8];
+        body[..4].copy_from_slice(&0xfee0_0000u32.to_le_bytes());
+
+        body.extend([0, 8, 0, 0, 1, 0, 0, 0]);
+        body.extend([1, 12, 2, 0, 0x00, 0x00, 0xc0, 0xfe, 0, 0, 0, 0]);
+        body.extend([2, 10, 0, 0, 2, 0, 0, 0, 0, 0]);
+
+        let table = table(b"APIC", 4, &body);
+        let madt = Madt::parse(&table).unwrap();
+
+        assert_eq!(madt.local_apic_address, 0xfee0_0000);
+        assert_eq!(
+            madt.entries().collect::<Vec<_>>(),
+            [
+                MadtEntry::LocalApic {
+                    processor_uid: 0,
+                    apic_id: 0,
+                    flags: 1,
+                },
+                MadtEntry::IoApic {
+                    id: 2,
+                    address: 0xfec0_0000,
+                    gsi_base: 0,
+                },
+                MadtEntry::InterruptSourceOverride {
+                    source: 0,
+                    gsi: 2,
+                    flags: 0,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn malformed_entry_ends_iteration() {
+        let mut body = vec![0; 8];
+
+        body.extend([0, 8, 0, 0, 1, 0, 0, 0]);
+        // A zero length would otherwise loop forever.
+        body.extend([0, 0]);
+
+        let table = table(b"APIC", 4, &body);
+
+        assert_eq!(Madt::parse(&table).unwrap().entries().count(), 1);
+    }
+}
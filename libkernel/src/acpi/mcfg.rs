@@ -0,0 +1,93 @@
+//! The PCI Express memory-mapped configuration table (signature `MCFG`).
+//!
+//! Each entry gives the Enhanced Configuration Access Mechanism (ECAM) window
+//! of one PCI segment: 4KiB of configuration space per function, laid out by
+//! bus, device and function.
+
+use super::{SDT_HEADER_LEN, read_u8, read_u16, read_u64};
+
+/// An ECAM window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct McfgEntry {
+    /// The physical address of the configuration space of bus 0, whether or
+    /// not bus 0 is in the window.
+    pub base_address: u64,
+    /// The PCI segment group.
+    pub segment: u16,
+    /// The first bus decoded.
+    pub start_bus: u8,
+    /// The last bus decoded.
+    pub end_bus: u8,
+}
+
+impl McfgEntry {
+    const LEN: usize = 16;
+    const BUS_SIZE: u64 = 1 << 20;
+
+    /// Returns the physical address and size of the part of the window
+    /// covering `start_bus..=end_bus`.
+    pub fn region(&self) -> (u64, u64) {
+        let buses = (self.end_bus as u64 + 1).saturating_sub(self.start_bus as u64);
+
+        (
+            self.base_address + self.start_bus as u64 * Self::BUS_SIZE,
+            buses * Self::BUS_SIZE,
+        )
+    }
+}
+
+/// The MCFG.
+#[derive(Debug, Clone, Copy)]
+pub struct Mcfg<'a> {
+    entries: &'a [u8],
+}
+
+impl<'a> Mcfg<'a> {
+    /// Parses an MCFG, header included.
+    pub fn parse(table: &'a [u8]) -> Option<Self> {
+        // The entries follow eight reserved bytes.
+        Some(Self {
+            entries: table.get(SDT_HEADER_LEN + 8..)?,
+        })
+    }
+
+    /// Iterates over the ECAM windows.
+    pub fn entries(&self) -> impl Iterator<Item = McfgEntry> + 'a {
+        let (entries, _) = self.entries.as_chunks::<{ McfgEntry::LEN }>();
+
+        entries.iter().filter_map(|entry| {
+            Some(McfgEntry {
+                base_address: read_u64(entry, 0)?,
+                segment: read_u16(entry, 8)?,
+                start_bus: read_u8(entry, 10)?,
+                end_bus: read_u8(entry, 11)?,
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::table;
+    use super::*;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn parses_entries() {
+        let mut body = vec![0; 8];
+
+        body.extend(0x40_1000_0000u64.to_le_bytes());
+        body.extend([0, 0, 0, 0xff, 0, 0, 0, 0]);
+        body.extend(0x3000_0000u64.to_le_bytes());
+        body.extend([1, 0, 0x10, 0x1f, 0, 0, 0, 0]);
+
+        let table = table(b"MCFG", 1, &body);
+        let entries: Vec<_> = Mcfg::parse(&table).unwrap().entries().collect();
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].region(), (0x40_1000_0000, 256 << 20));
+        assert_eq!(entries[1].segment, 1);
+        assert_eq!(entries[1].region(), (0x3000_0000 + (0x10 << 20), 16 << 20));
+    }
+}
@@ -0,0 +1,393 @@
+//! ACPI table discovery and parsing.
+//!
+//! Starting from the Root System Description Pointer (RSDP), [`AcpiTables`]
+//! walks the XSDT (or, on ACPI 1.0 firmware, the RSDT) and takes a validated
+//! copy of every table it lists. Typed views are provided for the tables the
+//! kernel uses:
+//!
+//! - [`Madt`]: interrupt controllers, and so the CPUs present.
+//! - [`Fadt`]: power management registers, boot architecture flags and the
+//!   CMOS century register.
+//! - [`Mcfg`]: PCI Express ECAM regions.
+//!
+//! Tables are copied out of firmware memory, rather than parsed in place, as
+//! they are often only reachable through device mappings, on which unaligned
+//! accesses fault.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use thiserror::Error;
+
+mod fadt;
+mod madt;
+mod mcfg;
+
+pub use fadt::{ArmBootArch, Fadt, GenericAddress};
+pub use madt::{Madt, MadtEntry};
+pub use mcfg::{Mcfg, McfgEntry};
+
+/// Errors from locating or validating ACPI tables.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcpiError {
+    /// A table didn't carry the expected signature.
+    #[error("Bad ACPI table signature")]
+    BadSignature,
+
+    /// A table's bytes didn't sum to zero.
+    #[error("Bad ACPI table checksum")]
+    BadChecksum,
+
+    /// A table was shorter than its fixed fields, or than its header claims.
+    #[error("ACPI table truncated")]
+    Truncated,
+
+    /// Firmware memory couldn't be read.
+    #[error("Could not read ACPI table memory")]
+    Unreadable,
+}
+
+/// Access to the physical memory holding the ACPI tables.
+pub trait AcpiHandler {
+    /// Fills `buf` with the physical memory starting at `pa`.
+    fn read_phys(&self, pa: u64, buf: &mut [u8]) -> Result<(), AcpiError>;
+}
+
+pub(crate) fn read_u8(bytes: &[u8], offset: usize) -> Option<u8> {
+    bytes.get(offset).copied()
+}
+
+pub(crate) fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        bytes.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+pub(crate) fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        bytes.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+pub(crate) fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        bytes.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+fn checksum_ok(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) == 0
+}
+
+/// The length of the header shared by all system description tables.
+pub const SDT_HEADER_LEN: usize = 36;
+
+/// The Root System Description Pointer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rsdp {
+    /// 0 for ACPI 1.0, 2 for later revisions.
+    pub revision: u8,
+    /// Physical address of the RSDT.
+    pub rsdt_address: u32,
+    /// Physical address of the XSDT, from ACPI 2.0.
+    pub xsdt_address: Option<u64>,
+}
+
+impl Rsdp {
+    const SIGNATURE: &'static [u8; 8] = b"RSD PTR ";
+    const V1_LEN: usize = 20;
+    /// The length of an ACPI 2.0 RSDP.
+    pub const LEN: usize = 36;
+
+    /// Parses and validates an RSDP. `bytes` may be [`Rsdp::LEN`] long even
+    /// for an ACPI 1.0 RSDP.
+    pub fn parse(bytes: &[u8]) -> Result<Self, AcpiError> {
+        let v1 = bytes.get(..Self::V1_LEN).ok_or(AcpiError::Truncated)?;
+
+        if &v1[..8] != Self::SIGNATURE {
+            return Err(AcpiError::BadSignature);
+        }
+
+        if !checksum_ok(v1) {
+            return Err(AcpiError::BadChecksum);
+        }
+
+        let revision = v1[15];
+        let rsdt_address = read_u32(v1, 16).ok_or(AcpiError::Truncated)?;
+
+        if revision < 2 {
+            return Ok(Self {
+                revision,
+                rsdt_address,
+                xsdt_address: None,
+            });
+        }
+
+        let len = read_u32(bytes, 20).ok_or(AcpiError::Truncated)? as usize;
+        let v2 = bytes
+            .get(..len.max(Self::LEN))
+            .ok_or(AcpiError::Truncated)?;
+
+        if !checksum_ok(v2) {
+            return Err(AcpiError::BadChecksum);
+        }
+
+        Ok(Self {
+            revision,
+            rsdt_address,
+            xsdt_address: read_u64(v2, 24).filter(|&addr| addr != 0),
+        })
+    }
+}
+
+/// Reads and validates the table at `pa`, checking its signature if one is
+/// given.
+fn read_table(
+    handler: &impl AcpiHandler,
+    pa: u64,
+    signature: Option<&[u8; 4]>,
+) -> Result<Vec<u8>, AcpiError> {
+    let mut header = [0; SDT_HEADER_LEN];
+    handler.read_phys(pa, &mut header)?;
+
+    if signature.is_some_and(|sig| &header[..4] != sig) {
+        return Err(AcpiError::BadSignature);
+    }
+
+    let len = read_u32(&header, 4).ok_or(AcpiError::Truncated)? as usize;
+
+    if len < SDT_HEADER_LEN {
+        return Err(AcpiError::Truncated);
+    }
+
+    let mut table = vec![0; len];
+    handler.read_phys(pa, &mut table)?;
+
+    if !checksum_ok(&table) {
+        return Err(AcpiError::BadChecksum);
+    }
+
+    Ok(table)
+}
+
+/// The system's ACPI tables.
+#[derive(Debug)]
+pub struct AcpiTables {
+    rsdp: Rsdp,
+    tables: Vec<Vec<u8>>,
+}
+
+impl AcpiTables {
+    /// Reads the tables reachable from the RSDP at `rsdp_pa`.
+    ///
+    /// Tables which fail validation are skipped, so that one bad table
+    /// doesn't hide the rest; only a bad RSDP or root table is an error.
+    pub fn from_rsdp(handler: &impl AcpiHandler, rsdp_pa: u64) -> Result<Self, AcpiError> {
+        let mut rsdp = [0; Rsdp::LEN];
+        handler.read_phys(rsdp_pa, &mut rsdp)?;
+        let rsdp = Rsdp::parse(&rsdp)?;
+
+        let (root, entry_size) = match rsdp.xsdt_address {
+            Some(xsdt) => (read_table(handler, xsdt, Some(b"XSDT"))?, 8),
+            None => (
+                read_table(handler, rsdp.rsdt_address as u64, Some(b"RSDT"))?,
+                4,
+            ),
+        };
+
+        let tables = root[SDT_HEADER_LEN..]
+            .chunks_exact(entry_size)
+            .filter_map(|entry| match entry_size {
+                8 => read_u64(entry, 0),
+                _ => read_u32(entry, 0).map(u64::from),
+            })
+            .filter_map(|pa| read_table(handler, pa, None).ok())
+            .collect();
+
+        Ok(Self { rsdp, tables })
+    }
+
+    /// Returns the RSDP the tables were found through.
+    pub fn rsdp(&self) -> &Rsdp {
+        &self.rsdp
+    }
+
+    /// Returns the signatures of all the tables found.
+    pub fn signatures(&self) -> impl Iterator<Item = &[u8]> {
+        self.tables.iter().map(|table| &table[..4])
+    }
+
+    /// Returns the first table with the given signature, header included.
+    pub fn find(&self, signature: &[u8; 4]) -> Option<&[u8]> {
+        self.tables
+            .iter()
+            .find(|table| &table[..4] == signature)
+            .map(Vec::as_slice)
+    }
+
+    /// Returns the Multiple APIC Description Table.
+    pub fn madt(&self) -> Option<Madt<'_>> {
+        Madt::parse(self.find(b"APIC")?)
+    }
+
+    /// Returns the Fixed ACPI Description Table.
+    pub fn fadt(&self) -> Option<Fadt> {
+        Fadt::parse(self.find(b"FACP")?)
+    }
+
+    /// Returns the PCI Express memory-mapped configuration table.
+    pub fn mcfg(&self) -> Option<Mcfg<'_>> {
+        Mcfg::parse(self.find(b"MCFG")?)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use alloc::collections::btree_map::BTreeMap;
+
+    /// Physical memory made of separately placed byte blobs.
+    #[derive(Default)]
+    pub(crate) struct FakeMemory {
+        blobs: BTreeMap<u64, Vec<u8>>,
+    }
+
+    impl FakeMemory {
+        pub(crate) fn place(&mut self, pa: u64, bytes: Vec<u8>) {
+            self.blobs.insert(pa, bytes);
+        }
+    }
+
+    impl AcpiHandler for FakeMemory {
+        fn read_phys(&self, pa: u64, buf: &mut [u8]) -> Result<(), AcpiError> {
+            let (&base, blob) = self
+                .blobs
+                .range(..=pa)
+                .next_back()
+                .ok_or(AcpiError::Unreadable)?;
+            let start = (pa - base) as usize;
+
+            buf.copy_from_slice(
+                blob.get(start..start + buf.len())
+                    .ok_or(AcpiError::Unreadable)?,
+            );
+
+            Ok(())
+        }
+    }
+
+    fn fix_checksum(bytes: &mut [u8], at: usize) {
+        bytes[at] = 0;
+        bytes[at] = 0u8.wrapping_sub(bytes.iter().fold(0u8, |s, &b| s.wrapping_add(b)));
+    }
+
+    /// Builds a table with the given signature and body.
+    pub(crate) fn table(signature: &[u8; 4], revision: u8, body: &[u8]) -> Vec<u8> {
+        let mut table = vec![0; SDT_HEADER_LEN];
+
+        table[..4].copy_from_slice(signature);
+        table[4..8].copy_from_slice(&((SDT_HEADER_LEN + body.len()) as u32).to_le_bytes());
+        table[8] = revision;
+        table[10..16].copy_from_slice(b"MOSS  ");
+        table.extend_from_slice(body);
+
+        fix_checksum(&mut table, 9);
+
+        table
+    }
+
+    fn rsdp(revision: u8, rsdt: u32, xsdt: u64) -> Vec<u8> {
+        let mut rsdp = vec![0; Rsdp::LEN];
+
+        rsdp[..8].copy_from_slice(b"RSD PTR ");
+        rsdp[9..15].copy_from_slice(b"MOSS  ");
+        rsdp[15] = revision;
+        rsdp[16..20].copy_from_slice(&rsdt.to_le_bytes());
+        rsdp[20..24].copy_from_slice(&(Rsdp::LEN as u32).to_le_bytes());
+        rsdp[24..32].copy_from_slice(&xsdt.to_le_bytes());
+
+        fix_checksum(&mut rsdp[..20], 8);
+        fix_checksum(&mut rsdp, 32);
+
+        rsdp
+    }
+
+    #[test]
+    fn rsdp_validation() {
+        let good = rsdp(2, 0x1000, 0x2000);
+
+        assert_eq!(
+            Rsdp::parse(&good),
+            Ok(Rsdp {
+                revision: 2,
+                rsdt_address: 0x1000,
+                xsdt_address: Some(0x2000),
+            })
+        );
+
+        let mut bad_sig = good.clone();
+        bad_sig[0] = b'X';
+        assert_eq!(Rsdp::parse(&bad_sig), Err(AcpiError::BadSignature));
+
+        let mut bad_ext = good.clone();
+        bad_ext[30] ^= 1;
+        assert_eq!(Rsdp::parse(&bad_ext), Err(AcpiError::BadChecksum));
+
+        // ACPI 1.0 has no extended checksum or XSDT.
+        let v1 = rsdp(0, 0x1000, 0);
+        assert_eq!(Rsdp::parse(&v1).unwrap().xsdt_address, None);
+    }
+
+    #[test]
+    fn walks_xsdt() {
+        let mut mem = FakeMemory::default();
+
+        let xsdt_body: Vec<u8> = [0x3000u64, 0x4000, 0x5000]
+            .iter()
+            .flat_map(|pa| pa.to_le_bytes())
+            .collect();
+
+        let mut corrupt = table(b"BAD!", 1, &[1, 2, 3]);
+        corrupt[SDT_HEADER_LEN] ^= 0xff;
+
+        mem.place(0x1000, rsdp(2, 0, 0x2000));
+        mem.place(0x2000, table(b"XSDT", 1, &xsdt_body));
+        mem.place(0x3000, table(b"TEST", 1, &[0xaa; 4]));
+        mem.place(0x4000, corrupt);
+        mem.place(0x5000, table(b"OTHR", 1, &[]));
+
+        let tables = AcpiTables::from_rsdp(&mem, 0x1000).unwrap();
+
+        assert_eq!(
+            tables.signatures().collect::<Vec<_>>(),
+            [b"TEST".as_slice(), b"OTHR".as_slice()]
+        );
+        assert_eq!(&tables.find(b"TEST").unwrap()[SDT_HEADER_LEN..], &[0xaa; 4]);
+        assert!(tables.find(b"BAD!").is_none());
+    }
+
+    #[test]
+    fn walks_rsdt_without_xsdt() {
+        let mut mem = FakeMemory::default();
+
+        mem.place(0x1000, rsdp(0, 0x2000, 0));
+        mem.place(0x2000, table(b"RSDT", 1, &0x3000u32.to_le_bytes()));
+        mem.place(0x3000, table(b"TEST", 1, &[]));
+
+        let tables = AcpiTables::from_rsdp(&mem, 0x1000).unwrap();
+
+        assert!(tables.find(b"TEST").is_some());
+    }
+
+    #[test]
+    fn bad_root_table_is_an_error() {
+        let mut mem = FakeMemory::default();
+
+        mem.place(0x1000, rsdp(2, 0, 0x2000));
+        mem.place(0x2000, table(b"RSDT", 1, &[]));
+
+        assert_eq!(
+            AcpiTables::from_rsdp(&mem, 0x1000).unwrap_err(),
+            AcpiError::BadSignature
+        );
+    }
+}
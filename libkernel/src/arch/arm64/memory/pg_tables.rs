@@ -317,6 +317,110 @@ where
     }
 }
 
+/// The bits of a block or page descriptor that hold the output address and
+/// the descriptor type. Everything else is a memory attribute that is shared
+/// between the L2 block and L3 page encodings.
+const DESC_OA_AND_TYPE_MASK: u64 = 0x0000_ffff_ffff_f003;
+
+/// Splits every 2MiB L2 block mapping that intersects `region` into a freshly
+/// allocated L3 table mapping the same physical memory with 4KiB pages.
+///
+/// The resulting page descriptors carry exactly the same memory attributes
+/// and permissions as the block they replace, so the split is invisible to
+/// anything other than the TLB. This is required before any operation which
+/// only covers part of a block (e.g. a partial `munmap` or `mprotect`), since
+/// the page-table walker operates solely on L3 descriptors.
+///
+/// `on_split` is invoked with the physical region backing each block that was
+/// split, allowing the caller to fix up any allocator metadata.
+///
+/// # Errors
+///
+/// - `MapError::VirtNotAligned`: `region` is not page-aligned.
+/// - `MapError::NotL3Mapped`: `region` is covered by a 1GiB L1 block, which is
+///   never created for user address spaces.
+/// - Any error returned by the allocator when allocating a new L3 table.
+pub fn split_block_mappings<PA, PM>(
+    l0_table: TPA<PgTableArray<L0Table>>,
+    region: VirtMemoryRegion,
+    ctx: &mut MappingContext<PA, PM>,
+    mut on_split: impl FnMut(PhysMemoryRegion),
+) -> Result<()>
+where
+    PA: PageAllocator,
+    PM: PageTableMapper,
+{
+    if !region.is_page_aligned() {
+        Err(MapError::VirtNotAligned)?;
+    }
+
+    let block_size = 1 << <L2Table as PgTable>::Descriptor::MAP_SHIFT;
+    let mut va = region.start_address().align(block_size);
+
+    while va < region.end_address() {
+        let l2 = unsafe {
+            let l0_desc = ctx
+                .mapper
+                .with_page_table(l0_table, |tbl| L0Table::from_ptr(tbl).get_desc(va))?;
+
+            match l0_desc.next_table_address() {
+                Some(l1) => {
+                    let l1_desc = ctx
+                        .mapper
+                        .with_page_table(l1, |tbl| L1Table::from_ptr(tbl).get_desc(va))?;
+
+                    if l1_desc.next_table_address().is_none() && l1_desc.is_valid() {
+                        Err(MapError::NotL3Mapped)?;
+                    }
+
+                    l1_desc.next_table_address()
+                }
+                None => None,
+            }
+        };
+
+        if let Some(l2) = l2 {
+            let desc = unsafe {
+                ctx.mapper
+                    .with_page_table(l2, |tbl| L2Table::from_ptr(tbl).get_desc(va))?
+            };
+
+            if desc.next_table_address().is_none()
+                && let Some(block_pa) = desc.mapped_address()
+                && desc.permissions().is_some()
+            {
+                let attrs = desc.as_raw() & !DESC_OA_AND_TYPE_MASK;
+                let l3 = ctx.allocator.allocate_page_table::<L3Table>()?;
+
+                unsafe {
+                    ctx.mapper.with_page_table(l3, |tbl| {
+                        let ptr = L3Table::from_ptr(tbl).to_raw_ptr();
+
+                        for i in 0..<L3Table as PgTable>::DESCRIPTORS_PER_PAGE {
+                            let page_pa = (block_pa.value() + i * PAGE_SIZE) as u64;
+                            ptr.add(i).write_volatile(attrs | page_pa | 0b11);
+                        }
+                    })?;
+
+                    // Break-before-make: the block must be invalidated before
+                    // the table descriptor is installed in its place.
+                    ctx.mapper.with_page_table(l2, |tbl| {
+                        let tbl = L2Table::from_ptr(tbl);
+                        tbl.set_desc(va, L2Descriptor::invalid(), ctx.invalidator);
+                        tbl.set_desc(va, L2Descriptor::new_next_table(l3), ctx.invalidator);
+                    })?;
+                }
+
+                on_split(PhysMemoryRegion::new(block_pa, block_size));
+            }
+        }
+
+        va = va.add_bytes(block_size);
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 #[allow(missing_docs)]
 pub mod tests {
@@ -931,4 +1035,70 @@ pub mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_split_2mb_block() -> Result<()> {
+        let mut harness = TestHarness::new(4);
+
+        let block_size = 1 << 21; // 2MiB
+        let phys = PhysMemoryRegion::new(PA::from_value(0x4000_0000), block_size);
+        let virt = VirtMemoryRegion::new(VA::from_value(0x2000_0000), block_size);
+
+        map_range(
+            harness.inner.root_table,
+            MapAttributes {
+                phys,
+                virt,
+                mem_type: MemoryType::Normal,
+                perms: PtePermissions::rw(true),
+            },
+            &mut harness.create_map_ctx(),
+        )?;
+
+        // Split using a region which only covers a single page in the middle
+        // of the block.
+        let mut split_regions = std::vec::Vec::new();
+        split_block_mappings(
+            harness.inner.root_table,
+            VirtMemoryRegion::new(virt.start_address().add_pages(10), PAGE_SIZE),
+            &mut harness.create_map_ctx(),
+            |region| split_regions.push(region),
+        )?;
+
+        assert_eq!(split_regions, [phys]);
+
+        // One extra page for the new L3 table.
+        assert_eq!(harness.inner.allocator.pages_allocated, 4);
+
+        let mut pages_seen = 0;
+        walk_and_modify_region(
+            harness.inner.root_table,
+            virt,
+            &mut harness.inner.create_walk_ctx(),
+            &mut |va: VA, desc: L3Descriptor| {
+                let offset = va.value() - virt.start_address().value();
+
+                assert_eq!(desc.permissions(), Some(PtePermissions::rw(true)));
+                assert_eq!(
+                    desc.mapped_address(),
+                    Some(phys.start_address().add_bytes(offset))
+                );
+
+                pages_seen += 1;
+                desc
+            },
+        )?;
+
+        assert_eq!(pages_seen, 512);
+
+        // Splitting again is a no-op.
+        split_block_mappings(
+            harness.inner.root_table,
+            virt,
+            &mut harness.create_map_ctx(),
+            |_| panic!("Block should have already been split"),
+        )?;
+
+        Ok(())
+    }
 }
@@ -2,18 +2,20 @@
 
 use super::{
     pg_descriptors::L3Descriptor,
-    pg_tables::{L0Table, L3Table},
+    pg_tables::{L0Table, L1Table, L2Table, L3Table},
 };
 use crate::{
     error::{MapError, Result},
     memory::{
         PAGE_SIZE,
-        address::{TPA, VA},
+        address::{PA, TPA, VA},
         paging::{
-            NullTlbInvalidator, PageTableEntry, PageTableMapper, PgTable, PgTableArray,
-            walk::{RecursiveWalker, WalkContext},
+            NullTlbInvalidator, PaMapper, PageTableEntry, PageTableMapper, PgTable, PgTableArray,
+            TableMapper,
+            permissions::PtePermissions,
+            walk::{RecursiveWalker, Translator, WalkContext},
         },
-        region::VirtMemoryRegion,
+        region::{PhysMemoryRegion, VirtMemoryRegion},
     },
 };
 
@@ -111,6 +113,97 @@ pub fn get_pte<PM: PageTableMapper>(
     Ok(descriptor)
 }
 
+/// Returns `true` if the L2 slot covering `va` holds neither a block nor a
+/// next-level table. In that case a 2MiB block can be mapped at `va` without
+/// disturbing any existing mapping.
+pub fn l2_slot_is_free<PM: PageTableMapper>(
+    l0_table: TPA<PgTableArray<L0Table>>,
+    va: VA,
+    mapper: &mut PM,
+) -> Result<bool> {
+    unsafe {
+        let l0_desc =
+            mapper.with_page_table(l0_table, |tbl| L0Table::from_ptr(tbl).get_desc(va))?;
+
+        let Some(l1) = l0_desc.next_table_address() else {
+            return Ok(true);
+        };
+
+        let l1_desc = mapper.with_page_table(l1, |tbl| L1Table::from_ptr(tbl).get_desc(va))?;
+
+        match l1_desc.next_table_address() {
+            Some(l2) => Ok(!mapper
+                .with_page_table(l2, |tbl| L2Table::from_ptr(tbl).get_desc(va))?
+                .is_valid()),
+            None => Ok(!l1_desc.is_valid()),
+        }
+    }
+}
+
+impl Translator for L0Table {
+    fn translate<PM: PageTableMapper>(
+        table_pa: TPA<PgTableArray<Self>>,
+        va: VA,
+        ctx: &mut WalkContext<PM>,
+    ) -> Result<Option<(PA, usize, PtePermissions)>> {
+        let desc = unsafe {
+            ctx.mapper
+                .with_page_table(table_pa, |pgtable| Self::from_ptr(pgtable).get_desc(va))?
+        };
+
+        match desc.next_table_address() {
+            Some(next_pa) => L1Table::translate(next_pa, va, ctx),
+            None if desc.is_valid() => Err(MapError::InvalidDescriptor.into()),
+            None => Ok(None),
+        }
+    }
+}
+
+impl Translator for L3Table {
+    fn translate<PM: PageTableMapper>(
+        table_pa: TPA<PgTableArray<Self>>,
+        va: VA,
+        ctx: &mut WalkContext<PM>,
+    ) -> Result<Option<(PA, usize, PtePermissions)>> {
+        let desc = unsafe {
+            ctx.mapper
+                .with_page_table(table_pa, |pgtable| Self::from_ptr(pgtable).get_desc(va))?
+        };
+
+        match (desc.mapped_address(), desc.permissions()) {
+            (Some(pa), Some(perms)) => Ok(Some((pa, PAGE_SIZE, perms))),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Translates the VA into the physical region of the page or block that maps
+/// it, plus the offset of the VA within that region and its permissions.
+///
+/// Unlike [`get_pte`], this function also handles VAs which are covered by a
+/// 2MiB or 1GiB block mapping.
+pub fn translate<PM: PageTableMapper>(
+    l0_table: TPA<PgTableArray<L0Table>>,
+    va: VA,
+    mapper: &mut PM,
+) -> Result<Option<(PhysMemoryRegion, usize, PtePermissions)>> {
+    let mut walk_ctx = WalkContext {
+        mapper,
+        // Safe to not invalidate the TLB, as we are not modifying any PTEs.
+        invalidator: &NullTlbInvalidator {},
+    };
+
+    if let Some((pa, blk_sz, perms)) = L0Table::translate(l0_table, va, &mut walk_ctx)? {
+        debug_assert!(blk_sz.is_power_of_two());
+
+        let offset = va.value() & (blk_sz - 1);
+
+        Ok(Some((PhysMemoryRegion::new(pa, blk_sz), offset, perms)))
+    } else {
+        Ok(None)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -382,4 +475,68 @@ mod tests {
             Err(KernelError::MappingError(MapError::VirtNotAligned))
         ));
     }
+
+    #[test]
+    fn translate_page_and_block() {
+        let mut harness = TestHarness::new(10);
+        let page_va = VA::from_value(0x1_0000_0000);
+        let block_va = VA::from_value(0x1_0020_0000);
+        let block_size = 1 << 21;
+
+        harness
+            .map_4k_pages(0x8_0000, page_va.value(), 1, PtePermissions::ro(true))
+            .unwrap();
+
+        crate::arch::arm64::memory::pg_tables::map_range(
+            harness.inner.root_table,
+            crate::arch::arm64::memory::pg_tables::MapAttributes {
+                phys: PhysMemoryRegion::new(PA::from_value(0x4000_0000), block_size),
+                virt: VirtMemoryRegion::new(block_va, block_size),
+                mem_type: MemoryType::Normal,
+                perms: PtePermissions::rw(true),
+            },
+            &mut harness.create_map_ctx(),
+        )
+        .unwrap();
+
+        let (region, offset, perms) = translate(
+            harness.inner.root_table,
+            page_va.add_bytes(0x10),
+            &mut harness.inner.mapper,
+        )
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(
+            region,
+            PhysMemoryRegion::new(PA::from_value(0x8_0000), PAGE_SIZE)
+        );
+        assert_eq!(offset, 0x10);
+        assert_eq!(perms, PtePermissions::ro(true));
+
+        let (region, offset, perms) = translate(
+            harness.inner.root_table,
+            block_va.add_bytes(0x1234),
+            &mut harness.inner.mapper,
+        )
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(
+            region,
+            PhysMemoryRegion::new(PA::from_value(0x4000_0000), block_size)
+        );
+        assert_eq!(offset, 0x1234);
+        assert_eq!(perms, PtePermissions::rw(true));
+
+        assert!(
+            translate(
+                harness.inner.root_table,
+                VA::from_value(0x2_0000_0000),
+                &mut harness.inner.mapper,
+            )
+            .unwrap()
+            .is_none()
+        );
+    }
 }
@@ -8,3 +8,27 @@ pub struct CharDevDescriptor {
     /// The minor device number (identifies the device instance).
     pub minor: u64,
 }
+
+impl CharDevDescriptor {
+    /// Encodes the pair as a `dev_t`, as Linux does.
+    pub fn to_dev_t(self) -> u64 {
+        (self.minor & 0xff)
+            | ((self.major & 0xfff) << 8)
+            | ((self.minor & !0xff) << 12)
+            | ((self.major & !0xfff) << 32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CharDevDescriptor;
+
+    #[test]
+    fn test_dev_t() {
+        let dev = |major, minor| CharDevDescriptor { major, minor }.to_dev_t();
+
+        assert_eq!(dev(1, 3), 0x103);
+        assert_eq!(dev(259, 0), 0x10300);
+        assert_eq!(dev(0x1234, 0x56789), 0x0000_1000_5672_3489);
+    }
+}
@@ -149,6 +149,10 @@ pub enum FsError {
     #[error("Too many open files")]
     TooManyFiles,
 
+    /// Too many files open across the system.
+    #[error("Too many open files in system")]
+    TooManyFilesInSystem,
+
     /// The device could not be found.
     #[error("The device could not be found")]
     NoDevice,
@@ -160,6 +164,14 @@ pub enum FsError {
     /// Attempted to rename across devices.
     #[error("Attempted to rename from cross device")]
     CrossDevice,
+
+    /// There's no space left on the device.
+    #[error("No space left on device")]
+    NoSpace,
+
+    /// The file would be larger than the filesystem allows.
+    #[error("File too large")]
+    FileTooLarge,
 }
 
 /// Errors that occur when loading or parsing an executable.
@@ -293,6 +305,50 @@ pub enum KernelError {
     #[error("Not a socket")]
     NotASocket,
 
+    /// Connection refused.
+    #[error("Connection refused")]
+    ConnectionRefused,
+
+    /// Network is unreachable.
+    #[error("Network is unreachable")]
+    NetworkUnreachable,
+
+    /// Transport endpoint is already connected.
+    #[error("Transport endpoint is already connected")]
+    AlreadyConnected,
+
+    /// Transport endpoint is not connected.
+    #[error("Transport endpoint is not connected")]
+    NotConnected,
+
+    /// Message too long.
+    #[error("Message too long")]
+    MessageTooLong,
+
+    /// Operation now in progress.
+    #[error("Operation now in progress")]
+    InProgress,
+
+    /// Operation already in progress.
+    #[error("Operation already in progress")]
+    AlreadyInProgress,
+
+    /// Protocol not available, e.g. an unknown socket option.
+    #[error("Protocol not available")]
+    NoProtocolOption,
+
+    /// No buffer space available.
+    #[error("No buffer space available")]
+    NoBufferSpace,
+
+    /// Address already in use.
+    #[error("Address already in use")]
+    AddressInUse,
+
+    /// Cannot assign requested address.
+    #[error("Cannot assign requested address")]
+    AddressNotAvailable,
+
     /// Other error with a static description.
     #[error("{0}")]
     Other(&'static str),
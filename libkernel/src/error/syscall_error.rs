@@ -47,8 +47,19 @@ pub const ENOSYS: isize = -38;
 pub const ENOTEMPTY: isize = -39;
 pub const ELOOP: isize = -40;
 pub const EAFNOSUPPORT: isize = -97;
+pub const EADDRINUSE: isize = -98;
+pub const EADDRNOTAVAIL: isize = -99;
 pub const EOPNOTSUPP: isize = -95;
+pub const EMSGSIZE: isize = -90;
+pub const ENOPROTOOPT: isize = -92;
+pub const ENETUNREACH: isize = -101;
+pub const ENOBUFS: isize = -105;
+pub const EISCONN: isize = -106;
+pub const ENOTCONN: isize = -107;
 pub const ETIMEDOUT: isize = -110;
+pub const ECONNREFUSED: isize = -111;
+pub const EALREADY: isize = -114;
+pub const EINPROGRESS: isize = -115;
 
 pub fn kern_err_to_syscall(err: KernelError) -> isize {
     match err {
@@ -69,8 +80,12 @@ pub fn kern_err_to_syscall(err: KernelError) -> isize {
         KernelError::Fs(FsError::InvalidInput) => EINVAL, // TODO: Is this right?
         KernelError::Fs(FsError::PermissionDenied) => EACCES,
         KernelError::Fs(FsError::TooManyFiles) => EMFILE,
+        KernelError::Fs(FsError::TooManyFilesInSystem) => ENFILE,
         KernelError::Fs(FsError::NoDevice) => ENODEV,
         KernelError::Fs(FsError::Loop) => ELOOP,
+        KernelError::Fs(FsError::CrossDevice) => EXDEV,
+        KernelError::Fs(FsError::NoSpace) => ENOSPC,
+        KernelError::Fs(FsError::FileTooLarge) => EFBIG,
         KernelError::NotATty => ENOTTY,
         KernelError::SeekPipe => ESPIPE,
         KernelError::NotSupported => ENOSYS,
@@ -82,6 +97,17 @@ pub fn kern_err_to_syscall(err: KernelError) -> isize {
         KernelError::Interrupted => EINTR,
         KernelError::NoProcess => ESRCH,
         KernelError::AddressFamilyNotSupported => EAFNOSUPPORT,
+        KernelError::ConnectionRefused => ECONNREFUSED,
+        KernelError::NetworkUnreachable => ENETUNREACH,
+        KernelError::AlreadyConnected => EISCONN,
+        KernelError::NotConnected => ENOTCONN,
+        KernelError::MessageTooLong => EMSGSIZE,
+        KernelError::NoProtocolOption => ENOPROTOOPT,
+        KernelError::InProgress => EINPROGRESS,
+        KernelError::AlreadyInProgress => EALREADY,
+        KernelError::NoBufferSpace => ENOBUFS,
+        KernelError::AddressInUse => EADDRINUSE,
+        KernelError::AddressNotAvailable => EADDRNOTAVAIL,
         e => todo!("{e}"),
     }
 }
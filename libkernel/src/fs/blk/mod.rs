@@ -1,5 +1,6 @@
 //! Block device layer.
 
 pub mod buffer;
+pub mod partition;
 #[cfg(feature = "paging")]
 pub mod ramdisk;
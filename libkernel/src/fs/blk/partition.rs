@@ -0,0 +1,303 @@
+//! Partitions of a block device, as laid out by an MBR partition table.
+
+use crate::{
+    error::{IoError, Result},
+    fs::BlockDevice,
+};
+use alloc::{boxed::Box, sync::Arc, vec, vec::Vec};
+use async_trait::async_trait;
+use log::warn;
+
+/// MBR counts in 512-byte sectors, whatever the device's block size.
+const SECTOR_SIZE: u64 = 512;
+const TABLE_OFFSET: usize = 0x1be;
+const ENTRY_SIZE: usize = 16;
+const NUM_ENTRIES: usize = 4;
+const SIGNATURE: [u8; 2] = [0x55, 0xaa];
+
+/// Partition types which hold further partitions rather than a filesystem.
+const EXTENDED_TYPES: [u8; 3] = [0x05, 0x0f, 0x85];
+
+/// Where a partition lies on its device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PartitionEntry {
+    /// The partition's number, counting the table's entries from 1.
+    pub number: usize,
+    /// The first block of the partition, in the device's blocks.
+    pub start: u64,
+    /// The length of the partition, in the device's blocks.
+    pub num_blocks: u64,
+}
+
+/// Returns whether `sector` looks like the boot sector of a FAT filesystem,
+/// whose boot code could otherwise pass for a partition table.
+fn is_fat_boot_sector(sector: &[u8]) -> bool {
+    let bytes_per_sector = u16::from_le_bytes([sector[0x0b], sector[0x0c]]);
+    let media = sector[0x15];
+
+    matches!(sector[0], 0xeb | 0xe9)
+        && bytes_per_sector.is_power_of_two()
+        && (512..=4096).contains(&bytes_per_sector)
+        && (media == 0xf0 || media >= 0xf8)
+}
+
+/// Reads the MBR partition table at the start of `dev`, returning its primary
+/// partitions. A device with no table, or one that doesn't look right, has no
+/// partitions.
+pub async fn read_partitions(dev: &dyn BlockDevice) -> Result<Vec<PartitionEntry>> {
+    let block_size = dev.block_size() as u64;
+    let blocks = SECTOR_SIZE.div_ceil(block_size);
+
+    if dev.num_blocks() < blocks {
+        return Ok(Vec::new());
+    }
+
+    let mut buf = vec![0; (blocks * block_size) as usize];
+    dev.read(0, &mut buf).await?;
+
+    if buf[510..512] != SIGNATURE {
+        return Ok(Vec::new());
+    }
+
+    let (entries, _) =
+        buf[TABLE_OFFSET..TABLE_OFFSET + ENTRY_SIZE * NUM_ENTRIES].as_chunks::<ENTRY_SIZE>();
+
+    // As on Linux, a boot flag other than 0 or 0x80 means this isn't a
+    // partition table, and neither is the boot sector of an unpartitioned FAT
+    // filesystem, unless a partition is marked bootable.
+    if entries.iter().any(|entry| entry[0] & 0x7f != 0) {
+        return Ok(Vec::new());
+    }
+
+    if is_fat_boot_sector(&buf) && !entries.iter().any(|entry| entry[0] == 0x80) {
+        return Ok(Vec::new());
+    }
+
+    let mut partitions = Vec::new();
+
+    for (i, entry) in entries.iter().enumerate() {
+        let kind = entry[4];
+        let lba = u32::from_le_bytes(entry[8..12].try_into().unwrap()) as u64;
+        let sectors = u32::from_le_bytes(entry[12..16].try_into().unwrap()) as u64;
+
+        if kind == 0 || sectors == 0 || EXTENDED_TYPES.contains(&kind) {
+            continue;
+        }
+
+        let (start, len) = (lba * SECTOR_SIZE, sectors * SECTOR_SIZE);
+
+        if !start.is_multiple_of(block_size) || !len.is_multiple_of(block_size) {
+            warn!("Ignoring partition {} which isn't block aligned", i + 1);
+            continue;
+        }
+
+        let (start, num_blocks) = (start / block_size, len / block_size);
+
+        if start + num_blocks > dev.num_blocks() {
+            warn!("Ignoring partition {} which runs off the device", i + 1);
+            continue;
+        }
+
+        partitions.push(PartitionEntry {
+            number: i + 1,
+            start,
+            num_blocks,
+        });
+    }
+
+    Ok(partitions)
+}
+
+/// A partition of a block device, which reads and writes the device's blocks
+/// that it spans.
+pub struct Partition {
+    dev: Arc<dyn BlockDevice>,
+    start: u64,
+    num_blocks: u64,
+}
+
+impl Partition {
+    /// Creates the partition `entry` describes on `dev`.
+    pub fn new(dev: Arc<dyn BlockDevice>, entry: &PartitionEntry) -> Self {
+        Self {
+            dev,
+            start: entry.start,
+            num_blocks: entry.num_blocks,
+        }
+    }
+
+    fn check_bounds(&self, block_id: u64, len: usize) -> Result<()> {
+        let blocks = (len / self.dev.block_size()) as u64;
+
+        if block_id.saturating_add(blocks) > self.num_blocks {
+            return Err(IoError::OutOfBounds.into());
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl BlockDevice for Partition {
+    async fn read(&self, block_id: u64, buf: &mut [u8]) -> Result<()> {
+        self.check_bounds(block_id, buf.len())?;
+        self.dev.read(self.start + block_id, buf).await
+    }
+
+    async fn write(&self, block_id: u64, buf: &[u8]) -> Result<()> {
+        self.check_bounds(block_id, buf.len())?;
+        self.dev.write(self.start + block_id, buf).await
+    }
+
+    fn block_size(&self) -> usize {
+        self.dev.block_size()
+    }
+
+    fn num_blocks(&self) -> u64 {
+        self.num_blocks
+    }
+
+    async fn sync(&self) -> Result<()> {
+        self.dev.sync().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::KernelError;
+    use std::sync::Mutex;
+
+    const BLOCK_SIZE: usize = 512;
+
+    struct MemBlkDevice {
+        data: Mutex<Vec<u8>>,
+    }
+
+    #[async_trait]
+    impl BlockDevice for MemBlkDevice {
+        async fn read(&self, block_id: u64, buf: &mut [u8]) -> Result<()> {
+            let start = block_id as usize * BLOCK_SIZE;
+            buf.copy_from_slice(&self.data.lock().unwrap()[start..start + buf.len()]);
+            Ok(())
+        }
+
+        async fn write(&self, block_id: u64, buf: &[u8]) -> Result<()> {
+            let start = block_id as usize * BLOCK_SIZE;
+            self.data.lock().unwrap()[start..start + buf.len()].copy_from_slice(buf);
+            Ok(())
+        }
+
+        fn block_size(&self) -> usize {
+            BLOCK_SIZE
+        }
+
+        fn num_blocks(&self) -> u64 {
+            (self.data.lock().unwrap().len() / BLOCK_SIZE) as _
+        }
+
+        async fn sync(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Creates a device of `blocks` blocks, with a table of `entries`, each a
+    /// boot flag, type, first sector and number of sectors.
+    fn setup_dev(blocks: usize, entries: &[(u8, u8, u32, u32)]) -> Arc<MemBlkDevice> {
+        let mut data = vec![0; blocks * BLOCK_SIZE];
+
+        for (i, &(boot, kind, lba, sectors)) in entries.iter().enumerate() {
+            let entry = &mut data[TABLE_OFFSET + i * ENTRY_SIZE..][..ENTRY_SIZE];
+            entry[0] = boot;
+            entry[4] = kind;
+            entry[8..12].copy_from_slice(&lba.to_le_bytes());
+            entry[12..16].copy_from_slice(&sectors.to_le_bytes());
+        }
+        data[510..512].copy_from_slice(&SIGNATURE);
+
+        Arc::new(MemBlkDevice {
+            data: Mutex::new(data),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_no_table() {
+        let dev = MemBlkDevice {
+            data: Mutex::new(vec![0; 16 * BLOCK_SIZE]),
+        };
+
+        assert!(read_partitions(&dev).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_primary_partitions() {
+        let dev = setup_dev(
+            64,
+            &[
+                (0x80, 0x83, 2, 8),
+                (0, 0, 0, 0),
+                (0, 0x0c, 16, 32),
+                (0, 0x05, 48, 8),
+            ],
+        );
+
+        let partitions = read_partitions(&*dev).await.unwrap();
+        assert_eq!(
+            partitions,
+            vec![
+                PartitionEntry {
+                    number: 1,
+                    start: 2,
+                    num_blocks: 8,
+                },
+                PartitionEntry {
+                    number: 3,
+                    start: 16,
+                    num_blocks: 32,
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bad_tables() {
+        // A boot flag that's neither 0 nor 0x80.
+        let dev = setup_dev(64, &[(0x12, 0x83, 2, 8)]);
+        assert!(read_partitions(&*dev).await.unwrap().is_empty());
+
+        // A partition that runs off the end is skipped.
+        let dev = setup_dev(64, &[(0, 0x83, 2, 8), (0, 0x83, 60, 8)]);
+        assert_eq!(read_partitions(&*dev).await.unwrap().len(), 1);
+
+        // A FAT boot sector isn't a partition table.
+        let dev = setup_dev(64, &[(0, 0x83, 2, 8)]);
+        {
+            let mut data = dev.data.lock().unwrap();
+            data[0] = 0xeb;
+            data[0x0b..0x0d].copy_from_slice(&512u16.to_le_bytes());
+            data[0x15] = 0xf8;
+        }
+        assert!(read_partitions(&*dev).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_partition_io() {
+        let dev = setup_dev(64, &[(0, 0x83, 8, 4)]);
+        let entry = read_partitions(&*dev).await.unwrap()[0];
+        let part = Partition::new(dev.clone(), &entry);
+
+        assert_eq!(part.num_blocks(), 4);
+
+        part.write(1, &[0xaa; BLOCK_SIZE]).await.unwrap();
+        assert_eq!(dev.data.lock().unwrap()[9 * BLOCK_SIZE], 0xaa);
+
+        let mut buf = [0; BLOCK_SIZE];
+        part.read(1, &mut buf).await.unwrap();
+        assert_eq!(buf, [0xaa; BLOCK_SIZE]);
+
+        assert!(matches!(
+            part.read(3, &mut [0; 2 * BLOCK_SIZE]).await,
+            Err(KernelError::Io(IoError::OutOfBounds))
+        ));
+    }
+}
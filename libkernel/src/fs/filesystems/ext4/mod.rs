@@ -14,7 +14,7 @@ use crate::{
     CpuOps,
     error::{KernelError, Result},
     fs::{
-        FileType, Filesystem, Inode, InodeId,
+        FileType, Filesystem, FsStats, Inode, InodeId,
         attr::{FileAttr, FilePermissions},
         blk::buffer::BlockBuffer,
     },
@@ -259,13 +259,16 @@ where
     }
 
     async fn truncate(&self, size: u64) -> Result<()> {
-        let inner = self.inner.lock().await;
+        let mut inner = self.inner.lock().await;
         if inner.file_type() != ext4plus::FileType::Regular {
             return Err(KernelError::NotSupported);
         }
         let fs = self.fs_ref.upgrade().unwrap();
         let mut file = File::open_inode(&fs.inner, inner.clone())?;
         file.truncate(size).await?;
+        // The truncation changed a copy of the inode, so keep that, lest a
+        // later write go through the blocks just freed.
+        *inner = InodeInner::Regular(file);
         Ok(())
     }
 
@@ -347,7 +350,7 @@ where
                     mode: InodeMode::S_IFDIR | InodeMode::from_bits(permissions.bits()).unwrap(),
                     uid: 0,
                     gid: 0,
-                    time: Default::default(),
+                    time: time.unwrap_or_default(),
                     flags: InodeFlags::empty(),
                 })
                 .await?;
@@ -629,6 +632,12 @@ where
     }
 }
 
+/// Where the superblock sits on the device.
+const SUPERBLOCK_OFFSET: u64 = 1024;
+
+/// Set in `s_feature_incompat` when block counts are 64 bits wide.
+const INCOMPAT_64BIT: u32 = 0x80;
+
 /// An EXT4 filesystem instance.
 ///
 /// For now this struct only stores the underlying block buffer and an ID
@@ -686,6 +695,39 @@ where
         }))
     }
 
+    /// Reads the block and inode counts from the superblock.
+    async fn statfs(&self) -> Result<FsStats> {
+        let mut sb = [0u8; 1024];
+        self.dev.read_at(SUPERBLOCK_OFFSET, &mut sb).await?;
+
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(sb[offset..offset + 4].try_into().unwrap()) as u64;
+        // Block counts are split into a low and, on 64-bit filesystems, a
+        // high half.
+        let is_64bit = u32_at(0x60) as u32 & INCOMPAT_64BIT != 0;
+        let blocks_at = |lo: usize, hi: usize| {
+            if is_64bit {
+                (u32_at(hi) << 32) | u32_at(lo)
+            } else {
+                u32_at(lo)
+            }
+        };
+
+        let total_blocks = blocks_at(0x4, 0x150);
+        let reserved_blocks = blocks_at(0x8, 0x154);
+        let free_blocks = blocks_at(0xc, 0x158);
+
+        Ok(FsStats {
+            block_size: 1024 << u32_at(0x18),
+            total_blocks,
+            free_blocks,
+            avail_blocks: free_blocks.saturating_sub(reserved_blocks),
+            total_inodes: u32_at(0x0),
+            free_inodes: u32_at(0x10),
+            name_len: 255,
+        })
+    }
+
     /// Flushes any dirty data to the underlying block device.  The current
     /// stub implementation simply forwards the request to `BlockBuffer::sync`.
     async fn sync(&self) -> Result<()> {
@@ -693,3 +735,230 @@ where
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        fs::{BlockDevice, attr::FilePermissions},
+        test::MockCpuOps,
+    };
+    use std::sync::Mutex as StdMutex;
+
+    const BLOCK_SIZE: usize = 1024;
+    const TOTAL_BLOCKS: u32 = 1024;
+    const INODES: u32 = 32;
+    const INODE_SIZE: usize = 256;
+    const INODE_TABLE: usize = 5;
+    const ROOT_DIR_BLOCK: u32 = 13;
+    const FIRST_INO: u32 = 11;
+
+    #[derive(Clone)]
+    struct MemBlkDevice {
+        data: Arc<StdMutex<Vec<u8>>>,
+    }
+
+    #[async_trait]
+    impl BlockDevice for MemBlkDevice {
+        async fn read(&self, block_id: u64, buf: &mut [u8]) -> Result<()> {
+            let start = block_id as usize * BLOCK_SIZE;
+            buf.copy_from_slice(&self.data.lock().unwrap()[start..start + buf.len()]);
+            Ok(())
+        }
+
+        async fn write(&self, block_id: u64, buf: &[u8]) -> Result<()> {
+            let start = block_id as usize * BLOCK_SIZE;
+            self.data.lock().unwrap()[start..start + buf.len()].copy_from_slice(buf);
+            Ok(())
+        }
+
+        fn block_size(&self) -> usize {
+            BLOCK_SIZE
+        }
+
+        fn num_blocks(&self) -> u64 {
+            TOTAL_BLOCKS as _
+        }
+
+        async fn sync(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Formats a 1 MiB volume with 1 KiB blocks, a single block group, extents
+    /// and an empty root directory. There is no journal and no metadata
+    /// checksums, so every structure can be laid down by hand.
+    fn mkfs() -> MemBlkDevice {
+        let mut data = vec![0; TOTAL_BLOCKS as usize * BLOCK_SIZE];
+        let mut put = |offset: usize, bytes: &[u8]| {
+            data[offset..offset + bytes.len()].copy_from_slice(bytes);
+        };
+
+        // Everything up to and including the root directory block is in use.
+        let used_blocks = ROOT_DIR_BLOCK;
+        let free_blocks = TOTAL_BLOCKS - 1 - used_blocks;
+        let free_inodes = INODES - (FIRST_INO - 1);
+
+        let sb = SUPERBLOCK_OFFSET as usize;
+        put(sb, &INODES.to_le_bytes());
+        put(sb + 0x4, &TOTAL_BLOCKS.to_le_bytes());
+        put(sb + 0xC, &free_blocks.to_le_bytes());
+        put(sb + 0x10, &free_inodes.to_le_bytes());
+        put(sb + 0x14, &1u32.to_le_bytes());
+        put(sb + 0x20, &(BLOCK_SIZE as u32 * 8).to_le_bytes());
+        put(sb + 0x24, &(BLOCK_SIZE as u32 * 8).to_le_bytes());
+        put(sb + 0x28, &INODES.to_le_bytes());
+        put(sb + 0x38, &0xEF53u16.to_le_bytes());
+        put(sb + 0x3A, &1u16.to_le_bytes());
+        put(sb + 0x4C, &1u32.to_le_bytes());
+        put(sb + 0x54, &FIRST_INO.to_le_bytes());
+        put(sb + 0x58, &(INODE_SIZE as u16).to_le_bytes());
+        // INCOMPAT_FILETYPE | INCOMPAT_EXTENTS
+        put(sb + 0x60, &0x42u32.to_le_bytes());
+        put(sb + 0x15C, &32u16.to_le_bytes());
+        put(sb + 0x15E, &32u16.to_le_bytes());
+
+        let gd = 2 * BLOCK_SIZE;
+        put(gd, &3u32.to_le_bytes());
+        put(gd + 0x4, &4u32.to_le_bytes());
+        put(gd + 0x8, &(INODE_TABLE as u32).to_le_bytes());
+        put(gd + 0xC, &(free_blocks as u16).to_le_bytes());
+        put(gd + 0xE, &(free_inodes as u16).to_le_bytes());
+        put(gd + 0x10, &1u16.to_le_bytes());
+
+        // Bit `n` of the block bitmap covers block `n + 1`. Bits past the end
+        // of the volume are set so they are never handed out.
+        let mut bitmap = [0u8; BLOCK_SIZE];
+        for bit in (0..used_blocks).chain(TOTAL_BLOCKS - 1..BLOCK_SIZE as u32 * 8) {
+            bitmap[bit as usize / 8] |= 1 << (bit % 8);
+        }
+        put(3 * BLOCK_SIZE, &bitmap);
+
+        let mut bitmap = [0u8; BLOCK_SIZE];
+        for bit in (0..FIRST_INO - 1).chain(INODES..BLOCK_SIZE as u32 * 8) {
+            bitmap[bit as usize / 8] |= 1 << (bit % 8);
+        }
+        put(4 * BLOCK_SIZE, &bitmap);
+
+        let root = INODE_TABLE * BLOCK_SIZE + INODE_SIZE;
+        put(root, &0o40755u16.to_le_bytes());
+        put(root + 0x4, &(BLOCK_SIZE as u32).to_le_bytes());
+        put(root + 0x1A, &2u16.to_le_bytes());
+        put(root + 0x1C, &((BLOCK_SIZE / 512) as u32).to_le_bytes());
+        // EXT4_EXTENTS_FL
+        put(root + 0x20, &0x80000u32.to_le_bytes());
+        // A leaf extent header with a single extent mapping the directory block.
+        put(root + 0x28, &0xF30Au16.to_le_bytes());
+        put(root + 0x2A, &1u16.to_le_bytes());
+        put(root + 0x2C, &4u16.to_le_bytes());
+        put(root + 0x38, &1u16.to_le_bytes());
+        put(root + 0x3C, &ROOT_DIR_BLOCK.to_le_bytes());
+        put(root + 0x80, &32u16.to_le_bytes());
+
+        let dir = ROOT_DIR_BLOCK as usize * BLOCK_SIZE;
+        put(dir, &2u32.to_le_bytes());
+        put(dir + 4, &12u16.to_le_bytes());
+        put(dir + 6, &[1, 2]);
+        put(dir + 8, b".");
+        put(dir + 12, &2u32.to_le_bytes());
+        put(dir + 16, &(BLOCK_SIZE as u16 - 12).to_le_bytes());
+        put(dir + 18, &[2, 2]);
+        put(dir + 20, b"..");
+
+        MemBlkDevice {
+            data: Arc::new(StdMutex::new(data)),
+        }
+    }
+
+    async fn mount(dev: &MemBlkDevice) -> Arc<Ext4Filesystem<MockCpuOps>> {
+        Ext4Filesystem::new(BlockBuffer::new(Box::new(dev.clone())), 1)
+            .await
+            .unwrap()
+    }
+
+    async fn create(dir: &Arc<dyn Inode>, name: &str, file_type: FileType) -> Arc<dyn Inode> {
+        dir.create(
+            name,
+            file_type,
+            FilePermissions::from_bits_retain(0o644),
+            None,
+        )
+        .await
+        .unwrap()
+    }
+
+    async fn read_all(inode: &Arc<dyn Inode>) -> Vec<u8> {
+        let mut buf = vec![0; inode.getattr().await.unwrap().size as usize];
+        let len = inode.read_at(0, &mut buf).await.unwrap();
+        buf.truncate(len);
+        buf
+    }
+
+    async fn free_blocks(fs: &Ext4Filesystem<MockCpuOps>) -> u64 {
+        fs.statfs().await.unwrap().free_blocks
+    }
+
+    #[tokio::test]
+    async fn test_truncate_then_write() {
+        let dev = mkfs();
+        let fs = mount(&dev).await;
+        let root = fs.root_inode().await.unwrap();
+
+        let a = create(&root, "a", FileType::File).await;
+        let free = free_blocks(&fs).await;
+        a.write_at(0, &[b'a'; 3000]).await.unwrap();
+        assert!(free_blocks(&fs).await < free);
+
+        a.truncate(0).await.unwrap();
+        assert_eq!(a.getattr().await.unwrap().size, 0);
+        assert_eq!(free_blocks(&fs).await, free);
+
+        // A second file picks up the blocks `a` just gave back. Writing to
+        // `a` must allocate afresh rather than go through its old block map.
+        let b = create(&root, "b", FileType::File).await;
+        b.write_at(0, &[b'b'; 3000]).await.unwrap();
+        a.write_at(0, b"x").await.unwrap();
+
+        assert_eq!(read_all(&a).await, b"x");
+        assert_eq!(read_all(&b).await, [b'b'; 3000]);
+
+        fs.sync().await.unwrap();
+        let fs = mount(&dev).await;
+        let root = fs.root_inode().await.unwrap();
+        assert_eq!(read_all(&root.lookup("a").await.unwrap()).await, b"x");
+        assert_eq!(
+            read_all(&root.lookup("b").await.unwrap()).await,
+            [b'b'; 3000]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_mkdir_time() {
+        let dev = mkfs();
+        let fs = mount(&dev).await;
+        let root = fs.root_inode().await.unwrap();
+        let time = Duration::from_secs(1_700_000_000);
+
+        let dir = root
+            .create(
+                "dir",
+                FileType::Directory,
+                FilePermissions::from_bits_retain(0o755),
+                Some(time),
+            )
+            .await
+            .unwrap();
+
+        let attr = dir.getattr().await.unwrap();
+        assert_eq!(attr.file_type, FileType::Directory);
+        assert_eq!((attr.atime, attr.ctime, attr.mtime), (time, time, time));
+
+        // The new directory is usable, and the times survive a remount.
+        create(&dir, "file", FileType::File).await;
+        fs.sync().await.unwrap();
+        let fs = mount(&dev).await;
+        let dir = fs.root_inode().await.unwrap().lookup("dir").await.unwrap();
+        assert_eq!(dir.getattr().await.unwrap().mtime, time);
+        assert!(dir.lookup("file").await.is_ok());
+    }
+}
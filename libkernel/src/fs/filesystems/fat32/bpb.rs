@@ -26,7 +26,7 @@ pub struct BiosParameterBlock {
     _sectors_per_track: u16,
     _head_count: u16,
     _hidden_sector_count: u32,
-    _total_sectors_32: u32,
+    pub total_sectors_32: u32,
 
     /* FAT32 Extended BPB */
     // The size of ONE FAT in sectors.
@@ -124,6 +124,14 @@ impl BiosParameterBlock {
         self.fat_region_start() + self.fat_len() * self.num_fats as usize
     }
 
+    /// Returns the number of clusters in the data region.
+    pub fn cluster_count(&self) -> usize {
+        let total_sectors = self.total_sectors_32 as usize;
+
+        total_sectors.saturating_sub(self.data_region_start().0 as usize)
+            / self.sectors_per_cluster as usize
+    }
+
     pub fn sector_size(&self) -> usize {
         self.bytes_per_sector as _
     }
@@ -162,7 +170,7 @@ pub mod test {
             _sectors_per_track: 0,
             _head_count: 0,
             _hidden_sector_count: 0,
-            _total_sectors_32: 0,
+            total_sectors_32: 0,
             fat_size_32: 1000, // Size of ONE FAT in sectors
             _ext_flags: 0,
             _fs_version: 0,
@@ -203,6 +211,20 @@ pub mod test {
         assert_eq!(bpb.data_region_start(), Sector(2032));
     }
 
+    #[test]
+    fn cluster_count() {
+        let mut bpb = create_test_bpb();
+        assert_eq!(bpb.cluster_count(), 0);
+
+        // (10032 - 2032) sectors / 8 sectors per cluster.
+        bpb.total_sectors_32 = 10032;
+        assert_eq!(bpb.cluster_count(), 1000);
+
+        // A partial cluster at the end doesn't count.
+        bpb.total_sectors_32 = 10039;
+        assert_eq!(bpb.cluster_count(), 1000);
+    }
+
     #[test]
     fn fat_region_lookup() {
         let bpb = create_test_bpb();
@@ -1,18 +1,24 @@
 use core::ptr;
 use core::time::Duration;
 
-use super::{Cluster, Fat32Operations, file::Fat32FileNode, reader::Fat32Reader};
+use super::{
+    Cluster, EntryPos, Fat32Operations, NodeState, SharedState,
+    file::Fat32FileNode,
+    lock_meta,
+    reader::{Fat32Reader, read_chain, write_chain},
+};
 use crate::{
-    error::{FsError, KernelError, Result},
+    error::{FsError, IoError, KernelError, Result},
     fs::{
         DirStream, Dirent, FileType, Inode, InodeId,
         attr::{FileAttr, FilePermissions},
     },
 };
-use alloc::{boxed::Box, string::String, sync::Arc, vec::Vec};
+use alloc::{
+    boxed::Box, collections::btree_set::BTreeSet, format, string::String, sync::Arc, vec::Vec,
+};
 use async_trait::async_trait;
 use core::any::Any;
-use log::warn;
 
 bitflags::bitflags! {
     #[derive(Clone, Copy, Debug)]
@@ -27,21 +33,32 @@ bitflags::bitflags! {
     }
 }
 
-impl TryFrom<Fat32Attributes> for FileType {
-    type Error = KernelError;
-
-    fn try_from(value: Fat32Attributes) -> Result<Self> {
+/// Anything that isn't a directory is a regular file, whether or not its
+/// archive bit is set.
+impl From<Fat32Attributes> for FileType {
+    fn from(value: Fat32Attributes) -> Self {
         if value.contains(Fat32Attributes::DIRECTORY) {
-            Ok(FileType::Directory)
-        } else if value.contains(Fat32Attributes::ARCHIVE) {
-            Ok(FileType::File)
+            FileType::Directory
         } else {
-            warn!("Entry is neither a regular file nor a directory. Ignoring.");
-            Err(FsError::InvalidFs.into())
+            FileType::File
         }
     }
 }
 
+/// The flags Windows NT keeps in an 8.3 entry's reserved byte, saying its
+/// name and extension are shown in lower case.
+const NT_LOWER_NAME: u8 = 0x08;
+const NT_LOWER_EXT: u8 = 0x10;
+
+/// The first byte of a deleted entry.
+const DELETED: u8 = 0xE5;
+
+/// Characters a long name can't have, beside control characters.
+const INVALID_CHARS: &[char] = &['"', '*', '/', ':', '<', '>', '?', '\\', '|'];
+
+/// Characters beside letters and digits that a short name can have.
+const SHORT_NAME_CHARS: &[u8] = b"$%'-_@~`!(){}^#&";
+
 #[derive(Clone, Copy)]
 #[repr(C, packed)]
 struct LfnEntry {
@@ -71,6 +88,42 @@ impl LfnEntry {
 
         chars
     }
+
+    /// Returns the entries holding `name`, in the order they're stored, to go
+    /// before the 8.3 entry with `checksum`.
+    fn encode(name: &str, checksum: u8) -> Vec<[u8; 32]> {
+        let mut chars: Vec<u16> = name.encode_utf16().collect();
+
+        // The name's only terminated if it doesn't fill the last entry, which
+        // is then padded out.
+        if !chars.len().is_multiple_of(13) {
+            chars.push(0x0000);
+            chars.resize(chars.len().next_multiple_of(13), 0xFFFF);
+        }
+
+        let count = chars.len() / 13;
+
+        (0..count)
+            .rev()
+            .map(|i| {
+                let chunk = &chars[i * 13..][..13];
+                let last = if i == count - 1 { 0x40 } else { 0 };
+
+                let lfn = LfnEntry {
+                    sequence_number: (i + 1) as u8 | last,
+                    name1: chunk[0..5].try_into().unwrap(),
+                    attributes: 0x0F,
+                    entry_type: 0,
+                    checksum,
+                    name2: chunk[5..11].try_into().unwrap(),
+                    first_cluster: 0,
+                    name3: chunk[11..13].try_into().unwrap(),
+                };
+
+                unsafe { core::mem::transmute::<LfnEntry, [u8; 32]>(lfn) }
+            })
+            .collect()
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -92,6 +145,70 @@ struct DirEntry {
 }
 
 impl DirEntry {
+    /// Creates an unnamed entry for a new file or directory starting at
+    /// `cluster`, made at `time`.
+    fn new(attributes: Fat32Attributes, cluster: Cluster, time: Duration) -> Self {
+        let (date, time, csecs) = duration_to_fat_datetime(time);
+        let (clust_high, clust_low) = cluster.to_high_low();
+
+        Self {
+            dos_file_name: [b' '; 8],
+            dos_extension: [b' '; 3],
+            attributes,
+            _reserved: 0,
+            ctime_ms: csecs,
+            ctime: time,
+            cdate: date,
+            adate: date,
+            clust_high,
+            mtime: time,
+            mdate: date,
+            clust_low,
+            size: 0,
+        }
+    }
+
+    fn from_bytes(bytes: &[u8; 32]) -> Self {
+        unsafe { ptr::read_unaligned(bytes.as_ptr() as *const _) }
+    }
+
+    fn to_bytes(self) -> [u8; 32] {
+        unsafe { core::mem::transmute(self) }
+    }
+
+    fn short_name(&self) -> [u8; 11] {
+        let mut name = [0; 11];
+        name[..8].copy_from_slice(&self.dos_file_name);
+        name[8..].copy_from_slice(&self.dos_extension);
+        name
+    }
+
+    fn set_short_name(&mut self, name: &[u8; 11], nt_flags: u8) {
+        self.dos_file_name.copy_from_slice(&name[..8]);
+        self.dos_extension.copy_from_slice(&name[8..]);
+        self._reserved = self._reserved & !(NT_LOWER_NAME | NT_LOWER_EXT) | nt_flags;
+    }
+
+    fn cluster(&self) -> Cluster {
+        Cluster::from_high_low(self.clust_high, self.clust_low)
+    }
+
+    fn set_cluster(&mut self, cluster: Cluster) {
+        (self.clust_high, self.clust_low) = cluster.to_high_low();
+    }
+
+    fn attr(&self) -> FileAttr {
+        FileAttr {
+            size: self.size as u64,
+            file_type: self.attributes.into(),
+            permissions: FilePermissions::from_bits_retain(0o755),
+            atime: fat_date_to_duration(self.adate),
+            mtime: fat_datetime_to_duration(self.mdate, self.mtime, 0),
+            ctime: fat_datetime_to_duration(self.cdate, self.ctime, self.ctime_ms),
+            ..Default::default()
+        }
+    }
+
     pub fn parse_filename(&self) -> String {
         let name_part = self
             .dos_file_name
@@ -117,17 +234,414 @@ impl DirEntry {
     }
 }
 
+/// Returns the checksum of an 8.3 name kept in the long filename entries
+/// before it.
+fn checksum(name: &[u8; 11]) -> u8 {
+    name.iter()
+        .fold(0u8, |sum, &byte| sum.rotate_right(1).wrapping_add(byte))
+}
+
+/// Checks `name` can be given to a file.
+fn check_name(name: &str) -> Result<()> {
+    if name.is_empty()
+        || name == "."
+        || name == ".."
+        || name.chars().any(|c| c < ' ' || INVALID_CHARS.contains(&c))
+    {
+        return Err(FsError::InvalidInput.into());
+    }
+
+    if name.encode_utf16().count() > 255 {
+        return Err(KernelError::NameTooLong);
+    }
+
+    Ok(())
+}
+
+/// Returns `name` as an 8.3 name, if it's one as it is, with the NT flags for
+/// each part that's in lower case. A name in mixed case isn't one, as it can't
+/// be read back as it was.
+fn exact_short_name(name: &str) -> Option<([u8; 11], u8)> {
+    let (base, ext) = name.split_once('.').unwrap_or((name, ""));
+
+    let valid = |part: &str, max| {
+        (1..=max).contains(&part.len())
+            && part
+                .bytes()
+                .all(|c| c.is_ascii_alphanumeric() || SHORT_NAME_CHARS.contains(&c))
+    };
+
+    if !valid(base, 8) || !(ext.is_empty() || valid(ext, 3)) {
+        return None;
+    }
+
+    let mut flags = 0;
+    for (part, flag) in [(base, NT_LOWER_NAME), (ext, NT_LOWER_EXT)] {
+        let lower = part.bytes().any(|c| c.is_ascii_lowercase());
+        let upper = part.bytes().any(|c| c.is_ascii_uppercase());
+
+        match (lower, upper) {
+            (true, true) => return None,
+            (true, false) => flags |= flag,
+            _ => {}
+        }
+    }
+
+    let mut short = [b' '; 11];
+    short[..base.len()].copy_from_slice(base.to_ascii_uppercase().as_bytes());
+    short[8..8 + ext.len()].copy_from_slice(ext.to_ascii_uppercase().as_bytes());
+
+    Some((short, flags))
+}
+
+/// Makes up an 8.3 name for the long name `name`, unlike any of `taken`, in
+/// the way Windows does: its characters are upper cased, any that can't be in
+/// a short name become underscores, and a numeric tail is added if that loses
+/// anything or the name's already taken.
+fn generate_short_name(name: &str, taken: &BTreeSet<[u8; 11]>) -> Result<[u8; 11]> {
+    let to_short = |s: &str| -> Vec<u8> {
+        s.chars()
+            .filter(|&c| c != ' ' && c != '.')
+            .map(|c| match u8::try_from(c.to_ascii_uppercase()) {
+                Ok(c) if c.is_ascii_alphanumeric() || SHORT_NAME_CHARS.contains(&c) => c,
+                _ => b'_',
+            })
+            .collect()
+    };
+
+    let trimmed = name.trim_start_matches('.');
+    let (base, ext) = match trimmed.rsplit_once('.') {
+        Some((base, ext)) => (to_short(base), to_short(ext)),
+        None => (to_short(trimmed), Vec::new()),
+    };
+
+    let mut short = [b' '; 11];
+    let ext = &ext[..ext.len().min(3)];
+    short[8..8 + ext.len()].copy_from_slice(ext);
+
+    if !base.is_empty() && base.len() <= 8 {
+        short[..base.len()].copy_from_slice(&base);
+
+        if !taken.contains(&short) {
+            return Ok(short);
+        }
+    }
+
+    let base = if base.is_empty() { b"_".to_vec() } else { base };
+
+    for n in 1..1_000_000 {
+        let tail = format!("~{n}");
+        let len = base.len().min(8 - tail.len());
+
+        short[..8].fill(b' ');
+        short[..len].copy_from_slice(&base[..len]);
+        short[len..len + tail.len()].copy_from_slice(tail.as_bytes());
+
+        if !taken.contains(&short) {
+            return Ok(short);
+        }
+    }
+
+    Err(FsError::NoSpace.into())
+}
+
+/// A directory's entries as they're laid out on disk, 32 bytes apiece, which
+/// is how it's changed.
+struct RawDir<'a, T: Fat32Operations> {
+    fs: &'a T,
+    chain: Vec<Cluster>,
+}
+
+impl<'a, T: Fat32Operations> RawDir<'a, T> {
+    fn new(fs: &'a T, root: Cluster) -> Result<Self> {
+        Ok(Self {
+            fs,
+            chain: fs.iter_clusters(root).collect::<Result<_>>()?,
+        })
+    }
+
+    fn num_slots(&self) -> u64 {
+        (self.chain.len() * self.fs.bytes_per_cluster() / 32) as _
+    }
+
+    async fn read(&self, index: u64) -> Result<[u8; 32]> {
+        let mut slot = [0; 32];
+        read_chain(self.fs, &self.chain, index * 32, &mut slot).await?;
+        Ok(slot)
+    }
+
+    async fn write(&self, index: u64, bytes: &[u8]) -> Result<()> {
+        write_chain(self.fs, &self.chain, index * 32, bytes).await
+    }
+
+    /// Returns the short names of the directory's entries.
+    async fn short_names(&self) -> Result<BTreeSet<[u8; 11]>> {
+        let mut names = BTreeSet::new();
+
+        for index in 0..self.num_slots() {
+            let slot = self.read(index).await?;
+
+            match slot[0] {
+                0x00 => break,
+                DELETED => {}
+                _ if slot[11] == 0x0F => {}
+                _ => {
+                    names.insert(DirEntry::from_bytes(&slot).short_name());
+                }
+            }
+        }
+
+        Ok(names)
+    }
+
+    /// Returns the index of the first run of `count` free slots, growing the
+    /// directory if it hasn't one.
+    async fn find_free(&mut self, count: u64) -> Result<u64> {
+        let mut run_start = 0;
+        let mut run = 0;
+        let mut at_end = false;
+
+        for index in 0..self.num_slots() {
+            match self.read(index).await?[0] {
+                // Everything from the end marker on is free.
+                0x00 => {
+                    if run == 0 {
+                        run_start = index;
+                    }
+                    at_end = true;
+                    break;
+                }
+                DELETED => {
+                    if run == 0 {
+                        run_start = index;
+                    }
+                    run += 1;
+
+                    if run == count {
+                        return Ok(run_start);
+                    }
+                }
+                _ => run = 0,
+            }
+        }
+
+        if !at_end && run == 0 {
+            run_start = self.num_slots();
+        }
+
+        while run_start + count > self.num_slots() {
+            let last = self.chain.last().copied();
+            self.chain.push(self.fs.alloc_cluster(last).await?);
+        }
+
+        // Whatever follows is past the end, so must start with the end
+        // marker, which mightn't be there if it was written by the run's
+        // last slot.
+        let next = run_start + count;
+        if next < self.num_slots() && self.read(next).await?[0] != 0x00 {
+            self.write(next, &[0; 32]).await?;
+        }
+
+        Ok(run_start)
+    }
+
+    /// Adds `entry` as `name`, with long filename entries before it if the
+    /// name needs them, returning the index of the 8.3 entry.
+    async fn add(&mut self, name: &str, mut entry: DirEntry) -> Result<u64> {
+        let taken = self.short_names().await?;
+
+        let mut slots = match exact_short_name(name) {
+            Some((short, flags)) if !taken.contains(&short) => {
+                entry.set_short_name(&short, flags);
+                Vec::new()
+            }
+            _ => {
+                let short = generate_short_name(name, &taken)?;
+                entry.set_short_name(&short, 0);
+                LfnEntry::encode(name, checksum(&short))
+            }
+        };
+        slots.push(entry.to_bytes());
+
+        let first = self.find_free(slots.len() as _).await?;
+
+        // The 8.3 entry goes last, so the long name isn't seen without it.
+        for (i, slot) in slots.iter().enumerate() {
+            self.write(first + i as u64, slot).await?;
+        }
+
+        Ok(first + slots.len() as u64 - 1)
+    }
+
+    /// Marks the slots from `first` to `last` as deleted.
+    async fn remove(&self, first: u64, last: u64) -> Result<()> {
+        for index in (first..=last).rev() {
+            self.write(index, &[DELETED]).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes the `.` and `..` entries of a new directory starting at `cluster`,
+/// in the directory starting at `parent`.
+async fn write_dots<T: Fat32Operations>(
+    fs: &T,
+    cluster: Cluster,
+    parent: Cluster,
+    time: Duration,
+) -> Result<()> {
+    let dir = RawDir::new(fs, cluster)?;
+
+    let mut dot = DirEntry::new(Fat32Attributes::DIRECTORY, cluster, time);
+    dot.set_short_name(b".          ", 0);
+    dir.write(0, &dot.to_bytes()).await?;
+
+    let mut dotdot = dot;
+    dotdot.set_short_name(b"..         ", 0);
+    dotdot.set_cluster(parent_link(fs, parent));
+    dir.write(1, &dotdot.to_bytes()).await
+}
+
+/// Returns the cluster a `..` entry gives for `parent`, which is 0 for the
+/// root directory.
+fn parent_link<T: Fat32Operations>(fs: &T, parent: Cluster) -> Cluster {
+    if parent == fs.root_cluster() {
+        Cluster(0)
+    } else {
+        parent
+    }
+}
+
+/// Returns whether the directory starting at `dir` is the one starting at
+/// `ancestor`, or is somewhere below it.
+async fn is_within<T: Fat32Operations>(
+    fs: &T,
+    mut dir: Cluster,
+    ancestor: Cluster,
+) -> Result<bool> {
+    let mut seen = BTreeSet::new();
+
+    while dir != fs.root_cluster() {
+        if dir == ancestor {
+            return Ok(true);
+        }
+
+        if !seen.insert(dir) {
+            return Err(IoError::MetadataCorruption.into());
+        }
+
+        let dotdot = DirEntry::from_bytes(&RawDir::new(fs, dir)?.read(1).await?);
+        dir = match dotdot.cluster() {
+            cluster if cluster.is_valid() => cluster,
+            _ => fs.root_cluster(),
+        };
+    }
+
+    Ok(dir == ancestor)
+}
+
+/// Returns whether the directory starting at `cluster` has nothing but its
+/// `.` and `..` entries.
+async fn dir_is_empty<T: Fat32Operations>(fs: &Arc<T>, cluster: Cluster) -> Result<bool> {
+    let mut stream = Fat32DirStream::new(fs.clone(), cluster);
+
+    while let Some(entry) = stream.next_fat32_entry().await? {
+        if entry.name != "." && entry.name != ".." {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+/// Writes the first cluster, size and times `state` gives to its entry, if
+/// it has one.
+pub(super) async fn store_state<T: Fat32Operations>(
+    fs: &T,
+    state: &SharedState<T::Cpu>,
+) -> Result<()> {
+    let (pos, cluster, attr) = {
+        let state = state.lock_save_irq();
+        (state.pos, state.cluster, state.attr.clone())
+    };
+
+    let Some(pos) = pos else {
+        return Ok(());
+    };
+
+    let dir = RawDir::new(fs, pos.dir)?;
+    let mut entry = DirEntry::from_bytes(&dir.read(pos.index).await?);
+
+    entry.set_cluster(cluster);
+    if attr.file_type != FileType::Directory {
+        entry.size = attr.size as _;
+    }
+    (entry.mdate, entry.mtime, _) = duration_to_fat_datetime(attr.mtime);
+    (entry.adate, _, _) = duration_to_fat_datetime(attr.atime);
+
+    dir.write(pos.index, &entry.to_bytes()).await
+}
+
+/// Sets the times of the node `state` is for to those in `attr`. FAT has no
+/// owners or permissions to keep, so those are left as they are.
+pub(super) async fn set_times<T: Fat32Operations>(
+    fs: &T,
+    state: &SharedState<T::Cpu>,
+    attr: &FileAttr,
+) -> Result<()> {
+    let _guard = lock_meta(fs).await?;
+
+    {
+        let mut state = state.lock_save_irq();
+        state.attr.atime = attr.atime;
+        state.attr.mtime = attr.mtime;
+        state.attr.ctime = attr.ctime;
+    }
+
+    store_state(fs, state).await
+}
+
+/// Removes `entry` from the directory starting at `dir`, freeing its clusters
+/// unless a node for it is still in use.
+async fn remove_entry<T: Fat32Operations>(
+    fs: &T,
+    dir: Cluster,
+    entry: &Fat32DirEntry,
+) -> Result<()> {
+    RawDir::new(fs, dir)?
+        .remove(entry.first_slot, entry.offset - 1)
+        .await?;
+
+    let pos = EntryPos {
+        dir,
+        index: entry.offset - 1,
+    };
+
+    if !fs.nodes().lock_save_irq().unlinked(pos) && entry.cluster.is_valid() {
+        fs.truncate_chain(entry.cluster, 0).await?;
+    }
+
+    Ok(())
+}
+
 struct Fat32DirEntry {
     attr: FileAttr,
     cluster: Cluster,
     name: String,
     offset: u64,
+    /// The index of the entry's first slot, which is its first long filename
+    /// entry if it has any.
+    first_slot: u64,
+    entry: DirEntry,
 }
 
 struct Fat32DirStream<T: Fat32Operations> {
     reader: Fat32Reader<T>,
+    root: Cluster,
     offset: u64,
     lfn_buffer: Vec<u16>,
+    lfn_start: u64,
     fs_id: u64,
 }
 
@@ -135,8 +649,10 @@ impl<T: Fat32Operations> Clone for Fat32DirStream<T> {
     fn clone(&self) -> Self {
         Self {
             reader: self.reader.clone(),
+            root: self.root,
             offset: self.offset,
             lfn_buffer: self.lfn_buffer.clone(),
+            lfn_start: self.lfn_start,
             fs_id: self.fs_id,
         }
     }
@@ -152,8 +668,10 @@ impl<T: Fat32Operations> Fat32DirStream<T> {
         // end.
         Self {
             reader: Fat32Reader::new(fs, root, max_sz),
+            root,
             offset: 0,
             lfn_buffer: Vec::new(),
+            lfn_start: 0,
             fs_id,
         }
     }
@@ -171,7 +689,7 @@ impl<T: Fat32Operations> Fat32DirStream<T> {
 
             match entry_bytes[0] {
                 0x00 => return Ok(None), // End of directory, no more entries
-                0xE5 => {
+                DELETED => {
                     // Deleted entry
                     self.lfn_buffer.clear();
                     self.offset += 1;
@@ -187,6 +705,10 @@ impl<T: Fat32Operations> Fat32DirStream<T> {
                 let lfn_entry: LfnEntry =
                     unsafe { ptr::read_unaligned(entry_bytes.as_ptr() as *const _) };
 
+                if self.lfn_buffer.is_empty() {
+                    self.lfn_start = self.offset;
+                }
+
                 // LFN entries are stored backwards, so we prepend.
                 let new_chars = lfn_entry.extract_chars();
                 self.lfn_buffer.splice(0..0, new_chars);
@@ -195,8 +717,7 @@ impl<T: Fat32Operations> Fat32DirStream<T> {
                 continue;
             }
 
-            let dir_entry: DirEntry =
-                unsafe { ptr::read_unaligned(entry_bytes.as_ptr() as *const _) };
+            let dir_entry = DirEntry::from_bytes(&entry_bytes);
 
             if dir_entry.attributes.contains(Fat32Attributes::VOLUME_LABEL) {
                 self.lfn_buffer.clear();
@@ -204,45 +725,33 @@ impl<T: Fat32Operations> Fat32DirStream<T> {
                 continue;
             }
 
-            let name = if !self.lfn_buffer.is_empty() {
+            let (name, first_slot) = if !self.lfn_buffer.is_empty() {
                 let len = self
                     .lfn_buffer
                     .iter()
                     .position(|&c| c == 0x0000 || c == 0xFFFF)
                     .unwrap_or(self.lfn_buffer.len());
-                String::from_utf16_lossy(&self.lfn_buffer[..len])
+                (
+                    String::from_utf16_lossy(&self.lfn_buffer[..len]),
+                    self.lfn_start,
+                )
             } else {
                 // No LFN, parse the 8.3 name.
-                dir_entry.parse_filename()
-            };
-
-            // Process the metadata from the 8.3 entry
-            let file_type = FileType::try_from(dir_entry.attributes)?;
-            let cluster = Cluster::from_high_low(dir_entry.clust_high, dir_entry.clust_low);
-            let attr = FileAttr {
-                size: dir_entry.size as u64,
-                file_type,
-                permissions: FilePermissions::from_bits_retain(0o755),
-                atime: fat_date_to_duration(dir_entry.adate),
-                mtime: fat_datetime_to_duration(dir_entry.mdate, dir_entry.mtime, 0),
-                ctime: fat_datetime_to_duration(
-                    dir_entry.cdate,
-                    dir_entry.ctime,
-                    dir_entry.ctime_ms,
-                ),
-                ..Default::default()
+                (dir_entry.parse_filename(), self.offset)
             };
 
             self.lfn_buffer.clear();
             self.offset += 1;
 
             return Ok(Some(Fat32DirEntry {
-                attr,
-                cluster,
+                attr: dir_entry.attr(),
+                cluster: dir_entry.cluster(),
                 name,
                 // Note that the offset should be to the *next* entry, so using
                 // the advanced entry is correct.
                 offset: self.offset,
+                first_slot,
+                entry: dir_entry,
             }));
         }
     }
@@ -325,36 +834,116 @@ fn fat_datetime_to_duration(date: u16, time: u16, csecs: u8) -> Duration {
     base + Duration::from_secs(hours * 3600 + minutes * 60 + secs) + Duration::from_millis(millis)
 }
 
+/// Converts a `Duration` since the Unix epoch into FAT (date, time,
+/// centisecond) fields, clamped to the years FAT can hold, 1980 to 2107.
+fn duration_to_fat_datetime(t: Duration) -> (u16, u16, u8) {
+    const DAYS_OFFSET: u64 = 3652;
+
+    let secs = t.as_secs();
+    let Some(mut days) = (secs / 86_400).checked_sub(DAYS_OFFSET) else {
+        return (1 << 5 | 1, 0, 0);
+    };
+
+    let mut year = 1980;
+    while days >= 365 + is_leap_year(year) as u64 {
+        days -= 365 + is_leap_year(year) as u64;
+        year += 1;
+
+        if year > 2107 {
+            return (127 << 9 | 12 << 5 | 31, 23 << 11 | 59 << 5 | 29, 199);
+        }
+    }
+
+    let mut month = 1;
+    while days >= days_in_month(year, month) as u64 {
+        days -= days_in_month(year, month) as u64;
+        month += 1;
+    }
+
+    let secs_of_day = secs % 86_400;
+    let (hours, minutes, secs) = (secs_of_day / 3600, secs_of_day / 60 % 60, secs_of_day % 60);
+
+    let date = (year - 1980) << 9 | month << 5 | (days as u32 + 1);
+    let time = hours << 11 | minutes << 5 | (secs / 2);
+    let csecs = (secs % 2) * 100 + t.subsec_millis() as u64 / 10;
+
+    (date as u16, time as u16, csecs as u8)
+}
+
 #[async_trait]
 impl<T: Fat32Operations> DirStream for Fat32DirStream<T> {
     async fn next_entry(&mut self) -> Result<Option<Dirent>> {
         let entry = self.next_fat32_entry().await?;
 
-        Ok(entry.map(|x| Dirent {
-            id: InodeId::from_fsid_and_inodeid(self.fs_id, x.cluster.value() as _),
-            name: x.name.clone(),
-            file_type: x.attr.file_type,
-            offset: x.offset,
+        Ok(entry.map(|x| {
+            let pos = EntryPos {
+                dir: self.root,
+                index: x.offset - 1,
+            };
+
+            Dirent {
+                id: InodeId::from_fsid_and_inodeid(self.fs_id, pos.ino(x.cluster)),
+                name: x.name.clone(),
+                file_type: x.attr.file_type,
+                offset: x.offset,
+            }
         }))
     }
 }
 
 pub struct Fat32DirNode<T: Fat32Operations> {
-    attr: FileAttr,
-    root: Cluster,
     fs: Arc<T>,
-    streamer: Fat32DirStream<T>,
+    state: SharedState<T::Cpu>,
 }
 
 impl<T: Fat32Operations> Fat32DirNode<T> {
-    pub fn new(fs: Arc<T>, root: Cluster, attr: FileAttr) -> Self {
-        let streamer = Fat32DirStream::new(fs.clone(), root);
+    pub fn new(fs: Arc<T>, state: SharedState<T::Cpu>) -> Self {
+        Self { fs, state }
+    }
 
-        Self {
-            attr,
-            root,
-            fs,
-            streamer,
+    /// Returns the directory's first cluster, unless it's been removed.
+    fn cluster(&self) -> Result<Cluster> {
+        let state = self.state.lock_save_irq();
+
+        if state.unlinked {
+            return Err(FsError::NotFound.into());
+        }
+
+        Ok(state.cluster)
+    }
+
+    async fn find(&self, name: &str) -> Result<Option<Fat32DirEntry>> {
+        let mut dir_iter = Fat32DirStream::new(self.fs.clone(), self.cluster()?);
+
+        while let Some(entry) = dir_iter.next_fat32_entry().await? {
+            if entry.name.eq_ignore_ascii_case(name) {
+                return Ok(Some(entry));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Returns a node for the entry at `index` in this directory, which
+    /// starts at `dir`, sharing the state of any other node for it.
+    fn node(&self, dir: Cluster, index: u64, cluster: Cluster, attr: FileAttr) -> Arc<dyn Inode> {
+        let pos = EntryPos { dir, index };
+        let file_type = attr.file_type;
+
+        // A `..` entry leading to the root directory has no cluster.
+        let cluster = if file_type == FileType::Directory && !cluster.is_valid() {
+            self.fs.root_cluster()
+        } else {
+            cluster
+        };
+
+        let state = self.fs.nodes().lock_save_irq().get_or_insert_with(pos, || {
+            NodeState::new(self.fs.id(), Some(pos), cluster, attr)
+        });
+
+        match file_type {
+            FileType::Directory => Arc::new(Self::new(self.fs.clone(), state)),
+            _ => Arc::new(Fat32FileNode::new(self.fs.clone(), state)),
         }
     }
 }
@@ -362,35 +951,172 @@ impl<T: Fat32Operations> Fat32DirNode<T> {
 #[async_trait]
 impl<T: Fat32Operations> Inode for Fat32DirNode<T> {
     fn id(&self) -> InodeId {
-        InodeId::from_fsid_and_inodeid(self.fs.id(), self.root.value() as _)
+        self.state.lock_save_irq().attr.id
     }
 
     async fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
-        let mut dir_iter = self.streamer.clone();
+        let entry = self.find(name).await?.ok_or(FsError::NotFound)?;
 
-        while let Some(entry) = dir_iter.next_fat32_entry().await? {
-            if entry.name.eq_ignore_ascii_case(name) {
-                return match entry.attr.file_type {
-                    FileType::File => Ok(Arc::new(Fat32FileNode::new(
-                        self.fs.clone(),
-                        entry.cluster,
-                        entry.attr.clone(),
-                    )?)),
-                    FileType::Directory => Ok(Arc::new(Self::new(
-                        self.fs.clone(),
-                        entry.cluster,
-                        entry.attr.clone(),
-                    ))),
-                    _ => Err(KernelError::NotSupported),
-                };
+        Ok(self.node(self.cluster()?, entry.offset - 1, entry.cluster, entry.attr))
+    }
+
+    async fn create(
+        &self,
+        name: &str,
+        file_type: FileType,
+        _permissions: FilePermissions,
+        time: Option<Duration>,
+    ) -> Result<Arc<dyn Inode>> {
+        check_name(name)?;
+
+        let _guard = lock_meta(&*self.fs).await?;
+        let dir = self.cluster()?;
+
+        if self.find(name).await?.is_some() {
+            return Err(FsError::AlreadyExists.into());
+        }
+
+        let (attributes, cluster) = match file_type {
+            FileType::File => (Fat32Attributes::ARCHIVE, Cluster(0)),
+            FileType::Directory => (
+                Fat32Attributes::DIRECTORY,
+                self.fs.alloc_cluster(None).await?,
+            ),
+            _ => return Err(KernelError::NotSupported),
+        };
+
+        let time = time.unwrap_or_default();
+        let entry = DirEntry::new(attributes, cluster, time);
+
+        let added = async {
+            if file_type == FileType::Directory {
+                write_dots(&*self.fs, cluster, dir, time).await?;
+            }
+
+            RawDir::new(&*self.fs, dir)?.add(name, entry).await
+        }
+        .await;
+
+        let index = match added {
+            Ok(index) => index,
+            Err(e) => {
+                if cluster.is_valid() {
+                    self.fs.truncate_chain(cluster, 0).await?;
+                }
+
+                return Err(e);
+            }
+        };
+
+        Ok(self.node(dir, index, cluster, entry.attr()))
+    }
+
+    async fn unlink(&self, name: &str) -> Result<()> {
+        let _guard = lock_meta(&*self.fs).await?;
+        let dir = self.cluster()?;
+        let entry = self.find(name).await?.ok_or(FsError::NotFound)?;
+
+        if entry.attr.file_type == FileType::Directory
+            && !dir_is_empty(&self.fs, entry.cluster).await?
+        {
+            return Err(FsError::DirectoryNotEmpty.into());
+        }
+
+        remove_entry(&*self.fs, dir, &entry).await
+    }
+
+    async fn rename_from(
+        &self,
+        old_parent: Arc<dyn Inode>,
+        old_name: &str,
+        new_name: &str,
+        no_replace: bool,
+    ) -> Result<()> {
+        let old_parent = Arc::downcast::<Self>(old_parent).map_err(|_| FsError::CrossDevice)?;
+
+        if !Arc::ptr_eq(&old_parent.fs, &self.fs) {
+            return Err(FsError::CrossDevice.into());
+        }
+
+        check_name(new_name)?;
+
+        let _guard = lock_meta(&*self.fs).await?;
+        let (old_dir, new_dir) = (old_parent.cluster()?, self.cluster()?);
+        let src = old_parent.find(old_name).await?.ok_or(FsError::NotFound)?;
+        let src_pos = EntryPos {
+            dir: old_dir,
+            index: src.offset - 1,
+        };
+        let src_is_dir = src.attr.file_type == FileType::Directory;
+
+        // A directory can't be moved below itself.
+        if src_is_dir && old_dir != new_dir && is_within(&*self.fs, new_dir, src.cluster).await? {
+            return Err(FsError::InvalidInput.into());
+        }
+
+        if let Some(dst) = self.find(new_name).await? {
+            let dst_pos = EntryPos {
+                dir: new_dir,
+                index: dst.offset - 1,
+            };
+
+            // Names differing only in case are the same entry, which is only
+            // renamed if the case changes.
+            if dst_pos == src_pos {
+                if src.name == new_name {
+                    return Ok(());
+                }
+            } else {
+                if no_replace {
+                    return Err(FsError::AlreadyExists.into());
+                }
+
+                match (src_is_dir, dst.attr.file_type == FileType::Directory) {
+                    (true, true) => {
+                        if !dir_is_empty(&self.fs, dst.cluster).await? {
+                            return Err(FsError::DirectoryNotEmpty.into());
+                        }
+                    }
+                    (false, true) => return Err(FsError::IsADirectory.into()),
+                    (true, false) => return Err(FsError::NotADirectory.into()),
+                    (false, false) => {}
+                }
+
+                remove_entry(&*self.fs, new_dir, &dst).await?;
+            }
+        }
+
+        let index = RawDir::new(&*self.fs, new_dir)?
+            .add(new_name, src.entry)
+            .await?;
+        RawDir::new(&*self.fs, old_dir)?
+            .remove(src.first_slot, src.offset - 1)
+            .await?;
+
+        if src_is_dir && old_dir != new_dir {
+            // Point the directory's `..` entry at its new parent.
+            let moved = RawDir::new(&*self.fs, src.cluster)?;
+            let mut dotdot = DirEntry::from_bytes(&moved.read(1).await?);
+
+            if dotdot.short_name() == *b"..         " {
+                dotdot.set_cluster(parent_link(&*self.fs, new_dir));
+                moved.write(1, &dotdot.to_bytes()).await?;
             }
         }
 
-        Err(FsError::NotFound.into())
+        self.fs.nodes().lock_save_irq().moved(
+            src_pos,
+            EntryPos {
+                dir: new_dir,
+                index,
+            },
+        );
+
+        Ok(())
     }
 
     async fn readdir(&self, start_offset: u64) -> Result<Box<dyn DirStream>> {
-        let mut iter = self.streamer.clone();
+        let mut iter = Fat32DirStream::new(self.fs.clone(), self.cluster()?);
 
         iter.advance(start_offset);
 
@@ -398,7 +1124,11 @@ impl<T: Fat32Operations> Inode for Fat32DirNode<T> {
     }
 
     async fn getattr(&self) -> Result<FileAttr> {
-        Ok(self.attr.clone())
+        Ok(self.state.lock_save_irq().attr.clone())
+    }
+
+    async fn setattr(&self, attr: FileAttr) -> Result<()> {
+        set_times(&*self.fs, &self.state, &attr).await
     }
 
     fn as_any(&self) -> &dyn Any {
@@ -758,4 +1488,65 @@ mod test {
         assert_eq!(entries[1].name, "my notes.md");
         assert_eq!(entries[1].cluster, Cluster(4));
     }
+
+    #[test]
+    fn test_short_names() {
+        assert_eq!(
+            exact_short_name("readme.txt"),
+            Some((*b"README  TXT", NT_LOWER_NAME | NT_LOWER_EXT))
+        );
+        assert_eq!(exact_short_name("KERNEL"), Some((*b"KERNEL     ", 0)));
+        assert_eq!(exact_short_name("Readme.txt"), None);
+        assert_eq!(exact_short_name("a.b.c"), None);
+        assert_eq!(exact_short_name("longer name.txt"), None);
+
+        let mut taken = BTreeSet::new();
+        let short = generate_short_name("A rather long name.text", &taken).unwrap();
+        assert_eq!(&short, b"ARATHE~1TEX");
+
+        taken.insert(short);
+        let short = generate_short_name("A rather long name.text", &taken).unwrap();
+        assert_eq!(&short, b"ARATHE~2TEX");
+
+        assert_eq!(
+            &generate_short_name("Mixed.Txt", &BTreeSet::new()).unwrap(),
+            b"MIXED   TXT"
+        );
+        assert_eq!(
+            &generate_short_name("caf\u{e9}+1", &BTreeSet::new()).unwrap(),
+            b"CAF__1     "
+        );
+
+        assert_eq!(checksum(b"TESTFI~1TXT"), checksum_83(b"TESTFI~1", b"TXT"));
+    }
+
+    #[test]
+    fn test_long_name_entries() {
+        let name = "a very long filename indeed.log";
+        assert_eq!(
+            LfnEntry::encode(name, 0x42),
+            LfnBuilder::new(name, 0x42).build()
+        );
+
+        // A name that fills its entries exactly isn't terminated.
+        let entries = LfnEntry::encode("thirteen char", 0x42);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0][0], 0x41);
+        assert_eq!(&entries[0][28..], &[b'a', 0, b'r', 0]);
+    }
+
+    #[test]
+    fn test_datetime_round_trip() {
+        // 2024-02-29 13:45:31.250
+        let time = Duration::from_millis(1_709_214_331_250);
+        let (date, fat_time, csecs) = duration_to_fat_datetime(time);
+        assert_eq!(fat_datetime_to_duration(date, fat_time, csecs), time);
+        assert_eq!(
+            fat_date_to_duration(date),
+            Duration::from_secs(1_709_164_800)
+        );
+
+        // Times before 1980 are clamped to its start.
+        assert_eq!(duration_to_fat_datetime(Duration::ZERO), (0x21, 0, 0));
+    }
 }
@@ -5,10 +5,11 @@ use crate::{
 
 use alloc::vec;
 use alloc::vec::Vec;
+use core::cmp::min;
 
 use super::{Cluster, bpb::BiosParameterBlock};
 
-#[derive(PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum FatEntry {
     Eoc,
     NextCluster(Cluster),
@@ -30,6 +31,20 @@ impl From<u32> for FatEntry {
     }
 }
 
+impl FatEntry {
+    /// Returns the value the entry is stored as, less the top four bits,
+    /// which are reserved.
+    pub fn to_raw(self) -> u32 {
+        match self {
+            Self::Eoc => 0x0FFFFFFF,
+            Self::NextCluster(cluster) => cluster.0,
+            Self::Bad => 0x0FFFFFF7,
+            Self::Reserved => 1,
+            Self::Free => 0,
+        }
+    }
+}
+
 #[derive(PartialEq, Eq, Debug)]
 pub struct Fat {
     data: Vec<FatEntry>,
@@ -91,13 +106,51 @@ impl Fat {
             fat.extend(
                 buf.chunks_exact(4)
                     .map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap()))
-                    .map(|v| v.into()),
+                    .map(FatEntry::from),
             );
         }
 
         Ok(Self { data: fat })
     }
 
+    /// Returns how many of the first `count` data clusters are free.
+    pub fn free_clusters(&self, count: usize) -> usize {
+        self.data
+            .iter()
+            .skip(2)
+            .take(count)
+            .filter(|entry| **entry == FatEntry::Free)
+            .count()
+    }
+
+    /// Returns how many entries the FAT has, including those for the two
+    /// reserved clusters and any past the last data cluster.
+    pub fn num_entries(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Returns the first free one of the `count` data clusters, looking from
+    /// `hint` onwards and then from the start.
+    pub fn find_free(&self, hint: Cluster, count: usize) -> Option<Cluster> {
+        let end = min(count.saturating_add(2), self.data.len());
+        let hint = hint.value().clamp(2, end);
+
+        (hint..end)
+            .chain(2..hint)
+            .find(|&n| self.data[n] == FatEntry::Free)
+            .map(|n| Cluster(n as _))
+    }
+
+    /// Sets the entry for `cluster`.
+    pub fn set(&mut self, cluster: Cluster, entry: FatEntry) -> Result<()> {
+        *self
+            .data
+            .get_mut(cluster.value())
+            .ok_or(IoError::OutOfBounds)? = entry;
+
+        Ok(())
+    }
+
     pub fn get_cluster_chain(&self, root: Cluster) -> impl Iterator<Item = Result<Cluster>> {
         ClusterChainIterator {
             fat: self,
@@ -144,6 +197,10 @@ mod test {
             1
         }
 
+        fn num_blocks(&self) -> u64 {
+            self.data.len() as _
+        }
+
         /// Flushes any caches to the underlying device.
         async fn sync(&self) -> Result<()> {
             unimplemented!()
@@ -253,6 +310,38 @@ mod test {
         Fat { data }
     }
 
+    #[test]
+    fn test_free_clusters() {
+        let fat = setup_chain_test_fat();
+
+        assert_eq!(fat.free_clusters(usize::MAX), 1);
+        assert_eq!(fat.free_clusters(10), 0);
+        // Clusters 0 and 1 aren't data clusters, even if they read as free.
+        assert_eq!(fat.free_clusters(0), 0);
+    }
+
+    #[test]
+    fn test_find_free() {
+        let mut fat = setup_chain_test_fat();
+
+        assert_eq!(fat.find_free(Cluster(2), usize::MAX), Some(Cluster(12)));
+        // The search wraps round to the start.
+        assert_eq!(fat.find_free(Cluster(13), usize::MAX), Some(Cluster(12)));
+        // Cluster 12 isn't one of the first ten data clusters.
+        assert_eq!(fat.find_free(Cluster(2), 10), None);
+
+        fat.set(Cluster(12), FatEntry::Eoc).unwrap();
+        assert_eq!(fat.find_free(Cluster(2), usize::MAX), None);
+        assert!(fat.set(Cluster(100), FatEntry::Free).is_err());
+    }
+
+    #[test]
+    fn test_entry_round_trip() {
+        for raw in [FREE, RESERVED, 5, BAD & 0x0FFFFFFF, EOC & 0x0FFFFFFF] {
+            assert_eq!(FatEntry::from(raw).to_raw(), raw);
+        }
+    }
+
     #[test]
     fn test_chain_single_cluster() {
         let fat = setup_chain_test_fat();
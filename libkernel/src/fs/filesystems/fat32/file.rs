@@ -1,44 +1,167 @@
 use crate::{
-    error::Result,
+    error::{FsError, Result},
     fs::{Inode, InodeId, attr::FileAttr},
 };
-use alloc::boxed::Box;
-use alloc::sync::Arc;
+use alloc::{boxed::Box, sync::Arc, vec, vec::Vec};
 use async_trait::async_trait;
 use core::any::Any;
 
-use super::{Cluster, Fat32Operations, reader::Fat32Reader};
+use super::{
+    Cluster, Fat32Operations, SharedState,
+    dir::{set_times, store_state},
+    lock_meta,
+    reader::{Fat32Reader, write_chain},
+};
+
+/// The largest a file can be, as its size is kept in 32 bits.
+const MAX_FILE_SIZE: u64 = u32::MAX as u64;
 
 pub struct Fat32FileNode<T: Fat32Operations> {
-    reader: Fat32Reader<T>,
-    attr: FileAttr,
-    id: InodeId,
+    fs: Arc<T>,
+    state: SharedState<T::Cpu>,
 }
 
 impl<T: Fat32Operations> Fat32FileNode<T> {
-    pub fn new(fs: Arc<T>, root: Cluster, attr: FileAttr) -> Result<Self> {
-        let id = InodeId::from_fsid_and_inodeid(fs.id() as _, root.value() as _);
+    pub fn new(fs: Arc<T>, state: SharedState<T::Cpu>) -> Self {
+        Self { fs, state }
+    }
+
+    /// Returns the clusters of the file starting at `cluster`, of which there
+    /// are none if it's empty.
+    fn chain(&self, cluster: Cluster) -> Result<Vec<Cluster>> {
+        if cluster.is_valid() {
+            self.fs.iter_clusters(cluster).collect()
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    /// Lengthens `chain` to hold `new_size` bytes, and zeroes what lies past
+    /// the file's old end, `old_size`, in its last cluster. New clusters are
+    /// zeroed already.
+    async fn extend(&self, chain: &mut Vec<Cluster>, old_size: u64, new_size: u64) -> Result<()> {
+        let bpc = self.fs.bytes_per_cluster() as u64;
+
+        while (chain.len() as u64) * bpc < new_size {
+            let cluster = self.fs.alloc_cluster(chain.last().copied()).await?;
+            chain.push(cluster);
+        }
+
+        let tail_end = new_size.min(old_size.next_multiple_of(bpc));
+
+        if tail_end > old_size {
+            write_chain(
+                &*self.fs,
+                chain,
+                old_size,
+                &vec![0; (tail_end - old_size) as usize],
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Records the file's new size, on `chain`.
+    async fn commit(&self, chain: &[Cluster], size: u64) -> Result<()> {
+        {
+            let mut state = self.state.lock_save_irq();
+            state.cluster = chain.first().copied().unwrap_or(Cluster(0));
+            state.attr.size = size;
+        }
 
-        Ok(Self {
-            reader: Fat32Reader::new(fs, root, attr.size),
-            attr,
-            id,
-        })
+        store_state(&*self.fs, &self.state).await
     }
 }
 
 #[async_trait]
 impl<T: Fat32Operations> Inode for Fat32FileNode<T> {
     fn id(&self) -> InodeId {
-        self.id
+        self.state.lock_save_irq().attr.id
     }
 
     async fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
-        self.reader.read_at(offset, buf).await
+        let (cluster, size) = {
+            let state = self.state.lock_save_irq();
+            (state.cluster, state.attr.size)
+        };
+
+        if !cluster.is_valid() {
+            return Ok(0);
+        }
+
+        Fat32Reader::new(self.fs.clone(), cluster, size)
+            .read_at(offset, buf)
+            .await
+    }
+
+    async fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let end = offset
+            .checked_add(buf.len() as u64)
+            .filter(|&end| end <= MAX_FILE_SIZE)
+            .ok_or(FsError::FileTooLarge)?;
+
+        let _guard = lock_meta(&*self.fs).await?;
+        let (cluster, size) = {
+            let state = self.state.lock_save_irq();
+            (state.cluster, state.attr.size)
+        };
+        let mut chain = self.chain(cluster)?;
+
+        if end > size {
+            self.extend(&mut chain, size, end).await?;
+        }
+
+        write_chain(&*self.fs, &chain, offset, buf).await?;
+
+        if end > size {
+            self.commit(&chain, end).await?;
+        }
+
+        Ok(buf.len())
+    }
+
+    async fn truncate(&self, size: u64) -> Result<()> {
+        if size > MAX_FILE_SIZE {
+            return Err(FsError::FileTooLarge.into());
+        }
+
+        let _guard = lock_meta(&*self.fs).await?;
+        let (cluster, old_size) = {
+            let state = self.state.lock_save_irq();
+            (state.cluster, state.attr.size)
+        };
+
+        if size == old_size {
+            return Ok(());
+        }
+
+        let mut chain = self.chain(cluster)?;
+
+        if size > old_size {
+            self.extend(&mut chain, old_size, size).await?;
+        } else {
+            let keep = size.div_ceil(self.fs.bytes_per_cluster() as u64) as usize;
+
+            if let Some(&first) = chain.first() {
+                self.fs.truncate_chain(first, keep).await?;
+            }
+            chain.truncate(keep);
+        }
+
+        self.commit(&chain, size).await
     }
 
     async fn getattr(&self) -> Result<FileAttr> {
-        Ok(self.attr.clone())
+        Ok(self.state.lock_save_irq().attr.clone())
+    }
+
+    async fn setattr(&self, attr: FileAttr) -> Result<()> {
+        set_times(&*self.fs, &self.state, &attr).await
     }
 
     fn as_any(&self) -> &dyn Any {
@@ -48,7 +171,12 @@ impl<T: Fat32Operations> Inode for Fat32FileNode<T> {
 
 #[cfg(test)]
 pub mod test {
-    use crate::{error::FsError, fs::filesystems::fat32::Sector};
+    use crate::{
+        error::FsError,
+        fs::filesystems::fat32::{NodeCache, NodeState, Sector},
+        sync::{mutex::Mutex, spinlock::SpinLockIrq},
+        test::MockCpuOps,
+    };
 
     use super::*;
     use alloc::{collections::BTreeMap, sync::Arc, vec};
@@ -57,6 +185,8 @@ pub mod test {
         file_data: BTreeMap<u32, Vec<u8>>, // Map Sector(u32) -> data
         sector_size: usize,
         sectors_per_cluster: usize,
+        meta: Mutex<(), MockCpuOps>,
+        nodes: SpinLockIrq<NodeCache<MockCpuOps>, MockCpuOps>,
     }
 
     impl MockFs {
@@ -75,11 +205,15 @@ pub mod test {
                 file_data,
                 sector_size,
                 sectors_per_cluster,
+                meta: Mutex::new(()),
+                nodes: SpinLockIrq::new(NodeCache::new()),
             }
         }
     }
 
     impl Fat32Operations for MockFs {
+        type Cpu = MockCpuOps;
+
         async fn read_sector(
             &self,
             sector: Sector,
@@ -93,6 +227,15 @@ pub mod test {
             Ok(read_size)
         }
 
+        async fn write_sector(
+            &self,
+            _sector: Sector,
+            _offset: usize,
+            _buf: &[u8],
+        ) -> Result<usize> {
+            unimplemented!()
+        }
+
         fn id(&self) -> u64 {
             0
         }
@@ -120,19 +263,41 @@ pub mod test {
                 (self.file_data.len() + self.sectors_per_cluster - 1) / self.sectors_per_cluster;
             (0..num_clusters).map(move |i| Ok(Cluster((root.value() + i) as u32)))
         }
+
+        fn root_cluster(&self) -> Cluster {
+            Cluster(2)
+        }
+
+        fn meta_lock(&self) -> &Mutex<(), MockCpuOps> {
+            &self.meta
+        }
+
+        fn nodes(&self) -> &SpinLockIrq<NodeCache<MockCpuOps>, MockCpuOps> {
+            &self.nodes
+        }
+
+        async fn alloc_cluster(&self, _prev: Option<Cluster>) -> Result<Cluster> {
+            unimplemented!()
+        }
+
+        async fn truncate_chain(&self, _root: Cluster, _keep: usize) -> Result<()> {
+            unimplemented!()
+        }
     }
 
     async fn setup_file_test(content: &[u8]) -> Fat32FileNode<MockFs> {
         let fs = Arc::new(MockFs::new(content, 512, 4));
-        Fat32FileNode::new(
-            fs,
+        let state = NodeState::new(
+            0,
+            None,
             Cluster(2),
             FileAttr {
                 size: content.len() as _,
                 ..FileAttr::default()
             },
-        )
-        .unwrap()
+        );
+
+        Fat32FileNode::new(fs, Arc::new(SpinLockIrq::new(state)))
     }
 
     #[tokio::test]
@@ -1,12 +1,26 @@
 //! FAT32 filesystem driver.
+//!
+//! Files can be read and written, and directories changed, with names that
+//! don't fit the 8.3 format kept in VFAT long filename entries beside a
+//! generated short name. A file's size and first cluster live in its directory
+//! entry, so every node for a file shares one [`NodeState`], found by where
+//! that entry is, which follows the file when it's renamed.
 
 use crate::{
+    CpuOps,
     error::{FsError, Result},
-    fs::{FileType, Filesystem, Inode, InodeId, attr::FileAttr, blk::buffer::BlockBuffer},
+    fs::{FileType, Filesystem, FsStats, Inode, InodeId, attr::FileAttr, blk::buffer::BlockBuffer},
+    sync::{
+        mutex::{AsyncMutexGuard, Mutex},
+        spinlock::SpinLockIrq,
+    },
 };
 use alloc::{
     boxed::Box,
+    collections::btree_map::BTreeMap,
     sync::{Arc, Weak},
+    vec,
+    vec::Vec,
 };
 use async_trait::async_trait;
 use bpb::BiosParameterBlock;
@@ -14,9 +28,10 @@ use core::{
     cmp::min,
     fmt::Display,
     ops::{Add, Mul},
+    sync::atomic::{AtomicU32, Ordering},
 };
 use dir::Fat32DirNode;
-use fat::Fat;
+use fat::{Fat, FatEntry};
 use log::warn;
 
 mod bpb;
@@ -71,6 +86,11 @@ impl Cluster {
     pub fn is_valid(self) -> bool {
         self.0 >= 2
     }
+
+    /// Returns the high and low 16-bit halves of the cluster number.
+    pub fn to_high_low(self) -> (u16, u16) {
+        ((self.0 >> 16) as u16, self.0 as u16)
+    }
 }
 
 impl Display for Cluster {
@@ -79,16 +99,162 @@ impl Display for Cluster {
     }
 }
 
+/// The signatures at the start of the FSInfo sector, and before its counts.
+const FSINFO_LEAD_SIG: u32 = 0x41615252;
+const FSINFO_STRUC_SIG: u32 = 0x61417272;
+const FSINFO_FREE_COUNT: u64 = 488;
+const FSINFO_NEXT_FREE: u64 = 492;
+
+/// Where a node's 8.3 entry is: the first cluster of the directory holding it,
+/// and its index among that directory's 32-byte entries.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct EntryPos {
+    dir: Cluster,
+    index: u64,
+}
+
+impl EntryPos {
+    /// Returns the inode number of the node whose entry is here, which is its
+    /// first cluster if it has one. Empty files have no clusters, so they're
+    /// told apart by where their entries are instead.
+    fn ino(self, cluster: Cluster) -> u64 {
+        if cluster.is_valid() {
+            cluster.value() as _
+        } else {
+            (self.dir.0 as u64) << 32 | self.index
+        }
+    }
+}
+
+/// What's known of a file or directory, shared by every node for it.
+pub struct NodeState {
+    /// Where the node's entry is. The root directory, and nodes that have been
+    /// unlinked, have none.
+    pos: Option<EntryPos>,
+    unlinked: bool,
+    /// The first cluster, which is 0 for an empty file.
+    cluster: Cluster,
+    attr: FileAttr,
+}
+
+impl NodeState {
+    fn new(fs_id: u64, pos: Option<EntryPos>, cluster: Cluster, mut attr: FileAttr) -> Self {
+        let ino = pos.map_or(cluster.value() as _, |pos| pos.ino(cluster));
+        attr.id = InodeId::from_fsid_and_inodeid(fs_id, ino);
+
+        Self {
+            pos,
+            unlinked: false,
+            cluster,
+            attr,
+        }
+    }
+}
+
+type SharedState<CPU> = Arc<SpinLockIrq<NodeState, CPU>>;
+
+/// The states of the nodes in use, by where their entries are, and of those
+/// unlinked while they were in use.
+pub struct NodeCache<CPU: CpuOps> {
+    by_pos: BTreeMap<EntryPos, Weak<SpinLockIrq<NodeState, CPU>>>,
+    /// Unlinked nodes, whose clusters are freed once nothing else holds them.
+    orphans: Vec<SharedState<CPU>>,
+}
+
+impl<CPU: CpuOps> NodeCache<CPU> {
+    const fn new() -> Self {
+        Self {
+            by_pos: BTreeMap::new(),
+            orphans: Vec::new(),
+        }
+    }
+
+    /// Returns the state of the node whose entry is at `pos`, which `new`
+    /// makes if no node for it is in use.
+    fn get_or_insert_with(
+        &mut self,
+        pos: EntryPos,
+        new: impl FnOnce() -> NodeState,
+    ) -> SharedState<CPU> {
+        if let Some(state) = self.by_pos.get(&pos).and_then(Weak::upgrade) {
+            return state;
+        }
+
+        self.by_pos.retain(|_, state| state.strong_count() > 0);
+
+        let state = Arc::new(SpinLockIrq::new(new()));
+        self.by_pos.insert(pos, Arc::downgrade(&state));
+        state
+    }
+
+    /// Notes that the entry at `from` has moved to `to`.
+    fn moved(&mut self, from: EntryPos, to: EntryPos) {
+        if let Some(state) = self.by_pos.remove(&from) {
+            if let Some(state) = state.upgrade() {
+                state.lock_save_irq().pos = Some(to);
+            }
+
+            self.by_pos.insert(to, state);
+        }
+    }
+
+    /// Notes that the entry at `pos` has been removed, returning whether a node
+    /// for it is still in use, in which case its clusters mustn't be freed yet.
+    fn unlinked(&mut self, pos: EntryPos) -> bool {
+        let Some(state) = self.by_pos.remove(&pos).and_then(|state| state.upgrade()) else {
+            return false;
+        };
+
+        {
+            let mut state = state.lock_save_irq();
+            state.pos = None;
+            state.unlinked = true;
+        }
+
+        self.orphans.push(state);
+        true
+    }
+
+    /// Forgets the unlinked nodes no longer in use, returning the first
+    /// clusters of those which had any, which are now free to go.
+    fn reap(&mut self) -> Vec<Cluster> {
+        let mut freed = Vec::new();
+
+        self.orphans.retain(|state| {
+            if Arc::strong_count(state) > 1 {
+                return true;
+            }
+
+            let cluster = state.lock_save_irq().cluster;
+            if cluster.is_valid() {
+                freed.push(cluster);
+            }
+
+            false
+        });
+
+        freed
+    }
+}
+
 /// A mounted FAT32 filesystem instance.
-pub struct Fat32Filesystem {
+pub struct Fat32Filesystem<CPU: CpuOps> {
     dev: BlockBuffer,
     bpb: BiosParameterBlock,
-    fat: Fat,
+    fat: SpinLockIrq<Fat, CPU>,
+    /// Where to start looking for a free cluster.
+    next_free: AtomicU32,
+    /// Held while the FAT or a directory is changed.
+    meta: Mutex<(), CPU>,
+    nodes: SpinLockIrq<NodeCache<CPU>, CPU>,
     id: u64,
     this: Weak<Self>,
 }
 
-impl Fat32Filesystem {
+impl<CPU> Fat32Filesystem<CPU>
+where
+    CPU: CpuOps + Send + Sync,
+{
     /// Creates a new FAT32 filesystem from the given block device buffer.
     pub async fn new(dev: BlockBuffer, id: u64) -> Result<Arc<Self>> {
         let bpb = BiosParameterBlock::new(&dev).await?;
@@ -106,14 +272,71 @@ impl Fat32Filesystem {
         Ok(Arc::new_cyclic(|weak| Self {
             bpb,
             dev,
-            fat,
+            fat: SpinLockIrq::new(fat),
+            next_free: AtomicU32::new(2),
+            meta: Mutex::new(()),
+            nodes: SpinLockIrq::new(NodeCache::new()),
             this: weak.clone(),
             id,
         }))
     }
+
+    /// Writes `entry` for `cluster` to every copy of the FAT, keeping the top
+    /// four bits of what was there.
+    async fn write_fat_entry(&self, cluster: Cluster, entry: FatEntry) -> Result<()> {
+        for fat_num in 0..self.bpb.num_fats as usize {
+            let (start, _) = self.bpb.fat_region(fat_num).ok_or(FsError::InvalidFs)?;
+            let offset = self.bpb.sector_offset(start) + cluster.value() as u64 * 4;
+
+            let mut raw = [0; 4];
+            self.dev.read_at(offset, &mut raw).await?;
+            let raw = u32::from_le_bytes(raw) & 0xF0000000 | entry.to_raw();
+
+            self.dev.write_at(offset, &raw.to_le_bytes()).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Brings the free cluster count and hint in the FSInfo sector up to date,
+    /// if the volume has one.
+    async fn write_fsinfo(&self) -> Result<()> {
+        let sector = self.bpb.fsinfo_sector;
+
+        if sector == 0 || sector == 0xFFFF {
+            return Ok(());
+        }
+
+        let offset = self.bpb.sector_offset(Sector(sector as _));
+        let mut lead = [0; 4];
+        let mut struc = [0; 4];
+        self.dev.read_at(offset, &mut lead).await?;
+        self.dev.read_at(offset + 484, &mut struc).await?;
+
+        if u32::from_le_bytes(lead) != FSINFO_LEAD_SIG
+            || u32::from_le_bytes(struc) != FSINFO_STRUC_SIG
+        {
+            return Ok(());
+        }
+
+        let free = self
+            .fat
+            .lock_save_irq()
+            .free_clusters(self.bpb.cluster_count()) as u32;
+        let next_free = self.next_free.load(Ordering::Relaxed);
+
+        self.dev
+            .write_at(offset + FSINFO_FREE_COUNT, &free.to_le_bytes())
+            .await?;
+        self.dev
+            .write_at(offset + FSINFO_NEXT_FREE, &next_free.to_le_bytes())
+            .await
+    }
 }
 
 trait Fat32Operations: Send + Sync + 'static {
+    type Cpu: CpuOps + Send + Sync;
+
     fn read_sector(
         &self,
         sector: Sector,
@@ -121,6 +344,13 @@ trait Fat32Operations: Send + Sync + 'static {
         buf: &mut [u8],
     ) -> impl Future<Output = Result<usize>> + Send;
 
+    fn write_sector(
+        &self,
+        sector: Sector,
+        offset: usize,
+        buf: &[u8],
+    ) -> impl Future<Output = Result<usize>> + Send;
+
     fn id(&self) -> u64;
     fn sector_size(&self) -> usize;
     fn sectors_per_cluster(&self) -> usize;
@@ -131,9 +361,44 @@ trait Fat32Operations: Send + Sync + 'static {
 
     fn cluster_to_sectors(&self, cluster: Cluster) -> Result<impl Iterator<Item = Sector> + Send>;
     fn iter_clusters(&self, root: Cluster) -> impl Iterator<Item = Result<Cluster>> + Send;
+
+    /// Returns the first cluster of the root directory.
+    fn root_cluster(&self) -> Cluster;
+
+    /// Returns the lock held while the FAT or a directory is changed.
+    fn meta_lock(&self) -> &Mutex<(), Self::Cpu>;
+
+    fn nodes(&self) -> &SpinLockIrq<NodeCache<Self::Cpu>, Self::Cpu>;
+
+    /// Allocates a zeroed cluster, putting it on the end of the chain that
+    /// ends with `prev`, if there is one.
+    fn alloc_cluster(&self, prev: Option<Cluster>) -> impl Future<Output = Result<Cluster>> + Send;
+
+    /// Cuts the chain starting at `root` down to its first `keep` clusters,
+    /// freeing the rest.
+    fn truncate_chain(&self, root: Cluster, keep: usize)
+    -> impl Future<Output = Result<()>> + Send;
+}
+
+/// Takes the lock held while the FAT or a directory is changed, first freeing
+/// the clusters of any unlinked nodes that are no longer in use.
+async fn lock_meta<T: Fat32Operations>(fs: &T) -> Result<AsyncMutexGuard<'_, (), T::Cpu>> {
+    let guard = fs.meta_lock().lock().await;
+    let freed = fs.nodes().lock_save_irq().reap();
+
+    for cluster in freed {
+        fs.truncate_chain(cluster, 0).await?;
+    }
+
+    Ok(guard)
 }
 
-impl Fat32Operations for Fat32Filesystem {
+impl<CPU> Fat32Operations for Fat32Filesystem<CPU>
+where
+    CPU: CpuOps + Send + Sync,
+{
+    type Cpu = CPU;
+
     async fn read_sector(&self, sector: Sector, offset: usize, buf: &mut [u8]) -> Result<usize> {
         debug_assert!(offset < self.bpb.sector_size());
 
@@ -151,6 +416,21 @@ impl Fat32Operations for Fat32Filesystem {
         Ok(read_sz)
     }
 
+    async fn write_sector(&self, sector: Sector, offset: usize, buf: &[u8]) -> Result<usize> {
+        debug_assert!(offset < self.bpb.sector_size());
+
+        let write_sz = min(buf.len(), self.bpb.sector_size() - offset);
+
+        self.dev
+            .write_at(
+                self.bpb.sector_offset(sector) + offset as u64,
+                &buf[..write_sz],
+            )
+            .await?;
+
+        Ok(write_sz)
+    }
+
     fn id(&self) -> u64 {
         self.id
     }
@@ -168,12 +448,110 @@ impl Fat32Operations for Fat32Filesystem {
     }
 
     fn iter_clusters(&self, root: Cluster) -> impl Iterator<Item = Result<Cluster>> {
-        self.fat.get_cluster_chain(root)
+        let fat = self.fat.lock_save_irq();
+
+        // No chain is longer than the FAT, so stopping there guards against
+        // cycles.
+        fat.get_cluster_chain(root)
+            .take(fat.num_entries())
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    fn root_cluster(&self) -> Cluster {
+        self.bpb.root_cluster
+    }
+
+    fn meta_lock(&self) -> &Mutex<(), CPU> {
+        &self.meta
+    }
+
+    fn nodes(&self) -> &SpinLockIrq<NodeCache<CPU>, CPU> {
+        &self.nodes
+    }
+
+    async fn alloc_cluster(&self, prev: Option<Cluster>) -> Result<Cluster> {
+        let hint = Cluster(self.next_free.load(Ordering::Relaxed));
+        let cluster = {
+            let mut fat = self.fat.lock_save_irq();
+            let cluster = fat
+                .find_free(hint, self.bpb.cluster_count())
+                .ok_or(FsError::NoSpace)?;
+
+            fat.set(cluster, FatEntry::Eoc)?;
+            if let Some(prev) = prev {
+                fat.set(prev, FatEntry::NextCluster(cluster))?;
+            }
+
+            cluster
+        };
+
+        self.next_free.store(cluster.0 + 1, Ordering::Relaxed);
+
+        // Zero the cluster before it's linked in, so the chain never takes in
+        // stale data.
+        let first = self
+            .bpb
+            .cluster_to_sectors(cluster)?
+            .next()
+            .ok_or(FsError::InvalidFs)?;
+        self.dev
+            .write_at(
+                self.bpb.sector_offset(first),
+                &vec![0; self.bytes_per_cluster()],
+            )
+            .await?;
+
+        self.write_fat_entry(cluster, FatEntry::Eoc).await?;
+        if let Some(prev) = prev {
+            self.write_fat_entry(prev, FatEntry::NextCluster(cluster))
+                .await?;
+        }
+
+        Ok(cluster)
+    }
+
+    async fn truncate_chain(&self, root: Cluster, keep: usize) -> Result<()> {
+        let chain = self.iter_clusters(root).collect::<Result<Vec<_>>>()?;
+
+        if chain.len() <= keep {
+            return Ok(());
+        }
+
+        let last = keep.checked_sub(1).map(|i| chain[i]);
+        let freed = &chain[keep..];
+
+        {
+            let mut fat = self.fat.lock_save_irq();
+
+            if let Some(last) = last {
+                fat.set(last, FatEntry::Eoc)?;
+            }
+            for &cluster in freed {
+                fat.set(cluster, FatEntry::Free)?;
+            }
+        }
+
+        if let Some(last) = last {
+            self.write_fat_entry(last, FatEntry::Eoc).await?;
+        }
+        for &cluster in freed {
+            self.write_fat_entry(cluster, FatEntry::Free).await?;
+        }
+
+        if let Some(first) = freed.iter().min() {
+            self.next_free.fetch_min(first.0, Ordering::Relaxed);
+        }
+
+        Ok(())
     }
 }
 
 #[async_trait]
-impl Filesystem for Fat32Filesystem {
+impl<CPU> Filesystem for Fat32Filesystem<CPU>
+where
+    CPU: CpuOps + Send + Sync,
+{
     fn id(&self) -> u64 {
         self.id
     }
@@ -182,16 +560,413 @@ impl Filesystem for Fat32Filesystem {
         0x4D44 // MSDOS magic number
     }
 
+    async fn statfs(&self) -> Result<FsStats> {
+        let clusters = self.bpb.cluster_count();
+        let free = self.fat.lock_save_irq().free_clusters(clusters) as u64;
+
+        // FAT has no inodes to count.
+        Ok(FsStats {
+            block_size: self.bytes_per_cluster() as _,
+            total_blocks: clusters as _,
+            free_blocks: free,
+            avail_blocks: free,
+            name_len: 255,
+            ..FsStats::default()
+        })
+    }
+
     /// Get the root inode of this filesystem.
     async fn root_inode(&self) -> Result<Arc<dyn Inode>> {
-        Ok(Arc::new(Fat32DirNode::new(
-            self.this.upgrade().unwrap(),
+        let state = NodeState::new(
+            self.id,
+            None,
             self.bpb.root_cluster,
             FileAttr {
-                id: InodeId::from_fsid_and_inodeid(self.id, self.bpb.root_cluster.0 as _),
                 file_type: FileType::Directory,
                 ..FileAttr::default()
             },
+        );
+
+        Ok(Arc::new(Fat32DirNode::new(
+            self.this.upgrade().unwrap(),
+            Arc::new(SpinLockIrq::new(state)),
         )))
     }
+
+    async fn sync(&self) -> Result<()> {
+        self.write_fsinfo().await?;
+        self.dev.sync().await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        error::KernelError,
+        fs::{BlockDevice, attr::FilePermissions},
+        test::MockCpuOps,
+    };
+    use alloc::{string::String, vec::Vec};
+    use std::sync::Mutex as StdMutex;
+
+    const SECTOR_SIZE: usize = 512;
+    const TOTAL_SECTORS: u32 = 4096;
+    const RESERVED_SECTORS: u16 = 32;
+    const FAT_SECTORS: u32 = 32;
+    const ROOT: u32 = 2;
+
+    #[derive(Clone)]
+    struct MemBlkDevice {
+        data: Arc<StdMutex<Vec<u8>>>,
+    }
+
+    #[async_trait]
+    impl BlockDevice for MemBlkDevice {
+        async fn read(&self, block_id: u64, buf: &mut [u8]) -> Result<()> {
+            let start = block_id as usize * SECTOR_SIZE;
+            buf.copy_from_slice(&self.data.lock().unwrap()[start..start + buf.len()]);
+            Ok(())
+        }
+
+        async fn write(&self, block_id: u64, buf: &[u8]) -> Result<()> {
+            let start = block_id as usize * SECTOR_SIZE;
+            self.data.lock().unwrap()[start..start + buf.len()].copy_from_slice(buf);
+            Ok(())
+        }
+
+        fn block_size(&self) -> usize {
+            SECTOR_SIZE
+        }
+
+        fn num_blocks(&self) -> u64 {
+            TOTAL_SECTORS as _
+        }
+
+        async fn sync(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Formats a 2 MiB volume with one sector per cluster, two FATs and an
+    /// empty root directory, as `mkfs.fat -F 32 -s 1` would.
+    fn mkfs() -> MemBlkDevice {
+        let mut data = vec![0; TOTAL_SECTORS as usize * SECTOR_SIZE];
+        let mut put = |offset: usize, bytes: &[u8]| {
+            data[offset..offset + bytes.len()].copy_from_slice(bytes);
+        };
+
+        put(0, &[0xEB, 0x58, 0x90]);
+        put(3, b"MSWIN4.1");
+        put(0x0B, &(SECTOR_SIZE as u16).to_le_bytes());
+        put(0x0D, &[1]);
+        put(0x0E, &RESERVED_SECTORS.to_le_bytes());
+        put(0x10, &[2]);
+        put(0x15, &[0xF8]);
+        put(0x20, &TOTAL_SECTORS.to_le_bytes());
+        put(0x24, &FAT_SECTORS.to_le_bytes());
+        put(0x2C, &ROOT.to_le_bytes());
+        put(0x30, &1u16.to_le_bytes());
+        put(510, &[0x55, 0xAA]);
+
+        let fsinfo = SECTOR_SIZE;
+        put(fsinfo, &FSINFO_LEAD_SIG.to_le_bytes());
+        put(fsinfo + 484, &FSINFO_STRUC_SIG.to_le_bytes());
+        put(fsinfo + 488, &u32::MAX.to_le_bytes());
+        put(fsinfo + 492, &u32::MAX.to_le_bytes());
+
+        for fat in 0..2 {
+            let start = (RESERVED_SECTORS as usize + fat * FAT_SECTORS as usize) * SECTOR_SIZE;
+            put(start, &0x0FFFFFF8u32.to_le_bytes());
+            put(start + 4, &0x0FFFFFFFu32.to_le_bytes());
+            put(start + 8, &0x0FFFFFFFu32.to_le_bytes());
+        }
+
+        MemBlkDevice {
+            data: Arc::new(StdMutex::new(data)),
+        }
+    }
+
+    async fn mount(dev: &MemBlkDevice) -> Arc<Fat32Filesystem<MockCpuOps>> {
+        Fat32Filesystem::new(BlockBuffer::new(Box::new(dev.clone())), 1)
+            .await
+            .unwrap()
+    }
+
+    async fn create(dir: &Arc<dyn Inode>, name: &str, file_type: FileType) -> Arc<dyn Inode> {
+        dir.create(
+            name,
+            file_type,
+            FilePermissions::from_bits_retain(0o644),
+            None,
+        )
+        .await
+        .unwrap()
+    }
+
+    async fn read_all(inode: &Arc<dyn Inode>) -> Vec<u8> {
+        let mut buf = vec![0; inode.getattr().await.unwrap().size as usize];
+        let len = inode.read_at(0, &mut buf).await.unwrap();
+        buf.truncate(len);
+        buf
+    }
+
+    async fn names(dir: &Arc<dyn Inode>) -> Vec<String> {
+        let mut stream = dir.readdir(0).await.unwrap();
+        let mut names = Vec::new();
+
+        while let Some(entry) = stream.next_entry().await.unwrap() {
+            names.push(entry.name);
+        }
+
+        names.sort();
+        names
+    }
+
+    async fn free_clusters(fs: &Fat32Filesystem<MockCpuOps>) -> u64 {
+        fs.statfs().await.unwrap().free_blocks
+    }
+
+    #[tokio::test]
+    async fn test_write_and_read_back() {
+        let dev = mkfs();
+        let fs = mount(&dev).await;
+        let root = fs.root_inode().await.unwrap();
+        let free = free_clusters(&fs).await;
+
+        let file = create(&root, "hello.txt", FileType::File).await;
+        let data: Vec<u8> = (0..1300).map(|i| i as u8).collect();
+        assert_eq!(file.write_at(0, &data).await.unwrap(), data.len());
+
+        // 1300 bytes take three 512-byte clusters.
+        assert_eq!(free_clusters(&fs).await, free - 3);
+        assert_eq!(read_all(&file).await, data);
+
+        // Writing past the end leaves a hole that reads as zeroes.
+        file.write_at(2000, b"end").await.unwrap();
+        let contents = read_all(&file).await;
+        assert_eq!(contents.len(), 2003);
+        assert!(contents[1300..2000].iter().all(|&b| b == 0));
+        assert_eq!(&contents[2000..], b"end");
+
+        // Another lookup sees the same file, as does a fresh mount.
+        let again = root.lookup("HELLO.TXT").await.unwrap();
+        assert_eq!(again.id(), file.id());
+        assert_eq!(read_all(&again).await, contents);
+
+        fs.sync().await.unwrap();
+        let fs = mount(&dev).await;
+        let file = fs
+            .root_inode()
+            .await
+            .unwrap()
+            .lookup("hello.txt")
+            .await
+            .unwrap();
+        assert_eq!(read_all(&file).await, contents);
+    }
+
+    #[tokio::test]
+    async fn test_long_names() {
+        let dev = mkfs();
+        let fs = mount(&dev).await;
+        let root = fs.root_inode().await.unwrap();
+
+        let long = "A rather long name, with spaces.txt";
+        create(&root, long, FileType::File).await;
+        create(&root, "short.txt", FileType::File).await;
+        create(&root, "Mixed.TXT", FileType::File).await;
+        create(&root, "a rather long name, too.txt", FileType::File).await;
+
+        let fs = mount(&dev).await;
+        let root = fs.root_inode().await.unwrap();
+        assert_eq!(
+            names(&root).await,
+            [
+                long,
+                "Mixed.TXT",
+                "a rather long name, too.txt",
+                "short.txt"
+            ]
+        );
+        assert!(
+            root.lookup("a RATHER long name, with spaces.txt")
+                .await
+                .is_ok()
+        );
+
+        assert!(matches!(
+            root.create("SHORT.TXT", FileType::File, FilePermissions::empty(), None)
+                .await,
+            Err(KernelError::Fs(FsError::AlreadyExists))
+        ));
+        assert!(matches!(
+            root.create("a:b", FileType::File, FilePermissions::empty(), None)
+                .await,
+            Err(KernelError::Fs(FsError::InvalidInput))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_directory_grows() {
+        let dev = mkfs();
+        let fs = mount(&dev).await;
+        let root = fs.root_inode().await.unwrap();
+
+        // Each name takes three entries, so a 16-entry cluster holds five.
+        let expected: Vec<String> = (0..20).map(|i| format!("long file name {i:02}")).collect();
+        for name in &expected {
+            create(&root, name, FileType::File).await;
+        }
+
+        assert_eq!(fs.iter_clusters(Cluster(ROOT)).count(), 4);
+        assert_eq!(names(&root).await, expected);
+    }
+
+    #[tokio::test]
+    async fn test_truncate() {
+        let dev = mkfs();
+        let fs = mount(&dev).await;
+        let root = fs.root_inode().await.unwrap();
+        let free = free_clusters(&fs).await;
+
+        let file = create(&root, "file", FileType::File).await;
+        file.write_at(0, &[0xAA; 2000]).await.unwrap();
+
+        file.truncate(100).await.unwrap();
+        assert_eq!(free_clusters(&fs).await, free - 1);
+
+        // What was cut off doesn't come back when the file's extended.
+        file.truncate(1000).await.unwrap();
+        let contents = read_all(&file).await;
+        assert_eq!(contents.len(), 1000);
+        assert!(contents[..100].iter().all(|&b| b == 0xAA));
+        assert!(contents[100..].iter().all(|&b| b == 0));
+
+        file.truncate(0).await.unwrap();
+        assert_eq!(free_clusters(&fs).await, free);
+        assert_eq!(file.getattr().await.unwrap().size, 0);
+    }
+
+    #[tokio::test]
+    async fn test_directories() {
+        let dev = mkfs();
+        let fs = mount(&dev).await;
+        let root = fs.root_inode().await.unwrap();
+        let free = free_clusters(&fs).await;
+
+        let dir = create(&root, "dir", FileType::Directory).await;
+        let file = create(&dir, "file", FileType::File).await;
+        file.write_at(0, b"data").await.unwrap();
+        drop(file);
+
+        assert_eq!(names(&dir).await, [".", "..", "file"]);
+        assert!(matches!(
+            root.unlink("dir").await,
+            Err(KernelError::Fs(FsError::DirectoryNotEmpty))
+        ));
+
+        dir.unlink("file").await.unwrap();
+        root.unlink("dir").await.unwrap();
+        assert_eq!(names(&root).await, Vec::<String>::new());
+
+        // The directory's node is still held, so its cluster is only freed
+        // once it's dropped.
+        assert_eq!(free_clusters(&fs).await, free - 1);
+        assert!(matches!(
+            dir.create("new", FileType::File, FilePermissions::empty(), None)
+                .await,
+            Err(KernelError::Fs(FsError::NotFound))
+        ));
+        drop(dir);
+
+        create(&root, "other", FileType::File).await;
+        assert_eq!(free_clusters(&fs).await, free);
+    }
+
+    #[tokio::test]
+    async fn test_rename() {
+        let dev = mkfs();
+        let fs = mount(&dev).await;
+        let root = fs.root_inode().await.unwrap();
+
+        let a = create(&root, "a", FileType::Directory).await;
+        let b = create(&root, "b", FileType::Directory).await;
+        let file = create(&a, "file", FileType::File).await;
+        file.write_at(0, b"old").await.unwrap();
+        create(&b, "target", FileType::File).await;
+
+        b.rename_from(a.clone(), "file", "target", false)
+            .await
+            .unwrap();
+        assert_eq!(names(&a).await, [".", ".."]);
+        assert_eq!(names(&b).await, [".", "..", "target"]);
+
+        // The open file follows its entry.
+        file.write_at(3, b" and new").await.unwrap();
+        let target = b.lookup("target").await.unwrap();
+        assert_eq!(read_all(&target).await, b"old and new");
+
+        assert!(matches!(
+            b.rename_from(b.clone(), "target", "target", true).await,
+            Ok(())
+        ));
+
+        // Moving a directory updates its `..`, but it can't go below itself.
+        b.rename_from(root.clone(), "a", "a", false).await.unwrap();
+        let a = b.lookup("a").await.unwrap();
+        assert_eq!(
+            a.lookup("..").await.unwrap().id(),
+            root.lookup("b").await.unwrap().id()
+        );
+        assert!(matches!(
+            a.rename_from(root.clone(), "b", "b", false).await,
+            Err(KernelError::Fs(FsError::InvalidInput))
+        ));
+
+        // Changing only the case of a name renames it.
+        b.rename_from(b.clone(), "target", "Target", false)
+            .await
+            .unwrap();
+        assert_eq!(names(&b).await, [".", "..", "Target", "a"]);
+    }
+
+    #[tokio::test]
+    async fn test_unlinked_while_open() {
+        let dev = mkfs();
+        let fs = mount(&dev).await;
+        let root = fs.root_inode().await.unwrap();
+        let free = free_clusters(&fs).await;
+
+        let file = create(&root, "file", FileType::File).await;
+        file.write_at(0, b"still here").await.unwrap();
+        root.unlink("file").await.unwrap();
+
+        assert!(root.lookup("file").await.is_err());
+        assert_eq!(read_all(&file).await, b"still here");
+        assert_eq!(free_clusters(&fs).await, free - 1);
+
+        drop(file);
+        create(&root, "other", FileType::File).await;
+        assert_eq!(free_clusters(&fs).await, free);
+    }
+
+    #[tokio::test]
+    async fn test_sync_updates_fsinfo() {
+        let dev = mkfs();
+        let fs = mount(&dev).await;
+        let root = fs.root_inode().await.unwrap();
+
+        let file = create(&root, "file", FileType::File).await;
+        file.write_at(0, &[1; 600]).await.unwrap();
+        fs.sync().await.unwrap();
+
+        let data = dev.data.lock().unwrap();
+        let free = u32::from_le_bytes(
+            data[SECTOR_SIZE + FSINFO_FREE_COUNT as usize..][..4]
+                .try_into()
+                .unwrap(),
+        );
+        assert_eq!(free as u64, fs.bpb.cluster_count() as u64 - 3);
+    }
 }
@@ -1,7 +1,7 @@
-use crate::error::Result;
+use crate::error::{FsError, IoError, Result};
 use alloc::sync::Arc;
 
-use super::{Cluster, Fat32Operations};
+use super::{Cluster, Fat32Operations, Sector};
 
 pub struct Fat32Reader<T: Fat32Operations> {
     fs: Arc<T>,
@@ -104,3 +104,57 @@ impl<T: Fat32Operations> Fat32Reader<T> {
         Ok(total_bytes_read)
     }
 }
+
+/// Returns the sector of `chain` which holds byte `pos`, and where in the
+/// sector that byte is.
+fn locate<T: Fat32Operations>(fs: &T, chain: &[Cluster], pos: u64) -> Result<(Sector, usize)> {
+    let bpc = fs.bytes_per_cluster() as u64;
+    let sector_size = fs.sector_size() as u64;
+
+    let cluster = *chain
+        .get((pos / bpc) as usize)
+        .ok_or(IoError::OutOfBounds)?;
+    let in_cluster = pos % bpc;
+    let sector = fs
+        .cluster_to_sectors(cluster)?
+        .nth((in_cluster / sector_size) as usize)
+        .ok_or(FsError::InvalidFs)?;
+
+    Ok((sector, (in_cluster % sector_size) as usize))
+}
+
+/// Fills `buf` from `offset` in the clusters of `chain`, which must hold all
+/// of it.
+pub async fn read_chain<T: Fat32Operations>(
+    fs: &T,
+    chain: &[Cluster],
+    offset: u64,
+    buf: &mut [u8],
+) -> Result<()> {
+    let mut done = 0;
+
+    while done < buf.len() {
+        let (sector, in_sector) = locate(fs, chain, offset + done as u64)?;
+        done += fs.read_sector(sector, in_sector, &mut buf[done..]).await?;
+    }
+
+    Ok(())
+}
+
+/// Writes `buf` at `offset` in the clusters of `chain`, which must be long
+/// enough to hold it.
+pub async fn write_chain<T: Fat32Operations>(
+    fs: &T,
+    chain: &[Cluster],
+    offset: u64,
+    buf: &[u8],
+) -> Result<()> {
+    let mut done = 0;
+
+    while done < buf.len() {
+        let (sector, in_sector) = locate(fs, chain, offset + done as u64)?;
+        done += fs.write_sector(sector, in_sector, &buf[done..]).await?;
+    }
+
+    Ok(())
+}
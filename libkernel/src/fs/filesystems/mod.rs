@@ -2,5 +2,6 @@
 
 pub mod ext4;
 pub mod fat32;
+pub mod overlayfs;
 #[cfg(feature = "alloc")]
 pub mod tmpfs;
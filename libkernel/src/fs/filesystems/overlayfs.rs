@@ -0,0 +1,1072 @@
+//! An overlay filesystem, which layers a writable upper directory over a
+//! read-only lower one, as Linux's overlayfs does.
+//!
+//! A name in the upper directory hides the same name in the lower one, except
+//! that two directories of the same name are merged, showing the entries of
+//! both. The lower layer is never changed. Anything from it is copied up into
+//! the upper layer, along with the directories it's in, before it's first
+//! changed, and removing a name the lower layer has leaves a whiteout in the
+//! upper layer to hide it: a character device numbered 0:0, as on Linux.
+//!
+//! Rather than being marked opaque, a directory made where a lower one was
+//! removed gets a whiteout for each of the lower directory's entries. A
+//! directory from the lower layer can't be renamed, failing with
+//! [`FsError::CrossDevice`] as it does on Linux without `redirect_dir`, so
+//! that programs like `mv` fall back to copying it.
+
+use crate::{
+    CpuOps,
+    driver::CharDevDescriptor,
+    error::{FsError, KernelError, Result},
+    fs::{
+        DirStream, Dirent, FileType, Filesystem, FsStats, Inode, InodeId, SimpleDirStream,
+        attr::{FileAttr, FilePermissions},
+        path::Path,
+        pathbuf::PathBuf,
+    },
+    sync::{mutex::Mutex, spinlock::SpinLockIrq},
+};
+use alloc::{
+    borrow::ToOwned,
+    boxed::Box,
+    collections::btree_map::BTreeMap,
+    string::String,
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+use async_trait::async_trait;
+use core::{any::Any, time::Duration};
+
+const OVERLAYFS_MAGIC: u64 = 0x794c7630;
+
+/// How much of a file is copied up at a time.
+const COPY_CHUNK: usize = 64 * 1024;
+
+/// What hides a name of the lower layer.
+const WHITEOUT: FileType = FileType::CharDevice(CharDevDescriptor { major: 0, minor: 0 });
+
+/// Returns the inode `name` names in `dir` and its attributes, whiteouts
+/// included, or `None` if there's no such name.
+async fn lookup_raw(
+    dir: &Arc<dyn Inode>,
+    name: &str,
+) -> Result<Option<(Arc<dyn Inode>, FileAttr)>> {
+    match dir.lookup(name).await {
+        Ok(inode) => {
+            let attr = inode.getattr().await?;
+            Ok(Some((inode, attr)))
+        }
+        Err(KernelError::Fs(FsError::NotFound)) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Returns the entries of `dir`, less `.` and `..`.
+async fn read_dir(dir: &Arc<dyn Inode>) -> Result<Vec<Dirent>> {
+    let mut stream = dir.readdir(0).await?;
+    let mut entries = Vec::new();
+
+    while let Some(entry) = stream.next_entry().await? {
+        if entry.name != "." && entry.name != ".." {
+            entries.push(entry);
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Returns the number of the overlay's inode for an object, from its lower
+/// inode if it has one so that it's kept when the object's copied up. The
+/// layers' numbers could clash, so the bottom bit tells them apart.
+fn overlay_ino(lower: Option<InodeId>, upper: Option<InodeId>) -> u64 {
+    match (lower, upper) {
+        (Some(lower), _) => lower.inode_id() << 1,
+        (None, Some(upper)) => upper.inode_id() << 1 | 1,
+        (None, None) => unreachable!("an overlay inode is in one layer or the other"),
+    }
+}
+
+/// A directory's entries, keyed by the directory's ID and the name.
+type NodeKey = (InodeId, String);
+
+/// The directory an object's in, and its name there.
+type Location<CPU> = (Arc<OverlayInode<CPU>>, String);
+
+/// An overlay of one directory on another.
+pub struct OverlayFs<CPU: CpuOps> {
+    id: u64,
+    lower: Arc<dyn Inode>,
+    upper: Arc<dyn Inode>,
+    /// The filesystem the upper directory is on, which the overlay's space is
+    /// counted against.
+    upper_fs: Arc<dyn Filesystem>,
+    /// Held while the upper layer's directories are changed, so that copy-ups
+    /// and whiteouts can't race.
+    lock: Mutex<(), CPU>,
+    /// The inodes that are in use, so that each object has just one, which
+    /// knows whether it's been copied up.
+    nodes: SpinLockIrq<BTreeMap<NodeKey, Weak<OverlayInode<CPU>>>, CPU>,
+    this: Weak<Self>,
+}
+
+impl<CPU: CpuOps> OverlayFs<CPU> {
+    /// Creates an overlay, numbered `id`, of the directory `upper` on the
+    /// directory `lower`. `upper` is on `upper_fs`.
+    pub fn new(
+        id: u64,
+        lower: Arc<dyn Inode>,
+        upper: Arc<dyn Inode>,
+        upper_fs: Arc<dyn Filesystem>,
+    ) -> Arc<Self> {
+        Arc::new_cyclic(|this| Self {
+            id,
+            lower,
+            upper,
+            upper_fs,
+            lock: Mutex::new(()),
+            nodes: SpinLockIrq::new(BTreeMap::new()),
+            this: this.clone(),
+        })
+    }
+}
+
+#[async_trait]
+impl<CPU: CpuOps> Filesystem for OverlayFs<CPU> {
+    async fn root_inode(&self) -> Result<Arc<dyn Inode>> {
+        let fs = self.this.upgrade().ok_or(FsError::InvalidFs)?;
+
+        Ok(OverlayInode::new(
+            fs,
+            None,
+            Some(self.lower.clone()),
+            Some(self.upper.clone()),
+        ))
+    }
+
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn magic(&self) -> u64 {
+        OVERLAYFS_MAGIC
+    }
+
+    async fn statfs(&self) -> Result<FsStats> {
+        self.upper_fs.statfs().await
+    }
+
+    async fn sync(&self) -> Result<()> {
+        self.upper_fs.sync().await
+    }
+}
+
+/// An object in the overlay, from either layer or both.
+struct OverlayInode<CPU: CpuOps> {
+    fs: Arc<OverlayFs<CPU>>,
+    id: InodeId,
+    /// The directory the object's in and its name there, which is where it's
+    /// copied up to. The root has none, being copied up already.
+    parent: SpinLockIrq<Option<Location<CPU>>, CPU>,
+    lower: Option<Arc<dyn Inode>>,
+    upper: SpinLockIrq<Option<Arc<dyn Inode>>, CPU>,
+    this: Weak<Self>,
+}
+
+impl<CPU: CpuOps> OverlayInode<CPU> {
+    fn new(
+        fs: Arc<OverlayFs<CPU>>,
+        parent: Option<Location<CPU>>,
+        lower: Option<Arc<dyn Inode>>,
+        upper: Option<Arc<dyn Inode>>,
+    ) -> Arc<Self> {
+        let ino = overlay_ino(
+            lower.as_ref().map(|inode| inode.id()),
+            upper.as_ref().map(|inode| inode.id()),
+        );
+
+        Arc::new_cyclic(|this| Self {
+            id: InodeId::from_fsid_and_inodeid(fs.id, ino),
+            fs,
+            parent: SpinLockIrq::new(parent),
+            lower,
+            upper: SpinLockIrq::new(upper),
+            this: this.clone(),
+        })
+    }
+
+    fn this(&self) -> Arc<Self> {
+        self.this.upgrade().unwrap()
+    }
+
+    fn upper(&self) -> Option<Arc<dyn Inode>> {
+        self.upper.lock_save_irq().clone()
+    }
+
+    /// Returns the inode the object's contents come from, which is its upper
+    /// one once it's been copied up.
+    fn real(&self) -> Arc<dyn Inode> {
+        self.upper()
+            .or_else(|| self.lower.clone())
+            .expect("an overlay inode is in one layer or the other")
+    }
+
+    async fn is_dir(&self) -> Result<bool> {
+        Ok(self.real().getattr().await?.file_type == FileType::Directory)
+    }
+
+    /// Returns what `name` names in the lower directory, if anything does.
+    async fn lower_entry(&self, name: &str) -> Result<Option<(Arc<dyn Inode>, FileAttr)>> {
+        let Some(dir) = &self.lower else {
+            return Ok(None);
+        };
+
+        Ok(lookup_raw(dir, name)
+            .await?
+            .filter(|(_, attr)| attr.file_type != WHITEOUT))
+    }
+
+    /// Looks `name` up in the merged directory.
+    async fn lookup_child(&self, name: &str) -> Result<Option<Arc<Self>>> {
+        if !self.is_dir().await? {
+            return Err(FsError::NotADirectory.into());
+        }
+
+        let key = (self.id, name.to_owned());
+
+        if let Some(node) = self
+            .fs
+            .nodes
+            .lock_save_irq()
+            .get(&key)
+            .and_then(Weak::upgrade)
+        {
+            return Ok(Some(node));
+        }
+
+        let upper = match self.upper() {
+            Some(dir) => lookup_raw(&dir, name).await?,
+            None => None,
+        };
+
+        let (lower, upper) = match upper {
+            Some((_, attr)) if attr.file_type == WHITEOUT => return Ok(None),
+            // Directories of the same name are merged, and the upper object
+            // hides anything else, though it takes the lower one's number if
+            // it's of the same type, as it would be if it were copied up.
+            Some((inode, attr)) => {
+                let lower = self
+                    .lower_entry(name)
+                    .await?
+                    .filter(|(_, lower_attr)| lower_attr.file_type == attr.file_type)
+                    .map(|(lower, _)| lower);
+
+                (lower, Some(inode))
+            }
+            None => match self.lower_entry(name).await? {
+                Some((inode, _)) => (Some(inode), None),
+                None => return Ok(None),
+            },
+        };
+
+        let node = Self::new(
+            self.fs.clone(),
+            Some((self.this(), name.to_owned())),
+            lower,
+            upper,
+        );
+
+        let mut nodes = self.fs.nodes.lock_save_irq();
+
+        // Someone else may have looked it up meanwhile.
+        if let Some(existing) = nodes.get(&key).and_then(Weak::upgrade) {
+            return Ok(Some(existing));
+        }
+
+        nodes.retain(|_, node| node.strong_count() > 0);
+        nodes.insert(key, Arc::downgrade(&node));
+
+        Ok(Some(node))
+    }
+
+    /// Returns the merged directory's entries, with their overlay inode
+    /// numbers.
+    async fn merged_entries(&self) -> Result<Vec<(String, FileType, u64)>> {
+        let mut lower: BTreeMap<String, Dirent> = match &self.lower {
+            Some(dir) => read_dir(dir)
+                .await?
+                .into_iter()
+                .map(|entry| (entry.name.clone(), entry))
+                .collect(),
+            None => BTreeMap::new(),
+        };
+        let mut entries = Vec::new();
+
+        if let Some(dir) = self.upper() {
+            for entry in read_dir(&dir).await? {
+                let under = lower.remove(&entry.name);
+
+                if entry.file_type == WHITEOUT {
+                    continue;
+                }
+
+                let under = under
+                    .filter(|under| under.file_type == entry.file_type)
+                    .map(|under| under.id);
+                let ino = overlay_ino(under, Some(entry.id));
+
+                entries.push((entry.name, entry.file_type, ino));
+            }
+        }
+
+        entries.extend(
+            lower
+                .into_values()
+                .filter(|entry| entry.file_type != WHITEOUT)
+                .map(|entry| {
+                    let ino = overlay_ino(Some(entry.id), None);
+                    (entry.name, entry.file_type, ino)
+                }),
+        );
+
+        Ok(entries)
+    }
+
+    /// Removes any whiteout at `name` in `dir`, the node's upper directory,
+    /// returning whether there was one.
+    async fn remove_whiteout(dir: &Arc<dyn Inode>, name: &str) -> Result<bool> {
+        match lookup_raw(dir, name).await? {
+            Some((_, attr)) if attr.file_type == WHITEOUT => {
+                dir.unlink(name).await?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Removes the whiteouts in `dir`, an upper directory that's about to be
+    /// removed, which would otherwise keep it from being empty.
+    async fn remove_whiteouts(dir: &Arc<dyn Inode>) -> Result<()> {
+        for entry in read_dir(dir).await? {
+            if entry.file_type == WHITEOUT {
+                dir.unlink(&entry.name).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Fails with [`FsError::AlreadyExists`] if `name` is in the merged
+    /// directory, and otherwise copies the directory up, clearing the way
+    /// for `name` to be added to it. Returns the upper directory, and whether
+    /// `name` was whited out.
+    async fn prepare_add(&self, name: &str) -> Result<(Arc<dyn Inode>, bool)> {
+        if self.lookup_child(name).await?.is_some() {
+            return Err(FsError::AlreadyExists.into());
+        }
+
+        let dir = self.copy_up_locked().await?;
+        let whited_out = Self::remove_whiteout(&dir, name).await?;
+
+        Ok((dir, whited_out))
+    }
+
+    /// Copies the object up if it hasn't been already, returning its upper
+    /// inode.
+    async fn copy_up(&self) -> Result<Arc<dyn Inode>> {
+        if let Some(upper) = self.upper() {
+            return Ok(upper);
+        }
+
+        let _guard = self.fs.lock.lock().await;
+        self.copy_up_locked().await
+    }
+
+    /// Copies the object up, along with any of the directories it's in that
+    /// haven't been, with the overlay's lock held.
+    async fn copy_up_locked(&self) -> Result<Arc<dyn Inode>> {
+        // The objects yet to be copied up, starting with this one.
+        let mut pending = Vec::new();
+        let mut node = self.this();
+
+        while node.upper().is_none() {
+            let (parent, _) = node
+                .parent
+                .lock_save_irq()
+                .clone()
+                .ok_or(FsError::InvalidFs)?;
+            pending.push(node);
+            node = parent;
+        }
+
+        for node in pending.into_iter().rev() {
+            node.copy_up_one().await?;
+        }
+
+        Ok(self.upper().unwrap())
+    }
+
+    /// Copies the object up into its directory, which has been copied up.
+    async fn copy_up_one(&self) -> Result<()> {
+        let (parent, name) = self
+            .parent
+            .lock_save_irq()
+            .clone()
+            .ok_or(FsError::InvalidFs)?;
+        let dir = parent.upper().ok_or(FsError::InvalidFs)?;
+        let lower = self.lower.clone().ok_or(FsError::InvalidFs)?;
+
+        match lookup_raw(&dir, &name).await? {
+            // It's been removed since it was looked up.
+            Some((_, attr)) if attr.file_type == WHITEOUT => return Err(FsError::NotFound.into()),
+            Some(_) => return Err(FsError::AlreadyExists.into()),
+            None => {}
+        }
+
+        let attr = lower.getattr().await?;
+
+        let upper = match attr.file_type {
+            FileType::Symlink => {
+                dir.symlink(&name, &lower.readlink().await?).await?;
+                dir.lookup(&name).await?
+            }
+            file_type => {
+                dir.create(&name, file_type, attr.permissions, Some(attr.mtime))
+                    .await?
+            }
+        };
+
+        if attr.file_type == FileType::File {
+            let mut buf = vec![0; COPY_CHUNK];
+            let mut offset = 0;
+
+            loop {
+                let len = lower.read_at(offset, &mut buf).await?;
+                if len == 0 {
+                    break;
+                }
+
+                let mut done = 0;
+                while done < len {
+                    done += upper
+                        .write_at(offset + done as u64, &buf[done..len])
+                        .await?;
+                }
+
+                offset += len as u64;
+            }
+        }
+
+        let mut upper_attr = upper.getattr().await?;
+        upper_attr.permissions = attr.permissions;
+        upper_attr.uid = attr.uid;
+        upper_attr.gid = attr.gid;
+        upper_attr.atime = attr.atime;
+        upper_attr.mtime = attr.mtime;
+        upper_attr.ctime = attr.ctime;
+        upper.setattr(upper_attr).await?;
+
+        for xattr in lower.listxattr().await? {
+            let value = lower.getxattr(&xattr).await?;
+
+            match upper.setxattr(&xattr, &value, false, false).await {
+                Err(KernelError::NotSupported) => {}
+                res => res?,
+            }
+        }
+
+        *self.upper.lock_save_irq() = Some(upper);
+
+        Ok(())
+    }
+
+    /// Forgets the inode for `name` in this directory.
+    fn forget(&self, name: &str) {
+        self.fs
+            .nodes
+            .lock_save_irq()
+            .remove(&(self.id, name.to_owned()));
+    }
+}
+
+#[async_trait]
+impl<CPU: CpuOps> Inode for OverlayInode<CPU> {
+    fn id(&self) -> InodeId {
+        self.id
+    }
+
+    async fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        self.real().read_at(offset, buf).await
+    }
+
+    async fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize> {
+        self.copy_up().await?.write_at(offset, buf).await
+    }
+
+    async fn truncate(&self, size: u64) -> Result<()> {
+        self.copy_up().await?.truncate(size).await
+    }
+
+    async fn getattr(&self) -> Result<FileAttr> {
+        let mut attr = self.real().getattr().await?;
+        attr.id = self.id;
+        Ok(attr)
+    }
+
+    async fn setattr(&self, attr: FileAttr) -> Result<()> {
+        let upper = self.copy_up().await?;
+        let mut upper_attr = upper.getattr().await?;
+
+        upper_attr.permissions = attr.permissions;
+        upper_attr.uid = attr.uid;
+        upper_attr.gid = attr.gid;
+        upper_attr.atime = attr.atime;
+        upper_attr.btime = attr.btime;
+        upper_attr.mtime = attr.mtime;
+        upper_attr.ctime = attr.ctime;
+
+        upper.setattr(upper_attr).await
+    }
+
+    async fn getxattr(&self, name: &str) -> Result<Vec<u8>> {
+        self.real().getxattr(name).await
+    }
+
+    async fn setxattr(&self, name: &str, buf: &[u8], create: bool, replace: bool) -> Result<()> {
+        self.copy_up()
+            .await?
+            .setxattr(name, buf, create, replace)
+            .await
+    }
+
+    async fn removexattr(&self, name: &str) -> Result<()> {
+        self.copy_up().await?.removexattr(name).await
+    }
+
+    async fn listxattr(&self) -> Result<Vec<String>> {
+        self.real().listxattr().await
+    }
+
+    async fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        match name {
+            "." => Ok(self.this()),
+            ".." => Ok(match self.parent.lock_save_irq().clone() {
+                Some((parent, _)) => parent,
+                None => self.this(),
+            }),
+            _ => self
+                .lookup_child(name)
+                .await?
+                .map(|node| node as Arc<dyn Inode>)
+                .ok_or(FsError::NotFound.into()),
+        }
+    }
+
+    async fn create(
+        &self,
+        name: &str,
+        file_type: FileType,
+        permissions: FilePermissions,
+        time: Option<Duration>,
+    ) -> Result<Arc<dyn Inode>> {
+        let _guard = self.fs.lock.lock().await;
+        let (dir, whited_out) = self.prepare_add(name).await?;
+        let inode = dir.create(name, file_type, permissions, time).await?;
+
+        // Hide what was in the directory that was removed.
+        if file_type == FileType::Directory
+            && whited_out
+            && let Some((lower, attr)) = self.lower_entry(name).await?
+            && attr.file_type == FileType::Directory
+        {
+            for entry in read_dir(&lower).await? {
+                if entry.file_type != WHITEOUT {
+                    inode
+                        .create(&entry.name, WHITEOUT, FilePermissions::empty(), None)
+                        .await?;
+                }
+            }
+        }
+
+        self.lookup_child(name)
+            .await?
+            .map(|node| node as Arc<dyn Inode>)
+            .ok_or(FsError::NotFound.into())
+    }
+
+    async fn unlink(&self, name: &str) -> Result<()> {
+        let _guard = self.fs.lock.lock().await;
+        let node = self.lookup_child(name).await?.ok_or(FsError::NotFound)?;
+        let is_dir = node.is_dir().await?;
+
+        if is_dir && !node.merged_entries().await?.is_empty() {
+            return Err(FsError::DirectoryNotEmpty.into());
+        }
+
+        let dir = self.copy_up_locked().await?;
+
+        if let Some(upper) = node.upper() {
+            if is_dir {
+                Self::remove_whiteouts(&upper).await?;
+            }
+
+            dir.unlink(name).await?;
+        }
+
+        if self.lower_entry(name).await?.is_some() {
+            dir.create(name, WHITEOUT, FilePermissions::empty(), None)
+                .await?;
+        }
+
+        self.forget(name);
+
+        Ok(())
+    }
+
+    async fn link(&self, name: &str, inode: Arc<dyn Inode>) -> Result<()> {
+        let target = Arc::downcast::<Self>(inode).map_err(|_| FsError::CrossDevice)?;
+
+        if !Arc::ptr_eq(&target.fs, &self.fs) {
+            return Err(FsError::CrossDevice.into());
+        }
+
+        let _guard = self.fs.lock.lock().await;
+        let target = target.copy_up_locked().await?;
+        let (dir, _) = self.prepare_add(name).await?;
+
+        dir.link(name, target).await
+    }
+
+    async fn symlink(&self, name: &str, target: &Path) -> Result<()> {
+        let _guard = self.fs.lock.lock().await;
+        let (dir, _) = self.prepare_add(name).await?;
+
+        dir.symlink(name, target).await
+    }
+
+    async fn rename_from(
+        &self,
+        old_parent: Arc<dyn Inode>,
+        old_name: &str,
+        new_name: &str,
+        no_replace: bool,
+    ) -> Result<()> {
+        let old_parent = Arc::downcast::<Self>(old_parent).map_err(|_| FsError::CrossDevice)?;
+
+        if !Arc::ptr_eq(&old_parent.fs, &self.fs) {
+            return Err(FsError::CrossDevice.into());
+        }
+
+        let _guard = self.fs.lock.lock().await;
+        let src = old_parent
+            .lookup_child(old_name)
+            .await?
+            .ok_or(FsError::NotFound)?;
+
+        if old_parent.id == self.id && old_name == new_name {
+            return Ok(());
+        }
+
+        let src_is_dir = src.is_dir().await?;
+
+        if src_is_dir && src.lower.is_some() {
+            return Err(FsError::CrossDevice.into());
+        }
+
+        let dst = self.lookup_child(new_name).await?;
+
+        if let Some(dst) = &dst {
+            if no_replace {
+                return Err(FsError::AlreadyExists.into());
+            }
+
+            match (src_is_dir, dst.is_dir().await?) {
+                (false, true) => return Err(FsError::IsADirectory.into()),
+                (true, false) => return Err(FsError::NotADirectory.into()),
+                (true, true) => {
+                    if !dst.merged_entries().await?.is_empty() {
+                        return Err(FsError::DirectoryNotEmpty.into());
+                    }
+
+                    // Replacing the upper directory would let the lower one's
+                    // entries show through.
+                    if dst.lower.is_some() {
+                        return Err(FsError::CrossDevice.into());
+                    }
+                }
+                (false, false) => {}
+            }
+        }
+
+        src.copy_up_locked().await?;
+        let old_dir = old_parent.upper().ok_or(FsError::InvalidFs)?;
+        let new_dir = self.copy_up_locked().await?;
+
+        // Whatever's at the new name goes first, whiteout or not, which spares
+        // the upper layer having to replace one kind of object with another.
+        if let Some((upper, attr)) = lookup_raw(&new_dir, new_name).await? {
+            if attr.file_type == FileType::Directory {
+                Self::remove_whiteouts(&upper).await?;
+            }
+
+            new_dir.unlink(new_name).await?;
+        }
+
+        new_dir
+            .rename_from(old_dir.clone(), old_name, new_name, false)
+            .await?;
+
+        if old_parent.lower_entry(old_name).await?.is_some() {
+            old_dir
+                .create(old_name, WHITEOUT, FilePermissions::empty(), None)
+                .await?;
+        }
+
+        old_parent.forget(old_name);
+        self.fs
+            .nodes
+            .lock_save_irq()
+            .insert((self.id, new_name.to_owned()), Arc::downgrade(&src));
+        *src.parent.lock_save_irq() = Some((self.this(), new_name.to_owned()));
+
+        Ok(())
+    }
+
+    async fn readdir(&self, start_offset: u64) -> Result<Box<dyn DirStream>> {
+        if !self.is_dir().await? {
+            return Err(FsError::NotADirectory.into());
+        }
+
+        let parent_id = match self.parent.lock_save_irq().as_ref() {
+            Some((parent, _)) => parent.id,
+            None => self.id,
+        };
+
+        let mut entries = vec![
+            Dirent::new(".".into(), self.id, FileType::Directory, 1),
+            Dirent::new("..".into(), parent_id, FileType::Directory, 2),
+        ];
+
+        for (name, file_type, ino) in self.merged_entries().await? {
+            let offset = entries.len() as u64 + 1;
+            let id = InodeId::from_fsid_and_inodeid(self.fs.id, ino);
+
+            entries.push(Dirent::new(name, id, file_type, offset));
+        }
+
+        Ok(Box::new(SimpleDirStream::new(entries, start_offset)))
+    }
+
+    async fn readlink(&self) -> Result<PathBuf> {
+        self.real().readlink().await
+    }
+
+    async fn sync(&self) -> Result<()> {
+        match self.upper() {
+            Some(upper) => upper.sync().await,
+            None => Ok(()),
+        }
+    }
+
+    async fn datasync(&self) -> Result<()> {
+        match self.upper() {
+            Some(upper) => upper.datasync().await,
+            None => Ok(()),
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{fs::filesystems::tmpfs::tests::new_tmpfs, test::MockCpuOps};
+
+    /// The layers of an overlay, the lower one holding a file `a`, a symlink
+    /// `link` and a directory `d` with a file `x` in it, and the upper one a
+    /// file `b` and a directory `d` with a file `y` in it.
+    struct Layers {
+        /// The VFS would keep this alive while it's mounted.
+        _lower_fs: Arc<dyn Filesystem>,
+        lower: Arc<dyn Inode>,
+        upper: Arc<dyn Inode>,
+        overlay: Arc<dyn Inode>,
+    }
+
+    async fn create(dir: &Arc<dyn Inode>, name: &str, file_type: FileType) -> Arc<dyn Inode> {
+        dir.create(
+            name,
+            file_type,
+            FilePermissions::from_bits_retain(0o644),
+            None,
+        )
+        .await
+        .unwrap()
+    }
+
+    async fn create_file(dir: &Arc<dyn Inode>, name: &str, contents: &[u8]) {
+        let file = create(dir, name, FileType::File).await;
+        file.write_at(0, contents).await.unwrap();
+    }
+
+    async fn setup() -> Layers {
+        let lower_fs = new_tmpfs(20);
+        let upper_fs = new_tmpfs(21);
+        let lower = lower_fs.root_inode().await.unwrap();
+        let upper = upper_fs.root_inode().await.unwrap();
+
+        create_file(&lower, "a", b"lower a").await;
+        lower.symlink("link", Path::new("a")).await.unwrap();
+        let dir = create(&lower, "d", FileType::Directory).await;
+        create_file(&dir, "x", b"lower x").await;
+
+        create_file(&upper, "b", b"upper b").await;
+        let dir = create(&upper, "d", FileType::Directory).await;
+        create_file(&dir, "y", b"upper y").await;
+
+        let fs = OverlayFs::<MockCpuOps>::new(30, lower.clone(), upper.clone(), upper_fs);
+        let overlay = fs.root_inode().await.unwrap();
+
+        Layers {
+            _lower_fs: lower_fs,
+            lower,
+            upper,
+            overlay,
+        }
+    }
+
+    async fn read_all(inode: &Arc<dyn Inode>) -> Vec<u8> {
+        let mut buf = vec![0; inode.getattr().await.unwrap().size as usize];
+        let len = inode.read_at(0, &mut buf).await.unwrap();
+        buf.truncate(len);
+        buf
+    }
+
+    async fn names(dir: &Arc<dyn Inode>) -> Vec<String> {
+        let mut stream = dir.readdir(0).await.unwrap();
+        let mut names = Vec::new();
+
+        while let Some(entry) = stream.next_entry().await.unwrap() {
+            names.push(entry.name);
+        }
+
+        names.sort();
+        names
+    }
+
+    async fn lookup(dir: &Arc<dyn Inode>, path: &str) -> Result<Arc<dyn Inode>> {
+        let mut inode = dir.clone();
+
+        for name in path.split('/') {
+            inode = inode.lookup(name).await?;
+        }
+
+        Ok(inode)
+    }
+
+    #[tokio::test]
+    async fn test_merged_lookup() {
+        let layers = setup().await;
+        let root = &layers.overlay;
+
+        assert_eq!(names(root).await, [".", "..", "a", "b", "d", "link"]);
+        assert_eq!(
+            names(&lookup(root, "d").await.unwrap()).await,
+            [".", "..", "x", "y"]
+        );
+
+        assert_eq!(
+            read_all(&lookup(root, "a").await.unwrap()).await,
+            b"lower a"
+        );
+        assert_eq!(
+            read_all(&lookup(root, "b").await.unwrap()).await,
+            b"upper b"
+        );
+        assert_eq!(
+            read_all(&lookup(root, "d/x").await.unwrap()).await,
+            b"lower x"
+        );
+        assert_eq!(
+            read_all(&lookup(root, "d/y").await.unwrap()).await,
+            b"upper y"
+        );
+        assert_eq!(
+            lookup(root, "link")
+                .await
+                .unwrap()
+                .readlink()
+                .await
+                .unwrap(),
+            PathBuf::from("a")
+        );
+
+        // Lookups give the same inode, within the overlay.
+        let x = lookup(root, "d/x").await.unwrap();
+        assert_eq!(lookup(root, "d/x").await.unwrap().id(), x.id());
+        assert_eq!(x.id().fs_id(), 30);
+        assert_eq!(lookup(root, "d/..").await.unwrap().id(), root.id());
+
+        // A file in the upper layer hides one of the same name in the lower.
+        create_file(&layers.upper, "a", b"upper a").await;
+        assert_eq!(
+            read_all(&lookup(root, "a").await.unwrap()).await,
+            b"upper a"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_copy_up() {
+        let layers = setup().await;
+        let x = lookup(&layers.overlay, "d/x").await.unwrap();
+        let id = x.id();
+
+        x.write_at(6, b"X, copied up").await.unwrap();
+        assert_eq!(read_all(&x).await, b"lower X, copied up");
+        assert_eq!(x.id(), id);
+
+        // The lower layer is left as it was.
+        let lower_x = lookup(&layers.lower, "d/x").await.unwrap();
+        assert_eq!(read_all(&lower_x).await, b"lower x");
+
+        let upper_x = lookup(&layers.upper, "d/x").await.unwrap();
+        assert_eq!(read_all(&upper_x).await, b"lower X, copied up");
+
+        // Changing attributes copies up too, along with the directories the
+        // file's in.
+        let lower_dir = create(&layers.lower, "e", FileType::Directory).await;
+        create_file(&lower_dir, "z", b"z").await;
+
+        let z = lookup(&layers.overlay, "e/z").await.unwrap();
+        let mut attr = z.getattr().await.unwrap();
+        attr.permissions = FilePermissions::from_bits_retain(0o600);
+        z.setattr(attr).await.unwrap();
+
+        let upper_z = lookup(&layers.upper, "e/z").await.unwrap();
+        assert_eq!(read_all(&upper_z).await, b"z");
+        assert_eq!(upper_z.getattr().await.unwrap().permissions.bits(), 0o600);
+        assert_eq!(
+            lookup(&layers.lower, "e/z")
+                .await
+                .unwrap()
+                .getattr()
+                .await
+                .unwrap()
+                .permissions
+                .bits(),
+            0o644
+        );
+    }
+
+    #[tokio::test]
+    async fn test_whiteouts() {
+        let layers = setup().await;
+        let root = &layers.overlay;
+
+        root.unlink("a").await.unwrap();
+        assert!(matches!(
+            root.lookup("a").await,
+            Err(KernelError::Fs(FsError::NotFound))
+        ));
+        assert_eq!(names(root).await, [".", "..", "b", "d", "link"]);
+
+        // The lower file's still there, hidden by a whiteout.
+        assert_eq!(
+            read_all(&layers.lower.lookup("a").await.unwrap()).await,
+            b"lower a"
+        );
+        let whiteout = layers.upper.lookup("a").await.unwrap();
+        assert_eq!(whiteout.getattr().await.unwrap().file_type, WHITEOUT);
+
+        // A new file takes the whiteout's place.
+        let a = create(root, "a", FileType::File).await;
+        a.write_at(0, b"new a").await.unwrap();
+        assert_eq!(read_all(&root.lookup("a").await.unwrap()).await, b"new a");
+        assert_eq!(names(&layers.upper).await, ["a", "b", "d"]);
+
+        // Removing a file that's only in the upper layer leaves no whiteout.
+        root.unlink("b").await.unwrap();
+        assert!(layers.upper.lookup("b").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_remove_directory() {
+        let layers = setup().await;
+        let root = &layers.overlay;
+        let d = root.lookup("d").await.unwrap();
+
+        assert!(matches!(
+            root.unlink("d").await,
+            Err(KernelError::Fs(FsError::DirectoryNotEmpty))
+        ));
+
+        d.unlink("x").await.unwrap();
+        d.unlink("y").await.unwrap();
+        assert_eq!(names(&d).await, [".", ".."]);
+        root.unlink("d").await.unwrap();
+        assert!(root.lookup("d").await.is_err());
+
+        // A directory made in its place doesn't show the lower one's entries.
+        let d = create(root, "d", FileType::Directory).await;
+        assert_eq!(names(&d).await, [".", ".."]);
+        assert!(d.lookup("x").await.is_err());
+
+        create_file(&d, "x", b"new x").await;
+        assert_eq!(
+            read_all(&lookup(root, "d/x").await.unwrap()).await,
+            b"new x"
+        );
+        assert_eq!(
+            read_all(&lookup(&layers.lower, "d/x").await.unwrap()).await,
+            b"lower x"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rename() {
+        let layers = setup().await;
+        let root = &layers.overlay;
+        let d = root.lookup("d").await.unwrap();
+        let a = root.lookup("a").await.unwrap();
+
+        d.rename_from(root.clone(), "a", "moved", false)
+            .await
+            .unwrap();
+        assert!(root.lookup("a").await.is_err());
+        assert_eq!(
+            read_all(&lookup(root, "d/moved").await.unwrap()).await,
+            b"lower a"
+        );
+        assert_eq!(lookup(root, "d/moved").await.unwrap().id(), a.id());
+        assert_eq!(
+            read_all(&layers.lower.lookup("a").await.unwrap()).await,
+            b"lower a"
+        );
+
+        // Renaming over a file replaces it, lower or not.
+        root.rename_from(d.clone(), "moved", "link", false)
+            .await
+            .unwrap();
+        assert_eq!(
+            read_all(&root.lookup("link").await.unwrap()).await,
+            b"lower a"
+        );
+        assert_eq!(names(&d).await, [".", "..", "x", "y"]);
+
+        // Directories from the lower layer stay put.
+        assert!(matches!(
+            root.rename_from(root.clone(), "d", "e", false).await,
+            Err(KernelError::Fs(FsError::CrossDevice))
+        ));
+
+        // Those only in the upper layer can be moved.
+        let e = create(root, "e", FileType::Directory).await;
+        create_file(&e, "f", b"f").await;
+        d.rename_from(root.clone(), "e", "e", false).await.unwrap();
+        assert_eq!(read_all(&lookup(root, "d/e/f").await.unwrap()).await, b"f");
+        assert_eq!(lookup(root, "d/e/..").await.unwrap().id(), d.id());
+    }
+}
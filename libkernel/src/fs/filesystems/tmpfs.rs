@@ -4,7 +4,7 @@ use crate::{
     CpuOps,
     error::{FsError, KernelError, Result},
     fs::{
-        DirStream, Dirent, FileType, Filesystem, Inode, InodeId,
+        DirStream, Dirent, FileType, Filesystem, FsStats, Inode, InodeId,
         attr::{FileAttr, FilePermissions},
         path::Path,
         pathbuf::PathBuf,
@@ -31,7 +31,7 @@ use core::{
     cmp::min,
     marker::PhantomData,
     mem::size_of,
-    sync::atomic::{AtomicU64, Ordering},
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
 };
 
 const BLOCK_SZ: usize = PAGE_SIZE;
@@ -49,6 +49,9 @@ where
     indirect_block: ClaimedPage<C, G, T>,
     size: usize,
     allocated_blocks: usize,
+    /// The count of blocks the whole filesystem holds, which this file's
+    /// blocks are counted in.
+    fs_blocks: Arc<AtomicUsize>,
 }
 
 impl<C, G, T> TmpFsRegInner<C, G, T>
@@ -83,6 +86,7 @@ where
 
             new_page.leak();
             self.allocated_blocks += 1;
+            self.fs_blocks.fetch_add(1, Ordering::Relaxed);
         }
 
         Ok(self.block_ptr_mut(block_idx))
@@ -110,6 +114,9 @@ where
                 // Drop happens here, releasing memory.
             }
         }
+
+        self.fs_blocks
+            .fetch_sub(self.allocated_blocks, Ordering::Relaxed);
     }
 }
 
@@ -130,7 +137,7 @@ where
     G: PageAllocGetter<C>,
     T: AddressTranslator<()>,
 {
-    fn new(id: InodeId, permissions: FilePermissions) -> Result<Self> {
+    fn new(id: InodeId, permissions: FilePermissions, fs_blocks: Arc<AtomicUsize>) -> Result<Self> {
         Ok(Self {
             id,
             attr: SpinLockIrq::new(FileAttr {
@@ -144,6 +151,7 @@ where
                 indirect_block: ClaimedPage::<C, G, T>::alloc_zeroed()?,
                 size: 0,
                 allocated_blocks: 0,
+                fs_blocks,
             }),
         })
     }
@@ -293,6 +301,7 @@ where
                 }
 
                 inner.allocated_blocks -= 1;
+                inner.fs_blocks.fetch_sub(1, Ordering::Relaxed);
             }
 
             // Zero out trailing data in the last retained page. This is POSIX
@@ -444,9 +453,17 @@ where
         let inode_id = InodeId::from_fsid_and_inodeid(fs.id(), new_id);
 
         let inode: Arc<dyn Inode> = match file_type {
-            FileType::File => Arc::new(TmpFsReg::<C, G, T>::new(inode_id, mode)?),
+            FileType::File => Arc::new(TmpFsReg::<C, G, T>::new(
+                inode_id,
+                mode,
+                fs.used_blocks.clone(),
+            )?),
             FileType::Directory => TmpFsDirInode::<C, G, T>::new(new_id, self.fs.clone(), mode),
-            _ => return Err(KernelError::NotSupported),
+            FileType::CharDevice(_)
+            | FileType::BlockDevice(_)
+            | FileType::Fifo
+            | FileType::Socket => Arc::new(TmpFsNodeInode::<C>::new(inode_id, file_type, mode)),
+            FileType::Symlink => return Err(KernelError::NotSupported),
         };
 
         entries.push(TmpFsDirEnt {
@@ -459,38 +476,66 @@ where
         Ok(inode)
     }
 
+    async fn create_tmpfile(
+        &self,
+        permissions: FilePermissions,
+        _time: Option<Duration>,
+    ) -> Result<Arc<dyn Inode>> {
+        let fs = self.fs.upgrade().ok_or(FsError::InvalidFs)?;
+        let inode_id = InodeId::from_fsid_and_inodeid(fs.id(), fs.alloc_inode_id());
+
+        let inode = TmpFsReg::<C, G, T>::new(inode_id, permissions, fs.used_blocks.clone())?;
+        inode.attr.lock_save_irq().nlinks = 0;
+
+        Ok(Arc::new(inode))
+    }
+
     async fn unlink(&self, name: &str) -> Result<()> {
-        let mut entries = self.entries.lock_save_irq();
-        let index = entries.iter().position(|e| e.name == name);
+        let entry = {
+            let mut entries = self.entries.lock_save_irq();
+            let index = entries
+                .iter()
+                .position(|e| e.name == name)
+                .ok_or(FsError::NotFound)?;
 
-        if let Some(idx) = index {
-            entries.remove(idx);
-            Ok(())
-        } else {
-            Err(FsError::NotFound.into())
+            entries.remove(index)
+        };
+
+        // The inode lives on through its other links, and open files.
+        if entry.kind != FileType::Directory {
+            let mut attr = entry.inode.getattr().await?;
+            attr.nlinks = attr.nlinks.saturating_sub(1);
+            entry.inode.setattr(attr).await?;
         }
+
+        Ok(())
     }
 
     async fn link(&self, name: &str, inode: Arc<dyn Inode>) -> Result<()> {
+        if inode.id().fs_id() != self.id().fs_id() {
+            return Err(FsError::CrossDevice.into());
+        }
+
         let mut attr = inode.getattr().await?;
         let kind = attr.file_type;
-        attr.nlinks += 1;
-        inode.setattr(attr).await?;
 
-        let mut entries = self.entries.lock_save_irq();
+        {
+            let mut entries = self.entries.lock_save_irq();
 
-        if entries.iter().any(|e| e.name == name) {
-            return Err(FsError::AlreadyExists.into());
-        }
+            if entries.iter().any(|e| e.name == name) {
+                return Err(FsError::AlreadyExists.into());
+            }
 
-        entries.push(TmpFsDirEnt {
-            name: name.to_string(),
-            id: inode.id(),
-            kind,
-            inode,
-        });
+            entries.push(TmpFsDirEnt {
+                name: name.to_string(),
+                id: inode.id(),
+                kind,
+                inode: inode.clone(),
+            });
+        }
 
-        Ok(())
+        attr.nlinks += 1;
+        inode.setattr(attr).await
     }
 
     async fn symlink(&self, name: &str, target: &Path) -> Result<()> {
@@ -785,6 +830,47 @@ impl<C: CpuOps> TmpFsSymlinkInode<C> {
     }
 }
 
+/// A device node, FIFO or socket, which holds nothing but its attributes.
+struct TmpFsNodeInode<C: CpuOps> {
+    id: InodeId,
+    attr: SpinLockIrq<FileAttr, C>,
+}
+
+#[async_trait]
+impl<C: CpuOps> Inode for TmpFsNodeInode<C> {
+    fn id(&self) -> InodeId {
+        self.id
+    }
+
+    async fn getattr(&self) -> Result<FileAttr> {
+        Ok(self.attr.lock_save_irq().clone())
+    }
+
+    async fn setattr(&self, attr: FileAttr) -> Result<()> {
+        *self.attr.lock_save_irq() = attr;
+        Ok(())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl<C: CpuOps> TmpFsNodeInode<C> {
+    fn new(id: InodeId, file_type: FileType, permissions: FilePermissions) -> Self {
+        Self {
+            id,
+            attr: SpinLockIrq::new(FileAttr {
+                id,
+                file_type,
+                permissions,
+                nlinks: 1,
+                ..Default::default()
+            }),
+        }
+    }
+}
+
 /// An in-memory temporary filesystem backed by page allocations.
 pub struct TmpFs<C, G, T>
 where
@@ -794,6 +880,8 @@ where
 {
     id: u64,
     next_inode_id: AtomicU64,
+    /// How many blocks the files in the filesystem hold between them.
+    used_blocks: Arc<AtomicUsize>,
     root: Arc<TmpFsDirInode<C, G, T>>,
     pg_allocator: PhantomData<G>,
     _phantom: PhantomData<T>,
@@ -814,6 +902,7 @@ where
             Self {
                 id: fs_id,
                 next_inode_id: AtomicU64::new(2),
+                used_blocks: Arc::new(AtomicUsize::new(0)),
                 root,
                 pg_allocator: PhantomData,
                 _phantom: PhantomData,
@@ -845,11 +934,28 @@ where
     fn magic(&self) -> u64 {
         0x01021994 // Tmpfs magic number
     }
+
+    /// There's no limit on how big a tmpfs grows besides the memory there is,
+    /// so it counts as big as what it holds plus the memory that's free.
+    async fn statfs(&self) -> Result<FsStats> {
+        let used = self.used_blocks.load(Ordering::Relaxed) as u64;
+        let free = G::global_page_alloc().free_pages() as u64;
+
+        Ok(FsStats {
+            block_size: BLOCK_SZ as _,
+            total_blocks: used + free,
+            free_blocks: free,
+            avail_blocks: free,
+            name_len: 255,
+            ..FsStats::default()
+        })
+    }
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
+    use crate::driver::CharDevDescriptor;
     use crate::fs::{FileType, InodeId, attr::FilePermissions};
     use crate::memory::allocators::phys::FrameAllocator;
     use crate::memory::allocators::phys::tests::TestFixture;
@@ -887,6 +993,7 @@ mod tests {
         let reg = TmpFsReg::new(
             InodeId::from_fsid_and_inodeid(0, 1024),
             FilePermissions::all(),
+            fs.used_blocks.clone(),
         )
         .unwrap();
         (fs, reg)
@@ -898,6 +1005,13 @@ mod tests {
         TmpFs::new(1)
     }
 
+    /// Creates a filesystem numbered `fs_id`, for other filesystems' tests to
+    /// build on.
+    pub(crate) fn new_tmpfs(fs_id: u64) -> Arc<dyn Filesystem> {
+        init_allocator();
+        TmpFs::<MockCpuOps, TmpFsPgAllocGetter, IdentityTranslator>::new(fs_id)
+    }
+
     #[tokio::test]
     async fn test_simple_read_write() {
         let (_, reg) = setup_env();
@@ -1042,6 +1156,37 @@ mod tests {
         assert!(res.is_err(), "Should not allow duplicate file creation");
     }
 
+    #[tokio::test]
+    async fn test_device_nodes() {
+        let fs = setup_fs();
+        let root = fs.root_inode().await.unwrap();
+        let desc = CharDevDescriptor { major: 1, minor: 3 };
+
+        let node = root
+            .create(
+                "null",
+                FileType::CharDevice(desc),
+                FilePermissions::from_bits_retain(0o666),
+                None,
+            )
+            .await
+            .unwrap();
+
+        let attr = root.lookup("null").await.unwrap().getattr().await.unwrap();
+        assert_eq!(attr.id, node.id());
+        assert_eq!(attr.file_type, FileType::CharDevice(desc));
+        assert_eq!(attr.permissions.bits(), 0o666);
+
+        root.create("fifo", FileType::Fifo, FilePermissions::empty(), None)
+            .await
+            .unwrap();
+        assert!(matches!(
+            root.create("link", FileType::Symlink, FilePermissions::empty(), None)
+                .await,
+            Err(KernelError::NotSupported)
+        ));
+    }
+
     #[tokio::test]
     async fn test_dir_subdirectories() {
         let fs = setup_fs();
@@ -1101,6 +1246,88 @@ mod tests {
         assert_eq!(names.len(), 3);
     }
 
+    #[tokio::test]
+    async fn test_link_counts() {
+        let fs = setup_fs();
+        let root = fs.root_inode().await.unwrap();
+
+        let file = root
+            .create("file", FileType::File, FilePermissions::empty(), None)
+            .await
+            .unwrap();
+        root.link("other", file.clone()).await.unwrap();
+        assert_eq!(file.getattr().await.unwrap().nlinks, 2);
+        assert_eq!(root.lookup("other").await.unwrap().id(), file.id());
+
+        // A name that's taken leaves the count alone.
+        assert!(root.link("other", file.clone()).await.is_err());
+        assert_eq!(file.getattr().await.unwrap().nlinks, 2);
+
+        root.unlink("file").await.unwrap();
+        assert_eq!(file.getattr().await.unwrap().nlinks, 1);
+        assert!(root.lookup("other").await.is_ok());
+
+        // Inodes can't be linked into another filesystem.
+        let other_fs = TmpFs::<MockCpuOps, TmpFsPgAllocGetter, IdentityTranslator>::new(2);
+        let other_root = other_fs.root_inode().await.unwrap();
+        assert!(matches!(
+            other_root.link("file", file).await,
+            Err(KernelError::Fs(FsError::CrossDevice))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_tmpfile() {
+        let fs = setup_fs();
+        let root = fs.root_inode().await.unwrap();
+
+        let file = root
+            .create_tmpfile(FilePermissions::empty(), None)
+            .await
+            .unwrap();
+        assert_eq!(file.getattr().await.unwrap().nlinks, 0);
+
+        // It's in no directory until it's linked into one.
+        let mut dir = root.readdir(0).await.unwrap();
+        assert!(dir.next_entry().await.unwrap().is_none());
+
+        file.write_at(0, b"data").await.unwrap();
+        root.link("named", file.clone()).await.unwrap();
+        assert_eq!(file.getattr().await.unwrap().nlinks, 1);
+
+        let named = root.lookup("named").await.unwrap();
+        let mut buf = [0u8; 4];
+        named.read_at(0, &mut buf).await.unwrap();
+        assert_eq!(&buf, b"data");
+    }
+
+    #[tokio::test]
+    async fn test_statfs() {
+        let fs = setup_fs();
+        let root = fs.root_inode().await.unwrap();
+        let before = fs.statfs().await.unwrap();
+
+        let file = root
+            .create("big", FileType::File, FilePermissions::empty(), None)
+            .await
+            .unwrap();
+        file.write_at(0, &vec![1u8; BLOCK_SZ * 3]).await.unwrap();
+        assert_eq!(fs.used_blocks.load(Ordering::Relaxed), 3);
+
+        file.truncate(BLOCK_SZ as u64).await.unwrap();
+        assert_eq!(fs.used_blocks.load(Ordering::Relaxed), 1);
+
+        root.unlink("big").await.unwrap();
+        drop(file);
+        assert_eq!(fs.used_blocks.load(Ordering::Relaxed), 0);
+
+        let after = fs.statfs().await.unwrap();
+        assert_eq!(after.block_size, BLOCK_SZ as u64);
+        assert_eq!(after.name_len, 255);
+        assert!(after.free_blocks <= after.total_blocks);
+        assert_eq!(before.total_blocks - before.free_blocks, 0);
+    }
+
     #[tokio::test]
     async fn test_inode_id_uniqueness() {
         let fs = setup_fs();
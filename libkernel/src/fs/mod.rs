@@ -25,6 +25,7 @@ use crate::{
     driver::CharDevDescriptor,
     error::{FsError, KernelError, Result},
     fs::{path::Path, pathbuf::PathBuf},
+    memory::PAGE_SIZE,
 };
 use alloc::vec::Vec;
 use alloc::{boxed::Box, string::String, sync::Arc};
@@ -49,6 +50,7 @@ mod _open_flags {
             const O_APPEND    = 0o2000;
             const O_NONBLOCK  = 0o4000;
             const O_CLOEXEC   = 0o2000000;
+            const O_TMPFILE   = 0o20200000;
         }
     }
 }
@@ -66,6 +68,26 @@ pub const CGROUPFS_ID: u64 = 4;
 /// Starting ID for user-mounted filesystem instances.
 pub const FS_ID_START: u64 = 10;
 
+/// How much space and how many inodes a filesystem has, and how many of each
+/// are free, as `statfs` reports them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FsStats {
+    /// The size of a block, which the counts of blocks are in.
+    pub block_size: u64,
+    /// The number of blocks in the filesystem.
+    pub total_blocks: u64,
+    /// The number of blocks that are free.
+    pub free_blocks: u64,
+    /// The number of free blocks an unprivileged user can use.
+    pub avail_blocks: u64,
+    /// The number of inodes in the filesystem, or zero if there's no limit.
+    pub total_inodes: u64,
+    /// The number of inodes that are free.
+    pub free_inodes: u64,
+    /// The longest a name in a directory can be.
+    pub name_len: u64,
+}
+
 /// Trait for a mounted filesystem instance. Its main role is to act as a
 /// factory for Inodes.
 #[async_trait]
@@ -79,6 +101,19 @@ pub trait Filesystem: Send + Sync {
     /// Get magic
     fn magic(&self) -> u64;
 
+    /// Returns how much space and how many inodes the filesystem has, and how
+    /// much of each is free.
+    ///
+    /// The default implementation suits pseudo-filesystems, which have no
+    /// storage to speak of, and reports them as empty, as Linux does.
+    async fn statfs(&self) -> Result<FsStats> {
+        Ok(FsStats {
+            block_size: PAGE_SIZE as _,
+            name_len: 255,
+            ..FsStats::default()
+        })
+    }
+
     /// Flushes all pending data to the underlying storage device(s).
     ///
     /// The default implementation is a no-op so that read-only filesystems do
@@ -199,10 +234,38 @@ pub trait BlockDevice: Send + Sync {
     /// The size of a single block in bytes.
     fn block_size(&self) -> usize;
 
+    /// The number of blocks on the device.
+    fn num_blocks(&self) -> u64;
+
     /// Flushes any caches to the underlying device.
     async fn sync(&self) -> Result<()>;
 }
 
+/// Lets a device be shared, e.g. by the filesystem mounted on it and the node
+/// it has in `/dev`.
+#[async_trait]
+impl<T: BlockDevice + ?Sized> BlockDevice for Arc<T> {
+    async fn read(&self, block_id: u64, buf: &mut [u8]) -> Result<()> {
+        (**self).read(block_id, buf).await
+    }
+
+    async fn write(&self, block_id: u64, buf: &[u8]) -> Result<()> {
+        (**self).write(block_id, buf).await
+    }
+
+    fn block_size(&self) -> usize {
+        (**self).block_size()
+    }
+
+    fn num_blocks(&self) -> u64 {
+        (**self).num_blocks()
+    }
+
+    async fn sync(&self) -> Result<()> {
+        (**self).sync().await
+    }
+}
+
 /// A stateless representation of a filesystem object.
 ///
 /// This trait represents an object on the disk (a file, a directory, etc.). All
@@ -284,6 +347,17 @@ pub trait Inode: Send + Sync + Any {
         Err(KernelError::NotSupported)
     }
 
+    /// Creates a regular file on the directory's filesystem without linking it
+    /// into any directory, for `O_TMPFILE`. It starts with no links, so lives
+    /// only as long as something holds it, unless it's linked in later.
+    async fn create_tmpfile(
+        &self,
+        _permissions: FilePermissions,
+        _time: Option<Duration>,
+    ) -> Result<Arc<dyn Inode>> {
+        Err(KernelError::NotSupported)
+    }
+
     /// Removes a link to an inode from a directory.
     async fn unlink(&self, _name: &str) -> Result<()> {
         Err(KernelError::NotSupported)
@@ -23,6 +23,7 @@
 //! | `fs`      | VFS traits, path manipulation, block I/O              | `proc`, `sync`   |
 //! | `proc_vm` | Process virtual-memory management (mmap, brk, CoW)    | `paging`, `fs`   |
 //! | `kbuf`    | Async-aware circular kernel buffers                   | `sync`           |
+//! | `acpi`    | ACPI table discovery and parsing                      | —                |
 //! | `all`     | Everything above                                      | all of the above |
 //!
 //! ## The `CpuOps` trait
@@ -45,10 +46,13 @@
 //! - [`proc`]   — Process identity types and Linux-compatible capabilities
 //!   *(feature `proc`)*.
 //! - [`arch`]   — Architecture-specific support code *(feature `paging`)*.
+//! - [`acpi`]   — ACPI table discovery and parsing *(feature `acpi`)*.
 
 #![cfg_attr(not(test), no_std)]
 #![warn(missing_docs)]
 
+#[cfg(feature = "acpi")]
+pub mod acpi;
 #[cfg(feature = "paging")]
 pub mod arch;
 #[cfg(feature = "fs")]
@@ -110,6 +114,15 @@ pub trait CpuOps: 'static {
 
     /// Explicitly enables maskable interrupts on the current CPU core.
     fn enable_interrupts();
+
+    /// Idles the current CPU core until an interrupt is pending.
+    ///
+    /// This must return when an interrupt becomes pending even if interrupts
+    /// are masked, so that callers can check a condition with interrupts
+    /// disabled and then wait without missing the wakeup. Spins by default.
+    fn wait_for_interrupt() {
+        core::hint::spin_loop();
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,528 @@
+//! A KASAN-style sanitizing wrapper for kernel heap allocators.
+//!
+//! [`KasanHeap`] wraps another [`GlobalAlloc`] and surrounds every object with
+//! poisoned redzones. A small header in front of each object records its state
+//! and the backtraces of the calls which allocated and freed it. Freed objects
+//! are poisoned and held in a quarantine before being returned to the
+//! underlying allocator, which delays their reuse.
+//!
+//! Without compiler instrumentation we can't trap on the offending access
+//! itself, so errors are detected after the fact:
+//!
+//! - Out-of-bounds writes are detected when the object is freed, by checking
+//!   its redzones.
+//! - Use-after-free writes are detected when the object leaves the
+//!   quarantine, by checking that its poison is intact.
+//! - Double and invalid frees are detected immediately from the header. The
+//!   object is leaked rather than handed back to the underlying allocator.
+
+use super::stack_trace::StackTrace;
+use crate::{CpuOps, sync::spinlock::SpinLockIrq};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    fmt::{self, Display},
+    marker::PhantomData,
+    ptr::{self, NonNull},
+    slice,
+};
+
+/// The minimum size of the redzones either side of an object.
+const REDZONE_SIZE: usize = 32;
+
+/// The maximum number of objects held in the quarantine.
+const QUARANTINE_OBJS: usize = 1024;
+
+/// The maximum number of bytes held in the quarantine.
+const QUARANTINE_BYTES: usize = 4 * 1024 * 1024;
+
+const REDZONE_POISON: u8 = 0xcc;
+const ALLOC_POISON: u8 = 0x5a;
+const FREE_POISON: u8 = 0x6b;
+
+const STATE_ALLOCATED: u64 = 0x4b41_5341_4e41_4c4c;
+const STATE_FREED: u64 = 0x4b41_5341_4e46_5245;
+
+/// Hooks through which the sanitizer interacts with the rest of the kernel.
+pub trait KasanOps {
+    /// Write the return addresses of the current call stack into `frames`,
+    /// returning the number of frames captured.
+    fn capture_backtrace(frames: &mut [usize]) -> usize;
+
+    /// Report a detected memory error.
+    fn report(report: &KasanReport<'_>);
+}
+
+/// The class of memory error detected by the sanitizer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KasanErrorKind {
+    /// A write to the redzone surrounding an object.
+    OutOfBounds,
+    /// A write to an object after it was freed.
+    UseAfterFree,
+    /// An object was freed twice.
+    DoubleFree,
+    /// A pointer not returned by the allocator, or with the wrong layout, was
+    /// freed.
+    InvalidFree,
+}
+
+impl Display for KasanErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::OutOfBounds => "slab-out-of-bounds",
+            Self::UseAfterFree => "use-after-free",
+            Self::DoubleFree => "double-free",
+            Self::InvalidFree => "invalid-free",
+        })
+    }
+}
+
+/// A memory error detected by the sanitizer.
+#[derive(Debug)]
+pub struct KasanReport<'a> {
+    /// The class of error.
+    pub kind: KasanErrorKind,
+    /// The address of the first corrupted byte, or the pointer passed to
+    /// `dealloc` for free errors.
+    pub addr: usize,
+    /// The start of the object involved.
+    pub object: usize,
+    /// The size of the object involved, in bytes.
+    pub size: usize,
+    /// Where the object was allocated, if known.
+    pub alloc_trace: &'a [usize],
+    /// Where the object was freed, if it has been.
+    pub free_trace: &'a [usize],
+}
+
+/// Metadata stored at the start of each allocation, ahead of the left redzone.
+#[repr(C)]
+struct ObjHeader {
+    state: u64,
+    size: usize,
+    alloc_trace: StackTrace,
+    free_trace: StackTrace,
+}
+
+/// The placement of an object within its underlying allocation.
+#[derive(Clone, Copy)]
+struct ObjLayout {
+    /// The layout passed to the underlying allocator.
+    outer: Layout,
+    /// The offset of the object from the start of the allocation.
+    offset: usize,
+    /// The size of the object.
+    size: usize,
+}
+
+impl ObjLayout {
+    fn new(layout: Layout) -> Option<Self> {
+        let align = layout.align().max(align_of::<ObjHeader>());
+        let offset = (size_of::<ObjHeader>() + REDZONE_SIZE).next_multiple_of(align);
+        let total = offset
+            .checked_add(layout.size())?
+            .checked_add(REDZONE_SIZE)?
+            .next_multiple_of(align);
+
+        Some(Self {
+            outer: Layout::from_size_align(total, align).ok()?,
+            offset,
+            size: layout.size(),
+        })
+    }
+
+    /// The byte ranges, relative to the start of the allocation, of the left
+    /// and right redzones.
+    fn redzones(&self) -> [(usize, usize); 2] {
+        [
+            (size_of::<ObjHeader>(), self.offset),
+            (self.offset + self.size, self.outer.size()),
+        ]
+    }
+}
+
+#[derive(Clone, Copy)]
+struct QuarantineEntry {
+    base: NonNull<u8>,
+    layout: ObjLayout,
+}
+
+/// A FIFO of freed objects awaiting release to the underlying allocator.
+struct Quarantine {
+    entries: [Option<QuarantineEntry>; QUARANTINE_OBJS],
+    head: usize,
+    len: usize,
+    bytes: usize,
+}
+
+// SAFETY: The quarantine exclusively owns the freed objects it holds.
+unsafe impl Send for Quarantine {}
+
+impl Quarantine {
+    const fn new() -> Self {
+        Self {
+            entries: [None; QUARANTINE_OBJS],
+            head: 0,
+            len: 0,
+            bytes: 0,
+        }
+    }
+
+    fn push(&mut self, entry: QuarantineEntry) {
+        debug_assert!(self.len < QUARANTINE_OBJS);
+
+        self.entries[(self.head + self.len) % QUARANTINE_OBJS] = Some(entry);
+        self.len += 1;
+        self.bytes += entry.layout.outer.size();
+    }
+
+    /// Pops the oldest entry if the quarantine is over either of its limits.
+    fn pop_excess(&mut self) -> Option<QuarantineEntry> {
+        if self.len < QUARANTINE_OBJS && self.bytes <= QUARANTINE_BYTES {
+            return None;
+        }
+
+        let entry = self.entries[self.head].take()?;
+
+        self.head = (self.head + 1) % QUARANTINE_OBJS;
+        self.len -= 1;
+        self.bytes -= entry.layout.outer.size();
+
+        Some(entry)
+    }
+}
+
+/// A [`GlobalAlloc`] which sanitizes the allocations of an inner allocator.
+/// See the [module documentation](self).
+pub struct KasanHeap<H: GlobalAlloc, CPU: CpuOps, K: KasanOps> {
+    inner: H,
+    quarantine: SpinLockIrq<Quarantine, CPU>,
+    phantom: PhantomData<K>,
+}
+
+impl<H: GlobalAlloc, CPU: CpuOps, K: KasanOps> KasanHeap<H, CPU, K> {
+    /// Creates a new sanitizing heap on top of `inner`.
+    pub const fn new(inner: H) -> Self {
+        Self {
+            inner,
+            quarantine: SpinLockIrq::new(Quarantine::new()),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Returns the first byte of `range` within the allocation at `base` which
+    /// doesn't hold `poison`.
+    ///
+    /// # Safety
+    ///
+    /// `range` must lie within the live allocation at `base`.
+    unsafe fn find_corruption(base: *const u8, range: (usize, usize), poison: u8) -> Option<usize> {
+        let bytes = unsafe { slice::from_raw_parts(base.add(range.0), range.1 - range.0) };
+
+        bytes.iter().position(|&b| b != poison).map(|i| range.0 + i)
+    }
+
+    /// Reports an error against the allocation at `base`.
+    ///
+    /// # Safety
+    ///
+    /// `base` must point to a live allocation with a valid header.
+    unsafe fn report(kind: KasanErrorKind, addr: usize, base: *const u8, layout: ObjLayout) {
+        // Copy the header out so that the reporter may freely allocate.
+        let header = unsafe { ptr::read(base.cast::<ObjHeader>()) };
+
+        K::report(&KasanReport {
+            kind,
+            addr,
+            object: base as usize + layout.offset,
+            size: layout.size,
+            alloc_trace: header.alloc_trace.frames(),
+            free_trace: header.free_trace.frames(),
+        });
+    }
+
+    /// Checks that a quarantined object hasn't been modified, then returns it
+    /// to the underlying allocator.
+    ///
+    /// # Safety
+    ///
+    /// `entry` must have been removed from the quarantine.
+    unsafe fn release(&self, entry: QuarantineEntry) {
+        let base = entry.base.as_ptr();
+        let layout = entry.layout;
+        let object = (layout.offset, layout.offset + layout.size);
+
+        let corruption = unsafe {
+            Self::find_corruption(base, object, FREE_POISON)
+                .or_else(|| Self::find_corruption(base, layout.redzones()[0], REDZONE_POISON))
+                .or_else(|| Self::find_corruption(base, layout.redzones()[1], REDZONE_POISON))
+        };
+
+        if let Some(offset) = corruption {
+            unsafe {
+                Self::report(
+                    KasanErrorKind::UseAfterFree,
+                    base as usize + offset,
+                    base,
+                    layout,
+                );
+            }
+        }
+
+        unsafe { self.inner.dealloc(base, layout.outer) };
+    }
+}
+
+unsafe impl<H: GlobalAlloc, CPU: CpuOps, K: KasanOps> GlobalAlloc for KasanHeap<H, CPU, K> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let Some(obj_layout) = ObjLayout::new(layout) else {
+            return ptr::null_mut();
+        };
+
+        let base = unsafe { self.inner.alloc(obj_layout.outer) };
+
+        if base.is_null() {
+            return base;
+        }
+
+        unsafe {
+            for (start, end) in obj_layout.redzones() {
+                ptr::write_bytes(base.add(start), REDZONE_POISON, end - start);
+            }
+
+            ptr::write_bytes(base.add(obj_layout.offset), ALLOC_POISON, layout.size());
+
+            ptr::write(
+                base.cast::<ObjHeader>(),
+                ObjHeader {
+                    state: STATE_ALLOCATED,
+                    size: layout.size(),
+                    alloc_trace: StackTrace::capture(K::capture_backtrace),
+                    free_trace: StackTrace::EMPTY,
+                },
+            );
+
+            base.add(obj_layout.offset)
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let Some(obj_layout) = ObjLayout::new(layout) else {
+            return;
+        };
+
+        let base = unsafe { ptr.sub(obj_layout.offset) };
+        let header = base.cast::<ObjHeader>();
+
+        match unsafe { ((*header).state, (*header).size) } {
+            (STATE_ALLOCATED, size) if size == layout.size() => {}
+            (STATE_FREED, _) => {
+                unsafe { Self::report(KasanErrorKind::DoubleFree, ptr as _, base, obj_layout) };
+                return;
+            }
+            _ => {
+                // The header can't be trusted, so report without the traces.
+                K::report(&KasanReport {
+                    kind: KasanErrorKind::InvalidFree,
+                    addr: ptr as _,
+                    object: ptr as _,
+                    size: layout.size(),
+                    alloc_trace: &[],
+                    free_trace: &[],
+                });
+                return;
+            }
+        }
+
+        for redzone in obj_layout.redzones() {
+            if let Some(offset) = unsafe { Self::find_corruption(base, redzone, REDZONE_POISON) } {
+                unsafe {
+                    Self::report(
+                        KasanErrorKind::OutOfBounds,
+                        base as usize + offset,
+                        base,
+                        obj_layout,
+                    );
+                }
+                break;
+            }
+        }
+
+        unsafe {
+            ptr::write_bytes(ptr, FREE_POISON, layout.size());
+
+            (*header).state = STATE_FREED;
+            (*header).free_trace = StackTrace::capture(K::capture_backtrace);
+        }
+
+        let mut quarantine = self.quarantine.lock_save_irq();
+
+        quarantine.push(QuarantineEntry {
+            // SAFETY: `base` is derived from a non-null allocation.
+            base: unsafe { NonNull::new_unchecked(base) },
+            layout: obj_layout,
+        });
+
+        // Release objects outside of the lock so that any reports may
+        // allocate.
+        while let Some(entry) = quarantine.pop_excess() {
+            drop(quarantine);
+
+            unsafe { self.release(entry) };
+
+            quarantine = self.quarantine.lock_save_irq();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::MockCpuOps;
+    use std::{alloc::System, cell::RefCell, vec::Vec};
+
+    thread_local! {
+        static REPORTS: RefCell<Vec<(KasanErrorKind, usize, usize)>> =
+            const { RefCell::new(Vec::new()) };
+    }
+
+    struct TestOps;
+
+    impl KasanOps for TestOps {
+        fn capture_backtrace(frames: &mut [usize]) -> usize {
+            frames[0] = 0x1234;
+            1
+        }
+
+        fn report(report: &KasanReport<'_>) {
+            if report.kind != KasanErrorKind::InvalidFree {
+                assert_eq!(report.alloc_trace, &[0x1234]);
+            }
+
+            REPORTS.with_borrow_mut(|r| r.push((report.kind, report.addr, report.object)));
+        }
+    }
+
+    type TestHeap = KasanHeap<System, MockCpuOps, TestOps>;
+
+    fn take_reports() -> Vec<(KasanErrorKind, usize, usize)> {
+        REPORTS.with(|r| r.take())
+    }
+
+    /// Frees enough objects to flush everything else out of the quarantine.
+    fn flush_quarantine(heap: &TestHeap) {
+        let layout = Layout::new::<u64>();
+
+        for _ in 0..QUARANTINE_OBJS {
+            unsafe { heap.dealloc(heap.alloc(layout), layout) };
+        }
+    }
+
+    #[test]
+    fn clean_alloc_and_free() {
+        let heap = TestHeap::new(System);
+
+        for align in [1, 8, 64, 4096] {
+            let layout = Layout::from_size_align(100, align).unwrap();
+            let ptr = unsafe { heap.alloc(layout) };
+
+            assert_eq!(ptr as usize % align, 0);
+
+            unsafe {
+                ptr::write_bytes(ptr, 0xff, 100);
+                heap.dealloc(ptr, layout);
+            }
+        }
+
+        flush_quarantine(&heap);
+
+        assert!(take_reports().is_empty());
+    }
+
+    #[test]
+    fn detects_out_of_bounds_write() {
+        let heap = TestHeap::new(System);
+        let layout = Layout::from_size_align(24, 8).unwrap();
+        let ptr = unsafe { heap.alloc(layout) };
+
+        unsafe {
+            ptr.add(24).write(0);
+            heap.dealloc(ptr, layout);
+        }
+
+        assert_eq!(
+            take_reports(),
+            [(KasanErrorKind::OutOfBounds, ptr as usize + 24, ptr as usize)]
+        );
+
+        let ptr = unsafe { heap.alloc(layout) };
+
+        unsafe {
+            ptr.sub(1).write(0);
+            heap.dealloc(ptr, layout);
+        }
+
+        assert_eq!(
+            take_reports(),
+            [(KasanErrorKind::OutOfBounds, ptr as usize - 1, ptr as usize)]
+        );
+    }
+
+    #[test]
+    fn detects_use_after_free_write() {
+        let heap = TestHeap::new(System);
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let ptr = unsafe { heap.alloc(layout) };
+
+        unsafe {
+            heap.dealloc(ptr, layout);
+            // The object is still quarantined, so this won't hit another
+            // allocation.
+            ptr.add(10).write(0);
+        }
+
+        assert!(take_reports().is_empty());
+
+        flush_quarantine(&heap);
+
+        assert_eq!(
+            take_reports(),
+            [(
+                KasanErrorKind::UseAfterFree,
+                ptr as usize + 10,
+                ptr as usize
+            )]
+        );
+    }
+
+    #[test]
+    fn detects_double_and_invalid_free() {
+        let heap = TestHeap::new(System);
+        let layout = Layout::from_size_align(32, 8).unwrap();
+        let ptr = unsafe { heap.alloc(layout) };
+
+        unsafe {
+            heap.dealloc(ptr, layout);
+            heap.dealloc(ptr, layout);
+        }
+
+        assert_eq!(
+            take_reports(),
+            [(KasanErrorKind::DoubleFree, ptr as usize, ptr as usize)]
+        );
+
+        let ptr = unsafe { heap.alloc(layout) };
+
+        unsafe { heap.dealloc(ptr, Layout::from_size_align(16, 8).unwrap()) };
+
+        assert_eq!(
+            take_reports(),
+            [(KasanErrorKind::InvalidFree, ptr as usize, ptr as usize)]
+        );
+
+        unsafe { heap.dealloc(ptr, layout) };
+        flush_quarantine(&heap);
+
+        assert!(take_reports().is_empty());
+    }
+}
@@ -0,0 +1,515 @@
+//! A kmemleak-style detector for leaked kernel heap objects.
+//!
+//! [`KmemleakHeap`] wraps another [`GlobalAlloc`] and places a header in front
+//! of every object, recording its size and the time and backtrace of its
+//! allocation. The header links the object into the list of live objects held
+//! by a [`Kmemleak`] tracker.
+//!
+//! [`Kmemleak::scan`] then performs a conservative mark phase. Every aligned
+//! word in the given root regions, and transitively in every object found to
+//! be referenced, is treated as a potential pointer. Live objects which no
+//! such word points into, and which are older than [`MIN_LEAK_AGE`], are
+//! flagged as suspected leaks.
+//!
+//! As with any conservative scan, a leak can be hidden by a stale value which
+//! happens to look like a pointer to it. Conversely, an object which is only
+//! referenced from memory outside the roots and the tracked heap, e.g. a page
+//! handed out by the frame allocator, will be falsely reported.
+
+use super::stack_trace::StackTrace;
+use crate::{
+    CpuOps,
+    memory::region::VirtMemoryRegion,
+    sync::spinlock::{SpinLockIrq, SpinLockIrqGuard},
+};
+use alloc::vec::Vec;
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    marker::PhantomData,
+    ptr,
+    time::Duration,
+};
+
+/// Objects younger than this are never reported, since a reference to them
+/// may still be in flight, e.g. held only in registers or on a stack.
+pub const MIN_LEAK_AGE: Duration = Duration::from_secs(5);
+
+/// Hooks through which the leak detector interacts with the rest of the
+/// kernel.
+pub trait KmemleakOps<CPU: CpuOps> {
+    /// Write the return addresses of the current call stack into `frames`,
+    /// returning the number of frames captured.
+    fn capture_backtrace(frames: &mut [usize]) -> usize;
+
+    /// The current time, as used to age objects.
+    fn now() -> Duration;
+
+    /// Returns the tracker that live objects are registered with.
+    fn tracker() -> &'static Kmemleak<CPU>;
+}
+
+/// Metadata stored immediately in front of each object.
+#[repr(C)]
+struct ObjHeader {
+    prev: *mut ObjHeader,
+    next: *mut ObjHeader,
+    /// The next object on the scan's work list.
+    next_grey: *mut ObjHeader,
+    size: usize,
+    allocated_at: Duration,
+    trace: StackTrace,
+    /// Whether the last scan found a reference to the object.
+    referenced: bool,
+    /// Whether the last scan flagged the object as a leak.
+    unreferenced: bool,
+    /// Whether the object has been counted as a new leak by a scan.
+    reported: bool,
+    /// Whether the object should never be reported.
+    ignored: bool,
+}
+
+impl ObjHeader {
+    fn object(&self) -> usize {
+        ptr::from_ref(self) as usize + size_of::<Self>()
+    }
+}
+
+/// A suspected leak found by [`Kmemleak::scan`].
+#[derive(Debug, Clone)]
+pub struct LeakSuspect {
+    /// The address of the object.
+    pub addr: usize,
+    /// The size of the object, in bytes.
+    pub size: usize,
+    /// When the object was allocated.
+    pub allocated_at: Duration,
+    /// Where the object was allocated.
+    pub trace: StackTrace,
+}
+
+struct ObjectList {
+    head: *mut ObjHeader,
+    count: usize,
+}
+
+// SAFETY: The list is only accessed under the tracker's lock.
+unsafe impl Send for ObjectList {}
+
+impl ObjectList {
+    fn iter(&self) -> impl Iterator<Item = *mut ObjHeader> + '_ {
+        let mut cursor = self.head;
+
+        core::iter::from_fn(move || {
+            let obj = cursor;
+
+            if obj.is_null() {
+                return None;
+            }
+
+            // SAFETY: Objects in the list are live until they're removed,
+            // which requires the lock we're borrowed from.
+            cursor = unsafe { (*obj).next };
+
+            Some(obj)
+        })
+    }
+}
+
+/// The set of live objects tracked by a [`KmemleakHeap`].
+pub struct Kmemleak<CPU: CpuOps> {
+    objects: SpinLockIrq<ObjectList, CPU>,
+}
+
+impl<CPU: CpuOps> Default for Kmemleak<CPU> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<CPU: CpuOps> Kmemleak<CPU> {
+    /// Creates an empty tracker.
+    pub const fn new() -> Self {
+        Self {
+            objects: SpinLockIrq::new(ObjectList {
+                head: ptr::null_mut(),
+                count: 0,
+            }),
+        }
+    }
+
+    /// The number of objects currently tracked.
+    pub fn object_count(&self) -> usize {
+        self.objects.lock_save_irq().count
+    }
+
+    fn insert(&self, obj: *mut ObjHeader) {
+        let mut objects = self.objects.lock_save_irq();
+
+        unsafe {
+            (*obj).prev = ptr::null_mut();
+            (*obj).next = objects.head;
+
+            if !objects.head.is_null() {
+                (*objects.head).prev = obj;
+            }
+        }
+
+        objects.head = obj;
+        objects.count += 1;
+    }
+
+    fn remove(&self, obj: *mut ObjHeader) {
+        let mut objects = self.objects.lock_save_irq();
+
+        unsafe {
+            let (prev, next) = ((*obj).prev, (*obj).next);
+
+            if prev.is_null() {
+                objects.head = next;
+            } else {
+                (*prev).next = next;
+            }
+
+            if !next.is_null() {
+                (*next).prev = prev;
+            }
+        }
+
+        objects.count -= 1;
+    }
+
+    /// Locks the object list, having first ensured that `vec` has room for an
+    /// entry per object.
+    ///
+    /// Allocating may itself take the lock, so `vec` must be grown while it's
+    /// dropped.
+    fn lock_with_capacity<E>(&self, vec: &mut Vec<E>) -> SpinLockIrqGuard<'_, ObjectList, CPU> {
+        loop {
+            let count = self.object_count();
+
+            // Leave some slack for objects allocated in the meantime.
+            vec.reserve(count + count / 8 + 16);
+
+            let objects = self.objects.lock_save_irq();
+
+            if objects.count <= vec.capacity() {
+                return objects;
+            }
+        }
+    }
+
+    /// Scans `roots` and the tracked objects for references, flagging any
+    /// unreferenced objects as leaks. Returns the number of leaks which
+    /// weren't flagged by a previous scan.
+    ///
+    /// # Safety
+    ///
+    /// `roots` must be mapped and readable.
+    pub unsafe fn scan(&self, roots: &[VirtMemoryRegion], now: Duration) -> usize {
+        let mut index: Vec<(usize, *mut ObjHeader)> = Vec::new();
+        let objects = self.lock_with_capacity(&mut index);
+
+        for obj in objects.iter() {
+            unsafe {
+                (*obj).referenced = false;
+                index.push(((*obj).object(), obj));
+            }
+        }
+
+        index.sort_unstable_by_key(|&(addr, _)| addr);
+
+        let mut grey: *mut ObjHeader = ptr::null_mut();
+
+        for root in roots {
+            unsafe {
+                Self::scan_range(&index, &mut grey, root.start_address().value(), root.size());
+            }
+        }
+
+        while !grey.is_null() {
+            let obj = grey;
+
+            unsafe {
+                grey = (*obj).next_grey;
+                Self::scan_range(&index, &mut grey, (*obj).object(), (*obj).size);
+            }
+        }
+
+        let mut new_leaks = 0;
+
+        for obj in objects.iter() {
+            let obj = unsafe { &mut *obj };
+            let old_enough = now.saturating_sub(obj.allocated_at) >= MIN_LEAK_AGE;
+
+            obj.unreferenced = !obj.referenced && !obj.ignored && old_enough;
+
+            if obj.unreferenced && !obj.reported {
+                obj.reported = true;
+                new_leaks += 1;
+            }
+        }
+
+        drop(objects);
+
+        new_leaks
+    }
+
+    /// Marks every object referenced by a word in `[start, start + len)`,
+    /// pushing newly marked objects onto `grey`.
+    ///
+    /// # Safety
+    ///
+    /// The range must be mapped and readable, and the object list locked.
+    unsafe fn scan_range(
+        index: &[(usize, *mut ObjHeader)],
+        grey: &mut *mut ObjHeader,
+        start: usize,
+        len: usize,
+    ) {
+        let first = start.next_multiple_of(align_of::<usize>());
+        let end = start + len;
+
+        for addr in (first..end.saturating_sub(size_of::<usize>() - 1)).step_by(size_of::<usize>())
+        {
+            // Other CPUs may be writing to the memory being scanned.
+            let word = unsafe { ptr::read_volatile(addr as *const usize) };
+
+            // Find the last object starting at or before `word`.
+            let Some(&(obj_start, obj)) = index
+                .partition_point(|&(obj_start, _)| obj_start <= word)
+                .checked_sub(1)
+                .map(|i| &index[i])
+            else {
+                continue;
+            };
+
+            unsafe {
+                if word >= obj_start + (*obj).size || (*obj).referenced {
+                    continue;
+                }
+
+                (*obj).referenced = true;
+                (*obj).next_grey = *grey;
+            }
+
+            *grey = obj;
+        }
+    }
+
+    /// Returns the objects flagged as leaks by the last scan.
+    pub fn suspects(&self) -> Vec<LeakSuspect> {
+        let mut suspects = Vec::new();
+        let objects = self.lock_with_capacity(&mut suspects);
+
+        for obj in objects.iter() {
+            let obj = unsafe { &*obj };
+
+            if obj.unreferenced {
+                suspects.push(LeakSuspect {
+                    addr: obj.object(),
+                    size: obj.size,
+                    allocated_at: obj.allocated_at,
+                    trace: obj.trace,
+                });
+            }
+        }
+
+        drop(objects);
+
+        suspects
+    }
+
+    /// Stops reporting all objects currently flagged as leaks.
+    pub fn clear(&self) {
+        let objects = self.objects.lock_save_irq();
+
+        for obj in objects.iter() {
+            let obj = unsafe { &mut *obj };
+
+            if obj.unreferenced {
+                obj.unreferenced = false;
+                obj.ignored = true;
+            }
+        }
+    }
+}
+
+/// A [`GlobalAlloc`] which registers the allocations of an inner allocator
+/// with a leak detector. See the [module documentation](self).
+pub struct KmemleakHeap<H: GlobalAlloc, CPU: CpuOps, K: KmemleakOps<CPU>> {
+    inner: H,
+    phantom1: PhantomData<CPU>,
+    phantom2: PhantomData<K>,
+}
+
+impl<H: GlobalAlloc, CPU: CpuOps, K: KmemleakOps<CPU>> KmemleakHeap<H, CPU, K> {
+    /// Creates a new leak-tracking heap on top of `inner`.
+    pub const fn new(inner: H) -> Self {
+        Self {
+            inner,
+            phantom1: PhantomData,
+            phantom2: PhantomData,
+        }
+    }
+
+    /// Returns the layout passed to the inner allocator for `layout`, and the
+    /// offset of the object within it.
+    fn outer_layout(layout: Layout) -> Option<(Layout, usize)> {
+        let align = layout.align().max(align_of::<ObjHeader>());
+        let offset = size_of::<ObjHeader>().next_multiple_of(align);
+        let size = offset.checked_add(layout.size())?;
+
+        Some((Layout::from_size_align(size, align).ok()?, offset))
+    }
+}
+
+unsafe impl<H: GlobalAlloc, CPU: CpuOps, K: KmemleakOps<CPU>> GlobalAlloc
+    for KmemleakHeap<H, CPU, K>
+{
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let Some((outer, offset)) = Self::outer_layout(layout) else {
+            return ptr::null_mut();
+        };
+
+        let base = unsafe { self.inner.alloc(outer) };
+
+        if base.is_null() {
+            return base;
+        }
+
+        unsafe {
+            let ptr = base.add(offset);
+            let header = ptr.sub(size_of::<ObjHeader>()).cast::<ObjHeader>();
+
+            header.write(ObjHeader {
+                prev: ptr::null_mut(),
+                next: ptr::null_mut(),
+                next_grey: ptr::null_mut(),
+                size: layout.size(),
+                allocated_at: K::now(),
+                trace: StackTrace::capture(K::capture_backtrace),
+                referenced: false,
+                unreferenced: false,
+                reported: false,
+                ignored: false,
+            });
+
+            K::tracker().insert(header);
+
+            ptr
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let Some((outer, offset)) = Self::outer_layout(layout) else {
+            return;
+        };
+
+        unsafe {
+            K::tracker().remove(ptr.sub(size_of::<ObjHeader>()).cast());
+
+            self.inner.dealloc(ptr.sub(offset), outer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{memory::address::VA, test::MockCpuOps};
+    use std::{alloc::System, boxed::Box};
+
+    static TRACKER: Kmemleak<MockCpuOps> = Kmemleak::new();
+
+    struct TestOps;
+
+    impl KmemleakOps<MockCpuOps> for TestOps {
+        fn capture_backtrace(frames: &mut [usize]) -> usize {
+            frames[0] = 0x1234;
+            1
+        }
+
+        fn now() -> Duration {
+            Duration::ZERO
+        }
+
+        fn tracker() -> &'static Kmemleak<MockCpuOps> {
+            &TRACKER
+        }
+    }
+
+    type TestHeap = KmemleakHeap<System, MockCpuOps, TestOps>;
+
+    fn suspect_addrs() -> Vec<usize> {
+        let mut addrs: Vec<_> = TRACKER.suspects().iter().map(|s| s.addr).collect();
+        addrs.sort_unstable();
+        addrs
+    }
+
+    #[test]
+    fn finds_unreferenced_objects() {
+        let heap = TestHeap::new(System);
+        let layout = Layout::new::<[usize; 4]>();
+        let alloc = || unsafe { heap.alloc(layout).cast::<usize>() };
+
+        let root = Box::new([0usize; 2]);
+        let root_region =
+            VirtMemoryRegion::new(VA::from_ptr(root.as_ptr().cast()), size_of_val(&*root));
+
+        // `a` is referenced directly by the root and `b` via an interior
+        // pointer from `a`. `leaked` and `cycle` aren't reachable, even
+        // though `cycle` references itself.
+        let a = alloc();
+        let b = alloc();
+        let leaked = alloc();
+        let cycle = alloc();
+
+        unsafe {
+            ptr::write_bytes(a, 0, 4);
+            ptr::write_bytes(b, 0, 4);
+            ptr::write_bytes(leaked, 0, 4);
+            ptr::write_bytes(cycle, 0, 4);
+
+            root.as_ptr().cast_mut().write_volatile(a as usize);
+            a.write(b.add(2) as usize);
+            cycle.add(1).write(cycle as usize);
+        }
+
+        assert_eq!(TRACKER.object_count(), 4);
+
+        // Nothing is old enough to report yet.
+        assert_eq!(
+            unsafe { TRACKER.scan(&[root_region], Duration::from_secs(1)) },
+            0
+        );
+        assert!(TRACKER.suspects().is_empty());
+
+        let mut expected = vec![leaked as usize, cycle as usize];
+        expected.sort_unstable();
+
+        assert_eq!(unsafe { TRACKER.scan(&[root_region], MIN_LEAK_AGE) }, 2);
+        assert_eq!(suspect_addrs(), expected);
+        assert_eq!(TRACKER.suspects()[0].trace.frames(), &[0x1234]);
+
+        // Leaks are only counted as new once, but are still listed.
+        assert_eq!(unsafe { TRACKER.scan(&[root_region], MIN_LEAK_AGE) }, 0);
+        assert_eq!(suspect_addrs(), expected);
+
+        // Dropping the root's reference leaks `a` and, transitively, `b`.
+        unsafe { root.as_ptr().cast_mut().write_volatile(0) };
+
+        assert_eq!(unsafe { TRACKER.scan(&[root_region], MIN_LEAK_AGE) }, 2);
+        assert_eq!(suspect_addrs().len(), 4);
+
+        TRACKER.clear();
+
+        assert!(TRACKER.suspects().is_empty());
+        assert_eq!(unsafe { TRACKER.scan(&[root_region], MIN_LEAK_AGE) }, 0);
+
+        for obj in [a, b, leaked, cycle] {
+            unsafe { heap.dealloc(obj.cast(), layout) };
+        }
+
+        assert_eq!(TRACKER.object_count(), 0);
+    }
+}
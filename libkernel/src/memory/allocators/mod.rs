@@ -1,6 +1,10 @@
 //! Memory allocators.
 
 mod frame;
+pub mod kasan;
+pub mod kmemleak;
 pub mod phys;
 pub mod slab;
 pub mod smalloc;
+pub mod stack_trace;
+pub mod vmalloc;
@@ -32,10 +32,35 @@ use super::{
 /// 2^MAX_ORDER pages.
 pub const MAX_ORDER: usize = 10;
 
+/// A snapshot of the buddy allocator's per-order state, used for diagnosing
+/// fragmentation.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BuddyStats {
+    /// The number of free blocks of each order.
+    pub free_blocks: [usize; MAX_ORDER + 1],
+    /// The number of allocations of each order that failed because no free
+    /// block of that order, or larger, was available.
+    pub failed_allocs: [usize; MAX_ORDER + 1],
+}
+
+impl BuddyStats {
+    /// Returns the number of free pages held in blocks of at least `order`.
+    /// An allocation of `order` can only succeed if this is nonzero.
+    pub fn free_pages_at_or_above(&self, order: usize) -> usize {
+        self.free_blocks
+            .iter()
+            .enumerate()
+            .skip(order)
+            .map(|(order, count)| count << order)
+            .sum()
+    }
+}
+
 pub(super) struct FrameAllocatorInner {
     frame_list: FrameList,
     free_pages: usize,
     free_lists: [LinkedList<FrameAdapter>; MAX_ORDER + 1],
+    stats: BuddyStats,
 }
 
 impl FrameAllocatorInner {
@@ -141,6 +166,7 @@ impl FrameAllocatorInner {
 
         self.free_lists[order]
             .push_front(unsafe { UnsafeRef::from_raw(self.get_frame(pfn) as *const _) });
+        self.stats.free_blocks[order] += 1;
     }
 
     fn remove_from_free_list(&mut self, pfn: PageFrame, order: usize) {
@@ -152,6 +178,8 @@ impl FrameAllocatorInner {
             panic!("Attempted to remove non-free block");
         };
 
+        self.stats.free_blocks[order] -= 1;
+
         // Mark the removed frame as uninitialized to prevent dangling pointers.
         self.get_frame_mut(pfn).state = FrameState::Uninitialized;
     }
@@ -201,7 +229,7 @@ pub struct PageAllocation<'a, CPU: CpuOps> {
     inner: &'a SpinLockIrq<FrameAllocatorInner, CPU>,
 }
 
-impl<CPU: CpuOps> PageAllocation<'_, CPU> {
+impl<'a, CPU: CpuOps> PageAllocation<'a, CPU> {
     /// Consumes the allocation without freeing it, returning the underlying region.
     pub fn leak(self) -> PhysMemoryRegion {
         let region = self.region;
@@ -214,6 +242,39 @@ impl<CPU: CpuOps> PageAllocation<'_, CPU> {
         &self.region
     }
 
+    /// Splits a multi-page allocation into independent single-page
+    /// allocations.
+    ///
+    /// Each resulting page carries the same reference count as the original
+    /// block and can be freed individually. This is used when a huge page
+    /// mapping is broken up into base pages, e.g. by a partial `munmap`.
+    pub fn split(self) -> impl Iterator<Item = PageAllocation<'a, CPU>> {
+        let inner = self.inner;
+        let region = self.leak();
+
+        {
+            let mut inner = inner.lock_save_irq();
+            let head = region.start_address().to_pfn();
+
+            let ref_count = match inner.get_frame(head).state {
+                FrameState::AllocatedHead(AllocatedInfo { ref_count, .. }) => ref_count,
+                _ => panic!("Inconsistent memory metadata detected"),
+            };
+
+            for pfn in region.iter_pfns() {
+                inner.get_frame_mut(pfn).state = FrameState::AllocatedHead(AllocatedInfo {
+                    ref_count,
+                    order: 0,
+                });
+            }
+        }
+
+        region.iter_pfns().map(move |pfn| PageAllocation {
+            region: pfn.as_phys_range(),
+            inner,
+        })
+    }
+
     /// Leak the allocation as a slab allocation, for it to be picked back up
     /// again once the slab has been free'd.
     ///
@@ -283,9 +344,12 @@ impl<CPU: CpuOps> FrameAllocator<CPU> {
                 Some((pg_block, order))
             })
         else {
+            inner.stats.failed_allocs[requested_order] += 1;
             return Err(KernelError::NoMemory);
         };
 
+        inner.stats.free_blocks[current_order] -= 1;
+
         let free_block = inner.get_frame_mut(free_block.pfn);
 
         free_block.state = FrameState::Uninitialized;
@@ -348,16 +412,22 @@ impl<CPU: CpuOps> FrameAllocator<CPU> {
 
     /// Returns `true` if the page is part of an allocated block and has a ref
     /// count of 1, `false` otherwise.
-    pub fn is_allocated_exclusive(&self, mut pfn: PageFrame) -> bool {
+    pub fn is_allocated_exclusive(&self, pfn: PageFrame) -> bool {
+        self.ref_count(pfn) == Some(1)
+    }
+
+    /// Returns the ref count of the allocated block containing the page, or
+    /// `None` if the page isn't allocated.
+    pub fn ref_count(&self, mut pfn: PageFrame) -> Option<u32> {
         let inner = self.inner.lock_save_irq();
 
         loop {
             match inner.get_frame(pfn).state {
                 FrameState::AllocatedTail(TailInfo { head }) => pfn = head,
-                FrameState::AllocatedHead(AllocatedInfo { ref_count: 1, .. }) => {
-                    return true;
+                FrameState::AllocatedHead(AllocatedInfo { ref_count, .. }) => {
+                    return Some(ref_count);
                 }
-                _ => return false,
+                _ => return None,
             }
         }
     }
@@ -374,6 +444,12 @@ impl<CPU: CpuOps> FrameAllocator<CPU> {
         self.inner.lock_save_irq().free_pages
     }
 
+    /// Returns a snapshot of the per-order free block counts and allocation
+    /// failures.
+    pub fn stats(&self) -> BuddyStats {
+        self.inner.lock_save_irq().stats.clone()
+    }
+
     /// Initializes the frame allocator. This is the main bootstrap function.
     /// Use the entire span of all memory regions as the memory pool. This
     /// function takes ownership of `smalloc` since the buddy allocator will
@@ -475,6 +551,7 @@ impl<CPU: CpuOps> FrameAllocator<CPU> {
             frame_list: frame_list.clone(),
             free_pages: 0,
             free_lists: core::array::from_fn(|_| LinkedList::new(FrameAdapter::new())),
+            stats: BuddyStats::default(),
         };
 
         for res_region in smalloc.res.iter() {
@@ -624,6 +701,11 @@ pub mod tests {
                     "Mismatch in free list count for order {}",
                     order
                 );
+                assert_eq!(
+                    self.allocator.stats().free_blocks[order],
+                    count,
+                    "Mismatch in free block stats for order {order}"
+                );
             }
         }
 
@@ -778,6 +860,40 @@ pub mod tests {
         ));
     }
 
+    #[test]
+    fn buddy_stats() {
+        let fixture = TestFixture::new(&[(0, (1 << (MAX_ORDER + PAGE_SHIFT)) * 2)], &[]);
+
+        let stats = fixture.allocator.stats();
+        assert_eq!(stats.free_blocks[MAX_ORDER], 1);
+        assert_eq!(stats.free_pages_at_or_above(0), fixture.free_pages());
+
+        // Splitting the max-order block leaves one free buddy at each lower
+        // order.
+        let page = fixture.allocator.alloc_frames(0).unwrap();
+
+        let stats = fixture.allocator.stats();
+        assert_eq!(stats.free_blocks[..MAX_ORDER], [1; MAX_ORDER]);
+        assert_eq!(stats.free_blocks[MAX_ORDER], 0);
+        assert_eq!(stats.free_pages_at_or_above(0), fixture.free_pages());
+        assert_eq!(
+            stats.free_pages_at_or_above(MAX_ORDER - 1),
+            1 << (MAX_ORDER - 1)
+        );
+
+        // No max-order block is available, so the allocation fails and is
+        // recorded.
+        assert!(fixture.allocator.alloc_frames(MAX_ORDER as _).is_err());
+        assert_eq!(fixture.allocator.stats().failed_allocs[MAX_ORDER], 1);
+
+        // Freeing the page merges everything back together.
+        drop(page);
+
+        let stats = fixture.allocator.stats();
+        assert_eq!(stats.free_blocks[MAX_ORDER], 1);
+        assert_eq!(stats.free_blocks[..MAX_ORDER], [0; MAX_ORDER]);
+    }
+
     /// Tests a simple allocation and deallocation cycle.
     #[test]
     fn simple_alloc_and_free() {
@@ -860,6 +976,42 @@ pub mod tests {
         }
     }
 
+    /// Tests that a split block can be freed page-by-page and coalesces back
+    /// into the free lists.
+    #[test]
+    fn split_block_frees_individually() {
+        let fixture = TestFixture::new(&[(0, (1 << (MAX_ORDER + PAGE_SHIFT)) * 2)], &[]);
+        let initial_free = fixture.free_pages();
+        let order = 9; // 2MiB
+
+        let block = fixture.allocator.alloc_frames(order).unwrap();
+        let head = block.region.start_address().to_pfn();
+        let mut pages: Vec<_> = block.split().collect();
+
+        assert_eq!(pages.len(), 1 << order);
+        assert_eq!(fixture.free_pages(), initial_free - (1 << order));
+
+        for pg in pages.iter() {
+            match fixture.frame_state(pg.region.start_address().to_pfn()) {
+                FrameState::AllocatedHead(info) => {
+                    assert_eq!(info.order, 0);
+                    assert_eq!(info.ref_count, 1);
+                }
+                _ => panic!("Split page has incorrect state"),
+            }
+        }
+
+        // Free a page from the middle of the block.
+        pages.remove(10);
+        assert_eq!(fixture.free_pages(), initial_free - (1 << order) + 1);
+        assert!(!fixture.allocator.is_allocated(head.add_pages(10)));
+        assert!(fixture.allocator.is_allocated(head));
+
+        drop(pages);
+
+        assert_eq!(fixture.free_pages(), initial_free);
+    }
+
     /// Tests that freeing a tail page correctly frees the entire block.
     #[test]
     fn free_tail_page() {
@@ -1092,6 +1244,9 @@ pub mod tests {
 
         let pfn = alloc1.region().start_address().to_pfn();
 
+        assert_eq!(fixture.allocator.ref_count(pfn.add_pages(1)), Some(3));
+        assert!(!fixture.allocator.is_allocated_exclusive(pfn));
+
         // First free should just decrement the count
         drop(alloc1);
 
@@ -1112,9 +1267,12 @@ pub mod tests {
             panic!("Page state changed unexpectedly");
         }
 
+        assert!(fixture.allocator.is_allocated_exclusive(pfn));
+
         // Third free should actually release the memory
         drop(alloc3);
         assert_eq!(fixture.free_pages(), initial_free);
+        assert_eq!(fixture.allocator.ref_count(pfn), None);
         assert!(matches!(fixture.frame_state(pfn), FrameState::Free { .. }));
     }
 }
@@ -1,10 +1,12 @@
 //! A slab memory allocator.
 use super::{
     SLAB_FRAME_ALLOC_ORDER, SLAB_MAX_OBJ_SHIFT, alloc_order,
+    named::{MAX_NAMED_CACHES, NamedCache},
     slab::{Slab, SlabState},
 };
 use crate::{
     CpuOps,
+    error::{KernelError, Result},
     memory::{
         address::{AddressTranslator, VA},
         allocators::{
@@ -14,7 +16,7 @@ use crate::{
     },
     sync::spinlock::SpinLockIrq,
 };
-use core::marker::PhantomData;
+use core::{alloc::Layout, marker::PhantomData};
 use intrusive_collections::{LinkedList, UnsafeRef};
 
 const MAX_FREE_SLABS: usize = 32;
@@ -60,7 +62,9 @@ pub struct SlabManager<CPU: CpuOps, A: PageAllocGetter<CPU>, T: AddressTranslato
     pub(super) free: LinkedList<FrameAdapter>,
     pub(super) partial: LinkedList<FrameAdapter>,
     pub(super) free_list_sz: usize,
-    obj_shift: usize,
+    /// The total number of slabs owned by this manager, including full ones.
+    slab_count: usize,
+    obj_size: usize,
     frame_list: FrameList,
     phantom1: PhantomData<A>,
     phantom2: PhantomData<CPU>,
@@ -68,12 +72,13 @@ pub struct SlabManager<CPU: CpuOps, A: PageAllocGetter<CPU>, T: AddressTranslato
 }
 
 impl<CPU: CpuOps, A: PageAllocGetter<CPU>, T: AddressTranslator<()>> SlabManager<CPU, A, T> {
-    fn new(obj_shift: usize, frame_list: FrameList) -> Self {
+    pub(super) fn new(obj_size: usize, frame_list: FrameList) -> Self {
         Self {
             free: LinkedList::new(FrameAdapter::new()),
             partial: LinkedList::new(FrameAdapter::new()),
             free_list_sz: 0,
-            obj_shift,
+            slab_count: 0,
+            obj_size,
             frame_list,
             phantom1: PhantomData,
             phantom2: PhantomData,
@@ -150,12 +155,14 @@ impl<CPU: CpuOps, A: PageAllocGetter<CPU>, T: AddressTranslator<()>> SlabManager
             .alloc_frames(SLAB_FRAME_ALLOC_ORDER as _)
             .expect("OOM - cannot allocate physical frame");
 
-        let mut slab = Slab::new::<T, CPU>(&new_alloc, self.obj_shift);
+        let mut slab = Slab::with_obj_size::<T, CPU>(&new_alloc, self.obj_size);
 
         let obj = slab.alloc_object().expect("Slab should be empty");
         let state = slab.state();
         let frame = new_alloc.into_slab(slab);
 
+        self.slab_count += 1;
+
         // We now have ownership of the frame.
         if state == SlabState::Partial {
             self.partial
@@ -167,6 +174,11 @@ impl<CPU: CpuOps, A: PageAllocGetter<CPU>, T: AddressTranslator<()>> SlabManager
         obj
     }
 
+    /// Returns the number of slabs currently owned by this manager.
+    pub fn slab_count(&self) -> usize {
+        self.slab_count
+    }
+
     /// Free the given allocation.
     pub fn free(&mut self, ptr: *mut u8) {
         // Find the frame.
@@ -184,16 +196,16 @@ impl<CPU: CpuOps, A: PageAllocGetter<CPU>, T: AddressTranslator<()>> SlabManager
                 frame: *mut Frame,
                 ptr: *mut u8,
                 frame_list: &FrameList,
-                obj_shift: usize,
+                obj_size: usize,
             ) -> (*mut Frame, SlabState) {
                 let state = unsafe { &mut (*frame).state };
                 match state {
                     FrameState::AllocatedTail(tail_info) => {
                         let head_frame = frame_list.get_frame(tail_info.head);
-                        do_free_obj(head_frame, ptr, frame_list, obj_shift)
+                        do_free_obj(head_frame, ptr, frame_list, obj_size)
                     }
                     FrameState::Slab(slab) => {
-                        if slab.obj_shift() != obj_shift {
+                        if slab.obj_size() != obj_size {
                             panic!("Slab allocator: Layout mismatch on free");
                         }
                         slab.put_object(ptr);
@@ -204,7 +216,7 @@ impl<CPU: CpuOps, A: PageAllocGetter<CPU>, T: AddressTranslator<()>> SlabManager
                 }
             }
 
-            do_free_obj(frame, ptr, &self.frame_list, self.obj_shift)
+            do_free_obj(frame, ptr, &self.frame_list, self.obj_size)
         };
 
         // SAFETY: As above
@@ -226,6 +238,7 @@ impl<CPU: CpuOps, A: PageAllocGetter<CPU>, T: AddressTranslator<()>> SlabManager
                     }
 
                     self.free_list_sz -= num_freed;
+                    self.slab_count -= num_freed;
                 }
 
                 if is_linked {
@@ -257,6 +270,11 @@ impl<CPU: CpuOps, A: PageAllocGetter<CPU>, T: AddressTranslator<()>> SlabManager
 pub struct SlabAllocator<CPU: CpuOps, A: PageAllocGetter<CPU>, T: AddressTranslator<()>> {
     pub(super) managers:
         [SpinLockIrq<SlabManager<CPU, A, T>, CPU>; SLAB_MAX_OBJ_SHIFT as usize + 1],
+    named: [Option<NamedCache<CPU, A, T>>; MAX_NAMED_CACHES],
+    /// The first named cache for each allocation order, chained through
+    /// [`NamedCache::next_same_order`].
+    named_by_order: [Option<usize>; SLAB_MAX_OBJ_SHIFT as usize + 1],
+    frame_list: FrameList,
 }
 
 unsafe impl<CPU: CpuOps, A: PageAllocGetter<CPU>, T: AddressTranslator<()>> Send
@@ -273,15 +291,94 @@ impl<CPU: CpuOps, A: PageAllocGetter<CPU>, T: AddressTranslator<()>> SlabAllocat
     pub fn new(frame_list: FrameList) -> Self {
         Self {
             managers: core::array::from_fn(|n| {
-                SpinLockIrq::new(SlabManager::new(n, frame_list.clone()))
+                SpinLockIrq::new(SlabManager::new(1 << n, frame_list.clone()))
             }),
+            named: core::array::from_fn(|_| None),
+            named_by_order: [None; _],
+            frame_list,
+        }
+    }
+
+    /// Creates a named cache for objects of `layout`.
+    ///
+    /// Named caches must be created before the allocator is published, since
+    /// objects allocated from the generic size classes can't be freed into a
+    /// named cache. If a cache for `layout` already exists, it is shared.
+    pub fn create_named_cache(&mut self, name: &'static str, layout: Layout) -> Result<()> {
+        if self.named_cache_for(layout).is_some() {
+            return Ok(());
         }
+
+        let idx = self
+            .named
+            .iter()
+            .position(Option::is_none)
+            .ok_or(KernelError::NoMemory)?;
+
+        let mut cache = NamedCache::new(name, layout, self.frame_list.clone())?;
+
+        // Any layout small enough for a named cache has an allocation order.
+        let order = alloc_order(layout).ok_or(KernelError::InvalidValue)?;
+
+        cache.next_same_order = self.named_by_order[order].replace(idx);
+        self.named[idx] = Some(cache);
+
+        Ok(())
+    }
+
+    /// Returns the index of the named cache for objects of exactly `layout`,
+    /// if one exists.
+    pub fn named_cache_index(&self, layout: Layout) -> Option<usize> {
+        let mut idx = self.named_by_order[alloc_order(layout)?];
+
+        while let Some(i) = idx {
+            let cache = self.named_cache(i);
+
+            if cache.layout() == layout {
+                return Some(i);
+            }
+
+            idx = cache.next_same_order;
+        }
+
+        None
+    }
+
+    /// Returns the named cache at `idx`, as returned by
+    /// [`Self::named_cache_index`].
+    pub fn named_cache(&self, idx: usize) -> &NamedCache<CPU, A, T> {
+        self.named[idx].as_ref().expect("Invalid named cache index")
+    }
+
+    /// Returns the named cache for objects of exactly `layout`, if one exists.
+    pub fn named_cache_for(&self, layout: Layout) -> Option<&NamedCache<CPU, A, T>> {
+        self.named_cache_index(layout)
+            .map(|idx| self.named_cache(idx))
+    }
+
+    /// Returns an iterator over all named caches.
+    pub fn named_caches(&self) -> impl Iterator<Item = &NamedCache<CPU, A, T>> {
+        // Caches are created in slot order.
+        self.named.iter().map_while(Option::as_ref)
+    }
+
+    /// Returns the number of pages backing slabs, across all size classes and
+    /// named caches. This includes cached, empty slabs.
+    pub fn slab_pages(&self) -> usize {
+        let slabs = self
+            .managers
+            .iter()
+            .map(|manager| manager.lock_save_irq().slab_count())
+            .chain(self.named_caches().map(NamedCache::slab_count))
+            .sum::<usize>();
+
+        slabs << SLAB_FRAME_ALLOC_ORDER
     }
 
     /// Returns a reference to the slab manager responsible for the given layout, if one exists.
     pub fn allocator_for_layout(
         &self,
-        layout: core::alloc::Layout,
+        layout: Layout,
     ) -> Option<&SpinLockIrq<SlabManager<CPU, A, T>, CPU>> {
         Some(&self.managers[alloc_order(layout)?])
     }
@@ -290,6 +387,7 @@ impl<CPU: CpuOps, A: PageAllocGetter<CPU>, T: AddressTranslator<()>> SlabAllocat
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::memory::allocators::slab::cache::{PTRS_PER_NAMED_CACHE, PtrCache};
     use crate::{
         memory::{
             address::{IdentityTranslator, PA},
@@ -481,6 +579,130 @@ mod tests {
         assert_eq!(alloc.free.iter().count(), 17);
     }
 
+    #[test]
+    fn named_cache_packs_objects() {
+        let mut allocator = create_allocator_fixture();
+
+        // 24-byte objects would occupy 32-byte slots in the generic caches.
+        let layout = Layout::from_size_align(24, 8).unwrap();
+        allocator.create_named_cache("test_obj", layout).unwrap();
+
+        let cache = allocator.named_cache_for(layout).unwrap();
+        assert_eq!(cache.name(), "test_obj");
+
+        let objs_per_slab = SLAB_SIZE_BYTES / 24;
+        let ptrs: Vec<_> = (0..objs_per_slab).map(|_| cache.alloc()).collect();
+
+        assert_eq!(cache.active_objs(), objs_per_slab);
+        assert_eq!(cache.slab_count(), 1);
+        assert_eq!(allocator.slab_pages(), 1 << SLAB_FRAME_ALLOC_ORDER);
+
+        // All objects should have been packed into a single slab.
+        for (i, ptr) in ptrs.iter().enumerate() {
+            assert_eq!(*ptr as usize, ptrs[0] as usize + i * 24);
+            assert_eq!(*ptr as usize % 8, 0);
+        }
+
+        for ptr in ptrs {
+            cache.free(ptr);
+        }
+
+        assert_eq!(cache.active_objs(), 0);
+
+        // The empty slab is kept cached.
+        assert_eq!(cache.slab_count(), 1);
+    }
+
+    #[test]
+    fn named_cache_creation() {
+        let mut allocator = create_allocator_fixture();
+        let layout = Layout::from_size_align(600, 8).unwrap();
+
+        assert!(allocator.named_cache_for(layout).is_none());
+
+        // Caches for the same layout are shared.
+        allocator.create_named_cache("first", layout).unwrap();
+        allocator.create_named_cache("second", layout).unwrap();
+        assert_eq!(allocator.named_caches().count(), 1);
+        assert_eq!(allocator.named_cache_for(layout).unwrap().name(), "first");
+
+        // Objects too large for a slab are rejected.
+        let huge = Layout::from_size_align(SLAB_SIZE_BYTES, 8).unwrap();
+        assert!(allocator.create_named_cache("huge", huge).is_err());
+
+        // Only a fixed number of caches may be created.
+        for i in 1..MAX_NAMED_CACHES {
+            let layout = Layout::from_size_align(600 + i * 8, 8).unwrap();
+            allocator.create_named_cache("obj", layout).unwrap();
+        }
+
+        let layout = Layout::from_size_align(8, 8).unwrap();
+        assert!(matches!(
+            allocator.create_named_cache("full", layout),
+            Err(KernelError::NoMemory)
+        ));
+    }
+
+    #[test]
+    fn named_cache_index_same_order() {
+        let mut allocator = create_allocator_fixture();
+
+        // All of these share the 1024-byte allocation order.
+        let layouts = [
+            Layout::from_size_align(600, 8).unwrap(),
+            Layout::from_size_align(608, 8).unwrap(),
+            Layout::from_size_align(600, 16).unwrap(),
+        ];
+
+        for layout in layouts {
+            allocator.create_named_cache("obj", layout).unwrap();
+        }
+
+        for (i, layout) in layouts.into_iter().enumerate() {
+            assert_eq!(allocator.named_cache_index(layout), Some(i));
+            assert_eq!(allocator.named_cache(i).layout(), layout);
+        }
+
+        assert!(
+            allocator
+                .named_cache_index(Layout::from_size_align(616, 8).unwrap())
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn named_cache_magazine() {
+        let mut allocator = create_allocator_fixture();
+        let layout = Layout::from_size_align(24, 8).unwrap();
+        allocator.create_named_cache("test_obj", layout).unwrap();
+
+        let cache = allocator.named_cache_for(layout).unwrap();
+        let mut line = PtrCache::new();
+
+        // A miss takes one object and fills the magazine with the rest.
+        let first = cache.alloc_cached(&mut line);
+        assert!(line.is_full());
+        assert_eq!(cache.active_objs(), 1);
+
+        // Hits are served from the magazine.
+        let ptrs: Vec<_> = (0..PTRS_PER_NAMED_CACHE)
+            .map(|_| cache.alloc_cached(&mut line))
+            .collect();
+        assert!(line.is_empty());
+        assert_eq!(cache.active_objs(), PTRS_PER_NAMED_CACHE + 1);
+
+        cache.free_cached(&mut line, first);
+        for ptr in ptrs {
+            cache.free_cached(&mut line, ptr);
+        }
+
+        assert_eq!(cache.active_objs(), 0);
+
+        // Overflowing the magazine spilled half of it back to the slab.
+        assert!(!line.is_full());
+        assert!(!line.is_empty());
+    }
+
     #[test]
     #[should_panic(expected = "Layout mismatch")]
     fn layout_mismatch_panic() {
@@ -503,7 +725,7 @@ mod tests {
         let ptr = alloc_alloc.alloc();
         // This should panic because the slab metadata inside the page
         // says "Size 64", but we are calling free on the "Size 32" inner allocator.
-        // The code has a check: `if slab.obj_shift() != obj_shift { panic! }`
+        // The code has a check: `if slab.obj_size() != obj_size { panic! }`
         alloc_free.free(ptr);
     }
 }
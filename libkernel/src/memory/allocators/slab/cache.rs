@@ -3,6 +3,7 @@
 use super::{
     alloc_order,
     allocator::{SlabAllocator, SlabManager},
+    named::MAX_NAMED_CACHES,
 };
 use crate::{
     CpuOps,
@@ -18,23 +19,27 @@ use core::ptr;
 
 const PTRS_PER_SZ_CLASS: usize = 32;
 const NUM_PTR_CACHES: usize = SLAB_MAX_OBJ_SHIFT as usize + 1;
+/// Named caches get smaller magazines, so that they all fit in the page
+/// alongside the size classes.
+pub(super) const PTRS_PER_NAMED_CACHE: usize = 8;
 
 // Ensure that our cache fits in a single page.
 const _: () = assert!(core::mem::size_of::<SlabCache>() <= PAGE_SIZE);
 
-/// A fixed-size cache of recently freed pointers for a single size class.
+/// A fixed-size cache of recently freed pointers for a single size class or
+/// named cache.
 #[repr(C)]
-pub struct PtrCache {
+pub struct PtrCache<const N: usize = PTRS_PER_SZ_CLASS> {
     next_free: usize,
-    ptrs: [*mut u8; PTRS_PER_SZ_CLASS],
+    ptrs: [*mut u8; N],
 }
 
-impl PtrCache {
+impl<const N: usize> PtrCache<N> {
     /// Creates an empty pointer cache.
     pub fn new() -> Self {
         Self {
             next_free: 0,
-            ptrs: [ptr::null_mut(); PTRS_PER_SZ_CLASS],
+            ptrs: [ptr::null_mut(); N],
         }
     }
 
@@ -45,7 +50,7 @@ impl PtrCache {
 
     /// Returns `true` if the cache has no remaining capacity.
     pub fn is_full(&self) -> bool {
-        self.next_free == PTRS_PER_SZ_CLASS
+        self.next_free == N
     }
 
     /// Caches as many allocations from the slab as possible.
@@ -100,7 +105,7 @@ impl PtrCache {
     }
 }
 
-impl Default for PtrCache {
+impl<const N: usize> Default for PtrCache<N> {
     fn default() -> Self {
         Self::new()
     }
@@ -114,6 +119,7 @@ impl Default for PtrCache {
 #[repr(C)]
 pub struct SlabCache {
     caches: [PtrCache; NUM_PTR_CACHES],
+    named: [PtrCache<PTRS_PER_NAMED_CACHE>; MAX_NAMED_CACHES],
 }
 
 impl SlabCache {
@@ -135,6 +141,15 @@ impl SlabCache {
             elem.write(PtrCache::new());
         }
 
+        let named = unsafe {
+            &mut *(&raw mut (*ptr).named
+                as *mut [MaybeUninit<PtrCache<PTRS_PER_NAMED_CACHE>>; MAX_NAMED_CACHES])
+        };
+
+        for elem in &mut named[..] {
+            elem.write(PtrCache::new());
+        }
+
         // Leak the page so it isn't dropped (freed) at the end of this scope.
         page.leak();
 
@@ -146,6 +161,12 @@ impl SlabCache {
         Some(&mut self.caches[alloc_order(layout)?])
     }
 
+    /// Returns the cache for the named cache at `idx`, as given by
+    /// [`SlabAllocator::named_cache_index`].
+    pub fn named_cache(&mut self, idx: usize) -> &mut PtrCache<PTRS_PER_NAMED_CACHE> {
+        &mut self.named[idx]
+    }
+
     /// Flush all cache lines back into the slab allocator.
     pub fn purge_into<CPU: CpuOps, A: PageAllocGetter<CPU>, T: AddressTranslator<()>>(
         &mut self,
@@ -158,5 +179,13 @@ impl SlabCache {
             }
             line.next_free = 0;
         }
+
+        for (line, named) in self.named.iter_mut().zip(slab_alloc.named_caches()) {
+            let mut slab = named.manager().lock_save_irq();
+            for &ptr in &line.ptrs[..line.next_free] {
+                slab.free(ptr);
+            }
+            line.next_free = 0;
+        }
     }
 }
@@ -109,6 +109,12 @@ where
     unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
         let mut cache = S::get();
 
+        if let Some(idx) = SG::global_slab_alloc().named_cache_index(layout) {
+            return SG::global_slab_alloc()
+                .named_cache(idx)
+                .alloc_cached(cache.named_cache(idx));
+        }
+
         let Some(cache_line) = cache.get_cache(layout) else {
             // Allocation is too big for SLAB. Defer to using the frame
             // allocator directly.
@@ -145,6 +151,13 @@ where
     unsafe fn dealloc(&self, ptr: *mut u8, layout: core::alloc::Layout) {
         let mut cache = S::get();
 
+        if let Some(idx) = SG::global_slab_alloc().named_cache_index(layout) {
+            SG::global_slab_alloc()
+                .named_cache(idx)
+                .free_cached(cache.named_cache(idx), ptr);
+            return;
+        }
+
         let Some(cache_line) = cache.get_cache(layout) else {
             // If the allocation didn't fit in the slab, we must have used the
             // FA directly.
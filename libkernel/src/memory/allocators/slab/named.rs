@@ -0,0 +1,147 @@
+//! Named slab caches for frequently allocated kernel objects.
+//!
+//! The generic size classes round every allocation up to a power of two, so a
+//! 600-byte object occupies a 1024-byte slot. A named cache is dedicated to a
+//! single [`Layout`] and packs its objects back-to-back, wasting at most the
+//! tail of each slab. Allocations whose layout exactly matches a named cache
+//! are routed to it by the kernel heap, through a per-CPU [`PtrCache`] just
+//! like the size classes.
+
+use super::{
+    SLAB_MAX_OBJ_SHIFT,
+    allocator::SlabManager,
+    cache::{PTRS_PER_NAMED_CACHE, PtrCache},
+};
+use crate::{
+    CpuOps,
+    error::{KernelError, Result},
+    memory::{
+        address::AddressTranslator,
+        allocators::{frame::FrameList, phys::PageAllocGetter},
+    },
+    sync::spinlock::SpinLockIrq,
+};
+use core::{
+    alloc::Layout,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// The maximum number of named caches that may be created.
+pub const MAX_NAMED_CACHES: usize = 4;
+
+/// A slab cache dedicated to objects of a single layout.
+pub struct NamedCache<CPU: CpuOps, A: PageAllocGetter<CPU>, T: AddressTranslator<()>> {
+    name: &'static str,
+    layout: Layout,
+    manager: SpinLockIrq<SlabManager<CPU, A, T>, CPU>,
+    active_objs: AtomicUsize,
+    /// The index of the next named cache with the same allocation order.
+    pub(super) next_same_order: Option<usize>,
+}
+
+unsafe impl<CPU: CpuOps, A: PageAllocGetter<CPU>, T: AddressTranslator<()>> Send
+    for NamedCache<CPU, A, T>
+{
+}
+unsafe impl<CPU: CpuOps, A: PageAllocGetter<CPU>, T: AddressTranslator<()>> Sync
+    for NamedCache<CPU, A, T>
+{
+}
+
+impl<CPU: CpuOps, A: PageAllocGetter<CPU>, T: AddressTranslator<()>> NamedCache<CPU, A, T> {
+    pub(super) fn new(name: &'static str, layout: Layout, frame_list: FrameList) -> Result<Self> {
+        let obj_size = Self::obj_size_for(layout).ok_or(KernelError::InvalidValue)?;
+
+        Ok(Self {
+            name,
+            layout,
+            manager: SpinLockIrq::new(SlabManager::new(obj_size, frame_list)),
+            active_objs: AtomicUsize::new(0),
+            next_same_order: None,
+        })
+    }
+
+    /// Returns the slot size for objects of `layout`, or `None` if the layout
+    /// is too large to be slab allocated.
+    fn obj_size_for(layout: Layout) -> Option<usize> {
+        // Slots must be at least large enough to hold the free-list index.
+        let obj_size = layout
+            .pad_to_align()
+            .size()
+            .max(2)
+            .next_multiple_of(layout.align());
+
+        (obj_size <= 1 << SLAB_MAX_OBJ_SHIFT).then_some(obj_size)
+    }
+
+    /// The name of this cache.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// The layout of objects served by this cache.
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// The number of objects currently allocated from this cache.
+    pub fn active_objs(&self) -> usize {
+        self.active_objs.load(Ordering::Relaxed)
+    }
+
+    pub(super) fn manager(&self) -> &SpinLockIrq<SlabManager<CPU, A, T>, CPU> {
+        &self.manager
+    }
+
+    /// The number of slabs currently backing this cache.
+    pub fn slab_count(&self) -> usize {
+        self.manager.lock_save_irq().slab_count()
+    }
+
+    /// Allocate an object from this cache.
+    pub fn alloc(&self) -> *mut u8 {
+        let ptr = self.manager.lock_save_irq().alloc();
+
+        self.active_objs.fetch_add(1, Ordering::Relaxed);
+
+        ptr
+    }
+
+    /// Return an object, previously allocated with [`Self::alloc`], to this
+    /// cache.
+    pub fn free(&self, ptr: *mut u8) {
+        self.manager.lock_save_irq().free(ptr);
+
+        self.active_objs.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Allocate an object, using `line` (this CPU's cache for this named
+    /// cache) to avoid taking the slab lock where possible.
+    pub fn alloc_cached(&self, line: &mut PtrCache<PTRS_PER_NAMED_CACHE>) -> *mut u8 {
+        let ptr = line.alloc().unwrap_or_else(|| {
+            let mut slab = self.manager.lock_save_irq();
+            let ptr = slab.alloc();
+
+            line.fill_from(&mut slab);
+
+            ptr
+        });
+
+        self.active_objs.fetch_add(1, Ordering::Relaxed);
+
+        ptr
+    }
+
+    /// Return an object, previously allocated with [`Self::alloc_cached`], to
+    /// `line`, spilling half of it back to the slab if it's full.
+    pub fn free_cached(&self, line: &mut PtrCache<PTRS_PER_NAMED_CACHE>, ptr: *mut u8) {
+        if let Err(ptr) = line.free(ptr) {
+            let mut slab = self.manager.lock_save_irq();
+
+            slab.free(ptr);
+            line.drain_into(&mut slab);
+        }
+
+        self.active_objs.fetch_sub(1, Ordering::Relaxed);
+    }
+}
@@ -10,7 +10,7 @@ use crate::{
 
 #[derive(Debug, Clone)]
 pub struct Slab {
-    obj_shift: usize,
+    obj_size: usize,
     num_free: usize,
     next_free: Option<u16>,
     base: VA,
@@ -27,16 +27,29 @@ impl Slab {
     pub fn new<T: AddressTranslator<()>, CPU: CpuOps>(
         alloc: &PageAllocation<'_, CPU>,
         obj_shift: usize,
+    ) -> Self {
+        // We don't go bigger than 4 pages.
+        assert!(obj_shift <= SLAB_MAX_OBJ_SHIFT as usize);
+
+        Self::with_obj_size::<T, CPU>(alloc, 1 << obj_shift)
+    }
+
+    /// Create a slab of objects of `obj_size` bytes, which need not be a power
+    /// of two. Objects are laid out back-to-back from the (page-aligned) start
+    /// of the slab, so `obj_size` must be a multiple of the object alignment.
+    pub fn with_obj_size<T: AddressTranslator<()>, CPU: CpuOps>(
+        alloc: &PageAllocation<'_, CPU>,
+        obj_size: usize,
     ) -> Self {
         assert_eq!(alloc.region().size(), SLAB_SIZE_BYTES);
 
         // We need *at least* a u16 for free list tracking.
-        assert!(obj_shift >= 1);
+        assert!(obj_size >= 2);
 
-        // We don't go bigger than 4 pages.
-        assert!(obj_shift <= SLAB_MAX_OBJ_SHIFT as usize);
+        // Every slab must hold at least two objects.
+        assert!(obj_size <= 1 << SLAB_MAX_OBJ_SHIFT);
 
-        let num_objs = SLAB_SIZE_BYTES >> obj_shift;
+        let num_objs = SLAB_SIZE_BYTES / obj_size;
 
         // Write free list at object slots.
         let va = alloc.region().start_address().to_va::<T>();
@@ -45,18 +58,17 @@ impl Slab {
 
         for i in 0..num_objs {
             unsafe {
-                base.byte_add(i * (1 << obj_shift))
-                    .write(if i == num_objs - 1 {
-                        // Sential value for no next list.
-                        u16::MAX
-                    } else {
-                        (i + 1) as u16
-                    });
+                base.byte_add(i * obj_size).write(if i == num_objs - 1 {
+                    // Sential value for no next list.
+                    u16::MAX
+                } else {
+                    (i + 1) as u16
+                });
             }
         }
 
         Self {
-            obj_shift,
+            obj_size,
             num_free: num_objs,
             next_free: Some(0),
             base: va,
@@ -64,7 +76,7 @@ impl Slab {
     }
 
     fn calc_obj_idx(&mut self, idx: u16) -> VA {
-        self.base.add_bytes(idx as usize * (1 << self.obj_shift))
+        self.base.add_bytes(idx as usize * self.obj_size)
     }
 
     pub fn alloc_object(&mut self) -> Option<*mut u8> {
@@ -92,7 +104,12 @@ impl Slab {
         // Eneusre ptr is within our slab.
         assert!(VirtMemoryRegion::new(self.base, SLAB_SIZE_BYTES).contains_address(va));
 
-        let idx = (va.value() - self.base.value()) >> self.obj_shift;
+        let offset = va.value() - self.base.value();
+
+        // Ensure ptr is the start of an object.
+        assert_eq!(offset % self.obj_size, 0);
+
+        let idx = offset / self.obj_size;
 
         unsafe { ptr.cast::<u16>().write(self.next_free.unwrap_or(u16::MAX)) };
 
@@ -101,7 +118,7 @@ impl Slab {
     }
 
     fn capacity(&self) -> usize {
-        SLAB_SIZE_BYTES >> self.obj_shift
+        SLAB_SIZE_BYTES / self.obj_size
     }
 
     pub fn state(&self) -> SlabState {
@@ -114,8 +131,8 @@ impl Slab {
         }
     }
 
-    pub fn obj_shift(&self) -> usize {
-        self.obj_shift
+    pub fn obj_size(&self) -> usize {
+        self.obj_size
     }
 }
 #[cfg(test)]
@@ -326,6 +343,28 @@ mod tests {
         }
     }
 
+    #[test]
+    fn slab_non_power_of_two_objects() {
+        let fixture = create_slab_fixture().leak_allocator();
+        let alloc = fixture.alloc_frames(SLAB_FRAME_ALLOC_ORDER as _).unwrap();
+
+        // 48 byte objects -> 341 objects, with a 16 byte tail.
+        let mut slab = Slab::with_obj_size::<IdentityTranslator, MockCpuOps>(&alloc, 48);
+
+        let mut ptrs = Vec::new();
+        while let Some(ptr) = slab.alloc_object() {
+            ptrs.push(ptr);
+        }
+
+        assert_eq!(ptrs.len(), SLAB_SIZE_BYTES / 48);
+        assert_eq!(slab.state(), SlabState::Full);
+        assert_eq!(unsafe { ptrs[0].byte_add(48) }, ptrs[1]);
+
+        slab.put_object(ptrs[5]);
+        assert_eq!(slab.next_free, Some(5));
+        assert_eq!(slab.alloc_object().unwrap(), ptrs[5]);
+    }
+
     #[test]
     fn slab_put_pointer_calculation() {
         let fixture = create_slab_fixture().leak_allocator();
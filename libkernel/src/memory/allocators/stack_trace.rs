@@ -0,0 +1,35 @@
+//! Call stack traces recorded by the debugging allocators.
+
+/// The number of return addresses recorded in a [`StackTrace`].
+pub const STACK_TRACE_DEPTH: usize = 8;
+
+/// The innermost return addresses of a call stack, captured at some event of
+/// interest such as an allocation.
+#[derive(Clone, Copy, Debug)]
+pub struct StackTrace {
+    frames: [usize; STACK_TRACE_DEPTH],
+    len: usize,
+}
+
+impl StackTrace {
+    /// A trace with no frames.
+    pub const EMPTY: Self = Self {
+        frames: [0; STACK_TRACE_DEPTH],
+        len: 0,
+    };
+
+    /// Captures a trace using `capture`, which should fill in the frames it's
+    /// given and return how many it wrote.
+    pub fn capture(capture: impl FnOnce(&mut [usize]) -> usize) -> Self {
+        let mut trace = Self::EMPTY;
+
+        trace.len = capture(&mut trace.frames).min(STACK_TRACE_DEPTH);
+
+        trace
+    }
+
+    /// The captured return addresses, innermost first.
+    pub fn frames(&self) -> &[usize] {
+        &self.frames[..self.len]
+    }
+}
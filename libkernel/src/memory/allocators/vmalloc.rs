@@ -0,0 +1,192 @@
+//! Virtual address allocator for the kernel's vmalloc area.
+//!
+//! The vmalloc area is a region of kernel virtual address space into which
+//! physically discontiguous pages are mapped to form a virtually contiguous
+//! allocation. This module only tracks which parts of the area are in use;
+//! mapping the backing pages is left to the caller.
+
+use crate::{
+    error::{KernelError, Result},
+    memory::{PAGE_SIZE, address::VA, region::VirtMemoryRegion},
+};
+use alloc::collections::BTreeMap;
+
+/// The size of the unmapped gap left after each allocation, so that an overrun
+/// faults rather than silently corrupting the next allocation.
+pub const VMALLOC_GUARD_SIZE: usize = PAGE_SIZE;
+
+/// Tracks allocations within the vmalloc area.
+pub struct VmallocArena {
+    area: VirtMemoryRegion,
+    /// Allocated regions keyed by start address. The guard gap is not included
+    /// in the region.
+    allocs: BTreeMap<VA, VirtMemoryRegion>,
+}
+
+impl VmallocArena {
+    /// Creates an arena managing the (page-aligned) `area`.
+    pub const fn new(area: VirtMemoryRegion) -> Self {
+        Self {
+            area,
+            allocs: BTreeMap::new(),
+        }
+    }
+
+    /// Reserves a region of at least `size` bytes, rounded up to a whole
+    /// number of pages. The region is followed by an unallocated guard gap.
+    pub fn alloc(&mut self, size: usize) -> Result<VirtMemoryRegion> {
+        self.alloc_aligned(size, PAGE_SIZE)
+    }
+
+    /// Like [`Self::alloc`], but the region starts at a multiple of `align`,
+    /// which must be a power of two no smaller than a page.
+    pub fn alloc_aligned(&mut self, size: usize, align: usize) -> Result<VirtMemoryRegion> {
+        if size == 0 || !align.is_power_of_two() || align < PAGE_SIZE {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let size = size.next_multiple_of(PAGE_SIZE);
+        let needed = size + VMALLOC_GUARD_SIZE;
+
+        // First fit: walk the gaps between existing allocations.
+        let mut candidate = self.area.start_address().align_up(align);
+
+        for region in self.allocs.values() {
+            if region.start_address().value().saturating_sub(candidate.value()) >= needed {
+                break;
+            }
+
+            candidate = region
+                .end_address()
+                .add_bytes(VMALLOC_GUARD_SIZE)
+                .align_up(align)
+                .max(candidate);
+        }
+
+        if self
+            .area
+            .end_address()
+            .value()
+            .saturating_sub(candidate.value())
+            < needed
+        {
+            return Err(KernelError::NoMemory);
+        }
+
+        let region = VirtMemoryRegion::new(candidate, size);
+
+        self.allocs.insert(candidate, region);
+
+        Ok(region)
+    }
+
+    /// Releases the allocation starting at `va`, returning its region.
+    pub fn free(&mut self, va: VA) -> Result<VirtMemoryRegion> {
+        self.allocs.remove(&va).ok_or(KernelError::InvalidValue)
+    }
+
+    /// Returns the allocation containing `va`, if any.
+    pub fn find(&self, va: VA) -> Option<VirtMemoryRegion> {
+        self.allocs
+            .range(..=va)
+            .next_back()
+            .map(|(_, region)| *region)
+            .filter(|region| region.contains_address(va))
+    }
+
+    /// Returns the number of bytes currently allocated, excluding guard gaps.
+    pub fn allocated_bytes(&self) -> usize {
+        self.allocs.values().map(|region| region.size()).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: usize = 0xffff_a000_0000_0000;
+
+    fn arena(pages: usize) -> VmallocArena {
+        VmallocArena::new(VirtMemoryRegion::new(
+            VA::from_value(BASE),
+            pages * PAGE_SIZE,
+        ))
+    }
+
+    #[test]
+    fn alloc_rounds_and_leaves_guard() {
+        let mut arena = arena(16);
+
+        let a = arena.alloc(100).unwrap();
+        assert_eq!(a.start_address().value(), BASE);
+        assert_eq!(a.size(), PAGE_SIZE);
+
+        let b = arena.alloc(2 * PAGE_SIZE).unwrap();
+        assert_eq!(
+            b.start_address(),
+            a.end_address().add_bytes(VMALLOC_GUARD_SIZE)
+        );
+        assert_eq!(b.size(), 2 * PAGE_SIZE);
+
+        assert_eq!(arena.allocated_bytes(), 3 * PAGE_SIZE);
+        assert!(arena.alloc(0).is_err());
+    }
+
+    #[test]
+    fn free_and_reuse_gap() {
+        let mut arena = arena(16);
+
+        let a = arena.alloc(PAGE_SIZE).unwrap();
+        let b = arena.alloc(PAGE_SIZE).unwrap();
+        let c = arena.alloc(PAGE_SIZE).unwrap();
+
+        assert_eq!(arena.free(b.start_address()).unwrap(), b);
+        assert!(arena.free(b.start_address()).is_err());
+
+        // The freed slot (plus its guard) fits exactly one page again.
+        assert_eq!(arena.alloc(PAGE_SIZE).unwrap(), b);
+
+        // But not two pages; those go after `c`.
+        let d = arena.alloc(2 * PAGE_SIZE).unwrap();
+        assert_eq!(
+            d.start_address(),
+            c.end_address().add_bytes(VMALLOC_GUARD_SIZE)
+        );
+
+        assert_eq!(arena.find(a.start_address().add_bytes(10)), Some(a));
+        assert_eq!(arena.find(a.end_address()), None);
+    }
+
+    #[test]
+    fn alloc_aligned_skips_to_alignment() {
+        let mut arena = arena(64);
+        let align = 8 * PAGE_SIZE;
+
+        let a = arena.alloc(PAGE_SIZE).unwrap();
+        let b = arena.alloc_aligned(2 * PAGE_SIZE, align).unwrap();
+        assert_eq!(b.start_address().value(), BASE + align);
+
+        // The gap left below `b` is still available to unaligned allocations.
+        let c = arena.alloc(PAGE_SIZE).unwrap();
+        assert_eq!(
+            c.start_address(),
+            a.end_address().add_bytes(VMALLOC_GUARD_SIZE)
+        );
+
+        let d = arena.alloc_aligned(PAGE_SIZE, align).unwrap();
+        assert_eq!(d.start_address().value(), BASE + 2 * align);
+
+        assert!(arena.alloc_aligned(PAGE_SIZE, 3 * PAGE_SIZE).is_err());
+        assert!(arena.alloc_aligned(PAGE_SIZE, PAGE_SIZE / 2).is_err());
+    }
+
+    #[test]
+    fn exhaustion() {
+        let mut arena = arena(4);
+
+        // Three pages plus a guard page consume the whole arena.
+        arena.alloc(3 * PAGE_SIZE).unwrap();
+
+        assert!(matches!(arena.alloc(1), Err(KernelError::NoMemory)));
+    }
+}
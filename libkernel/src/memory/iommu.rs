@@ -0,0 +1,229 @@
+//! IOMMU translation domains.
+//!
+//! An IOMMU domain is an I/O virtual address (IOVA) space through which one or
+//! more devices perform DMA. Devices attached to a domain can only reach
+//! physical memory that has been explicitly mapped into it, and only within
+//! the domain's aperture.
+//!
+//! [`IovaDomain`] manages the IOVA space of a domain, allocating addresses for
+//! new mappings and keeping the hardware page tables, abstracted by
+//! [`IoPageTable`], in sync.
+
+use crate::{
+    error::{KernelError, Result},
+    memory::{
+        PAGE_MASK, PAGE_SIZE, address::PA, paging::permissions::PtePermissions,
+        region::PhysMemoryRegion,
+    },
+};
+use alloc::collections::BTreeMap;
+
+/// The hardware page tables backing an IOMMU domain.
+pub trait IoPageTable {
+    /// Maps the page-aligned region `phys` at `iova` with the given
+    /// permissions.
+    fn map(&mut self, iova: usize, phys: PhysMemoryRegion, perms: PtePermissions) -> Result<()>;
+
+    /// Removes the mapping of `size` bytes at `iova` and invalidates any
+    /// cached translations for it.
+    fn unmap(&mut self, iova: usize, size: usize) -> Result<()>;
+}
+
+/// The IOVA space of an IOMMU domain.
+pub struct IovaDomain<P: IoPageTable> {
+    aperture_start: usize,
+    aperture_end: usize,
+    /// Page-aligned physical regions, keyed by the IOVA they're mapped at.
+    mappings: BTreeMap<usize, PhysMemoryRegion>,
+    page_table: P,
+}
+
+impl<P: IoPageTable> IovaDomain<P> {
+    /// Creates a domain whose IOVAs lie within the page-aligned range
+    /// `[aperture_start, aperture_start + aperture_size)`.
+    pub fn new(aperture_start: usize, aperture_size: usize, page_table: P) -> Self {
+        Self {
+            aperture_start,
+            aperture_end: aperture_start + aperture_size,
+            mappings: BTreeMap::new(),
+            page_table,
+        }
+    }
+
+    /// Maps `phys` into the domain, returning the IOVA through which the
+    /// device can access it. The region need not be page-aligned; the
+    /// returned address preserves its offset within the first page.
+    pub fn map(&mut self, phys: PhysMemoryRegion, perms: PtePermissions) -> Result<usize> {
+        if phys.size() == 0 {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let aligned = phys.align_to_page_boundary();
+        let iova = self.find_free(aligned.size())?;
+
+        self.page_table.map(iova, aligned, perms)?;
+        self.mappings.insert(iova, aligned);
+
+        Ok(iova + (phys.start_address().value() & PAGE_MASK))
+    }
+
+    /// Removes the mapping containing the IOVA returned by [`Self::map`],
+    /// returning the page-aligned physical region it covered.
+    pub fn unmap(&mut self, iova: usize) -> Result<PhysMemoryRegion> {
+        let base = iova & !PAGE_MASK;
+        let region = *self.mappings.get(&base).ok_or(KernelError::InvalidValue)?;
+
+        self.page_table.unmap(base, region.size())?;
+        self.mappings.remove(&base);
+
+        Ok(region)
+    }
+
+    /// Translates `iova` to the physical address it's mapped to, if any.
+    pub fn translate(&self, iova: usize) -> Option<PA> {
+        let (base, region) = self.mappings.range(..=iova).next_back()?;
+
+        let offset = iova - base;
+
+        (offset < region.size()).then(|| region.start_address().add_bytes(offset))
+    }
+
+    /// Returns the number of bytes currently mapped into the domain.
+    pub fn mapped_bytes(&self) -> usize {
+        self.mappings.values().map(|region| region.size()).sum()
+    }
+
+    /// Returns the page table backing this domain.
+    pub fn page_table(&self) -> &P {
+        &self.page_table
+    }
+
+    /// Finds the lowest free, page-aligned IOVA range of `size` bytes.
+    fn find_free(&self, size: usize) -> Result<usize> {
+        debug_assert_eq!(size % PAGE_SIZE, 0);
+
+        let mut candidate = self.aperture_start;
+
+        for (iova, region) in &self.mappings {
+            if iova - candidate >= size {
+                break;
+            }
+
+            candidate = iova + region.size();
+        }
+
+        if self.aperture_end.saturating_sub(candidate) < size {
+            return Err(KernelError::NoMemory);
+        }
+
+        Ok(candidate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    #[derive(Default)]
+    struct MockIoPageTable {
+        maps: Vec<(usize, PhysMemoryRegion, PtePermissions)>,
+        unmaps: Vec<(usize, usize)>,
+    }
+
+    impl IoPageTable for MockIoPageTable {
+        fn map(
+            &mut self,
+            iova: usize,
+            phys: PhysMemoryRegion,
+            perms: PtePermissions,
+        ) -> Result<()> {
+            self.maps.push((iova, phys, perms));
+            Ok(())
+        }
+
+        fn unmap(&mut self, iova: usize, size: usize) -> Result<()> {
+            self.unmaps.push((iova, size));
+            Ok(())
+        }
+    }
+
+    const APERTURE: usize = 0x1000_0000;
+
+    fn domain(pages: usize) -> IovaDomain<MockIoPageTable> {
+        IovaDomain::new(APERTURE, pages * PAGE_SIZE, MockIoPageTable::default())
+    }
+
+    fn phys(addr: usize, size: usize) -> PhysMemoryRegion {
+        PhysMemoryRegion::new(PA::from_value(addr), size)
+    }
+
+    #[test]
+    fn map_preserves_offset() {
+        let mut domain = domain(16);
+
+        let iova = domain
+            .map(phys(0x8000_0123, 0x2000), PtePermissions::rw(false))
+            .unwrap();
+
+        assert_eq!(iova, APERTURE + 0x123);
+        assert_eq!(
+            domain.page_table().maps[0],
+            (
+                APERTURE,
+                phys(0x8000_0000, 0x3000),
+                PtePermissions::rw(false)
+            )
+        );
+        assert_eq!(domain.mapped_bytes(), 0x3000);
+
+        assert_eq!(
+            domain.translate(iova + 0x10),
+            Some(PA::from_value(0x8000_0133))
+        );
+        assert_eq!(domain.translate(APERTURE + 0x3000), None);
+    }
+
+    #[test]
+    fn unmap_and_reuse() {
+        let mut domain = domain(16);
+
+        let a = domain
+            .map(phys(0x8000_0000, PAGE_SIZE), PtePermissions::ro(false))
+            .unwrap();
+        let b = domain
+            .map(phys(0x9000_0000, PAGE_SIZE), PtePermissions::ro(false))
+            .unwrap();
+        assert_eq!(b, a + PAGE_SIZE);
+
+        assert_eq!(domain.unmap(a).unwrap(), phys(0x8000_0000, PAGE_SIZE));
+        assert!(domain.unmap(a).is_err());
+        assert_eq!(domain.page_table().unmaps, [(a, PAGE_SIZE)]);
+        assert_eq!(domain.translate(a), None);
+
+        // The freed slot is handed out again.
+        let c = domain
+            .map(phys(0xa000_0000, PAGE_SIZE), PtePermissions::ro(false))
+            .unwrap();
+        assert_eq!(c, a);
+    }
+
+    #[test]
+    fn aperture_is_bounded() {
+        let mut domain = domain(2);
+
+        domain
+            .map(phys(0x8000_0000, PAGE_SIZE), PtePermissions::rw(false))
+            .unwrap();
+
+        assert!(matches!(
+            domain.map(phys(0x9000_0000, 2 * PAGE_SIZE), PtePermissions::rw(false)),
+            Err(KernelError::NoMemory)
+        ));
+        assert!(
+            domain
+                .map(phys(0x9000_0000, 0), PtePermissions::rw(false))
+                .is_err()
+        );
+    }
+}
@@ -160,6 +160,27 @@ impl<T: Copy, S: Storage<Item = T>, C: CpuOps> KBufCore<T, S, C> {
         size
     }
 
+    /// Asynchronously copies up to `buf.len()` items into `buf` without removing
+    /// them, blocking until at least one is available.
+    pub async fn peek_slice(&self, buf: &mut [T]) -> usize {
+        wait_until(
+            self.inner.clone(),
+            |inner| &mut inner.read_waiters,
+            |inner| {
+                let size = inner.buf.peek_slice(buf);
+
+                if size != 0 { Some(size) } else { None }
+            },
+        )
+        .await
+    }
+
+    /// Attempts to copy up to `buf.len()` items without removing them or
+    /// blocking.
+    pub fn try_peek_slice(&self, buf: &mut [T]) -> usize {
+        self.inner.lock_save_irq().buf.peek_slice(buf)
+    }
+
     /// Asynchronously pushes items from `buf`, blocking until space is available.
     pub async fn push_slice(&self, buf: &[T]) -> usize {
         wait_until(
@@ -359,6 +380,26 @@ mod tests {
         assert_eq!(in_buf, out_buf);
     }
 
+    #[tokio::test]
+    async fn peek_leaves_data_in_place() {
+        let kbuf = make_kbuf(16);
+        let mut out_buf = [0; 3];
+
+        assert_eq!(kbuf.try_peek_slice(&mut out_buf), 0);
+
+        kbuf.push_slice(&[1, 2, 3]).await;
+
+        assert_eq!(kbuf.peek_slice(&mut out_buf).await, 3);
+        assert_eq!(out_buf, [1, 2, 3]);
+        assert_eq!(kbuf.try_peek_slice(&mut out_buf[..2]), 2);
+
+        // The peeked data is still there to be read.
+        out_buf = [0; 3];
+        assert_eq!(kbuf.pop_slice(&mut out_buf).await, 3);
+        assert_eq!(out_buf, [1, 2, 3]);
+        assert_eq!(kbuf.try_peek_slice(&mut out_buf), 0);
+    }
+
     #[tokio::test]
     async fn read_blocks_when_empty() {
         let kbuf = make_kbuf(16);
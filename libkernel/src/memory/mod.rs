@@ -13,6 +13,8 @@ pub mod address;
 pub mod allocators;
 #[cfg(feature = "alloc")]
 pub mod claimed_page;
+#[cfg(feature = "paging")]
+pub mod iommu;
 #[cfg(feature = "kbuf")]
 pub mod kbuf;
 pub mod page;
@@ -28,3 +30,8 @@ pub const PAGE_SIZE: usize = 4096;
 pub const PAGE_SHIFT: usize = PAGE_SIZE.trailing_zeros() as usize;
 /// Bitmask for extracting the within-page offset from an address.
 pub const PAGE_MASK: usize = PAGE_SIZE - 1;
+/// The order (log2 of the number of base pages) of a huge page.
+pub const HUGE_PAGE_ORDER: usize = 9;
+/// The size of a huge page in bytes (2 MiB), the span of a single last-level
+/// block mapping.
+pub const HUGE_PAGE_SIZE: usize = PAGE_SIZE << HUGE_PAGE_ORDER;
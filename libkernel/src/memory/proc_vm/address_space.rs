@@ -4,7 +4,7 @@ use alloc::vec::Vec;
 
 use crate::{
     CpuOps,
-    error::Result,
+    error::{KernelError, Result},
     memory::{
         address::VA,
         page::PageFrame,
@@ -84,6 +84,44 @@ pub trait UserAddressSpace: Send + Sync {
     /// intermediate page tables cannot be allocated.
     fn map_page(&mut self, page: PageFrame, va: VA, perms: PtePermissions) -> Result<()>;
 
+    /// Returns `true` if a huge page could be mapped at the huge-page aligned
+    /// address `va` without disturbing any existing mappings.
+    ///
+    /// The default implementation returns `false`, for architectures which do
+    /// not support huge pages.
+    fn can_map_huge_page(&self, _va: VA) -> bool {
+        false
+    }
+
+    /// Maps a naturally aligned, physically contiguous huge page (see
+    /// [`HUGE_PAGE_SIZE`](crate::memory::HUGE_PAGE_SIZE)) at `va` using a
+    /// single block mapping.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any part of the range is already mapped. The
+    /// default implementation returns `KernelError::NotSupported`; callers
+    /// are expected to fall back to base pages in that case.
+    fn map_huge_page(
+        &mut self,
+        _block: PhysMemoryRegion,
+        _va: VA,
+        _perms: PtePermissions,
+    ) -> Result<()> {
+        Err(KernelError::NotSupported)
+    }
+
+    /// Splits any huge page mappings that intersect `va_range` into
+    /// equivalent base-page mappings.
+    ///
+    /// This must be called before any operation that only affects part of a
+    /// huge page, such as a partial `munmap` or `mprotect`. Once split, each
+    /// base page can be unmapped and freed individually. The default
+    /// implementation is a no-op.
+    fn split_huge_pages(&mut self, _va_range: VirtMemoryRegion) -> Result<()> {
+        Ok(())
+    }
+
     /// Unmaps a single virtual page, returning the physical page it was mapped
     /// to.
     ///
@@ -113,6 +151,11 @@ pub trait UserAddressSpace: Send + Sync {
     /// walks the page tables for the given `va_range` and updates the
     /// permissions of each PTE to match `perms`.
     ///
+    /// If `perms` is writable, pages that weren't previously writable must be
+    /// mapped as CoW rather than writable, since they may be shared (e.g. with
+    /// a forked process, or the zero page). A subsequent write fault will then
+    /// either take ownership of the page or copy it.
+    ///
     /// The implementation must ensure that the TLB is invalidated for the
     /// entire range.
     fn protect_range(&mut self, va_range: VirtMemoryRegion, perms: PtePermissions) -> Result<()>;
@@ -194,6 +237,10 @@ pub trait KernAddressSpace: Send {
         virt_range: VirtMemoryRegion,
         perms: PtePermissions,
     ) -> Result<()>;
+
+    /// Unmaps all pages within the given region, returning the page frames
+    /// that were mapped. The caller is responsible for freeing them.
+    fn unmap_range(&mut self, virt_range: VirtMemoryRegion) -> Result<Vec<PageFrame>>;
 }
 
 /// The types and functions required for the virtual memory subsystem.
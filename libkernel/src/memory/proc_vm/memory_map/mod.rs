@@ -2,23 +2,31 @@
 
 use super::{
     address_space::UserAddressSpace,
-    vmarea::{VMAPermissions, VMArea, VMAreaKind},
+    overcommit::VM_COMMIT,
+    vmarea::{VMAFlags, VMAPermissions, VMArea, VMAreaKind},
 };
 use crate::{
     error::{KernelError, Result},
     memory::{
-        PAGE_MASK, PAGE_SIZE, address::VA, page::PageFrame, paging::permissions::PtePermissions,
-        region::VirtMemoryRegion,
+        HUGE_PAGE_SIZE, PAGE_MASK, PAGE_SIZE, address::VA, page::PageFrame,
+        paging::permissions::PtePermissions, region::VirtMemoryRegion,
     },
 };
 use alloc::{collections::BTreeMap, string::String, vec::Vec};
 
 const MMAP_BASE: usize = 0x4000_0000_0000;
 
+/// The size of the gap kept free below a [`VMAFlags::GROWSDOWN`] VMA. Faults
+/// within this gap grow the stack, whereas the kernel won't place other
+/// mappings inside of it.
+pub const STACK_GUARD_GAP: usize = 256 * PAGE_SIZE;
+
 /// Manages mappings in a process's address space.
 pub struct MemoryMap<AS: UserAddressSpace> {
     pub(super) vmas: BTreeMap<VA, VMArea>,
     address_space: AS,
+    /// The number of pages charged to [`VM_COMMIT`] on behalf of this map.
+    charged: usize,
 }
 
 /// Specifies how the kernel should choose the virtual address for a mapping.
@@ -43,6 +51,7 @@ impl<AS: UserAddressSpace> MemoryMap<AS> {
         Ok(Self {
             vmas: BTreeMap::new(),
             address_space: AS::new()?,
+            charged: 0,
         })
     }
 
@@ -50,11 +59,15 @@ impl<AS: UserAddressSpace> MemoryMap<AS> {
         Self {
             vmas: BTreeMap::new(),
             address_space,
+            charged: 0,
         }
     }
 
     /// Create an address space from a pre-populated list of VMAs. Used by the
     /// ELF loader.
+    ///
+    /// Any [`VMAFlags::ACCOUNT`] VMAs are charged to [`VM_COMMIT`], failing
+    /// with [`KernelError::NoMemory`] if the overcommit policy refuses them.
     pub fn from_vmas(vmas: Vec<VMArea>) -> Result<Self> {
         let mut map = BTreeMap::new();
 
@@ -62,10 +75,18 @@ impl<AS: UserAddressSpace> MemoryMap<AS> {
             map.insert(vma.region.start_address(), vma);
         }
 
-        Ok(Self {
+        let mut map = Self {
             vmas: map,
             address_space: AS::new()?,
-        })
+            charged: 0,
+        };
+
+        let committed = map.committed_pages();
+
+        VM_COMMIT.charge(committed)?;
+        map.charged = committed;
+
+        Ok(map)
     }
 
     /// Finds the `VMArea` that contains the given virtual address.
@@ -93,23 +114,54 @@ impl<AS: UserAddressSpace> MemoryMap<AS> {
 
     /// Maps a region of memory.
     pub fn mmap(
+        &mut self,
+        requested_address: AddressRequest,
+        len: usize,
+        perms: VMAPermissions,
+        kind: VMAreaKind,
+        name: String,
+    ) -> Result<VA> {
+        self.mmap_with_flags(requested_address, len, perms, kind, name, VMAFlags::empty())
+    }
+
+    /// Maps a region of memory, setting `flags` on the resulting VMA.
+    ///
+    /// If [`VMAFlags::HUGEPAGE`] is requested the length is rounded up to a
+    /// multiple of [`HUGE_PAGE_SIZE`] and, when the kernel is free to choose
+    /// the address, the mapping is placed on a huge-page boundary.
+    pub fn mmap_with_flags(
         &mut self,
         requested_address: AddressRequest,
         mut len: usize,
         perms: VMAPermissions,
         kind: VMAreaKind,
         name: String,
+        flags: VMAFlags,
     ) -> Result<VA> {
         if len == 0 {
             return Err(KernelError::InvalidValue);
         }
 
+        let huge = flags.contains(VMAFlags::HUGEPAGE);
+
+        if huge {
+            if !matches!(kind, VMAreaKind::Anon) {
+                return Err(KernelError::InvalidValue);
+            }
+
+            len = len.next_multiple_of(HUGE_PAGE_SIZE);
+        }
+
         // Ensure the length is page-aligned.
         if len & PAGE_MASK != 0 {
             len = (len & !PAGE_MASK) + PAGE_SIZE;
         }
 
         let region = match requested_address {
+            AddressRequest::Any if huge => self
+                .find_free_region(len + HUGE_PAGE_SIZE - PAGE_SIZE)
+                .map(|r| VirtMemoryRegion::new(r.start_address().align_up(HUGE_PAGE_SIZE), len))
+                .ok_or(KernelError::NoMemory)?,
             AddressRequest::Any => self.find_free_region(len).ok_or(KernelError::NoMemory)?,
             AddressRequest::Hint(address) => {
                 // Be more permissive when it's a hint.
@@ -121,7 +173,7 @@ impl<AS: UserAddressSpace> MemoryMap<AS> {
 
                 let region = VirtMemoryRegion::new(address, len);
 
-                if self.is_region_free(region) {
+                if self.is_region_free(region) && !self.intrudes_stack_guard(region) {
                     region
                 } else {
                     self.find_free_region(len).ok_or(KernelError::NoMemory)?
@@ -145,14 +197,23 @@ impl<AS: UserAddressSpace> MemoryMap<AS> {
             }
         };
 
+        if flags.contains(VMAFlags::ACCOUNT) {
+            VM_COMMIT.charge(region.size() / PAGE_SIZE)?;
+            self.charged += region.size() / PAGE_SIZE;
+        }
+
         // At this point, `start_addr` points to a valid, free region.
         // We can now create and insert the new VMA, handling merges.
         let mut new_vma = VMArea::new(region, kind, perms);
 
         new_vma.set_name(name);
+        new_vma.set_flags(flags);
 
         self.insert_and_merge(new_vma);
 
+        // A fixed mapping may have replaced accounted pages.
+        self.settle_commit();
+
         Ok(region.start_address())
     }
 
@@ -178,7 +239,11 @@ impl<AS: UserAddressSpace> MemoryMap<AS> {
         }
 
         // Ensure len is page-sized.
-        self.unmap_region(range.align_to_page_boundary(), None)
+        let pages = self.unmap_region(range.align_to_page_boundary(), None);
+
+        self.settle_commit();
+
+        pages
     }
 
     /// Changes the memory protection flags for a page-aligned region.
@@ -205,6 +270,10 @@ impl<AS: UserAddressSpace> MemoryMap<AS> {
             .remove(&affected_vma_addr)
             .expect("Should have the same key as the start address");
 
+        if affected_vma.flags.contains(VMAFlags::HUGEPAGE) {
+            self.address_space.split_huge_pages(protect_region)?;
+        }
+
         // Easy case, the entire VMA is changing.
         if affected_vma.region == protect_region {
             let old_vma = affected_vma.clone();
@@ -243,6 +312,50 @@ impl<AS: UserAddressSpace> MemoryMap<AS> {
         Err(KernelError::NoMemory)
     }
 
+    /// Sets `insert` and clears `remove` in the flags of all VMAs within
+    /// `region`, splitting VMAs at the boundaries of the region as necessary.
+    ///
+    /// The region must be page-aligned and entirely covered by VMAs. The page
+    /// tables are left untouched.
+    pub fn update_flags(
+        &mut self,
+        region: VirtMemoryRegion,
+        insert: VMAFlags,
+        remove: VMAFlags,
+    ) -> Result<()> {
+        if !region.is_page_aligned() || region.size() == 0 {
+            return Err(KernelError::InvalidValue);
+        }
+
+        // Ensure there are no holes before modifying anything.
+        let mut affected_vmas = Vec::new();
+        let mut cursor = region.start_address();
+
+        while cursor < region.end_address() {
+            let vma = self.find_vma(cursor).ok_or(KernelError::NoMemory)?;
+            cursor = vma.region.end_address();
+            affected_vmas.push(vma.clone());
+        }
+
+        for vma in affected_vmas {
+            let intersection = vma.region.intersection(region).unwrap();
+            let (left, right) = vma.region.punch_hole(intersection);
+
+            self.vmas.remove(&vma.region.start_address());
+
+            let mut new_vma = vma.shrink_to(intersection);
+            new_vma.flags = (new_vma.flags | insert) - remove;
+            self.vmas.insert(intersection.start_address(), new_vma);
+
+            for remainder in [left, right].into_iter().flatten() {
+                self.vmas
+                    .insert(remainder.start_address(), vma.shrink_to(remainder));
+            }
+        }
+
+        Ok(())
+    }
+
     /// Checks if a given virtual memory region is completely free.
     fn is_region_free(&self, region: VirtMemoryRegion) -> bool {
         // Find the VMA that might overlap with the start of our desired region.
@@ -266,8 +379,19 @@ impl<AS: UserAddressSpace> MemoryMap<AS> {
         }
     }
 
+    /// Checks whether `region` overlaps the guard gap below a stack VMA.
+    fn intrudes_stack_guard(&self, region: VirtMemoryRegion) -> bool {
+        self.vmas
+            .range(region.start_address()..)
+            .next()
+            .is_some_and(|(start, vma)| {
+                vma.flags.contains(VMAFlags::GROWSDOWN)
+                    && region.end_address().value() + STACK_GUARD_GAP > start.value()
+            })
+    }
+
     /// Finds a free region of at least `len` bytes. Searches downwards from
-    /// `MMAP_BASE`.
+    /// `MMAP_BASE`, leaving the guard gap below any stack VMA unused.
     fn find_free_region(&self, len: usize) -> Option<VirtMemoryRegion> {
         let mut last_vma_end = VA::from_value(MMAP_BASE);
 
@@ -288,7 +412,12 @@ impl<AS: UserAddressSpace> MemoryMap<AS> {
                     ));
                 }
             }
-            last_vma_end = vma_start;
+
+            last_vma_end = if vma.flags.contains(VMAFlags::GROWSDOWN) {
+                VA::from_value(vma_start.value().saturating_sub(STACK_GUARD_GAP))
+            } else {
+                vma_start
+            };
         }
 
         // Check the final gap at the beginning of the mmap area.
@@ -302,6 +431,55 @@ impl<AS: UserAddressSpace> MemoryMap<AS> {
         }
     }
 
+    /// Grows a [`VMAFlags::GROWSDOWN`] VMA downwards so that it covers `addr`.
+    ///
+    /// This is used to satisfy faults just below a stack. The faulting address
+    /// must lie within [`STACK_GUARD_GAP`] of the bottom of the stack, the
+    /// grown stack must not exceed `max_size` bytes and a full guard gap must
+    /// remain between the new bottom of the stack and the preceding mapping.
+    /// Otherwise, the guard has been breached and `Err` is returned.
+    pub fn expand_stack(&mut self, addr: VA, max_size: usize) -> Result<()> {
+        let (&stack_start, stack_vma) = self.vmas.range(addr..).next().ok_or(KernelError::Fault)?;
+
+        if !stack_vma.flags.contains(VMAFlags::GROWSDOWN) {
+            return Err(KernelError::Fault);
+        }
+
+        let new_start = addr.page_aligned();
+
+        if stack_start.value() - new_start.value() > STACK_GUARD_GAP {
+            return Err(KernelError::Fault);
+        }
+
+        let new_size = stack_vma.region.end_address().value() - new_start.value();
+
+        if new_size > max_size {
+            return Err(KernelError::NoMemory);
+        }
+
+        let accounted = stack_vma.flags.contains(VMAFlags::ACCOUNT);
+        let growth = (stack_start.value() - new_start.value()) / PAGE_SIZE;
+
+        if let Some((_, prev_vma)) = self.vmas.range(..stack_start).next_back()
+            && prev_vma.region.end_address().value() + STACK_GUARD_GAP > new_start.value()
+        {
+            return Err(KernelError::Fault);
+        }
+
+        if accounted {
+            VM_COMMIT.charge(growth)?;
+            self.charged += growth;
+        }
+
+        // The stack is anonymous and the newly covered pages are unmapped, so
+        // no page-table fixups are required.
+        let mut vma = self.vmas.remove(&stack_start).unwrap();
+        vma.region = VirtMemoryRegion::new(new_start, new_size);
+        self.vmas.insert(new_start, vma);
+
+        Ok(())
+    }
+
     /// Inserts a new VMA, handling overlaps and merging it with neighbors if
     /// possible.
     pub(super) fn insert_and_merge(&mut self, mut vma: VMArea) {
@@ -355,6 +533,10 @@ impl<AS: UserAddressSpace> MemoryMap<AS> {
         let intersecting_region = fixup_region.intersection(old_vma.region);
 
         if let Some(intersection) = intersecting_region {
+            if old_vma.flags.contains(VMAFlags::HUGEPAGE) {
+                self.address_space.split_huge_pages(intersection)?;
+            }
+
             match new_vma {
                 Some(new_vma) => {
                     // We always unmap if file backing-stores are involoved.
@@ -488,12 +670,27 @@ impl<AS: UserAddressSpace> MemoryMap<AS> {
     /// pages as CoW pages. If the VMA isn't writable, the ref count is
     /// incremented.
     pub fn clone_as_cow(&mut self) -> Result<Self> {
-        let mut new_as = AS::new()?;
-        let new_vmas = self.vmas.clone();
+        // The child's private pages are committed separately from ours.
+        VM_COMMIT.charge(self.charged)?;
+
+        // Should cloning fail part way, dropping the new map releases the
+        // charge.
+        let mut new_map = Self {
+            vmas: self.vmas.clone(),
+            address_space: AS::new().inspect_err(|_| VM_COMMIT.uncharge(self.charged))?,
+            charged: self.charged,
+        };
 
-        for vma in new_vmas.values() {
+        for vma in new_map.vmas.values() {
             let mut pte_perms = PtePermissions::from(vma.permissions);
 
+            // CoW sharing is tracked per base page, so break up any huge
+            // pages first.
+            if vma.flags.contains(VMAFlags::HUGEPAGE) {
+                self.address_space
+                    .split_huge_pages(vma.region.align_to_page_boundary())?;
+            }
+
             // Mark all writable pages as CoW.
             if pte_perms.is_write() {
                 pte_perms = pte_perms.into_cow();
@@ -501,15 +698,17 @@ impl<AS: UserAddressSpace> MemoryMap<AS> {
 
             self.address_space.protect_and_clone_region(
                 vma.region.align_to_page_boundary(),
-                &mut new_as,
+                &mut new_map.address_space,
                 pte_perms,
             )?;
         }
 
-        Ok(Self {
-            vmas: new_vmas,
-            address_space: new_as,
-        })
+        Ok(new_map)
+    }
+
+    /// Returns a reference to the underlying address space.
+    pub fn address_space(&self) -> &AS {
+        &self.address_space
     }
 
     /// Returns a mutable reference to the underlying address space.
@@ -526,6 +725,35 @@ impl<AS: UserAddressSpace> MemoryMap<AS> {
     pub fn iter_vmas(&self) -> impl Iterator<Item = &VMArea> {
         self.vmas.values()
     }
+
+    /// Returns the number of pages covered by [`VMAFlags::ACCOUNT`] VMAs.
+    pub fn committed_pages(&self) -> usize {
+        self.vmas
+            .values()
+            .filter(|vma| vma.flags.contains(VMAFlags::ACCOUNT))
+            .map(|vma| vma.region.size() / PAGE_SIZE)
+            .sum()
+    }
+
+    /// Brings the pages charged to [`VM_COMMIT`] into line with the accounted
+    /// VMAs, after they have been resized, replaced or removed.
+    fn settle_commit(&mut self) {
+        let committed = self.committed_pages();
+
+        if committed > self.charged {
+            VM_COMMIT.force_charge(committed - self.charged);
+        } else {
+            VM_COMMIT.uncharge(self.charged - committed);
+        }
+
+        self.charged = committed;
+    }
+}
+
+impl<AS: UserAddressSpace> Drop for MemoryMap<AS> {
+    fn drop(&mut self) {
+        VM_COMMIT.uncharge(self.charged);
+    }
 }
 
 #[cfg(test)]
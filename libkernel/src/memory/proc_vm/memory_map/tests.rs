@@ -3,14 +3,16 @@ use crate::{
     error::Result,
     fs::Inode,
     memory::{
-        PAGE_SIZE,
+        HUGE_PAGE_SIZE, PAGE_SIZE,
         address::VA,
         page::PageFrame,
         paging::permissions::PtePermissions,
         proc_vm::{
             address_space::{PageInfo, UserAddressSpace},
-            memory_map::{AddressRequest, MMAP_BASE},
-            vmarea::{VMAPermissions, VMArea, VMAreaKind, VMFileMapping, tests::DummyTestInode},
+            memory_map::{AddressRequest, MMAP_BASE, STACK_GUARD_GAP},
+            vmarea::{
+                VMAFlags, VMAPermissions, VMArea, VMAreaKind, VMFileMapping, tests::DummyTestInode,
+            },
         },
         region::VirtMemoryRegion,
     },
@@ -28,6 +30,9 @@ pub enum MockPageTableOp {
         region: VirtMemoryRegion,
         perms: PtePermissions,
     },
+    SplitHugePages {
+        region: VirtMemoryRegion,
+    },
 }
 
 pub struct MockAddressSpace {
@@ -81,6 +86,14 @@ impl UserAddressSpace for MockAddressSpace {
         Ok(Vec::new())
     }
 
+    fn split_huge_pages(&mut self, va_range: VirtMemoryRegion) -> Result<()> {
+        self.ops_log
+            .lock()
+            .unwrap()
+            .push(MockPageTableOp::SplitHugePages { region: va_range });
+        Ok(())
+    }
+
     fn translate(&self, _va: VA) -> Option<PageInfo> {
         None
     }
@@ -245,6 +258,249 @@ fn test_mmap_any_with_existing() {
     assert!(pvm.address_space.ops_log.lock().unwrap().is_empty());
 }
 
+#[test]
+fn test_mmap_hugepage_aligned() {
+    let mut pvm: MemoryMap<MockAddressSpace> = MemoryMap::new().unwrap();
+
+    // Push the top of the free space off a huge-page boundary.
+    pvm.insert_and_merge(create_anon_vma(
+        MMAP_BASE - PAGE_SIZE,
+        PAGE_SIZE,
+        VMAPermissions::ro(),
+    ));
+
+    let addr = pvm
+        .mmap_with_flags(
+            AddressRequest::Any,
+            HUGE_PAGE_SIZE + PAGE_SIZE,
+            VMAPermissions::rw(),
+            VMAreaKind::Anon,
+            String::new(),
+            VMAFlags::HUGEPAGE,
+        )
+        .unwrap();
+
+    // The length should be rounded up to a whole number of huge pages.
+    assert!(addr.value().is_multiple_of(HUGE_PAGE_SIZE));
+    assert_vma_exists(&pvm, addr.value(), 2 * HUGE_PAGE_SIZE);
+
+    let vma = pvm.find_vma(addr).unwrap();
+    assert_eq!(vma.flags(), VMAFlags::HUGEPAGE);
+    assert_eq!(
+        vma.huge_page_region(addr.add_bytes(HUGE_PAGE_SIZE + 0x1234)),
+        Some(VirtMemoryRegion::new(
+            addr.add_bytes(HUGE_PAGE_SIZE),
+            HUGE_PAGE_SIZE
+        ))
+    );
+
+    // Huge pages can't be file-backed.
+    assert!(
+        pvm.mmap_with_flags(
+            AddressRequest::Any,
+            HUGE_PAGE_SIZE,
+            VMAPermissions::rw(),
+            VMAreaKind::new_file(new_inode(), 0, HUGE_PAGE_SIZE as u64),
+            String::new(),
+            VMAFlags::HUGEPAGE,
+        )
+        .is_err()
+    );
+}
+
+#[test]
+fn test_munmap_partial_hugepage_splits() {
+    let mut pvm: MemoryMap<MockAddressSpace> = MemoryMap::new().unwrap();
+
+    let addr = pvm
+        .mmap_with_flags(
+            AddressRequest::Any,
+            HUGE_PAGE_SIZE,
+            VMAPermissions::rw(),
+            VMAreaKind::Anon,
+            String::new(),
+            VMAFlags::HUGEPAGE,
+        )
+        .unwrap();
+
+    let region = VirtMemoryRegion::new(addr.add_pages(1), PAGE_SIZE);
+    pvm.munmap(region).unwrap();
+
+    assert_eq!(pvm.vmas.len(), 2);
+    assert_eq!(
+        *pvm.address_space.ops_log.lock().unwrap(),
+        &[
+            MockPageTableOp::SplitHugePages { region },
+            MockPageTableOp::UnmapRange { region }
+        ]
+    );
+
+    // The remaining pieces are too small to hold a huge page.
+    assert!(pvm.find_vma(addr).unwrap().huge_page_region(addr).is_none());
+}
+
+// Creates a stack VMA which grows down from `end`.
+fn create_stack_vma(end: usize, size: usize) -> VMArea {
+    let mut vma = create_anon_vma(end - size, size, VMAPermissions::rw());
+    vma.set_flags(VMAFlags::GROWSDOWN);
+    vma
+}
+
+#[test]
+fn test_expand_stack_within_gap() {
+    let stack_end = MMAP_BASE;
+    let stack_sz = 4 * PAGE_SIZE;
+    let mut pvm: MemoryMap<MockAddressSpace> =
+        MemoryMap::from_vmas(vec![create_stack_vma(stack_end, stack_sz)]).unwrap();
+
+    let fault_addr = VA::from_value(stack_end - stack_sz - 2 * PAGE_SIZE + 0x10);
+    pvm.expand_stack(fault_addr, usize::MAX).unwrap();
+
+    assert_eq!(pvm.vmas.len(), 1);
+    assert_vma_exists(
+        &pvm,
+        stack_end - stack_sz - 2 * PAGE_SIZE,
+        stack_sz + 2 * PAGE_SIZE,
+    );
+    assert!(pvm.find_vma(fault_addr).is_some());
+
+    // Growing an anonymous stack doesn't touch the page tables.
+    assert!(pvm.address_space.ops_log.lock().unwrap().is_empty());
+}
+
+#[test]
+fn test_expand_stack_rlimit() {
+    let stack_end = MMAP_BASE;
+    let stack_sz = 4 * PAGE_SIZE;
+    let mut pvm: MemoryMap<MockAddressSpace> =
+        MemoryMap::from_vmas(vec![create_stack_vma(stack_end, stack_sz)]).unwrap();
+
+    let fault_addr = VA::from_value(stack_end - stack_sz - PAGE_SIZE);
+
+    assert!(pvm.expand_stack(fault_addr, stack_sz).is_err());
+    pvm.expand_stack(fault_addr, stack_sz + PAGE_SIZE).unwrap();
+    assert_vma_exists(&pvm, stack_end - stack_sz - PAGE_SIZE, stack_sz + PAGE_SIZE);
+}
+
+#[test]
+fn test_expand_stack_guard_breached() {
+    let stack_end = MMAP_BASE;
+    let stack_sz = 4 * PAGE_SIZE;
+    let stack_start = stack_end - stack_sz;
+    let below = stack_start - STACK_GUARD_GAP - 2 * PAGE_SIZE;
+
+    let mut pvm: MemoryMap<MockAddressSpace> = MemoryMap::from_vmas(vec![
+        create_anon_vma(below, PAGE_SIZE, VMAPermissions::rw()),
+        create_stack_vma(stack_end, stack_sz),
+    ])
+    .unwrap();
+
+    // Too far below the stack to be a stack access.
+    assert!(
+        pvm.expand_stack(
+            VA::from_value(stack_start - STACK_GUARD_GAP - PAGE_SIZE),
+            usize::MAX
+        )
+        .is_err()
+    );
+
+    // Growing this far would leave less than a guard gap above the mapping
+    // below.
+    assert!(
+        pvm.expand_stack(VA::from_value(stack_start - 2 * PAGE_SIZE), usize::MAX)
+            .is_err()
+    );
+
+    pvm.expand_stack(VA::from_value(stack_start - PAGE_SIZE), usize::MAX)
+        .unwrap();
+    assert_vma_exists(&pvm, stack_start - PAGE_SIZE, stack_sz + PAGE_SIZE);
+
+    // Non-stack VMAs never grow.
+    assert!(
+        pvm.expand_stack(VA::from_value(below - PAGE_SIZE), usize::MAX)
+            .is_err()
+    );
+}
+
+#[test]
+fn test_mmap_any_avoids_stack_guard() {
+    let stack_end = MMAP_BASE;
+    let stack_sz = 4 * PAGE_SIZE;
+    let mut pvm: MemoryMap<MockAddressSpace> =
+        MemoryMap::from_vmas(vec![create_stack_vma(stack_end, stack_sz)]).unwrap();
+
+    let addr = pvm
+        .mmap(
+            AddressRequest::Any,
+            PAGE_SIZE,
+            VMAPermissions::rw(),
+            VMAreaKind::Anon,
+            String::new(),
+        )
+        .unwrap();
+
+    assert_eq!(
+        addr.value(),
+        stack_end - stack_sz - STACK_GUARD_GAP - PAGE_SIZE
+    );
+
+    // A hint inside the guard gap is ignored.
+    let hint = VA::from_value(stack_end - stack_sz - PAGE_SIZE);
+    let addr = pvm
+        .mmap(
+            AddressRequest::Hint(hint),
+            PAGE_SIZE,
+            VMAPermissions::rw(),
+            VMAreaKind::Anon,
+            String::new(),
+        )
+        .unwrap();
+
+    assert_ne!(addr, hint);
+}
+
+#[test]
+fn test_update_flags_splits_vma() {
+    let start = MMAP_BASE - 4 * PAGE_SIZE;
+    let mut pvm: MemoryMap<MockAddressSpace> = MemoryMap::from_vmas(vec![create_anon_vma(
+        start,
+        4 * PAGE_SIZE,
+        VMAPermissions::rw(),
+    )])
+    .unwrap();
+
+    let region = VirtMemoryRegion::new(VA::from_value(start + PAGE_SIZE), 2 * PAGE_SIZE);
+    pvm.update_flags(region, VMAFlags::UFFD_MISSING, VMAFlags::empty())
+        .unwrap();
+
+    assert_eq!(pvm.vmas.len(), 3);
+    assert_vma_exists(&pvm, start, PAGE_SIZE);
+    assert_vma_exists(&pvm, start + PAGE_SIZE, 2 * PAGE_SIZE);
+    assert_vma_exists(&pvm, start + 3 * PAGE_SIZE, PAGE_SIZE);
+    assert_eq!(
+        pvm.find_vma(region.start_address()).unwrap().flags(),
+        VMAFlags::UFFD_MISSING
+    );
+    assert!(pvm.find_vma(VA::from_value(start)).unwrap().flags().is_empty());
+
+    // No page-table modifications are required.
+    assert!(pvm.address_space.ops_log.lock().unwrap().is_empty());
+
+    pvm.update_flags(region, VMAFlags::empty(), VMAFlags::UFFD_MISSING)
+        .unwrap();
+    assert!(pvm.find_vma(region.start_address()).unwrap().flags().is_empty());
+
+    // Regions containing holes are rejected.
+    assert!(
+        pvm.update_flags(
+            VirtMemoryRegion::new(VA::from_value(start), 8 * PAGE_SIZE),
+            VMAFlags::UFFD_MISSING,
+            VMAFlags::empty(),
+        )
+        .is_err()
+    );
+}
+
 #[test]
 fn test_mmap_hint_free() {
     let mut pvm: MemoryMap<MockAddressSpace> = MemoryMap::new().unwrap();
@@ -902,3 +1158,54 @@ fn test_mprotect_merge_restoration() {
     assert_vma_exists(&pvm, start, size);
     assert_vma_perms(&pvm, start, VMAPermissions::rw());
 }
+
+#[test]
+fn test_committed_pages_follow_accounted_vmas() {
+    let mut pvm: MemoryMap<MockAddressSpace> = MemoryMap::new().unwrap();
+
+    let addr = pvm
+        .mmap_with_flags(
+            AddressRequest::Any,
+            4 * PAGE_SIZE,
+            VMAPermissions::rw(),
+            VMAreaKind::Anon,
+            String::new(),
+            VMAFlags::ACCOUNT,
+        )
+        .unwrap();
+
+    // Unaccounted mappings aren't committed.
+    pvm.mmap(
+        AddressRequest::Any,
+        2 * PAGE_SIZE,
+        VMAPermissions::rw(),
+        VMAreaKind::Anon,
+        String::new(),
+    )
+    .unwrap();
+
+    assert_eq!(pvm.committed_pages(), 4);
+    assert_eq!(pvm.charged, 4);
+
+    // Punching a hole releases the commitment for the hole only.
+    pvm.munmap(VirtMemoryRegion::new(addr.add_pages(1), PAGE_SIZE))
+        .unwrap();
+    assert_eq!(pvm.committed_pages(), 3);
+    assert_eq!(pvm.charged, 3);
+
+    // Replacing accounted pages with an unaccounted fixed mapping releases
+    // them too.
+    pvm.mmap(
+        AddressRequest::Fixed {
+            address: addr,
+            permit_overlap: true,
+        },
+        PAGE_SIZE,
+        VMAPermissions::ro(),
+        VMAreaKind::Anon,
+        String::new(),
+    )
+    .unwrap();
+    assert_eq!(pvm.committed_pages(), 2);
+    assert_eq!(pvm.charged, 2);
+}
@@ -6,10 +6,11 @@ use super::{
 use crate::error::{KernelError, Result};
 use alloc::string::ToString;
 use memory_map::{AddressRequest, MemoryMap};
-use vmarea::{AccessKind, FaultValidation, VMAPermissions, VMArea, VMAreaKind};
+use vmarea::{AccessKind, FaultValidation, VMAFlags, VMAPermissions, VMArea, VMAreaKind};
 
 pub mod address_space;
 pub mod memory_map;
+pub mod overcommit;
 pub mod pg_offset;
 pub mod vmarea;
 
@@ -146,7 +147,7 @@ impl<AS: UserAddressSpace> ProcessVM<AS> {
         if new_end_addr_aligned > current_end {
             let growth_size = new_end_addr_aligned.value() - current_end.value();
 
-            self.mm.mmap(
+            self.mm.mmap_with_flags(
                 AddressRequest::Fixed {
                     address: current_end,
                     permit_overlap: false,
@@ -155,6 +156,7 @@ impl<AS: UserAddressSpace> ProcessVM<AS> {
                 BRK_PERMISSIONS,
                 VMAreaKind::Anon,
                 "[heap]".to_string(),
+                VMAFlags::ACCOUNT,
             )?;
 
             self.brk = new_brk_region;
@@ -193,6 +195,7 @@ mod tests {
             region: VirtMemoryRegion::new(VA::from_value(0x1000), PAGE_SIZE),
             kind: VMAreaKind::Anon, // Simplification for test
             permissions: VMAPermissions::rx(),
+            flags: vmarea::VMAFlags::empty(),
             name: String::new(),
         };
 
@@ -349,6 +352,7 @@ mod tests {
             region: VirtMemoryRegion::new(obstacle_addr, PAGE_SIZE),
             kind: VMAreaKind::Anon,
             permissions: VMAPermissions::ro(),
+            flags: vmarea::VMAFlags::empty(),
             name: String::new(),
         };
         vm.mm.insert_and_merge(obstacle_vma);
@@ -0,0 +1,240 @@
+//! Commit accounting for user memory.
+//!
+//! Private writable mappings can be faulted in at any time, so the memory
+//! backing them is *committed* when the mapping is created rather than when it
+//! is touched. [`CommitAccounting`] keeps a system-wide tally of committed
+//! pages and decides, according to an [`OvercommitPolicy`], whether a new
+//! commitment may be made. This mirrors Linux's `vm.overcommit_memory`.
+
+use crate::error::{KernelError, Result};
+use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+
+/// The default percentage of RAM which may be committed under
+/// [`OvercommitPolicy::Never`].
+pub const DEFAULT_OVERCOMMIT_RATIO: usize = 50;
+
+/// How the kernel treats requests to commit more memory than is available.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum OvercommitPolicy {
+    /// Refuse only commitments which could never be satisfied, i.e. those
+    /// larger than all of RAM.
+    Heuristic = 0,
+    /// Never refuse a commitment.
+    Always = 1,
+    /// Refuse commitments which would take the total beyond the commit limit.
+    Never = 2,
+}
+
+impl TryFrom<u8> for OvercommitPolicy {
+    type Error = KernelError;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(Self::Heuristic),
+            1 => Ok(Self::Always),
+            2 => Ok(Self::Never),
+            _ => Err(KernelError::InvalidValue),
+        }
+    }
+}
+
+/// A system-wide tally of committed pages.
+pub struct CommitAccounting {
+    committed: AtomicUsize,
+    total_pages: AtomicUsize,
+    ratio: AtomicUsize,
+    policy: AtomicU8,
+}
+
+impl Default for CommitAccounting {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CommitAccounting {
+    /// Creates a tally with nothing committed and the heuristic policy.
+    ///
+    /// No commitment is refused until the amount of RAM has been set with
+    /// [`Self::set_total_pages`].
+    pub const fn new() -> Self {
+        Self {
+            committed: AtomicUsize::new(0),
+            total_pages: AtomicUsize::new(0),
+            ratio: AtomicUsize::new(DEFAULT_OVERCOMMIT_RATIO),
+            policy: AtomicU8::new(OvercommitPolicy::Heuristic as u8),
+        }
+    }
+
+    /// Sets the number of pages of RAM which commitments are measured
+    /// against.
+    pub fn set_total_pages(&self, pages: usize) {
+        self.total_pages.store(pages, Ordering::Relaxed);
+    }
+
+    /// Returns the current overcommit policy.
+    pub fn policy(&self) -> OvercommitPolicy {
+        OvercommitPolicy::try_from(self.policy.load(Ordering::Relaxed)).unwrap()
+    }
+
+    /// Sets the overcommit policy.
+    pub fn set_policy(&self, policy: OvercommitPolicy) {
+        self.policy.store(policy as u8, Ordering::Relaxed);
+    }
+
+    /// Returns the percentage of RAM which may be committed under
+    /// [`OvercommitPolicy::Never`].
+    pub fn ratio(&self) -> usize {
+        self.ratio.load(Ordering::Relaxed)
+    }
+
+    /// Sets the percentage of RAM which may be committed under
+    /// [`OvercommitPolicy::Never`].
+    pub fn set_ratio(&self, ratio: usize) {
+        self.ratio.store(ratio, Ordering::Relaxed);
+    }
+
+    /// Returns the number of pages currently committed.
+    pub fn committed(&self) -> usize {
+        self.committed.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of pages which may be committed under
+    /// [`OvercommitPolicy::Never`].
+    pub fn commit_limit(&self) -> usize {
+        // There is no swap, so only RAM counts towards the limit.
+        self.total_pages.load(Ordering::Relaxed) * self.ratio() / 100
+    }
+
+    /// Commits `pages` pages, subject to the overcommit policy.
+    ///
+    /// Returns [`KernelError::NoMemory`] if the policy refuses the
+    /// commitment, in which case nothing is charged.
+    pub fn charge(&self, pages: usize) -> Result<()> {
+        let total = self.total_pages.load(Ordering::Relaxed);
+
+        if total == 0 {
+            self.force_charge(pages);
+            return Ok(());
+        }
+
+        match self.policy() {
+            OvercommitPolicy::Always => {
+                self.force_charge(pages);
+                Ok(())
+            }
+            OvercommitPolicy::Heuristic => {
+                if pages > total {
+                    return Err(KernelError::NoMemory);
+                }
+
+                self.force_charge(pages);
+                Ok(())
+            }
+            OvercommitPolicy::Never => {
+                let limit = self.commit_limit();
+
+                let mut committed = self.committed.load(Ordering::Relaxed);
+
+                loop {
+                    let new = committed
+                        .checked_add(pages)
+                        .filter(|&new| new <= limit)
+                        .ok_or(KernelError::NoMemory)?;
+
+                    match self.committed.compare_exchange_weak(
+                        committed,
+                        new,
+                        Ordering::Relaxed,
+                        Ordering::Relaxed,
+                    ) {
+                        Ok(_) => return Ok(()),
+                        Err(c) => committed = c,
+                    }
+                }
+            }
+        }
+    }
+
+    /// Commits `pages` pages regardless of the overcommit policy.
+    pub fn force_charge(&self, pages: usize) {
+        self.committed.fetch_add(pages, Ordering::Relaxed);
+    }
+
+    /// Releases a commitment of `pages` pages.
+    pub fn uncharge(&self, pages: usize) {
+        let prev = self.committed.fetch_sub(pages, Ordering::Relaxed);
+
+        debug_assert!(prev >= pages, "commit accounting underflow");
+    }
+}
+
+/// The system-wide commit tally, charged by every [`MemoryMap`].
+///
+/// [`MemoryMap`]: super::memory_map::MemoryMap
+pub static VM_COMMIT: CommitAccounting = CommitAccounting::new();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unsized_tally_never_refuses() {
+        let acct = CommitAccounting::new();
+        acct.set_policy(OvercommitPolicy::Never);
+
+        assert!(acct.charge(1 << 30).is_ok());
+        assert_eq!(acct.committed(), 1 << 30);
+    }
+
+    #[test]
+    fn heuristic_refuses_more_than_ram() {
+        let acct = CommitAccounting::new();
+        acct.set_total_pages(100);
+
+        assert_eq!(acct.charge(101), Err(KernelError::NoMemory));
+        assert!(acct.charge(100).is_ok());
+        // Each commitment is judged on its own.
+        assert!(acct.charge(100).is_ok());
+        assert_eq!(acct.committed(), 200);
+    }
+
+    #[test]
+    fn always_never_refuses() {
+        let acct = CommitAccounting::new();
+        acct.set_total_pages(100);
+        acct.set_policy(OvercommitPolicy::Always);
+
+        assert!(acct.charge(1000).is_ok());
+    }
+
+    #[test]
+    fn never_enforces_commit_limit() {
+        let acct = CommitAccounting::new();
+        acct.set_total_pages(100);
+        acct.set_policy(OvercommitPolicy::Never);
+
+        assert_eq!(acct.commit_limit(), 50);
+        assert!(acct.charge(30).is_ok());
+        assert_eq!(acct.charge(21), Err(KernelError::NoMemory));
+        assert_eq!(acct.committed(), 30);
+        assert!(acct.charge(20).is_ok());
+
+        acct.uncharge(10);
+        assert!(acct.charge(10).is_ok());
+        assert_eq!(acct.committed(), 50);
+
+        acct.set_ratio(100);
+        assert!(acct.charge(50).is_ok());
+    }
+
+    #[test]
+    fn policy_from_sysctl_value() {
+        assert_eq!(OvercommitPolicy::try_from(2), Ok(OvercommitPolicy::Never));
+        assert_eq!(
+            OvercommitPolicy::try_from(3),
+            Err(KernelError::InvalidValue)
+        );
+    }
+}
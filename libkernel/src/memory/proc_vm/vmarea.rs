@@ -13,7 +13,7 @@ use core::cmp;
 
 use crate::{
     fs::{Inode, InodeId},
-    memory::{PAGE_MASK, PAGE_SIZE, address::VA, region::VirtMemoryRegion},
+    memory::{HUGE_PAGE_SIZE, PAGE_MASK, PAGE_SIZE, address::VA, region::VirtMemoryRegion},
 };
 use alloc::string::{String, ToString};
 use alloc::sync::Arc;
@@ -63,6 +63,26 @@ impl VMAPermissions {
     }
 }
 
+bitflags::bitflags! {
+    /// Additional properties attached to a VMA which don't affect its
+    /// permissions or backing store.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct VMAFlags: u32 {
+        /// Faults within this (anonymous) VMA may be satisfied with huge
+        /// pages, as requested by `MAP_HUGETLB`.
+        const HUGEPAGE = 1 << 0;
+        /// This VMA is a stack which grows downwards on faults below its
+        /// start address, as requested by `MAP_GROWSDOWN`.
+        const GROWSDOWN = 1 << 1;
+        /// Faults on missing pages within this VMA are reported to a
+        /// userfaultfd handler rather than being resolved by the kernel.
+        const UFFD_MISSING = 1 << 2;
+        /// The pages of this VMA are charged against the system-wide commit
+        /// tally, see [`super::overcommit`].
+        const ACCOUNT = 1 << 3;
+    }
+}
+
 /// Describes the kind of access that occurred during a page fault.
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum AccessKind {
@@ -186,6 +206,7 @@ pub struct VMArea {
     pub(super) name: String,
     pub(super) kind: VMAreaKind,
     pub(super) permissions: VMAPermissions,
+    pub(super) flags: VMAFlags,
 }
 
 impl VMArea {
@@ -200,6 +221,7 @@ impl VMArea {
             region,
             kind,
             permissions,
+            flags: VMAFlags::empty(),
             name: String::new(),
         }
     }
@@ -263,6 +285,7 @@ impl VMArea {
                 len: hdr.p_filesz(endian) + mappable_region.offset() as u64,
             }),
             permissions,
+            flags: VMAFlags::empty(),
             name: String::new(),
         }
     }
@@ -397,7 +420,7 @@ impl VMArea {
     /// Merging is possible if permissions are identical and the backing storage
     /// is of a compatible and contiguous nature.
     pub(super) fn can_merge_with(&self, other: &VMArea) -> bool {
-        if self.permissions != other.permissions {
+        if self.permissions != other.permissions || self.flags != other.flags {
             return false;
         }
 
@@ -496,6 +519,32 @@ impl VMArea {
     pub fn name(&self) -> &str {
         &self.name
     }
+
+    /// Returns the flags set on this VMA.
+    pub fn flags(&self) -> VMAFlags {
+        self.flags
+    }
+
+    /// Sets the flags for this VMA.
+    pub fn set_flags(&mut self, flags: VMAFlags) {
+        self.flags = flags;
+    }
+
+    /// Returns the huge-page sized and aligned region surrounding `addr` if a
+    /// fault at that address can be satisfied with a huge page.
+    ///
+    /// This is only the case for anonymous VMAs that have opted in via
+    /// [`VMAFlags::HUGEPAGE`] and where the entire huge page lies within the
+    /// VMA.
+    pub fn huge_page_region(&self, addr: VA) -> Option<VirtMemoryRegion> {
+        if !self.flags.contains(VMAFlags::HUGEPAGE) || !matches!(self.kind, VMAreaKind::Anon) {
+            return None;
+        }
+
+        let region = VirtMemoryRegion::new(addr.align(HUGE_PAGE_SIZE), HUGE_PAGE_SIZE);
+
+        self.region.contains(region).then_some(region)
+    }
 }
 
 #[cfg(test)]
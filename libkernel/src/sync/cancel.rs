@@ -0,0 +1,155 @@
+//! Cooperative cancellation.
+//!
+//! A [`CancellationToken`] is shared between whoever may ask for some work to
+//! stop, e.g. a socket being closed or a filesystem being unmounted, and the
+//! tasks doing that work. Tasks observe cancellation at their `.await` points,
+//! through [`CancellationToken::cancelled`] or
+//! [`CancellationToken::run_until_cancelled`], so cancellation never cuts a
+//! future off between two awaits. A cancelled future is dropped, and so
+//! releases whatever it holds through its destructors.
+
+use super::condvar::{CondVar, WakeupType};
+use super::scope::{Either, select};
+use crate::CpuOps;
+use crate::error::KernelError;
+
+/// The error returned when work was stopped by its [`CancellationToken`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl From<Cancelled> for KernelError {
+    fn from(_: Cancelled) -> Self {
+        KernelError::Interrupted
+    }
+}
+
+/// A shared flag which, once set, stops any work watching it.
+///
+/// Clones refer to the same flag.
+pub struct CancellationToken<CPU: CpuOps> {
+    cancelled: CondVar<bool, CPU>,
+}
+
+impl<CPU: CpuOps> Clone for CancellationToken<CPU> {
+    fn clone(&self) -> Self {
+        Self {
+            cancelled: self.cancelled.clone(),
+        }
+    }
+}
+
+impl<CPU: CpuOps> Default for CancellationToken<CPU> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<CPU: CpuOps> CancellationToken<CPU> {
+    /// Creates a token which has not been cancelled.
+    #[track_caller]
+    pub fn new() -> Self {
+        Self {
+            cancelled: CondVar::new(false),
+        }
+    }
+
+    /// Cancels the token, waking everything waiting on it. Cancelling an
+    /// already cancelled token does nothing.
+    pub fn cancel(&self) {
+        self.cancelled.update(|cancelled| {
+            if *cancelled {
+                WakeupType::None
+            } else {
+                *cancelled = true;
+                WakeupType::All
+            }
+        });
+    }
+
+    /// Returns `true` if the token has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        let mut result = false;
+
+        self.cancelled.update(|cancelled| {
+            result = *cancelled;
+            WakeupType::None
+        });
+
+        result
+    }
+
+    /// Completes once the token has been cancelled.
+    pub async fn cancelled(&self) {
+        self.cancelled
+            .wait_until(|cancelled| cancelled.then_some(()))
+            .await;
+    }
+
+    /// Drives `fut` to completion, unless the token is cancelled first, in
+    /// which case `fut` is dropped.
+    ///
+    /// `fut` is polled before the token is checked, so it may still complete
+    /// if it is ready when cancellation arrives.
+    pub async fn run_until_cancelled<F: Future>(&self, fut: F) -> Result<F::Output, Cancelled> {
+        match select(fut, self.cancelled()).await {
+            Either::Left(output) => Ok(output),
+            Either::Right(()) => Err(Cancelled),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::MockCpuOps;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn cancellation_stops_work_at_an_await_point() {
+        let token = CancellationToken::<MockCpuOps>::new();
+        let cleaned_up = Arc::new(AtomicBool::new(false));
+
+        struct Cleanup(Arc<AtomicBool>);
+
+        impl Drop for Cleanup {
+            fn drop(&mut self) {
+                self.0.store(true, Ordering::Relaxed);
+            }
+        }
+
+        let worker = {
+            let token = token.clone();
+            let cleanup = Cleanup(cleaned_up.clone());
+
+            tokio::spawn(async move {
+                token
+                    .run_until_cancelled(async move {
+                        let _cleanup = cleanup;
+                        tokio::time::sleep(Duration::from_secs(10)).await;
+                    })
+                    .await
+            })
+        };
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(!token.is_cancelled());
+
+        token.cancel();
+        token.cancel();
+
+        assert_eq!(worker.await.unwrap(), Err(Cancelled));
+        assert!(token.is_cancelled());
+        assert!(cleaned_up.load(Ordering::Relaxed));
+
+        // Already cancelled, so new work stops at once.
+        token.cancelled().await;
+        assert_eq!(
+            token
+                .run_until_cancelled(core::future::pending::<()>())
+                .await,
+            Err(Cancelled)
+        );
+    }
+}
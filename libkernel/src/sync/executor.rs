@@ -0,0 +1,548 @@
+//! A priority-aware executor for free-standing kernel tasks.
+//!
+//! Most kernel work runs on behalf of a user task, polled when that task is
+//! scheduled. Some work belongs to no task: completing a block request,
+//! flushing dirty pages, reading ahead. Such work is spawned onto an
+//! [`Executor`] with a [`Priority`], and the kernel drives the executor with
+//! [`Executor::run`]. An executor may be given a hook, called whenever a
+//! task becomes ready, so that an idle CPU can be woken to poll it.
+//!
+//! The ready queue is strictly ordered by priority: a task is only polled
+//! when no higher priority task is ready, so a flood of background work can't
+//! delay an interrupt's bottom half. Within a priority, tasks run in the
+//! order they were woken.
+//!
+//! Spawning returns a [`TaskHandle`], through which the task may be aborted.
+//! An aborted task is never polled again; its future is dropped, running any
+//! destructors, the next time the executor reaches it.
+//!
+//! Code which runs outside of any executor, e.g. during early boot or on a
+//! panic, can instead drive a single future to completion with [`block_on`].
+
+use super::spinlock::SpinLockIrq;
+use crate::CpuOps;
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use core::cell::UnsafeCell;
+use core::hint::spin_loop;
+use core::pin::Pin;
+use core::pin::pin;
+use core::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+use core::task::{Context, Poll, Waker};
+
+/// The priority class of a kernel task.
+///
+/// Variants are listed from most to least urgent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Deferred interrupt handling, e.g. completing I/O requests.
+    BottomHalf,
+    /// Ordinary kernel work.
+    Normal,
+    /// Bulk work which can wait, e.g. writeback and readahead.
+    Background,
+}
+
+const NR_PRIORITIES: usize = 3;
+
+/// The task is neither queued nor being polled.
+const IDLE: u8 = 0;
+/// The task is in the ready queue.
+const QUEUED: u8 = 1;
+/// The task is being polled.
+const RUNNING: u8 = 2;
+/// The task was woken while being polled, so must be polled again.
+const NOTIFIED: u8 = 3;
+/// The task's future has completed.
+const DONE: u8 = 4;
+
+type TaskFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+struct Task<CPU: CpuOps> {
+    executor: &'static Executor<CPU>,
+    priority: Priority,
+    state: AtomicU8,
+    aborted: AtomicBool,
+    /// Only accessed by whoever moved `state` to `RUNNING`.
+    future: UnsafeCell<Option<TaskFuture>>,
+}
+
+// SAFETY: The future is `Send`, and `state` ensures only one CPU polls it at a
+// time.
+unsafe impl<CPU: CpuOps> Sync for Task<CPU> {}
+
+impl<CPU: CpuOps> Wake for Task<CPU> {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        let mut state = self.state.load(Ordering::Acquire);
+
+        loop {
+            let new = match state {
+                IDLE => QUEUED,
+                // The poller will requeue the task when it sees this.
+                RUNNING => NOTIFIED,
+                _ => return,
+            };
+
+            match self
+                .state
+                .compare_exchange(state, new, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => break,
+                Err(actual) => state = actual,
+            }
+        }
+
+        if state == IDLE {
+            self.executor.enqueue(self.clone());
+            self.executor.notify();
+        }
+    }
+}
+
+/// A queue of ready kernel tasks, polled in priority order.
+pub struct Executor<CPU: CpuOps> {
+    ready: SpinLockIrq<[VecDeque<Arc<Task<CPU>>>; NR_PRIORITIES], CPU>,
+    /// The number of spawned tasks which haven't completed.
+    live: AtomicUsize,
+    /// Called when a task becomes ready.
+    on_ready: Option<fn()>,
+}
+
+impl<CPU: CpuOps> Default for Executor<CPU> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<CPU: CpuOps> Executor<CPU> {
+    /// Creates an executor with no tasks.
+    #[track_caller]
+    pub const fn new() -> Self {
+        Self {
+            ready: SpinLockIrq::new([const { VecDeque::new() }; NR_PRIORITIES]),
+            live: AtomicUsize::new(0),
+            on_ready: None,
+        }
+    }
+
+    /// Creates an executor with no tasks, which calls `on_ready` whenever a
+    /// task is spawned or woken.
+    ///
+    /// `on_ready` is called without any of the executor's locks held, but
+    /// possibly from an interrupt handler. It isn't called when a task that
+    /// is woken while being polled is requeued, as the CPU polling it is
+    /// already running the executor.
+    #[track_caller]
+    pub const fn with_ready_hook(on_ready: fn()) -> Self {
+        Self {
+            ready: SpinLockIrq::new([const { VecDeque::new() }; NR_PRIORITIES]),
+            live: AtomicUsize::new(0),
+            on_ready: Some(on_ready),
+        }
+    }
+
+    /// Spawns `future` as a new task of the given priority.
+    ///
+    /// The task is queued immediately, and runs the next time the executor
+    /// is driven. Dropping the returned handle detaches the task.
+    pub fn spawn(
+        &'static self,
+        priority: Priority,
+        future: impl Future<Output = ()> + Send + 'static,
+    ) -> TaskHandle<CPU> {
+        let task = Arc::new(Task {
+            executor: self,
+            priority,
+            state: AtomicU8::new(QUEUED),
+            aborted: AtomicBool::new(false),
+            future: UnsafeCell::new(Some(Box::pin(future))),
+        });
+
+        self.live.fetch_add(1, Ordering::Relaxed);
+        self.enqueue(task.clone());
+        self.notify();
+
+        TaskHandle { task }
+    }
+
+    fn enqueue(&self, task: Arc<Task<CPU>>) {
+        self.ready.lock_save_irq()[task.priority as usize].push_back(task);
+    }
+
+    fn notify(&self) {
+        if let Some(on_ready) = self.on_ready {
+            on_ready();
+        }
+    }
+
+    fn pop(&self) -> Option<Arc<Task<CPU>>> {
+        self.ready
+            .lock_save_irq()
+            .iter_mut()
+            .find_map(|queue| queue.pop_front())
+    }
+
+    /// Returns `true` if any task is ready to be polled.
+    pub fn has_ready(&self) -> bool {
+        self.ready.lock_save_irq().iter().any(|q| !q.is_empty())
+    }
+
+    /// Returns the number of tasks which have been spawned but not completed.
+    pub fn live_tasks(&self) -> usize {
+        self.live.load(Ordering::Relaxed)
+    }
+
+    /// Polls ready tasks, highest priority first, until none are ready or
+    /// `budget` polls have been made. Returns the number of polls made.
+    ///
+    /// The highest priority ready task is chosen afresh for each poll, so
+    /// work woken by a poll can overtake lower priority work already queued.
+    pub fn run(&self, budget: usize) -> usize {
+        let mut polls = 0;
+
+        while polls < budget {
+            let Some(task) = self.pop() else {
+                break;
+            };
+
+            polls += 1;
+            self.poll_task(task);
+        }
+
+        polls
+    }
+
+    fn poll_task(&self, task: Arc<Task<CPU>>) {
+        // Only queued tasks are in the ready queue, and each is in it at most
+        // once, so this can't fail.
+        let _ = task
+            .state
+            .compare_exchange(QUEUED, RUNNING, Ordering::AcqRel, Ordering::Acquire);
+
+        let waker = Waker::from(task.clone());
+
+        // SAFETY: We moved the task to `RUNNING`, so nobody else will touch
+        // the future until we move it out again.
+        let slot = unsafe { &mut *task.future.get() };
+        let future = slot.as_mut().expect("polled a completed task");
+
+        if task.aborted.load(Ordering::Acquire)
+            || future
+                .as_mut()
+                .poll(&mut Context::from_waker(&waker))
+                .is_ready()
+        {
+            *slot = None;
+            task.state.store(DONE, Ordering::Release);
+            self.live.fetch_sub(1, Ordering::Relaxed);
+            return;
+        }
+
+        if task
+            .state
+            .compare_exchange(RUNNING, IDLE, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            // Woken while we were polling it.
+            task.state.store(QUEUED, Ordering::Release);
+            self.enqueue(task);
+        }
+    }
+}
+
+/// A handle to a spawned task.
+pub struct TaskHandle<CPU: CpuOps> {
+    task: Arc<Task<CPU>>,
+}
+
+impl<CPU: CpuOps> TaskHandle<CPU> {
+    /// Aborts the task.
+    ///
+    /// The task stops at its next `.await` point: if it is being polled now,
+    /// that poll finishes first. Its future is then dropped without being
+    /// polled again. Aborting a finished task does nothing.
+    pub fn abort(&self) {
+        self.task.aborted.store(true, Ordering::Release);
+        // Queue the task, so the executor gets round to dropping it.
+        self.task.wake_by_ref();
+    }
+
+    /// Returns `true` if the task has completed or been aborted and dropped.
+    pub fn is_finished(&self) -> bool {
+        self.task.state.load(Ordering::Acquire) == DONE
+    }
+}
+
+/// How [`block_on`] waits while its future is pending.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WaitMode {
+    /// Idle the CPU until an interrupt arrives. Suits futures woken from
+    /// interrupt handlers, once interrupts are up.
+    Interrupt,
+    /// Busy-wait. Suits futures woken from another CPU, or contexts where
+    /// interrupts are unavailable, such as early boot.
+    Spin,
+}
+
+struct BlockOnWaker {
+    woken: AtomicBool,
+}
+
+impl Wake for BlockOnWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.woken.store(true, Ordering::Release);
+    }
+}
+
+/// Drives `future` to completion on the current CPU, without an executor.
+///
+/// Between polls, the CPU waits as set by `mode` until the future's waker is
+/// called. This lets code which can't `.await`, such as early boot and panic
+/// handling, call into async subsystems. A future which is never woken
+/// blocks forever.
+pub fn block_on<CPU: CpuOps, F: Future>(future: F, mode: WaitMode) -> F::Output {
+    let mut future = pin!(future);
+    let state = Arc::new(BlockOnWaker {
+        woken: AtomicBool::new(false),
+    });
+    let waker = Waker::from(state.clone());
+    let mut cx = Context::from_waker(&waker);
+
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+
+        while !state.woken.swap(false, Ordering::Acquire) {
+            match mode {
+                WaitMode::Spin => spin_loop(),
+                WaitMode::Interrupt => {
+                    // Check the flag with interrupts masked, so that a wakeup
+                    // from an interrupt handler can't slip in between the
+                    // check and the wait.
+                    let flags = CPU::disable_interrupts();
+
+                    if !state.woken.load(Ordering::Acquire) {
+                        CPU::wait_for_interrupt();
+                    }
+
+                    CPU::restore_interrupt_state(flags);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::MockCpuOps;
+    use std::sync::Mutex;
+
+    fn executor() -> &'static Executor<MockCpuOps> {
+        Box::leak(Box::new(Executor::new()))
+    }
+
+    #[test]
+    fn tasks_run_in_priority_order() {
+        let exec = executor();
+        let order = Arc::new(Mutex::new(Vec::new()));
+
+        for (priority, name) in [
+            (Priority::Background, "writeback"),
+            (Priority::Normal, "normal"),
+            (Priority::BottomHalf, "irq"),
+            (Priority::Normal, "normal2"),
+        ] {
+            let order = order.clone();
+            exec.spawn(priority, async move { order.lock().unwrap().push(name) });
+        }
+
+        assert_eq!(exec.live_tasks(), 4);
+        assert_eq!(exec.run(usize::MAX), 4);
+        assert_eq!(
+            *order.lock().unwrap(),
+            ["irq", "normal", "normal2", "writeback"]
+        );
+        assert_eq!(exec.live_tasks(), 0);
+        assert!(!exec.has_ready());
+    }
+
+    #[test]
+    fn budget_limits_polls() {
+        let exec = executor();
+
+        for _ in 0..3 {
+            exec.spawn(Priority::Normal, async {});
+        }
+
+        assert_eq!(exec.run(2), 2);
+        assert!(exec.has_ready());
+        assert_eq!(exec.run(2), 1);
+    }
+
+    #[test]
+    fn woken_tasks_are_requeued() {
+        let exec = executor();
+        let waker = Arc::new(Mutex::new(None::<Waker>));
+
+        // Parks until woken through `waker`.
+        let slot = waker.clone();
+        let mut parked = true;
+        exec.spawn(
+            Priority::Normal,
+            core::future::poll_fn(move |cx| {
+                if parked {
+                    parked = false;
+                    *slot.lock().unwrap() = Some(cx.waker().clone());
+                    Poll::Pending
+                } else {
+                    Poll::Ready(())
+                }
+            }),
+        );
+
+        // Wakes itself while being polled, so must be polled again.
+        let mut yielded = false;
+        exec.spawn(
+            Priority::Normal,
+            core::future::poll_fn(move |cx| {
+                if yielded {
+                    Poll::Ready(())
+                } else {
+                    yielded = true;
+                    cx.waker().wake_by_ref();
+                    Poll::Pending
+                }
+            }),
+        );
+
+        assert_eq!(exec.run(usize::MAX), 3);
+        assert_eq!(exec.live_tasks(), 1);
+        assert!(!exec.has_ready());
+
+        let waker = waker.lock().unwrap().take().unwrap();
+        waker.wake_by_ref();
+        // Waking an already queued task doesn't queue it twice.
+        waker.wake();
+
+        assert_eq!(exec.run(usize::MAX), 1);
+        assert_eq!(exec.live_tasks(), 0);
+    }
+
+    #[test]
+    fn ready_hook_is_called() {
+        static READY: AtomicUsize = AtomicUsize::new(0);
+
+        let exec: &'static Executor<MockCpuOps> =
+            Box::leak(Box::new(Executor::with_ready_hook(|| {
+                READY.fetch_add(1, Ordering::Relaxed);
+            })));
+
+        let waker = Arc::new(Mutex::new(None::<Waker>));
+        let slot = waker.clone();
+        let mut polls = 0;
+
+        exec.spawn(
+            Priority::Normal,
+            core::future::poll_fn(move |cx| {
+                polls += 1;
+
+                match polls {
+                    1 => {
+                        *slot.lock().unwrap() = Some(cx.waker().clone());
+                        Poll::Pending
+                    }
+                    2 => {
+                        // Woken while being polled.
+                        cx.waker().wake_by_ref();
+                        Poll::Pending
+                    }
+                    _ => Poll::Ready(()),
+                }
+            }),
+        );
+        assert_eq!(READY.load(Ordering::Relaxed), 1);
+
+        assert_eq!(exec.run(usize::MAX), 1);
+        waker.lock().unwrap().take().unwrap().wake();
+        assert_eq!(READY.load(Ordering::Relaxed), 2);
+
+        // Requeueing a task woken during its own poll doesn't call the hook.
+        assert_eq!(exec.run(usize::MAX), 2);
+        assert_eq!(READY.load(Ordering::Relaxed), 2);
+        assert_eq!(exec.live_tasks(), 0);
+    }
+
+    #[test]
+    fn aborted_tasks_are_dropped() {
+        struct Cleanup(Arc<Mutex<bool>>);
+
+        impl Drop for Cleanup {
+            fn drop(&mut self) {
+                *self.0.lock().unwrap() = true;
+            }
+        }
+
+        let exec = executor();
+        let cleaned_up = Arc::new(Mutex::new(false));
+        let cleanup = Cleanup(cleaned_up.clone());
+
+        let handle = exec.spawn(Priority::Normal, async move {
+            let _cleanup = cleanup;
+            core::future::pending::<()>().await;
+        });
+
+        assert_eq!(exec.run(usize::MAX), 1);
+        assert!(!handle.is_finished());
+        assert!(!*cleaned_up.lock().unwrap());
+
+        handle.abort();
+        assert_eq!(exec.run(usize::MAX), 1);
+        assert!(handle.is_finished());
+        assert!(*cleaned_up.lock().unwrap());
+        assert_eq!(exec.live_tasks(), 0);
+
+        // Aborting a finished task does nothing.
+        handle.abort();
+        assert!(!exec.has_ready());
+    }
+    #[test]
+    fn block_on_waits_for_wakeups() {
+        assert_eq!(block_on::<MockCpuOps, _>(async { 3 }, WaitMode::Spin), 3);
+
+        // Woken from another thread, as by an interrupt or another CPU.
+        let (tx, rx) = std::sync::mpsc::channel::<Waker>();
+        let waker = std::thread::spawn(move || {
+            rx.recv().unwrap().wake();
+        });
+
+        let mut polls = 0;
+        let out = block_on::<MockCpuOps, _>(
+            core::future::poll_fn(|cx| {
+                polls += 1;
+
+                if polls == 1 {
+                    tx.send(cx.waker().clone()).unwrap();
+                    Poll::Pending
+                } else {
+                    Poll::Ready(polls)
+                }
+            }),
+            WaitMode::Interrupt,
+        );
+
+        assert_eq!(out, 2);
+        waker.join().unwrap();
+    }
+}
@@ -0,0 +1,653 @@
+//! Runtime lock-ordering validation, enabled with the `lockdep` feature.
+//!
+//! Every lock belongs to a *class*, identified by the source location that
+//! constructed it and whether it spins or sleeps. Each time a lock is taken
+//! while others are held, an edge from each held class to the new class is
+//! added to a dependency graph. If the new edge would close a cycle, two code
+//! paths take the same locks in opposite orders and can deadlock; this is
+//! reported even if the deadlock never actually happens.
+//!
+//! Spinlocks are tracked per CPU, since interrupts are disabled while one is
+//! held and so the holder can't migrate. Sleeping locks are held across
+//! `.await` points, so are tracked per task. Waiting on a sleeping lock while
+//! holding a spinlock is also reported.
+//!
+//! Nested locks of the same class, such as two task locks taken together, are
+//! not checked against each other. After the first report validation is
+//! switched off, as the graph may no longer be trustworthy.
+//!
+//! Nothing is tracked until [`enable`] is called. All state lives in fixed
+//! size tables so that validation never allocates, as the heap is itself
+//! protected by a spinlock.
+
+use crate::CpuOps;
+use core::cell::UnsafeCell;
+use core::hint::spin_loop;
+use core::panic::Location;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+use log::error;
+
+/// Identifies a class of locks by the location which constructed them.
+pub type LockClass = &'static Location<'static>;
+
+/// The maximum number of distinct lock classes which can be tracked.
+pub const MAX_CLASSES: usize = 512;
+
+/// The maximum number of locks a single CPU or task can hold at once.
+pub const MAX_HELD: usize = 16;
+
+/// The maximum number of CPUs and tasks which can hold locks at once.
+const MAX_CONTEXTS: usize = 128;
+
+/// The maximum number of classes shown in a reported dependency chain.
+pub const MAX_CHAIN: usize = 8;
+
+const WORDS: usize = MAX_CLASSES / 64;
+
+/// How a lock waits for its holder.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LockKind {
+    /// The lock spins with interrupts disabled, e.g. `SpinLockIrq`.
+    Spin,
+    /// The lock suspends the waiting task, e.g. `Mutex` and `Rwlock`.
+    Sleeping,
+}
+
+/// Hooks through which the validator interacts with the rest of the kernel.
+pub struct LockdepHooks {
+    /// Write the return addresses of the current call stack into `frames`,
+    /// returning the number of frames captured.
+    pub capture_backtrace: fn(&mut [usize]) -> usize,
+    /// Returns an identifier for the running task, if there is one. Sleeping
+    /// locks are only tracked when this returns `Some`.
+    pub current_task: fn() -> Option<usize>,
+}
+
+/// A chain of lock classes, in acquisition order.
+#[derive(Clone, Copy, Debug)]
+pub struct Chain {
+    classes: [Option<LockClass>; MAX_CHAIN],
+    /// The full length of the chain, which may exceed [`MAX_CHAIN`].
+    len: usize,
+}
+
+impl Chain {
+    /// The classes in the chain, truncated to [`MAX_CHAIN`].
+    pub fn classes(&self) -> impl Iterator<Item = LockClass> + '_ {
+        self.classes.iter().map_while(|c| *c)
+    }
+
+    /// The full length of the chain.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the chain holds no classes.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// A locking problem detected by the validator.
+#[derive(Clone, Copy, Debug)]
+pub enum Violation {
+    /// A lock was taken in the opposite order to one seen before.
+    Inversion {
+        /// The class already held.
+        held: LockClass,
+        /// The class being taken.
+        acquiring: LockClass,
+        /// A previously seen chain of dependencies leading from `acquiring`
+        /// to `held`.
+        chain: Chain,
+    },
+    /// A task waited on a sleeping lock while holding a spinlock.
+    SleepInAtomic {
+        /// The innermost spinlock held.
+        held: LockClass,
+        /// The sleeping lock being waited on.
+        waiting: LockClass,
+    },
+}
+
+/// Who holds a set of locks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Owner {
+    Cpu(usize),
+    Task(usize),
+}
+
+#[derive(Clone, Copy)]
+struct HeldLocks {
+    owner: Option<Owner>,
+    classes: [u16; MAX_HELD],
+    len: usize,
+}
+
+impl HeldLocks {
+    const EMPTY: Self = Self {
+        owner: None,
+        classes: [0; MAX_HELD],
+        len: 0,
+    };
+
+    fn iter(&self) -> impl Iterator<Item = u16> + '_ {
+        self.classes[..self.len.min(MAX_HELD)].iter().copied()
+    }
+}
+
+/// The dependency graph between lock classes, and the locks currently held.
+pub struct LockGraph {
+    classes: [Option<(LockClass, LockKind)>; MAX_CLASSES],
+    /// `deps[a]` has bit `b` set if class `b` has been taken while holding
+    /// class `a`.
+    deps: [[u64; WORDS]; MAX_CLASSES],
+    held: [HeldLocks; MAX_CONTEXTS],
+    /// Scratch space for the graph search, kept here rather than on the stack.
+    parent: [u16; MAX_CLASSES],
+    queue: [u16; MAX_CLASSES],
+}
+
+impl Default for LockGraph {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LockGraph {
+    /// Creates an empty graph.
+    pub const fn new() -> Self {
+        Self {
+            classes: [None; MAX_CLASSES],
+            deps: [[0; WORDS]; MAX_CLASSES],
+            held: [HeldLocks::EMPTY; MAX_CONTEXTS],
+            parent: [0; MAX_CLASSES],
+            queue: [0; MAX_CLASSES],
+        }
+    }
+
+    /// Returns the index of `class`, registering it if needed. Returns `None`
+    /// if the class table is full.
+    fn class_id(&mut self, class: LockClass, kind: LockKind) -> Option<u16> {
+        let start = (ptr::from_ref(class) as usize >> 3) % MAX_CLASSES;
+
+        for i in 0..MAX_CLASSES {
+            let idx = (start + i) % MAX_CLASSES;
+
+            match self.classes[idx] {
+                Some((c, k)) if ptr::eq(c, class) && k == kind => return Some(idx as u16),
+                Some(_) => continue,
+                None => {
+                    self.classes[idx] = Some((class, kind));
+                    return Some(idx as u16);
+                }
+            }
+        }
+
+        None
+    }
+
+    fn location(&self, id: u16) -> LockClass {
+        self.classes[id as usize].unwrap().0
+    }
+
+    fn has_dep(&self, from: u16, to: u16) -> bool {
+        self.deps[from as usize][to as usize / 64] & (1 << (to % 64)) != 0
+    }
+
+    fn add_dep(&mut self, from: u16, to: u16) {
+        self.deps[from as usize][to as usize / 64] |= 1 << (to % 64);
+    }
+
+    fn context(&self, owner: Owner) -> Option<&HeldLocks> {
+        self.held.iter().find(|h| h.owner == Some(owner))
+    }
+
+    fn context_mut(&mut self, owner: Owner) -> Option<&mut HeldLocks> {
+        let idx = self
+            .held
+            .iter()
+            .position(|h| h.owner == Some(owner))
+            .or_else(|| self.held.iter().position(|h| h.owner.is_none()))?;
+
+        let held = &mut self.held[idx];
+        held.owner = Some(owner);
+
+        Some(held)
+    }
+
+    /// Searches for a path of dependencies from `from` to `to`, returning it
+    /// if found.
+    fn find_path(&mut self, from: u16, to: u16) -> Option<Chain> {
+        let mut visited = [0u64; WORDS];
+        let (mut head, mut tail) = (0, 1);
+
+        self.queue[0] = from;
+        visited[from as usize / 64] |= 1 << (from % 64);
+
+        while head < tail {
+            let cur = self.queue[head];
+            head += 1;
+
+            if cur == to {
+                return Some(self.chain_to(from, to));
+            }
+
+            for (word_idx, &word) in self.deps[cur as usize].iter().enumerate() {
+                let mut unseen = word & !visited[word_idx];
+
+                while unseen != 0 {
+                    let next = (word_idx * 64 + unseen.trailing_zeros() as usize) as u16;
+                    unseen &= unseen - 1;
+
+                    visited[word_idx] |= 1 << (next % 64);
+                    self.parent[next as usize] = cur;
+                    self.queue[tail] = next;
+                    tail += 1;
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Rebuilds the path found by `find_path` from the parent links.
+    fn chain_to(&self, from: u16, to: u16) -> Chain {
+        let walk = || {
+            core::iter::successors(Some(to), move |&cur| {
+                (cur != from).then(|| self.parent[cur as usize])
+            })
+        };
+
+        let len = walk().count();
+        let mut chain = Chain {
+            classes: [None; MAX_CHAIN],
+            len,
+        };
+
+        // The walk runs backwards from `to`, so fill the chain from the end.
+        for (pos, id) in (0..len).rev().zip(walk()) {
+            if pos < MAX_CHAIN {
+                chain.classes[pos] = Some(self.location(id));
+            }
+        }
+
+        chain
+    }
+
+    /// Records that `class` is about to be taken while the locks held by each
+    /// of `holders` are held, checking the new dependencies for cycles.
+    fn check_order(&mut self, id: u16, holders: &[Owner]) -> Result<(), Violation> {
+        for &owner in holders {
+            let Some(held) = self.context(owner).copied() else {
+                continue;
+            };
+
+            for held_id in held.iter() {
+                if held_id == id || self.has_dep(held_id, id) {
+                    continue;
+                }
+
+                if let Some(chain) = self.find_path(id, held_id) {
+                    return Err(Violation::Inversion {
+                        held: self.location(held_id),
+                        acquiring: self.location(id),
+                        chain,
+                    });
+                }
+
+                self.add_dep(held_id, id);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Records that `owner` has taken a lock of `class`, having held the locks
+    /// of each of `holders`.
+    fn acquire(
+        &mut self,
+        class: LockClass,
+        kind: LockKind,
+        owner: Owner,
+        holders: &[Owner],
+    ) -> Result<(), Violation> {
+        let Some(id) = self.class_id(class, kind) else {
+            return Ok(());
+        };
+
+        let result = self.check_order(id, holders);
+
+        if let Some(held) = self.context_mut(owner) {
+            if held.len < MAX_HELD {
+                held.classes[held.len] = id;
+            }
+
+            // Count locks beyond the limit so releases still balance.
+            held.len += 1;
+        }
+
+        result
+    }
+
+    /// Records that `owner` has released a lock of `class`.
+    fn release(&mut self, class: LockClass, kind: LockKind, owner: Owner) {
+        let Some(id) = self.class_id(class, kind) else {
+            return;
+        };
+
+        let Some(held) = self.held.iter_mut().find(|h| h.owner == Some(owner)) else {
+            return;
+        };
+
+        if held.len > MAX_HELD {
+            held.len -= 1;
+        } else if let Some(pos) = held.classes[..held.len].iter().rposition(|&c| c == id) {
+            held.classes.copy_within(pos + 1..held.len, pos);
+            held.len -= 1;
+        }
+
+        if held.len == 0 {
+            held.owner = None;
+        }
+    }
+
+    /// Records that `task` is about to wait on a sleeping lock of `class`,
+    /// having held the spinlocks of `cpu`.
+    fn wait(&mut self, class: LockClass, cpu: Owner, task: Owner) -> Result<(), Violation> {
+        let Some(id) = self.class_id(class, LockKind::Sleeping) else {
+            return Ok(());
+        };
+
+        if let Some(held) = self.context(cpu).and_then(|h| h.iter().last()) {
+            return Err(Violation::SleepInAtomic {
+                held: self.location(held),
+                waiting: class,
+            });
+        }
+
+        self.check_order(id, &[task])
+    }
+}
+
+/// The global graph, protected by a bare spinlock so that its own locking
+/// isn't validated.
+struct Lockdep {
+    lock: AtomicBool,
+    graph: UnsafeCell<LockGraph>,
+}
+
+// SAFETY: `graph` is only accessed with `lock` held.
+unsafe impl Sync for Lockdep {}
+
+static LOCKDEP: Lockdep = Lockdep {
+    lock: AtomicBool::new(false),
+    graph: UnsafeCell::new(LockGraph::new()),
+};
+
+static HOOKS: AtomicPtr<LockdepHooks> = AtomicPtr::new(ptr::null_mut());
+
+/// Whether validation is running. Cleared after the first report.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Starts validating lock usage, using `hooks` to capture backtraces and
+/// identify tasks.
+pub fn enable(hooks: &'static LockdepHooks) {
+    HOOKS.store(ptr::from_ref(hooks).cast_mut(), Ordering::Release);
+    ACTIVE.store(true, Ordering::Release);
+}
+
+/// Returns `true` if validation is running.
+pub fn is_active() -> bool {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+fn hooks() -> &'static LockdepHooks {
+    // SAFETY: `HOOKS` is set before `ACTIVE`, and only ever to a `'static`
+    // reference.
+    unsafe { &*HOOKS.load(Ordering::Acquire) }
+}
+
+/// Runs `f` on the global graph with interrupts disabled, reporting any
+/// violation it returns.
+fn with_graph<CPU: CpuOps>(f: impl FnOnce(&mut LockGraph, Owner) -> Result<(), Violation>) {
+    if !is_active() {
+        return;
+    }
+
+    let flags = CPU::disable_interrupts();
+
+    while LOCKDEP
+        .lock
+        .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        spin_loop();
+    }
+
+    // SAFETY: We hold `LOCKDEP.lock`.
+    let result = f(unsafe { &mut *LOCKDEP.graph.get() }, Owner::Cpu(CPU::id()));
+
+    LOCKDEP.lock.store(false, Ordering::Release);
+    CPU::restore_interrupt_state(flags);
+
+    if let Err(violation) = result {
+        report(&violation);
+    }
+}
+
+fn current_task() -> Option<Owner> {
+    (hooks().current_task)().map(Owner::Task)
+}
+
+/// Records that a lock of `class` has been taken on this CPU, or by the
+/// running task if it is a sleeping lock.
+pub fn acquire<CPU: CpuOps>(class: LockClass, kind: LockKind) {
+    if !is_active() {
+        return;
+    }
+
+    let task = current_task();
+
+    with_graph::<CPU>(|graph, cpu| match (kind, task) {
+        (LockKind::Spin, Some(task)) => graph.acquire(class, kind, cpu, &[cpu, task]),
+        (LockKind::Spin, None) => graph.acquire(class, kind, cpu, &[cpu]),
+        (LockKind::Sleeping, Some(task)) => graph.acquire(class, kind, task, &[task]),
+        (LockKind::Sleeping, None) => Ok(()),
+    });
+}
+
+/// Records that a lock of `class` has been released.
+pub fn release<CPU: CpuOps>(class: LockClass, kind: LockKind) {
+    if !is_active() {
+        return;
+    }
+
+    let task = current_task();
+
+    with_graph::<CPU>(|graph, cpu| {
+        match (kind, task) {
+            (LockKind::Spin, _) => graph.release(class, kind, cpu),
+            (LockKind::Sleeping, Some(task)) => graph.release(class, kind, task),
+            (LockKind::Sleeping, None) => (),
+        }
+
+        Ok(())
+    });
+}
+
+/// Records that the running task is about to sleep waiting for a lock of
+/// `class`.
+pub fn wait<CPU: CpuOps>(class: LockClass) {
+    if !is_active() {
+        return;
+    }
+
+    let Some(task) = current_task() else {
+        return;
+    };
+
+    with_graph::<CPU>(|graph, cpu| graph.wait(class, cpu, task));
+}
+
+fn report(violation: &Violation) {
+    // Stop validating first: logging takes locks of its own.
+    if !ACTIVE.swap(false, Ordering::AcqRel) {
+        return;
+    }
+
+    match violation {
+        Violation::Inversion {
+            held,
+            acquiring,
+            chain,
+        } => {
+            error!("lockdep: possible circular locking dependency detected");
+            error!("lockdep: taking lock {acquiring} while holding lock {held}");
+            error!("lockdep: but the reverse order has been seen before:");
+
+            for class in chain.classes() {
+                error!("lockdep:   {class}");
+            }
+
+            if chain.len() > MAX_CHAIN {
+                error!("lockdep:   ... {} more", chain.len() - MAX_CHAIN);
+            }
+        }
+        Violation::SleepInAtomic { held, waiting } => {
+            error!("lockdep: sleeping on lock {waiting} while holding spinlock {held}");
+        }
+    }
+
+    let mut frames = [0; 16];
+    let len = (hooks().capture_backtrace)(&mut frames);
+
+    error!("lockdep: backtrace:");
+
+    for frame in &frames[..len] {
+        error!("lockdep:   {frame:#018x}");
+    }
+
+    error!("lockdep: lock validation is now disabled");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CPU0: Owner = Owner::Cpu(0);
+    const TASK: Owner = Owner::Task(1);
+
+    #[track_caller]
+    fn class() -> LockClass {
+        Location::caller()
+    }
+
+    fn graph() -> Box<LockGraph> {
+        Box::new(LockGraph::new())
+    }
+
+    #[test]
+    fn consistent_order_is_accepted() {
+        let mut g = graph();
+        let (a, b) = (class(), class());
+
+        for _ in 0..2 {
+            g.acquire(a, LockKind::Spin, CPU0, &[CPU0]).unwrap();
+            g.acquire(b, LockKind::Spin, CPU0, &[CPU0]).unwrap();
+            g.release(b, LockKind::Spin, CPU0);
+            g.release(a, LockKind::Spin, CPU0);
+        }
+
+        assert!(g.context(CPU0).is_none());
+    }
+
+    #[test]
+    fn inverted_order_is_reported() {
+        let mut g = graph();
+        let (a, b, c) = (class(), class(), class());
+
+        // a -> b -> c
+        g.acquire(a, LockKind::Spin, CPU0, &[CPU0]).unwrap();
+        g.acquire(b, LockKind::Spin, CPU0, &[CPU0]).unwrap();
+        g.release(a, LockKind::Spin, CPU0);
+        g.acquire(c, LockKind::Spin, CPU0, &[CPU0]).unwrap();
+        g.release(c, LockKind::Spin, CPU0);
+        g.release(b, LockKind::Spin, CPU0);
+
+        // c -> a closes the cycle.
+        g.acquire(c, LockKind::Spin, CPU0, &[CPU0]).unwrap();
+        let Err(Violation::Inversion {
+            held,
+            acquiring,
+            chain,
+        }) = g.acquire(a, LockKind::Spin, CPU0, &[CPU0])
+        else {
+            panic!("inversion not detected");
+        };
+
+        assert!(ptr::eq(held, c));
+        assert!(ptr::eq(acquiring, a));
+
+        let chain: Vec<_> = chain.classes().collect();
+        assert_eq!(chain, [a, b, c]);
+    }
+
+    #[test]
+    fn same_class_nesting_is_ignored() {
+        let mut g = graph();
+        let a = class();
+
+        g.acquire(a, LockKind::Spin, CPU0, &[CPU0]).unwrap();
+        g.acquire(a, LockKind::Spin, CPU0, &[CPU0]).unwrap();
+        g.release(a, LockKind::Spin, CPU0);
+        g.release(a, LockKind::Spin, CPU0);
+
+        assert!(g.context(CPU0).is_none());
+    }
+
+    #[test]
+    fn sleeping_locks_follow_the_task() {
+        let mut g = graph();
+        let (m1, m2, s) = (class(), class(), class());
+
+        // The task takes `m1` on one CPU, then `m2` and `s` on another.
+        g.acquire(m1, LockKind::Sleeping, TASK, &[TASK]).unwrap();
+        g.acquire(m2, LockKind::Sleeping, TASK, &[TASK]).unwrap();
+        g.acquire(s, LockKind::Spin, Owner::Cpu(1), &[Owner::Cpu(1), TASK])
+            .unwrap();
+        g.release(s, LockKind::Spin, Owner::Cpu(1));
+        g.release(m1, LockKind::Sleeping, TASK);
+
+        let id = |g: &mut LockGraph, class, kind| g.class_id(class, kind).unwrap();
+        let (m1_id, m2_id, s_id) = (
+            id(&mut g, m1, LockKind::Sleeping),
+            id(&mut g, m2, LockKind::Sleeping),
+            id(&mut g, s, LockKind::Spin),
+        );
+        assert!(g.has_dep(m1_id, s_id) && g.has_dep(m2_id, s_id));
+
+        // Still holding `m2`, taking `m1` inverts the order.
+        assert!(matches!(
+            g.acquire(m1, LockKind::Sleeping, TASK, &[TASK]),
+            Err(Violation::Inversion { .. })
+        ));
+    }
+
+    #[test]
+    fn sleeping_with_spinlock_held_is_reported() {
+        let mut g = graph();
+        let (s, m) = (class(), class());
+
+        assert!(g.wait(m, CPU0, TASK).is_ok());
+
+        g.acquire(s, LockKind::Spin, CPU0, &[CPU0]).unwrap();
+        let Err(Violation::SleepInAtomic { held, waiting }) = g.wait(m, CPU0, TASK) else {
+            panic!("sleep in atomic context not detected");
+        };
+
+        assert!(ptr::eq(held, s));
+        assert!(ptr::eq(waiting, m));
+    }
+}
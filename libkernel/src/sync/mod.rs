@@ -3,11 +3,23 @@
 //! All primitives are generic over [`CpuOps`](crate::CpuOps) so they can
 //! disable/restore interrupts on the local core.
 
+pub mod cancel;
 pub mod condvar;
+pub mod executor;
+#[cfg(feature = "lockdep")]
+pub mod lockdep;
 pub mod mpsc;
 pub mod mutex;
+pub mod once_cell;
 pub mod once_lock;
 pub mod per_cpu;
+pub mod rcu;
 pub mod rwlock;
+pub mod scope;
+pub mod semaphore;
+pub mod seqlock;
 pub mod spinlock;
+pub mod timeout;
+pub mod timer;
 pub mod waker_set;
+pub mod watch;
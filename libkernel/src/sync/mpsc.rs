@@ -1,26 +1,52 @@
 //! An asynchronous, multi-producer, single-consumer (MPSC) channel.
 //!
 //! This module provides a queue for sending values between asynchronous tasks
-
-//! within the kernel.
-use super::condvar::{CondVar, WakeupType};
+//! within the kernel. It is the standard way for a driver to hand work to a
+//! kernel task: a [`bounded`] channel applies backpressure to producers, and
+//! [`Sender::try_send`] never waits, so may be used from interrupt handlers.
+use super::spinlock::SpinLockIrq;
+use super::waker_set::{WakerSet, wait_until};
 use crate::CpuOps;
 use alloc::collections::VecDeque;
+use alloc::sync::Arc;
 
 struct MpscState<T: Send> {
     data: VecDeque<T>,
+    capacity: usize,
     senders: usize,
-    recv_gone: bool,
+    /// Set once the receiver has been closed or dropped.
+    closed: bool,
+    /// The receiver, waiting for a message.
+    rx_waiters: WakerSet,
+    /// Senders waiting for space in the queue.
+    tx_waiters: WakerSet,
+}
+
+/// The error returned by [`Sender::send`] when the channel has been closed.
+///
+/// The unsent message is handed back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+/// The error returned by [`Sender::try_send`].
+///
+/// The unsent message is handed back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrySendError<T> {
+    /// The channel is at capacity.
+    Full(T),
+    /// The channel has been closed.
+    Closed(T),
 }
 
 /// The receiving half of the MPSC channel.
 ///
 /// There can only be one `Reciever` for a given channel.
 ///
-/// If the `Reciever` is dropped, the channel is closed. Any subsequent messages
-/// sent by a `Sender` will be dropped.
+/// If the `Reciever` is dropped, the channel is closed. Any subsequent sends
+/// fail, handing the message back to the sender.
 pub struct Reciever<T: Send, C: CpuOps> {
-    inner: CondVar<MpscState<T>, C>,
+    inner: Arc<SpinLockIrq<MpscState<T>, C>>,
 }
 
 enum RxResult<T> {
@@ -33,40 +59,63 @@ impl<T: Send, C: CpuOps> Reciever<T, C> {
     ///
     /// This function returns a `Future` that resolves to:
     /// - `Some(T)`: If a message was successfully received from the channel.
-    /// - `None`: If all `Sender` instances have been dropped, indicating that
-    ///   no more messages will ever be sent. The channel is now closed.
+    /// - `None`: If all `Sender` instances have been dropped, or the channel
+    ///   has been closed, and no messages remain.
     pub async fn recv(&self) -> Option<T> {
-        let result = self
-            .inner
-            .wait_until(|state| {
+        let result = wait_until(
+            self.inner.clone(),
+            |state| &mut state.rx_waiters,
+            |state| {
                 if let Some(data) = state.data.pop_front() {
+                    // Space has been made for a waiting sender.
+                    state.tx_waiters.wake_one();
                     Some(RxResult::Data(data))
-                } else if state.senders == 0 {
+                } else if state.senders == 0 || state.closed {
                     Some(RxResult::SenderGone)
                 } else {
                     None
                 }
-            })
-            .await;
+            },
+        )
+        .await;
 
         match result {
             RxResult::Data(d) => Some(d),
             RxResult::SenderGone => None,
         }
     }
+
+    /// Receives a message if one is queued, without waiting.
+    pub fn try_recv(&self) -> Option<T> {
+        let mut state = self.inner.lock_save_irq();
+        let data = state.data.pop_front()?;
+
+        state.tx_waiters.wake_one();
+
+        Some(data)
+    }
+
+    /// Closes the channel.
+    ///
+    /// Further sends fail, but messages already queued can still be received.
+    pub fn close(&self) {
+        let mut state = self.inner.lock_save_irq();
+
+        state.closed = true;
+        state.tx_waiters.wake_all();
+    }
 }
 
 impl<T: Send, C: CpuOps> Drop for Reciever<T, C> {
     fn drop(&mut self) {
-        self.inner.update(|state| {
-            // Since there can only be once reciever and we are now dropping
-            // it, drain the queue, and set a flag such that any more sends
-            // result in the value being dropped.
-            core::mem::take(&mut state.data);
-            state.recv_gone = true;
-
-            WakeupType::None
-        });
+        let mut state = self.inner.lock_save_irq();
+
+        // Since there can only be once reciever and we are now dropping it,
+        // drain the queue, and close the channel such that any more sends
+        // fail.
+        core::mem::take(&mut state.data);
+        state.closed = true;
+        state.tx_waiters.wake_all();
     }
 }
 
@@ -76,41 +125,71 @@ impl<T: Send, C: CpuOps> Drop for Reciever<T, C> {
 /// to the single `Reciever`.
 ///
 /// When the last `Sender` is dropped, the channel is closed. This will cause
-/// the `Reciever::recv` future to resolve to `None`.
+/// the `Reciever::recv` future to resolve to `None` once the queue is empty.
 pub struct Sender<T: Send, C: CpuOps> {
-    inner: CondVar<MpscState<T>, C>,
+    inner: Arc<SpinLockIrq<MpscState<T>, C>>,
 }
 
 impl<T: Send, C: CpuOps> Sender<T, C> {
     /// Sends a message into the channel.
     ///
-    /// This method enqueues the given object `obj` for the `Reciever` to
-    /// consume. After enqueuing the message, it notifies one waiting `Reciever`
-    /// task, if one exists.
+    /// If the channel is at capacity, this waits until the `Reciever` makes
+    /// space. Returns the message in a [`SendError`] if the channel is closed.
+    pub async fn send(&self, obj: T) -> Result<(), SendError<T>> {
+        let mut obj = Some(obj);
+
+        wait_until(
+            self.inner.clone(),
+            |state| &mut state.tx_waiters,
+            |state| {
+                if state.closed {
+                    return Some(Err(SendError(obj.take().unwrap())));
+                }
+
+                if state.data.len() >= state.capacity {
+                    return None;
+                }
+
+                state.data.push_back(obj.take().unwrap());
+                state.rx_waiters.wake_one();
+
+                Some(Ok(()))
+            },
+        )
+        .await
+    }
+
+    /// Attempts to send a message without waiting.
     ///
-    /// This operation is non-blocking from an async perspective, though it will
-    /// acquire a spinlock.
-    pub fn send(&self, obj: T) {
-        self.inner.update(|state| {
-            if state.recv_gone {
-                // Receiver has been dropped, so drop the message.
-                return WakeupType::None;
-            }
-
-            state.data.push_back(obj);
-
-            WakeupType::One
-        });
+    /// This only takes a spinlock with interrupts disabled, so is safe to call
+    /// from an interrupt handler.
+    pub fn try_send(&self, obj: T) -> Result<(), TrySendError<T>> {
+        let mut state = self.inner.lock_save_irq();
+
+        if state.closed {
+            return Err(TrySendError::Closed(obj));
+        }
+
+        if state.data.len() >= state.capacity {
+            return Err(TrySendError::Full(obj));
+        }
+
+        state.data.push_back(obj);
+        state.rx_waiters.wake_one();
+
+        Ok(())
+    }
+
+    /// Returns `true` if the `Reciever` has closed the channel or been
+    /// dropped.
+    pub fn is_closed(&self) -> bool {
+        self.inner.lock_save_irq().closed
     }
 }
 
 impl<T: Send, C: CpuOps> Clone for Sender<T, C> {
     fn clone(&self) -> Self {
-        self.inner.update(|state| {
-            state.senders += 1;
-
-            WakeupType::None
-        });
+        self.inner.lock_save_irq().senders += 1;
 
         Self {
             inner: self.inner.clone(),
@@ -120,40 +199,166 @@ impl<T: Send, C: CpuOps> Clone for Sender<T, C> {
 
 impl<T: Send, C: CpuOps> Drop for Sender<T, C> {
     fn drop(&mut self) {
-        self.inner.update(|state| {
-            state.senders -= 1;
-
-            if state.senders == 0 {
-                // Wake the receiver to let it know the channel is now closed. We
-                // use wake_all as a safeguard, though only one task should be
-                // waiting.
-                WakeupType::All
-            } else {
-                WakeupType::None
-            }
-        });
+        let mut state = self.inner.lock_save_irq();
+
+        state.senders -= 1;
+
+        if state.senders == 0 {
+            // Wake the receiver to let it know the channel is now closed. We
+            // use wake_all as a safeguard, though only one task should be
+            // waiting.
+            state.rx_waiters.wake_all();
+        }
     }
 }
 
-/// Creates a new asynchronous, multi-producer, single-consumer channel.
-///
-/// Returns a tuple containing the `Sender` and `Reciever` halves. The `Sender`
-/// can be cloned to create multiple producers, while the `Reciever` is the
-/// single consumer.
-pub fn channel<T: Send, C: CpuOps>() -> (Sender<T, C>, Reciever<T, C>) {
+#[track_caller]
+fn with_capacity<T: Send, C: CpuOps>(capacity: usize) -> (Sender<T, C>, Reciever<T, C>) {
     let state = MpscState {
         data: VecDeque::new(),
+        capacity,
         senders: 1,
-        recv_gone: false,
+        closed: false,
+        rx_waiters: WakerSet::new(),
+        tx_waiters: WakerSet::new(),
     };
 
-    let waitq = CondVar::new(state);
+    let inner = Arc::new(SpinLockIrq::new(state));
 
     let tx = Sender {
-        inner: waitq.clone(),
+        inner: inner.clone(),
     };
 
-    let rx = Reciever { inner: waitq };
+    let rx = Reciever { inner };
 
     (tx, rx)
 }
+
+/// Creates a new asynchronous, multi-producer, single-consumer channel.
+///
+/// Returns a tuple containing the `Sender` and `Reciever` halves. The `Sender`
+/// can be cloned to create multiple producers, while the `Reciever` is the
+/// single consumer. The channel is unbounded, so sends never wait.
+#[track_caller]
+pub fn channel<T: Send, C: CpuOps>() -> (Sender<T, C>, Reciever<T, C>) {
+    with_capacity(usize::MAX)
+}
+
+/// Creates a new channel holding at most `capacity` queued messages.
+///
+/// Once full, [`Sender::send`] waits for the `Reciever` to make space and
+/// [`Sender::try_send`] fails with [`TrySendError::Full`].
+#[track_caller]
+pub fn bounded<T: Send, C: CpuOps>(capacity: usize) -> (Sender<T, C>, Reciever<T, C>) {
+    assert!(capacity > 0, "bounded channel needs a non-zero capacity");
+
+    with_capacity(capacity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::MockCpuOps;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn messages_arrive_in_order() {
+        let (tx, rx) = channel::<_, MockCpuOps>();
+        let tx2 = tx.clone();
+
+        tx.send(1).await.unwrap();
+        tx2.try_send(2).unwrap();
+        assert_eq!(rx.recv().await, Some(1));
+        assert_eq!(rx.try_recv(), Some(2));
+        assert_eq!(rx.try_recv(), None);
+
+        drop(tx);
+        drop(tx2);
+        assert_eq!(rx.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn send_waits_for_space() {
+        let (tx, rx) = bounded::<_, MockCpuOps>(2);
+
+        tx.try_send(1).unwrap();
+        tx.try_send(2).unwrap();
+        assert_eq!(tx.try_send(3), Err(TrySendError::Full(3)));
+
+        let handle = tokio::spawn(async move {
+            tx.send(3).await.unwrap();
+            tx.send(4).await.unwrap();
+        });
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(!handle.is_finished(), "send should wait for space");
+
+        for expected in 1..=4 {
+            assert_eq!(rx.recv().await, Some(expected));
+        }
+
+        handle.await.unwrap();
+        assert_eq!(rx.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn close_fails_senders_but_keeps_queued_messages() {
+        let (tx, rx) = bounded::<_, MockCpuOps>(1);
+
+        tx.try_send(1).unwrap();
+
+        let blocked = {
+            let tx = tx.clone();
+            tokio::spawn(async move { tx.send(2).await })
+        };
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        rx.close();
+
+        assert_eq!(blocked.await.unwrap(), Err(SendError(2)));
+        assert!(tx.is_closed());
+        assert_eq!(tx.try_send(3), Err(TrySendError::Closed(3)));
+
+        assert_eq!(rx.recv().await, Some(1));
+        assert_eq!(rx.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn dropped_receiver_returns_messages() {
+        let (tx, rx) = channel::<_, MockCpuOps>();
+
+        drop(rx);
+        assert_eq!(tx.send(1).await, Err(SendError(1)));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn many_producers() {
+        let (tx, rx) = bounded::<usize, MockCpuOps>(4);
+
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let tx = tx.clone();
+
+                tokio::spawn(async move {
+                    for j in 0..100 {
+                        tx.send(i * 100 + j).await.unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        drop(tx);
+
+        let mut received = Vec::new();
+        while let Some(v) = rx.recv().await {
+            received.push(v);
+        }
+
+        for handle in handles {
+            handle.await.unwrap();
+        }
+
+        received.sort_unstable();
+        assert_eq!(received, (0..800).collect::<Vec<_>>());
+    }
+}
@@ -10,6 +10,10 @@ use core::task::{Context, Poll, Waker};
 use crate::CpuOps;
 
 use super::spinlock::SpinLockIrq;
+use super::timeout::{self, Timeout};
+
+#[cfg(feature = "lockdep")]
+use super::lockdep::{self, LockClass, LockKind};
 
 struct MutexState {
     is_locked: bool,
@@ -23,6 +27,8 @@ struct MutexState {
 /// dropped, the lock is released.
 pub struct Mutex<T: ?Sized, CPU: CpuOps> {
     state: SpinLockIrq<MutexState, CPU>,
+    #[cfg(feature = "lockdep")]
+    class: LockClass,
     data: UnsafeCell<T>,
 }
 
@@ -38,16 +44,21 @@ pub struct AsyncMutexGuard<'a, T: ?Sized, CPU: CpuOps> {
 /// A future that resolves to an `AsyncMutexGuard` when the lock is acquired.
 pub struct MutexGuardFuture<'a, T: ?Sized, CPU: CpuOps> {
     mutex: &'a Mutex<T, CPU>,
+    /// The waker this future last queued, if it is waiting.
+    waker: Option<Waker>,
 }
 
 impl<T, CPU: CpuOps> Mutex<T, CPU> {
     /// Creates a new asynchronous mutex in an unlocked state.
+    #[track_caller]
     pub const fn new(data: T) -> Self {
         Self {
             state: SpinLockIrq::new(MutexState {
                 is_locked: false,
                 waiters: VecDeque::new(),
             }),
+            #[cfg(feature = "lockdep")]
+            class: core::panic::Location::caller(),
             data: UnsafeCell::new(data),
         }
     }
@@ -68,7 +79,40 @@ impl<T: ?Sized, CPU: CpuOps> Mutex<T, CPU> {
     /// be `.await`ed to acquire the lock. The lock is released when the
     /// returned `AsyncMutexGuard` is dropped.
     pub fn lock(&self) -> MutexGuardFuture<'_, T, CPU> {
-        MutexGuardFuture { mutex: self }
+        MutexGuardFuture {
+            mutex: self,
+            waker: None,
+        }
+    }
+
+    /// Acquires the mutex lock, giving up if `timer` completes first.
+    ///
+    /// `timer` is typically a sleep on the system timer. Returns [`Timeout`]
+    /// if the lock could not be acquired before it fired.
+    pub async fn lock_before<D: Future>(
+        &self,
+        timer: D,
+    ) -> Result<AsyncMutexGuard<'_, T, CPU>, Timeout> {
+        timeout::before(self.lock(), timer).await
+    }
+
+    /// Attempts to acquire the mutex lock without waiting.
+    ///
+    /// Returns `None` if the lock is currently held.
+    pub fn try_lock(&self) -> Option<AsyncMutexGuard<'_, T, CPU>> {
+        let mut state = self.state.lock_save_irq();
+
+        if state.is_locked {
+            return None;
+        }
+
+        state.is_locked = true;
+        drop(state);
+
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire::<CPU>(self.class, LockKind::Sleeping);
+
+        Some(AsyncMutexGuard { mutex: self })
     }
 
     /// Returns a mutable reference to the underlying data.
@@ -88,21 +132,67 @@ impl<T: ?Sized, CPU: CpuOps> Mutex<T, CPU> {
 impl<'a, T: ?Sized, CPU: CpuOps> Future for MutexGuardFuture<'a, T, CPU> {
     type Output = AsyncMutexGuard<'a, T, CPU>;
 
-    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let mut state = self.mutex.state.lock_save_irq();
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mutex = self.mutex;
+        let mut state = mutex.state.lock_save_irq();
 
         if !state.is_locked {
             state.is_locked = true;
-            Poll::Ready(AsyncMutexGuard { mutex: self.mutex })
+
+            if let Some(waker) = self.waker.take() {
+                state.waiters.retain(|w| !w.will_wake(&waker));
+            }
+
+            drop(state);
+
+            #[cfg(feature = "lockdep")]
+            lockdep::acquire::<CPU>(mutex.class, LockKind::Sleeping);
+
+            Poll::Ready(AsyncMutexGuard { mutex })
         } else {
+            if let Some(old) = self.waker.take()
+                && !old.will_wake(cx.waker())
+            {
+                state.waiters.retain(|w| !w.will_wake(&old));
+            }
+
             if state.waiters.iter().all(|w| !w.will_wake(cx.waker())) {
                 state.waiters.push_back(cx.waker().clone());
             }
+
+            self.waker = Some(cx.waker().clone());
+            drop(state);
+
+            #[cfg(feature = "lockdep")]
+            lockdep::wait::<CPU>(mutex.class);
+
             Poll::Pending
         }
     }
 }
 
+impl<T: ?Sized, CPU: CpuOps> Drop for MutexGuardFuture<'_, T, CPU> {
+    fn drop(&mut self) {
+        let Some(waker) = self.waker.take() else {
+            return;
+        };
+
+        let mut state = self.mutex.state.lock_save_irq();
+        let len = state.waiters.len();
+
+        state.waiters.retain(|w| !w.will_wake(&waker));
+
+        // If our waker is gone we were woken to take the lock, so hand the
+        // wakeup on to the next waiter.
+        if state.waiters.len() == len
+            && !state.is_locked
+            && let Some(next) = state.waiters.pop_front()
+        {
+            next.wake();
+        }
+    }
+}
+
 impl<T: ?Sized, CPU: CpuOps> Drop for AsyncMutexGuard<'_, T, CPU> {
     fn drop(&mut self) {
         let mut state = self.mutex.state.lock_save_irq();
@@ -112,6 +202,10 @@ impl<T: ?Sized, CPU: CpuOps> Drop for AsyncMutexGuard<'_, T, CPU> {
         }
 
         state.is_locked = false;
+        drop(state);
+
+        #[cfg(feature = "lockdep")]
+        lockdep::release::<CPU>(self.mutex.class, LockKind::Sleeping);
     }
 }
 
@@ -134,46 +228,122 @@ impl<T: ?Sized, CPU: CpuOps> DerefMut for AsyncMutexGuard<'_, T, CPU> {
 unsafe impl<T: ?Sized + Send, CPU: CpuOps> Send for Mutex<T, CPU> {}
 unsafe impl<T: ?Sized + Send, CPU: CpuOps> Sync for Mutex<T, CPU> {}
 
-impl<CPU: CpuOps> Mutex<(), CPU> {
-    /// Acquires the mutex lock without caring about the data.
-    pub(crate) fn acquire(&self) -> MutexAcquireFuture<'_, CPU> {
-        MutexAcquireFuture { mutex: self }
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::MockCpuOps;
+    use std::future::pending;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::task::Wake;
+    use std::time::Duration;
 
-    /// Releases the mutex lock without caring about the data.
-    ///
-    /// # Safety
-    /// The caller must ensure that they have previously called [`Self::acquire()`].
-    pub(crate) unsafe fn release(&self) {
-        let mut state = self.state.lock_save_irq();
+    #[derive(Default)]
+    struct Flag(AtomicBool);
 
-        if let Some(next_waker) = state.waiters.pop_front() {
-            next_waker.wake();
+    impl Wake for Flag {
+        fn wake(self: Arc<Self>) {
+            self.0.store(true, Ordering::Relaxed);
         }
+    }
 
-        state.is_locked = false;
+    #[test]
+    fn try_lock_fails_while_held() {
+        let mutex = Mutex::<_, MockCpuOps>::new(0);
+
+        let mut guard = mutex.try_lock().expect("uncontended lock");
+        *guard += 1;
+
+        assert!(mutex.try_lock().is_none());
+
+        drop(guard);
+
+        assert_eq!(*mutex.try_lock().expect("lock was released"), 1);
     }
-}
 
-/// A future that resolves to a locked mutex
-pub struct MutexAcquireFuture<'a, CPU: CpuOps> {
-    mutex: &'a Mutex<(), CPU>,
-}
+    #[tokio::test]
+    async fn try_lock_fails_while_locked_async() {
+        let mutex = Mutex::<_, MockCpuOps>::new(());
 
-impl<CPU: CpuOps> Future for MutexAcquireFuture<'_, CPU> {
-    type Output = ();
+        let guard = mutex.lock().await;
+        assert!(mutex.try_lock().is_none());
 
-    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let mut state = self.mutex.state.lock_save_irq();
+        drop(guard);
+        assert!(mutex.try_lock().is_some());
+    }
 
-        if !state.is_locked {
-            state.is_locked = true;
-            Poll::Ready(())
-        } else {
-            if state.waiters.iter().all(|w| !w.will_wake(cx.waker())) {
-                state.waiters.push_back(cx.waker().clone());
-            }
-            Poll::Pending
-        }
+    #[tokio::test]
+    async fn lock_before_times_out_while_held() {
+        let mutex = Mutex::<_, MockCpuOps>::new(());
+
+        let guard = mutex.lock().await;
+        let result = mutex
+            .lock_before(tokio::time::sleep(Duration::from_millis(10)))
+            .await;
+        assert_eq!(result.err(), Some(Timeout));
+
+        // The abandoned attempt must not leave a waker behind.
+        assert!(mutex.state.lock_save_irq().waiters.is_empty());
+
+        drop(guard);
+        assert!(mutex.lock_before(pending::<()>()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn lock_before_waits_for_release() {
+        let mutex = Arc::new(Mutex::<_, MockCpuOps>::new(0));
+
+        let guard = mutex.lock().await;
+        let waiter = {
+            let mutex = mutex.clone();
+            tokio::spawn(async move {
+                let mut guard = mutex
+                    .lock_before(tokio::time::sleep(Duration::from_secs(10)))
+                    .await
+                    .expect("lock released before the deadline");
+                *guard += 1;
+            })
+        };
+
+        tokio::task::yield_now().await;
+        drop(guard);
+
+        waiter.await.unwrap();
+        assert_eq!(*mutex.lock().await, 1);
+    }
+
+    #[test]
+    fn abandoned_waiter_passes_on_wakeup() {
+        let mutex = Mutex::<_, MockCpuOps>::new(());
+        let (flag_a, flag_b) = (Arc::new(Flag::default()), Arc::new(Flag::default()));
+        let (waker_a, waker_b) = (Waker::from(flag_a.clone()), Waker::from(flag_b.clone()));
+
+        let guard = mutex.try_lock().unwrap();
+
+        let mut a = mutex.lock();
+        let mut b = mutex.lock();
+        assert!(
+            Pin::new(&mut a)
+                .poll(&mut Context::from_waker(&waker_a))
+                .is_pending()
+        );
+        assert!(
+            Pin::new(&mut b)
+                .poll(&mut Context::from_waker(&waker_b))
+                .is_pending()
+        );
+
+        drop(guard);
+        assert!(flag_a.0.load(Ordering::Relaxed));
+        assert!(!flag_b.0.load(Ordering::Relaxed));
+
+        // `a` gives up after being woken, so `b` must be woken in its place.
+        drop(a);
+        assert!(flag_b.0.load(Ordering::Relaxed));
+        assert!(
+            Pin::new(&mut b)
+                .poll(&mut Context::from_waker(&waker_b))
+                .is_ready()
+        );
     }
 }
@@ -0,0 +1,222 @@
+//! Asynchronous, once-only initialization.
+//!
+//! [`OnceLock`] runs its initializer with a spinlock held, so the initializer
+//! can't sleep. [`OnceCell`] instead serialises initialization with a
+//! [`Mutex`]: the first task to arrive runs the (possibly fallible, possibly
+//! sleeping) initializer while any others are parked until it completes. If
+//! the initializer fails, the next waiter tries its own.
+
+use super::mutex::Mutex;
+use super::once_lock::OnceLock;
+use crate::CpuOps;
+use crate::error::Result;
+use alloc::boxed::Box;
+use core::convert::Infallible;
+use core::fmt;
+use core::pin::Pin;
+
+/// A cell which is initialized at most once, by an async initializer.
+pub struct OnceCell<T, CPU: CpuOps> {
+    value: OnceLock<T, CPU>,
+    init: Mutex<(), CPU>,
+}
+
+impl<T, CPU: CpuOps> OnceCell<T, CPU> {
+    /// Creates a new, empty cell.
+    #[track_caller]
+    pub const fn new() -> Self {
+        Self {
+            value: OnceLock::new(),
+            init: Mutex::new(()),
+        }
+    }
+
+    /// Returns the value, if the cell has been initialized.
+    pub fn get(&self) -> Option<&T> {
+        self.value.get()
+    }
+
+    /// Returns `true` if the cell has been initialized.
+    pub fn is_initialized(&self) -> bool {
+        self.get().is_some()
+    }
+
+    /// Returns the value, initializing it with `f` if the cell is empty.
+    ///
+    /// If another task is already initializing the cell, this waits for it to
+    /// finish rather than running `f`.
+    pub async fn get_or_init<F, Fut>(&self, f: F) -> &T
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = T>,
+    {
+        let Ok(value) = self
+            .get_or_try_init(|| async { Ok::<_, Infallible>(f().await) })
+            .await;
+
+        value
+    }
+
+    /// Returns the value, initializing it with `f` if the cell is empty.
+    ///
+    /// If `f` fails, its error is returned and the cell is left empty, so a
+    /// later caller may try again. Only one initializer runs at a time; other
+    /// callers wait for it to finish, then either return the value or, if it
+    /// failed, run their own.
+    pub async fn get_or_try_init<F, Fut, E>(&self, f: F) -> core::result::Result<&T, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = core::result::Result<T, E>>,
+    {
+        if let Some(value) = self.get() {
+            return Ok(value);
+        }
+
+        let _guard = self.init.lock().await;
+
+        // We may have waited on someone else's successful initialization.
+        if let Some(value) = self.get() {
+            return Ok(value);
+        }
+
+        let value = f().await?;
+
+        // We hold `init`, so nobody else can have set the value meanwhile.
+        Ok(self.value.get_or_init(|| value))
+    }
+}
+
+impl<T, CPU: CpuOps> Default for OnceCell<T, CPU> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: fmt::Debug, CPU: CpuOps> fmt::Debug for OnceCell<T, CPU> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OnceCell")
+            .field("value", &self.get())
+            .finish()
+    }
+}
+
+/// The future returned by a [`Lazy`] value's initializer.
+pub type LazyInit<T> = Pin<Box<dyn Future<Output = Result<T>> + Send>>;
+
+/// A value which is initialized by an async, fallible function on first use.
+///
+/// This suits late-initialized subsystems which are kept in a `static`:
+///
+/// ```ignore
+/// static STACK: Lazy<NetStack, ArchImpl> = Lazy::new(|| Box::pin(NetStack::probe()));
+///
+/// let stack = STACK.get().await?;
+/// ```
+pub struct Lazy<T, CPU: CpuOps> {
+    cell: OnceCell<T, CPU>,
+    init: fn() -> LazyInit<T>,
+}
+
+impl<T, CPU: CpuOps> Lazy<T, CPU> {
+    /// Creates a value which will be initialized by `init` on first use.
+    #[track_caller]
+    pub const fn new(init: fn() -> LazyInit<T>) -> Self {
+        Self {
+            cell: OnceCell::new(),
+            init,
+        }
+    }
+
+    /// Returns the value, running the initializer if needed.
+    ///
+    /// If the initializer fails, the error is returned and the initializer
+    /// will be run again on the next call.
+    pub async fn get(&self) -> Result<&T> {
+        self.cell.get_or_try_init(self.init).await
+    }
+
+    /// Returns the value if it has already been initialized.
+    pub fn try_get(&self) -> Option<&T> {
+        self.cell.get()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::KernelError;
+    use crate::test::MockCpuOps;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn initializer_runs_once() {
+        let cell = Arc::new(OnceCell::<usize, MockCpuOps>::new());
+        let runs = Arc::new(AtomicUsize::new(0));
+
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let cell = cell.clone();
+                let runs = runs.clone();
+
+                tokio::spawn(async move {
+                    *cell
+                        .get_or_init(async || {
+                            runs.fetch_add(1, Ordering::Relaxed);
+                            // Sleep so that every other task parks on us.
+                            tokio::time::sleep(Duration::from_millis(10)).await;
+                            i
+                        })
+                        .await
+                })
+            })
+            .collect();
+
+        let mut values = Vec::new();
+        for handle in handles {
+            values.push(handle.await.unwrap());
+        }
+
+        assert_eq!(runs.load(Ordering::Relaxed), 1);
+        assert!(values.iter().all(|&v| v == values[0]));
+        assert_eq!(cell.get(), Some(&values[0]));
+    }
+
+    #[tokio::test]
+    async fn failed_initialization_can_be_retried() {
+        let cell = OnceCell::<usize, MockCpuOps>::new();
+
+        assert_eq!(
+            cell.get_or_try_init(async || Err(KernelError::TryAgain))
+                .await,
+            Err(KernelError::TryAgain)
+        );
+        assert!(!cell.is_initialized());
+
+        assert_eq!(
+            cell.get_or_try_init(async || Ok::<_, KernelError>(3)).await,
+            Ok(&3)
+        );
+        assert_eq!(
+            cell.get_or_try_init(async || Ok::<_, KernelError>(4)).await,
+            Ok(&3)
+        );
+    }
+
+    #[tokio::test]
+    async fn lazy_initializes_on_first_use() {
+        static RUNS: AtomicUsize = AtomicUsize::new(0);
+        static VALUE: Lazy<usize, MockCpuOps> = Lazy::new(|| {
+            Box::pin(async {
+                RUNS.fetch_add(1, Ordering::Relaxed);
+                Ok(42)
+            })
+        });
+
+        assert_eq!(VALUE.try_get(), None);
+        assert_eq!(VALUE.get().await, Ok(&42));
+        assert_eq!(VALUE.get().await, Ok(&42));
+        assert_eq!(RUNS.load(Ordering::Relaxed), 1);
+    }
+}
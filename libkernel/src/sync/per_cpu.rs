@@ -2,7 +2,8 @@
 //!
 //! This module provides a mechanism for creating data that is unique to each
 //! processor core. Accessing this data is extremely fast as it requires no
-//! locks.
+//! locks. Shared (`Sync`) per-CPU data can also be iterated over, e.g. to sum
+//! per-CPU statistics counters.
 //!
 //! The design relies on a custom linker section (`.percpu`) and a macro
 //! (`per_cpu!`) to automatically register and initialize all per-CPU variables
@@ -14,7 +15,7 @@ use core::cell::{Ref, RefCell, RefMut};
 use core::marker::PhantomData;
 use core::mem::ManuallyDrop;
 use core::ops::{Deref, DerefMut};
-use core::sync::atomic::{AtomicPtr, Ordering};
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
 use log::info;
 
 use crate::CpuOps;
@@ -112,6 +113,8 @@ pub struct PerCpu<T: Send, CPU: CpuOps> {
     /// A pointer to the heap-allocated array of `RefCell<T>`s, one for each
     /// CPU. It's `AtomicPtr` to ensure safe one-time initialization.
     ptr: AtomicPtr<T>,
+    /// The number of instances allocated by `init`.
+    num_cpus: AtomicUsize,
     /// A function pointer to the initializer for type `T`.
     /// This is stored so it can be called during the runtime `init` phase.
     initializer: fn() -> T,
@@ -126,11 +129,18 @@ impl<T: Send, CPU: CpuOps> PerCpu<T, CPU> {
     pub const fn new(initializer: fn() -> T) -> Self {
         Self {
             ptr: AtomicPtr::new(core::ptr::null_mut()),
+            num_cpus: AtomicUsize::new(0),
             initializer,
             phantom: PhantomData,
         }
     }
 
+    /// Returns the number of CPUs with an instance, or zero if the variable
+    /// has not been initialized.
+    pub fn num_cpus(&self) -> usize {
+        self.num_cpus.load(Ordering::Acquire)
+    }
+
     /// Returns a reference to the underlying data for the current CPU.
     ///
     /// # Panics
@@ -146,7 +156,8 @@ impl<T: Send, CPU: CpuOps> PerCpu<T, CPU> {
     /// This is unsafe because accessing another CPU's data without synchronization primitives
     /// will not end well. When accessing the current CPU's data, this is safe, and provided by [`Self::get_cell`] instead.
     /// # Panics
-    /// Panics if the `PerCpu` variable has not been initialized.
+    /// Panics if the `PerCpu` variable has not been initialized, or if
+    /// `cpu_id` is out of range.
     unsafe fn get_for_cpu(&self, cpu_id: usize) -> &T {
         let base_ptr = self.ptr.load(Ordering::Acquire);
 
@@ -154,6 +165,11 @@ impl<T: Send, CPU: CpuOps> PerCpu<T, CPU> {
             panic!("PerCpu variable accessed before initialization");
         }
 
+        assert!(
+            cpu_id < self.num_cpus(),
+            "PerCpu variable accessed for out of range CPU {cpu_id}"
+        );
+
         // SAFETY: We have checked for null, and `init` guarantees the allocation
         // is valid for `id`.
         unsafe { &*base_ptr.add(cpu_id) }
@@ -231,6 +247,35 @@ impl<T: Send + Sync, CPU: CpuOps> PerCpu<T, CPU> {
     pub fn get_by_cpu(&self, cpu_id: usize) -> &T {
         unsafe { self.get_for_cpu(cpu_id) }
     }
+
+    /// Returns an iterator over every CPU's instance, in CPU order.
+    ///
+    /// # Panics
+    /// Panics if the `PerCpu` variable has not been initialized.
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        let num_cpus = self.num_cpus();
+
+        if num_cpus == 0 {
+            panic!("PerCpu variable accessed before initialization");
+        }
+
+        (0..num_cpus).map(|cpu_id| self.get_by_cpu(cpu_id))
+    }
+}
+
+impl<CPU: CpuOps> PerCpu<AtomicUsize, CPU> {
+    /// Adds `n` to this CPU's count.
+    ///
+    /// This is intended for statistics counters: each CPU only ever touches
+    /// its own cache line, and the total is only computed when read.
+    pub fn add(&self, n: usize) {
+        self.get().fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// Returns the sum of every CPU's count.
+    pub fn sum(&self) -> usize {
+        self.iter().map(|count| count.load(Ordering::Relaxed)).sum()
+    }
 }
 
 // Implement the type-erased initializer trait.
@@ -243,6 +288,10 @@ impl<T: Send, CPU: CpuOps> PerCpuInitializer for PerCpu<T, CPU> {
 
         let leaked_ptr = Box::leak(values.into_boxed_slice()).as_mut_ptr();
 
+        // Publish the count before the pointer, so that anyone who sees the
+        // allocation also sees its size.
+        self.num_cpus.store(num_cpus, Ordering::Release);
+
         let result = self.ptr.compare_exchange(
             core::ptr::null_mut(),
             leaked_ptr,
@@ -373,6 +422,34 @@ mod tests {
         data.init(1); // This second call should panic.
     }
 
+    #[test]
+    fn test_iteration_and_counters() {
+        let data: PerCpu<_, MockArch> = PerCpu::new(|| AtomicUsize::new(0));
+        data.init(4);
+
+        for cpu in 0..4 {
+            MOCK_CPU_ID.with(|id| id.set(cpu));
+            data.add(cpu + 1);
+        }
+
+        assert_eq!(data.num_cpus(), 4);
+        assert_eq!(
+            data.iter()
+                .map(|v| v.load(Ordering::Relaxed))
+                .collect::<Vec<_>>(),
+            [1, 2, 3, 4]
+        );
+        assert_eq!(data.sum(), 10);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of range CPU 4")]
+    fn test_panic_on_out_of_range_cpu() {
+        let data: PerCpu<_, MockArch> = PerCpu::new(|| 0);
+        data.init(4);
+        let _ = data.get_by_cpu(4);
+    }
+
     #[test]
     #[should_panic(expected = "already borrowed")]
     fn test_refcell_panic_on_double_mutable_borrow() {
@@ -0,0 +1,524 @@
+//! Read-copy-update synchronisation for read-mostly data.
+//!
+//! Readers of an [`RcuCell`] never block or write to shared memory: they
+//! simply load a pointer inside a read-side critical section, marked by an
+//! [`RcuReadGuard`]. Writers publish a new version of the data by swapping the
+//! pointer, and free the old version only once every reader which might still
+//! see it has finished.
+//!
+//! Tasks are only switched at `.await` points, and a read-side critical
+//! section must not span one. So once every online CPU has passed through the
+//! scheduler — a *quiescent state* — since a version was retired, no reader
+//! can still hold it. That interval is a *grace period*. The scheduler reports
+//! quiescent states with [`Rcu::quiescent_state`], and [`Rcu::call_rcu`]
+//! defers a callback until the end of the next grace period.
+//!
+//! An idle CPU may sleep for a long time without passing through the
+//! scheduler, but it holds no read-side critical section either. Between
+//! [`Rcu::idle_enter`] and [`Rcu::idle_exit`] it is treated as permanently
+//! quiescent, so grace periods don't wait for it.
+
+use super::condvar::{CondVar, WakeupType};
+use super::spinlock::SpinLockIrq;
+use crate::CpuOps;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, Ordering};
+
+type Callback = Box<dyn FnOnce() + Send>;
+
+/// A set of CPUs, as a bitmap.
+#[derive(Default)]
+struct CpuSet(Vec<u64>);
+
+impl CpuSet {
+    fn insert(&mut self, cpu: usize) {
+        let word = cpu / 64;
+
+        if self.0.len() <= word {
+            self.0.resize(word + 1, 0);
+        }
+
+        self.0[word] |= 1 << (cpu % 64);
+    }
+
+    /// Removes `cpu`, returning `true` if it was present.
+    fn remove(&mut self, cpu: usize) -> bool {
+        let Some(word) = self.0.get_mut(cpu / 64) else {
+            return false;
+        };
+
+        let present = *word & (1 << (cpu % 64)) != 0;
+        *word &= !(1 << (cpu % 64));
+
+        present
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.iter().all(|&w| w == 0)
+    }
+
+    /// Returns the CPUs in `self` but not in `other`.
+    fn difference(&self, other: &CpuSet) -> CpuSet {
+        CpuSet(
+            self.0
+                .iter()
+                .enumerate()
+                .map(|(i, &w)| w & !other.0.get(i).copied().unwrap_or(0))
+                .collect(),
+        )
+    }
+}
+
+struct RcuState {
+    online: CpuSet,
+    /// Online CPUs which are idle, and so can't be in a read-side critical
+    /// section.
+    idle: CpuSet,
+    /// CPUs yet to pass through a quiescent state in the current grace
+    /// period.
+    pending: CpuSet,
+    /// Callbacks to run when the current grace period ends.
+    current: Vec<Callback>,
+    /// Callbacks queued during the current grace period, which must wait for
+    /// the next one.
+    next: Vec<Callback>,
+}
+
+/// An RCU domain, tracking grace periods across the online CPUs.
+pub struct Rcu<CPU: CpuOps> {
+    state: SpinLockIrq<RcuState, CPU>,
+    /// Mirrors whether a grace period is running, so that the scheduler's
+    /// check is cheap when there is nothing to do.
+    in_progress: AtomicBool,
+    /// The number of grace periods completed.
+    completed: AtomicU64,
+}
+
+/// Marks a read-side critical section.
+///
+/// References obtained from an [`RcuCell`] are tied to the guard's lifetime.
+/// The guard must not be held across an `.await`; it is `!Send` so that a
+/// future holding one can't be spawned.
+pub struct RcuReadGuard<'a, CPU: CpuOps> {
+    _rcu: &'a Rcu<CPU>,
+    _marker: PhantomData<*const ()>,
+}
+
+impl<CPU: CpuOps> Default for Rcu<CPU> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<CPU: CpuOps> Rcu<CPU> {
+    /// Creates a domain with no CPUs online.
+    pub const fn new() -> Self {
+        Self {
+            state: SpinLockIrq::new(RcuState {
+                online: CpuSet(Vec::new()),
+                idle: CpuSet(Vec::new()),
+                pending: CpuSet(Vec::new()),
+                current: Vec::new(),
+                next: Vec::new(),
+            }),
+            in_progress: AtomicBool::new(false),
+            completed: AtomicU64::new(0),
+        }
+    }
+
+    /// Marks `cpu` as online, so that grace periods wait for it.
+    pub fn cpu_online(&self, cpu: usize) {
+        self.state.lock_save_irq().online.insert(cpu);
+    }
+
+    /// Marks `cpu` as idle, reporting a quiescent state for it.
+    ///
+    /// Grace periods don't wait for an idle CPU, so it must not enter a
+    /// read-side critical section until [`Self::idle_exit`].
+    pub fn idle_enter(&self, cpu: usize) {
+        let mut state = self.state.lock_save_irq();
+
+        state.idle.insert(cpu);
+        let callbacks = self.report(&mut state, cpu);
+
+        drop(state);
+
+        for callback in callbacks {
+            callback();
+        }
+    }
+
+    /// Marks `cpu` as no longer idle, so that grace periods started from now
+    /// on wait for it.
+    pub fn idle_exit(&self, cpu: usize) {
+        self.state.lock_save_irq().idle.remove(cpu);
+    }
+
+    /// Enters a read-side critical section.
+    pub fn read_lock(&self) -> RcuReadGuard<'_, CPU> {
+        RcuReadGuard {
+            _rcu: self,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the number of grace periods completed.
+    pub fn completed(&self) -> u64 {
+        self.completed.load(Ordering::Acquire)
+    }
+
+    /// Runs `callback` once every reader which might currently be in a
+    /// read-side critical section has left it.
+    ///
+    /// Callbacks run on whichever CPU ends the grace period, from
+    /// [`Self::quiescent_state`], so must not block.
+    pub fn call_rcu(&self, callback: impl FnOnce() + Send + 'static) {
+        let mut state = self.state.lock_save_irq();
+
+        state.next.push(Box::new(callback));
+
+        let callbacks = if self.in_progress.load(Ordering::Relaxed) {
+            Vec::new()
+        } else {
+            self.start_grace_period(&mut state)
+        };
+
+        drop(state);
+
+        for callback in callbacks {
+            callback();
+        }
+    }
+
+    /// Waits for a full grace period to elapse.
+    pub async fn synchronize(&self) {
+        let done = CondVar::<bool, CPU>::new(false);
+        let notify = done.clone();
+
+        self.call_rcu(move || {
+            notify.update(|done| {
+                *done = true;
+                WakeupType::All
+            });
+        });
+
+        done.wait_until(|done| done.then_some(())).await;
+    }
+
+    /// Reports that `cpu` has passed through a quiescent state, i.e. that it
+    /// is not in a read-side critical section.
+    ///
+    /// This should be called by the scheduler each time it switches tasks.
+    pub fn quiescent_state(&self, cpu: usize) {
+        if !self.in_progress.load(Ordering::Acquire) {
+            return;
+        }
+
+        let mut state = self.state.lock_save_irq();
+        let callbacks = self.report(&mut state, cpu);
+
+        drop(state);
+
+        for callback in callbacks {
+            callback();
+        }
+    }
+
+    /// Records a quiescent state for `cpu`, returning the callbacks to run if
+    /// that ended the grace period.
+    fn report(&self, state: &mut RcuState, cpu: usize) -> Vec<Callback> {
+        if !state.pending.remove(cpu) || !state.pending.is_empty() {
+            return Vec::new();
+        }
+
+        // That was the last CPU, so the grace period has ended.
+        self.end_grace_period(state)
+    }
+
+    /// Starts a grace period for the callbacks in `next`, returning them
+    /// straight away if there are no CPUs to wait for.
+    fn start_grace_period(&self, state: &mut RcuState) -> Vec<Callback> {
+        state.current = core::mem::take(&mut state.next);
+        state.pending = state.online.difference(&state.idle);
+
+        if state.online.is_empty() {
+            // No CPUs are online yet, i.e. we're still booting on CPU 0, so
+            // end the grace period at its next quiescent state.
+            state.pending.insert(0);
+        } else if state.pending.is_empty() {
+            // Every online CPU is idle, so there are no readers to wait for.
+            return self.end_grace_period(state);
+        }
+
+        self.in_progress.store(true, Ordering::Release);
+
+        Vec::new()
+    }
+
+    fn end_grace_period(&self, state: &mut RcuState) -> Vec<Callback> {
+        let mut callbacks = core::mem::take(&mut state.current);
+
+        self.completed.fetch_add(1, Ordering::Release);
+        self.in_progress.store(false, Ordering::Release);
+
+        if !state.next.is_empty() {
+            callbacks.append(&mut self.start_grace_period(state));
+        }
+
+        callbacks
+    }
+}
+
+/// A pointer to RCU-protected data.
+///
+/// Readers borrow the current value for the length of a read-side critical
+/// section. Writers replace it wholesale, and must serialise among themselves,
+/// e.g. by holding a mutex.
+pub struct RcuCell<T: Send + Sync + 'static> {
+    ptr: AtomicPtr<T>,
+}
+
+impl<T: Send + Sync + 'static> RcuCell<T> {
+    /// Creates a cell holding `value`.
+    pub fn new(value: T) -> Self {
+        Self {
+            ptr: AtomicPtr::new(Box::into_raw(Box::new(value))),
+        }
+    }
+
+    /// Returns the current value.
+    pub fn read<'g, CPU: CpuOps>(&'g self, _guard: &'g RcuReadGuard<'_, CPU>) -> &'g T {
+        // SAFETY: The pointer always refers to a live allocation. A value
+        // which has been replaced is only freed after a grace period, which
+        // can't end while `_guard` is alive.
+        unsafe { &*self.ptr.load(Ordering::Acquire) }
+    }
+
+    /// Publishes `value`, freeing the previous value after a grace period of
+    /// `rcu`.
+    pub fn replace<CPU: CpuOps>(&self, value: T, rcu: &Rcu<CPU>) {
+        let old = self
+            .ptr
+            .swap(Box::into_raw(Box::new(value)), Ordering::AcqRel);
+
+        // Raw pointers aren't `Send`, so smuggle the address across.
+        let old = old as usize;
+
+        rcu.call_rcu(move || {
+            // SAFETY: `old` came from `Box::into_raw` and, having been
+            // unpublished a grace period ago, is no longer referenced.
+            drop(unsafe { Box::from_raw(old as *mut T) });
+        });
+    }
+
+    /// Returns a mutable reference to the current value.
+    ///
+    /// The exclusive borrow guarantees there are no readers.
+    pub fn get_mut(&mut self) -> &mut T {
+        // SAFETY: See `read`; `&mut self` excludes any concurrent access.
+        unsafe { &mut *self.ptr.load(Ordering::Relaxed) }
+    }
+}
+
+impl<T: Send + Sync + 'static> Drop for RcuCell<T> {
+    fn drop(&mut self) {
+        let ptr = *self.ptr.get_mut();
+
+        // SAFETY: `&mut self` guarantees there are no readers.
+        drop(unsafe { Box::from_raw(ptr) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::MockCpuOps;
+    use core::ptr;
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicUsize;
+
+    fn rcu_with_cpus(n: usize) -> Rcu<MockCpuOps> {
+        let rcu = Rcu::new();
+
+        for cpu in 0..n {
+            rcu.cpu_online(cpu);
+        }
+
+        rcu
+    }
+
+    #[test]
+    fn callbacks_wait_for_every_cpu() {
+        let rcu = rcu_with_cpus(2);
+        let ran = Arc::new(AtomicUsize::new(0));
+
+        let r = ran.clone();
+        rcu.call_rcu(move || {
+            r.fetch_add(1, Ordering::Relaxed);
+        });
+
+        rcu.quiescent_state(0);
+        rcu.quiescent_state(0);
+        assert_eq!(ran.load(Ordering::Relaxed), 0);
+
+        rcu.quiescent_state(1);
+        assert_eq!(ran.load(Ordering::Relaxed), 1);
+        assert_eq!(rcu.completed(), 1);
+    }
+
+    #[test]
+    fn late_callbacks_wait_for_the_next_grace_period() {
+        let rcu = rcu_with_cpus(2);
+        let ran = Arc::new(AtomicUsize::new(0));
+
+        rcu.call_rcu(|| {});
+        rcu.quiescent_state(0);
+
+        // CPU 0 may have entered a new read section since its quiescent
+        // state, so this must wait for another grace period.
+        let r = ran.clone();
+        rcu.call_rcu(move || {
+            r.fetch_add(1, Ordering::Relaxed);
+        });
+
+        rcu.quiescent_state(1);
+        assert_eq!(rcu.completed(), 1);
+        assert_eq!(ran.load(Ordering::Relaxed), 0);
+
+        rcu.quiescent_state(1);
+        rcu.quiescent_state(0);
+        assert_eq!(rcu.completed(), 2);
+        assert_eq!(ran.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn replaced_values_are_freed_after_a_grace_period() {
+        struct Tracked(Arc<AtomicUsize>);
+
+        impl Drop for Tracked {
+            fn drop(&mut self) {
+                self.0.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        let rcu = rcu_with_cpus(1);
+        let drops = Arc::new(AtomicUsize::new(0));
+        let cell = RcuCell::new(Tracked(drops.clone()));
+
+        {
+            let guard = rcu.read_lock();
+            let old = cell.read(&guard);
+
+            cell.replace(Tracked(drops.clone()), &rcu);
+
+            // The old value stays valid for the reader.
+            assert_eq!(old.0.load(Ordering::Relaxed), 0);
+            assert!(!ptr::eq(old, cell.read(&guard)));
+        }
+
+        assert_eq!(drops.load(Ordering::Relaxed), 0);
+        rcu.quiescent_state(0);
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+
+        drop(cell);
+        assert_eq!(drops.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn grace_periods_skip_idle_cpus() {
+        let rcu = rcu_with_cpus(2);
+        let ran = Arc::new(AtomicUsize::new(0));
+
+        rcu.idle_enter(1);
+
+        let r = ran.clone();
+        rcu.call_rcu(move || {
+            r.fetch_add(1, Ordering::Relaxed);
+        });
+
+        rcu.quiescent_state(0);
+        assert_eq!(ran.load(Ordering::Relaxed), 1);
+
+        // Once it wakes, CPU 1 holds up grace periods again.
+        rcu.idle_exit(1);
+        rcu.call_rcu(|| {});
+        rcu.quiescent_state(0);
+        assert_eq!(rcu.completed(), 1);
+
+        rcu.quiescent_state(1);
+        assert_eq!(rcu.completed(), 2);
+    }
+
+    #[test]
+    fn going_idle_ends_a_pending_grace_period() {
+        let rcu = rcu_with_cpus(2);
+        let ran = Arc::new(AtomicUsize::new(0));
+
+        let r = ran.clone();
+        rcu.call_rcu(move || {
+            r.fetch_add(1, Ordering::Relaxed);
+        });
+
+        rcu.quiescent_state(0);
+        assert_eq!(ran.load(Ordering::Relaxed), 0);
+
+        rcu.idle_enter(1);
+        assert_eq!(ran.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn callbacks_run_at_once_when_every_cpu_is_idle() {
+        let rcu = rcu_with_cpus(2);
+        let ran = Arc::new(AtomicUsize::new(0));
+
+        rcu.idle_enter(0);
+        rcu.idle_enter(1);
+
+        let r = ran.clone();
+        rcu.call_rcu(move || {
+            r.fetch_add(1, Ordering::Relaxed);
+        });
+
+        assert_eq!(ran.load(Ordering::Relaxed), 1);
+        assert_eq!(rcu.completed(), 1);
+    }
+
+    #[tokio::test]
+    async fn synchronize_waits_for_a_grace_period() {
+        let rcu = Arc::new(rcu_with_cpus(1));
+
+        let handle = {
+            let rcu = rcu.clone();
+            tokio::spawn(async move { rcu.synchronize().await })
+        };
+
+        while rcu.completed() == 0 {
+            tokio::task::yield_now().await;
+            rcu.quiescent_state(0);
+        }
+
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn synchronize_does_not_wait_for_an_idle_cpu() {
+        let rcu = Arc::new(rcu_with_cpus(2));
+
+        // CPU 1 sleeps throughout, never passing through the scheduler.
+        rcu.idle_enter(1);
+
+        let handle = {
+            let rcu = rcu.clone();
+            tokio::spawn(async move { rcu.synchronize().await })
+        };
+
+        while rcu.completed() == 0 {
+            tokio::task::yield_now().await;
+            rcu.quiescent_state(0);
+        }
+
+        handle.await.unwrap();
+    }
+}
@@ -1,23 +1,71 @@
 //! Async-aware readers–writer lock.
+//!
+//! The lock state is a single atomic word holding the number of readers and a
+//! handful of flag bits. Every transition is a compare-and-swap on that word,
+//! so acquisition and release never race with each other. A spinlock is only
+//! taken to queue wakers when a task has to wait.
 
 use super::spinlock::SpinLockIrq;
+use super::timeout::{self, Timeout};
+
+#[cfg(feature = "lockdep")]
+use super::lockdep::{self, LockClass, LockKind};
 use crate::CpuOps;
-use crate::sync::mutex::Mutex;
+use alloc::collections::VecDeque;
 use core::cell::UnsafeCell;
+use core::future::Future;
 use core::ops::{Deref, DerefMut};
+use core::pin::Pin;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::task::{Context, Poll, Waker};
+
+/// Set while a writer holds the lock.
+const WRITER: usize = 1 << (usize::BITS - 1);
+/// Set while at least one writer is waiting for the lock.
+const WRITERS_WAITING: usize = 1 << (usize::BITS - 2);
+/// Set while the readers of an [`RwlockPolicy::Alternating`] lock have their
+/// turn.
+const READER_PHASE: usize = 1 << (usize::BITS - 3);
+/// The bits counting the readers holding the lock.
+const READERS_MASK: usize = READER_PHASE - 1;
+
+/// Decides whether readers or writers acquire a contended [`Rwlock`] first.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RwlockPolicy {
+    /// Readers may join other readers even while writers are waiting. This
+    /// maximises read throughput, but a steady stream of readers can starve
+    /// writers.
+    #[default]
+    ReaderPreferring,
+    /// New readers wait while any writer is waiting, and a released lock is
+    /// handed to writers first. Readers can be starved by a steady stream of
+    /// writers.
+    WriterPreferring,
+    /// Readers and writers take turns: once a writer releases the lock, any
+    /// readers may enter until they have all left, after which waiting writers
+    /// are preferred again. Neither side can be starved.
+    Alternating,
+}
 
-struct RwlockState<CPU: CpuOps> {
-    num_readers: SpinLockIrq<usize, CPU>,
-    writer_lock: Mutex<(), CPU>,
+struct Waiters {
+    readers: VecDeque<Waker>,
+    writers: VecDeque<Waker>,
+    /// The number of pending write futures, which may be more than the number
+    /// of queued wakers if some have been woken but not yet polled.
+    writers_waiting: usize,
 }
 
 /// An asynchronous, rwlock primitive.
 ///
 /// This rwlock can be used to protect shared data across asynchronous tasks.
-/// `lock()` returns a future that resolves to a guard. When the guard is
-/// dropped, the lock is released.
+/// `read()` and `write()` return futures that resolve to guards. When a guard
+/// is dropped, the lock is released.
 pub struct Rwlock<T: ?Sized, CPU: CpuOps> {
-    state: RwlockState<CPU>,
+    state: AtomicUsize,
+    policy: RwlockPolicy,
+    waiters: SpinLockIrq<Waiters, CPU>,
+    #[cfg(feature = "lockdep")]
+    class: LockClass,
     data: UnsafeCell<T>,
 }
 
@@ -39,14 +87,45 @@ pub struct AsyncRwlockWriteGuard<'a, T: ?Sized, CPU: CpuOps> {
     rwlock: &'a Rwlock<T, CPU>,
 }
 
+/// A future that resolves to an `AsyncRwlockReadGuard` when read access is
+/// acquired.
+pub struct RwlockReadGuardFuture<'a, T: ?Sized, CPU: CpuOps> {
+    rwlock: &'a Rwlock<T, CPU>,
+    /// The waker this future last queued, if it is waiting.
+    waker: Option<Waker>,
+}
+
+/// A future that resolves to an `AsyncRwlockWriteGuard` when write access is
+/// acquired.
+pub struct RwlockWriteGuardFuture<'a, T: ?Sized, CPU: CpuOps> {
+    rwlock: &'a Rwlock<T, CPU>,
+    /// The waker this future last queued, if it is waiting. Waiting futures
+    /// are counted in [`Waiters::writers_waiting`].
+    waker: Option<Waker>,
+}
+
 impl<T, CPU: CpuOps> Rwlock<T, CPU> {
-    /// Creates a new asynchronous rwlock in an unlocked state.
-    pub fn new(data: T) -> Self {
+    /// Creates a new asynchronous rwlock in an unlocked state, using the
+    /// default [`RwlockPolicy::ReaderPreferring`] policy.
+    #[track_caller]
+    pub const fn new(data: T) -> Self {
+        Self::with_policy(data, RwlockPolicy::ReaderPreferring)
+    }
+
+    /// Creates a new asynchronous rwlock in an unlocked state, using the given
+    /// fairness policy.
+    #[track_caller]
+    pub const fn with_policy(data: T, policy: RwlockPolicy) -> Self {
         Self {
-            state: RwlockState {
-                num_readers: SpinLockIrq::new(0),
-                writer_lock: Mutex::new(()),
-            },
+            state: AtomicUsize::new(0),
+            policy,
+            #[cfg(feature = "lockdep")]
+            class: core::panic::Location::caller(),
+            waiters: SpinLockIrq::new(Waiters {
+                readers: VecDeque::new(),
+                writers: VecDeque::new(),
+                writers_waiting: 0,
+            }),
             data: UnsafeCell::new(data),
         }
     }
@@ -63,37 +142,378 @@ impl<T, CPU: CpuOps> Rwlock<T, CPU> {
 impl<T: ?Sized, CPU: CpuOps> Rwlock<T, CPU> {
     /// Acquires rwlock read.
     ///
-    /// Returns a guard asynchronously. The guard is released when the
-    /// returned [`AsyncRwlockReadGuard`] is dropped.
-    pub async fn read(&self) -> AsyncRwlockReadGuard<'_, T, CPU> {
-        let mut num_readers = self.state.num_readers.lock_save_irq();
-        *num_readers += 1;
-        if *num_readers == 1 {
-            self.state.writer_lock.acquire().await;
+    /// Returns a future that resolves to a lock guard. The guard is released
+    /// when the returned [`AsyncRwlockReadGuard`] is dropped.
+    pub fn read(&self) -> RwlockReadGuardFuture<'_, T, CPU> {
+        RwlockReadGuardFuture {
+            rwlock: self,
+            waker: None,
         }
-        AsyncRwlockReadGuard { rwlock: self }
     }
 
     /// Acquires rwlock write.
     ///
-    /// Returns a guard asynchronously. The guard is released when the
-    /// returned [`AsyncRwlockWriteGuard`] is dropped.
-    pub async fn write(&self) -> AsyncRwlockWriteGuard<'_, T, CPU> {
-        self.state.writer_lock.acquire().await;
+    /// Returns a future that resolves to a lock guard. The guard is released
+    /// when the returned [`AsyncRwlockWriteGuard`] is dropped.
+    pub fn write(&self) -> RwlockWriteGuardFuture<'_, T, CPU> {
+        RwlockWriteGuardFuture {
+            rwlock: self,
+            waker: None,
+        }
+    }
+
+    /// Acquires rwlock read, giving up if `timer` completes first.
+    ///
+    /// Returns [`Timeout`] if the lock could not be acquired before `timer`
+    /// fired.
+    pub async fn read_before<D: Future>(
+        &self,
+        timer: D,
+    ) -> Result<AsyncRwlockReadGuard<'_, T, CPU>, Timeout> {
+        timeout::before(self.read(), timer).await
+    }
+
+    /// Acquires rwlock write, giving up if `timer` completes first.
+    ///
+    /// Returns [`Timeout`] if the lock could not be acquired before `timer`
+    /// fired.
+    pub async fn write_before<D: Future>(
+        &self,
+        timer: D,
+    ) -> Result<AsyncRwlockWriteGuard<'_, T, CPU>, Timeout> {
+        timeout::before(self.write(), timer).await
+    }
+
+    /// Attempts to acquire rwlock read without waiting.
+    ///
+    /// Returns `None` if a writer holds the lock, or if the lock's policy
+    /// doesn't admit new readers while writers are waiting.
+    pub fn try_read(&self) -> Option<AsyncRwlockReadGuard<'_, T, CPU>> {
+        self.try_acquire_read().then(|| self.read_guard())
+    }
+
+    /// Attempts to acquire rwlock write without waiting.
+    ///
+    /// Returns `None` if the lock is held by any reader or writer.
+    pub fn try_write(&self) -> Option<AsyncRwlockWriteGuard<'_, T, CPU>> {
+        self.try_acquire_write().then(|| self.write_guard())
+    }
+
+    /// Returns the fairness policy of this lock.
+    pub fn policy(&self) -> RwlockPolicy {
+        self.policy
+    }
+
+    /// Returns a mutable reference to the underlying data.
+    ///
+    /// Since this call borrows the `Rwlock` mutably, no actual locking needs
+    /// to take place.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    /// Wraps a successful read acquisition in a guard.
+    fn read_guard(&self) -> AsyncRwlockReadGuard<'_, T, CPU> {
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire::<CPU>(self.class, LockKind::Sleeping);
+
+        AsyncRwlockReadGuard { rwlock: self }
+    }
+
+    /// Wraps a successful write acquisition in a guard.
+    fn write_guard(&self) -> AsyncRwlockWriteGuard<'_, T, CPU> {
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire::<CPU>(self.class, LockKind::Sleeping);
+
         AsyncRwlockWriteGuard { rwlock: self }
     }
+
+    /// Returns whether a new reader may join the lock in `state`.
+    fn admits_readers(&self, state: usize) -> bool {
+        if state & WRITER != 0 {
+            return false;
+        }
+
+        match self.policy {
+            RwlockPolicy::ReaderPreferring => true,
+            RwlockPolicy::WriterPreferring => state & WRITERS_WAITING == 0,
+            RwlockPolicy::Alternating => state & WRITERS_WAITING == 0 || state & READER_PHASE != 0,
+        }
+    }
+
+    fn try_acquire_read(&self) -> bool {
+        let mut state = self.state.load(Ordering::Relaxed);
+
+        while self.admits_readers(state) {
+            debug_assert!(state & READERS_MASK != READERS_MASK, "reader overflow");
+
+            match self.state.compare_exchange_weak(
+                state,
+                state + 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(s) => state = s,
+            }
+        }
+
+        false
+    }
+
+    fn try_acquire_write(&self) -> bool {
+        let mut state = self.state.load(Ordering::Relaxed);
+
+        while state & (WRITER | READERS_MASK) == 0 {
+            // Taking the lock ends any readers' turn.
+            let new = (state | WRITER) & !READER_PHASE;
+
+            match self
+                .state
+                .compare_exchange_weak(state, new, Ordering::Acquire, Ordering::Relaxed)
+            {
+                Ok(_) => return true,
+                Err(s) => state = s,
+            }
+        }
+
+        false
+    }
+
+    fn release_read(&self) {
+        let mut state = self.state.load(Ordering::Relaxed);
+
+        loop {
+            let new = state - 1;
+
+            // The last reader out ends the readers' turn.
+            let new = if new & READERS_MASK == 0 {
+                new & !READER_PHASE
+            } else {
+                new
+            };
+
+            match self
+                .state
+                .compare_exchange_weak(state, new, Ordering::Release, Ordering::Relaxed)
+            {
+                Ok(_) => break,
+                Err(s) => state = s,
+            }
+        }
+
+        if state & READERS_MASK == 1 {
+            // Only writers can be waiting on readers.
+            if let Some(waker) = self.waiters.lock_save_irq().writers.pop_front() {
+                waker.wake();
+            }
+        }
+    }
+
+    fn release_write(&self) {
+        let phase = if self.policy == RwlockPolicy::Alternating {
+            READER_PHASE
+        } else {
+            0
+        };
+
+        let mut state = self.state.load(Ordering::Relaxed);
+
+        while let Err(s) = self.state.compare_exchange_weak(
+            state,
+            (state & !WRITER) | phase,
+            Ordering::Release,
+            Ordering::Relaxed,
+        ) {
+            state = s;
+        }
+
+        let mut waiters = self.waiters.lock_save_irq();
+
+        let writers_first = self.policy == RwlockPolicy::WriterPreferring;
+
+        if (writers_first || waiters.readers.is_empty())
+            && let Some(waker) = waiters.writers.pop_front()
+        {
+            waker.wake();
+        } else {
+            waiters.readers.drain(..).for_each(Waker::wake);
+        }
+    }
+
+    /// Stops counting a write future as waiting, returning `true` if no
+    /// writers are left waiting.
+    fn stop_waiting(&self, waiters: &mut Waiters) -> bool {
+        waiters.writers_waiting -= 1;
+
+        if waiters.writers_waiting == 0 {
+            self.state.fetch_and(!WRITERS_WAITING, Ordering::Relaxed);
+            return true;
+        }
+
+        false
+    }
+
+    /// Wakes the next writer if the lock is free, for use when a woken waiter
+    /// gives up without taking the lock.
+    fn pass_on_wakeup(&self, waiters: &mut Waiters) {
+        if self.state.load(Ordering::Relaxed) & (WRITER | READERS_MASK) == 0
+            && let Some(waker) = waiters.writers.pop_front()
+        {
+            waker.wake();
+        }
+    }
 }
 
-impl<T: ?Sized, CPU: CpuOps> Drop for AsyncRwlockReadGuard<'_, T, CPU> {
+/// Queues `waker` in place of `queued`, the waker a future last queued.
+fn enqueue(queue: &mut VecDeque<Waker>, queued: &mut Option<Waker>, waker: &Waker) {
+    if let Some(old) = queued.take()
+        && !old.will_wake(waker)
+    {
+        dequeue(queue, &old);
+    }
+
+    if queue.iter().all(|w| !w.will_wake(waker)) {
+        queue.push_back(waker.clone());
+    }
+
+    *queued = Some(waker.clone());
+}
+
+/// Removes `waker` from `queue`, returning `true` if it was present.
+fn dequeue(queue: &mut VecDeque<Waker>, waker: &Waker) -> bool {
+    let len = queue.len();
+
+    queue.retain(|w| !w.will_wake(waker));
+
+    queue.len() != len
+}
+
+impl<'a, T: ?Sized, CPU: CpuOps> Future for RwlockReadGuardFuture<'a, T, CPU> {
+    type Output = AsyncRwlockReadGuard<'a, T, CPU>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let rwlock = self.rwlock;
+
+        if rwlock.try_acquire_read() {
+            if let Some(waker) = self.waker.take() {
+                dequeue(&mut rwlock.waiters.lock_save_irq().readers, &waker);
+            }
+
+            return Poll::Ready(rwlock.read_guard());
+        }
+
+        let mut waiters = rwlock.waiters.lock_save_irq();
+
+        enqueue(&mut waiters.readers, &mut self.waker, cx.waker());
+
+        // The lock may have been released before our waker was queued, in
+        // which case nobody would wake us.
+        if rwlock.try_acquire_read() {
+            dequeue(&mut waiters.readers, cx.waker());
+            self.waker = None;
+
+            return Poll::Ready(rwlock.read_guard());
+        }
+
+        drop(waiters);
+
+        #[cfg(feature = "lockdep")]
+        lockdep::wait::<CPU>(rwlock.class);
+
+        Poll::Pending
+    }
+}
+
+impl<'a, T: ?Sized, CPU: CpuOps> Future for RwlockWriteGuardFuture<'a, T, CPU> {
+    type Output = AsyncRwlockWriteGuard<'a, T, CPU>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let rwlock = self.rwlock;
+
+        if rwlock.try_acquire_write() {
+            if let Some(waker) = self.waker.take() {
+                let mut waiters = rwlock.waiters.lock_save_irq();
+
+                dequeue(&mut waiters.writers, &waker);
+                rwlock.stop_waiting(&mut waiters);
+            }
+
+            return Poll::Ready(rwlock.write_guard());
+        }
+
+        let mut waiters = rwlock.waiters.lock_save_irq();
+
+        if self.waker.is_none() {
+            waiters.writers_waiting += 1;
+            rwlock.state.fetch_or(WRITERS_WAITING, Ordering::Relaxed);
+        }
+
+        enqueue(&mut waiters.writers, &mut self.waker, cx.waker());
+
+        // The lock may have been released before our waker was queued, in
+        // which case nobody would wake us.
+        if rwlock.try_acquire_write() {
+            dequeue(&mut waiters.writers, cx.waker());
+            self.waker = None;
+            rwlock.stop_waiting(&mut waiters);
+
+            return Poll::Ready(rwlock.write_guard());
+        }
+
+        drop(waiters);
+
+        #[cfg(feature = "lockdep")]
+        lockdep::wait::<CPU>(rwlock.class);
+
+        Poll::Pending
+    }
+}
+
+impl<T: ?Sized, CPU: CpuOps> Drop for RwlockReadGuardFuture<'_, T, CPU> {
+    fn drop(&mut self) {
+        let Some(waker) = self.waker.take() else {
+            return;
+        };
+
+        let mut waiters = self.rwlock.waiters.lock_save_irq();
+
+        // If our waker is gone, we may have been woken in place of a writer.
+        if !dequeue(&mut waiters.readers, &waker) {
+            self.rwlock.pass_on_wakeup(&mut waiters);
+        }
+    }
+}
+
+impl<T: ?Sized, CPU: CpuOps> Drop for RwlockWriteGuardFuture<'_, T, CPU> {
     fn drop(&mut self) {
-        let mut num_readers = self.rwlock.state.num_readers.lock_save_irq();
-        *num_readers -= 1;
-        if *num_readers == 0 {
-            unsafe { self.rwlock.state.writer_lock.release() };
+        let Some(waker) = self.waker.take() else {
+            return;
+        };
+
+        let mut waiters = self.rwlock.waiters.lock_save_irq();
+
+        // Readers may have been held back for us.
+        if self.rwlock.stop_waiting(&mut waiters) {
+            waiters.readers.drain(..).for_each(Waker::wake);
+        }
+
+        // If our waker is gone, we may have been woken to take the lock.
+        if !dequeue(&mut waiters.writers, &waker) {
+            self.rwlock.pass_on_wakeup(&mut waiters);
         }
     }
 }
 
+impl<T: ?Sized, CPU: CpuOps> Drop for AsyncRwlockReadGuard<'_, T, CPU> {
+    fn drop(&mut self) {
+        self.rwlock.release_read();
+
+        #[cfg(feature = "lockdep")]
+        lockdep::release::<CPU>(self.rwlock.class, LockKind::Sleeping);
+    }
+}
+
 impl<T: ?Sized, CPU: CpuOps> Deref for AsyncRwlockReadGuard<'_, T, CPU> {
     type Target = T;
     fn deref(&self) -> &T {
@@ -105,7 +525,10 @@ impl<T: ?Sized, CPU: CpuOps> Deref for AsyncRwlockReadGuard<'_, T, CPU> {
 
 impl<T: ?Sized, CPU: CpuOps> Drop for AsyncRwlockWriteGuard<'_, T, CPU> {
     fn drop(&mut self) {
-        unsafe { self.rwlock.state.writer_lock.release() };
+        self.rwlock.release_write();
+
+        #[cfg(feature = "lockdep")]
+        lockdep::release::<CPU>(self.rwlock.class, LockKind::Sleeping);
     }
 }
 
@@ -127,4 +550,195 @@ impl<T: ?Sized, CPU: CpuOps> DerefMut for AsyncRwlockWriteGuard<'_, T, CPU> {
 }
 
 unsafe impl<T: ?Sized + Send, CPU: CpuOps> Send for Rwlock<T, CPU> {}
-unsafe impl<T: ?Sized + Send, CPU: CpuOps> Sync for Rwlock<T, CPU> {}
+unsafe impl<T: ?Sized + Send + Sync, CPU: CpuOps> Sync for Rwlock<T, CPU> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::MockCpuOps;
+    use std::sync::Arc;
+    use std::task::Waker;
+    use std::time::Duration;
+
+    fn poll_once<F: Future + Unpin>(fut: &mut F) -> Poll<F::Output> {
+        Pin::new(fut).poll(&mut Context::from_waker(Waker::noop()))
+    }
+
+    #[test]
+    fn try_read_shares_with_readers() {
+        let rwlock = Rwlock::<_, MockCpuOps>::new(5);
+
+        let r1 = rwlock.try_read().expect("uncontended read");
+        let r2 = rwlock.try_read().expect("readers share the lock");
+        assert_eq!(*r1 + *r2, 10);
+
+        assert!(rwlock.try_write().is_none());
+
+        drop(r1);
+        assert!(rwlock.try_write().is_none());
+
+        drop(r2);
+        assert!(rwlock.try_write().is_some());
+    }
+
+    #[test]
+    fn try_write_excludes_everyone() {
+        let rwlock = Rwlock::<_, MockCpuOps>::new(0);
+
+        let mut w = rwlock.try_write().expect("uncontended write");
+        *w = 1;
+
+        assert!(rwlock.try_read().is_none());
+        assert!(rwlock.try_write().is_none());
+
+        drop(w);
+        assert_eq!(*rwlock.try_read().expect("write lock released"), 1);
+    }
+
+    #[test]
+    fn reader_preferring_admits_readers_past_writers() {
+        let rwlock = Rwlock::<_, MockCpuOps>::new(());
+
+        let r1 = rwlock.try_read().unwrap();
+        let mut writer = rwlock.write();
+        assert!(poll_once(&mut writer).is_pending());
+
+        assert!(rwlock.try_read().is_some());
+
+        drop(r1);
+        assert!(poll_once(&mut writer).is_ready());
+    }
+
+    #[test]
+    fn writer_preferring_holds_back_readers() {
+        let rwlock = Rwlock::<_, MockCpuOps>::with_policy((), RwlockPolicy::WriterPreferring);
+
+        let r1 = rwlock.try_read().unwrap();
+        let mut writer = rwlock.write();
+        assert!(poll_once(&mut writer).is_pending());
+
+        let mut reader = rwlock.read();
+        assert!(poll_once(&mut reader).is_pending());
+
+        drop(r1);
+        let w = match poll_once(&mut writer) {
+            Poll::Ready(w) => w,
+            Poll::Pending => panic!("writer should take the released lock"),
+        };
+
+        assert!(poll_once(&mut reader).is_pending());
+        drop(w);
+        assert!(poll_once(&mut reader).is_ready());
+    }
+
+    #[test]
+    fn abandoned_writer_releases_readers() {
+        let rwlock = Rwlock::<_, MockCpuOps>::with_policy((), RwlockPolicy::WriterPreferring);
+
+        let r1 = rwlock.try_read().unwrap();
+        let mut writer = rwlock.write();
+        assert!(poll_once(&mut writer).is_pending());
+        assert!(rwlock.try_read().is_none());
+
+        drop(writer);
+        assert!(rwlock.try_read().is_some());
+        drop(r1);
+    }
+
+    #[test]
+    fn alternating_gives_readers_a_turn() {
+        let rwlock = Rwlock::<_, MockCpuOps>::with_policy((), RwlockPolicy::Alternating);
+
+        let w1 = rwlock.try_write().unwrap();
+        let mut writer = rwlock.write();
+        assert!(poll_once(&mut writer).is_pending());
+        let mut reader = rwlock.read();
+        assert!(poll_once(&mut reader).is_pending());
+
+        // Once the writer is done, readers go first despite the waiting
+        // writer.
+        drop(w1);
+        let r1 = match poll_once(&mut reader) {
+            Poll::Ready(r) => r,
+            Poll::Pending => panic!("reader should have its turn"),
+        };
+        let r2 = rwlock.try_read().expect("readers' turn admits new readers");
+
+        assert!(poll_once(&mut writer).is_pending());
+        drop(r1);
+        drop(r2);
+
+        // After the readers leave, the waiting writer is preferred.
+        assert!(rwlock.try_read().is_none());
+        assert!(poll_once(&mut writer).is_ready());
+    }
+
+    #[tokio::test]
+    async fn timed_acquisition_gives_up() {
+        let rwlock = Rwlock::<_, MockCpuOps>::with_policy((), RwlockPolicy::WriterPreferring);
+
+        let r1 = rwlock.read().await;
+        let result = rwlock
+            .write_before(tokio::time::sleep(Duration::from_millis(10)))
+            .await;
+        assert_eq!(result.err(), Some(Timeout));
+
+        // The writer that timed out no longer holds back readers.
+        assert!(rwlock.try_read().is_some());
+        assert!(rwlock.waiters.lock_save_irq().writers.is_empty());
+        drop(r1);
+
+        let w = rwlock.write().await;
+        let result = rwlock
+            .read_before(tokio::time::sleep(Duration::from_millis(10)))
+            .await;
+        assert_eq!(result.err(), Some(Timeout));
+        assert!(rwlock.waiters.lock_save_irq().readers.is_empty());
+        drop(w);
+
+        assert!(
+            rwlock
+                .read_before(std::future::pending::<()>())
+                .await
+                .is_ok()
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_readers_and_writers() {
+        for policy in [
+            RwlockPolicy::ReaderPreferring,
+            RwlockPolicy::WriterPreferring,
+            RwlockPolicy::Alternating,
+        ] {
+            let rwlock = Arc::new(Rwlock::<_, MockCpuOps>::with_policy((0, 0), policy));
+
+            let handles: Vec<_> = (0..16)
+                .map(|i| {
+                    let rwlock = rwlock.clone();
+
+                    tokio::spawn(async move {
+                        for _ in 0..200 {
+                            if i % 4 == 0 {
+                                let mut w = rwlock.write().await;
+                                w.0 += 1;
+                                tokio::task::yield_now().await;
+                                w.1 += 1;
+                            } else {
+                                let r = rwlock.read().await;
+                                assert_eq!(r.0, r.1, "reader saw a partial write");
+                                tokio::task::yield_now().await;
+                            }
+                        }
+                    })
+                })
+                .collect();
+
+            for handle in handles {
+                handle.await.unwrap();
+            }
+
+            assert_eq!(*rwlock.try_read().unwrap(), (800, 800));
+        }
+    }
+}
@@ -0,0 +1,344 @@
+//! Structured concurrency for kernel futures.
+//!
+//! A [`Scope`] runs a set of child futures concurrently, as part of the future
+//! which owns it. Children can borrow from their parent, since they can't
+//! outlive it: they only make progress while the scope is being awaited, and
+//! dropping the scope cancels any which haven't finished. Nothing is left
+//! running detached, so, for example, probing several devices at once can't
+//! leak a half-finished probe when one of them fails.
+//!
+//! [`join`] and [`select`] cover the common case of exactly two futures.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::future::{Future, poll_fn};
+use core::pin::{Pin, pin};
+use core::task::{Context, Poll};
+
+/// The output of [`select`]: whichever future finished first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Either<A, B> {
+    /// The first future finished first.
+    Left(A),
+    /// The second future finished first.
+    Right(B),
+}
+
+/// Drives `a` and `b` concurrently, returning both outputs once both have
+/// completed.
+pub async fn join<A: Future, B: Future>(a: A, b: B) -> (A::Output, B::Output) {
+    let mut a = pin!(a);
+    let mut b = pin!(b);
+    let mut a_out = None;
+    let mut b_out = None;
+
+    poll_fn(|cx| {
+        if a_out.is_none()
+            && let Poll::Ready(out) = a.as_mut().poll(cx)
+        {
+            a_out = Some(out);
+        }
+
+        if b_out.is_none()
+            && let Poll::Ready(out) = b.as_mut().poll(cx)
+        {
+            b_out = Some(out);
+        }
+
+        if a_out.is_some() && b_out.is_some() {
+            Poll::Ready((a_out.take().unwrap(), b_out.take().unwrap()))
+        } else {
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+/// Drives `a` and `b` concurrently, returning the output of whichever
+/// completes first. The other is dropped.
+///
+/// `a` is always polled first, so it wins if both are ready.
+pub async fn select<A: Future, B: Future>(a: A, b: B) -> Either<A::Output, B::Output> {
+    let mut a = pin!(a);
+    let mut b = pin!(b);
+
+    poll_fn(|cx| {
+        if let Poll::Ready(out) = a.as_mut().poll(cx) {
+            return Poll::Ready(Either::Left(out));
+        }
+
+        b.as_mut().poll(cx).map(Either::Right)
+    })
+    .await
+}
+
+type Child<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+enum Slot<'a, T> {
+    Running(Child<'a, T>),
+    Done(T),
+    /// The output has been handed out by [`Scope::next`].
+    Taken,
+}
+
+/// A set of child futures whose lifetimes are bound by their parent's.
+///
+/// Children are polled, in the order they were spawned, whenever the scope is
+/// awaited through [`Self::next`], [`Self::join`] or [`Self::try_join`].
+/// Dropping the scope drops, and so cancels, every unfinished child.
+pub struct Scope<'a, T> {
+    children: Vec<Slot<'a, T>>,
+    running: usize,
+}
+
+impl<T> Default for Scope<'_, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, T> Scope<'a, T> {
+    /// Creates a scope with no children.
+    pub fn new() -> Self {
+        Self {
+            children: Vec::new(),
+            running: 0,
+        }
+    }
+
+    /// Adds `fut` as a child of this scope.
+    pub fn spawn(&mut self, fut: impl Future<Output = T> + Send + 'a) {
+        self.children.push(Slot::Running(Box::pin(fut)));
+        self.running += 1;
+    }
+
+    /// Returns the number of children which haven't yet completed.
+    pub fn running(&self) -> usize {
+        self.running
+    }
+
+    /// Returns `true` if every child has completed.
+    pub fn is_done(&self) -> bool {
+        self.running == 0
+    }
+
+    /// Polls every running child once, stopping early if `stop` returns
+    /// `true` for a newly completed child. Returns whether it stopped early.
+    fn poll_children(
+        &mut self,
+        cx: &mut Context<'_>,
+        mut stop: impl FnMut(&mut Slot<'a, T>) -> bool,
+    ) -> bool {
+        for slot in self.children.iter_mut() {
+            let Slot::Running(child) = slot else {
+                continue;
+            };
+
+            if let Poll::Ready(out) = child.as_mut().poll(cx) {
+                *slot = Slot::Done(out);
+                self.running -= 1;
+
+                if stop(slot) {
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+
+    /// Waits for the next child to complete, returning its output, or `None`
+    /// if every child has already completed.
+    pub async fn next(&mut self) -> Option<T> {
+        poll_fn(|cx| {
+            if self.running == 0 {
+                return Poll::Ready(None);
+            }
+
+            let mut out = None;
+
+            if self.poll_children(cx, |slot| {
+                out = Some(take(slot));
+                true
+            }) {
+                Poll::Ready(out)
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    /// Waits for every child to complete, returning their outputs in the
+    /// order they were spawned.
+    ///
+    /// Outputs already returned by [`Self::next`] are not included.
+    pub async fn join(mut self) -> Vec<T> {
+        poll_fn(|cx| {
+            self.poll_children(cx, |_| false);
+
+            if self.running == 0 {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await;
+
+        self.children
+            .into_iter()
+            .filter_map(|slot| match slot {
+                Slot::Done(out) => Some(out),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl<U, E> Scope<'_, Result<U, E>> {
+    /// Waits for every child to succeed, returning their outputs in the order
+    /// they were spawned.
+    ///
+    /// If any child fails, its error is returned at once and the remaining
+    /// children are cancelled. Outputs already returned by [`Self::next`] are
+    /// not included.
+    pub async fn try_join(mut self) -> Result<Vec<U>, E> {
+        let mut error = None;
+
+        poll_fn(|cx| {
+            let failed = self.poll_children(cx, |slot| {
+                if matches!(slot, Slot::Done(Err(_))) {
+                    error = Some(take(slot));
+                    true
+                } else {
+                    false
+                }
+            });
+
+            if failed || self.running == 0 {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await;
+
+        if let Some(Err(e)) = error {
+            return Err(e);
+        }
+
+        self.children
+            .into_iter()
+            .filter_map(|slot| match slot {
+                Slot::Done(out) => Some(out),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+fn take<T>(slot: &mut Slot<'_, T>) -> T {
+    match core::mem::replace(slot, Slot::Taken) {
+        Slot::Done(out) => out,
+        _ => unreachable!("took the output of an unfinished child"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use tokio::time::sleep;
+
+    async fn after(ms: u64, value: usize) -> usize {
+        sleep(Duration::from_millis(ms)).await;
+        value
+    }
+
+    #[tokio::test]
+    async fn join_and_select() {
+        assert_eq!(join(after(20, 1), after(10, 2)).await, (1, 2));
+        assert_eq!(select(after(20, 1), after(10, 2)).await, Either::Right(2));
+        assert_eq!(
+            select(async { 1 }, async { 2 }).await,
+            Either::<_, usize>::Left(1)
+        );
+    }
+
+    #[tokio::test]
+    async fn scope_children_run_concurrently() {
+        let base = 100;
+        let mut scope = Scope::new();
+
+        // Children may borrow from their parent.
+        for (ms, value) in [(30, 1), (10, 2), (20, 3)] {
+            let base = &base;
+            scope.spawn(async move { after(ms, value).await + base });
+        }
+
+        assert_eq!(scope.running(), 3);
+        assert_eq!(scope.next().await, Some(102));
+        assert_eq!(scope.join().await, [101, 103]);
+
+        let mut scope = Scope::<usize>::new();
+        assert!(scope.is_done());
+        assert_eq!(scope.next().await, None);
+    }
+
+    struct DropCounter(Arc<AtomicUsize>);
+
+    impl Drop for DropCounter {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_child_cancels_its_siblings() {
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let mut scope = Scope::new();
+
+        scope.spawn(async { Ok(after(5, 1).await) });
+        scope.spawn(async { Err::<usize, _>(after(10, 2).await) });
+
+        let counter = DropCounter(dropped.clone());
+        scope.spawn(async move {
+            after(1_000, 3).await;
+            drop(counter);
+            Ok(3)
+        });
+
+        assert_eq!(scope.try_join().await, Err(2));
+        assert_eq!(dropped.load(Ordering::Relaxed), 1);
+
+        let mut scope = Scope::<Result<usize, usize>>::new();
+        scope.spawn(async { Ok(after(10, 1).await) });
+        scope.spawn(async { Ok(2) });
+        assert_eq!(scope.try_join().await, Ok(vec![1, 2]));
+    }
+
+    #[tokio::test]
+    async fn dropping_a_scope_cancels_its_children() {
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let mut scope = Scope::new();
+
+        for _ in 0..2 {
+            let counter = DropCounter(dropped.clone());
+            scope.spawn(async move {
+                sleep(Duration::from_secs(1)).await;
+                drop(counter);
+            });
+        }
+
+        assert!(
+            tokio::time::timeout(Duration::from_millis(10), scope.next())
+                .await
+                .is_err()
+        );
+        drop(scope);
+
+        assert_eq!(dropped.load(Ordering::Relaxed), 2);
+    }
+}
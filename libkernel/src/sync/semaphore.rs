@@ -0,0 +1,385 @@
+//! Async-aware counting semaphore.
+
+use super::spinlock::SpinLockIrq;
+use crate::CpuOps;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+
+struct Waiter {
+    wanted: usize,
+    waker: Waker,
+    /// Set once the permits have been taken from the pool on the waiter's
+    /// behalf.
+    granted: bool,
+}
+
+struct SemaphoreState {
+    permits: usize,
+    /// Waiters in arrival order, keyed by a ticket.
+    waiters: BTreeMap<u64, Waiter>,
+    next_ticket: u64,
+}
+
+impl SemaphoreState {
+    /// Returns `true` if `n` permits may be taken without queueing, i.e. they
+    /// are available and nobody is still waiting for theirs.
+    fn can_take(&self, n: usize) -> bool {
+        self.permits >= n && self.waiters.values().all(|w| w.granted)
+    }
+
+    /// Hands released permits to waiters in arrival order.
+    ///
+    /// Stops at the first waiter whose request can't be met, so a large
+    /// request isn't starved by a stream of small ones.
+    fn grant(&mut self) {
+        for waiter in self.waiters.values_mut().filter(|w| !w.granted) {
+            if waiter.wanted > self.permits {
+                break;
+            }
+
+            self.permits -= waiter.wanted;
+            waiter.granted = true;
+            waiter.waker.wake_by_ref();
+        }
+    }
+}
+
+/// An asynchronous counting semaphore.
+///
+/// The semaphore holds a pool of permits. `acquire(n)` waits until `n` permits
+/// are available and takes them, returning a [`SemaphorePermit`] which puts
+/// them back when dropped. Waiters are served in FIFO order.
+pub struct Semaphore<CPU: CpuOps> {
+    state: SpinLockIrq<SemaphoreState, CPU>,
+}
+
+/// Permits taken from a [`Semaphore`], returned to it when dropped.
+#[must_use = "if unused, the permits will immediately be released"]
+pub struct SemaphorePermit<'a, CPU: CpuOps> {
+    sem: &'a Semaphore<CPU>,
+    count: usize,
+}
+
+/// Permits taken from a reference-counted [`Semaphore`], returned to it when
+/// dropped.
+///
+/// Unlike [`SemaphorePermit`], this doesn't borrow the semaphore, so it can be
+/// stored alongside the request it bounds.
+#[must_use = "if unused, the permits will immediately be released"]
+pub struct OwnedSemaphorePermit<CPU: CpuOps> {
+    sem: Arc<Semaphore<CPU>>,
+    count: usize,
+}
+
+/// A future that resolves to a [`SemaphorePermit`] once the permits have been
+/// acquired.
+pub struct AcquireFuture<'a, CPU: CpuOps> {
+    sem: &'a Semaphore<CPU>,
+    wanted: usize,
+    /// Our place in the queue, if we are waiting.
+    ticket: Option<u64>,
+}
+
+impl<CPU: CpuOps> Semaphore<CPU> {
+    /// Creates a semaphore holding `permits` permits.
+    #[track_caller]
+    pub const fn new(permits: usize) -> Self {
+        Self {
+            state: SpinLockIrq::new(SemaphoreState {
+                permits,
+                waiters: BTreeMap::new(),
+                next_ticket: 0,
+            }),
+        }
+    }
+
+    /// Returns the number of permits currently available.
+    pub fn available_permits(&self) -> usize {
+        self.state.lock_save_irq().permits
+    }
+
+    /// Acquires `n` permits.
+    ///
+    /// Returns a future that resolves to a permit once `n` permits are
+    /// available and every earlier waiter has been served.
+    pub fn acquire(&self, n: usize) -> AcquireFuture<'_, CPU> {
+        AcquireFuture {
+            sem: self,
+            wanted: n,
+            ticket: None,
+        }
+    }
+
+    /// Acquires `n` permits, returning a permit which keeps the semaphore
+    /// alive.
+    pub async fn acquire_owned(self: Arc<Self>, n: usize) -> OwnedSemaphorePermit<CPU> {
+        self.acquire(n).await.forget();
+
+        OwnedSemaphorePermit {
+            sem: self,
+            count: n,
+        }
+    }
+
+    /// Attempts to acquire `n` permits without waiting.
+    ///
+    /// Returns `None` if there aren't enough permits, or if other tasks are
+    /// already waiting for them.
+    pub fn try_acquire(&self, n: usize) -> Option<SemaphorePermit<'_, CPU>> {
+        let mut state = self.state.lock_save_irq();
+
+        if !state.can_take(n) {
+            return None;
+        }
+
+        state.permits -= n;
+
+        Some(SemaphorePermit {
+            sem: self,
+            count: n,
+        })
+    }
+
+    /// Adds `n` permits to the semaphore, waking any waiters they satisfy.
+    ///
+    /// This is how dropped permits are returned, but may also be used to grow
+    /// the pool, or to return permits given up with
+    /// [`SemaphorePermit::forget`].
+    pub fn release(&self, n: usize) {
+        let mut state = self.state.lock_save_irq();
+
+        state.permits += n;
+        state.grant();
+    }
+}
+
+impl<CPU: CpuOps> SemaphorePermit<'_, CPU> {
+    /// Returns the number of permits held.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Drops the permit without returning its permits to the semaphore,
+    /// shrinking the pool.
+    pub fn forget(mut self) {
+        self.count = 0;
+    }
+}
+
+impl<CPU: CpuOps> Drop for SemaphorePermit<'_, CPU> {
+    fn drop(&mut self) {
+        if self.count > 0 {
+            self.sem.release(self.count);
+        }
+    }
+}
+
+impl<CPU: CpuOps> OwnedSemaphorePermit<CPU> {
+    /// Returns the number of permits held.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Drops the permit without returning its permits to the semaphore,
+    /// shrinking the pool.
+    pub fn forget(mut self) {
+        self.count = 0;
+    }
+}
+
+impl<CPU: CpuOps> Drop for OwnedSemaphorePermit<CPU> {
+    fn drop(&mut self) {
+        if self.count > 0 {
+            self.sem.release(self.count);
+        }
+    }
+}
+
+impl<'a, CPU: CpuOps> Future for AcquireFuture<'a, CPU> {
+    type Output = SemaphorePermit<'a, CPU>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let sem = self.sem;
+        let count = self.wanted;
+        let mut state = sem.state.lock_save_irq();
+
+        match self.ticket {
+            Some(ticket) => {
+                let waiter = state
+                    .waiters
+                    .get_mut(&ticket)
+                    .expect("queued waiter missing from semaphore");
+
+                if !waiter.granted {
+                    waiter.waker.clone_from(cx.waker());
+                    return Poll::Pending;
+                }
+
+                state.waiters.remove(&ticket);
+                self.ticket = None;
+            }
+            None => {
+                if !state.can_take(count) {
+                    let ticket = state.next_ticket;
+                    state.next_ticket = state.next_ticket.wrapping_add(1);
+
+                    state.waiters.insert(
+                        ticket,
+                        Waiter {
+                            wanted: count,
+                            waker: cx.waker().clone(),
+                            granted: false,
+                        },
+                    );
+                    self.ticket = Some(ticket);
+
+                    return Poll::Pending;
+                }
+
+                state.permits -= count;
+            }
+        }
+
+        Poll::Ready(SemaphorePermit { sem, count })
+    }
+}
+
+impl<CPU: CpuOps> Drop for AcquireFuture<'_, CPU> {
+    fn drop(&mut self) {
+        let Some(ticket) = self.ticket else {
+            return;
+        };
+
+        let mut state = self.sem.state.lock_save_irq();
+
+        if let Some(waiter) = state.waiters.remove(&ticket) {
+            if waiter.granted {
+                state.permits += waiter.wanted;
+            }
+
+            // Either we gave permits back, or we were blocking the waiters
+            // queued behind us.
+            state.grant();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::MockCpuOps;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    fn poll_once<F: Future + Unpin>(fut: &mut F) -> Poll<F::Output> {
+        Pin::new(fut).poll(&mut Context::from_waker(Waker::noop()))
+    }
+
+    #[test]
+    fn try_acquire_counts_permits() {
+        let sem = Semaphore::<MockCpuOps>::new(4);
+
+        let p1 = sem.try_acquire(3).expect("enough permits");
+        assert_eq!(p1.count(), 3);
+        assert_eq!(sem.available_permits(), 1);
+        assert!(sem.try_acquire(2).is_none());
+
+        drop(p1);
+        assert_eq!(sem.available_permits(), 4);
+
+        sem.try_acquire(1).unwrap().forget();
+        assert_eq!(sem.available_permits(), 3);
+
+        sem.release(2);
+        assert_eq!(sem.available_permits(), 5);
+    }
+
+    #[test]
+    fn waiters_are_served_in_order() {
+        let sem = Semaphore::<MockCpuOps>::new(2);
+
+        let held = sem.try_acquire(2).unwrap();
+        let mut big = sem.acquire(2);
+        let mut small = sem.acquire(1);
+        assert!(poll_once(&mut big).is_pending());
+        assert!(poll_once(&mut small).is_pending());
+
+        // A lone permit doesn't let the small request overtake the big one.
+        sem.release(1);
+        assert!(poll_once(&mut small).is_pending());
+        assert!(sem.try_acquire(1).is_none());
+
+        drop(held);
+        let big = match poll_once(&mut big) {
+            Poll::Ready(p) => p,
+            Poll::Pending => panic!("big request should have been granted"),
+        };
+        let small = match poll_once(&mut small) {
+            Poll::Ready(p) => p,
+            Poll::Pending => panic!("small request should have been granted"),
+        };
+        assert_eq!(big.count() + small.count(), 3);
+        assert_eq!(sem.available_permits(), 0);
+    }
+
+    #[test]
+    fn abandoned_waiter_unblocks_queue() {
+        let sem = Semaphore::<MockCpuOps>::new(1);
+
+        let mut big = sem.acquire(2);
+        let mut small = sem.acquire(1);
+        assert!(poll_once(&mut big).is_pending());
+        assert!(poll_once(&mut small).is_pending());
+
+        drop(big);
+        assert!(poll_once(&mut small).is_ready());
+        assert_eq!(sem.available_permits(), 1);
+    }
+
+    #[test]
+    fn abandoned_grant_is_returned() {
+        let sem = Semaphore::<MockCpuOps>::new(1);
+
+        let held = sem.try_acquire(1).unwrap();
+        let mut waiter = sem.acquire(1);
+        assert!(poll_once(&mut waiter).is_pending());
+
+        // The permit is handed to the waiter, which then gives up.
+        drop(held);
+        drop(waiter);
+        assert_eq!(sem.available_permits(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn bounds_concurrency() {
+        let sem = Arc::new(Semaphore::<MockCpuOps>::new(3));
+        let active = Arc::new(AtomicUsize::new(0));
+
+        let handles: Vec<_> = (0..12)
+            .map(|i| {
+                let sem = sem.clone();
+                let active = active.clone();
+
+                tokio::spawn(async move {
+                    let n = i % 3 + 1;
+                    let permit = sem.acquire_owned(n).await;
+
+                    let now = active.fetch_add(n, Ordering::SeqCst) + n;
+                    assert!(now <= 3, "{now} permits in use");
+
+                    tokio::time::sleep(Duration::from_millis(1)).await;
+                    active.fetch_sub(permit.count(), Ordering::SeqCst);
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.await.unwrap();
+        }
+
+        assert_eq!(sem.available_permits(), 3);
+    }
+}
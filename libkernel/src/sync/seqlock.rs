@@ -0,0 +1,157 @@
+//! Sequence lock for small, frequently read data.
+
+use super::spinlock::SpinLockIrq;
+use crate::CpuOps;
+use core::cell::UnsafeCell;
+use core::hint::spin_loop;
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering, fence};
+
+/// A sequence lock.
+///
+/// Readers never block writers and never write to shared memory: they copy
+/// the data out and retry if a writer was active meanwhile. Writers are
+/// serialised by a spinlock but never wait for readers. This suits small,
+/// `Copy` data which is read far more often than it is written, such as the
+/// timekeeper's epoch.
+///
+/// The sequence count is odd while a write is in progress.
+pub struct SeqLock<T: Copy, CPU: CpuOps> {
+    seq: AtomicUsize,
+    writer: SpinLockIrq<(), CPU>,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Copy + Send, CPU: CpuOps> Send for SeqLock<T, CPU> {}
+unsafe impl<T: Copy + Send, CPU: CpuOps> Sync for SeqLock<T, CPU> {}
+
+impl<T: Copy, CPU: CpuOps> SeqLock<T, CPU> {
+    /// Creates a new sequence lock holding `data`.
+    #[track_caller]
+    pub const fn new(data: T) -> Self {
+        Self {
+            seq: AtomicUsize::new(0),
+            writer: SpinLockIrq::new(()),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Returns a consistent copy of the data, retrying while a write is in
+    /// progress.
+    pub fn read(&self) -> T {
+        loop {
+            let seq = self.begin_read();
+
+            // SAFETY: A concurrent write may tear this copy, but `T` is
+            // `Copy`, so has no invariants to break, and a torn copy is
+            // discarded below.
+            let data = unsafe { ptr::read_volatile(self.data.get()) };
+
+            if !self.retry_read(seq) {
+                return data;
+            }
+        }
+    }
+
+    /// Starts a read, returning the sequence count to validate it with.
+    fn begin_read(&self) -> usize {
+        loop {
+            let seq = self.seq.load(Ordering::Acquire);
+
+            if seq & 1 == 0 {
+                return seq;
+            }
+
+            spin_loop();
+        }
+    }
+
+    /// Returns `true` if a write has started since `seq` was read.
+    fn retry_read(&self, seq: usize) -> bool {
+        // Order the data reads before the re-check of the count.
+        fence(Ordering::Acquire);
+
+        self.seq.load(Ordering::Relaxed) != seq
+    }
+
+    /// Updates the data with `f`.
+    ///
+    /// Interrupts are disabled for the duration, so that a reader in an
+    /// interrupt handler on this CPU can't spin forever on the odd count.
+    pub fn write<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        let _guard = self.writer.lock_save_irq();
+
+        self.seq.fetch_add(1, Ordering::Relaxed);
+        // Order the odd count before the data writes.
+        fence(Ordering::Release);
+
+        // SAFETY: Writers are serialised by `writer`, and readers discard
+        // anything they read while the count is odd.
+        let result = f(unsafe { &mut *self.data.get() });
+
+        self.seq.fetch_add(1, Ordering::Release);
+
+        result
+    }
+
+    /// Replaces the data with `data`.
+    pub fn set(&self, data: T) {
+        self.write(|d| *d = data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::MockCpuOps;
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicBool;
+    use std::thread;
+
+    #[test]
+    fn read_sees_writes() {
+        let lock = SeqLock::<_, MockCpuOps>::new((1, 2));
+
+        assert_eq!(lock.read(), (1, 2));
+
+        let sum = lock.write(|d| {
+            d.0 = 3;
+            d.0 + d.1
+        });
+        assert_eq!(sum, 5);
+        assert_eq!(lock.read(), (3, 2));
+
+        lock.set((4, 4));
+        assert_eq!(lock.read(), (4, 4));
+    }
+
+    #[test]
+    fn readers_never_see_torn_writes() {
+        let lock = Arc::new(SeqLock::<_, MockCpuOps>::new([0u64; 8]));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let readers: Vec<_> = (0..3)
+            .map(|_| {
+                let lock = lock.clone();
+                let stop = stop.clone();
+
+                thread::spawn(move || {
+                    while !stop.load(Ordering::Relaxed) {
+                        let data = lock.read();
+                        assert!(data.iter().all(|&v| v == data[0]), "torn read");
+                    }
+                })
+            })
+            .collect();
+
+        for i in 1..=100_000 {
+            lock.set([i; 8]);
+        }
+
+        stop.store(true, Ordering::Relaxed);
+
+        for reader in readers {
+            reader.join().unwrap();
+        }
+    }
+}
@@ -8,12 +8,17 @@ use core::sync::atomic::{AtomicBool, Ordering};
 
 use crate::CpuOps;
 
+#[cfg(feature = "lockdep")]
+use super::lockdep::{self, LockClass, LockKind};
+
 /// A spinlock that also disables interrupts on the local core while held.
 ///
 /// This prevents deadlocks with interrupt handlers on the same core and
 /// provides SMP-safety against other cores.
 pub struct SpinLockIrq<T: ?Sized, CPU: CpuOps> {
     lock: AtomicBool,
+    #[cfg(feature = "lockdep")]
+    class: LockClass,
     _phantom: PhantomData<CPU>,
     data: UnsafeCell<T>,
 }
@@ -23,9 +28,15 @@ unsafe impl<T: ?Sized + Send, CPU: CpuOps> Sync for SpinLockIrq<T, CPU> {}
 
 impl<T, CPU: CpuOps> SpinLockIrq<T, CPU> {
     /// Creates a new IRQ-safe spinlock.
+    ///
+    /// With the `lockdep` feature, the caller's location identifies the lock's
+    /// class.
+    #[track_caller]
     pub const fn new(data: T) -> Self {
         Self {
             lock: AtomicBool::new(false),
+            #[cfg(feature = "lockdep")]
+            class: core::panic::Location::caller(),
             _phantom: PhantomData,
             data: UnsafeCell::new(data),
         }
@@ -38,6 +49,11 @@ impl<T: ?Sized, CPU: CpuOps> SpinLockIrq<T, CPU> {
     pub fn lock_save_irq(&self) -> SpinLockIrqGuard<'_, T, CPU> {
         let saved_irq_flags = CPU::disable_interrupts();
 
+        // Validate before spinning, so that a deadlock is reported rather than
+        // just hanging.
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire::<CPU>(self.class, LockKind::Spin);
+
         while self
             .lock
             .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
@@ -95,6 +111,9 @@ impl<'a, T: ?Sized, CPU: CpuOps> Drop for SpinLockIrqGuard<'a, T, CPU> {
     fn drop(&mut self) {
         self.lock.lock.store(false, Ordering::Release);
 
+        #[cfg(feature = "lockdep")]
+        lockdep::release::<CPU>(self.lock.class, LockKind::Spin);
+
         CPU::restore_interrupt_state(self.irq_flags);
     }
 }
@@ -0,0 +1,29 @@
+//! Bounding how long a lock acquisition may wait.
+
+use core::future::Future;
+
+use super::scope::{Either, select};
+
+use crate::error::KernelError;
+
+/// The error returned when a lock could not be acquired before its deadline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeout;
+
+impl From<Timeout> for KernelError {
+    fn from(_: Timeout) -> Self {
+        KernelError::TimedOut
+    }
+}
+
+/// Drives `fut` to completion unless `timer` completes first.
+///
+/// `fut` is always polled before `timer`, so an acquisition which is ready
+/// when the deadline passes still succeeds. If `timer` wins, `fut` is dropped,
+/// abandoning its place in the queue.
+pub(crate) async fn before<F: Future, D: Future>(fut: F, timer: D) -> Result<F::Output, Timeout> {
+    match select(fut, timer).await {
+        Either::Left(output) => Ok(output),
+        Either::Right(_) => Err(Timeout),
+    }
+}
@@ -0,0 +1,540 @@
+//! Kernel timers, built on a hierarchical timer wheel.
+//!
+//! [`TimerWheel`] is the bookkeeping: it holds values keyed by an expiry
+//! time, in ticks, and hands them back once that time has been reached.
+//! [`Timers`] wraps a wheel of [`Waker`]s to provide [`Timers::sleep`] and
+//! [`Timers::timeout`], so that everything which waits on the clock — sleeps,
+//! socket and lock timeouts, retransmissions — shares one set of timers.
+//!
+//! The wheel has [`LEVELS`] levels of [`SLOTS`] slots each. A slot at level
+//! `n` spans `SLOTS^n` ticks, so the levels cover ever longer, ever coarser
+//! ranges. A timer is placed in the finest level whose range covers its
+//! expiry; as time passes, the slot it lands in comes due, and the timer
+//! cascades down to a finer level until it reaches level 0 and expires.
+//! Insertion and removal are cheap, and time can jump forward arbitrarily far
+//! (e.g. after idling with a one-shot hardware timer) without stepping through
+//! every tick in between.
+
+use super::spinlock::SpinLockIrq;
+use super::timeout::{Timeout, before};
+use crate::CpuOps;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+use core::time::Duration;
+
+const SLOT_BITS: u32 = 6;
+
+/// The number of slots in each level of the wheel.
+pub const SLOTS: usize = 1 << SLOT_BITS;
+
+/// The number of levels in the wheel.
+pub const LEVELS: usize = 6;
+
+/// Timers further out than this are parked in the top level, and re-placed
+/// as time approaches their expiry.
+const MAX_DELTA: u64 = (1 << (SLOT_BITS * LEVELS as u32)) - 1;
+
+/// Identifies a timer in a [`TimerWheel`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TimerId(u64);
+
+struct Entry<T> {
+    expires: u64,
+    level: usize,
+    slot: usize,
+    value: T,
+}
+
+struct Level {
+    /// Bit `n` is set if slot `n` holds any timers.
+    occupied: u64,
+    slots: [Vec<TimerId>; SLOTS],
+}
+
+impl Level {
+    const fn new() -> Self {
+        Self {
+            occupied: 0,
+            slots: [const { Vec::new() }; SLOTS],
+        }
+    }
+}
+
+/// A hierarchical timer wheel holding values of type `T`.
+pub struct TimerWheel<T> {
+    /// The time, in ticks, up to which the wheel has been advanced.
+    elapsed: u64,
+    levels: [Level; LEVELS],
+    entries: BTreeMap<TimerId, Entry<T>>,
+    next_id: u64,
+}
+
+impl<T> Default for TimerWheel<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> TimerWheel<T> {
+    /// Creates an empty wheel at tick zero.
+    pub const fn new() -> Self {
+        Self {
+            elapsed: 0,
+            levels: [const { Level::new() }; LEVELS],
+            entries: BTreeMap::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Returns the tick up to which the wheel has been advanced.
+    pub fn elapsed(&self) -> u64 {
+        self.elapsed
+    }
+
+    /// Returns the number of pending timers.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if no timers are pending.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Adds a timer which expires at tick `expires`.
+    ///
+    /// A timer which has already expired is handed back by the next call to
+    /// [`Self::advance`].
+    pub fn insert(&mut self, expires: u64, value: T) -> TimerId {
+        let id = TimerId(self.next_id);
+        self.next_id += 1;
+
+        let expires = expires.max(self.elapsed);
+        let (level, slot) = self.place(expires);
+
+        self.levels[level].slots[slot].push(id);
+        self.levels[level].occupied |= 1 << slot;
+        self.entries.insert(
+            id,
+            Entry {
+                expires,
+                level,
+                slot,
+                value,
+            },
+        );
+
+        id
+    }
+
+    /// Removes a pending timer, returning its value.
+    pub fn remove(&mut self, id: TimerId) -> Option<T> {
+        let entry = self.entries.remove(&id)?;
+        let level = &mut self.levels[entry.level];
+        let slot = &mut level.slots[entry.slot];
+
+        if let Some(pos) = slot.iter().position(|&i| i == id) {
+            slot.swap_remove(pos);
+        }
+
+        if slot.is_empty() {
+            level.occupied &= !(1 << entry.slot);
+        }
+
+        Some(entry.value)
+    }
+
+    /// Returns the earliest tick at which a timer may expire, or `None` if
+    /// there are no timers.
+    ///
+    /// This can be earlier than any timer's actual expiry, when the next
+    /// event is a coarse slot whose timers must be cascaded; advancing to it
+    /// then expires nothing. It is never later.
+    pub fn next_expiry(&self) -> Option<u64> {
+        self.next_slot().map(|(_, _, deadline)| deadline)
+    }
+
+    /// Advances the wheel to tick `now`, passing each timer which has expired
+    /// to `expired`, in expiry order.
+    pub fn advance(&mut self, now: u64, mut expired: impl FnMut(T)) {
+        while let Some((level, slot, deadline)) = self.next_slot() {
+            if deadline > now {
+                break;
+            }
+
+            self.elapsed = deadline;
+            self.levels[level].occupied &= !(1 << slot);
+
+            for id in core::mem::take(&mut self.levels[level].slots[slot]) {
+                if level == 0 {
+                    expired(self.entries.remove(&id).unwrap().value);
+                } else {
+                    // Cascade into a finer level.
+                    let entry = self.entries.get(&id).unwrap();
+                    let (level, slot) = self.place(entry.expires);
+                    let entry = self.entries.get_mut(&id).unwrap();
+
+                    entry.level = level;
+                    entry.slot = slot;
+                    self.levels[level].slots[slot].push(id);
+                    self.levels[level].occupied |= 1 << slot;
+                }
+            }
+        }
+
+        self.elapsed = self.elapsed.max(now);
+    }
+
+    /// Returns the level and slot in which to place a timer expiring at
+    /// `expires`, which must not be before `elapsed`.
+    fn place(&self, expires: u64) -> (usize, usize) {
+        let expires = expires.min(self.elapsed.saturating_add(MAX_DELTA));
+
+        // The highest bit in which the expiry differs from now selects the
+        // level. Or-ing in the slot mask puts anything due in the current
+        // slot into level 0. Anything beyond the top level's current rotation
+        // waits in the top level until it comes round again.
+        let differing = (self.elapsed ^ expires) | (SLOTS as u64 - 1);
+        let level = ((63 - differing.leading_zeros()) / SLOT_BITS) as usize;
+        let level = level.min(LEVELS - 1);
+
+        (level, Self::slot_of(expires, level))
+    }
+
+    fn slot_of(tick: u64, level: usize) -> usize {
+        ((tick >> (level as u32 * SLOT_BITS)) as usize) & (SLOTS - 1)
+    }
+
+    /// Finds the next occupied slot, returning its level, index and the tick
+    /// at which it comes due.
+    ///
+    /// Every timer in a level is due before any in the level above, so the
+    /// first occupied slot from the finest level is the next due.
+    fn next_slot(&self) -> Option<(usize, usize, u64)> {
+        self.levels.iter().enumerate().find_map(|(idx, level)| {
+            if level.occupied == 0 {
+                return None;
+            }
+
+            let shift = idx as u32 * SLOT_BITS;
+            let current = Self::slot_of(self.elapsed, idx);
+            let slot = (level.occupied.rotate_right(current as u32).trailing_zeros() as usize
+                + current)
+                % SLOTS;
+
+            let level_range = 1u64 << (shift + SLOT_BITS);
+            let level_start = self.elapsed & !(level_range - 1);
+            let mut deadline = level_start + ((slot as u64) << shift);
+
+            // The slot is behind us in this rotation of the level, so is due
+            // in the next.
+            if deadline < self.elapsed {
+                deadline += level_range;
+            }
+
+            Some((idx, slot, deadline))
+        })
+    }
+}
+
+/// A set of timers, measured against a monotonic clock.
+///
+/// The owner supplies the clock, and a hook to program the hardware timer
+/// whenever a timer is added which brings the wheel's next event forward. It
+/// must also call [`Timers::expire`] from the hardware timer's interrupt, and
+/// program the timer for the deadline it returns.
+pub struct Timers<CPU: CpuOps> {
+    wheel: SpinLockIrq<TimerWheel<Waker>, CPU>,
+    clock: fn() -> Duration,
+    arm: fn(Duration),
+}
+
+const TICK_NANOS: u64 = 1_000;
+
+/// The resolution of [`Timers`].
+pub const TICK: Duration = Duration::from_nanos(TICK_NANOS);
+
+fn to_ticks(time: Duration) -> u64 {
+    // Round up, so that a timer never fires early.
+    time.as_nanos()
+        .div_ceil(TICK_NANOS as u128)
+        .try_into()
+        .unwrap_or(u64::MAX)
+}
+
+fn from_ticks(ticks: u64) -> Duration {
+    Duration::from_nanos(ticks.saturating_mul(TICK_NANOS))
+}
+
+impl<CPU: CpuOps> Timers<CPU> {
+    /// Creates a set of timers driven by `clock`, which returns the time
+    /// since boot. `arm` is called with the wheel's next event whenever a new
+    /// timer brings it forward.
+    #[track_caller]
+    pub const fn new(clock: fn() -> Duration, arm: fn(Duration)) -> Self {
+        Self {
+            wheel: SpinLockIrq::new(TimerWheel::new()),
+            clock,
+            arm,
+        }
+    }
+
+    /// Returns the current time, according to the clock.
+    pub fn now(&self) -> Duration {
+        (self.clock)()
+    }
+
+    /// Wakes every timer which has expired, returning when the wheel's next
+    /// event is due, if there is one.
+    ///
+    /// The next event may be earlier than any timer's deadline, when timers
+    /// must be cascaded to a finer level of the wheel (see
+    /// [`TimerWheel::next_expiry`]).
+    ///
+    /// This should be called from the hardware timer's interrupt handler.
+    pub fn expire(&self) -> Option<Duration> {
+        let now = to_ticks(self.now());
+        let mut expired = Vec::new();
+
+        let next = {
+            let mut wheel = self.wheel.lock_save_irq();
+
+            wheel.advance(now, |waker| expired.push(waker));
+            wheel.next_expiry()
+        };
+
+        // Wake outside the lock, as a woken task may add a timer.
+        for waker in expired {
+            waker.wake();
+        }
+
+        next.map(from_ticks)
+    }
+
+    /// Returns when the wheel's next event is due, if there is one.
+    pub fn next_deadline(&self) -> Option<Duration> {
+        self.wheel.lock_save_irq().next_expiry().map(from_ticks)
+    }
+
+    fn register(&self, deadline: Duration, waker: Waker) -> TimerId {
+        let ticks = to_ticks(deadline);
+
+        let (id, next) = {
+            let mut wheel = self.wheel.lock_save_irq();
+            let prev = wheel.next_expiry();
+            let id = wheel.insert(ticks, waker);
+            let next = wheel.next_expiry();
+
+            (id, next.filter(|_| next != prev))
+        };
+
+        if let Some(next) = next {
+            (self.arm)(from_ticks(next));
+        }
+
+        id
+    }
+
+    fn cancel(&self, id: TimerId) {
+        self.wheel.lock_save_irq().remove(id);
+    }
+
+    /// Returns a future which completes once `duration` has passed.
+    pub fn sleep(&self, duration: Duration) -> Sleep<'_, CPU> {
+        self.sleep_until(self.now() + duration)
+    }
+
+    /// Returns a future which completes once the clock reaches `deadline`.
+    pub fn sleep_until(&self, deadline: Duration) -> Sleep<'_, CPU> {
+        Sleep {
+            timers: self,
+            deadline,
+            timer: None,
+        }
+    }
+
+    /// Drives `fut` to completion, unless `duration` passes first.
+    pub async fn timeout<F: Future>(
+        &self,
+        fut: F,
+        duration: Duration,
+    ) -> Result<F::Output, Timeout> {
+        before(fut, self.sleep(duration)).await
+    }
+}
+
+/// The future returned by [`Timers::sleep`] and [`Timers::sleep_until`].
+///
+/// Dropping it cancels the underlying timer.
+pub struct Sleep<'a, CPU: CpuOps> {
+    timers: &'a Timers<CPU>,
+    deadline: Duration,
+    timer: Option<TimerId>,
+}
+
+impl<CPU: CpuOps> Sleep<'_, CPU> {
+    /// Returns the time at which the sleep completes.
+    pub fn deadline(&self) -> Duration {
+        self.deadline
+    }
+}
+
+impl<CPU: CpuOps> Future for Sleep<'_, CPU> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if let Some(id) = self.timer.take() {
+            self.timers.cancel(id);
+        }
+
+        if self.timers.now() >= self.deadline {
+            return Poll::Ready(());
+        }
+
+        // Re-register on every poll, in case we've been moved to another
+        // task.
+        let id = self.timers.register(self.deadline, cx.waker().clone());
+        self.timer = Some(id);
+
+        Poll::Pending
+    }
+}
+
+impl<CPU: CpuOps> Drop for Sleep<'_, CPU> {
+    fn drop(&mut self) {
+        if let Some(id) = self.timer.take() {
+            self.timers.cancel(id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::MockCpuOps;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    fn expire(wheel: &mut TimerWheel<u64>, now: u64) -> Vec<u64> {
+        let mut expired = Vec::new();
+        wheel.advance(now, |v| expired.push(v));
+        expired
+    }
+
+    #[test]
+    fn timers_expire_in_order() {
+        let mut wheel = TimerWheel::new();
+
+        // Spread across several levels, inserted out of order.
+        for t in [5_000_000, 3, 70, 64, 1 << 20, 0, 4095] {
+            wheel.insert(t, t);
+        }
+
+        assert_eq!(expire(&mut wheel, 63), [0, 3]);
+        assert_eq!(expire(&mut wheel, 4095), [64, 70, 4095]);
+        assert_eq!(expire(&mut wheel, 1 << 20), [1 << 20]);
+        assert_eq!(wheel.next_expiry().map(|t| t <= 5_000_000), Some(true));
+        assert_eq!(expire(&mut wheel, 4_999_999), []);
+        assert_eq!(expire(&mut wheel, u64::MAX / 2), [5_000_000]);
+        assert!(wheel.is_empty());
+        assert_eq!(wheel.next_expiry(), None);
+    }
+
+    #[test]
+    fn timers_never_fire_early_or_late() {
+        let mut wheel = TimerWheel::new();
+        let mut seed = 0x1234_5678_u64;
+        let mut rand = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+
+        let mut now = 0;
+
+        for _ in 0..2000 {
+            let when = now + rand() % (1 << (rand() % 40));
+            wheel.insert(when, when);
+
+            if rand() % 4 == 0 {
+                let step = rand() % (1 << (rand() % 30));
+
+                // Step through each expiry in turn, checking that nothing
+                // fires before it's due, or stays past it.
+                while let Some(next) = wheel.next_expiry().filter(|&t| t <= now + step) {
+                    for t in expire(&mut wheel, next) {
+                        assert_eq!(t, next, "timer expired at the wrong time");
+                    }
+                }
+
+                now += step;
+                expire(&mut wheel, now);
+            }
+        }
+    }
+
+    #[test]
+    fn removed_timers_dont_fire() {
+        let mut wheel = TimerWheel::new();
+        let a = wheel.insert(100, 1);
+        wheel.insert(200, 2);
+
+        assert_eq!(wheel.remove(a), Some(1));
+        assert_eq!(wheel.remove(a), None);
+        assert_eq!(wheel.len(), 1);
+        assert_eq!(expire(&mut wheel, 1000), [2]);
+    }
+
+    #[test]
+    fn far_future_timers_are_parked() {
+        let mut wheel = TimerWheel::new();
+        let far = MAX_DELTA * 3 + 17;
+
+        wheel.insert(far, far);
+
+        assert_eq!(expire(&mut wheel, far - 1), []);
+        assert_eq!(expire(&mut wheel, far), [far]);
+    }
+
+    static CLOCK_US: AtomicU64 = AtomicU64::new(0);
+    static ARMED_US: AtomicU64 = AtomicU64::new(0);
+
+    fn clock() -> Duration {
+        Duration::from_micros(CLOCK_US.load(Ordering::SeqCst))
+    }
+
+    fn arm(at: Duration) {
+        ARMED_US.store(at.as_micros() as u64, Ordering::SeqCst);
+    }
+
+    #[tokio::test]
+    async fn sleep_and_timeout() {
+        static TIMERS: Timers<MockCpuOps> = Timers::new(clock, arm);
+
+        let sleeper = tokio::spawn(TIMERS.sleep(Duration::from_millis(5)));
+        let timeout =
+            tokio::spawn(TIMERS.timeout(core::future::pending::<()>(), Duration::from_millis(10)));
+        let quick = tokio::spawn(TIMERS.timeout(async { 7 }, Duration::from_millis(1)));
+
+        assert_eq!(quick.await.unwrap(), Ok(7));
+
+        tokio::task::yield_now().await;
+
+        // The hardware timer must fire no later than the first deadline.
+        let armed = ARMED_US.load(Ordering::SeqCst);
+        assert!(armed > 0 && armed <= 5_000);
+        assert_eq!(TIMERS.next_deadline(), Some(Duration::from_micros(armed)));
+
+        CLOCK_US.store(5_000, Ordering::SeqCst);
+        let next = TIMERS.expire().unwrap();
+        assert!(next <= Duration::from_millis(10));
+        sleeper.await.unwrap();
+        assert!(!timeout.is_finished());
+
+        CLOCK_US.store(10_000, Ordering::SeqCst);
+        assert_eq!(TIMERS.expire(), None);
+        assert_eq!(timeout.await.unwrap(), Err(Timeout));
+    }
+}
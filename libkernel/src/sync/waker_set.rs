@@ -162,14 +162,23 @@ where
 
         // Check the condition first.
         if let Some(result) = (this.predicate)(&mut inner) {
+            if let Some(token) = this.token.take() {
+                (this.get_waker_set)(&mut inner).remove(token);
+            }
+
             return Poll::Ready(result);
         }
 
-        // If the condition is not met, register our waker if we haven't already.
-        if this.token.is_none() {
-            let waker_set = (this.get_waker_set)(&mut inner);
-            let id = waker_set.register(cx.waker());
-            this.token = Some(id);
+        // If the condition is not met, register our waker, unless it is still
+        // registered from a previous poll. A woken waker has been removed from
+        // the set, so must be registered afresh.
+        let waker_set = (this.get_waker_set)(&mut inner);
+
+        if this
+            .token
+            .is_none_or(|token| !waker_set.contains_token(token))
+        {
+            this.token = Some(waker_set.register(cx.waker()));
         }
 
         Poll::Pending
@@ -188,7 +197,14 @@ where
         if let Some(token) = self.token {
             let mut inner = self.lock.lock_save_irq();
             let waker_set = (self.get_waker_set)(&mut inner);
-            waker_set.remove(token);
+
+            if waker_set.contains_token(token) {
+                waker_set.remove(token);
+            } else {
+                // We were woken but never acted on it; pass the wakeup on so
+                // it isn't lost.
+                waker_set.wake_one();
+            }
         }
     }
 }
@@ -0,0 +1,222 @@
+//! A single-producer, multi-consumer channel that broadcasts the latest value.
+//!
+//! A [`Publisher`] holds a piece of state, such as a link's carrier status or
+//! the mount table's generation, and any number of [`Subscriber`]s wait for it
+//! to change. Subscribers only ever see the most recent value: if several
+//! updates are published before a subscriber looks, it sees the last one.
+
+use super::spinlock::SpinLockIrq;
+use super::waker_set::{WakerSet, wait_until};
+use crate::CpuOps;
+use alloc::sync::Arc;
+
+struct WatchState<T> {
+    value: T,
+    /// Bumped on every update, so subscribers can tell what they have seen.
+    version: u64,
+    /// Set once the publisher has been dropped.
+    closed: bool,
+    waiters: WakerSet,
+}
+
+/// The error returned by [`Subscriber::changed`] once the publisher has gone
+/// and no unseen update remains.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Closed;
+
+/// The publishing half of a watch channel.
+///
+/// Dropping the publisher closes the channel, waking every subscriber.
+pub struct Publisher<T, C: CpuOps> {
+    inner: Arc<SpinLockIrq<WatchState<T>, C>>,
+}
+
+/// A subscription to a watch channel.
+///
+/// Each subscriber tracks which update it last saw. Cloning a subscriber
+/// gives a new one which has seen the same updates.
+pub struct Subscriber<T, C: CpuOps> {
+    inner: Arc<SpinLockIrq<WatchState<T>, C>>,
+    seen: u64,
+}
+
+impl<T, C: CpuOps> Publisher<T, C> {
+    /// Publishes `value`, waking every subscriber.
+    pub fn send(&self, value: T) {
+        self.send_modify(|v| *v = value);
+    }
+
+    /// Updates the value in place, waking every subscriber.
+    pub fn send_modify(&self, modify: impl FnOnce(&mut T)) {
+        let mut state = self.inner.lock_save_irq();
+
+        modify(&mut state.value);
+        state.version += 1;
+        state.waiters.wake_all();
+    }
+
+    /// Creates a new subscriber which has seen the current value.
+    pub fn subscribe(&self) -> Subscriber<T, C> {
+        Subscriber {
+            inner: self.inner.clone(),
+            seen: self.inner.lock_save_irq().version,
+        }
+    }
+
+    /// Returns a copy of the current value.
+    pub fn get(&self) -> T
+    where
+        T: Clone,
+    {
+        self.inner.lock_save_irq().value.clone()
+    }
+}
+
+impl<T, C: CpuOps> Drop for Publisher<T, C> {
+    fn drop(&mut self) {
+        let mut state = self.inner.lock_save_irq();
+
+        state.closed = true;
+        state.waiters.wake_all();
+    }
+}
+
+impl<T, C: CpuOps> Subscriber<T, C> {
+    /// Waits until a value this subscriber hasn't seen is published.
+    ///
+    /// Returns immediately if one already has been. Returns [`Closed`] if the
+    /// publisher is dropped first.
+    pub async fn changed(&mut self) -> Result<(), Closed> {
+        let seen = self.seen;
+
+        self.seen = wait_until(
+            self.inner.clone(),
+            |state| &mut state.waiters,
+            |state| {
+                if state.version != seen {
+                    Some(Ok(state.version))
+                } else if state.closed {
+                    Some(Err(Closed))
+                } else {
+                    None
+                }
+            },
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Returns `true` if a value this subscriber hasn't seen has been
+    /// published.
+    pub fn has_changed(&self) -> bool {
+        self.inner.lock_save_irq().version != self.seen
+    }
+
+    /// Returns a copy of the current value, marking it as seen.
+    pub fn get(&mut self) -> T
+    where
+        T: Clone,
+    {
+        let state = self.inner.lock_save_irq();
+
+        self.seen = state.version;
+        state.value.clone()
+    }
+}
+
+impl<T, C: CpuOps> Clone for Subscriber<T, C> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            seen: self.seen,
+        }
+    }
+}
+
+/// Creates a watch channel holding `initial`.
+///
+/// The returned subscriber has seen `initial`; more may be created with
+/// [`Publisher::subscribe`] or by cloning it.
+#[track_caller]
+pub fn channel<T, C: CpuOps>(initial: T) -> (Publisher<T, C>, Subscriber<T, C>) {
+    let inner = Arc::new(SpinLockIrq::new(WatchState {
+        value: initial,
+        version: 0,
+        closed: false,
+        waiters: WakerSet::new(),
+    }));
+
+    let subscriber = Subscriber {
+        inner: inner.clone(),
+        seen: 0,
+    };
+
+    (Publisher { inner }, subscriber)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::MockCpuOps;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn every_subscriber_sees_updates() {
+        let (tx, rx1) = channel::<_, MockCpuOps>(0);
+        let rx2 = tx.subscribe();
+
+        let handles: Vec<_> = [rx1, rx2]
+            .into_iter()
+            .map(|mut rx| {
+                tokio::spawn(async move {
+                    rx.changed().await.unwrap();
+                    rx.get()
+                })
+            })
+            .collect();
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(handles.iter().all(|h| !h.is_finished()));
+
+        tx.send(7);
+
+        for handle in handles {
+            assert_eq!(handle.await.unwrap(), 7);
+        }
+    }
+
+    #[tokio::test]
+    async fn only_the_latest_value_is_seen() {
+        let (tx, mut rx) = channel::<_, MockCpuOps>(0);
+
+        tx.send(1);
+        tx.send_modify(|v| *v += 1);
+        assert!(rx.has_changed());
+
+        rx.changed().await.unwrap();
+        assert_eq!(rx.get(), 2);
+        assert!(!rx.has_changed());
+
+        // A new subscriber has already seen the current value.
+        let late = tx.subscribe();
+        assert!(!late.has_changed());
+    }
+
+    #[tokio::test]
+    async fn dropping_publisher_closes() {
+        let (tx, mut rx) = channel::<_, MockCpuOps>(0);
+        let mut rx2 = rx.clone();
+
+        tx.send(1);
+        drop(tx);
+
+        // An update published before the close is still delivered.
+        assert_eq!(rx.changed().await, Ok(()));
+        assert_eq!(rx.get(), 1);
+        assert_eq!(rx.changed().await, Err(Closed));
+
+        rx2.get();
+        assert_eq!(rx2.changed().await, Err(Closed));
+    }
+}
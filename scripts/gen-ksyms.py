@@ -0,0 +1,103 @@
+#!/usr/bin/env python3
+"""Writes the kernel's symbol table into the space reserved for it in the ELF.
+
+The table is read by src/kernel/ksyms.rs to symbolize backtraces. It's laid
+out as a header (magic, base address, symbol count, offset of the names),
+followed by an (offset from base, offset of name) pair for each symbol sorted
+by address, followed by the NUL-terminated names.
+"""
+
+import argparse
+import struct
+import subprocess
+import sys
+
+MAGIC = b"MOSSSYMS"
+HEADER = struct.Struct("<8sQII")
+ENTRY = struct.Struct("<II")
+# Longer names, usually deeply nested generics, are cut short.
+MAX_NAME_LEN = 127
+
+
+def find_section(elf, name):
+    """Returns the file offset and size of section `name` in a 64-bit ELF."""
+    shoff, = struct.unpack_from("<Q", elf, 0x28)
+    shentsize, shnum, shstrndx = struct.unpack_from("<HHH", elf, 0x3A)
+
+    def header(idx):
+        # sh_name, sh_type, sh_flags, sh_addr, sh_offset, sh_size
+        return struct.unpack_from("<IIQQQQ", elf, shoff + idx * shentsize)
+
+    strtab_off = header(shstrndx)[4]
+
+    for idx in range(shnum):
+        sh_name, _, _, _, offset, size = header(idx)
+        end = elf.index(b"\0", strtab_off + sh_name)
+        if elf[strtab_off + sh_name:end].decode() == name:
+            return offset, size
+
+    sys.exit(f"gen-ksyms: no {name} section")
+
+
+def text_symbols(elf_path, nm):
+    out = subprocess.run(
+        [nm, "--numeric-sort", "--demangle", "--defined-only", elf_path],
+        check=True,
+        capture_output=True,
+        text=True,
+    ).stdout
+
+    for line in out.splitlines():
+        parts = line.split(" ", 2)
+        if len(parts) == 3 and parts[1] in "tTwW":
+            yield int(parts[0], 16), parts[2][:MAX_NAME_LEN]
+
+
+def build_table(symbols, capacity):
+    base = symbols[0][0]
+
+    # Drop symbols from the end until the table fits.
+    while symbols:
+        names = bytearray()
+        entries = bytearray()
+
+        for addr, name in symbols:
+            entries += ENTRY.pack(addr - base, len(names))
+            names += name.encode() + b"\0"
+
+        names_off = HEADER.size + len(entries)
+        table = HEADER.pack(MAGIC, base, len(symbols), names_off) + entries + names
+
+        if len(table) <= capacity:
+            return table
+
+        symbols = symbols[: len(symbols) * 9 // 10]
+        print(
+            f"gen-ksyms: table too large, keeping the first {len(symbols)} symbols",
+            file=sys.stderr,
+        )
+
+    sys.exit("gen-ksyms: the symbol table area is too small")
+
+
+def main():
+    parser = argparse.ArgumentParser(description=__doc__)
+    parser.add_argument("elf", help="The linked kernel")
+    parser.add_argument("--nm", default="aarch64-none-elf-nm")
+    args = parser.parse_args()
+
+    symbols = list(text_symbols(args.elf, args.nm))
+    if not symbols:
+        sys.exit("gen-ksyms: no symbols found")
+
+    with open(args.elf, "r+b") as f:
+        elf = f.read()
+        offset, size = find_section(elf, ".ksyms")
+        table = build_table(symbols, size)
+
+        f.seek(offset)
+        f.write(table.ljust(size, b"\0"))
+
+
+if __name__ == "__main__":
+    main()
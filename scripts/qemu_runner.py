@@ -1,7 +1,9 @@
 #!/usr/bin/env python3
 
 import argparse
+import os
 import subprocess
+import sys
 
 parser = argparse.ArgumentParser(description="QEMU runner")
 
@@ -22,15 +24,15 @@ args = parser.parse_args()
 
 elf_executable = args.elf_executable
 bin_executable_location = elf_executable.replace(".elf", "") + ".bin"
+# Embed the symbol table, for symbolized backtraces
+subprocess.run([sys.executable, os.path.join(os.path.dirname(__file__), "gen-ksyms.py"), elf_executable], check=True)
 # Convert the ELF executable to a binary format
 subprocess.run(["aarch64-none-elf-objcopy", "-O", "binary", elf_executable, bin_executable_location], check=True)
 
-append_args = ""
+append_args = f"init={args.init} rootfstype=ext4fs automount=/dev,devfs automount=/tmp,tmpfs automount=/proc,procfs automount=/sys,sysfs"
 
 if args.init.split("/")[-1] in ["bash", "sh"]:
-    append_args = f"--init={args.init} --init-arg=-i"
-else:
-    append_args = f"--init={args.init}"
+    append_args += " -- -i"
 
 default_args = {
     "-M": "virt,gic-version=3",
@@ -42,7 +44,7 @@ default_args = {
     "-nographic": None,
     "-s": None,
     "-kernel": bin_executable_location,
-    "-append": f"{append_args} --rootfs=ext4fs --automount=/dev,devfs --automount=/tmp,tmpfs --automount=/proc,procfs --automount=/sys,sysfs",
+    "-append": append_args,
 }
 
 # Arguments that can appear multiple times (e.g. -device)
@@ -0,0 +1,161 @@
+use crate::{
+    arch::ArchImpl,
+    drivers::{
+        fdt_prober::get_fdt,
+        pci::{IntxMap, PCI_BUS, PciHost, config::ConfigMechanism},
+    },
+    sync::OnceLock,
+};
+use alloc::vec::Vec;
+use core::ptr;
+use libkernel::{
+    acpi::{AcpiError, AcpiHandler, AcpiTables},
+    memory::{
+        address::PA,
+        proc_vm::address_space::{KernAddressSpace, VirtualMemory},
+        region::{PhysMemoryRegion, VirtMemoryRegion},
+    },
+};
+use log::{info, warn};
+
+/// `EFI_ACPI_20_TABLE_GUID`, as laid out in memory.
+const ACPI_20_TABLE_GUID: [u8; 16] = [
+    0x71, 0xe8, 0x68, 0x88, 0xf1, 0xe4, 0xd3, 0x11, 0xbc, 0x22, 0x00, 0x80, 0xc7, 0x3c, 0x88, 0x81,
+];
+
+// Offsets into `EFI_SYSTEM_TABLE` of `NumberOfTableEntries` and
+// `ConfigurationTable`.
+const EFI_ST_NR_TABLES: u64 = 104;
+const EFI_ST_CONFIG_TABLE: u64 = 112;
+
+/// The size of an `EFI_CONFIGURATION_TABLE` entry: a GUID and a pointer.
+const EFI_CONFIG_ENTRY_LEN: u64 = 24;
+
+static ACPI_TABLES: OnceLock<AcpiTables> = OnceLock::new();
+
+/// Reads firmware memory through temporary device mappings.
+///
+/// The tables are usually in memory the firmware reserved, which isn't
+/// covered by the logical map.
+struct MmioHandler;
+
+impl AcpiHandler for MmioHandler {
+    fn read_phys(&self, pa: u64, buf: &mut [u8]) -> Result<(), AcpiError> {
+        let region = PhysMemoryRegion::new(PA::from_value(pa as usize), buf.len());
+        let mut addr_spc = ArchImpl::kern_address_space().lock_save_irq();
+
+        let va = addr_spc
+            .map_mmio(region)
+            .map_err(|_| AcpiError::Unreadable)?;
+
+        let src = va.as_ptr().cast::<u8>();
+
+        // Device memory faults on unaligned accesses, so copy bytewise.
+        for (i, byte) in buf.iter_mut().enumerate() {
+            *byte = unsafe { ptr::read_volatile(src.add(i)) };
+        }
+
+        let mapped = region.to_mappable_region();
+
+        // The frames belong to the firmware, so we don't free them.
+        let _ = addr_spc.unmap_range(VirtMemoryRegion::new(
+            va.sub_bytes(mapped.offset()),
+            mapped.region().size(),
+        ));
+
+        Ok(())
+    }
+}
+
+fn read_u64(pa: u64) -> Result<u64, AcpiError> {
+    let mut buf = [0; 8];
+    MmioHandler.read_phys(pa, &mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// Finds the RSDP through the EFI configuration table, whose location the
+/// EFI stub passes in `/chosen`.
+fn find_rsdp() -> Option<u64> {
+    let system_table = get_fdt()
+        .find_nodes("/chosen")
+        .next()?
+        .find_property("linux,uefi-system-table")
+        .map(|prop| prop.u64())?;
+
+    let nr_tables = read_u64(system_table + EFI_ST_NR_TABLES).ok()?;
+    let config_table = read_u64(system_table + EFI_ST_CONFIG_TABLE).ok()?;
+
+    (0..nr_tables).find_map(|i| {
+        let mut entry = [0; EFI_CONFIG_ENTRY_LEN as usize];
+
+        MmioHandler
+            .read_phys(config_table + i * EFI_CONFIG_ENTRY_LEN, &mut entry)
+            .ok()?;
+
+        (entry[..16] == ACPI_20_TABLE_GUID)
+            .then(|| u64::from_le_bytes(entry[16..].try_into().unwrap()))
+    })
+}
+
+/// Reads the ACPI tables, if the firmware provides any.
+pub fn acpi_init() {
+    let Some(rsdp) = find_rsdp() else {
+        return;
+    };
+
+    let tables = match AcpiTables::from_rsdp(&MmioHandler, rsdp) {
+        Ok(tables) => tables,
+        Err(e) => {
+            warn!("Ignoring ACPI tables at 0x{rsdp:x}: {e}");
+            return;
+        }
+    };
+
+    for sig in tables.signatures() {
+        info!(
+            "ACPI: found {} table",
+            core::str::from_utf8(sig).unwrap_or("????")
+        );
+    }
+
+    // Host bridges described by the device tree take precedence; firmware
+    // has already assigned BARs behind the rest.
+    if let Some(mcfg) = tables.mcfg() {
+        let mut pci = PCI_BUS.lock_save_irq();
+
+        for entry in mcfg.entries() {
+            if pci.host_for_segment(entry.segment).is_some() {
+                continue;
+            }
+
+            let (base, size) = entry.region();
+
+            info!(
+                "ACPI: PCI segment {} buses {:02x}-{:02x} ECAM at 0x{base:x} (0x{size:x} bytes)",
+                entry.segment, entry.start_bus, entry.end_bus
+            );
+
+            let res = PciHost::new(
+                "acpi-pci",
+                entry.segment,
+                ConfigMechanism::Ecam,
+                base,
+                entry.start_bus..=entry.end_bus,
+                Vec::new(),
+                IntxMap::default(),
+            )
+            .and_then(|host| pci.add_host(host));
+
+            if let Err(e) = res {
+                warn!("ACPI: could not add PCI segment {}: {e}", entry.segment);
+            }
+        }
+    }
+
+    let _ = ACPI_TABLES.set(tables);
+}
+
+/// Returns the ACPI tables, on ACPI systems.
+pub fn acpi_tables() -> Option<&'static AcpiTables> {
+    ACPI_TABLES.get()
+}
@@ -0,0 +1,59 @@
+use core::arch::asm;
+use libkernel::memory::{address::VA, region::VirtMemoryRegion};
+
+/// Walks the AArch64 frame-record chain starting at the caller's frame.
+///
+/// Each frame record is a pair of `(previous x29, x30)` pointed to by x29. The
+/// kernel is built with frame pointers forced on, so the chain is complete
+/// until the outermost frame, which has a null frame pointer. We stop early
+/// if a record looks bogus: it's not in the kernel half of the address space,
+/// is misaligned, or doesn't move up the stack.
+pub fn capture_backtrace(frames: &mut [usize]) -> usize {
+    let mut fp: usize;
+
+    unsafe { asm!("mov {}, x29", out(reg) fp, options(nomem, nostack)) };
+
+    walk_frames(fp, |fp| fp & (1 << 63) != 0, frames)
+}
+
+/// Walks the frame-record chain starting at `fp`, only following records
+/// within `stack`. This is used to trace a context that faulted, whose stack
+/// may be partly unmapped.
+pub fn backtrace_from(fp: usize, stack: VirtMemoryRegion, frames: &mut [usize]) -> usize {
+    walk_frames(
+        fp,
+        |fp| {
+            stack.contains_address(VA::from_value(fp))
+                && stack.contains_address(VA::from_value(fp + 8))
+        },
+        frames,
+    )
+}
+
+fn walk_frames(mut fp: usize, valid: impl Fn(usize) -> bool, frames: &mut [usize]) -> usize {
+    let mut depth = 0;
+
+    while depth < frames.len() && fp.is_multiple_of(8) && valid(fp) {
+        // SAFETY: `fp` points to a frame record on a mapped kernel stack, per
+        // the checks above.
+        let (next_fp, lr) = unsafe {
+            let record = fp as *const usize;
+            (record.read(), record.add(1).read())
+        };
+
+        if lr == 0 {
+            break;
+        }
+
+        frames[depth] = lr;
+        depth += 1;
+
+        if next_fp <= fp {
+            break;
+        }
+
+        fp = next_fp;
+    }
+
+    depth
+}
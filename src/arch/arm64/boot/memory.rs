@@ -3,8 +3,13 @@ use crate::arch::arm64::memory::{
     set_kimage_start,
     tlb::AllEl1TlbInvalidator,
 };
-use crate::memory::INITAL_ALLOCATOR;
-use core::ptr::NonNull;
+use crate::drivers::fdt_prober::initrd_region;
+use crate::memory::{INITAL_ALLOCATOR, vmalloc::vmalloc_kernel_stack};
+use crate::sched::sched_task::NR_CPUS;
+use core::{
+    ptr::NonNull,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 use libkernel::{
     arch::arm64::memory::{
         pg_descriptors::MemoryType,
@@ -81,20 +86,31 @@ pub fn setup_allocator(dtb_ptr: TPA<u8>, image_start: PA, image_end: PA) -> Resu
     info!("Reserving FDT {dtb_ptr} (0x{:04x} bytes)", dt.total_size());
     alloc.add_reservation(PhysMemoryRegion::new(dtb_ptr.to_untyped(), dt.total_size()))?;
 
+    // Reserve the regions carved out under `/reserved-memory`, e.g. for
+    // firmware. Nodes giving only a size ask the OS to allocate a region for
+    // some driver, and are left alone.
+    for node in dt.reserved_memory() {
+        for region in node.reg().into_iter().flatten() {
+            let Some(size) = region.size else {
+                continue;
+            };
+
+            let start_addr = PA::from_value(region.address as usize);
+
+            info!("Reserving {} {start_addr} (0x{size:x} bytes)", node.name);
+
+            alloc.add_reservation(PhysMemoryRegion::new(start_addr, size))?;
+        }
+    }
+
     // Reserve the initrd.
-    if let Some(chosen) = dt.find_nodes("/chosen").next()
-        && let Some(start_addr) = chosen
-            .find_property("linux,initrd-start")
-            .map(|prop| prop.u64())
-        && let Some(end_addr) = chosen
-            .find_property("linux,initrd-end")
-            .map(|prop| prop.u64())
-    {
-        info!("Reserving initrd 0x{start_addr:X} - 0x{end_addr:X}");
-        alloc.add_reservation(PhysMemoryRegion::from_start_end_address(
-            PA::from_value(start_addr as _),
-            PA::from_value(end_addr as _),
-        ))?;
+    if let Some(initrd) = initrd_region(&dt) {
+        info!(
+            "Reserving initrd {} - {}",
+            initrd.start_address(),
+            initrd.end_address()
+        );
+        alloc.add_reservation(initrd)?;
     }
 
     set_kimage_start(image_start);
@@ -102,23 +118,55 @@ pub fn setup_allocator(dtb_ptr: TPA<u8>, image_start: PA, image_end: PA) -> Resu
     Ok(())
 }
 
-pub fn allocate_kstack_region() -> VirtMemoryRegion {
-    // Start allocating stacks at the second valid stack slot in
-    // `KERNEL_STACK_AREA`. This ensures that the faulting address of a stack
-    // overflow on the first allocated stack would still lie withing
-    // `KERNEL_STACK_AREA`.
-    static mut CURRENT_VA: VA = KERNEL_STACK_AREA
-        .start_address()
-        .add_bytes(KERNEL_STACK_SZ * 2);
+// Every kernel stack is aligned such that the `KERNEL_STACK_SHIFT` bit is set
+// for all addresses within it, and clear for all addresses in the unmapped
+// guard region of the same size below it. This lets the exception vectors
+// detect a stack overflow by testing a single bit of the SP.
 
-    let range = VirtMemoryRegion::new(unsafe { CURRENT_VA }, KERNEL_STACK_SZ);
+/// The boot CPU's stack. This is mapped before the vmalloc area is usable, so
+/// it lives in `KERNEL_STACK_AREA` instead.
+const BOOT_KSTACK: VirtMemoryRegion = VirtMemoryRegion::new(
+    KERNEL_STACK_AREA.start_address().add_bytes(KERNEL_STACK_SZ),
+    KERNEL_STACK_SZ,
+);
+
+/// The start addresses of the secondary CPUs' stacks.
+static SECONDARY_KSTACKS: [AtomicUsize; NR_CPUS] = [const { AtomicUsize::new(0) }; NR_CPUS];
+static NR_SECONDARY_KSTACKS: AtomicUsize = AtomicUsize::new(0);
+
+/// Allocate a kernel stack for a secondary CPU from the vmalloc area.
+pub fn allocate_kstack() -> Result<VirtMemoryRegion> {
+    let stack = vmalloc_kernel_stack(KERNEL_STACK_SZ)?;
 
-    // Add a guard region between allocations, this ensures that the
-    // `KERNEL_STACK_SHIFT` bit is set between stacks, allow us to detect stack
-    // overflow in the kernel.
-    unsafe { CURRENT_VA = CURRENT_VA.add_bytes(KERNEL_STACK_SZ * 2) };
+    let idx = NR_SECONDARY_KSTACKS.fetch_add(1, Ordering::AcqRel);
+    SECONDARY_KSTACKS
+        .get(idx)
+        .ok_or(KernelError::NoMemory)?
+        .store(stack.start_address().value(), Ordering::Release);
 
-    range
+    Ok(stack)
+}
+
+/// If `addr` lies in the guard region below a kernel stack, returns that
+/// stack.
+pub fn overflowed_kstack(addr: VA) -> Option<VirtMemoryRegion> {
+    let nr_stacks = NR_SECONDARY_KSTACKS.load(Ordering::Acquire).min(NR_CPUS);
+
+    let secondaries = SECONDARY_KSTACKS[..nr_stacks]
+        .iter()
+        .map(|start| start.load(Ordering::Acquire))
+        .filter(|&start| start != 0)
+        .map(|start| VirtMemoryRegion::new(VA::from_value(start), KERNEL_STACK_SZ));
+
+    core::iter::once(BOOT_KSTACK)
+        .chain(secondaries)
+        .find(|stack| {
+            VirtMemoryRegion::new(
+                stack.start_address().sub_bytes(KERNEL_STACK_SZ),
+                KERNEL_STACK_SZ,
+            )
+            .contains_address(addr)
+        })
 }
 
 // Returns the address that should be loaded into the SP.
@@ -129,7 +177,7 @@ pub fn setup_stack_and_heap(pgtbl_base: TPA<PgTableArray<L0Table>>) -> Result<VA
     // allocate the stack.
     let stack = alloc.alloc(KERNEL_STACK_SZ, PAGE_SIZE)?;
     let stack_phys_region = PhysMemoryRegion::new(stack, KERNEL_STACK_SZ);
-    let stack_virt_region = allocate_kstack_region();
+    let stack_virt_region = BOOT_KSTACK;
 
     let mut pg_alloc = SmallocPageAlloc::new(alloc);
     let mut ctx = MappingContext {
@@ -1,10 +1,8 @@
 use super::{
+    acpi::acpi_init,
+    cpuidle::cpuidle_init,
     exceptions::{ExceptionState, secondary_exceptions_init},
-    memory::{
-        fixmap::FIXMAPS,
-        heap::{KernelHeap, SLAB_ALLOC},
-        mmu::setup_kern_addr_space,
-    },
+    memory::{fixmap::FIXMAPS, heap::KernelHeap, mmu::setup_kern_addr_space},
     proc::vdso::vdso_init,
 };
 use crate::drivers::timer::kick_current_cpu;
@@ -14,10 +12,12 @@ use crate::{
     drivers::{
         fdt_prober::{probe_for_fdt_devices, set_fdt_va},
         init::run_initcalls,
+        pci::probe_for_pci_devices,
     },
     interrupts::{cpu_messenger::cpu_messenger_init, get_interrupt_root},
+    kernel::cmdline::cmdline_init,
     kmain,
-    memory::{INITAL_ALLOCATOR, PAGE_ALLOC},
+    memory::{INITAL_ALLOCATOR, PAGE_ALLOC, SLAB_ALLOC, create_named_caches},
     sched::{sched_init_secondary, uspc_ret::dispatch_userspace_task},
 };
 use aarch64_cpu::{
@@ -33,6 +33,7 @@ use libkernel::{
         address::{PA, TPA, VA},
         allocators::{phys::FrameAllocator, slab::allocator::SlabAllocator},
         paging::PgTableArray,
+        proc_vm::overcommit::VM_COMMIT,
     },
     sync::per_cpu::setup_percpu,
 };
@@ -111,36 +112,47 @@ fn arch_init_stage2(frame: *mut ExceptionState) -> *mut ExceptionState {
 
     let (page_alloc, frame_list) = unsafe { FrameAllocator::init(smalloc) };
 
+    VM_COMMIT.set_total_pages(page_alloc.total_pages());
+
     if PAGE_ALLOC.set(page_alloc).is_err() {
         panic!("Cannot setup physical memory allocator");
     }
 
-    if SLAB_ALLOC.set(SlabAllocator::new(frame_list)).is_err() {
+    let mut slab_alloc = SlabAllocator::new(frame_list);
+
+    create_named_caches(&mut slab_alloc).expect("Cannot create named slab caches");
+
+    if SLAB_ALLOC.set(slab_alloc).is_err() {
         panic!("Cannot setup slab allocator");
     }
 
     KernelHeap::init_for_this_cpu();
 
-    // Don't trap wfi/wfe in el0.
-    SCTLR_EL1.modify(SCTLR_EL1::NTWE::DontTrap + SCTLR_EL1::NTWI::DontTrap);
+    cmdline_init(&super::fdt::get_cmdline().unwrap_or_default());
+
+    // Trap wfi in el0, so that the idle task can enter idle states through
+    // the kernel. Don't trap wfe.
+    SCTLR_EL1.modify(SCTLR_EL1::NTWE::DontTrap + SCTLR_EL1::NTWI::Trap);
 
     exceptions_init().expect("Failed to initialize exceptions");
     ArchImpl::enable_interrupts();
 
     unsafe { run_initcalls() };
     probe_for_fdt_devices();
+    acpi_init();
+    probe_for_pci_devices();
 
     unsafe { setup_percpu(cpu_count()) };
 
     cpu_messenger_init(cpu_count());
 
+    cpuidle_init();
+
     if let Err(e) = vdso_init() {
         panic!("VDSO setup failed: {e}");
     }
 
-    let cmdline = super::fdt::get_cmdline();
-
-    kmain(cmdline.unwrap_or_default(), frame);
+    kmain(frame);
 
     boot_secondaries();
 
@@ -153,8 +165,8 @@ fn arch_init_secondary(ctx_frame: *mut ExceptionState) -> *mut ExceptionState {
     TCR_EL1.modify(TCR_EL1::EPD0::DisableTTBR0Walks);
     barrier::isb(barrier::SY);
 
-    // Don't trap secondaries wfi/wfe in el0.
-    SCTLR_EL1.modify(SCTLR_EL1::NTWE::DontTrap + SCTLR_EL1::NTWI::DontTrap);
+    // Trap secondaries wfi in el0, as on the boot CPU.
+    SCTLR_EL1.modify(SCTLR_EL1::NTWE::DontTrap + SCTLR_EL1::NTWI::Trap);
 
     // Setup heap per-cpu data.
     KernelHeap::init_for_this_cpu();
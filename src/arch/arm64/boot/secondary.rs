@@ -2,20 +2,19 @@ use crate::{
     arch::{
         ArchImpl,
         arm64::{
-            boot::{
-                arch_init_secondary,
-                memory::{KERNEL_STACK_PG_ORDER, allocate_kstack_region},
-            },
+            acpi::acpi_tables,
+            boot::{arch_init_secondary, memory::allocate_kstack},
             memory::flush_to_ram,
-            psci::{PSCIEntry, PSCIMethod, boot_secondary_psci},
+            psci::{PSCIEntry, PSCIMethod, boot_secondary_psci, fdt_psci_method},
         },
     },
     drivers::{fdt_prober::get_fdt, timer::now},
+    kernel::cmdline::params,
     kfunc_pa, ksym_pa,
-    memory::PAGE_ALLOC,
     sync::OnceLock,
 };
 use aarch64_cpu::asm::barrier::{SY, isb};
+use alloc::vec::Vec;
 use core::{
     arch::naked_asm,
     hint::spin_loop,
@@ -25,11 +24,11 @@ use core::{
 };
 use libkernel::{
     CpuOps,
+    acpi::{ArmBootArch, MadtEntry},
     error::{KernelError, Result},
     memory::{
         address::{PA, VA},
-        paging::permissions::PtePermissions,
-        proc_vm::address_space::{KernAddressSpace, VirtualMemory},
+        proc_vm::address_space::VirtualMemory,
     },
 };
 use log::{info, warn};
@@ -85,18 +84,11 @@ fn find_enable_method(node: &fdt_parser::Node<'static>) -> Result<EntryMethod> {
         .str();
 
     if method == "psci" {
-        let fdt = get_fdt();
-        let psci_node = fdt
+        let method = fdt_psci_method()?;
+        let cpu_on_id = get_fdt()
             .get_node_by_name("psci")
-            .ok_or(KernelError::Other("psci node missing"))?;
-
-        let method = match psci_node.find_property("method").map(|x| x.str()) {
-            Some("hvc") => PSCIMethod::Hvc,
-            Some("smc") => PSCIMethod::Smc,
-            _ => return Err(KernelError::Other("Unknown method in psci node")),
-        };
-
-        let cpu_on_id = psci_node.find_property("cpu_on").map(|x| x.u32());
+            .and_then(|psci_node| psci_node.find_property("cpu_on"))
+            .map(|x| x.u32());
 
         Ok(EntryMethod::Psci(PSCIEntry { method, cpu_on_id }))
     } else {
@@ -104,6 +96,29 @@ fn find_enable_method(node: &fdt_parser::Node<'static>) -> Result<EntryMethod> {
     }
 }
 
+/// On ACPI systems, secondaries are started through PSCI, with the conduit
+/// given by the FADT.
+fn acpi_enable_method() -> Result<EntryMethod> {
+    let fadt = acpi_tables()
+        .and_then(|tables| tables.fadt())
+        .ok_or(KernelError::Other("FADT missing"))?;
+
+    if !fadt.arm_boot_arch.contains(ArmBootArch::PSCI_COMPLIANT) {
+        return Err(KernelError::Other("Firmware is not PSCI compliant"));
+    }
+
+    let method = if fadt.arm_boot_arch.contains(ArmBootArch::PSCI_USE_HVC) {
+        PSCIMethod::Hvc
+    } else {
+        PSCIMethod::Smc
+    };
+
+    Ok(EntryMethod::Psci(PSCIEntry {
+        method,
+        cpu_on_id: None,
+    }))
+}
+
 fn prepare_for_secondary_entry() -> Result<(PA, PA)> {
     static mut SECONDARY_BOOT_CTX: MaybeUninit<SecondaryBootInfo> = MaybeUninit::uninit();
 
@@ -111,18 +126,7 @@ fn prepare_for_secondary_entry() -> Result<(PA, PA)> {
     let boot_stack = ksym_pa!(__boot_stack);
     let ctx = ksym_pa!(SECONDARY_BOOT_CTX);
 
-    let kstack_vaddr = allocate_kstack_region();
-    let kstack_paddr = PAGE_ALLOC
-        .get()
-        .unwrap()
-        .alloc_frames(KERNEL_STACK_PG_ORDER as _)?
-        .leak();
-
-    ArchImpl::kern_address_space().lock_save_irq().map_normal(
-        kstack_paddr,
-        kstack_vaddr,
-        PtePermissions::rw(false),
-    )?;
+    let kstack_vaddr = allocate_kstack()?;
 
     unsafe {
         let boot_ctx = &raw mut SECONDARY_BOOT_CTX as *mut SecondaryBootInfo;
@@ -131,9 +135,7 @@ fn prepare_for_secondary_entry() -> Result<(PA, PA)> {
             boot_stack_addr: boot_stack,
             kstack_addr: kstack_vaddr.end_address(),
             kmem_ttbr: ArchImpl::kern_address_space().lock_save_irq().table_pa(),
-            idmap_ttbr: *IDMAP_ADDR
-                .get()
-                .ok_or(KernelError::Other("Idmap not set"))?,
+            idmap_ttbr: idmap_addr().ok_or(KernelError::Other("Idmap not set"))?,
             start_fn: VA::from_value(arch_init_secondary as *const () as usize),
             exception_ret: VA::from_value(&exception_return as *const _ as usize),
         });
@@ -149,18 +151,13 @@ fn prepare_for_secondary_entry() -> Result<(PA, PA)> {
     Ok((entry_fn, ctx))
 }
 
-fn do_boot_secondary(cpu_node: fdt_parser::Node<'static>) -> Result<()> {
-    let id = cpu_node
-        .reg()
-        .and_then(|mut x| x.next().map(|x| x.address))
-        .ok_or(KernelError::Other("reg property missing on CPU node"))?;
-
+fn do_boot_secondary(id: u64, mode: impl FnOnce() -> Result<EntryMethod>) -> Result<()> {
     // Skip boot core.
     if id == 0 {
         return Ok(());
     }
 
-    let mode = find_enable_method(&cpu_node)?;
+    let mode = mode()?;
 
     let (entry_fn, ctx) = prepare_for_secondary_entry()?;
 
@@ -195,16 +192,56 @@ fn cpu_node_iter() -> impl Iterator<Item = fdt_parser::Node<'static>> {
     })
 }
 
+/// Returns the MPIDRs of the CPUs in the MADT, for when the device tree
+/// doesn't list any.
+fn acpi_cpu_ids() -> Vec<u64> {
+    if cpu_node_iter().next().is_some() {
+        return Vec::new();
+    }
+
+    let Some(madt) = acpi_tables().and_then(|tables| tables.madt()) else {
+        return Vec::new();
+    };
+
+    madt.cpus()
+        .filter_map(|cpu| match cpu {
+            MadtEntry::Gicc { mpidr, .. } => Some(mpidr),
+            _ => None,
+        })
+        .collect()
+}
+
 pub fn boot_secondaries() {
+    if params().nosmp {
+        info!("nosmp given, not booting secondary CPUs");
+        return;
+    }
+
     for cpu_node in cpu_node_iter() {
-        if let Err(e) = do_boot_secondary(cpu_node) {
+        let res = cpu_node
+            .reg()
+            .and_then(|mut x| x.next().map(|x| x.address))
+            .ok_or(KernelError::Other("reg property missing on CPU node"))
+            .and_then(|id| do_boot_secondary(id, || find_enable_method(&cpu_node)));
+
+        if let Err(e) = res {
+            log::warn!("Failed to boot secondary: {e}");
+        }
+    }
+
+    for id in acpi_cpu_ids() {
+        if let Err(e) = do_boot_secondary(id, acpi_enable_method) {
             log::warn!("Failed to boot secondary: {e}");
         }
     }
 }
 
 pub fn cpu_count() -> usize {
-    cpu_node_iter().count()
+    if params().nosmp {
+        return 1;
+    }
+
+    cpu_node_iter().count() + acpi_cpu_ids().len()
 }
 
 pub fn save_idmap(addr: PA) {
@@ -213,6 +250,12 @@ pub fn save_idmap(addr: PA) {
     }
 }
 
+/// Returns the address of the ID map set up at boot, which maps the kernel
+/// image at its physical address.
+pub fn idmap_addr() -> Option<PA> {
+    IDMAP_ADDR.get().copied()
+}
+
 pub fn secondary_booted() {
     let id = ArchImpl::id();
 
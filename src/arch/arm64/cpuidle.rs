@@ -0,0 +1,90 @@
+//! Idle states for arm64 CPUs.
+//!
+//! WFI is always available. Deeper states come from the device tree's
+//! `arm,idle-state` nodes, and are entered through PSCI's `CPU_SUSPEND`. Only
+//! retention states are used, which the CPU comes back from as it would from
+//! WFI: with its context and local timer intact.
+
+use super::psci::{fdt_psci_method, psci_call};
+use crate::{
+    drivers::fdt_prober::get_fdt,
+    kernel::cpuidle::{IdleState, register_idle_states},
+};
+use aarch64_cpu::asm::wfi;
+use alloc::{boxed::Box, string::String, vec, vec::Vec};
+use core::time::Duration;
+use log::info;
+
+const PSCI_FEATURES: u32 = 0x8400_000a;
+const CPU_SUSPEND: u32 = 0xc400_0001;
+
+/// Set in `PSCI_FEATURES(CPU_SUSPEND)` if power states use the extended
+/// StateID format.
+const FEATURE_EXTENDED_STATE_ID: i64 = 1 << 1;
+
+fn is_power_down(power_state: u32, extended: bool) -> bool {
+    let state_type = if extended { 1 << 30 } else { 1 << 16 };
+
+    power_state & state_type != 0
+}
+
+fn psci_states() -> Vec<IdleState> {
+    let Ok(method) = fdt_psci_method() else {
+        return Vec::new();
+    };
+
+    let features = psci_call(&method, PSCI_FEATURES, CPU_SUSPEND as _, 0, 0);
+    let extended = features >= 0 && features & FEATURE_EXTENDED_STATE_ID != 0;
+
+    let fdt = get_fdt();
+
+    fdt.all_nodes()
+        .filter(|node| {
+            node.compatible().is_some_and(|mut compats| {
+                compats.any(|compat| compat.is_ok_and(|compat| compat == "arm,idle-state"))
+            })
+        })
+        .filter_map(|node| {
+            let power_state = node.find_property("arm,psci-suspend-param")?.u32();
+
+            // The timer wouldn't wake the CPU from these, and the CPU loses
+            // its context in power down states.
+            if node.find_property("local-timer-stop").is_some()
+                || is_power_down(power_state, extended)
+            {
+                info!("cpuidle: not using {}", node.name);
+                return None;
+            }
+
+            let micros = |prop| {
+                Duration::from_micros(node.find_property(prop).map_or(0, |p| p.u32()) as u64)
+            };
+
+            Some(IdleState {
+                name: String::from(node.name),
+                exit_latency: micros("entry-latency-us") + micros("exit-latency-us"),
+                target_residency: micros("min-residency-us"),
+                enter: Box::new(move || {
+                    if psci_call(&method, CPU_SUSPEND, power_state as _, 0, 0) != 0 {
+                        wfi();
+                    }
+                }),
+            })
+        })
+        .collect()
+}
+
+pub fn cpuidle_init() {
+    let mut states = vec![IdleState {
+        name: String::from("WFI"),
+        exit_latency: Duration::from_micros(1),
+        target_residency: Duration::from_micros(1),
+        enter: Box::new(wfi),
+    }];
+
+    let mut deeper = psci_states();
+    deeper.sort_by_key(|state| state.target_residency);
+    states.extend(deeper);
+
+    register_idle_states(states);
+}
@@ -62,8 +62,10 @@ __vector_\handler:
     // Detect stack overflow without clobbering GP registers.
     msr     SP_EL0, x0
     mov     x0, sp
+    // Kernel stacks are aligned such that this bit is set within the stack
+    // and clear within the guard region below it.
     //TODO: share this const value with Rust.
-	tbnz	x0, #15, 0f // #15 = KERNEL_STACK_SHIFT.
+	tbz	x0, #15, 0f // #15 = KERNEL_STACK_SHIFT.
     mrs     x0, SP_EL0
     b       __impl_\handler
 
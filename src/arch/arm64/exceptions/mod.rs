@@ -5,6 +5,7 @@ use super::memory::{
 use crate::{
     arch::{ArchImpl, arm64::boot::memory::KERNEL_STACK_PG_ORDER},
     interrupts::get_interrupt_root,
+    kernel::cpuidle::enter_idle,
     ksym_pa,
     memory::PAGE_ALLOC,
     sched::{syscall_ctx::ProcessCtx, uspc_ret::dispatch_userspace_task},
@@ -176,6 +177,15 @@ extern "C" fn el0_sync(state_ptr: *mut ExceptionState) -> *const ExceptionState
             // TODO: Flag to start saving FP/SIMD context for this task and,
             // save the state.
         }
+        Exception::TrappedWFIorWFE(_) => {
+            // Step over the wfi. Other tasks may wait for an interrupt too,
+            // but only the idle task has nothing better to do.
+            ctx.task_mut().ctx.user_mut().elr_el1 += 4;
+
+            if ctx.task().is_idle_task() {
+                enter_idle();
+            }
+        }
         _ => default_handler(state),
     }
 
@@ -1,13 +1,15 @@
 use crate::{
     arch::{Arch, ArchImpl},
     clock::syscalls::{
-        gettime::sys_clock_gettime,
+        gettime::{sys_clock_getres, sys_clock_gettime},
         itimer::{sys_getitimer, sys_setitimer},
         settime::sys_clock_settime,
         timeofday::{sys_gettimeofday, sys_settimeofday},
+        timerfd::{sys_timerfd_create, sys_timerfd_gettime, sys_timerfd_settime},
     },
     fs::{
         dir::sys_getdents64,
+        eventfd::sys_eventfd2,
         pipe::sys_pipe2,
         syscalls::{
             at::{
@@ -48,24 +50,35 @@ use crate::{
         },
     },
     kernel::{
-        getcpu::sys_getcpu, hostname::sys_sethostname, power::sys_reboot, rand::sys_getrandom,
-        sysinfo::sys_sysinfo, uname::sys_uname,
+        getcpu::sys_getcpu,
+        hostname::{sys_setdomainname, sys_sethostname},
+        kexec::sys_kexec_load,
+        power::sys_reboot,
+        rand::sys_getrandom,
+        sysinfo::sys_sysinfo,
+        syslog::sys_syslog,
+        trace::{TraceEvent, trace_event},
+        uname::sys_uname,
     },
     memory::{
         brk::sys_brk,
         mincore::sys_mincore,
         mmap::{sys_mmap, sys_mprotect, sys_munmap},
-        process_vm::sys_process_vm_readv,
+        process_vm::{sys_process_vm_readv, sys_process_vm_writev},
+        userfaultfd::sys_userfaultfd,
     },
     net::syscalls::{
         accept::{sys_accept, sys_accept4},
         bind::sys_bind,
         connect::sys_connect,
         listen::sys_listen,
+        msg::{sys_recvmmsg, sys_recvmsg, sys_sendmmsg, sys_sendmsg},
         recv::sys_recvfrom,
         send::sys_sendto,
         shutdown::sys_shutdown,
-        socket::sys_socket,
+        socket::{sys_socket, sys_socketpair},
+        sockname::{sys_getpeername, sys_getsockname},
+        sockopt::{sys_getsockopt, sys_setsockopt},
     },
     process::{
         caps::{sys_capget, sys_capset},
@@ -136,6 +149,8 @@ pub async fn handle_syscall(mut ctx: ProcessCtx) {
         )
     };
 
+    trace_event(TraceEvent::SyscallEnter, [nr as u64, arg1, arg2, arg3]);
+
     let res = match nr {
         0x14 => sys_epoll_create1(&ctx, arg1 as _).await,
         0x15 => {
@@ -246,6 +261,7 @@ pub async fn handle_syscall(mut ctx: ProcessCtx) {
         0xf => sys_lremovexattr(&ctx, TUA::from_value(arg1 as _), TUA::from_value(arg2 as _)).await,
         0x10 => sys_fremovexattr(&ctx, arg1.into(), TUA::from_value(arg2 as _)).await,
         0x11 => sys_getcwd(&ctx, TUA::from_value(arg1 as _), arg2 as _).await,
+        0x13 => sys_eventfd2(&ctx, arg1 as _, arg2 as _).await,
         0x17 => sys_dup(&ctx, arg1.into()),
         0x18 => sys_dup3(&ctx, arg1.into(), arg2.into(), arg3 as _),
         0x19 => sys_fcntl(&ctx, arg1.into(), arg2 as _, arg3 as _).await,
@@ -451,6 +467,18 @@ pub async fn handle_syscall(mut ctx: ProcessCtx) {
         0x51 => sys_sync(&ctx).await,
         0x52 => sys_fsync(&ctx, arg1.into()).await,
         0x53 => sys_fdatasync(&ctx, arg1.into()).await,
+        0x55 => sys_timerfd_create(&ctx, arg1 as _, arg2 as _).await,
+        0x56 => {
+            sys_timerfd_settime(
+                &ctx,
+                arg1.into(),
+                arg2 as _,
+                TUA::from_value(arg3 as _),
+                TUA::from_value(arg4 as _),
+            )
+            .await
+        }
+        0x57 => sys_timerfd_gettime(&ctx, arg1.into(), TUA::from_value(arg2 as _)).await,
         0x58 => {
             sys_utimensat(
                 &ctx,
@@ -525,8 +553,19 @@ pub async fn handle_syscall(mut ctx: ProcessCtx) {
             )
             .await
         }
+        0x68 => {
+            sys_kexec_load(
+                &ctx,
+                arg1 as _,
+                arg2 as _,
+                TUA::from_value(arg3 as _),
+                arg4 as _,
+            )
+            .await
+        }
         0x70 => sys_clock_settime(arg1 as _, TUA::from_value(arg2 as _)).await,
         0x71 => sys_clock_gettime(&ctx, arg1 as _, TUA::from_value(arg2 as _)).await,
+        0x72 => sys_clock_getres(arg1 as _, TUA::from_value(arg2 as _)).await,
         0x73 => {
             sys_clock_nanosleep(
                 arg1 as _,
@@ -536,6 +575,7 @@ pub async fn handle_syscall(mut ctx: ProcessCtx) {
             )
             .await
         }
+        0x74 => sys_syslog(&ctx, arg1 as _, UA::from_value(arg2 as _), arg3 as _).await,
         0x75 => {
             sys_ptrace(
                 &ctx,
@@ -616,8 +656,9 @@ pub async fn handle_syscall(mut ctx: ProcessCtx) {
         0x9b => sys_getpgid(&ctx, arg1 as _),
         0x9c => sys_getsid(&ctx).await,
         0x9d => sys_setsid(&ctx).await,
-        0xa0 => sys_uname(TUA::from_value(arg1 as _)).await,
+        0xa0 => sys_uname(&ctx, TUA::from_value(arg1 as _)).await,
         0xa1 => sys_sethostname(&ctx, TUA::from_value(arg1 as _), arg2 as _).await,
+        0xa2 => sys_setdomainname(&ctx, TUA::from_value(arg1 as _), arg2 as _).await,
         0xa3 => Err(KernelError::InvalidValue),
         0xa6 => sys_umask(&ctx, arg1 as _).map_err(|e| match e {}),
         0xa7 => sys_prctl(&ctx, arg1 as _, arg2, arg3).await,
@@ -633,6 +674,16 @@ pub async fn handle_syscall(mut ctx: ProcessCtx) {
         0xb2 => sys_gettid(&ctx).map_err(|e| match e {}),
         0xb3 => sys_sysinfo(TUA::from_value(arg1 as _)).await,
         0xc6 => sys_socket(&ctx, arg1 as _, arg2 as _, arg3 as _).await,
+        0xc7 => {
+            sys_socketpair(
+                &ctx,
+                arg1 as _,
+                arg2 as _,
+                arg3 as _,
+                TUA::from_value(arg4 as _),
+            )
+            .await
+        }
         0xc8 => sys_bind(&ctx, arg1.into(), UA::from_value(arg2 as _), arg3 as _).await,
         0xc9 => sys_listen(&ctx, arg1.into(), arg2 as _).await,
         0xca => {
@@ -645,6 +696,24 @@ pub async fn handle_syscall(mut ctx: ProcessCtx) {
             .await
         }
         0xcb => sys_connect(&ctx, arg1.into(), UA::from_value(arg2 as _), arg3 as _).await,
+        0xcc => {
+            sys_getsockname(
+                &ctx,
+                arg1.into(),
+                UA::from_value(arg2 as _),
+                TUA::from_value(arg3 as _),
+            )
+            .await
+        }
+        0xcd => {
+            sys_getpeername(
+                &ctx,
+                arg1.into(),
+                UA::from_value(arg2 as _),
+                TUA::from_value(arg3 as _),
+            )
+            .await
+        }
         0xce => {
             sys_sendto(
                 &ctx,
@@ -669,7 +738,31 @@ pub async fn handle_syscall(mut ctx: ProcessCtx) {
             )
             .await
         }
+        0xd0 => {
+            sys_setsockopt(
+                &ctx,
+                arg1.into(),
+                arg2 as _,
+                arg3 as _,
+                TUA::from_value(arg4 as _),
+                arg5 as _,
+            )
+            .await
+        }
+        0xd1 => {
+            sys_getsockopt(
+                &ctx,
+                arg1.into(),
+                arg2 as _,
+                arg3 as _,
+                TUA::from_value(arg4 as _),
+                TUA::from_value(arg5 as _),
+            )
+            .await
+        }
         0xd2 => sys_shutdown(&ctx, arg1.into(), arg2 as _).await,
+        0xd3 => sys_sendmsg(&ctx, arg1.into(), TUA::from_value(arg2 as _), arg3 as _).await,
+        0xd4 => sys_recvmsg(&ctx, arg1.into(), TUA::from_value(arg2 as _), arg3 as _).await,
         0xd6 => sys_brk(&ctx, VA::from_value(arg1 as _))
             .await
             .map_err(|e| match e {}),
@@ -709,6 +802,17 @@ pub async fn handle_syscall(mut ctx: ProcessCtx) {
             )
             .await
         }
+        0xf3 => {
+            sys_recvmmsg(
+                &ctx,
+                arg1.into(),
+                TUA::from_value(arg2 as _),
+                arg3 as _,
+                arg4 as _,
+                TUA::from_value(arg5 as _),
+            )
+            .await
+        }
         0x104 => {
             sys_wait4(
                 &ctx,
@@ -732,8 +836,31 @@ pub async fn handle_syscall(mut ctx: ProcessCtx) {
         0x108 => sys_name_to_handle_at(),
         0x109 => Err(KernelError::NotSupported),
         0x10b => sys_syncfs(&ctx, arg1.into()).await,
+        0x10d => {
+            sys_sendmmsg(
+                &ctx,
+                arg1.into(),
+                TUA::from_value(arg2 as _),
+                arg3 as _,
+                arg4 as _,
+            )
+            .await
+        }
         0x10e => {
             sys_process_vm_readv(
+                &ctx,
+                arg1 as _,
+                TUA::from_value(arg2 as _),
+                arg3 as _,
+                TUA::from_value(arg4 as _),
+                arg5 as _,
+                arg6 as _,
+            )
+            .await
+        }
+        0x10f => {
+            sys_process_vm_writev(
+                &ctx,
                 arg1 as _,
                 TUA::from_value(arg2 as _),
                 arg3 as _,
@@ -755,6 +882,7 @@ pub async fn handle_syscall(mut ctx: ProcessCtx) {
             .await
         }
         0x116 => sys_getrandom(TUA::from_value(arg1 as _), arg2 as _, arg3 as _).await,
+        0x11a => sys_userfaultfd(&ctx, arg1 as _),
         0x11d => {
             sys_copy_file_range(
                 &ctx,
@@ -827,6 +955,10 @@ pub async fn handle_syscall(mut ctx: ProcessCtx) {
     };
 
     ctx.task_mut().ctx.user_mut().x[0] = ret_val.cast_unsigned() as u64;
+    trace_event(
+        TraceEvent::SyscallExit,
+        [nr as u64, ret_val.cast_unsigned() as u64, 0, 0],
+    );
     ptrace_stop(&ctx, TracePoint::SyscallExit).await;
     ctx.task_mut().update_accounting(None);
     ctx.task_mut().in_syscall = false;
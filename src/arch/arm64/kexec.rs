@@ -0,0 +1,173 @@
+//! Entering a new kernel loaded with `kexec_load`.
+//!
+//! The image may be placed over the running kernel, so the pages are copied
+//! into place by a small position independent routine, run with the MMU off
+//! from a control page which lies outside the image. The MMU is turned off
+//! from the kernel image's identity map, which is switched to beforehand.
+
+use super::{
+    boot::secondary::idmap_addr,
+    memory::{
+        cache::{clean_dcache_range, clean_inval_dcache_range},
+        tlb::AllEl0TlbInvalidator,
+    },
+};
+use crate::{arch::ArchImpl, kernel::kexec::KexecCopy, kfunc_pa, memory::PageOffsetTranslator};
+use aarch64_cpu::{
+    asm::barrier::{ISH, SY, dsb, isb},
+    registers::{ReadWriteable, TCR_EL1, TTBR0_EL1},
+};
+use core::{
+    arch::{global_asm, naked_asm},
+    mem::transmute,
+    slice,
+};
+use libkernel::{
+    CpuOps,
+    memory::{
+        PAGE_SIZE,
+        address::PA,
+        region::{PhysMemoryRegion, VirtMemoryRegion},
+    },
+};
+
+// Copies each page of the image into place, then enters the new kernel.
+//
+// x0: the KexecCopy list, x1: the number of copies, x2: the entry point,
+// x3: the argument for the new kernel.
+//
+// Runs with the MMU off, from a copy made elsewhere, so must be position
+// independent and use no stack.
+global_asm!(
+    ".pushsection .text",
+    ".balign 8",
+    ".globl kexec_relocate",
+    "kexec_relocate:",
+    "    cbz     x1, 3f",
+    "1:  ldp     x4, x5, [x0], #16", // dest, src
+    "    mov     x6, #{page_size}",
+    "2:  ldp     x7, x8, [x5], #16",
+    "    stp     x7, x8, [x4], #16",
+    "    subs    x6, x6, #16",
+    "    b.ne    2b",
+    "    subs    x1, x1, #1",
+    "    b.ne    1b",
+    "3:  dsb     sy",
+    "    ic      iallu",
+    "    dsb     sy",
+    "    isb",
+    "    mov     x9, x2",
+    "    mov     x0, x3",
+    "    mov     x1, xzr",
+    "    mov     x2, xzr",
+    "    mov     x3, xzr",
+    "    br      x9",
+    ".globl kexec_relocate_end",
+    "kexec_relocate_end:",
+    ".popsection",
+    page_size = const PAGE_SIZE,
+);
+
+unsafe extern "C" {
+    static kexec_relocate: u8;
+    static kexec_relocate_end: u8;
+}
+
+/// Turns the MMU and caches off, then branches to `relocate`, passing on the
+/// other arguments. Must be called through the identity map.
+#[unsafe(naked)]
+extern "C" fn kexec_mmu_off(
+    copies: usize,
+    nr_copies: usize,
+    entry: usize,
+    arg: usize,
+    relocate: usize,
+) -> ! {
+    naked_asm!(
+        "mrs  x9, sctlr_el1",
+        "mov  x10, #0x1005", // M, C and I.
+        "bic  x9, x9, x10",
+        "msr  sctlr_el1, x9",
+        "isb",
+        "br   x4",
+    )
+}
+
+pub fn kexec(
+    copies: PhysMemoryRegion,
+    nr_copies: usize,
+    control: PhysMemoryRegion,
+    entry: PA,
+    arg: PA,
+) -> ! {
+    ArchImpl::disable_interrupts();
+
+    // SAFETY: The copy list was written by `kexec_load`, and is only read
+    // from now on.
+    let list = unsafe {
+        slice::from_raw_parts(
+            copies
+                .map_via::<PageOffsetTranslator>()
+                .start_address()
+                .as_ptr()
+                .cast::<KexecCopy>(),
+            nr_copies,
+        )
+    };
+
+    let page = |pa: PA| PhysMemoryRegion::new(pa, PAGE_SIZE).map_via::<PageOffsetTranslator>();
+
+    // Everything the relocation routine reads must be in RAM once the caches
+    // are off. Destinations are invalidated as well, so that no dirty line
+    // is written back over the new kernel.
+    for copy in list {
+        clean_dcache_range(page(copy.src));
+        clean_inval_dcache_range(page(copy.dest));
+    }
+
+    clean_dcache_range(copies.map_via::<PageOffsetTranslator>());
+
+    // SAFETY: The routine lies between the two symbols, and the control page
+    // is ours to use.
+    unsafe {
+        let start = &raw const kexec_relocate;
+        let len = (&raw const kexec_relocate_end).offset_from(start) as usize;
+        let page = control.map_via::<PageOffsetTranslator>();
+
+        assert!(len <= control.size());
+
+        page.start_address()
+            .as_ptr_mut()
+            .cast::<u8>()
+            .copy_from_nonoverlapping(start, len);
+
+        clean_dcache_range(VirtMemoryRegion::new(page.start_address(), len));
+    }
+
+    dsb(SY);
+
+    let idmap = idmap_addr().expect("Idmap not set");
+
+    {
+        let _invalidator = AllEl0TlbInvalidator::new();
+        TTBR0_EL1.set_baddr(idmap.value() as u64);
+        dsb(ISH);
+        TCR_EL1.modify(TCR_EL1::EPD0::EnableTTBR0Walks);
+        isb(SY);
+    }
+
+    // SAFETY: `kexec_mmu_off` is part of the kernel image, which the ID map
+    // maps at its physical address.
+    unsafe {
+        let mmu_off: extern "C" fn(usize, usize, usize, usize, usize) -> ! =
+            transmute(kfunc_pa!(kexec_mmu_off as *const () as usize).value());
+
+        mmu_off(
+            copies.start_address().value(),
+            nr_copies,
+            entry.value(),
+            arg.value(),
+            control.start_address().value(),
+        )
+    }
+}
@@ -12,13 +12,13 @@ use alloc::vec::Vec;
 use libkernel::{
     arch::arm64::memory::{
         pg_descriptors::{L3Descriptor, MemoryType},
-        pg_tables::{L0Table, MapAttributes, MappingContext, map_range},
+        pg_tables::{L0Table, MapAttributes, MappingContext, map_range, split_block_mappings},
         pg_tear_down::tear_down_address_space,
-        pg_walk::{get_pte, walk_and_modify_region},
+        pg_walk::{l2_slot_is_free, translate, walk_and_modify_region},
     },
     error::{KernelError, MapError, Result},
     memory::{
-        PAGE_SIZE,
+        HUGE_PAGE_SIZE, PAGE_SIZE,
         address::{TPA, VA},
         page::PageFrame,
         paging::{
@@ -81,6 +81,59 @@ impl UserAddressSpace for Arm64ProcessAddressSpace {
         )
     }
 
+    fn can_map_huge_page(&self, va: VA) -> bool {
+        l2_slot_is_free(self.l0_table, va, &mut PageOffsetPgTableMapper {}).unwrap_or(false)
+    }
+
+    fn map_huge_page(
+        &mut self,
+        block: PhysMemoryRegion,
+        va: VA,
+        perms: PtePermissions,
+    ) -> Result<()> {
+        if block.size() != HUGE_PAGE_SIZE {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let mut ctx = MappingContext {
+            allocator: &mut PageTableAllocator::new(),
+            mapper: &mut PageOffsetPgTableMapper {},
+            invalidator: &AllEl0TlbInvalidator::new(),
+        };
+
+        // `map_range` will always use an L2 block descriptor here, since both
+        // addresses and the size are 2MiB aligned.
+        map_range(
+            self.l0_table,
+            MapAttributes {
+                phys: block,
+                virt: VirtMemoryRegion::new(va, HUGE_PAGE_SIZE),
+                mem_type: MemoryType::Normal,
+                perms,
+            },
+            &mut ctx,
+        )
+    }
+
+    fn split_huge_pages(&mut self, va_range: VirtMemoryRegion) -> Result<()> {
+        let mut ctx = MappingContext {
+            allocator: &mut PageTableAllocator::new(),
+            mapper: &mut PageOffsetPgTableMapper {},
+            invalidator: &AllEl0TlbInvalidator::new(),
+        };
+
+        split_block_mappings(self.l0_table, va_range, &mut ctx, |block| {
+            // SAFETY: The block was leaked when it was mapped by the fault
+            // handler. Each base page is leaked again, to be reclaimed
+            // individually by unmap or tear-down.
+            let alloc = unsafe { PAGE_ALLOC.get().unwrap().alloc_from_region(block) };
+
+            for page in alloc.split() {
+                page.leak();
+            }
+        })
+    }
+
     fn unmap(&mut self, _va: VA) -> Result<PageFrame> {
         todo!()
     }
@@ -94,6 +147,11 @@ impl UserAddressSpace for Arm64ProcessAddressSpace {
         walk_and_modify_region(self.l0_table, va_range, &mut walk_ctx, |_, desc| {
             match (perms.is_execute(), perms.is_read(), perms.is_write()) {
                 (false, false, false) => desc.mark_as_swapped(),
+                // The page may be shared; defer granting write access to the
+                // CoW fault handler.
+                (_, _, true) if !desc.permissions().is_some_and(|p| p.is_write()) => {
+                    desc.set_permissions(perms.into_cow())
+                }
                 _ => desc.set_permissions(perms),
             }
         })
@@ -137,16 +195,13 @@ impl UserAddressSpace for Arm64ProcessAddressSpace {
     }
 
     fn translate(&self, va: VA) -> Option<PageInfo> {
-        let pte = get_pte(
-            self.l0_table,
-            va.page_aligned(),
-            &mut PageOffsetPgTableMapper {},
-        )
-        .unwrap()?;
+        let (region, offset, perms) =
+            translate(self.l0_table, va, &mut PageOffsetPgTableMapper {}).unwrap()?;
 
+        // For huge pages, report the base page within the block.
         Some(PageInfo {
-            pfn: pte.mapped_address()?.to_pfn(),
-            perms: pte.permissions()?,
+            pfn: region.start_address().add_bytes(offset).to_pfn(),
+            perms,
         })
     }
 
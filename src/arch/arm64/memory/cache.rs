@@ -0,0 +1,45 @@
+//! Data cache maintenance by virtual address.
+
+use core::arch::asm;
+use libkernel::memory::region::VirtMemoryRegion;
+
+/// Returns the size of the smallest data cache line, in bytes.
+fn dcache_line_size() -> usize {
+    let ctr: usize;
+
+    unsafe { asm!("mrs {0}, ctr_el0", out(reg) ctr, options(nostack, nomem)) };
+
+    // CTR_EL0.DminLine is the log2 of the number of words in the line.
+    4 << ((ctr >> 16) & 0xf)
+}
+
+macro_rules! dcache_op_range {
+    ($op:literal, $region:expr) => {{
+        let region: VirtMemoryRegion = $region;
+        let stride = dcache_line_size();
+        let end = region.end_address().value();
+        let mut addr = region.start_address().value() & !(stride - 1);
+
+        while addr < end {
+            unsafe { asm!(concat!("dc ", $op, ", {0}"), in(reg) addr, options(nostack)) };
+
+            addr += stride;
+        }
+
+        // Ensure the maintenance has completed before any subsequent DMA is
+        // started or any data is read.
+        unsafe { asm!("dsb sy", options(nostack)) };
+    }};
+}
+
+/// Writes back any dirty cache lines covering `region` to the point of
+/// coherency.
+pub fn clean_dcache_range(region: VirtMemoryRegion) {
+    dcache_op_range!("cvac", region);
+}
+
+/// Writes back and invalidates any cache lines covering `region`, so that
+/// subsequent reads observe what is in memory.
+pub fn clean_inval_dcache_range(region: VirtMemoryRegion) {
+    dcache_op_range!("civac", region);
+}
@@ -1,48 +1,75 @@
-use core::mem;
+use core::{mem, slice};
 
 use crate::{
     arch::arm64::{
-        boot::memory::KERNEL_STACK_AREA,
+        backtrace::backtrace_from,
+        boot::memory::overflowed_kstack,
         exceptions::{
             ExceptionState,
             esr::{AbortIss, Exception, IfscCategory},
         },
         memory::uaccess::UAccessResult,
     },
-    memory::fault::{FaultResolution, handle_demand_fault, handle_protection_fault},
-    process::{ProcVM, thread_group::signal::SigId},
-    sched::{current_work, spawn_kernel_work, syscall_ctx::ProcessCtx},
-    sync::SpinLock,
+    kernel::{
+        backtrace::print_backtrace,
+        trace::{TraceEvent, trace_event},
+    },
+    memory::{
+        fault::{FaultResolution, handle_demand_fault, handle_protection_fault},
+        oom::out_of_memory,
+    },
+    process::{
+        Task,
+        thread_group::signal::{FaultInfo, SEGV_ACCERR, SEGV_MAPERR, SigId},
+    },
+    sched::{current_work, spawn_kernel_work, syscall_ctx::ProcessCtx, try_current_work},
 };
-use alloc::{boxed::Box, sync::Arc};
+use alloc::boxed::Box;
 use libkernel::{
-    error::Result,
+    error::{KernelError, Result},
     memory::{
-        address::VA,
+        address::{UA, VA},
         proc_vm::{address_space::UserAddressSpace, vmarea::AccessKind},
         region::VirtMemoryRegion,
     },
 };
+use log::error;
 
+/// An entry in the exception fixup table, emitted alongside each instruction
+/// that accesses user memory.
 #[repr(C)]
-struct FixupTable {
-    start: VA,
-    end: VA,
+struct FixupEntry {
+    /// The address of the instruction that may fault.
+    insn: VA,
+    /// Where to resume execution if the fault can't be resolved synchronously.
     fixup: VA,
 }
 
-unsafe extern "C" {
-    static __UACCESS_FIXUP: FixupTable;
-}
-
-impl FixupTable {
-    fn is_in_fixup(&self, addr: VA) -> bool {
-        VirtMemoryRegion::from_start_end_address(self.start, self.end).contains_address(addr)
+/// Returns the fixup address for the instruction at `addr`, if it's permitted
+/// to fault on user memory.
+fn search_fixups(addr: VA) -> Option<VA> {
+    unsafe extern "C" {
+        static __fixups_start: u8;
+        static __fixups_end: u8;
     }
+
+    // SAFETY: The linker script places the table, an array of `FixupEntry`,
+    // between these two symbols.
+    let fixups = unsafe {
+        let start = &__fixups_start as *const _ as *const FixupEntry;
+        let end = &__fixups_end as *const _ as *const FixupEntry;
+
+        slice::from_raw_parts(start, end.offset_from(start) as usize)
+    };
+
+    fixups
+        .iter()
+        .find(|entry| entry.insn == addr)
+        .map(|entry| entry.fixup)
 }
 
 fn run_mem_fault_handler(
-    proc_vm: Arc<SpinLock<ProcVM>>,
+    task: &Task,
     exception: Exception,
     info: AbortIss,
 ) -> Result<FaultResolution> {
@@ -51,12 +78,17 @@ fn run_mem_fault_handler(
     if let Some(far) = info.far {
         let fault_addr = VA::from_value(far as usize);
 
+        trace_event(TraceEvent::PageFault, [far, access_kind as u64, 0, 0]);
+
         match info.ifsc.category() {
-            IfscCategory::TranslationFault => {
-                handle_demand_fault(proc_vm.clone(), fault_addr, access_kind)
-            }
+            IfscCategory::TranslationFault => handle_demand_fault(
+                task.vm.clone(),
+                fault_addr,
+                access_kind,
+                task.process.stack_limit(),
+            ),
             IfscCategory::PermissionFault => {
-                let mut vm = proc_vm.lock_save_irq();
+                let mut vm = task.vm.lock_save_irq();
 
                 let pg_info = vm
                     .mm_mut()
@@ -73,15 +105,20 @@ fn run_mem_fault_handler(
     }
 }
 
-fn handle_uacess_abort(exception: Exception, info: AbortIss, state: &mut ExceptionState) {
-    match run_mem_fault_handler(current_work().vm.clone(), exception, info) {
+fn handle_uacess_abort(
+    exception: Exception,
+    info: AbortIss,
+    state: &mut ExceptionState,
+    fixup: VA,
+) {
+    match run_mem_fault_handler(&current_work(), exception, info) {
         // We mapped in a page, the uacess handler can proceed.
         Ok(FaultResolution::Resolved) => (),
         // If the fault couldn't be resolved, signal to the uacess fixup that
         // the abort failed.
         Ok(FaultResolution::Denied) => {
             state.x[0] = UAccessResult::AbortDenied as _;
-            state.elr_el1 = unsafe { __UACCESS_FIXUP.fixup.value() as u64 };
+            state.elr_el1 = fixup.value() as u64;
         }
         // If the page fault involves sleepy kernel work, we send that work
         // over to the uacess future for it to then await it.
@@ -96,47 +133,109 @@ fn handle_uacess_abort(exception: Exception, info: AbortIss, state: &mut Excepti
             state.x[0] = UAccessResult::AbortDeferred as _;
             state.x[1] = data_ptr as _;
             state.x[3] = vtable_ptr as _;
-            state.elr_el1 = unsafe { __UACCESS_FIXUP.fixup.value() as u64 };
+            state.elr_el1 = fixup.value() as u64;
+        }
+        // Free up some memory for the retry, but fail this access.
+        Err(KernelError::NoMemory) if out_of_memory() => {
+            state.x[0] = UAccessResult::AbortDenied as _;
+            state.elr_el1 = fixup.value() as u64;
         }
         Err(_) => panic!("Page fault handler error, SIGBUS on process"),
     }
 }
 
 pub fn handle_kernel_mem_fault(exception: Exception, info: AbortIss, state: &mut ExceptionState) {
-    if unsafe { __UACCESS_FIXUP.is_in_fixup(VA::from_value(state.elr_el1 as usize)) } {
-        handle_uacess_abort(exception, info, state);
+    if let Some(fixup) = search_fixups(VA::from_value(state.elr_el1 as usize)) {
+        handle_uacess_abort(exception, info, state, fixup);
         return;
     }
 
-    // If the source of the fault (ELR), wasn't in the uacess fixup section,
-    // then any abort genereated by the kernel is a panic since we don't
-    // demand-page any kernel memory.
+    // If the source of the fault (ELR), wasn't a user access instruction with
+    // an entry in the fixup table, then any abort genereated by the kernel is
+    // a panic since we don't demand-page any kernel memory.
     //
     // Try and differentiate between a stack overflow condition and other
     // faults.
     if let Some(far) = info.far
-        && KERNEL_STACK_AREA.contains_address(VA::from_value(far as _))
+        && let Some(stack) = overflowed_kstack(VA::from_value(far as _))
     {
+        report_kstack_overflow(stack, state);
+
         panic!("Kernel stack overflow detected.  Context:\n{state}");
     } else {
         panic!("Kernel memory fault detected.  Context:\n{state}");
     }
 }
 
+/// Logs the task which overflowed `stack`, and a backtrace of the faulting
+/// context.
+///
+/// We're running on the emergency stack, so the panic handler's own backtrace
+/// won't reach the frames which overflowed.
+fn report_kstack_overflow(stack: VirtMemoryRegion, state: &ExceptionState) {
+    let start = stack.start_address().value();
+    let end = stack.end_address().value();
+
+    match try_current_work() {
+        Some(work) => error!(
+            "Kernel stack {start:#x}-{end:#x} overflowed by task {} ({})",
+            work.tid.value(),
+            work.comm.lock_save_irq().as_str()
+        ),
+        None => error!("Kernel stack {start:#x}-{end:#x} overflowed by unknown task"),
+    }
+
+    let mut frames = [0; 32];
+    frames[0] = state.elr_el1 as usize;
+    let depth = 1 + backtrace_from(state.x[29] as usize, stack, &mut frames[1..]);
+
+    error!("Faulting context backtrace:");
+    print_backtrace(&frames[..depth]);
+}
+
 pub fn handle_mem_fault(ctx: &mut ProcessCtx, exception: Exception, info: AbortIss) {
-    match run_mem_fault_handler(ctx.shared().vm.clone(), exception, info) {
+    match run_mem_fault_handler(ctx.shared(), exception, info) {
         Ok(FaultResolution::Resolved) => {}
         Ok(FaultResolution::Denied) => {
-            ctx.task().process.deliver_signal(SigId::SIGSEGV);
+            let addr = VA::from_value(info.far.unwrap_or(0) as _);
+
+            // Distinguish between a hole in the address space (including a
+            // breached stack guard) and a mapping with insufficient
+            // permissions.
+            let code = if ctx
+                .shared()
+                .vm
+                .lock_save_irq()
+                .mm()
+                .find_vma(addr)
+                .is_some()
+            {
+                SEGV_ACCERR
+            } else {
+                SEGV_MAPERR
+            };
+
+            ctx.task().raise_fault_signal(FaultInfo {
+                signal: SigId::SIGSEGV,
+                code,
+                addr: UA::from_value(addr.value()),
+            });
         }
         // If the page fault involves sleepy kernel work, we can
         // spawn that work on the process, since there is no other
         // kernel work happening.
         Ok(FaultResolution::Deferred(fut)) => spawn_kernel_work(ctx, async {
-            if Box::into_pin(fut).await.is_err() {
-                panic!("Page fault defered error, SIGBUS on process");
+            match Box::into_pin(fut).await {
+                // A signal cut the wait short. It's delivered on the way back
+                // to userspace, after which the access is retried.
+                Ok(()) | Err(KernelError::Interrupted) => {}
+                Err(KernelError::NoMemory) if out_of_memory() => {}
+                Err(_) => panic!("Page fault defered error, SIGBUS on process"),
             }
         }),
+        // Once the OOM killer has freed up some memory, the faulting
+        // instruction is retried (unless this process was the victim).
+        Err(KernelError::NoMemory) if out_of_memory() => {}
         Err(_) => panic!("Page fault handler error, SIGBUS on process"),
     }
 }
@@ -1,26 +1,28 @@
+#[cfg(feature = "kasan")]
+use crate::memory::kasan::KasanReporter;
+#[cfg(feature = "kmemleak")]
+use crate::memory::kmemleak::KmemleakHooks;
 use crate::{
     arch::ArchImpl,
-    memory::{PageOffsetTranslator, page::PgAllocGetter},
-    sync::OnceLock,
+    memory::{PageOffsetTranslator, SLAB_ALLOC, SlabAlloc, page::PgAllocGetter},
 };
 use core::{
     arch::asm,
     ops::{Deref, DerefMut},
     ptr,
 };
+#[cfg(feature = "kasan")]
+use libkernel::memory::allocators::kasan::KasanHeap;
+#[cfg(feature = "kmemleak")]
+use libkernel::memory::allocators::kmemleak::KmemleakHeap;
 use libkernel::{
     CpuOps,
     memory::allocators::slab::{
-        allocator::SlabAllocator,
         cache::SlabCache,
         heap::{KHeap, SlabCacheStorage, SlabGetter},
     },
 };
 
-type SlabAlloc = SlabAllocator<ArchImpl, PgAllocGetter, PageOffsetTranslator>;
-
-pub static SLAB_ALLOC: OnceLock<SlabAlloc> = OnceLock::new();
-
 pub struct StaticSlabGetter {}
 
 impl SlabGetter<ArchImpl, PgAllocGetter, PageOffsetTranslator> for StaticSlabGetter {
@@ -91,5 +93,24 @@ impl Drop for PerCpuCache {
 pub type KernelHeap =
     KHeap<ArchImpl, PerCpuCache, PgAllocGetter, PageOffsetTranslator, StaticSlabGetter>;
 
+/// The kernel heap, wrapped by the leak detector when it's enabled.
+#[cfg(not(feature = "kmemleak"))]
+type TrackedHeap = KernelHeap;
+#[cfg(feature = "kmemleak")]
+type TrackedHeap = KmemleakHeap<KernelHeap, ArchImpl, KmemleakHooks>;
+
+const fn tracked_heap() -> TrackedHeap {
+    #[cfg(not(feature = "kmemleak"))]
+    return KernelHeap::new();
+
+    #[cfg(feature = "kmemleak")]
+    return KmemleakHeap::new(KernelHeap::new());
+}
+
+#[cfg(not(feature = "kasan"))]
+#[global_allocator]
+static K_HEAP: TrackedHeap = tracked_heap();
+
+#[cfg(feature = "kasan")]
 #[global_allocator]
-static K_HEAP: KernelHeap = KernelHeap::new();
+static K_HEAP: KasanHeap<TrackedHeap, ArchImpl, KasanReporter> = KasanHeap::new(tracked_heap());
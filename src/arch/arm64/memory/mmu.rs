@@ -1,15 +1,19 @@
 use super::{MMIO_BASE, tlb::AllEl1TlbInvalidator};
 use crate::sync::{OnceLock, SpinLock};
+use alloc::vec::Vec;
 use libkernel::{
     arch::arm64::memory::{
-        pg_descriptors::MemoryType,
+        pg_descriptors::{L3Descriptor, MemoryType},
         pg_tables::{L0Table, MapAttributes, MappingContext, map_range},
-        pg_walk::get_pte,
+        pg_walk::{get_pte, walk_and_modify_region},
     },
     error::Result,
     memory::{
         address::{PA, TPA, VA},
-        paging::{PaMapper, PgTableArray, permissions::PtePermissions},
+        page::PageFrame,
+        paging::{
+            PaMapper, PageTableEntry, PgTableArray, permissions::PtePermissions, walk::WalkContext,
+        },
         proc_vm::address_space::KernAddressSpace,
         region::{PhysMemoryRegion, VirtMemoryRegion},
     },
@@ -93,6 +97,24 @@ impl KernAddressSpace for Arm64KernelAddressSpace {
             base_va.value() + phys_mappable_region.offset(),
         ))
     }
+
+    fn unmap_range(&mut self, virt_range: VirtMemoryRegion) -> Result<Vec<PageFrame>> {
+        let mut walk_ctx = WalkContext {
+            mapper: &mut PageOffsetPgTableMapper {},
+            invalidator: &AllEl1TlbInvalidator::new(),
+        };
+        let mut unmapped_pages = Vec::new();
+
+        walk_and_modify_region(self.kernel_l0, virt_range, &mut walk_ctx, |_, desc| {
+            if let Some(addr) = desc.mapped_address() {
+                unmapped_pages.push(addr.to_pfn());
+            }
+
+            L3Descriptor::invalid()
+        })?;
+
+        Ok(unmapped_pages)
+    }
 }
 
 pub fn setup_kern_addr_space(pa: TPA<PgTableArray<L0Table>>) -> Result<()> {
@@ -3,6 +3,7 @@ use core::arch::asm;
 use libkernel::memory::address::{PA, VA};
 
 pub mod address_space;
+pub mod cache;
 pub mod fault;
 pub mod fixmap;
 pub mod heap;
@@ -13,6 +14,8 @@ pub mod uaccess;
 pub const PAGE_OFFSET: usize = 0xffff_0000_0000_0000;
 pub const IMAGE_BASE: VA = VA::from_value(0xffff_8000_0000_0000);
 pub const FIXMAP_BASE: VA = VA::from_value(0xffff_9000_0000_0000);
+pub const VMALLOC_BASE: VA = VA::from_value(0xffff_a000_0000_0000);
+pub const VMALLOC_SIZE: usize = 0x1000_0000_0000;
 pub const MMIO_BASE: VA = VA::from_value(0xffff_d000_0000_0000);
 pub const EXCEPTION_BASE: VA = VA::from_value(0xffff_e000_0000_0000);
 
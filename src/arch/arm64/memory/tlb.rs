@@ -1,3 +1,8 @@
+//! TLB maintenance.
+//!
+//! Invalidations are issued to the Inner Shareable domain, so they reach the
+//! TLBs of every CPU without needing a shootdown IPI.
+
 use core::arch::asm;
 
 use libkernel::memory::paging::TLBInvalidator;
@@ -42,20 +42,35 @@ impl From<u64> for UAccessResult {
 }
 
 /// A helper function to handle the common polling logic for uaccess operations.
+///
+/// If `partial` is set and a fault can't be resolved after some bytes have
+/// been copied, the number of bytes copied is returned rather than an error.
 fn poll_uaccess<F>(
     deferred_fault: &mut Option<Pin<Box<Fut>>>,
     bytes_coped: &mut usize,
+    partial: bool,
     cx: &mut Context<'_>,
     mut do_copy: F,
 ) -> Poll<Result<usize>>
 where
     F: FnMut(usize) -> (UAccessResult, usize, usize, usize),
 {
+    let fault = |bytes_copied: usize| {
+        if partial && bytes_copied > 0 {
+            Poll::Ready(Ok(bytes_copied))
+        } else {
+            Poll::Ready(Err(KernelError::Fault))
+        }
+    };
+
     // First, if a deferred fault has been set, poll that.
     loop {
         if let Some(mut fut) = deferred_fault.take() {
             match fut.as_mut().poll(cx) {
-                Poll::Ready(Err(_)) => return Poll::Ready(Err(KernelError::Fault)),
+                Poll::Ready(Err(KernelError::Interrupted)) => {
+                    return Poll::Ready(Err(KernelError::Interrupted));
+                }
+                Poll::Ready(Err(_)) => return fault(*bytes_coped),
                 Poll::Ready(Ok(())) => {}
                 Poll::Pending => {
                     *deferred_fault = Some(fut);
@@ -70,7 +85,7 @@ where
 
         match status {
             UAccessResult::Ok => return Poll::Ready(Ok(new_bytes_copied)),
-            UAccessResult::AbortDenied => return Poll::Ready(Err(KernelError::Fault)),
+            UAccessResult::AbortDenied => return fault(new_bytes_copied),
             UAccessResult::AbortDeferred => {
                 *bytes_coped = new_bytes_copied;
                 let ptr: *mut Fut =
@@ -115,17 +130,29 @@ fn do_copy_from_user(
 }
 
 pub fn try_copy_from_user(src: UA, dst: *const (), len: usize) -> Result<()> {
-    match do_copy_from_user(src, dst, len, 0).0 {
-        UAccessResult::Ok => Ok(()),
-        UAccessResult::AbortDenied => Err(KernelError::Fault),
-        UAccessResult::AbortDeferred => Err(KernelError::Fault),
+    match do_copy_from_user(src, dst, len, 0) {
+        (UAccessResult::Ok, ..) => Ok(()),
+        (UAccessResult::AbortDenied, ..) => Err(KernelError::Fault),
+        (UAccessResult::AbortDeferred, work_ptr, work_vtable, _) => {
+            // We can't sleep here, so discard the work needed to resolve the
+            // fault.
+            let ptr: *mut Fut =
+                unsafe { transmute((work_ptr as *mut (), work_vtable as *const ())) };
+            drop(unsafe { Box::from_raw(ptr) });
+
+            Err(KernelError::Fault)
+        }
     }
 }
 
+/// Copies from userspace, resolving any faults along the way. Resolves to the
+/// number of bytes copied, which is only less than the requested length for
+/// partial copies.
 pub struct Arm64CopyFromUser {
     src: UA,
     dst: *const (),
     len: usize,
+    partial: bool,
     bytes_coped: usize,
     deferred_fault: Option<Pin<Box<Fut>>>,
 }
@@ -136,14 +163,24 @@ impl Arm64CopyFromUser {
             src,
             dst,
             len,
+            partial: false,
             bytes_coped: 0,
             deferred_fault: None,
         }
     }
+
+    /// As [`Self::new`], but stops at the first unresolvable fault rather
+    /// than failing, provided at least one byte was copied.
+    pub fn new_partial(src: UA, dst: *const (), len: usize) -> Self {
+        Self {
+            partial: true,
+            ..Self::new(src, dst, len)
+        }
+    }
 }
 
 impl Future for Arm64CopyFromUser {
-    type Output = Result<()>;
+    type Output = Result<usize>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let this = unsafe { self.get_unchecked_mut() };
@@ -151,10 +188,10 @@ impl Future for Arm64CopyFromUser {
         poll_uaccess(
             &mut this.deferred_fault,
             &mut this.bytes_coped,
+            this.partial,
             cx,
             |bytes_copied| do_copy_from_user(this.src, this.dst, this.len, bytes_copied),
         )
-        .map(|x| x.map(|_| ()))
     }
 }
 
@@ -187,6 +224,7 @@ impl Future for Arm64CopyStrnFromUser {
         poll_uaccess(
             &mut this.deferred_fault,
             &mut this.bytes_coped,
+            false,
             cx,
             |mut bytes_copied| {
                 let mut status: u64;
@@ -219,10 +257,14 @@ impl Future for Arm64CopyStrnFromUser {
     }
 }
 
+/// Copies to userspace, resolving any faults along the way. Resolves to the
+/// number of bytes copied, which is only less than the requested length for
+/// partial copies.
 pub struct Arm64CopyToUser {
     src: *const (),
     dst: UA,
     len: usize,
+    partial: bool,
     bytes_coped: usize,
     deferred_fault: Option<Pin<Box<Fut>>>,
 }
@@ -233,14 +275,24 @@ impl Arm64CopyToUser {
             src,
             dst,
             len,
+            partial: false,
             bytes_coped: 0,
             deferred_fault: None,
         }
     }
+
+    /// As [`Self::new`], but stops at the first unresolvable fault rather
+    /// than failing, provided at least one byte was copied.
+    pub fn new_partial(src: *const (), dst: UA, len: usize) -> Self {
+        Self {
+            partial: true,
+            ..Self::new(src, dst, len)
+        }
+    }
 }
 
 impl Future for Arm64CopyToUser {
-    type Output = Result<()>;
+    type Output = Result<usize>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let this = unsafe { self.get_unchecked_mut() };
@@ -248,6 +300,7 @@ impl Future for Arm64CopyToUser {
         poll_uaccess(
             &mut this.deferred_fault,
             &mut this.bytes_coped,
+            this.partial,
             cx,
             |mut bytes_copied| {
                 let mut status: u64;
@@ -276,6 +329,5 @@ impl Future for Arm64CopyToUser {
                 )
             },
         )
-        .map(|x| x.map(|_| ()))
     }
 }
@@ -1,5 +1,13 @@
+// Every instruction that accesses user memory must have an entry in the
+// exception fixup table, pairing it with the code to resume at should the
+// access fault. When the kernel takes an abort on such an instruction, the
+// fault handler attempts to resolve the fault. If it can't do so
+// synchronously, it sets x0 to the abort status (and x1/x3 to any deferred
+// work) and resumes execution at the fixup.
+//
+// x2 holds the number of bytes copied so far and is preserved across the
+// fault, so the caller can resume or report a partial copy.
 
-__uacess_begin:
 // Copy the type 'T: UserCopyable' from userspace.
 //
 // Arguments:
@@ -18,11 +26,16 @@ __uacess_begin:
 __do_copy_from_user:
     cmp     x2, x3
     beq     1f
-    ldrb    w4, [x0, x2]
+2:  ldrb    w4, [x0, x2]
     strb    w4, [x1, x2]
     add     x2, x2, #1
     b       __do_copy_from_user
 
+    .pushsection .exception_fixups, "a"
+    .balign 8
+    .quad   2b, __uaccess_fixup
+    .popsection
+
 // Copy bytes userspace, halting when encountering a NULL byte, or reaching the
 // end of the buffer..
 //
@@ -42,13 +55,17 @@ __do_copy_from_user:
 __do_copy_from_user_halt_nul:
     cmp     x2, x3
     beq     1f
-    ldrb    w4, [x0, x2]
+2:  ldrb    w4, [x0, x2]
     strb    w4, [x1, x2]
     cmp     w4, #0
     beq     1f
     add     x2, x2, #1
     b       __do_copy_from_user_halt_nul
 
+    .pushsection .exception_fixups, "a"
+    .balign 8
+    .quad   2b, __uaccess_fixup
+    .popsection
 
 // Copy the type 'T: UserCopyable' to userspace.
 //
@@ -69,18 +86,15 @@ __do_copy_to_user:
     cmp     x2, x3
     beq     1f
     ldrb    w4, [x0, x2]
-    strb    w4, [x1, x2]
+2:  strb    w4, [x1, x2]
     add     x2, x2, #1
     b       __do_copy_to_user
 
+    .pushsection .exception_fixups, "a"
+    .balign 8
+    .quad   2b, __uaccess_fixup
+    .popsection
+
 1:  mov     x0, #0
-__uacess_end:
-fixup:
+__uaccess_fixup:
     ret
-
-    .section .exception_fixups
-    .global __UACCESS_FIXUP
-__UACCESS_FIXUP:
-    .quad   __uacess_begin
-    .quad   __uacess_end
-    .quad   fixup
@@ -6,25 +6,29 @@ use alloc::string::String;
 use alloc::sync::Arc;
 use cpu_ops::{local_irq_restore, local_irq_save};
 use exceptions::ExceptionState;
+use futures::FutureExt;
 use libkernel::{
     CpuOps,
     arch::arm64::memory::pg_tables::L0Table,
     error::Result,
     memory::{
-        address::{UA, VA},
+        address::{PA, UA, VA},
         paging::PgTableArray,
         proc_vm::address_space::VirtualMemory,
+        region::{PhysMemoryRegion, VirtMemoryRegion},
     },
 };
 use memory::{
-    PAGE_OFFSET,
+    PAGE_OFFSET, VMALLOC_BASE, VMALLOC_SIZE,
     address_space::Arm64ProcessAddressSpace,
+    cache::{clean_dcache_range, clean_inval_dcache_range},
     mmu::{Arm64KernelAddressSpace, KERN_ADDR_SPC},
     uaccess::{Arm64CopyFromUser, Arm64CopyStrnFromUser, Arm64CopyToUser, try_copy_from_user},
 };
 use ptrace::Arm64PtraceGPRegs;
 
 use crate::{
+    memory::dma::DmaDirection,
     process::{
         Task,
         owned::OwnedTask,
@@ -36,10 +40,14 @@ use crate::{
 
 use super::Arch;
 
+mod acpi;
+mod backtrace;
 mod boot;
 mod cpu_ops;
+mod cpuidle;
 mod exceptions;
 mod fdt;
+mod kexec;
 mod memory;
 mod proc;
 pub mod psci;
@@ -71,6 +79,11 @@ impl CpuOps for Aarch64 {
     fn enable_interrupts() {
         DAIF.modify(DAIF::I::Unmasked);
     }
+
+    fn wait_for_interrupt() {
+        // WFI wakes on a pending interrupt regardless of the DAIF mask.
+        wfi();
+    }
 }
 
 impl VirtualMemory for Aarch64 {
@@ -89,6 +102,8 @@ impl Arch for Aarch64 {
 
     const PAGE_OFFSET: usize = PAGE_OFFSET;
 
+    const VMALLOC_AREA: VirtMemoryRegion = VirtMemoryRegion::new(VMALLOC_BASE, VMALLOC_SIZE);
+
     fn new_user_context(entry_point: VA, stack_top: VA) -> Self::UserContext {
         ExceptionState {
             x: [0; 31],
@@ -151,28 +166,85 @@ impl Arch for Aarch64 {
         Self::halt()
     }
 
+    fn cpu_off() -> ! {
+        const PSCI_CPU_OFF: u32 = 0x8400_0002;
+        unsafe {
+            psci::do_psci_hyp_call(PSCI_CPU_OFF, 0, 0, 0);
+        }
+
+        // Fallback: halt the CPU indefinitely.
+        Self::halt()
+    }
+
+    fn kexec(
+        copies: PhysMemoryRegion,
+        nr_copies: usize,
+        control: PhysMemoryRegion,
+        entry: PA,
+        arg: PA,
+    ) -> ! {
+        kexec::kexec(copies, nr_copies, control, entry, arg)
+    }
+
     fn get_cmdline() -> Option<String> {
         fdt::get_cmdline()
     }
 
+    fn capture_backtrace(frames: &mut [usize]) -> usize {
+        backtrace::capture_backtrace(frames)
+    }
+
+    fn dma_sync_for_device(region: VirtMemoryRegion, dir: DmaDirection) {
+        match dir {
+            // Invalidate as well, so that a dirty line can't be evicted over
+            // data the device has written.
+            DmaDirection::FromDevice => clean_inval_dcache_range(region),
+            DmaDirection::ToDevice | DmaDirection::Bidirectional => clean_dcache_range(region),
+        }
+    }
+
+    fn dma_sync_for_cpu(region: VirtMemoryRegion, dir: DmaDirection) {
+        // Discard any lines speculatively fetched while the device owned the
+        // buffer.
+        if dir != DmaDirection::ToDevice {
+            clean_inval_dcache_range(region);
+        }
+    }
+
     unsafe fn copy_from_user(
         src: UA,
         dst: *mut (),
         len: usize,
     ) -> impl Future<Output = Result<()>> {
-        Arm64CopyFromUser::new(src, dst, len)
+        Arm64CopyFromUser::new(src, dst, len).map(|res| res.map(|_| ()))
     }
 
     unsafe fn try_copy_from_user(src: UA, dst: *mut (), len: usize) -> Result<()> {
         try_copy_from_user(src, dst, len)
     }
 
+    unsafe fn copy_from_user_partial(
+        src: UA,
+        dst: *mut (),
+        len: usize,
+    ) -> impl Future<Output = Result<usize>> {
+        Arm64CopyFromUser::new_partial(src, dst, len)
+    }
+
     unsafe fn copy_to_user(
         src: *const (),
         dst: UA,
         len: usize,
     ) -> impl Future<Output = Result<()>> {
-        Arm64CopyToUser::new(src, dst, len)
+        Arm64CopyToUser::new(src, dst, len).map(|res| res.map(|_| ()))
+    }
+
+    unsafe fn copy_to_user_partial(
+        src: *const (),
+        dst: UA,
+        len: usize,
+    ) -> impl Future<Output = Result<usize>> {
+        Arm64CopyToUser::new_partial(src, dst, len)
     }
 
     unsafe fn copy_strn_from_user(
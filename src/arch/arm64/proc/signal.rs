@@ -3,10 +3,12 @@ use crate::{
     arch::arm64::exceptions::ExceptionState,
     memory::uaccess::{UserCopyable, copy_from_user, copy_to_user},
     process::thread_group::signal::{
-        SigId, ksigaction::UserspaceSigAction, sigaction::SigActionFlags,
+        FaultInfo, SigId, SigSet, ksigaction::UserspaceSigAction, sigaction::SigActionFlags,
+        sigaltstack::UserSigAltStack,
     },
     sched::syscall_ctx::ProcessCtx,
 };
+use core::mem;
 use libkernel::{
     error::Result,
     memory::{
@@ -15,11 +17,77 @@ use libkernel::{
     },
 };
 
+/// The generic `siginfo_t` layout, with the `si_addr` member of the union
+/// used by fault signals.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct UserSigInfo {
+    signo: i32,
+    errno: i32,
+    code: i32,
+    _pad: i32,
+    addr: u64,
+    _rest: [u64; 13],
+}
+
+impl UserSigInfo {
+    fn new(id: SigId, fault: Option<FaultInfo>) -> Self {
+        Self {
+            signo: id.user_id() as _,
+            errno: 0,
+            code: fault.map(|f| f.code).unwrap_or(0),
+            _pad: 0,
+            addr: fault.map(|f| f.addr.value() as u64).unwrap_or(0),
+            _rest: [0; 13],
+        }
+    }
+}
+
+/// The condition flags, which are all of `PSTATE` that a signal handler may
+/// change on return.
+const PSTATE_NZCV: u64 = 0xf000_0000;
+
+/// Space for the records which follow the registers in a `sigcontext`, e.g.
+/// the FP/SIMD state. None are saved, so it holds only the zeroed terminator.
+#[repr(C, align(16))]
+#[derive(Clone, Copy)]
+struct SigContextReserved([u8; 4096]);
+
+/// The AArch64 `struct sigcontext`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SigContext {
+    fault_address: u64,
+    regs: [u64; 31],
+    sp: u64,
+    pc: u64,
+    pstate: u64,
+    _pad: u64,
+    reserved: SigContextReserved,
+}
+
+/// The AArch64 `struct ucontext`, as passed to an `SA_SIGINFO` handler.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct UContext {
+    flags: u64,
+    link: u64,
+    stack: UserSigAltStack,
+    sigmask: SigSet,
+    _unused: [u8; 1024 / 8 - size_of::<SigSet>()],
+    _pad: u64,
+    mcontext: SigContext,
+}
+
+const _: () = assert!(mem::offset_of!(UContext, mcontext) == 176);
+
 #[repr(C)]
 #[derive(Clone, Copy)]
 struct RtSigFrame {
-    uctx: ExceptionState,
+    info: UserSigInfo,
+    uc: UContext,
     alt_stack_prev_addr: UA,
+    _pad: u64,
 }
 
 // SAFETY: The signal frame that's copied to user-space only contains
@@ -36,9 +104,36 @@ pub async fn do_signal(
 
     let saved_state = *task.ctx.user();
     let mut new_state = saved_state;
+
+    // Fault details are only reported for the signal that the fault raised.
+    let fault = task
+        .fault_info
+        .lock_save_irq()
+        .take_if(|info| info.signal == id);
+
+    let old_mask = task.sig_mask.load();
+
     let mut frame = RtSigFrame {
-        uctx: saved_state,
+        info: UserSigInfo::new(id, fault),
+        uc: UContext {
+            flags: 0,
+            link: 0,
+            stack: signal.alt_stack.clone().into(),
+            sigmask: old_mask,
+            _unused: [0; _],
+            _pad: 0,
+            mcontext: SigContext {
+                fault_address: fault.map(|f| f.addr.value() as u64).unwrap_or(0),
+                regs: saved_state.x,
+                sp: saved_state.sp_el0,
+                pc: saved_state.elr_el1,
+                pstate: saved_state.spsr_el1,
+                _pad: 0,
+                reserved: SigContextReserved([0; _]),
+            },
+        },
         alt_stack_prev_addr: UA::null(),
+        _pad: 0,
     };
 
     // Use the provided restorer trampoline, or the one provided by the VDSO if
@@ -64,11 +159,27 @@ pub async fn do_signal(
 
     copy_to_user(addr, frame).await?;
 
+    // Block the handler's mask, and the signal itself unless asked not to,
+    // until it returns.
+    let mut handler_mask = old_mask.union(sa.mask);
+
+    if !sa.flags.contains(SigActionFlags::SA_NODEFER) {
+        handler_mask.insert(id.into());
+    }
+
+    handler_mask.remove(SigSet::UNMASKABLE_SIGNALS);
+    task.sig_mask.store(handler_mask);
+
     new_state.sp_el0 = addr.value() as _;
     new_state.elr_el1 = sa.action.value() as _;
     new_state.x[30] = restorer as _;
     new_state.x[0] = id.user_id();
 
+    if sa.flags.contains(SigActionFlags::SA_SIGINFO) {
+        new_state.x[1] = addr.value() as u64 + mem::offset_of!(RtSigFrame, info) as u64;
+        new_state.x[2] = addr.value() as u64 + mem::offset_of!(RtSigFrame, uc) as u64;
+    }
+
     Ok(new_state)
 }
 
@@ -78,6 +189,7 @@ pub async fn do_signal_return(ctx: ProcessCtx) -> Result<ExceptionState> {
     let sig_frame_addr: TUA<RtSigFrame> = TUA::from_value(task.ctx.user().sp_el0 as _);
 
     let sig_frame = copy_from_user(sig_frame_addr).await?;
+    let mcontext = &sig_frame.uc.mcontext;
 
     if !sig_frame.alt_stack_prev_addr.is_null() {
         task.process
@@ -89,5 +201,18 @@ pub async fn do_signal_return(ctx: ProcessCtx) -> Result<ExceptionState> {
             .restore_alt_stack(sig_frame.alt_stack_prev_addr);
     }
 
-    Ok(sig_frame.uctx)
+    let mut mask = sig_frame.uc.sigmask;
+    mask.remove(SigSet::UNMASKABLE_SIGNALS);
+    task.sig_mask.store(mask);
+
+    let mut state = *task.ctx.user();
+
+    state.x = mcontext.regs;
+    state.sp_el0 = mcontext.sp;
+    state.elr_el1 = mcontext.pc;
+    // Anything beyond the condition flags could return the handler to a
+    // privileged mode.
+    state.spsr_el1 = mcontext.pstate & PSTATE_NZCV;
+
+    Ok(state)
 }
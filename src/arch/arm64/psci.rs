@@ -1,11 +1,16 @@
+use crate::drivers::fdt_prober::get_fdt;
 use core::arch::naked_asm;
-use libkernel::memory::address::PA;
+use libkernel::{
+    error::{KernelError, Result},
+    memory::address::PA,
+};
 
 pub struct PSCIEntry {
     pub method: PSCIMethod,
     pub cpu_on_id: Option<u32>,
 }
 
+#[derive(Clone, Copy)]
 pub enum PSCIMethod {
     Hvc,
     Smc,
@@ -13,6 +18,20 @@ pub enum PSCIMethod {
 
 const CPU_ON_ID: u32 = 0xc400_0003;
 
+/// Returns the conduit given by the device tree's `psci` node.
+pub fn fdt_psci_method() -> Result<PSCIMethod> {
+    let fdt = get_fdt();
+    let psci_node = fdt
+        .get_node_by_name("psci")
+        .ok_or(KernelError::Other("psci node missing"))?;
+
+    match psci_node.find_property("method").map(|x| x.str()) {
+        Some("hvc") => Ok(PSCIMethod::Hvc),
+        Some("smc") => Ok(PSCIMethod::Smc),
+        _ => Err(KernelError::Other("Unknown method in psci node")),
+    }
+}
+
 // Re-export the low-level PSCI helpers so other modules (e.g. `arch::arm64::mod`)
 // can invoke them without repeating the `use` dance.
 
@@ -39,6 +58,14 @@ pub fn boot_secondary_psci(entry: PSCIEntry, core_id: usize, entry_fn: PA, ctx:
     };
 }
 
+/// Makes a PSCI call through the given conduit.
+pub fn psci_call(method: &PSCIMethod, id: u32, arg1: u64, arg2: u64, arg3: u64) -> i64 {
+    match method {
+        PSCIMethod::Hvc => unsafe { do_psci_hyp_call(id, arg1, arg2, arg3) },
+        PSCIMethod::Smc => unsafe { do_psci_smc_call(id, arg1, arg2, arg3) },
+    }
+}
+
 #[unsafe(naked)]
 pub unsafe extern "C" fn do_psci_hyp_call(id: u32, arg1: u64, arg2: u64, arg3: u64) -> i64 {
     naked_asm!("hvc #0", "ret")
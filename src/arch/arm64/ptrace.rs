@@ -1,4 +1,4 @@
-use crate::memory::uaccess::UserCopyable;
+use crate::{memory::uaccess::UserCopyable, process::ptrace::SyscallRegs};
 
 use super::exceptions::ExceptionState;
 
@@ -23,3 +23,29 @@ impl From<&ExceptionState> for Arm64PtraceGPRegs {
         }
     }
 }
+
+impl SyscallRegs for Arm64PtraceGPRegs {
+    const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+    fn instruction_pointer(&self) -> u64 {
+        self.pc
+    }
+
+    fn stack_pointer(&self) -> u64 {
+        self.sp
+    }
+
+    fn syscall_nr(&self) -> u64 {
+        self.x[8]
+    }
+
+    fn syscall_args(&self) -> [u64; 6] {
+        [
+            self.x[0], self.x[1], self.x[2], self.x[3], self.x[4], self.x[5],
+        ]
+    }
+
+    fn syscall_ret(&self) -> u64 {
+        self.x[0]
+    }
+}
@@ -9,10 +9,11 @@
 //! architecture-specific functions and types.
 
 use crate::{
-    memory::uaccess::UserCopyable,
+    memory::{dma::DmaDirection, uaccess::UserCopyable},
     process::{
         Task,
         owned::OwnedTask,
+        ptrace::SyscallRegs,
         thread_group::signal::{SigId, ksigaction::UserspaceSigAction},
     },
     sched::syscall_ctx::ProcessCtx,
@@ -23,8 +24,9 @@ use libkernel::{
     CpuOps,
     error::Result,
     memory::{
-        address::{UA, VA},
+        address::{PA, UA, VA},
         proc_vm::address_space::VirtualMemory,
+        region::{PhysMemoryRegion, VirtMemoryRegion},
     },
 };
 
@@ -35,11 +37,15 @@ pub trait Arch: CpuOps + VirtualMemory {
     type UserContext: Sized + Send + Sync + Clone;
 
     /// The type for GP regs copied via `PTRACE_GETREGSET`.
-    type PTraceGpRegs: UserCopyable + for<'a> From<&'a Self::UserContext>;
+    type PTraceGpRegs: UserCopyable + SyscallRegs + for<'a> From<&'a Self::UserContext>;
 
     /// The starting address for the logical mapping of all physical ram.
     const PAGE_OFFSET: usize;
 
+    /// The region of kernel virtual address space used for virtually
+    /// contiguous (vmalloc) allocations.
+    const VMALLOC_AREA: VirtMemoryRegion;
+
     fn name() -> &'static str;
 
     fn cpu_count() -> usize;
@@ -62,8 +68,37 @@ pub trait Arch: CpuOps + VirtualMemory {
     /// Restarts the machine. Implementations must never return.
     fn restart() -> !;
 
+    /// Takes the calling CPU offline. Implementations must never return.
+    fn cpu_off() -> !;
+
+    /// Enters a kernel loaded by `kexec_load`. `copies` holds `nr_copies`
+    /// [`KexecCopy`](crate::kernel::kexec::KexecCopy)s, each a page to be
+    /// copied into place first, which may be done from the `control` page.
+    /// `entry` is then jumped to, with `arg` as its first argument.
+    ///
+    /// Called with the other CPUs stopped. Implementations must never return.
+    fn kexec(
+        copies: PhysMemoryRegion,
+        nr_copies: usize,
+        control: PhysMemoryRegion,
+        entry: PA,
+        arg: PA,
+    ) -> !;
+
     fn get_cmdline() -> Option<String>;
 
+    /// Writes the return addresses of the current call stack, innermost
+    /// first, into `frames`. Returns the number of frames captured.
+    fn capture_backtrace(frames: &mut [usize]) -> usize;
+
+    /// Performs any cache maintenance needed before the device accesses
+    /// `region` in a DMA transfer of direction `dir`.
+    fn dma_sync_for_device(region: VirtMemoryRegion, dir: DmaDirection);
+
+    /// Performs any cache maintenance needed before the CPU reads `region`
+    /// after a DMA transfer of direction `dir` has completed.
+    fn dma_sync_for_cpu(region: VirtMemoryRegion, dir: DmaDirection);
+
     /// Call a user-specified signal handler in the current process.
     fn do_signal(
         ctx: ProcessCtx,
@@ -139,6 +174,26 @@ pub trait Arch: CpuOps + VirtualMemory {
     /// `copy_from_user_slice` whenever possible.
     unsafe fn try_copy_from_user(src: UA, dst: *mut (), len: usize) -> Result<()>;
 
+    /// Copies a block of memory from userspace to the kernel, stopping at the
+    /// first fault that can't be resolved.
+    ///
+    /// This behaves as `copy_from_user`, except that a fault part-way through
+    /// the copy isn't an error: the number of bytes successfully copied is
+    /// returned instead, as required by syscalls such as `write`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `KernelError::Fault` if no bytes could be copied.
+    ///
+    /// # Safety
+    ///
+    /// As for `copy_from_user`.
+    unsafe fn copy_from_user_partial(
+        src: UA,
+        dst: *mut (),
+        len: usize,
+    ) -> impl Future<Output = Result<usize>>;
+
     /// Copies a block of memory from the kernel to userspace.
     ///
     /// This is the raw, unsafe primitive for transferring data from a kernel
@@ -175,6 +230,26 @@ pub trait Arch: CpuOps + VirtualMemory {
     unsafe fn copy_to_user(src: *const (), dst: UA, len: usize)
     -> impl Future<Output = Result<()>>;
 
+    /// Copies a block of memory from the kernel to userspace, stopping at the
+    /// first fault that can't be resolved.
+    ///
+    /// This behaves as `copy_to_user`, except that a fault part-way through
+    /// the copy isn't an error: the number of bytes successfully copied is
+    /// returned instead, as required by syscalls such as `read`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `KernelError::Fault` if no bytes could be copied.
+    ///
+    /// # Safety
+    ///
+    /// As for `copy_to_user`.
+    unsafe fn copy_to_user_partial(
+        src: *const (),
+        dst: UA,
+        len: usize,
+    ) -> impl Future<Output = Result<usize>>;
+
     /// Copies a null-terminated string from userspace into a kernel buffer.
     ///
     /// Copies at most `len` bytes from the userspace address `src` into the
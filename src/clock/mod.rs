@@ -2,6 +2,7 @@ pub mod realtime;
 pub mod syscalls;
 pub mod timer;
 pub mod timespec;
+pub mod timeval;
 
 pub enum ClockId {
     Realtime = 0,
@@ -1,12 +1,12 @@
 use crate::{
     drivers::timer::{Instant, now, uptime},
-    sync::SpinLock,
+    sync::SeqLock,
 };
 use core::time::Duration;
 
 // Return a duration from the epoch.
 pub fn date() -> Duration {
-    let epoch_info = *EPOCH_DURATION.lock_save_irq();
+    let epoch_info = EPOCH_DURATION.read();
 
     if let Some(ep_info) = epoch_info
         && let Some(now) = now()
@@ -20,13 +20,13 @@ pub fn date() -> Duration {
 
 pub fn set_date(duration: Duration) {
     if let Some(now) = now() {
-        let mut epoch_info = EPOCH_DURATION.lock_save_irq();
-        *epoch_info = Some((duration, now));
+        EPOCH_DURATION.set(Some((duration, now)));
     }
 }
 
-// Represents a known duration since the epoch at the associated instant.
-static EPOCH_DURATION: SpinLock<Option<(Duration, Instant)>> = SpinLock::new(None);
+// Represents a known duration since the epoch at the associated instant. Read
+// on every clock_gettime(), so readers shouldn't contend with each other.
+static EPOCH_DURATION: SeqLock<Option<(Duration, Instant)>> = SeqLock::new(None);
 
 #[cfg(test)]
 mod tests {
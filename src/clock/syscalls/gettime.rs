@@ -6,7 +6,7 @@ use libkernel::{
 };
 
 use crate::clock::{ClockId, realtime::date, timespec::TimeSpec};
-use crate::drivers::timer::{Instant, now};
+use crate::drivers::timer::{Instant, now, resolution};
 use crate::sched::syscall_ctx::ProcessCtx;
 use crate::{drivers::timer::uptime, memory::uaccess::copy_to_user};
 
@@ -46,3 +46,20 @@ pub async fn sys_clock_gettime(
 
     Ok(0)
 }
+
+pub async fn sys_clock_getres(clockid: i32, res: TUA<TimeSpec>) -> Result<usize> {
+    // Every clock we support is read from the system timer.
+    match ClockId::try_from(clockid).map_err(|_| KernelError::InvalidValue)? {
+        ClockId::Realtime
+        | ClockId::Monotonic
+        | ClockId::ProcessCpuTimeId
+        | ClockId::ThreadCpuTimeId => {}
+        _ => return Err(KernelError::InvalidValue),
+    }
+
+    if !res.is_null() {
+        copy_to_user(res, resolution().into()).await?;
+    }
+
+    Ok(0)
+}
@@ -2,3 +2,4 @@ pub mod gettime;
 pub mod itimer;
 pub mod settime;
 pub mod timeofday;
+pub mod timerfd;
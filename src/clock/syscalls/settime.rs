@@ -1,6 +1,7 @@
 use crate::clock::ClockId;
 use crate::clock::realtime::set_date;
 use crate::clock::timespec::TimeSpec;
+use crate::drivers::rtc::update_rtc;
 use crate::memory::uaccess::copy_from_user;
 use libkernel::error::KernelError;
 use libkernel::memory::address::TUA;
@@ -20,6 +21,7 @@ pub async fn sys_clock_settime(
         }
         ClockId::Realtime => {
             set_date(time_spec.into());
+            update_rtc(time_spec.into());
             Ok(0)
         }
         _ => Err(KernelError::NotSupported),
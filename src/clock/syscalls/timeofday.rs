@@ -1,5 +1,6 @@
 use crate::clock::realtime::{date, set_date};
 use crate::clock::timespec::TimeSpec;
+use crate::drivers::rtc::update_rtc;
 use crate::memory::uaccess::{UserCopyable, copy_from_user, copy_to_user};
 use core::time::Duration;
 use libkernel::{error::Result, memory::address::TUA};
@@ -37,6 +38,7 @@ pub async fn sys_settimeofday(tv: TUA<TimeSpec>, _tz: TUA<TimeZone>) -> Result<u
         let time: TimeSpec = copy_from_user(tv).await?;
         let duration: Duration = time.into();
         set_date(duration);
+        update_rtc(duration);
     }
     Ok(0)
 }
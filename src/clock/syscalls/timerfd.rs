@@ -0,0 +1,351 @@
+use crate::clock::{ClockId, realtime::date, timespec::TimeSpec};
+use crate::drivers::timer::{TIMERS, uptime};
+use crate::fs::fops::FileOps;
+use crate::fs::open_file::{FileCtx, OpenFile};
+use crate::memory::uaccess::{UserCopyable, copy_from_user, copy_to_user};
+use crate::process::fd_table::{Fd, FdFlags};
+use crate::process::thread_group::signal::{InterruptResult, Interruptable};
+use crate::sched::syscall_ctx::ProcessCtx;
+use crate::sync::CondVar;
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use async_trait::async_trait;
+use bitflags::bitflags;
+use core::future;
+use core::mem::size_of;
+use core::pin::Pin;
+use core::time::Duration;
+use futures::FutureExt;
+use libkernel::error::{KernelError, Result};
+use libkernel::fs::OpenFlags;
+use libkernel::memory::address::{TUA, UA};
+use libkernel::sync::condvar::WakeupType;
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct TimerFdFlags: u32 {
+        const TFD_NONBLOCK = OpenFlags::O_NONBLOCK.bits();
+        const TFD_CLOEXEC = OpenFlags::O_CLOEXEC.bits();
+    }
+}
+
+const TFD_TIMER_ABSTIME: u32 = 1;
+
+#[derive(Copy, Clone, Default)]
+#[repr(C)]
+pub struct ITimerSpec {
+    it_interval: TimeSpec,
+    it_value: TimeSpec,
+}
+
+unsafe impl UserCopyable for ITimerSpec {}
+
+#[derive(Default)]
+struct TimerState {
+    /// When the timer next expires, against [`uptime`], or `None` if it's
+    /// disarmed.
+    next: Option<Duration>,
+    /// The period of the timer, or zero if it only expires once.
+    interval: Duration,
+    /// Expirations which haven't been read yet.
+    expirations: u64,
+    /// Bumped whenever the timer is set, so that anyone waiting on the old
+    /// setting looks again.
+    generation: u64,
+}
+
+impl TimerState {
+    /// Counts the expirations up to `now`, moving the timer on to its next
+    /// expiry.
+    fn catch_up(&mut self, now: Duration) {
+        let Some(next) = self.next.filter(|next| *next <= now) else {
+            return;
+        };
+
+        if self.interval.is_zero() {
+            self.expirations += 1;
+            self.next = None;
+        } else {
+            let interval = self.interval.as_nanos();
+            let periods = (now - next).as_nanos() / interval + 1;
+
+            self.expirations += periods as u64;
+            self.next = Some(next + Duration::from_nanos((periods * interval) as u64));
+        }
+    }
+}
+
+/// A timer whose expirations are read from a file, as a count of how many
+/// there have been since the last read.
+///
+/// Expirations are counted when the timer is looked at, rather than from an
+/// interrupt, so an idle timer costs nothing.
+pub struct TimerFd {
+    clock: ClockId,
+    state: CondVar<TimerState>,
+}
+
+impl TimerFd {
+    fn new(clock: ClockId) -> Self {
+        Self {
+            clock,
+            state: CondVar::new(TimerState::default()),
+        }
+    }
+
+    /// Takes the expirations counted so far, if there have been any.
+    fn take_expirations(&self) -> Option<u64> {
+        let mut expirations = None;
+
+        self.state.update(|state| {
+            state.catch_up(uptime());
+            if state.expirations > 0 {
+                expirations = Some(core::mem::take(&mut state.expirations));
+            }
+            WakeupType::None
+        });
+
+        expirations
+    }
+
+    async fn do_read(&self, buf: UA, count: usize, nonblock: bool) -> Result<usize> {
+        if count < size_of::<u64>() {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let expirations = loop {
+            if let Some(expirations) = self.take_expirations() {
+                break expirations;
+            }
+
+            if nonblock {
+                return Err(KernelError::TryAgain);
+            }
+
+            if let InterruptResult::Interrupted =
+                wait_for_expiry(self.state.clone()).interruptable().await
+            {
+                return Err(KernelError::Interrupted);
+            }
+        };
+
+        copy_to_user(buf.cast(), expirations).await?;
+
+        Ok(size_of::<u64>())
+    }
+
+    /// Returns the current setting, with the time left until the next expiry.
+    fn get(&self) -> ITimerSpec {
+        let mut spec = ITimerSpec::default();
+
+        self.state.update(|state| {
+            let now = uptime();
+
+            state.catch_up(now);
+            spec = ITimerSpec {
+                it_interval: state.interval.into(),
+                it_value: state.next.map_or(Duration::ZERO, |next| next - now).into(),
+            };
+            WakeupType::None
+        });
+
+        spec
+    }
+
+    /// Arms the timer with `spec`, or disarms it if `it_value` is zero,
+    /// returning the previous setting.
+    fn set(&self, spec: ITimerSpec, absolute: bool) -> ITimerSpec {
+        let old = self.get();
+        let value: Duration = spec.it_value.into();
+
+        let next = if value.is_zero() {
+            None
+        } else if !absolute {
+            Some(uptime() + value)
+        } else {
+            // Absolute times are against the timer's own clock, so convert them
+            // to the equivalent uptime now.
+            let clock_now = match self.clock {
+                ClockId::Realtime => date(),
+                _ => uptime(),
+            };
+
+            Some(uptime() + value.saturating_sub(clock_now))
+        };
+
+        self.state.update(|state| {
+            state.next = next;
+            state.interval = spec.it_interval.into();
+            state.expirations = 0;
+            state.generation += 1;
+            WakeupType::All
+        });
+
+        old
+    }
+}
+
+/// Waits until the timer has expired, or has been set again.
+async fn wait_for_expiry(state: CondVar<TimerState>) {
+    let mut next = None;
+    let mut generation = 0;
+
+    state.update(|state| {
+        state.catch_up(uptime());
+        next = (state.expirations == 0).then_some(state.next);
+        generation = state.generation;
+        WakeupType::None
+    });
+
+    let Some(next) = next else {
+        // It's already expired.
+        return;
+    };
+
+    let mut expired = Box::pin(
+        async move {
+            match next {
+                Some(next) => TIMERS.sleep_until(next).await,
+                None => future::pending::<()>().await,
+            }
+        }
+        .fuse(),
+    );
+    let mut reset = Box::pin(
+        state
+            .wait_until(move |state| (state.generation != generation).then_some(()))
+            .fuse(),
+    );
+
+    futures::select_biased! {
+        _ = expired => (),
+        _ = reset => (),
+    }
+}
+
+#[async_trait]
+impl FileOps for TimerFd {
+    async fn read(&mut self, ctx: &mut FileCtx, buf: UA, count: usize) -> Result<usize> {
+        self.do_read(buf, count, ctx.flags.contains(OpenFlags::O_NONBLOCK))
+            .await
+    }
+
+    async fn readat(&mut self, buf: UA, count: usize, _offset: u64) -> Result<usize> {
+        self.do_read(buf, count, false).await
+    }
+
+    async fn writeat(&mut self, _buf: UA, _count: usize, _offset: u64) -> Result<usize> {
+        Err(KernelError::InvalidValue)
+    }
+
+    fn poll_read_ready(&self) -> Pin<Box<dyn Future<Output = Result<()>> + 'static + Send>> {
+        let state = self.state.clone();
+        Box::pin(async move {
+            loop {
+                let mut expired = false;
+
+                state.update(|state| {
+                    state.catch_up(uptime());
+                    expired = state.expirations > 0;
+                    WakeupType::None
+                });
+
+                if expired {
+                    return Ok(());
+                }
+
+                wait_for_expiry(state.clone()).await;
+            }
+        })
+    }
+
+    fn as_timerfd(&mut self) -> Option<&mut TimerFd> {
+        Some(self)
+    }
+}
+
+pub async fn sys_timerfd_create(ctx: &ProcessCtx, clockid: i32, flags: u32) -> Result<usize> {
+    let clock = match ClockId::try_from(clockid).map_err(|_| KernelError::InvalidValue)? {
+        clock @ (ClockId::Realtime | ClockId::Monotonic | ClockId::BootTime) => clock,
+        _ => return Err(KernelError::InvalidValue),
+    };
+    let flags = TimerFdFlags::from_bits(flags).ok_or(KernelError::InvalidValue)?;
+
+    let file_flags = if flags.contains(TimerFdFlags::TFD_NONBLOCK) {
+        OpenFlags::O_RDONLY | OpenFlags::O_NONBLOCK
+    } else {
+        OpenFlags::O_RDONLY
+    };
+    let fd_flags = if flags.contains(TimerFdFlags::TFD_CLOEXEC) {
+        FdFlags::CLOEXEC
+    } else {
+        FdFlags::empty()
+    };
+
+    let file = Arc::new(OpenFile::new(Box::new(TimerFd::new(clock)), file_flags));
+    let fd = ctx
+        .shared()
+        .fd_table
+        .lock_save_irq()
+        .insert_with_flags(file, fd_flags)?;
+
+    Ok(fd.as_raw() as _)
+}
+
+fn timerfd_file(ctx: &ProcessCtx, fd: Fd) -> Result<Arc<OpenFile>> {
+    ctx.shared()
+        .fd_table
+        .lock_save_irq()
+        .get(fd)
+        .ok_or(KernelError::BadFd)
+}
+
+pub async fn sys_timerfd_settime(
+    ctx: &ProcessCtx,
+    fd: Fd,
+    flags: u32,
+    new_value: TUA<ITimerSpec>,
+    old_value: TUA<ITimerSpec>,
+) -> Result<usize> {
+    if flags & !TFD_TIMER_ABSTIME != 0 {
+        return Err(KernelError::InvalidValue);
+    }
+
+    let spec: ITimerSpec = copy_from_user(new_value).await?;
+    for time in [spec.it_interval, spec.it_value] {
+        if time.tv_sec < 0 || time.tv_nsec > 999_999_999 {
+            return Err(KernelError::InvalidValue);
+        }
+    }
+
+    let file = timerfd_file(ctx, fd)?;
+    let old = {
+        let (ops, _) = &mut *file.lock().await;
+        let timerfd = ops.as_timerfd().ok_or(KernelError::InvalidValue)?;
+
+        timerfd.set(spec, flags & TFD_TIMER_ABSTIME != 0)
+    };
+
+    if !old_value.is_null() {
+        copy_to_user(old_value, old).await?;
+    }
+
+    Ok(0)
+}
+
+pub async fn sys_timerfd_gettime(
+    ctx: &ProcessCtx,
+    fd: Fd,
+    curr_value: TUA<ITimerSpec>,
+) -> Result<usize> {
+    let file = timerfd_file(ctx, fd)?;
+    let spec = {
+        let (ops, _) = &mut *file.lock().await;
+
+        ops.as_timerfd().ok_or(KernelError::InvalidValue)?.get()
+    };
+
+    copy_to_user(curr_value, spec).await?;
+
+    Ok(0)
+}
@@ -8,7 +8,7 @@ use libkernel::{
 use crate::memory::uaccess::{UserCopyable, copy_from_user};
 
 #[repr(C)]
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Default)]
 pub struct TimeSpec {
     pub tv_sec: i64,
     pub tv_nsec: u64,
@@ -0,0 +1,49 @@
+use core::time::Duration;
+
+use libkernel::{
+    error::{KernelError, Result},
+    memory::address::TUA,
+};
+
+use crate::memory::uaccess::{UserCopyable, copy_from_user};
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TimeVal {
+    pub tv_sec: i64,
+    pub tv_usec: i64,
+}
+
+unsafe impl UserCopyable for TimeVal {}
+
+impl From<TimeVal> for Duration {
+    fn from(value: TimeVal) -> Self {
+        Duration::new(value.tv_sec as _, (value.tv_usec * 1000) as _)
+    }
+}
+
+impl From<Duration> for TimeVal {
+    fn from(value: Duration) -> Self {
+        TimeVal {
+            tv_sec: value.as_secs() as _,
+            tv_usec: value.subsec_micros() as _,
+        }
+    }
+}
+
+impl TimeVal {
+    pub async fn copy_from_user(src: TUA<Self>) -> Result<Self> {
+        let timeval = copy_from_user(src).await?;
+
+        // Sanity checking.
+        if !(0..1_000_000).contains(&timeval.tv_usec) {
+            return Err(KernelError::InvalidValue);
+        }
+
+        if timeval.tv_sec < 0 {
+            return Err(KernelError::InvalidValue);
+        }
+
+        Ok(timeval)
+    }
+}
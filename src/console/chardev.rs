@@ -37,7 +37,7 @@ impl OpenableDevice for ConsoleDev {
     fn open(&self, flags: OpenFlags) -> Result<Arc<OpenFile>> {
         let char_dev_desc = match *CONSOLE.lock_save_irq() {
             super::ConsoleState::Buffered => return Err(FsError::NoDevice.into()),
-            super::ConsoleState::Device(_, char_dev_descriptor) => char_dev_descriptor,
+            super::ConsoleState::Device(_, char_dev_descriptor, _) => char_dev_descriptor,
         };
 
         let char_driver = DM
@@ -0,0 +1,310 @@
+//! The kernel log.
+//!
+//! Log records are kept in a ring buffer as lines of text, in the format read
+//! by `dmesg`: `<level>[seconds.micros] message`. The consoles print the log
+//! from the buffer, so anything logged before a console is registered is
+//! printed once it is. Userspace reads the log through `syslog` and
+//! /proc/kmsg.
+
+use crate::{
+    process::thread_group::signal::{InterruptResult, Interruptable},
+    sync::{CondVar, OnceLock, SpinLock},
+};
+use core::{
+    fmt::{self, Write},
+    sync::atomic::{AtomicBool, AtomicU8, Ordering, fence},
+    time::Duration,
+};
+use libkernel::{
+    error::{KernelError, Result},
+    sync::condvar::WakeupType,
+};
+use log::Level;
+
+/// The size of the log buffer.
+pub const LOG_BUF_LEN: usize = 1 << 18;
+
+/// The longest line kept in the log. Longer messages are truncated.
+pub const LOG_LINE_MAX: usize = 1024;
+
+/// Lines are printed to the consoles if their level is below the console
+/// loglevel. By default, everything is printed.
+const DEFAULT_CONSOLE_LOGLEVEL: u8 = 8;
+const MINIMUM_CONSOLE_LOGLEVEL: u8 = 1;
+
+static CONSOLE_LOGLEVEL: AtomicU8 = AtomicU8::new(DEFAULT_CONSOLE_LOGLEVEL);
+
+/// The console loglevel from before the consoles were turned off, or 0 if
+/// they're on.
+static SAVED_CONSOLE_LOGLEVEL: AtomicU8 = AtomicU8::new(0);
+
+struct LogBuf {
+    buf: [u8; LOG_BUF_LEN],
+    // Positions count every byte ever logged, so only grow. The byte at
+    // position `pos` is kept at `buf[pos % LOG_BUF_LEN]`.
+    /// Where the next line will be written.
+    head: usize,
+    /// The start of the oldest line still in the buffer.
+    tail: usize,
+    /// The next byte to be read by `SYSLOG_ACTION_READ` and /proc/kmsg.
+    read: usize,
+    /// Where the log was last cleared.
+    clear: usize,
+}
+
+impl LogBuf {
+    const fn new() -> Self {
+        Self {
+            buf: [0; LOG_BUF_LEN],
+            head: 0,
+            tail: 0,
+            read: 0,
+            clear: 0,
+        }
+    }
+
+    fn byte(&self, pos: usize) -> u8 {
+        self.buf[pos % LOG_BUF_LEN]
+    }
+
+    /// Returns the position just past the end of the line containing `pos`.
+    fn line_end(&self, pos: usize) -> usize {
+        (pos..self.head)
+            .find(|&pos| self.byte(pos) == b'\n')
+            .map_or(self.head, |pos| pos + 1)
+    }
+
+    /// Appends a line made up of `parts`, dropping the oldest lines to make
+    /// room for it.
+    fn push_line(&mut self, parts: &[&[u8]]) {
+        let len = parts.iter().map(|part| part.len()).sum::<usize>() + 1;
+
+        while self.head + len - self.tail > LOG_BUF_LEN {
+            self.tail = self.line_end(self.tail);
+        }
+
+        for &b in parts.iter().copied().flatten().chain(b"\n") {
+            self.buf[self.head % LOG_BUF_LEN] = b;
+            self.head += 1;
+        }
+
+        self.read = self.read.max(self.tail);
+        self.clear = self.clear.max(self.tail);
+    }
+
+    /// Copies out as much of the log from `start` as fits in `buf`, returning
+    /// how much was copied.
+    fn copy_out(&self, start: usize, buf: &mut [u8]) -> usize {
+        let len = buf.len().min(self.head - start);
+
+        for (i, b) in buf[..len].iter_mut().enumerate() {
+            *b = self.byte(start + i);
+        }
+
+        len
+    }
+}
+
+static LOG_BUF: SpinLock<LogBuf> = SpinLock::new(LogBuf::new());
+
+/// Tasks waiting in [`read`]. Created by the first of them, since this module
+/// is in use before the heap is.
+static READERS: OnceLock<CondVar<()>> = OnceLock::new();
+
+/// Set while readers are being woken. Waking them may log, which mustn't try
+/// to wake them again.
+static WAKING_READERS: AtomicBool = AtomicBool::new(false);
+
+/// Formats into a fixed buffer, truncating whatever doesn't fit.
+struct LineBuf<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> LineBuf<N> {
+    fn new() -> Self {
+        Self {
+            buf: [0; N],
+            len: 0,
+        }
+    }
+
+    fn bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+impl<const N: usize> Write for LineBuf<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let len = s.len().min(N - self.len);
+
+        self.buf[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+
+        Ok(())
+    }
+}
+
+fn syslog_level(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// Appends a message to the log. A message spanning several lines is logged
+/// as that many lines.
+pub fn log(level: Level, timestamp: Duration, args: fmt::Arguments) {
+    let mut prefix = LineBuf::<32>::new();
+    let _ = write!(
+        prefix,
+        "<{}>[{:5}.{:06}] ",
+        syslog_level(level),
+        timestamp.as_secs(),
+        timestamp.subsec_micros()
+    );
+
+    let mut msg = LineBuf::<LOG_LINE_MAX>::new();
+    let _ = msg.write_fmt(args);
+
+    let max_len = LOG_LINE_MAX - prefix.bytes().len() - 1;
+
+    {
+        let mut log = LOG_BUF.lock_save_irq();
+
+        for line in msg.bytes().trim_ascii_end().split(|&b| b == b'\n') {
+            log.push_line(&[prefix.bytes(), &line[..line.len().min(max_len)]]);
+        }
+    }
+
+    // Pairs with the reader checking the log after registering itself, so
+    // that one of the two sees the other.
+    fence(Ordering::SeqCst);
+
+    if let Some(readers) = READERS.get()
+        && !WAKING_READERS.swap(true, Ordering::SeqCst)
+    {
+        readers.update(|_| WakeupType::All);
+        WAKING_READERS.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Copies the line at `next` into `buf`, then moves `next` on to the line
+/// after it. Returns the length of the line, or `None` if `next` is the end
+/// of the log. If the line at `next` has been dropped from the log, the oldest
+/// line is copied instead.
+pub fn read_line(next: &mut usize, buf: &mut [u8; LOG_LINE_MAX]) -> Option<usize> {
+    let log = LOG_BUF.lock_save_irq();
+    let start = (*next).max(log.tail);
+
+    if start == log.head {
+        return None;
+    }
+
+    let end = log.line_end(start);
+    let len = log.copy_out(start, &mut buf[..end - start]);
+
+    *next = end;
+
+    Some(len)
+}
+
+/// Splits a line of the log into its level and the rest of the line.
+pub fn split_level(line: &[u8]) -> (u8, &[u8]) {
+    match line {
+        [b'<', level @ b'0'..=b'7', b'>', rest @ ..] => (level - b'0', rest),
+        _ => (syslog_level(Level::Info), line),
+    }
+}
+
+/// Reads from the log, from where the last read finished, waiting for a line
+/// to be logged if there's nothing new. Each byte is only read once.
+pub async fn read(buf: &mut [u8]) -> Result<usize> {
+    if buf.is_empty() {
+        return Ok(0);
+    }
+
+    let readers = READERS.get_or_init(|| CondVar::new(()));
+
+    loop {
+        let unread = readers.wait_until(|_| {
+            let log = LOG_BUF.lock_save_irq();
+
+            (log.read.max(log.tail) < log.head).then_some(())
+        });
+
+        if let InterruptResult::Interrupted = unread.interruptable().await {
+            return Err(KernelError::Interrupted);
+        }
+
+        let mut log = LOG_BUF.lock_save_irq();
+        let start = log.read.max(log.tail);
+        let len = log.copy_out(start, buf);
+
+        log.read = start + len;
+
+        // Another reader may have got there first.
+        if len != 0 {
+            return Ok(len);
+        }
+    }
+}
+
+/// Copies out as many of the most recent lines as fit in `buf`, without
+/// marking them as read. Lines from before the log was last cleared are
+/// left out.
+pub fn read_all(buf: &mut [u8]) -> usize {
+    let log = LOG_BUF.lock_save_irq();
+    let mut start = log.clear.max(log.tail);
+
+    while log.head - start > buf.len() {
+        start = log.line_end(start);
+    }
+
+    log.copy_out(start, buf)
+}
+
+/// Clears the log, as far as [`read_all`] is concerned.
+pub fn clear() {
+    let mut log = LOG_BUF.lock_save_irq();
+
+    log.clear = log.head;
+}
+
+/// Returns how much of the log hasn't yet been [`read`].
+pub fn unread() -> usize {
+    let log = LOG_BUF.lock_save_irq();
+
+    log.head - log.read.max(log.tail)
+}
+
+/// Returns the level below which lines are printed to the consoles.
+pub fn console_loglevel() -> u8 {
+    CONSOLE_LOGLEVEL.load(Ordering::Relaxed)
+}
+
+/// Sets the console loglevel, turning the consoles back on if they were off.
+pub fn set_console_loglevel(level: u8) {
+    CONSOLE_LOGLEVEL.store(level.max(MINIMUM_CONSOLE_LOGLEVEL), Ordering::Relaxed);
+    SAVED_CONSOLE_LOGLEVEL.store(0, Ordering::Relaxed);
+}
+
+/// Stops the consoles printing anything but the most severe messages.
+pub fn console_off() {
+    let level = CONSOLE_LOGLEVEL.swap(MINIMUM_CONSOLE_LOGLEVEL, Ordering::Relaxed);
+
+    if SAVED_CONSOLE_LOGLEVEL.load(Ordering::Relaxed) == 0 {
+        SAVED_CONSOLE_LOGLEVEL.store(level, Ordering::Relaxed);
+    }
+}
+
+/// Restores the console loglevel from before [`console_off`].
+pub fn console_on() {
+    let level = SAVED_CONSOLE_LOGLEVEL.swap(0, Ordering::Relaxed);
+
+    if level != 0 {
+        CONSOLE_LOGLEVEL.store(level, Ordering::Relaxed);
+    }
+}
@@ -1,4 +1,7 @@
-use alloc::sync::{Arc, Weak};
+use alloc::{
+    sync::{Arc, Weak},
+    vec::Vec,
+};
 use core::{
     fmt::{self, Write},
     ptr::addr_of_mut,
@@ -11,6 +14,7 @@ use tty::TtyInputHandler;
 use crate::{drivers::timer::uptime, sync::SpinLock};
 
 mod buf;
+pub mod kmsg;
 pub mod tty;
 use buf::BufConsole;
 pub mod chardev;
@@ -31,12 +35,18 @@ static mut EARLY_BOOT_BUFFER: BufConsole = BufConsole::new();
 enum ConsoleState {
     /// Early boot, messages are written to a temporary memory buffer.
     Buffered,
-    /// A real console driver has been initialized.
-    Device(Arc<dyn Console>, CharDevDescriptor),
+    /// A real console driver has been initialized. It has printed the kernel
+    /// log up to the given position.
+    Device(Arc<dyn Console>, CharDevDescriptor, usize),
 }
 
 static CONSOLE: SpinLock<ConsoleState> = SpinLock::new(ConsoleState::Buffered);
 
+/// Consoles which mirror the kernel log, in addition to the active console,
+/// e.g. a framebuffer console, and the position in the log each has printed up
+/// to.
+static LOG_SINKS: SpinLock<Vec<(Arc<dyn Console>, usize)>> = SpinLock::new(Vec::new());
+
 /// Writes formatted output to the active console.
 pub fn write_fmt(args: fmt::Arguments) -> fmt::Result {
     let console_state = CONSOLE.lock_save_irq();
@@ -47,7 +57,7 @@ pub fn write_fmt(args: fmt::Arguments) -> fmt::Result {
             // can be reading or writing to the buffer at the same time.
             unsafe { (*addr_of_mut!(EARLY_BOOT_BUFFER)).write_fmt(args) }
         }
-        ConsoleState::Device(ref console, _) => console.write_fmt(args),
+        ConsoleState::Device(ref console, _, _) => console.write_fmt(args),
     }
 }
 
@@ -58,9 +68,16 @@ pub fn set_active_console(
 ) -> Result<(), KernelError> {
     let mut console_state = CONSOLE.lock_save_irq();
 
+    // Carry on from where the old console got to in the kernel log. A console
+    // replacing the buffer prints the log from the start.
+    let log_pos = match *console_state {
+        ConsoleState::Buffered => 0,
+        ConsoleState::Device(_, _, log_pos) => log_pos,
+    };
+
     let old_state = core::mem::replace(
         &mut *console_state,
-        ConsoleState::Device(console.clone(), char_dev),
+        ConsoleState::Device(console.clone(), char_dev, log_pos),
     );
 
     // If the old state was the buffer, flush its contents to the new device.
@@ -75,9 +92,73 @@ pub fn set_active_console(
         }
     }
 
+    if let ConsoleState::Device(ref console, _, ref mut log_pos) = *console_state {
+        print_log(console.as_ref(), log_pos);
+    }
+
     Ok(())
 }
 
+/// Mirrors the kernel log to `console` from now on.
+pub fn add_log_sink(console: Arc<dyn Console>) {
+    let mut log_pos = 0;
+
+    print_log(console.as_ref(), &mut log_pos);
+    LOG_SINKS.lock_save_irq().push((console, log_pos));
+}
+
+/// Prints the lines of the kernel log from `log_pos` which are below the
+/// console loglevel, moving `log_pos` on past them.
+fn print_log(console: &dyn Console, log_pos: &mut usize) {
+    let mut line = [0; kmsg::LOG_LINE_MAX];
+
+    while let Some(len) = kmsg::read_line(log_pos, &mut line) {
+        let (level, text) = kmsg::split_level(&line[..len]);
+
+        if level < kmsg::console_loglevel() {
+            console.write_buf(text.strip_suffix(b"\n").unwrap_or(text));
+            console.write_buf(b"\r\n");
+        }
+    }
+}
+
+/// Prints any new lines of the kernel log to the consoles.
+fn flush_log() {
+    if let ConsoleState::Device(ref console, _, ref mut log_pos) = *CONSOLE.lock_save_irq() {
+        print_log(console.as_ref(), log_pos);
+    }
+
+    for (sink, log_pos) in LOG_SINKS.lock_save_irq().iter_mut() {
+        print_log(sink.as_ref(), log_pos);
+    }
+}
+
+/// Prints the lines of `log`, a copy of the end of the kernel log, which the
+/// console loglevel kept off the console. Used on panic, so that the messages
+/// leading up to it are seen regardless.
+pub fn print_held_back(log: &[u8]) {
+    let mut held_back = log
+        .split(|&b| b == b'\n')
+        .map(kmsg::split_level)
+        .filter(|&(level, text)| level >= kmsg::console_loglevel() && !text.is_empty())
+        .peekable();
+
+    if held_back.peek().is_none() {
+        return;
+    }
+
+    let _ = write_fmt(format_args!(
+        "Recent messages below the console loglevel:\r\n"
+    ));
+
+    for (_, text) in held_back {
+        let _ = write_fmt(format_args!(
+            "{}\r\n",
+            str::from_utf8(text).unwrap_or("<invalid UTF-8>")
+        ));
+    }
+}
+
 struct ConsoleLogger;
 static CONSOLE_LOGGER: ConsoleLogger = ConsoleLogger;
 
@@ -87,17 +168,20 @@ impl Log for ConsoleLogger {
     }
 
     fn log(&self, record: &log::Record) {
-        let uptime = uptime();
-        let _ = write_fmt(format_args!(
-            "[{:5}.{:06}] {}: {}\r\n",
-            uptime.as_secs(),
-            uptime.as_micros(),
-            record
-                .module_path()
-                .map(|x| x.strip_prefix("moss::").unwrap_or(x))
-                .unwrap_or(""),
-            *record.args()
-        ));
+        kmsg::log(
+            record.level(),
+            uptime(),
+            format_args!(
+                "{}: {}",
+                record
+                    .module_path()
+                    .map(|x| x.strip_prefix("moss::").unwrap_or(x))
+                    .unwrap_or(""),
+                *record.args()
+            ),
+        );
+
+        flush_log();
     }
 
     fn flush(&self) {}
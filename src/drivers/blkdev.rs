@@ -0,0 +1,234 @@
+//! Block devices, which get a node in /dev when they're registered, as does
+//! each partition on them. Each also gets a directory in sysfs, under
+//! `/sys/block`, giving its size and device number.
+//!
+//! Every block device shares one major number, as block devices numbered as
+//! they turn up do on Linux, and is told apart by its minor number. An open
+//! node reads and writes the device as one big file.
+
+use crate::{
+    drivers::fs::{
+        dev::devfs,
+        sys::kobject::{Attribute, KObject, kobject_root},
+    },
+    fs::{
+        fops::FileOps,
+        open_file::{FileCtx, OpenFile},
+    },
+    memory::uaccess::{copy_from_user_slice, copy_to_user, copy_to_user_slice},
+    sync::SpinLock,
+};
+use alloc::{
+    boxed::Box,
+    collections::btree_map::BTreeMap,
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec,
+};
+use async_trait::async_trait;
+use core::{
+    cmp::min,
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use libkernel::{
+    driver::CharDevDescriptor,
+    error::{FsError, KernelError, Result},
+    fs::{
+        BlockDevice, OpenFlags, SeekFrom,
+        attr::FilePermissions,
+        blk::{
+            buffer::BlockBuffer,
+            partition::{Partition, read_partitions},
+        },
+    },
+    memory::address::{TUA, UA},
+};
+use log::info;
+
+/// The major number of every block device, Linux's `BLOCK_EXT_MAJOR`.
+pub const BLOCK_MAJOR: u64 = 259;
+
+/// The most an open device reads or writes in one go.
+const CHUNK_SZ: usize = 64 * 1024;
+
+const BLKSSZGET: usize = 0x1268;
+const BLKGETSIZE64: usize = 0x80081272;
+
+static BLOCK_DEVICES: SpinLock<BTreeMap<u64, Arc<dyn BlockDevice>>> =
+    SpinLock::new(BTreeMap::new());
+static NEXT_MINOR: AtomicU64 = AtomicU64::new(0);
+
+/// Adds the device `name`, with its directory below `parent` in sysfs,
+/// returning that directory.
+fn add_device(parent: &KObject, name: String, dev: Arc<dyn BlockDevice>) -> Result<Arc<KObject>> {
+    let desc = CharDevDescriptor {
+        major: BLOCK_MAJOR,
+        minor: NEXT_MINOR.fetch_add(1, Ordering::Relaxed),
+    };
+    // As on Linux, the size is in 512-byte sectors, whatever the block size.
+    let sectors = dev.num_blocks() * dev.block_size() as u64 / 512;
+
+    let kobj = parent.child(&name);
+    kobj.add_attr(
+        "dev",
+        Attribute::read_only(move || Ok(format!("{}:{}\n", desc.major, desc.minor))),
+    );
+    kobj.add_attr(
+        "size",
+        Attribute::read_only(move || Ok(format!("{sectors}\n"))),
+    );
+
+    devfs().mknod_block(name, desc, FilePermissions::from_bits_retain(0o660))?;
+    BLOCK_DEVICES.lock_save_irq().insert(desc.minor, dev);
+
+    Ok(kobj)
+}
+
+/// Registers `dev` as `/dev/<name>`, and each partition on it after it, as
+/// e.g. `/dev/ram0p1` or `/dev/vda1`, as on Linux. A partition's directory in
+/// sysfs is within its device's.
+pub async fn register_block_device(name: &str, dev: Arc<dyn BlockDevice>) -> Result<()> {
+    let kobj = add_device(
+        &kobject_root().child("block"),
+        name.to_string(),
+        dev.clone(),
+    )?;
+
+    let partitions = read_partitions(&*dev).await?;
+    let separator = if name.ends_with(|c: char| c.is_ascii_digit()) {
+        "p"
+    } else {
+        ""
+    };
+
+    for entry in partitions.iter() {
+        add_device(
+            &kobj,
+            format!("{name}{separator}{}", entry.number),
+            Arc::new(Partition::new(dev.clone(), entry)),
+        )?;
+    }
+
+    info!(
+        "Registered block device {name}, with {} partitions",
+        partitions.len()
+    );
+
+    Ok(())
+}
+
+/// Opens the block device `desc` names.
+pub fn open(desc: CharDevDescriptor, flags: OpenFlags) -> Result<Arc<OpenFile>> {
+    if desc.major != BLOCK_MAJOR {
+        return Err(FsError::NoDevice.into());
+    }
+
+    let dev = BLOCK_DEVICES
+        .lock_save_irq()
+        .get(&desc.minor)
+        .cloned()
+        .ok_or(FsError::NoDevice)?;
+
+    let block_size = dev.block_size();
+    let size = dev.num_blocks() * block_size as u64;
+
+    Ok(Arc::new(OpenFile::new(
+        Box::new(BlockFile {
+            dev: BlockBuffer::new(Box::new(dev)),
+            block_size,
+            size,
+        }),
+        flags,
+    )))
+}
+
+/// An open block device.
+struct BlockFile {
+    dev: BlockBuffer,
+    block_size: usize,
+    /// The size of the device, in bytes.
+    size: u64,
+}
+
+#[async_trait]
+impl FileOps for BlockFile {
+    async fn readat(&mut self, buf: UA, count: usize, offset: u64) -> Result<usize> {
+        let count = min(count as u64, self.size.saturating_sub(offset)) as usize;
+        let mut kbuf = vec![0; min(count, CHUNK_SZ)];
+        let mut done = 0;
+
+        while done < count {
+            let len = min(count - done, CHUNK_SZ);
+
+            self.dev
+                .read_at(offset + done as u64, &mut kbuf[..len])
+                .await?;
+            copy_to_user_slice(&kbuf[..len], buf.add_bytes(done)).await?;
+
+            done += len;
+        }
+
+        Ok(done)
+    }
+
+    async fn writeat(&mut self, buf: UA, count: usize, offset: u64) -> Result<usize> {
+        // As on Linux, a write that starts past the end of the device finds
+        // no space there.
+        if count > 0 && offset >= self.size {
+            return Err(FsError::NoSpace.into());
+        }
+
+        let count = min(count as u64, self.size - offset) as usize;
+        let mut kbuf = vec![0; min(count, CHUNK_SZ)];
+        let mut done = 0;
+
+        while done < count {
+            let len = min(count - done, CHUNK_SZ);
+
+            copy_from_user_slice(buf.add_bytes(done), &mut kbuf[..len]).await?;
+            self.dev
+                .write_at(offset + done as u64, &kbuf[..len])
+                .await?;
+
+            done += len;
+        }
+
+        Ok(done)
+    }
+
+    fn poll_read_ready(&self) -> Pin<Box<dyn Future<Output = Result<()>> + 'static + Send>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn poll_write_ready(&self) -> Pin<Box<dyn Future<Output = Result<()>> + 'static + Send>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    async fn seek(&mut self, ctx: &mut FileCtx, pos: SeekFrom) -> Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(x) => Some(x),
+            SeekFrom::End(x) => self.size.checked_add_signed(x),
+            SeekFrom::Current(x) => ctx.pos.checked_add_signed(x),
+        };
+
+        ctx.pos = pos.ok_or(KernelError::InvalidValue)?;
+
+        Ok(ctx.pos)
+    }
+
+    async fn ioctl(&mut self, _ctx: &mut FileCtx, request: usize, argp: usize) -> Result<usize> {
+        match request {
+            BLKSSZGET => {
+                copy_to_user(TUA::<i32>::from_value(argp), self.block_size as i32).await?;
+            }
+            BLKGETSIZE64 => {
+                copy_to_user(TUA::<u64>::from_value(argp), self.size).await?;
+            }
+            _ => return Err(KernelError::NotATty),
+        }
+
+        Ok(0)
+    }
+}
@@ -7,7 +7,7 @@ use crate::{
         fops::FileOps,
         open_file::{FileCtx, OpenFile},
     },
-    kernel::rand::fill_random_bytes,
+    kernel::rand::{fill_random_bytes, fill_random_bytes_insecure},
     kernel_driver,
     memory::uaccess::copy_to_user_slice,
 };
@@ -21,7 +21,11 @@ use libkernel::{
     memory::address::UA,
 };
 
-struct RandomFileOps;
+struct RandomFileOps {
+    /// Whether reads wait for the entropy pool to be seeded: true for
+    /// `/dev/random`, false for `/dev/urandom`.
+    blocking: bool,
+}
 
 #[async_trait]
 impl FileOps for RandomFileOps {
@@ -35,10 +39,14 @@ impl FileOps for RandomFileOps {
     }
 
     async fn readat(&mut self, buf: UA, count: usize, _offset: u64) -> Result<usize> {
-        // TODO: Add an implementation of `/dev/urandom` which doesn't block if
-        // the entropy pool hasn't yet been seeded.
         let mut kbuf = vec![0u8; count];
-        fill_random_bytes(&mut kbuf).await;
+
+        if self.blocking {
+            fill_random_bytes(&mut kbuf).await;
+        } else {
+            fill_random_bytes_insecure(&mut kbuf);
+        }
+
         copy_to_user_slice(&kbuf, buf).await?;
         Ok(count)
     }
@@ -48,41 +56,55 @@ impl FileOps for RandomFileOps {
     }
 }
 
-struct RandomDev;
+struct RandomDev {
+    blocking: bool,
+}
 
 impl OpenableDevice for RandomDev {
     fn open(&self, flags: OpenFlags) -> Result<Arc<OpenFile>> {
-        Ok(Arc::new(OpenFile::new(Box::new(RandomFileOps), flags)))
+        Ok(Arc::new(OpenFile::new(
+            Box::new(RandomFileOps {
+                blocking: self.blocking,
+            }),
+            flags,
+        )))
     }
 }
 
+const RANDOM_MINOR: u64 = 0;
+const URANDOM_MINOR: u64 = 1;
+
 struct RandomCharDev {
     random_dev: Arc<dyn OpenableDevice>,
+    urandom_dev: Arc<dyn OpenableDevice>,
 }
 
 impl RandomCharDev {
     fn new() -> Result<Self> {
-        devfs().mknod(
-            "random".to_string(),
-            CharDevDescriptor {
-                major: ReservedMajors::Random as _,
-                minor: 0,
-            },
-            FilePermissions::from_bits_retain(0o666),
-        )?;
+        for (name, minor) in [("random", RANDOM_MINOR), ("urandom", URANDOM_MINOR)] {
+            devfs().mknod(
+                name.to_string(),
+                CharDevDescriptor {
+                    major: ReservedMajors::Random as _,
+                    minor,
+                },
+                FilePermissions::from_bits_retain(0o666),
+            )?;
+        }
 
         Ok(Self {
-            random_dev: Arc::new(RandomDev),
+            random_dev: Arc::new(RandomDev { blocking: true }),
+            urandom_dev: Arc::new(RandomDev { blocking: false }),
         })
     }
 }
 
 impl CharDriver for RandomCharDev {
     fn get_device(&self, minor: u64) -> Option<Arc<dyn OpenableDevice>> {
-        if minor == 0 {
-            Some(self.random_dev.clone())
-        } else {
-            None
+        match minor {
+            RANDOM_MINOR => Some(self.random_dev.clone()),
+            URANDOM_MINOR => Some(self.urandom_dev.clone()),
+            _ => None,
         }
     }
 }
@@ -0,0 +1,621 @@
+//! A text console drawn on the system display.
+//!
+//! Text is rendered with the built-in bitmap [`font`](super::font) into a grid
+//! of character cells, scrolling up a line when output runs off the bottom of
+//! the screen. A subset of ANSI escape sequences is understood: cursor
+//! movement and positioning, erasing the display and lines, and the standard
+//! SGR colours.
+//!
+//! The console mirrors the kernel log, and is exposed to userspace as the tty
+//! `/dev/tty0`.
+
+use super::{
+    Display,
+    font::{GLYPH_HEIGHT, GLYPH_WIDTH, glyph},
+};
+use crate::{
+    console::{
+        Console, add_log_sink,
+        tty::{Tty, TtyInputHandler},
+    },
+    drivers::{
+        CharDriver, DriverManager, OpenableDevice, ReservedMajors, fs::dev::devfs,
+        init::PlatformBus,
+    },
+    fs::open_file::OpenFile,
+    kernel_driver,
+    sync::{OnceLock, SpinLock},
+};
+use alloc::{
+    boxed::Box,
+    string::ToString,
+    sync::{Arc, Weak},
+};
+use core::fmt::{self, Write};
+use libkernel::{
+    driver::CharDevDescriptor,
+    error::{FsError, KernelError, Result},
+    fs::{OpenFlags, attr::FilePermissions},
+};
+
+/// Each row of the font is drawn twice, for 8x16 cells.
+const SCALE_Y: usize = 2;
+const CELL_WIDTH: usize = GLYPH_WIDTH;
+const CELL_HEIGHT: usize = GLYPH_HEIGHT * SCALE_Y;
+const BYTES_PER_PIXEL: usize = 4;
+const TAB_WIDTH: usize = 8;
+const MAX_PARAMS: usize = 8;
+
+/// The eight standard ANSI colours followed by their bright variants, as RGB.
+const PALETTE: [[u8; 3]; 16] = [
+    [0x00, 0x00, 0x00],
+    [0xaa, 0x00, 0x00],
+    [0x00, 0xaa, 0x00],
+    [0xaa, 0x55, 0x00],
+    [0x00, 0x00, 0xaa],
+    [0xaa, 0x00, 0xaa],
+    [0x00, 0xaa, 0xaa],
+    [0xaa, 0xaa, 0xaa],
+    [0x55, 0x55, 0x55],
+    [0xff, 0x55, 0x55],
+    [0x55, 0xff, 0x55],
+    [0xff, 0xff, 0x55],
+    [0x55, 0x55, 0xff],
+    [0xff, 0x55, 0xff],
+    [0x55, 0xff, 0xff],
+    [0xff, 0xff, 0xff],
+];
+
+const DEFAULT_FG: usize = 7;
+const DEFAULT_BG: usize = 0;
+
+enum ParseState {
+    Ground,
+    /// Seen `ESC`.
+    Escape,
+    /// Seen `ESC [`, collecting parameters.
+    Csi,
+}
+
+/// The console's cursor, colours and escape sequence parser.
+struct TextState {
+    /// The framebuffer's width, in pixels.
+    width: usize,
+    cols: usize,
+    rows: usize,
+    x: usize,
+    y: usize,
+    fg: usize,
+    bg: usize,
+    bold: bool,
+    parse: ParseState,
+    params: [u16; MAX_PARAMS],
+    nr_params: usize,
+}
+
+/// The text state, paired with the framebuffer it draws into.
+struct Painter<'a> {
+    state: &'a mut TextState,
+    fb: &'a mut [u8],
+}
+
+impl TextState {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            cols: width / CELL_WIDTH,
+            rows: height / CELL_HEIGHT,
+            x: 0,
+            y: 0,
+            fg: DEFAULT_FG,
+            bg: DEFAULT_BG,
+            bold: false,
+            parse: ParseState::Ground,
+            params: [0; MAX_PARAMS],
+            nr_params: 0,
+        }
+    }
+
+    fn fg_colour(&self) -> [u8; 3] {
+        if self.bold && self.fg < 8 {
+            PALETTE[self.fg + 8]
+        } else {
+            PALETTE[self.fg]
+        }
+    }
+
+    /// Returns parameter `n` of the current escape sequence, or `default` if
+    /// it was omitted or zero.
+    fn param(&self, n: usize, default: usize) -> usize {
+        match self.params.get(n).copied() {
+            Some(p) if n < self.nr_params && p != 0 => p as usize,
+            _ => default,
+        }
+    }
+}
+
+impl Painter<'_> {
+    fn fill_pixel(&mut self, x: usize, y: usize, rgb: [u8; 3]) {
+        let offset = (y * self.state.width + x) * BYTES_PER_PIXEL;
+
+        if let Some(pixel) = self.fb.get_mut(offset..offset + BYTES_PER_PIXEL) {
+            pixel.copy_from_slice(&[rgb[0], rgb[1], rgb[2], 0xff]);
+        }
+    }
+
+    fn draw_cell(&mut self, col: usize, row: usize, c: char) {
+        let glyph = glyph(c);
+        let fg = self.state.fg_colour();
+        let bg = PALETTE[self.state.bg];
+
+        for dy in 0..CELL_HEIGHT {
+            let bits = glyph[dy / SCALE_Y];
+
+            for dx in 0..CELL_WIDTH {
+                let rgb = if bits & (1 << dx) != 0 { fg } else { bg };
+
+                self.fill_pixel(col * CELL_WIDTH + dx, row * CELL_HEIGHT + dy, rgb);
+            }
+        }
+    }
+
+    /// Blanks the cells from `start` up to, but not including, `end` on
+    /// `row`.
+    fn clear_cells(&mut self, row: usize, start: usize, end: usize) {
+        for col in start..end.min(self.state.cols) {
+            self.draw_cell(col, row, ' ');
+        }
+    }
+
+    fn clear_rows(&mut self, start: usize, end: usize) {
+        for row in start..end.min(self.state.rows) {
+            self.clear_cells(row, 0, self.state.cols);
+        }
+    }
+
+    fn scroll_up(&mut self) {
+        let line_bytes = CELL_HEIGHT * self.state.width * BYTES_PER_PIXEL;
+        let screen_bytes = (self.state.rows * line_bytes).min(self.fb.len());
+
+        self.fb.copy_within(line_bytes..screen_bytes, 0);
+        self.clear_rows(self.state.rows - 1, self.state.rows);
+    }
+
+    fn newline(&mut self) {
+        if self.state.y + 1 < self.state.rows {
+            self.state.y += 1;
+        } else {
+            self.scroll_up();
+        }
+    }
+
+    fn print(&mut self, c: char) {
+        if self.state.x >= self.state.cols {
+            self.state.x = 0;
+            self.newline();
+        }
+
+        self.draw_cell(self.state.x, self.state.y, c);
+        self.state.x += 1;
+    }
+
+    fn put_char(&mut self, c: char) {
+        if self.state.cols == 0 || self.state.rows == 0 {
+            return;
+        }
+
+        match self.state.parse {
+            ParseState::Ground => self.put_ground(c),
+            ParseState::Escape => {
+                self.state.parse = match c {
+                    '[' => {
+                        self.state.params = [0; MAX_PARAMS];
+                        self.state.nr_params = 0;
+                        ParseState::Csi
+                    }
+                    'c' => {
+                        self.reset();
+                        ParseState::Ground
+                    }
+                    _ => ParseState::Ground,
+                }
+            }
+            ParseState::Csi => self.put_csi(c),
+        }
+    }
+
+    fn put_ground(&mut self, c: char) {
+        match c {
+            '\n' => self.newline(),
+            '\r' => self.state.x = 0,
+            '\x08' => self.state.x = self.state.x.saturating_sub(1),
+            '\t' => {
+                let next = (self.state.x / TAB_WIDTH + 1) * TAB_WIDTH;
+                self.state.x = next.min(self.state.cols - 1);
+            }
+            '\x1b' => self.state.parse = ParseState::Escape,
+            // Ignore other control characters, including BEL.
+            c if c.is_control() => {}
+            c => self.print(c),
+        }
+    }
+
+    fn put_csi(&mut self, c: char) {
+        let state = &mut *self.state;
+
+        match c {
+            '0'..='9' => {
+                let n = state.nr_params.max(1) - 1;
+                let digit = c as u16 - b'0' as u16;
+
+                state.nr_params = state.nr_params.max(1);
+                state.params[n] = state.params[n].saturating_mul(10).saturating_add(digit);
+            }
+            ';' => {
+                state.nr_params = (state.nr_params.max(1) + 1).min(MAX_PARAMS);
+            }
+            // Private mode markers, e.g. `ESC [ ? 25 l`; we have nothing to
+            // configure, so accept and ignore them.
+            '?' | '>' | '=' => {}
+            '\x40'..='\x7e' => {
+                state.parse = ParseState::Ground;
+                self.dispatch_csi(c);
+            }
+            // Anything else aborts the sequence.
+            _ => state.parse = ParseState::Ground,
+        }
+    }
+
+    fn dispatch_csi(&mut self, cmd: char) {
+        let (cols, rows) = (self.state.cols, self.state.rows);
+        let (x, y) = (self.state.x, self.state.y);
+
+        match cmd {
+            'A' => self.state.y = y.saturating_sub(self.state.param(0, 1)),
+            'B' => self.state.y = (y + self.state.param(0, 1)).min(rows - 1),
+            'C' => self.state.x = (x + self.state.param(0, 1)).min(cols - 1),
+            'D' => self.state.x = x.saturating_sub(self.state.param(0, 1)),
+            'G' => self.state.x = (self.state.param(0, 1) - 1).min(cols - 1),
+            'H' | 'f' => {
+                self.state.y = (self.state.param(0, 1) - 1).min(rows - 1);
+                self.state.x = (self.state.param(1, 1) - 1).min(cols - 1);
+            }
+            'J' => match self.state.param(0, 0) {
+                0 => {
+                    self.clear_cells(y, x, cols);
+                    self.clear_rows(y + 1, rows);
+                }
+                1 => {
+                    self.clear_rows(0, y);
+                    self.clear_cells(y, 0, x + 1);
+                }
+                _ => self.clear_rows(0, rows),
+            },
+            'K' => match self.state.param(0, 0) {
+                0 => self.clear_cells(y, x, cols),
+                1 => self.clear_cells(y, 0, x + 1),
+                _ => self.clear_cells(y, 0, cols),
+            },
+            'm' => self.select_graphic_rendition(),
+            // Unsupported sequences are ignored.
+            _ => {}
+        }
+    }
+
+    fn select_graphic_rendition(&mut self) {
+        let state = &mut *self.state;
+
+        // `ESC [ m` is equivalent to `ESC [ 0 m`.
+        let nr_params = state.nr_params.max(1);
+
+        for &p in &state.params[..nr_params] {
+            match p {
+                0 => {
+                    state.fg = DEFAULT_FG;
+                    state.bg = DEFAULT_BG;
+                    state.bold = false;
+                }
+                1 => state.bold = true,
+                22 => state.bold = false,
+                30..=37 => state.fg = (p - 30) as usize,
+                39 => state.fg = DEFAULT_FG,
+                40..=47 => state.bg = (p - 40) as usize,
+                49 => state.bg = DEFAULT_BG,
+                90..=97 => state.fg = (p - 90) as usize + 8,
+                100..=107 => state.bg = (p - 100) as usize + 8,
+                _ => {}
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        let state = &mut *self.state;
+
+        state.fg = DEFAULT_FG;
+        state.bg = DEFAULT_BG;
+        state.bold = false;
+        state.x = 0;
+        state.y = 0;
+
+        self.clear_rows(0, self.state.rows);
+    }
+}
+
+impl Write for Painter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        s.chars().for_each(|c| self.put_char(c));
+        Ok(())
+    }
+}
+
+/// A console which renders text onto a [`Display`].
+pub struct FbConsole {
+    display: Arc<dyn Display>,
+    state: SpinLock<TextState>,
+    tty_handler: SpinLock<Option<Weak<dyn TtyInputHandler>>>,
+}
+
+impl FbConsole {
+    /// Creates a console covering the whole of `display`, and clears it.
+    pub fn new(display: Arc<dyn Display>) -> Self {
+        let (width, height) = display.resolution();
+
+        let this = Self {
+            display,
+            state: SpinLock::new(TextState::new(width, height)),
+            tty_handler: SpinLock::new(None),
+        };
+
+        this.paint(|painter| painter.reset());
+
+        this
+    }
+
+    /// Runs `f` with the framebuffer locked, then flushes it to the display.
+    fn paint<R>(&self, f: impl FnOnce(&mut Painter<'_>) -> R) -> R {
+        let result = {
+            let mut state = self.state.lock_save_irq();
+            let mut fb = self.display.lock_framebuffer();
+
+            f(&mut Painter {
+                state: &mut state,
+                fb: &mut fb,
+            })
+        };
+
+        // Don't log a failure: we may be writing the log.
+        let _ = self.display.flush();
+
+        result
+    }
+
+    /// Forwards a byte of input, e.g. from a keyboard, to the attached tty.
+    pub fn push_input(&self, byte: u8) {
+        let handler = self
+            .tty_handler
+            .lock_save_irq()
+            .as_ref()
+            .and_then(|h| h.upgrade());
+
+        if let Some(handler) = handler {
+            handler.push_byte(byte);
+        }
+    }
+}
+
+impl Console for FbConsole {
+    fn write_char(&self, c: char) {
+        self.paint(|painter| painter.put_char(c));
+    }
+
+    fn write_fmt(&self, args: fmt::Arguments) -> fmt::Result {
+        self.paint(|painter| painter.write_fmt(args))
+    }
+
+    fn write_buf(&self, buf: &[u8]) {
+        self.paint(|painter| {
+            buf.iter()
+                .for_each(|&b| painter.put_char(if b.is_ascii() { b as char } else { '?' }));
+        });
+    }
+
+    fn register_input_handler(&self, handler: Weak<dyn TtyInputHandler>) {
+        *self.tty_handler.lock_save_irq() = Some(handler);
+    }
+}
+
+static FBCON: OnceLock<Arc<FbConsole>> = OnceLock::new();
+
+struct FbConInstance;
+
+impl OpenableDevice for FbConInstance {
+    fn open(&self, flags: OpenFlags) -> Result<Arc<OpenFile>> {
+        let console = FBCON.get().ok_or(FsError::NoDevice)?;
+        let tty = Tty::new(console.clone())?;
+
+        Ok(Arc::new(OpenFile::new(Box::new(tty), flags)))
+    }
+}
+
+struct FbConCharDev {
+    instance: Arc<dyn OpenableDevice>,
+}
+
+impl CharDriver for FbConCharDev {
+    fn get_device(&self, minor: u64) -> Option<Arc<dyn OpenableDevice>> {
+        (minor == 0 && FBCON.get().is_some()).then(|| self.instance.clone())
+    }
+}
+
+/// Feeds keyboard input to the framebuffer console's tty, if there is one.
+pub fn fbcon_push_input(bytes: &[u8]) {
+    if let Some(console) = FBCON.get() {
+        bytes.iter().for_each(|&b| console.push_input(b));
+    }
+}
+
+/// Starts a framebuffer console on `display`, mirroring the kernel log to it
+/// and creating `/dev/tty0`.
+pub fn fbcon_attach(display: Arc<dyn Display>) -> Result<()> {
+    let console = Arc::new(FbConsole::new(display));
+
+    FBCON.set(console.clone()).map_err(|_| KernelError::InUse)?;
+
+    devfs().mknod(
+        "tty0".to_string(),
+        CharDevDescriptor {
+            major: ReservedMajors::Vt as _,
+            minor: 0,
+        },
+        FilePermissions::from_bits_retain(0o620),
+    )?;
+
+    add_log_sink(console);
+
+    Ok(())
+}
+
+pub fn fbcon_init(_bus: &mut PlatformBus, dm: &mut DriverManager) -> Result<()> {
+    dm.register_char_driver(
+        ReservedMajors::Vt as _,
+        Arc::new(FbConCharDev {
+            instance: Arc::new(FbConInstance),
+        }),
+    )
+}
+
+kernel_driver!(fbcon_init);
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        BYTES_PER_PIXEL, CELL_HEIGHT, CELL_WIDTH, DEFAULT_BG, DEFAULT_FG, Painter, ParseState,
+        TextState,
+    };
+    use alloc::{vec, vec::Vec};
+    use core::fmt::Write;
+    use moss_macros::ktest;
+
+    const COLS: usize = 10;
+    const ROWS: usize = 4;
+
+    struct Screen {
+        state: TextState,
+        fb: Vec<u8>,
+    }
+
+    impl Screen {
+        fn new() -> Self {
+            let (width, height) = (COLS * CELL_WIDTH, ROWS * CELL_HEIGHT);
+
+            Self {
+                state: TextState::new(width, height),
+                fb: vec![0; width * height * BYTES_PER_PIXEL],
+            }
+        }
+
+        fn write(&mut self, s: &str) {
+            Painter {
+                state: &mut self.state,
+                fb: &mut self.fb,
+            }
+            .write_str(s)
+            .unwrap();
+        }
+
+        fn cursor(&self) -> (usize, usize) {
+            (self.state.x, self.state.y)
+        }
+    }
+
+    #[ktest]
+    fn csi_parameters_position_the_cursor() {
+        let mut screen = Screen::new();
+
+        screen.write("\x1b[3;7H");
+        assert_eq!(screen.cursor(), (6, 2));
+
+        // Omitted and zero parameters take their defaults.
+        screen.write("\x1b[;4H");
+        assert_eq!(screen.cursor(), (3, 0));
+        screen.write("\x1b[0;0H");
+        assert_eq!(screen.cursor(), (0, 0));
+
+        screen.write("\x1b[2C\x1b[B");
+        assert_eq!(screen.cursor(), (2, 1));
+    }
+
+    #[ktest]
+    fn sequences_survive_being_split_across_writes() {
+        let mut screen = Screen::new();
+
+        screen.write("\x1b");
+        screen.write("[");
+        screen.write("3");
+        assert_eq!(screen.cursor(), (0, 0));
+        screen.write("1m");
+
+        assert_eq!(screen.state.fg, 1);
+        assert!(matches!(screen.state.parse, ParseState::Ground));
+
+        screen.write("\x1b[2;");
+        screen.write("5H");
+        assert_eq!(screen.cursor(), (4, 1));
+    }
+
+    #[ktest]
+    fn invalid_sequences_are_abandoned() {
+        let mut screen = Screen::new();
+
+        // A control character in the middle of a sequence aborts it, and the
+        // final byte is then printed as ordinary text.
+        screen.write("\x1b[31\x07m");
+        assert!(matches!(screen.state.parse, ParseState::Ground));
+        assert_eq!(screen.state.fg, DEFAULT_FG);
+        assert_eq!(screen.cursor(), (1, 0));
+
+        // An escape that doesn't start a CSI sequence is dropped.
+        screen.write("\x1bZ");
+        assert_eq!(screen.cursor(), (1, 0));
+    }
+
+    #[ktest]
+    fn overlong_parameters_are_clamped() {
+        let mut screen = Screen::new();
+
+        screen.write("\x1b[99999999999C");
+        assert_eq!(screen.cursor(), (COLS - 1, 0));
+        screen.write("\x1b[99999999999;99999999999H");
+        assert_eq!(screen.cursor(), (COLS - 1, ROWS - 1));
+
+        // Parameters past the limit run into the last one rather than being
+        // written out of bounds.
+        screen.write("\x1b[1;2;3;4;5;6;7;8;9;31m");
+        assert!(matches!(screen.state.parse, ParseState::Ground));
+        assert_eq!(screen.state.nr_params, super::MAX_PARAMS);
+        assert_eq!(screen.state.fg, DEFAULT_FG);
+    }
+
+    #[ktest]
+    fn sgr_reset_restores_the_default_rendition() {
+        let mut screen = Screen::new();
+
+        screen.write("\x1b[1;31;44m");
+        assert!(screen.state.bold);
+        assert_eq!(screen.state.fg, 1);
+        assert_eq!(screen.state.bg, 4);
+
+        screen.write("\x1b[0m");
+        assert!(!screen.state.bold);
+        assert_eq!(screen.state.fg, DEFAULT_FG);
+        assert_eq!(screen.state.bg, DEFAULT_BG);
+
+        // A bare `ESC [ m` is a reset too, as is a trailing `0`.
+        screen.write("\x1b[92;101m\x1b[m");
+        assert_eq!(screen.state.fg, DEFAULT_FG);
+        assert_eq!(screen.state.bg, DEFAULT_BG);
+        screen.write("\x1b[1;33;0m");
+        assert!(!screen.state.bold);
+        assert_eq!(screen.state.fg, DEFAULT_FG);
+    }
+}
@@ -16,6 +16,8 @@ use libkernel::fs::OpenFlags;
 use libkernel::fs::attr::FilePermissions;
 use libkernel::memory::address::UA;
 
+pub mod fbcon;
+mod font;
 pub mod virtio;
 
 /// Kernel display abstraction: a framebuffer (RGBA8888) and the
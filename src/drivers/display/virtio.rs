@@ -1,10 +1,10 @@
-use crate::drivers::virtio_hal::VirtioHal;
+use crate::drivers::virtio_hal::{VirtioHal, attach_fdt_node};
 use crate::sync::SpinLock;
 use crate::{
     arch::ArchImpl,
     drivers::{
         Driver, DriverManager,
-        display::{Display, FramebufferGuard, set_system_display},
+        display::{Display, FramebufferGuard, fbcon::fbcon_attach, set_system_display},
         init::PlatformBus,
         probe::{DeviceDescriptor, DeviceMatchType},
     },
@@ -138,6 +138,8 @@ fn virtio_gpu_probe(_dm: &mut DriverManager, d: DeviceDescriptor) -> Result<Arc<
                 return Err(KernelError::Probe(ProbeError::NoMatch));
             }
 
+            attach_fdt_node(&fdt_node)?;
+
             info!("virtio-gpu found at {mapped:?} (node {})", fdt_node.name);
 
             let disp = Arc::new(VirtioGpuDisplay::new(Some(fdt_node.name), transport)?);
@@ -148,6 +150,10 @@ fn virtio_gpu_probe(_dm: &mut DriverManager, d: DeviceDescriptor) -> Result<Arc<
             } else {
                 let (w, h) = disp.resolution();
                 info!("System display set to virtio-gpu ({w}x{h})");
+
+                if let Err(e) = fbcon_attach(disp.clone()) {
+                    warn!("Failed to start framebuffer console: {e:?}");
+                }
             }
 
             Ok(disp)
@@ -4,7 +4,10 @@ use core::ptr::NonNull;
 use fdt_parser::Fdt;
 use libkernel::{
     error::{KernelError, ProbeError},
-    memory::address::TVA,
+    memory::{
+        address::{PA, TVA},
+        region::PhysMemoryRegion,
+    },
 };
 use log::{error, warn};
 
@@ -22,6 +25,36 @@ pub fn get_fdt() -> Fdt<'static> {
     unsafe { Fdt::from_ptr(NonNull::new_unchecked(FDT.as_ptr_mut())).unwrap() }
 }
 
+/// Reads a property holding an address, in one cell or two. Bootloaders
+/// write the bounds of the initrd either way.
+fn read_addr(raw: &[u8]) -> Option<u64> {
+    match raw.len() {
+        4 => Some(u32::from_be_bytes(raw.try_into().ok()?) as u64),
+        8 => Some(u64::from_be_bytes(raw.try_into().ok()?)),
+        _ => None,
+    }
+}
+
+/// Returns the region of physical memory the bootloader loaded the initrd
+/// into, as given in `/chosen`.
+pub fn initrd_region(fdt: &Fdt<'_>) -> Option<PhysMemoryRegion> {
+    let chosen = fdt.find_nodes("/chosen").next()?;
+
+    let addr = |name| {
+        chosen
+            .find_property(name)
+            .and_then(|prop| read_addr(prop.raw_value()))
+    };
+
+    let start = addr("linux,initrd-start")?;
+    let end = addr("linux,initrd-end")?;
+
+    Some(PhysMemoryRegion::from_start_end_address(
+        PA::from_value(start as _),
+        PA::from_value(end as _),
+    ))
+}
+
 pub fn is_intc_root(node: &fdt_parser::Node) -> bool {
     assert!(node.find_property("interrupt-controller").is_some());
 
@@ -52,42 +52,125 @@ impl DevFs {
         })
     }
 
+    fn alloc_inode_id(&self) -> InodeId {
+        InodeId::from_fsid_and_inodeid(DEVFS_ID, self.next_inode_id.fetch_add(1, Ordering::SeqCst))
+    }
+
+    /// Returns the directory at `path`, relative to the root, creating any
+    /// missing components.
+    fn dir_at(&self, path: &str) -> Result<Arc<DevFsINode>> {
+        let mut dir = self.root.clone();
+
+        for component in path.split('/').filter(|c| !c.is_empty()) {
+            let InodeKind::Directory(ref children) = dir.kind else {
+                return Err(FsError::NotADirectory.into());
+            };
+
+            let next = children
+                .lock_save_irq()
+                .entry(component.to_string())
+                .or_insert_with(|| {
+                    let id = self.alloc_inode_id();
+
+                    Arc::new(DevFsINode {
+                        id,
+                        attr: SpinLock::new(FileAttr {
+                            id,
+                            file_type: FileType::Directory,
+                            permissions: FilePermissions::from_bits_retain(0o755),
+                            ..FileAttr::default()
+                        }),
+                        kind: InodeKind::Directory(SpinLock::new(BTreeMap::new())),
+                    })
+                })
+                .clone();
+
+            dir = next;
+        }
+
+        Ok(dir)
+    }
+
+    /// Creates a character device node. `name` may contain slashes, e.g.
+    /// `input/event0`, in which case any missing parent directories are
+    /// created.
     pub fn mknod(
         &self,
         name: String,
         device_id: CharDevDescriptor,
         permissions: FilePermissions,
     ) -> Result<()> {
-        let InodeKind::Directory(ref children) = self.root.kind else {
-            // This should be impossible as the root is always a directory.
-            return Err(FsError::InvalidFs.into());
+        self.add_node(&name, FileType::CharDevice(device_id), permissions)
+    }
+
+    /// Creates a block device node, as [`Self::mknod`] does a character one.
+    pub fn mknod_block(
+        &self,
+        name: String,
+        device_id: CharDevDescriptor,
+        permissions: FilePermissions,
+    ) -> Result<()> {
+        self.add_node(&name, FileType::BlockDevice(device_id), permissions)
+    }
+
+    fn add_node(
+        &self,
+        name: &str,
+        file_type: FileType,
+        permissions: FilePermissions,
+    ) -> Result<()> {
+        let (parent, name) = name.rsplit_once('/').unwrap_or(("", name));
+        let parent = self.dir_at(parent)?;
+
+        let InodeKind::Directory(ref children) = parent.kind else {
+            return Err(FsError::NotADirectory.into());
         };
 
         let mut children = children.lock_save_irq();
-        if children.contains_key(&name) {
+        if children.contains_key(name) {
             return Err(KernelError::InUse);
         }
 
-        let id = InodeId::from_fsid_and_inodeid(
-            DEVFS_ID,
-            self.next_inode_id.fetch_add(1, Ordering::SeqCst),
-        );
+        let id = self.alloc_inode_id();
 
         let new_inode = Arc::new(DevFsINode {
             id,
             attr: SpinLock::new(FileAttr {
                 id,
-                file_type: FileType::CharDevice(device_id),
+                file_type,
                 permissions,
                 ..FileAttr::default()
             }),
-            // This is the crucial part: we store the device handle.
-            kind: InodeKind::CharDevice { device_id },
+            kind: InodeKind::Device,
         });
 
         children.insert(name.to_string(), new_inode);
         Ok(())
     }
+
+    /// Removes every device node for which `matches` holds of its type, e.g.
+    /// all of a device's nodes when it goes away, along with any directories
+    /// which that leaves empty.
+    pub fn remove_nodes(&self, matches: impl Fn(FileType) -> bool) {
+        Self::remove_nodes_in(&self.root, &matches);
+    }
+
+    /// Returns whether `dir` was emptied.
+    fn remove_nodes_in(dir: &DevFsINode, matches: &impl Fn(FileType) -> bool) -> bool {
+        let InodeKind::Directory(ref children) = dir.kind else {
+            return false;
+        };
+
+        let mut children = children.lock_save_irq();
+        let was_empty = children.is_empty();
+
+        children.retain(|_, child| match child.kind {
+            InodeKind::Directory(_) => !Self::remove_nodes_in(child, matches),
+            InodeKind::Device => !matches(child.attr.lock_save_irq().file_type),
+        });
+
+        !was_empty && children.is_empty()
+    }
 }
 
 #[async_trait]
@@ -110,8 +193,9 @@ impl Filesystem for DevFs {
 enum InodeKind {
     /// A directory, which contains a map of names to child inodes.
     Directory(SpinLock<BTreeMap<String, Arc<DevFsINode>>>),
-    /// A character device, which stores its major/minor handle (`dev_t`).
-    CharDevice { device_id: CharDevDescriptor },
+    /// A character or block device, whose type and major/minor handle
+    /// (`dev_t`) are in its attributes.
+    Device,
 }
 
 struct DevDirStreamer {
@@ -159,16 +243,12 @@ impl Inode for DevFsINode {
                     .map(|inode| inode.clone() as Arc<dyn Inode>)
                     .ok_or_else(|| FsError::NotFound.into())
             }
-            InodeKind::CharDevice { .. } => Err(FsError::NotADirectory.into()),
+            InodeKind::Device => Err(FsError::NotADirectory.into()),
         }
     }
 
     async fn getattr(&self) -> Result<FileAttr> {
-        let mut attr = self.attr.lock_save_irq().clone();
-        if let InodeKind::CharDevice { device_id } = self.kind {
-            attr.file_type = FileType::CharDevice(device_id);
-        }
-        Ok(attr)
+        Ok(self.attr.lock_save_irq().clone())
     }
 
     async fn readdir(&self, start_offset: u64) -> Result<Box<dyn DirStream>> {
@@ -180,7 +260,7 @@ impl Inode for DevFsINode {
                     idx: start_offset as usize,
                 }))
             }
-            InodeKind::CharDevice { .. } => Err(FsError::NotADirectory.into()),
+            InodeKind::Device => Err(FsError::NotADirectory.into()),
         }
     }
 
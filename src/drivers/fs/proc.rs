@@ -1,9 +1,16 @@
 #![allow(clippy::module_name_repetitions)]
 
+mod buddyinfo;
 mod cmdline;
+mod cpuidle;
+#[cfg(feature = "kmemleak")]
+mod kmemleak;
+mod kmsg;
 mod meminfo;
+mod net;
 mod root;
 mod stat;
+mod sys;
 mod task;
 
 use crate::drivers::{Driver, FilesystemDriver};
@@ -0,0 +1,51 @@
+use crate::memory::PAGE_ALLOC;
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use async_trait::async_trait;
+use libkernel::fs::attr::FileAttr;
+use libkernel::fs::{InodeId, SimpleFile};
+
+pub struct ProcBuddyinfoInode {
+    id: InodeId,
+    attr: FileAttr,
+}
+
+impl ProcBuddyinfoInode {
+    pub fn new(inode_id: InodeId) -> Self {
+        Self {
+            id: inode_id,
+            attr: FileAttr {
+                file_type: libkernel::fs::FileType::File,
+                ..FileAttr::default()
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl SimpleFile for ProcBuddyinfoInode {
+    fn id(&self) -> InodeId {
+        self.id
+    }
+
+    async fn getattr(&self) -> libkernel::error::Result<FileAttr> {
+        Ok(self.attr.clone())
+    }
+
+    async fn read(&self) -> libkernel::error::Result<Vec<u8>> {
+        let page_alloc = PAGE_ALLOC.get().expect("PAGE_ALLOC must be initialised");
+        let stats = page_alloc.stats();
+
+        // Mirror Linux's format: one column of free block counts per order.
+        // We have a single node and zone.
+        let mut buddyinfo_content = String::from("Node 0, zone   Normal ");
+        for count in stats.free_blocks {
+            buddyinfo_content.push_str(&format!("{count:>6} "));
+        }
+        buddyinfo_content.push('\n');
+
+        Ok(buddyinfo_content.into_bytes())
+    }
+}
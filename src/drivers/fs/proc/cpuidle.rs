@@ -0,0 +1,59 @@
+use crate::kernel::cpuidle::{IDLE_STATS, idle_states};
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use async_trait::async_trait;
+use core::sync::atomic::Ordering;
+use libkernel::fs::attr::FileAttr;
+use libkernel::fs::{InodeId, SimpleFile};
+
+pub struct ProcCpuidleInode {
+    id: InodeId,
+    attr: FileAttr,
+}
+
+impl ProcCpuidleInode {
+    pub fn new(inode_id: InodeId) -> Self {
+        Self {
+            id: inode_id,
+            attr: FileAttr {
+                file_type: libkernel::fs::FileType::File,
+                ..FileAttr::default()
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl SimpleFile for ProcCpuidleInode {
+    fn id(&self) -> InodeId {
+        self.id
+    }
+
+    async fn getattr(&self) -> libkernel::error::Result<FileAttr> {
+        Ok(self.attr.clone())
+    }
+
+    async fn read(&self) -> libkernel::error::Result<Vec<u8>> {
+        // One line per CPU and idle state, with the same fields as Linux's
+        // /sys/devices/system/cpu/cpuN/cpuidle/stateM. Latencies and times
+        // are in microseconds.
+        let mut content = String::from("cpu state name latency residency usage time\n");
+
+        for (cpu, stats) in IDLE_STATS.iter().enumerate() {
+            for (idx, state) in idle_states().iter().enumerate() {
+                content.push_str(&format!(
+                    "{cpu} {idx} {} {} {} {} {}\n",
+                    state.name,
+                    state.exit_latency.as_micros(),
+                    state.target_residency.as_micros(),
+                    stats.usage[idx].load(Ordering::Relaxed),
+                    stats.time_us[idx].load(Ordering::Relaxed),
+                ));
+            }
+        }
+
+        Ok(content.into_bytes())
+    }
+}
@@ -0,0 +1,74 @@
+use crate::memory::kmemleak;
+use alloc::boxed::Box;
+use async_trait::async_trait;
+use core::any::Any;
+use libkernel::error::{KernelError, Result};
+use libkernel::fs::attr::{FileAttr, FilePermissions};
+use libkernel::fs::{FileType, Inode, InodeId};
+
+pub struct ProcKmemleakInode {
+    id: InodeId,
+    attr: FileAttr,
+}
+
+impl ProcKmemleakInode {
+    pub fn new(id: InodeId) -> Self {
+        Self {
+            id,
+            attr: FileAttr {
+                file_type: FileType::File,
+                permissions: FilePermissions::from_bits_retain(0o600),
+                ..FileAttr::default()
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl Inode for ProcKmemleakInode {
+    fn id(&self) -> InodeId {
+        self.id
+    }
+
+    async fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        // Only rescan at the start of a read, so that the report doesn't
+        // change under a reader working through it.
+        if offset == 0 {
+            kmemleak::scan_if_stale();
+        }
+
+        let report = kmemleak::report();
+        let data = report.as_bytes();
+        let start = offset as usize;
+        if start >= data.len() {
+            return Ok(0);
+        }
+
+        let end = usize::min(start + buf.len(), data.len());
+        let slice = &data[start..end];
+        buf[..slice.len()].copy_from_slice(slice);
+        Ok(slice.len())
+    }
+
+    async fn write_at(&self, _offset: u64, buf: &[u8]) -> Result<usize> {
+        let cmd = str::from_utf8(buf)
+            .map(str::trim)
+            .map_err(|_| KernelError::InvalidValue)?;
+
+        match cmd {
+            "scan" => kmemleak::scan(),
+            "clear" => kmemleak::KMEMLEAK.clear(),
+            _ => return Err(KernelError::InvalidValue),
+        }
+
+        Ok(buf.len())
+    }
+
+    async fn getattr(&self) -> Result<FileAttr> {
+        Ok(self.attr.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
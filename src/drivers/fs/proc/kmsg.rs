@@ -0,0 +1,46 @@
+use crate::console::kmsg;
+use alloc::boxed::Box;
+use async_trait::async_trait;
+use core::any::Any;
+use libkernel::error::Result;
+use libkernel::fs::attr::{FileAttr, FilePermissions};
+use libkernel::fs::{FileType, Inode, InodeId};
+
+pub struct ProcKmsgInode {
+    id: InodeId,
+    attr: FileAttr,
+}
+
+impl ProcKmsgInode {
+    pub fn new(id: InodeId) -> Self {
+        Self {
+            id,
+            attr: FileAttr {
+                file_type: FileType::File,
+                permissions: FilePermissions::from_bits_retain(0o400),
+                ..FileAttr::default()
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl Inode for ProcKmsgInode {
+    fn id(&self) -> InodeId {
+        self.id
+    }
+
+    async fn read_at(&self, _offset: u64, buf: &mut [u8]) -> Result<usize> {
+        // A stream of the log: each read picks up where the last, by anyone,
+        // left off.
+        kmsg::read(buf).await
+    }
+
+    async fn getattr(&self) -> Result<FileAttr> {
+        Ok(self.attr.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
@@ -1,12 +1,15 @@
-use crate::memory::PAGE_ALLOC;
+use crate::arch::{Arch, ArchImpl};
+use crate::memory::{PAGE_ALLOC, SLAB_ALLOC, vmalloc::vmalloc_used};
 use alloc::boxed::Box;
 use alloc::format;
 use alloc::string::String;
 use alloc::vec::Vec;
 use async_trait::async_trait;
+use core::fmt::Write;
 use libkernel::fs::attr::FileAttr;
 use libkernel::fs::{InodeId, SimpleFile};
 use libkernel::memory::PAGE_SIZE;
+use libkernel::memory::proc_vm::overcommit::VM_COMMIT;
 
 pub struct ProcMeminfoInode {
     id: InodeId,
@@ -41,12 +44,38 @@ impl SimpleFile for ProcMeminfoInode {
 
         let total_pages = page_alloc.total_pages();
         let free_pages = page_alloc.free_pages();
+        let slab_pages = SLAB_ALLOC.get().map_or(0, |slab| slab.slab_pages());
+
+        let kb = |pages: usize| (pages * PAGE_SIZE) / 1024;
+
+        // There is no page cache or swap yet, so nothing is reclaimable.
+        let fields = [
+            ("MemTotal", kb(total_pages)),
+            ("MemFree", kb(free_pages)),
+            ("MemAvailable", kb(free_pages)),
+            ("Buffers", 0),
+            ("Cached", 0),
+            ("SwapCached", 0),
+            ("Shmem", 0),
+            ("Slab", kb(slab_pages)),
+            ("SReclaimable", 0),
+            ("SUnreclaim", kb(slab_pages)),
+            ("SwapTotal", 0),
+            ("SwapFree", 0),
+            ("CommitLimit", kb(VM_COMMIT.commit_limit())),
+            ("Committed_AS", kb(VM_COMMIT.committed())),
+            ("VmallocTotal", ArchImpl::VMALLOC_AREA.size() / 1024),
+            ("VmallocUsed", vmalloc_used() / 1024),
+        ];
 
-        let total_ram = (total_pages * PAGE_SIZE) / 1024;
-        let free_ram = (free_pages * PAGE_SIZE) / 1204;
         let mut meminfo_content = String::new();
-        meminfo_content.push_str(&format!("MemTotal: {total_ram} kB\n"));
-        meminfo_content.push_str(&format!("MemFree: {free_ram} kB\n"));
+
+        for (name, val) in fields {
+            let label = format!("{name}:");
+
+            writeln!(meminfo_content, "{label:<16}{val:>8} kB").unwrap();
+        }
+
         Ok(meminfo_content.into_bytes())
     }
 }
@@ -0,0 +1,111 @@
+use crate::drivers::fs::proc::get_inode_id;
+use crate::net::proc::NetTable;
+use alloc::boxed::Box;
+use alloc::string::ToString;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use async_trait::async_trait;
+use core::any::Any;
+use libkernel::error::{FsError, Result};
+use libkernel::fs::attr::{FileAttr, FilePermissions};
+use libkernel::fs::{
+    DirStream, Dirent, FileType, Inode, InodeId, PROCFS_ID, SimpleDirStream, SimpleFile,
+};
+
+fn net_inode_id(name: &str) -> InodeId {
+    InodeId::from_fsid_and_inodeid(PROCFS_ID, get_inode_id(&["net", name]))
+}
+
+/// /proc/net, which holds the network stack's tables.
+pub struct ProcNetDirInode {
+    id: InodeId,
+    attr: FileAttr,
+}
+
+impl ProcNetDirInode {
+    pub fn new(id: InodeId) -> Self {
+        Self {
+            id,
+            attr: FileAttr {
+                file_type: FileType::Directory,
+                permissions: FilePermissions::from_bits_retain(0o555),
+                ..FileAttr::default()
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl Inode for ProcNetDirInode {
+    fn id(&self) -> InodeId {
+        self.id
+    }
+
+    async fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        let (_, table) = NetTable::ALL
+            .into_iter()
+            .find(|(table_name, _)| *table_name == name)
+            .ok_or(FsError::NotFound)?;
+
+        Ok(Arc::new(ProcNetTableInode::new(net_inode_id(name), table)))
+    }
+
+    async fn getattr(&self) -> Result<FileAttr> {
+        Ok(self.attr.clone())
+    }
+
+    async fn readdir(&self, start_offset: u64) -> Result<Box<dyn DirStream>> {
+        let mut entries: Vec<Dirent> = Vec::new();
+
+        for (name, _) in NetTable::ALL {
+            entries.push(Dirent::new(
+                name.to_string(),
+                net_inode_id(name),
+                FileType::File,
+                (entries.len() + 1) as u64,
+            ));
+        }
+
+        Ok(Box::new(SimpleDirStream::new(entries, start_offset)))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// One of the tables in /proc/net.
+pub struct ProcNetTableInode {
+    id: InodeId,
+    attr: FileAttr,
+    table: NetTable,
+}
+
+impl ProcNetTableInode {
+    pub fn new(id: InodeId, table: NetTable) -> Self {
+        Self {
+            id,
+            attr: FileAttr {
+                file_type: FileType::File,
+                permissions: FilePermissions::from_bits_retain(0o444),
+                ..FileAttr::default()
+            },
+            table,
+        }
+    }
+}
+
+#[async_trait]
+impl SimpleFile for ProcNetTableInode {
+    fn id(&self) -> InodeId {
+        self.id
+    }
+
+    async fn getattr(&self) -> Result<FileAttr> {
+        Ok(self.attr.clone())
+    }
+
+    async fn read(&self) -> Result<Vec<u8>> {
+        Ok(self.table.read().into_bytes())
+    }
+}
@@ -1,13 +1,20 @@
+use crate::drivers::fs::proc::buddyinfo::ProcBuddyinfoInode;
 use crate::drivers::fs::proc::cmdline::ProcCmdlineInode;
+use crate::drivers::fs::proc::cpuidle::ProcCpuidleInode;
 use crate::drivers::fs::proc::get_inode_id;
+#[cfg(feature = "kmemleak")]
+use crate::drivers::fs::proc::kmemleak::ProcKmemleakInode;
+use crate::drivers::fs::proc::kmsg::ProcKmsgInode;
 use crate::drivers::fs::proc::meminfo::ProcMeminfoInode;
+use crate::drivers::fs::proc::net::ProcNetDirInode;
 use crate::drivers::fs::proc::stat::ProcStatInode;
+use crate::drivers::fs::proc::sys::ProcSysDirInode;
 use crate::drivers::fs::proc::task::ProcTaskInode;
 use crate::process::thread_group::pid::PidT;
 use crate::process::{TASK_LIST, TaskDescriptor, Tid, find_task_by_tid};
 use crate::sched::current_work;
 use alloc::boxed::Box;
-use alloc::string::ToString;
+use alloc::string::{String, ToString};
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use async_trait::async_trait;
@@ -43,6 +50,13 @@ impl Inode for ProcRootInode {
     async fn lookup(&self, name: &str) -> error::Result<Arc<dyn Inode>> {
         let current = current_work();
 
+        #[cfg(feature = "kmemleak")]
+        if name == "kmemleak" {
+            return Ok(Arc::new(ProcKmemleakInode::new(
+                InodeId::from_fsid_and_inodeid(self.id.fs_id(), get_inode_id(&["kmemleak"])),
+            )));
+        }
+
         // Lookup a PID directory.
         let desc = if name == "self" {
             // FIXME: The group leader may have exited.
@@ -57,10 +71,31 @@ impl Inode for ProcRootInode {
             return Ok(Arc::new(ProcMeminfoInode::new(
                 InodeId::from_fsid_and_inodeid(self.id.fs_id(), get_inode_id(&["meminfo"])),
             )));
+        } else if name == "buddyinfo" {
+            return Ok(Arc::new(ProcBuddyinfoInode::new(
+                InodeId::from_fsid_and_inodeid(self.id.fs_id(), get_inode_id(&["buddyinfo"])),
+            )));
         } else if name == "cmdline" {
             return Ok(Arc::new(ProcCmdlineInode::new(
                 InodeId::from_fsid_and_inodeid(self.id.fs_id(), get_inode_id(&["cmdline"])),
             )));
+        } else if name == "cpuidle" {
+            return Ok(Arc::new(ProcCpuidleInode::new(
+                InodeId::from_fsid_and_inodeid(self.id.fs_id(), get_inode_id(&["cpuidle"])),
+            )));
+        } else if name == "kmsg" {
+            return Ok(Arc::new(ProcKmsgInode::new(
+                InodeId::from_fsid_and_inodeid(self.id.fs_id(), get_inode_id(&["kmsg"])),
+            )));
+        } else if name == "net" {
+            return Ok(Arc::new(ProcNetDirInode::new(
+                InodeId::from_fsid_and_inodeid(self.id.fs_id(), get_inode_id(&["net"])),
+            )));
+        } else if name == "sys" {
+            return Ok(Arc::new(ProcSysDirInode::new(
+                InodeId::from_fsid_and_inodeid(self.id.fs_id(), get_inode_id(&["sys"])),
+                String::new(),
+            )));
         } else {
             let pid: PidT = name.parse().map_err(|_| FsError::NotFound)?;
             // Search for the task descriptor.
@@ -134,12 +169,49 @@ impl Inode for ProcRootInode {
             FileType::File,
             (entries.len() + 1) as u64,
         ));
+        entries.push(Dirent::new(
+            "buddyinfo".to_string(),
+            InodeId::from_fsid_and_inodeid(PROCFS_ID, get_inode_id(&["buddyinfo"])),
+            FileType::File,
+            (entries.len() + 1) as u64,
+        ));
         entries.push(Dirent::new(
             "cmdline".to_string(),
             InodeId::from_fsid_and_inodeid(PROCFS_ID, get_inode_id(&["cmdline"])),
             FileType::File,
             (entries.len() + 1) as u64,
         ));
+        entries.push(Dirent::new(
+            "cpuidle".to_string(),
+            InodeId::from_fsid_and_inodeid(PROCFS_ID, get_inode_id(&["cpuidle"])),
+            FileType::File,
+            (entries.len() + 1) as u64,
+        ));
+        entries.push(Dirent::new(
+            "kmsg".to_string(),
+            InodeId::from_fsid_and_inodeid(PROCFS_ID, get_inode_id(&["kmsg"])),
+            FileType::File,
+            (entries.len() + 1) as u64,
+        ));
+        entries.push(Dirent::new(
+            "net".to_string(),
+            InodeId::from_fsid_and_inodeid(PROCFS_ID, get_inode_id(&["net"])),
+            FileType::Directory,
+            (entries.len() + 1) as u64,
+        ));
+        entries.push(Dirent::new(
+            "sys".to_string(),
+            InodeId::from_fsid_and_inodeid(PROCFS_ID, get_inode_id(&["sys"])),
+            FileType::Directory,
+            (entries.len() + 1) as u64,
+        ));
+        #[cfg(feature = "kmemleak")]
+        entries.push(Dirent::new(
+            "kmemleak".to_string(),
+            InodeId::from_fsid_and_inodeid(PROCFS_ID, get_inode_id(&["kmemleak"])),
+            FileType::File,
+            (entries.len() + 1) as u64,
+        ));
 
         Ok(Box::new(SimpleDirStream::new(entries, start_offset)))
     }
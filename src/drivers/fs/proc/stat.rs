@@ -1,10 +1,8 @@
-use crate::arch::{Arch, ArchImpl};
 use crate::drivers::timer::uptime;
-use crate::kernel::cpu_id::CpuId;
 use crate::process::TASK_LIST;
 use crate::process::clone::NUM_FORKS;
 use crate::sched::sched_task::state::TaskState;
-use crate::sched::{CpuStat, NUM_CONTEXT_SWITCHES, get_cpu_stat};
+use crate::sched::{CPU_STAT, CpuStat, NUM_CONTEXT_SWITCHES};
 use alloc::boxed::Box;
 use alloc::format;
 use alloc::string::{String, ToString};
@@ -44,10 +42,7 @@ impl SimpleFile for ProcStatInode {
     async fn read(&self) -> libkernel::error::Result<Vec<u8>> {
         let mut stat_content = String::new();
 
-        let mut cpu_stats = Vec::new();
-        for cpu_id in 0..ArchImpl::cpu_count() {
-            cpu_stats.push(get_cpu_stat(CpuId::from_value(cpu_id)));
-        }
+        let mut cpu_stats: Vec<_> = CPU_STAT.iter().map(CpuStat::to_usize).collect();
         let total: CpuStat<usize> =
             cpu_stats
                 .iter()
@@ -98,15 +93,9 @@ impl SimpleFile for ProcStatInode {
                 _ => {}
             }
         }
-        stat_content.push_str(&format!(
-            "ctxt {}\n",
-            NUM_CONTEXT_SWITCHES.load(Ordering::Relaxed)
-        ));
+        stat_content.push_str(&format!("ctxt {}\n", NUM_CONTEXT_SWITCHES.sum()));
         stat_content.push_str(&format!("btime {}\n", uptime().as_secs()));
-        stat_content.push_str(&format!(
-            "processes {}\n",
-            NUM_FORKS.load(Ordering::Relaxed)
-        ));
+        stat_content.push_str(&format!("processes {}\n", NUM_FORKS.sum()));
         stat_content.push_str(&format!("procs_running {procs_running}\n",));
         stat_content.push_str(&format!("procs_blocked {procs_blocked}\n",));
         Ok(stat_content.into_bytes())
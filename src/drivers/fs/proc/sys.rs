@@ -0,0 +1,158 @@
+use crate::drivers::fs::proc::get_inode_id;
+use crate::kernel::sysctl::{Sysctl, find_sysctl, sysctl_dir};
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use async_trait::async_trait;
+use core::any::Any;
+use libkernel::error::{FsError, Result};
+use libkernel::fs::attr::{FileAttr, FilePermissions};
+use libkernel::fs::{DirStream, Dirent, FileType, Inode, InodeId, PROCFS_ID, SimpleDirStream};
+
+fn sysctl_inode_id(name: &str) -> InodeId {
+    InodeId::from_fsid_and_inodeid(PROCFS_ID, get_inode_id(&["sys", name]))
+}
+
+/// Joins a directory's dotted path and an entry within it.
+fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else {
+        format!("{dir}.{name}")
+    }
+}
+
+/// A directory under /proc/sys, for the sysctls whose names start with `path`.
+pub struct ProcSysDirInode {
+    id: InodeId,
+    attr: FileAttr,
+    /// The directory's dotted path, empty for /proc/sys itself.
+    path: String,
+}
+
+impl ProcSysDirInode {
+    pub fn new(id: InodeId, path: String) -> Self {
+        Self {
+            id,
+            attr: FileAttr {
+                file_type: FileType::Directory,
+                permissions: FilePermissions::from_bits_retain(0o555),
+                ..FileAttr::default()
+            },
+            path,
+        }
+    }
+}
+
+#[async_trait]
+impl Inode for ProcSysDirInode {
+    fn id(&self) -> InodeId {
+        self.id
+    }
+
+    async fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        let path = join(&self.path, name);
+
+        if let Some(sysctl) = find_sysctl(&path) {
+            return Ok(Arc::new(ProcSysctlInode::new(
+                sysctl_inode_id(&path),
+                sysctl,
+            )));
+        }
+
+        if sysctl_dir(&path).is_some() {
+            return Ok(Arc::new(ProcSysDirInode::new(sysctl_inode_id(&path), path)));
+        }
+
+        Err(FsError::NotFound.into())
+    }
+
+    async fn getattr(&self) -> Result<FileAttr> {
+        Ok(self.attr.clone())
+    }
+
+    async fn readdir(&self, start_offset: u64) -> Result<Box<dyn DirStream>> {
+        let mut entries: Vec<Dirent> = Vec::new();
+
+        for (name, is_dir) in sysctl_dir(&self.path).unwrap_or_default() {
+            entries.push(Dirent::new(
+                name.to_string(),
+                sysctl_inode_id(&join(&self.path, name)),
+                if is_dir {
+                    FileType::Directory
+                } else {
+                    FileType::File
+                },
+                (entries.len() + 1) as u64,
+            ));
+        }
+
+        Ok(Box::new(SimpleDirStream::new(entries, start_offset)))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// A sysctl's value, as a file.
+pub struct ProcSysctlInode {
+    id: InodeId,
+    attr: FileAttr,
+    sysctl: &'static Sysctl,
+}
+
+impl ProcSysctlInode {
+    pub fn new(id: InodeId, sysctl: &'static Sysctl) -> Self {
+        Self {
+            id,
+            attr: FileAttr {
+                file_type: FileType::File,
+                permissions: FilePermissions::from_bits_retain(0o644),
+                ..FileAttr::default()
+            },
+            sysctl,
+        }
+    }
+}
+
+#[async_trait]
+impl Inode for ProcSysctlInode {
+    fn id(&self) -> InodeId {
+        self.id
+    }
+
+    async fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let value = self.sysctl.read();
+        let data = value.as_bytes();
+        let start = (offset as usize).min(data.len());
+        let len = buf.len().min(data.len() - start);
+
+        buf[..len].copy_from_slice(&data[start..start + len]);
+        Ok(len)
+    }
+
+    /// Replaces the value with what's written. Each write must hold the whole
+    /// of the new value.
+    async fn write_at(&self, _offset: u64, buf: &[u8]) -> Result<usize> {
+        self.sysctl.write(buf)?;
+
+        Ok(buf.len())
+    }
+
+    /// Opening with `O_TRUNC`, as shells do for `>`, leaves the value as it
+    /// is until it's written.
+    async fn truncate(&self, _size: u64) -> Result<()> {
+        Ok(())
+    }
+
+    async fn getattr(&self) -> Result<FileAttr> {
+        Ok(self.attr.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
@@ -139,12 +139,24 @@ impl Inode for ProcTaskInode {
             FileType::File,
             10,
         ));
+        entries.push(Dirent::new(
+            "smaps".to_string(),
+            InodeId::from_fsid_and_inodeid(PROCFS_ID, get_inode_id(&[&initial_str, "smaps"])),
+            FileType::File,
+            11,
+        ));
+        entries.push(Dirent::new(
+            "oom_score".to_string(),
+            InodeId::from_fsid_and_inodeid(PROCFS_ID, get_inode_id(&[&initial_str, "oom_score"])),
+            FileType::File,
+            12,
+        ));
         if !self.is_task_dir {
             entries.push(Dirent::new(
                 "task".to_string(),
                 InodeId::from_fsid_and_inodeid(PROCFS_ID, get_inode_id(&[&initial_str, "task"])),
                 FileType::Directory,
-                11,
+                13,
             ));
         }
 
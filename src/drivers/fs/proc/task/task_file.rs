@@ -1,5 +1,6 @@
 use crate::{
     drivers::fs::cgroup::cgroup_path_for_thread_group,
+    memory::{PAGE_ALLOC, oom::oom_score, page::is_zero_page},
     process::{Tid, find_task_by_tid},
 };
 use alloc::boxed::Box;
@@ -7,11 +8,15 @@ use alloc::format;
 use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 use async_trait::async_trait;
+use core::fmt::Write;
 use core::sync::atomic::Ordering;
 use libkernel::error::{FsError, KernelError};
 use libkernel::fs::attr::{FileAttr, FilePermissions};
 use libkernel::fs::pathbuf::PathBuf;
 use libkernel::fs::{FileType, InodeId, SimpleFile};
+use libkernel::memory::PAGE_SIZE;
+use libkernel::memory::proc_vm::address_space::UserAddressSpace;
+use libkernel::memory::proc_vm::vmarea::VMArea;
 
 pub enum TaskFileType {
     Status,
@@ -21,8 +26,10 @@ pub enum TaskFileType {
     State,
     Stat,
     Maps,
+    Smaps,
     Exe,
     Cgroup,
+    OomScore,
 }
 
 impl TryFrom<&str> for TaskFileType {
@@ -37,13 +44,108 @@ impl TryFrom<&str> for TaskFileType {
             "cwd" => Ok(TaskFileType::Cwd),
             "root" => Ok(TaskFileType::Root),
             "maps" => Ok(TaskFileType::Maps),
+            "smaps" => Ok(TaskFileType::Smaps),
             "exe" => Ok(TaskFileType::Exe),
             "cgroup" => Ok(TaskFileType::Cgroup),
+            "oom_score" => Ok(TaskFileType::OomScore),
             _ => Err(()),
         }
     }
 }
 
+/// Formats a VMA as a line of `/proc/<pid>/maps`.
+fn maps_line(vma: &VMArea) -> String {
+    let mut line = format!(
+        "{:08x}-{:08x} {}{}{}{} {:08x} 00:00 {}",
+        vma.region().start_address().value(),
+        vma.region().end_address().value(),
+        if vma.permissions().read { "r" } else { "-" },
+        if vma.permissions().write { "w" } else { "-" },
+        if vma.permissions().execute { "x" } else { "-" },
+        "p", // Don't suport shared mappings... yet!
+        vma.file_offset().unwrap_or_default(),
+        vma.inode_id().map_or(0, |id| id.inode_id()),
+    );
+
+    if !vma.name().is_empty() {
+        // The path is padded to a fixed column, as on Linux.
+        let pad = 73usize.saturating_sub(line.len()).max(1);
+        line.push_str(&format!("{:pad$}{}", "", vma.name()));
+    }
+
+    line.push('\n');
+    line
+}
+
+/// Formats the resident memory statistics of a VMA for
+/// `/proc/<pid>/smaps`.
+fn smaps_stats(vma: &VMArea, addr_space: &impl UserAddressSpace) -> String {
+    let page_alloc = PAGE_ALLOC.get().unwrap();
+
+    let mut rss = 0;
+    // Proportional set size, scaled by the page size to avoid rounding.
+    let mut pss = 0;
+    let mut shared_clean = 0;
+    let mut shared_dirty = 0;
+    let mut private_clean = 0;
+    let mut private_dirty = 0;
+    let mut anonymous = 0;
+
+    for va in vma.region().iter_pages() {
+        // As on Linux, the shared zero page isn't counted as resident.
+        let Some(info) = addr_space.translate(va).filter(|i| !is_zero_page(i.pfn)) else {
+            continue;
+        };
+
+        let ref_count = page_alloc.ref_count(info.pfn).unwrap_or(1) as usize;
+
+        // Anonymous memory and pages that have been written since they were
+        // mapped have no clean copy elsewhere.
+        let dirty = !vma.is_file_backed() || (info.perms.is_write() && !info.perms.is_cow());
+
+        rss += PAGE_SIZE;
+        pss += PAGE_SIZE * PAGE_SIZE / ref_count;
+
+        match (ref_count > 1, dirty) {
+            (true, true) => shared_dirty += PAGE_SIZE,
+            (true, false) => shared_clean += PAGE_SIZE,
+            (false, true) => private_dirty += PAGE_SIZE,
+            (false, false) => private_clean += PAGE_SIZE,
+        }
+
+        if !vma.is_file_backed() {
+            anonymous += PAGE_SIZE;
+        }
+    }
+
+    let fields = [
+        ("Size", vma.region().size()),
+        ("KernelPageSize", PAGE_SIZE),
+        ("MMUPageSize", PAGE_SIZE),
+        ("Rss", rss),
+        ("Pss", pss / PAGE_SIZE),
+        ("Shared_Clean", shared_clean),
+        ("Shared_Dirty", shared_dirty),
+        ("Private_Clean", private_clean),
+        ("Private_Dirty", private_dirty),
+        ("Referenced", rss),
+        ("Anonymous", anonymous),
+        ("Swap", 0),
+        ("SwapPss", 0),
+        ("Locked", 0),
+    ];
+
+    let mut output = String::new();
+
+    for (name, bytes) in fields {
+        let label = format!("{name}:");
+
+        writeln!(output, "{label:<16}{:>8} kB", bytes / 1024).unwrap();
+    }
+
+    output
+}
+
 pub struct ProcTaskFileInode {
     id: InodeId,
     file_type: TaskFileType,
@@ -62,8 +164,10 @@ impl ProcTaskFileInode {
                     | TaskFileType::Comm
                     | TaskFileType::State
                     | TaskFileType::Maps
+                    | TaskFileType::Smaps
                     | TaskFileType::Stat
-                    | TaskFileType::Cgroup => FileType::File,
+                    | TaskFileType::Cgroup
+                    | TaskFileType::OomScore => FileType::File,
                     TaskFileType::Cwd | TaskFileType::Root | TaskFileType::Exe => FileType::Symlink,
                 },
                 permissions: FilePermissions::from_bits_retain(0o444),
@@ -217,20 +321,21 @@ Threads:\t{tasks}\n",
                 TaskFileType::Root => task.root.lock_save_irq().1.as_str().to_string(),
                 TaskFileType::Maps => {
                     let mut output = String::new();
-                    let mut vm = task.vm.lock_save_irq();
-
-                    for vma in vm.mm_mut().iter_vmas() {
-                        output.push_str(&format!(
-                            "{:x}-{:x} {}{}{}{} {:010x}                    {}\n",
-                            vma.region().start_address().value(),
-                            vma.region().end_address().value(),
-                            if vma.permissions().read { "r" } else { "-" },
-                            if vma.permissions().write { "w" } else { "-" },
-                            if vma.permissions().execute { "x" } else { "-" },
-                            "p", // Don't suport shared mappings... yet!
-                            vma.file_offset().unwrap_or_default(),
-                            vma.name()
-                        ));
+                    let vm = task.vm.lock_save_irq();
+
+                    for vma in vm.mm().iter_vmas() {
+                        output.push_str(&maps_line(vma));
+                    }
+
+                    output
+                }
+                TaskFileType::Smaps => {
+                    let mut output = String::new();
+                    let vm = task.vm.lock_save_irq();
+
+                    for vma in vm.mm().iter_vmas() {
+                        output.push_str(&maps_line(vma));
+                        output.push_str(&smaps_stats(vma, vm.mm().address_space()));
                     }
 
                     output
@@ -246,6 +351,7 @@ Threads:\t{tasks}\n",
                 TaskFileType::Cgroup => {
                     format!("0::{}\n", cgroup_path_for_thread_group(task.process.tgid))
                 }
+                TaskFileType::OomScore => format!("{}\n", oom_score(&task)),
             }
         } else {
             "State:\tGone\n".to_string()
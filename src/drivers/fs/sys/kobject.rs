@@ -0,0 +1,254 @@
+//! The tree of kernel objects, through which drivers and subsystems export
+//! attributes in sysfs.
+//!
+//! Each [`KObject`] is a directory, holding the objects below it and its
+//! attributes. An [`Attribute`] is a file, whose contents are produced when
+//! it's read and, if it can be set, taken when it's written. The objects at
+//! the top of the tree sit in the root of sysfs beside its fixed directories,
+//! so the MTU of a network interface is at e.g. `/sys/class/net/eth0/mtu`.
+
+use super::get_inode_id;
+use crate::sync::{OnceLock, SpinLock};
+use alloc::boxed::Box;
+use alloc::collections::btree_map::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use async_trait::async_trait;
+use core::any::Any;
+use libkernel::error::{FsError, KernelError, Result};
+use libkernel::fs::attr::{FileAttr, FilePermissions};
+use libkernel::fs::{DirStream, Dirent, FileType, Inode, InodeId, SYSFS_ID, SimpleDirStream};
+
+type ShowFn = Box<dyn Fn() -> Result<String> + Send + Sync>;
+type StoreFn = Box<dyn Fn(&str) -> Result<()> + Send + Sync>;
+
+/// A value an object exports, as a file in its directory.
+pub struct Attribute {
+    show: ShowFn,
+    store: Option<StoreFn>,
+}
+
+impl Attribute {
+    /// An attribute whose contents `show` returns.
+    pub fn read_only(show: impl Fn() -> Result<String> + Send + Sync + 'static) -> Self {
+        Self {
+            show: Box::new(show),
+            store: None,
+        }
+    }
+
+    /// An attribute whose contents `show` returns, and which `store` sets from
+    /// what's written to it, less any surrounding whitespace.
+    pub fn read_write(
+        show: impl Fn() -> Result<String> + Send + Sync + 'static,
+        store: impl Fn(&str) -> Result<()> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            show: Box::new(show),
+            store: Some(Box::new(store)),
+        }
+    }
+}
+
+/// A kernel object, shown as a directory in sysfs.
+pub struct KObject {
+    /// The object's path from the root of sysfs, from which its inode IDs
+    /// come.
+    path: String,
+    children: SpinLock<BTreeMap<String, Arc<KObject>>>,
+    attrs: SpinLock<BTreeMap<String, Arc<Attribute>>>,
+}
+
+impl KObject {
+    fn new(path: String) -> Arc<Self> {
+        Arc::new(Self {
+            path,
+            children: SpinLock::new(BTreeMap::new()),
+            attrs: SpinLock::new(BTreeMap::new()),
+        })
+    }
+
+    /// Returns the object `name` below this one, adding it if there isn't one.
+    pub fn child(&self, name: &str) -> Arc<KObject> {
+        self.children
+            .lock_save_irq()
+            .entry(name.to_string())
+            .or_insert_with(|| KObject::new(format!("{}/{name}", self.path)))
+            .clone()
+    }
+
+    /// Gives the object the attribute `name`, replacing any it had of that
+    /// name.
+    pub fn add_attr(&self, name: &str, attr: Attribute) {
+        self.attrs
+            .lock_save_irq()
+            .insert(name.to_string(), Arc::new(attr));
+    }
+
+    fn entry_id(&self, name: &str) -> InodeId {
+        InodeId::from_fsid_and_inodeid(SYSFS_ID, get_inode_id(&[&self.path, "/", name]))
+    }
+
+    /// Looks up the object or attribute `name` below this object.
+    pub(super) fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        if let Some(child) = self.children.lock_save_irq().get(name) {
+            return Ok(Arc::new(KObjectInode::new(
+                self.entry_id(name),
+                child.clone(),
+            )));
+        }
+
+        let attr = self
+            .attrs
+            .lock_save_irq()
+            .get(name)
+            .cloned()
+            .ok_or(FsError::NotFound)?;
+
+        Ok(Arc::new(AttributeInode::new(self.entry_id(name), attr)))
+    }
+
+    /// Returns the entries for the objects and attributes below this object,
+    /// numbering them on from `offset`.
+    pub(super) fn dirents(&self, mut offset: u64) -> Vec<Dirent> {
+        let mut entries = Vec::new();
+        let mut push = |name: &String, file_type| {
+            offset += 1;
+            entries.push(Dirent::new(
+                name.clone(),
+                self.entry_id(name),
+                file_type,
+                offset,
+            ));
+        };
+
+        for name in self.children.lock_save_irq().keys() {
+            push(name, FileType::Directory);
+        }
+        for name in self.attrs.lock_save_irq().keys() {
+            push(name, FileType::File);
+        }
+
+        entries
+    }
+}
+
+static ROOT: OnceLock<Arc<KObject>> = OnceLock::new();
+
+/// Returns the root of the tree, the objects directly below which are in the
+/// root of sysfs.
+pub fn kobject_root() -> Arc<KObject> {
+    ROOT.get_or_init(|| KObject::new(String::new())).clone()
+}
+
+/// An object's directory.
+struct KObjectInode {
+    id: InodeId,
+    attr: FileAttr,
+    kobj: Arc<KObject>,
+}
+
+impl KObjectInode {
+    fn new(id: InodeId, kobj: Arc<KObject>) -> Self {
+        Self {
+            id,
+            attr: FileAttr {
+                file_type: FileType::Directory,
+                permissions: FilePermissions::from_bits_retain(0o755),
+                ..FileAttr::default()
+            },
+            kobj,
+        }
+    }
+}
+
+#[async_trait]
+impl Inode for KObjectInode {
+    fn id(&self) -> InodeId {
+        self.id
+    }
+
+    async fn getattr(&self) -> Result<FileAttr> {
+        Ok(self.attr.clone())
+    }
+
+    async fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        self.kobj.lookup(name)
+    }
+
+    async fn readdir(&self, start_offset: u64) -> Result<Box<dyn DirStream>> {
+        Ok(Box::new(SimpleDirStream::new(
+            self.kobj.dirents(0),
+            start_offset,
+        )))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// An attribute's file.
+struct AttributeInode {
+    id: InodeId,
+    attr: FileAttr,
+    value: Arc<Attribute>,
+}
+
+impl AttributeInode {
+    fn new(id: InodeId, value: Arc<Attribute>) -> Self {
+        let mode = if value.store.is_some() { 0o644 } else { 0o444 };
+
+        Self {
+            id,
+            attr: FileAttr {
+                file_type: FileType::File,
+                permissions: FilePermissions::from_bits_retain(mode),
+                ..FileAttr::default()
+            },
+            value,
+        }
+    }
+}
+
+#[async_trait]
+impl Inode for AttributeInode {
+    fn id(&self) -> InodeId {
+        self.id
+    }
+
+    async fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let contents = (self.value.show)()?;
+        let data = contents.as_bytes();
+        let start = (offset as usize).min(data.len());
+        let len = buf.len().min(data.len() - start);
+
+        buf[..len].copy_from_slice(&data[start..start + len]);
+        Ok(len)
+    }
+
+    async fn write_at(&self, _offset: u64, buf: &[u8]) -> Result<usize> {
+        let store = self.value.store.as_ref().ok_or(FsError::PermissionDenied)?;
+        let value = str::from_utf8(buf).map_err(|_| KernelError::InvalidValue)?;
+
+        store(value.trim())?;
+
+        Ok(buf.len())
+    }
+
+    /// Opening with `O_TRUNC`, as shells do for `>`, leaves the attribute as
+    /// it is.
+    async fn truncate(&self, _size: u64) -> Result<()> {
+        Ok(())
+    }
+
+    async fn getattr(&self) -> Result<FileAttr> {
+        Ok(self.attr.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
@@ -1,3 +1,7 @@
+pub mod kobject;
+mod pstore;
+mod tracing;
+
 use crate::drivers::Driver;
 use crate::fs::FilesystemDriver;
 use crate::sync::OnceLock;
@@ -7,6 +11,7 @@ use alloc::sync::Arc;
 use alloc::vec::Vec;
 use async_trait::async_trait;
 use core::hash::Hasher;
+use kobject::{KObject, kobject_root};
 use libkernel::error::FsError;
 use libkernel::fs::attr::FileAttr;
 use libkernel::fs::{
@@ -17,6 +22,8 @@ use libkernel::{
     fs::Filesystem,
 };
 use log::warn;
+use pstore::DmesgRamoopsInode;
+use tracing::{AvailableEventsInode, SetEventInode, TraceInode, TracePipeRawInode};
 
 /// Deterministically generates an inode ID for the given path segments within the sysfs filesystem.
 fn get_inode_id(path_segments: &[&str]) -> u64 {
@@ -31,8 +38,10 @@ fn get_inode_id(path_segments: &[&str]) -> u64 {
     hash
 }
 
+/// Defines a directory of fixed entries. One given a `kobject` also holds the
+/// objects and attributes below that [`KObject`].
 macro_rules! static_dir {
-    ($name:ident, $path:expr, $( $entry_name:expr => $entry_type:expr, $entry_ident:ident ),* $(,)? ) => {
+    ($name:ident, $path:expr, kobject: $kobj:expr, $( $entry_name:expr => $entry_type:expr, $entry_ident:ident ),* $(,)? ) => {
         struct $name {
             id: InodeId,
             attr: FileAttr,
@@ -75,7 +84,13 @@ macro_rules! static_dir {
                             Ok(Arc::new($entry_ident::new(inode_id)))
                         },
                     )*
-                    _ => Err(KernelError::Fs(FsError::NotFound)),
+                    _ => {
+                        let kobj: Option<Arc<KObject>> = $kobj;
+                        match kobj {
+                            Some(kobj) => kobj.lookup(name),
+                            None => Err(KernelError::Fs(FsError::NotFound)),
+                        }
+                    }
                 }
             }
 
@@ -93,6 +108,10 @@ macro_rules! static_dir {
                         file_type: $entry_type,
                     });
                 )*
+                let kobj: Option<Arc<KObject>> = $kobj;
+                if let Some(kobj) = kobj {
+                    entries.extend(kobj.dirents(entries.len() as u64));
+                }
                 Ok(Box::new(SimpleDirStream::new(entries, start_offset)))
             }
 
@@ -101,6 +120,14 @@ macro_rules! static_dir {
             }
         }
     };
+    ($name:ident, $path:expr, $( $entry_name:expr => $entry_type:expr, $entry_ident:ident ),* $(,)? ) => {
+        static_dir! {
+            $name,
+            $path,
+            kobject: None,
+            $( $entry_name => $entry_type, $entry_ident, )*
+        }
+    };
 }
 
 static_dir! {
@@ -135,20 +162,38 @@ static_dir! {
     "fs/cgroup",
 }
 
+static_dir! {
+    PstoreInode,
+    "fs/pstore",
+    "dmesg-ramoops-0" => FileType::File, DmesgRamoopsInode,
+}
+
 static_dir! {
     FsInode,
     "fs",
     "cgroup" => FileType::Directory, CgroupInode,
+    "pstore" => FileType::Directory, PstoreInode,
+}
+
+static_dir! {
+    TracingInode,
+    "kernel/tracing",
+    "available_events" => FileType::File, AvailableEventsInode,
+    "set_event" => FileType::File, SetEventInode,
+    "trace" => FileType::File, TraceInode,
+    "trace_pipe_raw" => FileType::File, TracePipeRawInode,
 }
 
 static_dir! {
     KernelInode,
     "kernel",
+    "tracing" => FileType::Directory, TracingInode,
 }
 
 static_dir! {
     RootInode,
     "",
+    kobject: Some(kobject_root()),
     "dev" => FileType::Directory, DevInode,
     "devices" => FileType::Directory, DevicesInode,
     "firmware" => FileType::Directory, FirmwareInode,
@@ -0,0 +1,43 @@
+//! The crash record saved by the previous boot, under `fs/pstore`.
+
+use crate::kernel::pstore;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use async_trait::async_trait;
+use libkernel::error::Result;
+use libkernel::fs::attr::{FileAttr, FilePermissions};
+use libkernel::fs::{FileType, InodeId, SimpleFile};
+
+pub struct DmesgRamoopsInode {
+    id: InodeId,
+    attr: FileAttr,
+}
+
+impl DmesgRamoopsInode {
+    pub fn new(id: InodeId) -> Self {
+        Self {
+            id,
+            attr: FileAttr {
+                file_type: FileType::File,
+                permissions: FilePermissions::from_bits_retain(0o400),
+                size: pstore::last_record().len() as u64,
+                ..FileAttr::default()
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl SimpleFile for DmesgRamoopsInode {
+    fn id(&self) -> InodeId {
+        self.id
+    }
+
+    async fn getattr(&self) -> Result<FileAttr> {
+        Ok(self.attr.clone())
+    }
+
+    async fn read(&self) -> Result<Vec<u8>> {
+        Ok(pstore::last_record().to_vec())
+    }
+}
@@ -0,0 +1,249 @@
+//! The tracepoint files, under `kernel/tracing`.
+//!
+//! - `available_events` lists the tracepoints.
+//! - `set_event` lists the enabled tracepoints. Writing a tracepoint's name to
+//!   it enables it, and writing the name prefixed with `!` disables it. `*`
+//!   stands for every tracepoint.
+//! - `trace` prints the records made so far, without consuming them.
+//! - `trace_pipe_raw` consumes the records, oldest first, as [`TraceRecord`]s.
+
+use crate::kernel::trace::{self, TraceEvent, TraceRecord};
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use async_trait::async_trait;
+use core::any::Any;
+use core::mem::size_of;
+use libkernel::error::{KernelError, Result};
+use libkernel::fs::attr::{FileAttr, FilePermissions};
+use libkernel::fs::{FileType, Inode, InodeId, SimpleFile};
+
+fn file_attr(mode: u16) -> FileAttr {
+    FileAttr {
+        file_type: FileType::File,
+        permissions: FilePermissions::from_bits_retain(mode),
+        ..FileAttr::default()
+    }
+}
+
+pub struct AvailableEventsInode {
+    id: InodeId,
+    attr: FileAttr,
+}
+
+impl AvailableEventsInode {
+    pub fn new(id: InodeId) -> Self {
+        Self {
+            id,
+            attr: file_attr(0o444),
+        }
+    }
+}
+
+#[async_trait]
+impl SimpleFile for AvailableEventsInode {
+    fn id(&self) -> InodeId {
+        self.id
+    }
+
+    async fn getattr(&self) -> Result<FileAttr> {
+        Ok(self.attr.clone())
+    }
+
+    async fn read(&self) -> Result<Vec<u8>> {
+        let mut events = String::new();
+
+        for event in TraceEvent::ALL {
+            events.push_str(event.name());
+            events.push('\n');
+        }
+
+        Ok(events.into_bytes())
+    }
+}
+
+pub struct SetEventInode {
+    id: InodeId,
+    attr: FileAttr,
+}
+
+impl SetEventInode {
+    pub fn new(id: InodeId) -> Self {
+        Self {
+            id,
+            attr: file_attr(0o644),
+        }
+    }
+}
+
+#[async_trait]
+impl Inode for SetEventInode {
+    fn id(&self) -> InodeId {
+        self.id
+    }
+
+    async fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let mut events = String::new();
+
+        for event in TraceEvent::ALL.into_iter().filter(|e| e.is_enabled()) {
+            events.push_str(event.name());
+            events.push('\n');
+        }
+
+        let data = events.as_bytes();
+        let start = (offset as usize).min(data.len());
+        let len = buf.len().min(data.len() - start);
+
+        buf[..len].copy_from_slice(&data[start..start + len]);
+        Ok(len)
+    }
+
+    async fn write_at(&self, _offset: u64, buf: &[u8]) -> Result<usize> {
+        let cmds = str::from_utf8(buf).map_err(|_| KernelError::InvalidValue)?;
+
+        for cmd in cmds.split_whitespace() {
+            let (name, enable) = match cmd.strip_prefix('!') {
+                Some(name) => (name, false),
+                None => (cmd, true),
+            };
+
+            if name == "*" {
+                for event in TraceEvent::ALL {
+                    event.set_enabled(enable);
+                }
+            } else {
+                TraceEvent::from_name(name)
+                    .ok_or(KernelError::InvalidValue)?
+                    .set_enabled(enable);
+            }
+        }
+
+        Ok(buf.len())
+    }
+
+    /// Opening with `O_TRUNC`, as shells do for `>`, leaves the enabled
+    /// tracepoints as they are.
+    async fn truncate(&self, _size: u64) -> Result<()> {
+        Ok(())
+    }
+
+    async fn getattr(&self) -> Result<FileAttr> {
+        Ok(self.attr.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+pub struct TraceInode {
+    id: InodeId,
+    attr: FileAttr,
+}
+
+impl TraceInode {
+    pub fn new(id: InodeId) -> Self {
+        Self {
+            id,
+            attr: file_attr(0o444),
+        }
+    }
+}
+
+#[async_trait]
+impl SimpleFile for TraceInode {
+    fn id(&self) -> InodeId {
+        self.id
+    }
+
+    async fn getattr(&self) -> Result<FileAttr> {
+        Ok(self.attr.clone())
+    }
+
+    async fn read(&self) -> Result<Vec<u8>> {
+        let mut records = Vec::new();
+
+        trace::for_each_record(|record| records.push(*record));
+        records.sort_by_key(|record| record.timestamp);
+
+        let mut trace = format!(
+            "# entries: {} overruns: {}\n# cpu tid timestamp event args\n",
+            records.len(),
+            trace::overruns()
+        );
+
+        for record in records {
+            let args = record.args;
+
+            trace.push_str(&format!(
+                "{:3} {:6} {:6}.{:09} {}: {:#x} {:#x} {:#x} {:#x}\n",
+                record.cpu,
+                record.tid,
+                record.timestamp / 1_000_000_000,
+                record.timestamp % 1_000_000_000,
+                record.event().map_or("unknown", TraceEvent::name),
+                args[0],
+                args[1],
+                args[2],
+                args[3]
+            ));
+        }
+
+        Ok(trace.into_bytes())
+    }
+}
+
+pub struct TracePipeRawInode {
+    id: InodeId,
+    attr: FileAttr,
+}
+
+impl TracePipeRawInode {
+    pub fn new(id: InodeId) -> Self {
+        Self {
+            id,
+            attr: file_attr(0o444),
+        }
+    }
+}
+
+#[async_trait]
+impl Inode for TracePipeRawInode {
+    fn id(&self) -> InodeId {
+        self.id
+    }
+
+    /// Reads as many whole records as fit in `buf`. Doesn't wait for a record
+    /// to be made if there are none.
+    async fn read_at(&self, _offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let mut len = 0;
+
+        for chunk in buf.as_chunks_mut::<{ size_of::<TraceRecord>() }>().0 {
+            let Some(record) = trace::pop_record() else {
+                break;
+            };
+
+            // SAFETY: TraceRecord is repr(C), with no padding.
+            let bytes = unsafe {
+                core::slice::from_raw_parts(
+                    (&raw const record).cast::<u8>(),
+                    size_of::<TraceRecord>(),
+                )
+            };
+
+            chunk.copy_from_slice(bytes);
+            len += chunk.len();
+        }
+
+        Ok(len)
+    }
+
+    async fn getattr(&self) -> Result<FileAttr> {
+        Ok(self.attr.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
@@ -0,0 +1,562 @@
+//! Evdev: input devices as `/dev/input/eventN`.
+//!
+//! Every input device gets a character device from which userspace reads
+//! `struct input_event`s and queries the device with the `EVIOC*` ioctls, as
+//! on Linux. Each open file has its own event queue, so several readers, e.g.
+//! a compositor and a debugging tool, all see every event unless one of them
+//! grabs the device with `EVIOCGRAB`.
+
+use super::{InputDevice, InputEvent, InputHandler, InputId, codes::*, register_handler};
+use crate::{
+    clock::{ClockId, realtime::date},
+    drivers::{
+        CharDriver, DriverManager, OpenableDevice, ReservedMajors, fs::dev::devfs,
+        init::PlatformBus, timer::uptime,
+    },
+    fs::{
+        fops::FileOps,
+        open_file::{FileCtx, OpenFile},
+    },
+    kernel_driver,
+    memory::uaccess::{
+        UserCopyable, copy_from_user, copy_objs_to_user, copy_to_user, copy_to_user_slice,
+    },
+    process::thread_group::signal::{InterruptResult, Interruptable},
+    sync::{CondVar, SpinLock},
+};
+use alloc::{
+    boxed::Box,
+    collections::{btree_map::BTreeMap, vec_deque::VecDeque},
+    format,
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+use async_trait::async_trait;
+use core::{pin::Pin, time::Duration};
+use libkernel::{
+    driver::CharDevDescriptor,
+    error::{FsError, KernelError, Result},
+    fs::{OpenFlags, attr::FilePermissions},
+    memory::address::{TUA, UA},
+    sync::condvar::WakeupType,
+};
+use log::warn;
+
+/// Evdev minors start at 64, after those of the legacy joystick and mouse
+/// devices.
+const EVDEV_MINOR_BASE: u64 = 64;
+
+/// The number of events each reader may have queued.
+const CLIENT_BUFFER: usize = 64;
+
+const EV_VERSION: i32 = 0x010001;
+
+const IOC_READ: usize = 2;
+
+const EVIOCGVERSION: usize = 0x80044501;
+const EVIOCGID: usize = 0x80084502;
+const EVIOCGRAB: usize = 0x40044590;
+const EVIOCSCLOCKID: usize = 0x400445a0;
+
+// Numbers of the variable-length `EVIOC*` ioctls, whose size is the caller's
+// buffer length.
+const EVIOCGNAME_NR: usize = 0x06;
+const EVIOCGPHYS_NR: usize = 0x07;
+const EVIOCGUNIQ_NR: usize = 0x08;
+const EVIOCGPROP_NR: usize = 0x09;
+const EVIOCGKEY_NR: usize = 0x18;
+const EVIOCGLED_NR: usize = 0x19;
+const EVIOCGSND_NR: usize = 0x1a;
+const EVIOCGSW_NR: usize = 0x1b;
+const EVIOCGBIT_NR: usize = 0x20;
+const EVIOCGABS_NR: usize = 0x40;
+
+/// `struct input_event`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct RawInputEvent {
+    tv_sec: i64,
+    tv_usec: i64,
+    kind: u16,
+    code: u16,
+    value: i32,
+}
+
+unsafe impl UserCopyable for RawInputEvent {}
+
+/// `struct input_absinfo`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct RawAbsInfo {
+    value: i32,
+    minimum: i32,
+    maximum: i32,
+    fuzz: i32,
+    flat: i32,
+    resolution: i32,
+}
+
+unsafe impl UserCopyable for RawAbsInfo {}
+unsafe impl UserCopyable for InputId {}
+
+/// Returns the highest code of events of type `kind`, which determines the
+/// size of the bitmaps `EVIOCGBIT` returns.
+fn max_code(kind: u16) -> Option<u16> {
+    Some(match kind {
+        EV_SYN => EV_MAX,
+        EV_KEY => KEY_MAX,
+        EV_REL => REL_MAX,
+        EV_ABS => ABS_MAX,
+        EV_MSC => MSC_MAX,
+        EV_SW => SW_MAX,
+        EV_LED => LED_MAX,
+        EV_SND => SND_MAX,
+        EV_FF => FF_MAX,
+        _ => return None,
+    })
+}
+
+/// An event, timestamped with the uptime at which it was reported.
+#[derive(Clone, Copy)]
+struct Stamped {
+    time: Duration,
+    event: InputEvent,
+}
+
+struct ClientQueue {
+    events: VecDeque<Stamped>,
+    /// The number of events at the front of `events` which make up complete
+    /// frames, and so may be read.
+    ready: usize,
+}
+
+/// The per-open-file state of an evdev reader.
+struct Client {
+    queue: CondVar<ClientQueue>,
+}
+
+impl Client {
+    fn push(&self, stamped: Stamped) {
+        self.queue.update(|queue| {
+            if queue.events.len() == CLIENT_BUFFER {
+                // The reader has fallen behind. Drop what's queued and tell it
+                // to resynchronise its view of the device.
+                queue.events.clear();
+                queue.events.push_back(Stamped {
+                    time: stamped.time,
+                    event: InputEvent::new(EV_SYN, SYN_DROPPED, 0),
+                });
+                queue.ready = 1;
+            }
+
+            queue.events.push_back(stamped);
+
+            if stamped.event.kind == EV_SYN && stamped.event.code == SYN_REPORT {
+                queue.ready = queue.events.len();
+                WakeupType::All
+            } else {
+                WakeupType::None
+            }
+        });
+    }
+
+    /// Takes up to `max` readable events.
+    fn take(queue: &mut ClientQueue, max: usize) -> Option<Vec<Stamped>> {
+        if queue.ready == 0 {
+            return None;
+        }
+
+        let n = max.min(queue.ready);
+        queue.ready -= n;
+
+        Some(queue.events.drain(..n).collect())
+    }
+}
+
+struct EvdevState {
+    clients: Vec<Weak<Client>>,
+    /// The client which has grabbed the device, if any, and so receives all
+    /// of its events.
+    grab: Option<Weak<Client>>,
+    /// A bitmap of keys currently held.
+    keys: Vec<u8>,
+    /// The current value of each absolute axis.
+    abs: BTreeMap<u16, i32>,
+}
+
+/// The evdev interface to one input device.
+struct Evdev {
+    device: Arc<InputDevice>,
+    state: SpinLock<EvdevState>,
+}
+
+impl Evdev {
+    fn handle_event(&self, event: InputEvent) {
+        let stamped = Stamped {
+            time: uptime(),
+            event,
+        };
+
+        let clients = {
+            let mut state = self.state.lock_save_irq();
+
+            match event.kind {
+                EV_KEY => {
+                    if let Some(byte) = state.keys.get_mut(event.code as usize / 8) {
+                        let bit = 1 << (event.code % 8);
+
+                        if event.value == 0 {
+                            *byte &= !bit;
+                        } else {
+                            *byte |= bit;
+                        }
+                    }
+                }
+                EV_ABS => {
+                    state.abs.insert(event.code, event.value);
+                }
+                _ => {}
+            }
+
+            match state.grab.as_ref().and_then(Weak::upgrade) {
+                Some(grabber) => vec![grabber],
+                None => {
+                    state.clients.retain(|c| c.strong_count() > 0);
+                    state.clients.iter().filter_map(Weak::upgrade).collect()
+                }
+            }
+        };
+
+        clients.iter().for_each(|client| client.push(stamped));
+    }
+}
+
+/// Live evdev devices, keyed by input device number.
+static EVDEVS: SpinLock<BTreeMap<usize, Arc<Evdev>>> = SpinLock::new(BTreeMap::new());
+
+struct EvdevFile {
+    evdev: Arc<Evdev>,
+    client: Arc<Client>,
+    /// The clock timestamps are reported against, set by `EVIOCSCLOCKID`.
+    realtime: bool,
+}
+
+impl EvdevFile {
+    async fn read_impl(&mut self, buf: UA, count: usize, nonblock: bool) -> Result<usize> {
+        let event_size = size_of::<RawInputEvent>();
+
+        if count < event_size {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let max = count / event_size;
+        let take = move |queue: &mut ClientQueue| Client::take(queue, max);
+
+        let mut taken = None;
+
+        self.client.queue.update(|queue| {
+            taken = take(queue);
+            WakeupType::None
+        });
+
+        let events = match taken {
+            Some(events) => events,
+            None if nonblock => return Err(KernelError::TryAgain),
+            None => match self.client.queue.wait_until(take).interruptable().await {
+                InterruptResult::Interrupted => return Err(KernelError::Interrupted),
+                InterruptResult::Uninterrupted(events) => events,
+            },
+        };
+
+        // Timestamps are taken from the monotonic clock; shift them if the
+        // reader asked for wall-clock time.
+        let offset = if self.realtime {
+            date().saturating_sub(uptime())
+        } else {
+            Duration::ZERO
+        };
+
+        let raw: Vec<RawInputEvent> = events
+            .iter()
+            .map(|stamped| {
+                let time = stamped.time + offset;
+
+                RawInputEvent {
+                    tv_sec: time.as_secs() as i64,
+                    tv_usec: time.subsec_micros() as i64,
+                    kind: stamped.event.kind,
+                    code: stamped.event.code,
+                    value: stamped.event.value,
+                }
+            })
+            .collect();
+
+        copy_objs_to_user(&raw, buf.cast()).await?;
+
+        Ok(raw.len() * event_size)
+    }
+
+    /// Copies as much of `data` as fits in the caller's `size`-byte buffer,
+    /// returning the number of bytes copied.
+    async fn copy_out(data: &[u8], argp: usize, size: usize) -> Result<usize> {
+        let len = data.len().min(size);
+
+        copy_to_user_slice(&data[..len], UA::from_value(argp)).await?;
+
+        Ok(len)
+    }
+
+    /// Handles the ioctls whose size is given by the caller.
+    async fn ioctl_read(&mut self, nr: usize, argp: usize, size: usize) -> Result<usize> {
+        let device = &self.evdev.device;
+        let caps = device.capabilities();
+
+        let mut data = match nr {
+            EVIOCGNAME_NR => {
+                let mut name = device.name().as_bytes().to_vec();
+                name.push(0);
+                name
+            }
+            EVIOCGPHYS_NR | EVIOCGUNIQ_NR => return Err(FsError::NotFound.into()),
+            EVIOCGPROP_NR => {
+                let mut props = caps.props.clone();
+                props.resize(INPUT_PROP_MAX as usize / 8 + 1, 0);
+                props
+            }
+            EVIOCGKEY_NR => self.evdev.state.lock_save_irq().keys.clone(),
+            EVIOCGLED_NR => vec![0; LED_MAX as usize / 8 + 1],
+            EVIOCGSND_NR => vec![0; SND_MAX as usize / 8 + 1],
+            EVIOCGSW_NR => vec![0; SW_MAX as usize / 8 + 1],
+            nr if (EVIOCGBIT_NR..EVIOCGBIT_NR + EV_MAX as usize + 1).contains(&nr) => {
+                let kind = (nr - EVIOCGBIT_NR) as u16;
+                let max = max_code(kind).ok_or(KernelError::InvalidValue)?;
+
+                let mut bitmap = caps.bitmap(kind);
+                bitmap.resize(max as usize / 8 + 1, 0);
+                bitmap
+            }
+            nr if (EVIOCGABS_NR..EVIOCGABS_NR + ABS_MAX as usize + 1).contains(&nr) => {
+                let axis = (nr - EVIOCGABS_NR) as u16;
+                let info = caps.abs.get(&axis).copied().unwrap_or_default();
+                let value = self
+                    .evdev
+                    .state
+                    .lock_save_irq()
+                    .abs
+                    .get(&axis)
+                    .copied()
+                    .unwrap_or(info.minimum);
+
+                if size < size_of::<RawAbsInfo>() {
+                    return Err(KernelError::InvalidValue);
+                }
+
+                copy_to_user(
+                    TUA::from_value(argp),
+                    RawAbsInfo {
+                        value,
+                        minimum: info.minimum,
+                        maximum: info.maximum,
+                        fuzz: info.fuzz,
+                        flat: info.flat,
+                        resolution: info.resolution,
+                    },
+                )
+                .await?;
+
+                return Ok(0);
+            }
+            _ => return Err(KernelError::NotATty),
+        };
+
+        // Names are truncated, but still NUL-terminated.
+        if nr == EVIOCGNAME_NR && data.len() > size && size > 0 {
+            data.truncate(size);
+            data[size - 1] = 0;
+        }
+
+        Self::copy_out(&data, argp, size).await
+    }
+
+    fn grab(&self, grab: bool) -> Result<usize> {
+        let mut state = self.evdev.state.lock_save_irq();
+        let me = Arc::downgrade(&self.client);
+        let current = state.grab.as_ref().filter(|g| g.strong_count() > 0);
+
+        match (grab, current) {
+            (true, None) => state.grab = Some(me),
+            (false, Some(g)) if Weak::ptr_eq(g, &me) => state.grab = None,
+            _ => return Err(KernelError::InUse),
+        }
+
+        Ok(0)
+    }
+}
+
+#[async_trait]
+impl FileOps for EvdevFile {
+    async fn read(&mut self, ctx: &mut FileCtx, buf: UA, count: usize) -> Result<usize> {
+        self.read_impl(buf, count, ctx.flags.contains(OpenFlags::O_NONBLOCK))
+            .await
+    }
+
+    async fn readat(&mut self, buf: UA, count: usize, _offset: u64) -> Result<usize> {
+        self.read_impl(buf, count, false).await
+    }
+
+    async fn writeat(&mut self, _buf: UA, _count: usize, _offset: u64) -> Result<usize> {
+        Err(KernelError::NotSupported)
+    }
+
+    fn poll_read_ready(&self) -> Pin<Box<dyn Future<Output = Result<()>> + 'static + Send>> {
+        let client = self.client.clone();
+
+        Box::pin(async move {
+            client
+                .queue
+                .wait_until(|queue| (queue.ready > 0).then_some(()))
+                .await;
+
+            Ok(())
+        })
+    }
+
+    async fn ioctl(&mut self, _ctx: &mut FileCtx, request: usize, argp: usize) -> Result<usize> {
+        match request {
+            EVIOCGVERSION => {
+                copy_to_user(TUA::from_value(argp), EV_VERSION).await?;
+                Ok(0)
+            }
+            EVIOCGID => {
+                copy_to_user(TUA::from_value(argp), self.evdev.device.input_id()).await?;
+                Ok(0)
+            }
+            EVIOCGRAB => self.grab(argp != 0),
+            EVIOCSCLOCKID => {
+                let clock: i32 = copy_from_user(TUA::from_value(argp)).await?;
+
+                self.realtime = match ClockId::try_from(clock) {
+                    Ok(ClockId::Realtime) => true,
+                    Ok(ClockId::Monotonic | ClockId::BootTime) => false,
+                    _ => return Err(KernelError::InvalidValue),
+                };
+
+                Ok(0)
+            }
+            _ if request >> 30 == IOC_READ && (request >> 8) & 0xff == b'E' as usize => {
+                self.ioctl_read(request & 0xff, argp, (request >> 16) & 0x3fff)
+                    .await
+            }
+            _ => Err(KernelError::NotATty),
+        }
+    }
+
+    async fn release(&mut self, _ctx: &FileCtx) -> Result<()> {
+        let mut state = self.evdev.state.lock_save_irq();
+        let me = Arc::downgrade(&self.client);
+
+        state.clients.retain(|c| !Weak::ptr_eq(c, &me));
+
+        if state.grab.as_ref().is_some_and(|g| Weak::ptr_eq(g, &me)) {
+            state.grab = None;
+        }
+
+        Ok(())
+    }
+}
+
+struct EvdevNode {
+    evdev: Arc<Evdev>,
+}
+
+impl OpenableDevice for EvdevNode {
+    fn open(&self, flags: OpenFlags) -> Result<Arc<OpenFile>> {
+        let client = Arc::new(Client {
+            queue: CondVar::new(ClientQueue {
+                events: VecDeque::with_capacity(CLIENT_BUFFER + 1),
+                ready: 0,
+            }),
+        });
+
+        self.evdev
+            .state
+            .lock_save_irq()
+            .clients
+            .push(Arc::downgrade(&client));
+
+        Ok(Arc::new(OpenFile::new(
+            Box::new(EvdevFile {
+                evdev: self.evdev.clone(),
+                client,
+                realtime: false,
+            }),
+            flags,
+        )))
+    }
+}
+
+struct EvdevCharDev;
+
+impl CharDriver for EvdevCharDev {
+    fn get_device(&self, minor: u64) -> Option<Arc<dyn OpenableDevice>> {
+        let id = minor.checked_sub(EVDEV_MINOR_BASE)? as usize;
+        let evdev = EVDEVS.lock_save_irq().get(&id)?.clone();
+
+        Some(Arc::new(EvdevNode { evdev }))
+    }
+}
+
+/// The input handler which exposes every device through evdev.
+struct EvdevHandler;
+
+impl EvdevHandler {
+    fn create_node(device: &InputDevice) -> Result<()> {
+        devfs().mknod(
+            format!("input/event{}", device.id()),
+            CharDevDescriptor {
+                major: ReservedMajors::Input as _,
+                minor: EVDEV_MINOR_BASE + device.id() as u64,
+            },
+            FilePermissions::from_bits_retain(0o660),
+        )
+    }
+}
+
+impl InputHandler for EvdevHandler {
+    fn connect(&self, device: &Arc<InputDevice>) {
+        let evdev = Arc::new(Evdev {
+            device: device.clone(),
+            state: SpinLock::new(EvdevState {
+                clients: Vec::new(),
+                grab: None,
+                keys: vec![0; KEY_MAX as usize / 8 + 1],
+                abs: BTreeMap::new(),
+            }),
+        });
+
+        EVDEVS.lock_save_irq().insert(device.id(), evdev);
+
+        if let Err(e) = Self::create_node(device) {
+            warn!("evdev: failed to create node for {}: {e:?}", device.name());
+        }
+    }
+
+    fn handle_event(&self, device: &InputDevice, event: InputEvent) {
+        let evdev = EVDEVS.lock_save_irq().get(&device.id()).cloned();
+
+        if let Some(evdev) = evdev {
+            evdev.handle_event(event);
+        }
+    }
+}
+
+pub fn evdev_init(_bus: &mut PlatformBus, dm: &mut DriverManager) -> Result<()> {
+    dm.register_char_driver(ReservedMajors::Input as _, Arc::new(EvdevCharDev))?;
+
+    register_handler(Arc::new(EvdevHandler));
+
+    Ok(())
+}
+
+kernel_driver!(evdev_init);
@@ -0,0 +1,219 @@
+//! Translation of key events into terminal input.
+//!
+//! [`KeymapHandler`] follows the modifier keys across all keyboards and turns
+//! each key press into the bytes a terminal would send for it, using a US
+//! layout: printable characters, control codes for Ctrl and an ESC prefix for
+//! Alt, and VT100 escape sequences for the cursor and editing keys. The bytes
+//! are fed to the framebuffer console's tty.
+
+use super::{InputDevice, InputEvent, InputHandler, codes::*, register_handler};
+use crate::{
+    drivers::{DriverManager, display::fbcon::fbcon_push_input, init::PlatformBus},
+    kernel_driver,
+    sync::SpinLock,
+};
+use alloc::sync::Arc;
+use libkernel::error::Result;
+
+#[derive(Default)]
+struct Modifiers {
+    shift: u8,
+    ctrl: u8,
+    alt: u8,
+    caps_lock: bool,
+}
+
+/// Returns the unshifted and shifted characters for a key, if it produces
+/// one.
+fn key_chars(code: u16) -> Option<(u8, u8)> {
+    Some(match code {
+        KEY_1 => (b'1', b'!'),
+        KEY_2 => (b'2', b'@'),
+        KEY_3 => (b'3', b'#'),
+        KEY_4 => (b'4', b'$'),
+        KEY_5 => (b'5', b'%'),
+        KEY_6 => (b'6', b'^'),
+        KEY_7 => (b'7', b'&'),
+        KEY_8 => (b'8', b'*'),
+        KEY_9 => (b'9', b'('),
+        KEY_0 => (b'0', b')'),
+        KEY_MINUS => (b'-', b'_'),
+        KEY_EQUAL => (b'=', b'+'),
+        KEY_TAB => (b'\t', b'\t'),
+        KEY_Q => (b'q', b'Q'),
+        KEY_W => (b'w', b'W'),
+        KEY_E => (b'e', b'E'),
+        KEY_R => (b'r', b'R'),
+        KEY_T => (b't', b'T'),
+        KEY_Y => (b'y', b'Y'),
+        KEY_U => (b'u', b'U'),
+        KEY_I => (b'i', b'I'),
+        KEY_O => (b'o', b'O'),
+        KEY_P => (b'p', b'P'),
+        KEY_LEFTBRACE => (b'[', b'{'),
+        KEY_RIGHTBRACE => (b']', b'}'),
+        KEY_A => (b'a', b'A'),
+        KEY_S => (b's', b'S'),
+        KEY_D => (b'd', b'D'),
+        KEY_F => (b'f', b'F'),
+        KEY_G => (b'g', b'G'),
+        KEY_H => (b'h', b'H'),
+        KEY_J => (b'j', b'J'),
+        KEY_K => (b'k', b'K'),
+        KEY_L => (b'l', b'L'),
+        KEY_SEMICOLON => (b';', b':'),
+        KEY_APOSTROPHE => (b'\'', b'"'),
+        KEY_GRAVE => (b'`', b'~'),
+        KEY_BACKSLASH => (b'\\', b'|'),
+        KEY_Z => (b'z', b'Z'),
+        KEY_X => (b'x', b'X'),
+        KEY_C => (b'c', b'C'),
+        KEY_V => (b'v', b'V'),
+        KEY_B => (b'b', b'B'),
+        KEY_N => (b'n', b'N'),
+        KEY_M => (b'm', b'M'),
+        KEY_COMMA => (b',', b'<'),
+        KEY_DOT => (b'.', b'>'),
+        KEY_SLASH => (b'/', b'?'),
+        KEY_SPACE => (b' ', b' '),
+        KEY_KPASTERISK => (b'*', b'*'),
+        KEY_KPMINUS => (b'-', b'-'),
+        KEY_KPPLUS => (b'+', b'+'),
+        KEY_KPSLASH => (b'/', b'/'),
+        _ => return None,
+    })
+}
+
+/// Returns the escape sequence sent for a key which doesn't produce a
+/// character.
+fn key_sequence(code: u16) -> Option<&'static [u8]> {
+    Some(match code {
+        KEY_ENTER | KEY_KPENTER => b"\r",
+        KEY_BACKSPACE => b"\x7f",
+        KEY_ESC => b"\x1b",
+        KEY_UP => b"\x1b[A",
+        KEY_DOWN => b"\x1b[B",
+        KEY_RIGHT => b"\x1b[C",
+        KEY_LEFT => b"\x1b[D",
+        KEY_HOME => b"\x1b[H",
+        KEY_END => b"\x1b[F",
+        KEY_INSERT => b"\x1b[2~",
+        KEY_DELETE => b"\x1b[3~",
+        KEY_PAGEUP => b"\x1b[5~",
+        KEY_PAGEDOWN => b"\x1b[6~",
+        KEY_F1 => b"\x1bOP",
+        KEY_F2 => b"\x1bOQ",
+        KEY_F3 => b"\x1bOR",
+        KEY_F4 => b"\x1bOS",
+        KEY_F5 => b"\x1b[15~",
+        KEY_F6 => b"\x1b[17~",
+        KEY_F7 => b"\x1b[18~",
+        KEY_F8 => b"\x1b[19~",
+        KEY_F9 => b"\x1b[20~",
+        KEY_F10 => b"\x1b[21~",
+        KEY_F11 => b"\x1b[23~",
+        KEY_F12 => b"\x1b[24~",
+        _ => return None,
+    })
+}
+
+impl Modifiers {
+    /// Updates the modifier state for a key event, returning `true` if the
+    /// key was a modifier.
+    fn update(&mut self, code: u16, value: i32) -> bool {
+        let count = match code {
+            KEY_LEFTSHIFT | KEY_RIGHTSHIFT => &mut self.shift,
+            KEY_LEFTCTRL | KEY_RIGHTCTRL => &mut self.ctrl,
+            KEY_LEFTALT | KEY_RIGHTALT => &mut self.alt,
+            KEY_CAPSLOCK => {
+                if value == 1 {
+                    self.caps_lock = !self.caps_lock;
+                }
+                return true;
+            }
+            _ => return false,
+        };
+
+        // Count held keys, so releasing one shift leaves the other in effect.
+        match value {
+            0 => *count = count.saturating_sub(1),
+            1 => *count = count.saturating_add(1),
+            _ => {}
+        }
+
+        true
+    }
+
+    /// Returns the byte for a character key, given the modifiers held.
+    fn apply(&self, (plain, shifted): (u8, u8)) -> u8 {
+        let mut shift = self.shift > 0;
+
+        if plain.is_ascii_alphabetic() {
+            shift ^= self.caps_lock;
+        }
+
+        let c = if shift { shifted } else { plain };
+
+        if self.ctrl > 0 {
+            match c {
+                b'a'..=b'z' | b'A'..=b'Z' => c.to_ascii_uppercase() - b'@',
+                b'@' | b'2' | b' ' => 0x00,
+                b'[' | b'3' => 0x1b,
+                b'\\' | b'4' => 0x1c,
+                b']' | b'5' => 0x1d,
+                b'^' | b'6' => 0x1e,
+                b'_' | b'-' | b'/' => 0x1f,
+                b'?' | b'8' => 0x7f,
+                _ => c,
+            }
+        } else {
+            c
+        }
+    }
+}
+
+/// An input handler which types key presses into the console.
+pub struct KeymapHandler {
+    modifiers: SpinLock<Modifiers>,
+}
+
+impl InputHandler for KeymapHandler {
+    fn handle_event(&self, _device: &InputDevice, event: InputEvent) {
+        if event.kind != EV_KEY {
+            return;
+        }
+
+        let mut modifiers = self.modifiers.lock_save_irq();
+
+        if modifiers.update(event.code, event.value) || event.value == 0 {
+            return;
+        }
+
+        let alt = modifiers.alt > 0;
+
+        if let Some(chars) = key_chars(event.code) {
+            let c = modifiers.apply(chars);
+            drop(modifiers);
+
+            if alt {
+                fbcon_push_input(b"\x1b");
+            }
+
+            fbcon_push_input(&[c]);
+        } else if let Some(seq) = key_sequence(event.code) {
+            drop(modifiers);
+
+            fbcon_push_input(seq);
+        }
+    }
+}
+
+pub fn keymap_init(_bus: &mut PlatformBus, _dm: &mut DriverManager) -> Result<()> {
+    register_handler(Arc::new(KeymapHandler {
+        modifiers: SpinLock::new(Modifiers::default()),
+    }));
+
+    Ok(())
+}
+
+kernel_driver!(keymap_init);
@@ -0,0 +1,202 @@
+//! The input subsystem.
+//!
+//! Drivers for keyboards, mice and tablets register an [`InputDevice`] and
+//! report [`InputEvent`]s through it. Each event is passed to every registered
+//! [`InputHandler`]; for example, the [`keymap`] handler turns key presses
+//! into tty input.
+//!
+//! Events use the same types and codes as Linux's evdev, listed in [`codes`],
+//! and come in frames ended by a [`codes::SYN_REPORT`] event.
+
+use crate::sync::SpinLock;
+use alloc::{collections::btree_map::BTreeMap, string::String, sync::Arc, vec, vec::Vec};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub mod codes;
+pub mod evdev;
+pub mod keymap;
+pub mod pl050;
+pub mod ps2;
+pub mod virtio;
+
+/// A single input event, as reported by a device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InputEvent {
+    /// The event type, one of the `EV_*` constants.
+    pub kind: u16,
+    /// The key, button or axis, depending on `kind`.
+    pub code: u16,
+    /// For keys: 0 for release, 1 for press and 2 for autorepeat. For axes,
+    /// the position or relative motion.
+    pub value: i32,
+}
+
+impl InputEvent {
+    pub const fn new(kind: u16, code: u16, value: i32) -> Self {
+        Self { kind, code, value }
+    }
+
+    /// Returns the event which ends a frame.
+    pub const fn sync() -> Self {
+        Self::new(codes::EV_SYN, codes::SYN_REPORT, 0)
+    }
+}
+
+/// Identifies the hardware behind a device, as `struct input_id`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct InputId {
+    pub bustype: u16,
+    pub vendor: u16,
+    pub product: u16,
+    pub version: u16,
+}
+
+/// The range of an absolute axis, as `struct input_absinfo` less the current
+/// value.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AbsInfo {
+    pub minimum: i32,
+    pub maximum: i32,
+    pub fuzz: i32,
+    pub flat: i32,
+    pub resolution: i32,
+}
+
+/// The events a device can report.
+#[derive(Clone, Debug, Default)]
+pub struct Capabilities {
+    /// A bitmap of supported codes for each supported event type.
+    codes: BTreeMap<u16, Vec<u8>>,
+    /// A bitmap of `INPUT_PROP_*` properties.
+    pub props: Vec<u8>,
+    pub abs: BTreeMap<u16, AbsInfo>,
+}
+
+impl Capabilities {
+    /// Marks events of type `kind` with code `code` as supported.
+    pub fn set(&mut self, kind: u16, code: u16) {
+        let bitmap = self.codes.entry(kind).or_default();
+        let byte = code as usize / 8;
+
+        if bitmap.len() <= byte {
+            bitmap.resize(byte + 1, 0);
+        }
+
+        bitmap[byte] |= 1 << (code % 8);
+    }
+
+    /// Sets the bitmap of supported codes for events of type `kind`. Types
+    /// with no codes are left unsupported.
+    pub fn set_bitmap(&mut self, kind: u16, bitmap: &[u8]) {
+        if bitmap.iter().any(|&b| b != 0) {
+            self.codes.insert(kind, bitmap.to_vec());
+        }
+    }
+
+    /// Returns the bitmap of supported codes for events of type `kind`. For
+    /// `EV_SYN`, this is the bitmap of supported event types.
+    pub fn bitmap(&self, kind: u16) -> Vec<u8> {
+        if kind != codes::EV_SYN {
+            return self.codes.get(&kind).cloned().unwrap_or_default();
+        }
+
+        let mut types = vec![0; codes::EV_MAX as usize / 8 + 1];
+
+        for kind in self.codes.keys().copied().chain([codes::EV_SYN]) {
+            if let Some(byte) = types.get_mut(kind as usize / 8) {
+                *byte |= 1 << (kind % 8);
+            }
+        }
+
+        types
+    }
+}
+
+/// A registered source of input events.
+pub struct InputDevice {
+    id: usize,
+    name: String,
+    input_id: InputId,
+    caps: Capabilities,
+}
+
+impl InputDevice {
+    /// Returns the device's number, unique for the life of the system.
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn input_id(&self) -> InputId {
+        self.input_id
+    }
+
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.caps
+    }
+
+    /// Passes `event` to every handler.
+    ///
+    /// This may be called from an interrupt handler.
+    pub fn report(&self, event: InputEvent) {
+        for handler in HANDLERS.lock_save_irq().iter() {
+            handler.handle_event(self, event);
+        }
+    }
+
+    /// Reports each of `events`, followed by a [`InputEvent::sync`].
+    pub fn report_frame(&self, events: &[InputEvent]) {
+        events.iter().for_each(|&event| self.report(event));
+        self.report(InputEvent::sync());
+    }
+}
+
+/// A consumer of input events.
+pub trait InputHandler: Send + Sync {
+    /// Called once for every device, including those registered before the
+    /// handler.
+    fn connect(&self, _device: &Arc<InputDevice>) {}
+
+    /// Handles an event from `device`. This may be called from an interrupt
+    /// handler, so must not block.
+    fn handle_event(&self, device: &InputDevice, event: InputEvent);
+}
+
+static DEVICES: SpinLock<Vec<Arc<InputDevice>>> = SpinLock::new(Vec::new());
+static HANDLERS: SpinLock<Vec<Arc<dyn InputHandler>>> = SpinLock::new(Vec::new());
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Registers a new input device, able to report the events in `caps`.
+pub fn register_device(name: String, input_id: InputId, caps: Capabilities) -> Arc<InputDevice> {
+    let device = Arc::new(InputDevice {
+        id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        name,
+        input_id,
+        caps,
+    });
+
+    DEVICES.lock_save_irq().push(device.clone());
+
+    let handlers = HANDLERS.lock_save_irq().clone();
+
+    for handler in handlers {
+        handler.connect(&device);
+    }
+
+    device
+}
+
+/// Registers a handler for events from every input device.
+pub fn register_handler(handler: Arc<dyn InputHandler>) {
+    let devices = DEVICES.lock_save_irq().clone();
+
+    for device in devices.iter() {
+        handler.connect(device);
+    }
+
+    HANDLERS.lock_save_irq().push(handler);
+}
@@ -0,0 +1,268 @@
+//! ARM PrimeCell PS/2 keyboard/mouse interface (PL050 KMI).
+//!
+//! Each KMI drives a single PS/2 port. At probe time the attached device is
+//! asked to identify itself, and only keyboards are claimed; scancodes then
+//! arrive one byte per receive interrupt and are decoded by
+//! [`Set2Decoder`](super::ps2::Set2Decoder).
+
+use super::{
+    InputDevice, InputEvent, InputId,
+    codes::{BUS_I8042, EV_KEY},
+    ps2::{CMD_IDENTIFY, ID_KEYBOARD, RESP_ACK, Set2Decoder, set2_capabilities},
+    register_device,
+};
+use crate::{
+    arch::ArchImpl,
+    drivers::{DeviceDescriptor, Driver, DriverManager, init::PlatformBus, probe::DeviceMatchType},
+    interrupts::{ClaimedInterrupt, InterruptDescriptor, InterruptHandler},
+    kernel_driver,
+    sync::SpinLock,
+};
+use aarch64_cpu::registers::{Readable, Writeable};
+use alloc::{boxed::Box, string::ToString, sync::Arc};
+use core::hint::spin_loop;
+use libkernel::{
+    error::{ProbeError, Result},
+    memory::{
+        address::{PA, VA},
+        proc_vm::address_space::{KernAddressSpace, VirtualMemory},
+        region::PhysMemoryRegion,
+    },
+};
+use tock_registers::{
+    register_bitfields, register_structs,
+    registers::{ReadOnly, ReadWrite},
+};
+
+register_bitfields![u32,
+    /// Control Register
+    CR [
+        /// KMI Enable
+        KMIEN OFFSET(2) NUMBITS(1) [
+            Disable = 0,
+            Enable = 1
+        ],
+        /// Receive Interrupt Enable
+        KMIRXINTREN OFFSET(4) NUMBITS(1) [
+            Disable = 0,
+            Enable = 1
+        ]
+    ],
+
+    /// Status Register
+    STAT [
+        /// Receive Register Full
+        RXFULL OFFSET(4) NUMBITS(1) [
+            Empty = 0,
+            Full = 1
+        ],
+        /// Transmit Register Empty
+        TXEMPTY OFFSET(6) NUMBITS(1) [
+            Full = 0,
+            Empty = 1
+        ]
+    ],
+
+    /// Data Register
+    DATA [
+        /// Transmit/Receive Data
+        DATA OFFSET(0) NUMBITS(8) []
+    ]
+];
+
+register_structs! {
+    /// ARM PL050 KMI Register Bank
+    Pl050RegBank {
+        (0x00 => cr: ReadWrite<u32, CR::Register>),
+        (0x04 => stat: ReadOnly<u32, STAT::Register>),
+        (0x08 => data: ReadWrite<u32, DATA::Register>),
+        (0x0C => clkdiv: ReadWrite<u32>),
+        (0x10 => ir: ReadOnly<u32>),
+        (0x14 => @END),
+    }
+}
+
+/// How long to poll for the device during identification. PS/2 clocks run
+/// at 10-16kHz, so a byte takes around a millisecond.
+const POLL_SPINS: usize = 1_000_000;
+
+struct Pl050Port {
+    regs: &'static mut Pl050RegBank,
+}
+
+unsafe impl Send for Pl050Port {}
+unsafe impl Sync for Pl050Port {}
+
+impl Pl050Port {
+    fn new(addr: VA) -> Self {
+        let regs = unsafe { &mut *(addr.as_ptr_mut() as *mut Pl050RegBank) };
+
+        // Enable the port with interrupts off while we identify the device.
+        // The clock divisor is left as firmware configured it.
+        regs.cr.write(CR::KMIEN::Enable);
+
+        Self { regs }
+    }
+
+    fn read_byte(&mut self) -> Option<u8> {
+        self.regs
+            .stat
+            .is_set(STAT::RXFULL)
+            .then(|| self.regs.data.read(DATA::DATA) as u8)
+    }
+
+    fn poll_byte(&mut self) -> Option<u8> {
+        (0..POLL_SPINS).find_map(|_| {
+            spin_loop();
+            self.read_byte()
+        })
+    }
+
+    fn send_byte(&mut self, byte: u8) -> Option<()> {
+        (0..POLL_SPINS)
+            .find(|_| {
+                spin_loop();
+                self.regs.stat.is_set(STAT::TXEMPTY)
+            })
+            .map(|_| self.regs.data.write(DATA::DATA.val(byte as u32)))
+    }
+
+    /// Returns the two-byte ID of the keyboard attached to the port, or
+    /// `None` if there is no keyboard.
+    fn identify_keyboard(&mut self) -> Option<u16> {
+        // Discard anything left over, e.g. the keyboard's self-test result.
+        while self.read_byte().is_some() {}
+
+        self.send_byte(CMD_IDENTIFY)?;
+
+        if self.poll_byte()? != RESP_ACK || self.poll_byte()? != ID_KEYBOARD {
+            return None;
+        }
+
+        let id = u16::from_be_bytes([ID_KEYBOARD, self.poll_byte()?]);
+
+        // Consume anything else the device sends.
+        while self.poll_byte().is_some() {}
+
+        Some(id)
+    }
+
+    fn enable_rx_interrupt(&mut self) {
+        self.regs
+            .cr
+            .write(CR::KMIEN::Enable + CR::KMIRXINTREN::Enable);
+    }
+}
+
+pub struct Pl050Keyboard {
+    port: SpinLock<Pl050Port>,
+    decoder: SpinLock<Set2Decoder>,
+    input: Arc<InputDevice>,
+    name: &'static str,
+    _interrupt: ClaimedInterrupt,
+}
+
+impl Driver for Pl050Keyboard {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+impl InterruptHandler for Pl050Keyboard {
+    fn handle_irq(&self, _desc: InterruptDescriptor) {
+        // Each interrupt normally brings a single byte, but guard against
+        // a port which never empties.
+        const MAX_DRAIN_ITERS: usize = 16;
+
+        for _ in 0..MAX_DRAIN_ITERS {
+            let Some(byte) = self.port.lock_save_irq().read_byte() else {
+                break;
+            };
+
+            let key = self.decoder.lock_save_irq().feed(byte);
+
+            if let Some((code, value)) = key {
+                self.input
+                    .report_frame(&[InputEvent::new(EV_KEY, code, value)]);
+            }
+        }
+    }
+}
+
+pub fn pl050_probe(dm: &mut DriverManager, d: DeviceDescriptor) -> Result<Arc<dyn Driver>> {
+    match d {
+        DeviceDescriptor::Fdt(fdt_node, _flags) => {
+            use ProbeError::*;
+
+            let mut regs = fdt_node.reg().ok_or(NoReg)?;
+            let region = regs.next().ok_or(NoReg)?;
+            let size = region.size.ok_or(NoRegSize)?;
+
+            let mut interrupts = fdt_node
+                .interrupts()
+                .ok_or(NoInterrupts)?
+                .next()
+                .ok_or(NoInterrupts)?;
+
+            let interrupt_node = fdt_node.interrupt_parent().ok_or(NoParentInterrupt)?.node;
+
+            let interrupt_manager = dm
+                .find_by_name(interrupt_node.name)
+                .ok_or(Deferred)?
+                .as_interrupt_manager()
+                .ok_or(NotInterruptController)?;
+
+            let interrupt_config = interrupt_manager.parse_fdt_interrupt_regs(&mut interrupts)?;
+
+            let mem =
+                ArchImpl::kern_address_space()
+                    .lock_save_irq()
+                    .map_mmio(PhysMemoryRegion::new(
+                        PA::from_value(region.address as usize),
+                        size,
+                    ))?;
+
+            let mut port = Pl050Port::new(mem);
+
+            // Mice are left for a future mouse driver.
+            let Some(keyboard_id) = port.identify_keyboard() else {
+                return Err(NoMatch.into());
+            };
+
+            let dev = interrupt_manager.claim_interrupt(interrupt_config, |claimed_interrupt| {
+                Pl050Keyboard {
+                    port: SpinLock::new(port),
+                    decoder: SpinLock::new(Set2Decoder::new()),
+                    input: register_device(
+                        "PL050 PS/2 Keyboard".to_string(),
+                        InputId {
+                            bustype: BUS_I8042,
+                            vendor: 0x0001,
+                            // The scancode set.
+                            product: 0x0002,
+                            version: keyboard_id,
+                        },
+                        set2_capabilities(),
+                    ),
+                    name: fdt_node.name,
+                    _interrupt: claimed_interrupt,
+                }
+            })?;
+
+            dev.port.lock_save_irq().enable_rx_interrupt();
+
+            Ok(dev)
+        }
+    }
+}
+
+pub fn pl050_init(bus: &mut PlatformBus, _dm: &mut DriverManager) -> Result<()> {
+    bus.register_platform_driver(
+        DeviceMatchType::FdtCompatible("arm,pl050"),
+        Box::new(pl050_probe),
+    );
+
+    Ok(())
+}
+
+kernel_driver!(pl050_init);
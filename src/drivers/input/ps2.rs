@@ -0,0 +1,217 @@
+//! The PS/2 keyboard protocol.
+//!
+//! A PS/2 keyboard sends a stream of scancodes, which [`Set2Decoder`] turns
+//! into key events. The decoder is independent of how the bytes arrive, so it
+//! serves any PS/2 controller, be it an i8042 or an ARM PL050.
+
+use super::{Capabilities, codes::*};
+
+/// Host-to-keyboard commands and keyboard responses.
+pub const CMD_IDENTIFY: u8 = 0xf2;
+pub const RESP_ACK: u8 = 0xfa;
+/// The first byte of a keyboard's identify response; mice send `0x00`.
+pub const ID_KEYBOARD: u8 = 0xab;
+
+const PREFIX_EXTENDED: u8 = 0xe0;
+const PREFIX_PAUSE: u8 = 0xe1;
+const PREFIX_RELEASE: u8 = 0xf0;
+/// The Pause key sends `E1 14 77 E1 F0 14 F0 77`, and nothing on release.
+const PAUSE_LEN: u8 = 8;
+
+/// Decodes scancode set 2, the set every PS/2 keyboard speaks by default.
+#[derive(Default)]
+pub struct Set2Decoder {
+    extended: bool,
+    release: bool,
+    /// Bytes of a Pause sequence still to come.
+    skip: u8,
+    /// Keys currently held, to tell autorepeat from a fresh press.
+    held: [u64; 4],
+}
+
+impl Set2Decoder {
+    pub const fn new() -> Self {
+        Self {
+            extended: false,
+            release: false,
+            skip: 0,
+            held: [0; 4],
+        }
+    }
+
+    /// Feeds a byte from the keyboard, returning the key code and value (as
+    /// for an `EV_KEY` event) once a complete scancode has been received.
+    pub fn feed(&mut self, byte: u8) -> Option<(u16, i32)> {
+        if self.skip > 0 {
+            self.skip -= 1;
+            return None;
+        }
+
+        match byte {
+            PREFIX_EXTENDED => {
+                self.extended = true;
+                return None;
+            }
+            PREFIX_RELEASE => {
+                self.release = true;
+                return None;
+            }
+            PREFIX_PAUSE => {
+                self.skip = PAUSE_LEN - 1;
+                return None;
+            }
+            _ => {}
+        }
+
+        let extended = core::mem::take(&mut self.extended);
+        let release = core::mem::take(&mut self.release);
+        let code = translate(byte, extended)?;
+
+        let (word, bit) = (code as usize / 64, 1 << (code % 64));
+        let was_held = self.held[word] & bit != 0;
+
+        let value = if release {
+            self.held[word] &= !bit;
+            0
+        } else {
+            self.held[word] |= bit;
+            if was_held { 2 } else { 1 }
+        };
+
+        Some((code, value))
+    }
+}
+
+/// Returns the keys a set 2 keyboard may report.
+pub fn set2_capabilities() -> Capabilities {
+    let mut caps = Capabilities::default();
+
+    for scancode in 0..=u8::MAX {
+        for extended in [false, true] {
+            if let Some(code) = translate(scancode, extended) {
+                caps.set(EV_KEY, code);
+            }
+        }
+    }
+
+    caps
+}
+
+/// Maps a set 2 scancode to a key code.
+fn translate(scancode: u8, extended: bool) -> Option<u16> {
+    let code = if extended {
+        match scancode {
+            0x11 => KEY_RIGHTALT,
+            0x14 => KEY_RIGHTCTRL,
+            0x1f => KEY_LEFTMETA,
+            0x27 => KEY_RIGHTMETA,
+            0x4a => KEY_KPSLASH,
+            0x5a => KEY_KPENTER,
+            0x69 => KEY_END,
+            0x6b => KEY_LEFT,
+            0x6c => KEY_HOME,
+            0x70 => KEY_INSERT,
+            0x71 => KEY_DELETE,
+            0x72 => KEY_DOWN,
+            0x74 => KEY_RIGHT,
+            0x75 => KEY_UP,
+            0x7a => KEY_PAGEDOWN,
+            0x7c => KEY_SYSRQ,
+            0x7d => KEY_PAGEUP,
+            // Includes the fake shifts around Print Screen, `E0 12`.
+            _ => return None,
+        }
+    } else {
+        match scancode {
+            0x01 => KEY_F9,
+            0x03 => KEY_F5,
+            0x04 => KEY_F3,
+            0x05 => KEY_F1,
+            0x06 => KEY_F2,
+            0x07 => KEY_F12,
+            0x09 => KEY_F10,
+            0x0a => KEY_F8,
+            0x0b => KEY_F6,
+            0x0c => KEY_F4,
+            0x0d => KEY_TAB,
+            0x0e => KEY_GRAVE,
+            0x11 => KEY_LEFTALT,
+            0x12 => KEY_LEFTSHIFT,
+            0x14 => KEY_LEFTCTRL,
+            0x15 => KEY_Q,
+            0x16 => KEY_1,
+            0x1a => KEY_Z,
+            0x1b => KEY_S,
+            0x1c => KEY_A,
+            0x1d => KEY_W,
+            0x1e => KEY_2,
+            0x21 => KEY_C,
+            0x22 => KEY_X,
+            0x23 => KEY_D,
+            0x24 => KEY_E,
+            0x25 => KEY_4,
+            0x26 => KEY_3,
+            0x29 => KEY_SPACE,
+            0x2a => KEY_V,
+            0x2b => KEY_F,
+            0x2c => KEY_T,
+            0x2d => KEY_R,
+            0x2e => KEY_5,
+            0x31 => KEY_N,
+            0x32 => KEY_B,
+            0x33 => KEY_H,
+            0x34 => KEY_G,
+            0x35 => KEY_Y,
+            0x36 => KEY_6,
+            0x3a => KEY_M,
+            0x3b => KEY_J,
+            0x3c => KEY_U,
+            0x3d => KEY_7,
+            0x3e => KEY_8,
+            0x41 => KEY_COMMA,
+            0x42 => KEY_K,
+            0x43 => KEY_I,
+            0x44 => KEY_O,
+            0x45 => KEY_0,
+            0x46 => KEY_9,
+            0x49 => KEY_DOT,
+            0x4a => KEY_SLASH,
+            0x4b => KEY_L,
+            0x4c => KEY_SEMICOLON,
+            0x4d => KEY_P,
+            0x4e => KEY_MINUS,
+            0x52 => KEY_APOSTROPHE,
+            0x54 => KEY_LEFTBRACE,
+            0x55 => KEY_EQUAL,
+            0x58 => KEY_CAPSLOCK,
+            0x59 => KEY_RIGHTSHIFT,
+            0x5a => KEY_ENTER,
+            0x5b => KEY_RIGHTBRACE,
+            0x5d => KEY_BACKSLASH,
+            0x66 => KEY_BACKSPACE,
+            0x69 => KEY_KP1,
+            0x6b => KEY_KP4,
+            0x6c => KEY_KP7,
+            0x70 => KEY_KP0,
+            0x71 => KEY_KPDOT,
+            0x72 => KEY_KP2,
+            0x73 => KEY_KP5,
+            0x74 => KEY_KP6,
+            0x75 => KEY_KP8,
+            0x76 => KEY_ESC,
+            0x77 => KEY_NUMLOCK,
+            0x78 => KEY_F11,
+            0x79 => KEY_KPPLUS,
+            0x7a => KEY_KP3,
+            0x7b => KEY_KPMINUS,
+            0x7c => KEY_KPASTERISK,
+            0x7d => KEY_KP9,
+            0x7e => KEY_SCROLLLOCK,
+            0x83 => KEY_F7,
+            // Acknowledgements, self-test results and errors.
+            _ => return None,
+        }
+    };
+
+    Some(code)
+}
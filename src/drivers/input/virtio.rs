@@ -0,0 +1,211 @@
+//! VirtIO input devices: keyboards, mice and tablets.
+//!
+//! virtio-input already speaks evdev, so the device's name, IDs and
+//! capabilities are read from its config space and its events are reported
+//! unchanged, `SYN_REPORT`s included.
+
+use super::{AbsInfo, Capabilities, InputDevice, InputEvent, InputId, codes::*, register_device};
+use crate::drivers::virtio_hal::{VirtioHal, attach_fdt_node};
+use crate::sync::SpinLock;
+use crate::{
+    arch::ArchImpl,
+    drivers::{
+        Driver, DriverManager,
+        init::PlatformBus,
+        probe::{DeviceDescriptor, DeviceMatchType},
+    },
+    interrupts::{ClaimedInterrupt, InterruptDescriptor, InterruptHandler},
+    kernel_driver,
+};
+use alloc::{boxed::Box, sync::Arc};
+use core::ptr::NonNull;
+use libkernel::memory::proc_vm::address_space::{KernAddressSpace, VirtualMemory};
+use libkernel::{
+    error::{KernelError, ProbeError, Result},
+    memory::{
+        address::{PA, VA},
+        region::PhysMemoryRegion,
+    },
+};
+use log::info;
+use virtio_drivers::{
+    device::input::VirtIOInput,
+    transport::{
+        DeviceType, Transport,
+        mmio::{MmioTransport, VirtIOHeader},
+    },
+};
+
+type VirtioInputDev = VirtIOInput<VirtioHal, MmioTransport<'static>>;
+
+pub struct VirtioInputDriver {
+    fdt_name: &'static str,
+    input: SpinLock<VirtioInputDev>,
+    device: Arc<InputDevice>,
+    _interrupt: ClaimedInterrupt,
+}
+
+impl Driver for VirtioInputDriver {
+    fn name(&self) -> &'static str {
+        self.fdt_name
+    }
+}
+
+impl InterruptHandler for VirtioInputDriver {
+    fn handle_irq(&self, _desc: InterruptDescriptor) {
+        let mut input = self.input.lock_save_irq();
+
+        let _ = input.ack_interrupt();
+
+        while let Some(event) = input.pop_pending_event() {
+            self.device.report(InputEvent::new(
+                event.event_type,
+                event.code,
+                event.value as i32,
+            ));
+        }
+    }
+}
+
+/// Reads the events the device supports from its config space.
+fn read_capabilities(input: &mut VirtioInputDev) -> Result<Capabilities> {
+    let err = |_| KernelError::Other("virtio-input config read failed");
+    let mut caps = Capabilities::default();
+
+    for kind in EV_KEY..=EV_MAX {
+        caps.set_bitmap(kind, &input.ev_bits(kind as u8).map_err(err)?);
+    }
+
+    caps.props = input.prop_bits().map_err(err)?.to_vec();
+
+    let axes = caps.bitmap(EV_ABS);
+    let has_axis = |axis: u16| {
+        axes.get(axis as usize / 8)
+            .is_some_and(|byte| byte & (1 << (axis % 8)) != 0)
+    };
+
+    for axis in (0..=ABS_MAX).filter(|&axis| has_axis(axis)) {
+        let info = input.abs_info(axis as u8).map_err(err)?;
+
+        caps.abs.insert(
+            axis,
+            AbsInfo {
+                minimum: info.min as i32,
+                maximum: info.max as i32,
+                fuzz: info.fuzz as i32,
+                flat: info.flat as i32,
+                resolution: info.res as i32,
+            },
+        );
+    }
+
+    Ok(caps)
+}
+
+fn virtio_input_probe(dm: &mut DriverManager, d: DeviceDescriptor) -> Result<Arc<dyn Driver>> {
+    match d {
+        DeviceDescriptor::Fdt(fdt_node, _flags) => {
+            let region = fdt_node
+                .reg()
+                .ok_or(ProbeError::NoReg)?
+                .next()
+                .ok_or(ProbeError::NoReg)?;
+
+            let size = region.size.ok_or(ProbeError::NoRegSize)?;
+
+            let mapped: VA =
+                ArchImpl::kern_address_space()
+                    .lock_save_irq()
+                    .map_mmio(PhysMemoryRegion::new(
+                        PA::from_value(region.address as usize),
+                        size,
+                    ))?;
+
+            let header = NonNull::new(mapped.value() as *mut VirtIOHeader)
+                .ok_or(KernelError::InvalidValue)?;
+
+            let transport = unsafe {
+                match MmioTransport::new(header, size) {
+                    Ok(t) => t,
+                    Err(_) => return Err(KernelError::Probe(ProbeError::NoMatch)),
+                }
+            };
+
+            if !matches!(transport.device_type(), DeviceType::Input) {
+                return Err(KernelError::Probe(ProbeError::NoMatch));
+            }
+
+            attach_fdt_node(&fdt_node)?;
+
+            let mut interrupts = fdt_node
+                .interrupts()
+                .ok_or(ProbeError::NoInterrupts)?
+                .next()
+                .ok_or(ProbeError::NoInterrupts)?;
+
+            let interrupt_node = fdt_node
+                .interrupt_parent()
+                .ok_or(ProbeError::NoParentInterrupt)?
+                .node;
+
+            let interrupt_manager = dm
+                .find_by_name(interrupt_node.name)
+                .ok_or(ProbeError::Deferred)?
+                .as_interrupt_manager()
+                .ok_or(ProbeError::NotInterruptController)?;
+
+            let interrupt_config = interrupt_manager.parse_fdt_interrupt_regs(&mut interrupts)?;
+
+            let mut input = VirtioInputDev::new(transport)
+                .map_err(|_| KernelError::Other("virtio-input init failed"))?;
+
+            let name = input
+                .name()
+                .map_err(|_| KernelError::Other("virtio-input config read failed"))?;
+            let ids = input
+                .ids()
+                .map_err(|_| KernelError::Other("virtio-input config read failed"))?;
+            let caps = read_capabilities(&mut input)?;
+
+            info!("virtio-input found: {name} (node {})", fdt_node.name);
+
+            let device = register_device(
+                name,
+                InputId {
+                    bustype: ids.bustype,
+                    vendor: ids.vendor,
+                    product: ids.product,
+                    version: ids.version,
+                },
+                caps,
+            );
+
+            let driver = interrupt_manager.claim_interrupt(interrupt_config, |claimed| {
+                VirtioInputDriver {
+                    fdt_name: fdt_node.name,
+                    input: SpinLock::new(input),
+                    device,
+                    _interrupt: claimed,
+                }
+            })?;
+
+            Ok(driver)
+        }
+    }
+}
+
+pub fn virtio_input_init(bus: &mut PlatformBus, _dm: &mut DriverManager) -> Result<()> {
+    bus.register_platform_driver(
+        DeviceMatchType::FdtCompatible("virtio,mmio"),
+        Box::new(virtio_input_probe),
+    );
+
+    bus.register_platform_driver(
+        DeviceMatchType::FdtCompatible("virtio-mmio"),
+        Box::new(virtio_input_probe),
+    );
+
+    Ok(())
+}
+
+kernel_driver!(virtio_input_init);
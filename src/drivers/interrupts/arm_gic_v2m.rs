@@ -0,0 +1,147 @@
+//! GICv2m MSI frames.
+//!
+//! A GICv2 has no notion of MSIs, so a v2m frame sits alongside it: a write
+//! of an SPI's interrupt ID to the frame's doorbell raises that SPI. Each
+//! frame owns a fixed range of SPIs, which are handed out one per MSI.
+
+use alloc::{boxed::Box, collections::btree_set::BTreeSet, sync::Arc};
+use libkernel::{
+    error::{KernelError, ProbeError, Result},
+    memory::{
+        address::{PA, VA},
+        proc_vm::address_space::{KernAddressSpace, VirtualMemory},
+        region::PhysMemoryRegion,
+    },
+};
+use log::info;
+
+use crate::{
+    arch::ArchImpl,
+    drivers::{
+        Driver, DriverManager,
+        init::PlatformBus,
+        probe::{DeviceDescriptor, DeviceMatchType},
+    },
+    interrupts::{
+        InterruptConfig, InterruptDescriptor, TriggerMode, get_interrupt_root,
+        msi::{MsiController, MsiMessage, MsiVector, set_msi_controller},
+    },
+    kernel_driver,
+    sync::SpinLock,
+};
+
+const MSI_TYPER: usize = 0x008;
+const MSI_SETSPI_NS: usize = 0x040;
+
+/// The first interrupt ID of an SPI.
+const SPI_BASE: u32 = 32;
+
+struct GicV2m {
+    name: &'static str,
+    doorbell: u64,
+    free: SpinLock<BTreeSet<u32>>,
+}
+
+impl Driver for GicV2m {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+impl MsiController for GicV2m {
+    fn alloc_msi(&self) -> Result<MsiVector> {
+        let intid = self
+            .free
+            .lock_save_irq()
+            .pop_first()
+            .ok_or(KernelError::NoMemory)?;
+
+        Ok(MsiVector {
+            config: InterruptConfig {
+                descriptor: InterruptDescriptor::Spi((intid - SPI_BASE) as usize),
+                trigger: TriggerMode::EdgeRising,
+            },
+            message: MsiMessage {
+                address: self.doorbell,
+                data: intid,
+            },
+        })
+    }
+
+    fn free_msi(&self, vector: MsiVector) {
+        self.free.lock_save_irq().insert(vector.message.data);
+    }
+}
+
+pub fn gic_v2m_probe(_dm: &mut DriverManager, d: DeviceDescriptor) -> Result<Arc<dyn Driver>> {
+    match d {
+        DeviceDescriptor::Fdt(fdt_node, _) => {
+            // The SPIs are claimed from the GIC the frame belongs to.
+            if get_interrupt_root().is_none() {
+                return Err(KernelError::Probe(ProbeError::Deferred));
+            }
+
+            let region = fdt_node
+                .reg()
+                .ok_or(ProbeError::NoReg)?
+                .next()
+                .ok_or(ProbeError::NoReg)?;
+
+            let regs: VA =
+                ArchImpl::kern_address_space()
+                    .lock_save_irq()
+                    .map_mmio(PhysMemoryRegion::new(
+                        PA::from_value(region.address as usize),
+                        region.size.ok_or(ProbeError::NoRegSize)?,
+                    ))?;
+
+            // SAFETY: The frame was mapped as device memory above.
+            let typer = unsafe {
+                core::ptr::read_volatile(regs.add_bytes(MSI_TYPER).as_ptr() as *const u32)
+            };
+
+            // Some frames report the wrong range, so firmware may override it.
+            let base = fdt_node
+                .find_property("arm,msi-base-spi")
+                .map(|p| p.u32())
+                .unwrap_or((typer >> 16) & 0x3ff);
+
+            let count = fdt_node
+                .find_property("arm,msi-num-spis")
+                .map(|p| p.u32())
+                .unwrap_or(typer & 0x3ff);
+
+            if base < SPI_BASE || count == 0 {
+                return Err(KernelError::InvalidValue);
+            }
+
+            info!(
+                "GICv2m frame {}: SPIs {}-{}",
+                fdt_node.name,
+                base,
+                base + count - 1
+            );
+
+            let dev = Arc::new(GicV2m {
+                name: fdt_node.name,
+                doorbell: region.address + MSI_SETSPI_NS as u64,
+                free: SpinLock::new((base..base + count).collect()),
+            });
+
+            set_msi_controller(fdt_node.name, dev.clone());
+
+            Ok(dev)
+        }
+    }
+}
+
+pub fn arm_gicv2m_init(bus: &mut PlatformBus, _dm: &mut DriverManager) -> Result<()> {
+    bus.register_platform_driver(
+        DeviceMatchType::FdtCompatible("arm,gic-v2m-frame"),
+        Box::new(gic_v2m_probe),
+    );
+
+    Ok(())
+}
+
+kernel_driver!(arm_gicv2m_init);
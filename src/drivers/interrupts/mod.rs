@@ -1,2 +1,3 @@
 pub mod arm_gic_v2;
+pub mod arm_gic_v2m;
 pub mod arm_gic_v3;
@@ -0,0 +1,689 @@
+//! Driver for the Arm System MMU v3 (SMMUv3).
+//!
+//! The SMMU is configured with a linear stream table. Streams default to
+//! bypass, so devices that aren't described as sitting behind the SMMU keep
+//! working with physical addresses. When a device is attached, its stream is
+//! given its own stage-1 translation domain, using the same long-descriptor
+//! page table format as the CPU. Once the last user of a domain drops it, the
+//! stream returns to bypass and the domain's page tables are freed.
+
+use super::{Iommu, IommuDomain, dma_perms, register_iommu};
+use crate::{
+    arch::ArchImpl,
+    drivers::{
+        Driver, DriverManager,
+        init::PlatformBus,
+        probe::{DeviceDescriptor, DeviceMatchType},
+    },
+    kernel_driver,
+    memory::{
+        PageOffsetTranslator,
+        dma::{DmaAddr, DmaCoherent, DmaDirection, dma_alloc_coherent},
+        page::ClaimedPage,
+    },
+    sync::SpinLock,
+};
+use alloc::{
+    boxed::Box,
+    collections::btree_map::BTreeMap,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::{
+    arch::asm,
+    hint::spin_loop,
+    ptr,
+    sync::atomic::{AtomicU16, Ordering},
+};
+use libkernel::{
+    arch::arm64::memory::{
+        pg_descriptors::{L3Descriptor, MemoryType},
+        pg_tables::{L0Table, L1Table, L2Table, L3Table, MapAttributes, MappingContext, map_range},
+        pg_tear_down::tear_down_address_space,
+        pg_walk::walk_and_modify_region,
+    },
+    error::{KernelError, ProbeError, Result},
+    memory::{
+        PAGE_SIZE,
+        address::{PA, TPA, TVA, VA},
+        iommu::{IoPageTable, IovaDomain},
+        paging::{
+            NullTlbInvalidator, PageAllocator, PageTableEntry, PageTableMapper, PgTable,
+            PgTableArray, TableMapper,
+            permissions::PtePermissions,
+            tear_down::{EntryKind, TeardownAction},
+            walk::WalkContext,
+        },
+        proc_vm::address_space::{KernAddressSpace, VirtualMemory},
+        region::{PhysMemoryRegion, VirtMemoryRegion},
+    },
+};
+use log::{info, warn};
+use tock_registers::{
+    interfaces::*,
+    register_structs,
+    registers::{ReadOnly, ReadWrite},
+};
+
+register_structs! {
+    /// SMMUv3 page 0 registers.
+    #[allow(non_snake_case)]
+    SmmuRegs {
+        (0x0000 => IDR0: ReadOnly<u32>),
+        (0x0004 => IDR1: ReadOnly<u32>),
+        (0x0008 => _reserved_0),
+        (0x0014 => IDR5: ReadOnly<u32>),
+        (0x0018 => _reserved_1),
+        (0x0020 => CR0: ReadWrite<u32>),
+        (0x0024 => CR0ACK: ReadOnly<u32>),
+        (0x0028 => CR1: ReadWrite<u32>),
+        (0x002c => CR2: ReadWrite<u32>),
+        (0x0030 => _reserved_2),
+        (0x0080 => STRTAB_BASE: ReadWrite<u64>),
+        (0x0088 => STRTAB_BASE_CFG: ReadWrite<u32>),
+        (0x008c => _reserved_3),
+        (0x0090 => CMDQ_BASE: ReadWrite<u64>),
+        (0x0098 => CMDQ_PROD: ReadWrite<u32>),
+        (0x009c => CMDQ_CONS: ReadWrite<u32>),
+        (0x00a0 => @END),
+    }
+}
+
+const IDR0_S1P: u32 = 1 << 1;
+const IDR0_TTF_AARCH64: u32 = 1 << 3;
+const IDR1_SIDSIZE_MASK: u32 = 0x3f;
+const IDR1_CMDQS_SHIFT: u32 = 21;
+const IDR5_GRAN4K: u32 = 1 << 4;
+const IDR5_OAS_MASK: u32 = 0x7;
+
+const CR0_SMMUEN: u32 = 1 << 0;
+const CR0_CMDQEN: u32 = 1 << 3;
+
+/// Write-back cacheable, inner shareable table and queue accesses.
+const CR1_WB_ISH: u32 = (3 << 10) | (1 << 8) | (1 << 6) | (3 << 4) | (1 << 2) | 1;
+const CR2_RECINVSID: u32 = 1 << 1;
+
+const BASE_RA: u64 = 1 << 62;
+const BASE_ADDR_MASK: u64 = ((1 << 52) - 1) & !0x1f;
+
+const CMDQ_CONS_ERR_SHIFT: u32 = 24;
+const CMDQ_CONS_ERR_MASK: u32 = 0x7f;
+
+const CMD_CFGI_STE: u64 = 0x03;
+const CMD_CFGI_ALL: u64 = 0x04;
+const CMD_TLBI_NH_ASID: u64 = 0x11;
+const CMD_TLBI_NSNH_ALL: u64 = 0x30;
+const CMD_SYNC: u64 = 0x46;
+
+const STE_DWORDS: usize = 8;
+const STE_0_V: u64 = 1;
+const STE_0_CFG_BYPASS: u64 = 0b100 << 1;
+const STE_0_CFG_S1_TRANS: u64 = 0b101 << 1;
+const STE_1_S1_WB_ISH: u64 = (1 << 2) | (1 << 4) | (3 << 6);
+const STE_1_SHCFG_INCOMING: u64 = 1 << 44;
+
+const CD_DWORDS: usize = 8;
+const CD_0_T0SZ_48BIT: u64 = 16;
+const CD_0_WB_ISH: u64 = (1 << 8) | (1 << 10) | (3 << 12);
+const CD_0_EPD1: u64 = 1 << 30;
+const CD_0_V: u64 = 1 << 31;
+const CD_0_IPS_SHIFT: u64 = 32;
+const CD_0_AA64: u64 = 1 << 41;
+const CD_0_R: u64 = 1 << 45;
+const CD_0_A: u64 = 1 << 46;
+const CD_0_ASET: u64 = 1 << 47;
+const CD_0_ASID_SHIFT: u64 = 48;
+
+/// MAIR value matching the attribute indices used by [`MemoryType`].
+const MAIR: u64 = 0x00ff;
+
+/// The largest stream ID width supported with a linear stream table.
+const MAX_SID_BITS: u32 = 8;
+const MAX_CMDQ_LOG2: u32 = 8;
+
+/// IOVAs handed out to devices, kept below 4 GiB for 32-bit DMA masters.
+const IOVA_APERTURE_START: usize = 0x1000_0000;
+const IOVA_APERTURE_SIZE: usize = 0x1_0000_0000 - IOVA_APERTURE_START;
+
+const POLL_LIMIT: usize = 1_000_000;
+
+fn poll(mut done: impl FnMut() -> bool, what: &'static str) -> Result<()> {
+    for _ in 0..POLL_LIMIT {
+        if done() {
+            return Ok(());
+        }
+
+        spin_loop();
+    }
+
+    Err(KernelError::Other(what))
+}
+
+/// Ensures table and queue updates are visible to the SMMU before it's told
+/// about them.
+fn wmb() {
+    unsafe { asm!("dsb ishst", options(nostack, preserves_flags)) };
+}
+
+/// Writes `dwords` to `buf` at `offset`, finishing with the first dword so
+/// that a valid bit held there is set last.
+fn write_dwords(buf: &DmaCoherent, offset: usize, dwords: &[u64]) {
+    let base: *mut u64 = buf.va().add_bytes(offset).as_ptr_mut().cast();
+
+    for (i, dword) in dwords.iter().enumerate().skip(1) {
+        unsafe { ptr::write_volatile(base.add(i), *dword) };
+    }
+
+    wmb();
+
+    unsafe { ptr::write_volatile(base, dwords[0]) };
+}
+
+/// The SMMU's registers, stream table and command queue.
+struct SmmuHw {
+    regs: &'static mut SmmuRegs,
+    strtab: DmaCoherent,
+    cmdq: DmaCoherent,
+    cmdq_log2: u32,
+    cmdq_prod: u32,
+}
+
+unsafe impl Send for SmmuHw {}
+
+impl SmmuHw {
+    fn write_cr0(&mut self, val: u32) -> Result<()> {
+        self.regs.CR0.set(val);
+
+        let regs = &*self.regs;
+        poll(|| regs.CR0ACK.get() == val, "SMMU CR0 update timed out")
+    }
+
+    /// Submits `cmds` to the command queue, followed by a sync, and waits for
+    /// them to complete.
+    fn submit(&mut self, cmds: &[[u64; 2]]) -> Result<()> {
+        // Index bits plus the wrap bit.
+        let ptr_mask = (2 << self.cmdq_log2) - 1;
+        let idx_mask = (1 << self.cmdq_log2) - 1;
+
+        // We always wait for the queue to drain, so it's empty on entry.
+        debug_assert!(cmds.len() < idx_mask as usize);
+
+        for cmd in cmds.iter().chain(&[[CMD_SYNC, 0]]) {
+            let slot: *mut u64 = self
+                .cmdq
+                .va()
+                .add_bytes((self.cmdq_prod & idx_mask) as usize * 16)
+                .as_ptr_mut()
+                .cast();
+
+            unsafe {
+                ptr::write_volatile(slot, cmd[0]);
+                ptr::write_volatile(slot.add(1), cmd[1]);
+            }
+
+            self.cmdq_prod = (self.cmdq_prod + 1) & ptr_mask;
+        }
+
+        wmb();
+        self.regs.CMDQ_PROD.set(self.cmdq_prod);
+
+        let prod = self.cmdq_prod;
+        let regs = &*self.regs;
+
+        poll(
+            || {
+                let cons = regs.CMDQ_CONS.get();
+                cons & ptr_mask == prod || (cons >> CMDQ_CONS_ERR_SHIFT) & CMDQ_CONS_ERR_MASK != 0
+            },
+            "SMMU command queue timed out",
+        )?;
+
+        if (self.regs.CMDQ_CONS.get() >> CMDQ_CONS_ERR_SHIFT) & CMDQ_CONS_ERR_MASK != 0 {
+            return Err(KernelError::Other("SMMU command error"));
+        }
+
+        Ok(())
+    }
+
+    fn write_ste(&mut self, sid: u32, ste: &[u64; STE_DWORDS]) -> Result<()> {
+        write_dwords(&self.strtab, sid as usize * STE_DWORDS * 8, ste);
+
+        self.submit(&[[CMD_CFGI_STE | ((sid as u64) << 32), 1]])
+    }
+
+    /// Invalidates all TLB and walk cache entries tagged with `asid`.
+    fn invalidate_asid(&mut self, asid: u16) -> Result<()> {
+        self.submit(&[[CMD_TLBI_NH_ASID | ((asid as u64) << 48), 0]])
+    }
+}
+
+/// A stream table entry that lets the stream's transactions through
+/// untranslated.
+fn bypass_ste() -> [u64; STE_DWORDS] {
+    let mut ste = [0; STE_DWORDS];
+    ste[0] = STE_0_V | STE_0_CFG_BYPASS;
+    ste[1] = STE_1_SHCFG_INCOMING;
+    ste
+}
+
+struct PgTableAllocator;
+
+impl PageAllocator for PgTableAllocator {
+    fn allocate_page_table<T: PgTable>(&mut self) -> Result<TPA<PgTableArray<T>>> {
+        Ok(ClaimedPage::alloc_zeroed()?.leak().pa().cast())
+    }
+}
+
+struct PgOffsetMapper;
+
+impl PageTableMapper for PgOffsetMapper {
+    unsafe fn with_page_table<T: PgTable, R>(
+        &mut self,
+        pa: TPA<PgTableArray<T>>,
+        f: impl FnOnce(TVA<PgTableArray<T>>) -> R,
+    ) -> Result<R> {
+        Ok(f(pa.to_va::<PageOffsetTranslator>()))
+    }
+}
+
+/// Returns `true` if no entry of the table at `table_pa` is valid.
+fn table_is_empty<T: PgTable>(table_pa: TPA<PgTableArray<T>>) -> bool {
+    let table = T::from_ptr(table_pa.to_va::<PageOffsetTranslator>());
+
+    (0..T::DESCRIPTORS_PER_PAGE).all(|i| !table.get_idx(i).is_valid())
+}
+
+/// Clears the entries of the table at `table_pa`, whose first entry maps
+/// `base`, that point to next-level tables overlapping `region` which
+/// `prune_child` reports to be empty. The cleared tables are queued on
+/// `freed`, to be released once the SMMU can no longer walk them.
+///
+/// Returns `true` if the table itself is left empty.
+fn prune_table<T: PgTable<Descriptor: TableMapper>>(
+    table_pa: TPA<PgTableArray<T>>,
+    base: usize,
+    region: VirtMemoryRegion,
+    freed: &mut Vec<PA>,
+    mut prune_child: impl FnMut(
+        TPA<PgTableArray<<T::Descriptor as TableMapper>::NextLevel>>,
+        usize,
+        &mut Vec<PA>,
+    ) -> bool,
+) -> bool {
+    let table = T::from_ptr(table_pa.to_va::<PageOffsetTranslator>());
+    let span = 1 << <T::Descriptor as PageTableEntry>::MAP_SHIFT;
+
+    for i in 0..T::DESCRIPTORS_PER_PAGE {
+        let entry_base = base + i * span;
+
+        if !VirtMemoryRegion::new(VA::from_value(entry_base), span).overlaps(region) {
+            continue;
+        }
+
+        let Some(child) = table.get_idx(i).next_table_address() else {
+            continue;
+        };
+
+        if prune_child(child, entry_base, freed) {
+            table.set_desc(
+                VA::from_value(entry_base),
+                T::Descriptor::invalid(),
+                &NullTlbInvalidator {},
+            );
+            freed.push(child.to_untyped());
+        }
+    }
+
+    table_is_empty(table_pa)
+}
+
+/// The stage-1 page tables of a domain.
+struct SmmuPageTable {
+    l0: TPA<PgTableArray<L0Table>>,
+    asid: u16,
+    hw: Arc<SpinLock<SmmuHw>>,
+}
+
+unsafe impl Send for SmmuPageTable {}
+
+impl IoPageTable for SmmuPageTable {
+    fn map(&mut self, iova: usize, phys: PhysMemoryRegion, perms: PtePermissions) -> Result<()> {
+        // Device transactions are unprivileged by default, so make the
+        // mapping accessible at EL0.
+        let perms = if perms.is_write() {
+            PtePermissions::rw(true)
+        } else {
+            PtePermissions::ro(true)
+        };
+
+        // Map page by page so that `unmap` never has to split a block.
+        for (i, pa) in phys.iter_pages().enumerate() {
+            map_range(
+                self.l0,
+                MapAttributes {
+                    phys: PhysMemoryRegion::new(pa, PAGE_SIZE),
+                    virt: VirtMemoryRegion::new(VA::from_value(iova).add_pages(i), PAGE_SIZE),
+                    mem_type: MemoryType::Normal,
+                    perms,
+                },
+                &mut MappingContext {
+                    allocator: &mut PgTableAllocator,
+                    mapper: &mut PgOffsetMapper,
+                    invalidator: &NullTlbInvalidator {},
+                },
+            )?;
+        }
+
+        wmb();
+
+        Ok(())
+    }
+
+    fn unmap(&mut self, iova: usize, size: usize) -> Result<()> {
+        let region = VirtMemoryRegion::new(VA::from_value(iova), size);
+
+        walk_and_modify_region(
+            self.l0,
+            region,
+            &mut WalkContext {
+                mapper: &mut PgOffsetMapper,
+                invalidator: &NullTlbInvalidator {},
+            },
+            |_, _| L3Descriptor::invalid(),
+        )?;
+
+        // The root table is never freed, so whether it's empty is irrelevant.
+        let mut freed = Vec::new();
+        prune_table::<L0Table>(self.l0, 0, region, &mut freed, |l1, base, freed| {
+            prune_table::<L1Table>(l1, base, region, freed, |l2, base, freed| {
+                prune_table::<L2Table>(l2, base, region, freed, |l3, _, _| {
+                    table_is_empty::<L3Table>(l3)
+                })
+            })
+        });
+
+        wmb();
+
+        self.hw.lock_save_irq().invalidate_asid(self.asid)?;
+
+        for pa in freed {
+            // SAFETY: The table was allocated by `PgTableAllocator`, is no
+            // longer referenced, and the SMMU's walk caches have been
+            // invalidated.
+            drop(unsafe { ClaimedPage::from_pfn(pa.to_pfn()) });
+        }
+
+        Ok(())
+    }
+}
+
+type DomainMap = SpinLock<BTreeMap<u32, Weak<SmmuDomain>>>;
+
+/// A per-device stage-1 translation domain.
+struct SmmuDomain {
+    sid: u32,
+    /// The SMMU's attached domains, which this one is removed from on drop.
+    domains: Arc<DomainMap>,
+    /// The domain's context descriptor, referenced by its stream table entry.
+    _cd: DmaCoherent,
+    iova: SpinLock<IovaDomain<SmmuPageTable>>,
+}
+
+impl Drop for SmmuDomain {
+    fn drop(&mut self) {
+        let mut domains = self.domains.lock_save_irq();
+
+        // The stream may already have been given a new domain.
+        if domains
+            .get(&self.sid)
+            .is_some_and(|domain| domain.strong_count() > 0)
+        {
+            return;
+        }
+
+        domains.remove(&self.sid);
+
+        let iova = self.iova.lock_save_irq();
+        let page_table = iova.page_table();
+
+        let detached = {
+            let mut hw = page_table.hw.lock_save_irq();
+
+            hw.write_ste(self.sid, &bypass_ste())
+                .and_then(|_| hw.invalidate_asid(page_table.asid))
+        };
+
+        if detached.is_err() {
+            // The SMMU may still walk the tables, so they can't be freed.
+            warn!(
+                "SMMU: failed to detach stream {}; leaking its page tables",
+                self.sid
+            );
+            return;
+        }
+
+        let teardown = tear_down_address_space(
+            page_table.l0,
+            &mut WalkContext {
+                mapper: &mut PgOffsetMapper,
+                invalidator: &NullTlbInvalidator {},
+            },
+            // The mapped pages belong to the domain's users.
+            |entry| match entry.kind {
+                EntryKind::Mapping(_) => TeardownAction::Skip,
+                _ => TeardownAction::Free,
+            },
+            |region| {
+                // SAFETY: The tables were allocated by `PgTableAllocator` and
+                // the stream no longer references them.
+                drop(unsafe { ClaimedPage::from_pfn(region.start_address().to_pfn()) });
+            },
+        );
+
+        if teardown.is_err() {
+            warn!("SMMU: domain tear down failed. Probable memory leakage!");
+        }
+    }
+}
+
+impl IommuDomain for SmmuDomain {
+    fn map(&self, phys: PhysMemoryRegion, dir: DmaDirection) -> Result<DmaAddr> {
+        self.iova.lock_save_irq().map(phys, dma_perms(dir))
+    }
+
+    fn unmap(&self, addr: DmaAddr) -> Result<()> {
+        self.iova.lock_save_irq().unmap(addr).map(|_| ())
+    }
+}
+
+pub struct ArmSmmuV3 {
+    hw: Arc<SpinLock<SmmuHw>>,
+    sid_bits: u32,
+    oas: u64,
+    next_asid: AtomicU16,
+    domains: Arc<DomainMap>,
+}
+
+impl ArmSmmuV3 {
+    /// Resets and enables the SMMU whose registers are mapped at `base`, with
+    /// all streams in bypass.
+    fn new(base: VA) -> Result<Self> {
+        let regs = unsafe { &mut *(base.value() as *mut SmmuRegs) };
+
+        let idr0 = regs.IDR0.get();
+        let idr1 = regs.IDR1.get();
+        let idr5 = regs.IDR5.get();
+
+        if idr0 & IDR0_S1P == 0 || idr0 & IDR0_TTF_AARCH64 == 0 || idr5 & IDR5_GRAN4K == 0 {
+            return Err(KernelError::NotSupported);
+        }
+
+        let sid_bits = (idr1 & IDR1_SIDSIZE_MASK).min(MAX_SID_BITS);
+        let cmdq_log2 = ((idr1 >> IDR1_CMDQS_SHIFT) & 0x1f).min(MAX_CMDQ_LOG2);
+
+        let strtab = dma_alloc_coherent((STE_DWORDS * 8) << sid_bits)?;
+        let cmdq = dma_alloc_coherent(16 << cmdq_log2)?;
+
+        let mut hw = SmmuHw {
+            regs,
+            strtab,
+            cmdq,
+            cmdq_log2,
+            cmdq_prod: 0,
+        };
+
+        hw.write_cr0(0)?;
+
+        hw.regs.CR1.set(CR1_WB_ISH);
+        hw.regs.CR2.set(CR2_RECINVSID);
+
+        for sid in 0..1 << sid_bits {
+            write_dwords(&hw.strtab, sid * STE_DWORDS * 8, &bypass_ste());
+        }
+
+        wmb();
+
+        hw.regs
+            .STRTAB_BASE
+            .set(BASE_RA | (hw.strtab.dma_addr() as u64 & BASE_ADDR_MASK));
+        hw.regs.STRTAB_BASE_CFG.set(sid_bits);
+
+        hw.regs
+            .CMDQ_BASE
+            .set(BASE_RA | (hw.cmdq.dma_addr() as u64 & BASE_ADDR_MASK) | cmdq_log2 as u64);
+        hw.regs.CMDQ_PROD.set(0);
+        hw.regs.CMDQ_CONS.set(0);
+
+        hw.write_cr0(CR0_CMDQEN)?;
+
+        hw.submit(&[[CMD_CFGI_ALL, 31], [CMD_TLBI_NSNH_ALL, 0]])?;
+
+        hw.write_cr0(CR0_CMDQEN | CR0_SMMUEN)?;
+
+        Ok(Self {
+            hw: Arc::new(SpinLock::new(hw)),
+            sid_bits,
+            oas: (idr5 & IDR5_OAS_MASK) as u64,
+            next_asid: AtomicU16::new(1),
+            domains: Arc::new(SpinLock::new(BTreeMap::new())),
+        })
+    }
+
+    /// Creates a new domain and routes `sid` through it.
+    fn attach(&self, sid: u32) -> Result<Arc<SmmuDomain>> {
+        let asid = self.next_asid.fetch_add(1, Ordering::Relaxed);
+        let l0 = PgTableAllocator.allocate_page_table::<L0Table>()?;
+        let cd = dma_alloc_coherent(CD_DWORDS * 8)?;
+
+        let mut desc = [0; CD_DWORDS];
+        desc[0] = CD_0_T0SZ_48BIT
+            | CD_0_WB_ISH
+            | CD_0_EPD1
+            | CD_0_V
+            | (self.oas << CD_0_IPS_SHIFT)
+            | CD_0_AA64
+            | CD_0_R
+            | CD_0_A
+            | CD_0_ASET
+            | ((asid as u64) << CD_0_ASID_SHIFT);
+        desc[1] = l0.value() as u64 & BASE_ADDR_MASK;
+        desc[3] = MAIR;
+
+        write_dwords(&cd, 0, &desc);
+
+        let mut ste = [0; STE_DWORDS];
+        ste[0] = STE_0_V | STE_0_CFG_S1_TRANS | (cd.dma_addr() as u64 & BASE_ADDR_MASK);
+        ste[1] = STE_1_S1_WB_ISH | STE_1_SHCFG_INCOMING;
+
+        self.hw.lock_save_irq().write_ste(sid, &ste)?;
+
+        Ok(Arc::new(SmmuDomain {
+            sid,
+            domains: self.domains.clone(),
+            _cd: cd,
+            iova: SpinLock::new(IovaDomain::new(
+                IOVA_APERTURE_START,
+                IOVA_APERTURE_SIZE,
+                SmmuPageTable {
+                    l0,
+                    asid,
+                    hw: self.hw.clone(),
+                },
+            )),
+        }))
+    }
+}
+
+impl Iommu for ArmSmmuV3 {
+    fn domain_for_stream(&self, stream_id: u32) -> Result<Arc<dyn IommuDomain>> {
+        if stream_id >= 1 << self.sid_bits {
+            return Err(KernelError::NotSupported);
+        }
+
+        let mut domains = self.domains.lock_save_irq();
+
+        if let Some(domain) = domains.get(&stream_id).and_then(Weak::upgrade) {
+            return Ok(domain);
+        }
+
+        let domain = self.attach(stream_id)?;
+
+        domains.insert(stream_id, Arc::downgrade(&domain));
+
+        Ok(domain)
+    }
+}
+
+impl Driver for ArmSmmuV3 {
+    fn name(&self) -> &'static str {
+        "arm-smmu-v3"
+    }
+}
+
+fn smmu_v3_probe(_dm: &mut DriverManager, d: DeviceDescriptor) -> Result<Arc<dyn Driver>> {
+    match d {
+        DeviceDescriptor::Fdt(fdt_node, _flags) => {
+            let region = fdt_node
+                .reg()
+                .ok_or(ProbeError::NoReg)?
+                .next()
+                .ok_or(ProbeError::NoReg)?;
+
+            let size = region.size.ok_or(ProbeError::NoRegSize)?;
+
+            let mem =
+                ArchImpl::kern_address_space()
+                    .lock_save_irq()
+                    .map_mmio(PhysMemoryRegion::new(
+                        PA::from_value(region.address as usize),
+                        size,
+                    ))?;
+
+            let smmu = Arc::new(ArmSmmuV3::new(mem)?);
+
+            info!(
+                "SMMUv3 found @ {:#x}: {} stream ID bits",
+                region.address, smmu.sid_bits
+            );
+
+            // Without a phandle no device can reference the SMMU, so all
+            // streams stay in bypass.
+            if let Some(phandle) = fdt_node.find_property("phandle").map(|p| p.u32()) {
+                register_iommu(phandle, smmu.clone())?;
+            }
+
+            Ok(smmu)
+        }
+    }
+}
+
+pub fn smmu_v3_init(bus: &mut PlatformBus, _dm: &mut DriverManager) -> Result<()> {
+    bus.register_platform_driver(
+        DeviceMatchType::FdtCompatible("arm,smmu-v3"),
+        Box::new(smmu_v3_probe),
+    );
+
+    Ok(())
+}
+
+kernel_driver!(smmu_v3_init);
@@ -0,0 +1,91 @@
+//! IOMMU support.
+//!
+//! IOMMU drivers register themselves, keyed by their device tree phandle, as
+//! they are probed. Device drivers then obtain a [`DmaDevice`] for their node
+//! with [`dma_device_for_fdt_node`], which attaches the device to its own
+//! translation domain if the node has an `iommus` property, or falls back to
+//! identity-mapped DMA otherwise.
+
+use crate::{
+    memory::dma::{DmaAddr, DmaDevice, DmaDirection},
+    sync::SpinLock,
+};
+use alloc::{collections::btree_map::BTreeMap, sync::Arc};
+use libkernel::{
+    error::{KernelError, ProbeError, Result},
+    memory::{paging::permissions::PtePermissions, region::PhysMemoryRegion},
+};
+
+pub mod arm_smmu_v3;
+
+/// A translation domain: an I/O virtual address space that one or more
+/// devices perform DMA through.
+pub trait IommuDomain: Send + Sync {
+    /// Maps `phys` into the domain for DMA in direction `dir`, returning the
+    /// address the device should use to access it.
+    fn map(&self, phys: PhysMemoryRegion, dir: DmaDirection) -> Result<DmaAddr>;
+
+    /// Removes a mapping previously created with [`Self::map`].
+    fn unmap(&self, addr: DmaAddr) -> Result<()>;
+}
+
+/// An IOMMU instance.
+pub trait Iommu: Send + Sync {
+    /// Returns the translation domain for the device with the given stream
+    /// ID, creating it and routing the device's DMA through it on first use.
+    fn domain_for_stream(&self, stream_id: u32) -> Result<Arc<dyn IommuDomain>>;
+}
+
+/// The device-visible permissions for a DMA mapping in direction `dir`.
+pub fn dma_perms(dir: DmaDirection) -> PtePermissions {
+    match dir {
+        DmaDirection::ToDevice => PtePermissions::ro(false),
+        DmaDirection::FromDevice | DmaDirection::Bidirectional => PtePermissions::rw(false),
+    }
+}
+
+/// Registered IOMMUs, keyed by phandle.
+static IOMMUS: SpinLock<BTreeMap<u32, Arc<dyn Iommu>>> = SpinLock::new(BTreeMap::new());
+
+/// Registers an IOMMU so that devices referencing `phandle` in their `iommus`
+/// property are attached to it.
+pub fn register_iommu(phandle: u32, iommu: Arc<dyn Iommu>) -> Result<()> {
+    let mut iommus = IOMMUS.lock_save_irq();
+
+    if iommus.contains_key(&phandle) {
+        return Err(KernelError::InUse);
+    }
+
+    iommus.insert(phandle, iommu);
+
+    Ok(())
+}
+
+/// Returns the [`DmaDevice`] through which the device described by `node`
+/// should perform DMA.
+///
+/// Returns [`ProbeError::Deferred`] if the node references an IOMMU that
+/// hasn't been probed yet.
+pub fn dma_device_for_fdt_node(node: &fdt_parser::Node<'static>) -> Result<DmaDevice> {
+    let Some(prop) = node.find_property("iommus") else {
+        return Ok(DmaDevice::identity());
+    };
+
+    // Only single-cell specifiers, `<&iommu stream-id>`, are supported.
+    let cells = prop.raw_value();
+
+    if cells.len() < 8 {
+        return Err(KernelError::InvalidValue);
+    }
+
+    let phandle = u32::from_be_bytes(cells[0..4].try_into().unwrap());
+    let stream_id = u32::from_be_bytes(cells[4..8].try_into().unwrap());
+
+    let iommu = IOMMUS
+        .lock_save_irq()
+        .get(&phandle)
+        .cloned()
+        .ok_or(KernelError::Probe(ProbeError::Deferred))?;
+
+    Ok(DmaDevice::new(iommu.domain_for_stream(stream_id)?))
+}
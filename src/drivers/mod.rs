@@ -20,17 +20,24 @@ use crate::{
     sync::SpinLock,
 };
 
+pub mod blkdev;
 pub mod chrdev;
 pub mod display;
 pub mod fdt_prober;
 pub mod fs;
 pub mod init;
+pub mod input;
 pub mod interrupts;
+pub mod iommu;
+pub mod net;
+pub mod pci;
 pub mod probe;
 pub mod rng;
 pub mod rtc;
 pub mod timer;
 pub mod uart;
+pub mod usb;
+pub mod virtio_console;
 mod virtio_hal;
 
 #[repr(u64)]
@@ -38,10 +45,14 @@ pub enum ReservedMajors {
     Null = 1,
     Zero = 2,
     Random = 3,
+    Vt = 4,
     Console = 5,
     Fb = 6,
     Uart = 10,
-    End = 11,
+    Input = 13,
+    Hvc = 14,
+    Rtc = 15,
+    End = 16,
 }
 
 pub trait Driver: Send + Sync + Any {
@@ -71,7 +82,7 @@ pub trait CharDriver: Send + Sync + 'static {
 pub struct DriverManager {
     /// Every driver instance in the system.
     active_drivers: Vec<Arc<dyn Driver>>,
-    _next_major: AtomicU64,
+    next_major: AtomicU64,
     /// Maps a major number to an instance of a CharDriver.
     char_drivers: BTreeMap<u64, Arc<dyn CharDriver>>,
 }
@@ -80,7 +91,7 @@ impl DriverManager {
     pub const fn new() -> Self {
         Self {
             active_drivers: Vec::new(),
-            _next_major: AtomicU64::new(ReservedMajors::End as _),
+            next_major: AtomicU64::new(ReservedMajors::End as _),
             char_drivers: BTreeMap::new(),
         }
     }
@@ -99,8 +110,8 @@ impl DriverManager {
         })
     }
 
-    pub fn _allocate_major(&self) -> u64 {
-        self._next_major.fetch_add(1, Ordering::SeqCst)
+    pub fn allocate_major(&self) -> u64 {
+        self.next_major.fetch_add(1, Ordering::SeqCst)
     }
 
     pub fn register_char_driver(&mut self, major: u64, driver: Arc<dyn CharDriver>) -> Result<()> {
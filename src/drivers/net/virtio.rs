@@ -0,0 +1,253 @@
+//! VirtIO network devices.
+//!
+//! Each device becomes an Ethernet interface, `ethN`, in the order they're
+//! found. The stack pulls received frames out of the device when it runs, so
+//! the receive interrupt only has to wake it.
+
+use crate::drivers::virtio_hal::{VIRTIO_PCI_VENDOR, VirtioHal, attach_fdt_node, pci_transport};
+use crate::sync::SpinLock;
+use crate::{
+    arch::ArchImpl,
+    drivers::{
+        Driver, DriverManager,
+        init::PlatformBus,
+        pci::{IrqTypes, PCI_BUS, PciDevice, PciMatchType},
+        probe::{DeviceDescriptor, DeviceMatchType},
+    },
+    interrupts::{
+        ClaimedInterrupt, InterruptConfig, InterruptDescriptor, InterruptHandler, InterruptManager,
+        get_interrupt_root,
+    },
+    kernel_driver,
+    net::{device::NetDevice, iface::register_device, wake_poll_task},
+};
+use alloc::{boxed::Box, format, sync::Arc, vec::Vec};
+use core::{
+    ptr::NonNull,
+    sync::atomic::{AtomicU32, Ordering},
+};
+use libkernel::memory::proc_vm::address_space::{KernAddressSpace, VirtualMemory};
+use libkernel::{
+    error::{KernelError, ProbeError, Result},
+    memory::{
+        address::{PA, VA},
+        region::PhysMemoryRegion,
+    },
+};
+use log::info;
+use virtio_drivers::{
+    device::net::{TxBuffer, VirtIONet},
+    transport::{
+        DeviceType, SomeTransport, Transport,
+        mmio::{MmioTransport, VirtIOHeader},
+    },
+};
+
+const QUEUE_SIZE: usize = 16;
+
+/// Large enough for the virtio-net header and a full Ethernet frame.
+const RX_BUF_SIZE: usize = 2048;
+
+/// The longest frame sent, an Ethernet header and a 1500 byte payload, as the
+/// device doesn't tell us its MTU.
+const FRAME_MTU: usize = 1514;
+
+/// The number of the next interface to be named.
+static NEXT_ETH: AtomicU32 = AtomicU32::new(0);
+
+type VirtioNetDev = VirtIONet<VirtioHal, SomeTransport<'static>, QUEUE_SIZE>;
+
+struct VirtioNet {
+    net: SpinLock<VirtioNetDev>,
+    mac: [u8; 6],
+}
+
+impl NetDevice for VirtioNet {
+    fn transmit(&self, frame: &[u8]) -> Result<()> {
+        self.net
+            .lock_save_irq()
+            .send(TxBuffer::from(frame))
+            .map_err(|_| KernelError::Other("virtio-net send failed"))
+    }
+
+    fn receive(&self) -> Option<Vec<u8>> {
+        let mut net = self.net.lock_save_irq();
+        let buf = net.receive().ok()?;
+        let frame = buf.packet().to_vec();
+
+        // The buffer was just taken from the queue, so its slot is free.
+        let _ = net.recycle_rx_buffer(buf);
+
+        Some(frame)
+    }
+
+    fn mtu(&self) -> usize {
+        FRAME_MTU
+    }
+
+    fn mac(&self) -> Option<[u8; 6]> {
+        Some(self.mac)
+    }
+}
+
+pub struct VirtioNetDriver {
+    name: &'static str,
+    dev: Arc<VirtioNet>,
+    _interrupt: ClaimedInterrupt,
+}
+
+impl Driver for VirtioNetDriver {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+impl InterruptHandler for VirtioNetDriver {
+    fn handle_irq(&self, _desc: InterruptDescriptor) {
+        let _ = self.dev.net.lock_save_irq().ack_interrupt();
+
+        wake_poll_task();
+    }
+}
+
+fn virtio_net_probe(dm: &mut DriverManager, d: DeviceDescriptor) -> Result<Arc<dyn Driver>> {
+    match d {
+        DeviceDescriptor::Fdt(fdt_node, _flags) => {
+            let region = fdt_node
+                .reg()
+                .ok_or(ProbeError::NoReg)?
+                .next()
+                .ok_or(ProbeError::NoReg)?;
+
+            let size = region.size.ok_or(ProbeError::NoRegSize)?;
+
+            let mapped: VA =
+                ArchImpl::kern_address_space()
+                    .lock_save_irq()
+                    .map_mmio(PhysMemoryRegion::new(
+                        PA::from_value(region.address as usize),
+                        size,
+                    ))?;
+
+            let header = NonNull::new(mapped.value() as *mut VirtIOHeader)
+                .ok_or(KernelError::InvalidValue)?;
+
+            let transport = unsafe {
+                match MmioTransport::new(header, size) {
+                    Ok(t) => t,
+                    Err(_) => return Err(KernelError::Probe(ProbeError::NoMatch)),
+                }
+            };
+
+            if !matches!(transport.device_type(), DeviceType::Network) {
+                return Err(KernelError::Probe(ProbeError::NoMatch));
+            }
+
+            attach_fdt_node(&fdt_node)?;
+
+            let mut interrupts = fdt_node
+                .interrupts()
+                .ok_or(ProbeError::NoInterrupts)?
+                .next()
+                .ok_or(ProbeError::NoInterrupts)?;
+
+            let interrupt_node = fdt_node
+                .interrupt_parent()
+                .ok_or(ProbeError::NoParentInterrupt)?
+                .node;
+
+            let interrupt_manager = dm
+                .find_by_name(interrupt_node.name)
+                .ok_or(ProbeError::Deferred)?
+                .as_interrupt_manager()
+                .ok_or(ProbeError::NotInterruptController)?;
+
+            let interrupt_config = interrupt_manager.parse_fdt_interrupt_regs(&mut interrupts)?;
+
+            bring_up(
+                fdt_node.name,
+                SomeTransport::Mmio(transport),
+                interrupt_manager,
+                interrupt_config,
+            )
+        }
+    }
+}
+
+fn virtio_net_pci_probe(_dm: &mut DriverManager, pci: Arc<PciDevice>) -> Result<Arc<dyn Driver>> {
+    let transport = pci_transport(&pci)?;
+
+    // The transport leaves the MSI-X vectors unprogrammed, so the device
+    // signals over INTx.
+    let interrupt_config = pci.alloc_irq_vectors(1, IrqTypes::INTX)?[0];
+    let interrupt_manager = get_interrupt_root().ok_or(ProbeError::Deferred)?;
+
+    pci.enable_bus_master();
+
+    bring_up(
+        "virtio-pci-net",
+        SomeTransport::Pci(transport),
+        interrupt_manager,
+        interrupt_config,
+    )
+}
+
+fn bring_up(
+    name: &'static str,
+    transport: SomeTransport<'static>,
+    interrupt_manager: Arc<InterruptManager>,
+    interrupt_config: InterruptConfig,
+) -> Result<Arc<dyn Driver>> {
+    let mut net = VirtioNetDev::new(transport, RX_BUF_SIZE)
+        .map_err(|_| KernelError::Other("virtio-net init failed"))?;
+
+    net.enable_interrupts();
+
+    let dev = Arc::new(VirtioNet {
+        mac: net.mac_address(),
+        net: SpinLock::new(net),
+    });
+
+    let driver =
+        interrupt_manager.claim_interrupt(interrupt_config, |claimed| VirtioNetDriver {
+            name,
+            dev: dev.clone(),
+            _interrupt: claimed,
+        })?;
+
+    let iface = format!("eth{}", NEXT_ETH.fetch_add(1, Ordering::Relaxed));
+    register_device(&iface, dev)?;
+
+    info!("virtio-net found ({name}), as {iface}");
+
+    Ok(driver)
+}
+
+pub fn virtio_net_init(bus: &mut PlatformBus, _dm: &mut DriverManager) -> Result<()> {
+    bus.register_platform_driver(
+        DeviceMatchType::FdtCompatible("virtio,mmio"),
+        Box::new(virtio_net_probe),
+    );
+
+    bus.register_platform_driver(
+        DeviceMatchType::FdtCompatible("virtio-mmio"),
+        Box::new(virtio_net_probe),
+    );
+
+    let mut pci = PCI_BUS.lock_save_irq();
+
+    // Transitional and modern device IDs.
+    for device in [0x1000, 0x1041] {
+        pci.register_pci_driver(
+            PciMatchType::Id {
+                vendor: VIRTIO_PCI_VENDOR,
+                device,
+            },
+            Box::new(virtio_net_pci_probe),
+        );
+    }
+
+    Ok(())
+}
+
+kernel_driver!(virtio_net_init);
@@ -0,0 +1,85 @@
+//! Configuration space access.
+
+use super::PciAddress;
+use libkernel::memory::address::VA;
+
+/// How a host bridge lays out the configuration space of its buses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigMechanism {
+    /// PCIe's Enhanced Configuration Access Mechanism: 4KiB per function.
+    Ecam,
+    /// The conventional PCI layout: 256 bytes per function.
+    Cam,
+}
+
+impl ConfigMechanism {
+    /// The size of a function's configuration space.
+    pub fn function_size(self) -> usize {
+        match self {
+            Self::Ecam => 1 << 12,
+            Self::Cam => 1 << 8,
+        }
+    }
+
+    /// The size of a bus's configuration space.
+    pub fn bus_size(self) -> usize {
+        self.function_size() << 8
+    }
+
+    /// Returns the offset of a function's configuration space from that of
+    /// bus 0.
+    pub fn offset(self, addr: PciAddress) -> usize {
+        let devfn = ((addr.device as usize) << 3) | addr.function as usize;
+
+        addr.bus as usize * self.bus_size() + devfn * self.function_size()
+    }
+}
+
+/// The configuration space of one function.
+#[derive(Debug, Clone, Copy)]
+pub struct ConfigSpace {
+    base: VA,
+    size: usize,
+}
+
+// SAFETY: The window is a device mapping which lives for as long as the
+// kernel does.
+unsafe impl Send for ConfigSpace {}
+unsafe impl Sync for ConfigSpace {}
+
+impl ConfigSpace {
+    /// # Safety
+    ///
+    /// `base` must be a device mapping of `size` bytes of configuration
+    /// space which is never unmapped.
+    pub unsafe fn new(base: VA, size: usize) -> Self {
+        Self { base, size }
+    }
+
+    fn reg<T>(&self, offset: usize) -> *mut T {
+        // Host bridges need not cope with unaligned or out of bounds accesses.
+        assert!(offset.is_multiple_of(size_of::<T>()) && offset + size_of::<T>() <= self.size);
+
+        self.base.add_bytes(offset).as_ptr_mut().cast()
+    }
+
+    pub fn read_u8(&self, offset: usize) -> u8 {
+        unsafe { self.reg::<u8>(offset).read_volatile() }
+    }
+
+    pub fn read_u16(&self, offset: usize) -> u16 {
+        unsafe { self.reg::<u16>(offset).read_volatile() }
+    }
+
+    pub fn read_u32(&self, offset: usize) -> u32 {
+        unsafe { self.reg::<u32>(offset).read_volatile() }
+    }
+
+    pub fn write_u16(&self, offset: usize, val: u16) {
+        unsafe { self.reg::<u16>(offset).write_volatile(val) }
+    }
+
+    pub fn write_u32(&self, offset: usize, val: u32) {
+        unsafe { self.reg::<u32>(offset).write_volatile(val) }
+    }
+}
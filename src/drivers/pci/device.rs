@@ -0,0 +1,342 @@
+use super::{PciAddress, config::ConfigSpace, host::PciHost, regs::*};
+use crate::{
+    arch::ArchImpl,
+    interrupts::{InterruptConfig, msi::MsiVector},
+    sync::SpinLock,
+};
+use alloc::{sync::Arc, vec::Vec};
+use core::fmt::{self, Display};
+use libkernel::{
+    error::{KernelError, Result},
+    memory::{
+        address::{PA, VA},
+        proc_vm::address_space::{KernAddressSpace, VirtualMemory},
+        region::PhysMemoryRegion,
+    },
+};
+
+/// A Base Address Register's decoded window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bar {
+    Memory {
+        /// The CPU physical address of the window.
+        address: u64,
+        size: u64,
+        prefetchable: bool,
+        /// Whether the BAR takes the next BAR's slot for its high half.
+        is_64bit: bool,
+    },
+    Io {
+        /// The CPU physical address of the window; on Arm, I/O space is
+        /// reached through memory.
+        address: u64,
+        size: u64,
+    },
+}
+
+impl Display for Bar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Bar::Memory {
+                address,
+                size,
+                prefetchable,
+                is_64bit,
+            } => {
+                write!(f, "mem 0x{address:x} (0x{size:x} bytes")?;
+
+                if is_64bit {
+                    f.write_str(", 64-bit")?;
+                }
+
+                if prefetchable {
+                    f.write_str(", prefetchable")?;
+                }
+
+                f.write_str(")")
+            }
+            Bar::Io { address, size } => write!(f, "io 0x{address:x} (0x{size:x} bytes)"),
+        }
+    }
+}
+
+impl Bar {
+    pub fn address(&self) -> u64 {
+        match *self {
+            Bar::Memory { address, .. } | Bar::Io { address, .. } => address,
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        match *self {
+            Bar::Memory { size, .. } | Bar::Io { size, .. } => size,
+        }
+    }
+}
+
+/// A PCI function.
+pub struct PciDevice {
+    pub addr: PciAddress,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub revision: u8,
+    config: ConfigSpace,
+    host: Arc<PciHost>,
+    bars: [Option<Bar>; 6],
+    pub(super) msi_vectors: SpinLock<Vec<MsiVector>>,
+}
+
+impl Display for PciDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} [{:04x}:{:04x}] class {:02x}{:02x}{:02x} rev {:02x}",
+            self.addr,
+            self.vendor_id,
+            self.device_id,
+            self.class,
+            self.subclass,
+            self.prog_if,
+            self.revision
+        )
+    }
+}
+
+impl PciDevice {
+    /// Reads a type 0 header, and sizes the function's BARs, assigning
+    /// addresses to any firmware left unassigned.
+    pub(super) fn new(host: Arc<PciHost>, addr: PciAddress, config: ConfigSpace) -> Self {
+        let mut dev = Self {
+            addr,
+            vendor_id: config.read_u16(VENDOR_ID),
+            device_id: config.read_u16(DEVICE_ID),
+            class: config.read_u8(CLASS),
+            subclass: config.read_u8(SUBCLASS),
+            prog_if: config.read_u8(PROG_IF),
+            revision: config.read_u8(REVISION),
+            config,
+            host,
+            bars: [None; 6],
+            msi_vectors: SpinLock::new(Vec::new()),
+        };
+
+        // Stop the function decoding while its BARs hold sizing masks.
+        let cmd = dev.command();
+        dev.set_command(cmd - (Command::IO_SPACE | Command::MEMORY_SPACE));
+
+        let mut idx = 0;
+        let mut decode = Command::empty();
+
+        while idx < dev.bars.len() {
+            let (bar, slots) = dev.probe_bar(idx);
+
+            match bar {
+                Some(Bar::Memory { .. }) => decode |= Command::MEMORY_SPACE,
+                Some(Bar::Io { .. }) => decode |= Command::IO_SPACE,
+                None => {}
+            }
+
+            dev.bars[idx] = bar;
+            idx += slots;
+        }
+
+        dev.set_command(cmd | decode);
+
+        dev
+    }
+
+    /// Sizes BAR `idx` by writing all ones and seeing which bits stick.
+    /// Returns the BAR, if it is implemented and has an address, and the
+    /// number of BAR slots it takes.
+    fn probe_bar(&self, idx: usize) -> (Option<Bar>, usize) {
+        let reg = BAR0 + idx * 4;
+        let orig = self.config.read_u32(reg);
+
+        self.config.write_u32(reg, u32::MAX);
+        let mask = self.config.read_u32(reg);
+        self.config.write_u32(reg, orig);
+
+        if mask == 0 {
+            return (None, 1);
+        }
+
+        if orig & BAR_IO != 0 {
+            let mut mask = mask & !0x3;
+
+            // Many functions only decode 16 bits of I/O address.
+            if mask & 0xffff_0000 == 0 {
+                mask |= 0xffff_0000;
+            }
+
+            let size = (!mask).wrapping_add(1) as u64;
+            let bus_addr = (orig & !0x3) as u64;
+            let bar = self
+                .assign_bar(reg, bus_addr, size, BarKind::Io, false)
+                .map(|address| Bar::Io { address, size });
+
+            return (bar, 1);
+        }
+
+        let is_64bit = orig & BAR_TYPE_MASK == BAR_TYPE_64 && idx < 5;
+        let prefetchable = orig & BAR_PREFETCHABLE != 0;
+
+        let (bus_addr, mask) = if is_64bit {
+            let orig_hi = self.config.read_u32(reg + 4);
+
+            self.config.write_u32(reg + 4, u32::MAX);
+            let mask_hi = self.config.read_u32(reg + 4);
+            self.config.write_u32(reg + 4, orig_hi);
+
+            (
+                ((orig_hi as u64) << 32) | (orig & !0xf) as u64,
+                ((mask_hi as u64) << 32) | (mask & !0xf) as u64,
+            )
+        } else {
+            ((orig & !0xf) as u64, (mask & !0xf) as u64 | !0u64 << 32)
+        };
+
+        let size = (!mask).wrapping_add(1);
+        let kind = if is_64bit && prefetchable {
+            BarKind::Mem64
+        } else {
+            BarKind::Mem32
+        };
+
+        let bar = self
+            .assign_bar(reg, bus_addr, size, kind, is_64bit)
+            .map(|address| Bar::Memory {
+                address,
+                size,
+                prefetchable,
+                is_64bit,
+            });
+
+        (bar, if is_64bit { 2 } else { 1 })
+    }
+
+    /// Returns the CPU address of a BAR, giving it a bus address from the
+    /// host's windows first if it doesn't have one.
+    fn assign_bar(
+        &self,
+        reg: usize,
+        bus_addr: u64,
+        size: u64,
+        kind: BarKind,
+        is_64bit: bool,
+    ) -> Option<u64> {
+        if bus_addr != 0 {
+            return self.host.bus_to_cpu(bus_addr);
+        }
+
+        let bus_addr = self.host.allocate_window(kind, size)?;
+
+        self.config.write_u32(reg, bus_addr as u32);
+
+        if is_64bit {
+            self.config.write_u32(reg + 4, (bus_addr >> 32) as u32);
+        }
+
+        self.host.bus_to_cpu(bus_addr)
+    }
+
+    pub fn config(&self) -> &ConfigSpace {
+        &self.config
+    }
+
+    /// The host bridge the function sits behind.
+    pub fn host(&self) -> &Arc<PciHost> {
+        &self.host
+    }
+
+    pub fn command(&self) -> Command {
+        Command::from_bits_retain(self.config.read_u16(COMMAND))
+    }
+
+    pub fn set_command(&self, cmd: Command) {
+        self.config.write_u16(COMMAND, cmd.bits());
+    }
+
+    /// Lets the function access memory: needed for DMA, and for MSIs.
+    pub fn enable_bus_master(&self) {
+        self.set_command(self.command() | Command::BUS_MASTER);
+    }
+
+    pub fn bar(&self, idx: usize) -> Option<Bar> {
+        self.bars.get(idx).copied().flatten()
+    }
+
+    /// Iterates over the function's BARs, as `(index, bar)` pairs.
+    pub fn bars(&self) -> impl Iterator<Item = (usize, Bar)> + '_ {
+        self.bars
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, bar)| Some((idx, (*bar)?)))
+    }
+
+    /// Maps a BAR into the kernel's address space as device memory.
+    pub fn map_bar(&self, idx: usize) -> Result<VA> {
+        let bar = self.bar(idx).ok_or(KernelError::InvalidValue)?;
+
+        ArchImpl::kern_address_space()
+            .lock_save_irq()
+            .map_mmio(PhysMemoryRegion::new(
+                PA::from_value(bar.address() as usize),
+                bar.size() as usize,
+            ))
+    }
+
+    /// Iterates over the function's capabilities, as `(id, offset)` pairs.
+    pub fn capabilities(&self) -> impl Iterator<Item = (u8, usize)> + '_ {
+        let mut next = if self.config.read_u16(STATUS) & STATUS_CAP_LIST != 0 {
+            self.config.read_u8(CAP_PTR) as usize & !0x3
+        } else {
+            0
+        };
+
+        // A malformed list could loop, but can hold at most this many
+        // capabilities.
+        let mut budget = (256 - 0x40) / 4;
+
+        core::iter::from_fn(move || {
+            if next < 0x40 || budget == 0 {
+                return None;
+            }
+
+            budget -= 1;
+
+            let offset = next;
+            let id = self.config.read_u8(offset);
+            next = self.config.read_u8(offset + 1) as usize & !0x3;
+
+            Some((id, offset))
+        })
+    }
+
+    /// Returns the offset of the first capability with the given ID.
+    pub fn find_capability(&self, id: u8) -> Option<usize> {
+        self.capabilities()
+            .find(|&(cap, _)| cap == id)
+            .map(|(_, offset)| offset)
+    }
+
+    /// Returns the function's legacy INTx interrupt, if it has one and the
+    /// host bridge routes it.
+    pub(super) fn legacy_interrupt(&self) -> Option<InterruptConfig> {
+        match self.config.read_u8(INTERRUPT_PIN) {
+            0 => None,
+            pin => self.host.route_intx(self.addr, pin),
+        }
+    }
+}
+
+/// Which host window a BAR is allocated from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BarKind {
+    Io,
+    Mem32,
+    /// 64-bit prefetchable memory, which can fall back to a 32-bit window.
+    Mem64,
+}
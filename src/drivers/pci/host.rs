@@ -0,0 +1,254 @@
+use super::{
+    PciAddress,
+    config::{ConfigMechanism, ConfigSpace},
+    device::{BarKind, PciDevice},
+    regs::*,
+};
+use crate::{arch::ArchImpl, drivers::Driver, interrupts::InterruptConfig, sync::SpinLock};
+use alloc::{sync::Arc, vec::Vec};
+use core::ops::RangeInclusive;
+use libkernel::{
+    error::Result,
+    memory::{
+        address::{PA, VA},
+        proc_vm::address_space::{KernAddressSpace, VirtualMemory},
+        region::PhysMemoryRegion,
+    },
+};
+use log::{debug, info};
+
+/// A range of bus addresses that a host bridge forwards to its buses, out
+/// of which BARs are assigned.
+#[derive(Debug, Clone, Copy)]
+pub struct Window {
+    pub kind: BarKind,
+    pub bus_base: u64,
+    pub cpu_base: u64,
+    pub size: u64,
+    /// The next free bus address.
+    next: u64,
+}
+
+impl Window {
+    pub fn new(kind: BarKind, bus_base: u64, cpu_base: u64, size: u64) -> Self {
+        Self {
+            kind,
+            bus_base,
+            cpu_base,
+            size,
+            next: bus_base,
+        }
+    }
+
+    fn contains(&self, bus_addr: u64) -> bool {
+        (self.bus_base..self.bus_base + self.size).contains(&bus_addr)
+    }
+
+    fn allocate(&mut self, size: u64) -> Option<u64> {
+        // BARs are naturally aligned.
+        let addr = self.next.checked_next_multiple_of(size)?;
+
+        if addr + size > self.bus_base + self.size {
+            return None;
+        }
+
+        self.next = addr + size;
+
+        Some(addr)
+    }
+}
+
+/// How a host bridge routes each slot's INTx pins to interrupt controller
+/// inputs: a device tree `interrupt-map`.
+#[derive(Debug, Clone, Default)]
+pub struct IntxMap {
+    /// Applied to `[phys.hi, phys.mid, phys.lo, pin]` before lookup.
+    pub mask: [u32; 4],
+    pub entries: Vec<([u32; 4], InterruptConfig)>,
+}
+
+impl IntxMap {
+    fn lookup(&self, addr: PciAddress, pin: u8) -> Option<InterruptConfig> {
+        let key = [addr.devfn_cells(), 0, 0, pin as u32];
+
+        self.entries.iter().find_map(|(child, config)| {
+            (0..4)
+                .all(|i| key[i] & self.mask[i] == child[i] & self.mask[i])
+                .then_some(*config)
+        })
+    }
+}
+
+/// A PCI host bridge: the root of one segment's buses.
+pub struct PciHost {
+    name: &'static str,
+    pub segment: u16,
+    pub buses: RangeInclusive<u8>,
+    mechanism: ConfigMechanism,
+    /// The configuration space of the first bus.
+    config: VA,
+    windows: SpinLock<Vec<Window>>,
+    intx: IntxMap,
+}
+
+impl PciHost {
+    /// Maps the configuration space of `buses`, found at `config_pa`.
+    pub fn new(
+        name: &'static str,
+        segment: u16,
+        mechanism: ConfigMechanism,
+        config_pa: u64,
+        buses: RangeInclusive<u8>,
+        windows: Vec<Window>,
+        intx: IntxMap,
+    ) -> Result<Arc<Self>> {
+        let size = buses.len() * mechanism.bus_size();
+
+        let config =
+            ArchImpl::kern_address_space()
+                .lock_save_irq()
+                .map_mmio(PhysMemoryRegion::new(
+                    PA::from_value(config_pa as usize),
+                    size,
+                ))?;
+
+        Ok(Arc::new(Self {
+            name,
+            segment,
+            buses,
+            mechanism,
+            config,
+            windows: SpinLock::new(windows),
+            intx,
+        }))
+    }
+
+    pub fn mechanism(&self) -> ConfigMechanism {
+        self.mechanism
+    }
+
+    /// Returns where bus 0's configuration space would be mapped. Offsets
+    /// from this, as computed by [`ConfigMechanism::offset`], are only valid
+    /// for the host's buses.
+    pub fn config_base(&self) -> VA {
+        self.config
+            .sub_bytes(*self.buses.start() as usize * self.mechanism.bus_size())
+    }
+
+    /// Returns a function's configuration space, if it is on one of the
+    /// host's buses.
+    pub fn config_space(&self, addr: PciAddress) -> Option<ConfigSpace> {
+        if addr.segment != self.segment || !self.buses.contains(&addr.bus) {
+            return None;
+        }
+
+        let base = self.config_base().add_bytes(self.mechanism.offset(addr));
+
+        // SAFETY: The host's buses were mapped at creation, and stay mapped.
+        Some(unsafe { ConfigSpace::new(base, self.mechanism.function_size()) })
+    }
+
+    /// Translates a bus address to a CPU physical address. Without windows,
+    /// firmware set the host up and addresses are taken to be identical.
+    pub(super) fn bus_to_cpu(&self, bus_addr: u64) -> Option<u64> {
+        let windows = self.windows.lock_save_irq();
+
+        if windows.is_empty() {
+            return Some(bus_addr);
+        }
+
+        windows
+            .iter()
+            .find(|w| w.contains(bus_addr))
+            .map(|w| w.cpu_base + (bus_addr - w.bus_base))
+    }
+
+    /// Allocates a bus address range for a BAR.
+    pub(super) fn allocate_window(&self, kind: BarKind, size: u64) -> Option<u64> {
+        if size == 0 || !size.is_power_of_two() {
+            return None;
+        }
+
+        // Prefetchable 64-bit BARs can go anywhere, but prefer to leave the
+        // scarce 32-bit space to BARs that need it.
+        let fallbacks: &[BarKind] = match kind {
+            BarKind::Mem64 => &[BarKind::Mem64, BarKind::Mem32],
+            BarKind::Mem32 => &[BarKind::Mem32],
+            BarKind::Io => &[BarKind::Io],
+        };
+
+        let mut windows = self.windows.lock_save_irq();
+
+        fallbacks.iter().find_map(|&kind| {
+            windows
+                .iter_mut()
+                .filter(|w| w.kind == kind)
+                .find_map(|w| w.allocate(size))
+        })
+    }
+
+    pub(super) fn route_intx(&self, addr: PciAddress, pin: u8) -> Option<InterruptConfig> {
+        self.intx.lookup(addr, pin)
+    }
+
+    /// Finds the functions on the host's buses.
+    ///
+    /// Bridges aren't configured, so devices behind them are only found if
+    /// firmware has already numbered the buses.
+    pub(super) fn scan(self: &Arc<Self>) -> Vec<Arc<PciDevice>> {
+        let mut devices = Vec::new();
+
+        for bus in self.buses.clone() {
+            for device in 0..32 {
+                for function in 0..8 {
+                    let addr = PciAddress {
+                        segment: self.segment,
+                        bus,
+                        device,
+                        function,
+                    };
+
+                    let Some(config) = self.config_space(addr) else {
+                        continue;
+                    };
+
+                    if config.read_u16(VENDOR_ID) == 0xffff {
+                        if function == 0 {
+                            break;
+                        }
+
+                        continue;
+                    }
+
+                    let header = config.read_u8(HEADER_TYPE);
+
+                    if header & HEADER_TYPE_MASK == HEADER_TYPE_NORMAL {
+                        let dev = Arc::new(PciDevice::new(self.clone(), addr, config));
+
+                        info!("PCI: found {dev}");
+
+                        for (idx, bar) in dev.bars() {
+                            debug!("PCI: {addr} BAR{idx}: {bar}");
+                        }
+
+                        devices.push(dev);
+                    } else {
+                        debug!("PCI: skipping bridge at {addr}");
+                    }
+
+                    if function == 0 && header & HEADER_MULTI_FUNCTION == 0 {
+                        break;
+                    }
+                }
+            }
+        }
+
+        devices
+    }
+}
+
+impl Driver for PciHost {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+}
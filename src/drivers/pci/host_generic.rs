@@ -0,0 +1,223 @@
+//! Generic memory-mapped PCI host bridges, as described by the device tree
+//! bindings `pci-host-ecam-generic` and `pci-host-cam-generic`.
+
+use super::{
+    PCI_BUS,
+    config::ConfigMechanism,
+    device::BarKind,
+    host::{IntxMap, PciHost, Window},
+};
+use crate::{
+    drivers::{
+        Driver, DriverManager,
+        fdt_prober::get_fdt,
+        init::PlatformBus,
+        probe::{DeviceDescriptor, DeviceMatchType},
+    },
+    kernel_driver,
+};
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use libkernel::error::{KernelError, ProbeError, Result};
+use log::info;
+
+/// `phys.hi` space codes in `ranges`.
+const SPACE_MASK: u32 = 0x3 << 24;
+const SPACE_IO: u32 = 0x1 << 24;
+const SPACE_MEM32: u32 = 0x2 << 24;
+const SPACE_MEM64: u32 = 0x3 << 24;
+const PREFETCHABLE: u32 = 1 << 30;
+
+fn cells(raw: &[u8]) -> Vec<u32> {
+    raw.as_chunks::<4>()
+        .0
+        .iter()
+        .map(|&c| u32::from_be_bytes(c))
+        .collect()
+}
+
+/// Joins one to two cells into a number.
+fn join_cells(cells: &[u32]) -> u64 {
+    cells.iter().fold(0, |acc, &c| (acc << 32) | c as u64)
+}
+
+fn cell_count(node: &fdt_parser::Node<'static>, name: &str, default: u32) -> usize {
+    node.find_property(name).map(|p| p.u32()).unwrap_or(default) as usize
+}
+
+/// Parses `ranges` into the windows BARs are assigned from.
+fn parse_ranges(node: &fdt_parser::Node<'static>) -> Result<Vec<Window>> {
+    let Some(ranges) = node.find_property("ranges") else {
+        return Ok(Vec::new());
+    };
+
+    let parent_addr_cells = get_fdt()
+        .find_nodes("/")
+        .next()
+        .map(|root| cell_count(&root, "#address-cells", 2))
+        .unwrap_or(2);
+
+    let size_cells = cell_count(node, "#size-cells", 2);
+    let entry_cells = 3 + parent_addr_cells + size_cells;
+
+    let cells = cells(ranges.raw_value());
+
+    if entry_cells == 3 || !cells.len().is_multiple_of(entry_cells) {
+        return Err(KernelError::InvalidValue);
+    }
+
+    Ok(cells
+        .chunks_exact(entry_cells)
+        .filter_map(|entry| {
+            let (child, rest) = entry.split_at(3);
+            let (parent, size) = rest.split_at(parent_addr_cells);
+
+            let kind = match child[0] & SPACE_MASK {
+                SPACE_IO => BarKind::Io,
+                SPACE_MEM32 => BarKind::Mem32,
+                SPACE_MEM64 if child[0] & PREFETCHABLE != 0 => BarKind::Mem64,
+                SPACE_MEM64 => BarKind::Mem32,
+                _ => return None,
+            };
+
+            Some(Window::new(
+                kind,
+                join_cells(&child[1..]),
+                join_cells(parent),
+                join_cells(size),
+            ))
+        })
+        .collect())
+}
+
+/// Parses `interrupt-map`, which routes INTx pins to the interrupt parent.
+fn parse_interrupt_map(dm: &DriverManager, node: &fdt_parser::Node<'static>) -> Result<IntxMap> {
+    let Some(map) = node.find_property("interrupt-map") else {
+        return Ok(IntxMap::default());
+    };
+
+    let mask = node
+        .find_property("interrupt-map-mask")
+        .map(|p| cells(p.raw_value()))
+        .unwrap_or_default();
+
+    let mask: [u32; 4] = mask.try_into().unwrap_or([u32::MAX; 4]);
+
+    // Only maps onto the host's own interrupt parent are supported.
+    let parent = node
+        .interrupt_parent()
+        .ok_or(ProbeError::NoParentInterrupt)?
+        .node;
+
+    let parent_phandle = parent.find_property("phandle").map(|p| p.u32());
+    let parent_addr_cells = cell_count(&parent, "#address-cells", 0);
+    let parent_int_cells = cell_count(&parent, "#interrupt-cells", 3);
+
+    let manager = dm
+        .find_by_name(parent.name)
+        .ok_or(ProbeError::Deferred)?
+        .as_interrupt_manager()
+        .ok_or(ProbeError::NotInterruptController)?;
+
+    let cells = cells(map.raw_value());
+    let entry_cells = 4 + 1 + parent_addr_cells + parent_int_cells;
+    let mut entries = Vec::new();
+
+    for entry in cells.chunks_exact(entry_cells) {
+        if parent_phandle.is_some_and(|phandle| phandle != entry[4]) {
+            continue;
+        }
+
+        let child: [u32; 4] = entry[..4].try_into().unwrap();
+        let spec = &entry[5 + parent_addr_cells..];
+
+        let config = manager.parse_fdt_interrupt_regs(&mut spec.iter().copied())?;
+
+        entries.push((child, config));
+    }
+
+    Ok(IntxMap { mask, entries })
+}
+
+fn pci_host_probe(
+    dm: &mut DriverManager,
+    d: DeviceDescriptor,
+    mechanism: ConfigMechanism,
+) -> Result<Arc<dyn Driver>> {
+    match d {
+        DeviceDescriptor::Fdt(fdt_node, _) => {
+            let region = fdt_node
+                .reg()
+                .ok_or(ProbeError::NoReg)?
+                .next()
+                .ok_or(ProbeError::NoReg)?;
+
+            let size = region.size.ok_or(ProbeError::NoRegSize)?;
+
+            let buses = match fdt_node.find_property("bus-range") {
+                Some(prop) => match cells(prop.raw_value())[..] {
+                    [start, end] if start <= end && end <= 0xff => start as u8..=end as u8,
+                    _ => return Err(KernelError::InvalidValue),
+                },
+                None => {
+                    let nr_buses = (size / mechanism.bus_size()).clamp(1, 256);
+                    0..=(nr_buses - 1) as u8
+                }
+            };
+
+            // The configuration space must cover every bus.
+            if buses.len() * mechanism.bus_size() > size {
+                return Err(KernelError::InvalidValue);
+            }
+
+            let segment = fdt_node
+                .find_property("linux,pci-domain")
+                .map(|p| p.u32() as u16)
+                .unwrap_or(0);
+
+            let windows = parse_ranges(&fdt_node)?;
+            let intx = parse_interrupt_map(dm, &fdt_node)?;
+
+            info!(
+                "PCI host {}: segment {segment}, buses {:02x}-{:02x}, {} windows",
+                fdt_node.name,
+                buses.start(),
+                buses.end(),
+                windows.len()
+            );
+
+            let host = PciHost::new(
+                fdt_node.name,
+                segment,
+                mechanism,
+                region.address,
+                buses,
+                windows,
+                intx,
+            )?;
+
+            PCI_BUS.lock_save_irq().add_host(host.clone())?;
+
+            Ok(host)
+        }
+    }
+}
+
+pub fn pci_host_generic_init(bus: &mut PlatformBus, _dm: &mut DriverManager) -> Result<()> {
+    bus.register_platform_driver(
+        DeviceMatchType::FdtCompatible("pci-host-ecam-generic"),
+        Box::new(|dm: &mut DriverManager, d: DeviceDescriptor| {
+            pci_host_probe(dm, d, ConfigMechanism::Ecam)
+        }),
+    );
+
+    bus.register_platform_driver(
+        DeviceMatchType::FdtCompatible("pci-host-cam-generic"),
+        Box::new(|dm: &mut DriverManager, d: DeviceDescriptor| {
+            pci_host_probe(dm, d, ConfigMechanism::Cam)
+        }),
+    );
+
+    Ok(())
+}
+
+kernel_driver!(pci_host_generic_init);
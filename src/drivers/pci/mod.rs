@@ -0,0 +1,172 @@
+//! The PCI bus.
+//!
+//! Host bridge drivers map their configuration space and register a
+//! [`PciHost`], whose buses are then scanned for functions. BARs that
+//! firmware left unassigned are given addresses from the host's windows.
+//!
+//! Drivers for PCI functions register a probe function against a vendor and
+//! device ID, or against a class code, from their init function. Once every
+//! device tree device has been probed, and with it every host bridge,
+//! [`probe_for_pci_devices`] binds the functions found to their drivers.
+
+use super::{DM, Driver, DriverManager};
+use crate::sync::SpinLock;
+use alloc::{boxed::Box, collections::btree_map::BTreeMap, sync::Arc, vec::Vec};
+use core::fmt::{self, Display};
+use libkernel::error::{KernelError, ProbeError, Result};
+use log::{error, warn};
+
+pub mod config;
+pub mod device;
+mod host;
+mod host_generic;
+mod msi;
+pub mod regs;
+
+pub use device::PciDevice;
+pub use host::{IntxMap, PciHost};
+pub use msi::IrqTypes;
+
+/// The location of a function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PciAddress {
+    pub segment: u16,
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl PciAddress {
+    /// The `phys.hi` cell of the function's device tree unit address.
+    fn devfn_cells(&self) -> u32 {
+        (self.bus as u32) << 16 | (self.device as u32) << 11 | (self.function as u32) << 8
+    }
+}
+
+impl Display for PciAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04x}:{:02x}:{:02x}.{}",
+            self.segment, self.bus, self.device, self.function
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PciMatchType {
+    Id {
+        vendor: u16,
+        device: u16,
+    },
+    /// A class and subclass, and optionally a programming interface.
+    Class {
+        class: u8,
+        subclass: u8,
+        prog_if: Option<u8>,
+    },
+}
+
+pub type PciProbeFn =
+    Box<dyn Fn(&mut DriverManager, Arc<PciDevice>) -> Result<Arc<dyn Driver>> + Send>;
+
+pub struct PciBus {
+    probers: BTreeMap<PciMatchType, Vec<PciProbeFn>>,
+    hosts: Vec<Arc<PciHost>>,
+    /// Functions not yet bound to a driver.
+    unbound: Vec<Arc<PciDevice>>,
+}
+
+impl PciBus {
+    pub const fn new() -> Self {
+        Self {
+            probers: BTreeMap::new(),
+            hosts: Vec::new(),
+            unbound: Vec::new(),
+        }
+    }
+
+    /// Called by driver `init` functions to register their ability to drive
+    /// certain functions.
+    pub fn register_pci_driver(&mut self, match_type: PciMatchType, probe_fn: PciProbeFn) {
+        self.probers.entry(match_type).or_default().push(probe_fn);
+    }
+
+    /// Adds a host bridge, and scans its buses.
+    pub fn add_host(&mut self, host: Arc<PciHost>) -> Result<()> {
+        if self.host_for_segment(host.segment).is_some() {
+            return Err(KernelError::InUse);
+        }
+
+        self.unbound.extend(host.scan());
+        self.hosts.push(host);
+
+        Ok(())
+    }
+
+    pub fn host_for_segment(&self, segment: u16) -> Option<Arc<PciHost>> {
+        self.hosts.iter().find(|h| h.segment == segment).cloned()
+    }
+
+    fn probe_device(
+        &self,
+        dm: &mut DriverManager,
+        dev: &Arc<PciDevice>,
+    ) -> Result<Option<Arc<dyn Driver>>> {
+        // Most specific first.
+        let matches = [
+            PciMatchType::Id {
+                vendor: dev.vendor_id,
+                device: dev.device_id,
+            },
+            PciMatchType::Class {
+                class: dev.class,
+                subclass: dev.subclass,
+                prog_if: Some(dev.prog_if),
+            },
+            PciMatchType::Class {
+                class: dev.class,
+                subclass: dev.subclass,
+                prog_if: None,
+            },
+        ];
+
+        for probe_fn in matches.iter().filter_map(|m| self.probers.get(m)).flatten() {
+            match probe_fn(dm, dev.clone()) {
+                Ok(driver) => {
+                    dm.insert_driver(driver.clone());
+                    return Ok(Some(driver));
+                }
+                Err(KernelError::Probe(ProbeError::NoMatch)) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// Binds drivers to any functions without one.
+pub fn probe_for_pci_devices() {
+    let mut driver_man = DM.lock_save_irq();
+    let mut bus = PCI_BUS.lock_save_irq();
+
+    let unbound = core::mem::take(&mut bus.unbound);
+
+    for dev in unbound {
+        match bus.probe_device(&mut driver_man, &dev) {
+            Ok(Some(_)) => {}
+            Ok(None) => bus.unbound.push(dev),
+            Err(KernelError::Probe(ProbeError::Deferred)) => {
+                warn!("Could not probe PCI device {dev} due to missing dependencies.");
+                bus.unbound.push(dev);
+            }
+            Err(e) => {
+                error!("Fatal error while probing PCI device {dev}: {e}");
+                bus.unbound.push(dev);
+            }
+        }
+    }
+}
+
+pub static PCI_BUS: SpinLock<PciBus> = SpinLock::new(PciBus::new());
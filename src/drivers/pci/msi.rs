@@ -0,0 +1,222 @@
+//! MSI and MSI-X setup.
+//!
+//! MSI gives a function a single message, programmed into its capability.
+//! MSI-X gives it a table of messages, held in one of its BARs. Either way,
+//! the messages come from the system [`MsiController`], and the resulting
+//! interrupts are claimed by the driver through the root interrupt
+//! controller, as for any other interrupt.
+//!
+//! [`MsiController`]: crate::interrupts::msi::MsiController
+
+use super::{
+    device::PciDevice,
+    regs::{CAP_ID_MSI, CAP_ID_MSIX, Command},
+};
+use crate::interrupts::{
+    InterruptConfig,
+    msi::{MsiVector, get_msi_controller},
+};
+use alloc::{vec, vec::Vec};
+use libkernel::error::{KernelError, ProbeError, Result};
+
+// MSI capability.
+const MSI_CTRL: usize = 2;
+const MSI_ADDR_LO: usize = 4;
+const MSI_ADDR_HI: usize = 8;
+const MSI_DATA_32: usize = 8;
+const MSI_DATA_64: usize = 12;
+
+const MSI_CTRL_ENABLE: u16 = 1 << 0;
+/// Multiple Message Enable: how many of the requested vectors are granted.
+const MSI_CTRL_MME_MASK: u16 = 0x7 << 4;
+const MSI_CTRL_64BIT: u16 = 1 << 7;
+
+// MSI-X capability.
+const MSIX_CTRL: usize = 2;
+const MSIX_TABLE: usize = 4;
+
+const MSIX_CTRL_TABLE_SIZE_MASK: u16 = 0x7ff;
+const MSIX_CTRL_FUNCTION_MASK: u16 = 1 << 14;
+const MSIX_CTRL_ENABLE: u16 = 1 << 15;
+
+const MSIX_TABLE_BIR_MASK: u32 = 0x7;
+const MSIX_ENTRY_SIZE: usize = 16;
+const MSIX_ENTRY_VECTOR_CTRL: usize = 12;
+
+bitflags::bitflags! {
+    /// The kinds of interrupt a driver can cope with.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct IrqTypes: u8 {
+        const INTX = 1 << 0;
+        const MSI = 1 << 1;
+        const MSIX = 1 << 2;
+    }
+}
+
+impl PciDevice {
+    /// Sets up between 1 and `max` interrupts for the function, trying
+    /// MSI-X, then MSI, then INTx, as allowed by `types`. The interrupts are
+    /// then claimed from the root interrupt controller.
+    pub fn alloc_irq_vectors(&self, max: usize, types: IrqTypes) -> Result<Vec<InterruptConfig>> {
+        if types.contains(IrqTypes::MSIX)
+            && let Ok(vectors) = self.enable_msix(max)
+        {
+            return Ok(vectors);
+        }
+
+        if types.contains(IrqTypes::MSI)
+            && let Ok(vector) = self.enable_msi()
+        {
+            return Ok(vec![vector]);
+        }
+
+        if types.contains(IrqTypes::INTX)
+            && let Some(intx) = self.legacy_interrupt()
+        {
+            return Ok(vec![intx]);
+        }
+
+        Err(KernelError::Probe(ProbeError::NoInterrupts))
+    }
+
+    /// Gives the function a single MSI, and switches it from INTx to MSI.
+    fn enable_msi(&self) -> Result<InterruptConfig> {
+        let cap = self
+            .find_capability(CAP_ID_MSI)
+            .ok_or(KernelError::NotSupported)?;
+
+        let controller = get_msi_controller().ok_or(KernelError::NotSupported)?;
+        let config = self.config();
+
+        self.disable_msi();
+
+        let vector = controller.alloc_msi()?;
+        let ctrl = config.read_u16(cap + MSI_CTRL);
+
+        if ctrl & MSI_CTRL_64BIT != 0 {
+            config.write_u32(cap + MSI_ADDR_LO, vector.message.address as u32);
+            config.write_u32(cap + MSI_ADDR_HI, (vector.message.address >> 32) as u32);
+            config.write_u16(cap + MSI_DATA_64, vector.message.data as u16);
+        } else if vector.message.address >> 32 == 0 {
+            config.write_u32(cap + MSI_ADDR_LO, vector.message.address as u32);
+            config.write_u16(cap + MSI_DATA_32, vector.message.data as u16);
+        } else {
+            controller.free_msi(vector);
+            return Err(KernelError::NotSupported);
+        }
+
+        config.write_u16(
+            cap + MSI_CTRL,
+            (ctrl & !MSI_CTRL_MME_MASK) | MSI_CTRL_ENABLE,
+        );
+
+        self.msi_vectors.lock_save_irq().push(vector);
+        self.set_command(self.command() | Command::INTX_DISABLE | Command::BUS_MASTER);
+
+        Ok(vector.config)
+    }
+
+    /// Gives the function up to `count` MSI-X vectors, returning the
+    /// interrupts for table entries `0..n`.
+    fn enable_msix(&self, count: usize) -> Result<Vec<InterruptConfig>> {
+        let cap = self
+            .find_capability(CAP_ID_MSIX)
+            .ok_or(KernelError::NotSupported)?;
+
+        let controller = get_msi_controller().ok_or(KernelError::NotSupported)?;
+        let config = self.config();
+
+        self.disable_msi();
+
+        let ctrl = config.read_u16(cap + MSIX_CTRL);
+        let table_size = (ctrl & MSIX_CTRL_TABLE_SIZE_MASK) as usize + 1;
+        let count = count.min(table_size);
+
+        let table = config.read_u32(cap + MSIX_TABLE);
+        let bir = (table & MSIX_TABLE_BIR_MASK) as usize;
+        let offset = (table & !MSIX_TABLE_BIR_MASK) as usize;
+
+        let table = self
+            .map_bar(bir)?
+            .add_bytes(offset)
+            .as_ptr_mut()
+            .cast::<u32>();
+
+        // Keep every vector masked until the table is written.
+        config.write_u16(
+            cap + MSIX_CTRL,
+            ctrl | MSIX_CTRL_ENABLE | MSIX_CTRL_FUNCTION_MASK,
+        );
+
+        let mut vectors = Vec::with_capacity(count);
+
+        for i in 0..count {
+            let vector = match controller.alloc_msi() {
+                Ok(vector) => vector,
+                // Make do with fewer vectors than asked for.
+                Err(_) if i > 0 => break,
+                Err(e) => {
+                    config.write_u16(cap + MSIX_CTRL, ctrl & !MSIX_CTRL_ENABLE);
+                    return Err(e);
+                }
+            };
+
+            // SAFETY: The table lies within the BAR mapped above, and has at
+            // least `count` entries.
+            unsafe {
+                let entry = table.byte_add(i * MSIX_ENTRY_SIZE);
+
+                entry.write_volatile(vector.message.address as u32);
+                entry
+                    .add(1)
+                    .write_volatile((vector.message.address >> 32) as u32);
+                entry.add(2).write_volatile(vector.message.data);
+                entry.byte_add(MSIX_ENTRY_VECTOR_CTRL).write_volatile(0);
+            }
+
+            vectors.push(vector);
+        }
+
+        config.write_u16(
+            cap + MSIX_CTRL,
+            (ctrl | MSIX_CTRL_ENABLE) & !MSIX_CTRL_FUNCTION_MASK,
+        );
+
+        self.set_command(self.command() | Command::INTX_DISABLE | Command::BUS_MASTER);
+
+        let configs = vectors.iter().map(|v| v.config).collect();
+        self.msi_vectors.lock_save_irq().extend(vectors);
+
+        Ok(configs)
+    }
+
+    /// Turns off MSI and MSI-X, returning the function to INTx, and frees
+    /// its vectors.
+    fn disable_msi(&self) {
+        let config = self.config();
+
+        if let Some(cap) = self.find_capability(CAP_ID_MSI) {
+            let ctrl = config.read_u16(cap + MSI_CTRL);
+            config.write_u16(cap + MSI_CTRL, ctrl & !MSI_CTRL_ENABLE);
+        }
+
+        if let Some(cap) = self.find_capability(CAP_ID_MSIX) {
+            let ctrl = config.read_u16(cap + MSIX_CTRL);
+            config.write_u16(cap + MSIX_CTRL, ctrl & !MSIX_CTRL_ENABLE);
+        }
+
+        let vectors: Vec<MsiVector> = self.msi_vectors.lock_save_irq().drain(..).collect();
+
+        if vectors.is_empty() {
+            return;
+        }
+
+        self.set_command(self.command() - Command::INTX_DISABLE);
+
+        if let Some(controller) = get_msi_controller() {
+            for vector in vectors {
+                controller.free_msi(vector);
+            }
+        }
+    }
+}
@@ -0,0 +1,38 @@
+//! Configuration space register offsets and fields.
+
+pub const VENDOR_ID: usize = 0x00;
+pub const DEVICE_ID: usize = 0x02;
+pub const COMMAND: usize = 0x04;
+pub const STATUS: usize = 0x06;
+pub const REVISION: usize = 0x08;
+pub const PROG_IF: usize = 0x09;
+pub const SUBCLASS: usize = 0x0a;
+pub const CLASS: usize = 0x0b;
+pub const HEADER_TYPE: usize = 0x0e;
+pub const BAR0: usize = 0x10;
+pub const CAP_PTR: usize = 0x34;
+pub const INTERRUPT_PIN: usize = 0x3d;
+
+pub const STATUS_CAP_LIST: u16 = 1 << 4;
+
+pub const HEADER_TYPE_MASK: u8 = 0x7f;
+pub const HEADER_TYPE_NORMAL: u8 = 0;
+pub const HEADER_MULTI_FUNCTION: u8 = 1 << 7;
+
+pub const BAR_IO: u32 = 1 << 0;
+pub const BAR_TYPE_MASK: u32 = 0x3 << 1;
+pub const BAR_TYPE_64: u32 = 0x2 << 1;
+pub const BAR_PREFETCHABLE: u32 = 1 << 3;
+
+pub const CAP_ID_MSI: u8 = 0x05;
+pub const CAP_ID_MSIX: u8 = 0x11;
+
+bitflags::bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Command: u16 {
+        const IO_SPACE = 1 << 0;
+        const MEMORY_SPACE = 1 << 1;
+        const BUS_MASTER = 1 << 2;
+        const INTX_DISABLE = 1 << 10;
+    }
+}
@@ -0,0 +1,81 @@
+//! The Armv8.5 random number generator (`FEAT_RNG`).
+//!
+//! CPUs implementing it provide the `RNDR` register, the equivalent of
+//! x86's `RDRAND`: each read returns 64 bits from a hardware generator which
+//! is reseeded from a true entropy source.
+
+use crate::{
+    drivers::{DriverManager, init::PlatformBus},
+    kernel::rand::{EntropySource, register_entropy_source},
+    kernel_driver,
+};
+use alloc::sync::Arc;
+use core::arch::asm;
+use libkernel::error::Result;
+use log::info;
+
+/// The `RNDR` field of `ID_AA64ISAR0_EL1`.
+const ISAR0_RNDR_SHIFT: u64 = 60;
+const ISAR0_RNDR_MASK: u64 = 0xf;
+
+/// How many times a failed read is retried before giving up on this poll.
+const RNDR_RETRIES: usize = 10;
+
+fn has_rndr() -> bool {
+    let isar0: u64;
+
+    unsafe { asm!("mrs {}, ID_AA64ISAR0_EL1", out(reg) isar0, options(nostack, nomem)) };
+
+    (isar0 >> ISAR0_RNDR_SHIFT) & ISAR0_RNDR_MASK != 0
+}
+
+/// Reads `RNDR`, which fails if the generator couldn't produce a number in
+/// reasonable time.
+fn read_rndr() -> Option<u64> {
+    let val: u64;
+    let ok: u64;
+
+    // RNDR is named by its encoding, so as not to need the `rand` target
+    // feature. It clears the Z flag on success.
+    unsafe {
+        asm!(
+            "mrs {val}, s3_3_c2_c4_0",
+            "cset {ok}, ne",
+            val = out(reg) val,
+            ok = out(reg) ok,
+            options(nostack, nomem),
+        );
+    }
+
+    (ok != 0).then_some(val)
+}
+
+struct ArmRndr;
+
+impl EntropySource for ArmRndr {
+    fn get_entropy(&self, buf: &mut [u8]) -> (usize, usize) {
+        let mut written = 0;
+
+        for chunk in buf.chunks_mut(size_of::<u64>()) {
+            let Some(val) = (0..RNDR_RETRIES).find_map(|_| read_rndr()) else {
+                break;
+            };
+
+            chunk.copy_from_slice(&val.to_le_bytes()[..chunk.len()]);
+            written += chunk.len();
+        }
+
+        (written, written * 8)
+    }
+}
+
+fn arm_rndr_init(_bus: &mut PlatformBus, _dm: &mut DriverManager) -> Result<()> {
+    if has_rndr() {
+        info!("rng: using FEAT_RNG RNDR");
+        register_entropy_source(Arc::new(ArmRndr));
+    }
+
+    Ok(())
+}
+
+kernel_driver!(arm_rndr_init);
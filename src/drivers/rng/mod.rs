@@ -1 +1,2 @@
+pub mod arm_rndr;
 pub mod virtio;
@@ -1,10 +1,11 @@
-use crate::drivers::virtio_hal::VirtioHal;
+use crate::drivers::virtio_hal::{VIRTIO_PCI_VENDOR, VirtioHal, attach_fdt_node, pci_transport};
 use crate::sync::SpinLock;
 use crate::{
     arch::ArchImpl,
     drivers::{
         Driver, DriverManager,
         init::PlatformBus,
+        pci::{PCI_BUS, PciDevice, PciMatchType},
         probe::{DeviceDescriptor, DeviceMatchType},
     },
     kernel::rand::{EntropySource, register_entropy_source},
@@ -24,13 +25,13 @@ use log::info;
 use virtio_drivers::{
     device::rng::VirtIORng,
     transport::{
-        DeviceType, Transport,
+        DeviceType, SomeTransport, Transport,
         mmio::{MmioTransport, VirtIOHeader},
     },
 };
 
 pub struct VirtioRngDriver {
-    rng: SpinLock<VirtIORng<VirtioHal, MmioTransport<'static>>>,
+    rng: SpinLock<VirtIORng<VirtioHal, SomeTransport<'static>>>,
 }
 
 impl Driver for VirtioRngDriver {
@@ -82,20 +83,37 @@ fn virtio_rng_probe(_dm: &mut DriverManager, d: DeviceDescriptor) -> Result<Arc<
                 return Err(KernelError::Probe(ProbeError::NoMatch));
             }
 
+            attach_fdt_node(&fdt_node)?;
+
             info!("virtio-rng found (node {})", fdt_node.name);
 
-            let rng = VirtIORng::<VirtioHal, _>::new(transport)
-                .map_err(|_| KernelError::Other("virtio-rng init failed"))?;
+            bring_up(SomeTransport::Mmio(transport))
+        }
+    }
+}
 
-            let driver = Arc::new(VirtioRngDriver {
-                rng: SpinLock::new(rng),
-            });
+fn virtio_rng_pci_probe(_dm: &mut DriverManager, pci: Arc<PciDevice>) -> Result<Arc<dyn Driver>> {
+    let transport = pci_transport(&pci)?;
 
-            register_entropy_source(driver.clone());
+    info!("virtio-rng found (PCI {})", pci.addr);
 
-            Ok(driver)
-        }
-    }
+    // Requests are polled for, so no interrupt is needed.
+    pci.enable_bus_master();
+
+    bring_up(SomeTransport::Pci(transport))
+}
+
+fn bring_up(transport: SomeTransport<'static>) -> Result<Arc<dyn Driver>> {
+    let rng = VirtIORng::<VirtioHal, _>::new(transport)
+        .map_err(|_| KernelError::Other("virtio-rng init failed"))?;
+
+    let driver = Arc::new(VirtioRngDriver {
+        rng: SpinLock::new(rng),
+    });
+
+    register_entropy_source(driver.clone());
+
+    Ok(driver)
 }
 
 fn virtio_rng_init(bus: &mut PlatformBus, _dm: &mut DriverManager) -> Result<()> {
@@ -109,6 +127,19 @@ fn virtio_rng_init(bus: &mut PlatformBus, _dm: &mut DriverManager) -> Result<()>
         Box::new(virtio_rng_probe),
     );
 
+    let mut pci = PCI_BUS.lock_save_irq();
+
+    // Transitional and modern device IDs.
+    for device in [0x1005, 0x1044] {
+        pci.register_pci_driver(
+            PciMatchType::Id {
+                vendor: VIRTIO_PCI_VENDOR,
+                device,
+            },
+            Box::new(virtio_rng_pci_probe),
+        );
+    }
+
     Ok(())
 }
 
@@ -0,0 +1,266 @@
+//! Motorola MC146818-compatible "CMOS" RTCs.
+//!
+//! This is the PC's RTC. The chip's registers are reached through an index
+//! register and a data register; on x86 these sit at I/O ports 0x70 and 0x71.
+//! aarch64 has no I/O port instructions, so the pair is memory-mapped here,
+//! as it is when the chip is described by a device tree, or behind a PCI
+//! I/O window.
+//!
+//! The time is kept as calendar fields, in BCD or binary and with a 12- or
+//! 24-hour clock depending on how firmware set the chip up, and is only
+//! stable while no update cycle is in progress.
+
+use super::{Rtc, RtcTime, set_rtc_driver};
+use crate::{
+    arch::ArchImpl,
+    drivers::{
+        Driver, DriverManager,
+        init::PlatformBus,
+        probe::{DeviceDescriptor, DeviceMatchType},
+    },
+    kernel_driver,
+    sync::SpinLock,
+};
+use alloc::{boxed::Box, sync::Arc};
+use core::{hint::spin_loop, time::Duration};
+use libkernel::{
+    error::{KernelError, ProbeError, Result},
+    memory::{
+        address::{PA, VA},
+        proc_vm::address_space::{KernAddressSpace, VirtualMemory},
+        region::PhysMemoryRegion,
+    },
+};
+
+const INDEX: usize = 0;
+const DATA: usize = 1;
+
+// Register indices.
+const SECONDS: u8 = 0x00;
+const MINUTES: u8 = 0x02;
+const HOURS: u8 = 0x04;
+const DAY_OF_MONTH: u8 = 0x07;
+const MONTH: u8 = 0x08;
+const YEAR: u8 = 0x09;
+const STATUS_A: u8 = 0x0a;
+const STATUS_B: u8 = 0x0b;
+
+const STATUS_A_UIP: u8 = 1 << 7;
+
+const STATUS_B_24H: u8 = 1 << 1;
+const STATUS_B_BINARY: u8 = 1 << 2;
+/// Stops updates while the time is being set.
+const STATUS_B_SET: u8 = 1 << 7;
+
+const HOURS_PM: u8 = 1 << 7;
+
+/// Setting bit 7 of the index register masks NMIs on PCs; leave it clear.
+const INDEX_MASK: u8 = 0x7f;
+
+/// The chip only stores a two-digit year. Years before this are taken to be
+/// in the 2000s.
+const CENTURY_PIVOT: i32 = 70;
+
+struct CmosRegs {
+    base: VA,
+}
+
+unsafe impl Send for CmosRegs {}
+
+impl CmosRegs {
+    fn read(&mut self, reg: u8) -> u8 {
+        // SAFETY: The index and data registers were mapped as device memory
+        // at probe time.
+        unsafe {
+            core::ptr::write_volatile(
+                self.base.add_bytes(INDEX).as_ptr_mut() as *mut u8,
+                reg & INDEX_MASK,
+            );
+            core::ptr::read_volatile(self.base.add_bytes(DATA).as_ptr_mut() as *const u8)
+        }
+    }
+
+    fn write(&mut self, reg: u8, val: u8) {
+        // SAFETY: As for `read`.
+        unsafe {
+            core::ptr::write_volatile(
+                self.base.add_bytes(INDEX).as_ptr_mut() as *mut u8,
+                reg & INDEX_MASK,
+            );
+            core::ptr::write_volatile(self.base.add_bytes(DATA).as_ptr_mut() as *mut u8, val);
+        }
+    }
+
+    /// Reads the raw time registers, in the chip's own format.
+    fn read_raw(&mut self) -> [u8; 6] {
+        while self.read(STATUS_A) & STATUS_A_UIP != 0 {
+            spin_loop();
+        }
+
+        [SECONDS, MINUTES, HOURS, DAY_OF_MONTH, MONTH, YEAR].map(|reg| self.read(reg))
+    }
+}
+
+fn from_bcd(val: u8) -> u8 {
+    (val >> 4) * 10 + (val & 0xf)
+}
+
+fn to_bcd(val: u8) -> u8 {
+    ((val / 10) << 4) | (val % 10)
+}
+
+pub struct Cmos {
+    regs: SpinLock<CmosRegs>,
+    name: &'static str,
+}
+
+impl Rtc for Cmos {
+    fn time(&self) -> Option<Duration> {
+        let mut regs = self.regs.lock_save_irq();
+
+        // An update may start between checking UIP and reading the fields, so
+        // read until two consecutive reads agree.
+        let mut raw = regs.read_raw();
+
+        loop {
+            let again = regs.read_raw();
+
+            if again == raw {
+                break;
+            }
+
+            raw = again;
+        }
+
+        let status = regs.read(STATUS_B);
+        let field = |val: u8| {
+            if status & STATUS_B_BINARY != 0 {
+                val
+            } else {
+                from_bcd(val)
+            }
+        };
+
+        let [sec, min, hour, mday, mon, year] = raw;
+
+        let pm = status & STATUS_B_24H == 0 && hour & HOURS_PM != 0;
+        let hour = field(hour & !HOURS_PM) as i32;
+        let hour = match (status & STATUS_B_24H != 0, pm) {
+            (true, _) => hour,
+            (false, false) => hour % 12,
+            (false, true) => hour % 12 + 12,
+        };
+
+        let year = field(year) as i32;
+
+        RtcTime {
+            tm_sec: field(sec) as i32,
+            tm_min: field(min) as i32,
+            tm_hour: hour,
+            tm_mday: field(mday) as i32,
+            tm_mon: field(mon) as i32 - 1,
+            tm_year: if year < CENTURY_PIVOT {
+                year + 100
+            } else {
+                year
+            },
+            ..Default::default()
+        }
+        .to_unix()
+        .ok()
+    }
+
+    fn set_time(&self, time: Duration) -> Result<()> {
+        let tm = RtcTime::from_unix(time);
+
+        if !(1970..2070).contains(&(tm.tm_year + 1900)) {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let mut regs = self.regs.lock_save_irq();
+        let status = regs.read(STATUS_B);
+        let field = |val: i32| {
+            if status & STATUS_B_BINARY != 0 {
+                val as u8
+            } else {
+                to_bcd(val as u8)
+            }
+        };
+
+        let hour = if status & STATUS_B_24H != 0 {
+            field(tm.tm_hour)
+        } else {
+            let pm = if tm.tm_hour >= 12 { HOURS_PM } else { 0 };
+            let hour = match tm.tm_hour % 12 {
+                0 => 12,
+                h => h,
+            };
+
+            field(hour) | pm
+        };
+
+        regs.write(STATUS_B, status | STATUS_B_SET);
+
+        for (reg, val) in [
+            (SECONDS, field(tm.tm_sec)),
+            (MINUTES, field(tm.tm_min)),
+            (HOURS, hour),
+            (DAY_OF_MONTH, field(tm.tm_mday)),
+            (MONTH, field(tm.tm_mon + 1)),
+            (YEAR, field(tm.tm_year % 100)),
+        ] {
+            regs.write(reg, val);
+        }
+
+        regs.write(STATUS_B, status & !STATUS_B_SET);
+
+        Ok(())
+    }
+}
+
+impl Driver for Cmos {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+pub fn cmos_probe(_dm: &mut DriverManager, d: DeviceDescriptor) -> Result<Arc<dyn Driver>> {
+    match d {
+        DeviceDescriptor::Fdt(fdt_node, _flags) => {
+            let region = fdt_node
+                .reg()
+                .ok_or(ProbeError::NoReg)?
+                .next()
+                .ok_or(ProbeError::NoReg)?;
+
+            let size = region.size.ok_or(ProbeError::NoRegSize)?;
+
+            let mem =
+                ArchImpl::kern_address_space()
+                    .lock_save_irq()
+                    .map_mmio(PhysMemoryRegion::new(
+                        PA::from_value(region.address as usize),
+                        size,
+                    ))?;
+
+            let dev = Arc::new(Cmos {
+                regs: SpinLock::new(CmosRegs { base: mem }),
+                name: fdt_node.name,
+            });
+
+            set_rtc_driver(dev.clone());
+            Ok(dev)
+        }
+    }
+}
+
+pub fn cmos_init(bus: &mut PlatformBus, _dm: &mut DriverManager) -> Result<()> {
+    bus.register_platform_driver(
+        DeviceMatchType::FdtCompatible("motorola,mc146818"),
+        Box::new(cmos_probe),
+    );
+
+    Ok(())
+}
+
+kernel_driver!(cmos_init);
@@ -0,0 +1,101 @@
+//! `/dev/rtc0`, giving userspace (e.g. `hwclock`) access to the RTC.
+
+use super::{RtcTime, get_rtc, sync_system_clock};
+use crate::{
+    drivers::{
+        CharDriver, DriverManager, OpenableDevice, ReservedMajors, fs::dev::devfs,
+        init::PlatformBus,
+    },
+    fs::{
+        fops::FileOps,
+        open_file::{FileCtx, OpenFile},
+    },
+    kernel_driver,
+    memory::uaccess::{UserCopyable, copy_from_user, copy_to_user},
+};
+use alloc::{boxed::Box, string::ToString, sync::Arc};
+use async_trait::async_trait;
+use libkernel::{
+    driver::CharDevDescriptor,
+    error::{FsError, KernelError, Result},
+    fs::{OpenFlags, attr::FilePermissions},
+    memory::address::{TUA, UA},
+};
+
+const RTC_RD_TIME: usize = 0x8024_7009;
+const RTC_SET_TIME: usize = 0x4024_700a;
+
+unsafe impl UserCopyable for RtcTime {}
+
+struct RtcFile;
+
+#[async_trait]
+impl FileOps for RtcFile {
+    async fn readat(&mut self, _buf: UA, _count: usize, _offset: u64) -> Result<usize> {
+        // Alarm and update interrupts aren't supported, so there is never
+        // anything to read.
+        Err(KernelError::NotSupported)
+    }
+
+    async fn writeat(&mut self, _buf: UA, _count: usize, _offset: u64) -> Result<usize> {
+        Err(KernelError::NotSupported)
+    }
+
+    async fn ioctl(&mut self, _ctx: &mut FileCtx, request: usize, argp: usize) -> Result<usize> {
+        let rtc = get_rtc().ok_or(FsError::NoDevice)?;
+
+        match request {
+            RTC_RD_TIME => {
+                let time = rtc.time().ok_or(KernelError::InvalidValue)?;
+
+                copy_to_user(TUA::from_value(argp), RtcTime::from_unix(time)).await?;
+                Ok(0)
+            }
+            RTC_SET_TIME => {
+                let tm: RtcTime = copy_from_user(TUA::from_value(argp)).await?;
+
+                rtc.set_time(tm.to_unix()?)?;
+
+                // The system clock follows the RTC; don't wait for the next
+                // resync to catch up.
+                sync_system_clock();
+                Ok(0)
+            }
+            _ => Err(KernelError::NotATty),
+        }
+    }
+}
+
+struct RtcDev;
+
+impl OpenableDevice for RtcDev {
+    fn open(&self, flags: OpenFlags) -> Result<Arc<OpenFile>> {
+        Ok(Arc::new(OpenFile::new(Box::new(RtcFile), flags)))
+    }
+}
+
+struct RtcCharDev;
+
+impl CharDriver for RtcCharDev {
+    fn get_device(&self, minor: u64) -> Option<Arc<dyn OpenableDevice>> {
+        (minor == 0).then(|| Arc::new(RtcDev) as _)
+    }
+}
+
+/// Creates `/dev/rtc0`, once an RTC has been found.
+pub(super) fn create_node() -> Result<()> {
+    devfs().mknod(
+        "rtc0".to_string(),
+        CharDevDescriptor {
+            major: ReservedMajors::Rtc as _,
+            minor: 0,
+        },
+        FilePermissions::from_bits_retain(0o600),
+    )
+}
+
+pub fn rtc_dev_init(_bus: &mut PlatformBus, dm: &mut DriverManager) -> Result<()> {
+    dm.register_char_driver(ReservedMajors::Rtc as _, Arc::new(RtcCharDev))
+}
+
+kernel_driver!(rtc_dev_init);
@@ -2,20 +2,36 @@
 //!
 //! RTCs often differ in how they represent time, so the idea is to return a [`Duration`] since the Unix epoch,
 //! with each driver responsible for converting/handling hardware bugs.
+//!
+//! The RTC seeds `CLOCK_REALTIME` at boot, and the system clock is then
+//! periodically resynchronised from it to correct drift. Setting the system
+//! clock also sets the RTC, so that a resync doesn't undo the change.
 
+pub mod cmos;
+mod dev;
 pub mod pl031;
 
-use crate::sync::OnceLock;
+use crate::{
+    clock::realtime::set_date, drivers::timer::sleep, sched::spawn_kernel_task, sync::OnceLock,
+};
 use alloc::sync::Arc;
 use core::time::Duration;
+use libkernel::{
+    error::{KernelError, Result},
+    sync::executor::Priority,
+};
+use log::warn;
+
+/// How often the system clock is resynchronised from the RTC. This is the
+/// interval Linux uses to sync the RTC the other way, from NTP.
+const RESYNC_INTERVAL: Duration = Duration::from_secs(11 * 60);
 
 pub trait Rtc: Send + Sync {
     /// Gets the current RTC time as a `Duration` since the Unix epoch.
     fn time(&self) -> Option<Duration>;
 
     /// Sets the RTC time. The provided `Duration` should represent the time since the Unix epoch.
-    #[expect(unused)]
-    fn set_time(&mut self, time: Duration) -> libkernel::error::Result<()>;
+    fn set_time(&self, time: Duration) -> Result<()>;
 }
 
 pub static RTC_DRIVER: OnceLock<Arc<dyn Rtc>> = OnceLock::new();
@@ -25,5 +41,183 @@ pub fn get_rtc() -> Option<&'static Arc<dyn Rtc>> {
 }
 
 fn set_rtc_driver(driver: Arc<dyn Rtc>) -> bool {
-    RTC_DRIVER.set(driver).is_ok()
+    if RTC_DRIVER.set(driver).is_err() {
+        return false;
+    }
+
+    if let Err(e) = dev::create_node() {
+        warn!("Could not create /dev/rtc0: {e}");
+    }
+
+    true
+}
+
+/// Sets the system clock from the RTC, if there is one.
+pub fn sync_system_clock() {
+    if let Some(time) = get_rtc().and_then(|rtc| rtc.time()) {
+        set_date(time);
+    }
+}
+
+/// Sets the RTC, if there is one, to match a new system time.
+pub fn update_rtc(time: Duration) {
+    if let Some(rtc) = get_rtc()
+        && let Err(e) = rtc.set_time(time)
+    {
+        warn!("Could not set the RTC: {e}");
+    }
+}
+
+/// Seeds the system clock from the RTC and keeps it in step from then on.
+pub fn rtc_clock_init() {
+    if get_rtc().is_none() {
+        return;
+    }
+
+    sync_system_clock();
+
+    spawn_kernel_task(Priority::Background, async {
+        loop {
+            sleep(RESYNC_INTERVAL).await;
+            sync_system_clock();
+        }
+    });
+}
+
+/// `struct rtc_time`: a broken-down UTC time, as used by the RTC ioctls and
+/// by RTCs which count in calendar fields rather than seconds.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RtcTime {
+    pub tm_sec: i32,
+    pub tm_min: i32,
+    pub tm_hour: i32,
+    pub tm_mday: i32,
+    /// Months since January.
+    pub tm_mon: i32,
+    /// Years since 1900.
+    pub tm_year: i32,
+    pub tm_wday: i32,
+    pub tm_yday: i32,
+    pub tm_isdst: i32,
+}
+
+// Calendar conversions after Howard Hinnant's `days_from_civil` and
+// `civil_from_days`, which work on a year starting in March so that the leap
+// day falls at its end.
+
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+
+    era * 146_097 + doe - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let doe = days.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = (mp + 2) % 12 + 1;
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    (year, month, day)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    days_from_civil(year + month / 12, month % 12 + 1, 1) - days_from_civil(year, month, 1)
+}
+
+impl RtcTime {
+    pub fn from_unix(time: Duration) -> Self {
+        let secs = time.as_secs() as i64;
+        let days = secs.div_euclid(86_400);
+        let secs_of_day = secs.rem_euclid(86_400);
+        let (year, month, day) = civil_from_days(days);
+
+        Self {
+            tm_sec: (secs_of_day % 60) as i32,
+            tm_min: (secs_of_day / 60 % 60) as i32,
+            tm_hour: (secs_of_day / 3600) as i32,
+            tm_mday: day as i32,
+            tm_mon: (month - 1) as i32,
+            tm_year: (year - 1900) as i32,
+            // 1970-01-01 was a Thursday.
+            tm_wday: (days + 4).rem_euclid(7) as i32,
+            tm_yday: (days - days_from_civil(year, 1, 1)) as i32,
+            tm_isdst: 0,
+        }
+    }
+
+    /// Converts the time to a `Duration` since the Unix epoch. The weekday
+    /// and day of the year are ignored.
+    pub fn to_unix(self) -> Result<Duration> {
+        let year = self.tm_year as i64 + 1900;
+        let month = self.tm_mon as i64 + 1;
+
+        if year < 1970
+            || !(1..=12).contains(&month)
+            || !(1..=days_in_month(year, month)).contains(&(self.tm_mday as i64))
+            || !(0..24).contains(&self.tm_hour)
+            || !(0..60).contains(&self.tm_min)
+            || !(0..60).contains(&self.tm_sec)
+        {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let days = days_from_civil(year, month, self.tm_mday as i64);
+        let secs = days * 86_400
+            + self.tm_hour as i64 * 3600
+            + self.tm_min as i64 * 60
+            + self.tm_sec as i64;
+
+        Ok(Duration::from_secs(secs as u64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use moss_macros::ktest;
+
+    #[ktest]
+    fn rtc_time_round_trips() {
+        // 2024-02-29 12:34:56 UTC, a Thursday.
+        let time = Duration::from_secs(1_709_210_096);
+        let tm = RtcTime::from_unix(time);
+
+        assert_eq!(
+            tm,
+            RtcTime {
+                tm_sec: 56,
+                tm_min: 34,
+                tm_hour: 12,
+                tm_mday: 29,
+                tm_mon: 1,
+                tm_year: 124,
+                tm_wday: 4,
+                tm_yday: 59,
+                tm_isdst: 0,
+            }
+        );
+        assert_eq!(tm.to_unix().unwrap(), time);
+    }
+
+    #[ktest]
+    fn rtc_time_rejects_invalid_dates() {
+        let tm = RtcTime {
+            tm_mday: 29,
+            tm_mon: 1,
+            tm_year: 123,
+            ..Default::default()
+        };
+
+        assert!(tm.to_unix().is_err());
+    }
 }
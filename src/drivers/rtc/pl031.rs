@@ -4,17 +4,18 @@ use crate::drivers::probe::{DeviceDescriptor, DeviceMatchType};
 use crate::drivers::rtc::{Rtc, set_rtc_driver};
 use crate::drivers::{Driver, DriverManager};
 use crate::kernel_driver;
+use crate::sync::SpinLock;
 use alloc::boxed::Box;
 use alloc::sync::Arc;
 use core::time::Duration;
-use libkernel::error::{ProbeError, Result};
+use libkernel::error::{KernelError, ProbeError, Result};
 use libkernel::memory::address::{PA, VA};
 use libkernel::memory::proc_vm::address_space::{KernAddressSpace, VirtualMemory};
 use libkernel::memory::region::PhysMemoryRegion;
 
 /// Driver for a PL031 real-time clock.
 pub struct PL031 {
-    inner: arm_pl031::Rtc,
+    inner: SpinLock<arm_pl031::Rtc>,
 }
 
 impl PL031 {
@@ -22,17 +23,25 @@ impl PL031 {
     /// given base address.
     pub fn new(base_addr: VA) -> Self {
         let rtc = unsafe { arm_pl031::Rtc::new(base_addr.as_ptr_mut() as _) };
-        Self { inner: rtc }
+        Self {
+            inner: SpinLock::new(rtc),
+        }
     }
 }
 
 impl Rtc for PL031 {
     fn time(&self) -> Option<Duration> {
-        Some(Duration::new(self.inner.get_unix_timestamp() as u64, 0))
+        Some(Duration::new(
+            self.inner.lock_save_irq().get_unix_timestamp() as u64,
+            0,
+        ))
     }
 
-    fn set_time(&mut self, time: Duration) -> libkernel::error::Result<()> {
-        self.inner.set_unix_timestamp(time.as_secs() as _);
+    fn set_time(&self, time: Duration) -> libkernel::error::Result<()> {
+        // The counter is 32 bits wide, so it can't go beyond 2106.
+        let secs = u32::try_from(time.as_secs()).map_err(|_| KernelError::InvalidValue)?;
+
+        self.inner.lock_save_irq().set_unix_timestamp(secs);
         Ok(())
     }
 }
@@ -1,10 +1,8 @@
-use core::time::Duration;
-
 use aarch64_cpu::registers::{
     CNTFRQ_EL0, CNTP_CTL_EL0, CNTP_CVAL_EL0, CNTPCT_EL0, Readable, Writeable,
 };
 use alloc::{boxed::Box, sync::Arc};
-use libkernel::error::Result;
+use libkernel::error::{KernelError, Result};
 use log::warn;
 
 use crate::{
@@ -63,7 +61,16 @@ fn armv8_timer_probe(dm: &mut DriverManager, d: DeviceDescriptor) -> Result<Arc<
                 .as_interrupt_manager()
                 .ok_or(NotInterruptController)?;
 
-            let freq = CNTFRQ_EL0.get();
+            // CNTFRQ_EL0 is set by firmware, which sometimes gets it wrong; the
+            // device tree can override it.
+            let freq = fdt_node
+                .find_property("clock-frequency")
+                .map(|p| p.u32() as u64)
+                .unwrap_or_else(|| CNTFRQ_EL0.get());
+
+            if freq == 0 {
+                return Err(KernelError::InvalidValue);
+            }
 
             let mut el1_phys_timer_interrupt = None;
 
@@ -84,8 +91,6 @@ fn armv8_timer_probe(dm: &mut DriverManager, d: DeviceDescriptor) -> Result<Arc<
                     freq,
                 });
 
-                base_driver.schedule_interrupt(Some(base_driver.now() + Duration::from_secs(5)));
-
                 SysTimer::from_driver(base_driver)
             })?;
 
@@ -93,6 +98,8 @@ fn armv8_timer_probe(dm: &mut DriverManager, d: DeviceDescriptor) -> Result<Arc<
                 warn!("Failed to set system timer");
             }
 
+            sys_timer.kick_current_cpu();
+
             Ok(sys_timer)
         }
     }
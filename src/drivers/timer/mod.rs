@@ -1,4 +1,5 @@
 use super::Driver;
+use crate::arch::ArchImpl;
 use crate::interrupts::{InterruptDescriptor, InterruptHandler};
 use crate::per_cpu_private;
 use crate::process::Tid;
@@ -6,11 +7,11 @@ use crate::sync::OnceLock;
 use alloc::boxed::Box;
 use alloc::{collections::binary_heap::BinaryHeap, sync::Arc};
 use core::{
-    future::poll_fn,
     ops::{Add, Sub},
-    task::{Poll, Waker},
     time::Duration,
 };
+use libkernel::sync::timeout::Timeout;
+use libkernel::sync::timer::Timers;
 
 pub mod armv8_arch;
 
@@ -71,9 +72,6 @@ impl Ord for Instant {
 }
 
 enum WakeupKind {
-    ///  This scheduled wake up is for an async task.
-    Task(Waker),
-
     /// This wake up is for the kernel's preemption mechanism.
     Preempt,
 
@@ -117,8 +115,11 @@ impl Add<Duration> for Instant {
     type Output = Self;
 
     fn add(self, rhs: Duration) -> Self::Output {
+        // Round up, so that a deadline computed as `now + duration` is never
+        // reached before `duration` has passed.
         let secs_tick = rhs.as_secs() * self.freq;
-        let nsecs_tick = ((self.freq as u128 * rhs.subsec_nanos() as u128) / 1_000_000_000) as u64;
+        let nsecs_tick =
+            (self.freq as u128 * rhs.subsec_nanos() as u128).div_ceil(1_000_000_000) as u64;
 
         Self {
             ticks: self.ticks + secs_tick + nsecs_tick,
@@ -151,6 +152,10 @@ impl PartialOrd for Instant {
     }
 }
 
+/// A per-CPU, one-shot deadline timer.
+///
+/// There is no periodic tick: the timer is programmed for the next event on
+/// each CPU, and left off when there is none, so idle CPUs aren't woken.
 pub trait HwTimer: Send + Sync + Driver {
     /// Return an instant that represents this instant.
     fn now(&self) -> Instant;
@@ -180,7 +185,6 @@ impl InterruptHandler for SysTimer {
                 let event = wake_q.pop().unwrap(); // We know it's there from peek()
 
                 match event.what {
-                    WakeupKind::Task(waker) => waker.wake(),
                     WakeupKind::Preempt => {
                         // Do nothing, the IRQ return-to-userspace code will
                         // call schedule() for us.
@@ -201,15 +205,13 @@ impl InterruptHandler for SysTimer {
             }
         }
 
-        // Always re-arm: either next task/event, or a periodic/preemption tick.
-        let next_deadline = wake_q.peek().map(|e| e.when).or_else(|| {
-            // fallback: schedule a preemption tick in 50 ms
-            // TODO: Remove when feeling more secure about scheduling
-            let when = self.driver.now() + Duration::from_millis(50);
-            Some(when)
-        });
+        drop(wake_q);
+
+        TIMERS.expire();
 
-        self.driver.schedule_interrupt(next_deadline);
+        // With nothing left to wait for, the timer is switched off until
+        // something is scheduled.
+        self.driver.schedule_interrupt(self.next_event());
     }
 }
 
@@ -218,6 +220,14 @@ impl SysTimer {
         self.driver.now() - self.start_time
     }
 
+    /// Returns the length of a tick of the hardware timer, rounded up to a
+    /// whole nanosecond.
+    pub fn resolution(&self) -> Duration {
+        let freq = self.driver.now().freq;
+
+        Duration::from_nanos(1_000_000_000u64.div_ceil(freq))
+    }
+
     fn from_driver(driver: Arc<dyn HwTimer>) -> Self {
         Self {
             start_time: driver.now(),
@@ -225,30 +235,25 @@ impl SysTimer {
         }
     }
 
-    pub async fn sleep(&self, duration: Duration) -> () {
-        let when = self.driver.now() + duration;
-
-        poll_fn(|cx| {
-            if self.driver.now() >= when {
-                Poll::Ready(())
-            } else {
-                let mut wakeup_q = WAKEUP_Q.borrow_mut();
-
-                wakeup_q.push(WakeupEvent {
-                    when,
-                    what: WakeupKind::Task(cx.waker().clone()),
-                });
-
-                // After pushing, we must update the hardware timer in case our
-                // new event is the earliest one.
-                if let Some(next_event) = wakeup_q.peek() {
-                    self.driver.schedule_interrupt(Some(next_event.when));
-                }
+    /// Returns the earliest of this CPU's wake up events and the next event
+    /// of the kernel's [`TIMERS`].
+    fn next_event(&self) -> Option<Instant> {
+        let timers = TIMERS.next_deadline().map(|at| self.start_time + at);
+
+        WAKEUP_Q
+            .borrow()
+            .peek()
+            .map(|e| e.when)
+            .into_iter()
+            .chain(timers)
+            .min()
+    }
 
-                Poll::Pending
-            }
-        })
-        .await;
+    /// Programs the hardware timer for the next event, if there is one.
+    fn rearm(&self) {
+        if let Some(when) = self.next_event() {
+            self.driver.schedule_interrupt(Some(when));
+        }
     }
 
     pub fn schedule_timer(
@@ -265,11 +270,11 @@ impl SysTimer {
             what: WakeupKind::Timer(tid, id, callback),
         });
 
+        drop(wakeup_q);
+
         // After pushing, we must update the hardware timer in case our
         // new event is the earliest one.
-        if let Some(next_event) = wakeup_q.peek() {
-            self.driver.schedule_interrupt(Some(next_event.when));
-        }
+        self.rearm();
     }
 
     pub fn remove_scheduled_timer(&self, tid: Tid, id: u64) {
@@ -284,42 +289,39 @@ impl SysTimer {
             }
         });
 
+        drop(wakeup_q);
+
         // After removing, we must update the hardware timer in case we removed
         // the earliest event.
-        if let Some(next_event) = wakeup_q.peek() {
-            self.driver.schedule_interrupt(Some(next_event.when));
-        }
+        self.rearm();
     }
 
-    /// Schedule a preemption event for the current CPU.
+    /// Schedule a preemption event for the current CPU, replacing any earlier
+    /// one.
     pub fn schedule_preempt(&self, when: Instant) {
         let mut wake_q = WAKEUP_Q.borrow_mut();
 
-        // Insert the preemption event.
+        // Only the running task can be preempted, so a deadline left by a task
+        // that has since been switched out would just be a spurious wake up.
+        wake_q.retain(|event| !matches!(event.what, WakeupKind::Preempt));
+
         wake_q.push(WakeupEvent {
             when,
             what: WakeupKind::Preempt,
         });
 
+        drop(wake_q);
+
         // Ensure the hardware timer is armed for the earliest event.
-        if let Some(next_event) = wake_q.peek() {
-            self.driver.schedule_interrupt(Some(next_event.when));
-        }
+        self.rearm();
     }
 
     /// Arms the hardware timer on the current CPU so that the next scheduled
-    /// `WakeupEvent` (or the fallback preemption tick) will fire.
+    /// `WakeupEvent` will fire.
     /// Secondary CPUs should call this right after they have enabled their
     /// interrupt controller so that they start receiving timer interrupts.
     pub fn kick_current_cpu(&self) {
-        let wake_q = WAKEUP_Q.borrow_mut();
-
-        let next_deadline = wake_q.peek().map(|e| e.when).or_else(|| {
-            // Fallback: re-use the same 15 ms periodic tick as the primary CPU.
-            Some(self.driver.now() + Duration::from_millis(15))
-        });
-
-        self.driver.schedule_interrupt(next_deadline);
+        self.driver.schedule_interrupt(self.next_event());
     }
 }
 
@@ -332,6 +334,15 @@ pub fn uptime() -> Duration {
         .unwrap_or(Duration::ZERO)
 }
 
+/// Returns the resolution of the system timer, or a nanosecond if it has not
+/// been initialised.
+pub fn resolution() -> Duration {
+    SYS_TIMER
+        .get()
+        .map(|timer| timer.resolution())
+        .unwrap_or(Duration::from_nanos(1))
+}
+
 /// Returns the current instant, if the system timer has been initialised.
 pub fn now() -> Option<Instant> {
     SYS_TIMER.get().map(|timer| timer.driver.now())
@@ -345,11 +356,26 @@ pub async fn sleep(duration: Duration) {
         return;
     }
 
-    if let Some(timer) = SYS_TIMER.get() {
-        timer.sleep(duration).await;
+    if SYS_TIMER.get().is_some() {
+        TIMERS.sleep(duration).await;
     }
 }
 
+/// Drives `fut` to completion, unless `duration` passes first. If no timer
+/// driver has yet been loaded, `fut` is never timed out.
+pub async fn timeout<F: Future>(fut: F, duration: Duration) -> Result<F::Output, Timeout> {
+    if SYS_TIMER.get().is_some() {
+        TIMERS.timeout(fut, duration).await
+    } else {
+        Ok(fut.await)
+    }
+}
+
+/// Returns when the current CPU's next timer event is due, if it has one.
+pub fn next_event() -> Option<Instant> {
+    SYS_TIMER.get().and_then(|timer| timer.next_event())
+}
+
 /// Arms the per-CPU hardware timer for the current core.
 /// See [`SysTimer::kick_current_cpu`]
 pub fn kick_current_cpu() {
@@ -368,6 +394,16 @@ pub fn schedule_preempt(when: Instant) {
 
 pub static SYS_TIMER: OnceLock<Arc<SysTimer>> = OnceLock::new();
 
+/// The kernel's timers, measured against [`uptime`]. Everything which waits on
+/// the clock, from `nanosleep` to lock timeouts, sleeps on these.
+pub static TIMERS: Timers<ArchImpl> = Timers::new(uptime, arm_timers);
+
+fn arm_timers(_next: Duration) {
+    if let Some(timer) = SYS_TIMER.get() {
+        timer.rearm();
+    }
+}
+
 per_cpu_private! {
     static WAKEUP_Q: BinaryHeap<WakeupEvent> = BinaryHeap::new;
 }
@@ -35,6 +35,7 @@ use crate::{
     },
     fs::open_file::OpenFile,
     interrupts::{ClaimedInterrupt, InterruptHandler},
+    kernel::cmdline::is_console,
     kernel_driver,
     sync::{OnceLock, SpinLock},
 };
@@ -52,6 +53,7 @@ use libkernel::{
 
 //pub mod bcm2835_aux;
 pub mod imx_lp;
+pub mod ns16550;
 pub mod pl011;
 
 /// A trait for low-level, hardware-specific UART drivers.
@@ -228,7 +230,7 @@ impl UartCharDev {
                     FilePermissions::from_bits_retain(0o600),
                 )?;
 
-                if active_console {
+                if is_console(&format!("ttyS{minor}"), active_console) {
                     set_active_console(driver, desc)?;
                 }
 
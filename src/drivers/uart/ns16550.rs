@@ -0,0 +1,249 @@
+//! National Semiconductor 16550-compatible UARTs.
+//!
+//! The 16550 register file is the same everywhere, but how it is reached
+//! varies: registers may be spaced out (`reg-shift`) and accessed as bytes or
+//! words (`reg-io-width`). [`RegisterAccess`] captures this. On aarch64 there
+//! are no I/O port instructions; x86-style "port" UARTs, e.g. those behind a
+//! PCI I/O BAR, sit in a memory-mapped I/O window and so are also driven
+//! through [`MmioAccess`].
+
+use crate::{
+    arch::ArchImpl,
+    drivers::{
+        DeviceDescriptor, Driver, DriverManager,
+        init::PlatformBus,
+        probe::{DeviceMatchType, FdtFlags},
+    },
+    kernel_driver,
+};
+use alloc::{boxed::Box, sync::Arc};
+use core::hint::spin_loop;
+use libkernel::{
+    error::{ProbeError, Result},
+    memory::{
+        address::{PA, VA},
+        proc_vm::address_space::{KernAddressSpace, VirtualMemory},
+        region::PhysMemoryRegion,
+    },
+};
+
+use super::{UART_CHAR_DEV, Uart, UartDriver};
+
+// Register indices, before scaling by the register shift.
+const RBR_THR_DLL: usize = 0;
+const IER_DLM: usize = 1;
+const FCR: usize = 2;
+const LCR: usize = 3;
+const MCR: usize = 4;
+const LSR: usize = 5;
+
+const IER_RX_AVAILABLE: u8 = 1 << 0;
+
+const FCR_ENABLE: u8 = 1 << 0;
+const FCR_CLEAR_RX: u8 = 1 << 1;
+const FCR_CLEAR_TX: u8 = 1 << 2;
+
+const LCR_8N1: u8 = 0x03;
+const LCR_DLAB: u8 = 1 << 7;
+
+const MCR_DTR: u8 = 1 << 0;
+const MCR_RTS: u8 = 1 << 1;
+/// Routes the interrupt line out of the chip on PC-style boards.
+const MCR_OUT2: u8 = 1 << 3;
+
+const LSR_DATA_READY: u8 = 1 << 0;
+const LSR_THR_EMPTY: u8 = 1 << 5;
+
+const DEFAULT_BAUD: u32 = 115_200;
+
+/// Access to the 16550 register file.
+pub trait RegisterAccess: Send + Sync + 'static {
+    fn read(&self, reg: usize) -> u8;
+    fn write(&self, reg: usize, val: u8);
+}
+
+/// Memory-mapped registers, `1 << shift` bytes apart and accessed with
+/// `width`-byte loads and stores.
+pub struct MmioAccess {
+    base: VA,
+    shift: u32,
+    width: u32,
+}
+
+unsafe impl Send for MmioAccess {}
+unsafe impl Sync for MmioAccess {}
+
+impl MmioAccess {
+    pub fn new(base: VA, shift: u32, width: u32) -> Self {
+        Self { base, shift, width }
+    }
+
+    fn addr(&self, reg: usize) -> VA {
+        self.base.add_bytes(reg << self.shift)
+    }
+}
+
+impl RegisterAccess for MmioAccess {
+    fn read(&self, reg: usize) -> u8 {
+        let ptr = self.addr(reg).as_ptr_mut();
+
+        // SAFETY: The register bank was mapped as device memory at probe time
+        // and covers every register index.
+        unsafe {
+            match self.width {
+                4 => core::ptr::read_volatile(ptr as *const u32) as u8,
+                _ => core::ptr::read_volatile(ptr as *const u8),
+            }
+        }
+    }
+
+    fn write(&self, reg: usize, val: u8) {
+        let ptr = self.addr(reg).as_ptr_mut();
+
+        // SAFETY: As for `read`.
+        unsafe {
+            match self.width {
+                4 => core::ptr::write_volatile(ptr as *mut u32, val as u32),
+                _ => core::ptr::write_volatile(ptr as *mut u8, val),
+            }
+        }
+    }
+}
+
+pub struct Ns16550<A: RegisterAccess> {
+    regs: A,
+}
+
+impl<A: RegisterAccess> Ns16550<A> {
+    /// Initialises the UART for 8N1 operation with the receive interrupt
+    /// enabled. If `clock` is given, the baud rate is also programmed;
+    /// otherwise the rate set by firmware is kept.
+    pub fn new(regs: A, clock: Option<u32>, baud: u32) -> Self {
+        regs.write(IER_DLM, 0);
+
+        if let Some(clock) = clock {
+            let divisor = (clock / (16 * baud)).clamp(1, u16::MAX as u32) as u16;
+            let [low, high] = divisor.to_le_bytes();
+
+            regs.write(LCR, LCR_DLAB);
+            regs.write(RBR_THR_DLL, low);
+            regs.write(IER_DLM, high);
+        }
+
+        regs.write(LCR, LCR_8N1);
+        regs.write(FCR, FCR_ENABLE | FCR_CLEAR_RX | FCR_CLEAR_TX);
+        regs.write(MCR, MCR_DTR | MCR_RTS | MCR_OUT2);
+        regs.write(IER_DLM, IER_RX_AVAILABLE);
+
+        Self { regs }
+    }
+}
+
+impl<A: RegisterAccess> core::fmt::Write for Ns16550<A> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.write_buf(s.as_bytes());
+
+        Ok(())
+    }
+}
+
+impl<A: RegisterAccess> UartDriver for Ns16550<A> {
+    fn write_buf(&mut self, buf: &[u8]) {
+        for &c in buf {
+            while self.regs.read(LSR) & LSR_THR_EMPTY == 0 {
+                spin_loop();
+            }
+
+            self.regs.write(RBR_THR_DLL, c);
+        }
+    }
+
+    fn drain_uart_rx(&mut self, buf: &mut [u8]) -> usize {
+        let mut bytes_read = 0;
+
+        // Reading RBR until the FIFO is empty also clears the interrupt.
+        while bytes_read < buf.len() && self.regs.read(LSR) & LSR_DATA_READY != 0 {
+            buf[bytes_read] = self.regs.read(RBR_THR_DLL);
+            bytes_read += 1;
+        }
+
+        bytes_read
+    }
+}
+
+pub fn ns16550_probe(dm: &mut DriverManager, d: DeviceDescriptor) -> Result<Arc<dyn Driver>> {
+    match d {
+        DeviceDescriptor::Fdt(fdt_node, flags) => {
+            let region = fdt_node
+                .reg()
+                .ok_or(ProbeError::NoReg)?
+                .next()
+                .ok_or(ProbeError::NoReg)?;
+
+            let size = region.size.ok_or(ProbeError::NoRegSize)?;
+
+            let mut interrupts = fdt_node
+                .interrupts()
+                .ok_or(ProbeError::NoInterrupts)?
+                .next()
+                .ok_or(ProbeError::NoInterrupts)?;
+
+            let interrupt_node = fdt_node
+                .interrupt_parent()
+                .ok_or(ProbeError::NoParentInterrupt)?
+                .node;
+
+            let interrupt_manager = dm
+                .find_by_name(interrupt_node.name)
+                .ok_or(ProbeError::Deferred)?
+                .as_interrupt_manager()
+                .ok_or(ProbeError::NotInterruptController)?;
+
+            let uart_cdev = UART_CHAR_DEV.get().ok_or(ProbeError::Deferred)?;
+
+            let u32_prop = |name: &str| fdt_node.find_property(name).map(|p| p.u32());
+
+            let shift = u32_prop("reg-shift").unwrap_or(0);
+            let width = u32_prop("reg-io-width").unwrap_or(1);
+            let clock = u32_prop("clock-frequency").filter(|&c| c != 0);
+            let baud = u32_prop("current-speed")
+                .filter(|&b| b != 0)
+                .unwrap_or(DEFAULT_BAUD);
+
+            let mem =
+                ArchImpl::kern_address_space()
+                    .lock_save_irq()
+                    .map_mmio(PhysMemoryRegion::new(
+                        PA::from_value(region.address as usize),
+                        size,
+                    ))?;
+
+            let interrupt_config = interrupt_manager.parse_fdt_interrupt_regs(&mut interrupts)?;
+
+            let dev = interrupt_manager.claim_interrupt(interrupt_config, |claimed_interrupt| {
+                Uart::new(
+                    Ns16550::new(MmioAccess::new(mem, shift, width), clock, baud),
+                    claimed_interrupt,
+                    fdt_node.name,
+                )
+            })?;
+
+            uart_cdev.register_console(dev.clone(), flags.contains(FdtFlags::ACTIVE_CONSOLE))?;
+
+            Ok(dev)
+        }
+    }
+}
+
+pub fn ns16550_init(bus: &mut PlatformBus, _dm: &mut DriverManager) -> Result<()> {
+    for compatible in ["ns16550a", "ns16550", "ns16450"] {
+        bus.register_platform_driver(
+            DeviceMatchType::FdtCompatible(compatible),
+            Box::new(ns16550_probe),
+        );
+    }
+
+    Ok(())
+}
+
+kernel_driver!(ns16550_init);
@@ -0,0 +1,231 @@
+//! Standard descriptors and requests, as in chapter 9 of the USB 2.0
+//! specification.
+
+use alloc::vec::Vec;
+
+pub const DESC_DEVICE: u8 = 1;
+pub const DESC_CONFIGURATION: u8 = 2;
+pub const DESC_INTERFACE: u8 = 4;
+pub const DESC_ENDPOINT: u8 = 5;
+
+pub const REQ_GET_DESCRIPTOR: u8 = 6;
+pub const REQ_SET_CONFIGURATION: u8 = 9;
+
+/// `bmRequestType` fields.
+pub const RT_DIR_IN: u8 = 1 << 7;
+pub const RT_TYPE_CLASS: u8 = 1 << 5;
+pub const RT_RECIP_INTERFACE: u8 = 1;
+
+/// The eight bytes which start every control transfer.
+#[derive(Clone, Copy, Debug)]
+pub struct SetupPacket {
+    pub request_type: u8,
+    pub request: u8,
+    pub value: u16,
+    pub index: u16,
+    /// The length of the data stage.
+    pub length: u16,
+}
+
+impl SetupPacket {
+    pub fn get_descriptor(kind: u8, index: u8, length: u16) -> Self {
+        Self {
+            request_type: RT_DIR_IN,
+            request: REQ_GET_DESCRIPTOR,
+            value: (kind as u16) << 8 | index as u16,
+            index: 0,
+            length,
+        }
+    }
+
+    pub fn set_configuration(value: u8) -> Self {
+        Self {
+            request_type: 0,
+            request: REQ_SET_CONFIGURATION,
+            value: value as u16,
+            index: 0,
+            length: 0,
+        }
+    }
+
+    /// Whether the data stage, if any, is device-to-host.
+    pub fn is_in(&self) -> bool {
+        self.request_type & RT_DIR_IN != 0
+    }
+
+    /// Returns the packet as it goes on the wire, read as a little-endian
+    /// integer.
+    pub fn to_bits(self) -> u64 {
+        u64::from_le_bytes([
+            self.request_type,
+            self.request,
+            self.value as u8,
+            (self.value >> 8) as u8,
+            self.index as u8,
+            (self.index >> 8) as u8,
+            self.length as u8,
+            (self.length >> 8) as u8,
+        ])
+    }
+}
+
+fn le16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct DeviceDescriptor {
+    /// `bcdUSB`: the version of the specification the device follows.
+    pub usb_version: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub protocol: u8,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    /// `bcdDevice`.
+    pub device_version: u16,
+}
+
+impl DeviceDescriptor {
+    pub const SIZE: usize = 18;
+
+    pub fn parse(buf: &[u8]) -> Option<Self> {
+        if buf.len() < Self::SIZE || buf[1] != DESC_DEVICE {
+            return None;
+        }
+
+        Some(Self {
+            usb_version: le16(buf, 2),
+            class: buf[4],
+            subclass: buf[5],
+            protocol: buf[6],
+            vendor_id: le16(buf, 8),
+            product_id: le16(buf, 10),
+            device_version: le16(buf, 12),
+        })
+    }
+
+    /// Returns `bMaxPacketSize0` from the first eight bytes of the
+    /// descriptor, which is as much as can be read before the default
+    /// pipe's packet size is known.
+    pub fn max_packet_size0(header: &[u8]) -> Option<u8> {
+        (header.len() >= 8 && header[1] == DESC_DEVICE).then(|| header[7])
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransferType {
+    Control,
+    Isochronous,
+    Bulk,
+    Interrupt,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct EndpointDescriptor {
+    /// The endpoint number, with bit 7 set for IN endpoints.
+    pub address: u8,
+    pub attributes: u8,
+    /// Bits 0-10 hold the packet size; for high-speed periodic endpoints,
+    /// bits 11-12 hold the number of extra transactions per microframe.
+    pub max_packet_size: u16,
+    pub interval: u8,
+}
+
+impl EndpointDescriptor {
+    pub fn number(&self) -> u8 {
+        self.address & 0xf
+    }
+
+    pub fn is_in(&self) -> bool {
+        self.address & 0x80 != 0
+    }
+
+    pub fn transfer_type(&self) -> TransferType {
+        match self.attributes & 0x3 {
+            0 => TransferType::Control,
+            1 => TransferType::Isochronous,
+            2 => TransferType::Bulk,
+            _ => TransferType::Interrupt,
+        }
+    }
+
+    /// The packet size, less the transactions-per-microframe bits.
+    pub fn packet_size(&self) -> u16 {
+        self.max_packet_size & 0x7ff
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct InterfaceDescriptor {
+    pub number: u8,
+    pub alt_setting: u8,
+    pub class: u8,
+    pub subclass: u8,
+    pub protocol: u8,
+    pub endpoints: Vec<EndpointDescriptor>,
+}
+
+/// A configuration descriptor, together with the interface and endpoint
+/// descriptors which follow it.
+#[derive(Clone, Debug)]
+pub struct ConfigDescriptor {
+    /// `bConfigurationValue`, as passed to `SET_CONFIGURATION`.
+    pub value: u8,
+    pub interfaces: Vec<InterfaceDescriptor>,
+}
+
+impl ConfigDescriptor {
+    pub const HEADER_SIZE: usize = 9;
+
+    /// Returns `wTotalLength` from the first [`Self::HEADER_SIZE`] bytes of
+    /// the descriptor.
+    pub fn total_length(header: &[u8]) -> Option<u16> {
+        (header.len() >= Self::HEADER_SIZE && header[1] == DESC_CONFIGURATION)
+            .then(|| le16(header, 2))
+    }
+
+    /// Parses a full configuration descriptor. Class-specific descriptors
+    /// are skipped.
+    pub fn parse(buf: &[u8]) -> Option<Self> {
+        Self::total_length(buf)?;
+
+        let value = buf[5];
+        let mut interfaces: Vec<InterfaceDescriptor> = Vec::new();
+        let mut offset = buf[0] as usize;
+
+        while let Some(&[len, kind]) = buf.get(offset..offset + 2) {
+            let len = len as usize;
+
+            if len < 2 {
+                return None;
+            }
+
+            let desc = buf.get(offset..offset + len)?;
+
+            match kind {
+                DESC_INTERFACE if len >= 9 => interfaces.push(InterfaceDescriptor {
+                    number: desc[2],
+                    alt_setting: desc[3],
+                    class: desc[5],
+                    subclass: desc[6],
+                    protocol: desc[7],
+                    endpoints: Vec::new(),
+                }),
+                DESC_ENDPOINT if len >= 7 => {
+                    interfaces.last_mut()?.endpoints.push(EndpointDescriptor {
+                        address: desc[2],
+                        attributes: desc[3],
+                        max_packet_size: le16(desc, 4),
+                        interval: desc[6],
+                    });
+                }
+                _ => {}
+            }
+
+            offset += len;
+        }
+
+        Some(Self { value, interfaces })
+    }
+}
@@ -0,0 +1,344 @@
+//! USB HID keyboards and mice.
+//!
+//! Devices are switched to the boot protocol, whose fixed report formats
+//! every keyboard and mouse supports, so no report descriptor parsing is
+//! needed. Keyboards send the modifiers and up to six held keys in each
+//! report, and key events are found by comparing it with the last. Keys
+//! don't autorepeat.
+
+use super::{
+    TransferHandler, UsbDevice,
+    descriptor::{
+        InterfaceDescriptor, RT_RECIP_INTERFACE, RT_TYPE_CLASS, SetupPacket, TransferType,
+    },
+};
+use crate::{
+    drivers::input::{Capabilities, InputDevice, InputEvent, InputId, codes::*, register_device},
+    sync::SpinLock,
+};
+use alloc::{format, sync::Arc, vec::Vec};
+use libkernel::error::{ProbeError, Result};
+use log::info;
+
+pub const CLASS_HID: u8 = 3;
+
+const SUBCLASS_BOOT: u8 = 1;
+const PROTOCOL_KEYBOARD: u8 = 1;
+const PROTOCOL_MOUSE: u8 = 2;
+
+const REQ_SET_IDLE: u8 = 0x0a;
+const REQ_SET_PROTOCOL: u8 = 0x0b;
+const BOOT_PROTOCOL: u16 = 0;
+
+/// The usage a keyboard reports in every key slot when too many keys are
+/// held to tell which.
+const USAGE_ERROR_ROLLOVER: u8 = 0x01;
+
+/// The keys for the bits of a keyboard report's modifier byte.
+const MODIFIER_KEYS: [u16; 8] = [
+    KEY_LEFTCTRL,
+    KEY_LEFTSHIFT,
+    KEY_LEFTALT,
+    KEY_LEFTMETA,
+    KEY_RIGHTCTRL,
+    KEY_RIGHTSHIFT,
+    KEY_RIGHTALT,
+    KEY_RIGHTMETA,
+];
+
+const MOUSE_BUTTONS: [u16; 3] = [BTN_LEFT, BTN_RIGHT, BTN_MIDDLE];
+
+/// Maps a usage from the keyboard page to a key code.
+fn translate(usage: u8) -> Option<u16> {
+    Some(match usage {
+        0x04 => KEY_A,
+        0x05 => KEY_B,
+        0x06 => KEY_C,
+        0x07 => KEY_D,
+        0x08 => KEY_E,
+        0x09 => KEY_F,
+        0x0a => KEY_G,
+        0x0b => KEY_H,
+        0x0c => KEY_I,
+        0x0d => KEY_J,
+        0x0e => KEY_K,
+        0x0f => KEY_L,
+        0x10 => KEY_M,
+        0x11 => KEY_N,
+        0x12 => KEY_O,
+        0x13 => KEY_P,
+        0x14 => KEY_Q,
+        0x15 => KEY_R,
+        0x16 => KEY_S,
+        0x17 => KEY_T,
+        0x18 => KEY_U,
+        0x19 => KEY_V,
+        0x1a => KEY_W,
+        0x1b => KEY_X,
+        0x1c => KEY_Y,
+        0x1d => KEY_Z,
+        0x1e => KEY_1,
+        0x1f => KEY_2,
+        0x20 => KEY_3,
+        0x21 => KEY_4,
+        0x22 => KEY_5,
+        0x23 => KEY_6,
+        0x24 => KEY_7,
+        0x25 => KEY_8,
+        0x26 => KEY_9,
+        0x27 => KEY_0,
+        0x28 => KEY_ENTER,
+        0x29 => KEY_ESC,
+        0x2a => KEY_BACKSPACE,
+        0x2b => KEY_TAB,
+        0x2c => KEY_SPACE,
+        0x2d => KEY_MINUS,
+        0x2e => KEY_EQUAL,
+        0x2f => KEY_LEFTBRACE,
+        0x30 => KEY_RIGHTBRACE,
+        // Also the non-US `#`, which sits in the same place.
+        0x31 | 0x32 => KEY_BACKSLASH,
+        0x33 => KEY_SEMICOLON,
+        0x34 => KEY_APOSTROPHE,
+        0x35 => KEY_GRAVE,
+        0x36 => KEY_COMMA,
+        0x37 => KEY_DOT,
+        0x38 => KEY_SLASH,
+        0x39 => KEY_CAPSLOCK,
+        0x3a => KEY_F1,
+        0x3b => KEY_F2,
+        0x3c => KEY_F3,
+        0x3d => KEY_F4,
+        0x3e => KEY_F5,
+        0x3f => KEY_F6,
+        0x40 => KEY_F7,
+        0x41 => KEY_F8,
+        0x42 => KEY_F9,
+        0x43 => KEY_F10,
+        0x44 => KEY_F11,
+        0x45 => KEY_F12,
+        0x46 => KEY_SYSRQ,
+        0x47 => KEY_SCROLLLOCK,
+        0x49 => KEY_INSERT,
+        0x4a => KEY_HOME,
+        0x4b => KEY_PAGEUP,
+        0x4c => KEY_DELETE,
+        0x4d => KEY_END,
+        0x4e => KEY_PAGEDOWN,
+        0x4f => KEY_RIGHT,
+        0x50 => KEY_LEFT,
+        0x51 => KEY_DOWN,
+        0x52 => KEY_UP,
+        0x53 => KEY_NUMLOCK,
+        0x54 => KEY_KPSLASH,
+        0x55 => KEY_KPASTERISK,
+        0x56 => KEY_KPMINUS,
+        0x57 => KEY_KPPLUS,
+        0x58 => KEY_KPENTER,
+        0x59 => KEY_KP1,
+        0x5a => KEY_KP2,
+        0x5b => KEY_KP3,
+        0x5c => KEY_KP4,
+        0x5d => KEY_KP5,
+        0x5e => KEY_KP6,
+        0x5f => KEY_KP7,
+        0x60 => KEY_KP8,
+        0x61 => KEY_KP9,
+        0x62 => KEY_KP0,
+        0x63 => KEY_KPDOT,
+        _ => return None,
+    })
+}
+
+/// A boot protocol keyboard report: the modifier byte, a reserved byte, and
+/// the usages of up to six held keys.
+const KEYBOARD_REPORT_LEN: usize = 8;
+
+struct HidKeyboard {
+    input: Arc<InputDevice>,
+    last: SpinLock<[u8; KEYBOARD_REPORT_LEN]>,
+}
+
+impl TransferHandler for HidKeyboard {
+    fn complete(&self, data: &[u8]) {
+        let Some(report) = data.first_chunk::<KEYBOARD_REPORT_LEN>() else {
+            return;
+        };
+
+        if report[2..].contains(&USAGE_ERROR_ROLLOVER) {
+            return;
+        }
+
+        let mut last = self.last.lock_save_irq();
+        let mut events = Vec::new();
+
+        for (bit, &code) in MODIFIER_KEYS.iter().enumerate() {
+            let (was, is) = (last[0] & 1 << bit != 0, report[0] & 1 << bit != 0);
+
+            if was != is {
+                events.push(InputEvent::new(EV_KEY, code, is as i32));
+            }
+        }
+
+        let (old_keys, new_keys) = (&last[2..], &report[2..]);
+
+        for &usage in old_keys.iter().filter(|u| !new_keys.contains(u)) {
+            if let Some(code) = translate(usage) {
+                events.push(InputEvent::new(EV_KEY, code, 0));
+            }
+        }
+
+        for &usage in new_keys.iter().filter(|u| !old_keys.contains(u)) {
+            if let Some(code) = translate(usage) {
+                events.push(InputEvent::new(EV_KEY, code, 1));
+            }
+        }
+
+        *last = *report;
+        drop(last);
+
+        if !events.is_empty() {
+            self.input.report_frame(&events);
+        }
+    }
+}
+
+/// A boot protocol mouse report: a button bitmap, then X and Y motion. Most
+/// mice follow these with wheel motion.
+const MOUSE_REPORT_MIN_LEN: usize = 3;
+
+struct HidMouse {
+    input: Arc<InputDevice>,
+    buttons: SpinLock<u8>,
+}
+
+impl TransferHandler for HidMouse {
+    fn complete(&self, data: &[u8]) {
+        if data.len() < MOUSE_REPORT_MIN_LEN {
+            return;
+        }
+
+        let mut events = Vec::new();
+        let mut buttons = self.buttons.lock_save_irq();
+
+        for (bit, &code) in MOUSE_BUTTONS.iter().enumerate() {
+            let (was, is) = (*buttons & 1 << bit != 0, data[0] & 1 << bit != 0);
+
+            if was != is {
+                events.push(InputEvent::new(EV_KEY, code, is as i32));
+            }
+        }
+
+        *buttons = data[0];
+        drop(buttons);
+
+        let motion = [(REL_X, data[1]), (REL_Y, data[2])]
+            .into_iter()
+            .chain(data.get(3).map(|&wheel| (REL_WHEEL, wheel)));
+
+        events.extend(
+            motion
+                .filter(|&(_, delta)| delta != 0)
+                .map(|(axis, delta)| InputEvent::new(EV_REL, axis, delta as i8 as i32)),
+        );
+
+        if !events.is_empty() {
+            self.input.report_frame(&events);
+        }
+    }
+}
+
+fn keyboard_capabilities() -> Capabilities {
+    let mut caps = Capabilities::default();
+
+    for code in (0..=u8::MAX).filter_map(translate).chain(MODIFIER_KEYS) {
+        caps.set(EV_KEY, code);
+    }
+
+    caps
+}
+
+fn mouse_capabilities() -> Capabilities {
+    let mut caps = Capabilities::default();
+
+    for code in MOUSE_BUTTONS {
+        caps.set(EV_KEY, code);
+    }
+
+    for axis in [REL_X, REL_Y, REL_WHEEL] {
+        caps.set(EV_REL, axis);
+    }
+
+    caps
+}
+
+fn class_request(iface: &InterfaceDescriptor, request: u8, value: u16) -> SetupPacket {
+    SetupPacket {
+        request_type: RT_TYPE_CLASS | RT_RECIP_INTERFACE,
+        request,
+        value,
+        index: iface.number as u16,
+        length: 0,
+    }
+}
+
+pub fn hid_probe(dev: &mut dyn UsbDevice, iface: &InterfaceDescriptor) -> Result<()> {
+    if iface.subclass != SUBCLASS_BOOT
+        || !matches!(iface.protocol, PROTOCOL_KEYBOARD | PROTOCOL_MOUSE)
+    {
+        return Err(ProbeError::NoMatch.into());
+    }
+
+    let endpoint = iface
+        .endpoints
+        .iter()
+        .find(|ep| ep.is_in() && ep.transfer_type() == TransferType::Interrupt)
+        .ok_or(ProbeError::NoMatch)?;
+
+    dev.control_transfer(
+        class_request(iface, REQ_SET_PROTOCOL, BOOT_PROTOCOL),
+        &mut [],
+    )?;
+
+    // Only report changes. Some devices don't support this, which is
+    // harmless.
+    let _ = dev.control_transfer(class_request(iface, REQ_SET_IDLE, 0), &mut []);
+
+    let desc = *dev.descriptor();
+    let keyboard = iface.protocol == PROTOCOL_KEYBOARD;
+    let kind = if keyboard { "Keyboard" } else { "Mouse" };
+
+    let input = register_device(
+        format!(
+            "USB HID {:04x}:{:04x} {kind}",
+            desc.vendor_id, desc.product_id
+        ),
+        InputId {
+            bustype: BUS_USB,
+            vendor: desc.vendor_id,
+            product: desc.product_id,
+            version: desc.device_version,
+        },
+        if keyboard {
+            keyboard_capabilities()
+        } else {
+            mouse_capabilities()
+        },
+    );
+
+    info!("USB: {} is input{}", input.name(), input.id());
+
+    let handler: Arc<dyn TransferHandler> = if keyboard {
+        Arc::new(HidKeyboard {
+            input,
+            last: SpinLock::new([0; KEYBOARD_REPORT_LEN]),
+        })
+    } else {
+        Arc::new(HidMouse {
+            input,
+            buttons: SpinLock::new(0),
+        })
+    };
+
+    dev.poll_interrupt_in(endpoint, handler)
+}
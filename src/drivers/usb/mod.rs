@@ -0,0 +1,72 @@
+//! USB.
+//!
+//! Host controller drivers enumerate the devices on their ports, select
+//! each device's first configuration, and then offer its interfaces to the
+//! class drivers through [`probe_interfaces`]. A class driver talks to its
+//! device through the [`UsbDevice`] it is handed, and is called back with
+//! the data of each completed interrupt transfer.
+//!
+//! Hubs aren't supported, so only devices plugged straight into a root port
+//! are found.
+
+use alloc::sync::Arc;
+use descriptor::{
+    ConfigDescriptor, DeviceDescriptor, EndpointDescriptor, InterfaceDescriptor, SetupPacket,
+};
+use libkernel::error::{KernelError, ProbeError, Result};
+use log::warn;
+
+pub mod descriptor;
+pub mod hid;
+pub mod xhci;
+
+/// A configured device, as seen by class drivers.
+pub trait UsbDevice {
+    fn descriptor(&self) -> &DeviceDescriptor;
+
+    /// Performs a control transfer on the default pipe, with the data stage
+    /// read into, or written from, `data`. Returns the length of the data
+    /// stage.
+    fn control_transfer(&mut self, setup: SetupPacket, data: &mut [u8]) -> Result<usize>;
+
+    /// Starts polling an interrupt IN endpoint, passing each transfer's data
+    /// to `handler` until the device goes away.
+    fn poll_interrupt_in(
+        &mut self,
+        endpoint: &EndpointDescriptor,
+        handler: Arc<dyn TransferHandler>,
+    ) -> Result<()>;
+}
+
+/// Receives the data of completed transfers on a pipe.
+pub trait TransferHandler: Send + Sync {
+    /// Called, possibly from an interrupt handler, with each transfer's data.
+    fn complete(&self, data: &[u8]);
+}
+
+/// Binds to an interface if it is supported, returning
+/// [`ProbeError::NoMatch`] otherwise.
+pub type ClassProbeFn = fn(&mut dyn UsbDevice, &InterfaceDescriptor) -> Result<()>;
+
+/// Class drivers, by the interface class they handle.
+const CLASS_DRIVERS: &[(u8, ClassProbeFn)] = &[(hid::CLASS_HID, hid::hid_probe)];
+
+/// Offers each interface of a newly configured device to the class drivers.
+pub fn probe_interfaces(dev: &mut dyn UsbDevice, config: &ConfigDescriptor) {
+    for iface in config.interfaces.iter().filter(|i| i.alt_setting == 0) {
+        let drivers = CLASS_DRIVERS
+            .iter()
+            .filter(|(class, _)| *class == iface.class);
+
+        for (_, probe) in drivers {
+            match probe(dev, iface) {
+                Ok(()) => break,
+                Err(KernelError::Probe(ProbeError::NoMatch)) => continue,
+                Err(e) => {
+                    warn!("USB: could not bind interface {}: {e}", iface.number);
+                    break;
+                }
+            }
+        }
+    }
+}
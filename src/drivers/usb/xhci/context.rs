@@ -0,0 +1,138 @@
+//! Device and input contexts.
+//!
+//! The controller keeps each slot's state in an output device context: a
+//! slot context followed by one context per endpoint, indexed by Device
+//! Context Index (DCI). Software changes that state by passing an input
+//! context, which prefixes the same layout with a control context saying
+//! which of the contexts to add or drop.
+
+use crate::{
+    drivers::usb::descriptor::{EndpointDescriptor, TransferType},
+    memory::dma::{DmaAddr, DmaCoherent},
+};
+use core::slice;
+
+/// The DCI of the default control endpoint.
+pub const EP0_DCI: u8 = 1;
+
+// Endpoint types.
+pub const EP_TYPE_CONTROL: u32 = 4;
+const EP_TYPE_ISOCH_OUT: u32 = 1;
+const EP_TYPE_BULK_OUT: u32 = 2;
+const EP_TYPE_INTERRUPT_OUT: u32 = 3;
+const EP_TYPE_ISOCH_IN: u32 = 5;
+const EP_TYPE_BULK_IN: u32 = 6;
+const EP_TYPE_INTERRUPT_IN: u32 = 7;
+
+/// Error Count: how many times a transaction is retried.
+const EP_CERR: u32 = 3 << 1;
+
+/// Returns the DCI of an endpoint.
+pub fn dci(endpoint: &EndpointDescriptor) -> u8 {
+    endpoint.number() * 2 + endpoint.is_in() as u8
+}
+
+pub struct InputContext {
+    buf: DmaCoherent,
+    /// The size of each context: 32 or 64 bytes.
+    ctx_size: usize,
+}
+
+impl InputContext {
+    pub fn new(buf: DmaCoherent, ctx_size: usize) -> Self {
+        // The control context, the slot context, and 31 endpoints.
+        assert!(buf.len() >= 33 * ctx_size);
+
+        Self { buf, ctx_size }
+    }
+
+    pub fn dma_addr(&self) -> DmaAddr {
+        self.buf.dma_addr()
+    }
+
+    /// Returns the first eight dwords of context `idx`, which are all that
+    /// the controller reads.
+    fn context(&mut self, idx: usize) -> &mut [u32] {
+        // SAFETY: The context lies within the buffer, which is borrowed
+        // mutably.
+        unsafe {
+            slice::from_raw_parts_mut(
+                self.buf
+                    .va()
+                    .add_bytes(idx * self.ctx_size)
+                    .as_ptr_mut()
+                    .cast(),
+                8,
+            )
+        }
+    }
+
+    /// Sets which contexts the next command acts on, as a bitmap indexed by
+    /// DCI, with bit 0 for the slot context.
+    pub fn set_add_flags(&mut self, flags: u32) {
+        let control = self.context(0);
+        control[0] = 0;
+        control[1] = flags;
+    }
+
+    /// Fills in the slot context for a device on a root port, with
+    /// endpoints up to `last_dci`.
+    pub fn set_slot(&mut self, speed: u8, port: u8, last_dci: u8) {
+        let slot = self.context(1);
+        slot[0] = (last_dci as u32) << 27 | (speed as u32) << 20;
+        slot[1] = (port as u32) << 16;
+    }
+
+    pub fn set_last_dci(&mut self, last_dci: u8) {
+        let slot = self.context(1);
+        slot[0] = (slot[0] & !(0x1f << 27)) | (last_dci as u32) << 27;
+    }
+
+    /// Fills in an endpoint context, whose transfer ring starts at `ring`.
+    pub fn set_endpoint(
+        &mut self,
+        dci: u8,
+        ep_type: u32,
+        max_packet_size: u16,
+        interval: u8,
+        ring: DmaAddr,
+    ) {
+        let ep = self.context(1 + dci as usize);
+
+        ep.fill(0);
+        ep[0] = (interval as u32) << 16;
+        ep[1] = EP_CERR | ep_type << 3 | (max_packet_size as u32) << 16;
+        // The ring starts out with a cycle state of 1.
+        ep[2] = ring as u32 | 1;
+        ep[3] = (ring as u64 >> 32) as u32;
+        ep[4] = average_trb_length(ep_type, max_packet_size);
+    }
+
+    pub fn set_max_packet_size(&mut self, dci: u8, max_packet_size: u16) {
+        let ep = self.context(1 + dci as usize);
+        ep[1] = (ep[1] & 0xffff) | (max_packet_size as u32) << 16;
+    }
+}
+
+/// Returns the Average TRB Length and, for periodic endpoints, the Max ESIT
+/// Payload fields of an endpoint context.
+fn average_trb_length(ep_type: u32, max_packet_size: u16) -> u32 {
+    match ep_type {
+        EP_TYPE_CONTROL => 8,
+        EP_TYPE_BULK_IN | EP_TYPE_BULK_OUT => 3 * 1024,
+        _ => max_packet_size as u32 | (max_packet_size as u32) << 16,
+    }
+}
+
+/// Returns the endpoint context type of an endpoint.
+pub fn endpoint_type(endpoint: &EndpointDescriptor) -> u32 {
+    match (endpoint.transfer_type(), endpoint.is_in()) {
+        (TransferType::Control, _) => EP_TYPE_CONTROL,
+        (TransferType::Isochronous, false) => EP_TYPE_ISOCH_OUT,
+        (TransferType::Isochronous, true) => EP_TYPE_ISOCH_IN,
+        (TransferType::Bulk, false) => EP_TYPE_BULK_OUT,
+        (TransferType::Bulk, true) => EP_TYPE_BULK_IN,
+        (TransferType::Interrupt, false) => EP_TYPE_INTERRUPT_OUT,
+        (TransferType::Interrupt, true) => EP_TYPE_INTERRUPT_IN,
+    }
+}
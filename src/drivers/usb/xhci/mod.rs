@@ -0,0 +1,960 @@
+//! xHCI USB host controllers.
+//!
+//! The controller is driven through rings of TRBs in coherent DMA memory:
+//! commands go on the command ring, transfers on each endpoint's transfer
+//! ring, and the controller reports their completion, along with port
+//! changes, on the event ring.
+//!
+//! Devices attached at boot are enumerated during the probe, polling for
+//! completions as interrupts are still off. Devices plugged in later are
+//! enumerated from a kernel task, kicked off by the port change event. Once
+//! a device is up, its interrupt transfers complete through the controller's
+//! interrupt handler.
+
+use super::{
+    TransferHandler, UsbDevice,
+    descriptor::{
+        ConfigDescriptor, DESC_CONFIGURATION, DESC_DEVICE, DeviceDescriptor, EndpointDescriptor,
+        SetupPacket, TransferType,
+    },
+    probe_interfaces,
+};
+use crate::{
+    drivers::{
+        Driver, DriverManager,
+        init::PlatformBus,
+        pci::{IrqTypes, PCI_BUS, PciDevice, PciMatchType},
+        timer::now,
+    },
+    interrupts::{ClaimedInterrupt, InterruptDescriptor, InterruptHandler, get_interrupt_root},
+    kernel_driver,
+    memory::dma::{DmaCoherent, dma_alloc_coherent},
+    sched::spawn_kernel_task,
+    sync::SpinLock,
+};
+use alloc::{
+    boxed::Box,
+    collections::{btree_map::BTreeMap, btree_set::BTreeSet},
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+use context::{EP_TYPE_CONTROL, EP0_DCI, InputContext, dci, endpoint_type};
+use core::{
+    hint::spin_loop,
+    sync::atomic::{Ordering, fence},
+    time::Duration,
+};
+use libkernel::{
+    error::{KernelError, ProbeError, Result},
+    memory::PAGE_SIZE,
+    sync::executor::Priority,
+};
+use log::{debug, error, info, warn};
+use regs::*;
+use ring::{
+    CC_SHORT_PACKET, CC_SUCCESS, EventRing, Ring, TRB_COMMAND_COMPLETION, TRB_PORT_STATUS_CHANGE,
+    TRB_SIZE, TRB_TRANSFER_EVENT, Trb,
+};
+
+mod context;
+mod regs;
+mod ring;
+
+const PCI_CLASS_SERIAL_BUS: u8 = 0x0c;
+const PCI_SUBCLASS_USB: u8 = 0x03;
+const PCI_PROG_IF_XHCI: u8 = 0x30;
+
+const HALT_TIMEOUT: Duration = Duration::from_millis(100);
+const RESET_TIMEOUT: Duration = Duration::from_secs(1);
+const HANDOFF_TIMEOUT: Duration = Duration::from_secs(1);
+const COMMAND_TIMEOUT: Duration = Duration::from_millis(500);
+const TRANSFER_TIMEOUT: Duration = Duration::from_secs(1);
+/// How long devices get to connect once the ports are powered.
+const CONNECT_DELAY: Duration = Duration::from_millis(100);
+/// How long a device gets to recover from a port reset, per the USB 2.0
+/// specification.
+const RESET_RECOVERY: Duration = Duration::from_millis(10);
+
+/// Spins until `f` returns a value, for up to `timeout`.
+fn poll<T>(timeout: Duration, mut f: impl FnMut() -> Option<T>) -> Result<T> {
+    let deadline = now().map(|now| now + timeout);
+    // Before the timer is up, make do with counting spins.
+    let mut spins = timeout.as_micros();
+
+    loop {
+        if let Some(val) = f() {
+            return Ok(val);
+        }
+
+        let expired = match deadline {
+            Some(deadline) => now().is_some_and(|now| now >= deadline),
+            None => {
+                spins = spins.saturating_sub(1);
+                spins == 0
+            }
+        };
+
+        if expired {
+            return Err(KernelError::TimedOut);
+        }
+
+        spin_loop();
+    }
+}
+
+fn delay(duration: Duration) {
+    let _ = poll(duration, || None::<()>);
+}
+
+/// Allocates DMA memory the controller can reach.
+fn dma_alloc(ac64: bool, size: usize) -> Result<DmaCoherent> {
+    let buf = dma_alloc_coherent(size)?;
+
+    if !ac64 && (buf.dma_addr() + size - 1) >> 32 != 0 {
+        return Err(KernelError::NoMemory);
+    }
+
+    Ok(buf)
+}
+
+/// Sets entry `idx` of a table of 64-bit pointers.
+fn set_entry(table: &mut DmaCoherent, idx: usize, val: u64) {
+    table.as_slice_mut()[idx * 8..(idx + 1) * 8].copy_from_slice(&val.to_le_bytes());
+}
+
+/// Converts an endpoint's `bInterval` to the controller's polling interval:
+/// a power of two of 125us microframes.
+fn interval(speed: u8, endpoint: &EndpointDescriptor) -> u8 {
+    match endpoint.transfer_type() {
+        // Given in 1ms frames.
+        TransferType::Interrupt if matches!(speed, SPEED_FULL | SPEED_LOW) => {
+            (endpoint.interval.max(1) as u32 * 8).ilog2().clamp(3, 10) as u8
+        }
+        // Already a power of two of microframes, plus one.
+        TransferType::Interrupt => endpoint.interval.clamp(1, 16) - 1,
+        _ => 0,
+    }
+}
+
+/// An interrupt IN endpoint being polled.
+struct Pipe {
+    buf: DmaCoherent,
+    len: usize,
+    handler: Arc<dyn TransferHandler>,
+}
+
+/// A device which has been given a slot.
+struct Slot {
+    port: u8,
+    speed: u8,
+    input: InputContext,
+    /// The controller's copy of the device's state.
+    _output: DmaCoherent,
+    /// Transfer rings, by DCI.
+    rings: BTreeMap<u8, Ring>,
+    pipes: BTreeMap<u8, Pipe>,
+    /// Holds the data stage of control transfers.
+    control_buf: DmaCoherent,
+}
+
+struct XhciInner {
+    op: Mmio,
+    interrupter: Mmio,
+    doorbells: Mmio,
+    max_ports: u8,
+    /// The size of each device context entry: 32 or 64 bytes.
+    ctx_size: usize,
+    /// Whether the controller can reach memory above 4GiB.
+    ac64: bool,
+    /// Whether ports must be powered on by software.
+    port_power: bool,
+    /// The Device Context Base Address Array: each slot's output context,
+    /// and the scratchpad buffer array in entry 0.
+    dcbaa: DmaCoherent,
+    _scratchpads: Vec<DmaCoherent>,
+    commands: Ring,
+    events: EventRing,
+    slots: BTreeMap<u8, Slot>,
+    /// Ports with a status change yet to be looked at.
+    port_changes: BTreeSet<u8>,
+}
+
+impl XhciInner {
+    /// Resets the controller found at `cap`, and sets up its data
+    /// structures.
+    fn new(cap: Mmio) -> Result<Self> {
+        let op = cap.at(cap.read(CAPLENGTH) as usize & 0xff);
+        let interrupter = cap.at((cap.read(RTSOFF) & !0x1f) as usize + IR0);
+        let doorbells = cap.at((cap.read(DBOFF) & !0x3) as usize);
+
+        let hcsparams1 = cap.read(HCSPARAMS1);
+        let hcsparams2 = cap.read(HCSPARAMS2);
+        let hccparams1 = cap.read(HCCPARAMS1);
+
+        let max_slots = hcsparams1 & 0xff;
+        let ac64 = hccparams1 & HCCPARAMS1_AC64 != 0;
+
+        take_ownership(cap, hccparams1);
+        reset(op)?;
+
+        if op.read(PAGESIZE) & PAGESIZE_4K == 0 {
+            return Err(KernelError::NotSupported);
+        }
+
+        let mut dcbaa = dma_alloc(ac64, (max_slots as usize + 1) * 8)?;
+
+        let scratchpad_count = ((hcsparams2 >> 21) & 0x1f) << 5 | (hcsparams2 >> 27) & 0x1f;
+        let mut scratchpads = Vec::new();
+
+        if scratchpad_count > 0 {
+            let mut array = dma_alloc(ac64, scratchpad_count as usize * 8)?;
+
+            for idx in 0..scratchpad_count as usize {
+                let page = dma_alloc(ac64, PAGE_SIZE)?;
+                set_entry(&mut array, idx, page.dma_addr() as u64);
+                scratchpads.push(page);
+            }
+
+            set_entry(&mut dcbaa, 0, array.dma_addr() as u64);
+            scratchpads.push(array);
+        }
+
+        let commands = Ring::new(dma_alloc(ac64, PAGE_SIZE)?);
+        let events = EventRing::new(dma_alloc(ac64, PAGE_SIZE)?, dma_alloc(ac64, TRB_SIZE)?);
+
+        op.write(CONFIG, max_slots);
+        op.write_u64(DCBAAP, dcbaa.dma_addr() as u64);
+        op.write_u64(CRCR, commands.dma_addr() as u64 | CRCR_RCS);
+
+        interrupter.write(ERSTSZ, 1);
+        interrupter.write_u64(ERDP, events.dequeue_ptr() as u64);
+        interrupter.write_u64(ERSTBA, events.table_addr() as u64);
+
+        Ok(Self {
+            op,
+            interrupter,
+            doorbells,
+            max_ports: (hcsparams1 >> 24) as u8,
+            ctx_size: if hccparams1 & HCCPARAMS1_CSZ != 0 {
+                64
+            } else {
+                32
+            },
+            ac64,
+            port_power: hccparams1 & HCCPARAMS1_PPC != 0,
+            dcbaa,
+            _scratchpads: scratchpads,
+            commands,
+            events,
+            slots: BTreeMap::new(),
+            port_changes: BTreeSet::new(),
+        })
+    }
+
+    fn alloc(&self, size: usize) -> Result<DmaCoherent> {
+        dma_alloc(self.ac64, size)
+    }
+
+    fn usbsts(&self) -> UsbSts {
+        UsbSts::from_bits_retain(self.op.read(USBSTS))
+    }
+
+    fn start(&mut self) -> Result<()> {
+        if self.port_power {
+            for port in 1..=self.max_ports {
+                self.write_portsc(port, Portsc::PP);
+            }
+        }
+
+        self.op.write(USBCMD, UsbCmd::RUN.bits());
+        poll(HALT_TIMEOUT, || {
+            (!self.usbsts().contains(UsbSts::HCH)).then_some(())
+        })?;
+
+        delay(CONNECT_DELAY);
+
+        Ok(())
+    }
+
+    fn enable_interrupts(&mut self) {
+        self.interrupter.write(IMAN, IMAN_IP | IMAN_IE);
+        self.op.write(USBCMD, (UsbCmd::RUN | UsbCmd::INTE).bits());
+    }
+
+    fn ack_interrupt(&mut self) {
+        let status = self.usbsts();
+
+        if status.contains(UsbSts::HSE) {
+            error!("xHCI: host system error");
+        }
+
+        self.op.write(USBSTS, UsbSts::EINT.bits());
+        self.interrupter.write(IMAN, IMAN_IP | IMAN_IE);
+    }
+
+    fn ring_doorbell(&self, slot: u8, target: u8) {
+        // Make the TRBs visible before the controller goes looking for them.
+        fence(Ordering::SeqCst);
+        self.doorbells.write(slot as usize * 4, target as u32);
+    }
+
+    fn update_dequeue(&self) {
+        self.interrupter
+            .write_u64(ERDP, self.events.dequeue_ptr() as u64 | ERDP_EHB);
+    }
+
+    fn drain_events(&mut self) {
+        while let Some(event) = self.events.pop() {
+            self.handle_event(event);
+        }
+
+        self.update_dequeue();
+    }
+
+    fn handle_event(&mut self, event: Trb) {
+        match event.kind() {
+            TRB_TRANSFER_EVENT => self.complete_transfer(event),
+            TRB_PORT_STATUS_CHANGE => {
+                self.port_changes.insert(event.port_id());
+            }
+            kind => debug!("xHCI: ignoring event of type {kind}"),
+        }
+    }
+
+    /// Waits for an event matching `pred`, handling any others which arrive
+    /// first.
+    fn wait_event(&mut self, timeout: Duration, pred: impl Fn(&Trb) -> bool) -> Result<Trb> {
+        let result = poll(timeout, || {
+            while let Some(event) = self.events.pop() {
+                if pred(&event) {
+                    return Some(event);
+                }
+
+                self.handle_event(event);
+            }
+
+            None
+        });
+
+        self.update_dequeue();
+
+        result
+    }
+
+    fn command(&mut self, trb: Trb) -> Result<Trb> {
+        let addr = self.commands.push(trb) as u64;
+
+        self.ring_doorbell(0, 0);
+
+        let event = self.wait_event(COMMAND_TIMEOUT, |e| {
+            e.kind() == TRB_COMMAND_COMPLETION && e.parameter == addr
+        })?;
+
+        match event.completion_code() {
+            CC_SUCCESS => Ok(event),
+            cc => {
+                warn!(
+                    "xHCI: command {} failed with completion code {cc}",
+                    trb.kind()
+                );
+                Err(KernelError::Other("xHCI command failed"))
+            }
+        }
+    }
+
+    /// Performs a control transfer on a slot's default pipe, returning the
+    /// length of the data stage.
+    fn control_transfer(
+        &mut self,
+        slot_id: u8,
+        setup: SetupPacket,
+        data: &mut [u8],
+    ) -> Result<usize> {
+        let len = setup.length as usize;
+
+        if len > data.len() || len > PAGE_SIZE {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let slot = self
+            .slots
+            .get_mut(&slot_id)
+            .ok_or(KernelError::InvalidValue)?;
+
+        if !setup.is_in() {
+            slot.control_buf.as_slice_mut()[..len].copy_from_slice(&data[..len]);
+        }
+
+        let buf = slot.control_buf.dma_addr();
+        let ring = slot.rings.get_mut(&EP0_DCI).unwrap();
+
+        ring.push(Trb::setup(setup));
+
+        let data_trb = (len > 0).then(|| ring.push(Trb::data(buf, len, setup.is_in())) as u64);
+        let status_trb = ring.push(Trb::status_stage(len == 0 || !setup.is_in())) as u64;
+
+        self.ring_doorbell(slot_id, EP0_DCI);
+
+        // A short data stage is reported before the status stage completes.
+        let mut residue = 0;
+
+        loop {
+            let event = self.wait_event(TRANSFER_TIMEOUT, |e| {
+                e.kind() == TRB_TRANSFER_EVENT
+                    && (e.parameter == status_trb || Some(e.parameter) == data_trb)
+            })?;
+
+            match event.completion_code() {
+                CC_SUCCESS if event.parameter == status_trb => break,
+                CC_SHORT_PACKET if Some(event.parameter) == data_trb => {
+                    residue = event.residue();
+                }
+                cc => {
+                    debug!(
+                        "xHCI: slot {slot_id}: control transfer failed with completion code {cc}"
+                    );
+                    self.recover_endpoint(slot_id, EP0_DCI)?;
+                    return Err(KernelError::Other("USB transfer failed"));
+                }
+            }
+        }
+
+        let len = len.saturating_sub(residue);
+
+        if setup.is_in() {
+            data[..len].copy_from_slice(&self.slots[&slot_id].control_buf.as_slice()[..len]);
+        }
+
+        Ok(len)
+    }
+
+    /// Clears a halted endpoint, skipping whatever was left on its ring.
+    fn recover_endpoint(&mut self, slot_id: u8, dci: u8) -> Result<()> {
+        self.command(Trb::reset_endpoint(slot_id, dci))?;
+
+        let (dequeue, cycle) = self
+            .slots
+            .get(&slot_id)
+            .and_then(|slot| slot.rings.get(&dci))
+            .ok_or(KernelError::InvalidValue)?
+            .enqueue_ptr();
+
+        self.command(Trb::set_tr_dequeue(slot_id, dci, dequeue, cycle))
+            .map(|_| ())
+    }
+
+    /// Starts polling an interrupt IN endpoint.
+    fn start_pipe(
+        &mut self,
+        slot_id: u8,
+        endpoint: &EndpointDescriptor,
+        handler: Arc<dyn TransferHandler>,
+    ) -> Result<()> {
+        let dci = dci(endpoint);
+        let len = endpoint.packet_size() as usize;
+
+        if endpoint.transfer_type() != TransferType::Interrupt || !endpoint.is_in() || len == 0 {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let buf = self.alloc(len)?;
+
+        let slot = self
+            .slots
+            .get_mut(&slot_id)
+            .ok_or(KernelError::InvalidValue)?;
+
+        if slot.pipes.contains_key(&dci) {
+            return Err(KernelError::InUse);
+        }
+
+        slot.rings
+            .get_mut(&dci)
+            .ok_or(KernelError::InvalidValue)?
+            .push(Trb::normal(buf.dma_addr(), len));
+
+        slot.pipes.insert(dci, Pipe { buf, len, handler });
+
+        self.ring_doorbell(slot_id, dci);
+
+        Ok(())
+    }
+
+    /// Passes a completed interrupt transfer to its handler, and queues the
+    /// next.
+    fn complete_transfer(&mut self, event: Trb) {
+        let (slot_id, dci) = (event.slot_id(), event.endpoint_id());
+
+        let Some(slot) = self.slots.get_mut(&slot_id) else {
+            return;
+        };
+
+        let (Some(pipe), Some(ring)) = (slot.pipes.get(&dci), slot.rings.get_mut(&dci)) else {
+            return;
+        };
+
+        match event.completion_code() {
+            CC_SUCCESS | CC_SHORT_PACKET => {
+                let len = pipe.len.saturating_sub(event.residue());
+
+                pipe.handler.complete(&pipe.buf.as_slice()[..len]);
+                ring.push(Trb::normal(pipe.buf.dma_addr(), pipe.len));
+            }
+            cc => {
+                warn!("xHCI: slot {slot_id}: endpoint {dci} stopped with completion code {cc}");
+                slot.pipes.remove(&dci);
+                return;
+            }
+        }
+
+        self.ring_doorbell(slot_id, dci);
+    }
+
+    fn portsc_offset(port: u8) -> usize {
+        PORTSC_BASE + (port as usize - 1) * PORT_STRIDE
+    }
+
+    fn portsc(&self, port: u8) -> Portsc {
+        Portsc::from_bits_retain(self.op.read(Self::portsc_offset(port)))
+    }
+
+    /// Sets `bits` in a port's status and control register.
+    fn write_portsc(&self, port: u8, bits: Portsc) {
+        let portsc = self.portsc(port) & Portsc::PRESERVE;
+
+        self.op
+            .write(Self::portsc_offset(port), (portsc | bits).bits());
+    }
+
+    /// Clears a port's change bits, returning its status from before.
+    fn ack_port_changes(&self, port: u8) -> Portsc {
+        let portsc = self.portsc(port);
+
+        self.write_portsc(port, portsc & Portsc::CHANGES);
+
+        portsc
+    }
+
+    fn reset_port(&mut self, port: u8) -> Result<()> {
+        self.write_portsc(port, Portsc::PR);
+
+        poll(RESET_TIMEOUT, || {
+            self.portsc(port).contains(Portsc::PRC).then_some(())
+        })?;
+
+        self.write_portsc(port, Portsc::PRC);
+        delay(RESET_RECOVERY);
+
+        Ok(())
+    }
+
+    /// Enumerates the devices attached at boot.
+    fn scan_ports(&mut self) {
+        for port in 1..=self.max_ports {
+            if self.portsc(port).contains(Portsc::CCS)
+                && let Err(e) = self.enumerate_port(port)
+            {
+                warn!("USB: port {port}: could not enumerate device: {e}");
+            }
+        }
+    }
+
+    /// Deals with devices coming and going.
+    fn service_port_changes(&mut self) {
+        while let Some(port) = self.port_changes.pop_first() {
+            let portsc = self.ack_port_changes(port);
+
+            // Resets and the like need no attention.
+            if !portsc.contains(Portsc::CSC) {
+                continue;
+            }
+
+            let attached = self
+                .slots
+                .iter()
+                .find(|(_, slot)| slot.port == port)
+                .map(|(&id, _)| id);
+
+            if let Some(slot_id) = attached {
+                info!("USB: port {port}: device disconnected");
+                self.release_slot(slot_id);
+            }
+
+            if portsc.contains(Portsc::CCS)
+                && let Err(e) = self.enumerate_port(port)
+            {
+                warn!("USB: port {port}: could not enumerate device: {e}");
+            }
+        }
+    }
+
+    fn enumerate_port(&mut self, port: u8) -> Result<()> {
+        self.ack_port_changes(port);
+
+        // USB 3 ports enable themselves; USB 2 ports need a reset.
+        if !self.portsc(port).contains(Portsc::PED) {
+            self.reset_port(port)?;
+        }
+
+        let portsc = self.portsc(port);
+
+        if !portsc.contains(Portsc::CCS | Portsc::PED) {
+            return Err(KernelError::Probe(ProbeError::NoMatch));
+        }
+
+        let speed = ((portsc.bits() >> PORTSC_SPEED_SHIFT) & PORTSC_SPEED_MASK) as u8;
+        let slot_id = self.command(Trb::enable_slot())?.slot_id();
+
+        let result = self
+            .address_device(slot_id, port, speed)
+            .and_then(|()| self.configure_device(slot_id, port));
+
+        if result.is_err() {
+            self.release_slot(slot_id);
+        }
+
+        result
+    }
+
+    /// Sets up a slot's default pipe, and has the controller give the
+    /// device an address.
+    fn address_device(&mut self, slot_id: u8, port: u8, speed: u8) -> Result<()> {
+        let max_packet_size = match speed {
+            SPEED_LOW | SPEED_FULL => 8,
+            SPEED_HIGH => 64,
+            _ => 512,
+        };
+
+        let output = self.alloc(PAGE_SIZE)?;
+        let mut input = InputContext::new(self.alloc(PAGE_SIZE)?, self.ctx_size);
+        let ep0 = Ring::new(self.alloc(PAGE_SIZE)?);
+        let control_buf = self.alloc(PAGE_SIZE)?;
+
+        input.set_add_flags(1 | 1 << EP0_DCI);
+        input.set_slot(speed, port, EP0_DCI);
+        input.set_endpoint(EP0_DCI, EP_TYPE_CONTROL, max_packet_size, 0, ep0.dma_addr());
+
+        let input_addr = input.dma_addr();
+
+        set_entry(&mut self.dcbaa, slot_id as usize, output.dma_addr() as u64);
+
+        self.slots.insert(
+            slot_id,
+            Slot {
+                port,
+                speed,
+                input,
+                _output: output,
+                rings: BTreeMap::from([(EP0_DCI, ep0)]),
+                pipes: BTreeMap::new(),
+                control_buf,
+            },
+        );
+
+        self.command(Trb::address_device(input_addr, slot_id))?;
+
+        // A full-speed device's default pipe may be bigger than the 8 bytes
+        // assumed until now.
+        let mut header = [0; 8];
+
+        self.control_transfer(
+            slot_id,
+            SetupPacket::get_descriptor(DESC_DEVICE, 0, header.len() as u16),
+            &mut header,
+        )?;
+
+        let actual =
+            DeviceDescriptor::max_packet_size0(&header).ok_or(KernelError::InvalidValue)? as u16;
+
+        if speed == SPEED_FULL && actual != max_packet_size {
+            let slot = self.slots.get_mut(&slot_id).unwrap();
+
+            slot.input.set_add_flags(1 << EP0_DCI);
+            slot.input.set_max_packet_size(EP0_DCI, actual);
+
+            let input_addr = slot.input.dma_addr();
+
+            self.command(Trb::evaluate_context(input_addr, slot_id))?;
+        }
+
+        Ok(())
+    }
+
+    /// Reads a device's descriptors, selects its first configuration, and
+    /// hands its interfaces to the class drivers.
+    fn configure_device(&mut self, slot_id: u8, port: u8) -> Result<()> {
+        let mut buf = [0; DeviceDescriptor::SIZE];
+
+        self.control_transfer(
+            slot_id,
+            SetupPacket::get_descriptor(DESC_DEVICE, 0, buf.len() as u16),
+            &mut buf,
+        )?;
+
+        let desc = DeviceDescriptor::parse(&buf).ok_or(KernelError::InvalidValue)?;
+
+        let mut header = [0; ConfigDescriptor::HEADER_SIZE];
+
+        self.control_transfer(
+            slot_id,
+            SetupPacket::get_descriptor(DESC_CONFIGURATION, 0, header.len() as u16),
+            &mut header,
+        )?;
+
+        let total_len = ConfigDescriptor::total_length(&header).ok_or(KernelError::InvalidValue)?;
+        let mut buf = vec![0; (total_len as usize).min(PAGE_SIZE)];
+
+        let len = self.control_transfer(
+            slot_id,
+            SetupPacket::get_descriptor(DESC_CONFIGURATION, 0, buf.len() as u16),
+            &mut buf,
+        )?;
+
+        let config = ConfigDescriptor::parse(&buf[..len]).ok_or(KernelError::InvalidValue)?;
+
+        info!(
+            "USB: port {port}: device {:04x}:{:04x} (USB {:x}.{:x}, class {:02x}:{:02x}:{:02x})",
+            desc.vendor_id,
+            desc.product_id,
+            desc.usb_version >> 8,
+            (desc.usb_version >> 4) & 0xf,
+            desc.class,
+            desc.subclass,
+            desc.protocol,
+        );
+
+        self.configure_endpoints(slot_id, &config)?;
+        self.control_transfer(
+            slot_id,
+            SetupPacket::set_configuration(config.value),
+            &mut [],
+        )?;
+
+        probe_interfaces(
+            &mut SlotDevice {
+                xhci: self,
+                slot_id,
+                descriptor: desc,
+            },
+            &config,
+        );
+
+        Ok(())
+    }
+
+    /// Gives each endpoint of the configuration's default interface settings
+    /// a transfer ring.
+    fn configure_endpoints(&mut self, slot_id: u8, config: &ConfigDescriptor) -> Result<()> {
+        let endpoints: Vec<EndpointDescriptor> = config
+            .interfaces
+            .iter()
+            .filter(|iface| iface.alt_setting == 0)
+            .flat_map(|iface| iface.endpoints.iter().copied())
+            // Isochronous transfers aren't supported.
+            .filter(|ep| ep.transfer_type() != TransferType::Isochronous)
+            .collect();
+
+        if endpoints.is_empty() {
+            return Ok(());
+        }
+
+        let rings = endpoints
+            .iter()
+            .map(|ep| -> Result<_> { Ok((dci(ep), Ring::new(self.alloc(PAGE_SIZE)?))) })
+            .collect::<Result<Vec<_>>>()?;
+
+        let slot = self
+            .slots
+            .get_mut(&slot_id)
+            .ok_or(KernelError::InvalidValue)?;
+
+        let mut add_flags = 1;
+        let mut last_dci = EP0_DCI;
+
+        for (ep, (dci, ring)) in endpoints.iter().zip(&rings) {
+            slot.input.set_endpoint(
+                *dci,
+                endpoint_type(ep),
+                ep.packet_size(),
+                interval(slot.speed, ep),
+                ring.dma_addr(),
+            );
+
+            add_flags |= 1 << *dci;
+            last_dci = last_dci.max(*dci);
+        }
+
+        slot.input.set_add_flags(add_flags);
+        slot.input.set_last_dci(last_dci);
+        slot.rings.extend(rings);
+
+        let input_addr = slot.input.dma_addr();
+
+        self.command(Trb::configure_endpoint(input_addr, slot_id))
+            .map(|_| ())
+    }
+
+    fn release_slot(&mut self, slot_id: u8) {
+        if let Err(e) = self.command(Trb::disable_slot(slot_id)) {
+            warn!("xHCI: could not disable slot {slot_id}: {e}");
+        }
+
+        set_entry(&mut self.dcbaa, slot_id as usize, 0);
+        self.slots.remove(&slot_id);
+    }
+}
+
+impl Drop for XhciInner {
+    fn drop(&mut self) {
+        // Stop the controller before its memory is freed.
+        self.op.write(USBCMD, 0);
+        let _ = poll(HALT_TIMEOUT, || {
+            self.usbsts().contains(UsbSts::HCH).then_some(())
+        });
+    }
+}
+
+/// Takes the controller from firmware, if firmware is using it.
+fn take_ownership(cap: Mmio, hccparams1: u32) {
+    let mut offset = ((hccparams1 >> 16) as usize) << 2;
+
+    while offset != 0 {
+        let header = cap.read(offset);
+
+        if header & 0xff == XECP_LEGACY {
+            cap.write(offset, header | USBLEGSUP_OS_OWNED);
+
+            let released = poll(HANDOFF_TIMEOUT, || {
+                (cap.read(offset) & USBLEGSUP_BIOS_OWNED == 0).then_some(())
+            });
+
+            if released.is_err() {
+                warn!("xHCI: firmware did not hand over the controller");
+            }
+
+            return;
+        }
+
+        match (header >> 8) & 0xff {
+            0 => return,
+            next => offset += (next as usize) << 2,
+        }
+    }
+}
+
+fn reset(op: Mmio) -> Result<()> {
+    let status = || UsbSts::from_bits_retain(op.read(USBSTS));
+
+    op.write(USBCMD, 0);
+    poll(HALT_TIMEOUT, || {
+        status().contains(UsbSts::HCH).then_some(())
+    })?;
+
+    op.write(USBCMD, UsbCmd::HCRST.bits());
+    poll(RESET_TIMEOUT, || {
+        (!UsbCmd::from_bits_retain(op.read(USBCMD)).contains(UsbCmd::HCRST)
+            && !status().contains(UsbSts::CNR))
+        .then_some(())
+    })
+}
+
+/// A device, as handed to class drivers.
+struct SlotDevice<'a> {
+    xhci: &'a mut XhciInner,
+    slot_id: u8,
+    descriptor: DeviceDescriptor,
+}
+
+impl UsbDevice for SlotDevice<'_> {
+    fn descriptor(&self) -> &DeviceDescriptor {
+        &self.descriptor
+    }
+
+    fn control_transfer(&mut self, setup: SetupPacket, data: &mut [u8]) -> Result<usize> {
+        self.xhci.control_transfer(self.slot_id, setup, data)
+    }
+
+    fn poll_interrupt_in(
+        &mut self,
+        endpoint: &EndpointDescriptor,
+        handler: Arc<dyn TransferHandler>,
+    ) -> Result<()> {
+        self.xhci.start_pipe(self.slot_id, endpoint, handler)
+    }
+}
+
+pub struct XhciDriver {
+    inner: Arc<SpinLock<XhciInner>>,
+    _interrupt: ClaimedInterrupt,
+}
+
+impl Driver for XhciDriver {
+    fn name(&self) -> &'static str {
+        "xhci"
+    }
+}
+
+impl InterruptHandler for XhciDriver {
+    fn handle_irq(&self, _desc: InterruptDescriptor) {
+        let mut inner = self.inner.lock_save_irq();
+
+        inner.ack_interrupt();
+        inner.drain_events();
+
+        if inner.port_changes.is_empty() {
+            return;
+        }
+
+        drop(inner);
+
+        // Enumeration waits on the controller, so can't be done here.
+        let inner = self.inner.clone();
+
+        spawn_kernel_task(Priority::Normal, async move {
+            inner.lock_save_irq().service_port_changes();
+        });
+    }
+}
+
+fn xhci_probe(_dm: &mut DriverManager, pci: Arc<PciDevice>) -> Result<Arc<dyn Driver>> {
+    let interrupt_manager = get_interrupt_root().ok_or(ProbeError::Deferred)?;
+    let regs = pci.map_bar(0)?;
+
+    pci.enable_bus_master();
+
+    // SAFETY: BAR 0 holds the controller's registers, and stays mapped.
+    let mut inner = XhciInner::new(unsafe { Mmio::new(regs) })?;
+
+    info!("xHCI controller at {}: {} ports", pci.addr, inner.max_ports);
+
+    inner.start()?;
+    inner.scan_ports();
+
+    let interrupt_config = pci.alloc_irq_vectors(1, IrqTypes::all())?[0];
+    let inner = Arc::new(SpinLock::new(inner));
+
+    let driver = interrupt_manager.claim_interrupt(interrupt_config, |claimed| XhciDriver {
+        inner: inner.clone(),
+        _interrupt: claimed,
+    })?;
+
+    inner.lock_save_irq().enable_interrupts();
+
+    Ok(driver)
+}
+
+pub fn xhci_init(_bus: &mut PlatformBus, _dm: &mut DriverManager) -> Result<()> {
+    PCI_BUS.lock_save_irq().register_pci_driver(
+        PciMatchType::Class {
+            class: PCI_CLASS_SERIAL_BUS,
+            subclass: PCI_SUBCLASS_USB,
+            prog_if: Some(PCI_PROG_IF_XHCI),
+        },
+        Box::new(xhci_probe),
+    );
+
+    Ok(())
+}
+
+kernel_driver!(xhci_init);
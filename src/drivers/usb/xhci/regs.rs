@@ -0,0 +1,156 @@
+//! Register offsets and fields.
+
+use libkernel::memory::address::VA;
+
+// Capability registers.
+pub const CAPLENGTH: usize = 0x00;
+pub const HCSPARAMS1: usize = 0x04;
+pub const HCSPARAMS2: usize = 0x08;
+pub const HCCPARAMS1: usize = 0x10;
+pub const DBOFF: usize = 0x14;
+pub const RTSOFF: usize = 0x18;
+
+pub const HCCPARAMS1_AC64: u32 = 1 << 0;
+pub const HCCPARAMS1_CSZ: u32 = 1 << 2;
+pub const HCCPARAMS1_PPC: u32 = 1 << 3;
+
+// Operational registers.
+pub const USBCMD: usize = 0x00;
+pub const USBSTS: usize = 0x04;
+pub const PAGESIZE: usize = 0x08;
+pub const CRCR: usize = 0x18;
+pub const DCBAAP: usize = 0x30;
+pub const CONFIG: usize = 0x38;
+pub const PORTSC_BASE: usize = 0x400;
+pub const PORT_STRIDE: usize = 0x10;
+
+/// `PAGESIZE` bit for 4KiB pages.
+pub const PAGESIZE_4K: u32 = 1 << 0;
+/// Ring Cycle State, in `CRCR`.
+pub const CRCR_RCS: u64 = 1 << 0;
+
+// Interrupter 0, in the runtime registers.
+pub const IR0: usize = 0x20;
+pub const IMAN: usize = 0x00;
+pub const ERSTSZ: usize = 0x08;
+pub const ERSTBA: usize = 0x10;
+pub const ERDP: usize = 0x18;
+
+pub const IMAN_IP: u32 = 1 << 0;
+pub const IMAN_IE: u32 = 1 << 1;
+/// Event Handler Busy, in `ERDP`.
+pub const ERDP_EHB: u64 = 1 << 3;
+
+// Extended capabilities.
+pub const XECP_LEGACY: u32 = 1;
+pub const USBLEGSUP_BIOS_OWNED: u32 = 1 << 16;
+pub const USBLEGSUP_OS_OWNED: u32 = 1 << 24;
+
+pub const PORTSC_SPEED_SHIFT: u32 = 10;
+pub const PORTSC_SPEED_MASK: u32 = 0xf;
+
+// Port speeds, as reported in `PORTSC` and set in slot contexts.
+pub const SPEED_FULL: u8 = 1;
+pub const SPEED_LOW: u8 = 2;
+pub const SPEED_HIGH: u8 = 3;
+
+bitflags::bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct UsbCmd: u32 {
+        const RUN = 1 << 0;
+        const HCRST = 1 << 1;
+        const INTE = 1 << 2;
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct UsbSts: u32 {
+        const HCH = 1 << 0;
+        const HSE = 1 << 2;
+        const EINT = 1 << 3;
+        const PCD = 1 << 4;
+        const CNR = 1 << 11;
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Portsc: u32 {
+        const CCS = 1 << 0;
+        const PED = 1 << 1;
+        const PR = 1 << 4;
+        const PLS = 0xf << 5;
+        const PP = 1 << 9;
+        const PIC = 0x3 << 14;
+        const CSC = 1 << 17;
+        const PEC = 1 << 18;
+        const WRC = 1 << 19;
+        const OCC = 1 << 20;
+        const PRC = 1 << 21;
+        const PLC = 1 << 22;
+        const CEC = 1 << 23;
+        const WCE = 1 << 25;
+        const WDE = 1 << 26;
+        const WOE = 1 << 27;
+
+        /// The write-1-to-clear change bits.
+        const CHANGES = Self::CSC.bits()
+            | Self::PEC.bits()
+            | Self::WRC.bits()
+            | Self::OCC.bits()
+            | Self::PRC.bits()
+            | Self::PLC.bits()
+            | Self::CEC.bits();
+
+        /// The bits which must be written back unchanged. Writing back
+        /// anything else would clear change bits, or disable the port.
+        const PRESERVE = Self::PLS.bits()
+            | Self::PP.bits()
+            | Self::PIC.bits()
+            | Self::WCE.bits()
+            | Self::WDE.bits()
+            | Self::WOE.bits();
+    }
+}
+
+/// A block of 32-bit registers.
+#[derive(Clone, Copy)]
+pub struct Mmio(VA);
+
+// SAFETY: The registers are a device mapping which lives for as long as the
+// kernel does.
+unsafe impl Send for Mmio {}
+unsafe impl Sync for Mmio {}
+
+impl Mmio {
+    /// # Safety
+    ///
+    /// `base` must be a device mapping of every register accessed through
+    /// the block, which is never unmapped.
+    pub unsafe fn new(base: VA) -> Self {
+        Self(base)
+    }
+
+    /// Returns the block of registers starting `offset` bytes in.
+    pub fn at(self, offset: usize) -> Self {
+        Self(self.0.add_bytes(offset))
+    }
+
+    fn reg(self, offset: usize) -> *mut u32 {
+        assert!(offset.is_multiple_of(4));
+
+        self.0.add_bytes(offset).as_ptr_mut().cast()
+    }
+
+    pub fn read(self, offset: usize) -> u32 {
+        unsafe { self.reg(offset).read_volatile() }
+    }
+
+    pub fn write(self, offset: usize, val: u32) {
+        unsafe { self.reg(offset).write_volatile(val) }
+    }
+
+    /// Writes a 64-bit register, low half first, for controllers which
+    /// can't take 64-bit accesses.
+    pub fn write_u64(self, offset: usize, val: u64) {
+        self.write(offset, val as u32);
+        self.write(offset + 4, (val >> 32) as u32);
+    }
+}
@@ -0,0 +1,332 @@
+//! Transfer Request Blocks and the rings which hold them.
+//!
+//! Software produces TRBs onto the command ring and each endpoint's transfer
+//! ring, and the controller produces them onto the event ring. Ownership of
+//! each TRB is passed back and forth with its cycle bit, which the producer
+//! flips each time it wraps around the ring.
+
+use crate::{
+    drivers::usb::descriptor::SetupPacket,
+    memory::dma::{DmaAddr, DmaCoherent},
+};
+use core::sync::atomic::{Ordering, fence};
+use libkernel::memory::PAGE_SIZE;
+
+pub const TRB_SIZE: usize = 16;
+
+/// How many TRBs fit in a one-page ring segment.
+const SEGMENT_TRBS: usize = PAGE_SIZE / TRB_SIZE;
+
+// TRB types.
+pub const TRB_NORMAL: u32 = 1;
+pub const TRB_SETUP: u32 = 2;
+pub const TRB_DATA: u32 = 3;
+pub const TRB_STATUS: u32 = 4;
+pub const TRB_LINK: u32 = 6;
+pub const TRB_ENABLE_SLOT: u32 = 9;
+pub const TRB_DISABLE_SLOT: u32 = 10;
+pub const TRB_ADDRESS_DEVICE: u32 = 11;
+pub const TRB_CONFIGURE_ENDPOINT: u32 = 12;
+pub const TRB_EVALUATE_CONTEXT: u32 = 13;
+pub const TRB_RESET_ENDPOINT: u32 = 14;
+pub const TRB_SET_TR_DEQUEUE: u32 = 16;
+pub const TRB_TRANSFER_EVENT: u32 = 32;
+pub const TRB_COMMAND_COMPLETION: u32 = 33;
+pub const TRB_PORT_STATUS_CHANGE: u32 = 34;
+
+// Control field bits.
+const TRB_CYCLE: u32 = 1 << 0;
+/// In Link TRBs: flip the cycle bit on following the link.
+const TRB_TOGGLE_CYCLE: u32 = 1 << 1;
+/// Interrupt on Short Packet.
+const TRB_ISP: u32 = 1 << 2;
+/// Interrupt On Completion.
+const TRB_IOC: u32 = 1 << 5;
+/// Immediate Data: the parameter holds the data, not a pointer to it.
+const TRB_IDT: u32 = 1 << 6;
+const TRB_DIR_IN: u32 = 1 << 16;
+
+/// Setup TRB Transfer Types.
+const TRT_NO_DATA: u32 = 0;
+const TRT_OUT: u32 = 2;
+const TRT_IN: u32 = 3;
+
+// Completion codes.
+pub const CC_SUCCESS: u8 = 1;
+pub const CC_SHORT_PACKET: u8 = 13;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Trb {
+    pub parameter: u64,
+    pub status: u32,
+    pub control: u32,
+}
+
+impl Trb {
+    fn new(kind: u32, parameter: u64, status: u32, flags: u32) -> Self {
+        Self {
+            parameter,
+            status,
+            control: kind << 10 | flags,
+        }
+    }
+
+    fn slot_flags(slot: u8) -> u32 {
+        (slot as u32) << 24
+    }
+
+    fn endpoint_flags(slot: u8, dci: u8) -> u32 {
+        Self::slot_flags(slot) | (dci as u32) << 16
+    }
+
+    pub fn enable_slot() -> Self {
+        Self::new(TRB_ENABLE_SLOT, 0, 0, 0)
+    }
+
+    pub fn disable_slot(slot: u8) -> Self {
+        Self::new(TRB_DISABLE_SLOT, 0, 0, Self::slot_flags(slot))
+    }
+
+    pub fn address_device(input: DmaAddr, slot: u8) -> Self {
+        Self::new(TRB_ADDRESS_DEVICE, input as u64, 0, Self::slot_flags(slot))
+    }
+
+    pub fn configure_endpoint(input: DmaAddr, slot: u8) -> Self {
+        Self::new(
+            TRB_CONFIGURE_ENDPOINT,
+            input as u64,
+            0,
+            Self::slot_flags(slot),
+        )
+    }
+
+    pub fn evaluate_context(input: DmaAddr, slot: u8) -> Self {
+        Self::new(
+            TRB_EVALUATE_CONTEXT,
+            input as u64,
+            0,
+            Self::slot_flags(slot),
+        )
+    }
+
+    pub fn reset_endpoint(slot: u8, dci: u8) -> Self {
+        Self::new(TRB_RESET_ENDPOINT, 0, 0, Self::endpoint_flags(slot, dci))
+    }
+
+    /// Points an endpoint at `dequeue` on its transfer ring, with the ring's
+    /// current cycle state.
+    pub fn set_tr_dequeue(slot: u8, dci: u8, dequeue: DmaAddr, cycle: bool) -> Self {
+        Self::new(
+            TRB_SET_TR_DEQUEUE,
+            dequeue as u64 | cycle as u64,
+            0,
+            Self::endpoint_flags(slot, dci),
+        )
+    }
+
+    pub fn setup(setup: SetupPacket) -> Self {
+        let trt = match (setup.length, setup.is_in()) {
+            (0, _) => TRT_NO_DATA,
+            (_, true) => TRT_IN,
+            (_, false) => TRT_OUT,
+        };
+
+        Self::new(TRB_SETUP, setup.to_bits(), 8, TRB_IDT | trt << 16)
+    }
+
+    pub fn data(buf: DmaAddr, len: usize, is_in: bool) -> Self {
+        let dir = if is_in { TRB_DIR_IN } else { 0 };
+
+        Self::new(TRB_DATA, buf as u64, len as u32, TRB_ISP | dir)
+    }
+
+    /// The status stage goes the opposite way to the data stage, or IN
+    /// without one.
+    pub fn status_stage(is_in: bool) -> Self {
+        let dir = if is_in { TRB_DIR_IN } else { 0 };
+
+        Self::new(TRB_STATUS, 0, 0, TRB_IOC | dir)
+    }
+
+    /// A bulk or interrupt transfer.
+    pub fn normal(buf: DmaAddr, len: usize) -> Self {
+        Self::new(TRB_NORMAL, buf as u64, len as u32, TRB_ISP | TRB_IOC)
+    }
+
+    fn link(target: DmaAddr) -> Self {
+        Self::new(TRB_LINK, target as u64, 0, TRB_TOGGLE_CYCLE)
+    }
+
+    pub fn kind(&self) -> u32 {
+        (self.control >> 10) & 0x3f
+    }
+
+    pub fn completion_code(&self) -> u8 {
+        (self.status >> 24) as u8
+    }
+
+    /// For transfer events: how many bytes of the transfer were not sent.
+    pub fn residue(&self) -> usize {
+        (self.status & 0xff_ffff) as usize
+    }
+
+    pub fn slot_id(&self) -> u8 {
+        (self.control >> 24) as u8
+    }
+
+    pub fn endpoint_id(&self) -> u8 {
+        ((self.control >> 16) & 0x1f) as u8
+    }
+
+    /// For port status change events: the port that changed.
+    pub fn port_id(&self) -> u8 {
+        (self.parameter >> 24) as u8
+    }
+}
+
+fn trb_ptr(buf: &DmaCoherent, idx: usize) -> *mut Trb {
+    debug_assert!(idx < SEGMENT_TRBS);
+
+    // SAFETY: The index is within the one-page segment.
+    unsafe { buf.va().as_ptr_mut().cast::<Trb>().add(idx) }
+}
+
+/// A ring of TRBs produced by software: the command ring, or a transfer
+/// ring. The last TRB of the segment links back to the first.
+pub struct Ring {
+    segment: DmaCoherent,
+    enqueue: usize,
+    cycle: bool,
+}
+
+impl Ring {
+    pub fn new(segment: DmaCoherent) -> Self {
+        assert!(segment.len() >= PAGE_SIZE);
+
+        let link = Trb::link(segment.dma_addr());
+
+        // SAFETY: The segment is zeroed, so the controller owns none of it.
+        unsafe { trb_ptr(&segment, SEGMENT_TRBS - 1).write_volatile(link) };
+
+        Self {
+            segment,
+            enqueue: 0,
+            cycle: true,
+        }
+    }
+
+    /// The address the controller should start dequeuing from.
+    pub fn dma_addr(&self) -> DmaAddr {
+        self.segment.dma_addr()
+    }
+
+    /// The current enqueue pointer and cycle state, as needed to point the
+    /// controller back at the ring.
+    pub fn enqueue_ptr(&self) -> (DmaAddr, bool) {
+        (
+            self.segment.dma_addr() + self.enqueue * TRB_SIZE,
+            self.cycle,
+        )
+    }
+
+    /// Hands `trb` to the controller, returning its address. The controller
+    /// won't see it until its doorbell is rung.
+    pub fn push(&mut self, mut trb: Trb) -> DmaAddr {
+        let addr = self.enqueue_ptr().0;
+
+        trb.control = (trb.control & !TRB_CYCLE) | self.cycle as u32;
+        self.write(self.enqueue, trb);
+        self.enqueue += 1;
+
+        if self.enqueue == SEGMENT_TRBS - 1 {
+            let link = trb_ptr(&self.segment, self.enqueue);
+
+            // SAFETY: The link TRB lies within the segment.
+            let mut link_trb = unsafe { link.read_volatile() };
+            link_trb.control = (link_trb.control & !TRB_CYCLE) | self.cycle as u32;
+            self.write(self.enqueue, link_trb);
+
+            self.enqueue = 0;
+            self.cycle = !self.cycle;
+        }
+
+        addr
+    }
+
+    fn write(&mut self, idx: usize, trb: Trb) {
+        let ptr = trb_ptr(&self.segment, idx);
+
+        // SAFETY: The TRB lies within the segment. The cycle bit is written
+        // last, so the controller never sees a partial TRB.
+        unsafe {
+            (&raw mut (*ptr).parameter).write_volatile(trb.parameter);
+            (&raw mut (*ptr).status).write_volatile(trb.status);
+            fence(Ordering::Release);
+            (&raw mut (*ptr).control).write_volatile(trb.control);
+        }
+    }
+}
+
+/// The ring of events produced by the controller, with the one-entry Event
+/// Ring Segment Table describing it.
+pub struct EventRing {
+    segment: DmaCoherent,
+    table: DmaCoherent,
+    dequeue: usize,
+    cycle: bool,
+}
+
+impl EventRing {
+    pub fn new(segment: DmaCoherent, mut table: DmaCoherent) -> Self {
+        assert!(segment.len() >= PAGE_SIZE && table.len() >= TRB_SIZE);
+
+        let entry = table.as_slice_mut();
+        entry[..8].copy_from_slice(&(segment.dma_addr() as u64).to_le_bytes());
+        entry[8..12].copy_from_slice(&(SEGMENT_TRBS as u32).to_le_bytes());
+
+        Self {
+            segment,
+            table,
+            dequeue: 0,
+            cycle: true,
+        }
+    }
+
+    /// The address of the Event Ring Segment Table.
+    pub fn table_addr(&self) -> DmaAddr {
+        self.table.dma_addr()
+    }
+
+    /// The address of the next TRB to be dequeued, for `ERDP`.
+    pub fn dequeue_ptr(&self) -> DmaAddr {
+        self.segment.dma_addr() + self.dequeue * TRB_SIZE
+    }
+
+    /// Takes the next event, if the controller has produced one.
+    pub fn pop(&mut self) -> Option<Trb> {
+        let ptr = trb_ptr(&self.segment, self.dequeue);
+
+        // SAFETY: The TRB lies within the segment. Its cycle bit is checked
+        // before the rest is read.
+        let trb = unsafe {
+            let control = (&raw const (*ptr).control).read_volatile();
+
+            if (control & TRB_CYCLE != 0) != self.cycle {
+                return None;
+            }
+
+            fence(Ordering::Acquire);
+            ptr.read_volatile()
+        };
+
+        self.dequeue += 1;
+
+        if self.dequeue == SEGMENT_TRBS {
+            self.dequeue = 0;
+            self.cycle = !self.cycle;
+        }
+
+        Some(trb)
+    }
+}
@@ -0,0 +1,895 @@
+//! VirtIO console devices.
+//!
+//! A virtio-console device carries one or more ports, each a pair of
+//! virtqueues. Without `VIRTIO_CONSOLE_F_MULTIPORT` there is a single port,
+//! which is a console. With it, the host announces ports, and says which of
+//! them are consoles, through messages on a control queue pair.
+//!
+//! Every port is exposed as a raw byte stream, `/dev/vport<dev>p<port>`, and
+//! named ports also as `/dev/virtio-ports/<name>`, as guest agents expect.
+//! Console ports are additionally ttys, `/dev/hvcN`.
+
+use crate::{
+    arch::ArchImpl,
+    console::{
+        Console, set_active_console,
+        tty::{Tty, TtyInputHandler},
+    },
+    drivers::{
+        CharDriver, Driver, DriverManager, OpenableDevice, ReservedMajors,
+        fs::dev::devfs,
+        init::PlatformBus,
+        pci::{IrqTypes, PCI_BUS, PciDevice, PciMatchType},
+        probe::{DeviceDescriptor, DeviceMatchType, FdtFlags},
+        virtio_hal::{VIRTIO_PCI_VENDOR, VirtioHal, attach_fdt_node, pci_transport},
+    },
+    fs::{
+        fops::FileOps,
+        open_file::{FileCtx, OpenFile},
+    },
+    interrupts::{
+        ClaimedInterrupt, InterruptConfig, InterruptDescriptor, InterruptHandler, InterruptManager,
+        get_interrupt_root,
+    },
+    kernel::cmdline::is_console,
+    kernel_driver,
+    memory::uaccess::{copy_from_user_slice, copy_to_user_slice},
+    process::thread_group::signal::{InterruptResult, Interruptable},
+    sync::{CondVar, OnceLock, SpinLock},
+};
+use alloc::{
+    boxed::Box,
+    collections::{btree_map::BTreeMap, vec_deque::VecDeque},
+    format,
+    string::String,
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+use async_trait::async_trait;
+use core::{
+    fmt::{self, Write},
+    pin::Pin,
+    ptr::NonNull,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+};
+use libkernel::{
+    driver::CharDevDescriptor,
+    error::{FsError, KernelError, ProbeError, Result},
+    fs::{FileType, OpenFlags, attr::FilePermissions},
+    memory::{
+        address::{PA, UA, VA},
+        proc_vm::address_space::{KernAddressSpace, VirtualMemory},
+        region::PhysMemoryRegion,
+    },
+    sync::condvar::WakeupType,
+};
+use log::{info, warn};
+use virtio_drivers::{
+    queue::VirtQueue,
+    transport::{
+        DeviceType, SomeTransport, Transport,
+        mmio::{MmioTransport, VirtIOHeader},
+    },
+};
+
+const QUEUE_SIZE: usize = 16;
+const RX_BUF_SIZE: usize = 256;
+
+/// Ports beyond this are ignored, to bound the memory spent on virtqueues.
+const MAX_PORTS: u32 = 8;
+
+const CONTROL_RECEIVEQ: u16 = 2;
+const CONTROL_TRANSMITQ: u16 = 3;
+
+// Control message events.
+const DEVICE_READY: u16 = 0;
+const DEVICE_ADD: u16 = 1;
+const DEVICE_REMOVE: u16 = 2;
+const PORT_READY: u16 = 3;
+const CONSOLE_PORT: u16 = 4;
+const PORT_OPEN: u16 = 6;
+const PORT_NAME: u16 = 7;
+
+bitflags::bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    struct Features: u64 {
+        const MULTIPORT = 1 << 1;
+        const VERSION_1 = 1 << 32;
+    }
+}
+
+/// Returns the receive and transmit queue indices of a port.
+fn port_queues(port: u32) -> (u16, u16) {
+    match port {
+        0 => (0, 1),
+        n => (2 + 2 * n as u16, 3 + 2 * n as u16),
+    }
+}
+
+fn virtio_err(_: virtio_drivers::Error) -> KernelError {
+    KernelError::Other("virtio-console queue error")
+}
+
+/// `struct virtio_console_control`.
+#[derive(Clone, Copy, Debug)]
+struct ControlMsg {
+    id: u32,
+    event: u16,
+    value: u16,
+}
+
+impl ControlMsg {
+    const SIZE: usize = 8;
+
+    fn parse(buf: &[u8]) -> Option<Self> {
+        let buf = buf.get(..Self::SIZE)?;
+
+        Some(Self {
+            id: u32::from_le_bytes(buf[0..4].try_into().unwrap()),
+            event: u16::from_le_bytes(buf[4..6].try_into().unwrap()),
+            value: u16::from_le_bytes(buf[6..8].try_into().unwrap()),
+        })
+    }
+
+    fn to_bytes(self) -> [u8; Self::SIZE] {
+        let mut buf = [0; Self::SIZE];
+
+        buf[0..4].copy_from_slice(&self.id.to_le_bytes());
+        buf[4..6].copy_from_slice(&self.event.to_le_bytes());
+        buf[6..8].copy_from_slice(&self.value.to_le_bytes());
+
+        buf
+    }
+}
+
+/// A device-writable queue, kept stocked with buffers.
+struct RxQueue {
+    queue: VirtQueue<VirtioHal, QUEUE_SIZE>,
+    idx: u16,
+    bufs: BTreeMap<u16, Box<[u8]>>,
+}
+
+impl RxQueue {
+    fn new(transport: &mut SomeTransport<'static>, idx: u16) -> Result<Self> {
+        Ok(Self {
+            queue: VirtQueue::new(transport, idx, false, false).map_err(virtio_err)?,
+            idx,
+            bufs: BTreeMap::new(),
+        })
+    }
+
+    fn fill(&mut self, transport: &mut SomeTransport<'static>) -> Result<()> {
+        while self.queue.available_desc() > 0 {
+            let mut buf = vec![0; RX_BUF_SIZE].into_boxed_slice();
+
+            // SAFETY: The buffer is kept in `bufs` until the device returns it.
+            let token = unsafe { self.queue.add(&[], &mut [&mut buf]) }.map_err(virtio_err)?;
+            self.bufs.insert(token, buf);
+        }
+
+        if self.queue.should_notify() {
+            transport.notify(self.idx);
+        }
+
+        Ok(())
+    }
+
+    /// Takes the data from the next filled buffer, if any.
+    fn pop(&mut self) -> Option<Vec<u8>> {
+        let token = self.queue.peek_used()?;
+        let mut buf = self.bufs.remove(&token)?;
+
+        // SAFETY: `buf` is the buffer that was added with `token`.
+        let len = unsafe { self.queue.pop_used(token, &[], &mut [&mut buf]) }.ok()?;
+
+        Some(buf[..(len as usize).min(buf.len())].to_vec())
+    }
+}
+
+struct PortQueues {
+    rx: RxQueue,
+    tx: VirtQueue<VirtioHal, QUEUE_SIZE>,
+}
+
+struct Inner {
+    transport: SomeTransport<'static>,
+    control: Option<(RxQueue, VirtQueue<VirtioHal, QUEUE_SIZE>)>,
+    queues: BTreeMap<u32, PortQueues>,
+}
+
+/// One port of a virtio-console device.
+struct Port {
+    dev: Weak<VirtioConsole>,
+    id: u32,
+    /// Set while a guest process has the raw port open.
+    guest_open: AtomicBool,
+    /// Whether the host end is open; writes are dropped while it is closed.
+    host_open: AtomicBool,
+    tty_handler: SpinLock<Option<Weak<dyn TtyInputHandler>>>,
+    /// Received data, for raw readers of the port.
+    rx: CondVar<VecDeque<u8>>,
+}
+
+impl Port {
+    fn transmit(&self, buf: &[u8]) {
+        if let Some(dev) = self.dev.upgrade() {
+            // Don't log a failure: the port may be carrying the log.
+            let _ = dev.transmit(self.id, buf);
+        }
+    }
+
+    fn receive(&self, data: &[u8]) {
+        let handler = self
+            .tty_handler
+            .lock_save_irq()
+            .as_ref()
+            .and_then(|h| h.upgrade());
+
+        match handler {
+            Some(handler) => data.iter().for_each(|&b| handler.push_byte(b)),
+            None => self.rx.update(|rx| {
+                rx.extend(data);
+                WakeupType::All
+            }),
+        }
+    }
+}
+
+impl Write for &Port {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.transmit(s.as_bytes());
+        Ok(())
+    }
+}
+
+impl Console for Port {
+    fn write_char(&self, c: char) {
+        let mut buf = [0; 4];
+        self.transmit(c.encode_utf8(&mut buf).as_bytes());
+    }
+
+    fn write_fmt(&self, args: fmt::Arguments) -> fmt::Result {
+        let mut this = self;
+        Write::write_fmt(&mut this, args)
+    }
+
+    fn write_buf(&self, buf: &[u8]) {
+        self.transmit(buf);
+    }
+
+    fn register_input_handler(&self, handler: Weak<dyn TtyInputHandler>) {
+        *self.tty_handler.lock_save_irq() = Some(handler);
+    }
+}
+
+/// A virtio-console device.
+pub struct VirtioConsole {
+    name: &'static str,
+    /// The device's number, used in its ports' `vport` names.
+    index: usize,
+    /// Whether the firmware chose this device as the system console.
+    active_console: bool,
+    inner: SpinLock<Inner>,
+    ports: SpinLock<BTreeMap<u32, Arc<Port>>>,
+}
+
+impl VirtioConsole {
+    fn transmit(&self, port: u32, buf: &[u8]) -> Result<()> {
+        // The buffer may be on the stack; give the device a copy it can reach.
+        let data = buf.to_vec();
+
+        let mut inner = self.inner.lock_save_irq();
+        let Inner {
+            transport, queues, ..
+        } = &mut *inner;
+
+        let queues = queues.get_mut(&port).ok_or(FsError::NoDevice)?;
+
+        queues
+            .tx
+            .add_notify_wait_pop(&[&data], &mut [], transport)
+            .map_err(virtio_err)?;
+
+        Ok(())
+    }
+
+    fn send_control(&self, id: u32, event: u16, value: u16) -> Result<()> {
+        let msg = ControlMsg { id, event, value }.to_bytes().to_vec();
+
+        let mut inner = self.inner.lock_save_irq();
+        let Inner {
+            transport, control, ..
+        } = &mut *inner;
+
+        let (_, tx) = control.as_mut().ok_or(KernelError::NotSupported)?;
+
+        tx.add_notify_wait_pop(&[&msg], &mut [], transport)
+            .map_err(virtio_err)?;
+
+        Ok(())
+    }
+
+    fn add_port(self: &Arc<Self>, id: u32) -> Result<Arc<Port>> {
+        if !self.inner.lock_save_irq().queues.contains_key(&id) {
+            warn!("virtio-console: ignoring port {id}, beyond the ports supported");
+            return Err(KernelError::InvalidValue);
+        }
+
+        let port = Arc::new(Port {
+            dev: Arc::downgrade(self),
+            id,
+            guest_open: AtomicBool::new(false),
+            host_open: AtomicBool::new(false),
+            tty_handler: SpinLock::new(None),
+            rx: CondVar::new(VecDeque::new()),
+        });
+
+        self.ports.lock_save_irq().insert(id, port.clone());
+
+        let minor = VPORT_NEXT.fetch_add(1, Ordering::Relaxed);
+
+        VPORTS.lock_save_irq().insert(minor, port.clone());
+
+        if let Some(&major) = VPORT_MAJOR.get() {
+            devfs().mknod(
+                format!("vport{}p{id}", self.index),
+                CharDevDescriptor { major, minor },
+                FilePermissions::from_bits_retain(0o600),
+            )?;
+        }
+
+        Ok(port)
+    }
+
+    fn make_console(&self, port: &Arc<Port>) -> Result<()> {
+        let minor = HVC_NEXT.fetch_add(1, Ordering::Relaxed);
+        let desc = CharDevDescriptor {
+            major: ReservedMajors::Hvc as _,
+            minor,
+        };
+
+        HVC_PORTS.lock_save_irq().insert(minor, port.clone());
+
+        devfs().mknod(
+            format!("hvc{minor}"),
+            desc,
+            FilePermissions::from_bits_retain(0o600),
+        )?;
+
+        // Consoles are always considered open by the host.
+        port.host_open.store(true, Ordering::Relaxed);
+
+        if is_console(&format!("hvc{minor}"), self.active_console && minor == 0) {
+            set_active_console(port.clone(), desc)?;
+        }
+
+        Ok(())
+    }
+
+    fn name_port(&self, port: &Arc<Port>, name: &[u8]) -> Result<()> {
+        let name = String::from_utf8_lossy(name.split(|&b| b == 0).next().unwrap_or(&[]));
+
+        if name.is_empty() || name.contains('/') {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let minor = VPORTS
+            .lock_save_irq()
+            .iter()
+            .find(|(_, p)| Arc::ptr_eq(p, port))
+            .map(|(&minor, _)| minor)
+            .ok_or(FsError::NoDevice)?;
+
+        let major = *VPORT_MAJOR.get().ok_or(FsError::NoDevice)?;
+
+        devfs().mknod(
+            format!("virtio-ports/{name}"),
+            CharDevDescriptor { major, minor },
+            FilePermissions::from_bits_retain(0o600),
+        )
+    }
+
+    fn handle_control(self: &Arc<Self>, msg: ControlMsg, extra: &[u8]) -> Result<()> {
+        let port = self.ports.lock_save_irq().get(&msg.id).cloned();
+
+        match (msg.event, port) {
+            (DEVICE_ADD, None) => {
+                let ready = self.add_port(msg.id).is_ok();
+                self.send_control(msg.id, PORT_READY, ready as u16)?;
+            }
+            (DEVICE_REMOVE, Some(port)) => {
+                port.host_open.store(false, Ordering::Relaxed);
+                self.ports.lock_save_irq().remove(&msg.id);
+                remove_port_nodes(&port);
+            }
+            (CONSOLE_PORT, Some(port)) => {
+                self.make_console(&port)?;
+                self.send_control(msg.id, PORT_OPEN, 1)?;
+            }
+            (PORT_OPEN, Some(port)) => {
+                port.host_open.store(msg.value != 0, Ordering::Relaxed);
+                port.rx.update(|_| WakeupType::All);
+            }
+            (PORT_NAME, Some(port)) => self.name_port(&port, extra)?,
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+struct VirtioConsoleDriver {
+    dev: Arc<VirtioConsole>,
+    _interrupt: ClaimedInterrupt,
+}
+
+impl Driver for VirtioConsoleDriver {
+    fn name(&self) -> &'static str {
+        self.dev.name
+    }
+}
+
+impl InterruptHandler for VirtioConsoleDriver {
+    fn handle_irq(&self, _desc: InterruptDescriptor) {
+        let mut control_msgs = Vec::new();
+        let mut port_data = Vec::new();
+
+        {
+            let mut inner = self.dev.inner.lock_save_irq();
+            let Inner {
+                transport,
+                control,
+                queues,
+            } = &mut *inner;
+
+            let _ = transport.ack_interrupt();
+
+            if let Some((rx, _)) = control {
+                while let Some(buf) = rx.pop() {
+                    control_msgs.push(buf);
+                }
+
+                let _ = rx.fill(transport);
+            }
+
+            for (&id, port) in queues.iter_mut() {
+                while let Some(buf) = port.rx.pop() {
+                    port_data.push((id, buf));
+                }
+
+                let _ = port.rx.fill(transport);
+            }
+        }
+
+        // Act on the data with the device unlocked, as control messages may
+        // need replies.
+        for buf in control_msgs {
+            if let Some(msg) = ControlMsg::parse(&buf)
+                && let Err(e) = self.dev.handle_control(msg, &buf[ControlMsg::SIZE..])
+            {
+                warn!("virtio-console: control message {msg:?} failed: {e:?}");
+            }
+        }
+
+        let ports = self.dev.ports.lock_save_irq().clone();
+
+        for (id, data) in port_data {
+            if let Some(port) = ports.get(&id) {
+                port.receive(&data);
+            }
+        }
+    }
+}
+
+/// A raw, byte-stream view of a port.
+struct PortFile {
+    port: Arc<Port>,
+}
+
+impl PortFile {
+    async fn read_impl(&mut self, buf: UA, count: usize, nonblock: bool) -> Result<usize> {
+        if count == 0 {
+            return Ok(0);
+        }
+
+        let take = move |rx: &mut VecDeque<u8>| {
+            (!rx.is_empty()).then(|| rx.drain(..count.min(rx.len())).collect::<Vec<u8>>())
+        };
+
+        let mut taken = None;
+
+        self.port.rx.update(|rx| {
+            taken = take(rx);
+            WakeupType::None
+        });
+
+        let data = match taken {
+            Some(data) => data,
+            None if nonblock => return Err(KernelError::TryAgain),
+            None => match self.port.rx.wait_until(take).interruptable().await {
+                InterruptResult::Interrupted => return Err(KernelError::Interrupted),
+                InterruptResult::Uninterrupted(data) => data,
+            },
+        };
+
+        copy_to_user_slice(&data, buf).await?;
+
+        Ok(data.len())
+    }
+}
+
+#[async_trait]
+impl FileOps for PortFile {
+    async fn read(&mut self, ctx: &mut FileCtx, buf: UA, count: usize) -> Result<usize> {
+        self.read_impl(buf, count, ctx.flags.contains(OpenFlags::O_NONBLOCK))
+            .await
+    }
+
+    async fn readat(&mut self, buf: UA, count: usize, _offset: u64) -> Result<usize> {
+        self.read_impl(buf, count, false).await
+    }
+
+    async fn writeat(&mut self, buf: UA, count: usize, _offset: u64) -> Result<usize> {
+        // Data written while the host isn't listening would be lost.
+        if !self.port.host_open.load(Ordering::Relaxed) {
+            return Err(KernelError::TryAgain);
+        }
+
+        let mut data = vec![0; count.min(RX_BUF_SIZE * QUEUE_SIZE)];
+        copy_from_user_slice(buf, &mut data).await?;
+
+        let dev = self.port.dev.upgrade().ok_or(FsError::NoDevice)?;
+        dev.transmit(self.port.id, &data)?;
+
+        Ok(data.len())
+    }
+
+    fn poll_read_ready(&self) -> Pin<Box<dyn Future<Output = Result<()>> + 'static + Send>> {
+        let port = self.port.clone();
+
+        Box::pin(async move {
+            port.rx
+                .wait_until(|rx| (!rx.is_empty()).then_some(()))
+                .await;
+            Ok(())
+        })
+    }
+
+    fn poll_write_ready(&self) -> Pin<Box<dyn Future<Output = Result<()>> + 'static + Send>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    async fn release(&mut self, _ctx: &FileCtx) -> Result<()> {
+        self.port.guest_open.store(false, Ordering::Relaxed);
+
+        if let Some(dev) = self.port.dev.upgrade() {
+            let _ = dev.send_control(self.port.id, PORT_OPEN, 0);
+        }
+
+        Ok(())
+    }
+}
+
+struct PortNode {
+    port: Arc<Port>,
+    /// Open as a tty, rather than a raw port.
+    tty: bool,
+}
+
+impl OpenableDevice for PortNode {
+    fn open(&self, flags: OpenFlags) -> Result<Arc<OpenFile>> {
+        if self.tty {
+            let tty = Tty::new(self.port.clone())?;
+
+            return Ok(Arc::new(OpenFile::new(Box::new(tty), flags)));
+        }
+
+        // Like Linux, allow only one raw reader per port.
+        if self.port.guest_open.swap(true, Ordering::Relaxed) {
+            return Err(KernelError::InUse);
+        }
+
+        if let Some(dev) = self.port.dev.upgrade() {
+            let _ = dev.send_control(self.port.id, PORT_OPEN, 1);
+        }
+
+        Ok(Arc::new(OpenFile::new(
+            Box::new(PortFile {
+                port: self.port.clone(),
+            }),
+            flags,
+        )))
+    }
+}
+
+/// Serves either the `hvc` or the `vport` devices.
+struct PortCharDev {
+    ports: &'static SpinLock<BTreeMap<u64, Arc<Port>>>,
+    tty: bool,
+}
+
+impl CharDriver for PortCharDev {
+    fn get_device(&self, minor: u64) -> Option<Arc<dyn OpenableDevice>> {
+        let port = self.ports.lock_save_irq().get(&minor)?.clone();
+
+        Some(Arc::new(PortNode {
+            port,
+            tty: self.tty,
+        }))
+    }
+}
+
+static HVC_PORTS: SpinLock<BTreeMap<u64, Arc<Port>>> = SpinLock::new(BTreeMap::new());
+static HVC_NEXT: AtomicU64 = AtomicU64::new(0);
+static VPORTS: SpinLock<BTreeMap<u64, Arc<Port>>> = SpinLock::new(BTreeMap::new());
+static VPORT_NEXT: AtomicU64 = AtomicU64::new(0);
+static VPORT_MAJOR: OnceLock<u64> = OnceLock::new();
+static NEXT_INDEX: AtomicUsize = AtomicUsize::new(0);
+
+/// Forgets a port the host has removed, taking away its nodes in /dev.
+fn remove_port_nodes(port: &Arc<Port>) {
+    let mut nodes = Vec::new();
+
+    if let Some(&major) = VPORT_MAJOR.get() {
+        VPORTS.lock_save_irq().retain(|&minor, p| {
+            let keep = !Arc::ptr_eq(p, port);
+            if !keep {
+                nodes.push(CharDevDescriptor { major, minor });
+            }
+            keep
+        });
+    }
+
+    HVC_PORTS.lock_save_irq().retain(|&minor, p| {
+        let keep = !Arc::ptr_eq(p, port);
+        if !keep {
+            nodes.push(CharDevDescriptor {
+                major: ReservedMajors::Hvc as _,
+                minor,
+            });
+        }
+        keep
+    });
+
+    devfs().remove_nodes(
+        |file_type| matches!(file_type, FileType::CharDevice(desc) if nodes.contains(&desc)),
+    );
+}
+
+fn setup_inner(mut transport: SomeTransport<'static>) -> Result<(Inner, bool)> {
+    let features = transport.begin_init(Features::MULTIPORT | Features::VERSION_1);
+    let multiport = features.contains(Features::MULTIPORT);
+
+    let nr_ports = if multiport {
+        // `max_nr_ports` follows the `cols` and `rows` fields.
+        let max: u32 = transport.read_config_space(4).map_err(virtio_err)?;
+        max.min(MAX_PORTS)
+    } else {
+        1
+    };
+
+    let mut queues = BTreeMap::new();
+    let mut control = None;
+
+    for port in 0..nr_ports {
+        let (rx, tx) = port_queues(port);
+
+        queues.insert(
+            port,
+            PortQueues {
+                rx: RxQueue::new(&mut transport, rx)?,
+                tx: VirtQueue::new(&mut transport, tx, false, false).map_err(virtio_err)?,
+            },
+        );
+
+        if port == 0 && multiport {
+            control = Some((
+                RxQueue::new(&mut transport, CONTROL_RECEIVEQ)?,
+                VirtQueue::new(&mut transport, CONTROL_TRANSMITQ, false, false)
+                    .map_err(virtio_err)?,
+            ));
+        }
+    }
+
+    transport.finish_init();
+
+    let mut inner = Inner {
+        transport,
+        control,
+        queues,
+    };
+
+    let Inner {
+        transport,
+        control,
+        queues,
+    } = &mut inner;
+
+    if let Some((rx, _)) = control {
+        rx.fill(transport)?;
+    }
+
+    for port in queues.values_mut() {
+        port.rx.fill(transport)?;
+    }
+
+    Ok((inner, multiport))
+}
+
+fn virtio_console_probe(dm: &mut DriverManager, d: DeviceDescriptor) -> Result<Arc<dyn Driver>> {
+    match d {
+        DeviceDescriptor::Fdt(fdt_node, flags) => {
+            let region = fdt_node
+                .reg()
+                .ok_or(ProbeError::NoReg)?
+                .next()
+                .ok_or(ProbeError::NoReg)?;
+
+            let size = region.size.ok_or(ProbeError::NoRegSize)?;
+
+            let mapped: VA =
+                ArchImpl::kern_address_space()
+                    .lock_save_irq()
+                    .map_mmio(PhysMemoryRegion::new(
+                        PA::from_value(region.address as usize),
+                        size,
+                    ))?;
+
+            let header = NonNull::new(mapped.value() as *mut VirtIOHeader)
+                .ok_or(KernelError::InvalidValue)?;
+
+            let transport = unsafe {
+                match MmioTransport::new(header, size) {
+                    Ok(t) => t,
+                    Err(_) => return Err(KernelError::Probe(ProbeError::NoMatch)),
+                }
+            };
+
+            if !matches!(transport.device_type(), DeviceType::Console) {
+                return Err(KernelError::Probe(ProbeError::NoMatch));
+            }
+
+            attach_fdt_node(&fdt_node)?;
+
+            let mut interrupts = fdt_node
+                .interrupts()
+                .ok_or(ProbeError::NoInterrupts)?
+                .next()
+                .ok_or(ProbeError::NoInterrupts)?;
+
+            let interrupt_node = fdt_node
+                .interrupt_parent()
+                .ok_or(ProbeError::NoParentInterrupt)?
+                .node;
+
+            let interrupt_manager = dm
+                .find_by_name(interrupt_node.name)
+                .ok_or(ProbeError::Deferred)?
+                .as_interrupt_manager()
+                .ok_or(ProbeError::NotInterruptController)?;
+
+            let interrupt_config = interrupt_manager.parse_fdt_interrupt_regs(&mut interrupts)?;
+
+            bring_up(
+                fdt_node.name,
+                flags.contains(FdtFlags::ACTIVE_CONSOLE),
+                SomeTransport::Mmio(transport),
+                interrupt_manager,
+                interrupt_config,
+            )
+        }
+    }
+}
+
+fn virtio_console_pci_probe(
+    _dm: &mut DriverManager,
+    pci: Arc<PciDevice>,
+) -> Result<Arc<dyn Driver>> {
+    let transport = pci_transport(&pci)?;
+
+    // The transport leaves the MSI-X vectors unprogrammed, so the device
+    // signals over INTx.
+    let interrupt_config = pci.alloc_irq_vectors(1, IrqTypes::INTX)?[0];
+    let interrupt_manager = get_interrupt_root().ok_or(ProbeError::Deferred)?;
+
+    pci.enable_bus_master();
+
+    bring_up(
+        "virtio-pci-console",
+        false,
+        SomeTransport::Pci(transport),
+        interrupt_manager,
+        interrupt_config,
+    )
+}
+
+fn bring_up(
+    name: &'static str,
+    active_console: bool,
+    transport: SomeTransport<'static>,
+    interrupt_manager: Arc<InterruptManager>,
+    interrupt_config: InterruptConfig,
+) -> Result<Arc<dyn Driver>> {
+    let (inner, multiport) = setup_inner(transport)?;
+
+    info!(
+        "virtio-console found ({name}, {} ports)",
+        inner.queues.len()
+    );
+
+    let dev = Arc::new(VirtioConsole {
+        name,
+        index: NEXT_INDEX.fetch_add(1, Ordering::Relaxed),
+        active_console,
+        inner: SpinLock::new(inner),
+        ports: SpinLock::new(BTreeMap::new()),
+    });
+
+    let driver =
+        interrupt_manager.claim_interrupt(interrupt_config, |claimed| VirtioConsoleDriver {
+            dev: dev.clone(),
+            _interrupt: claimed,
+        })?;
+
+    if multiport {
+        // The host replies with the ports it has.
+        dev.send_control(0, DEVICE_READY, 1)?;
+    } else {
+        let port = dev.add_port(0)?;
+        dev.make_console(&port)?;
+    }
+
+    Ok(driver)
+}
+
+pub fn virtio_console_init(bus: &mut PlatformBus, dm: &mut DriverManager) -> Result<()> {
+    dm.register_char_driver(
+        ReservedMajors::Hvc as _,
+        Arc::new(PortCharDev {
+            ports: &HVC_PORTS,
+            tty: true,
+        }),
+    )?;
+
+    let vport_major = dm.allocate_major();
+
+    dm.register_char_driver(
+        vport_major,
+        Arc::new(PortCharDev {
+            ports: &VPORTS,
+            tty: false,
+        }),
+    )?;
+
+    VPORT_MAJOR
+        .set(vport_major)
+        .map_err(|_| KernelError::InUse)?;
+
+    bus.register_platform_driver(
+        DeviceMatchType::FdtCompatible("virtio,mmio"),
+        Box::new(virtio_console_probe),
+    );
+
+    bus.register_platform_driver(
+        DeviceMatchType::FdtCompatible("virtio-mmio"),
+        Box::new(virtio_console_probe),
+    );
+
+    let mut pci = PCI_BUS.lock_save_irq();
+
+    // Transitional and modern device IDs.
+    for device in [0x1003, 0x1043] {
+        pci.register_pci_driver(
+            PciMatchType::Id {
+                vendor: VIRTIO_PCI_VENDOR,
+                device,
+            },
+            Box::new(virtio_console_pci_probe),
+        );
+    }
+
+    Ok(())
+}
+
+kernel_driver!(virtio_console_init);
@@ -1,89 +1,159 @@
-use crate::arch::{Arch, ArchImpl};
-use crate::memory::PageOffsetTranslator;
+use crate::arch::ArchImpl;
+use crate::drivers::iommu::dma_device_for_fdt_node;
+use crate::drivers::pci::{PciDevice, config::ConfigMechanism};
+use crate::memory::dma::{DmaCoherent, DmaDevice, DmaDirection};
+use crate::sync::SpinLock;
 use core::ptr::NonNull;
+use libkernel::error::{KernelError, ProbeError, Result};
 use libkernel::memory::PAGE_SIZE;
-use libkernel::memory::address::{PA, TPA};
-use libkernel::memory::region::PhysMemoryRegion;
+use libkernel::memory::address::{PA, VA};
+use libkernel::memory::proc_vm::address_space::{KernAddressSpace, VirtualMemory};
+use libkernel::memory::region::{PhysMemoryRegion, VirtMemoryRegion};
 use log::trace;
-use virtio_drivers::{BufferDirection, Hal, PhysAddr};
+use virtio_drivers::{
+    BufferDirection, Hal, PhysAddr,
+    transport::pci::{
+        PciTransport,
+        bus::{Cam, DeviceFunction, MmioCam, PciRoot},
+    },
+};
+
+/// The PCI vendor ID of virtio devices.
+pub(super) const VIRTIO_PCI_VENDOR: u16 = 0x1af4;
 
 pub(super) struct VirtioHal;
 
-impl VirtioHal {
-    #[inline]
-    fn pages_to_order(pages: usize) -> u8 {
-        let pages = pages.max(1);
-        let rounded = pages.next_power_of_two();
-        rounded.ilog2() as u8
+/// The device through which all virtio DMA is performed.
+///
+/// [`Hal`] isn't told which device it's acting for, so every virtio device
+/// must share one translation: the first device to be attached decides it.
+/// Until then, DMA is identity mapped.
+static VIRTIO_DMA: SpinLock<Option<DmaDevice>> = SpinLock::new(None);
+
+fn dma_device() -> DmaDevice {
+    VIRTIO_DMA
+        .lock_save_irq()
+        .clone()
+        .unwrap_or(DmaDevice::identity())
+}
+
+/// Routes the virtio device's DMA through `dev`.
+///
+/// Fails with [`KernelError::NotSupported`] if `dev` doesn't share the
+/// translation of the virtio devices attached before it.
+fn attach(dev: DmaDevice) -> Result<()> {
+    let mut shared = VIRTIO_DMA.lock_save_irq();
+
+    match &*shared {
+        None => {
+            *shared = Some(dev);
+            Ok(())
+        }
+        Some(shared) if shared.same_translation(&dev) => Ok(()),
+        Some(_) => Err(KernelError::NotSupported),
     }
 }
 
-unsafe impl Hal for VirtioHal {
-    fn dma_alloc(pages: usize, _direction: BufferDirection) -> (PhysAddr, NonNull<u8>) {
-        let order = Self::pages_to_order(pages);
+/// Attaches the virtio-mmio device described by `node`, through its IOMMU
+/// domain if it has one. Must be called before its transport is created.
+pub(super) fn attach_fdt_node(node: &fdt_parser::Node<'static>) -> Result<()> {
+    attach(dma_device_for_fdt_node(node)?)
+}
 
-        let region = crate::memory::PAGE_ALLOC
-            .get()
-            .expect("PAGE_ALLOC not initialized")
-            .alloc_frames(order)
-            .expect("virtio dma_alloc: out of memory")
-            .leak();
+impl From<BufferDirection> for DmaDirection {
+    fn from(dir: BufferDirection) -> Self {
+        match dir {
+            BufferDirection::DriverToDevice => DmaDirection::ToDevice,
+            BufferDirection::DeviceToDriver => DmaDirection::FromDevice,
+            BufferDirection::Both => DmaDirection::Bidirectional,
+        }
+    }
+}
 
-        let region_start = region.start_address();
-        let paddr = region_start.value() as PhysAddr;
+fn buffer_region(buffer: NonNull<[u8]>) -> VirtMemoryRegion {
+    VirtMemoryRegion::new(
+        VA::from_value(buffer.as_ptr() as *mut u8 as usize),
+        buffer.len(),
+    )
+}
 
-        // Convert PA->VA using the kernel's direct mapping window.
-        let vaddr = region_start.to_va::<PageOffsetTranslator>().as_ptr_mut() as *mut u8;
-        let vaddr = NonNull::new(vaddr).expect("virtio dma_alloc: null vaddr");
+unsafe impl Hal for VirtioHal {
+    fn dma_alloc(pages: usize, _direction: BufferDirection) -> (PhysAddr, NonNull<u8>) {
+        let buf = dma_device()
+            .alloc_coherent(pages * PAGE_SIZE)
+            .expect("virtio dma_alloc: out of memory");
 
-        unsafe {
-            core::ptr::write_bytes(vaddr.as_ptr(), 0, pages * PAGE_SIZE);
-        }
+        let vaddr =
+            NonNull::new(buf.va().as_ptr_mut().cast()).expect("virtio dma_alloc: null vaddr");
+        let paddr = buf.into_raw() as PhysAddr;
 
-        trace!("alloc DMA: paddr={paddr:#x}, pages={pages}, order={order}");
+        trace!("alloc DMA: paddr={paddr:#x}, pages={pages}");
         (paddr, vaddr)
     }
 
-    unsafe fn dma_dealloc(paddr: PhysAddr, _vaddr: NonNull<u8>, pages: usize) -> i32 {
+    unsafe fn dma_dealloc(paddr: PhysAddr, vaddr: NonNull<u8>, pages: usize) -> i32 {
         trace!("dealloc DMA: paddr={paddr:#x}, pages={pages}");
 
-        let order = Self::pages_to_order(pages);
-        let region = PhysMemoryRegion::new(
-            PA::from_value(paddr as usize),
-            (1usize << order) * PAGE_SIZE,
-        );
-
-        // SAFETY: `dma_alloc` leaked an allocation for exactly this region.
-        let alloc = unsafe {
-            crate::memory::PAGE_ALLOC
-                .get()
-                .expect("PAGE_ALLOC not initialized")
-                .alloc_from_region(region)
-        };
-
-        drop(alloc);
+        // SAFETY: `dma_alloc` released a buffer of exactly this size, through
+        // the shared device, which never changes once set.
+        drop(unsafe {
+            DmaCoherent::from_raw(
+                &dma_device(),
+                paddr as usize,
+                VA::from_value(vaddr.as_ptr() as usize),
+                pages * PAGE_SIZE,
+            )
+        });
         0
     }
 
-    unsafe fn mmio_phys_to_virt(paddr: PhysAddr, _size: usize) -> NonNull<u8> {
-        let vaddr = TPA::from_value(paddr as usize)
-            .to_va::<PageOffsetTranslator>()
-            .as_ptr_mut();
-        NonNull::new(vaddr).unwrap()
+    unsafe fn mmio_phys_to_virt(paddr: PhysAddr, size: usize) -> NonNull<u8> {
+        // Only the PCI transport asks for this, for regions within BARs,
+        // which aren't in the linear map.
+        let vaddr = ArchImpl::kern_address_space()
+            .lock_save_irq()
+            .map_mmio(PhysMemoryRegion::new(PA::from_value(paddr as usize), size))
+            .expect("virtio mmio_phys_to_virt: cannot map BAR");
+
+        NonNull::new(vaddr.as_ptr_mut().cast()).unwrap()
     }
 
-    unsafe fn share(buffer: NonNull<[u8]>, _direction: BufferDirection) -> PhysAddr {
-        // We're assuming that all ram is DMA-coherent in QEMU.
-        // We don't need to adjust page table mapping properties to disable caching and the like to make this work.
-        let vaddr = buffer.as_ptr() as *mut u8 as usize;
+    unsafe fn share(buffer: NonNull<[u8]>, direction: BufferDirection) -> PhysAddr {
+        // SAFETY: virtio-drivers doesn't touch the buffer until it is unshared.
+        let addr = unsafe { dma_device().map_single(buffer_region(buffer), direction.into()) };
 
-        // Buffer must be in the direct map for this fast translation.
-        if vaddr < ArchImpl::PAGE_OFFSET {
-            panic!("virtio share: buffer VA is not in direct map: {vaddr:#x}");
-        }
+        addr.unwrap_or_else(|e| panic!("virtio share: cannot map buffer {buffer:p}: {e:?}"))
+            as PhysAddr
+    }
 
-        (vaddr - ArchImpl::PAGE_OFFSET) as PhysAddr
+    unsafe fn unshare(paddr: PhysAddr, buffer: NonNull<[u8]>, direction: BufferDirection) {
+        dma_device().unmap_single(paddr as usize, buffer_region(buffer), direction.into());
     }
+}
+
+/// Sets up the virtio transport for a PCI device, through its host bridge's
+/// configuration space.
+pub(super) fn pci_transport(pci: &PciDevice) -> Result<PciTransport> {
+    // PCI devices are never attached to an IOMMU, so their streams bypass it.
+    attach(DmaDevice::identity())?;
+
+    let host = pci.host();
+
+    let cam = match host.mechanism() {
+        ConfigMechanism::Ecam => Cam::Ecam,
+        ConfigMechanism::Cam => Cam::MmioCam,
+    };
+
+    // SAFETY: The host's configuration space stays mapped for good.
+    let mut root =
+        PciRoot::new(unsafe { MmioCam::new(host.config_base().as_ptr_mut().cast(), cam) });
+
+    let function = DeviceFunction {
+        bus: pci.addr.bus,
+        device: pci.addr.device,
+        function: pci.addr.function,
+    };
 
-    unsafe fn unshare(_paddr: PhysAddr, _buffer: NonNull<[u8]>, _direction: BufferDirection) {}
+    PciTransport::new::<VirtioHal, _>(&mut root, function)
+        .map_err(|_| KernelError::Probe(ProbeError::NoMatch))
 }
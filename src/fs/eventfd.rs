@@ -0,0 +1,237 @@
+use crate::{
+    memory::uaccess::{copy_from_user, copy_to_user},
+    process::{
+        fd_table::FdFlags,
+        thread_group::signal::{InterruptResult, Interruptable},
+    },
+    sched::syscall_ctx::ProcessCtx,
+    sync::CondVar,
+};
+use alloc::{boxed::Box, sync::Arc};
+use async_trait::async_trait;
+use bitflags::bitflags;
+use core::{mem::size_of, pin::Pin};
+use libkernel::{
+    error::{KernelError, Result},
+    fs::OpenFlags,
+    memory::address::UA,
+    sync::condvar::WakeupType,
+};
+
+use super::{
+    fops::FileOps,
+    open_file::{FileCtx, OpenFile},
+};
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct EventFdFlags: u32 {
+        const EFD_SEMAPHORE = 1;
+        const EFD_NONBLOCK = OpenFlags::O_NONBLOCK.bits();
+        const EFD_CLOEXEC = OpenFlags::O_CLOEXEC.bits();
+    }
+}
+
+/// The largest value the counter can hold.
+const COUNT_MAX: u64 = u64::MAX - 1;
+
+/// A counter which reads wait on until it's non-zero, and writes add to,
+/// waiting for there to be room if they'd overflow it.
+pub struct EventFd {
+    count: CondVar<u64>,
+    /// Whether a read takes one from the counter, rather than all of it.
+    semaphore: bool,
+}
+
+impl EventFd {
+    pub fn new(initval: u64, semaphore: bool) -> Self {
+        Self {
+            count: CondVar::new(initval),
+            semaphore,
+        }
+    }
+
+    /// Takes from the counter, if it's non-zero, returning what was taken.
+    fn take(count: &mut u64, semaphore: bool) -> Option<u64> {
+        if *count == 0 {
+            None
+        } else if semaphore {
+            *count -= 1;
+            Some(1)
+        } else {
+            Some(core::mem::take(count))
+        }
+    }
+
+    /// Adds `value` to the counter, if there's room.
+    fn add(count: &mut u64, value: u64) -> Option<()> {
+        if COUNT_MAX - *count < value {
+            return None;
+        }
+
+        *count += value;
+        Some(())
+    }
+
+    async fn read_value(&self, nonblock: bool) -> Result<u64> {
+        let semaphore = self.semaphore;
+        let mut value = None;
+
+        // Try without waiting first, so that nonblocking reads never wait.
+        self.count.update(|count| {
+            value = Self::take(count, semaphore);
+            if value.is_some() {
+                WakeupType::All
+            } else {
+                WakeupType::None
+            }
+        });
+
+        if let Some(value) = value {
+            return Ok(value);
+        }
+
+        if nonblock {
+            return Err(KernelError::TryAgain);
+        }
+
+        match self
+            .count
+            .wait_until(move |count| Self::take(count, semaphore))
+            .interruptable()
+            .await
+        {
+            InterruptResult::Interrupted => Err(KernelError::Interrupted),
+            InterruptResult::Uninterrupted(value) => {
+                // Let any writers waiting for room know there's more now.
+                self.count.update(|_| WakeupType::All);
+                Ok(value)
+            }
+        }
+    }
+
+    async fn write_value(&self, value: u64, nonblock: bool) -> Result<()> {
+        let mut added = None;
+
+        self.count.update(|count| {
+            added = Self::add(count, value);
+            if added.is_some() {
+                WakeupType::All
+            } else {
+                WakeupType::None
+            }
+        });
+
+        if added.is_some() {
+            return Ok(());
+        }
+
+        if nonblock {
+            return Err(KernelError::TryAgain);
+        }
+
+        match self
+            .count
+            .wait_until(move |count| Self::add(count, value))
+            .interruptable()
+            .await
+        {
+            InterruptResult::Interrupted => Err(KernelError::Interrupted),
+            InterruptResult::Uninterrupted(()) => {
+                self.count.update(|_| WakeupType::All);
+                Ok(())
+            }
+        }
+    }
+
+    async fn do_read(&self, buf: UA, count: usize, nonblock: bool) -> Result<usize> {
+        if count < size_of::<u64>() {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let value = self.read_value(nonblock).await?;
+        copy_to_user(buf.cast(), value).await?;
+
+        Ok(size_of::<u64>())
+    }
+
+    async fn do_write(&self, buf: UA, count: usize, nonblock: bool) -> Result<usize> {
+        if count < size_of::<u64>() {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let value: u64 = copy_from_user(buf.cast()).await?;
+        if value == u64::MAX {
+            return Err(KernelError::InvalidValue);
+        }
+
+        self.write_value(value, nonblock).await?;
+
+        Ok(size_of::<u64>())
+    }
+}
+
+#[async_trait]
+impl FileOps for EventFd {
+    async fn read(&mut self, ctx: &mut FileCtx, buf: UA, count: usize) -> Result<usize> {
+        self.do_read(buf, count, ctx.flags.contains(OpenFlags::O_NONBLOCK))
+            .await
+    }
+
+    async fn readat(&mut self, buf: UA, count: usize, _offset: u64) -> Result<usize> {
+        self.do_read(buf, count, false).await
+    }
+
+    async fn write(&mut self, ctx: &mut FileCtx, buf: UA, count: usize) -> Result<usize> {
+        self.do_write(buf, count, ctx.flags.contains(OpenFlags::O_NONBLOCK))
+            .await
+    }
+
+    async fn writeat(&mut self, buf: UA, count: usize, _offset: u64) -> Result<usize> {
+        self.do_write(buf, count, false).await
+    }
+
+    fn poll_read_ready(&self) -> Pin<Box<dyn Future<Output = Result<()>> + 'static + Send>> {
+        let count = self.count.clone();
+        Box::pin(async move {
+            count.wait_until(|count| (*count > 0).then_some(())).await;
+            Ok(())
+        })
+    }
+
+    fn poll_write_ready(&self) -> Pin<Box<dyn Future<Output = Result<()>> + 'static + Send>> {
+        let count = self.count.clone();
+        Box::pin(async move {
+            count
+                .wait_until(|count| (*count < COUNT_MAX).then_some(()))
+                .await;
+            Ok(())
+        })
+    }
+}
+
+pub async fn sys_eventfd2(ctx: &ProcessCtx, initval: u32, flags: u32) -> Result<usize> {
+    let flags = EventFdFlags::from_bits(flags).ok_or(KernelError::InvalidValue)?;
+
+    let eventfd = EventFd::new(initval as u64, flags.contains(EventFdFlags::EFD_SEMAPHORE));
+
+    let file_flags = if flags.contains(EventFdFlags::EFD_NONBLOCK) {
+        OpenFlags::O_RDWR | OpenFlags::O_NONBLOCK
+    } else {
+        OpenFlags::O_RDWR
+    };
+    let fd_flags = if flags.contains(EventFdFlags::EFD_CLOEXEC) {
+        FdFlags::CLOEXEC
+    } else {
+        FdFlags::empty()
+    };
+
+    let file = Arc::new(OpenFile::new(Box::new(eventfd), file_flags));
+    let fd = ctx
+        .shared()
+        .fd_table
+        .lock_save_irq()
+        .insert_with_flags(file, fd_flags)?;
+
+    Ok(fd.as_raw() as _)
+}
@@ -1,4 +1,4 @@
-use core::pin::Pin;
+use core::{future, pin::Pin};
 
 use alloc::boxed::Box;
 use async_trait::async_trait;
@@ -8,9 +8,9 @@ use libkernel::{
     memory::address::UA,
 };
 
-use crate::kernel::kpipe::KPipe;
+use crate::{kernel::kpipe::KPipe, memory::uaccess::iovec::IoVec};
 
-use super::{dir::OpenFileDirIter, open_file::FileCtx, syscalls::iov::IoVec};
+use super::{dir::OpenFileDirIter, open_file::FileCtx};
 
 macro_rules! process_iovec {
     ($iovecs:expr, |$addr:ident, $count:ident| $call:expr) => {
@@ -92,6 +92,13 @@ pub trait FileOps: Send + Sync {
         Box::pin(async { Err(KernelError::NotSupported) })
     }
 
+    /// Puts the current task to sleep until the other end of the file has hung
+    /// up, e.g. when the last writer of a pipe closes it. Files which can't
+    /// hang up never complete.
+    fn poll_hangup(&self) -> Pin<Box<dyn Future<Output = Result<()>> + 'static + Send>> {
+        Box::pin(future::pending())
+    }
+
     /// Moves the file's cursor to a new position.
     /// Returns the new position from the start of the file.
     async fn seek(&mut self, _ctx: &mut FileCtx, _pos: SeekFrom) -> Result<u64> {
@@ -151,4 +158,8 @@ pub trait FileOps: Send + Sync {
     ) -> Option<&mut crate::process::thread_group::signal::signalfd::SignalFd> {
         None
     }
+
+    fn as_timerfd(&mut self) -> Option<&mut crate::clock::syscalls::timerfd::TimerFd> {
+        None
+    }
 }
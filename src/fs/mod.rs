@@ -1,8 +1,10 @@
 use crate::clock::realtime::date;
 use crate::{
-    drivers::{DM, Driver},
+    arch::ArchImpl,
+    drivers::{DM, Driver, blkdev},
+    kernel::trace::TracedBlockDevice,
     process::Task,
-    sync::SpinLock,
+    sync::{OnceCell, OnceLock, RCU, SpinLock},
 };
 use alloc::{borrow::ToOwned, boxed::Box, collections::btree_map::BTreeMap, sync::Arc, vec::Vec};
 use async_trait::async_trait;
@@ -13,14 +15,16 @@ use libkernel::{
     error::{FsError, KernelError, Result},
     fs::{
         BlockDevice, FS_ID_START, FileType, Filesystem, Inode, InodeId, OpenFlags,
-        attr::FilePermissions, path::Path,
+        attr::FilePermissions, filesystems::overlayfs::OverlayFs, path::Path,
     },
     proc::caps::CapabilitiesFlags,
+    sync::rcu::RcuCell,
 };
 use open_file::OpenFile;
 use reg::RegFile;
 
 pub mod dir;
+pub mod eventfd;
 pub mod fops;
 pub mod open_file;
 pub mod pipe;
@@ -43,6 +47,7 @@ impl Inode for DummyInode {
 }
 
 /// Represents a mounted filesystem.
+#[derive(Clone)]
 struct Mount {
     fs: Arc<dyn Filesystem>,
     root_inode: Arc<dyn Inode>,
@@ -63,6 +68,10 @@ pub trait FilesystemDriver: Driver + Send + Sync {
 ///
 /// This struct consolidates the filesystem-wide collections (the list of all
 /// registered filesystem instances and the mapping of mount points).
+///
+/// It's consulted for every component of every path lookup, but only changes
+/// on mount and unmount, so it's read under RCU and copied to be updated.
+#[derive(Clone)]
 struct VfsState {
     /// A map from an InodeId of a directory to the Mount that is mounted there.
     mounts: BTreeMap<InodeId, Mount>,
@@ -108,19 +117,44 @@ impl VfsState {
 #[allow(clippy::upper_case_acronyms)]
 pub struct VFS {
     next_fs_id: AtomicU64,
-    state: SpinLock<VfsState>,
-    root_inode: SpinLock<Option<Arc<dyn Inode>>>,
+    state: OnceLock<RcuCell<VfsState>>,
+    /// Serialises updates to `state`.
+    state_update: SpinLock<()>,
+    root_inode: OnceCell<Arc<dyn Inode>>,
 }
 
 impl VFS {
     const fn new() -> Self {
         Self {
             next_fs_id: AtomicU64::new(FS_ID_START),
-            state: SpinLock::new(VfsState::new()),
-            root_inode: SpinLock::new(None),
+            state: OnceLock::new(),
+            state_update: SpinLock::new(()),
+            root_inode: OnceCell::new(),
         }
     }
 
+    fn state(&self) -> &RcuCell<VfsState> {
+        self.state.get_or_init(|| RcuCell::new(VfsState::new()))
+    }
+
+    /// Runs `f` on the current state, without taking any lock.
+    fn read_state<R>(&self, f: impl FnOnce(&VfsState) -> R) -> R {
+        let guard = RCU.read_lock();
+
+        f(self.state().read(&guard))
+    }
+
+    /// Runs `f` on a copy of the current state, then publishes the copy.
+    fn update_state<R>(&self, f: impl FnOnce(&mut VfsState) -> R) -> R {
+        let _update = self.state_update.lock_save_irq();
+        let mut state = self.read_state(VfsState::clone);
+        let ret = f(&mut state);
+
+        self.state().replace(state, &RCU);
+
+        ret
+    }
+
     /// Creates an instance of a filesystem from a registered driver.
     ///
     /// This does not mount the filesystem, but prepares an instance that can
@@ -138,31 +172,46 @@ impl VFS {
             .ok_or(FsError::DriverNotFound)?;
 
         let id = self.next_fs_id.fetch_add(1, Ordering::SeqCst);
+        let blkdev =
+            blkdev.map(|dev| Box::new(TracedBlockDevice::new(dev)) as Box<dyn BlockDevice>);
 
         driver.construct(id, blkdev).await
     }
 
     /// Mounts the root filesystem.
+    ///
+    /// Fails with [`FsError::Busy`] if a root filesystem is already
+    /// mounted.
     pub async fn mount_root(
         &self,
         driver_name: &str,
         blkdev: Option<Box<dyn BlockDevice>>,
     ) -> Result<()> {
-        let fs = self.create_fs_instance(driver_name, blkdev).await?;
-        let root_inode = fs.root_inode().await?;
+        let mut mounted = false;
 
-        let mount = Mount {
-            fs,
-            root_inode: root_inode.clone(),
-        };
+        self.root_inode
+            .get_or_try_init(|| async {
+                let fs = self.create_fs_instance(driver_name, blkdev).await?;
+                let root_inode = fs.root_inode().await?;
 
-        // Lock the state to add the new mount and filesystem.
-        self.state.lock_save_irq().add_mount(root_inode.id(), mount);
+                let mount = Mount {
+                    fs,
+                    root_inode: root_inode.clone(),
+                };
 
-        // Set the global root inode.
-        *self.root_inode.lock_save_irq() = Some(root_inode);
+                // Lock the state to add the new mount and filesystem.
+                self.update_state(|state| state.add_mount(root_inode.id(), mount));
+                mounted = true;
 
-        Ok(())
+                Ok::<_, KernelError>(root_inode)
+            })
+            .await?;
+
+        if mounted {
+            Ok(())
+        } else {
+            Err(FsError::Busy.into())
+        }
     }
 
     /// Mounts a filesystem at a given directory (mount point).
@@ -182,10 +231,31 @@ impl VFS {
 
         let new_mount = Mount { fs, root_inode };
 
-        // Lock the state and insert the new mount.
-        self.state
-            .lock_save_irq()
-            .add_mount(mount_point_id, new_mount);
+        self.update_state(|state| state.add_mount(mount_point_id, new_mount));
+
+        Ok(())
+    }
+
+    /// Mounts an overlay of the directory `upper` on the directory `lower` at
+    /// `mount_point`.
+    pub async fn mount_overlay(
+        &self,
+        mount_point: Arc<dyn Inode>,
+        lower: Arc<dyn Inode>,
+        upper: Arc<dyn Inode>,
+    ) -> Result<()> {
+        for dir in [&mount_point, &lower, &upper] {
+            if dir.getattr().await?.file_type != FileType::Directory {
+                return Err(FsError::NotADirectory.into());
+            }
+        }
+
+        let upper_fs = self.get_fs(upper.clone()).await?;
+        let id = self.next_fs_id.fetch_add(1, Ordering::SeqCst);
+        let fs = OverlayFs::<ArchImpl>::new(id, lower, upper, upper_fs);
+        let root_inode = fs.root_inode().await?;
+
+        self.update_state(|state| state.add_mount(mount_point.id(), Mount { fs, root_inode }));
 
         Ok(())
     }
@@ -194,19 +264,14 @@ impl VFS {
     pub async fn unmount(&self, mount_point: Arc<dyn Inode>) -> Result<()> {
         let mount_point_id = mount_point.id();
 
-        // Lock the state and remove the mount.
-        self.state
-            .lock_save_irq()
-            .remove_mount(&mount_point_id)
+        self.update_state(|state| state.remove_mount(&mount_point_id))
             .ok_or(FsError::NotFound)?;
 
         Ok(())
     }
 
     pub async fn get_fs(&self, inode: Arc<dyn Inode>) -> Result<Arc<dyn Filesystem>> {
-        self.state
-            .lock_save_irq()
-            .get_fs(inode.id())
+        self.read_state(|state| state.get_fs(inode.id()))
             .ok_or(KernelError::from(FsError::NoDevice))
     }
 
@@ -218,13 +283,15 @@ impl VFS {
         root: Arc<dyn Inode>,
         task: &Arc<Task>,
     ) -> Result<Arc<dyn Inode>> {
+        // Use the task's root inode, in case a custom chroot was set.
+        let fs_root = task.root.lock_save_irq().0.clone();
         let root = if path.is_absolute() {
-            task.root.lock_save_irq().0.clone() // use the task's root inode, in case a custom chroot was set
+            fs_root.clone()
         } else {
             root
         };
 
-        self.resolve_path_internal(path, root, true).await
+        self.resolve_path_internal(path, root, fs_root, true).await
     }
 
     /// Resolves a path string to an Inode, starting from a given root for
@@ -235,13 +302,14 @@ impl VFS {
         root: Arc<dyn Inode>,
         task: &Arc<Task>,
     ) -> Result<Arc<dyn Inode>> {
+        let fs_root = task.root.lock_save_irq().0.clone();
         let root = if path.is_absolute() {
-            task.root.lock_save_irq().0.clone()
+            fs_root.clone()
         } else {
             root
         };
 
-        self.resolve_path_internal(path, root, false).await
+        self.resolve_path_internal(path, root, fs_root, false).await
     }
 
     /// Resolves a path string to an Inode, starting from a given root for
@@ -251,23 +319,24 @@ impl VFS {
         path: &Path,
         root: Arc<dyn Inode>,
     ) -> Result<Arc<dyn Inode>> {
+        let fs_root = self.root_inode.get().cloned().ok_or(FsError::NotFound)?;
         let root = if path.is_absolute() {
-            self.root_inode
-                .lock_save_irq()
-                .as_ref()
-                .cloned()
-                .ok_or(FsError::NotFound)?
+            fs_root.clone()
         } else {
             root
         };
 
-        self.resolve_path_internal(path, root, true).await
+        self.resolve_path_internal(path, root, fs_root, true).await
     }
 
+    /// Walks `path` from `root`, following the symlinks along the way, and the
+    /// last one too if `follow_last_sym` is set. Absolute symlinks are followed
+    /// from `fs_root`, the root the path was looked up under.
     async fn resolve_path_internal(
         &self,
         path: &Path,
         root: Arc<dyn Inode>,
+        fs_root: Arc<dyn Inode>,
         follow_last_sym: bool,
     ) -> Result<Arc<dyn Inode>> {
         let mut current_inode = root;
@@ -279,10 +348,8 @@ impl VFS {
         while let Some(component) = components.pop() {
             // Before looking up the component, check if the current inode is a
             // mount point. If so, traverse into the mounted filesystem's root.
-            if let Some(mount_root) = self
-                .state
-                .lock_save_irq()
-                .get_mount_root(&current_inode.id())
+            if let Some(mount_root) =
+                self.read_state(|state| state.get_mount_root(&current_inode.id()))
             {
                 current_inode = mount_root;
             }
@@ -307,7 +374,7 @@ impl VFS {
 
                 if target.is_absolute() {
                     // if absolute, restart from root
-                    current_inode = self.root_inode.lock_save_irq().as_ref().unwrap().clone();
+                    current_inode = fs_root.clone();
                 }
 
                 continue;
@@ -318,10 +385,7 @@ impl VFS {
         }
 
         // After the final lookup, check if the destination is itself a mount point.
-        if let Some(mount_root) = self
-            .state
-            .lock_save_irq()
-            .get_mount_root(&current_inode.id())
+        if let Some(mount_root) = self.read_state(|state| state.get_mount_root(&current_inode.id()))
         {
             current_inode = mount_root;
         }
@@ -330,8 +394,14 @@ impl VFS {
     }
 
     /// Returns a clone of the root inode.
+    ///
+    /// # Panics
+    /// Panics if the root filesystem has not been mounted.
     pub fn root_inode(&self) -> Arc<dyn Inode> {
-        self.root_inode.lock_save_irq().as_ref().unwrap().clone()
+        self.root_inode
+            .get()
+            .expect("root filesystem not mounted")
+            .clone()
     }
 
     pub async fn open(
@@ -342,6 +412,10 @@ impl VFS {
         mode: FilePermissions,
         task: &Arc<Task>,
     ) -> Result<Arc<OpenFile>> {
+        if flags.intersects(OpenFlags::O_TMPFILE.difference(OpenFlags::O_DIRECTORY)) {
+            return self.open_tmpfile(path, flags, root, mode, task).await;
+        }
+
         // Attempt to resolve the full path first.
         let resolve_result = self.resolve_path(path, root.clone(), task).await;
 
@@ -427,7 +501,15 @@ impl VFS {
                 Ok(Arc::new(open_file))
             }
             FileType::Symlink => unimplemented!(), // this is implemented at resolve_path_internal
-            FileType::BlockDevice(_) => todo!(),
+            FileType::BlockDevice(block_dev_descriptor) => {
+                let mut open_file = blkdev::open(block_dev_descriptor, flags)?;
+
+                if let Some(of) = Arc::get_mut(&mut open_file) {
+                    of.update(target_inode, path.to_owned());
+                }
+
+                Ok(open_file)
+            }
             FileType::CharDevice(char_dev_descriptor) => {
                 let char_driver = DM
                     .lock_save_irq()
@@ -450,6 +532,40 @@ impl VFS {
         }
     }
 
+    /// Opens a new file with no name in the directory at `path`, for
+    /// `O_TMPFILE`. It goes away when it's closed, unless it's been linked
+    /// into a directory by then.
+    async fn open_tmpfile(
+        &self,
+        path: &Path,
+        flags: OpenFlags,
+        root: Arc<dyn Inode>,
+        mode: FilePermissions,
+        task: &Arc<Task>,
+    ) -> Result<Arc<OpenFile>> {
+        // The file can only be written through the fd, so it must be opened
+        // for writing, and O_CREAT makes no sense with it.
+        if !flags.contains(OpenFlags::O_TMPFILE)
+            || flags.contains(OpenFlags::O_CREAT)
+            || !flags.intersects(OpenFlags::O_WRONLY | OpenFlags::O_RDWR)
+        {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let dir = self.resolve_path(path, root, task).await?;
+
+        if dir.getattr().await?.file_type != FileType::Directory {
+            return Err(FsError::NotADirectory.into());
+        }
+
+        let inode = dir.create_tmpfile(mode, Some(date())).await?;
+
+        let mut open_file = OpenFile::new(Box::new(RegFile::new(inode.clone())), flags);
+        open_file.update(inode, path.to_owned());
+
+        Ok(Arc::new(open_file))
+    }
+
     pub async fn mkdir(
         &self,
         path: &Path,
@@ -557,7 +673,12 @@ impl VFS {
         new_parent: Arc<dyn Inode>,
         name: &str,
     ) -> Result<()> {
-        // just delegate to inode only, all handling is done at the syscall level
+        // Links can't cross from one filesystem to another.
+        if target.id().fs_id() != new_parent.id().fs_id() {
+            return Err(FsError::CrossDevice.into());
+        }
+
+        // The rest is handled at the syscall level.
         new_parent.link(name, target).await
     }
 
@@ -568,7 +689,13 @@ impl VFS {
         root: Arc<dyn Inode>,
         task: &Arc<Task>,
     ) -> Result<()> {
-        match self.resolve_path(link, root.clone(), task).await {
+        // As on Linux, a symlink can't point nowhere.
+        if target.as_str().is_empty() {
+            return Err(FsError::NotFound.into());
+        }
+
+        // A dangling symlink still takes up its name.
+        match self.resolve_path_nofollow(link, root.clone(), task).await {
             Ok(_) => Err(FsError::AlreadyExists.into()),
             Err(KernelError::Fs(FsError::NotFound)) => {
                 let name = link.file_name().ok_or(FsError::InvalidInput)?;
@@ -616,11 +743,12 @@ impl VFS {
     }
 
     pub fn is_mount_root(&self, id: InodeId) -> bool {
-        self.state
-            .lock_save_irq()
-            .mounts
-            .values()
-            .any(|mount| mount.root_inode.id() == id)
+        self.read_state(|state| {
+            state
+                .mounts
+                .values()
+                .any(|mount| mount.root_inode.id() == id)
+        })
     }
 }
 
@@ -631,10 +759,8 @@ impl VFS {
     /// Any individual error is logged and ignored so that a single faulty
     /// filesystem does not block the shutdown sequence.
     pub async fn sync_all(&self) -> Result<()> {
-        let filesystems: Vec<_> = {
-            let state = self.state.lock_save_irq();
-            state.filesystems.values().cloned().collect()
-        };
+        let filesystems: Vec<_> =
+            self.read_state(|state| state.filesystems.values().cloned().collect());
 
         for fs in filesystems {
             // Ignore per-filesystem errors; best-effort
@@ -647,9 +773,7 @@ impl VFS {
     /// Syncs the filesystem that contains the given inode.
     pub async fn sync(&self, inode: Arc<dyn Inode>) -> Result<()> {
         let fs = self
-            .state
-            .lock_save_irq()
-            .get_fs(inode.id())
+            .read_state(|state| state.get_fs(inode.id()))
             .ok_or(FsError::NoDevice)?;
         fs.sync().await
     }
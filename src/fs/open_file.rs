@@ -4,7 +4,12 @@ use crate::{
     sync::{AsyncMutexGuard, Mutex},
 };
 use alloc::{boxed::Box, sync::Arc, vec::Vec};
-use core::{future, pin::Pin, task::Poll};
+use core::{
+    future,
+    pin::Pin,
+    sync::atomic::{AtomicUsize, Ordering},
+    task::Poll,
+};
 use libkernel::{
     error::Result,
     fs::{Inode, OpenFlags, path::Path, pathbuf::PathBuf},
@@ -21,6 +26,28 @@ impl FileCtx {
     }
 }
 
+/// The default for `fs.file-max`.
+const FILE_MAX_DEFAULT: usize = 65536;
+
+/// How many files are open across the system.
+static NR_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// The most files that can be open across the system, `fs.file-max`. Beyond
+/// it, files are refused a descriptor.
+static FILE_MAX: AtomicUsize = AtomicUsize::new(FILE_MAX_DEFAULT);
+
+pub fn nr_files() -> usize {
+    NR_FILES.load(Ordering::Relaxed)
+}
+
+pub fn file_max() -> usize {
+    FILE_MAX.load(Ordering::Relaxed)
+}
+
+pub fn set_file_max(max: usize) {
+    FILE_MAX.store(max, Ordering::Relaxed);
+}
+
 pub struct OpenFile {
     inode: Option<Arc<dyn Inode>>,
     path: Option<PathBuf>,
@@ -29,6 +56,8 @@ pub struct OpenFile {
 
 impl OpenFile {
     pub fn new(ops: Box<dyn FileOps>, flags: OpenFlags) -> Self {
+        NR_FILES.fetch_add(1, Ordering::Relaxed);
+
         Self {
             state: Mutex::new((ops, FileCtx::new(flags))),
             inode: None,
@@ -86,16 +115,18 @@ impl OpenFile {
                     write_fut.await.map(|_| PollFlags::POLLOUT)
                 }));
             }
+
+            // A hangup is always reported, whether or not it was asked for.
+            let hangup_fut = ops.poll_hangup();
+
+            futs.push(Box::pin(async move {
+                hangup_fut.await.map(|_| PollFlags::POLLHUP)
+            }));
         }
 
         future::poll_fn(move |cx| {
             let mut flags = PollFlags::empty();
 
-            // If no events were requested, return immediately.
-            if futs.is_empty() {
-                return Poll::Ready(Ok(PollFlags::empty()));
-            }
-
             for fut in futs.iter_mut() {
                 match fut.as_mut().poll(cx) {
                     Poll::Ready(Ok(flag)) => flags.insert(flag),
@@ -112,3 +143,9 @@ impl OpenFile {
         })
     }
 }
+
+impl Drop for OpenFile {
+    fn drop(&mut self) {
+        NR_FILES.fetch_sub(1, Ordering::Relaxed);
+    }
+}
@@ -141,6 +141,18 @@ impl FileOps for PipeReader {
         })
     }
 
+    fn poll_hangup(&self) -> Pin<Box<dyn Future<Output = Result<()>> + 'static + Send>> {
+        let inner = self.inner.clone();
+        Box::pin(async move {
+            inner
+                .other_side_gone
+                .wait_until(|gone| if *gone { Some(()) } else { None })
+                .await;
+
+            Ok(())
+        })
+    }
+
     async fn read(&mut self, _ctx: &mut FileCtx, u_buf: UA, count: usize) -> Result<usize> {
         self.readat(u_buf, count, 0).await
     }
@@ -3,7 +3,7 @@ use crate::{
     kernel::kpipe::KPipe,
     memory::{
         page::ClaimedPage,
-        uaccess::{copy_from_user_slice, copy_to_user_slice},
+        uaccess::{copy_from_user_slice_partial, copy_to_user_slice_partial},
     },
 };
 use alloc::{boxed::Box, sync::Arc};
@@ -43,7 +43,6 @@ impl FileOps for RegFile {
 
         while count > 0 {
             let chunk_sz = min(PAGE_SIZE, count);
-            copy_from_user_slice(user_buf, &mut kbuf[..chunk_sz]).await?;
 
             let bytes_read = self.inode.read_at(offset, &mut kbuf[..chunk_sz]).await?;
 
@@ -51,12 +50,23 @@ impl FileOps for RegFile {
                 break;
             }
 
-            copy_to_user_slice(&kbuf[..bytes_read], user_buf).await?;
-
-            offset += bytes_read as u64;
-            total_bytes_read += bytes_read;
-            user_buf = user_buf.add_bytes(bytes_read);
-            count -= bytes_read;
+            // If the user buffer faults part-way through, report what was
+            // copied so far, as Linux does.
+            let res = copy_to_user_slice_partial(&kbuf[..bytes_read], user_buf).await;
+            let bytes_copied = match res {
+                Ok(n) => n,
+                Err(_) if total_bytes_read > 0 => break,
+                Err(e) => return Err(e),
+            };
+
+            offset += bytes_copied as u64;
+            total_bytes_read += bytes_copied;
+            user_buf = user_buf.add_bytes(bytes_copied);
+            count -= bytes_copied;
+
+            if bytes_copied < bytes_read {
+                break;
+            }
         }
 
         Ok(total_bytes_read)
@@ -70,9 +80,12 @@ impl FileOps for RegFile {
         let mut total_bytes_written = 0;
 
         while count > 0 {
-            let chunk_sz = min(PAGE_SIZE, count);
-
-            copy_from_user_slice(buf, &mut kbuf[..chunk_sz]).await?;
+            let res = copy_from_user_slice_partial(buf, &mut kbuf[..min(PAGE_SIZE, count)]).await;
+            let chunk_sz = match res {
+                Ok(n) => n,
+                Err(_) if total_bytes_written > 0 => break,
+                Err(e) => return Err(e),
+            };
 
             let bytes_written = self.inode.write_at(offset, &kbuf[..chunk_sz]).await?;
 
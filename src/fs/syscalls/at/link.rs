@@ -2,7 +2,7 @@ use core::ffi::c_char;
 
 use libkernel::{
     error::{FsError, KernelError, Result},
-    fs::{FileType, path::Path},
+    fs::{FileType, OpenFlags, path::Path},
     memory::address::TUA,
     proc::caps::CapabilitiesFlags,
 };
@@ -30,6 +30,9 @@ pub async fn sys_linkat(
 
     let task = ctx.shared().clone();
     let mut flags = AtFlags::from_bits_retain(flags);
+    if !(AtFlags::AT_SYMLINK_FOLLOW | AtFlags::AT_EMPTY_PATH).contains(flags) {
+        return Err(KernelError::InvalidValue);
+    }
 
     // following symlinks is implied for any other syscall.
     // for linkat though, we need to specify nofollow since
@@ -70,6 +73,31 @@ pub async fn sys_linkat(
         return Err(FsError::IsADirectory.into());
     }
 
+    // A file with no links left can't be brought back, unless it was opened
+    // with O_TMPFILE, and without O_EXCL, and it's being linked through that
+    // fd.
+    if attr.nlinks == 0 {
+        let linkable = if flags.contains(AtFlags::AT_EMPTY_PATH)
+            && old_path.as_str().is_empty()
+            && !old_dirfd.is_atcwd()
+        {
+            let file = task
+                .fd_table
+                .lock_save_irq()
+                .get(old_dirfd)
+                .ok_or(KernelError::BadFd)?;
+            let open_flags = file.flags().await;
+
+            open_flags.contains(OpenFlags::O_TMPFILE) && !open_flags.contains(OpenFlags::O_EXCL)
+        } else {
+            false
+        };
+
+        if !linkable {
+            return Err(FsError::NotFound.into());
+        }
+    }
+
     // newpath does not follow flags, and doesnt follow symlinks either
     if VFS
         .resolve_path_nofollow(new_path, new_start_node.clone(), &task)
@@ -30,16 +30,15 @@ pub async fn sys_readlinkat(
             .await?,
     );
 
-    let start = resolve_at_start_node(ctx, dirfd, path, AtFlags::empty()).await?;
-    let name = path.file_name().ok_or(FsError::InvalidInput)?;
+    if size == 0 || size > isize::MAX as usize {
+        return Err(FsError::InvalidInput.into());
+    }
 
-    let parent = if let Some(p) = path.parent() {
-        VFS.resolve_path_nofollow(p, start.clone(), &task).await?
-    } else {
-        start
-    };
+    let start = resolve_at_start_node(ctx, dirfd, path, AtFlags::empty()).await?;
 
-    let inode = parent.lookup(name).await?;
+    // Symlinks along the way are followed, but not the last one, which is
+    // what's read.
+    let inode = VFS.resolve_path_nofollow(path, start, &task).await?;
     let attr = inode.getattr().await?;
 
     if attr.file_type != FileType::Symlink {
@@ -7,7 +7,7 @@ use crate::{
 use core::ffi::c_char;
 use libkernel::{
     error::{KernelError, Result},
-    fs::{attr::FileAttr, path::Path},
+    fs::{FileType, attr::FileAttr, path::Path},
     memory::address::TUA,
 };
 
@@ -49,7 +49,10 @@ impl From<FileAttr> for Stat {
             st_nlink: value.nlinks,
             st_uid: value.uid.into(),
             st_gid: value.gid.into(),
-            st_rdev: 0,
+            st_rdev: match value.file_type {
+                FileType::CharDevice(desc) | FileType::BlockDevice(desc) => desc.to_dev_t(),
+                _ => 0,
+            },
             __pad1: 0,
             st_size: value.size as _,
             st_blksize: value.block_size as _,
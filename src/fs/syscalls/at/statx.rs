@@ -8,7 +8,11 @@ use crate::{
     sched::syscall_ctx::ProcessCtx,
 };
 use core::{ffi::c_char, time::Duration};
-use libkernel::{error::Result, fs::path::Path, memory::address::TUA};
+use libkernel::{
+    error::Result,
+    fs::{FileType, path::Path},
+    memory::address::TUA,
+};
 
 use super::AtFlags;
 
@@ -205,6 +209,11 @@ pub async fn sys_statx(
         stat_x.stx_mnt_id = attr.id.fs_id();
     }
 
+    if let FileType::CharDevice(desc) | FileType::BlockDevice(desc) = attr.file_type {
+        stat_x.stx_rdev_major = desc.major as _;
+        stat_x.stx_rdev_minor = desc.minor as _;
+    }
+
     stat_x.stx_attributes_mask = StatXAttr::STATX_ATTR_MOUNT_ROOT.bits();
     if VFS.is_mount_root(attr.id) {
         stat_x.stx_attributes |= StatXAttr::STATX_ATTR_MOUNT_ROOT.bits();
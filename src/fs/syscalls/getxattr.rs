@@ -1,6 +1,6 @@
 use crate::fs::VFS;
-use crate::memory::uaccess::copy_to_user_slice;
 use crate::memory::uaccess::cstr::UserCStr;
+use crate::memory::uaccess::user_slice::UserSlice;
 use crate::process::fd_table::Fd;
 use crate::sched::syscall_ctx::ProcessCtx;
 use alloc::sync::Arc;
@@ -12,12 +12,8 @@ use libkernel::memory::address::{TUA, UA};
 
 async fn getxattr(node: Arc<dyn Inode>, name: &str, ua: UA, size: usize) -> Result<usize> {
     let value = node.getxattr(name).await?;
-    if size < value.len() {
-        Err(KernelError::RangeError)
-    } else {
-        copy_to_user_slice(&value, ua).await?;
-        Ok(value.len())
-    }
+    UserSlice::new(ua.cast(), size)?.write(&value).await?;
+    Ok(value.len())
 }
 
 pub async fn sys_getxattr(
@@ -9,6 +9,10 @@ pub async fn sys_ioctl(ctx: &ProcessCtx, fd: Fd, request: usize, arg: usize) ->
         .get(fd)
         .ok_or(KernelError::BadFd)?;
 
+    // The request is an `unsigned int`, as on Linux, so a libc that passes
+    // it sign-extended still matches requests with the top bit set.
+    let request = request as u32 as usize;
+
     let (ops, ctx) = &mut *fd.lock().await;
     ops.ioctl(ctx, request, arg).await
 }
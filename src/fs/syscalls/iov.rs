@@ -1,23 +1,13 @@
 use crate::{
-    memory::uaccess::{UserCopyable, copy_obj_array_from_user},
+    memory::uaccess::iovec::{IoVec, UserIoVec},
     process::fd_table::Fd,
     sched::syscall_ctx::ProcessCtx,
 };
 use libkernel::{
     error::{KernelError, Result},
-    memory::address::{TUA, UA},
+    memory::address::TUA,
 };
 
-#[derive(Clone, Copy)]
-#[repr(C)]
-pub struct IoVec {
-    pub iov_base: UA,
-    pub iov_len: usize,
-}
-
-// SAFETY: An IoVec is safe to copy to-and-from userspace.
-unsafe impl UserCopyable for IoVec {}
-
 pub async fn sys_writev(
     ctx: &ProcessCtx,
     fd: Fd,
@@ -31,11 +21,11 @@ pub async fn sys_writev(
         .get(fd)
         .ok_or(KernelError::BadFd)?;
 
-    let iovs = copy_obj_array_from_user(iov_ptr, no_iov).await?;
+    let iovs = UserIoVec::from_user(iov_ptr, no_iov).await?;
 
     let (ops, state) = &mut *file.lock().await;
 
-    ops.writev(state, &iovs).await
+    ops.writev(state, iovs.iovs()).await
 }
 
 pub async fn sys_readv(
@@ -51,11 +41,11 @@ pub async fn sys_readv(
         .get(fd)
         .ok_or(KernelError::BadFd)?;
 
-    let iovs = copy_obj_array_from_user(iov_ptr, no_iov).await?;
+    let iovs = UserIoVec::from_user(iov_ptr, no_iov).await?;
 
     let (ops, state) = &mut *file.lock().await;
 
-    ops.readv(state, &iovs).await
+    ops.readv(state, iovs.iovs()).await
 }
 
 pub async fn sys_pwritev(
@@ -93,11 +83,11 @@ pub async fn sys_pwritev2(
         .get(fd)
         .ok_or(KernelError::BadFd)?;
 
-    let iovs = copy_obj_array_from_user(iov_ptr, no_iov).await?;
+    let iovs = UserIoVec::from_user(iov_ptr, no_iov).await?;
 
     let (ops, _state) = &mut *file.lock().await;
 
-    ops.writevat(&iovs, offset).await
+    ops.writevat(iovs.iovs(), offset).await
 }
 
 pub async fn sys_preadv2(
@@ -115,9 +105,9 @@ pub async fn sys_preadv2(
         .get(fd)
         .ok_or(KernelError::BadFd)?;
 
-    let iovs = copy_obj_array_from_user(iov_ptr, no_iov).await?;
+    let iovs = UserIoVec::from_user(iov_ptr, no_iov).await?;
 
     let (ops, _state) = &mut *file.lock().await;
 
-    ops.readvat(&iovs, offset).await
+    ops.readvat(iovs.iovs(), offset).await
 }
@@ -1,6 +1,6 @@
 use crate::fs::VFS;
-use crate::memory::uaccess::copy_to_user_slice;
 use crate::memory::uaccess::cstr::UserCStr;
+use crate::memory::uaccess::user_slice::UserSlice;
 use crate::process::fd_table::Fd;
 use crate::sched::syscall_ctx::ProcessCtx;
 use alloc::sync::Arc;
@@ -14,12 +14,8 @@ async fn listxattr(node: Arc<dyn Inode>, ua: UA, size: usize) -> Result<usize> {
     // Join with \0
     let list = list.join("\0");
     let list_bytes = list.as_bytes();
-    if size < list_bytes.len() {
-        Err(KernelError::RangeError)
-    } else {
-        copy_to_user_slice(list_bytes, ua).await?;
-        Ok(list_bytes.len())
-    }
+    UserSlice::new(ua.cast(), size)?.write(list_bytes).await?;
+    Ok(list_bytes.len())
 }
 
 pub async fn sys_listxattr(
@@ -51,7 +51,7 @@ pub async fn sys_mount(
     dir_name: TUA<c_char>,
     type_: TUA<c_char>,
     flags: i64,
-    _data: UA,
+    data: UA,
 ) -> Result<usize> {
     let flags = MountFlags::from_bits_truncate(flags as u64);
     if flags.contains(MountFlags::MS_REC) {
@@ -91,6 +91,53 @@ pub async fn sys_mount(
         s => s,
     };
 
+    if fs_name == "overlay" {
+        let mut buf = [0u8; 1024];
+        let options = if data.is_null() {
+            ""
+        } else {
+            UserCStr::from_ptr(data.cast())
+                .copy_from_user(&mut buf)
+                .await?
+        };
+        let (lower, upper) = overlay_dirs(options)?;
+
+        let lower = VFS
+            .resolve_path(Path::new(lower), VFS.root_inode(), ctx.shared())
+            .await?;
+        let upper = VFS
+            .resolve_path(Path::new(upper), VFS.root_inode(), ctx.shared())
+            .await?;
+
+        VFS.mount_overlay(mount_point, lower, upper).await?;
+        return Ok(0);
+    }
+
     VFS.mount(mount_point, fs_name, None).await?;
     Ok(0)
 }
+
+/// Returns the lower and upper directories given by an overlay's options,
+/// `lowerdir=<dir>,upperdir=<dir>`. Only one lower directory is supported.
+/// Linux's `workdir` is accepted but not used, as copy-ups are made in
+/// place.
+fn overlay_dirs(options: &str) -> Result<(&str, &str)> {
+    let (mut lower, mut upper) = (None, None);
+
+    for option in options.split(',').filter(|option| !option.is_empty()) {
+        match option.split_once('=') {
+            Some(("lowerdir", dirs)) if dirs.contains(':') => {
+                return Err(KernelError::NotSupported);
+            }
+            Some(("lowerdir", dir)) => lower = Some(dir),
+            Some(("upperdir", dir)) => upper = Some(dir),
+            Some(("workdir", _)) => {}
+            _ => return Err(KernelError::InvalidValue),
+        }
+    }
+
+    Ok((
+        lower.ok_or(KernelError::InvalidValue)?,
+        upper.ok_or(KernelError::InvalidValue)?,
+    ))
+}
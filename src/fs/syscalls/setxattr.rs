@@ -1,10 +1,9 @@
 use crate::fs::VFS;
-use crate::memory::uaccess::copy_from_user_slice;
 use crate::memory::uaccess::cstr::UserCStr;
+use crate::memory::uaccess::user_slice::UserSlice;
 use crate::process::fd_table::Fd;
 use crate::sched::syscall_ctx::ProcessCtx;
 use alloc::sync::Arc;
-use alloc::vec;
 use bitflags::bitflags;
 use core::ffi::c_char;
 use libkernel::error::{KernelError, Result};
@@ -38,8 +37,9 @@ async fn setxattr(
     if size > 2 * 1024 * 1024 {
         return Err(KernelError::RangeError);
     }
-    let mut value_vec = vec![0u8; size];
-    copy_from_user_slice(value, &mut value_vec[..]).await?;
+    let value_vec = UserSlice::new(value.cast::<u8>(), size)?
+        .read_to_vec()
+        .await?;
     node.setxattr(
         name,
         &value_vec,
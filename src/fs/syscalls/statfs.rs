@@ -49,17 +49,19 @@ unsafe impl UserCopyable for StatFs {}
 
 async fn statfs_impl(inode: Arc<dyn Inode>) -> libkernel::error::Result<StatFs> {
     let fs = VFS.get_fs(inode).await?;
+    let stats = fs.statfs().await?;
+
     Ok(StatFs {
         f_type: fs.magic() as _,
-        f_bsize: 0,
-        f_blocks: 0,
-        f_bfree: 0,
-        f_bavail: 0,
-        f_files: 0,
-        f_ffree: 0,
+        f_bsize: stats.block_size as _,
+        f_blocks: stats.total_blocks,
+        f_bfree: stats.free_blocks,
+        f_bavail: stats.avail_blocks,
+        f_files: stats.total_inodes,
+        f_ffree: stats.free_inodes,
         f_fsid: fs.id(),
-        f_namelen: 0,
-        f_frsize: 0,
+        f_namelen: stats.name_len as _,
+        f_frsize: stats.block_size as _,
         f_flags: 0,
         f_spare: [0; 6],
     })
@@ -72,9 +74,9 @@ pub async fn sys_statfs(
 ) -> libkernel::error::Result<usize> {
     let mut buf = [0; 1024];
     let path = Path::new(UserCStr::from_ptr(path).copy_from_user(&mut buf).await?);
-    let inode = VFS
-        .resolve_path(path, VFS.root_inode(), &ctx.shared().clone())
-        .await?;
+    let task = ctx.shared().clone();
+    let cwd = task.cwd.lock_save_irq().0.clone();
+    let inode = VFS.resolve_path(path, cwd, &task).await?;
     let statfs = statfs_impl(inode).await?;
     copy_to_user(stat, statfs).await?;
     Ok(0)
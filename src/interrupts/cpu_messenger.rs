@@ -1,6 +1,11 @@
 //! A module for sending messages between CPUs, utilising IPIs.
 
-use core::task::Waker;
+use core::{
+    hint::spin_loop,
+    sync::atomic::{AtomicUsize, Ordering},
+    task::Waker,
+    time::Duration,
+};
 
 use super::{
     ClaimedInterrupt, InterruptConfig, InterruptDescriptor, InterruptHandler, get_interrupt_root,
@@ -8,8 +13,8 @@ use super::{
 use crate::kernel::cpu_id::CpuId;
 use crate::sched::sched_task::Work;
 use crate::{
-    arch::ArchImpl,
-    drivers::Driver,
+    arch::{Arch, ArchImpl},
+    drivers::{Driver, timer::now},
     kernel::kpipe::KBuf,
     sched,
     sync::{OnceLock, SpinLock},
@@ -25,6 +30,12 @@ pub enum Message {
     EnqueueWork(Arc<Work>),
     #[expect(unused)]
     WakeupTask(Waker),
+    /// Takes the CPU offline, e.g. before jumping to a new kernel.
+    Stop,
+    /// Wakes the CPU to poll ready kernel tasks. Returning from the IPI
+    /// passes through the dispatcher, which does so, so there's nothing else
+    /// to do.
+    Kick,
 }
 
 struct CpuMessenger {
@@ -40,18 +51,25 @@ impl Driver for CpuMessenger {
 
 impl InterruptHandler for CpuMessenger {
     fn handle_irq(&self, _desc: InterruptDescriptor) {
-        while let Some(message) = CPU_MESSENGER
-            .get()
-            .unwrap()
-            .mailboxes
-            .lock_save_irq()
-            .get(ArchImpl::id())
-            .unwrap()
-            .try_pop()
-        {
+        loop {
+            // Pop in a statement of its own, so the mailboxes aren't locked
+            // while the message is handled.
+            let message = self
+                .mailboxes
+                .lock_save_irq()
+                .get(ArchImpl::id())
+                .unwrap()
+                .try_pop();
+
             match message {
-                Message::EnqueueWork(work) => sched::insert_work(work),
-                Message::WakeupTask(waker) => waker.wake(),
+                Some(Message::EnqueueWork(work)) => sched::insert_work(work),
+                Some(Message::WakeupTask(waker)) => waker.wake(),
+                Some(Message::Kick) => {}
+                Some(Message::Stop) => {
+                    STOPPED_CPUS.fetch_add(1, Ordering::Release);
+                    ArchImpl::cpu_off()
+                }
+                None => break,
             }
         }
     }
@@ -104,4 +122,37 @@ pub fn message_cpu(cpu_id: CpuId, message: Message) -> Result<()> {
     Ok(())
 }
 
+/// Stops every other CPU, waiting up to [`STOP_TIMEOUT`] for them to go
+/// offline.
+pub fn stop_other_cpus() {
+    let this = CpuId::this();
+
+    for cpu in (0..ArchImpl::cpu_count()).map(CpuId::from_value) {
+        if cpu != this && message_cpu(cpu, Message::Stop).is_err() {
+            warn!("Could not stop CPU {}", cpu.value());
+        }
+    }
+
+    let others = ArchImpl::cpu_count() - 1;
+    let deadline = now().map(|now| now + STOP_TIMEOUT);
+
+    while STOPPED_CPUS.load(Ordering::Acquire) < others {
+        if let Some(deadline) = deadline
+            && now().is_some_and(|now| now >= deadline)
+        {
+            warn!(
+                "Only {} of {others} CPUs stopped",
+                STOPPED_CPUS.load(Ordering::Acquire)
+            );
+            break;
+        }
+
+        spin_loop();
+    }
+}
+
+/// How long [`stop_other_cpus`] waits for the other CPUs.
+const STOP_TIMEOUT: Duration = Duration::from_millis(100);
+
+static STOPPED_CPUS: AtomicUsize = AtomicUsize::new(0);
 static CPU_MESSENGER: OnceLock<Arc<CpuMessenger>> = OnceLock::new();
@@ -12,6 +12,7 @@ use crate::{
 };
 
 pub mod cpu_messenger;
+pub mod msi;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TriggerMode {
@@ -0,0 +1,51 @@
+//! Message-signalled interrupts.
+//!
+//! Rather than asserting a wire, a device raises an MSI by writing a value to
+//! an address, a doorbell in some interrupt controller. An [`MsiController`]
+//! hands out interrupts along with the message which raises each one; the
+//! device's driver programs the message into the device, and claims the
+//! interrupt from the root interrupt controller as usual.
+
+use super::InterruptConfig;
+use crate::sync::OnceLock;
+use alloc::sync::Arc;
+use libkernel::error::Result;
+use log::info;
+
+/// The write a device performs to raise an MSI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MsiMessage {
+    /// The physical address of the doorbell.
+    pub address: u64,
+    /// The value to write.
+    pub data: u32,
+}
+
+/// An allocated MSI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MsiVector {
+    /// The interrupt to claim from the root interrupt controller.
+    pub config: InterruptConfig,
+    /// The message which raises it.
+    pub message: MsiMessage,
+}
+
+pub trait MsiController: Send + Sync {
+    /// Allocates an interrupt raised by a message.
+    fn alloc_msi(&self) -> Result<MsiVector>;
+
+    /// Returns an interrupt from [`MsiController::alloc_msi`] to the pool.
+    fn free_msi(&self, vector: MsiVector);
+}
+
+static MSI_CONTROLLER: OnceLock<Arc<dyn MsiController>> = OnceLock::new();
+
+pub fn set_msi_controller(name: &str, controller: Arc<dyn MsiController>) {
+    if MSI_CONTROLLER.set(controller).is_ok() {
+        info!("Using device {name} for MSIs");
+    }
+}
+
+pub fn get_msi_controller() -> Option<Arc<dyn MsiController>> {
+    MSI_CONTROLLER.get().cloned()
+}
@@ -0,0 +1,27 @@
+use crate::arch::{Arch, ArchImpl};
+use crate::kernel::ksyms;
+use log::error;
+
+/// The maximum number of frames printed by [`dump_backtrace`].
+const MAX_FRAMES: usize = 32;
+
+/// Prints a previously captured backtrace to the kernel log, with each address
+/// symbolized if the symbol table has it.
+pub fn print_backtrace(frames: &[usize]) {
+    for (i, &addr) in frames.iter().enumerate() {
+        // Each frame is a return address. Look up the call before it, which
+        // may have been the last instruction of its function.
+        match ksyms::lookup(addr - 1) {
+            Some(sym) => error!("  #{i:<2} {addr:#018x} {}+{:#x}", sym.name, addr - sym.addr),
+            None => error!("  #{i:<2} {addr:#018x}"),
+        }
+    }
+}
+
+/// Captures and prints a backtrace of the current call stack.
+pub fn dump_backtrace() {
+    let mut frames = [0; MAX_FRAMES];
+    let depth = ArchImpl::capture_backtrace(&mut frames);
+
+    print_backtrace(&frames[..depth]);
+}
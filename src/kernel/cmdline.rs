@@ -0,0 +1,206 @@
+//! The kernel command line.
+//!
+//! The command line is parsed once, early in boot, into [`KernelParams`],
+//! which subsystems consult as they're brought up. Parameters are given as
+//! `name=value`, or as a bare `name` for flags. Dashes and underscores in names
+//! are interchangeable, and a leading `--` is ignored. Everything after a lone
+//! `--` is passed on to init as its arguments.
+
+use crate::sync::OnceLock;
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use libkernel::{fs::pathbuf::PathBuf, memory::proc_vm::overcommit::OvercommitPolicy};
+use log::warn;
+
+pub struct KernelParams {
+    /// `root=`: the device holding the root filesystem. Only the initrd,
+    /// `/dev/ram0`, is supported, which is also the default.
+    pub root: Option<String>,
+    /// `rootfstype=`: the filesystem driver to mount the root with.
+    pub rootfstype: Option<String>,
+    /// `console=`: the device to use as the console, e.g. `ttyS0` or `hvc0`,
+    /// rather than the one the firmware names. Options following a comma, such
+    /// as the baud rate, are dropped.
+    pub console: Option<String>,
+    /// `loglevel=`: the console loglevel.
+    pub loglevel: Option<u8>,
+    /// `init=`: the program to run as init.
+    pub init: Option<PathBuf>,
+    /// `init-arg=`, which may be repeated, followed by anything after `--`.
+    pub init_args: Vec<String>,
+    /// `automount=path,fs`, which may be repeated: filesystems to mount
+    /// before init is run.
+    pub automounts: Vec<(PathBuf, String)>,
+    /// `overcommit-memory=`
+    pub overcommit_memory: Option<OvercommitPolicy>,
+    /// `overcommit-ratio=`
+    pub overcommit_ratio: Option<usize>,
+    /// `nosmp`: only run on the boot CPU.
+    pub nosmp: bool,
+}
+
+/// The parameters before the command line has been parsed.
+static DEFAULT_PARAMS: KernelParams = KernelParams::new();
+
+static PARAMS: OnceLock<KernelParams> = OnceLock::new();
+
+impl KernelParams {
+    const fn new() -> Self {
+        Self {
+            root: None,
+            rootfstype: None,
+            console: None,
+            loglevel: None,
+            init: None,
+            init_args: Vec::new(),
+            automounts: Vec::new(),
+            overcommit_memory: None,
+            overcommit_ratio: None,
+            nosmp: false,
+        }
+    }
+
+    /// Parses a command line, warning about and skipping parameters that
+    /// aren't understood.
+    pub fn parse(cmdline: &str) -> Self {
+        let mut params = Self::new();
+        let mut args = cmdline.split_whitespace();
+
+        for arg in args.by_ref() {
+            if arg == "--" {
+                break;
+            }
+
+            let (name, value) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (arg, None),
+            };
+
+            let name = name.strip_prefix("--").unwrap_or(name).replace('_', "-");
+
+            if !params.set(&name, value) {
+                warn!("Ignoring kernel parameter {arg}");
+            }
+        }
+
+        params.init_args.extend(args.map(str::to_string));
+
+        params
+    }
+
+    /// Sets the parameter `name`, returning whether it's known and `value`
+    /// suits it.
+    fn set(&mut self, name: &str, value: Option<&str>) -> bool {
+        match (name, value) {
+            ("root", Some(root)) => self.root = Some(root.to_string()),
+            ("rootfstype" | "rootfs", Some(fs)) => self.rootfstype = Some(fs.to_string()),
+            ("console", Some(console)) => {
+                let name = console.split(',').next().unwrap_or(console);
+
+                self.console = Some(name.to_string());
+            }
+            ("loglevel", Some(level)) => match level.parse() {
+                Ok(level) => self.loglevel = Some(level),
+                Err(_) => return false,
+            },
+            ("init", Some(init)) => self.init = Some(PathBuf::from(init)),
+            ("init-arg", Some(arg)) => self.init_args.push(arg.to_string()),
+            ("automount", Some(automount)) => {
+                let Some((path, fs)) = automount.split_once(',') else {
+                    return false;
+                };
+
+                self.automounts.push((PathBuf::from(path), fs.to_string()));
+            }
+            ("overcommit-memory", Some(policy)) => {
+                match policy
+                    .parse::<u8>()
+                    .ok()
+                    .and_then(|x| OvercommitPolicy::try_from(x).ok())
+                {
+                    Some(policy) => self.overcommit_memory = Some(policy),
+                    None => return false,
+                }
+            }
+            ("overcommit-ratio", Some(ratio)) => match ratio.parse() {
+                Ok(ratio) => self.overcommit_ratio = Some(ratio),
+                Err(_) => return false,
+            },
+            ("nosmp", None) => self.nosmp = true,
+            _ => return false,
+        }
+
+        true
+    }
+}
+
+/// Parses the command line given by the bootloader. Must be called once, as
+/// early in boot as the heap allows.
+pub fn cmdline_init(cmdline: &str) {
+    if PARAMS.set(KernelParams::parse(cmdline)).is_err() {
+        warn!("Attempted to parse the kernel command line multiple times");
+    }
+}
+
+/// Returns the parameters from the kernel command line, or the defaults if it
+/// hasn't been parsed yet.
+pub fn params() -> &'static KernelParams {
+    PARAMS.get().unwrap_or(&DEFAULT_PARAMS)
+}
+
+/// Returns whether the console device `name`, e.g. `ttyS0`, should be made
+/// the active console. `console=` decides if it was given, otherwise it's up
+/// to the firmware, whose choice is `firmware_choice`.
+pub fn is_console(name: &str, firmware_choice: bool) -> bool {
+    match params().console.as_deref() {
+        Some(console) => console == name,
+        None => firmware_choice,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::KernelParams;
+    use libkernel::fs::pathbuf::PathBuf;
+    use moss_macros::ktest;
+
+    #[ktest]
+    fn parse_linux_style() {
+        let params = KernelParams::parse(
+            "root=/dev/ram0 rootfstype=ext4fs console=ttyS0,115200n8 loglevel=4 nosmp",
+        );
+
+        assert_eq!(params.root.as_deref(), Some("/dev/ram0"));
+        assert_eq!(params.rootfstype.as_deref(), Some("ext4fs"));
+        assert_eq!(params.console.as_deref(), Some("ttyS0"));
+        assert_eq!(params.loglevel, Some(4));
+        assert!(params.nosmp);
+    }
+
+    #[ktest]
+    fn parse_dashed_and_init_args() {
+        let params = KernelParams::parse(
+            "--init=/bin/sh --init-arg=-i automount=/proc,procfs overcommit_ratio=80 -- -l x",
+        );
+
+        assert_eq!(params.init, Some(PathBuf::from("/bin/sh")));
+        assert_eq!(params.init_args, ["-i", "-l", "x"]);
+        assert_eq!(
+            params.automounts,
+            [(PathBuf::from("/proc"), "procfs".into())]
+        );
+        assert_eq!(params.overcommit_ratio, Some(80));
+        assert!(!params.nosmp);
+    }
+
+    #[ktest]
+    fn parse_skips_bad_params() {
+        let params = KernelParams::parse("loglevel=loud nosmp=1 bogus init");
+
+        assert_eq!(params.loglevel, None);
+        assert!(!params.nosmp);
+        assert_eq!(params.init, None);
+    }
+}
@@ -0,0 +1,115 @@
+//! CPU idle state selection.
+//!
+//! The architecture registers the idle states its CPUs support, shallowest
+//! first. When a CPU has nothing to run, [`enter_idle`] predicts how long it
+//! will stay idle from when its next timer event is due, and enters the
+//! deepest state which is worth entering for that long.
+
+use crate::{
+    drivers::timer::{next_event, now},
+    kernel::cpu_id::CpuId,
+    per_cpu_shared,
+    sync::{OnceLock, RCU},
+};
+use alloc::{boxed::Box, string::String, vec::Vec};
+use core::{
+    array,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+use log::{info, warn};
+
+/// The most idle states that can be registered.
+pub const MAX_IDLE_STATES: usize = 8;
+
+pub struct IdleState {
+    pub name: String,
+    /// How long it takes the CPU to come back out of the state.
+    pub exit_latency: Duration,
+    /// The shortest stay in the state which saves any power, once the cost
+    /// of entering and leaving it is counted.
+    pub target_residency: Duration,
+    /// Enters the state, returning once the CPU has been woken by an
+    /// interrupt. Called with interrupts masked.
+    pub enter: Box<dyn Fn() + Send + Sync>,
+}
+
+/// How often, and for how long, a CPU has entered each idle state.
+pub struct IdleStats {
+    pub usage: [AtomicU64; MAX_IDLE_STATES],
+    pub time_us: [AtomicU64; MAX_IDLE_STATES],
+}
+
+per_cpu_shared! {
+    pub static IDLE_STATS: IdleStats = || IdleStats {
+        usage: array::from_fn(|_| AtomicU64::new(0)),
+        time_us: array::from_fn(|_| AtomicU64::new(0)),
+    };
+}
+
+static IDLE_STATES: OnceLock<Vec<IdleState>> = OnceLock::new();
+
+/// Registers the idle states, which must be ordered from shallowest to
+/// deepest. The first should always be safe to enter.
+pub fn register_idle_states(mut states: Vec<IdleState>) {
+    if states.len() > MAX_IDLE_STATES {
+        warn!("Ignoring idle states beyond the first {MAX_IDLE_STATES}");
+        states.truncate(MAX_IDLE_STATES);
+    }
+
+    for state in &states {
+        info!(
+            "cpuidle: {} (exit latency {:?}, target residency {:?})",
+            state.name, state.exit_latency, state.target_residency
+        );
+    }
+
+    if IDLE_STATES.set(states).is_err() {
+        warn!("Attempted to register idle states multiple times");
+    }
+}
+
+/// Returns the registered idle states.
+pub fn idle_states() -> &'static [IdleState] {
+    IDLE_STATES.get().map(Vec::as_slice).unwrap_or_default()
+}
+
+/// Idles the current CPU until it's woken by an interrupt. Called with
+/// interrupts masked.
+pub fn enter_idle() {
+    let states = idle_states();
+
+    if states.is_empty() {
+        return;
+    }
+
+    let start = now();
+
+    // With nothing else to go on, assume the CPU is idle until it's next
+    // woken by its timer.
+    let predicted = start
+        .zip(next_event())
+        .map(|(start, next)| next - start)
+        .unwrap_or(Duration::MAX);
+
+    let idx = states
+        .iter()
+        .rposition(|state| state.target_residency <= predicted)
+        .unwrap_or(0);
+
+    // Nothing runs on this CPU until it wakes, interrupts included, so it
+    // can't hold up a grace period meanwhile.
+    let cpu = CpuId::this().value();
+    RCU.idle_enter(cpu);
+    (states[idx].enter)();
+    RCU.idle_exit(cpu);
+
+    let stats = IDLE_STATS.get();
+    stats.usage[idx].fetch_add(1, Ordering::Relaxed);
+
+    if let Some(start) = start
+        && let Some(end) = now()
+    {
+        stats.time_us[idx].fetch_add((end - start).as_micros() as u64, Ordering::Relaxed);
+    }
+}
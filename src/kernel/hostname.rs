@@ -3,25 +3,53 @@ use crate::sched::syscall_ctx::ProcessCtx;
 use crate::sync::OnceLock;
 use crate::sync::SpinLock;
 use alloc::string::{String, ToString};
+use alloc::sync::Arc;
 use alloc::vec;
 use core::ffi::c_char;
 use libkernel::error::{KernelError, Result};
 use libkernel::memory::address::TUA;
 use libkernel::proc::caps::CapabilitiesFlags;
 
-static HOSTNAME: OnceLock<SpinLock<String>> = OnceLock::new();
+pub const HOST_NAME_MAX: usize = 64;
 
-pub fn hostname() -> &'static SpinLock<String> {
-    HOSTNAME.get_or_init(|| SpinLock::new(String::from("moss-machine")))
+/// A UTS namespace: the host and domain names seen by the tasks within it.
+pub struct UtsNamespace {
+    pub hostname: SpinLock<String>,
+    pub domainname: SpinLock<String>,
 }
 
-const HOST_NAME_MAX: usize = 64;
+impl UtsNamespace {
+    /// Creates a new namespace, for `CLONE_NEWUTS`, which starts out with the
+    /// same names as this one.
+    pub fn copy(&self) -> Self {
+        Self {
+            hostname: SpinLock::new(self.hostname.lock_save_irq().clone()),
+            domainname: SpinLock::new(self.domainname.lock_save_irq().clone()),
+        }
+    }
+}
 
-pub async fn sys_sethostname(
+static INIT_UTS_NS: OnceLock<Arc<UtsNamespace>> = OnceLock::new();
+
+/// Returns the namespace which init, and everything not in a namespace of its
+/// own, belongs to.
+pub fn init_uts_ns() -> Arc<UtsNamespace> {
+    INIT_UTS_NS
+        .get_or_init(|| {
+            Arc::new(UtsNamespace {
+                hostname: SpinLock::new(String::from("moss-machine")),
+                domainname: SpinLock::new(String::from("(none)")),
+            })
+        })
+        .clone()
+}
+
+/// Copies in a name for `sethostname` or `setdomainname`.
+async fn copy_name_from_user(
     ctx: &ProcessCtx,
     name_ptr: TUA<c_char>,
     name_len: usize,
-) -> Result<usize> {
+) -> Result<String> {
     {
         let creds = ctx.shared().creds.lock_save_irq();
         creds
@@ -37,7 +65,26 @@ pub async fn sys_sethostname(
     let name = core::str::from_utf8(&buf)
         .map_err(|_| KernelError::InvalidValue)?
         .trim_end_matches('\0');
-    *hostname().lock_save_irq() = name.to_string();
+    Ok(name.to_string())
+}
+
+pub async fn sys_sethostname(
+    ctx: &ProcessCtx,
+    name_ptr: TUA<c_char>,
+    name_len: usize,
+) -> Result<usize> {
+    let name = copy_name_from_user(ctx, name_ptr, name_len).await?;
+    *ctx.shared().uts_ns.hostname.lock_save_irq() = name;
+    Ok(0)
+}
+
+pub async fn sys_setdomainname(
+    ctx: &ProcessCtx,
+    name_ptr: TUA<c_char>,
+    name_len: usize,
+) -> Result<usize> {
+    let name = copy_name_from_user(ctx, name_ptr, name_len).await?;
+    *ctx.shared().uts_ns.domainname.lock_save_irq() = name;
     Ok(0)
 }
 
@@ -0,0 +1,273 @@
+//! Loading a new kernel and jumping straight into it, without going back
+//! through the firmware.
+//!
+//! `kexec_load` copies each segment of the new image into pages of the
+//! running kernel's memory, chosen so that none of them lie where the image is
+//! to be placed. `reboot(LINUX_REBOOT_CMD_KEXEC)` then stops the other CPUs and
+//! hands the list of pages to the architecture, which copies them into place
+//! and enters the new kernel.
+
+use crate::{
+    arch::{Arch, ArchImpl},
+    drivers::fdt_prober::get_fdt,
+    interrupts::cpu_messenger::stop_other_cpus,
+    memory::{
+        PAGE_ALLOC, PageOffsetTranslator,
+        uaccess::{UserCopyable, copy_from_user_slice, copy_obj_array_from_user},
+    },
+    sched::syscall_ctx::ProcessCtx,
+    sync::SpinLock,
+};
+use alloc::vec::Vec;
+use core::{mem::size_of, slice};
+use libkernel::{
+    error::{KernelError, Result},
+    memory::{
+        PAGE_SIZE,
+        address::{PA, TUA, UA},
+        allocators::phys::PageAllocation,
+        region::PhysMemoryRegion,
+    },
+    proc::caps::CapabilitiesFlags,
+};
+use log::info;
+
+const KEXEC_ON_CRASH: usize = 0x1;
+const KEXEC_PRESERVE_CONTEXT: usize = 0x2;
+const KEXEC_ARCH_MASK: usize = 0xffff << 16;
+
+const KEXEC_ARCH_DEFAULT: usize = 0;
+const KEXEC_ARCH_AARCH64: usize = 183 << 16;
+
+/// The most segments an image may be loaded in.
+const KEXEC_SEGMENT_MAX: usize = 16;
+
+const FDT_MAGIC: [u8; 4] = 0xd00d_feed_u32.to_be_bytes();
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct KexecSegment {
+    buf: UA,
+    bufsz: usize,
+    mem: usize,
+    memsz: usize,
+}
+
+// SAFETY: A KexecSegment is safe to copy to-and-from userspace.
+unsafe impl UserCopyable for KexecSegment {}
+
+/// A page of a loaded image, and the address it must be copied to before the
+/// new kernel is entered.
+#[repr(C)]
+pub struct KexecCopy {
+    pub dest: PA,
+    pub src: PA,
+}
+
+struct KexecImage {
+    entry: PA,
+    /// Passed to the new kernel in its first argument: the address of its
+    /// device tree, if one was loaded.
+    arg: PA,
+    /// The [`KexecCopy`]s, one for each page of the image.
+    copies: PageAllocation<'static, ArchImpl>,
+    nr_copies: usize,
+    /// A spare page, for the architecture to copy the image into place from.
+    control: PageAllocation<'static, ArchImpl>,
+    /// The pages holding the image until it's copied into place.
+    _pages: Vec<PageAllocation<'static, ArchImpl>>,
+}
+
+static KEXEC_IMAGE: SpinLock<Option<KexecImage>> = SpinLock::new(None);
+
+/// Allocates pages which lie outside of where the image is to be placed, so
+/// that copying the image into place can't overwrite them.
+struct ImageAllocator {
+    dests: Vec<PhysMemoryRegion>,
+    /// Pages which were in the way. They're held onto until the image is
+    /// loaded, so they aren't handed out again.
+    rejected: Vec<PageAllocation<'static, ArchImpl>>,
+}
+
+impl ImageAllocator {
+    fn alloc(&mut self, order: u8) -> Result<PageAllocation<'static, ArchImpl>> {
+        loop {
+            let alloc = PAGE_ALLOC.get().unwrap().alloc_frames(order)?;
+
+            if !self.dests.iter().any(|dest| dest.overlaps(*alloc.region())) {
+                return Ok(alloc);
+            }
+
+            self.rejected.push(alloc);
+        }
+    }
+}
+
+fn page_slice<'a>(alloc: &'a mut PageAllocation<'static, ArchImpl>) -> &'a mut [u8] {
+    let region = alloc.region().map_via::<PageOffsetTranslator>();
+
+    // SAFETY: The pages are owned by the allocation and are accessed through
+    // the linear map.
+    unsafe { slice::from_raw_parts_mut(region.start_address().as_ptr_mut().cast(), region.size()) }
+}
+
+fn is_ram(region: PhysMemoryRegion) -> bool {
+    get_fdt().memory().any(|mem| {
+        mem.regions().any(|ram| {
+            PhysMemoryRegion::new(PA::from_value(ram.address.addr()), ram.size).contains(region)
+        })
+    })
+}
+
+/// Checks that the segments are page aligned, lie within RAM and don't
+/// overlap, returning where each is to be placed.
+fn check_segments(segments: &[KexecSegment]) -> Result<Vec<PhysMemoryRegion>> {
+    let mut dests: Vec<PhysMemoryRegion> = Vec::with_capacity(segments.len());
+
+    for seg in segments {
+        let dest = PhysMemoryRegion::new(PA::from_value(seg.mem), seg.memsz);
+
+        if dest.is_empty()
+            || !dest.is_page_aligned()
+            || !seg.memsz.is_multiple_of(PAGE_SIZE)
+            || seg.bufsz > seg.memsz
+            || !is_ram(dest)
+            || dests.iter().any(|other| other.overlaps(dest))
+        {
+            return Err(KernelError::InvalidValue);
+        }
+
+        dests.push(dest);
+    }
+
+    Ok(dests)
+}
+
+async fn load_image(entry: PA, segments: &[KexecSegment]) -> Result<KexecImage> {
+    let dests = check_segments(segments)?;
+
+    if !dests.iter().any(|dest| dest.contains_address(entry)) {
+        return Err(KernelError::InvalidValue);
+    }
+
+    let mut allocator = ImageAllocator {
+        dests,
+        rejected: Vec::new(),
+    };
+
+    let mut pages = Vec::new();
+    let mut copies = Vec::new();
+    let mut arg = PA::from_value(0);
+
+    for seg in segments {
+        for offset in (0..seg.memsz).step_by(PAGE_SIZE) {
+            let mut page = allocator.alloc(0)?;
+            let buf = page_slice(&mut page);
+            let len = seg.bufsz.saturating_sub(offset).min(PAGE_SIZE);
+
+            copy_from_user_slice(seg.buf.add_bytes(offset), &mut buf[..len]).await?;
+            buf[len..].fill(0);
+
+            if offset == 0 && buf.starts_with(&FDT_MAGIC) {
+                arg = PA::from_value(seg.mem);
+            }
+
+            copies.push(KexecCopy {
+                dest: PA::from_value(seg.mem + offset),
+                src: page.region().start_address(),
+            });
+            pages.push(page);
+        }
+    }
+
+    let list_size = copies.len() * size_of::<KexecCopy>();
+    let order = list_size
+        .div_ceil(PAGE_SIZE)
+        .next_power_of_two()
+        .trailing_zeros();
+    let mut list = allocator.alloc(order as u8)?;
+
+    // SAFETY: The list was sized to hold every copy.
+    unsafe {
+        page_slice(&mut list)
+            .as_mut_ptr()
+            .cast::<KexecCopy>()
+            .copy_from_nonoverlapping(copies.as_ptr(), copies.len());
+    }
+
+    let control = allocator.alloc(0)?;
+
+    Ok(KexecImage {
+        entry,
+        arg,
+        copies: list,
+        nr_copies: copies.len(),
+        control,
+        _pages: pages,
+    })
+}
+
+pub async fn sys_kexec_load(
+    ctx: &ProcessCtx,
+    entry: usize,
+    nr_segments: usize,
+    segments: TUA<KexecSegment>,
+    flags: usize,
+) -> Result<usize> {
+    ctx.shared()
+        .creds
+        .lock_save_irq()
+        .caps()
+        .check_capable(CapabilitiesFlags::CAP_SYS_BOOT)?;
+
+    if flags & !(KEXEC_ON_CRASH | KEXEC_PRESERVE_CONTEXT | KEXEC_ARCH_MASK) != 0
+        || !matches!(
+            flags & KEXEC_ARCH_MASK,
+            KEXEC_ARCH_DEFAULT | KEXEC_ARCH_AARCH64
+        )
+        || nr_segments > KEXEC_SEGMENT_MAX
+    {
+        return Err(KernelError::InvalidValue);
+    }
+
+    // There's no crash kernel reservation, nor a way back from the new
+    // kernel.
+    if flags & (KEXEC_ON_CRASH | KEXEC_PRESERVE_CONTEXT) != 0 {
+        return Err(KernelError::NotSupported);
+    }
+
+    if nr_segments == 0 {
+        KEXEC_IMAGE.lock_save_irq().take();
+        return Ok(0);
+    }
+
+    let segments = copy_obj_array_from_user(segments, nr_segments).await?;
+    let image = load_image(PA::from_value(entry), &segments).await?;
+
+    info!(
+        "kexec: loaded {} pages, entry at {}",
+        image.nr_copies, image.entry
+    );
+
+    *KEXEC_IMAGE.lock_save_irq() = Some(image);
+
+    Ok(0)
+}
+
+/// Enters the kernel loaded by `kexec_load`. Only returns if there isn't one.
+pub fn kernel_kexec() -> Result<usize> {
+    let image = KEXEC_IMAGE.lock_save_irq().take();
+    let image = image.ok_or(KernelError::InvalidValue)?;
+
+    info!("kexec: starting new kernel");
+
+    stop_other_cpus();
+
+    ArchImpl::kexec(
+        *image.copies.region(),
+        image.nr_copies,
+        *image.control.region(),
+        image.entry,
+        image.arg,
+    )
+}
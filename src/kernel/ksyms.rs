@@ -0,0 +1,102 @@
+//! The kernel's symbol table, for symbolizing backtraces.
+//!
+//! The table is written into the image after it's linked, by
+//! `scripts/gen-ksyms.py`, into space reserved here. An image which hasn't been
+//! through the script has an empty table, and its addresses aren't symbolized.
+
+use core::{ffi::CStr, mem::size_of, slice};
+
+/// The space reserved for the table.
+const KSYMS_SIZE: usize = 1 << 20;
+
+const KSYMS_MAGIC: [u8; 8] = *b"MOSSSYMS";
+
+#[used]
+#[unsafe(link_section = ".ksyms")]
+static KSYMS_AREA: [u8; KSYMS_SIZE] = [0; KSYMS_SIZE];
+
+#[repr(C)]
+struct KsymsHeader {
+    magic: [u8; 8],
+    /// The address the symbols' offsets are from.
+    base: u64,
+    count: u32,
+    /// Where the names start, from the start of the table.
+    names: u32,
+}
+
+/// Follows the header, sorted by address.
+#[repr(C)]
+struct KsymEntry {
+    offset: u32,
+    /// Where the symbol's NUL-terminated name starts, from the start of the
+    /// names.
+    name: u32,
+}
+
+pub struct Symbol {
+    pub name: &'static str,
+    pub addr: usize,
+}
+
+fn table() -> Option<(&'static KsymsHeader, &'static [KsymEntry], &'static [u8])> {
+    unsafe extern "C" {
+        static __ksyms_start: u8;
+        static __ksyms_end: u8;
+    }
+
+    // SAFETY: The linker script places `KSYMS_AREA` between these two
+    // symbols. They're used rather than the static itself, which the compiler
+    // would otherwise assume is still all zeroes.
+    let area = unsafe {
+        let start = &raw const __ksyms_start;
+        let end = &raw const __ksyms_end;
+
+        slice::from_raw_parts(start, end.offset_from(start) as usize)
+    };
+
+    // SAFETY: The area is 8-byte aligned by the linker script, and larger
+    // than the header.
+    let header = unsafe { &*area.as_ptr().cast::<KsymsHeader>() };
+
+    if header.magic != KSYMS_MAGIC {
+        return None;
+    }
+
+    let entries_len = header.count as usize * size_of::<KsymEntry>();
+    let names = area.get(header.names as usize..)?;
+
+    if size_of::<KsymsHeader>() + entries_len > header.names as usize {
+        return None;
+    }
+
+    // SAFETY: The entries lie between the header and the names, checked above.
+    let entries = unsafe {
+        slice::from_raw_parts(
+            area.as_ptr()
+                .add(size_of::<KsymsHeader>())
+                .cast::<KsymEntry>(),
+            header.count as usize,
+        )
+    };
+
+    Some((header, entries, names))
+}
+
+/// Finds the symbol containing `addr`: the closest one at or below it.
+pub fn lookup(addr: usize) -> Option<Symbol> {
+    let (header, entries, names) = table()?;
+    let offset = addr.checked_sub(header.base as usize)?;
+
+    let idx = entries
+        .partition_point(|entry| entry.offset as usize <= offset)
+        .checked_sub(1)?;
+
+    let entry = &entries[idx];
+    let name = CStr::from_bytes_until_nul(names.get(entry.name as usize..)?).ok()?;
+
+    Some(Symbol {
+        name: name.to_str().ok()?,
+        addr: header.base as usize + entry.offset as usize,
+    })
+}
@@ -1,8 +1,17 @@
+pub mod backtrace;
+pub mod cmdline;
 pub mod cpu_id;
+pub mod cpuidle;
 pub mod getcpu;
 pub mod hostname;
+pub mod kexec;
 pub mod kpipe;
+pub mod ksyms;
 pub mod power;
+pub mod pstore;
 pub mod rand;
+pub mod sysctl;
 pub mod sysinfo;
+pub mod syslog;
+pub mod trace;
 pub mod uname;
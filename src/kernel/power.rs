@@ -1,4 +1,4 @@
-use crate::{ArchImpl, arch::Arch, sched::syscall_ctx::ProcessCtx};
+use crate::{ArchImpl, arch::Arch, kernel::kexec::kernel_kexec, sched::syscall_ctx::ProcessCtx};
 use core::sync::atomic::AtomicBool;
 use libkernel::{
     error::{KernelError, Result},
@@ -28,7 +28,7 @@ pub async fn sys_reboot(
     const LINUX_REBOOT_CMD_CAD_OFF: u32 = 0x0000_0000;
     const LINUX_REBOOT_CMD_CAD_ON: u32 = 0x89ab_cdef;
     // const LINUX_REBOOT_CMD_HALT: u32 = 0xcdef_0123;
-    // const LINUX_REBOOT_CMD_KEXEC: u32 = 0x4558_4543;
+    const LINUX_REBOOT_CMD_KEXEC: u32 = 0x4558_4543;
     const LINUX_REBOOT_CMD_POWER_OFF: u32 = 0x4321_fedc;
     const LINUX_REBOOT_CMD_RESTART: u32 = 0x0123_4567;
     // const LINUX_REBOOT_CMD_RESTART2: u32 = 0xa1b2_c3d4;
@@ -47,6 +47,7 @@ pub async fn sys_reboot(
             ArchImpl::power_off()
         }
         LINUX_REBOOT_CMD_RESTART => ArchImpl::restart(),
+        LINUX_REBOOT_CMD_KEXEC => kernel_kexec(),
         LINUX_REBOOT_CMD_CAD_ON => {
             CAD_ENABLED.store(true, core::sync::atomic::Ordering::SeqCst);
             Ok(0)
@@ -0,0 +1,132 @@
+//! Crash records which survive a reboot.
+//!
+//! If the device tree reserves a `ramoops` region, the kernel log is saved to
+//! it on panic, in the format of one of Linux's ramoops records. RAM which
+//! isn't powered off over a warm reboot keeps the record for the next boot,
+//! which reads it back and exposes it in sysfs, at
+//! `fs/pstore/dmesg-ramoops-0`.
+
+use crate::{
+    console::kmsg, drivers::fdt_prober::get_fdt, memory::PageOffsetTranslator, sync::OnceLock,
+};
+use alloc::vec::Vec;
+use core::{mem::size_of, ptr::NonNull, slice};
+use libkernel::memory::address::PA;
+use log::info;
+
+/// The signature of a record, "DBGC".
+const PERSISTENT_RAM_SIG: u32 = 0x4347_4244;
+
+#[repr(C)]
+struct PersistentRamHeader {
+    sig: u32,
+    /// Where the oldest byte is, for records used as ring buffers. Always 0
+    /// for those written here.
+    start: u32,
+    size: u32,
+}
+
+struct Pstore {
+    header: NonNull<PersistentRamHeader>,
+    capacity: usize,
+}
+
+// SAFETY: The region is reserved for the pstore, and only written on panic.
+unsafe impl Send for Pstore {}
+unsafe impl Sync for Pstore {}
+
+impl Pstore {
+    fn data(&self) -> *mut u8 {
+        unsafe { self.header.as_ptr().add(1).cast() }
+    }
+}
+
+static PSTORE: OnceLock<Pstore> = OnceLock::new();
+
+/// The record left by the previous boot.
+static LAST_RECORD: OnceLock<Vec<u8>> = OnceLock::new();
+
+/// Finds the pstore region, taking the record left by the previous boot out of
+/// it.
+pub fn pstore_init() {
+    let fdt = get_fdt();
+
+    let Some(node) = fdt.reserved_memory().find(|node| {
+        node.compatible().is_some_and(|mut compats| {
+            compats.any(|compat| compat.is_ok_and(|compat| compat == "ramoops"))
+        })
+    }) else {
+        return;
+    };
+
+    let Some(region) = node.reg().into_iter().flatten().next() else {
+        return;
+    };
+
+    let Some(capacity) = region
+        .size
+        .and_then(|size| size.checked_sub(size_of::<PersistentRamHeader>()))
+    else {
+        return;
+    };
+
+    let header = PA::from_value(region.address as usize)
+        .to_va::<PageOffsetTranslator>()
+        .cast::<PersistentRamHeader>()
+        .as_ptr_mut();
+
+    let pstore = Pstore {
+        header: NonNull::new(header).unwrap(),
+        capacity,
+    };
+
+    // SAFETY: The region is reserved, so nothing else uses it.
+    unsafe {
+        let header = &mut *pstore.header.as_ptr();
+
+        if header.sig == PERSISTENT_RAM_SIG && (header.size as usize) <= capacity {
+            let record = slice::from_raw_parts(pstore.data(), header.size as usize);
+
+            info!(
+                "pstore: found a crash record from the previous boot ({} bytes)",
+                record.len()
+            );
+
+            let _ = LAST_RECORD.set(record.to_vec());
+        }
+
+        header.sig = 0;
+    }
+
+    info!(
+        "pstore: saving crash records to {} (0x{capacity:x} bytes)",
+        node.name
+    );
+
+    let _ = PSTORE.set(pstore);
+}
+
+/// Saves as much of the end of the kernel log as fits to the pstore, if there
+/// is one.
+pub fn write_crash_record() {
+    let Some(pstore) = PSTORE.get() else {
+        return;
+    };
+
+    // SAFETY: The region is reserved for the pstore.
+    unsafe {
+        let data = slice::from_raw_parts_mut(pstore.data(), pstore.capacity);
+        let len = kmsg::read_all(data);
+
+        pstore.header.write(PersistentRamHeader {
+            sig: PERSISTENT_RAM_SIG,
+            start: 0,
+            size: len as u32,
+        });
+    }
+}
+
+/// Returns the crash record left by the previous boot, if there is one.
+pub fn last_record() -> &'static [u8] {
+    LAST_RECORD.get().map(Vec::as_slice).unwrap_or_default()
+}
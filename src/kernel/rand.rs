@@ -1,9 +1,12 @@
-use core::sync::atomic::{AtomicUsize, Ordering};
+use core::{
+    hint::black_box,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
-use alloc::{sync::Arc, vec::Vec};
+use alloc::{sync::Arc, vec, vec::Vec};
 
 use crate::{
-    drivers::timer::uptime,
+    drivers::timer::{now, uptime},
     memory::uaccess::copy_to_user_slice,
     per_cpu_private,
     sync::{CondVar, OnceLock, SpinLock},
@@ -11,7 +14,10 @@ use crate::{
 use blake2::{Blake2s256, Digest};
 use chacha20::ChaCha20Rng;
 use libkernel::memory::address::TUA;
-use libkernel::{error::Result, sync::condvar::WakeupType};
+use libkernel::{
+    error::{KernelError, Result},
+    sync::condvar::WakeupType,
+};
 use rand::{Rng, SeedableRng};
 
 /// A hardware or software source of entropy that the pool can query.
@@ -24,6 +30,68 @@ pub trait EntropySource: Send + Sync {
 /// Number of bytes generated before a per-CPU RNG reseeds from the entropy pool.
 const RESEED_BYTES: usize = 1024 * 1024;
 
+/// How many times the sources are polled for a seed before waiting for
+/// entropy to arrive by other means. Sources such as [`JitterSource`] only
+/// credit a little entropy each time.
+const SEED_POLL_ROUNDS: usize = 8;
+
+/// Gathers entropy from the jitter in how long it takes the CPU to do a little
+/// memory-bound work, as measured by the system timer. Caches, the memory bus
+/// and the other CPUs all perturb the timing, which is what's harvested.
+///
+/// This is always available once the timer is, so the pool can be seeded on
+/// machines without a hardware RNG.
+struct JitterSource;
+
+impl JitterSource {
+    /// Timing samples folded into each byte of output.
+    const SAMPLES_PER_BYTE: usize = 8;
+}
+
+impl EntropySource for JitterSource {
+    fn get_entropy(&self, buf: &mut [u8]) -> (usize, usize) {
+        let Some(mut last) = now() else {
+            return (0, 0);
+        };
+
+        let mut scratch = [0u8; 512];
+        let mut last_delta = 0;
+        let mut varied = false;
+
+        for byte in buf.iter_mut() {
+            let mut acc = 0u8;
+
+            for _ in 0..Self::SAMPLES_PER_BYTE {
+                for (i, x) in scratch.iter_mut().enumerate() {
+                    *x = black_box(x.wrapping_add(acc ^ i as u8));
+                }
+
+                let Some(t) = now() else {
+                    return (0, 0);
+                };
+
+                let delta = t.ticks().wrapping_sub(last.ticks());
+                varied |= delta != last_delta;
+                last_delta = delta;
+                last = t;
+
+                acc = acc.rotate_left(3) ^ delta as u8;
+            }
+
+            *byte = acc;
+        }
+
+        // A timer too coarse to see any jitter yields nothing. Otherwise,
+        // credit one bit per byte, as only the low bits of each delta are at
+        // all unpredictable.
+        if varied {
+            (buf.len(), buf.len())
+        } else {
+            (buf.len(), 0)
+        }
+    }
+}
+
 pub struct EntropyPool {
     state: SpinLock<Blake2s256>,
     pool_waiters: CondVar<bool>,
@@ -37,7 +105,7 @@ impl EntropyPool {
             state: SpinLock::new(Blake2s256::default()),
             pool_waiters: CondVar::new(false),
             pool_bits: AtomicUsize::new(0),
-            sources: SpinLock::new(Vec::new()),
+            sources: SpinLock::new(vec![Arc::new(JitterSource) as Arc<dyn EntropySource>]),
         }
     }
 
@@ -81,10 +149,26 @@ impl EntropyPool {
         }
     }
 
+    fn is_seeded(&self) -> bool {
+        self.pool_bits.load(Ordering::Relaxed) >= 256
+    }
+
+    /// Poll the sources until the pool is seeded, giving up after
+    /// [`SEED_POLL_ROUNDS`] attempts.
+    fn poll_for_seed(&self) {
+        for _ in 0..SEED_POLL_ROUNDS {
+            if self.is_seeded() {
+                return;
+            }
+
+            self.poll_sources();
+        }
+    }
+
     /// Block until the pool has accumulated at least 256 bits of entropy, then
     /// return a 32-byte seed derived from the pool state.
     pub async fn extract_seed(&self) -> [u8; 32] {
-        self.poll_sources();
+        self.poll_for_seed();
 
         self.pool_waiters
             .wait_until(|s| if *s { Some(()) } else { None })
@@ -97,9 +181,9 @@ impl EntropyPool {
     /// Non-blocking seed extraction.  Returns `None` if the pool has not yet
     /// accumulated 256 bits of entropy.
     pub fn try_extract_seed(&self) -> Option<[u8; 32]> {
-        self.poll_sources();
+        self.poll_for_seed();
 
-        if self.pool_bits.load(Ordering::Relaxed) < 256 {
+        if !self.is_seeded() {
             return None;
         }
 
@@ -182,6 +266,18 @@ per_cpu_private! {
     static CPU_RNG: CpuRng = CpuRng::new;
 }
 
+/// Fill `buf` from this CPU's RNG, which must have been seeded, reseeding it
+/// first if it is due.
+fn fill_from_cpu_rng(buf: &mut [u8]) {
+    // Reseed from the entropy pool if we have generated enough bytes.
+    let needs_reseed = CPU_RNG.borrow().bytes_since_reseed >= RESEED_BYTES;
+    if needs_reseed && let Some(blake_seed) = entropy_pool().try_extract_seed() {
+        CPU_RNG.borrow_mut().reseed_with_blake(blake_seed);
+    }
+
+    CPU_RNG.borrow_mut().fill(buf);
+}
+
 /// Fill `buf` with cryptographically-strong random bytes.
 ///
 /// On the first invocation per CPU the call blocks until the global entropy
@@ -196,18 +292,63 @@ pub async fn fill_random_bytes(buf: &mut [u8]) {
         CPU_RNG.borrow_mut().apply_seed(seed);
     }
 
-    // Reseed from the entropy pool if we have generated enough bytes.
-    let needs_reseed = CPU_RNG.borrow().bytes_since_reseed >= RESEED_BYTES;
-    if needs_reseed && let Some(blake_seed) = entropy_pool().try_extract_seed() {
-        CPU_RNG.borrow_mut().reseed_with_blake(blake_seed);
+    fill_from_cpu_rng(buf);
+}
+
+/// Like [`fill_random_bytes`], but fails with [`KernelError::TryAgain`]
+/// rather than blocking if the entropy pool hasn't yet been seeded.
+pub fn try_fill_random_bytes(buf: &mut [u8]) -> Result<()> {
+    let seeded = CPU_RNG.borrow().seeded;
+
+    if !seeded {
+        let seed = entropy_pool()
+            .try_extract_seed()
+            .ok_or(KernelError::TryAgain)?;
+        CPU_RNG.borrow_mut().apply_seed(seed);
     }
 
-    CPU_RNG.borrow_mut().fill(buf);
+    fill_from_cpu_rng(buf);
+
+    Ok(())
+}
+
+/// Fill `buf` with random bytes without ever blocking.
+///
+/// Until the entropy pool has been seeded, the output is derived from
+/// whatever entropy it holds so far, so it may be predictable. This is what
+/// `/dev/urandom` and `GRND_INSECURE` provide.
+pub fn fill_random_bytes_insecure(buf: &mut [u8]) {
+    if try_fill_random_bytes(buf).is_ok() {
+        return;
+    }
+
+    // The CPU's RNG is left unseeded, so that it picks up a proper seed as
+    // soon as one is available.
+    let seed = entropy_pool().extract_seed_inner();
+    let mut rng = ChaCha20Rng::from_seed(seed);
+    rng.fill_bytes(buf);
 }
 
 const GETRANDOM_CHUNK: usize = 256;
 
-pub async fn sys_getrandom(ubuf: TUA<u8>, size: isize, _flags: u32) -> Result<usize> {
+bitflags::bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    struct GetRandomFlags: u32 {
+        const GRND_NONBLOCK = 0x0001;
+        const GRND_RANDOM = 0x0002;
+        const GRND_INSECURE = 0x0004;
+    }
+}
+
+pub async fn sys_getrandom(ubuf: TUA<u8>, size: isize, flags: u32) -> Result<usize> {
+    let flags = GetRandomFlags::from_bits(flags).ok_or(KernelError::InvalidValue)?;
+
+    // There's only the one pool, so `GRND_RANDOM` behaves like the default,
+    // but it still can't be combined with `GRND_INSECURE`.
+    if flags.contains(GetRandomFlags::GRND_RANDOM | GetRandomFlags::GRND_INSECURE) {
+        return Err(KernelError::InvalidValue);
+    }
+
     let total = size as usize;
     let mut buf = [0u8; GETRANDOM_CHUNK];
     let mut offset = 0;
@@ -216,7 +357,14 @@ pub async fn sys_getrandom(ubuf: TUA<u8>, size: isize, _flags: u32) -> Result<us
         let n = (total - offset).min(GETRANDOM_CHUNK);
         let chunk = &mut buf[..n];
 
-        fill_random_bytes(chunk).await;
+        if flags.contains(GetRandomFlags::GRND_INSECURE) {
+            fill_random_bytes_insecure(chunk);
+        } else if flags.contains(GetRandomFlags::GRND_NONBLOCK) {
+            // Once seeded, this can't fail, so only the first chunk can.
+            try_fill_random_bytes(chunk)?;
+        } else {
+            fill_random_bytes(chunk).await;
+        }
 
         copy_to_user_slice(chunk, ubuf.to_untyped().add_bytes(offset)).await?;
 
@@ -0,0 +1,244 @@
+//! Kernel parameters which can be changed at runtime, exposed under
+//! /proc/sys.
+//!
+//! Each [`Sysctl`] is named by a dotted path, e.g. `vm.overcommit_memory`,
+//! which is also its path under /proc/sys with the dots as slashes. The value
+//! itself stays with the subsystem that owns it: a parameter only has callbacks
+//! to read it and to tell the owner of a new one. Writes are checked against
+//! the parameter's type before the owner sees them.
+
+use crate::{
+    fs::open_file::{file_max, set_file_max},
+    kernel::hostname::HOST_NAME_MAX,
+    net::{RMEM_DEFAULT, RMEM_MAX, SOCK_MIN_BUF, SOMAXCONN, WMEM_DEFAULT, WMEM_MAX},
+    sched::current_work,
+};
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::sync::atomic::Ordering;
+use libkernel::{
+    error::{KernelError, Result},
+    memory::proc_vm::overcommit::{OvercommitPolicy, VM_COMMIT},
+};
+
+pub enum SysctlKind {
+    /// An integer within `min..=max`.
+    Int {
+        min: i64,
+        max: i64,
+        get: fn() -> i64,
+        set: fn(i64) -> Result<()>,
+    },
+    /// A string of at most `max_len` bytes.
+    String {
+        max_len: usize,
+        get: fn() -> String,
+        set: fn(&str) -> Result<()>,
+    },
+}
+
+pub struct Sysctl {
+    pub name: &'static str,
+    pub kind: SysctlKind,
+}
+
+impl Sysctl {
+    /// Formats the value, as read from its file.
+    pub fn read(&self) -> String {
+        match self.kind {
+            SysctlKind::Int { get, .. } => format!("{}\n", get()),
+            SysctlKind::String { get, .. } => format!("{}\n", get()),
+        }
+    }
+
+    /// Parses a new value, as written to its file, and hands it to the
+    /// parameter's owner.
+    pub fn write(&self, buf: &[u8]) -> Result<()> {
+        let value = str::from_utf8(buf).map_err(|_| KernelError::InvalidValue)?;
+        let value = value.strip_suffix('\n').unwrap_or(value);
+
+        match self.kind {
+            SysctlKind::Int { min, max, set, .. } => {
+                let value = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| KernelError::InvalidValue)?;
+
+                if !(min..=max).contains(&value) {
+                    return Err(KernelError::InvalidValue);
+                }
+
+                set(value)
+            }
+            SysctlKind::String { max_len, set, .. } => {
+                if value.len() > max_len {
+                    return Err(KernelError::InvalidValue);
+                }
+
+                set(value)
+            }
+        }
+    }
+}
+
+static SYSCTLS: &[Sysctl] = &[
+    Sysctl {
+        name: "fs.file-max",
+        kind: SysctlKind::Int {
+            min: 1,
+            max: i64::MAX,
+            get: || file_max() as i64,
+            set: |max| {
+                set_file_max(max as usize);
+                Ok(())
+            },
+        },
+    },
+    Sysctl {
+        name: "kernel.domainname",
+        kind: SysctlKind::String {
+            max_len: HOST_NAME_MAX,
+            get: || current_work().uts_ns.domainname.lock_save_irq().clone(),
+            set: |name| {
+                *current_work().uts_ns.domainname.lock_save_irq() = name.to_string();
+                Ok(())
+            },
+        },
+    },
+    Sysctl {
+        name: "kernel.hostname",
+        kind: SysctlKind::String {
+            max_len: HOST_NAME_MAX,
+            get: || current_work().uts_ns.hostname.lock_save_irq().clone(),
+            set: |name| {
+                *current_work().uts_ns.hostname.lock_save_irq() = name.to_string();
+                Ok(())
+            },
+        },
+    },
+    Sysctl {
+        name: "net.core.rmem_default",
+        kind: SysctlKind::Int {
+            min: SOCK_MIN_BUF as i64,
+            max: i32::MAX as i64,
+            get: || RMEM_DEFAULT.load(Ordering::Relaxed) as i64,
+            set: |size| {
+                RMEM_DEFAULT.store(size as usize, Ordering::Relaxed);
+                Ok(())
+            },
+        },
+    },
+    Sysctl {
+        name: "net.core.rmem_max",
+        kind: SysctlKind::Int {
+            min: SOCK_MIN_BUF as i64,
+            max: i32::MAX as i64,
+            get: || RMEM_MAX.load(Ordering::Relaxed) as i64,
+            set: |size| {
+                RMEM_MAX.store(size as usize, Ordering::Relaxed);
+                Ok(())
+            },
+        },
+    },
+    Sysctl {
+        name: "net.core.somaxconn",
+        kind: SysctlKind::Int {
+            min: 1,
+            max: 4096,
+            get: || SOMAXCONN.load(Ordering::Relaxed) as i64,
+            set: |max| {
+                SOMAXCONN.store(max as usize, Ordering::Relaxed);
+                Ok(())
+            },
+        },
+    },
+    Sysctl {
+        name: "net.core.wmem_default",
+        kind: SysctlKind::Int {
+            min: SOCK_MIN_BUF as i64,
+            max: i32::MAX as i64,
+            get: || WMEM_DEFAULT.load(Ordering::Relaxed) as i64,
+            set: |size| {
+                WMEM_DEFAULT.store(size as usize, Ordering::Relaxed);
+                Ok(())
+            },
+        },
+    },
+    Sysctl {
+        name: "net.core.wmem_max",
+        kind: SysctlKind::Int {
+            min: SOCK_MIN_BUF as i64,
+            max: i32::MAX as i64,
+            get: || WMEM_MAX.load(Ordering::Relaxed) as i64,
+            set: |size| {
+                WMEM_MAX.store(size as usize, Ordering::Relaxed);
+                Ok(())
+            },
+        },
+    },
+    Sysctl {
+        name: "vm.overcommit_memory",
+        kind: SysctlKind::Int {
+            min: 0,
+            max: 2,
+            get: || VM_COMMIT.policy() as i64,
+            set: |policy| {
+                VM_COMMIT.set_policy(OvercommitPolicy::try_from(policy as u8)?);
+                Ok(())
+            },
+        },
+    },
+    Sysctl {
+        name: "vm.overcommit_ratio",
+        kind: SysctlKind::Int {
+            min: 0,
+            max: i64::MAX,
+            get: || VM_COMMIT.ratio() as i64,
+            set: |ratio| {
+                VM_COMMIT.set_ratio(ratio as usize);
+                Ok(())
+            },
+        },
+    },
+];
+
+/// Finds the parameter named `name`.
+pub fn find_sysctl(name: &str) -> Option<&'static Sysctl> {
+    SYSCTLS.iter().find(|sysctl| sysctl.name == name)
+}
+
+/// Returns the names of the entries directly within the directory `dir`,
+/// given as a dotted path, or as `""` for the top level. Each comes with
+/// whether it's a directory itself. Returns `None` if `dir` isn't a directory.
+pub fn sysctl_dir(dir: &str) -> Option<Vec<(&'static str, bool)>> {
+    let mut entries: Vec<(&'static str, bool)> = Vec::new();
+
+    for sysctl in SYSCTLS {
+        let rest = if dir.is_empty() {
+            sysctl.name
+        } else {
+            match sysctl
+                .name
+                .strip_prefix(dir)
+                .and_then(|n| n.strip_prefix('.'))
+            {
+                Some(rest) => rest,
+                None => continue,
+            }
+        };
+
+        let entry = match rest.split_once('.') {
+            Some((subdir, _)) => (subdir, true),
+            None => (rest, false),
+        };
+
+        if !entries.contains(&entry) {
+            entries.push(entry);
+        }
+    }
+
+    (dir.is_empty() || !entries.is_empty()).then_some(entries)
+}
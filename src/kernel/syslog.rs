@@ -0,0 +1,83 @@
+use crate::{
+    console::kmsg::{self, LOG_BUF_LEN},
+    memory::uaccess::copy_to_user_slice,
+    sched::syscall_ctx::ProcessCtx,
+};
+use alloc::vec;
+use libkernel::{
+    error::{KernelError, Result},
+    memory::address::UA,
+    proc::caps::CapabilitiesFlags,
+};
+
+const SYSLOG_ACTION_CLOSE: i32 = 0;
+const SYSLOG_ACTION_OPEN: i32 = 1;
+const SYSLOG_ACTION_READ: i32 = 2;
+const SYSLOG_ACTION_READ_ALL: i32 = 3;
+const SYSLOG_ACTION_READ_CLEAR: i32 = 4;
+const SYSLOG_ACTION_CLEAR: i32 = 5;
+const SYSLOG_ACTION_CONSOLE_OFF: i32 = 6;
+const SYSLOG_ACTION_CONSOLE_ON: i32 = 7;
+const SYSLOG_ACTION_CONSOLE_LEVEL: i32 = 8;
+const SYSLOG_ACTION_SIZE_UNREAD: i32 = 9;
+const SYSLOG_ACTION_SIZE_BUFFER: i32 = 10;
+
+pub async fn sys_syslog(ctx: &ProcessCtx, action: i32, buf: UA, len: i32) -> Result<usize> {
+    // Anyone may read the log without consuming it, as with Linux's
+    // `kernel.dmesg_restrict = 0`.
+    if !matches!(action, SYSLOG_ACTION_READ_ALL | SYSLOG_ACTION_SIZE_BUFFER) {
+        ctx.shared()
+            .creds
+            .lock_save_irq()
+            .caps()
+            .check_capable(CapabilitiesFlags::CAP_SYSLOG)?;
+    }
+
+    match action {
+        SYSLOG_ACTION_CLOSE | SYSLOG_ACTION_OPEN => Ok(0),
+        SYSLOG_ACTION_READ | SYSLOG_ACTION_READ_ALL | SYSLOG_ACTION_READ_CLEAR => {
+            if buf.is_null() || len < 0 {
+                return Err(KernelError::InvalidValue);
+            }
+
+            let mut kbuf = vec![0; (len as usize).min(LOG_BUF_LEN)];
+
+            let count = if action == SYSLOG_ACTION_READ {
+                kmsg::read(&mut kbuf).await?
+            } else {
+                kmsg::read_all(&mut kbuf)
+            };
+
+            if action == SYSLOG_ACTION_READ_CLEAR {
+                kmsg::clear();
+            }
+
+            copy_to_user_slice(&kbuf[..count], buf).await?;
+
+            Ok(count)
+        }
+        SYSLOG_ACTION_CLEAR => {
+            kmsg::clear();
+            Ok(0)
+        }
+        SYSLOG_ACTION_CONSOLE_OFF => {
+            kmsg::console_off();
+            Ok(0)
+        }
+        SYSLOG_ACTION_CONSOLE_ON => {
+            kmsg::console_on();
+            Ok(0)
+        }
+        SYSLOG_ACTION_CONSOLE_LEVEL => {
+            if !(1..=8).contains(&len) {
+                return Err(KernelError::InvalidValue);
+            }
+
+            kmsg::set_console_loglevel(len as u8);
+            Ok(0)
+        }
+        SYSLOG_ACTION_SIZE_UNREAD => Ok(kmsg::unread()),
+        SYSLOG_ACTION_SIZE_BUFFER => Ok(LOG_BUF_LEN),
+        _ => Err(KernelError::InvalidValue),
+    }
+}
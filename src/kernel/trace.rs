@@ -0,0 +1,250 @@
+//! Static tracepoints.
+//!
+//! Each [`TraceEvent`] is recorded from a fixed point in the kernel, but only
+//! once it's been enabled, so that a disabled tracepoint costs no more than a
+//! load. Records go into a ring buffer for the CPU they were made on, dropping
+//! the oldest once it's full. They're read through the `tracing` directory in
+//! sysfs.
+
+use crate::{
+    arch::ArchImpl, drivers::timer::uptime, per_cpu_shared, sched::try_current_work, sync::SpinLock,
+};
+use alloc::{boxed::Box, collections::VecDeque};
+use async_trait::async_trait;
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use libkernel::{
+    CpuOps,
+    error::{Result, syscall_error::kern_err_to_syscall},
+    fs::BlockDevice,
+};
+
+/// How many records each CPU's buffer holds.
+pub const TRACE_BUF_RECORDS: usize = 4096;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
+pub enum TraceEvent {
+    /// `[nr, arg1, arg2, arg3]`
+    SyscallEnter,
+    /// `[nr, ret]`
+    SyscallExit,
+    /// `[prev tid, next tid]`, where the idle task is tid 0.
+    SchedSwitch,
+    /// `[address, access kind]`, where the access kind is 0 for a read, 1 for
+    /// a write and 2 for an instruction fetch.
+    PageFault,
+    /// `[device, block, length, write]`
+    BlockIssue,
+    /// `[device, block, length, errno]`
+    BlockComplete,
+}
+
+impl TraceEvent {
+    pub const ALL: [TraceEvent; 6] = [
+        TraceEvent::SyscallEnter,
+        TraceEvent::SyscallExit,
+        TraceEvent::SchedSwitch,
+        TraceEvent::PageFault,
+        TraceEvent::BlockIssue,
+        TraceEvent::BlockComplete,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TraceEvent::SyscallEnter => "sys_enter",
+            TraceEvent::SyscallExit => "sys_exit",
+            TraceEvent::SchedSwitch => "sched_switch",
+            TraceEvent::PageFault => "page_fault",
+            TraceEvent::BlockIssue => "block_rq_issue",
+            TraceEvent::BlockComplete => "block_rq_complete",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|event| event.name() == name)
+    }
+
+    fn from_id(id: u16) -> Option<Self> {
+        Self::ALL.get(id as usize).copied()
+    }
+
+    pub fn is_enabled(self) -> bool {
+        ENABLED_EVENTS.load(Ordering::Relaxed) & (1 << self as u32) != 0
+    }
+
+    pub fn set_enabled(self, enabled: bool) {
+        if enabled {
+            // Allocate the buffers now, rather than from wherever the first
+            // record is made.
+            for buf in TRACE_BUFS.iter() {
+                buf.lock_save_irq().records.reserve_exact(TRACE_BUF_RECORDS);
+            }
+
+            ENABLED_EVENTS.fetch_or(1 << self as u32, Ordering::Relaxed);
+        } else {
+            ENABLED_EVENTS.fetch_and(!(1 << self as u32), Ordering::Relaxed);
+        }
+    }
+}
+
+static ENABLED_EVENTS: AtomicU32 = AtomicU32::new(0);
+
+/// A tracepoint hit, in the format read from `trace_pipe_raw`.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct TraceRecord {
+    /// Nanoseconds since boot.
+    pub timestamp: u64,
+    pub event: u16,
+    pub cpu: u16,
+    /// The task running when the record was made, or 0 if it was an idle
+    /// task or couldn't be told.
+    pub tid: u32,
+    /// The event's arguments, as documented on [`TraceEvent`].
+    pub args: [u64; 4],
+}
+
+impl TraceRecord {
+    pub fn event(&self) -> Option<TraceEvent> {
+        TraceEvent::from_id(self.event)
+    }
+}
+
+pub struct TraceBuf {
+    records: VecDeque<TraceRecord>,
+    /// Records dropped to make room for newer ones.
+    overruns: u64,
+}
+
+per_cpu_shared! {
+    pub static TRACE_BUFS: SpinLock<TraceBuf> = || SpinLock::new(TraceBuf {
+        records: VecDeque::new(),
+        overruns: 0,
+    });
+}
+
+/// Records `event`, if it's enabled.
+#[inline]
+pub fn trace_event(event: TraceEvent, args: [u64; 4]) {
+    if event.is_enabled() {
+        record(event, args);
+    }
+}
+
+fn record(event: TraceEvent, args: [u64; 4]) {
+    let record = TraceRecord {
+        timestamp: uptime().as_nanos() as u64,
+        event: event as u16,
+        cpu: ArchImpl::id() as u16,
+        tid: try_current_work()
+            .filter(|work| !work.task.is_idle_task())
+            .map_or(0, |work| work.tid.value()),
+        args,
+    };
+
+    let mut buf = TRACE_BUFS.get().lock_save_irq();
+
+    if buf.records.len() == TRACE_BUF_RECORDS {
+        buf.records.pop_front();
+        buf.overruns += 1;
+    }
+
+    buf.records.push_back(record);
+}
+
+/// Removes the oldest record across all CPUs.
+pub fn pop_record() -> Option<TraceRecord> {
+    let (cpu, _) = TRACE_BUFS
+        .iter()
+        .enumerate()
+        .filter_map(|(cpu, buf)| Some((cpu, buf.lock_save_irq().records.front()?.timestamp)))
+        .min_by_key(|&(_, timestamp)| timestamp)?;
+
+    TRACE_BUFS
+        .get_by_cpu(cpu)
+        .lock_save_irq()
+        .records
+        .pop_front()
+}
+
+/// Returns how many records have been dropped to make room for newer ones,
+/// across all CPUs.
+pub fn overruns() -> u64 {
+    TRACE_BUFS
+        .iter()
+        .map(|buf| buf.lock_save_irq().overruns)
+        .sum()
+}
+
+/// Calls `f` on each CPU's records, oldest first, without removing them.
+pub fn for_each_record(mut f: impl FnMut(&TraceRecord)) {
+    for buf in TRACE_BUFS.iter() {
+        buf.lock_save_irq().records.iter().for_each(&mut f);
+    }
+}
+
+static NEXT_BLOCK_DEVICE: AtomicU64 = AtomicU64::new(0);
+
+/// Records the I/O made to a block device.
+pub struct TracedBlockDevice {
+    inner: Box<dyn BlockDevice>,
+    /// Tells the device apart from others in records.
+    id: u64,
+}
+
+impl TracedBlockDevice {
+    pub fn new(inner: Box<dyn BlockDevice>) -> Self {
+        Self {
+            inner,
+            id: NEXT_BLOCK_DEVICE.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    fn complete(&self, block_id: u64, len: usize, res: &Result<()>) {
+        let errno = match res {
+            Ok(()) => 0,
+            Err(e) => kern_err_to_syscall(e.clone()).unsigned_abs() as u64,
+        };
+
+        trace_event(
+            TraceEvent::BlockComplete,
+            [self.id, block_id, len as u64, errno],
+        );
+    }
+}
+
+#[async_trait]
+impl BlockDevice for TracedBlockDevice {
+    async fn read(&self, block_id: u64, buf: &mut [u8]) -> Result<()> {
+        let len = buf.len();
+
+        trace_event(TraceEvent::BlockIssue, [self.id, block_id, len as u64, 0]);
+        let res = self.inner.read(block_id, buf).await;
+        self.complete(block_id, len, &res);
+
+        res
+    }
+
+    async fn write(&self, block_id: u64, buf: &[u8]) -> Result<()> {
+        trace_event(
+            TraceEvent::BlockIssue,
+            [self.id, block_id, buf.len() as u64, 1],
+        );
+        let res = self.inner.write(block_id, buf).await;
+        self.complete(block_id, buf.len(), &res);
+
+        res
+    }
+
+    fn block_size(&self) -> usize {
+        self.inner.block_size()
+    }
+
+    fn num_blocks(&self) -> u64 {
+        self.inner.num_blocks()
+    }
+
+    async fn sync(&self) -> Result<()> {
+        self.inner.sync().await
+    }
+}
@@ -1,7 +1,8 @@
-use crate::kernel::hostname::hostname;
+use crate::kernel::hostname::UtsNamespace;
 use crate::{
     arch::{Arch, ArchImpl},
     memory::uaccess::{UserCopyable, copy_to_user},
+    sched::syscall_ctx::ProcessCtx,
 };
 use alloc::ffi::CString;
 use core::ffi::CStr;
@@ -13,7 +14,8 @@ const SYSNAME: &CStr = c"Moss";
 
 /// Systemd uses the release field to determine compatibility.
 /// It's also necessary for libc programs; otherwise they exit with an error Kernel too old.
-const RELEASE: &CStr = c"4.2.3";
+/// Those only look at the leading version numbers, so moss's own version follows them.
+const RELEASE: &str = concat!("4.2.3-moss-", env!("CARGO_PKG_VERSION"));
 
 ///  POSIX specifies the order when using -a (equivalent to -snrvm):
 ///   1. sysname (-s) - OS name
@@ -21,17 +23,20 @@ const RELEASE: &CStr = c"4.2.3";
 ///   3. release (-r) - OS release
 ///   4. version (-v) - OS version
 ///   5. machine (-m) - hardware type
+///
+/// The domain name follows, as a GNU extension.
 #[repr(C)]
 #[derive(Clone, Copy)]
-pub struct OldUtsname {
+pub struct Utsname {
     sysname: [c_char; 65],
     nodename: [c_char; 65],
     release: [c_char; 65],
     version: [c_char; 65],
     machine: [c_char; 65],
+    domainname: [c_char; 65],
 }
 
-impl Default for OldUtsname {
+impl Default for Utsname {
     fn default() -> Self {
         Self {
             sysname: [0; 65],
@@ -39,11 +44,12 @@ impl Default for OldUtsname {
             release: [0; 65],
             version: [0; 65],
             machine: [0; 65],
+            domainname: [0; 65],
         }
     }
 }
 
-unsafe impl UserCopyable for OldUtsname {}
+unsafe impl UserCopyable for Utsname {}
 
 fn copy_str_to_c_char_arr(dest: &mut [c_char], src: &[u8]) {
     let len = core::cmp::min(dest.len(), src.len());
@@ -58,17 +64,18 @@ fn copy_str_to_c_char_arr(dest: &mut [c_char], src: &[u8]) {
     // The rest of `dest` will remain zeroed from the initial `mem::zeroed`.
 }
 
-/// Build an `OldUtsname` struct with the current system information, without involving the
-/// kernel. This makes it easier to test.
-fn build_utsname() -> OldUtsname {
-    let mut uts = OldUtsname::default();
+/// Build a `Utsname` struct with the system information as seen from the UTS namespace `ns`,
+/// without involving the kernel. This makes it easier to test.
+fn build_utsname(ns: &UtsNamespace) -> Utsname {
+    let mut uts = Utsname::default();
 
     copy_str_to_c_char_arr(&mut uts.sysname, SYSNAME.to_bytes_with_nul());
 
-    let nodename = CString::from_str(&hostname().lock_save_irq()).unwrap();
+    let nodename = CString::from_str(&ns.hostname.lock_save_irq()).unwrap();
     copy_str_to_c_char_arr(&mut uts.nodename, nodename.as_c_str().to_bytes_with_nul());
 
-    copy_str_to_c_char_arr(&mut uts.release, RELEASE.to_bytes_with_nul());
+    let release = CString::from_str(RELEASE).unwrap();
+    copy_str_to_c_char_arr(&mut uts.release, release.as_c_str().to_bytes_with_nul());
 
     let version = CString::from_str(env!("MOSS_VERSION")).unwrap();
     copy_str_to_c_char_arr(&mut uts.version, version.as_c_str().to_bytes_with_nul());
@@ -77,25 +84,34 @@ fn build_utsname() -> OldUtsname {
     let machine = machine.to_bytes_with_nul();
     copy_str_to_c_char_arr(&mut uts.machine, machine);
 
+    let domainname = CString::from_str(&ns.domainname.lock_save_irq()).unwrap();
+    copy_str_to_c_char_arr(
+        &mut uts.domainname,
+        domainname.as_c_str().to_bytes_with_nul(),
+    );
+
     uts
 }
 
 /// Implement the uname syscall, returning 0 for success
-pub async fn sys_uname(uts_ptr: TUA<OldUtsname>) -> Result<usize> {
-    let uts = build_utsname();
+pub async fn sys_uname(ctx: &ProcessCtx, uts_ptr: TUA<Utsname>) -> Result<usize> {
+    let uts = build_utsname(&ctx.shared().uts_ns);
     copy_to_user(uts_ptr, uts).await?;
     Ok(0)
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::kernel::uname::{SYSNAME, build_utsname};
+    use crate::kernel::{
+        hostname::init_uts_ns,
+        uname::{SYSNAME, build_utsname},
+    };
     use core::ffi::CStr;
     use moss_macros::ktest;
 
     #[ktest]
     fn sysname_correct() {
-        let uts = build_utsname();
+        let uts = build_utsname(&init_uts_ns());
         let sysname_cstr = unsafe { CStr::from_ptr(uts.sysname.as_ptr()) };
         assert_eq!(sysname_cstr, SYSNAME);
     }
@@ -146,7 +162,7 @@ mod tests {
     // Test that the version string is of the format "#1 Moss SMP Tue Feb 20 12:34:56 UTC 2024"
     #[ktest]
     fn version_format_smp() {
-        let uts = build_utsname();
+        let uts = build_utsname(&init_uts_ns());
         let version_cstr = unsafe { CStr::from_ptr(uts.version.as_ptr()) };
         let version = version_cstr.to_str().unwrap();
 
@@ -9,17 +9,19 @@
 #![reexport_test_harness_main = "test_main"]
 #![test_runner(crate::testing::test_runner)]
 
-use alloc::{
-    boxed::Box,
-    string::{String, ToString},
-    vec,
-    vec::Vec,
-};
+use alloc::{boxed::Box, string::ToString, sync::Arc, vec};
 use arch::{Arch, ArchImpl};
-use core::panic::PanicInfo;
-use drivers::{fdt_prober::get_fdt, fs::register_fs_drivers};
+use core::{
+    panic::PanicInfo,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use drivers::{
+    blkdev::register_block_device,
+    fdt_prober::{get_fdt, initrd_region},
+    fs::register_fs_drivers,
+};
 use fs::VFS;
-use getargs::{Opt, Options};
+use kernel::{backtrace::dump_backtrace, cmdline::params};
 use libkernel::{
     CpuOps,
     fs::{
@@ -27,16 +29,17 @@ use libkernel::{
         pathbuf::PathBuf,
     },
     memory::{
-        address::{PA, VA},
-        proc_vm::address_space::VirtualMemory,
-        region::PhysMemoryRegion,
+        address::VA,
+        proc_vm::{address_space::VirtualMemory, overcommit::VM_COMMIT},
     },
 };
-use log::{error, warn};
+use log::error;
 use process::ctx::UserCtx;
 use sched::{
-    sched_init, spawn_kernel_work, syscall_ctx::ProcessCtx, uspc_ret::dispatch_userspace_task,
+    sched_init, spawn_kernel_work, syscall_ctx::ProcessCtx, try_current_work,
+    uspc_ret::dispatch_userspace_task,
 };
+use sync::SpinLock;
 
 extern crate alloc;
 extern crate moss_macros;
@@ -56,10 +59,24 @@ mod sync;
 #[cfg(test)]
 pub mod testing;
 
+/// Set by the first panic. A panic while reporting it, or on another CPU at the
+/// same time, would only garble the report.
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+/// The end of the kernel log from just before the panic.
+static PANIC_LOG_TAIL: SpinLock<[u8; 2048]> = SpinLock::new([0; 2048]);
+
 #[panic_handler]
 fn on_panic(info: &PanicInfo) -> ! {
     ArchImpl::disable_interrupts();
 
+    if PANICKING.swap(true, Ordering::SeqCst) {
+        ArchImpl::halt();
+    }
+
+    let mut log_tail = PANIC_LOG_TAIL.lock_save_irq();
+    let log_tail_len = console::kmsg::read_all(&mut *log_tail);
+
     let panic_msg = info.message();
 
     if let Some(location) = info.location() {
@@ -74,59 +91,80 @@ fn on_panic(info: &PanicInfo) -> ! {
         error!("Kernel panicked at unknown location: {panic_msg}");
     }
 
+    match try_current_work() {
+        Some(work) if work.task.is_idle_task() => {
+            error!("CPU {}, in the idle task", ArchImpl::id());
+        }
+        Some(work) => error!(
+            "CPU {}, in task {} ({})",
+            ArchImpl::id(),
+            work.tid.value(),
+            work.comm.lock_save_irq().as_str()
+        ),
+        None => error!("CPU {}, in an unknown task", ArchImpl::id()),
+    }
+
+    error!("Backtrace:");
+    dump_backtrace();
+
+    console::print_held_back(&log_tail[..log_tail_len]);
+    kernel::pstore::write_crash_record();
+
     ArchImpl::power_off();
 }
 
-async fn launch_init(mut ctx: ProcessCtx, mut opts: KOptions) {
-    let init = opts
+async fn launch_init(mut ctx: ProcessCtx) {
+    let params = params();
+
+    let init = params
         .init
+        .as_ref()
         .unwrap_or_else(|| panic!("No init specified in kernel command line"));
 
-    let dt = get_fdt();
-
-    let initrd_block_dev: Option<Box<dyn BlockDevice>> = if let Some(chosen) =
-        dt.find_nodes("/chosen").next()
-        && let Some(start_addr) = chosen
-            .find_property("linux,initrd-start")
-            .map(|prop| prop.u64())
-        && let Some(end_addr) = chosen
-            .find_property("linux,initrd-end")
-            .map(|prop| prop.u64())
+    if let Some(root) = params.root.as_deref()
+        && root != "/dev/ram0"
     {
-        let region = PhysMemoryRegion::from_start_end_address(
-            PA::from_value(start_addr as _),
-            PA::from_value(end_addr as _),
-        );
+        panic!("Unsupported root device {root}, only the initrd (/dev/ram0) can be mounted");
+    }
 
-        Some(Box::new(
+    let initrd_block_dev = initrd_region(&get_fdt()).map(|region| -> Arc<dyn BlockDevice> {
+        Arc::new(
             RamdiskBlkDev::new(
                 region,
                 VA::from_value(0xffff_9800_0000_0000),
                 &mut *ArchImpl::kern_address_space().lock_save_irq(),
             )
             .unwrap(),
-        ))
-    } else {
-        None
-    };
+        )
+    });
 
-    // Set time to rtc time if possible
-    if let Some(rtc) = drivers::rtc::get_rtc()
-        && let Some(time) = rtc.time()
-    {
-        clock::realtime::set_date(time);
-    }
+    // Set time to rtc time if possible, and keep it in step.
+    drivers::rtc::rtc_clock_init();
+
+    // Keep the network stack running in the background.
+    net::net_init();
 
-    let root_fs = opts
-        .root_fs
+    let root_fs = params
+        .rootfstype
+        .as_ref()
         .unwrap_or_else(|| panic!("No root FS driver specified in kernel command line"));
 
-    VFS.mount_root(&root_fs, initrd_block_dev)
-        .await
-        .unwrap_or_else(|e| panic!("Failed to mount root FS: {e}"));
+    // The initrd shows up in /dev as well as being mounted.
+    if let Some(dev) = &initrd_block_dev {
+        register_block_device("ram0", dev.clone())
+            .await
+            .unwrap_or_else(|e| panic!("Failed to register the initrd: {e}"));
+    }
+
+    VFS.mount_root(
+        root_fs,
+        initrd_block_dev.map(|dev| Box::new(dev) as Box<dyn BlockDevice>),
+    )
+    .await
+    .unwrap_or_else(|e| panic!("Failed to mount root FS: {e}"));
 
     // Process all automounts.
-    for (path, fs) in opts.automounts.iter() {
+    for (path, fs) in params.automounts.iter() {
         let mount_point = VFS
             .resolve_path_absolute(path, VFS.root_inode())
             .await
@@ -138,7 +176,7 @@ async fn launch_init(mut ctx: ProcessCtx, mut opts: KOptions) {
     }
 
     let inode = VFS
-        .resolve_path_absolute(&init, VFS.root_inode())
+        .resolve_path_absolute(init, VFS.root_inode())
         .await
         .expect("Unable to find init");
 
@@ -185,59 +223,35 @@ async fn launch_init(mut ctx: ProcessCtx, mut opts: KOptions) {
 
     let mut init_args = vec![init.as_str().to_string()];
 
-    init_args.append(&mut opts.init_args);
+    init_args.extend(params.init_args.iter().cloned());
 
     process::exec::kernel_exec(&mut ctx, init.as_path(), inode, init_args, vec![])
         .await
         .expect("Could not launch init process");
 }
 
-struct KOptions {
-    init: Option<PathBuf>,
-    root_fs: Option<String>,
-    automounts: Vec<(PathBuf, String)>,
-    init_args: Vec<String>,
-}
-
-fn parse_args(args: &str) -> KOptions {
-    let mut kopts = KOptions {
-        init: None,
-        root_fs: None,
-        automounts: Vec::new(),
-        init_args: Vec::new(),
-    };
-
-    let mut opts = Options::new(args.split(" "));
-
-    loop {
-        match opts.next_opt() {
-            Ok(Some(arg)) => match arg {
-                Opt::Long("init") => kopts.init = Some(PathBuf::from(opts.value().unwrap())),
-                Opt::Long("init-arg") => kopts.init_args.push(opts.value().unwrap().to_string()),
-                Opt::Long("rootfs") => kopts.root_fs = Some(opts.value().unwrap().to_string()),
-                Opt::Long("automount") => {
-                    let string = opts.value().unwrap();
-                    let mut split = string.split(",");
-                    let path = split.next().unwrap();
-                    let fs = split.next().unwrap();
-
-                    kopts.automounts.push((PathBuf::from(path), fs.to_string()));
-                }
-                Opt::Long(x) => warn!("Unknown option {x}"),
-                Opt::Short(x) => warn!("Unknown option {x}"),
-            },
-            Ok(None) => return kopts,
-            Err(e) => error!("Could not parse option: {e}, ignoring."),
-        }
-    }
-}
-
-pub fn kmain(args: String, ctx_frame: *mut UserCtx) {
+pub fn kmain(ctx_frame: *mut UserCtx) {
     sched_init();
 
+    #[cfg(feature = "lockdep")]
+    sync::lockdep::init();
+
     register_fs_drivers();
+    kernel::pstore::pstore_init();
 
-    let kopts = parse_args(&args);
+    let params = params();
+
+    if let Some(policy) = params.overcommit_memory {
+        VM_COMMIT.set_policy(policy);
+    }
+
+    if let Some(ratio) = params.overcommit_ratio {
+        VM_COMMIT.set_ratio(ratio);
+    }
+
+    if let Some(level) = params.loglevel {
+        console::kmsg::set_console_loglevel(level);
+    }
 
     {
         // SAFETY: kmain is called prior to init being launched. Thefore, we
@@ -245,7 +259,7 @@ pub fn kmain(args: String, ctx_frame: *mut UserCtx) {
         let mut ctx = unsafe { ProcessCtx::from_current() };
         let ctx2 = unsafe { ctx.clone() };
 
-        spawn_kernel_work(&mut ctx, launch_init(ctx2, kopts));
+        spawn_kernel_work(&mut ctx, launch_init(ctx2));
     }
 
     dispatch_userspace_task(ctx_frame);
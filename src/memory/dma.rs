@@ -0,0 +1,318 @@
+//! DMA mapping API.
+//!
+//! Drivers should use this module rather than translating addresses
+//! themselves. Two kinds of mapping are provided:
+//!
+//! - Coherent allocations ([`dma_alloc_coherent`]), which are shared between
+//!   the CPU and a device for their whole lifetime, e.g. descriptor rings.
+//! - Streaming mappings ([`DmaDevice::map_single`]), which hand an existing
+//!   kernel buffer to a device for a single transfer. The buffer belongs to
+//!   the device until [`DmaDevice::unmap_single`] hands it back to the CPU.
+//!
+//! Mappings are made on behalf of a [`DmaDevice`]. If the device sits behind
+//! an IOMMU, buffers are mapped into its translation domain and the device
+//! only ever sees I/O virtual addresses. Otherwise (and for the free
+//! functions in this module) DMA addresses are identity mapped to physical
+//! addresses.
+//!
+//! The architecture is responsible for any cache maintenance required by each
+//! transfer.
+
+use super::{PAGE_ALLOC, PageOffsetTranslator};
+use crate::{
+    arch::{Arch, ArchImpl},
+    drivers::iommu::IommuDomain,
+};
+use alloc::sync::Arc;
+use core::slice;
+use libkernel::{
+    error::{KernelError, Result},
+    memory::{
+        PAGE_SIZE,
+        address::VA,
+        allocators::phys::PageAllocation,
+        region::{PhysMemoryRegion, VirtMemoryRegion},
+    },
+};
+
+/// An address as seen by a device performing DMA.
+pub type DmaAddr = usize;
+
+/// The direction of a DMA transfer, relative to the CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DmaDirection {
+    /// The device reads from the buffer.
+    ToDevice,
+    /// The device writes to the buffer.
+    FromDevice,
+    /// The device may both read from and write to the buffer.
+    Bidirectional,
+}
+
+/// A device capable of DMA, and the translation its transfers go through.
+#[derive(Clone)]
+pub struct DmaDevice {
+    /// The device's IOMMU domain, or `None` if it's identity mapped.
+    domain: Option<Arc<dyn IommuDomain>>,
+}
+
+impl DmaDevice {
+    /// A device that isn't behind an IOMMU; DMA addresses are physical
+    /// addresses.
+    pub const fn identity() -> Self {
+        Self { domain: None }
+    }
+
+    /// A device whose DMA is translated by `domain`.
+    pub fn new(domain: Arc<dyn IommuDomain>) -> Self {
+        Self {
+            domain: Some(domain),
+        }
+    }
+
+    pub fn is_identity(&self) -> bool {
+        self.domain.is_none()
+    }
+
+    /// Returns `true` if DMA by `self` and `other` goes through the same
+    /// translation, so that addresses mapped for one are valid for the other.
+    pub fn same_translation(&self, other: &DmaDevice) -> bool {
+        match (&self.domain, &other.domain) {
+            (None, None) => true,
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+
+    fn map(&self, phys: PhysMemoryRegion, dir: DmaDirection) -> Result<DmaAddr> {
+        match &self.domain {
+            Some(domain) => domain.map(phys, dir),
+            None => Ok(phys.start_address().value()),
+        }
+    }
+
+    fn unmap(&self, addr: DmaAddr) {
+        if let Some(domain) = &self.domain {
+            domain
+                .unmap(addr)
+                .expect("Attempted to unmap an unknown DMA address");
+        }
+    }
+
+    /// Allocates a zeroed, physically contiguous buffer of at least `size`
+    /// bytes for coherent DMA by this device.
+    ///
+    /// On arm64 the buffer is accessed through the cacheable linear map; this
+    /// relies on the platform's DMA masters being cache coherent, which is the
+    /// case for all devices on QEMU's `virt` machine.
+    pub fn alloc_coherent(&self, size: usize) -> Result<DmaCoherent> {
+        if size == 0 {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let alloc = PAGE_ALLOC
+            .get()
+            .expect("PAGE_ALLOC not initialized")
+            .alloc_frames(order_for_size(size))?;
+
+        let dma_addr = self.map(*alloc.region(), DmaDirection::Bidirectional)?;
+
+        let mut buf = DmaCoherent {
+            alloc,
+            len: size,
+            dma_addr,
+            device: self.clone(),
+        };
+
+        buf.as_slice_mut().fill(0);
+
+        Ok(buf)
+    }
+
+    /// Maps the kernel buffer `buf` for a single DMA transfer by this device
+    /// in direction `dir`, returning the address the device should use.
+    ///
+    /// The buffer must be physically contiguous. Addresses outside the
+    /// kernel's linear map, such as user or `vmalloc` addresses, are rejected
+    /// with [`KernelError::InvalidValue`].
+    ///
+    /// # Safety
+    ///
+    /// `buf` must be a heap or page allocation (i.e. not part of the kernel
+    /// image) and the caller must ensure that it remains allocated, and is not
+    /// accessed by the CPU, until it is unmapped with [`Self::unmap_single`].
+    pub unsafe fn map_single(&self, buf: VirtMemoryRegion, dir: DmaDirection) -> Result<DmaAddr> {
+        let addr = self.map(linear_map_region(buf)?, dir)?;
+
+        ArchImpl::dma_sync_for_device(buf, dir);
+
+        Ok(addr)
+    }
+
+    /// Ends a streaming DMA mapping created by [`Self::map_single`],
+    /// returning ownership of the buffer to the CPU.
+    pub fn unmap_single(&self, addr: DmaAddr, buf: VirtMemoryRegion, dir: DmaDirection) {
+        if self.is_identity() {
+            debug_assert_eq!(
+                linear_map_region(buf)
+                    .ok()
+                    .map(|r| r.start_address().value()),
+                Some(addr)
+            );
+        }
+
+        ArchImpl::dma_sync_for_cpu(buf, dir);
+
+        self.unmap(addr);
+    }
+}
+
+/// A physically contiguous, zero-initialised buffer shared between the CPU and
+/// a device. The memory is unmapped from the device and freed on drop.
+pub struct DmaCoherent {
+    alloc: PageAllocation<'static, ArchImpl>,
+    len: usize,
+    dma_addr: DmaAddr,
+    device: DmaDevice,
+}
+
+// SAFETY: The buffer exclusively owns its pages.
+unsafe impl Send for DmaCoherent {}
+unsafe impl Sync for DmaCoherent {}
+
+impl DmaCoherent {
+    /// The address the device should use to access this buffer.
+    pub fn dma_addr(&self) -> DmaAddr {
+        self.dma_addr
+    }
+
+    /// The CPU virtual address of this buffer.
+    pub fn va(&self) -> VA {
+        self.alloc
+            .region()
+            .map_via::<PageOffsetTranslator>()
+            .start_address()
+    }
+
+    /// The requested size of the buffer, in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns the buffer as a byte slice.
+    pub fn as_slice(&self) -> &[u8] {
+        // SAFETY: The pages are owned by `self` and mapped in the direct map.
+        unsafe { slice::from_raw_parts(self.va().as_ptr().cast(), self.len) }
+    }
+
+    /// Returns the buffer as a mutable byte slice.
+    pub fn as_slice_mut(&mut self) -> &mut [u8] {
+        // SAFETY: As above, and we hold a mutable borrow.
+        unsafe { slice::from_raw_parts_mut(self.va().as_ptr_mut().cast(), self.len) }
+    }
+
+    /// Releases ownership of the buffer without freeing or unmapping it,
+    /// returning its DMA address. It may be reclaimed with [`Self::from_raw`].
+    pub fn into_raw(self) -> DmaAddr {
+        let addr = self.dma_addr();
+
+        // SAFETY: `self` is forgotten immediately, so the allocation is not
+        // dropped twice.
+        let alloc = unsafe { core::ptr::read(&self.alloc) };
+        core::mem::forget(self);
+
+        alloc.leak();
+
+        addr
+    }
+
+    /// Reclaims a buffer previously released with [`Self::into_raw`].
+    ///
+    /// # Safety
+    ///
+    /// `device`, `addr`, `va` and `size` must match a buffer, allocated with
+    /// [`DmaDevice::alloc_coherent`] and released with [`Self::into_raw`],
+    /// which must not be reclaimed more than once.
+    pub unsafe fn from_raw(device: &DmaDevice, addr: DmaAddr, va: VA, size: usize) -> Self {
+        let region = PhysMemoryRegion::new(
+            va.to_pa::<PageOffsetTranslator>(),
+            (1 << order_for_size(size)) * PAGE_SIZE,
+        );
+
+        let alloc = unsafe {
+            PAGE_ALLOC
+                .get()
+                .expect("PAGE_ALLOC not initialized")
+                .alloc_from_region(region)
+        };
+
+        Self {
+            alloc,
+            len: size,
+            dma_addr: addr,
+            device: device.clone(),
+        }
+    }
+}
+
+impl Drop for DmaCoherent {
+    fn drop(&mut self) {
+        self.device.unmap(self.dma_addr);
+    }
+}
+
+/// Returns the smallest buddy order that can hold `size` bytes.
+fn order_for_size(size: usize) -> u8 {
+    size.div_ceil(PAGE_SIZE).max(1).next_power_of_two().ilog2() as u8
+}
+
+/// Allocates a zeroed, physically contiguous buffer of at least `size` bytes
+/// for coherent DMA by an identity-mapped device.
+pub fn dma_alloc_coherent(size: usize) -> Result<DmaCoherent> {
+    DmaDevice::identity().alloc_coherent(size)
+}
+
+/// Returns the physical region backing `buf`, which must lie within the
+/// kernel's linear map.
+fn linear_map_region(buf: VirtMemoryRegion) -> Result<PhysMemoryRegion> {
+    if buf.start_address().value() < ArchImpl::PAGE_OFFSET
+        || buf.start_address().value() >= ArchImpl::VMALLOC_AREA.start_address().value()
+    {
+        return Err(KernelError::InvalidValue);
+    }
+
+    Ok(buf.map_via::<PageOffsetTranslator>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use moss_macros::ktest;
+
+    #[ktest]
+    fn coherent_alloc_is_zeroed_and_contiguous() {
+        let mut buf = dma_alloc_coherent(3 * PAGE_SIZE).unwrap();
+
+        assert_eq!(buf.len(), 3 * PAGE_SIZE);
+        assert_eq!(buf.dma_addr() % PAGE_SIZE, 0);
+        assert!(buf.as_slice().iter().all(|&b| b == 0));
+
+        buf.as_slice_mut()[PAGE_SIZE] = 0xaa;
+
+        let va = buf.va();
+        let addr = buf.into_raw();
+        let buf = unsafe { DmaCoherent::from_raw(&DmaDevice::identity(), addr, va, 3 * PAGE_SIZE) };
+        assert_eq!(buf.dma_addr(), addr);
+        assert_eq!(buf.as_slice()[PAGE_SIZE], 0xaa);
+    }
+
+    #[ktest]
+    fn streaming_map_rejects_vmalloc() {
+        let buf = crate::memory::vmalloc::vmalloc(PAGE_SIZE).unwrap();
+        let region = VirtMemoryRegion::new(VA::from_ptr(buf.as_ptr().cast()), PAGE_SIZE);
+
+        assert!(
+            unsafe { DmaDevice::identity().map_single(region, DmaDirection::ToDevice) }.is_err()
+        );
+    }
+}
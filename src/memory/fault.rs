@@ -4,16 +4,21 @@ use alloc::sync::Arc;
 use libkernel::{
     error::{KernelError, MapError, Result},
     memory::{
+        HUGE_PAGE_ORDER,
         address::VA,
         paging::permissions::PtePermissions,
         proc_vm::{
             address_space::{PageInfo, UserAddressSpace},
-            vmarea::AccessKind,
+            vmarea::{AccessKind, VMAFlags},
         },
     },
 };
 
-use super::{PAGE_ALLOC, page::ClaimedPage};
+use super::{
+    PAGE_ALLOC, PageOffsetTranslator,
+    page::{ClaimedPage, get_zero_page, is_zero_page},
+    userfaultfd::find_uffd_ctx,
+};
 
 /// Represents the outcome of a page fault handling attempt.
 ///
@@ -44,23 +49,93 @@ pub enum FaultResolution {
 }
 
 /// Handle a page fault when a PTE is not present.
+///
+/// `stack_limit` is the `RLIMIT_STACK` of the process owning `proc_vm`, which
+/// bounds how far a stack may grow to cover the fault.
 pub fn handle_demand_fault(
     proc_vm: Arc<SpinLock<ProcVM>>,
     faulting_addr: VA,
     access_kind: AccessKind,
+    stack_limit: usize,
 ) -> Result<FaultResolution> {
     let mut vm = proc_vm.lock_save_irq();
 
+    // A fault in the gap just below a stack may grow the stack to cover it. If
+    // that's not possible, the stack guard has been breached.
+    if vm.mm().find_vma(faulting_addr).is_none()
+        && vm.mm_mut().expand_stack(faulting_addr, stack_limit).is_err()
+    {
+        return Ok(FaultResolution::Denied);
+    }
+
     let vma = match vm.find_vma_for_fault(faulting_addr, access_kind) {
         Some(vma) => vma,
         None => return Ok(FaultResolution::Denied),
     }
     .clone();
 
-    let mut new_page = ClaimedPage::alloc_zeroed()?;
+    // Missing pages in a userfaultfd-registered range are supplied by the
+    // userspace handler.
+    if vma.flags().contains(VMAFlags::UFFD_MISSING)
+        && let Some(uffd) = find_uffd_ctx(&proc_vm, faulting_addr)
+    {
+        drop(vm);
+
+        return Ok(FaultResolution::Deferred(Box::new(async move {
+            uffd.handle_fault(faulting_addr, access_kind).await?;
+
+            // Return to userspace and retry the access. If the handler
+            // didn't map a page (e.g. the userfaultfd was closed), the
+            // kernel will resolve the fault.
+            Ok(())
+        })));
+    }
+
+    if let Some(block_region) = vma.huge_page_region(faulting_addr)
+        && try_map_huge_page(
+            &mut vm,
+            block_region.start_address(),
+            vma.permissions().into(),
+        )
+    {
+        return Ok(FaultResolution::Resolved);
+    }
+
     let page_va = faulting_addr.page_aligned();
+    let vma_read = vma.resolve_fault(faulting_addr);
+
+    // Reads of untouched anonymous memory are satisfied by the shared zero
+    // page; real memory is only allocated on the first write.
+    if vma_read.is_none() && access_kind != AccessKind::Write {
+        let perms = PtePermissions::from(vma.permissions());
+        let perms = if perms.is_write() {
+            perms.into_cow()
+        } else {
+            perms
+        };
+
+        let zero_page = get_zero_page();
+
+        if let Err(e) = vm
+            .mm_mut()
+            .address_space_mut()
+            .map_page(zero_page, page_va, perms)
+        {
+            // Drop the reference we took on the zero page.
+            drop(unsafe { ClaimedPage::from_pfn(zero_page) });
+
+            // As below, another CPU may have mapped the page for us.
+            if !matches!(e, KernelError::MappingError(MapError::AlreadyMapped)) {
+                return Err(e);
+            }
+        }
+
+        return Ok(FaultResolution::Resolved);
+    }
+
+    let mut new_page = ClaimedPage::alloc_zeroed()?;
 
-    if let Some(vma_read) = vma.resolve_fault(faulting_addr) {
+    if let Some(vma_read) = vma_read {
         drop(vm);
 
         Ok(FaultResolution::Deferred(Box::new(async move {
@@ -129,6 +204,45 @@ pub fn handle_demand_fault(
     }
 }
 
+/// Attempt to satisfy an anonymous fault by mapping a zeroed huge page at
+/// `block_va`. Returns `false` if the caller should fall back to mapping a
+/// single base page, e.g. because part of the block is already populated or
+/// no physically contiguous memory is available.
+fn try_map_huge_page(vm: &mut ProcVM, block_va: VA, perms: PtePermissions) -> bool {
+    let address_space = vm.mm_mut().address_space_mut();
+
+    if !address_space.can_map_huge_page(block_va) {
+        return false;
+    }
+
+    let Ok(block) = PAGE_ALLOC.get().unwrap().alloc_frames(HUGE_PAGE_ORDER as _) else {
+        return false;
+    };
+
+    let region = *block.region();
+
+    // SAFETY: We own the freshly allocated block and it's accessible via the
+    // kernel's linear map.
+    unsafe {
+        region
+            .start_address()
+            .to_va::<PageOffsetTranslator>()
+            .as_ptr_mut()
+            .cast::<u8>()
+            .write_bytes(0, region.size());
+    }
+
+    match address_space.map_huge_page(region, block_va, perms) {
+        Ok(()) => {
+            // Leak the block for reclamation by the address-space tear-down
+            // code.
+            block.leak();
+            true
+        }
+        Err(_) => false,
+    }
+}
+
 /// Handle a page fault when a page is present, but the access kind differ from
 /// permissible accesses defined in the PTE, a 'protection' fault.
 pub fn handle_protection_fault(
@@ -161,7 +275,7 @@ pub fn handle_protection_fault(
             // Take ownership of the page.
             vm.mm_mut()
                 .address_space_mut()
-                .protect_range(faulting_addr.page_region(), new_pte_perms)?;
+                .remap(faulting_addr, pg_info.pfn, new_pte_perms)?;
 
             Ok(FaultResolution::Resolved)
         } else {
@@ -171,10 +285,11 @@ pub fn handle_protection_fault(
             // the refcount on the shared page.
             let src_page = unsafe { ClaimedPage::from_pfn(pg_info.pfn) };
 
-            let src = src_page.as_slice();
-            let dst = new_page.as_slice_mut();
-
-            dst.copy_from_slice(src);
+            // A fresh page is already zeroed, so there's nothing to copy
+            // from the zero page.
+            if !is_zero_page(pg_info.pfn) {
+                new_page.as_slice_mut().copy_from_slice(src_page.as_slice());
+            }
 
             // Remap the existing CoW mapping with the fresh page.
             vm.mm_mut()
@@ -0,0 +1,42 @@
+//! Reporting for the KASAN-style heap sanitizer, enabled with the `kasan`
+//! feature. See [`libkernel::memory::allocators::kasan`].
+
+use crate::{
+    arch::{Arch, ArchImpl},
+    kernel::backtrace::{dump_backtrace, print_backtrace},
+};
+use libkernel::memory::allocators::kasan::{KasanOps, KasanReport};
+use log::error;
+
+pub struct KasanReporter;
+
+impl KasanOps for KasanReporter {
+    fn capture_backtrace(frames: &mut [usize]) -> usize {
+        ArchImpl::capture_backtrace(frames)
+    }
+
+    fn report(report: &KasanReport<'_>) {
+        error!("==================================================================");
+        error!("BUG: KASAN: {} at addr {:#x}", report.kind, report.addr);
+        error!(
+            "Object at {:#x} of size {}, offset {}",
+            report.object,
+            report.size,
+            report.addr.wrapping_sub(report.object) as isize
+        );
+        error!("Detected by:");
+        dump_backtrace();
+
+        if !report.alloc_trace.is_empty() {
+            error!("Allocated by:");
+            print_backtrace(report.alloc_trace);
+        }
+
+        if !report.free_trace.is_empty() {
+            error!("Freed by:");
+            print_backtrace(report.free_trace);
+        }
+
+        error!("==================================================================");
+    }
+}
@@ -0,0 +1,128 @@
+//! Kernel memory leak detection, enabled with the `kmemleak` feature. See
+//! [`libkernel::memory::allocators::kmemleak`].
+//!
+//! The kernel's `.data` and `.bss` sections are scanned as roots. Task state
+//! lives in heap-allocated futures which are reachable from there, so kernel
+//! stacks aren't scanned; any transient reference held on one is covered by
+//! the minimum age an object must reach before it's reported.
+//!
+//! Results are exposed through `/proc/kmemleak`. Reading it lists the objects
+//! flagged as leaks, first rescanning if the last scan is more than
+//! [`SCAN_INTERVAL`] old. Writing `scan` forces a scan, and writing `clear`
+//! stops the currently listed objects from being reported again.
+
+use crate::{
+    arch::{Arch, ArchImpl},
+    drivers::timer::uptime,
+    sync::SpinLock,
+};
+use alloc::string::String;
+use core::{fmt::Write, time::Duration};
+use libkernel::memory::{
+    address::VA,
+    allocators::kmemleak::{Kmemleak, KmemleakOps},
+    region::VirtMemoryRegion,
+};
+use log::warn;
+
+/// How long a scan's results are considered fresh.
+pub const SCAN_INTERVAL: Duration = Duration::from_secs(60);
+
+pub static KMEMLEAK: Kmemleak<ArchImpl> = Kmemleak::new();
+
+/// When the last scan was performed.
+static LAST_SCAN: SpinLock<Option<Duration>> = SpinLock::new(None);
+
+pub struct KmemleakHooks;
+
+impl KmemleakOps<ArchImpl> for KmemleakHooks {
+    fn capture_backtrace(frames: &mut [usize]) -> usize {
+        ArchImpl::capture_backtrace(frames)
+    }
+
+    fn now() -> Duration {
+        uptime()
+    }
+
+    fn tracker() -> &'static Kmemleak<ArchImpl> {
+        &KMEMLEAK
+    }
+}
+
+/// Returns the kernel image's writable data sections.
+fn roots() -> [VirtMemoryRegion; 2] {
+    unsafe extern "C" {
+        static __data_start: u8;
+        static __data_end: u8;
+        static __bss_start: u8;
+        static __bss_end: u8;
+    }
+
+    let region = |start: *const u8, end: *const u8| {
+        VirtMemoryRegion::from_start_end_address(
+            VA::from_ptr(start.cast()),
+            VA::from_ptr(end.cast()),
+        )
+    };
+
+    unsafe {
+        [
+            region(&raw const __data_start, &raw const __data_end),
+            region(&raw const __bss_start, &raw const __bss_end),
+        ]
+    }
+}
+
+/// Scans for leaked objects, logging how many new leaks were found.
+pub fn scan() {
+    let now = uptime();
+
+    // SAFETY: The kernel image's data sections are always mapped.
+    let new_leaks = unsafe { KMEMLEAK.scan(&roots(), now) };
+
+    *LAST_SCAN.lock_save_irq() = Some(now);
+
+    if new_leaks > 0 {
+        warn!("kmemleak: {new_leaks} new suspected memory leaks (see /proc/kmemleak)");
+    }
+}
+
+/// Rescans if the last scan's results are stale.
+pub fn scan_if_stale() {
+    let stale = LAST_SCAN
+        .lock_save_irq()
+        .is_none_or(|last| uptime().saturating_sub(last) >= SCAN_INTERVAL);
+
+    if stale {
+        scan();
+    }
+}
+
+/// Formats the current suspects in the style of Linux's kmemleak.
+pub fn report() -> String {
+    let now = uptime();
+    let mut report = String::new();
+
+    for suspect in KMEMLEAK.suspects() {
+        let age = now.saturating_sub(suspect.allocated_at);
+
+        let _ = writeln!(
+            report,
+            "unreferenced object {:#x} (size {}):",
+            suspect.addr, suspect.size
+        );
+        let _ = writeln!(
+            report,
+            "  age {}.{:03}s",
+            age.as_secs(),
+            age.subsec_millis()
+        );
+        let _ = writeln!(report, "  backtrace:");
+
+        for frame in suspect.trace.frames() {
+            let _ = writeln!(report, "    [<{frame:#018x}>]");
+        }
+    }
+
+    report
+}
@@ -5,10 +5,12 @@ use alloc::string::{String, ToString};
 use libkernel::{
     error::{KernelError, Result},
     memory::{
+        HUGE_PAGE_SIZE,
         address::VA,
         proc_vm::{
             memory_map::AddressRequest,
-            vmarea::{VMAPermissions, VMAreaKind},
+            overcommit::{OvercommitPolicy, VM_COMMIT},
+            vmarea::{VMAFlags, VMAPermissions, VMAreaKind},
         },
         region::VirtMemoryRegion,
     },
@@ -24,6 +26,14 @@ const MAP_FIXED: u64 = 0x0010;
 const MAP_FIXED_NOREPLACE: u64 = 0x100000;
 const MAP_ANON: u64 = 0x0020;
 const MAP_ANONYMOUS: u64 = 0x0020;
+const MAP_GROWSDOWN: u64 = 0x0100;
+const MAP_NORESERVE: u64 = 0x4000;
+const MAP_HUGETLB: u64 = 0x40000;
+
+/// The log2 of the requested huge page size is encoded in these bits of the
+/// flags when `MAP_HUGETLB` is given. Zero selects the default size.
+const MAP_HUGE_SHIFT: u64 = 26;
+const MAP_HUGE_MASK: u64 = 0x3f;
 
 /// Determines the minimal address that user-space is allowed to specify for
 /// MAP_FIXED{,_NOREPLACE}.
@@ -87,6 +97,48 @@ pub async fn sys_mmap(
 
     let requested_len = len as usize;
 
+    let vma_flags = if (flags & MAP_HUGETLB) != 0 {
+        let huge_shift = (flags >> MAP_HUGE_SHIFT) & MAP_HUGE_MASK;
+
+        // Only anonymous huge pages of the default size are supported.
+        if (flags & (MAP_ANON | MAP_ANONYMOUS)) == 0
+            || (huge_shift != 0 && 1 << huge_shift != HUGE_PAGE_SIZE)
+        {
+            return Err(KernelError::InvalidValue);
+        }
+
+        if (flags & (MAP_FIXED | MAP_FIXED_NOREPLACE)) != 0
+            && !addr.value().is_multiple_of(HUGE_PAGE_SIZE)
+        {
+            return Err(KernelError::InvalidValue);
+        }
+
+        VMAFlags::HUGEPAGE
+    } else {
+        VMAFlags::empty()
+    };
+
+    let vma_flags = if (flags & MAP_GROWSDOWN) != 0 {
+        // Only anonymous mappings can be grown on demand.
+        if (flags & (MAP_ANON | MAP_ANONYMOUS)) == 0 {
+            return Err(KernelError::InvalidValue);
+        }
+
+        vma_flags | VMAFlags::GROWSDOWN
+    } else {
+        vma_flags
+    };
+
+    // Private writable pages may be dirtied at any time, so are committed up
+    // front. `MAP_NORESERVE` opts out of this, unless overcommit is disabled.
+    let vma_flags = if permissions.write
+        && ((flags & MAP_NORESERVE) == 0 || VM_COMMIT.policy() == OvercommitPolicy::Never)
+    {
+        vma_flags | VMAFlags::ACCOUNT
+    } else {
+        vma_flags
+    };
+
     let (kind, name) = if (flags & (MAP_ANON | MAP_ANONYMOUS)) != 0 {
         (VMAreaKind::Anon, String::new())
     } else {
@@ -127,12 +179,13 @@ pub async fn sys_mmap(
     };
 
     // Lock the task and call the core memory manager to perform the mapping.
-    let new_mapping_addr = ctx.shared().vm.lock_save_irq().mm_mut().mmap(
+    let new_mapping_addr = ctx.shared().vm.lock_save_irq().mm_mut().mmap_with_flags(
         address_request,
         requested_len,
         permissions,
         kind,
         name,
+        vma_flags,
     )?;
 
     Ok(new_mapping_addr.value())
@@ -1,22 +1,39 @@
 use crate::{
     arch::{Arch, ArchImpl},
+    net::TcpSocket,
+    process::Task,
     sync::{OnceLock, SpinLock},
 };
-use libkernel::memory::{
-    allocators::{
-        phys::FrameAllocator,
-        smalloc::{RegionList, Smalloc},
+use core::{alloc::Layout, sync::atomic::AtomicUsize};
+use libkernel::{
+    error::Result,
+    fs::filesystems::ext4::Ext4Inode,
+    memory::{
+        allocators::{
+            phys::FrameAllocator,
+            slab::allocator::SlabAllocator,
+            smalloc::{RegionList, Smalloc},
+        },
+        region::PhysMemoryRegion,
     },
-    region::PhysMemoryRegion,
 };
+use page::PgAllocGetter;
 
 pub mod brk;
+pub mod dma;
 pub mod fault;
+#[cfg(feature = "kasan")]
+pub mod kasan;
+#[cfg(feature = "kmemleak")]
+pub mod kmemleak;
 pub mod mincore;
 pub mod mmap;
+pub mod oom;
 pub mod page;
 pub mod process_vm;
 pub mod uaccess;
+pub mod userfaultfd;
+pub mod vmalloc;
 
 pub type PageOffsetTranslator =
     libkernel::memory::proc_vm::pg_offset::PageOffsetTranslator<{ ArchImpl::PAGE_OFFSET }>;
@@ -37,3 +54,30 @@ pub static INITAL_ALLOCATOR: SpinLock<Option<Smalloc<PageOffsetTranslator>>> =
 
 // Main page allocator, setup by consuming smalloc.
 pub static PAGE_ALLOC: OnceLock<FrameAllocator<ArchImpl>> = OnceLock::new();
+
+pub type SlabAlloc = SlabAllocator<ArchImpl, PgAllocGetter, PageOffsetTranslator>;
+
+// Slab allocator backing the kernel heap.
+pub static SLAB_ALLOC: OnceLock<SlabAlloc> = OnceLock::new();
+
+/// Returns the layout of the heap allocation backing an `Arc<T>`: the strong
+/// and weak reference counts, followed by `T`.
+fn arc_layout<T>() -> Layout {
+    Layout::new::<[AtomicUsize; 2]>()
+        .extend(Layout::new::<T>())
+        .unwrap()
+        .0
+        .pad_to_align()
+}
+
+/// Create dedicated slab caches for frequently allocated kernel objects.
+///
+/// This must be called before the slab allocator is used for any allocation.
+pub fn create_named_caches(slab: &mut SlabAlloc) -> Result<()> {
+    slab.create_named_cache("task_struct", arc_layout::<Task>())?;
+    slab.create_named_cache("inode", arc_layout::<Ext4Inode<ArchImpl>>())?;
+    // Sockets are boxed into their `OpenFile`, not reference counted.
+    slab.create_named_cache("socket", Layout::new::<TcpSocket>())?;
+
+    Ok(())
+}
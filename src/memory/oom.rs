@@ -0,0 +1,153 @@
+//! The out-of-memory killer.
+//!
+//! When a page can't be allocated to satisfy a fault there's no page cache or
+//! swap to reclaim from, so the only way to make progress is to free memory
+//! by killing a process. The victim is the process with the highest
+//! [`badness`], i.e. the one whose death frees the most memory.
+
+use super::{PAGE_ALLOC, page::is_zero_page};
+use crate::process::{
+    ProcVM, TASK_LIST, Task,
+    thread_group::{
+        ProcessState,
+        signal::{SigId, SigSet},
+    },
+};
+use alloc::{collections::BTreeSet, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use libkernel::memory::proc_vm::address_space::UserAddressSpace;
+use log::{error, warn};
+
+/// How many times in a row the OOM killer waits on a dying process before
+/// giving up on it and killing another.
+const MAX_OOM_WAITS: usize = 100;
+
+/// The number of calls to [`out_of_memory`] which have waited on a dying
+/// process since the last kill.
+static OOM_WAITS: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns the number of pages of RAM mapped into `vm`.
+///
+/// As on Linux, the shared zero page isn't counted.
+pub fn resident_pages(vm: &ProcVM) -> usize {
+    let addr_space = vm.mm().address_space();
+
+    vm.mm()
+        .iter_vmas()
+        .flat_map(|vma| vma.region().iter_pages())
+        .filter(|&va| {
+            addr_space
+                .translate(va)
+                .is_some_and(|info| !is_zero_page(info.pfn))
+        })
+        .count()
+}
+
+/// Returns how attractive `task`'s process is as a victim of the OOM killer.
+///
+/// This is the number of resident pages in its address space. Init and the
+/// idle task are never killed, and so have a badness of zero.
+pub fn badness(task: &Task) -> usize {
+    if task.process.tgid.is_init() || task.process.tgid.is_idle() {
+        return 0;
+    }
+
+    resident_pages(&task.vm.lock_save_irq())
+}
+
+/// Returns `badness` scaled to the range 0-1000 for `/proc/<pid>/oom_score`.
+pub fn oom_score(task: &Task) -> usize {
+    let total_pages = PAGE_ALLOC.get().map_or(0, |alloc| alloc.total_pages());
+
+    if total_pages == 0 {
+        return 0;
+    }
+
+    (badness(task) * 1000 / total_pages).min(1000)
+}
+
+/// Frees memory by killing the process with the highest badness.
+///
+/// Returns `false` if there was nothing to kill. If a previous victim hasn't
+/// finished dying and still holds memory, no further process is killed as its
+/// memory will be released shortly. After [`MAX_OOM_WAITS`] such calls the
+/// victim is assumed to be stuck, and another process is killed.
+///
+/// This takes the VM lock of every process, so must not be called with any
+/// of them held.
+pub fn out_of_memory() -> bool {
+    let tasks: Vec<_> = TASK_LIST
+        .lock_save_irq()
+        .values()
+        .filter_map(|task| task.upgrade())
+        .collect();
+
+    let mut scored = BTreeSet::new();
+    let mut victim: Option<(&Arc<Task>, usize)> = None;
+
+    for work in &tasks {
+        let task: &Arc<Task> = work;
+        let process = &task.process;
+
+        if *process.state.lock_save_irq() == ProcessState::Exiting
+            || process
+                .pending_signals
+                .lock_save_irq()
+                .contains(SigSet::SIGKILL)
+        {
+            // Once a dying process has released its memory there's nothing
+            // more to wait for.
+            if resident_pages(&task.vm.lock_save_irq()) == 0 {
+                continue;
+            }
+
+            let waits = OOM_WAITS.fetch_add(1, Ordering::Relaxed);
+
+            if waits < MAX_OOM_WAITS {
+                warn!(
+                    "Out of memory: waiting for process {} to exit",
+                    process.tgid
+                );
+                return true;
+            }
+
+            if waits == MAX_OOM_WAITS {
+                warn!(
+                    "Out of memory: process {} isn't exiting, killing another",
+                    process.tgid
+                );
+            }
+
+            // Don't pick it again.
+            continue;
+        }
+
+        // Threads share their address space, so only score each process once.
+        if !scored.insert(process.tgid) {
+            continue;
+        }
+
+        let points = badness(task);
+
+        if points > 0 && victim.is_none_or(|(_, best)| points > best) {
+            victim = Some((task, points));
+        }
+    }
+
+    let Some((victim, points)) = victim else {
+        error!("Out of memory: no killable process");
+        return false;
+    };
+
+    error!(
+        "Out of memory: killed process {} ({}), {} resident pages",
+        victim.process.tgid,
+        victim.comm.lock_save_irq().as_str(),
+        points
+    );
+
+    victim.process.deliver_signal(SigId::SIGKILL);
+    OOM_WAITS.store(0, Ordering::Relaxed);
+
+    true
+}
@@ -1,6 +1,6 @@
 use super::{PAGE_ALLOC, PageOffsetTranslator};
-use crate::arch::ArchImpl;
-use libkernel::memory::allocators::phys::PageAllocGetter;
+use crate::{arch::ArchImpl, sync::OnceLock};
+use libkernel::memory::{allocators::phys::PageAllocGetter, page::PageFrame};
 
 pub struct PgAllocGetter {}
 
@@ -13,3 +13,38 @@ impl PageAllocGetter<ArchImpl> for PgAllocGetter {
 
 pub type ClaimedPage =
     libkernel::memory::claimed_page::ClaimedPage<ArchImpl, PgAllocGetter, PageOffsetTranslator>;
+
+/// A page of zeroes, shared by every anonymous mapping that has been read but
+/// not yet written. It holds a reference of its own, so it is never freed.
+static ZERO_PAGE: OnceLock<PageFrame> = OnceLock::new();
+
+/// Returns the shared zero page, taking a new reference to it on behalf of
+/// the caller. The reference is released when the page is unmapped.
+///
+/// The zero page must only ever be mapped read-only or CoW.
+pub fn get_zero_page() -> PageFrame {
+    let pfn = *ZERO_PAGE.get_or_init(|| {
+        ClaimedPage::alloc_zeroed()
+            .expect("Failed to allocate the zero page")
+            .leak()
+    });
+
+    // SAFETY: The zero page is permanently allocated.
+    let page = unsafe {
+        PAGE_ALLOC
+            .get()
+            .unwrap()
+            .alloc_from_region(pfn.as_phys_range())
+    };
+
+    // Increase ref count.
+    page.clone().leak();
+    page.leak();
+
+    pfn
+}
+
+/// Returns `true` if `pfn` is the shared zero page.
+pub fn is_zero_page(pfn: PageFrame) -> bool {
+    ZERO_PAGE.get() == Some(&pfn)
+}
@@ -2,105 +2,154 @@ use core::{cmp::min, slice};
 
 use super::{
     PageOffsetTranslator,
-    uaccess::{copy_obj_array_from_user, copy_to_user_slice},
+    uaccess::iovec::{IoVec, UserIoVec},
 };
-use crate::process::{Tid, find_task_by_tid};
-use crate::{fs::syscalls::iov::IoVec, process::thread_group::pid::PidT};
+use crate::process::thread_group::pid::PidT;
+use crate::process::{Tid, find_task_by_tid, ptrace::ptrace_may_access};
+use crate::sched::syscall_ctx::ProcessCtx;
 use libkernel::{
     error::{KernelError, Result},
     memory::{PAGE_SIZE, address::TUA, proc_vm::vmarea::AccessKind},
 };
 
 pub async fn sys_process_vm_readv(
+    ctx: &ProcessCtx,
     pid: PidT,
     local_iov: TUA<IoVec>,
     liov_count: usize,
     remote_iov: TUA<IoVec>,
     riov_count: usize,
-    _flags: usize,
+    flags: usize,
 ) -> Result<usize> {
-    let tgid = Tid::from_pid_t(pid);
-    let remote_proc = find_task_by_tid(tgid).ok_or(KernelError::NoProcess)?;
-    let local_iovs = copy_obj_array_from_user(local_iov, liov_count).await?;
-    let remote_iovs = copy_obj_array_from_user(remote_iov, riov_count).await?;
+    process_vm_rw(
+        ctx,
+        pid,
+        local_iov,
+        liov_count,
+        remote_iov,
+        riov_count,
+        flags,
+        AccessKind::Read,
+    )
+    .await
+}
 
-    let mut total_bytes_copied = 0;
+pub async fn sys_process_vm_writev(
+    ctx: &ProcessCtx,
+    pid: PidT,
+    local_iov: TUA<IoVec>,
+    liov_count: usize,
+    remote_iov: TUA<IoVec>,
+    riov_count: usize,
+    flags: usize,
+) -> Result<usize> {
+    process_vm_rw(
+        ctx,
+        pid,
+        local_iov,
+        liov_count,
+        remote_iov,
+        riov_count,
+        flags,
+        AccessKind::Write,
+    )
+    .await
+}
 
-    let mut local_iov_idx = 0;
-    let mut local_iov_curr_offset = 0;
+/// Transfer data between the local iovecs and the remote process's iovecs,
+/// in the direction given by `access_kind` (from the remote process's point
+/// of view).
+#[allow(clippy::too_many_arguments)]
+async fn process_vm_rw(
+    ctx: &ProcessCtx,
+    pid: PidT,
+    local_iov: TUA<IoVec>,
+    liov_count: usize,
+    remote_iov: TUA<IoVec>,
+    riov_count: usize,
+    flags: usize,
+    access_kind: AccessKind,
+) -> Result<usize> {
+    // No flags are currently defined.
+    if flags != 0 {
+        return Err(KernelError::InvalidValue);
+    }
 
-    let mut remote_iov_idx = 0;
-    let mut remote_iov_curr_offset = 0;
+    let tgid = Tid::from_pid_t(pid);
+    let remote_proc = find_task_by_tid(tgid).ok_or(KernelError::NoProcess)?;
 
-    while let Some(remote_iov) = remote_iovs.get(remote_iov_idx)
-        && let Some(local_iov) = local_iovs.get(local_iov_idx)
-    {
-        let remote_remaining = remote_iov.iov_len - remote_iov_curr_offset;
-        let local_remaining = local_iov.iov_len - local_iov_curr_offset;
+    ptrace_may_access(ctx.shared(), &remote_proc)?;
 
-        let remote_va = remote_iov.iov_base.add_bytes(remote_iov_curr_offset);
+    let local_iovs = UserIoVec::from_user(local_iov, liov_count).await?;
+    let remote_iovs = UserIoVec::from_user(remote_iov, riov_count).await?;
 
-        let chunk_sz = min(
-            PAGE_SIZE - remote_va.page_offset(),
-            min(remote_remaining, local_remaining),
-        );
+    let mut local_reader = local_iovs.reader();
+    let mut local_writer = local_iovs.writer();
 
-        // If we have nothing left to copy in current vectors, advance them.
-        if chunk_sz == 0 {
-            if remote_remaining == 0 {
-                remote_iov_idx += 1;
-                remote_iov_curr_offset = 0;
-            }
+    let mut total_bytes_copied = 0;
 
-            if local_remaining == 0 {
-                local_iov_idx += 1;
-                local_iov_curr_offset = 0;
+    for remote_iov in remote_iovs.iovs() {
+        let mut remote_offset = 0;
+
+        while remote_offset < remote_iov.iov_len {
+            let remote_va = remote_iov.iov_base.add_bytes(remote_offset);
+
+            let chunk_sz = min(
+                PAGE_SIZE - remote_va.page_offset(),
+                remote_iov.iov_len - remote_offset,
+            );
+
+            let copy_result = async {
+                // Get the page (pins it)
+                // SAFETY: We only access the page as described by `access_kind`.
+                let remote_page = unsafe { remote_proc.get_page(remote_va, access_kind).await? };
+
+                // Map physical page to kernel virtual address (Direct Map)
+                let remote_pg_ptr = remote_page
+                    .region()
+                    .start_address()
+                    .to_va::<PageOffsetTranslator>()
+                    .cast::<u8>()
+                    .add_bytes(remote_va.page_offset())
+                    .as_ptr_mut();
+
+                match access_kind {
+                    AccessKind::Write => {
+                        // Copy from local user memory into the remote page.
+                        let remote_pg_slice =
+                            unsafe { slice::from_raw_parts_mut(remote_pg_ptr, chunk_sz) };
+
+                        local_reader.read(remote_pg_slice).await
+                    }
+                    _ => {
+                        // Copy to local user memory
+                        let remote_pg_slice =
+                            unsafe { slice::from_raw_parts(remote_pg_ptr, chunk_sz) };
+
+                        local_writer.write(remote_pg_slice).await
+                    }
+                }
             }
+            .await;
 
-            continue;
-        }
+            match copy_result {
+                Ok(bytes_copied) => {
+                    total_bytes_copied += bytes_copied;
+                    remote_offset += bytes_copied;
 
-        let copy_result = async {
-            // Get the page (pins it)
-            // SAFETY: We only read.
-            let remote_page = unsafe { remote_proc.get_page(remote_va, AccessKind::Read).await? };
-
-            // Map physical page to kernel virtual address (Direct Map)
-            let remote_pg_slice = unsafe {
-                slice::from_raw_parts(
-                    remote_page
-                        .region()
-                        .start_address()
-                        .to_va::<PageOffsetTranslator>()
-                        .cast::<u8>()
-                        .add_bytes(remote_va.page_offset())
-                        .as_ptr(),
-                    chunk_sz,
-                )
-            };
-
-            // Copy to local user memory
-            copy_to_user_slice(
-                remote_pg_slice,
-                local_iov.iov_base.add_bytes(local_iov_curr_offset),
-            )
-            .await
-        }
-        .await;
-
-        match copy_result {
-            Ok(_) => {
-                total_bytes_copied += chunk_sz;
-                remote_iov_curr_offset += chunk_sz;
-                local_iov_curr_offset += chunk_sz;
-            }
-            Err(e) => {
-                if total_bytes_copied > 0 {
-                    // Partial success: return what we got so far.
-                    return Ok(total_bytes_copied);
-                } else {
-                    // No data copied at all: return the error.
-                    return Err(e);
+                    // The local iovecs are exhausted, or faulted part-way.
+                    if bytes_copied < chunk_sz {
+                        return Ok(total_bytes_copied);
+                    }
+                }
+                Err(e) => {
+                    if total_bytes_copied > 0 {
+                        // Partial success: return what we got so far.
+                        return Ok(total_bytes_copied);
+                    } else {
+                        // No data copied at all: return the error.
+                        return Err(e);
+                    }
                 }
             }
         }
@@ -6,6 +6,8 @@ use libkernel::error::Result;
 use libkernel::memory::address::{TUA, UA};
 
 pub mod cstr;
+pub mod iovec;
+pub mod user_slice;
 
 /// A marker trait for types that are safe to copy to or from userspace.
 ///
@@ -102,6 +104,18 @@ pub async fn copy_to_user_slice(src: &[u8], dst: UA) -> Result<()> {
     unsafe { ArchImpl::copy_to_user(src.as_ptr().cast(), dst, src.len()).await }
 }
 
+/// Copies from userspace into `dst`, returning the number of bytes copied. A
+/// fault part-way through results in a short copy rather than an error.
+pub async fn copy_from_user_slice_partial(src: UA, dst: &mut [u8]) -> Result<usize> {
+    unsafe { ArchImpl::copy_from_user_partial(src, dst.as_mut_ptr().cast(), dst.len()).await }
+}
+
+/// Copies `src` to userspace, returning the number of bytes copied. A fault
+/// part-way through results in a short copy rather than an error.
+pub async fn copy_to_user_slice_partial(src: &[u8], dst: UA) -> Result<usize> {
+    unsafe { ArchImpl::copy_to_user_partial(src.as_ptr().cast(), dst, src.len()).await }
+}
+
 macro_rules! impl_user_copyable_for_primitives {
     ($($t:ty),*) => {
         $(
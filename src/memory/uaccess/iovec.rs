@@ -0,0 +1,178 @@
+use super::{
+    UserCopyable, copy_from_user_slice_partial, copy_to_user_slice_partial, user_slice::UserSlice,
+};
+use alloc::vec::Vec;
+use libkernel::error::{KernelError, Result};
+use libkernel::memory::address::{TUA, UA};
+
+/// The maximum number of `iovec`s that may be passed to a single syscall.
+pub const UIO_MAXIOV: usize = 1024;
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct IoVec {
+    pub iov_base: UA,
+    pub iov_len: usize,
+}
+
+// SAFETY: An IoVec is safe to copy to-and-from userspace.
+unsafe impl UserCopyable for IoVec {}
+
+/// An array of I/O vectors, copied in from userspace and validated.
+pub struct UserIoVec {
+    iovs: Vec<IoVec>,
+}
+
+impl UserIoVec {
+    /// Copies `count` I/O vectors from `ptr`.
+    ///
+    /// Fails with [`KernelError::InvalidValue`] if there are more than
+    /// [`UIO_MAXIOV`] vectors or their total length overflows an `isize`, and
+    /// with [`KernelError::Fault`] if any vector wraps around the end of the
+    /// address space.
+    pub async fn from_user(ptr: TUA<IoVec>, count: usize) -> Result<Self> {
+        if count > UIO_MAXIOV {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let iovs = UserSlice::new(ptr, count)?.read_to_vec().await?;
+
+        let mut total_len: usize = 0;
+
+        for iov in &iovs {
+            iov.iov_base
+                .value()
+                .checked_add(iov.iov_len)
+                .ok_or(KernelError::Fault)?;
+
+            total_len = total_len
+                .checked_add(iov.iov_len)
+                .filter(|&len| len <= isize::MAX as usize)
+                .ok_or(KernelError::InvalidValue)?;
+        }
+
+        Ok(Self { iovs })
+    }
+
+    pub fn iovs(&self) -> &[IoVec] {
+        &self.iovs
+    }
+
+    /// Returns a reader which gathers data from the vectors in order.
+    pub fn reader(&self) -> UserIoVecReader<'_> {
+        UserIoVecReader(Cursor::new(&self.iovs))
+    }
+
+    /// Returns a writer which scatters data across the vectors in order.
+    pub fn writer(&self) -> UserIoVecWriter<'_> {
+        UserIoVecWriter(Cursor::new(&self.iovs))
+    }
+}
+
+/// A position within an array of I/O vectors.
+struct Cursor<'a> {
+    iovs: &'a [IoVec],
+    offset: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(iovs: &'a [IoVec]) -> Self {
+        Self { iovs, offset: 0 }
+    }
+
+    /// Returns the next contiguous user range of at most `max` bytes,
+    /// skipping any exhausted vectors.
+    fn next_chunk(&mut self, max: usize) -> Option<(UA, usize)> {
+        while let Some(iov) = self.iovs.first() {
+            let remaining = iov.iov_len - self.offset;
+
+            if remaining == 0 {
+                self.iovs = &self.iovs[1..];
+                self.offset = 0;
+                continue;
+            }
+
+            return Some((iov.iov_base.add_bytes(self.offset), remaining.min(max)));
+        }
+
+        None
+    }
+
+    /// Records that `n` bytes of the chunk returned by [`Self::next_chunk`]
+    /// were copied. Returns `false` if the copy was short, i.e. it faulted.
+    fn advance(&mut self, n: usize, chunk_len: usize) -> bool {
+        self.offset += n;
+
+        n == chunk_len
+    }
+}
+
+/// Gathers data from userspace I/O vectors. See [`UserIoVec::reader`].
+pub struct UserIoVecReader<'a>(Cursor<'a>);
+
+impl UserIoVecReader<'_> {
+    /// Copies from the vectors into `buf`, continuing where the previous read
+    /// left off.
+    ///
+    /// Returns the number of bytes copied. This is less than `buf.len()` if the
+    /// vectors are exhausted, or if a fault occurs after some bytes have been
+    /// copied.
+    pub async fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let mut copied = 0;
+
+        while copied < buf.len()
+            && let Some((addr, len)) = self.0.next_chunk(buf.len() - copied)
+        {
+            let dst = &mut buf[copied..copied + len];
+
+            let n = match copy_from_user_slice_partial(addr, dst).await {
+                Ok(n) => n,
+                Err(_) if copied > 0 => break,
+                Err(e) => return Err(e),
+            };
+
+            copied += n;
+
+            if !self.0.advance(n, len) {
+                break;
+            }
+        }
+
+        Ok(copied)
+    }
+}
+
+/// Scatters data to userspace I/O vectors. See [`UserIoVec::writer`].
+pub struct UserIoVecWriter<'a>(Cursor<'a>);
+
+impl UserIoVecWriter<'_> {
+    /// Copies `buf` to the vectors, continuing where the previous write left
+    /// off.
+    ///
+    /// Returns the number of bytes copied. This is less than `buf.len()` if the
+    /// vectors are exhausted, or if a fault occurs after some bytes have been
+    /// copied.
+    pub async fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let mut copied = 0;
+
+        while copied < buf.len()
+            && let Some((addr, len)) = self.0.next_chunk(buf.len() - copied)
+        {
+            let src = &buf[copied..copied + len];
+
+            let n = match copy_to_user_slice_partial(src, addr).await {
+                Ok(n) => n,
+                Err(_) if copied > 0 => break,
+                Err(e) => return Err(e),
+            };
+
+            copied += n;
+
+            if !self.0.advance(n, len) {
+                break;
+            }
+        }
+
+        Ok(copied)
+    }
+}
@@ -0,0 +1,99 @@
+use super::UserCopyable;
+use crate::arch::{Arch, ArchImpl};
+use alloc::vec::Vec;
+use libkernel::error::{KernelError, Result};
+use libkernel::memory::address::TUA;
+
+/// An array of `len` objects of type `T` in userspace.
+///
+/// Construction validates that the size of the array in bytes doesn't
+/// overflow, and that the array doesn't wrap around the end of the address
+/// space. The copy routines can therefore operate on the whole range without
+/// any further checks.
+pub struct UserSlice<T: UserCopyable> {
+    ptr: TUA<T>,
+    len: usize,
+}
+
+impl<T: UserCopyable> Clone for UserSlice<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: UserCopyable> Copy for UserSlice<T> {}
+
+impl<T: UserCopyable> UserSlice<T> {
+    pub fn new(ptr: TUA<T>, len: usize) -> Result<Self> {
+        let bytes = len
+            .checked_mul(size_of::<T>())
+            .ok_or(KernelError::InvalidValue)?;
+
+        ptr.value().checked_add(bytes).ok_or(KernelError::Fault)?;
+
+        Ok(Self { ptr, len })
+    }
+
+    /// The size of the slice in bytes.
+    pub fn byte_len(&self) -> usize {
+        self.len * size_of::<T>()
+    }
+
+    /// Copies the entire slice into a new `Vec`.
+    pub async fn read_to_vec(&self) -> Result<Vec<T>> {
+        let mut vec = Vec::<T>::new();
+
+        vec.try_reserve_exact(self.len)
+            .map_err(|_| KernelError::NoMemory)?;
+
+        unsafe {
+            ArchImpl::copy_from_user(
+                self.ptr.to_untyped(),
+                vec.as_mut_ptr().cast(),
+                self.byte_len(),
+            )
+            .await?;
+
+            // SAFETY: The copy above initialised all `len` elements.
+            vec.set_len(self.len);
+        }
+
+        Ok(vec)
+    }
+
+    /// Copies `src` to the start of the slice.
+    ///
+    /// Returns [`KernelError::RangeError`] if `src` doesn't fit.
+    pub async fn write(&self, src: &[T]) -> Result<()> {
+        if src.len() > self.len {
+            return Err(KernelError::RangeError);
+        }
+
+        unsafe {
+            ArchImpl::copy_to_user(src.as_ptr().cast(), self.ptr.to_untyped(), size_of_val(src))
+                .await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UserSlice;
+    use libkernel::{error::KernelError, memory::address::TUA};
+    use moss_macros::ktest;
+
+    #[ktest]
+    fn user_slice_rejects_overflow() {
+        assert!(matches!(
+            UserSlice::new(TUA::<u64>::from_value(0x1000), usize::MAX / 4),
+            Err(KernelError::InvalidValue)
+        ));
+        assert!(matches!(
+            UserSlice::new(TUA::<u8>::from_value(usize::MAX - 4), 8),
+            Err(KernelError::Fault)
+        ));
+
+        let slice = UserSlice::new(TUA::<u32>::from_value(0x1000), 4).unwrap();
+        assert_eq!(slice.byte_len(), 16);
+    }
+}
@@ -0,0 +1,634 @@
+//! userfaultfd(2): delivering page faults on registered ranges to a userspace
+//! handler.
+//!
+//! Missing-page faults within a registered range don't allocate memory.
+//! Instead, a `uffd_msg` is queued on the userfaultfd and the faulting task
+//! sleeps until the handler resolves the fault with `UFFDIO_COPY`,
+//! `UFFDIO_ZEROPAGE` or `UFFDIO_WAKE`.
+
+use super::{
+    page::ClaimedPage,
+    uaccess::{UserCopyable, copy_from_user, copy_from_user_slice, copy_to_user},
+};
+use crate::{
+    fs::{
+        fops::FileOps,
+        open_file::{FileCtx, OpenFile},
+    },
+    process::{
+        ProcVM,
+        fd_table::FdFlags,
+        thread_group::signal::{InterruptResult, Interruptable},
+    },
+    sched::syscall_ctx::ProcessCtx,
+    sync::{CondVar, SpinLock},
+};
+use alloc::{
+    boxed::Box,
+    collections::{btree_set::BTreeSet, vec_deque::VecDeque},
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use async_trait::async_trait;
+use core::pin::Pin;
+use libkernel::{
+    error::{FsError, KernelError, MapError, Result, syscall_error::kern_err_to_syscall},
+    fs::OpenFlags,
+    memory::{
+        PAGE_SIZE,
+        address::{TUA, UA, VA},
+        paging::permissions::PtePermissions,
+        proc_vm::{
+            address_space::UserAddressSpace,
+            vmarea::{AccessKind, VMAFlags},
+        },
+        region::VirtMemoryRegion,
+    },
+    sync::condvar::WakeupType,
+};
+
+const UFFD_API: u64 = 0xaa;
+
+const UFFD_USER_MODE_ONLY: i32 = 1;
+
+const _UFFDIO_REGISTER: u64 = 0x00;
+const _UFFDIO_UNREGISTER: u64 = 0x01;
+const _UFFDIO_WAKE: u64 = 0x02;
+const _UFFDIO_COPY: u64 = 0x03;
+const _UFFDIO_ZEROPAGE: u64 = 0x04;
+const _UFFDIO_API: u64 = 0x3f;
+
+const UFFDIO_API: usize = 0xc018_aa3f;
+const UFFDIO_REGISTER: usize = 0xc020_aa00;
+const UFFDIO_UNREGISTER: usize = 0x8010_aa01;
+const UFFDIO_WAKE: usize = 0x8010_aa02;
+const UFFDIO_COPY: usize = 0xc028_aa03;
+const UFFDIO_ZEROPAGE: usize = 0xc020_aa04;
+
+/// The ioctls available on a userfaultfd once the API handshake is complete.
+const UFFD_API_IOCTLS: u64 = 1 << _UFFDIO_REGISTER | 1 << _UFFDIO_UNREGISTER | 1 << _UFFDIO_API;
+
+/// The ioctls available on a registered range.
+const UFFD_API_RANGE_IOCTLS: u64 = 1 << _UFFDIO_WAKE | 1 << _UFFDIO_COPY | 1 << _UFFDIO_ZEROPAGE;
+
+const UFFDIO_REGISTER_MODE_MISSING: u64 = 1 << 0;
+
+const UFFDIO_COPY_MODE_DONTWAKE: u64 = 1 << 0;
+const UFFDIO_ZEROPAGE_MODE_DONTWAKE: u64 = 1 << 0;
+
+const UFFD_EVENT_PAGEFAULT: u8 = 0x12;
+const UFFD_PAGEFAULT_FLAG_WRITE: u64 = 1 << 0;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct UffdMsg {
+    event: u8,
+    _reserved1: u8,
+    _reserved2: u16,
+    _reserved3: u32,
+    flags: u64,
+    address: u64,
+    ptid: u32,
+    _pad: u32,
+}
+
+unsafe impl UserCopyable for UffdMsg {}
+
+impl UffdMsg {
+    fn new_pagefault(addr: VA, access_kind: AccessKind) -> Self {
+        Self {
+            event: UFFD_EVENT_PAGEFAULT,
+            _reserved1: 0,
+            _reserved2: 0,
+            _reserved3: 0,
+            flags: if access_kind == AccessKind::Write {
+                UFFD_PAGEFAULT_FLAG_WRITE
+            } else {
+                0
+            },
+            address: addr.value() as _,
+            ptid: 0,
+            _pad: 0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct UffdioApi {
+    api: u64,
+    features: u64,
+    ioctls: u64,
+}
+
+unsafe impl UserCopyable for UffdioApi {}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct UffdioRange {
+    start: u64,
+    len: u64,
+}
+
+unsafe impl UserCopyable for UffdioRange {}
+
+impl UffdioRange {
+    fn to_region(self) -> Result<VirtMemoryRegion> {
+        let region = VirtMemoryRegion::new(VA::from_value(self.start as _), self.len as _);
+
+        if self.len == 0 || !region.is_page_aligned() {
+            return Err(KernelError::InvalidValue);
+        }
+
+        Ok(region)
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct UffdioRegister {
+    range: UffdioRange,
+    mode: u64,
+    ioctls: u64,
+}
+
+unsafe impl UserCopyable for UffdioRegister {}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct UffdioCopy {
+    dst: u64,
+    src: u64,
+    len: u64,
+    mode: u64,
+    copy: i64,
+}
+
+unsafe impl UserCopyable for UffdioCopy {}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct UffdioZeropage {
+    range: UffdioRange,
+    mode: u64,
+    zeropage: i64,
+}
+
+unsafe impl UserCopyable for UffdioZeropage {}
+
+struct UffdState {
+    /// Fault messages which haven't been read by the handler yet.
+    events: VecDeque<UffdMsg>,
+    /// Page addresses of faults which are awaiting resolution.
+    pending: BTreeSet<usize>,
+    /// Ranges registered with this userfaultfd.
+    ranges: Vec<VirtMemoryRegion>,
+    /// Set once the userfaultfd has been closed; faulting tasks are woken and
+    /// further faults are resolved by the kernel.
+    released: bool,
+}
+
+/// A userfaultfd context, shared between the file and any faulting tasks.
+pub struct UffdCtx {
+    vm: Weak<SpinLock<ProcVM>>,
+    state: CondVar<UffdState>,
+}
+
+/// All live userfaultfd contexts in the system.
+static UFFD_CTXS: SpinLock<Vec<Weak<UffdCtx>>> = SpinLock::new(Vec::new());
+
+/// Find the userfaultfd context that has registered `addr` in `proc_vm`.
+pub fn find_uffd_ctx(proc_vm: &Arc<SpinLock<ProcVM>>, addr: VA) -> Option<Arc<UffdCtx>> {
+    UFFD_CTXS
+        .lock_save_irq()
+        .iter()
+        .filter_map(Weak::upgrade)
+        .find(|ctx| Weak::as_ptr(&ctx.vm) == Arc::as_ptr(proc_vm) && ctx.is_registered(addr))
+}
+
+impl UffdCtx {
+    fn with_state<T>(&self, f: impl FnOnce(&mut UffdState) -> T) -> T {
+        let mut ret = None;
+
+        self.state.update(|state| {
+            ret = Some(f(state));
+            WakeupType::None
+        });
+
+        ret.unwrap()
+    }
+
+    fn is_registered(&self, addr: VA) -> bool {
+        self.with_state(|state| {
+            !state.released && state.ranges.iter().any(|r| r.contains_address(addr))
+        })
+    }
+
+    /// Report a missing-page fault at `addr` to the handler and sleep until
+    /// the fault has been resolved, or a signal interrupts the wait.
+    pub async fn handle_fault(&self, addr: VA, access_kind: AccessKind) -> Result<()> {
+        let page = addr.page_aligned();
+
+        self.state.update(|state| {
+            if state.released {
+                return WakeupType::None;
+            }
+
+            // Only report the first fault on a given page.
+            if state.pending.insert(page.value()) {
+                state
+                    .events
+                    .push_back(UffdMsg::new_pagefault(page, access_kind));
+            }
+
+            WakeupType::All
+        });
+
+        match self
+            .state
+            .wait_until(|state| {
+                (state.released || !state.pending.contains(&page.value())).then_some(())
+            })
+            .interruptable()
+            .await
+        {
+            InterruptResult::Interrupted => Err(KernelError::Interrupted),
+            InterruptResult::Uninterrupted(()) => Ok(()),
+        }
+    }
+
+    /// Wake any tasks waiting on faults within `region`.
+    fn wake(&self, region: VirtMemoryRegion) {
+        self.state.update(|state| {
+            state
+                .pending
+                .retain(|&page| !region.contains_address(VA::from_value(page)));
+
+            WakeupType::All
+        });
+    }
+
+    /// Map pages at `region`, copying their contents from `src` or zeroing
+    /// them if `src` is `None`. Returns the number of bytes that were mapped.
+    async fn map_pages(&self, region: VirtMemoryRegion, src: Option<UA>) -> Result<usize> {
+        let vm = self.vm.upgrade().ok_or(KernelError::NoProcess)?;
+        let mut mapped = 0;
+
+        while mapped < region.size() {
+            let va = region.start_address().add_bytes(mapped);
+
+            let mut page = ClaimedPage::alloc_zeroed()?;
+
+            if let Some(src) = src
+                && let Err(e) =
+                    copy_from_user_slice(src.add_bytes(mapped), page.as_slice_mut()).await
+            {
+                return if mapped > 0 { Ok(mapped) } else { Err(e) };
+            }
+
+            let mut vm = vm.lock_save_irq();
+
+            // The range may have been unmapped or unregistered while we were
+            // copying the page contents.
+            let perms = vm
+                .mm()
+                .find_vma(va)
+                .filter(|_| self.is_registered(va))
+                .map(|vma| PtePermissions::from(vma.permissions()));
+
+            let ret = match perms {
+                Some(perms) => {
+                    vm.mm_mut()
+                        .address_space_mut()
+                        .map_page(page.pa().to_pfn(), va, perms)
+                }
+                None => Err(FsError::NotFound.into()),
+            };
+
+            match ret {
+                Ok(()) => {
+                    // The page is now owned by the address space.
+                    page.leak();
+                    mapped += PAGE_SIZE;
+                }
+                // Report a partial mapping; the caller can retry the rest.
+                Err(_) if mapped > 0 => break,
+                Err(KernelError::MappingError(MapError::AlreadyMapped)) => {
+                    return Err(FsError::AlreadyExists.into());
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(mapped)
+    }
+}
+
+pub struct UserFaultFd {
+    ctx: Arc<UffdCtx>,
+    api_done: bool,
+}
+
+impl UserFaultFd {
+    fn new(vm: &Arc<SpinLock<ProcVM>>) -> Self {
+        let ctx = Arc::new(UffdCtx {
+            vm: Arc::downgrade(vm),
+            state: CondVar::new(UffdState {
+                events: VecDeque::new(),
+                pending: BTreeSet::new(),
+                ranges: Vec::new(),
+                released: false,
+            }),
+        });
+
+        let mut ctxs = UFFD_CTXS.lock_save_irq();
+        ctxs.retain(|ctx| ctx.strong_count() > 0);
+        ctxs.push(Arc::downgrade(&ctx));
+
+        Self {
+            ctx,
+            api_done: false,
+        }
+    }
+
+    async fn read_impl(&mut self, buf: UA, count: usize, nonblock: bool) -> Result<usize> {
+        let msg_size = size_of::<UffdMsg>();
+
+        if count < msg_size {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let take_msg = |state: &mut UffdState| state.events.pop_front();
+
+        let msg = match self.ctx.with_state(take_msg) {
+            Some(msg) => msg,
+            None if nonblock => return Err(KernelError::TryAgain),
+            None => match self.ctx.state.wait_until(take_msg).interruptable().await {
+                InterruptResult::Interrupted => return Err(KernelError::Interrupted),
+                InterruptResult::Uninterrupted(msg) => msg,
+            },
+        };
+
+        copy_to_user(buf.cast(), msg).await?;
+
+        Ok(msg_size)
+    }
+
+    async fn api(&mut self, argp: TUA<UffdioApi>) -> Result<usize> {
+        let mut api = copy_from_user(argp).await?;
+
+        if self.api_done {
+            return Err(KernelError::InvalidValue);
+        }
+
+        if api.api != UFFD_API || api.features != 0 {
+            api.features = 0;
+            api.ioctls = 0;
+            copy_to_user(argp, api).await?;
+
+            return Err(KernelError::InvalidValue);
+        }
+
+        api.ioctls = UFFD_API_IOCTLS;
+        copy_to_user(argp, api).await?;
+
+        self.api_done = true;
+
+        Ok(0)
+    }
+
+    async fn register(&mut self, argp: TUA<UffdioRegister>) -> Result<usize> {
+        let mut reg = copy_from_user(argp).await?;
+        let region = reg.range.to_region()?;
+
+        // Only missing-page tracking is supported.
+        if reg.mode != UFFDIO_REGISTER_MODE_MISSING {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let vm = self.ctx.vm.upgrade().ok_or(KernelError::NoProcess)?;
+
+        {
+            let mut vm = vm.lock_save_irq();
+
+            // Userfaults are only supported on anonymous memory.
+            if vm
+                .mm()
+                .iter_vmas()
+                .any(|vma| vma.region().overlaps(region) && vma.is_file_backed())
+            {
+                return Err(KernelError::InvalidValue);
+            }
+
+            vm.mm_mut()
+                .update_flags(region, VMAFlags::UFFD_MISSING, VMAFlags::empty())?;
+        }
+
+        self.ctx.with_state(|state| state.ranges.push(region));
+
+        reg.ioctls = UFFD_API_RANGE_IOCTLS;
+        copy_to_user(argp, reg).await?;
+
+        Ok(0)
+    }
+
+    async fn unregister(&mut self, argp: TUA<UffdioRange>) -> Result<usize> {
+        let region = copy_from_user(argp).await?.to_region()?;
+
+        let vm = self.ctx.vm.upgrade().ok_or(KernelError::NoProcess)?;
+
+        vm.lock_save_irq().mm_mut().update_flags(
+            region,
+            VMAFlags::empty(),
+            VMAFlags::UFFD_MISSING,
+        )?;
+
+        self.ctx.with_state(|state| {
+            state.ranges = state
+                .ranges
+                .iter()
+                .flat_map(|r| {
+                    let (left, right) = r.punch_hole(region);
+                    [left, right]
+                })
+                .flatten()
+                .collect();
+        });
+
+        // Faults in the unregistered range are now handled by the kernel.
+        self.ctx.wake(region);
+
+        Ok(0)
+    }
+
+    async fn copy(&mut self, argp: TUA<UffdioCopy>) -> Result<usize> {
+        let mut copy = copy_from_user(argp).await?;
+
+        let region = UffdioRange {
+            start: copy.dst,
+            len: copy.len,
+        }
+        .to_region()?;
+
+        if copy.mode & !UFFDIO_COPY_MODE_DONTWAKE != 0
+            || !(copy.src as usize).is_multiple_of(PAGE_SIZE)
+        {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let src = UA::from_value(copy.src as _);
+
+        let ret = self.ctx.map_pages(region, Some(src)).await;
+
+        copy.copy = match &ret {
+            Ok(n) => *n as _,
+            Err(e) => kern_err_to_syscall(e.clone()) as _,
+        };
+        copy_to_user(argp, copy).await?;
+
+        let copied = ret?;
+
+        if copy.mode & UFFDIO_COPY_MODE_DONTWAKE == 0 {
+            self.ctx
+                .wake(VirtMemoryRegion::new(region.start_address(), copied));
+        }
+
+        Ok(0)
+    }
+
+    async fn zeropage(&mut self, argp: TUA<UffdioZeropage>) -> Result<usize> {
+        let mut zp = copy_from_user(argp).await?;
+        let region = zp.range.to_region()?;
+
+        if zp.mode & !UFFDIO_ZEROPAGE_MODE_DONTWAKE != 0 {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let ret = self.ctx.map_pages(region, None).await;
+
+        zp.zeropage = match &ret {
+            Ok(n) => *n as _,
+            Err(e) => kern_err_to_syscall(e.clone()) as _,
+        };
+        copy_to_user(argp, zp).await?;
+
+        let zeroed = ret?;
+
+        if zp.mode & UFFDIO_ZEROPAGE_MODE_DONTWAKE == 0 {
+            self.ctx
+                .wake(VirtMemoryRegion::new(region.start_address(), zeroed));
+        }
+
+        Ok(0)
+    }
+}
+
+impl Drop for UserFaultFd {
+    fn drop(&mut self) {
+        let ranges = self.ctx.with_state(|state| {
+            state.released = true;
+            core::mem::take(&mut state.ranges)
+        });
+
+        // Hand any outstanding faults back to the kernel.
+        self.ctx.state.update(|_| WakeupType::All);
+
+        if let Some(vm) = self.ctx.vm.upgrade() {
+            let mut vm = vm.lock_save_irq();
+
+            for range in ranges {
+                let _ = vm
+                    .mm_mut()
+                    .update_flags(range, VMAFlags::empty(), VMAFlags::UFFD_MISSING);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl FileOps for UserFaultFd {
+    async fn read(&mut self, ctx: &mut FileCtx, buf: UA, count: usize) -> Result<usize> {
+        self.read_impl(buf, count, ctx.flags.contains(OpenFlags::O_NONBLOCK))
+            .await
+    }
+
+    async fn readat(&mut self, buf: UA, count: usize, _offset: u64) -> Result<usize> {
+        self.read_impl(buf, count, false).await
+    }
+
+    async fn writeat(&mut self, _buf: UA, _count: usize, _offset: u64) -> Result<usize> {
+        Err(KernelError::InvalidValue)
+    }
+
+    async fn ioctl(&mut self, _ctx: &mut FileCtx, request: usize, argp: usize) -> Result<usize> {
+        if request == UFFDIO_API {
+            return self.api(TUA::from_value(argp)).await;
+        }
+
+        // All other operations require the API handshake first.
+        if !self.api_done {
+            return Err(KernelError::InvalidValue);
+        }
+
+        match request {
+            UFFDIO_REGISTER => self.register(TUA::from_value(argp)).await,
+            UFFDIO_UNREGISTER => self.unregister(TUA::from_value(argp)).await,
+            UFFDIO_WAKE => {
+                let region = copy_from_user(TUA::<UffdioRange>::from_value(argp))
+                    .await?
+                    .to_region()?;
+
+                self.ctx.wake(region);
+
+                Ok(0)
+            }
+            UFFDIO_COPY => self.copy(TUA::from_value(argp)).await,
+            UFFDIO_ZEROPAGE => self.zeropage(TUA::from_value(argp)).await,
+            _ => Err(KernelError::InvalidValue),
+        }
+    }
+
+    fn poll_read_ready(&self) -> Pin<Box<dyn Future<Output = Result<()>> + 'static + Send>> {
+        let ctx = self.ctx.clone();
+
+        Box::pin(async move {
+            ctx.state
+                .wait_until(|state| (!state.events.is_empty()).then_some(()))
+                .await;
+
+            Ok(())
+        })
+    }
+}
+
+pub fn sys_userfaultfd(ctx: &ProcessCtx, flags: i32) -> Result<usize> {
+    let allowed_flags =
+        (OpenFlags::O_NONBLOCK | OpenFlags::O_CLOEXEC).bits() as i32 | UFFD_USER_MODE_ONLY;
+
+    if flags & !allowed_flags != 0 {
+        return Err(KernelError::InvalidValue);
+    }
+
+    let file_flags = if flags & OpenFlags::O_NONBLOCK.bits() as i32 != 0 {
+        OpenFlags::O_NONBLOCK
+    } else {
+        OpenFlags::empty()
+    };
+
+    let fd_flags = if flags & OpenFlags::O_CLOEXEC.bits() as i32 != 0 {
+        FdFlags::CLOEXEC
+    } else {
+        FdFlags::empty()
+    };
+
+    let uffd = UserFaultFd::new(&ctx.shared().vm);
+    let file = Arc::new(OpenFile::new(Box::new(uffd), file_flags));
+
+    let fd = ctx
+        .shared()
+        .fd_table
+        .lock_save_irq()
+        .insert_with_flags(file, fd_flags)?;
+
+    Ok(fd.as_raw() as _)
+}
@@ -0,0 +1,207 @@
+//! Virtually contiguous kernel allocations.
+//!
+//! `vmalloc` maps individually allocated (and therefore potentially physically
+//! discontiguous) pages into a contiguous range of the kernel's vmalloc area.
+//! It should be used for large buffers where physical contiguity isn't
+//! required, since high-order allocations from the buddy allocator may fail
+//! once physical memory is fragmented.
+
+use super::page::ClaimedPage;
+use crate::{
+    arch::{Arch, ArchImpl},
+    sync::SpinLock,
+};
+use core::slice;
+use libkernel::{
+    error::Result,
+    memory::{
+        PAGE_SIZE,
+        allocators::vmalloc::VmallocArena,
+        paging::permissions::PtePermissions,
+        proc_vm::address_space::{KernAddressSpace, VirtualMemory},
+        region::{PhysMemoryRegion, VirtMemoryRegion},
+    },
+};
+
+static VMALLOC_ARENA: SpinLock<VmallocArena> =
+    SpinLock::new(VmallocArena::new(ArchImpl::VMALLOC_AREA));
+
+/// A zero-initialised, virtually contiguous kernel buffer. The backing pages
+/// are unmapped and freed on drop.
+pub struct VmallocBuf {
+    region: VirtMemoryRegion,
+    len: usize,
+}
+
+// SAFETY: The buffer exclusively owns its mapping.
+unsafe impl Send for VmallocBuf {}
+unsafe impl Sync for VmallocBuf {}
+
+impl VmallocBuf {
+    pub fn as_ptr(&self) -> *const u8 {
+        self.region.start_address().as_ptr().cast()
+    }
+
+    pub fn as_ptr_mut(&mut self) -> *mut u8 {
+        self.region.start_address().as_ptr_mut().cast()
+    }
+
+    /// Returns the buffer as a byte slice.
+    pub fn as_slice(&self) -> &[u8] {
+        // SAFETY: The region is mapped for the lifetime of `self`.
+        unsafe { slice::from_raw_parts(self.as_ptr(), self.len) }
+    }
+
+    /// Returns the buffer as a mutable byte slice.
+    pub fn as_slice_mut(&mut self) -> &mut [u8] {
+        // SAFETY: The region is mapped for the lifetime of `self`, and we hold
+        // a mutable borrow.
+        unsafe { slice::from_raw_parts_mut(self.as_ptr_mut(), self.len) }
+    }
+}
+
+impl Drop for VmallocBuf {
+    fn drop(&mut self) {
+        let pages = ArchImpl::kern_address_space()
+            .lock_save_irq()
+            .unmap_range(self.region)
+            .expect("Failed to unmap vmalloc region");
+
+        for pfn in pages {
+            // SAFETY: These pages were allocated by `vmalloc` and were only
+            // reachable through this buffer's mapping, which is now gone.
+            drop(unsafe { ClaimedPage::from_pfn(pfn) });
+        }
+
+        VMALLOC_ARENA
+            .lock_save_irq()
+            .free(self.region.start_address())
+            .expect("vmalloc region should be allocated");
+    }
+}
+
+/// Returns the number of bytes of the vmalloc area currently allocated.
+pub fn vmalloc_used() -> usize {
+    VMALLOC_ARENA.lock_save_irq().allocated_bytes()
+}
+
+/// Back each page of `region` with a freshly allocated, zeroed page.
+fn populate(region: VirtMemoryRegion) -> Result<()> {
+    for va in region.iter_pages() {
+        let page = ClaimedPage::alloc_zeroed()?;
+
+        ArchImpl::kern_address_space().lock_save_irq().map_normal(
+            PhysMemoryRegion::new(page.pa(), PAGE_SIZE),
+            VirtMemoryRegion::new(va, PAGE_SIZE),
+            PtePermissions::rw(false),
+        )?;
+
+        // The page is now owned by the mapping.
+        page.leak();
+    }
+
+    Ok(())
+}
+
+/// Allocate a zeroed, virtually contiguous buffer of `size` bytes.
+pub fn vmalloc(size: usize) -> Result<VmallocBuf> {
+    let region = VMALLOC_ARENA.lock_save_irq().alloc(size)?;
+
+    // If we fail part-way through, dropping the buffer tears down whatever has
+    // been mapped so far.
+    let buf = VmallocBuf { region, len: size };
+
+    populate(region)?;
+
+    Ok(buf)
+}
+
+/// Allocate a kernel stack of `size` bytes, which must be a power of two.
+///
+/// The stack occupies the upper half of a vmalloc region of twice its size,
+/// aligned to that size. The lower half is left unmapped as a guard, so that
+/// an overflow faults rather than running into a neighbouring allocation.
+/// Kernel stacks live for the lifetime of the kernel and are never freed.
+pub fn vmalloc_kernel_stack(size: usize) -> Result<VirtMemoryRegion> {
+    let region = VMALLOC_ARENA
+        .lock_save_irq()
+        .alloc_aligned(2 * size, 2 * size)?;
+
+    let buf = VmallocBuf {
+        region,
+        len: region.size(),
+    };
+
+    let stack = VirtMemoryRegion::new(region.start_address().add_bytes(size), size);
+
+    populate(stack)?;
+
+    // The stack is never freed, so its mapping must outlive the buffer.
+    core::mem::forget(buf);
+
+    Ok(stack)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{vmalloc, vmalloc_kernel_stack};
+    use crate::arch::ArchImpl;
+    use libkernel::memory::{
+        PAGE_SIZE,
+        address::VA,
+        proc_vm::address_space::{KernAddressSpace, VirtualMemory},
+        region::VirtMemoryRegion,
+    };
+    use moss_macros::ktest;
+
+    #[ktest]
+    fn vmalloc_alloc_and_free() {
+        let mut buf = vmalloc(3 * PAGE_SIZE + 1).unwrap();
+        assert_eq!(buf.as_slice().len(), 3 * PAGE_SIZE + 1);
+        assert!(buf.as_slice().iter().all(|&b| b == 0));
+
+        for (i, b) in buf.as_slice_mut().iter_mut().enumerate() {
+            *b = i as u8;
+        }
+
+        for (i, b) in buf.as_slice().iter().enumerate() {
+            assert_eq!(*b, i as u8);
+        }
+
+        // The whole of the last page is backed.
+        let region = VirtMemoryRegion::new(VA::from_ptr(buf.as_ptr().cast()), 4 * PAGE_SIZE);
+        drop(buf);
+
+        // The mapping and backing pages should have been released.
+        assert!(
+            ArchImpl::kern_address_space()
+                .lock_save_irq()
+                .unmap_range(region)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[ktest]
+    fn vmalloc_kernel_stack_guard() {
+        let size = 8 * PAGE_SIZE;
+        let stack = vmalloc_kernel_stack(size).unwrap();
+
+        assert_eq!(stack.size(), size);
+        assert_eq!(stack.start_address().value() % (2 * size), size);
+
+        // SAFETY: The stack is mapped and never freed, and nothing else uses
+        // it.
+        unsafe { stack.start_address().as_ptr_mut().cast::<u8>().write(1) };
+
+        // The guard below the stack must be unmapped.
+        let guard = VirtMemoryRegion::new(stack.start_address().sub_bytes(size), size);
+        assert!(
+            ArchImpl::kern_address_space()
+                .lock_save_irq()
+                .unmap_range(guard)
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
@@ -0,0 +1,227 @@
+//! The sizes of sockets' buffers, and the memory they're charged for.
+//!
+//! A socket starts with buffers of `net.core.rmem_default` and
+//! `net.core.wmem_default` bytes, which `SO_RCVBUF` and `SO_SNDBUF` can change,
+//! up to `net.core.rmem_max` and `net.core.wmem_max`. Every byte of buffer is
+//! charged to the process which made the socket, and to the system as a whole,
+//! so that no one process can fill memory with sockets.
+
+use crate::net::sockopt::SockOpt;
+use crate::process::thread_group::Tgid;
+use crate::sched::current_work;
+use crate::sync::SpinLock;
+use alloc::collections::BTreeMap;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use libkernel::error::{KernelError, Result};
+
+/// The smallest a buffer can be made, as Linux's `SOCK_MIN_RCVBUF`.
+pub const SOCK_MIN_BUF: usize = 2304;
+
+/// `net.core.rmem_default`: the size a socket's receive buffer starts at.
+pub static RMEM_DEFAULT: AtomicUsize = AtomicUsize::new(16 * 1024);
+/// `net.core.wmem_default`: the size a socket's send buffer starts at.
+pub static WMEM_DEFAULT: AtomicUsize = AtomicUsize::new(16 * 1024);
+/// `net.core.rmem_max`: the largest `SO_RCVBUF` can make a receive buffer.
+pub static RMEM_MAX: AtomicUsize = AtomicUsize::new(212992);
+/// `net.core.wmem_max`: the largest `SO_SNDBUF` can make a send buffer.
+pub static WMEM_MAX: AtomicUsize = AtomicUsize::new(212992);
+
+/// The most buffer memory the sockets one process has made can hold.
+const PROCESS_MEM_MAX: usize = 4 * 1024 * 1024;
+/// The most buffer memory all sockets together can hold.
+const SYSTEM_MEM_MAX: usize = 32 * 1024 * 1024;
+
+struct SocketMem {
+    total: usize,
+    by_process: BTreeMap<Tgid, usize>,
+}
+
+static SOCKET_MEM: SpinLock<SocketMem> = SpinLock::new(SocketMem {
+    total: 0,
+    by_process: BTreeMap::new(),
+});
+
+/// Buffer memory charged to a process, which is given back when dropped.
+struct BufferCharge {
+    tgid: Tgid,
+    bytes: usize,
+}
+
+impl BufferCharge {
+    /// Charges `bytes` to the current process.
+    fn new(bytes: usize) -> Result<Self> {
+        let mut charge = Self {
+            tgid: current_work().process.tgid,
+            bytes: 0,
+        };
+        charge.resize(bytes)?;
+
+        Ok(charge)
+    }
+
+    /// Changes the charge to `bytes`. Growing it fails with `NoBufferSpace` if
+    /// that would take the process or the system over its limit, leaving the
+    /// charge as it was.
+    fn resize(&mut self, bytes: usize) -> Result<()> {
+        let mut mem = SOCKET_MEM.lock_save_irq();
+        let process = mem.by_process.get(&self.tgid).copied().unwrap_or(0) - self.bytes + bytes;
+        let total = mem.total - self.bytes + bytes;
+
+        if bytes > self.bytes && (process > PROCESS_MEM_MAX || total > SYSTEM_MEM_MAX) {
+            return Err(KernelError::NoBufferSpace);
+        }
+
+        mem.total = total;
+        if process == 0 {
+            mem.by_process.remove(&self.tgid);
+        } else {
+            mem.by_process.insert(self.tgid, process);
+        }
+        self.bytes = bytes;
+
+        Ok(())
+    }
+}
+
+impl Drop for BufferCharge {
+    fn drop(&mut self) {
+        // Shrinking a charge can't fail.
+        let _ = self.resize(0);
+    }
+}
+
+/// The sizes of a socket's receive and send buffers.
+pub struct BufferSizes {
+    rx: usize,
+    tx: usize,
+    charge: BufferCharge,
+}
+
+impl BufferSizes {
+    /// Charges the current process for buffers of the default sizes.
+    pub fn new() -> Result<Self> {
+        Self::with_sizes(
+            RMEM_DEFAULT.load(Ordering::Relaxed),
+            WMEM_DEFAULT.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Charges the current process for buffers of the given sizes, e.g. those
+    /// of the listening socket a connection is accepted from.
+    pub fn with_sizes(rx: usize, tx: usize) -> Result<Self> {
+        Ok(Self {
+            rx,
+            tx,
+            charge: BufferCharge::new(rx + tx)?,
+        })
+    }
+
+    pub fn rx(&self) -> usize {
+        self.rx
+    }
+
+    pub fn tx(&self) -> usize {
+        self.tx
+    }
+
+    /// Returns the size `SO_RCVBUF` or `SO_SNDBUF` reports.
+    pub fn get(&self, opt: SockOpt) -> i32 {
+        match opt {
+            SockOpt::RcvBuf => self.rx as i32,
+            _ => self.tx as i32,
+        }
+    }
+
+    /// Resizes the buffer `SO_RCVBUF` or `SO_SNDBUF` names to `value` bytes,
+    /// brought within the limits for its size, as on Linux. The socket must
+    /// then make new buffers of the new sizes.
+    pub fn set(&mut self, opt: SockOpt, value: i32) -> Result<()> {
+        let max = match opt {
+            SockOpt::RcvBuf => &RMEM_MAX,
+            _ => &WMEM_MAX,
+        };
+        let size = (value.max(0) as usize)
+            .clamp(SOCK_MIN_BUF, max.load(Ordering::Relaxed).max(SOCK_MIN_BUF));
+
+        let (rx, tx) = match opt {
+            SockOpt::RcvBuf => (size, self.tx),
+            _ => (self.rx, size),
+        };
+
+        self.charge.resize(rx + tx)?;
+        self.rx = rx;
+        self.tx = tx;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BufferSizes, PROCESS_MEM_MAX, SOCK_MIN_BUF, SOCKET_MEM, WMEM_MAX};
+    use crate::net::sockopt::SockOpt;
+    use crate::sched::current_work;
+    use core::sync::atomic::Ordering;
+    use libkernel::error::KernelError;
+    use moss_macros::ktest;
+
+    /// Returns the bytes charged to the system, and to the current process.
+    fn charged() -> (usize, usize) {
+        let tgid = current_work().process.tgid;
+        let mem = SOCKET_MEM.lock_save_irq();
+
+        (mem.total, mem.by_process.get(&tgid).copied().unwrap_or(0))
+    }
+
+    #[ktest]
+    fn charges_are_released_on_close() {
+        let (total, process) = charged();
+
+        let mut sizes = BufferSizes::with_sizes(8192, 4096).unwrap();
+        assert_eq!(charged(), (total + 12288, process + 12288));
+
+        sizes.set(SockOpt::SndBuf, 16384).unwrap();
+        assert_eq!(charged(), (total + 24576, process + 24576));
+
+        drop(sizes);
+        assert_eq!(charged(), (total, process));
+    }
+
+    #[ktest]
+    fn sizes_are_clamped_to_the_limits() {
+        let mut sizes = BufferSizes::with_sizes(8192, 8192).unwrap();
+
+        sizes.set(SockOpt::SndBuf, 1).unwrap();
+        assert_eq!(sizes.get(SockOpt::SndBuf), SOCK_MIN_BUF as i32);
+
+        sizes.set(SockOpt::SndBuf, i32::MAX).unwrap();
+        assert_eq!(
+            sizes.get(SockOpt::SndBuf),
+            WMEM_MAX.load(Ordering::Relaxed) as i32
+        );
+        assert_eq!(sizes.get(SockOpt::RcvBuf), 8192);
+    }
+
+    #[ktest]
+    fn charges_past_the_process_limit_fail() {
+        let before = charged();
+
+        assert!(matches!(
+            BufferSizes::with_sizes(PROCESS_MEM_MAX, 1),
+            Err(KernelError::NoBufferSpace)
+        ));
+        assert_eq!(charged(), before);
+
+        // A failed resize leaves the charge as it was.
+        let _held = BufferSizes::with_sizes(PROCESS_MEM_MAX - before.1 - 8192, 0).unwrap();
+        let mut sizes = BufferSizes::with_sizes(4096, 4096).unwrap();
+        let charged_now = charged();
+
+        assert!(matches!(
+            sizes.set(SockOpt::RcvBuf, 65536),
+            Err(KernelError::NoBufferSpace)
+        ));
+        assert_eq!(sizes.get(SockOpt::RcvBuf), 4096);
+        assert_eq!(charged(), charged_now);
+    }
+}
@@ -0,0 +1,179 @@
+//! Network devices, which drivers implement to plug into the network stack.
+//!
+//! A driver hands an [`Arc<dyn NetDevice>`] to
+//! [`register_device`](super::iface::register_device), which gives it an
+//! interface. From then on the stack pulls frames which have arrived out of
+//! the device, and pushes frames to send into it, through the adapter here.
+
+use super::packet::tap_frame;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use libkernel::error::{KernelError, Result};
+use smoltcp::phy::{self, DeviceCapabilities, Medium};
+use smoltcp::time::Instant;
+
+pub trait NetDevice: Send + Sync {
+    /// Sends `frame`, which is at most [`Self::mtu`] bytes long.
+    fn transmit(&self, frame: &[u8]) -> Result<()>;
+
+    /// Takes the next frame which has arrived, if there is one.
+    fn receive(&self) -> Option<Vec<u8>>;
+
+    /// Returns the longest frame the device can send, including the Ethernet
+    /// header if it has one.
+    fn mtu(&self) -> usize;
+
+    /// Returns the device's MAC address, or `None` if it carries bare IP
+    /// packets rather than Ethernet frames, as loopback does.
+    fn mac(&self) -> Option<[u8; 6]>;
+
+    fn medium(&self) -> Medium {
+        match self.mac() {
+            Some(_) => Medium::Ethernet,
+            None => Medium::Ip,
+        }
+    }
+
+    /// Whether every frame sent comes straight back, as on loopback, so that
+    /// packet sockets needn't see it twice.
+    fn loops_back(&self) -> bool {
+        false
+    }
+}
+
+/// Lets smoltcp drive a [`NetDevice`], showing packet sockets the frames that
+/// pass through it.
+pub(super) struct DeviceAdapter {
+    device: Arc<dyn NetDevice>,
+    /// The index of the device's interface.
+    index: u32,
+    /// The longest frame the stack may send, which starts as the device's
+    /// [`NetDevice::mtu`] and can be lowered.
+    mtu: usize,
+    stats: DeviceStats,
+}
+
+/// Counts of the frames which have passed through a device, and their bytes.
+#[derive(Default)]
+pub struct DeviceStats {
+    pub rx_packets: AtomicU64,
+    pub rx_bytes: AtomicU64,
+    pub tx_packets: AtomicU64,
+    pub tx_bytes: AtomicU64,
+    /// Frames which the device failed to send.
+    pub tx_dropped: AtomicU64,
+}
+
+impl DeviceAdapter {
+    pub fn new(device: Arc<dyn NetDevice>, index: u32) -> Self {
+        let mtu = device.mtu();
+
+        Self {
+            device,
+            index,
+            mtu,
+            stats: DeviceStats::default(),
+        }
+    }
+
+    pub fn device(&self) -> &dyn NetDevice {
+        &*self.device
+    }
+
+    pub fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    pub fn stats(&self) -> &DeviceStats {
+        &self.stats
+    }
+
+    /// Limits frames to `mtu` bytes, which can't be more than the device
+    /// takes.
+    pub fn set_mtu(&mut self, mtu: usize) -> Result<()> {
+        if mtu > self.device.mtu() {
+            return Err(KernelError::InvalidValue);
+        }
+
+        self.mtu = mtu;
+        Ok(())
+    }
+}
+
+impl phy::Device for DeviceAdapter {
+    type RxToken<'a> = RxToken;
+    type TxToken<'a> = TxToken<'a>;
+
+    fn receive(&mut self, _timestamp: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        let frame = self.device.receive()?;
+        tap_frame(self.index, self.device.medium(), &frame, false);
+
+        self.stats.rx_packets.fetch_add(1, Ordering::Relaxed);
+        self.stats
+            .rx_bytes
+            .fetch_add(frame.len() as u64, Ordering::Relaxed);
+
+        Some((RxToken(frame), TxToken(self)))
+    }
+
+    fn transmit(&mut self, _timestamp: Instant) -> Option<Self::TxToken<'_>> {
+        Some(TxToken(self))
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut caps = DeviceCapabilities::default();
+        caps.medium = self.device.medium();
+        caps.max_transmission_unit = self.mtu;
+        caps
+    }
+}
+
+pub(super) struct RxToken(Vec<u8>);
+
+impl phy::RxToken for RxToken {
+    fn consume<R, F>(self, f: F) -> R
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        f(&self.0)
+    }
+}
+
+pub(super) struct TxToken<'a>(&'a DeviceAdapter);
+
+impl phy::TxToken for TxToken<'_> {
+    fn consume<R, F>(self, len: usize, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        let mut frame = vec![0; len];
+        let result = f(&mut frame);
+
+        let DeviceAdapter {
+            device,
+            index,
+            stats,
+            ..
+        } = self.0;
+        if !device.loops_back() {
+            tap_frame(*index, device.medium(), &frame, true);
+        }
+
+        // As with a real link, a frame which can't be sent is dropped, and
+        // it's up to the protocol to notice.
+        match device.transmit(&frame) {
+            Ok(()) => {
+                stats.tx_packets.fetch_add(1, Ordering::Relaxed);
+                stats.tx_bytes.fetch_add(len as u64, Ordering::Relaxed);
+            }
+            Err(e) => {
+                stats.tx_dropped.fetch_add(1, Ordering::Relaxed);
+                log::warn!("Dropping frame which couldn't be sent: {e}");
+            }
+        }
+
+        result
+    }
+}
@@ -0,0 +1,481 @@
+//! ICMP sockets, which `ping` uses.
+//!
+//! There are two kinds, as on Linux. A raw socket, `SOCK_RAW`, which needs
+//! `CAP_NET_RAW`, sends ICMP messages just as it's given them, and receives
+//! them with an IP header in front. A ping socket, `SOCK_DGRAM`, only sends echo
+//! requests, which it stamps with its own identifier, and receives bare ICMP
+//! messages. Either way, the stack hands each socket the echo messages which
+//! carry the identifier it's bound to.
+
+use crate::fs::fops::FileOps;
+use crate::fs::open_file::{FileCtx, OpenFile};
+use crate::memory::uaccess::iovec::UserIoVec;
+use crate::memory::uaccess::{copy_from_user_slice, copy_to_user_slice};
+use crate::net::buffer::BufferSizes;
+use crate::net::port::{PortBinding, PortTable, Reuse};
+use crate::net::sockopt::SockOpt;
+use crate::net::sops::{RecvFlags, RecvMsg, SendFlags, SocketOps};
+use crate::net::timeout::{SocketTimeouts, block};
+use crate::net::{
+    AF_INET, IPPROTO_ICMP, SOCK_DGRAM, SOCK_RAW, ShutdownHow, SockAddr, process_packets, sockets,
+    wait_for_sockets,
+};
+use crate::sync::SpinLock;
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use async_trait::async_trait;
+use core::net::Ipv4Addr;
+use core::pin::Pin;
+use libkernel::error::{KernelError, Result};
+use libkernel::fs::OpenFlags;
+use libkernel::memory::address::UA;
+use smoltcp::iface::{SocketHandle, SocketSet};
+use smoltcp::phy::ChecksumCapabilities;
+use smoltcp::socket::icmp::{self, PacketBuffer, PacketMetadata, SendError};
+use smoltcp::wire::{IpAddress, IpEndpoint, IpProtocol, Ipv4Packet, Ipv4Repr};
+
+/// How many messages each of a socket's buffers can hold.
+const BUFFER_PACKETS: usize = 16;
+
+/// The length of an echo message's header: its type, code, checksum,
+/// identifier and sequence number.
+const ECHO_HEADER_LEN: usize = 8;
+const ECHO_REQUEST: u8 = 8;
+
+/// The TTL reported in the IP header raw sockets receive, which the stack
+/// doesn't pass on.
+const DEFAULT_TTL: u8 = 64;
+
+/// The identifiers ICMP sockets are bound to.
+static ICMP_IDENTS: PortTable = PortTable::new();
+
+pub struct IcmpSocket {
+    handle: SocketHandle,
+    /// `SOCK_RAW` or `SOCK_DGRAM`.
+    ty: i32,
+    /// The identifier the socket's bound to, which is given back when it's
+    /// dropped.
+    ident: SpinLock<Option<PortBinding>>,
+    /// Where messages go by default, once the socket's been connected.
+    remote: SpinLock<Option<IpAddress>>,
+    buffers: SpinLock<BufferSizes>,
+    timeouts: SocketTimeouts,
+}
+
+/// Makes a socket with buffers of the given sizes.
+fn new_inner(sizes: &BufferSizes) -> icmp::Socket<'static> {
+    let rx_buffer = PacketBuffer::new(
+        vec![PacketMetadata::EMPTY; BUFFER_PACKETS],
+        vec![0; sizes.rx()],
+    );
+    let tx_buffer = PacketBuffer::new(
+        vec![PacketMetadata::EMPTY; BUFFER_PACKETS],
+        vec![0; sizes.tx()],
+    );
+    icmp::Socket::new(rx_buffer, tx_buffer)
+}
+
+impl IcmpSocket {
+    pub fn new(ty: i32) -> Result<Self> {
+        let buffers = BufferSizes::new()?;
+        let handle = sockets().lock_save_irq().add(new_inner(&buffers));
+
+        Ok(Self {
+            handle,
+            ty,
+            ident: SpinLock::new(None),
+            remote: SpinLock::new(None),
+            buffers: SpinLock::new(buffers),
+            timeouts: SocketTimeouts::default(),
+        })
+    }
+
+    /// Binds the socket to the identifier `ident`, or an unused one if it's
+    /// zero, returning the identifier.
+    fn do_bind(&self, ident: u16) -> Result<u16> {
+        let mut bound = self.ident.lock_save_irq();
+        if bound.is_some() {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let binding = ICMP_IDENTS.bind(None, ident, Reuse::default())?;
+        let ident = binding.port();
+
+        sockets()
+            .lock_save_irq()
+            .get_mut::<icmp::Socket>(self.handle)
+            .bind(icmp::Endpoint::Ident(ident))
+            .map_err(|_| KernelError::InvalidValue)?;
+
+        *bound = Some(binding);
+        Ok(ident)
+    }
+
+    /// Returns the identifier the socket's bound to, if it has been.
+    fn bound_ident(&self) -> Option<u16> {
+        self.ident.lock_save_irq().as_ref().map(PortBinding::port)
+    }
+
+    async fn send_message(&self, mut message: Vec<u8>, remote: IpAddress) -> Result<usize> {
+        if message.len() < ECHO_HEADER_LEN {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let ident = u16::from_be_bytes([message[4], message[5]]);
+        if self.ty == SOCK_DGRAM {
+            // As on Linux, a ping socket only sends echo requests, and gives
+            // them its own identifier so the replies find their way back.
+            if message[0] != ECHO_REQUEST || message[1] != 0 {
+                return Err(KernelError::InvalidValue);
+            }
+
+            let ident = match self.bound_ident() {
+                Some(ident) => ident,
+                None => self.do_bind(0)?,
+            };
+            message[4..6].copy_from_slice(&ident.to_be_bytes());
+        } else if message[0] == ECHO_REQUEST && self.bound_ident().is_none() {
+            // Listen for the replies to the first echo request sent.
+            self.do_bind(ident)?;
+        }
+
+        let send = |message: &[u8]| {
+            sockets()
+                .lock_save_irq()
+                .get_mut::<icmp::Socket>(self.handle)
+                .send_slice(message, remote)
+        };
+
+        let result = match send(&message) {
+            // Sending the messages already queued makes room.
+            Err(SendError::BufferFull) => {
+                process_packets();
+                send(&message)
+            }
+            result => result,
+        };
+
+        result.map_err(|e| match e {
+            SendError::BufferFull => KernelError::TryAgain,
+            _ => KernelError::InvalidValue,
+        })?;
+
+        process_packets();
+
+        Ok(message.len())
+    }
+
+    /// Takes the next message, returning up to `count` bytes of it along with
+    /// its full length and sender.
+    async fn recv_message(
+        &self,
+        count: usize,
+        flags: RecvFlags,
+        nonblock: bool,
+    ) -> Result<(Vec<u8>, usize, IpAddress)> {
+        // The stack can't hand over a message without taking it.
+        if flags.contains(RecvFlags::MSG_PEEK) {
+            return Err(KernelError::OpNotSupported);
+        }
+
+        let handle = self.handle;
+        let raw = self.ty == SOCK_RAW;
+
+        let take_message = move |sockets: &mut SocketSet<'static>| {
+            let (message, from) = sockets.get_mut::<icmp::Socket>(handle).recv().ok()?;
+            let mut data = if raw {
+                with_ip_header(message, from)
+            } else {
+                message.to_vec()
+            };
+
+            let len = data.len();
+            data.truncate(count);
+            Some((data, len, from))
+        };
+
+        let message = take_message(&mut sockets().lock_save_irq());
+        match message {
+            Some(message) => Ok(message),
+            None if nonblock => Err(KernelError::TryAgain),
+            None => block(wait_for_sockets(take_message), self.timeouts.recv()).await,
+        }
+    }
+
+    /// Returns where a message sent without an address goes.
+    fn default_remote(&self) -> Result<IpAddress> {
+        self.remote.lock_save_irq().ok_or(KernelError::NotConnected)
+    }
+
+    /// Copies a message of `count` bytes in from `buf`.
+    async fn message_from_user(&self, buf: UA, count: usize) -> Result<Vec<u8>> {
+        self.check_message_len(count)?;
+
+        let mut data = vec![0; count];
+        copy_from_user_slice(buf, &mut data).await?;
+
+        Ok(data)
+    }
+
+    /// Checks that a message of `len` bytes fits in the send buffer.
+    fn check_message_len(&self, len: usize) -> Result<()> {
+        if len > self.buffers.lock_save_irq().tx() {
+            return Err(KernelError::MessageTooLong);
+        }
+
+        Ok(())
+    }
+}
+
+/// Puts an IPv4 header in front of `message`, as raw sockets receive.
+///
+/// The stack only passes on the ICMP message itself, so the header is made up
+/// from what's known of it: the sender, but not which of our addresses it was
+/// sent to.
+fn with_ip_header(message: &[u8], from: IpAddress) -> Vec<u8> {
+    let IpAddress::Ipv4(src_addr) = from else {
+        return message.to_vec();
+    };
+
+    let repr = Ipv4Repr {
+        src_addr,
+        dst_addr: Ipv4Addr::UNSPECIFIED,
+        next_header: IpProtocol::Icmp,
+        payload_len: message.len(),
+        hop_limit: DEFAULT_TTL,
+    };
+
+    let mut packet = vec![0; repr.buffer_len() + message.len()];
+    repr.emit(
+        &mut Ipv4Packet::new_unchecked(&mut packet),
+        &ChecksumCapabilities::default(),
+    );
+    packet[repr.buffer_len()..].copy_from_slice(message);
+
+    packet
+}
+
+fn is_nonblocking(ctx: &FileCtx, flags: RecvFlags) -> bool {
+    flags.contains(RecvFlags::MSG_DONTWAIT) || ctx.flags.contains(OpenFlags::O_NONBLOCK)
+}
+
+impl Drop for IcmpSocket {
+    fn drop(&mut self) {
+        sockets().lock_save_irq().remove(self.handle);
+    }
+}
+
+#[async_trait]
+impl SocketOps for IcmpSocket {
+    async fn bind(&self, addr: SockAddr) -> Result<()> {
+        let endpoint: IpEndpoint = addr.try_into()?;
+
+        // A ping socket takes its identifier from the port it's bound to. A
+        // raw socket has no ports, and only the address it's bound to, which
+        // isn't checked.
+        if self.ty == SOCK_DGRAM {
+            self.do_bind(endpoint.port)?;
+        }
+
+        Ok(())
+    }
+
+    async fn connect(&self, _ctx: &FileCtx, addr: SockAddr) -> Result<()> {
+        let remote: IpEndpoint = addr.try_into()?;
+        *self.remote.lock_save_irq() = Some(remote.addr);
+
+        Ok(())
+    }
+
+    async fn recv(
+        &mut self,
+        ctx: &mut FileCtx,
+        buf: UA,
+        count: usize,
+        flags: RecvFlags,
+    ) -> Result<(usize, Option<SockAddr>)> {
+        let (data, len, from) = self
+            .recv_message(count, flags, is_nonblocking(ctx, flags))
+            .await?;
+
+        copy_to_user_slice(&data, buf).await?;
+
+        let len = if flags.contains(RecvFlags::MSG_TRUNC) {
+            len
+        } else {
+            data.len()
+        };
+
+        Ok((len, Some(IpEndpoint::new(from, 0).into())))
+    }
+
+    async fn recvfrom(
+        &mut self,
+        ctx: &mut FileCtx,
+        buf: UA,
+        count: usize,
+        flags: RecvFlags,
+        _addr: Option<SockAddr>,
+    ) -> Result<(usize, Option<SockAddr>)> {
+        self.recv(ctx, buf, count, flags).await
+    }
+
+    async fn send(
+        &mut self,
+        _ctx: &mut FileCtx,
+        buf: UA,
+        count: usize,
+        _flags: SendFlags,
+    ) -> Result<usize> {
+        let remote = self.default_remote()?;
+        let message = self.message_from_user(buf, count).await?;
+
+        self.send_message(message, remote).await
+    }
+
+    async fn sendto(
+        &mut self,
+        _ctx: &mut FileCtx,
+        buf: UA,
+        count: usize,
+        _flags: SendFlags,
+        addr: SockAddr,
+    ) -> Result<usize> {
+        let remote: IpEndpoint = addr.try_into()?;
+        let message = self.message_from_user(buf, count).await?;
+
+        self.send_message(message, remote.addr).await
+    }
+
+    async fn sendmsg(
+        &mut self,
+        _ctx: &mut FileCtx,
+        iov: &UserIoVec,
+        _flags: SendFlags,
+        addr: Option<SockAddr>,
+        rights: Vec<Arc<OpenFile>>,
+    ) -> Result<usize> {
+        if !rights.is_empty() {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let remote = match addr {
+            Some(addr) => IpEndpoint::try_from(addr)?.addr,
+            None => self.default_remote()?,
+        };
+
+        // The vectors are gathered into a single message.
+        let count: usize = iov.iovs().iter().map(|vec| vec.iov_len).sum();
+        self.check_message_len(count)?;
+
+        let mut message = vec![0; count];
+        let read = iov.reader().read(&mut message).await?;
+        message.truncate(read);
+
+        self.send_message(message, remote).await
+    }
+
+    async fn recvmsg(
+        &mut self,
+        ctx: &mut FileCtx,
+        iov: &UserIoVec,
+        flags: RecvFlags,
+    ) -> Result<RecvMsg> {
+        let count: usize = iov.iovs().iter().map(|vec| vec.iov_len).sum();
+        let (data, len, from) = self
+            .recv_message(count, flags, is_nonblocking(ctx, flags))
+            .await?;
+
+        iov.writer().write(&data).await?;
+
+        Ok(RecvMsg {
+            len: if flags.contains(RecvFlags::MSG_TRUNC) {
+                len
+            } else {
+                data.len()
+            },
+            addr: Some(IpEndpoint::new(from, 0).into()),
+            rights: Vec::new(),
+            truncated: len > data.len(),
+        })
+    }
+
+    async fn shutdown(&self, _how: ShutdownHow) -> Result<()> {
+        Ok(())
+    }
+
+    fn getsockopt(&self, opt: SockOpt) -> Result<i32> {
+        match opt {
+            SockOpt::Type => Ok(self.ty),
+            SockOpt::Error => Ok(0),
+            SockOpt::SndBuf | SockOpt::RcvBuf => Ok(self.buffers.lock_save_irq().get(opt)),
+            SockOpt::Protocol => Ok(IPPROTO_ICMP),
+            SockOpt::Domain => Ok(AF_INET),
+            _ => Err(KernelError::NoProtocolOption),
+        }
+    }
+
+    fn setsockopt(&self, opt: SockOpt, value: i32) -> Result<()> {
+        match opt {
+            SockOpt::SndBuf | SockOpt::RcvBuf => {
+                // As for UDP, the buffers can only be swapped out before the
+                // socket's bound.
+                let ident = self.ident.lock_save_irq();
+                if ident.is_none() {
+                    let mut buffers = self.buffers.lock_save_irq();
+                    buffers.set(opt, value)?;
+
+                    *sockets()
+                        .lock_save_irq()
+                        .get_mut::<icmp::Socket>(self.handle) = new_inner(&buffers);
+                }
+            }
+            _ => return Err(KernelError::NoProtocolOption),
+        }
+
+        Ok(())
+    }
+
+    fn local_addr(&self) -> Result<SockAddr> {
+        // As on Linux, a ping socket's identifier stands in for its port.
+        let ident = self.bound_ident().unwrap_or(0);
+
+        Ok(IpEndpoint::new(IpAddress::Ipv4(Ipv4Addr::UNSPECIFIED), ident).into())
+    }
+
+    fn peer_addr(&self) -> Result<SockAddr> {
+        Ok(IpEndpoint::new(self.default_remote()?, 0).into())
+    }
+
+    fn timeouts(&self) -> Option<&SocketTimeouts> {
+        Some(&self.timeouts)
+    }
+
+    fn poll_read_ready(&self) -> Pin<Box<dyn Future<Output = Result<()>> + 'static + Send>> {
+        let handle = self.handle;
+
+        Box::pin(wait_for_sockets(move |sockets| {
+            sockets
+                .get::<icmp::Socket>(handle)
+                .can_recv()
+                .then_some(Ok(()))
+        }))
+    }
+
+    fn poll_write_ready(&self) -> Pin<Box<dyn Future<Output = Result<()>> + 'static + Send>> {
+        let handle = self.handle;
+
+        Box::pin(wait_for_sockets(move |sockets| {
+            sockets
+                .get::<icmp::Socket>(handle)
+                .can_send()
+                .then_some(Ok(()))
+        }))
+    }
+
+    fn as_file(self: Box<Self>) -> Box<dyn FileOps> {
+        self
+    }
+}
@@ -0,0 +1,477 @@
+//! The kernel's network interfaces.
+//!
+//! Each [`NetDevice`] a driver registers becomes an interface, with its own
+//! index, name and addresses, much as on Linux. Loopback, `lo`, is always
+//! there, as index 1. Every interface shares the one set of sockets, so
+//! [`Interfaces::poll`] runs the stack over all of them, and sockets reach the
+//! interface they need through [`interfaces`], e.g. to pick a source address
+//! when connecting, which is where the [`RoutingTable`] is consulted.
+
+use super::device::{DeviceAdapter, DeviceStats, NetDevice};
+use super::loopback::LoopbackDevice;
+use super::packet::{ARPHRD_ETHER, ARPHRD_LOOPBACK};
+use super::route::{Route, RoutingTable};
+use super::sysfs;
+use crate::drivers::timer::uptime;
+use crate::sched::current_work;
+use crate::sync::{OnceLock, SpinLock};
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::net::{Ipv4Addr, Ipv6Addr};
+use core::time::Duration;
+use libkernel::error::{FsError, KernelError, Result};
+use libkernel::proc::caps::CapabilitiesFlags;
+use smoltcp::iface::{Config, Context, Interface, PollResult, SocketSet};
+use smoltcp::phy::Medium;
+use smoltcp::time::Instant;
+use smoltcp::wire::{EthernetAddress, HardwareAddress, IpAddress, IpCidr};
+
+pub const IFF_UP: u32 = 0x1;
+pub const IFF_BROADCAST: u32 = 0x2;
+pub const IFF_LOOPBACK: u32 = 0x8;
+pub const IFF_RUNNING: u32 = 0x40;
+pub const IFF_MULTICAST: u32 = 0x1000;
+
+/// The smallest MTU an interface can have, which is what IPv4 needs.
+const MIN_MTU: usize = 68;
+
+/// The most times [`NetInterface::poll`] goes round the stack before giving
+/// the CPU back, so a flood of traffic can't hold it forever.
+const MAX_POLL_ROUNDS: usize = 64;
+
+pub struct NetInterface {
+    index: u32,
+    name: String,
+    iface: Interface,
+    device: DeviceAdapter,
+}
+
+impl NetInterface {
+    fn new(index: u32, name: String, device: Arc<dyn NetDevice>) -> Self {
+        let hardware_addr = match device.medium() {
+            Medium::Ethernet => {
+                HardwareAddress::Ethernet(EthernetAddress(device.mac().unwrap_or_default()))
+            }
+            _ => HardwareAddress::Ip,
+        };
+
+        let mut device = DeviceAdapter::new(device, index);
+        let iface = Interface::new(Config::new(hardware_addr), &mut device, timestamp());
+
+        Self {
+            index,
+            name,
+            iface,
+            device,
+        }
+    }
+
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the MAC address of the interface's device, if it has one.
+    pub fn mac(&self) -> Option<[u8; 6]> {
+        self.device.device().mac()
+    }
+
+    /// Returns the longest IP packet the interface can send, which, as Linux
+    /// reports it, leaves out the Ethernet header.
+    pub fn mtu(&self) -> usize {
+        self.device.mtu() - self.header_len()
+    }
+
+    /// Limits the IP packets the interface sends to `mtu` bytes, which must be
+    /// at least the 68 IPv4 needs, and no more than the device can carry.
+    pub fn set_mtu(&mut self, mtu: usize) -> Result<()> {
+        if mtu < MIN_MTU {
+            return Err(KernelError::InvalidValue);
+        }
+
+        self.device.set_mtu(mtu + self.header_len())
+    }
+
+    fn header_len(&self) -> usize {
+        if self.mac().is_some() { 14 } else { 0 }
+    }
+
+    /// Returns counts of the frames the interface has sent and received.
+    pub fn stats(&self) -> &DeviceStats {
+        self.device.stats()
+    }
+
+    pub fn is_loopback(&self) -> bool {
+        self.device.device().loops_back()
+    }
+
+    /// Returns the `ARPHRD_*` type of the interface's hardware.
+    pub fn hatype(&self) -> u16 {
+        if self.is_loopback() {
+            ARPHRD_LOOPBACK
+        } else {
+            ARPHRD_ETHER
+        }
+    }
+
+    /// Returns the interface's `IFF_*` flags. Interfaces are always up.
+    pub fn flags(&self) -> u32 {
+        if self.is_loopback() {
+            IFF_UP | IFF_LOOPBACK | IFF_RUNNING
+        } else {
+            IFF_UP | IFF_BROADCAST | IFF_RUNNING | IFF_MULTICAST
+        }
+    }
+
+    /// Returns the addresses the interface has been given.
+    pub fn ip_addrs(&self) -> &[IpCidr] {
+        self.iface.ip_addrs()
+    }
+
+    /// Gives the interface `addr`, unless it has it already.
+    pub fn add_ip_addr(&mut self, addr: IpCidr) -> Result<()> {
+        let mut result = Ok(());
+
+        self.iface.update_ip_addrs(|addrs| {
+            if !addrs.contains(&addr) {
+                result = addrs.push(addr).map_err(|_| KernelError::NoMemory);
+            }
+        });
+
+        result
+    }
+
+    /// Takes `addr` away from the interface.
+    pub fn remove_ip_addr(&mut self, addr: IpCidr) {
+        self.iface
+            .update_ip_addrs(|addrs| addrs.retain(|a| *a != addr));
+    }
+
+    /// Whether `addr` is on one of the interface's networks.
+    fn reaches(&self, addr: IpAddress) -> bool {
+        self.ip_addrs().iter().any(|cidr| cidr.contains_addr(&addr))
+    }
+
+    /// Returns the prefix length of the most specific of the interface's
+    /// networks `addr` is on.
+    fn network_prefix_len(&self, addr: IpAddress) -> Option<u8> {
+        self.ip_addrs()
+            .iter()
+            .filter(|cidr| cidr.contains_addr(&addr))
+            .map(IpCidr::prefix_len)
+            .max()
+    }
+
+    /// Has the stack hand packets for `dest` to the router `gateway`.
+    fn add_gateway(&mut self, dest: IpCidr, gateway: IpAddress) -> Result<()> {
+        let mut result = Ok(());
+
+        self.iface.routes_mut().update(|routes| {
+            result = routes
+                .push(smoltcp::iface::Route {
+                    cidr: dest,
+                    via_router: gateway,
+                    preferred_until: None,
+                    expires_at: None,
+                })
+                .map_err(|_| KernelError::NoMemory);
+        });
+
+        result
+    }
+
+    fn remove_gateway(&mut self, dest: IpCidr) {
+        self.iface
+            .routes_mut()
+            .update(|routes| routes.retain(|route| route.cidr != dest));
+    }
+
+    /// Sends and receives whatever packets are waiting, updating `sockets` to
+    /// match.
+    ///
+    /// On loopback, sending a packet is what makes the next one arrive, so
+    /// this keeps going until the stack has nothing more to do.
+    pub fn poll(&mut self, sockets: &mut SocketSet<'static>) {
+        for _ in 0..MAX_POLL_ROUNDS {
+            if let PollResult::None = self.iface.poll(timestamp(), &mut self.device, sockets) {
+                break;
+            }
+        }
+    }
+
+    /// Returns how long until the stack next needs polling for a timer, such as
+    /// a retransmission, or `None` if nothing is pending.
+    pub fn poll_delay(&mut self, sockets: &SocketSet<'static>) -> Option<Duration> {
+        self.iface
+            .poll_delay(timestamp(), sockets)
+            .map(|delay| Duration::from_micros(delay.total_micros()))
+    }
+
+    /// Returns the context sockets need to act on the interface.
+    pub fn context(&mut self) -> &mut Context {
+        self.iface.context()
+    }
+}
+
+/// The registry of every interface.
+pub struct Interfaces {
+    list: Vec<NetInterface>,
+    next_index: u32,
+    routes: RoutingTable,
+}
+
+impl Interfaces {
+    fn new() -> Self {
+        let mut interfaces = Self {
+            list: Vec::new(),
+            next_index: 1,
+            routes: RoutingTable::new(),
+        };
+
+        let index = interfaces
+            .register("lo", Arc::new(LoopbackDevice::new()))
+            .expect("the first interface can't clash");
+        let lo = interfaces.get_mut(index).unwrap();
+        lo.add_ip_addr(IpCidr::new(IpAddress::Ipv4(Ipv4Addr::LOCALHOST), 8))
+            .unwrap();
+        lo.add_ip_addr(IpCidr::new(IpAddress::Ipv6(Ipv6Addr::LOCALHOST), 128))
+            .unwrap();
+
+        interfaces
+    }
+
+    /// Adds an interface named `name` for `device`, returning its index. Besides
+    /// the IPv6 link-local address an Ethernet device gives itself, it has no
+    /// addresses until it's given some.
+    pub fn register(&mut self, name: &str, device: Arc<dyn NetDevice>) -> Result<u32> {
+        if self.index_of(name).is_some() {
+            return Err(KernelError::InUse);
+        }
+
+        let index = self.next_index;
+        self.next_index += 1;
+
+        let link_local = device.mac().map(link_local_addr);
+        let mut iface = NetInterface::new(index, name.to_string(), device);
+        if let Some(addr) = link_local {
+            iface.add_ip_addr(IpCidr::new(IpAddress::Ipv6(addr), 64))?;
+        }
+        self.list.push(iface);
+        sysfs::add_iface(index, name);
+
+        Ok(index)
+    }
+
+    /// Returns every interface, in the order they were added.
+    pub fn iter(&self) -> impl Iterator<Item = &NetInterface> {
+        self.list.iter()
+    }
+
+    pub fn get(&self, index: u32) -> Option<&NetInterface> {
+        self.list.iter().find(|iface| iface.index == index)
+    }
+
+    pub fn get_mut(&mut self, index: u32) -> Option<&mut NetInterface> {
+        self.list.iter_mut().find(|iface| iface.index == index)
+    }
+
+    /// Returns the name of the interface numbered `index`.
+    pub fn name_of(&self, index: u32) -> Option<&str> {
+        self.list
+            .iter()
+            .find(|iface| iface.index == index)
+            .map(NetInterface::name)
+    }
+
+    /// Returns the index of the interface named `name`.
+    pub fn index_of(&self, name: &str) -> Option<u32> {
+        self.list
+            .iter()
+            .find(|iface| iface.name == name)
+            .map(NetInterface::index)
+    }
+
+    /// Returns the interface to send packets for `addr` through: the one with
+    /// the most specific network or route which covers it, preferring an
+    /// interface's own network to a route of the same length.
+    pub fn route(&mut self, addr: IpAddress) -> Result<&mut NetInterface> {
+        let connected = self
+            .list
+            .iter()
+            .filter_map(|iface| Some((iface.network_prefix_len(addr)?, iface.index)));
+        let routed = self
+            .routes
+            .lookup(addr)
+            .map(|route| (route.dest.prefix_len(), route.ifindex));
+
+        // `max_by_key` takes the last of equals, so the routed one goes first.
+        let index = routed
+            .into_iter()
+            .chain(connected)
+            .max_by_key(|(prefix_len, _)| *prefix_len)
+            .map(|(_, index)| index)
+            .ok_or(KernelError::NetworkUnreachable)?;
+
+        self.get_mut(index).ok_or(KernelError::NetworkUnreachable)
+    }
+
+    /// Returns the routes which have been added.
+    pub fn routes(&self) -> &[Route] {
+        self.routes.routes()
+    }
+
+    /// Adds `route`. Its gateway, if it has one, must be on one of its
+    /// interface's networks.
+    pub fn add_route(&mut self, route: Route) -> Result<()> {
+        let iface = self
+            .list
+            .iter_mut()
+            .find(|iface| iface.index == route.ifindex)
+            .ok_or(KernelError::from(FsError::NoDevice))?;
+
+        if let Some(gateway) = route.gateway {
+            if gateway.version() != route.dest.address().version() {
+                return Err(KernelError::InvalidValue);
+            }
+            if !iface.reaches(gateway) {
+                return Err(KernelError::NetworkUnreachable);
+            }
+        }
+
+        self.routes.add(route)?;
+
+        if let Some(gateway) = route.gateway
+            && let Err(e) = iface.add_gateway(route.dest, gateway)
+        {
+            self.routes.remove(route.dest)?;
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    /// Takes away the route to `dest`.
+    pub fn remove_route(&mut self, dest: IpCidr) -> Result<()> {
+        let route = self.routes.remove(dest)?;
+
+        if route.gateway.is_some()
+            && let Some(iface) = self.get_mut(route.ifindex)
+        {
+            iface.remove_gateway(dest);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the interface to send packets for `addr` through, as
+    /// [`Self::route`] does, except that a link-local IPv6 address is only
+    /// reachable through the interface `scope_id` names, which must be given.
+    pub fn route_scoped(&mut self, addr: IpAddress, scope_id: u32) -> Result<&mut NetInterface> {
+        if !is_link_local(addr) {
+            return self.route(addr);
+        }
+
+        if scope_id == 0 {
+            return Err(KernelError::InvalidValue);
+        }
+
+        self.get_mut(scope_id)
+            .ok_or(KernelError::from(FsError::NoDevice))
+    }
+
+    /// Returns the scope to report for `addr`: for a link-local IPv6 address,
+    /// the first interface with a link-local address of its own, and otherwise
+    /// zero.
+    pub fn scope_of(&self, addr: IpAddress) -> u32 {
+        if !is_link_local(addr) {
+            return 0;
+        }
+
+        self.list
+            .iter()
+            .find(|iface| {
+                iface
+                    .ip_addrs()
+                    .iter()
+                    .any(|cidr| is_link_local(cidr.address()))
+            })
+            .map_or(0, NetInterface::index)
+    }
+
+    /// Runs the stack over every interface.
+    pub fn poll(&mut self, sockets: &mut SocketSet<'static>) {
+        for iface in self.list.iter_mut() {
+            iface.poll(sockets);
+        }
+    }
+
+    /// Returns how long until any interface next needs polling.
+    pub fn poll_delay(&mut self, sockets: &SocketSet<'static>) -> Option<Duration> {
+        self.list
+            .iter_mut()
+            .filter_map(|iface| iface.poll_delay(sockets))
+            .min()
+    }
+}
+
+static INTERFACES: OnceLock<SpinLock<Interfaces>> = OnceLock::new();
+
+/// Returns the registry of network interfaces.
+///
+/// If both this and [`super::sockets`] are needed, this must be locked first.
+pub fn interfaces() -> &'static SpinLock<Interfaces> {
+    INTERFACES.get_or_init(|| SpinLock::new(Interfaces::new()))
+}
+
+/// Checks that the current task may configure interfaces, which takes
+/// `CAP_NET_ADMIN`.
+pub fn check_net_admin() -> Result<()> {
+    current_work()
+        .creds
+        .lock_save_irq()
+        .caps()
+        .check_capable(CapabilitiesFlags::CAP_NET_ADMIN)
+}
+
+/// Adds an interface named `name` for `device`, which a driver has brought up,
+/// returning its index.
+pub fn register_device(name: &str, device: Arc<dyn NetDevice>) -> Result<u32> {
+    interfaces().lock_save_irq().register(name, device)
+}
+
+/// Whether `addr` is an IPv6 link-local address, in `fe80::/10`.
+fn is_link_local(addr: IpAddress) -> bool {
+    matches!(addr, IpAddress::Ipv6(addr) if addr.is_unicast_link_local())
+}
+
+/// Returns the IPv6 link-local address an Ethernet device with the MAC address
+/// `mac` gives itself, with an interface identifier derived from the MAC, as
+/// in RFC 4291's modified EUI-64.
+fn link_local_addr(mac: [u8; 6]) -> Ipv6Addr {
+    Ipv6Addr::from([
+        0xfe,
+        0x80,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        mac[0] ^ 0x02,
+        mac[1],
+        mac[2],
+        0xff,
+        0xfe,
+        mac[3],
+        mac[4],
+        mac[5],
+    ])
+}
+
+fn timestamp() -> Instant {
+    Instant::from_micros(uptime().as_micros() as i64)
+}
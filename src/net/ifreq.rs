@@ -0,0 +1,300 @@
+//! The classic interface ioctls, which take a `struct ifreq` naming an
+//! interface, as busybox `ifconfig` uses them.
+//!
+//! They work on any socket, whatever its family, as on Linux. An interface's
+//! IPv4 address, netmask, flags, MTU and hardware address can be read, and its
+//! address, netmask and MTU set, which takes `CAP_NET_ADMIN`. Interfaces are
+//! always up, so setting the flags can't take one down, and the hardware
+//! address belongs to the device, so it can't be changed. Netlink is the more
+//! complete way to do all this.
+
+use crate::memory::uaccess::{UserCopyable, copy_from_user, copy_to_user};
+use crate::net::iface::{IFF_UP, Interfaces, NetInterface, check_net_admin, interfaces};
+use crate::net::{AF_INET, SockAddr};
+use alloc::vec::Vec;
+use core::net::Ipv4Addr;
+use libkernel::error::{FsError, KernelError, Result};
+use libkernel::memory::address::TUA;
+use smoltcp::wire::{IpAddress, IpCidr, IpEndpoint};
+
+const SIOCGIFNAME: usize = 0x8910;
+const SIOCGIFCONF: usize = 0x8912;
+const SIOCGIFFLAGS: usize = 0x8913;
+const SIOCSIFFLAGS: usize = 0x8914;
+const SIOCGIFADDR: usize = 0x8915;
+const SIOCSIFADDR: usize = 0x8916;
+const SIOCGIFNETMASK: usize = 0x891b;
+const SIOCSIFNETMASK: usize = 0x891c;
+const SIOCGIFMTU: usize = 0x8921;
+const SIOCSIFMTU: usize = 0x8922;
+const SIOCGIFHWADDR: usize = 0x8927;
+const SIOCGIFINDEX: usize = 0x8933;
+
+const IFNAMSIZ: usize = 16;
+
+/// A `struct ifreq`: an interface's name, and a union of whatever the request
+/// reads or writes, be it a `sockaddr`, flags or a number.
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct IfReq {
+    name: [u8; IFNAMSIZ],
+    data: [u8; 24],
+}
+
+unsafe impl UserCopyable for IfReq {}
+
+/// A `struct ifconf`: a buffer of `ifreq`s and its length in bytes.
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct IfConf {
+    len: i32,
+    buf: usize,
+}
+
+unsafe impl UserCopyable for IfConf {}
+
+impl IfReq {
+    fn for_iface(iface: &NetInterface) -> Self {
+        let mut req = Self {
+            name: [0; IFNAMSIZ],
+            data: [0; 24],
+        };
+
+        // Names are short enough to leave room for the NUL.
+        let name = iface.name().as_bytes();
+        let len = name.len().min(IFNAMSIZ - 1);
+        req.name[..len].copy_from_slice(&name[..len]);
+        req
+    }
+
+    fn name(&self) -> Result<&str> {
+        let len = self.name.iter().position(|b| *b == 0).unwrap_or(IFNAMSIZ);
+
+        core::str::from_utf8(&self.name[..len]).map_err(|_| FsError::NoDevice.into())
+    }
+
+    fn int(&self) -> i32 {
+        i32::from_ne_bytes(self.data[..4].try_into().unwrap())
+    }
+
+    fn set_int(&mut self, value: i32) {
+        self.data[..4].copy_from_slice(&value.to_ne_bytes());
+    }
+
+    /// Reads the `sockaddr_in` in the request.
+    fn addr(&self) -> Result<Ipv4Addr> {
+        if u16::from_ne_bytes([self.data[0], self.data[1]]) != AF_INET as u16 {
+            return Err(KernelError::InvalidValue);
+        }
+
+        Ok(Ipv4Addr::new(
+            self.data[4],
+            self.data[5],
+            self.data[6],
+            self.data[7],
+        ))
+    }
+
+    fn set_addr(&mut self, addr: Ipv4Addr) {
+        let sockaddr = SockAddr::from(IpEndpoint::new(IpAddress::Ipv4(addr), 0)).to_bytes();
+        self.data[..sockaddr.len()].copy_from_slice(&sockaddr);
+    }
+}
+
+/// Returns the IPv4 address `iface` has, with its prefix length.
+fn ipv4_addr(iface: &NetInterface) -> Option<(Ipv4Addr, u8)> {
+    iface
+        .ip_addrs()
+        .iter()
+        .find_map(|cidr| match cidr.address() {
+            IpAddress::Ipv4(addr) => Some((addr, cidr.prefix_len())),
+            _ => None,
+        })
+}
+
+fn ipv4_cidr(addr: Ipv4Addr, prefix_len: u8) -> IpCidr {
+    IpCidr::new(IpAddress::Ipv4(addr), prefix_len)
+}
+
+/// Returns the prefix length of `addr`'s network class, which an address set
+/// with `SIOCSIFADDR` starts with, as on Linux.
+fn class_prefix_len(addr: Ipv4Addr) -> Result<u8> {
+    match addr.octets()[0] {
+        0..=127 => Ok(8),
+        128..=191 => Ok(16),
+        192..=223 => Ok(24),
+        _ => Err(KernelError::InvalidValue),
+    }
+}
+
+fn lookup<'a>(interfaces: &'a mut Interfaces, req: &IfReq) -> Result<&'a mut NetInterface> {
+    let index = interfaces
+        .index_of(req.name()?)
+        .ok_or(KernelError::from(FsError::NoDevice))?;
+
+    interfaces
+        .get_mut(index)
+        .ok_or(KernelError::from(FsError::NoDevice))
+}
+
+/// Carries out the interface ioctl `request`, which reads or writes the
+/// `ifreq` at `argp`.
+pub async fn ioctl(request: usize, argp: usize) -> Result<usize> {
+    if request == SIOCGIFCONF {
+        return get_conf(argp).await;
+    }
+
+    let mut req: IfReq = copy_from_user(TUA::from_value(argp)).await?;
+
+    match request {
+        SIOCGIFNAME | SIOCGIFFLAGS | SIOCGIFADDR | SIOCGIFNETMASK | SIOCGIFMTU | SIOCGIFHWADDR
+        | SIOCGIFINDEX => {
+            req = get(request, req)?;
+            copy_to_user(TUA::from_value(argp), req).await?;
+        }
+        SIOCSIFFLAGS | SIOCSIFADDR | SIOCSIFNETMASK | SIOCSIFMTU => {
+            check_net_admin()?;
+            set(request, req)?;
+        }
+        _ => return Err(KernelError::NotATty),
+    }
+
+    Ok(0)
+}
+
+fn get(request: usize, mut req: IfReq) -> Result<IfReq> {
+    let mut interfaces = interfaces().lock_save_irq();
+
+    // The name is what's looked up here, rather than what's read.
+    if request == SIOCGIFNAME {
+        let iface = u32::try_from(req.int())
+            .ok()
+            .and_then(|index| interfaces.get(index))
+            .ok_or(KernelError::from(FsError::NoDevice))?;
+
+        return Ok(IfReq {
+            data: req.data,
+            ..IfReq::for_iface(iface)
+        });
+    }
+
+    let iface = lookup(&mut interfaces, &req)?;
+
+    match request {
+        SIOCGIFFLAGS => req.data[..2].copy_from_slice(&(iface.flags() as u16).to_ne_bytes()),
+        SIOCGIFADDR => {
+            let (addr, _) = ipv4_addr(iface).ok_or(KernelError::AddressNotAvailable)?;
+            req.set_addr(addr);
+        }
+        SIOCGIFNETMASK => {
+            let (_, prefix_len) = ipv4_addr(iface).ok_or(KernelError::AddressNotAvailable)?;
+            let mask = u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0);
+            req.set_addr(Ipv4Addr::from(mask));
+        }
+        SIOCGIFMTU => req.set_int(iface.mtu() as i32),
+        SIOCGIFHWADDR => {
+            req.data[..2].copy_from_slice(&iface.hatype().to_ne_bytes());
+            req.data[2..8].copy_from_slice(&iface.mac().unwrap_or_default());
+        }
+        SIOCGIFINDEX => req.set_int(iface.index() as i32),
+        _ => unreachable!(),
+    }
+
+    Ok(req)
+}
+
+fn set(request: usize, req: IfReq) -> Result<()> {
+    let mut interfaces = interfaces().lock_save_irq();
+    let iface = lookup(&mut interfaces, &req)?;
+
+    match request {
+        SIOCSIFFLAGS => {
+            let flags = u16::from_ne_bytes([req.data[0], req.data[1]]) as u32;
+            if flags & IFF_UP == 0 {
+                return Err(KernelError::OpNotSupported);
+            }
+
+            Ok(())
+        }
+        SIOCSIFADDR => {
+            // Setting the address replaces the one the interface has, and
+            // setting it to 0.0.0.0 takes it away.
+            let addr = req.addr()?;
+            let old = ipv4_addr(iface);
+            if old.is_some_and(|(old, _)| old == addr) {
+                return Ok(());
+            }
+
+            let new = if addr.is_unspecified() {
+                None
+            } else {
+                Some(ipv4_cidr(addr, class_prefix_len(addr)?))
+            };
+
+            if let Some((old, prefix_len)) = old {
+                iface.remove_ip_addr(ipv4_cidr(old, prefix_len));
+            }
+            if let Some(new) = new {
+                iface.add_ip_addr(new)?;
+            }
+
+            Ok(())
+        }
+        SIOCSIFNETMASK => {
+            let mask = u32::from(req.addr()?);
+            if mask.leading_ones() != mask.count_ones() {
+                return Err(KernelError::InvalidValue);
+            }
+
+            let (addr, prefix_len) = ipv4_addr(iface).ok_or(KernelError::AddressNotAvailable)?;
+            iface.remove_ip_addr(ipv4_cidr(addr, prefix_len));
+            iface.add_ip_addr(ipv4_cidr(addr, mask.count_ones() as u8))
+        }
+        SIOCSIFMTU => {
+            let mtu = usize::try_from(req.int()).map_err(|_| KernelError::InvalidValue)?;
+
+            iface.set_mtu(mtu)
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Lists the interfaces which have an IPv4 address, with their addresses, as
+/// `ifreq`s in the buffer the `ifconf` at `argp` gives, or, if it's null, says
+/// how big a buffer they need.
+async fn get_conf(argp: usize) -> Result<usize> {
+    let conf: IfConf = copy_from_user(TUA::from_value(argp)).await?;
+    let room =
+        usize::try_from(conf.len).map_err(|_| KernelError::InvalidValue)? / size_of::<IfReq>();
+
+    let reqs: Vec<IfReq> = interfaces()
+        .lock_save_irq()
+        .iter()
+        .filter_map(|iface| {
+            let (addr, _) = ipv4_addr(iface)?;
+
+            let mut req = IfReq::for_iface(iface);
+            req.set_addr(addr);
+            Some(req)
+        })
+        .collect();
+
+    let count = if conf.buf == 0 {
+        reqs.len()
+    } else {
+        let buf = TUA::<IfReq>::from_value(conf.buf);
+        for (i, req) in reqs.iter().take(room).enumerate() {
+            copy_to_user(buf.add_objs(i), *req).await?;
+        }
+
+        reqs.len().min(room)
+    };
+
+    copy_to_user(
+        TUA::<i32>::from_value(argp),
+        (count * size_of::<IfReq>()) as i32,
+    )
+    .await?;
+
+    Ok(0)
+}
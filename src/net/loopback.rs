@@ -0,0 +1,46 @@
+//! The loopback device, which delivers everything sent on it straight back to
+//! the stack.
+
+use super::device::NetDevice;
+use crate::sync::SpinLock;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use libkernel::error::Result;
+
+/// As Linux's loopback MTU.
+const LOOPBACK_MTU: usize = 65536;
+
+pub struct LoopbackDevice {
+    queue: SpinLock<VecDeque<Vec<u8>>>,
+}
+
+impl LoopbackDevice {
+    pub fn new() -> Self {
+        Self {
+            queue: SpinLock::new(VecDeque::new()),
+        }
+    }
+}
+
+impl NetDevice for LoopbackDevice {
+    fn transmit(&self, frame: &[u8]) -> Result<()> {
+        self.queue.lock_save_irq().push_back(frame.to_vec());
+        Ok(())
+    }
+
+    fn receive(&self) -> Option<Vec<u8>> {
+        self.queue.lock_save_irq().pop_front()
+    }
+
+    fn mtu(&self) -> usize {
+        LOOPBACK_MTU
+    }
+
+    fn mac(&self) -> Option<[u8; 6]> {
+        None
+    }
+
+    fn loops_back(&self) -> bool {
+        true
+    }
+}
@@ -1,21 +1,49 @@
+mod buffer;
+pub mod device;
+mod icmp;
+pub mod iface;
+mod ifreq;
+mod loopback;
+mod netlink;
+mod packet;
+mod port;
+pub mod proc;
+mod route;
+mod sockaddr;
+mod sockopt;
 mod sops;
 pub mod syscalls;
+mod sysfs;
 mod tcp;
+mod timeout;
+mod udp;
 mod unix;
 
-use crate::drivers::timer::now;
-use crate::memory::uaccess::{copy_from_user, copy_from_user_slice};
+use crate::drivers::timer::sleep;
+use crate::sched::spawn_kernel_task;
 use crate::sync::OnceLock;
-use crate::sync::SpinLock;
+use crate::sync::{CondVar, SpinLock};
+use alloc::boxed::Box;
 use alloc::vec;
-use alloc::vec::Vec;
-use core::net::Ipv4Addr;
+pub use buffer::{RMEM_DEFAULT, RMEM_MAX, SOCK_MIN_BUF, WMEM_DEFAULT, WMEM_MAX};
+use core::future::{self, poll_fn};
+use core::mem;
+use core::net::{Ipv4Addr, Ipv6Addr};
+use core::task::Poll;
+use futures::FutureExt;
+use iface::interfaces;
 use libkernel::error::KernelError;
-use libkernel::memory::address::UA;
+use libkernel::sync::condvar::WakeupType;
+use libkernel::sync::executor::Priority;
 use libkernel::sync::waker_set::WakerSet;
 use smoltcp::iface::SocketSet;
 use smoltcp::wire::{IpAddress, IpEndpoint};
+pub use sockaddr::{
+    SockAddr, SockAddrIn, SockAddrIn6, SockAddrLl, SockAddrNl, SockAddrUn, parse_sockaddr,
+    write_sockaddr,
+};
 pub use sops::SocketOps;
+pub use tcp::{SOMAXCONN, TcpSocket};
 
 static SOCKETS: OnceLock<SpinLock<SocketSet>> = OnceLock::new();
 
@@ -23,8 +51,6 @@ fn sockets() -> &'static SpinLock<SocketSet<'static>> {
     SOCKETS.get_or_init(|| SpinLock::new(SocketSet::new(vec![])))
 }
 
-// static INTERFACE: OnceLock<SpinLock<EthernetInterface<OurDevice>>> = OnceLock::new();
-
 static SOCKET_WAIT_QUEUE: OnceLock<SpinLock<WakerSet>> = OnceLock::new();
 
 fn socket_wait_queue() -> &'static SpinLock<WakerSet> {
@@ -33,15 +59,19 @@ fn socket_wait_queue() -> &'static SpinLock<WakerSet> {
 
 pub const AF_UNIX: i32 = 1;
 pub const AF_INET: i32 = 2;
+pub const AF_INET6: i32 = 10;
+pub const AF_NETLINK: i32 = 16;
+pub const AF_PACKET: i32 = 17;
 pub const SOCK_STREAM: i32 = 1;
 pub const SOCK_DGRAM: i32 = 2;
+pub const SOCK_RAW: i32 = 3;
 pub const SOCK_SEQPACKET: i32 = 5;
+pub const IPPROTO_ICMP: i32 = 1;
 pub const IPPROTO_TCP: i32 = 6;
-#[expect(dead_code)]
 pub const IPPROTO_UDP: i32 = 17;
 
-// TODO: Needs to be u32
-pub type SocketLen = usize;
+/// A `socklen_t`.
+pub type SocketLen = u32;
 
 #[repr(i32)]
 pub enum ShutdownHow {
@@ -62,124 +92,200 @@ impl TryFrom<i32> for ShutdownHow {
     }
 }
 
-#[non_exhaustive]
-#[derive(Debug, Clone)]
-pub enum SockAddr {
-    In(SockAddrIn),
-    Un(SockAddrUn),
+/// An endpoint named by an `AF_INET` or `AF_INET6` socket address, as an IP
+/// socket of a given domain sees it.
+///
+/// An `AF_INET6` socket reaches IPv4 peers through v4-mapped addresses
+/// (`::ffff:a.b.c.d`), which stand for the plain IPv4 endpoint, unless the
+/// socket's been made `IPV6_V6ONLY`.
+#[derive(Clone, Copy, Debug)]
+pub struct InetEndpoint {
+    pub endpoint: IpEndpoint,
+    /// The interface a link-local IPv6 address is on, or zero if it's not
+    /// given.
+    pub scope_id: u32,
 }
 
-impl SockAddr {
-    pub fn len(&self) -> SocketLen {
-        match self {
-            SockAddr::In(_) => size_of::<SockAddrIn>(),
-            SockAddr::Un(_) => size_of::<SockAddrUn>(),
+impl InetEndpoint {
+    /// Parses `sockaddr` for a socket of `domain`, which must be of the same
+    /// family.
+    pub fn from_sockaddr(sockaddr: SockAddr, domain: i32) -> Result<Self, KernelError> {
+        match (sockaddr, domain) {
+            (SockAddr::In(SockAddrIn { port, addr, .. }), AF_INET) => Ok(Self {
+                endpoint: IpEndpoint::new(
+                    IpAddress::Ipv4(Ipv4Addr::from(addr)),
+                    u16::from_be_bytes(port),
+                ),
+                scope_id: 0,
+            }),
+            (
+                SockAddr::In6(SockAddrIn6 {
+                    port,
+                    addr,
+                    scope_id,
+                    ..
+                }),
+                AF_INET6,
+            ) => {
+                let addr = Ipv6Addr::from(addr);
+                let addr = match addr.to_ipv4_mapped() {
+                    Some(addr) => IpAddress::Ipv4(addr),
+                    None => IpAddress::Ipv6(addr),
+                };
+
+                Ok(Self {
+                    endpoint: IpEndpoint::new(addr, u16::from_be_bytes(port)),
+                    scope_id,
+                })
+            }
+            _ => Err(KernelError::AddressFamilyNotSupported),
         }
     }
 
-    pub fn to_bytes(&self) -> Vec<u8> {
-        match self {
-            SockAddr::In(sain) => unsafe {
-                core::slice::from_raw_parts(
-                    (sain as *const SockAddrIn).cast::<u8>(),
-                    size_of::<SockAddrIn>(),
-                )
-                .to_vec()
-            },
-            SockAddr::Un(saun) => unsafe {
-                core::slice::from_raw_parts(
-                    (saun as *const SockAddrUn).cast::<u8>(),
-                    size_of::<SockAddrUn>(),
-                )
-                .to_vec()
-            },
-        }
+    /// Makes the socket address a socket of `domain` reports for `endpoint`,
+    /// giving a link-local IPv6 address the scope of the interface it's on.
+    ///
+    /// This locks [`interfaces`], so mustn't be called with [`sockets`] held.
+    pub fn to_sockaddr(endpoint: IpEndpoint, domain: i32) -> SockAddr {
+        let addr = match endpoint.addr {
+            IpAddress::Ipv4(addr) if domain == AF_INET6 => addr.to_ipv6_mapped(),
+            IpAddress::Ipv4(_) => return endpoint.into(),
+            IpAddress::Ipv6(addr) => addr,
+        };
+
+        SockAddr::In6(SockAddrIn6 {
+            family: AF_INET6 as u16,
+            port: endpoint.port.to_be_bytes(),
+            flowinfo: 0,
+            addr: addr.octets(),
+            scope_id: interfaces().lock_save_irq().scope_of(IpAddress::Ipv6(addr)),
+        })
+    }
+
+    /// Whether the endpoint was given to an `AF_INET6` socket as a v4-mapped
+    /// address.
+    pub fn is_v4_mapped(&self, domain: i32) -> bool {
+        domain == AF_INET6 && matches!(self.endpoint.addr, IpAddress::Ipv4(_))
     }
 }
 
-#[derive(Copy, Clone, Debug)]
-#[repr(C, packed)]
-pub struct SockAddrIn {
-    family: u16,
-    port: [u8; 2],
-    addr: [u8; 4],
-    zero: [u8; 8],
+/// Whether an IP socket of `domain` can talk to `addr`: an `AF_INET` socket
+/// only to IPv4 addresses, and an `AF_INET6` one to either, unless it's
+/// `v6only`.
+pub fn family_reaches(domain: i32, v6only: bool, addr: IpAddress) -> bool {
+    match addr {
+        IpAddress::Ipv4(_) => domain == AF_INET || !v6only,
+        IpAddress::Ipv6(_) => domain == AF_INET6,
+    }
 }
 
-#[derive(Copy, Clone, Debug)]
-#[repr(C, packed)]
-pub struct SockAddrUn {
-    family: u16,
-    path: [u8; 108],
+/// Returns the unspecified address of an IP socket of `domain`, which binding
+/// on stands for any address.
+pub fn unspecified_addr(domain: i32) -> IpAddress {
+    match domain {
+        AF_INET6 => IpAddress::Ipv6(Ipv6Addr::UNSPECIFIED),
+        _ => IpAddress::Ipv4(Ipv4Addr::UNSPECIFIED),
+    }
 }
 
-unsafe impl crate::memory::uaccess::UserCopyable for SockAddrIn {}
-unsafe impl crate::memory::uaccess::UserCopyable for SockAddrUn {}
+/// Set when the polling task should run the stack without waiting for its next
+/// timer.
+static POLL_PENDING: OnceLock<CondVar<bool>> = OnceLock::new();
 
-impl TryFrom<SockAddr> for IpEndpoint {
-    type Error = KernelError;
-    fn try_from(sockaddr: SockAddr) -> Result<IpEndpoint, KernelError> {
-        match sockaddr {
-            SockAddr::In(SockAddrIn { port, addr, .. }) => Ok(IpEndpoint {
-                port: u16::from_be_bytes(port),
-                addr: IpAddress::Ipv4(Ipv4Addr::from(addr)),
-            }),
-            _ => Err(KernelError::InvalidValue),
-        }
-    }
+fn poll_pending() -> &'static CondVar<bool> {
+    POLL_PENDING.get_or_init(|| CondVar::new(false))
 }
 
-impl From<IpEndpoint> for SockAddr {
-    fn from(endpoint: IpEndpoint) -> SockAddr {
-        SockAddr::In(SockAddrIn {
-            family: AF_INET as u16,
-            port: endpoint.port.to_be_bytes(),
-            addr: match endpoint.addr {
-                IpAddress::Ipv4(addr) => addr.octets(),
-                _ => unimplemented!(),
-            },
-            zero: [0; 8],
-        })
+/// Has the polling task run the stack as soon as it can.
+///
+/// Drivers call this from their receive interrupt, once frames have arrived.
+pub fn wake_poll_task() {
+    poll_pending().update(|pending| {
+        *pending = true;
+        WakeupType::One
+    });
+}
+
+/// Runs the network stack, then wakes any tasks waiting on socket progress.
+fn poll_stack() {
+    {
+        let mut interfaces = interfaces().lock_save_irq();
+        let mut sockets = sockets().lock_save_irq();
+
+        interfaces.poll(&mut sockets);
+        tcp::reap_closed_sockets(&mut sockets);
     }
+
+    socket_wait_queue().lock_save_irq().wake_all();
 }
 
+/// Runs the network stack on behalf of a socket which has queued something to
+/// send, or made room to receive.
+///
+/// This may have set a timer, such as for retransmitting a SYN, so the polling
+/// task is woken to take it into account.
 pub fn process_packets() {
-    // For now, just wake any tasks waiting on socket progress.
-    let _ = sockets().lock_save_irq();
-    let _ = now();
-    socket_wait_queue().lock_save_irq().wake_all();
+    poll_stack();
+    wake_poll_task();
 }
 
-pub async fn parse_sockaddr(uaddr: UA, len: SocketLen) -> Result<SockAddr, KernelError> {
-    use crate::memory::uaccess::try_copy_from_user;
-    use libkernel::memory::address::TUA;
+/// Starts the task which keeps the network stack running, both when frames
+/// arrive and when its timers, such as for retransmission or delayed ACKs, are
+/// due.
+pub fn net_init() {
+    spawn_kernel_task(Priority::Normal, async {
+        loop {
+            poll_stack();
 
-    // Need at least a family field
-    if len < size_of::<u16>() {
-        return Err(KernelError::InvalidValue);
-    }
+            // Bind the delay first, so the locks aren't held across the wait.
+            let delay = interfaces()
+                .lock_save_irq()
+                .poll_delay(&sockets().lock_save_irq());
 
-    let family: u16 = copy_from_user(TUA::from_value(uaddr.value())).await?;
+            let mut woken = Box::pin(
+                poll_pending()
+                    .wait_until(|pending| mem::take(pending).then_some(()))
+                    .fuse(),
+            );
+            let mut timer = Box::pin(
+                async move {
+                    match delay {
+                        Some(delay) => sleep(delay).await,
+                        None => future::pending::<()>().await,
+                    }
+                }
+                .fuse(),
+            );
 
-    match family as i32 {
-        AF_INET => {
-            if len < size_of::<SockAddrIn>() {
-                return Err(KernelError::InvalidValue);
+            futures::select_biased! {
+                _ = woken => {},
+                _ = timer => {},
             }
-            let sain: SockAddrIn = try_copy_from_user(uaddr.cast())?;
-            Ok(SockAddr::In(sain))
         }
-        AF_UNIX => {
-            let path_len = len - size_of::<u16>() * 2;
-            if path_len > 108 {
-                return Err(KernelError::InvalidValue);
+    });
+}
+
+/// Waits until `predicate`, which is checked each time the network stack
+/// runs, returns `Some`.
+async fn wait_for_sockets<R>(mut predicate: impl FnMut(&mut SocketSet<'static>) -> Option<R>) -> R {
+    let mut token = None;
+
+    poll_fn(|cx| {
+        // Hold the queue while checking, so that a wakeup for a change made
+        // after the check can't be missed.
+        let mut queue = socket_wait_queue().lock_save_irq();
+
+        if let Some(token) = token.take() {
+            queue.remove(token);
+        }
+
+        match predicate(&mut sockets().lock_save_irq()) {
+            Some(result) => Poll::Ready(result),
+            None => {
+                token = Some(queue.register(cx.waker()));
+                Poll::Pending
             }
-            let mut path = [0u8; 108];
-            copy_from_user_slice(uaddr.add_bytes(size_of::<u16>()), &mut path[..path_len]).await?;
-            // print str of path
-            let saun: SockAddrUn = SockAddrUn { family, path };
-            Ok(SockAddr::Un(saun))
         }
-        _ => Err(KernelError::AddressFamilyNotSupported),
-    }
+    })
+    .await
 }
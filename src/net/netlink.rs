@@ -0,0 +1,877 @@
+//! Netlink sockets, `AF_NETLINK`, through which userspace asks the kernel
+//! about its network interfaces, and configures them, as iproute2 does.
+//!
+//! Only `NETLINK_ROUTE` is supported, and of it only listing interfaces, their
+//! addresses and the routes which have been added, with `RTM_GETLINK`,
+//! `RTM_GETADDR` and `RTM_GETROUTE`, adding addresses, with `RTM_NEWADDR`, and
+//! adding and removing routes, with `RTM_NEWROUTE` and `RTM_DELROUTE`. Making
+//! changes needs `CAP_NET_ADMIN`. A request is answered as soon as it's sent,
+//! its replies being queued on the socket as one datagram. Sockets can only talk to the
+//! kernel, not to each other, and aren't sent notifications, so multicast
+//! groups are ignored.
+
+use crate::fs::fops::FileOps;
+use crate::fs::open_file::{FileCtx, OpenFile};
+use crate::memory::uaccess::iovec::UserIoVec;
+use crate::memory::uaccess::{copy_from_user_slice, copy_to_user_slice};
+use crate::net::buffer::BufferSizes;
+use crate::net::iface::{NetInterface, check_net_admin, interfaces};
+use crate::net::route::Route;
+use crate::net::sockopt::SockOpt;
+use crate::net::sops::{RecvFlags, RecvMsg, SendFlags, SocketOps};
+use crate::net::timeout::{SocketTimeouts, block};
+use crate::net::{AF_INET, AF_INET6, AF_NETLINK, ShutdownHow, SockAddr, SockAddrNl};
+use crate::sched::current_work;
+use crate::sync::{CondVar, SpinLock};
+use alloc::boxed::Box;
+use alloc::collections::{BTreeSet, VecDeque};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use async_trait::async_trait;
+use core::future;
+use core::net::{Ipv4Addr, Ipv6Addr};
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU32, Ordering};
+use libkernel::error::syscall_error::kern_err_to_syscall;
+use libkernel::error::{FsError, KernelError, Result};
+use libkernel::fs::OpenFlags;
+use libkernel::memory::address::UA;
+use libkernel::sync::condvar::WakeupType;
+use smoltcp::wire::{IpAddress, IpCidr};
+
+pub const NETLINK_ROUTE: i32 = 0;
+
+const NLMSG_ERROR: u16 = 2;
+const NLMSG_DONE: u16 = 3;
+
+const NLM_F_REQUEST: u16 = 0x1;
+const NLM_F_MULTI: u16 = 0x2;
+const NLM_F_ACK: u16 = 0x4;
+const NLM_F_DUMP: u16 = 0x300;
+
+const RTM_NEWLINK: u16 = 16;
+const RTM_GETLINK: u16 = 18;
+const RTM_NEWADDR: u16 = 20;
+const RTM_GETADDR: u16 = 22;
+const RTM_NEWROUTE: u16 = 24;
+const RTM_DELROUTE: u16 = 25;
+const RTM_GETROUTE: u16 = 26;
+
+const IFLA_ADDRESS: u16 = 1;
+const IFLA_IFNAME: u16 = 3;
+const IFLA_MTU: u16 = 4;
+
+const IFA_ADDRESS: u16 = 1;
+const IFA_LOCAL: u16 = 2;
+const IFA_LABEL: u16 = 3;
+
+const RTA_DST: u16 = 1;
+const RTA_OIF: u16 = 4;
+const RTA_GATEWAY: u16 = 5;
+const RTA_TABLE: u16 = 15;
+
+const RT_TABLE_MAIN: u8 = 254;
+const RTPROT_BOOT: u8 = 3;
+const RTN_UNICAST: u8 = 1;
+
+const RT_SCOPE_UNIVERSE: u8 = 0;
+const RT_SCOPE_LINK: u8 = 253;
+const RT_SCOPE_HOST: u8 = 254;
+
+/// The length of a `struct nlmsghdr`.
+const HEADER_LEN: usize = 16;
+/// The lengths of `struct ifinfomsg`, `struct ifaddrmsg` and `struct rtmsg`.
+const IFINFOMSG_LEN: usize = 16;
+const IFADDRMSG_LEN: usize = 8;
+const RTMSG_LEN: usize = 12;
+
+/// The port IDs netlink sockets are bound to.
+static PORT_IDS: SpinLock<BTreeSet<u32>> = SpinLock::new(BTreeSet::new());
+
+/// Rounds `len` up to the four bytes messages and attributes are aligned to.
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+fn read_u16(bytes: &[u8], at: usize) -> u16 {
+    u16::from_ne_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_ne_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+/// A `struct nlmsghdr`.
+#[derive(Clone, Copy)]
+struct Header {
+    len: u32,
+    ty: u16,
+    flags: u16,
+    seq: u32,
+    pid: u32,
+}
+
+impl Header {
+    fn parse(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < HEADER_LEN {
+            return None;
+        }
+
+        Some(Self {
+            len: read_u32(bytes, 0),
+            ty: read_u16(bytes, 4),
+            flags: read_u16(bytes, 6),
+            seq: read_u32(bytes, 8),
+            pid: read_u32(bytes, 12),
+        })
+    }
+
+    fn to_bytes(self) -> [u8; HEADER_LEN] {
+        let mut bytes = [0; HEADER_LEN];
+        bytes[0..4].copy_from_slice(&self.len.to_ne_bytes());
+        bytes[4..6].copy_from_slice(&self.ty.to_ne_bytes());
+        bytes[6..8].copy_from_slice(&self.flags.to_ne_bytes());
+        bytes[8..12].copy_from_slice(&self.seq.to_ne_bytes());
+        bytes[12..16].copy_from_slice(&self.pid.to_ne_bytes());
+        bytes
+    }
+}
+
+/// Returns the `struct rtattr`s packed into `bytes`, as each one's type and
+/// payload.
+fn attrs(mut bytes: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    core::iter::from_fn(move || {
+        if bytes.len() < 4 {
+            return None;
+        }
+
+        let len = read_u16(bytes, 0) as usize;
+        if len < 4 || len > bytes.len() {
+            return None;
+        }
+
+        let attr = (read_u16(bytes, 2), &bytes[4..len]);
+        bytes = &bytes[align(len).min(bytes.len())..];
+        Some(attr)
+    })
+}
+
+fn push_attr(buf: &mut Vec<u8>, ty: u16, payload: &[u8]) {
+    buf.extend_from_slice(&((4 + payload.len()) as u16).to_ne_bytes());
+    buf.extend_from_slice(&ty.to_ne_bytes());
+    buf.extend_from_slice(payload);
+    buf.resize(align(buf.len()), 0);
+}
+
+/// Parses an address of `family` out of an attribute's payload.
+fn parse_addr(family: u8, payload: &[u8]) -> Result<IpAddress> {
+    match (family as i32, payload.len()) {
+        (AF_INET, 4) => Ok(IpAddress::Ipv4(Ipv4Addr::from(
+            <[u8; 4]>::try_from(payload).unwrap(),
+        ))),
+        (AF_INET6, 16) => Ok(IpAddress::Ipv6(Ipv6Addr::from(
+            <[u8; 16]>::try_from(payload).unwrap(),
+        ))),
+        _ => Err(KernelError::InvalidValue),
+    }
+}
+
+fn addr_family(addr: IpAddress) -> u8 {
+    match addr {
+        IpAddress::Ipv4(_) => AF_INET as u8,
+        IpAddress::Ipv6(_) => AF_INET6 as u8,
+    }
+}
+
+fn addr_bytes(addr: IpAddress) -> Vec<u8> {
+    match addr {
+        IpAddress::Ipv4(addr) => addr.octets().to_vec(),
+        IpAddress::Ipv6(addr) => addr.octets().to_vec(),
+    }
+}
+
+/// Returns the scope Linux gives `addr`.
+fn addr_scope(addr: IpAddress) -> u8 {
+    match addr {
+        IpAddress::Ipv4(addr) if addr.is_loopback() => RT_SCOPE_HOST,
+        IpAddress::Ipv6(addr) if addr.is_loopback() => RT_SCOPE_HOST,
+        IpAddress::Ipv6(addr) if addr.is_unicast_link_local() => RT_SCOPE_LINK,
+        _ => RT_SCOPE_UNIVERSE,
+    }
+}
+
+/// The replies to one datagram of requests, as they're built.
+struct Replies {
+    /// The port ID of the socket which sent the requests.
+    pid: u32,
+    buf: Vec<u8>,
+}
+
+impl Replies {
+    /// Adds a message of type `ty`, answering `request`, with `payload`
+    /// followed by `attrs`.
+    fn push(&mut self, request: &Header, ty: u16, flags: u16, payload: &[u8], attrs: &[u8]) {
+        let header = Header {
+            len: (HEADER_LEN + align(payload.len()) + attrs.len()) as u32,
+            ty,
+            flags,
+            seq: request.seq,
+            pid: self.pid,
+        };
+
+        self.buf.extend_from_slice(&header.to_bytes());
+        self.buf.extend_from_slice(payload);
+        self.buf.resize(align(self.buf.len()), 0);
+        self.buf.extend_from_slice(attrs);
+    }
+
+    /// Adds the `NLMSG_DONE` which ends a dump.
+    fn done(&mut self, request: &Header) {
+        self.push(request, NLMSG_DONE, NLM_F_MULTI, &0i32.to_ne_bytes(), &[]);
+    }
+
+    /// Adds an `NLMSG_ERROR` carrying `result`, which is an acknowledgement if
+    /// it's `Ok`, quoting `request`'s header.
+    fn ack(&mut self, request: &Header, result: Result<()>) {
+        let error = match result {
+            Ok(()) => 0,
+            Err(e) => kern_err_to_syscall(e) as i32,
+        };
+
+        let mut payload = error.to_ne_bytes().to_vec();
+        payload.extend_from_slice(&request.to_bytes());
+        self.push(request, NLMSG_ERROR, 0, &payload, &[]);
+    }
+
+    /// Adds an `RTM_NEWLINK` describing `iface`.
+    fn link(&mut self, request: &Header, flags: u16, iface: &NetInterface) {
+        let mut payload = [0; IFINFOMSG_LEN];
+        payload[2..4].copy_from_slice(&iface.hatype().to_ne_bytes());
+        payload[4..8].copy_from_slice(&iface.index().to_ne_bytes());
+        payload[8..12].copy_from_slice(&iface.flags().to_ne_bytes());
+
+        let mut attrs = Vec::new();
+        let mut name = iface.name().as_bytes().to_vec();
+        name.push(0);
+        push_attr(&mut attrs, IFLA_IFNAME, &name);
+        push_attr(&mut attrs, IFLA_MTU, &(iface.mtu() as u32).to_ne_bytes());
+        push_attr(&mut attrs, IFLA_ADDRESS, &iface.mac().unwrap_or_default());
+
+        self.push(request, RTM_NEWLINK, flags, &payload, &attrs);
+    }
+
+    /// Adds an `RTM_NEWADDR` describing `cidr`, one of `iface`'s addresses.
+    fn addr(&mut self, request: &Header, iface: &NetInterface, cidr: IpCidr) {
+        let addr = cidr.address();
+
+        let mut payload = [0; IFADDRMSG_LEN];
+        payload[0] = addr_family(addr);
+        payload[1] = cidr.prefix_len();
+        payload[3] = addr_scope(addr);
+        payload[4..8].copy_from_slice(&iface.index().to_ne_bytes());
+
+        let mut attrs = Vec::new();
+        push_attr(&mut attrs, IFA_ADDRESS, &addr_bytes(addr));
+        push_attr(&mut attrs, IFA_LOCAL, &addr_bytes(addr));
+        let mut label = iface.name().as_bytes().to_vec();
+        label.push(0);
+        push_attr(&mut attrs, IFA_LABEL, &label);
+
+        self.push(request, RTM_NEWADDR, NLM_F_MULTI, &payload, &attrs);
+    }
+
+    /// Adds an `RTM_NEWROUTE` describing `route`.
+    fn route(&mut self, request: &Header, route: &Route) {
+        let mut payload = [0; RTMSG_LEN];
+        payload[0] = addr_family(route.dest.address());
+        payload[1] = route.dest.prefix_len();
+        payload[4] = RT_TABLE_MAIN;
+        payload[5] = RTPROT_BOOT;
+        payload[6] = match route.gateway {
+            Some(_) => RT_SCOPE_UNIVERSE,
+            None => RT_SCOPE_LINK,
+        };
+        payload[7] = RTN_UNICAST;
+
+        let mut attrs = Vec::new();
+        push_attr(&mut attrs, RTA_TABLE, &(RT_TABLE_MAIN as u32).to_ne_bytes());
+        if route.dest.prefix_len() != 0 {
+            push_attr(&mut attrs, RTA_DST, &addr_bytes(route.dest.address()));
+        }
+        if let Some(gateway) = route.gateway {
+            push_attr(&mut attrs, RTA_GATEWAY, &addr_bytes(gateway));
+        }
+        push_attr(&mut attrs, RTA_OIF, &route.ifindex.to_ne_bytes());
+
+        self.push(request, RTM_NEWROUTE, NLM_F_MULTI, &payload, &attrs);
+    }
+}
+
+/// The parts of an `RTM_NEWROUTE` or `RTM_DELROUTE` request.
+struct RouteRequest {
+    dest: IpCidr,
+    gateway: Option<IpAddress>,
+    ifindex: Option<u32>,
+}
+
+impl RouteRequest {
+    fn parse(body: &[u8]) -> Result<Self> {
+        if body.len() < RTMSG_LEN {
+            return Err(KernelError::InvalidValue);
+        }
+        let family = body[0];
+        let dst_len = body[1];
+
+        let mut dest = None;
+        let mut gateway = None;
+        let mut ifindex = None;
+        for (ty, payload) in attrs(&body[RTMSG_LEN..]) {
+            match ty {
+                RTA_DST => dest = Some(parse_addr(family, payload)?),
+                RTA_GATEWAY => gateway = Some(parse_addr(family, payload)?),
+                RTA_OIF if payload.len() == 4 => ifindex = Some(read_u32(payload, 0)),
+                _ => {}
+            }
+        }
+
+        // Without a destination, the route is a default one.
+        let dest = match dest {
+            Some(dest) => dest,
+            None => match family as i32 {
+                AF_INET => IpAddress::Ipv4(Ipv4Addr::UNSPECIFIED),
+                AF_INET6 => IpAddress::Ipv6(Ipv6Addr::UNSPECIFIED),
+                _ => return Err(KernelError::AddressFamilyNotSupported),
+            },
+        };
+        let max_prefix_len = if family as i32 == AF_INET { 32 } else { 128 };
+        if dst_len > max_prefix_len {
+            return Err(KernelError::InvalidValue);
+        }
+
+        Ok(Self {
+            dest: IpCidr::new(dest, dst_len),
+            gateway,
+            ifindex,
+        })
+    }
+}
+
+/// Carries out `request`, whose payload is `body`, adding its replies, other
+/// than an acknowledgement, to `replies`.
+fn handle(replies: &mut Replies, request: &Header, body: &[u8]) -> Result<()> {
+    match request.ty {
+        RTM_GETLINK => {
+            let interfaces = interfaces().lock_save_irq();
+
+            if request.flags & NLM_F_DUMP == NLM_F_DUMP {
+                for iface in interfaces.iter() {
+                    replies.link(request, NLM_F_MULTI, iface);
+                }
+                replies.done(request);
+                return Ok(());
+            }
+
+            if body.len() < IFINFOMSG_LEN {
+                return Err(KernelError::InvalidValue);
+            }
+
+            // The interface is named by its index, or failing that its name.
+            let index = match read_u32(body, 4) {
+                0 => attrs(&body[IFINFOMSG_LEN..])
+                    .find(|(ty, _)| *ty == IFLA_IFNAME)
+                    .and_then(|(_, name)| {
+                        let name = name.split(|b| *b == 0).next()?;
+                        interfaces.index_of(core::str::from_utf8(name).ok()?)
+                    }),
+                index => Some(index),
+            };
+            let iface = index
+                .and_then(|index| interfaces.get(index))
+                .ok_or(KernelError::from(FsError::NoDevice))?;
+
+            replies.link(request, 0, iface);
+            Ok(())
+        }
+        RTM_GETADDR => {
+            let family = body.first().copied().unwrap_or(0);
+            let interfaces = interfaces().lock_save_irq();
+
+            for iface in interfaces.iter() {
+                for cidr in iface.ip_addrs() {
+                    if family == 0 || family == addr_family(cidr.address()) {
+                        replies.addr(request, iface, *cidr);
+                    }
+                }
+            }
+            replies.done(request);
+            Ok(())
+        }
+        RTM_NEWADDR => {
+            check_net_admin()?;
+
+            if body.len() < IFADDRMSG_LEN {
+                return Err(KernelError::InvalidValue);
+            }
+            let family = body[0];
+            let prefix_len = body[1];
+            let index = read_u32(body, 4);
+
+            // As on Linux, the local address is the one given to the
+            // interface, unless only the other is there.
+            let mut addr = None;
+            for (ty, payload) in attrs(&body[IFADDRMSG_LEN..]) {
+                match ty {
+                    IFA_LOCAL => addr = Some(parse_addr(family, payload)?),
+                    IFA_ADDRESS if addr.is_none() => addr = Some(parse_addr(family, payload)?),
+                    _ => {}
+                }
+            }
+            let addr = addr.ok_or(KernelError::InvalidValue)?;
+
+            let max_prefix_len = if family as i32 == AF_INET { 32 } else { 128 };
+            if prefix_len > max_prefix_len {
+                return Err(KernelError::InvalidValue);
+            }
+
+            interfaces()
+                .lock_save_irq()
+                .get_mut(index)
+                .ok_or(KernelError::from(FsError::NoDevice))?
+                .add_ip_addr(IpCidr::new(addr, prefix_len))
+        }
+        RTM_NEWROUTE => {
+            check_net_admin()?;
+
+            let request = RouteRequest::parse(body)?;
+            let mut interfaces = interfaces().lock_save_irq();
+
+            // Without an interface, the route goes through the one the gateway
+            // is on.
+            let ifindex = match (request.ifindex, request.gateway) {
+                (Some(ifindex), _) => ifindex,
+                (None, Some(gateway)) => interfaces.route(gateway)?.index(),
+                (None, None) => return Err(KernelError::InvalidValue),
+            };
+
+            let route = match request.gateway {
+                Some(gateway) if request.dest.prefix_len() == 0 => {
+                    Route::default_via(gateway, ifindex)
+                }
+                gateway => Route {
+                    dest: request.dest,
+                    ifindex,
+                    gateway,
+                },
+            };
+
+            interfaces.add_route(route)
+        }
+        RTM_DELROUTE => {
+            check_net_admin()?;
+
+            let request = RouteRequest::parse(body)?;
+
+            interfaces().lock_save_irq().remove_route(request.dest)
+        }
+        RTM_GETROUTE => {
+            let family = body.first().copied().unwrap_or(0);
+            let interfaces = interfaces().lock_save_irq();
+
+            for route in interfaces.routes() {
+                if family == 0 || family == addr_family(route.dest.address()) {
+                    replies.route(request, route);
+                }
+            }
+            replies.done(request);
+            Ok(())
+        }
+        _ => Err(KernelError::OpNotSupported),
+    }
+}
+
+struct ReplyQueue {
+    datagrams: VecDeque<Vec<u8>>,
+    /// How many bytes of replies are queued.
+    bytes: usize,
+    /// How many bytes of replies can be queued, after which requests fail
+    /// with `ENOBUFS`.
+    limit: usize,
+}
+
+pub struct NetlinkSocket {
+    /// `SOCK_RAW` or `SOCK_DGRAM`, which are the same.
+    ty: i32,
+    /// The port ID the socket's bound to, or zero until it's bound.
+    port_id: AtomicU32,
+    replies: CondVar<ReplyQueue>,
+    buffers: SpinLock<BufferSizes>,
+    timeouts: SocketTimeouts,
+}
+
+impl NetlinkSocket {
+    pub fn new(ty: i32) -> Result<Self> {
+        let buffers = BufferSizes::new()?;
+        let replies = CondVar::new(ReplyQueue {
+            datagrams: VecDeque::new(),
+            bytes: 0,
+            limit: buffers.rx(),
+        });
+
+        Ok(Self {
+            ty,
+            port_id: AtomicU32::new(0),
+            replies,
+            buffers: SpinLock::new(buffers),
+            timeouts: SocketTimeouts::default(),
+        })
+    }
+
+    /// Binds the socket to `port_id`, or, if it's zero, to the process's ID, or
+    /// failing that an unused negative one, as Linux does, returning the port
+    /// ID. A socket which is already bound keeps its port ID.
+    fn do_bind(&self, port_id: u32) -> Result<u32> {
+        let mut port_ids = PORT_IDS.lock_save_irq();
+
+        let bound = self.port_id.load(Ordering::Relaxed);
+        if bound != 0 {
+            return if port_id == 0 || port_id == bound {
+                Ok(bound)
+            } else {
+                Err(KernelError::InvalidValue)
+            };
+        }
+
+        let port_id = if port_id != 0 {
+            if port_ids.contains(&port_id) {
+                return Err(KernelError::AddressInUse);
+            }
+            port_id
+        } else {
+            let tgid = current_work().process.tgid.0;
+            core::iter::once(tgid)
+                .chain((4097..).map(|n: i32| n.wrapping_neg() as u32))
+                .find(|id| !port_ids.contains(id))
+                .ok_or(KernelError::AddressInUse)?
+        };
+
+        port_ids.insert(port_id);
+        self.port_id.store(port_id, Ordering::Relaxed);
+        Ok(port_id)
+    }
+
+    /// Answers the requests in `datagram`, queueing the replies for the socket
+    /// to receive.
+    fn request(&self, datagram: &[u8]) -> Result<usize> {
+        let mut replies = Replies {
+            pid: self.do_bind(0)?,
+            buf: Vec::new(),
+        };
+
+        let mut rest = datagram;
+        while let Some(header) = Header::parse(rest) {
+            let len = header.len as usize;
+            if len < HEADER_LEN || len > rest.len() {
+                break;
+            }
+
+            // Only requests are acted on, as on Linux.
+            if header.flags & NLM_F_REQUEST != 0 {
+                let result = handle(&mut replies, &header, &rest[HEADER_LEN..len]);
+                if result.is_err() || header.flags & NLM_F_ACK != 0 {
+                    replies.ack(&header, result);
+                }
+            }
+
+            rest = &rest[align(len).min(rest.len())..];
+        }
+
+        if !replies.buf.is_empty() {
+            let mut result = Ok(());
+            self.replies.update(|queue| {
+                if queue.bytes + replies.buf.len() > queue.limit {
+                    result = Err(KernelError::NoBufferSpace);
+                    return WakeupType::None;
+                }
+
+                queue.bytes += replies.buf.len();
+                queue.datagrams.push_back(replies.buf);
+                WakeupType::All
+            });
+            result?;
+        }
+
+        Ok(datagram.len())
+    }
+
+    /// Takes the next datagram of replies, or only looks at it with
+    /// `MSG_PEEK`, returning up to `count` bytes of it along with its full
+    /// length.
+    async fn recv_datagram(
+        &self,
+        count: usize,
+        flags: RecvFlags,
+        nonblock: bool,
+    ) -> Result<(Vec<u8>, usize)> {
+        let peek = flags.contains(RecvFlags::MSG_PEEK);
+
+        let take = move |queue: &mut ReplyQueue| {
+            let datagram = queue.datagrams.front()?;
+            let taken = (
+                datagram[..datagram.len().min(count)].to_vec(),
+                datagram.len(),
+            );
+
+            if !peek {
+                queue.bytes -= datagram.len();
+                queue.datagrams.pop_front();
+            }
+
+            Some(taken)
+        };
+
+        let mut datagram = None;
+        self.replies.update(|queue| {
+            datagram = take(queue);
+            WakeupType::None
+        });
+
+        match datagram {
+            Some(datagram) => Ok(datagram),
+            None if nonblock => Err(KernelError::TryAgain),
+            None => block(self.replies.wait_until(take), self.timeouts.recv()).await,
+        }
+    }
+
+    fn check_message_len(&self, count: usize) -> Result<()> {
+        if count > self.buffers.lock_save_irq().tx() {
+            return Err(KernelError::MessageTooLong);
+        }
+
+        Ok(())
+    }
+}
+
+/// The address replies come from: the kernel's.
+fn kernel_addr() -> SockAddr {
+    SockAddr::Nl(SockAddrNl {
+        family: AF_NETLINK as u16,
+        pad: 0,
+        pid: 0,
+        groups: 0,
+    })
+}
+
+/// Checks that `addr` is the kernel's, the only one a socket can send to.
+fn check_kernel_addr(addr: &SockAddr) -> Result<()> {
+    match addr {
+        SockAddr::Nl(sanl) if sanl.pid == 0 => Ok(()),
+        SockAddr::Nl(_) => Err(KernelError::ConnectionRefused),
+        _ => Err(KernelError::InvalidValue),
+    }
+}
+
+fn is_nonblocking(ctx: &FileCtx, flags: RecvFlags) -> bool {
+    flags.contains(RecvFlags::MSG_DONTWAIT) || ctx.flags.contains(OpenFlags::O_NONBLOCK)
+}
+
+impl Drop for NetlinkSocket {
+    fn drop(&mut self) {
+        let port_id = self.port_id.load(Ordering::Relaxed);
+        if port_id != 0 {
+            PORT_IDS.lock_save_irq().remove(&port_id);
+        }
+    }
+}
+
+#[async_trait]
+impl SocketOps for NetlinkSocket {
+    async fn bind(&self, addr: SockAddr) -> Result<()> {
+        let SockAddr::Nl(sanl) = addr else {
+            return Err(KernelError::InvalidValue);
+        };
+
+        self.do_bind(sanl.pid).map(|_| ())
+    }
+
+    async fn connect(&self, _ctx: &FileCtx, addr: SockAddr) -> Result<()> {
+        check_kernel_addr(&addr)?;
+        self.do_bind(0).map(|_| ())
+    }
+
+    async fn recv(
+        &mut self,
+        ctx: &mut FileCtx,
+        buf: UA,
+        count: usize,
+        flags: RecvFlags,
+    ) -> Result<(usize, Option<SockAddr>)> {
+        let (data, len) = self
+            .recv_datagram(count, flags, is_nonblocking(ctx, flags))
+            .await?;
+
+        copy_to_user_slice(&data, buf).await?;
+
+        let len = if flags.contains(RecvFlags::MSG_TRUNC) {
+            len
+        } else {
+            data.len()
+        };
+
+        Ok((len, Some(kernel_addr())))
+    }
+
+    async fn recvfrom(
+        &mut self,
+        ctx: &mut FileCtx,
+        buf: UA,
+        count: usize,
+        flags: RecvFlags,
+        _addr: Option<SockAddr>,
+    ) -> Result<(usize, Option<SockAddr>)> {
+        self.recv(ctx, buf, count, flags).await
+    }
+
+    async fn recvmsg(
+        &mut self,
+        ctx: &mut FileCtx,
+        iov: &UserIoVec,
+        flags: RecvFlags,
+    ) -> Result<RecvMsg> {
+        let count: usize = iov.iovs().iter().map(|vec| vec.iov_len).sum();
+        let (data, len) = self
+            .recv_datagram(count, flags, is_nonblocking(ctx, flags))
+            .await?;
+
+        iov.writer().write(&data).await?;
+
+        Ok(RecvMsg {
+            len: if flags.contains(RecvFlags::MSG_TRUNC) {
+                len
+            } else {
+                data.len()
+            },
+            addr: Some(kernel_addr()),
+            rights: Vec::new(),
+            truncated: len > data.len(),
+        })
+    }
+
+    async fn send(
+        &mut self,
+        _ctx: &mut FileCtx,
+        buf: UA,
+        count: usize,
+        _flags: SendFlags,
+    ) -> Result<usize> {
+        self.check_message_len(count)?;
+
+        let mut datagram = vec![0; count];
+        copy_from_user_slice(buf, &mut datagram).await?;
+
+        self.request(&datagram)
+    }
+
+    async fn sendto(
+        &mut self,
+        ctx: &mut FileCtx,
+        buf: UA,
+        count: usize,
+        flags: SendFlags,
+        addr: SockAddr,
+    ) -> Result<usize> {
+        check_kernel_addr(&addr)?;
+        self.send(ctx, buf, count, flags).await
+    }
+
+    async fn sendmsg(
+        &mut self,
+        _ctx: &mut FileCtx,
+        iov: &UserIoVec,
+        _flags: SendFlags,
+        addr: Option<SockAddr>,
+        rights: Vec<Arc<OpenFile>>,
+    ) -> Result<usize> {
+        if !rights.is_empty() {
+            return Err(KernelError::InvalidValue);
+        }
+        if let Some(addr) = addr {
+            check_kernel_addr(&addr)?;
+        }
+
+        // The vectors are gathered into a single datagram.
+        let count: usize = iov.iovs().iter().map(|vec| vec.iov_len).sum();
+        self.check_message_len(count)?;
+
+        let mut datagram = vec![0; count];
+        let read = iov.reader().read(&mut datagram).await?;
+        datagram.truncate(read);
+
+        self.request(&datagram)
+    }
+
+    async fn shutdown(&self, _how: ShutdownHow) -> Result<()> {
+        Ok(())
+    }
+
+    fn getsockopt(&self, opt: SockOpt) -> Result<i32> {
+        match opt {
+            SockOpt::Type => Ok(self.ty),
+            SockOpt::Error => Ok(0),
+            SockOpt::SndBuf | SockOpt::RcvBuf => Ok(self.buffers.lock_save_irq().get(opt)),
+            SockOpt::Protocol => Ok(NETLINK_ROUTE),
+            SockOpt::Domain => Ok(AF_NETLINK),
+            _ => Err(KernelError::NoProtocolOption),
+        }
+    }
+
+    fn setsockopt(&self, opt: SockOpt, value: i32) -> Result<()> {
+        match opt {
+            SockOpt::SndBuf | SockOpt::RcvBuf => {
+                let mut buffers = self.buffers.lock_save_irq();
+                buffers.set(opt, value)?;
+
+                let limit = buffers.rx();
+                self.replies.update(|queue| {
+                    queue.limit = limit;
+                    WakeupType::None
+                });
+            }
+            _ => return Err(KernelError::NoProtocolOption),
+        }
+
+        Ok(())
+    }
+
+    fn local_addr(&self) -> Result<SockAddr> {
+        Ok(SockAddr::Nl(SockAddrNl {
+            family: AF_NETLINK as u16,
+            pad: 0,
+            pid: self.port_id.load(Ordering::Relaxed),
+            groups: 0,
+        }))
+    }
+
+    fn peer_addr(&self) -> Result<SockAddr> {
+        Ok(kernel_addr())
+    }
+
+    fn timeouts(&self) -> Option<&SocketTimeouts> {
+        Some(&self.timeouts)
+    }
+
+    fn poll_read_ready(&self) -> Pin<Box<dyn Future<Output = Result<()>> + 'static + Send>> {
+        Box::pin(
+            self.replies
+                .wait_until(|queue| (!queue.datagrams.is_empty()).then_some(Ok(()))),
+        )
+    }
+
+    fn poll_write_ready(&self) -> Pin<Box<dyn Future<Output = Result<()>> + 'static + Send>> {
+        Box::pin(future::ready(Ok(())))
+    }
+
+    fn as_file(self: Box<Self>) -> Box<dyn FileOps> {
+        self
+    }
+}
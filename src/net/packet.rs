@@ -0,0 +1,425 @@
+//! Packet sockets, `AF_PACKET`, which see the frames devices send and receive
+//! before the network stack does, as tcpdump uses.
+//!
+//! A `SOCK_RAW` socket receives whole frames, link-layer header and all, and a
+//! `SOCK_DGRAM` one only what follows the header. Devices which carry bare IP
+//! packets, such as loopback, are given an Ethernet header with no addresses,
+//! as Linux's loopback has. Only capturing frames is supported so far, not
+//! sending them.
+
+use crate::fs::fops::FileOps;
+use crate::fs::open_file::FileCtx;
+use crate::memory::uaccess::copy_to_user_slice;
+use crate::memory::uaccess::iovec::UserIoVec;
+use crate::net::buffer::BufferSizes;
+use crate::net::iface::interfaces;
+use crate::net::sockopt::SockOpt;
+use crate::net::sops::{RecvFlags, RecvMsg, SendFlags, SocketOps};
+use crate::net::timeout::{SocketTimeouts, block};
+use crate::net::{AF_PACKET, SOCK_RAW, ShutdownHow, SockAddr, SockAddrLl};
+use crate::sync::{CondVar, SpinLock};
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use async_trait::async_trait;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU16, AtomicU32, Ordering};
+use libkernel::error::{FsError, KernelError, Result};
+use libkernel::fs::OpenFlags;
+use libkernel::memory::address::UA;
+use libkernel::sync::condvar::WakeupType;
+use smoltcp::phy::Medium;
+
+/// The protocol which matches every frame.
+pub const ETH_P_ALL: u16 = 0x0003;
+const ETH_P_IP: u16 = 0x0800;
+const ETH_P_IPV6: u16 = 0x86dd;
+
+const ETH_HEADER_LEN: usize = 14;
+const ETH_ALEN: usize = 6;
+
+pub const ARPHRD_ETHER: u16 = 1;
+pub const ARPHRD_LOOPBACK: u16 = 772;
+
+/// A frame which was addressed to us.
+const PACKET_HOST: u8 = 0;
+/// A frame which we sent.
+const PACKET_OUTGOING: u8 = 4;
+
+/// A frame, with its Ethernet header, as a packet socket queues it.
+struct Frame {
+    ifindex: u32,
+    hatype: u16,
+    pkttype: u8,
+    data: Vec<u8>,
+}
+
+impl Frame {
+    fn protocol(&self) -> u16 {
+        u16::from_be_bytes([self.data[12], self.data[13]])
+    }
+
+    /// Returns the address of the frame's sender, as `recvfrom` reports it.
+    fn sockaddr(&self) -> SockAddr {
+        let mut addr = [0; 8];
+        addr[..ETH_ALEN].copy_from_slice(&self.data[ETH_ALEN..2 * ETH_ALEN]);
+
+        SockAddr::Ll(SockAddrLl {
+            family: AF_PACKET as u16,
+            protocol: self.protocol().to_be_bytes(),
+            ifindex: self.ifindex as i32,
+            hatype: self.hatype,
+            pkttype: self.pkttype,
+            halen: ETH_ALEN as u8,
+            addr,
+        })
+    }
+}
+
+struct FrameQueue {
+    frames: VecDeque<Frame>,
+    /// How many bytes of frames are queued.
+    bytes: usize,
+    /// How many bytes of frames can be queued, after which new ones are
+    /// dropped.
+    limit: usize,
+}
+
+/// Where the frames a packet socket wants are queued for it.
+struct Tap {
+    /// The protocol of the frames wanted, or [`ETH_P_ALL`]. Zero means none
+    /// are, until the socket's bound to a protocol.
+    protocol: AtomicU16,
+    /// The interface the socket's bound to, or zero for any.
+    ifindex: AtomicU32,
+    queue: CondVar<FrameQueue>,
+}
+
+impl Tap {
+    fn wants(&self, ifindex: u32, protocol: u16) -> bool {
+        let wanted = self.protocol.load(Ordering::Relaxed);
+        let bound = self.ifindex.load(Ordering::Relaxed);
+
+        (wanted == ETH_P_ALL || (wanted != 0 && wanted == protocol))
+            && (bound == 0 || bound == ifindex)
+    }
+}
+
+static TAPS: SpinLock<Vec<Arc<Tap>>> = SpinLock::new(Vec::new());
+
+/// Shows `frame`, which passed through the interface numbered `ifindex`, to
+/// every packet socket which wants it.
+pub fn tap_frame(ifindex: u32, medium: Medium, frame: &[u8], outgoing: bool) {
+    let taps = TAPS.lock_save_irq();
+    if taps.is_empty() {
+        return;
+    }
+
+    let (data, hatype) = match medium {
+        Medium::Ethernet if frame.len() >= ETH_HEADER_LEN => (frame.to_vec(), ARPHRD_ETHER),
+        Medium::Ip if !frame.is_empty() => {
+            let protocol = match frame[0] >> 4 {
+                6 => ETH_P_IPV6,
+                _ => ETH_P_IP,
+            };
+
+            let mut data = Vec::with_capacity(ETH_HEADER_LEN + frame.len());
+            data.extend_from_slice(&[0; 2 * ETH_ALEN]);
+            data.extend_from_slice(&protocol.to_be_bytes());
+            data.extend_from_slice(frame);
+            (data, ARPHRD_LOOPBACK)
+        }
+        _ => return,
+    };
+    let protocol = u16::from_be_bytes([data[12], data[13]]);
+
+    for tap in taps.iter().filter(|tap| tap.wants(ifindex, protocol)) {
+        let frame = Frame {
+            ifindex,
+            hatype,
+            pkttype: if outgoing {
+                PACKET_OUTGOING
+            } else {
+                PACKET_HOST
+            },
+            data: data.clone(),
+        };
+
+        tap.queue.update(|queue| {
+            // As with a full receive buffer on Linux, the frame's dropped.
+            if queue.bytes + frame.data.len() > queue.limit {
+                return WakeupType::None;
+            }
+
+            queue.bytes += frame.data.len();
+            queue.frames.push_back(frame);
+            WakeupType::All
+        });
+    }
+}
+
+pub struct PacketSocket {
+    /// `SOCK_RAW` or `SOCK_DGRAM`.
+    ty: i32,
+    tap: Arc<Tap>,
+    buffers: SpinLock<BufferSizes>,
+    timeouts: SocketTimeouts,
+}
+
+impl PacketSocket {
+    /// Makes a socket which receives frames of `protocol`, given in network
+    /// byte order, as `socket` takes it.
+    pub fn new(ty: i32, protocol: u16) -> Result<Self> {
+        let buffers = BufferSizes::new()?;
+        let tap = Arc::new(Tap {
+            protocol: AtomicU16::new(u16::from_be(protocol)),
+            ifindex: AtomicU32::new(0),
+            queue: CondVar::new(FrameQueue {
+                frames: VecDeque::new(),
+                bytes: 0,
+                limit: buffers.rx(),
+            }),
+        });
+
+        TAPS.lock_save_irq().push(tap.clone());
+
+        Ok(Self {
+            ty,
+            tap,
+            buffers: SpinLock::new(buffers),
+            timeouts: SocketTimeouts::default(),
+        })
+    }
+
+    /// Takes the next frame, returning up to `count` bytes of it along with
+    /// its full length and where it came from.
+    async fn recv_frame(
+        &self,
+        count: usize,
+        flags: RecvFlags,
+        nonblock: bool,
+    ) -> Result<(Vec<u8>, usize, SockAddr)> {
+        let peek = flags.contains(RecvFlags::MSG_PEEK);
+        let header_len = if self.ty == SOCK_RAW {
+            0
+        } else {
+            ETH_HEADER_LEN
+        };
+
+        // Trims the frame down to what the socket receives of it.
+        let take = move |queue: &mut FrameQueue| {
+            let frame = queue.frames.front()?;
+            let frame_len = frame.data.len();
+            let data = &frame.data[header_len..];
+            let taken = (
+                data[..data.len().min(count)].to_vec(),
+                data.len(),
+                frame.sockaddr(),
+            );
+
+            if !peek {
+                queue.bytes -= frame_len;
+                queue.frames.pop_front();
+            }
+
+            Some(taken)
+        };
+
+        let mut frame = None;
+        self.tap.queue.update(|queue| {
+            frame = take(queue);
+            WakeupType::None
+        });
+
+        match frame {
+            Some(frame) => Ok(frame),
+            None if nonblock => Err(KernelError::TryAgain),
+            None => block(self.tap.queue.wait_until(take), self.timeouts.recv()).await,
+        }
+    }
+}
+
+fn is_nonblocking(ctx: &FileCtx, flags: RecvFlags) -> bool {
+    flags.contains(RecvFlags::MSG_DONTWAIT) || ctx.flags.contains(OpenFlags::O_NONBLOCK)
+}
+
+impl Drop for PacketSocket {
+    fn drop(&mut self) {
+        TAPS.lock_save_irq()
+            .retain(|tap| !Arc::ptr_eq(tap, &self.tap));
+    }
+}
+
+#[async_trait]
+impl SocketOps for PacketSocket {
+    async fn bind(&self, addr: SockAddr) -> Result<()> {
+        let SockAddr::Ll(sall) = addr else {
+            return Err(KernelError::InvalidValue);
+        };
+
+        let ifindex = sall.ifindex;
+        if ifindex < 0 {
+            return Err(KernelError::InvalidValue);
+        }
+        if ifindex != 0
+            && interfaces()
+                .lock_save_irq()
+                .name_of(ifindex as u32)
+                .is_none()
+        {
+            return Err(FsError::NoDevice.into());
+        }
+
+        // As on Linux, a protocol of zero leaves the one the socket has.
+        let protocol = u16::from_be_bytes(sall.protocol);
+        if protocol != 0 {
+            self.tap.protocol.store(protocol, Ordering::Relaxed);
+        }
+        self.tap.ifindex.store(ifindex as u32, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    async fn recv(
+        &mut self,
+        ctx: &mut FileCtx,
+        buf: UA,
+        count: usize,
+        flags: RecvFlags,
+    ) -> Result<(usize, Option<SockAddr>)> {
+        let (data, len, from) = self
+            .recv_frame(count, flags, is_nonblocking(ctx, flags))
+            .await?;
+
+        copy_to_user_slice(&data, buf).await?;
+
+        let len = if flags.contains(RecvFlags::MSG_TRUNC) {
+            len
+        } else {
+            data.len()
+        };
+
+        Ok((len, Some(from)))
+    }
+
+    async fn recvfrom(
+        &mut self,
+        ctx: &mut FileCtx,
+        buf: UA,
+        count: usize,
+        flags: RecvFlags,
+        _addr: Option<SockAddr>,
+    ) -> Result<(usize, Option<SockAddr>)> {
+        self.recv(ctx, buf, count, flags).await
+    }
+
+    async fn recvmsg(
+        &mut self,
+        ctx: &mut FileCtx,
+        iov: &UserIoVec,
+        flags: RecvFlags,
+    ) -> Result<RecvMsg> {
+        let count: usize = iov.iovs().iter().map(|vec| vec.iov_len).sum();
+        let (data, len, from) = self
+            .recv_frame(count, flags, is_nonblocking(ctx, flags))
+            .await?;
+
+        iov.writer().write(&data).await?;
+
+        Ok(RecvMsg {
+            len: if flags.contains(RecvFlags::MSG_TRUNC) {
+                len
+            } else {
+                data.len()
+            },
+            addr: Some(from),
+            rights: Vec::new(),
+            truncated: len > data.len(),
+        })
+    }
+
+    async fn send(
+        &mut self,
+        _ctx: &mut FileCtx,
+        _buf: UA,
+        _count: usize,
+        _flags: SendFlags,
+    ) -> Result<usize> {
+        Err(KernelError::OpNotSupported)
+    }
+
+    async fn sendto(
+        &mut self,
+        _ctx: &mut FileCtx,
+        _buf: UA,
+        _count: usize,
+        _flags: SendFlags,
+        _addr: SockAddr,
+    ) -> Result<usize> {
+        Err(KernelError::OpNotSupported)
+    }
+
+    async fn shutdown(&self, _how: ShutdownHow) -> Result<()> {
+        Ok(())
+    }
+
+    fn getsockopt(&self, opt: SockOpt) -> Result<i32> {
+        match opt {
+            SockOpt::Type => Ok(self.ty),
+            SockOpt::Error => Ok(0),
+            SockOpt::SndBuf | SockOpt::RcvBuf => Ok(self.buffers.lock_save_irq().get(opt)),
+            SockOpt::Protocol => Ok(self.tap.protocol.load(Ordering::Relaxed).to_be() as i32),
+            SockOpt::Domain => Ok(AF_PACKET),
+            _ => Err(KernelError::NoProtocolOption),
+        }
+    }
+
+    fn setsockopt(&self, opt: SockOpt, value: i32) -> Result<()> {
+        match opt {
+            SockOpt::SndBuf | SockOpt::RcvBuf => {
+                let mut buffers = self.buffers.lock_save_irq();
+                buffers.set(opt, value)?;
+
+                // Frames already queued stay, even if they're over the new
+                // limit.
+                let limit = buffers.rx();
+                self.tap.queue.update(|queue| {
+                    queue.limit = limit;
+                    WakeupType::None
+                });
+            }
+            _ => return Err(KernelError::NoProtocolOption),
+        }
+
+        Ok(())
+    }
+
+    fn local_addr(&self) -> Result<SockAddr> {
+        Ok(SockAddr::Ll(SockAddrLl {
+            family: AF_PACKET as u16,
+            protocol: self.tap.protocol.load(Ordering::Relaxed).to_be_bytes(),
+            ifindex: self.tap.ifindex.load(Ordering::Relaxed) as i32,
+            hatype: 0,
+            pkttype: 0,
+            halen: 0,
+            addr: [0; 8],
+        }))
+    }
+
+    fn timeouts(&self) -> Option<&SocketTimeouts> {
+        Some(&self.timeouts)
+    }
+
+    fn poll_read_ready(&self) -> Pin<Box<dyn Future<Output = Result<()>> + 'static + Send>> {
+        Box::pin(
+            self.tap
+                .queue
+                .wait_until(|queue| (!queue.frames.is_empty()).then_some(Ok(()))),
+        )
+    }
+
+    fn as_file(self: Box<Self>) -> Box<dyn FileOps> {
+        self
+    }
+}
@@ -0,0 +1,280 @@
+//! The ports TCP and UDP sockets are bound to.
+//!
+//! Each protocol has its own [`PortTable`]. Binding to a port takes it until
+//! the [`PortBinding`] is dropped, along with the socket that holds it, so a
+//! second socket can't bind to the same address and port.
+//!
+//! As on Linux, sockets can agree to share a port. With `SO_REUSEADDR`, a
+//! socket can bind to a port which another connection is still closing on, or
+//! which another such socket holds, so long as that one isn't listening. With
+//! `SO_REUSEPORT`, any number of sockets which all set it can bind, and listen,
+//! on the same port, though which of them hears each connection or datagram is
+//! left to the stack.
+
+use crate::net::AF_INET6;
+use crate::sync::SpinLock;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::ops::RangeInclusive;
+use libkernel::error::{KernelError, Result};
+use smoltcp::wire::IpAddress;
+
+/// The ports handed out to sockets which need one without being bound to one,
+/// as Linux's default `net.ipv4.ip_local_port_range`.
+const EPHEMERAL_PORTS: RangeInclusive<u16> = 32768..=60999;
+
+/// Which of `SO_REUSEADDR` and `SO_REUSEPORT` a socket had set when it bound.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Reuse {
+    pub addr: bool,
+    pub port: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HolderState {
+    Bound,
+    Listening,
+    /// The socket's been dropped, and its connection is closing, e.g. in
+    /// `TIME_WAIT`.
+    Closing,
+}
+
+/// One socket's hold on a port.
+struct Holder {
+    id: u64,
+    addr: Option<IpAddress>,
+    reuse: Reuse,
+    state: HolderState,
+}
+
+struct Ports {
+    next_id: u64,
+    /// Each port's holders, with the addresses they're bound on. `None` stands
+    /// for any address of either family, and an unspecified address for any
+    /// address of its own family.
+    holders: BTreeMap<u16, Vec<Holder>>,
+}
+
+impl Ports {
+    fn holders_of(&mut self, port: u16, id: u64) -> Option<(&mut Vec<Holder>, usize)> {
+        let holders = self.holders.get_mut(&port)?;
+        let index = holders.iter().position(|holder| holder.id == id)?;
+
+        Some((holders, index))
+    }
+
+    fn add(&mut self, port: u16, addr: Option<IpAddress>, reuse: Reuse) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.holders.entry(port).or_default().push(Holder {
+            id,
+            addr,
+            reuse,
+            state: HolderState::Bound,
+        });
+
+        id
+    }
+}
+
+/// A socket's hold on a port, as [`PortTable::holders`] lists it.
+pub struct Held {
+    pub addr: Option<IpAddress>,
+    pub port: u16,
+    pub listening: bool,
+}
+
+/// The ports which sockets of one protocol are bound to.
+pub struct PortTable(SpinLock<Ports>);
+
+impl PortTable {
+    pub const fn new() -> Self {
+        Self(SpinLock::new(Ports {
+            next_id: 0,
+            holders: BTreeMap::new(),
+        }))
+    }
+
+    /// Binds to `port` on `addr`, or on any address if it's `None`. A port of
+    /// zero picks one from the ephemeral range.
+    ///
+    /// As on Linux, binding on any address clashes with binding on a particular
+    /// one of the same family, and vice versa, unless `reuse` allows the
+    /// sockets to share the port.
+    pub fn bind(
+        &'static self,
+        addr: Option<IpAddress>,
+        port: u16,
+        reuse: Reuse,
+    ) -> Result<PortBinding> {
+        let mut ports = self.0.lock_save_irq();
+
+        let port = if port == 0 {
+            EPHEMERAL_PORTS
+                .clone()
+                .find(|port| !ports.holders.contains_key(port))
+                .ok_or(KernelError::AddressInUse)?
+        } else {
+            port
+        };
+
+        let clash = ports.holders.get(&port).is_some_and(|holders| {
+            holders.iter().any(|holder| {
+                overlaps(holder.addr, addr) && !may_share(holder.reuse, holder.state, reuse)
+            })
+        });
+        if clash {
+            return Err(KernelError::AddressInUse);
+        }
+
+        let id = ports.add(port, addr, reuse);
+
+        Ok(PortBinding {
+            table: self,
+            id,
+            addr,
+            port,
+        })
+    }
+
+    /// Returns the holds sockets have on ports, in port order, leaving out
+    /// those of sockets which have been dropped.
+    pub fn holders(&self) -> Vec<Held> {
+        let ports = self.0.lock_save_irq();
+
+        ports
+            .holders
+            .iter()
+            .flat_map(|(port, holders)| holders.iter().map(move |holder| (*port, holder)))
+            .filter(|(_, holder)| holder.state != HolderState::Closing)
+            .map(|(port, holder)| Held {
+                addr: holder.addr,
+                port,
+                listening: holder.state == HolderState::Listening,
+            })
+            .collect()
+    }
+}
+
+/// Whether binding on `a` and on `b` take the same port on some address.
+fn overlaps(a: Option<IpAddress>, b: Option<IpAddress>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => {
+            a.version() == b.version() && (a.is_unspecified() || b.is_unspecified() || a == b)
+        }
+        _ => true,
+    }
+}
+
+/// Whether a socket binding with `reuse` may share a port with one which
+/// holds it with `held` and is in `state`.
+fn may_share(held: Reuse, state: HolderState, reuse: Reuse) -> bool {
+    if held.port && reuse.port {
+        return true;
+    }
+
+    reuse.addr
+        && match state {
+            HolderState::Closing => true,
+            HolderState::Bound => held.addr,
+            HolderState::Listening => false,
+        }
+}
+
+/// Returns the address a socket of `domain` binding on `addr` takes its port
+/// on: any address of either family for an `AF_INET6` socket bound on `::`,
+/// unless it's `v6only`, and otherwise `addr` itself.
+pub fn bound_addr(domain: i32, v6only: bool, addr: IpAddress) -> Option<IpAddress> {
+    let dual_stack = domain == AF_INET6 && !v6only && addr.is_unspecified();
+
+    (!dual_stack).then_some(addr)
+}
+
+/// A socket's hold on a port, which is given back when dropped.
+pub struct PortBinding {
+    table: &'static PortTable,
+    id: u64,
+    addr: Option<IpAddress>,
+    port: u16,
+}
+
+impl PortBinding {
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Takes another hold on the port, as a connection accepted on a listening
+    /// socket does, which doesn't clash with this one.
+    pub fn share(&self) -> PortBinding {
+        let mut ports = self.table.0.lock_save_irq();
+        let reuse = ports
+            .holders_of(self.port, self.id)
+            .map(|(holders, index)| holders[index].reuse)
+            .unwrap_or_default();
+        let id = ports.add(self.port, self.addr, reuse);
+
+        PortBinding {
+            table: self.table,
+            id,
+            addr: self.addr,
+            port: self.port,
+        }
+    }
+
+    /// Marks the socket as listening, which fails if another socket is already
+    /// listening on the same address and port, unless both set `SO_REUSEPORT`.
+    pub fn listen(&self) -> Result<()> {
+        let mut ports = self.table.0.lock_save_irq();
+        let Some((holders, index)) = ports.holders_of(self.port, self.id) else {
+            return Ok(());
+        };
+
+        let reuse = holders[index].reuse;
+        let clash = holders.iter().any(|holder| {
+            holder.id != self.id
+                && holder.state == HolderState::Listening
+                && overlaps(holder.addr, self.addr)
+                && !(holder.reuse.port && reuse.port)
+        });
+        if clash {
+            return Err(KernelError::AddressInUse);
+        }
+
+        holders[index].state = HolderState::Listening;
+        Ok(())
+    }
+
+    /// Marks the socket as no longer listening.
+    pub fn stop_listening(&self) {
+        self.set_state(HolderState::Bound);
+    }
+
+    /// Marks the socket as dropped while its connection closes, after which
+    /// sockets with `SO_REUSEADDR` may bind to the port.
+    pub fn closing(&self) {
+        self.set_state(HolderState::Closing);
+    }
+
+    fn set_state(&self, state: HolderState) {
+        let mut ports = self.table.0.lock_save_irq();
+
+        if let Some((holders, index)) = ports.holders_of(self.port, self.id) {
+            holders[index].state = state;
+        }
+    }
+}
+
+impl Drop for PortBinding {
+    fn drop(&mut self) {
+        let mut ports = self.table.0.lock_save_irq();
+
+        if let Some((holders, index)) = ports.holders_of(self.port, self.id) {
+            holders.swap_remove(index);
+
+            if holders.is_empty() {
+                ports.holders.remove(&self.port);
+            }
+        }
+    }
+}
@@ -0,0 +1,182 @@
+//! The tables of sockets and interfaces under /proc/net, in Linux's format, as
+//! `netstat` and `ss` read them.
+//!
+//! Listening TCP sockets and UDP sockets are listed by the ports they hold, so
+//! one bound on any address of either family shows up in the IPv6 table, as
+//! on Linux. UDP sockets are listed without the peers they're connected to.
+//! Sockets don't have inodes or record their owners, so those columns are
+//! always zero.
+
+use crate::net::iface::interfaces;
+use crate::net::{tcp, udp};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use core::fmt::Write;
+use core::sync::atomic::Ordering;
+use smoltcp::socket::tcp::State;
+use smoltcp::wire::{IpAddress, IpEndpoint};
+
+const TCP_ESTABLISHED: u8 = 1;
+const TCP_SYN_SENT: u8 = 2;
+const TCP_SYN_RECV: u8 = 3;
+const TCP_FIN_WAIT1: u8 = 4;
+const TCP_FIN_WAIT2: u8 = 5;
+const TCP_TIME_WAIT: u8 = 6;
+pub const TCP_CLOSE: u8 = 7;
+const TCP_CLOSE_WAIT: u8 = 8;
+const TCP_LAST_ACK: u8 = 9;
+pub const TCP_LISTEN: u8 = 10;
+const TCP_CLOSING: u8 = 11;
+
+/// Returns the number Linux gives a TCP socket in `state`.
+pub fn tcp_state(state: State) -> u8 {
+    match state {
+        State::Closed => TCP_CLOSE,
+        State::Listen => TCP_LISTEN,
+        State::SynSent => TCP_SYN_SENT,
+        State::SynReceived => TCP_SYN_RECV,
+        State::Established => TCP_ESTABLISHED,
+        State::FinWait1 => TCP_FIN_WAIT1,
+        State::FinWait2 => TCP_FIN_WAIT2,
+        State::CloseWait => TCP_CLOSE_WAIT,
+        State::Closing => TCP_CLOSING,
+        State::LastAck => TCP_LAST_ACK,
+        State::TimeWait => TCP_TIME_WAIT,
+    }
+}
+
+/// A socket, as a line of /proc/net/tcp or /proc/net/udp.
+pub struct SocketRow {
+    /// The address the socket's bound on, `None` standing for any address of
+    /// either family.
+    pub local_addr: Option<IpAddress>,
+    pub local_port: u16,
+    /// The peer, if the socket's connected.
+    pub remote: Option<IpEndpoint>,
+    pub state: u8,
+    /// How many bytes are waiting to be sent, and to be read.
+    pub tx_queue: usize,
+    pub rx_queue: usize,
+}
+
+/// The files under /proc/net.
+#[derive(Clone, Copy)]
+pub enum NetTable {
+    Dev,
+    Tcp,
+    Tcp6,
+    Udp,
+    Udp6,
+}
+
+impl NetTable {
+    pub const ALL: [(&'static str, NetTable); 5] = [
+        ("dev", NetTable::Dev),
+        ("tcp", NetTable::Tcp),
+        ("tcp6", NetTable::Tcp6),
+        ("udp", NetTable::Udp),
+        ("udp6", NetTable::Udp6),
+    ];
+
+    /// Returns the table's contents.
+    pub fn read(self) -> String {
+        match self {
+            NetTable::Dev => dev_table(),
+            NetTable::Tcp => socket_table(&tcp::socket_rows(), false, 4),
+            NetTable::Tcp6 => socket_table(&tcp::socket_rows(), true, 4),
+            NetTable::Udp => socket_table(&udp::socket_rows(), false, 5),
+            NetTable::Udp6 => socket_table(&udp::socket_rows(), true, 5),
+        }
+    }
+}
+
+/// Writes `addr` as Linux does, as 32-bit words in hex, each read from the
+/// address's bytes in the CPU's byte order.
+fn write_addr(out: &mut String, addr: Option<IpAddress>, v6: bool) {
+    let octets = match addr {
+        Some(IpAddress::Ipv4(addr)) => addr.octets().to_vec(),
+        Some(IpAddress::Ipv6(addr)) => addr.octets().to_vec(),
+        None => vec![0; if v6 { 16 } else { 4 }],
+    };
+
+    for word in octets.chunks(4) {
+        write!(out, "{:08X}", u32::from_ne_bytes(word.try_into().unwrap())).unwrap();
+    }
+}
+
+/// Returns the table of the rows of `rows` in one family, numbering each with
+/// a field `sl_width` wide.
+fn socket_table(rows: &[SocketRow], v6: bool, sl_width: usize) -> String {
+    let (addr_width, remote_header) = if v6 {
+        (38, "remote_address")
+    } else {
+        (14, "rem_address")
+    };
+    let mut table = format!(
+        "{:>sl_width$}  {:<addr_width$}{:<addr_width$}st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode\n",
+        "sl", "local_address", remote_header,
+    );
+
+    let in_family = |row: &&SocketRow| match row.local_addr {
+        Some(IpAddress::Ipv4(_)) => !v6,
+        Some(IpAddress::Ipv6(_)) | None => v6,
+    };
+
+    for (sl, row) in rows.iter().filter(in_family).enumerate() {
+        write!(table, "{sl:>sl_width$}: ").unwrap();
+        write_addr(&mut table, row.local_addr, v6);
+        write!(table, ":{:04X} ", row.local_port).unwrap();
+        write_addr(&mut table, row.remote.map(|remote| remote.addr), v6);
+        // No timers, retransmits, owner, timeout or inode.
+        writeln!(
+            table,
+            ":{:04X} {:02X} {:08X}:{:08X} 00:00000000 00000000     0        0 0",
+            row.remote.map_or(0, |remote| remote.port),
+            row.state,
+            row.tx_queue,
+            row.rx_queue,
+        )
+        .unwrap();
+    }
+
+    table
+}
+
+/// Returns /proc/net/dev: each interface's counts of what it's received and
+/// sent.
+fn dev_table() -> String {
+    let mut table = String::from(
+        "Inter-|   Receive                                                |  Transmit\n \
+         face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed\n",
+    );
+
+    for iface in interfaces().lock_save_irq().iter() {
+        let stats = iface.stats();
+
+        writeln!(
+            table,
+            "{:>6}:{:>8} {:>7} {:>4} {:>4} {:>4} {:>5} {:>10} {:>9} {:>8} {:>7} {:>4} {:>4} {:>4} {:>5} {:>7} {:>10}",
+            iface.name(),
+            stats.rx_bytes.load(Ordering::Relaxed),
+            stats.rx_packets.load(Ordering::Relaxed),
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            stats.tx_bytes.load(Ordering::Relaxed),
+            stats.tx_packets.load(Ordering::Relaxed),
+            0,
+            stats.tx_dropped.load(Ordering::Relaxed),
+            0,
+            0,
+            0,
+            0,
+        )
+        .unwrap();
+    }
+
+    table
+}
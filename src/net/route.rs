@@ -0,0 +1,141 @@
+//! The routing table, which says which interface, and which router on its link,
+//! packets for a destination beyond the interfaces' own networks go through.
+//!
+//! As Linux's connected routes, each interface's own networks are reached
+//! through it without needing a route. A lookup takes the most specific route
+//! which covers the destination, so a default route, to `0.0.0.0/0` or `::/0`,
+//! only catches what nothing else does.
+
+use alloc::vec::Vec;
+use core::net::{Ipv4Addr, Ipv6Addr};
+use libkernel::error::{FsError, KernelError, Result};
+use smoltcp::wire::{IpAddress, IpCidr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Route {
+    /// The destinations the route covers.
+    pub dest: IpCidr,
+    /// The index of the interface packets leave through.
+    pub ifindex: u32,
+    /// The router packets are handed to, or `None` if the destinations are on
+    /// the interface's link.
+    pub gateway: Option<IpAddress>,
+}
+
+impl Route {
+    /// Returns a default route, through the router `gateway` on the interface
+    /// numbered `ifindex`, for destinations of `gateway`'s family.
+    pub fn default_via(gateway: IpAddress, ifindex: u32) -> Self {
+        let any = match gateway {
+            IpAddress::Ipv4(_) => IpAddress::Ipv4(Ipv4Addr::UNSPECIFIED),
+            IpAddress::Ipv6(_) => IpAddress::Ipv6(Ipv6Addr::UNSPECIFIED),
+        };
+
+        Self {
+            dest: IpCidr::new(any, 0),
+            ifindex,
+            gateway: Some(gateway),
+        }
+    }
+}
+
+pub struct RoutingTable(Vec<Route>);
+
+impl RoutingTable {
+    pub const fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    pub fn routes(&self) -> &[Route] {
+        &self.0
+    }
+
+    /// Adds `route`, unless there's already one to the same destinations.
+    pub fn add(&mut self, route: Route) -> Result<()> {
+        if self.0.iter().any(|r| r.dest == route.dest) {
+            return Err(FsError::AlreadyExists.into());
+        }
+
+        self.0.push(route);
+        Ok(())
+    }
+
+    /// Takes away the route to `dest`, returning it.
+    pub fn remove(&mut self, dest: IpCidr) -> Result<Route> {
+        let index = self
+            .0
+            .iter()
+            .position(|r| r.dest == dest)
+            .ok_or(KernelError::NoProcess)?;
+
+        Ok(self.0.swap_remove(index))
+    }
+
+    /// Returns the most specific route to `addr`.
+    pub fn lookup(&self, addr: IpAddress) -> Option<&Route> {
+        self.0
+            .iter()
+            .filter(|r| r.dest.contains_addr(&addr))
+            .max_by_key(|r| r.dest.prefix_len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Route, RoutingTable};
+    use core::net::Ipv4Addr;
+    use moss_macros::ktest;
+    use smoltcp::wire::{IpAddress, IpCidr};
+
+    fn addr(a: u8, b: u8, c: u8, d: u8) -> IpAddress {
+        IpAddress::Ipv4(Ipv4Addr::new(a, b, c, d))
+    }
+
+    fn route(dest: IpAddress, prefix_len: u8, ifindex: u32) -> Route {
+        Route {
+            dest: IpCidr::new(dest, prefix_len),
+            ifindex,
+            gateway: None,
+        }
+    }
+
+    #[ktest]
+    fn lookup_takes_the_longest_prefix() {
+        let mut table = RoutingTable::new();
+        table.add(route(addr(10, 0, 0, 0), 8, 1)).unwrap();
+        table.add(route(addr(10, 1, 0, 0), 16, 2)).unwrap();
+        table.add(route(addr(10, 1, 2, 0), 24, 3)).unwrap();
+
+        assert_eq!(table.lookup(addr(10, 1, 2, 3)).unwrap().ifindex, 3);
+        assert_eq!(table.lookup(addr(10, 1, 3, 1)).unwrap().ifindex, 2);
+        assert_eq!(table.lookup(addr(10, 2, 0, 1)).unwrap().ifindex, 1);
+        assert!(table.lookup(addr(192, 168, 0, 1)).is_none());
+    }
+
+    #[ktest]
+    fn default_route_catches_the_rest() {
+        let mut table = RoutingTable::new();
+        table.add(Route::default_via(addr(10, 0, 2, 2), 1)).unwrap();
+        table.add(route(addr(192, 168, 0, 0), 16, 2)).unwrap();
+
+        assert_eq!(table.lookup(addr(192, 168, 1, 1)).unwrap().ifindex, 2);
+
+        let fallback = table.lookup(addr(8, 8, 8, 8)).unwrap();
+        assert_eq!(fallback.ifindex, 1);
+        assert_eq!(fallback.gateway, Some(addr(10, 0, 2, 2)));
+        assert_eq!(fallback.dest.prefix_len(), 0);
+    }
+
+    #[ktest]
+    fn routes_are_unique_and_removable() {
+        let mut table = RoutingTable::new();
+        let dest = IpCidr::new(addr(10, 0, 0, 0), 8);
+        table.add(route(addr(10, 0, 0, 0), 8, 1)).unwrap();
+
+        assert!(table.add(route(addr(10, 0, 0, 0), 8, 2)).is_err());
+        assert_eq!(table.remove(dest).unwrap().ifindex, 1);
+        assert!(table.lookup(addr(10, 0, 0, 1)).is_none());
+        assert!(table.remove(dest).is_err());
+        assert!(table.routes().is_empty());
+    }
+}
@@ -0,0 +1,253 @@
+//! Socket addresses, as userspace passes them to and from the socket syscalls.
+//!
+//! Each family's `struct sockaddr_*` is copied in whole, after checking that
+//! the length userspace gives covers it, and kept in a [`SockAddr`], which the
+//! sockets of that family make sense of.
+
+use crate::memory::uaccess::{
+    UserCopyable, copy_from_user, copy_from_user_slice, copy_to_user, copy_to_user_slice,
+};
+use crate::net::{AF_INET, AF_INET6, AF_NETLINK, AF_PACKET, AF_UNIX, SocketLen};
+use alloc::vec::Vec;
+use core::net::{Ipv4Addr, Ipv6Addr};
+use libkernel::error::KernelError;
+use libkernel::memory::address::{TUA, UA};
+use smoltcp::wire::{IpAddress, IpEndpoint};
+
+/// The length of a `sockaddr_un`'s path.
+const SUN_PATH_LEN: usize = 108;
+
+/// The length of a `sockaddr_storage`, which any socket address fits in.
+const SOCKADDR_STORAGE_LEN: usize = 128;
+
+#[non_exhaustive]
+#[derive(Debug, Clone)]
+pub enum SockAddr {
+    In(SockAddrIn),
+    In6(SockAddrIn6),
+    Un(SockAddrUn),
+    Ll(SockAddrLl),
+    Nl(SockAddrNl),
+}
+
+impl SockAddr {
+    /// Returns the length of the address, which for a unix socket, as on
+    /// Linux, runs to the end of its path, or is that of the family alone if
+    /// it's unnamed.
+    pub fn len(&self) -> SocketLen {
+        (match self {
+            SockAddr::In(_) => size_of::<SockAddrIn>(),
+            SockAddr::In6(_) => size_of::<SockAddrIn6>(),
+            SockAddr::Un(saun) => {
+                let path_len = match saun.path.iter().position(|b| *b == 0) {
+                    Some(0) => 0,
+                    // Take in the terminating NUL.
+                    Some(len) => len + 1,
+                    None => saun.path.len(),
+                };
+
+                size_of::<u16>() + path_len
+            }
+            SockAddr::Ll(_) => size_of::<SockAddrLl>(),
+            SockAddr::Nl(_) => size_of::<SockAddrNl>(),
+        }) as SocketLen
+    }
+
+    /// Returns the address as userspace sees it, [`Self::len`] bytes long.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            SockAddr::In(sain) => unsafe {
+                core::slice::from_raw_parts(
+                    (sain as *const SockAddrIn).cast::<u8>(),
+                    size_of::<SockAddrIn>(),
+                )
+                .to_vec()
+            },
+            SockAddr::In6(sain6) => unsafe {
+                core::slice::from_raw_parts(
+                    (sain6 as *const SockAddrIn6).cast::<u8>(),
+                    size_of::<SockAddrIn6>(),
+                )
+                .to_vec()
+            },
+            SockAddr::Un(saun) => unsafe {
+                core::slice::from_raw_parts(
+                    (saun as *const SockAddrUn).cast::<u8>(),
+                    self.len() as usize,
+                )
+                .to_vec()
+            },
+            SockAddr::Ll(sall) => unsafe {
+                core::slice::from_raw_parts(
+                    (sall as *const SockAddrLl).cast::<u8>(),
+                    size_of::<SockAddrLl>(),
+                )
+                .to_vec()
+            },
+            SockAddr::Nl(sanl) => unsafe {
+                core::slice::from_raw_parts(
+                    (sanl as *const SockAddrNl).cast::<u8>(),
+                    size_of::<SockAddrNl>(),
+                )
+                .to_vec()
+            },
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+#[repr(C, packed)]
+pub struct SockAddrIn {
+    pub(super) family: u16,
+    pub(super) port: [u8; 2],
+    pub(super) addr: [u8; 4],
+    pub(super) zero: [u8; 8],
+}
+
+#[derive(Copy, Clone, Debug)]
+#[repr(C, packed)]
+pub struct SockAddrIn6 {
+    pub(super) family: u16,
+    pub(super) port: [u8; 2],
+    pub(super) flowinfo: u32,
+    pub(super) addr: [u8; 16],
+    pub(super) scope_id: u32,
+}
+
+#[derive(Copy, Clone, Debug)]
+#[repr(C, packed)]
+pub struct SockAddrUn {
+    pub(super) family: u16,
+    pub(super) path: [u8; SUN_PATH_LEN],
+}
+
+/// A `sockaddr_ll`, which names a device, and a frame's protocol, for packet
+/// sockets. The protocol is in network byte order.
+#[derive(Copy, Clone, Debug)]
+#[repr(C, packed)]
+pub struct SockAddrLl {
+    pub family: u16,
+    pub protocol: [u8; 2],
+    pub ifindex: i32,
+    pub hatype: u16,
+    pub pkttype: u8,
+    pub halen: u8,
+    pub addr: [u8; 8],
+}
+
+/// A `sockaddr_nl`, which names a netlink socket by its port ID, zero being
+/// the kernel's.
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+pub struct SockAddrNl {
+    pub family: u16,
+    pub pad: u16,
+    pub pid: u32,
+    pub groups: u32,
+}
+
+unsafe impl UserCopyable for SockAddrIn {}
+unsafe impl UserCopyable for SockAddrIn6 {}
+unsafe impl UserCopyable for SockAddrUn {}
+unsafe impl UserCopyable for SockAddrLl {}
+unsafe impl UserCopyable for SockAddrNl {}
+
+impl TryFrom<SockAddr> for IpEndpoint {
+    type Error = KernelError;
+    fn try_from(sockaddr: SockAddr) -> Result<IpEndpoint, KernelError> {
+        match sockaddr {
+            SockAddr::In(SockAddrIn { port, addr, .. }) => Ok(IpEndpoint {
+                port: u16::from_be_bytes(port),
+                addr: IpAddress::Ipv4(Ipv4Addr::from(addr)),
+            }),
+            SockAddr::In6(SockAddrIn6 { port, addr, .. }) => Ok(IpEndpoint {
+                port: u16::from_be_bytes(port),
+                addr: IpAddress::Ipv6(Ipv6Addr::from(addr)),
+            }),
+            _ => Err(KernelError::InvalidValue),
+        }
+    }
+}
+
+impl From<IpEndpoint> for SockAddr {
+    fn from(endpoint: IpEndpoint) -> SockAddr {
+        match endpoint.addr {
+            IpAddress::Ipv4(addr) => SockAddr::In(SockAddrIn {
+                family: AF_INET as u16,
+                port: endpoint.port.to_be_bytes(),
+                addr: addr.octets(),
+                zero: [0; 8],
+            }),
+            IpAddress::Ipv6(addr) => SockAddr::In6(SockAddrIn6 {
+                family: AF_INET6 as u16,
+                port: endpoint.port.to_be_bytes(),
+                flowinfo: 0,
+                addr: addr.octets(),
+                scope_id: 0,
+            }),
+        }
+    }
+}
+
+/// Copies `sockaddr` out to the buffer at `uaddr`, of the length `*ulen`, as
+/// for `accept` and `recvfrom`. The address is truncated if it doesn't fit, and
+/// `*ulen` is set to its full length either way.
+pub async fn write_sockaddr(
+    sockaddr: &SockAddr,
+    uaddr: UA,
+    ulen: TUA<SocketLen>,
+) -> Result<(), KernelError> {
+    if ulen.is_null() {
+        return Err(KernelError::Fault);
+    }
+
+    let len = copy_from_user(ulen).await? as usize;
+    let bytes = sockaddr.to_bytes();
+    copy_to_user_slice(&bytes[..bytes.len().min(len)], uaddr).await?;
+    copy_to_user(ulen, sockaddr.len()).await
+}
+
+/// Copies the socket address of `len` bytes at `uaddr` in from userspace, as
+/// for `bind`, `connect` and `sendto`, checking that it's long enough for its
+/// family.
+pub async fn parse_sockaddr(uaddr: UA, len: SocketLen) -> Result<SockAddr, KernelError> {
+    let len = len as usize;
+
+    // As on Linux, the address must at least have a family, and can't be
+    // longer than a `sockaddr_storage`.
+    if !(size_of::<u16>()..=SOCKADDR_STORAGE_LEN).contains(&len) {
+        return Err(KernelError::InvalidValue);
+    }
+
+    let family: u16 = copy_from_user(uaddr.cast()).await?;
+
+    match family as i32 {
+        AF_INET => Ok(SockAddr::In(copy_sockaddr(uaddr, len).await?)),
+        AF_INET6 => Ok(SockAddr::In6(copy_sockaddr(uaddr, len).await?)),
+        AF_UNIX => {
+            // The path runs to the end of the address, and may leave out its
+            // terminating NUL.
+            let path_len = len - size_of::<u16>();
+            if path_len > SUN_PATH_LEN {
+                return Err(KernelError::InvalidValue);
+            }
+
+            let mut path = [0u8; SUN_PATH_LEN];
+            copy_from_user_slice(uaddr.add_bytes(size_of::<u16>()), &mut path[..path_len]).await?;
+            Ok(SockAddr::Un(SockAddrUn { family, path }))
+        }
+        AF_PACKET => Ok(SockAddr::Ll(copy_sockaddr(uaddr, len).await?)),
+        AF_NETLINK => Ok(SockAddr::Nl(copy_sockaddr(uaddr, len).await?)),
+        _ => Err(KernelError::AddressFamilyNotSupported),
+    }
+}
+
+/// Copies in a fixed-size socket address, which the `len` bytes given for it
+/// must cover.
+async fn copy_sockaddr<T: UserCopyable>(uaddr: UA, len: usize) -> Result<T, KernelError> {
+    if len < size_of::<T>() {
+        return Err(KernelError::InvalidValue);
+    }
+
+    copy_from_user(uaddr.cast()).await
+}
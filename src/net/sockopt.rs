@@ -0,0 +1,128 @@
+//! Socket options, as set and read by `setsockopt` and `getsockopt`.
+//!
+//! The syscalls turn each `(level, optname)` pair into a [`SockOpt`], which is
+//! handed to the socket itself. Every option supported so far is an `int`,
+//! besides the timeouts, which are `struct timeval`s.
+
+use libkernel::error::{KernelError, Result};
+
+pub const SOL_SOCKET: i32 = 1;
+pub const SOL_TCP: i32 = 6;
+pub const SOL_IPV6: i32 = 41;
+
+const SO_REUSEADDR: i32 = 2;
+const SO_TYPE: i32 = 3;
+const SO_ERROR: i32 = 4;
+const SO_SNDBUF: i32 = 7;
+const SO_RCVBUF: i32 = 8;
+const SO_KEEPALIVE: i32 = 9;
+const SO_REUSEPORT: i32 = 15;
+const SO_RCVTIMEO_OLD: i32 = 20;
+const SO_SNDTIMEO_OLD: i32 = 21;
+const SO_ACCEPTCONN: i32 = 30;
+const SO_PROTOCOL: i32 = 38;
+const SO_DOMAIN: i32 = 39;
+const SO_RCVTIMEO_NEW: i32 = 66;
+const SO_SNDTIMEO_NEW: i32 = 67;
+
+const TCP_NODELAY: i32 = 1;
+const TCP_KEEPIDLE: i32 = 4;
+const TCP_KEEPINTVL: i32 = 5;
+const TCP_KEEPCNT: i32 = 6;
+const TCP_QUICKACK: i32 = 12;
+const TCP_USER_TIMEOUT: i32 = 18;
+
+const IPV6_V6ONLY: i32 = 26;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SockOpt {
+    /// `SO_REUSEADDR`: whether the socket may bind to an address that's still
+    /// in use.
+    ReuseAddr,
+    /// `SO_REUSEPORT`: whether the socket may share its address and port with
+    /// others which set this too.
+    ReusePort,
+    /// `SO_TYPE`: the socket's type, e.g. `SOCK_STREAM`. Read only.
+    Type,
+    /// `SO_ERROR`: the socket's pending error, which is cleared by reading it.
+    /// Read only.
+    Error,
+    /// `SO_SNDBUF`: the size of the socket's send buffer.
+    SndBuf,
+    /// `SO_RCVBUF`: the size of the socket's receive buffer.
+    RcvBuf,
+    /// `SO_KEEPALIVE`: whether keepalives are sent on an idle connection.
+    KeepAlive,
+    /// `SO_RCVTIMEO`: how long a receive may block for.
+    RcvTimeo,
+    /// `SO_SNDTIMEO`: how long a send may block for.
+    SndTimeo,
+    /// `SO_ACCEPTCONN`: whether the socket is listening. Read only.
+    AcceptConn,
+    /// `SO_PROTOCOL`: the socket's protocol, e.g. `IPPROTO_TCP`. Read only.
+    Protocol,
+    /// `SO_DOMAIN`: the socket's address family, e.g. `AF_INET`. Read only.
+    Domain,
+    /// `TCP_NODELAY`: whether small segments are sent straight away, rather
+    /// than being held back to coalesce with later ones.
+    TcpNoDelay,
+    /// `TCP_KEEPIDLE`: how long, in seconds, a connection is idle before
+    /// keepalives are sent.
+    TcpKeepIdle,
+    /// `TCP_KEEPINTVL`: how long, in seconds, between keepalives.
+    TcpKeepIntvl,
+    /// `TCP_KEEPCNT`: how many keepalives go unanswered before the connection
+    /// is dropped.
+    TcpKeepCnt,
+    /// `TCP_QUICKACK`: whether segments are acknowledged straight away, rather
+    /// than the ACK being delayed in the hope of sending it with data.
+    TcpQuickAck,
+    /// `TCP_USER_TIMEOUT`: how long, in milliseconds, the peer may go quiet
+    /// before the connection is dropped, or zero for the default.
+    TcpUserTimeout,
+    /// `IPV6_V6ONLY`: whether an `AF_INET6` socket is kept to IPv6, rather
+    /// than also reaching IPv4 peers through v4-mapped addresses.
+    V6Only,
+}
+
+impl SockOpt {
+    pub fn from_raw(level: i32, optname: i32) -> Result<Self> {
+        match (level, optname) {
+            (SOL_SOCKET, SO_REUSEADDR) => Ok(Self::ReuseAddr),
+            (SOL_SOCKET, SO_TYPE) => Ok(Self::Type),
+            (SOL_SOCKET, SO_ERROR) => Ok(Self::Error),
+            (SOL_SOCKET, SO_SNDBUF) => Ok(Self::SndBuf),
+            (SOL_SOCKET, SO_RCVBUF) => Ok(Self::RcvBuf),
+            (SOL_SOCKET, SO_KEEPALIVE) => Ok(Self::KeepAlive),
+            (SOL_SOCKET, SO_REUSEPORT) => Ok(Self::ReusePort),
+            // On 64-bit targets, the old and new timevals are the same.
+            (SOL_SOCKET, SO_RCVTIMEO_OLD | SO_RCVTIMEO_NEW) => Ok(Self::RcvTimeo),
+            (SOL_SOCKET, SO_SNDTIMEO_OLD | SO_SNDTIMEO_NEW) => Ok(Self::SndTimeo),
+            (SOL_SOCKET, SO_ACCEPTCONN) => Ok(Self::AcceptConn),
+            (SOL_SOCKET, SO_PROTOCOL) => Ok(Self::Protocol),
+            (SOL_SOCKET, SO_DOMAIN) => Ok(Self::Domain),
+            (SOL_TCP, TCP_NODELAY) => Ok(Self::TcpNoDelay),
+            (SOL_TCP, TCP_KEEPIDLE) => Ok(Self::TcpKeepIdle),
+            (SOL_TCP, TCP_KEEPINTVL) => Ok(Self::TcpKeepIntvl),
+            (SOL_TCP, TCP_KEEPCNT) => Ok(Self::TcpKeepCnt),
+            (SOL_TCP, TCP_QUICKACK) => Ok(Self::TcpQuickAck),
+            (SOL_TCP, TCP_USER_TIMEOUT) => Ok(Self::TcpUserTimeout),
+            (SOL_IPV6, IPV6_V6ONLY) => Ok(Self::V6Only),
+            _ => Err(KernelError::NoProtocolOption),
+        }
+    }
+
+    /// Whether the option is a timeout, which is a `struct timeval` rather
+    /// than an `int`.
+    pub fn is_timeout(self) -> bool {
+        matches!(self, Self::RcvTimeo | Self::SndTimeo)
+    }
+
+    /// Whether the option can only be read.
+    pub fn is_read_only(self) -> bool {
+        matches!(
+            self,
+            Self::Type | Self::Error | Self::AcceptConn | Self::Protocol | Self::Domain
+        )
+    }
+}
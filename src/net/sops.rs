@@ -1,9 +1,17 @@
 use crate::fs::fops::FileOps;
-use crate::fs::open_file::FileCtx;
+use crate::fs::open_file::{FileCtx, OpenFile};
+use crate::memory::uaccess::iovec::UserIoVec;
+use crate::net::ifreq;
+use crate::net::sockopt::SockOpt;
+use crate::net::timeout::SocketTimeouts;
 use crate::net::{ShutdownHow, SockAddr};
 use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
 use async_trait::async_trait;
 use bitflags::bitflags;
+use core::future;
+use core::pin::Pin;
 use libkernel::error::KernelError;
 use libkernel::memory::address::UA;
 
@@ -24,17 +32,33 @@ bitflags! {
     #[derive(Copy, Clone)]
     pub struct RecvFlags: u32 {
         // TODO: rest of flags
+        const MSG_PEEK = 0x2;
+        const MSG_TRUNC = 0x20;
         const MSG_DONTWAIT = 0x40;
+        const MSG_WAITALL = 0x100;
+        const MSG_CMSG_CLOEXEC = 0x40000000;
     }
 }
 
+/// What a call to [`SocketOps::recvmsg`] received, besides the data itself.
+pub struct RecvMsg {
+    /// How many bytes were received.
+    pub len: usize,
+    /// Where the message came from.
+    pub addr: Option<SockAddr>,
+    /// Files passed along with the message, with `SCM_RIGHTS`.
+    pub rights: Vec<Arc<OpenFile>>,
+    /// Whether part of the message was discarded, as it didn't fit.
+    pub truncated: bool,
+}
+
 #[async_trait]
 pub trait SocketOps: Send + Sync {
     async fn bind(&self, _addr: SockAddr) -> libkernel::error::Result<()> {
         Err(KernelError::NotSupported)
     }
 
-    async fn connect(&self, _addr: SockAddr) -> libkernel::error::Result<()> {
+    async fn connect(&self, _ctx: &FileCtx, _addr: SockAddr) -> libkernel::error::Result<()> {
         Err(KernelError::NotSupported)
     }
 
@@ -42,7 +66,10 @@ pub trait SocketOps: Send + Sync {
         Err(KernelError::NotSupported)
     }
 
-    async fn accept(&self) -> libkernel::error::Result<(Box<dyn SocketOps>, SockAddr)> {
+    async fn accept(
+        &self,
+        _ctx: &FileCtx,
+    ) -> libkernel::error::Result<(Box<dyn SocketOps>, SockAddr)> {
         Err(KernelError::NotSupported)
     }
 
@@ -77,10 +104,150 @@ pub trait SocketOps: Send + Sync {
         addr: SockAddr,
     ) -> libkernel::error::Result<usize>;
 
+    /// Sends the data gathered from `iov`, along with `rights`, to `addr`, or
+    /// to the connected peer if there isn't one.
+    ///
+    /// By default each vector is sent in turn, which suits stream sockets.
+    /// Sockets which keep message boundaries, or which can pass files, should
+    /// override this.
+    async fn sendmsg(
+        &mut self,
+        ctx: &mut FileCtx,
+        iov: &UserIoVec,
+        flags: SendFlags,
+        addr: Option<SockAddr>,
+        rights: Vec<Arc<OpenFile>>,
+    ) -> libkernel::error::Result<usize> {
+        if !rights.is_empty() {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let mut total = 0;
+
+        for vec in iov.iovs().iter().filter(|vec| vec.iov_len != 0) {
+            let sent = match addr.clone() {
+                Some(addr) => {
+                    self.sendto(ctx, vec.iov_base, vec.iov_len, flags, addr)
+                        .await
+                }
+                None => self.send(ctx, vec.iov_base, vec.iov_len, flags).await,
+            };
+
+            match sent {
+                Ok(n) => {
+                    total += n;
+                    if n < vec.iov_len {
+                        break;
+                    }
+                }
+                Err(e) if total == 0 => return Err(e),
+                Err(_) => break,
+            }
+        }
+
+        Ok(total)
+    }
+
+    /// Receives data, scattering it across `iov`.
+    ///
+    /// By default each vector is filled in turn, without blocking once
+    /// anything's been received, which suits stream sockets. Sockets which keep
+    /// message boundaries, or which can pass files, should override this.
+    async fn recvmsg(
+        &mut self,
+        ctx: &mut FileCtx,
+        iov: &UserIoVec,
+        mut flags: RecvFlags,
+    ) -> libkernel::error::Result<RecvMsg> {
+        let mut msg = RecvMsg {
+            len: 0,
+            addr: None,
+            rights: Vec::new(),
+            truncated: false,
+        };
+
+        for vec in iov.iovs().iter().filter(|vec| vec.iov_len != 0) {
+            match self
+                .recvfrom(ctx, vec.iov_base, vec.iov_len, flags, None)
+                .await
+            {
+                Ok((n, addr)) => {
+                    msg.len += n;
+                    msg.addr = msg.addr.or(addr);
+                    if n < vec.iov_len {
+                        break;
+                    }
+                }
+                Err(e) if msg.len == 0 => return Err(e),
+                Err(_) => break,
+            }
+
+            // Peeking again would only see the same data.
+            if flags.contains(RecvFlags::MSG_PEEK) {
+                break;
+            }
+
+            flags.insert(RecvFlags::MSG_DONTWAIT);
+        }
+
+        Ok(msg)
+    }
+
     async fn shutdown(&self, _how: ShutdownHow) -> libkernel::error::Result<()> {
         Err(KernelError::NotSupported)
     }
 
+    /// Returns the value of the option `opt`.
+    fn getsockopt(&self, opt: SockOpt) -> libkernel::error::Result<i32> {
+        match opt {
+            SockOpt::Error => Ok(0),
+            _ => Err(KernelError::NoProtocolOption),
+        }
+    }
+
+    /// Sets the option `opt`, which isn't read only, to `value`.
+    fn setsockopt(&self, _opt: SockOpt, _value: i32) -> libkernel::error::Result<()> {
+        Err(KernelError::NoProtocolOption)
+    }
+
+    /// Returns the address the socket's bound to, for `getsockname`.
+    fn local_addr(&self) -> libkernel::error::Result<SockAddr> {
+        Err(KernelError::NotSupported)
+    }
+
+    /// Returns the address of the peer the socket's connected to, for
+    /// `getpeername`.
+    fn peer_addr(&self) -> libkernel::error::Result<SockAddr> {
+        Err(KernelError::NotConnected)
+    }
+
+    /// Returns the socket's `SO_RCVTIMEO` and `SO_SNDTIMEO`, if it has them.
+    fn timeouts(&self) -> Option<&SocketTimeouts> {
+        None
+    }
+
+    /// Waits until a call to `recv()` wouldn't block, including when a
+    /// listening socket has a connection waiting to be accepted.
+    fn poll_read_ready(
+        &self,
+    ) -> Pin<Box<dyn Future<Output = libkernel::error::Result<()>> + 'static + Send>> {
+        Box::pin(async { Err(KernelError::NotSupported) })
+    }
+
+    /// Waits until a call to `send()` wouldn't block.
+    fn poll_write_ready(
+        &self,
+    ) -> Pin<Box<dyn Future<Output = libkernel::error::Result<()>> + 'static + Send>> {
+        Box::pin(async { Err(KernelError::NotSupported) })
+    }
+
+    /// Waits until the connection has been closed in both directions.
+    fn poll_hangup(
+        &self,
+    ) -> Pin<Box<dyn Future<Output = libkernel::error::Result<()>> + 'static + Send>> {
+        Box::pin(future::pending())
+    }
+
     fn as_file(self: Box<Self>) -> Box<dyn FileOps>;
 }
 
@@ -127,6 +294,34 @@ where
         Err(KernelError::NotSupported)
     }
 
+    /// Sockets of every family take the interface ioctls.
+    async fn ioctl(
+        &mut self,
+        _ctx: &mut FileCtx,
+        request: usize,
+        argp: usize,
+    ) -> libkernel::error::Result<usize> {
+        ifreq::ioctl(request, argp).await
+    }
+
+    fn poll_read_ready(
+        &self,
+    ) -> Pin<Box<dyn Future<Output = libkernel::error::Result<()>> + 'static + Send>> {
+        SocketOps::poll_read_ready(self)
+    }
+
+    fn poll_write_ready(
+        &self,
+    ) -> Pin<Box<dyn Future<Output = libkernel::error::Result<()>> + 'static + Send>> {
+        SocketOps::poll_write_ready(self)
+    }
+
+    fn poll_hangup(
+        &self,
+    ) -> Pin<Box<dyn Future<Output = libkernel::error::Result<()>> + 'static + Send>> {
+        SocketOps::poll_hangup(self)
+    }
+
     fn as_socket(&mut self) -> Option<&mut dyn SocketOps> {
         Some(self)
     }
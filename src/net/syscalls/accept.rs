@@ -1,7 +1,7 @@
 use crate::fs::open_file::OpenFile;
-use crate::memory::uaccess::{copy_from_user, copy_to_user, copy_to_user_slice};
-use crate::net::SocketLen;
-use crate::process::fd_table::Fd;
+use crate::net::syscalls::socket::{CLOSE_ON_EXEC, NONBLOCK};
+use crate::net::{SocketLen, write_sockaddr};
+use crate::process::fd_table::{Fd, FdFlags};
 use crate::sched::syscall_ctx::ProcessCtx;
 use libkernel::error::KernelError;
 use libkernel::fs::OpenFlags;
@@ -12,8 +12,12 @@ pub async fn sys_accept4(
     fd: Fd,
     addr: UA,
     addrlen: TUA<SocketLen>,
-    _flags: i32,
+    flags: i32,
 ) -> libkernel::error::Result<usize> {
+    if flags & !(CLOSE_ON_EXEC | NONBLOCK) != 0 {
+        return Err(KernelError::InvalidValue);
+    }
+
     let file = ctx
         .shared()
         .fd_table
@@ -21,30 +25,36 @@ pub async fn sys_accept4(
         .get(fd)
         .ok_or(KernelError::BadFd)?;
 
-    let (ops, _ctx) = &mut *file.lock().await;
+    let (ops, file_ctx) = &mut *file.lock().await;
 
     let (new_socket, socket_addr) = ops
         .as_socket()
         .ok_or(KernelError::NotASocket)?
-        .accept()
+        .accept(file_ctx)
         .await?;
     let new_socket = new_socket.as_file();
 
-    let open_file = OpenFile::new(new_socket, OpenFlags::empty());
+    // The flags are applied as the socket's given its fd, so that no other
+    // thread can see it without them.
+    let open_flags = if flags & NONBLOCK != 0 {
+        OpenFlags::O_NONBLOCK
+    } else {
+        OpenFlags::empty()
+    };
+    let fd_flags = if flags & CLOSE_ON_EXEC != 0 {
+        FdFlags::CLOEXEC
+    } else {
+        FdFlags::empty()
+    };
+
+    let open_file = OpenFile::new(new_socket, open_flags);
     let new_fd = ctx
         .shared()
         .fd_table
         .lock_save_irq()
-        .insert(alloc::sync::Arc::new(open_file))?;
+        .insert_with_flags(alloc::sync::Arc::new(open_file), fd_flags)?;
     if !addr.is_null() {
-        if addrlen.is_null() {
-            return Err(KernelError::InvalidValue);
-        }
-        let addrlen_val = copy_from_user(addrlen).await?;
-        let bytes = socket_addr.to_bytes();
-        let to_copy = bytes.len().min(addrlen_val);
-        copy_to_user_slice(&bytes[..to_copy], addr).await?;
-        copy_to_user(addrlen, bytes.len()).await?;
+        write_sockaddr(&socket_addr, addr, addrlen).await?;
     }
     Ok(new_fd.as_raw() as usize)
 }
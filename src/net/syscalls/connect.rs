@@ -16,12 +16,12 @@ pub async fn sys_connect(
         .get(fd)
         .ok_or(libkernel::error::KernelError::BadFd)?;
 
-    let (ops, _ctx) = &mut *file.lock().await;
+    let (ops, file_ctx) = &mut *file.lock().await;
     let addr = parse_sockaddr(addr, addrlen).await?;
 
     ops.as_socket()
         .ok_or(libkernel::error::KernelError::NotASocket)?
-        .connect(addr)
+        .connect(file_ctx, addr)
         .await?;
     Ok(0)
 }
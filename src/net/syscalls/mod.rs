@@ -2,7 +2,10 @@ pub mod accept;
 pub mod bind;
 pub mod connect;
 pub mod listen;
+pub mod msg;
 pub mod recv;
 pub mod send;
 pub mod shutdown;
 pub mod socket;
+pub mod sockname;
+pub mod sockopt;
@@ -0,0 +1,396 @@
+//! `sendmsg` and `recvmsg`, and their batched forms `sendmmsg` and
+//! `recvmmsg`, along with the control messages which pass files between
+//! processes over unix sockets with `SCM_RIGHTS`.
+
+use crate::clock::timespec::TimeSpec;
+use crate::drivers::timer::uptime;
+use crate::fs::open_file::{FileCtx, OpenFile};
+use crate::memory::uaccess::iovec::{IoVec, UIO_MAXIOV, UserIoVec};
+use crate::memory::uaccess::{
+    UserCopyable, copy_from_user, copy_from_user_slice, copy_to_user, copy_to_user_slice,
+};
+use crate::net::sockopt::SOL_SOCKET;
+use crate::net::sops::{RecvFlags, SendFlags};
+use crate::net::{SocketLen, SocketOps, parse_sockaddr};
+use crate::process::fd_table::{Fd, FdFlags};
+use crate::sched::syscall_ctx::ProcessCtx;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::time::Duration;
+use libkernel::error::{KernelError, Result};
+use libkernel::memory::address::{TUA, UA};
+
+const SCM_RIGHTS: i32 = 1;
+
+/// The most files which can be passed in one message, as on Linux.
+const SCM_MAX_FD: usize = 253;
+
+/// The largest control buffer `sendmsg` takes, as Linux's default
+/// `net.core.optmem_max`.
+const OPTMEM_MAX: usize = 20480;
+
+const MSG_CTRUNC: i32 = 0x8;
+const MSG_TRUNC: i32 = 0x20;
+const MSG_WAITFORONE: i32 = 0x10000;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct MsgHdr {
+    name: UA,
+    namelen: SocketLen,
+    _pad0: u32,
+    iov: TUA<IoVec>,
+    iovlen: usize,
+    control: UA,
+    controllen: usize,
+    flags: i32,
+    _pad1: u32,
+}
+
+unsafe impl UserCopyable for MsgHdr {}
+
+/// A `struct mmsghdr`, one of the messages `sendmmsg` and `recvmmsg` take,
+/// along with how long it turned out to be.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct MMsgHdr {
+    hdr: MsgHdr,
+    len: u32,
+    _pad: u32,
+}
+
+unsafe impl UserCopyable for MMsgHdr {}
+
+/// The length of a `cmsghdr`: a `size_t` length, then an `int` level and type.
+const CMSG_HDR_LEN: usize = size_of::<usize>() + 2 * size_of::<i32>();
+
+/// Rounds `len` up to the alignment of control messages.
+const fn cmsg_align(len: usize) -> usize {
+    (len + size_of::<usize>() - 1) & !(size_of::<usize>() - 1)
+}
+
+/// Looks up the files named by the `SCM_RIGHTS` messages in `control`.
+///
+/// Messages at other levels are ignored, as on Linux.
+fn parse_rights(ctx: &ProcessCtx, control: &[u8]) -> Result<Vec<Arc<OpenFile>>> {
+    let fd_table = ctx.shared().fd_table.lock_save_irq();
+    let mut rights = Vec::new();
+    let mut offset = 0;
+
+    while offset + CMSG_HDR_LEN <= control.len() {
+        let cmsg = &control[offset..];
+        let (len, rest) = cmsg.split_at(size_of::<usize>());
+        let len = usize::from_ne_bytes(len.try_into().unwrap());
+        let level = i32::from_ne_bytes(rest[..4].try_into().unwrap());
+        let ty = i32::from_ne_bytes(rest[4..8].try_into().unwrap());
+
+        if len < CMSG_HDR_LEN || len > cmsg.len() {
+            return Err(KernelError::InvalidValue);
+        }
+
+        if level == SOL_SOCKET {
+            if ty != SCM_RIGHTS {
+                return Err(KernelError::InvalidValue);
+            }
+
+            for &fd in cmsg[CMSG_HDR_LEN..len]
+                .as_chunks::<{ size_of::<i32>() }>()
+                .0
+            {
+                if rights.len() == SCM_MAX_FD {
+                    return Err(KernelError::InvalidValue);
+                }
+
+                let fd = Fd(i32::from_ne_bytes(fd));
+                rights.push(fd_table.get(fd).ok_or(KernelError::BadFd)?);
+            }
+        }
+
+        offset += cmsg_align(len);
+    }
+
+    Ok(rights)
+}
+
+/// Gives `rights` fds in the task's table, and writes them out to the control
+/// buffer as an `SCM_RIGHTS` message, returning how much of the buffer was
+/// used. Files which don't fit are closed, and `MSG_CTRUNC` is set.
+async fn put_rights(
+    ctx: &ProcessCtx,
+    hdr: &mut MsgHdr,
+    rights: Vec<Arc<OpenFile>>,
+    flags: RecvFlags,
+) -> Result<usize> {
+    let room = if hdr.control.is_null() {
+        0
+    } else {
+        hdr.controllen.saturating_sub(CMSG_HDR_LEN) / size_of::<i32>()
+    };
+    let fd_flags = if flags.contains(RecvFlags::MSG_CMSG_CLOEXEC) {
+        FdFlags::CLOEXEC
+    } else {
+        FdFlags::empty()
+    };
+
+    let mut fds = Vec::new();
+    let mut left_over = Vec::new();
+    {
+        let mut fd_table = ctx.shared().fd_table.lock_save_irq();
+
+        for file in rights {
+            if fds.len() == room {
+                left_over.push(file);
+                continue;
+            }
+
+            match fd_table.insert_with_flags(file.clone(), fd_flags.clone()) {
+                Ok(fd) => fds.push(fd.as_raw()),
+                Err(_) => left_over.push(file),
+            }
+        }
+    }
+
+    if !left_over.is_empty() {
+        hdr.flags |= MSG_CTRUNC;
+
+        for file in left_over {
+            if let Some(file) = Arc::into_inner(file) {
+                let (ops, ctx) = &mut *file.lock().await;
+                let _ = ops.release(ctx).await;
+            }
+        }
+    }
+
+    if fds.is_empty() {
+        return Ok(0);
+    }
+
+    let len = CMSG_HDR_LEN + fds.len() * size_of::<i32>();
+    let mut cmsg = Vec::with_capacity(len);
+    cmsg.extend_from_slice(&len.to_ne_bytes());
+    cmsg.extend_from_slice(&SOL_SOCKET.to_ne_bytes());
+    cmsg.extend_from_slice(&SCM_RIGHTS.to_ne_bytes());
+    for fd in fds {
+        cmsg.extend_from_slice(&fd.to_ne_bytes());
+    }
+
+    copy_to_user_slice(&cmsg, hdr.control).await?;
+
+    Ok(cmsg_align(len).min(hdr.controllen))
+}
+
+/// Sends the message `hdr` describes on `socket`.
+async fn send_one(
+    ctx: &ProcessCtx,
+    socket: &mut dyn SocketOps,
+    file_ctx: &mut FileCtx,
+    hdr: &MsgHdr,
+    flags: SendFlags,
+) -> Result<usize> {
+    let addr = if hdr.name.is_null() || hdr.namelen == 0 {
+        None
+    } else {
+        Some(parse_sockaddr(hdr.name, hdr.namelen).await?)
+    };
+    let iov = UserIoVec::from_user(hdr.iov, hdr.iovlen).await?;
+
+    let rights = if hdr.control.is_null() || hdr.controllen == 0 {
+        Vec::new()
+    } else {
+        if hdr.controllen > OPTMEM_MAX {
+            return Err(KernelError::NoBufferSpace);
+        }
+
+        let mut control = vec![0; hdr.controllen];
+        copy_from_user_slice(hdr.control, &mut control).await?;
+        parse_rights(ctx, &control)?
+    };
+
+    socket.sendmsg(file_ctx, &iov, flags, addr, rights).await
+}
+
+/// Receives a message on `socket` into the buffers `hdr` describes, filling
+/// in its address, control messages and flags.
+async fn recv_one(
+    ctx: &ProcessCtx,
+    socket: &mut dyn SocketOps,
+    file_ctx: &mut FileCtx,
+    hdr: &mut MsgHdr,
+    flags: RecvFlags,
+) -> Result<usize> {
+    let iov = UserIoVec::from_user(hdr.iov, hdr.iovlen).await?;
+    let received = socket.recvmsg(file_ctx, &iov, flags).await?;
+
+    hdr.flags = if received.truncated { MSG_TRUNC } else { 0 };
+
+    hdr.namelen = match received.addr {
+        Some(addr) if !hdr.name.is_null() => {
+            let bytes = addr.to_bytes();
+            copy_to_user_slice(&bytes[..bytes.len().min(hdr.namelen as usize)], hdr.name).await?;
+            addr.len()
+        }
+        _ => 0,
+    };
+
+    hdr.controllen = put_rights(ctx, hdr, received.rights, flags).await?;
+
+    Ok(received.len)
+}
+
+pub async fn sys_sendmsg(ctx: &ProcessCtx, fd: Fd, msg: TUA<MsgHdr>, flags: i32) -> Result<usize> {
+    let file = ctx
+        .shared()
+        .fd_table
+        .lock_save_irq()
+        .get(fd)
+        .ok_or(KernelError::BadFd)?;
+    let hdr = copy_from_user(msg).await?;
+    let flags = SendFlags::from_bits_truncate(flags as u32);
+
+    let (ops, file_ctx) = &mut *file.lock().await;
+    let socket = ops.as_socket().ok_or(KernelError::NotASocket)?;
+
+    send_one(ctx, socket, file_ctx, &hdr, flags).await
+}
+
+pub async fn sys_recvmsg(ctx: &ProcessCtx, fd: Fd, msg: TUA<MsgHdr>, flags: i32) -> Result<usize> {
+    let file = ctx
+        .shared()
+        .fd_table
+        .lock_save_irq()
+        .get(fd)
+        .ok_or(KernelError::BadFd)?;
+    let mut hdr = copy_from_user(msg).await?;
+
+    if RecvFlags::from_bits(flags as u32).is_none() {
+        log::warn!("sys_recvmsg: flags parameter is not supported yet: {flags}");
+    }
+    let flags = RecvFlags::from_bits_truncate(flags as u32);
+
+    let len = {
+        let (ops, file_ctx) = &mut *file.lock().await;
+        let socket = ops.as_socket().ok_or(KernelError::NotASocket)?;
+
+        recv_one(ctx, socket, file_ctx, &mut hdr, flags).await?
+    };
+
+    copy_to_user(msg, hdr).await?;
+
+    Ok(len)
+}
+
+/// Sends each of the `vlen` messages in `msgvec` in turn, holding the socket
+/// for the whole batch, and returns how many were sent.
+///
+/// As on Linux, an error after the first message ends the batch early, and
+/// is only reported if nothing was sent.
+pub async fn sys_sendmmsg(
+    ctx: &ProcessCtx,
+    fd: Fd,
+    msgvec: TUA<MMsgHdr>,
+    vlen: u32,
+    flags: i32,
+) -> Result<usize> {
+    let file = ctx
+        .shared()
+        .fd_table
+        .lock_save_irq()
+        .get(fd)
+        .ok_or(KernelError::BadFd)?;
+    let flags = SendFlags::from_bits_truncate(flags as u32);
+
+    let (ops, file_ctx) = &mut *file.lock().await;
+    let socket = ops.as_socket().ok_or(KernelError::NotASocket)?;
+
+    let mut sent = 0;
+    for i in 0..(vlen as usize).min(UIO_MAXIOV) {
+        let entry = msgvec.add_objs(i);
+        let result: Result<()> = async {
+            let mut mmsg = copy_from_user(entry).await?;
+            mmsg.len = send_one(ctx, socket, file_ctx, &mmsg.hdr, flags).await? as u32;
+            copy_to_user(entry, mmsg).await
+        }
+        .await;
+
+        match result {
+            Ok(()) => sent += 1,
+            Err(e) if sent == 0 => return Err(e),
+            Err(_) => break,
+        }
+    }
+
+    Ok(sent)
+}
+
+/// Receives up to `vlen` messages into `msgvec`, holding the socket for the
+/// whole batch, and returns how many were received.
+///
+/// With `MSG_WAITFORONE`, only the first message is waited for. As on Linux,
+/// the timeout is only checked once each message has been received, and what's
+/// left of it is written back.
+pub async fn sys_recvmmsg(
+    ctx: &ProcessCtx,
+    fd: Fd,
+    msgvec: TUA<MMsgHdr>,
+    vlen: u32,
+    flags: i32,
+    timeout: TUA<TimeSpec>,
+) -> Result<usize> {
+    let file = ctx
+        .shared()
+        .fd_table
+        .lock_save_irq()
+        .get(fd)
+        .ok_or(KernelError::BadFd)?;
+    let deadline = if timeout.is_null() {
+        None
+    } else {
+        Some(uptime() + Duration::from(TimeSpec::copy_from_user(timeout).await?))
+    };
+
+    let wait_for_one = flags & MSG_WAITFORONE != 0;
+    let flags = flags & !MSG_WAITFORONE;
+    if RecvFlags::from_bits(flags as u32).is_none() {
+        log::warn!("sys_recvmmsg: flags parameter is not supported yet: {flags}");
+    }
+    let mut flags = RecvFlags::from_bits_truncate(flags as u32);
+
+    let received = {
+        let (ops, file_ctx) = &mut *file.lock().await;
+        let socket = ops.as_socket().ok_or(KernelError::NotASocket)?;
+
+        let mut received = 0;
+        for i in 0..(vlen as usize).min(UIO_MAXIOV) {
+            let entry = msgvec.add_objs(i);
+            let result: Result<()> = async {
+                let mut mmsg = copy_from_user(entry).await?;
+                mmsg.len = recv_one(ctx, socket, file_ctx, &mut mmsg.hdr, flags).await? as u32;
+                copy_to_user(entry, mmsg).await
+            }
+            .await;
+
+            match result {
+                Ok(()) => received += 1,
+                Err(e) if received == 0 => return Err(e),
+                Err(_) => break,
+            }
+
+            if wait_for_one {
+                flags |= RecvFlags::MSG_DONTWAIT;
+            }
+            if deadline.is_some_and(|deadline| uptime() >= deadline) {
+                break;
+            }
+        }
+
+        received
+    };
+
+    if let Some(deadline) = deadline {
+        let left = deadline.saturating_sub(uptime());
+        copy_to_user(timeout, TimeSpec::from(left)).await?;
+    }
+
+    Ok(received)
+}
@@ -1,6 +1,5 @@
-use crate::memory::uaccess::{copy_from_user, copy_to_user, copy_to_user_slice};
 use crate::net::sops::RecvFlags;
-use crate::net::{SocketLen, parse_sockaddr};
+use crate::net::{SocketLen, write_sockaddr};
 use crate::process::fd_table::Fd;
 use crate::sched::syscall_ctx::ProcessCtx;
 use libkernel::error::KernelError;
@@ -21,31 +20,19 @@ pub async fn sys_recvfrom(
         .lock_save_irq()
         .get(fd)
         .ok_or(KernelError::BadFd)?;
-    if flags != 0 {
+    if RecvFlags::from_bits(flags as u32).is_none() {
         log::warn!("sys_recvfrom: flags parameter is not supported yet: {flags}");
     }
+    let flags = RecvFlags::from_bits_truncate(flags as u32);
 
     let (ops, ctx) = &mut *file.lock().await;
     let socket = ops.as_socket().ok_or(KernelError::NotASocket)?;
-    let flags = RecvFlags::from_bits(flags as u32).unwrap_or(RecvFlags::empty());
-    let socket_addr = if !addr.is_null() {
-        let addrlen_val = copy_from_user(addrlen).await?;
-        Some(parse_sockaddr(addr, addrlen_val).await?)
-    } else {
-        None
-    };
-    let (message_len, recv_addr) = socket.recvfrom(ctx, buf, len, flags, socket_addr).await?;
+    // `addr` is only written to, with where the message came from.
+    let (message_len, recv_addr) = socket.recvfrom(ctx, buf, len, flags, None).await?;
     if let Some(recv_addr) = recv_addr
-        && addr.is_null()
+        && !addr.is_null()
     {
-        if addrlen.is_null() {
-            return Err(KernelError::InvalidValue);
-        }
-        let addrlen_val = copy_from_user(addrlen).await?;
-        let bytes = recv_addr.to_bytes();
-        let to_copy = bytes.len().min(addrlen_val);
-        copy_to_user_slice(&bytes[..to_copy], addr).await?;
-        copy_to_user(addrlen, bytes.len()).await?;
+        write_sockaddr(&recv_addr, addr, addrlen).await?;
     }
     Ok(message_len)
 }
@@ -1,42 +1,155 @@
 use crate::fs::fops::FileOps;
 use crate::fs::open_file::OpenFile;
+use crate::memory::uaccess::copy_to_user;
+use crate::net::icmp::IcmpSocket;
+use crate::net::netlink::{NETLINK_ROUTE, NetlinkSocket};
+use crate::net::packet::PacketSocket;
 use crate::net::tcp::TcpSocket;
+use crate::net::udp::UdpSocket;
 use crate::net::unix::UnixSocket;
-use crate::net::{AF_INET, AF_UNIX, IPPROTO_TCP, SOCK_DGRAM, SOCK_SEQPACKET, SOCK_STREAM};
+use crate::net::{
+    AF_INET, AF_INET6, AF_NETLINK, AF_PACKET, AF_UNIX, IPPROTO_ICMP, IPPROTO_TCP, IPPROTO_UDP,
+    SOCK_DGRAM, SOCK_RAW, SOCK_SEQPACKET, SOCK_STREAM,
+};
+use crate::process::fd_table::FdFlags;
 use crate::sched::syscall_ctx::ProcessCtx;
 use alloc::boxed::Box;
 use alloc::sync::Arc;
 use libkernel::error::KernelError;
 use libkernel::fs::OpenFlags;
+use libkernel::memory::address::TUA;
+use libkernel::proc::caps::CapabilitiesFlags;
 
 pub const CLOSE_ON_EXEC: i32 = 0x80000;
 pub const NONBLOCK: i32 = 0x800;
 
+/// The bits of a socket's type which say what type it is, rather than flags.
+const SOCK_TYPE_MASK: i32 = 0xf;
+
+/// Splits `SOCK_NONBLOCK` and `SOCK_CLOEXEC` off the type passed to `socket`
+/// or `socketpair`, returning the type, and the flags the new sockets' files
+/// and fds start with.
+fn split_type(type_: i32) -> libkernel::error::Result<(i32, OpenFlags, FdFlags)> {
+    if type_ & !SOCK_TYPE_MASK & !(CLOSE_ON_EXEC | NONBLOCK) != 0 {
+        return Err(KernelError::InvalidValue);
+    }
+
+    let open_flags = if type_ & NONBLOCK != 0 {
+        OpenFlags::O_NONBLOCK
+    } else {
+        OpenFlags::empty()
+    };
+    let fd_flags = if type_ & CLOSE_ON_EXEC != 0 {
+        FdFlags::CLOEXEC
+    } else {
+        FdFlags::empty()
+    };
+
+    Ok((type_ & SOCK_TYPE_MASK, open_flags, fd_flags))
+}
+
 pub async fn sys_socket(
     ctx: &ProcessCtx,
     domain: i32,
     type_: i32,
     protocol: i32,
 ) -> libkernel::error::Result<usize> {
-    let _close_on_exec = (type_ & CLOSE_ON_EXEC) != 0;
-    let _nonblock = (type_ & NONBLOCK) != 0;
-    // Mask out flags
-    let type_ = type_ & !(CLOSE_ON_EXEC | NONBLOCK);
+    let (type_, open_flags, fd_flags) = split_type(type_)?;
     let new_socket: Box<dyn FileOps> = match (domain, type_, protocol) {
-        (AF_INET, SOCK_STREAM, 0) | (AF_INET, SOCK_STREAM, IPPROTO_TCP) => {
-            Box::new(TcpSocket::new())
+        (domain @ (AF_INET | AF_INET6), SOCK_STREAM, 0 | IPPROTO_TCP) => {
+            Box::new(TcpSocket::new(domain)?)
+        }
+        (domain @ (AF_INET | AF_INET6), SOCK_DGRAM, 0 | IPPROTO_UDP) => {
+            Box::new(UdpSocket::new(domain)?)
+        }
+        (AF_INET, SOCK_RAW, IPPROTO_ICMP) => {
+            ctx.shared()
+                .creds
+                .lock_save_irq()
+                .caps()
+                .check_capable(CapabilitiesFlags::CAP_NET_RAW)?;
+
+            Box::new(IcmpSocket::new(SOCK_RAW)?)
+        }
+        (AF_INET, SOCK_DGRAM, IPPROTO_ICMP) => Box::new(IcmpSocket::new(SOCK_DGRAM)?),
+        (AF_PACKET, ty @ (SOCK_RAW | SOCK_DGRAM), protocol) => {
+            ctx.shared()
+                .creds
+                .lock_save_irq()
+                .caps()
+                .check_capable(CapabilitiesFlags::CAP_NET_RAW)?;
+
+            Box::new(PacketSocket::new(ty, protocol as u16)?)
+        }
+        (AF_NETLINK, ty @ (SOCK_RAW | SOCK_DGRAM), NETLINK_ROUTE) => {
+            Box::new(NetlinkSocket::new(ty)?)
         }
         (AF_UNIX, SOCK_STREAM, _) => Box::new(UnixSocket::new_stream()),
         (AF_UNIX, SOCK_DGRAM, _) => Box::new(UnixSocket::new_datagram()),
         (AF_UNIX, SOCK_SEQPACKET, _) => Box::new(UnixSocket::new_seqpacket()),
         _ => return Err(KernelError::AddressFamilyNotSupported),
     };
-    // TODO: Correct flags
-    let open_file = OpenFile::new(new_socket, OpenFlags::empty());
+
+    // The flags are applied as the socket's given its fd, so that no other
+    // thread can see it without them.
+    let open_file = OpenFile::new(new_socket, open_flags);
     let fd = ctx
         .shared()
         .fd_table
         .lock_save_irq()
-        .insert(Arc::new(open_file))?;
+        .insert_with_flags(Arc::new(open_file), fd_flags)?;
     Ok(fd.as_raw() as usize)
 }
+
+/// Makes a pair of unix sockets connected to each other, storing their fds
+/// in `sv`.
+pub async fn sys_socketpair(
+    ctx: &ProcessCtx,
+    domain: i32,
+    type_: i32,
+    protocol: i32,
+    sv: TUA<[i32; 2]>,
+) -> libkernel::error::Result<usize> {
+    let (type_, open_flags, fd_flags) = split_type(type_)?;
+
+    let new = match (domain, type_, protocol) {
+        (AF_UNIX, SOCK_STREAM, 0 | AF_UNIX) => UnixSocket::new_stream,
+        (AF_UNIX, SOCK_DGRAM, 0 | AF_UNIX) => UnixSocket::new_datagram,
+        (AF_UNIX, SOCK_SEQPACKET, 0 | AF_UNIX) => UnixSocket::new_seqpacket,
+        // Only unix sockets come in pairs.
+        (AF_INET | AF_INET6 | AF_NETLINK | AF_PACKET, _, _) => {
+            return Err(KernelError::OpNotSupported);
+        }
+        _ => return Err(KernelError::AddressFamilyNotSupported),
+    };
+    let (first, second) = (new(), new());
+    first.connect_pair(&second);
+
+    let fds = {
+        let mut fd_table = ctx.shared().fd_table.lock_save_irq();
+        let first = fd_table.insert_with_flags(
+            Arc::new(OpenFile::new(Box::new(first), open_flags)),
+            fd_flags.clone(),
+        )?;
+        match fd_table.insert_with_flags(
+            Arc::new(OpenFile::new(Box::new(second), open_flags)),
+            fd_flags,
+        ) {
+            Ok(second) => [first, second],
+            Err(e) => {
+                fd_table.remove(first);
+                return Err(e);
+            }
+        }
+    };
+
+    if let Err(e) = copy_to_user(sv, fds.map(|fd| fd.as_raw())).await {
+        let mut fd_table = ctx.shared().fd_table.lock_save_irq();
+        for fd in fds {
+            fd_table.remove(fd);
+        }
+        return Err(e);
+    }
+
+    Ok(0)
+}
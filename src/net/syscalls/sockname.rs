@@ -0,0 +1,48 @@
+use crate::net::{SockAddr, SocketLen, SocketOps, write_sockaddr};
+use crate::process::fd_table::Fd;
+use crate::sched::syscall_ctx::ProcessCtx;
+use libkernel::error::{KernelError, Result};
+use libkernel::memory::address::{TUA, UA};
+
+/// Looks up one of the addresses of the socket at `fd` with `get` and copies it
+/// out to userspace.
+async fn sys_sockname(
+    ctx: &ProcessCtx,
+    fd: Fd,
+    addr: UA,
+    addrlen: TUA<SocketLen>,
+    get: fn(&dyn SocketOps) -> Result<SockAddr>,
+) -> Result<usize> {
+    let file = ctx
+        .shared()
+        .fd_table
+        .lock_save_irq()
+        .get(fd)
+        .ok_or(KernelError::BadFd)?;
+
+    let sockaddr = {
+        let (ops, _) = &mut *file.lock().await;
+        get(ops.as_socket().ok_or(KernelError::NotASocket)?)?
+    };
+
+    write_sockaddr(&sockaddr, addr, addrlen).await?;
+    Ok(0)
+}
+
+pub async fn sys_getsockname(
+    ctx: &ProcessCtx,
+    fd: Fd,
+    addr: UA,
+    addrlen: TUA<SocketLen>,
+) -> Result<usize> {
+    sys_sockname(ctx, fd, addr, addrlen, |socket| socket.local_addr()).await
+}
+
+pub async fn sys_getpeername(
+    ctx: &ProcessCtx,
+    fd: Fd,
+    addr: UA,
+    addrlen: TUA<SocketLen>,
+) -> Result<usize> {
+    sys_sockname(ctx, fd, addr, addrlen, |socket| socket.peer_addr()).await
+}
@@ -0,0 +1,111 @@
+use crate::clock::timeval::TimeVal;
+use crate::memory::uaccess::{copy_from_user, copy_to_user};
+use crate::net::SocketLen;
+use crate::net::sockopt::SockOpt;
+use crate::process::fd_table::Fd;
+use crate::sched::syscall_ctx::ProcessCtx;
+use libkernel::error::{KernelError, Result};
+use libkernel::memory::address::TUA;
+
+pub async fn sys_getsockopt(
+    ctx: &ProcessCtx,
+    fd: Fd,
+    level: i32,
+    optname: i32,
+    optval: TUA<i32>,
+    optlen: TUA<SocketLen>,
+) -> Result<usize> {
+    let file = ctx
+        .shared()
+        .fd_table
+        .lock_save_irq()
+        .get(fd)
+        .ok_or(KernelError::BadFd)?;
+    let opt = SockOpt::from_raw(level, optname)?;
+
+    if opt.is_timeout() {
+        if (copy_from_user(optlen).await? as usize) < size_of::<TimeVal>() {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let timeout = {
+            let (ops, _) = &mut *file.lock().await;
+            ops.as_socket()
+                .ok_or(KernelError::NotASocket)?
+                .timeouts()
+                .ok_or(KernelError::NoProtocolOption)?
+                .get(opt)
+        };
+
+        copy_to_user(optval.to_untyped().cast(), TimeVal::from(timeout)).await?;
+        copy_to_user(optlen, size_of::<TimeVal>() as SocketLen).await?;
+
+        return Ok(0);
+    }
+
+    if (copy_from_user(optlen).await? as usize) < size_of::<i32>() {
+        return Err(KernelError::InvalidValue);
+    }
+
+    let value = {
+        let (ops, _) = &mut *file.lock().await;
+        ops.as_socket()
+            .ok_or(KernelError::NotASocket)?
+            .getsockopt(opt)?
+    };
+
+    copy_to_user(optval, value).await?;
+    copy_to_user(optlen, size_of::<i32>() as SocketLen).await?;
+
+    Ok(0)
+}
+
+pub async fn sys_setsockopt(
+    ctx: &ProcessCtx,
+    fd: Fd,
+    level: i32,
+    optname: i32,
+    optval: TUA<i32>,
+    optlen: SocketLen,
+) -> Result<usize> {
+    let file = ctx
+        .shared()
+        .fd_table
+        .lock_save_irq()
+        .get(fd)
+        .ok_or(KernelError::BadFd)?;
+    let opt = SockOpt::from_raw(level, optname)?;
+
+    if opt.is_read_only() {
+        return Err(KernelError::NoProtocolOption);
+    }
+    if opt.is_timeout() {
+        if (optlen as usize) < size_of::<TimeVal>() {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let timeout = TimeVal::copy_from_user(optval.to_untyped().cast()).await?;
+
+        let (ops, _) = &mut *file.lock().await;
+        ops.as_socket()
+            .ok_or(KernelError::NotASocket)?
+            .timeouts()
+            .ok_or(KernelError::NoProtocolOption)?
+            .set(opt, timeout.into());
+
+        return Ok(0);
+    }
+
+    if (optlen as usize) < size_of::<i32>() {
+        return Err(KernelError::InvalidValue);
+    }
+
+    let value = copy_from_user(optval).await?;
+
+    let (ops, _) = &mut *file.lock().await;
+    ops.as_socket()
+        .ok_or(KernelError::NotASocket)?
+        .setsockopt(opt, value)?;
+
+    Ok(0)
+}
@@ -0,0 +1,89 @@
+//! Each interface's directory in sysfs, `/sys/class/net/<iface>`, holding
+//! its attributes as Linux lays them out.
+//!
+//! The attributes look the interface up by its index each time they're read,
+//! so they needn't hold on to it.
+
+use super::device::DeviceStats;
+use super::iface::{NetInterface, check_net_admin, interfaces};
+use crate::drivers::fs::sys::kobject::{Attribute, kobject_root};
+use alloc::format;
+use alloc::string::String;
+use core::sync::atomic::{AtomicU64, Ordering};
+use libkernel::error::{FsError, KernelError, Result};
+
+/// Picks one of a device's counters out of its [`DeviceStats`].
+type StatCounter = fn(&DeviceStats) -> &AtomicU64;
+
+/// The counters under `statistics`, each with the file it's in.
+const STATISTICS: [(&str, StatCounter); 5] = [
+    ("rx_bytes", |stats| &stats.rx_bytes),
+    ("rx_packets", |stats| &stats.rx_packets),
+    ("tx_bytes", |stats| &stats.tx_bytes),
+    ("tx_packets", |stats| &stats.tx_packets),
+    ("tx_dropped", |stats| &stats.tx_dropped),
+];
+
+fn with_iface<T>(index: u32, f: impl FnOnce(&mut NetInterface) -> Result<T>) -> Result<T> {
+    let mut interfaces = interfaces().lock_save_irq();
+    let iface = interfaces.get_mut(index).ok_or(FsError::NoDevice)?;
+
+    f(iface)
+}
+
+/// An attribute showing what `show` makes of the interface numbered `index`.
+fn iface_attr(index: u32, show: fn(&NetInterface) -> String) -> Attribute {
+    Attribute::read_only(move || with_iface(index, |iface| Ok(show(iface))))
+}
+
+/// Adds the directory of the interface `name`, numbered `index`.
+pub fn add_iface(index: u32, name: &str) {
+    let kobj = kobject_root().child("class").child("net").child(name);
+
+    kobj.add_attr(
+        "address",
+        iface_attr(index, |iface| {
+            let [a, b, c, d, e, f] = iface.mac().unwrap_or_default();
+            format!("{a:02x}:{b:02x}:{c:02x}:{d:02x}:{e:02x}:{f:02x}\n")
+        }),
+    );
+    kobj.add_attr(
+        "flags",
+        iface_attr(index, |iface| format!("{:#x}\n", iface.flags())),
+    );
+    kobj.add_attr(
+        "ifindex",
+        iface_attr(index, |iface| format!("{}\n", iface.index())),
+    );
+    kobj.add_attr(
+        "type",
+        iface_attr(index, |iface| format!("{}\n", iface.hatype())),
+    );
+    kobj.add_attr(
+        "mtu",
+        Attribute::read_write(
+            move || with_iface(index, |iface| Ok(format!("{}\n", iface.mtu()))),
+            move |value| {
+                check_net_admin()?;
+                let mtu = value.parse().map_err(|_| KernelError::InvalidValue)?;
+
+                with_iface(index, |iface| iface.set_mtu(mtu))
+            },
+        ),
+    );
+
+    let stats = kobj.child("statistics");
+    for (name, counter) in STATISTICS {
+        stats.add_attr(
+            name,
+            Attribute::read_only(move || {
+                with_iface(index, |iface| {
+                    Ok(format!(
+                        "{}\n",
+                        counter(iface.stats()).load(Ordering::Relaxed)
+                    ))
+                })
+            }),
+        );
+    }
+}
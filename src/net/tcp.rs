@@ -1,26 +1,66 @@
-use crate::arch::ArchImpl;
 use crate::fs::fops::FileOps;
 use crate::fs::open_file::FileCtx;
+use crate::memory::uaccess::{copy_from_user_slice, copy_to_user_slice};
+use crate::net::buffer::BufferSizes;
+use crate::net::iface::interfaces;
+use crate::net::port::{PortBinding, PortTable, Reuse, bound_addr};
+use crate::net::proc::{SocketRow, TCP_LISTEN, tcp_state};
+use crate::net::sockopt::SockOpt;
 use crate::net::sops::{RecvFlags, SendFlags, SocketOps};
-use crate::net::{ShutdownHow, SockAddr, process_packets, sockets};
+use crate::net::timeout::{SocketTimeouts, block};
+use crate::net::{
+    AF_INET6, IPPROTO_TCP, InetEndpoint, SOCK_STREAM, ShutdownHow, SockAddr, family_reaches,
+    process_packets, sockets, unspecified_addr, wait_for_sockets,
+};
 use crate::sync::SpinLock;
 use alloc::boxed::Box;
-use alloc::collections::BTreeSet;
-use alloc::sync::Arc;
+use alloc::collections::VecDeque;
 use alloc::vec;
 use alloc::vec::Vec;
 use async_trait::async_trait;
-use core::sync::atomic::{AtomicUsize, Ordering};
+use core::future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
 use libkernel::error::KernelError;
+use libkernel::error::syscall_error::ECONNREFUSED;
+use libkernel::fs::OpenFlags;
 use libkernel::memory::address::UA;
-use libkernel::sync::spinlock::SpinLockIrqGuard;
-use smoltcp::iface::SocketHandle;
-use smoltcp::socket::tcp::SocketBuffer;
-use smoltcp::wire::IpEndpoint;
+use smoltcp::iface::{SocketHandle, SocketSet};
+use smoltcp::socket::tcp::{ConnectError, SocketBuffer, State};
+use smoltcp::time::Duration;
+use smoltcp::wire::{IpAddress, IpEndpoint, IpListenEndpoint};
 
-const BACKLOG_MAX: usize = 8;
-#[expect(dead_code)]
-static INUSE_ENDPOINTS: SpinLock<BTreeSet<u16>> = SpinLock::new(BTreeSet::new());
+/// `net.core.somaxconn`: the longest a listening socket's backlog can be. The
+/// backlog bounds both the connections waiting to be accepted and the sockets,
+/// with their buffers, which wait for new ones, so this is kept small.
+pub static SOMAXCONN: AtomicUsize = AtomicUsize::new(8);
+
+/// How often keepalives are sent on an idle connection, once they're turned on
+/// with `SO_KEEPALIVE`, unless `TCP_KEEPIDLE` says otherwise, as Linux's
+/// default `net.ipv4.tcp_keepalive_time`.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(7200);
+
+/// The defaults for `TCP_KEEPINTVL` and `TCP_KEEPCNT`, as Linux's
+/// `net.ipv4.tcp_keepalive_intvl` and `net.ipv4.tcp_keepalive_probes`.
+const KEEPALIVE_PROBE_INTERVAL: u32 = 75;
+const KEEPALIVE_PROBES: u32 = 9;
+
+/// The longest `TCP_KEEPIDLE` and `TCP_KEEPINTVL`, in seconds, and
+/// `TCP_KEEPCNT` can be, as on Linux.
+const MAX_KEEPALIVE_TIME: i32 = 32767;
+const MAX_KEEPALIVE_PROBES: i32 = 127;
+
+/// How long an ACK is held back for, unless `TCP_QUICKACK` is set, as
+/// smoltcp's default.
+const ACK_DELAY: Duration = Duration::from_millis(10);
+
+/// The ports TCP sockets are bound to.
+static TCP_PORTS: PortTable = PortTable::new();
+
+/// Sockets which were dropped before their connection had closed, which the
+/// stack keeps hold of until it has finished closing them, along with their
+/// hold on their port.
+static CLOSING: SpinLock<Vec<(SocketHandle, Option<PortBinding>)>> = SpinLock::new(Vec::new());
 #[expect(dead_code)]
 static PASSIVE_OPENS_TOTAL: AtomicUsize = AtomicUsize::new(0);
 #[expect(dead_code)]
@@ -30,117 +70,894 @@ static READ_BYTES_TOTAL: AtomicUsize = AtomicUsize::new(0);
 
 pub struct TcpSocket {
     handle: SocketHandle,
+    /// `AF_INET` or `AF_INET6`.
+    domain: i32,
+    /// `IPV6_V6ONLY`, which keeps an `AF_INET6` socket from reaching IPv4
+    /// peers.
+    v6only: AtomicBool,
     local_endpoint: SpinLock<Option<IpEndpoint>>,
-    backlogs: SpinLock<Vec<Arc<TcpSocket>>>,
-    num_backlogs: AtomicUsize,
+    /// The port the socket's bound to, either by `bind` or when it connected
+    /// or listened without being bound, which is given back when it's dropped.
+    /// Accepted sockets share their listener's.
+    port: SpinLock<Option<PortBinding>>,
+    /// The connections coming in, once the socket's listening.
+    queues: SpinLock<ListenQueues>,
+    /// The backlog given to `listen`, or zero if the socket isn't listening.
+    backlog: AtomicUsize,
+    reuse_addr: AtomicBool,
+    reuse_port: AtomicBool,
+    /// `TCP_KEEPIDLE`, in seconds, which is how often the stack sends a
+    /// keepalive on an idle connection. It has no separate interval between
+    /// unanswered ones, or limit on how many go unanswered, so
+    /// `TCP_KEEPINTVL` and `TCP_KEEPCNT` are only remembered.
+    keep_idle: AtomicU32,
+    keep_intvl: AtomicU32,
+    keep_cnt: AtomicU32,
+    /// Whether a nonblocking `connect` was started, which `SO_ERROR` reports
+    /// the outcome of.
+    connecting: AtomicBool,
+    /// Whether the socket's been shut down for reading, after which anything
+    /// the peer sends is thrown away.
+    rd_shutdown: AtomicBool,
+    buffers: SpinLock<BufferSizes>,
+    timeouts: SocketTimeouts,
+}
+
+/// A listening socket's connections, from the handshake to `accept`.
+///
+/// The SYN queue is a pool of sockets listening on the socket's endpoint, which
+/// the stack carries out handshakes with. Once a handshake's finished, the
+/// connection moves to the accept queue, which holds at most the backlog, and
+/// the pool is topped up. When the accept queue's full, finished connections
+/// wait in the pool instead, so once the pool's used up too, the stack resets
+/// any more peers which try to connect, rather than the socket taking on more
+/// connections than its backlog says.
+#[derive(Default)]
+struct ListenQueues {
+    syn: Vec<TcpSocket>,
+    /// Connections waiting to be accepted, oldest first, with their peers.
+    accept: VecDeque<(TcpSocket, IpEndpoint)>,
+}
+
+/// Whether `socket`, from a SYN queue, has got past the handshake.
+fn handshake_done(socket: &smoltcp::socket::tcp::Socket) -> bool {
+    !matches!(socket.state(), State::Listen | State::SynReceived)
+}
+
+/// Makes a socket with buffers of the given sizes.
+fn new_inner(sizes: &BufferSizes) -> smoltcp::socket::tcp::Socket<'static> {
+    let rx_buffer = SocketBuffer::new(vec![0; sizes.rx()]);
+    let tx_buffer = SocketBuffer::new(vec![0; sizes.tx()]);
+    smoltcp::socket::tcp::Socket::new(rx_buffer, tx_buffer)
+}
+
+/// The options `setsockopt` sets on the stack's socket, which are carried over
+/// when it's replaced, and to accepted sockets from their listener.
+#[derive(Clone, Copy)]
+struct StackOptions {
+    keep_alive: Option<Duration>,
+    nagle: bool,
+    ack_delay: Option<Duration>,
+    timeout: Option<Duration>,
+}
+
+impl StackOptions {
+    fn of(socket: &smoltcp::socket::tcp::Socket) -> Self {
+        Self {
+            keep_alive: socket.keep_alive(),
+            nagle: socket.nagle_enabled(),
+            ack_delay: socket.ack_delay(),
+            timeout: socket.timeout(),
+        }
+    }
+
+    fn apply(self, socket: &mut smoltcp::socket::tcp::Socket) {
+        socket.set_keep_alive(self.keep_alive);
+        socket.set_nagle_enabled(self.nagle);
+        socket.set_ack_delay(self.ack_delay);
+        socket.set_timeout(self.timeout);
+    }
 }
 
 impl TcpSocket {
-    pub fn new() -> Self {
-        let rx_buffer = SocketBuffer::new(vec![0; 4096]);
-        let tx_buffer = SocketBuffer::new(vec![0; 4096]);
-        let inner = smoltcp::socket::tcp::Socket::new(rx_buffer, tx_buffer);
-        let handle = sockets().lock_save_irq().add(inner);
+    pub fn new(domain: i32) -> Result<Self, KernelError> {
+        Ok(Self::with_buffers(domain, BufferSizes::new()?))
+    }
+
+    fn with_buffers(domain: i32, buffers: BufferSizes) -> Self {
+        let handle = sockets().lock_save_irq().add(new_inner(&buffers));
         TcpSocket {
             handle,
+            domain,
+            v6only: AtomicBool::new(false),
             local_endpoint: SpinLock::new(None),
-            backlogs: SpinLock::new(Vec::new()),
-            num_backlogs: AtomicUsize::new(0),
+            port: SpinLock::new(None),
+            queues: SpinLock::new(ListenQueues::default()),
+            backlog: AtomicUsize::new(0),
+            reuse_addr: AtomicBool::new(false),
+            reuse_port: AtomicBool::new(false),
+            keep_idle: AtomicU32::new(KEEPALIVE_INTERVAL.secs() as u32),
+            keep_intvl: AtomicU32::new(KEEPALIVE_PROBE_INTERVAL),
+            keep_cnt: AtomicU32::new(KEEPALIVE_PROBES),
+            connecting: AtomicBool::new(false),
+            rd_shutdown: AtomicBool::new(false),
+            buffers: SpinLock::new(buffers),
+            timeouts: SocketTimeouts::default(),
         }
     }
 
-    fn refill_backlog_sockets(
-        &self,
-        backlogs: &mut SpinLockIrqGuard<Vec<Arc<TcpSocket>>, ArchImpl>,
-    ) -> Result<(), KernelError> {
+    /// Tops the SYN queue `syn` up to the backlog.
+    fn refill_syn_queue(&self, syn: &mut Vec<TcpSocket>) -> Result<(), KernelError> {
         let local_endpoint = match *self.local_endpoint.lock_save_irq() {
             Some(local_endpoint) => local_endpoint,
             None => return Err(KernelError::InvalidValue),
         };
 
-        for _ in 0..(self.num_backlogs.load(Ordering::Relaxed) - backlogs.len()) {
-            let socket = TcpSocket::new();
-            sockets()
-                .lock_save_irq()
-                .get_mut::<smoltcp::socket::tcp::Socket>(socket.handle)
-                .listen(local_endpoint)
-                .unwrap();
-            backlogs.push(Arc::new(socket));
+        // Listen on any address unless the socket was bound to one.
+        let listen_endpoint = IpListenEndpoint {
+            addr: (!local_endpoint.addr.is_unspecified()).then_some(local_endpoint.addr),
+            port: local_endpoint.port,
+        };
+
+        for _ in syn.len()..self.backlog.load(Ordering::Relaxed) {
+            // Accepted sockets take after the listening one.
+            let buffers = {
+                let buffers = self.buffers.lock_save_irq();
+                BufferSizes::with_sizes(buffers.rx(), buffers.tx())?
+            };
+            let socket = TcpSocket::with_buffers(self.domain, buffers);
+            socket
+                .v6only
+                .store(self.v6only.load(Ordering::Relaxed), Ordering::Relaxed);
+            *socket.port.lock_save_irq() =
+                self.port.lock_save_irq().as_ref().map(PortBinding::share);
+            for (from, to) in [
+                (&self.keep_idle, &socket.keep_idle),
+                (&self.keep_intvl, &socket.keep_intvl),
+                (&self.keep_cnt, &socket.keep_cnt),
+            ] {
+                to.store(from.load(Ordering::Relaxed), Ordering::Relaxed);
+            }
+            let listening = {
+                let mut sockets = sockets().lock_save_irq();
+                let options =
+                    StackOptions::of(sockets.get::<smoltcp::socket::tcp::Socket>(self.handle));
+                let inner = sockets.get_mut::<smoltcp::socket::tcp::Socket>(socket.handle);
+                let listening = inner.listen(listen_endpoint);
+                options.apply(inner);
+                listening
+            };
+            // The sockets lock has to be dropped first, as dropping `socket`
+            // on an error takes it.
+            listening.map_err(|_| KernelError::InvalidValue)?;
+            *socket.local_endpoint.lock_save_irq() = Some(local_endpoint);
+            syn.push(socket);
         }
 
         Ok(())
     }
+
+    /// Moves the connections in the SYN queue which have finished their
+    /// handshake to the accept queue, while it has room, and tops the SYN
+    /// queue back up.
+    fn queue_connections(&self, queues: &mut ListenQueues) -> Result<(), KernelError> {
+        let backlog = self.backlog.load(Ordering::Relaxed);
+        let mut index = 0;
+
+        while index < queues.syn.len() && queues.accept.len() < backlog {
+            let (done, remote) = {
+                let sockets = sockets().lock_save_irq();
+                let socket = sockets.get::<smoltcp::socket::tcp::Socket>(queues.syn[index].handle);
+                (handshake_done(socket), socket.remote_endpoint())
+            };
+            if !done {
+                index += 1;
+                continue;
+            }
+
+            // Connections which were reset before they got here are let go.
+            // So are those from peers the socket can't talk to, e.g. IPv4
+            // peers of an `IPV6_V6ONLY` socket, which it hears from when it's
+            // listening on any address.
+            let socket = queues.syn.remove(index);
+            match remote {
+                Some(remote)
+                    if family_reaches(
+                        self.domain,
+                        self.v6only.load(Ordering::Relaxed),
+                        remote.addr,
+                    ) =>
+                {
+                    queues.accept.push_back((socket, remote));
+                }
+                _ => drop(socket),
+            }
+        }
+
+        self.refill_syn_queue(&mut queues.syn)
+    }
+
+    /// Binds the socket to `addr` and `port`, or an ephemeral port if `port`
+    /// is zero.
+    fn do_bind(&self, addr: IpAddress, port: u16) -> Result<IpEndpoint, KernelError> {
+        let mut local_endpoint = self.local_endpoint.lock_save_irq();
+        if local_endpoint.is_some() {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let binding = TCP_PORTS.bind(
+            bound_addr(self.domain, self.v6only.load(Ordering::Relaxed), addr),
+            port,
+            Reuse {
+                addr: self.reuse_addr.load(Ordering::Relaxed),
+                port: self.reuse_port.load(Ordering::Relaxed),
+            },
+        )?;
+        let endpoint = IpEndpoint {
+            addr,
+            port: binding.port(),
+        };
+
+        *self.port.lock_save_irq() = Some(binding);
+        *local_endpoint = Some(endpoint);
+
+        Ok(endpoint)
+    }
+
+    /// Returns the endpoint the socket's bound to, binding it to an ephemeral
+    /// port on any address if it hasn't been bound yet.
+    fn autobind(&self) -> Result<IpEndpoint, KernelError> {
+        let bound = *self.local_endpoint.lock_save_irq();
+
+        match bound {
+            Some(endpoint) => Ok(endpoint),
+            None => self.do_bind(unspecified_addr(self.domain), 0),
+        }
+    }
+
+    /// Returns the endpoint to connect from: the one the socket's bound to, or
+    /// an ephemeral port if it hasn't been given one.
+    fn connect_endpoint(&self) -> Result<IpListenEndpoint, KernelError> {
+        let endpoint = self.autobind()?;
+
+        // Unless the socket was bound to an address, leave the stack to pick
+        // one which can reach the peer.
+        Ok(IpListenEndpoint {
+            addr: (!endpoint.addr.is_unspecified()).then_some(endpoint.addr),
+            port: endpoint.port,
+        })
+    }
+
+    /// Returns a future which completes once `ready` holds for the socket.
+    fn poll_ready(
+        &self,
+        ready: fn(&smoltcp::socket::tcp::Socket) -> bool,
+    ) -> Pin<Box<dyn Future<Output = libkernel::error::Result<()>> + 'static + Send>> {
+        let handle = self.handle;
+
+        Box::pin(wait_for_sockets(move |sockets| {
+            ready(sockets.get::<smoltcp::socket::tcp::Socket>(handle)).then_some(Ok(()))
+        }))
+    }
+
+    /// Returns a future which completes once the listening socket has a
+    /// connection to accept, either in its accept queue or still in its SYN
+    /// queue.
+    fn poll_accept_ready(
+        &self,
+    ) -> Pin<Box<dyn Future<Output = libkernel::error::Result<()>> + 'static + Send>> {
+        let queues = self.queues.lock_save_irq();
+        if !queues.accept.is_empty() {
+            return Box::pin(future::ready(Ok(())));
+        }
+
+        let handles: Vec<SocketHandle> = queues.syn.iter().map(|socket| socket.handle).collect();
+        Box::pin(wait_for_sockets(move |sockets| {
+            handles
+                .iter()
+                .any(|handle| handshake_done(sockets.get::<smoltcp::socket::tcp::Socket>(*handle)))
+                .then_some(Ok(()))
+        }))
+    }
+
+    /// Waits for the handshake started by `connect` to finish.
+    async fn wait_for_connection(&self) -> Result<(), KernelError> {
+        let handle = self.handle;
+
+        wait_for_sockets(move |sockets| {
+            match sockets.get::<smoltcp::socket::tcp::Socket>(handle).state() {
+                State::SynSent | State::SynReceived => None,
+                // The peer answered with a reset.
+                State::Closed => Some(Err(KernelError::ConnectionRefused)),
+                _ => Some(Ok(())),
+            }
+        })
+        .await
+    }
+}
+
+/// Throws away whatever `socket` has received.
+fn discard_received(socket: &mut smoltcp::socket::tcp::Socket) {
+    while socket.can_recv() {
+        if socket.recv(|data| (data.len(), ())).is_err() {
+            break;
+        }
+    }
+}
+
+/// Takes the sockets which have finished closing since they were dropped out of
+/// `sockets`.
+pub fn reap_closed_sockets(sockets: &mut SocketSet<'static>) {
+    CLOSING.lock_save_irq().retain(|(handle, _)| {
+        let closed = sockets.get::<smoltcp::socket::tcp::Socket>(*handle).state() == State::Closed;
+        if closed {
+            sockets.remove(*handle);
+        }
+
+        !closed
+    });
+}
+
+/// Returns a row for each listening socket and each connection, for
+/// /proc/net/tcp. Listeners are found by the ports they hold, as the sockets
+/// the stack listens with are their SYN queues'.
+pub fn socket_rows() -> Vec<SocketRow> {
+    let mut rows: Vec<SocketRow> = TCP_PORTS
+        .holders()
+        .into_iter()
+        .filter(|held| held.listening)
+        .map(|held| SocketRow {
+            local_addr: held.addr,
+            local_port: held.port,
+            remote: None,
+            state: TCP_LISTEN,
+            tx_queue: 0,
+            rx_queue: 0,
+        })
+        .collect();
+
+    let sockets = sockets().lock_save_irq();
+    for (_, socket) in sockets.iter() {
+        let smoltcp::socket::Socket::Tcp(socket) = socket else {
+            continue;
+        };
+        // Only connections have both ends.
+        let (Some(local), Some(remote)) = (socket.local_endpoint(), socket.remote_endpoint())
+        else {
+            continue;
+        };
+
+        rows.push(SocketRow {
+            local_addr: Some(local.addr),
+            local_port: local.port,
+            remote: Some(remote),
+            state: tcp_state(socket.state()),
+            tx_queue: socket.send_queue(),
+            rx_queue: socket.recv_queue(),
+        });
+    }
+
+    rows
+}
+
+impl Drop for TcpSocket {
+    fn drop(&mut self) {
+        let mut sockets = sockets().lock_save_irq();
+        let socket = sockets.get_mut::<smoltcp::socket::tcp::Socket>(self.handle);
+
+        match socket.state() {
+            State::Closed | State::Listen | State::SynSent => {
+                sockets.remove(self.handle);
+            }
+            // Let the connection close gracefully, as the peer expects.
+            // The port stays taken until then, as in `TIME_WAIT`.
+            _ => {
+                socket.close();
+
+                let port = self.port.lock_save_irq().take();
+                if let Some(port) = &port {
+                    port.closing();
+                }
+                CLOSING.lock_save_irq().push((self.handle, port));
+            }
+        }
+    }
 }
 
 #[async_trait]
 impl SocketOps for TcpSocket {
     async fn bind(&self, addr: SockAddr) -> libkernel::error::Result<()> {
-        *self.local_endpoint.lock_save_irq() = Some(addr.try_into()?);
-        Ok(())
+        let local = InetEndpoint::from_sockaddr(addr, self.domain)?;
+        if local.is_v4_mapped(self.domain) && self.v6only.load(Ordering::Relaxed) {
+            return Err(KernelError::InvalidValue);
+        }
+
+        self.do_bind(local.endpoint.addr, local.endpoint.port)
+            .map(|_| ())
+    }
+
+    async fn connect(&self, ctx: &FileCtx, addr: SockAddr) -> libkernel::error::Result<()> {
+        let remote = InetEndpoint::from_sockaddr(addr, self.domain)?;
+        if remote.is_v4_mapped(self.domain) && self.v6only.load(Ordering::Relaxed) {
+            return Err(KernelError::NetworkUnreachable);
+        }
+        let local = self.connect_endpoint()?;
+
+        {
+            let mut interfaces = interfaces().lock_save_irq();
+            let iface = interfaces.route_scoped(remote.endpoint.addr, remote.scope_id)?;
+            let mut sockets = sockets().lock_save_irq();
+            let socket = sockets.get_mut::<smoltcp::socket::tcp::Socket>(self.handle);
+
+            match socket.state() {
+                State::Closed => {}
+                State::SynSent | State::SynReceived => {
+                    return Err(KernelError::AlreadyInProgress);
+                }
+                _ => return Err(KernelError::AlreadyConnected),
+            }
+
+            socket
+                .connect(iface.context(), remote.endpoint, local)
+                .map_err(|e| match e {
+                    ConnectError::InvalidState => KernelError::AlreadyConnected,
+                    _ => KernelError::NetworkUnreachable,
+                })?;
+        }
+
+        // Send the SYN.
+        process_packets();
+
+        if ctx.flags.contains(OpenFlags::O_NONBLOCK) {
+            self.connecting.store(true, Ordering::SeqCst);
+            return Err(KernelError::InProgress);
+        }
+
+        match block(self.wait_for_connection(), self.timeouts.send()).await {
+            // As on Linux, a connect which times out carries on in the
+            // background, as if it were nonblocking.
+            Err(KernelError::TryAgain) => {
+                self.connecting.store(true, Ordering::SeqCst);
+                Err(KernelError::InProgress)
+            }
+            result => result?,
+        }
     }
 
     async fn listen(&self, backlog: i32) -> Result<(), KernelError> {
-        let mut backlogs = self.backlogs.lock_save_irq();
+        let mut queues = self.queues.lock_save_irq();
+
+        // As on Linux, listening without being bound binds to an ephemeral port.
+        self.autobind()?;
+        if let Some(port) = &*self.port.lock_save_irq() {
+            port.listen()?;
+        }
 
-        let new_num_backlogs = (backlog as usize).min(BACKLOG_MAX);
-        backlogs.truncate(new_num_backlogs);
-        self.num_backlogs.store(new_num_backlogs, Ordering::SeqCst);
+        // As on Linux, a backlog of zero (or less) still takes one connection.
+        // Connections already waiting to be accepted are kept if it shrinks.
+        let backlog = (backlog.max(1) as usize).min(SOMAXCONN.load(Ordering::Relaxed));
+        queues.syn.truncate(backlog);
+        self.backlog.store(backlog, Ordering::SeqCst);
 
-        self.refill_backlog_sockets(&mut backlogs)
+        self.refill_syn_queue(&mut queues.syn)
+    }
+
+    async fn accept(&self, ctx: &FileCtx) -> Result<(Box<dyn SocketOps>, SockAddr), KernelError> {
+        if self.backlog.load(Ordering::SeqCst) == 0 {
+            return Err(KernelError::InvalidValue);
+        }
+
+        loop {
+            let accepted = {
+                let mut queues = self.queues.lock_save_irq();
+                self.queue_connections(&mut queues)?;
+                queues.accept.pop_front()
+            };
+
+            if let Some((socket, remote)) = accepted {
+                return Ok((
+                    Box::new(socket),
+                    InetEndpoint::to_sockaddr(remote, self.domain),
+                ));
+            }
+
+            if ctx.flags.contains(OpenFlags::O_NONBLOCK) {
+                return Err(KernelError::TryAgain);
+            }
+            block(self.poll_accept_ready(), self.timeouts.recv()).await??;
+        }
     }
 
     async fn recv(
         &mut self,
-        _ctx: &mut FileCtx,
-        _buf: UA,
-        _count: usize,
-        _flags: RecvFlags,
+        ctx: &mut FileCtx,
+        buf: UA,
+        count: usize,
+        flags: RecvFlags,
     ) -> libkernel::error::Result<(usize, Option<SockAddr>)> {
-        todo!()
+        let nonblock =
+            flags.contains(RecvFlags::MSG_DONTWAIT) || ctx.flags.contains(OpenFlags::O_NONBLOCK);
+        let peek = flags.contains(RecvFlags::MSG_PEEK);
+        // With `MSG_WAITALL`, keep reading until the buffer's full, unless the
+        // connection ends or something goes wrong first. Peeking can't look
+        // past the first read, so it ignores it.
+        let waitall = flags.contains(RecvFlags::MSG_WAITALL) && !peek;
+        let handle = self.handle;
+        let mut total = 0;
+
+        while total < count {
+            let mut data = vec![0u8; (count - total).min(self.buffers.lock_save_irq().rx())];
+
+            let mut try_read = |sockets: &mut SocketSet<'static>| {
+                let socket = sockets.get_mut::<smoltcp::socket::tcp::Socket>(handle);
+
+                if self.rd_shutdown.load(Ordering::SeqCst) {
+                    discard_received(socket);
+                    Some(Ok(0))
+                } else if socket.can_recv() {
+                    let read = if peek {
+                        socket.peek_slice(&mut data)
+                    } else {
+                        socket.recv_slice(&mut data)
+                    };
+
+                    Some(read.map_err(|_| KernelError::NotConnected))
+                } else if socket.may_recv() || matches!(socket.state(), State::SynSent) {
+                    None
+                } else if matches!(socket.state(), State::Listen) {
+                    Some(Err(KernelError::NotConnected))
+                } else {
+                    // The peer's closed its end, so there's nothing more to come.
+                    Some(Ok(0))
+                }
+            };
+
+            let read = try_read(&mut sockets().lock_save_irq());
+            let read = match read {
+                Some(read) => read,
+                None if nonblock => Err(KernelError::TryAgain),
+                None => block(wait_for_sockets(try_read), self.timeouts.recv())
+                    .await
+                    .and_then(|read| read),
+            };
+
+            // As on Linux, what's been read so far is returned, rather than
+            // an error which cuts the read short.
+            let read = match read {
+                Ok(read) => read,
+                Err(_) if total > 0 => break,
+                Err(e) => return Err(e),
+            };
+            if read == 0 {
+                break;
+            }
+
+            // Let the peer know there's room in the window again.
+            if !peek {
+                process_packets();
+            }
+
+            copy_to_user_slice(&data[..read], buf.add_bytes(total)).await?;
+            total += read;
+
+            if !waitall {
+                break;
+            }
+        }
+
+        Ok((total, None))
     }
 
     async fn recvfrom(
         &mut self,
-        _ctx: &mut FileCtx,
-        _buf: UA,
-        _count: usize,
-        _flags: RecvFlags,
+        ctx: &mut FileCtx,
+        buf: UA,
+        count: usize,
+        flags: RecvFlags,
         _addr: Option<SockAddr>,
     ) -> libkernel::error::Result<(usize, Option<SockAddr>)> {
-        todo!()
+        self.recv(ctx, buf, count, flags).await
     }
 
     async fn send(
         &mut self,
-        _ctx: &mut FileCtx,
-        _buf: UA,
-        _count: usize,
-        _flags: SendFlags,
+        ctx: &mut FileCtx,
+        buf: UA,
+        count: usize,
+        flags: SendFlags,
     ) -> libkernel::error::Result<usize> {
-        todo!()
+        let nonblock =
+            flags.contains(SendFlags::MSG_DONT_WAIT) || ctx.flags.contains(OpenFlags::O_NONBLOCK);
+        let handle = self.handle;
+
+        let mut data = vec![0u8; count.min(self.buffers.lock_save_irq().tx())];
+        copy_from_user_slice(buf, &mut data).await?;
+
+        let try_write = |sockets: &mut SocketSet<'static>| {
+            let socket = sockets.get_mut::<smoltcp::socket::tcp::Socket>(handle);
+
+            if socket.can_send() {
+                Some(
+                    socket
+                        .send_slice(&data)
+                        .map_err(|_| KernelError::NotConnected),
+                )
+            } else if socket.may_send()
+                || matches!(socket.state(), State::SynSent | State::SynReceived)
+            {
+                None
+            } else if matches!(socket.state(), State::Closed | State::Listen) {
+                Some(Err(KernelError::NotConnected))
+            } else {
+                // Our end's been closed.
+                Some(Err(KernelError::BrokenPipe))
+            }
+        };
+
+        let written = try_write(&mut sockets().lock_save_irq());
+        let written = match written {
+            Some(written) => written,
+            None if nonblock => return Err(KernelError::TryAgain),
+            None => block(wait_for_sockets(try_write), self.timeouts.send()).await?,
+        }?;
+
+        process_packets();
+
+        Ok(written)
     }
 
     async fn sendto(
         &mut self,
-        _ctx: &mut FileCtx,
-        _buf: UA,
-        _count: usize,
-        _flags: SendFlags,
+        ctx: &mut FileCtx,
+        buf: UA,
+        count: usize,
+        flags: SendFlags,
         _addr: SockAddr,
     ) -> libkernel::error::Result<usize> {
-        todo!()
+        // As on Linux, the address is ignored on a connected socket.
+        self.send(ctx, buf, count, flags).await
     }
 
-    async fn shutdown(&self, _how: ShutdownHow) -> libkernel::error::Result<()> {
-        sockets()
-            .lock_save_irq()
-            .get_mut::<smoltcp::socket::tcp::Socket>(self.handle)
-            .close();
+    async fn shutdown(&self, how: ShutdownHow) -> libkernel::error::Result<()> {
+        let read = matches!(how, ShutdownHow::Read | ShutdownHow::ReadWrite);
+        let write = matches!(how, ShutdownHow::Write | ShutdownHow::ReadWrite);
+
+        if self.backlog.load(Ordering::SeqCst) != 0 {
+            // As on Linux, shutting a listening socket down for reading stops
+            // it listening, and resets the connections waiting to be accepted.
+            // The queues are dropped outside the lock, as dropping sockets
+            // takes the sockets lock.
+            if read {
+                let queues = core::mem::take(&mut *self.queues.lock_save_irq());
+                self.backlog.store(0, Ordering::SeqCst);
+                drop(queues);
+
+                if let Some(port) = &*self.port.lock_save_irq() {
+                    port.stop_listening();
+                }
+            }
+
+            return Ok(());
+        }
+
+        {
+            let mut sockets = sockets().lock_save_irq();
+            let socket = sockets.get_mut::<smoltcp::socket::tcp::Socket>(self.handle);
+
+            if matches!(socket.state(), State::Closed | State::SynSent) {
+                return Err(KernelError::NotConnected);
+            }
 
+            if read {
+                self.rd_shutdown.store(true, Ordering::SeqCst);
+                discard_received(socket);
+            }
+
+            // Send a FIN, leaving the other direction open until the peer
+            // closes its end.
+            if write {
+                socket.close();
+            }
+        }
+
+        // Send the FIN, or a window update for the discarded data, and wake
+        // anyone blocked on a read which will now find nothing.
         process_packets();
         Ok(())
     }
 
+    fn getsockopt(&self, opt: SockOpt) -> libkernel::error::Result<i32> {
+        let mut sockets = sockets().lock_save_irq();
+        let socket = sockets.get_mut::<smoltcp::socket::tcp::Socket>(self.handle);
+
+        match opt {
+            SockOpt::ReuseAddr => Ok(self.reuse_addr.load(Ordering::Relaxed) as i32),
+            SockOpt::ReusePort => Ok(self.reuse_port.load(Ordering::Relaxed) as i32),
+            SockOpt::Type => Ok(SOCK_STREAM),
+            SockOpt::Error => {
+                // Report a nonblocking connect which was refused, once.
+                let refused = socket.state() == State::Closed
+                    && self.connecting.swap(false, Ordering::SeqCst);
+
+                Ok(if refused { -ECONNREFUSED as i32 } else { 0 })
+            }
+            SockOpt::SndBuf | SockOpt::RcvBuf => Ok(self.buffers.lock_save_irq().get(opt)),
+            SockOpt::KeepAlive => Ok(socket.keep_alive().is_some() as i32),
+            SockOpt::AcceptConn => Ok((self.backlog.load(Ordering::SeqCst) != 0) as i32),
+            SockOpt::Protocol => Ok(IPPROTO_TCP),
+            SockOpt::Domain => Ok(self.domain),
+            SockOpt::TcpNoDelay => Ok(!socket.nagle_enabled() as i32),
+            SockOpt::TcpKeepIdle => Ok(self.keep_idle.load(Ordering::Relaxed) as i32),
+            SockOpt::TcpKeepIntvl => Ok(self.keep_intvl.load(Ordering::Relaxed) as i32),
+            SockOpt::TcpKeepCnt => Ok(self.keep_cnt.load(Ordering::Relaxed) as i32),
+            SockOpt::TcpQuickAck => Ok(socket.ack_delay().is_none() as i32),
+            SockOpt::TcpUserTimeout => Ok(socket.timeout().map_or(0, |timeout| {
+                timeout.total_millis().min(i32::MAX as u64) as i32
+            })),
+            SockOpt::V6Only if self.domain == AF_INET6 => {
+                Ok(self.v6only.load(Ordering::Relaxed) as i32)
+            }
+            // The timeouts are handled through `timeouts`.
+            SockOpt::V6Only | SockOpt::RcvTimeo | SockOpt::SndTimeo => {
+                Err(KernelError::NoProtocolOption)
+            }
+        }
+    }
+
+    fn setsockopt(&self, opt: SockOpt, value: i32) -> libkernel::error::Result<()> {
+        let mut sockets = sockets().lock_save_irq();
+        let socket = sockets.get_mut::<smoltcp::socket::tcp::Socket>(self.handle);
+
+        match opt {
+            SockOpt::ReuseAddr => self.reuse_addr.store(value != 0, Ordering::Relaxed),
+            SockOpt::ReusePort => self.reuse_port.store(value != 0, Ordering::Relaxed),
+            SockOpt::SndBuf | SockOpt::RcvBuf => {
+                // Buffers can't be swapped out from under a connection, so
+                // once the socket's in use, asking for a size is only a hint,
+                // which is ignored.
+                if socket.state() == State::Closed {
+                    let mut buffers = self.buffers.lock_save_irq();
+                    buffers.set(opt, value)?;
+
+                    let options = StackOptions::of(socket);
+                    *socket = new_inner(&buffers);
+                    options.apply(socket);
+                }
+            }
+            SockOpt::KeepAlive => {
+                let idle = Duration::from_secs(self.keep_idle.load(Ordering::Relaxed) as u64);
+                socket.set_keep_alive((value != 0).then_some(idle));
+            }
+            SockOpt::TcpNoDelay => socket.set_nagle_enabled(value == 0),
+            SockOpt::TcpKeepIdle => {
+                if !(1..=MAX_KEEPALIVE_TIME).contains(&value) {
+                    return Err(KernelError::InvalidValue);
+                }
+                self.keep_idle.store(value as u32, Ordering::Relaxed);
+
+                // Keepalives which are already on take the new time.
+                if socket.keep_alive().is_some() {
+                    socket.set_keep_alive(Some(Duration::from_secs(value as u64)));
+                }
+            }
+            SockOpt::TcpKeepIntvl => {
+                if !(1..=MAX_KEEPALIVE_TIME).contains(&value) {
+                    return Err(KernelError::InvalidValue);
+                }
+                self.keep_intvl.store(value as u32, Ordering::Relaxed);
+            }
+            SockOpt::TcpKeepCnt => {
+                if !(1..=MAX_KEEPALIVE_PROBES).contains(&value) {
+                    return Err(KernelError::InvalidValue);
+                }
+                self.keep_cnt.store(value as u32, Ordering::Relaxed);
+            }
+            SockOpt::TcpQuickAck => {
+                socket.set_ack_delay((value == 0).then_some(ACK_DELAY));
+            }
+            SockOpt::TcpUserTimeout => {
+                if value < 0 {
+                    return Err(KernelError::InvalidValue);
+                }
+                // Unlike Linux's, the stack's timeout runs while the
+                // connection's idle too, not only while data is unacknowledged.
+                socket.set_timeout((value != 0).then(|| Duration::from_millis(value as u64)));
+            }
+            SockOpt::V6Only if self.domain == AF_INET6 => {
+                // As on Linux, this can't change once the socket's bound.
+                if self.local_endpoint.lock_save_irq().is_some() {
+                    return Err(KernelError::InvalidValue);
+                }
+                self.v6only.store(value != 0, Ordering::Relaxed);
+            }
+            _ => return Err(KernelError::NoProtocolOption),
+        }
+
+        Ok(())
+    }
+
+    fn local_addr(&self) -> Result<SockAddr, KernelError> {
+        // Once connected, the stack knows which address the socket's using,
+        // even if it was bound on any.
+        let connected = sockets()
+            .lock_save_irq()
+            .get::<smoltcp::socket::tcp::Socket>(self.handle)
+            .local_endpoint();
+        let endpoint = connected
+            .or(*self.local_endpoint.lock_save_irq())
+            .unwrap_or(IpEndpoint::new(unspecified_addr(self.domain), 0));
+
+        Ok(InetEndpoint::to_sockaddr(endpoint, self.domain))
+    }
+
+    fn peer_addr(&self) -> Result<SockAddr, KernelError> {
+        let remote = sockets()
+            .lock_save_irq()
+            .get::<smoltcp::socket::tcp::Socket>(self.handle)
+            .remote_endpoint()
+            .ok_or(KernelError::NotConnected)?;
+
+        Ok(InetEndpoint::to_sockaddr(remote, self.domain))
+    }
+
+    fn timeouts(&self) -> Option<&SocketTimeouts> {
+        Some(&self.timeouts)
+    }
+
+    fn poll_read_ready(
+        &self,
+    ) -> Pin<Box<dyn Future<Output = libkernel::error::Result<()>> + 'static + Send>> {
+        // A read on a socket shut down for reading returns straight away.
+        if self.rd_shutdown.load(Ordering::SeqCst) {
+            return Box::pin(future::ready(Ok(())));
+        }
+
+        // A listening socket is readable once it has a connection to accept.
+        if self.backlog.load(Ordering::SeqCst) != 0 {
+            return self.poll_accept_ready();
+        }
+
+        // Besides when there's data, a read won't block once the peer's closed
+        // its end.
+        self.poll_ready(|socket| {
+            socket.can_recv()
+                || !(socket.may_recv()
+                    || matches!(
+                        socket.state(),
+                        State::Listen | State::SynSent | State::SynReceived
+                    ))
+        })
+    }
+
+    fn poll_write_ready(
+        &self,
+    ) -> Pin<Box<dyn Future<Output = libkernel::error::Result<()>> + 'static + Send>> {
+        if self.backlog.load(Ordering::SeqCst) != 0 {
+            // A listening socket never becomes writable.
+            return Box::pin(future::pending());
+        }
+
+        self.poll_ready(|socket| {
+            socket.can_send()
+                || !(socket.may_send()
+                    || matches!(socket.state(), State::SynSent | State::SynReceived))
+        })
+    }
+
+    fn poll_hangup(
+        &self,
+    ) -> Pin<Box<dyn Future<Output = libkernel::error::Result<()>> + 'static + Send>> {
+        if self.backlog.load(Ordering::SeqCst) != 0 {
+            return Box::pin(future::pending());
+        }
+
+        self.poll_ready(|socket| {
+            !(socket.may_recv()
+                || socket.may_send()
+                || matches!(socket.state(), State::SynSent | State::SynReceived))
+        })
+    }
+
     fn as_file(self: Box<Self>) -> Box<dyn FileOps> {
         self
     }
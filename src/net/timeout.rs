@@ -0,0 +1,75 @@
+//! How long a socket's calls may block for, as set by `SO_RCVTIMEO` and
+//! `SO_SNDTIMEO`.
+//!
+//! A receive, or an `accept`, which has waited for the receive timeout gives up
+//! with `EAGAIN`, as does a send after the send timeout, as on Linux. Until a
+//! timeout is set, a call waits for as long as it takes.
+
+use crate::drivers::timer;
+use crate::net::sockopt::SockOpt;
+use crate::process::thread_group::signal::{InterruptResult, Interruptable};
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+use libkernel::error::{KernelError, Result};
+
+/// A socket's receive and send timeouts, in microseconds, with zero standing
+/// for none.
+#[derive(Default)]
+pub struct SocketTimeouts {
+    recv: AtomicU64,
+    send: AtomicU64,
+}
+
+impl SocketTimeouts {
+    fn slot(&self, opt: SockOpt) -> &AtomicU64 {
+        match opt {
+            SockOpt::RcvTimeo => &self.recv,
+            _ => &self.send,
+        }
+    }
+
+    /// Returns the timeout `SO_RCVTIMEO` or `SO_SNDTIMEO` names, or zero if
+    /// there's none.
+    pub fn get(&self, opt: SockOpt) -> Duration {
+        Duration::from_micros(self.slot(opt).load(Ordering::Relaxed))
+    }
+
+    /// Sets the timeout `SO_RCVTIMEO` or `SO_SNDTIMEO` names, or takes it away
+    /// if `timeout` is zero.
+    pub fn set(&self, opt: SockOpt, timeout: Duration) {
+        // A timeout shorter than a microsecond would otherwise be taken for
+        // none.
+        let micros = match timeout.as_micros() {
+            0 if !timeout.is_zero() => 1,
+            micros => micros.min(u64::MAX as u128) as u64,
+        };
+
+        self.slot(opt).store(micros, Ordering::Relaxed);
+    }
+
+    pub fn recv(&self) -> Option<Duration> {
+        Some(self.get(SockOpt::RcvTimeo)).filter(|timeout| !timeout.is_zero())
+    }
+
+    pub fn send(&self) -> Option<Duration> {
+        Some(self.get(SockOpt::SndTimeo)).filter(|timeout| !timeout.is_zero())
+    }
+}
+
+/// Waits for `fut`, on which a socket call is blocked, giving up with `EINTR`
+/// if a signal arrives, or with `EAGAIN` once `timeout` has passed.
+pub async fn block<T>(fut: impl Future<Output = T>, timeout: Option<Duration>) -> Result<T> {
+    let fut = fut.interruptable();
+
+    let result = match timeout {
+        Some(timeout) => timer::timeout(fut, timeout)
+            .await
+            .map_err(|_| KernelError::TryAgain)?,
+        None => fut.await,
+    };
+
+    match result {
+        InterruptResult::Interrupted => Err(KernelError::Interrupted),
+        InterruptResult::Uninterrupted(value) => Ok(value),
+    }
+}
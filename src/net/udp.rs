@@ -0,0 +1,567 @@
+use crate::fs::fops::FileOps;
+use crate::fs::open_file::{FileCtx, OpenFile};
+use crate::memory::uaccess::iovec::UserIoVec;
+use crate::memory::uaccess::{copy_from_user_slice, copy_to_user_slice};
+use crate::net::buffer::BufferSizes;
+use crate::net::iface::interfaces;
+use crate::net::port::{PortBinding, PortTable, Reuse, bound_addr};
+use crate::net::proc::{SocketRow, TCP_CLOSE};
+use crate::net::sockopt::SockOpt;
+use crate::net::sops::{RecvFlags, RecvMsg, SendFlags, SocketOps};
+use crate::net::timeout::{SocketTimeouts, block};
+use crate::net::{
+    AF_INET6, IPPROTO_UDP, InetEndpoint, SOCK_DGRAM, ShutdownHow, SockAddr, family_reaches,
+    process_packets, sockets, unspecified_addr, wait_for_sockets,
+};
+use crate::sync::SpinLock;
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use async_trait::async_trait;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use libkernel::error::{KernelError, Result};
+use libkernel::fs::OpenFlags;
+use libkernel::memory::address::UA;
+use smoltcp::iface::{SocketHandle, SocketSet};
+use smoltcp::socket::udp::{self, BindError, PacketBuffer, PacketMetadata, SendError};
+use smoltcp::wire::{IpEndpoint, IpListenEndpoint};
+
+/// How many datagrams each of a socket's buffers can hold.
+const BUFFER_PACKETS: usize = 16;
+
+/// The ports UDP sockets are bound to.
+static UDP_PORTS: PortTable = PortTable::new();
+
+pub struct UdpSocket {
+    handle: SocketHandle,
+    /// `AF_INET` or `AF_INET6`.
+    domain: i32,
+    /// `IPV6_V6ONLY`, which keeps an `AF_INET6` socket from reaching IPv4
+    /// peers.
+    v6only: AtomicBool,
+    local_endpoint: SpinLock<Option<IpListenEndpoint>>,
+    /// Where datagrams go by default, once the socket's been connected.
+    remote_endpoint: SpinLock<Option<IpEndpoint>>,
+    /// The port the socket's bound to, which is given back when it's dropped.
+    port: SpinLock<Option<PortBinding>>,
+    reuse_addr: AtomicBool,
+    reuse_port: AtomicBool,
+    buffers: SpinLock<BufferSizes>,
+    timeouts: SocketTimeouts,
+}
+
+/// Makes a socket with buffers of the given sizes.
+fn new_inner(sizes: &BufferSizes) -> udp::Socket<'static> {
+    let rx_buffer = PacketBuffer::new(
+        vec![PacketMetadata::EMPTY; BUFFER_PACKETS],
+        vec![0; sizes.rx()],
+    );
+    let tx_buffer = PacketBuffer::new(
+        vec![PacketMetadata::EMPTY; BUFFER_PACKETS],
+        vec![0; sizes.tx()],
+    );
+    udp::Socket::new(rx_buffer, tx_buffer)
+}
+
+impl UdpSocket {
+    pub fn new(domain: i32) -> Result<Self> {
+        let buffers = BufferSizes::new()?;
+        let handle = sockets().lock_save_irq().add(new_inner(&buffers));
+
+        Ok(Self {
+            handle,
+            domain,
+            v6only: AtomicBool::new(false),
+            local_endpoint: SpinLock::new(None),
+            remote_endpoint: SpinLock::new(None),
+            port: SpinLock::new(None),
+            reuse_addr: AtomicBool::new(false),
+            reuse_port: AtomicBool::new(false),
+            buffers: SpinLock::new(buffers),
+            timeouts: SocketTimeouts::default(),
+        })
+    }
+
+    /// Binds the socket to `endpoint`, giving it an ephemeral port if it
+    /// doesn't name one.
+    fn do_bind(&self, mut endpoint: IpListenEndpoint) -> Result<()> {
+        let mut local_endpoint = self.local_endpoint.lock_save_irq();
+        if local_endpoint.is_some() {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let addr = endpoint.addr.unwrap_or(unspecified_addr(self.domain));
+        let binding = UDP_PORTS.bind(
+            bound_addr(self.domain, self.v6only.load(Ordering::Relaxed), addr),
+            endpoint.port,
+            Reuse {
+                addr: self.reuse_addr.load(Ordering::Relaxed),
+                port: self.reuse_port.load(Ordering::Relaxed),
+            },
+        )?;
+        endpoint.port = binding.port();
+
+        sockets()
+            .lock_save_irq()
+            .get_mut::<udp::Socket>(self.handle)
+            .bind(endpoint)
+            .map_err(|e| match e {
+                BindError::InvalidState => KernelError::InvalidValue,
+                _ => KernelError::NetworkUnreachable,
+            })?;
+
+        *self.port.lock_save_irq() = Some(binding);
+        *local_endpoint = Some(endpoint);
+        Ok(())
+    }
+
+    /// Binds the socket to an ephemeral port, as it needs to be bound before
+    /// it can send, if it hasn't been bound already.
+    fn autobind(&self) -> Result<()> {
+        if self.local_endpoint.lock_save_irq().is_some() {
+            return Ok(());
+        }
+
+        self.do_bind(IpListenEndpoint {
+            addr: None,
+            port: 0,
+        })
+    }
+
+    /// Takes the next datagram, or only looks at it with `MSG_PEEK`, returning
+    /// up to `count` bytes of it along with its full length and sender.
+    async fn recv_datagram(
+        &self,
+        count: usize,
+        flags: RecvFlags,
+        nonblock: bool,
+    ) -> Result<(Vec<u8>, usize, IpEndpoint)> {
+        let handle = self.handle;
+        let peek = flags.contains(RecvFlags::MSG_PEEK);
+        let domain = self.domain;
+        let v6only = self.v6only.load(Ordering::Relaxed);
+
+        // Anything beyond `count` is discarded, as the datagram is consumed
+        // whole, but its full length is kept for `MSG_TRUNC`.
+        let take_datagram = move |sockets: &mut SocketSet<'static>| {
+            let socket = sockets.get_mut::<udp::Socket>(handle);
+
+            // A socket bound on any address hears from peers of either family,
+            // so drop datagrams from those it can't talk to.
+            while socket
+                .peek()
+                .is_ok_and(|(_, meta)| !family_reaches(domain, v6only, meta.endpoint.addr))
+            {
+                let _ = socket.recv();
+            }
+
+            let (data, from) = if peek {
+                socket
+                    .peek()
+                    .ok()
+                    .map(|(data, meta)| (data, meta.endpoint))?
+            } else {
+                socket
+                    .recv()
+                    .ok()
+                    .map(|(data, meta)| (data, meta.endpoint))?
+            };
+
+            Some((data[..data.len().min(count)].to_vec(), data.len(), from))
+        };
+
+        let datagram = take_datagram(&mut sockets().lock_save_irq());
+        match datagram {
+            Some(datagram) => Ok(datagram),
+            None if nonblock => Err(KernelError::TryAgain),
+            None => block(wait_for_sockets(take_datagram), self.timeouts.recv()).await,
+        }
+    }
+
+    async fn send_datagram(&self, data: &[u8], remote: IpEndpoint) -> Result<usize> {
+        self.autobind()?;
+
+        let send = |data: &[u8]| {
+            sockets()
+                .lock_save_irq()
+                .get_mut::<udp::Socket>(self.handle)
+                .send_slice(data, remote)
+        };
+
+        let result = match send(data) {
+            // Sending the datagrams already queued makes room.
+            Err(SendError::BufferFull) => {
+                process_packets();
+                send(data)
+            }
+            result => result,
+        };
+
+        result.map_err(|e| match e {
+            SendError::BufferFull => KernelError::TryAgain,
+            _ => KernelError::InvalidValue,
+        })?;
+
+        process_packets();
+
+        Ok(data.len())
+    }
+
+    /// Copies a datagram of `count` bytes in from `buf`.
+    async fn datagram_from_user(&self, buf: UA, count: usize) -> Result<Vec<u8>> {
+        self.check_datagram_len(count)?;
+
+        let mut data = vec![0; count];
+        copy_from_user_slice(buf, &mut data).await?;
+
+        Ok(data)
+    }
+
+    /// Checks that a datagram of `len` bytes fits in the send buffer.
+    fn check_datagram_len(&self, len: usize) -> Result<()> {
+        if len > self.buffers.lock_save_irq().tx() {
+            return Err(KernelError::MessageTooLong);
+        }
+
+        Ok(())
+    }
+
+    /// Parses `addr` as a peer to send to.
+    fn remote_from(&self, addr: SockAddr) -> Result<IpEndpoint> {
+        let remote = InetEndpoint::from_sockaddr(addr, self.domain)?;
+        if remote.is_v4_mapped(self.domain) && self.v6only.load(Ordering::Relaxed) {
+            return Err(KernelError::NetworkUnreachable);
+        }
+
+        // A link-local peer must be given the interface it's on.
+        interfaces()
+            .lock_save_irq()
+            .route_scoped(remote.endpoint.addr, remote.scope_id)?;
+
+        Ok(remote.endpoint)
+    }
+
+    /// Returns where a datagram sent without an address goes.
+    fn default_remote(&self) -> Result<IpEndpoint> {
+        self.remote_endpoint
+            .lock_save_irq()
+            .ok_or(KernelError::NotConnected)
+    }
+}
+
+fn is_nonblocking(ctx: &FileCtx, flags: RecvFlags) -> bool {
+    flags.contains(RecvFlags::MSG_DONTWAIT) || ctx.flags.contains(OpenFlags::O_NONBLOCK)
+}
+
+/// Returns a row for each bound socket, for /proc/net/udp.
+pub fn socket_rows() -> Vec<SocketRow> {
+    UDP_PORTS
+        .holders()
+        .into_iter()
+        .map(|held| SocketRow {
+            local_addr: held.addr,
+            local_port: held.port,
+            remote: None,
+            state: TCP_CLOSE,
+            tx_queue: 0,
+            rx_queue: 0,
+        })
+        .collect()
+}
+
+impl Drop for UdpSocket {
+    fn drop(&mut self) {
+        sockets().lock_save_irq().remove(self.handle);
+    }
+}
+
+#[async_trait]
+impl SocketOps for UdpSocket {
+    async fn bind(&self, addr: SockAddr) -> Result<()> {
+        let local = InetEndpoint::from_sockaddr(addr, self.domain)?;
+        if local.is_v4_mapped(self.domain) && self.v6only.load(Ordering::Relaxed) {
+            return Err(KernelError::InvalidValue);
+        }
+        let endpoint = local.endpoint;
+
+        self.do_bind(IpListenEndpoint {
+            addr: (!endpoint.addr.is_unspecified()).then_some(endpoint.addr),
+            port: endpoint.port,
+        })
+    }
+
+    async fn connect(&self, _ctx: &FileCtx, addr: SockAddr) -> Result<()> {
+        let remote = self.remote_from(addr)?;
+
+        self.autobind()?;
+        *self.remote_endpoint.lock_save_irq() = Some(remote);
+
+        Ok(())
+    }
+
+    async fn recv(
+        &mut self,
+        ctx: &mut FileCtx,
+        buf: UA,
+        count: usize,
+        flags: RecvFlags,
+    ) -> Result<(usize, Option<SockAddr>)> {
+        let (data, len, from) = self
+            .recv_datagram(count, flags, is_nonblocking(ctx, flags))
+            .await?;
+
+        copy_to_user_slice(&data, buf).await?;
+
+        let len = if flags.contains(RecvFlags::MSG_TRUNC) {
+            len
+        } else {
+            data.len()
+        };
+
+        Ok((len, Some(InetEndpoint::to_sockaddr(from, self.domain))))
+    }
+
+    async fn recvfrom(
+        &mut self,
+        ctx: &mut FileCtx,
+        buf: UA,
+        count: usize,
+        flags: RecvFlags,
+        _addr: Option<SockAddr>,
+    ) -> Result<(usize, Option<SockAddr>)> {
+        self.recv(ctx, buf, count, flags).await
+    }
+
+    async fn send(
+        &mut self,
+        _ctx: &mut FileCtx,
+        buf: UA,
+        count: usize,
+        _flags: SendFlags,
+    ) -> Result<usize> {
+        let remote = self.default_remote()?;
+        let data = self.datagram_from_user(buf, count).await?;
+
+        self.send_datagram(&data, remote).await
+    }
+
+    async fn sendto(
+        &mut self,
+        _ctx: &mut FileCtx,
+        buf: UA,
+        count: usize,
+        _flags: SendFlags,
+        addr: SockAddr,
+    ) -> Result<usize> {
+        let remote = self.remote_from(addr)?;
+        let data = self.datagram_from_user(buf, count).await?;
+
+        self.send_datagram(&data, remote).await
+    }
+
+    async fn sendmsg(
+        &mut self,
+        _ctx: &mut FileCtx,
+        iov: &UserIoVec,
+        _flags: SendFlags,
+        addr: Option<SockAddr>,
+        rights: Vec<Arc<OpenFile>>,
+    ) -> Result<usize> {
+        if !rights.is_empty() {
+            return Err(KernelError::InvalidValue);
+        }
+
+        let remote: IpEndpoint = match addr {
+            Some(addr) => self.remote_from(addr)?,
+            None => self.default_remote()?,
+        };
+
+        // The vectors are gathered into a single datagram.
+        let count: usize = iov.iovs().iter().map(|vec| vec.iov_len).sum();
+        self.check_datagram_len(count)?;
+
+        let mut data = vec![0; count];
+        let read = iov.reader().read(&mut data).await?;
+
+        self.send_datagram(&data[..read], remote).await
+    }
+
+    async fn recvmsg(
+        &mut self,
+        ctx: &mut FileCtx,
+        iov: &UserIoVec,
+        flags: RecvFlags,
+    ) -> Result<RecvMsg> {
+        let count: usize = iov.iovs().iter().map(|vec| vec.iov_len).sum();
+        let (data, len, from) = self
+            .recv_datagram(count, flags, is_nonblocking(ctx, flags))
+            .await?;
+
+        iov.writer().write(&data).await?;
+
+        Ok(RecvMsg {
+            len: if flags.contains(RecvFlags::MSG_TRUNC) {
+                len
+            } else {
+                data.len()
+            },
+            addr: Some(InetEndpoint::to_sockaddr(from, self.domain)),
+            rights: Vec::new(),
+            truncated: len > data.len(),
+        })
+    }
+
+    async fn shutdown(&self, _how: ShutdownHow) -> Result<()> {
+        sockets()
+            .lock_save_irq()
+            .get_mut::<udp::Socket>(self.handle)
+            .close();
+
+        Ok(())
+    }
+
+    fn getsockopt(&self, opt: SockOpt) -> Result<i32> {
+        match opt {
+            SockOpt::ReuseAddr => Ok(self.reuse_addr.load(Ordering::Relaxed) as i32),
+            SockOpt::ReusePort => Ok(self.reuse_port.load(Ordering::Relaxed) as i32),
+            SockOpt::Type => Ok(SOCK_DGRAM),
+            SockOpt::Error => Ok(0),
+            SockOpt::SndBuf | SockOpt::RcvBuf => Ok(self.buffers.lock_save_irq().get(opt)),
+            SockOpt::Protocol => Ok(IPPROTO_UDP),
+            SockOpt::Domain => Ok(self.domain),
+            SockOpt::V6Only if self.domain == AF_INET6 => {
+                Ok(self.v6only.load(Ordering::Relaxed) as i32)
+            }
+            _ => Err(KernelError::NoProtocolOption),
+        }
+    }
+
+    fn setsockopt(&self, opt: SockOpt, value: i32) -> Result<()> {
+        match opt {
+            SockOpt::ReuseAddr => self.reuse_addr.store(value != 0, Ordering::Relaxed),
+            SockOpt::ReusePort => self.reuse_port.store(value != 0, Ordering::Relaxed),
+            SockOpt::SndBuf | SockOpt::RcvBuf => {
+                // Buffers can't be swapped out from under a bound socket, which
+                // may have datagrams queued, so from then on asking for a size
+                // is only a hint, which is ignored.
+                let local_endpoint = self.local_endpoint.lock_save_irq();
+                if local_endpoint.is_none() {
+                    let mut buffers = self.buffers.lock_save_irq();
+                    buffers.set(opt, value)?;
+
+                    *sockets()
+                        .lock_save_irq()
+                        .get_mut::<udp::Socket>(self.handle) = new_inner(&buffers);
+                }
+            }
+            SockOpt::V6Only if self.domain == AF_INET6 => {
+                // As on Linux, this can't change once the socket's bound.
+                if self.local_endpoint.lock_save_irq().is_some() {
+                    return Err(KernelError::InvalidValue);
+                }
+                self.v6only.store(value != 0, Ordering::Relaxed);
+            }
+            _ => return Err(KernelError::NoProtocolOption),
+        }
+
+        Ok(())
+    }
+
+    fn local_addr(&self) -> Result<SockAddr> {
+        let endpoint = match *self.local_endpoint.lock_save_irq() {
+            Some(endpoint) => IpEndpoint::new(
+                endpoint.addr.unwrap_or(unspecified_addr(self.domain)),
+                endpoint.port,
+            ),
+            None => IpEndpoint::new(unspecified_addr(self.domain), 0),
+        };
+
+        Ok(InetEndpoint::to_sockaddr(endpoint, self.domain))
+    }
+
+    fn peer_addr(&self) -> Result<SockAddr> {
+        Ok(InetEndpoint::to_sockaddr(
+            self.default_remote()?,
+            self.domain,
+        ))
+    }
+
+    fn timeouts(&self) -> Option<&SocketTimeouts> {
+        Some(&self.timeouts)
+    }
+
+    fn poll_read_ready(&self) -> Pin<Box<dyn Future<Output = Result<()>> + 'static + Send>> {
+        let handle = self.handle;
+
+        Box::pin(wait_for_sockets(move |sockets| {
+            sockets
+                .get::<udp::Socket>(handle)
+                .can_recv()
+                .then_some(Ok(()))
+        }))
+    }
+
+    fn poll_write_ready(&self) -> Pin<Box<dyn Future<Output = Result<()>> + 'static + Send>> {
+        let handle = self.handle;
+
+        Box::pin(wait_for_sockets(move |sockets| {
+            sockets
+                .get::<udp::Socket>(handle)
+                .can_send()
+                .then_some(Ok(()))
+        }))
+    }
+
+    fn as_file(self: Box<Self>) -> Box<dyn FileOps> {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UdpSocket;
+    use crate::net::sockopt::SockOpt;
+    use crate::net::sops::SocketOps;
+    use crate::net::{AF_INET, SOCK_MIN_BUF, sockets};
+    use libkernel::error::KernelError;
+    use moss_macros::ktest;
+    use smoltcp::socket::udp;
+
+    #[ktest]
+    fn datagrams_larger_than_sndbuf_fail() {
+        let socket = UdpSocket::new(AF_INET).unwrap();
+        socket.setsockopt(SockOpt::SndBuf, 0).unwrap();
+
+        let size = socket.getsockopt(SockOpt::SndBuf).unwrap() as usize;
+        assert_eq!(size, SOCK_MIN_BUF);
+        assert_eq!(
+            sockets()
+                .lock_save_irq()
+                .get::<udp::Socket>(socket.handle)
+                .payload_send_capacity(),
+            size
+        );
+
+        assert!(socket.check_datagram_len(size).is_ok());
+        assert!(matches!(
+            socket.check_datagram_len(size + 1),
+            Err(KernelError::MessageTooLong)
+        ));
+    }
+
+    #[ktest]
+    fn rcvbuf_sizes_the_receive_queue() {
+        let socket = UdpSocket::new(AF_INET).unwrap();
+        socket.setsockopt(SockOpt::RcvBuf, 4096).unwrap();
+
+        assert_eq!(
+            sockets()
+                .lock_save_irq()
+                .get::<udp::Socket>(socket.handle)
+                .payload_recv_capacity(),
+            4096
+        );
+    }
+}
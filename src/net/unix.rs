@@ -1,10 +1,15 @@
-use crate::fs::open_file::FileCtx;
+use crate::fs::open_file::{FileCtx, OpenFile};
 use crate::kernel::kpipe::KPipe;
+use crate::memory::uaccess::iovec::UserIoVec;
 use crate::memory::uaccess::{copy_from_user_slice, copy_to_user_slice};
-use crate::net::sops::{RecvFlags, SendFlags};
-use crate::net::{SockAddr, SockAddrUn, SocketOps};
+use crate::net::sockopt::SockOpt;
+use crate::net::sops::{RecvFlags, RecvMsg, SendFlags};
+use crate::net::timeout::{SocketTimeouts, block};
+use crate::net::{
+    AF_UNIX, SOCK_DGRAM, SOCK_SEQPACKET, SOCK_STREAM, SockAddr, SockAddrUn, SocketOps,
+};
 use crate::sync::SpinLock;
-use crate::sync::{Mutex, OnceLock};
+use crate::sync::{AsyncMutexGuard, Mutex, MutexTimeout, OnceLock};
 use alloc::boxed::Box;
 use alloc::collections::{BTreeMap, VecDeque};
 use alloc::sync::Arc;
@@ -12,63 +17,213 @@ use alloc::vec;
 use alloc::vec::Vec;
 use async_trait::async_trait;
 use core::future::poll_fn;
+use core::pin::Pin;
 use core::task::Poll;
 use core::task::Waker;
+use core::time::Duration;
 use libkernel::error::{FsError, KernelError, Result};
+use libkernel::fs::OpenFlags;
 use libkernel::memory::address::UA;
 
+/// Files passed over a socket with `SCM_RIGHTS`.
+type Rights = Vec<Arc<OpenFile>>;
+
+/// The address of a socket which isn't bound to a path.
+const UNNAMED: SockAddrUn = SockAddrUn {
+    family: AF_UNIX as u16,
+    path: [0; 108],
+};
+
 struct Message {
     sender: SockAddrUn,
     data: Vec<u8>,
+    rights: Rights,
 }
 
 #[derive(Clone)]
 enum Inbox {
-    Pipe(Arc<KPipe>),
+    Pipe {
+        pipe: Arc<KPipe>,
+        /// Files sent alongside the stream, each batch to be handed to the
+        /// next receive that gets some data.
+        rights: Arc<SpinLock<VecDeque<Rights>>>,
+    },
     Datagram(Arc<Mutex<VecDeque<Message>>>),
 }
 
+/// Locks a datagram queue, giving up with `EAGAIN` once `timeout` has passed,
+/// as the rest of a socket call would.
+async fn lock_queue(
+    queue: &Mutex<VecDeque<Message>>,
+    timeout: Option<Duration>,
+) -> Result<AsyncMutexGuard<'_, VecDeque<Message>>> {
+    match timeout {
+        Some(timeout) => queue
+            .lock_timeout(timeout)
+            .await
+            .map_err(|_| KernelError::TryAgain),
+        None => Ok(queue.lock().await),
+    }
+}
+
 impl Inbox {
     fn new(socket_type: SocketType) -> Self {
         match socket_type {
-            SocketType::Stream | SocketType::SeqPacket => {
-                Inbox::Pipe(Arc::new(KPipe::new().expect("KPipe creation failed")))
-            }
+            SocketType::Stream | SocketType::SeqPacket => Inbox::Pipe {
+                pipe: Arc::new(KPipe::new().expect("KPipe creation failed")),
+                rights: Arc::new(SpinLock::new(VecDeque::new())),
+            },
             SocketType::Datagram => Inbox::Datagram(Arc::new(Mutex::new(VecDeque::new()))),
         }
     }
 
-    async fn send(&self, origin: SockAddrUn, buf: UA, count: usize) -> Result<usize> {
+    /// Returns how much of a `count` byte send can go in one go.
+    fn send_len(&self, count: usize) -> usize {
         match self {
-            Inbox::Pipe(pipe) => pipe.copy_from_user(buf, count).await,
+            Inbox::Pipe { pipe, .. } => count.min(pipe.capacity().get()),
+            Inbox::Datagram(_) => count,
+        }
+    }
+
+    /// Sends `data`, waiting for room for up to `timeout`, or for as long as it
+    /// takes if there's none, unless it's `nonblock`.
+    async fn send(
+        &self,
+        origin: SockAddrUn,
+        data: Vec<u8>,
+        nonblock: bool,
+        timeout: Option<Duration>,
+        rights: Rights,
+    ) -> Result<usize> {
+        match self {
+            Inbox::Pipe {
+                pipe,
+                rights: queue,
+            } => {
+                // Queue the files first, so the data can't be read without
+                // them.
+                let has_rights = !rights.is_empty();
+                if has_rights {
+                    queue.lock_save_irq().push_back(rights);
+                }
+
+                let sent = if data.is_empty() {
+                    Ok(0)
+                } else if nonblock {
+                    match pipe.try_push_slice(&data) {
+                        0 => Err(KernelError::TryAgain),
+                        sent => Ok(sent),
+                    }
+                } else {
+                    block(pipe.push_slice(&data), timeout).await
+                };
+
+                if sent.is_err() && has_rights {
+                    queue.lock_save_irq().pop_back();
+                }
+
+                sent
+            }
             Inbox::Datagram(queue) => {
-                let mut data = vec![0u8; count];
-                copy_from_user_slice(buf, &mut data).await?;
+                let count = data.len();
                 let msg = Message {
                     sender: origin,
                     data,
+                    rights,
                 };
-                queue.lock().await.push_back(msg);
+                lock_queue(queue, timeout).await?.push_back(msg);
                 Ok(count)
             }
         }
     }
 
-    async fn recv(&self, buf: UA, count: usize) -> Result<(usize, Option<SockAddrUn>)> {
+    /// Receives up to `count` bytes, or with `peek`, copies them while leaving
+    /// them, and any files which came with them, to be received again. Waits
+    /// like [`Self::send`].
+    async fn recv(
+        &self,
+        count: usize,
+        nonblock: bool,
+        timeout: Option<Duration>,
+        peek: bool,
+    ) -> Result<(Vec<u8>, Option<SockAddrUn>, Rights)> {
         match self {
-            Inbox::Pipe(pipe) => Ok((pipe.copy_to_user(buf, count).await?, None)),
+            Inbox::Pipe { pipe, rights } => {
+                if count == 0 {
+                    return Ok((Vec::new(), None, Vec::new()));
+                }
+                let mut data = vec![0u8; count.min(pipe.capacity().get())];
+                let n = match (nonblock, peek) {
+                    (true, false) => pipe.try_pop_slice(&mut data),
+                    (true, true) => pipe.try_peek_slice(&mut data),
+                    (false, false) => block(pipe.pop_slice(&mut data), timeout).await?,
+                    (false, true) => block(pipe.peek_slice(&mut data), timeout).await?,
+                };
+                if n == 0 {
+                    return Err(KernelError::TryAgain);
+                }
+                data.truncate(n);
+
+                let mut queue = rights.lock_save_irq();
+                let rights = if peek {
+                    queue.front().cloned().unwrap_or_default()
+                } else {
+                    queue.pop_front().unwrap_or_default()
+                };
+                Ok((data, None, rights))
+            }
             Inbox::Datagram(queue) => {
-                let mut q = queue.lock().await;
-                if let Some(msg) = q.pop_front() {
-                    let n = msg.data.len().min(count);
-                    copy_to_user_slice(&msg.data[..n], buf).await?;
-                    Ok((n, Some(msg.sender)))
+                let mut q = lock_queue(queue, timeout).await?;
+                let msg = if peek {
+                    q.front().map(|msg| Message {
+                        sender: msg.sender,
+                        data: msg.data.clone(),
+                        rights: msg.rights.clone(),
+                    })
+                } else {
+                    q.pop_front()
+                };
+                if let Some(mut msg) = msg {
+                    msg.data.truncate(count);
+                    Ok((msg.data, Some(msg.sender), msg.rights))
+                } else if nonblock {
+                    Err(KernelError::TryAgain)
                 } else {
-                    Ok((0, None))
+                    Ok((Vec::new(), None, Vec::new()))
                 }
             }
         }
     }
+
+    /// Waits until there's something to receive.
+    fn read_ready(&self) -> Pin<Box<dyn Future<Output = Result<()>> + 'static + Send>> {
+        match self {
+            Inbox::Pipe { pipe, .. } => {
+                let read_fut = pipe.read_ready();
+                Box::pin(async move {
+                    read_fut.await;
+                    Ok(())
+                })
+            }
+            // Nothing wakes a task waiting on a datagram queue yet.
+            Inbox::Datagram(_) => Box::pin(async { Err(KernelError::NotSupported) }),
+        }
+    }
+
+    /// Waits until there's room to send.
+    fn write_ready(&self) -> Pin<Box<dyn Future<Output = Result<()>> + 'static + Send>> {
+        match self {
+            Inbox::Pipe { pipe, .. } => {
+                let pipe = pipe.clone();
+                Box::pin(async move {
+                    pipe.write_ready().await;
+                    Ok(())
+                })
+            }
+            // Datagram queues are unbounded, so sending never blocks.
+            Inbox::Datagram(_) => Box::pin(async { Ok(()) }),
+        }
+    }
 }
 
 /// Registry mapping Unix socket path bytes to endpoint inbox and listening state
@@ -102,12 +257,15 @@ pub struct UnixSocket {
     /// The peer endpoint's inbox
     peer_inbox: SpinLock<Option<Inbox>>,
     local_addr: SpinLock<Option<SockAddrUn>>,
+    /// The address of the peer the socket's connected to, unless it's unnamed.
+    peer_addr: SpinLock<Option<SockAddrUn>>,
     connected: SpinLock<bool>,
     listening: SpinLock<bool>,
     backlog: SpinLock<usize>,
     // Shutdown state
     rd_shutdown: SpinLock<bool>,
     wr_shutdown: SpinLock<bool>,
+    timeouts: SocketTimeouts,
 }
 
 impl UnixSocket {
@@ -117,11 +275,13 @@ impl UnixSocket {
             inbox: Inbox::new(socket_type),
             peer_inbox: SpinLock::new(None),
             local_addr: SpinLock::new(None),
+            peer_addr: SpinLock::new(None),
             connected: SpinLock::new(false),
             listening: SpinLock::new(false),
             backlog: SpinLock::new(0),
             rd_shutdown: SpinLock::new(false),
             wr_shutdown: SpinLock::new(false),
+            timeouts: SocketTimeouts::default(),
         }
     }
 
@@ -135,6 +295,15 @@ impl UnixSocket {
         Self::new(SocketType::SeqPacket)
     }
 
+    /// Connects the socket and `other`, a new unnamed socket of the same type,
+    /// to each other, as `socketpair` does.
+    pub fn connect_pair(&self, other: &UnixSocket) {
+        *self.peer_inbox.lock_save_irq() = Some(other.inbox.clone());
+        *other.peer_inbox.lock_save_irq() = Some(self.inbox.clone());
+        *self.connected.lock_save_irq() = true;
+        *other.connected.lock_save_irq() = true;
+    }
+
     fn path_bytes(saun: &crate::net::SockAddrUn) -> Option<Vec<u8>> {
         // Unix path is a sun_path-like fixed-size buffer which may be null-terminated
         let mut end = saun.path.len();
@@ -152,6 +321,96 @@ impl UnixSocket {
     }
 }
 
+impl UnixSocket {
+    /// Returns the inbox of the socket bound to `addr`.
+    fn lookup_inbox(addr: SockAddr) -> Result<Inbox> {
+        let SockAddr::Un(saun) = addr else {
+            return Err(KernelError::InvalidValue);
+        };
+        let Some(path) = UnixSocket::path_bytes(&saun) else {
+            return Err(KernelError::InvalidValue);
+        };
+        let reg = endpoints().lock_save_irq();
+        let Some(ep) = reg.get(&path) else {
+            return Err(KernelError::Fs(FsError::NotFound));
+        };
+        Ok(ep.inbox.clone())
+    }
+
+    /// Returns the inbox of the peer the socket's connected to.
+    fn connected_peer(&self) -> Result<Inbox> {
+        if *self.wr_shutdown.lock_save_irq() {
+            return Err(KernelError::BrokenPipe);
+        }
+        match self.socket_type {
+            SocketType::Stream | SocketType::SeqPacket => {
+                if !*self.connected.lock_save_irq() {
+                    return Err(KernelError::InvalidValue);
+                }
+            }
+            SocketType::Datagram => {}
+        }
+        self.peer_inbox
+            .lock_save_irq()
+            .clone()
+            .ok_or(KernelError::InvalidValue)
+    }
+
+    async fn send_data(
+        &self,
+        ctx: &FileCtx,
+        peer: Inbox,
+        data: Vec<u8>,
+        flags: SendFlags,
+        rights: Rights,
+    ) -> Result<usize> {
+        let nonblock =
+            flags.contains(SendFlags::MSG_DONT_WAIT) || ctx.flags.contains(OpenFlags::O_NONBLOCK);
+        let local_addr = { self.local_addr.lock_save_irq().unwrap_or(UNNAMED) };
+        peer.send(local_addr, data, nonblock, self.timeouts.send(), rights)
+            .await
+    }
+
+    async fn recv_data(
+        &self,
+        ctx: &FileCtx,
+        count: usize,
+        flags: RecvFlags,
+    ) -> Result<(Vec<u8>, Option<SockAddrUn>, Rights)> {
+        let nonblock =
+            flags.contains(RecvFlags::MSG_DONTWAIT) || ctx.flags.contains(OpenFlags::O_NONBLOCK);
+        if *self.rd_shutdown.lock_save_irq() {
+            return Ok((Vec::new(), None, Vec::new()));
+        }
+        let peek = flags.contains(RecvFlags::MSG_PEEK);
+        let (mut data, sender, mut rights) = self
+            .inbox
+            .recv(count, nonblock, self.timeouts.recv(), peek)
+            .await?;
+
+        // With `MSG_WAITALL`, a stream socket keeps reading until the buffer's
+        // full. Once some data has been read, an error just ends the read.
+        let waitall = flags.contains(RecvFlags::MSG_WAITALL)
+            && !peek
+            && matches!(self.socket_type, SocketType::Stream);
+        while waitall && !data.is_empty() && data.len() < count {
+            match self
+                .inbox
+                .recv(count - data.len(), nonblock, self.timeouts.recv(), false)
+                .await
+            {
+                Ok((more, _, more_rights)) if !more.is_empty() => {
+                    data.extend_from_slice(&more);
+                    rights.extend(more_rights);
+                }
+                _ => break,
+            }
+        }
+
+        Ok((data, sender, rights))
+    }
+}
+
 #[async_trait]
 impl SocketOps for UnixSocket {
     async fn bind(&self, addr: SockAddr) -> Result<()> {
@@ -182,7 +441,7 @@ impl SocketOps for UnixSocket {
         }
     }
 
-    async fn connect(&self, addr: SockAddr) -> Result<()> {
+    async fn connect(&self, _ctx: &FileCtx, addr: SockAddr) -> Result<()> {
         match addr {
             SockAddr::Un(saun) => {
                 let Some(path) = UnixSocket::path_bytes(&saun) else {
@@ -200,10 +459,12 @@ impl SocketOps for UnixSocket {
                     // For accepted sockets, local address matches the listening path (Linux getsockname).
                     *server_sock.local_addr.lock_save_irq() = Some(saun);
                     *server_sock.peer_inbox.lock_save_irq() = Some(self.inbox.clone());
+                    *server_sock.peer_addr.lock_save_irq() = *self.local_addr.lock_save_irq();
                     *server_sock.connected.lock_save_irq() = true;
 
                     // Client links to accepted socket inbox.
                     *self.peer_inbox.lock_save_irq() = Some(server_sock.inbox.clone());
+                    *self.peer_addr.lock_save_irq() = Some(saun);
                     *self.connected.lock_save_irq() = true;
 
                     ep.pending.push(server_sock);
@@ -215,6 +476,7 @@ impl SocketOps for UnixSocket {
                 } else {
                     // Non-listening endpoint: treat as datagram or pre-bound stream endpoint
                     *self.peer_inbox.lock_save_irq() = Some(ep.inbox.clone());
+                    *self.peer_addr.lock_save_irq() = Some(saun);
                     *self.connected.lock_save_irq() = true;
                     Ok(())
                 }
@@ -248,7 +510,9 @@ impl SocketOps for UnixSocket {
         Ok(())
     }
 
-    async fn accept(&self) -> Result<(Box<dyn SocketOps>, SockAddr)> {
+    async fn accept(&self, ctx: &FileCtx) -> Result<(Box<dyn SocketOps>, SockAddr)> {
+        let nonblock = ctx.flags.contains(OpenFlags::O_NONBLOCK);
+
         {
             if !*self.listening.lock_save_irq() {
                 return Err(KernelError::InvalidValue);
@@ -274,6 +538,8 @@ impl SocketOps for UnixSocket {
             if !ep.pending.is_empty() {
                 let sock = ep.pending.remove(0);
                 Poll::Ready(Ok(sock))
+            } else if nonblock {
+                Poll::Ready(Err(KernelError::TryAgain))
             } else {
                 ep.waiters.push(cx.waker().clone());
                 Poll::Pending
@@ -281,36 +547,27 @@ impl SocketOps for UnixSocket {
         })
         .await?;
 
-        // Best-effort peer address. For now we return our own bound path when available.
-        // (Linux often returns unnamed for AF_UNIX unless the peer is bound.)
-        let peer_addr = {
-            let guard = sock.local_addr.lock_save_irq();
-            let Some(saun) = &*guard else {
-                return Err(KernelError::InvalidValue);
-            };
-            SockAddr::Un(*saun)
-        };
+        // As on Linux, the peer is unnamed unless it was bound.
+        let peer_addr = SockAddr::Un(sock.peer_addr.lock_save_irq().unwrap_or(UNNAMED));
 
         Ok((Box::new(sock), peer_addr))
     }
 
     async fn recv(
         &mut self,
-        _ctx: &mut FileCtx,
+        ctx: &mut FileCtx,
         buf: UA,
         count: usize,
-        _flags: RecvFlags,
+        flags: RecvFlags,
     ) -> Result<(usize, Option<SockAddr>)> {
         if count == 0 {
             return Ok((0, None));
         }
-        if *self.rd_shutdown.lock_save_irq() {
-            return Ok((0, None));
-        }
-        self.inbox.recv(buf, count).await.map(|(n, peer)| {
-            let peer_addr = peer.map(SockAddr::Un);
-            (n, peer_addr)
-        })
+        // Any files which came with the data are closed, as there's nowhere to
+        // put them.
+        let (data, sender, _) = self.recv_data(ctx, count, flags).await?;
+        copy_to_user_slice(&data, buf).await?;
+        Ok((data.len(), sender.map(SockAddr::Un)))
     }
 
     async fn recvfrom(
@@ -327,65 +584,68 @@ impl SocketOps for UnixSocket {
 
     async fn send(
         &mut self,
-        _ctx: &mut FileCtx,
+        ctx: &mut FileCtx,
         buf: UA,
         count: usize,
-        _flags: SendFlags,
+        flags: SendFlags,
     ) -> Result<usize> {
         if count == 0 {
             return Ok(0);
         }
-        if *self.wr_shutdown.lock_save_irq() {
-            return Err(KernelError::BrokenPipe);
-        }
-        match self.socket_type {
-            SocketType::Stream | SocketType::SeqPacket => {
-                if !*self.connected.lock_save_irq() {
-                    return Err(KernelError::InvalidValue);
-                }
-            }
-            SocketType::Datagram => {}
-        }
-        let Some(peer) = self.peer_inbox.lock_save_irq().clone() else {
-            return Err(KernelError::InvalidValue);
-        };
-        let local_addr = {
-            self.local_addr.lock_save_irq().unwrap_or(SockAddrUn {
-                family: crate::net::AF_UNIX as u16,
-                path: [0; 108],
-            })
-        };
-        peer.send(local_addr, buf, count).await
+        let peer = self.connected_peer()?;
+        let mut data = vec![0u8; peer.send_len(count)];
+        copy_from_user_slice(buf, &mut data).await?;
+        self.send_data(ctx, peer, data, flags, Vec::new()).await
     }
 
     async fn sendto(
         &mut self,
-        _ctx: &mut FileCtx,
+        ctx: &mut FileCtx,
         buf: UA,
         count: usize,
-        _flags: SendFlags,
+        flags: SendFlags,
         addr: SockAddr,
     ) -> Result<usize> {
-        let peer_inbox = match addr {
-            SockAddr::Un(saun) => {
-                let Some(path) = UnixSocket::path_bytes(&saun) else {
-                    return Err(KernelError::InvalidValue);
-                };
-                let reg = endpoints().lock_save_irq();
-                let Some(ep) = reg.get(&path) else {
-                    return Err(KernelError::Fs(FsError::NotFound));
-                };
-                ep.inbox.clone()
-            }
-            _ => return Err(KernelError::InvalidValue),
-        };
-        let local_addr = {
-            self.local_addr.lock_save_irq().unwrap_or(SockAddrUn {
-                family: crate::net::AF_UNIX as u16,
-                path: [0; 108],
-            })
+        let peer = UnixSocket::lookup_inbox(addr)?;
+        let mut data = vec![0u8; peer.send_len(count)];
+        copy_from_user_slice(buf, &mut data).await?;
+        self.send_data(ctx, peer, data, flags, Vec::new()).await
+    }
+
+    async fn sendmsg(
+        &mut self,
+        ctx: &mut FileCtx,
+        iov: &UserIoVec,
+        flags: SendFlags,
+        addr: Option<SockAddr>,
+        rights: Rights,
+    ) -> Result<usize> {
+        let peer = match addr {
+            Some(addr) => UnixSocket::lookup_inbox(addr)?,
+            None => self.connected_peer()?,
         };
-        peer_inbox.send(local_addr, buf, count).await
+        let count = iov.iovs().iter().map(|vec| vec.iov_len).sum();
+        let mut data = vec![0u8; peer.send_len(count)];
+        let read = iov.reader().read(&mut data).await?;
+        data.truncate(read);
+        self.send_data(ctx, peer, data, flags, rights).await
+    }
+
+    async fn recvmsg(
+        &mut self,
+        ctx: &mut FileCtx,
+        iov: &UserIoVec,
+        flags: RecvFlags,
+    ) -> Result<RecvMsg> {
+        let count = iov.iovs().iter().map(|vec| vec.iov_len).sum();
+        let (data, sender, rights) = self.recv_data(ctx, count, flags).await?;
+        let len = iov.writer().write(&data).await?;
+        Ok(RecvMsg {
+            len,
+            addr: sender.map(SockAddr::Un),
+            rights,
+            truncated: false,
+        })
     }
 
     async fn shutdown(&self, how: crate::net::ShutdownHow) -> Result<()> {
@@ -404,6 +664,51 @@ impl SocketOps for UnixSocket {
         Ok(())
     }
 
+    fn getsockopt(&self, opt: SockOpt) -> Result<i32> {
+        match opt {
+            SockOpt::Type => Ok(match self.socket_type {
+                SocketType::Stream => SOCK_STREAM,
+                SocketType::Datagram => SOCK_DGRAM,
+                SocketType::SeqPacket => SOCK_SEQPACKET,
+            }),
+            SockOpt::Error | SockOpt::Protocol => Ok(0),
+            SockOpt::Domain => Ok(AF_UNIX),
+            _ => Err(KernelError::NoProtocolOption),
+        }
+    }
+
+    fn local_addr(&self) -> Result<SockAddr> {
+        Ok(SockAddr::Un(
+            self.local_addr.lock_save_irq().unwrap_or(UNNAMED),
+        ))
+    }
+
+    fn peer_addr(&self) -> Result<SockAddr> {
+        if self.peer_inbox.lock_save_irq().is_none() {
+            return Err(KernelError::NotConnected);
+        }
+
+        Ok(SockAddr::Un(
+            self.peer_addr.lock_save_irq().unwrap_or(UNNAMED),
+        ))
+    }
+
+    fn timeouts(&self) -> Option<&SocketTimeouts> {
+        Some(&self.timeouts)
+    }
+
+    fn poll_read_ready(&self) -> Pin<Box<dyn Future<Output = Result<()>> + 'static + Send>> {
+        self.inbox.read_ready()
+    }
+
+    fn poll_write_ready(&self) -> Pin<Box<dyn Future<Output = Result<()>> + 'static + Send>> {
+        match self.peer_inbox.lock_save_irq().as_ref() {
+            Some(peer) => peer.write_ready(),
+            // Sending fails straight away without a peer.
+            None => Box::pin(async { Ok(()) }),
+        }
+    }
+
     fn as_file(self: Box<Self>) -> Box<dyn crate::fs::fops::FileOps> {
         self
     }
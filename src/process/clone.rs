@@ -9,14 +9,16 @@ use crate::memory::uaccess::copy_to_user;
 use crate::sched::sched_task::Work;
 use crate::sched::syscall_ctx::ProcessCtx;
 use crate::{
+    per_cpu_shared,
     process::{TASK_LIST, Task},
     sched::{self},
-    sync::SpinLock,
+    sync::{CondVar, SpinLock},
 };
 use alloc::boxed::Box;
 use bitflags::bitflags;
 use core::sync::atomic::AtomicUsize;
 use libkernel::memory::address::TUA;
+use libkernel::proc::caps::CapabilitiesFlags;
 use libkernel::{
     error::{KernelError, Result},
     memory::address::UA,
@@ -24,7 +26,9 @@ use libkernel::{
 };
 use ringbuf::Arc;
 
-pub static NUM_FORKS: AtomicUsize = AtomicUsize::new(0);
+per_cpu_shared! {
+    pub static NUM_FORKS: AtomicUsize = || AtomicUsize::new(0);
+}
 
 bitflags! {
     #[derive(Debug)]
@@ -65,6 +69,14 @@ pub async fn sys_clone(
 ) -> Result<usize> {
     let flags = CloneFlags::from_bits_truncate(flags);
 
+    if flags.contains(CloneFlags::CLONE_NEWUTS) {
+        ctx.shared()
+            .creds
+            .lock_save_irq()
+            .caps()
+            .check_capable(CapabilitiesFlags::CAP_SYS_ADMIN)?;
+    }
+
     let trace_point = if flags.contains(CloneFlags::CLONE_THREAD) {
         TracePoint::Clone
     } else {
@@ -145,6 +157,12 @@ pub async fn sys_clone(
             Arc::new(SpinLock::new(current_task.root.lock_save_irq().clone()))
         };
 
+        let uts_ns = if flags.contains(CloneFlags::CLONE_NEWUTS) {
+            Arc::new(current_task.uts_ns.copy())
+        } else {
+            current_task.uts_ns.clone()
+        };
+
         let ptrace = if flags.contains(CloneFlags::CLONE_PTRACE) || should_trace_new_tsk {
             current_task.ptrace.lock_save_irq().clone()
         } else {
@@ -181,15 +199,18 @@ pub async fn sys_clone(
                 fd_table: files,
                 cwd,
                 root,
+                uts_ns,
                 i_timers: SpinLock::new(ITimers::default()),
                 creds: SpinLock::new(creds),
                 ptrace: SpinLock::new(ptrace),
                 sig_mask: new_sigmask,
                 pending_signals: initial_signals,
+                fault_info: SpinLock::new(None),
                 signal_notifier: SpinLock::new(WakerSet::new()),
                 utime: AtomicUsize::new(0),
                 stime: AtomicUsize::new(0),
                 last_account: AtomicUsize::new(0),
+                exited: CondVar::new(false),
             }),
             in_syscall: false,
         }
@@ -209,7 +230,7 @@ pub async fn sys_clone(
 
     sched::insert_work_cross_cpu(work);
 
-    NUM_FORKS.fetch_add(1, core::sync::atomic::Ordering::Relaxed);
+    NUM_FORKS.add(1);
 
     // Honour CLONE_*SETTID semantics for the parent and (shared-VM) child.
     if flags.contains(CloneFlags::CLONE_PARENT_SETTID) && !parent_tidptr.is_null() {
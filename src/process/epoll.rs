@@ -1,5 +1,5 @@
 use alloc::{boxed::Box, collections::BTreeMap, sync::Arc, vec::Vec};
-use core::{future::poll_fn, pin::pin, task::Poll};
+use core::{future::poll_fn, pin::pin, task::Poll, time::Duration};
 use libkernel::{
     error::{FsError, KernelError, Result},
     fs::OpenFlags,
@@ -12,7 +12,7 @@ use crate::{
     memory::uaccess::{UserCopyable, copy_from_user, copy_objs_to_user},
     process::{fd_table::Fd, fd_table::select::PollFlags, thread_group::signal::SigSet},
     sched::syscall_ctx::ProcessCtx,
-    sync::Mutex,
+    sync::{Rwlock, RwlockTimeout},
 };
 
 pub const EPOLL_CTL_ADD: i32 = 1;
@@ -33,7 +33,7 @@ pub trait EpollOps: Send + Sync {
 }
 
 pub struct Epoll {
-    watches: Mutex<BTreeMap<Fd, (EpollEvent, Arc<OpenFile>)>>,
+    watches: Rwlock<BTreeMap<Fd, (EpollEvent, Arc<OpenFile>)>>,
 }
 
 impl Default for Epoll {
@@ -45,7 +45,7 @@ impl Default for Epoll {
 impl Epoll {
     pub fn new() -> Self {
         Self {
-            watches: Mutex::new(BTreeMap::new()),
+            watches: Rwlock::new(BTreeMap::new()),
         }
     }
 }
@@ -122,7 +122,7 @@ pub async fn sys_epoll_ctl(
 
     let epoll = ops.as_epoll().ok_or(KernelError::InvalidValue)?.get_epoll();
 
-    let mut watches = epoll.watches.lock().await;
+    let mut watches = epoll.watches.write().await;
 
     match op {
         EPOLL_CTL_ADD => {
@@ -184,97 +184,98 @@ pub async fn sys_epoll_pwait(
     }
 
     let mut timeout_fut = if timeout >= 0 {
-        Some(pin!(sleep(core::time::Duration::from_millis(
-            timeout as u64
-        ))))
+        Some(pin!(sleep(Duration::from_millis(timeout as u64))))
     } else {
         None
     };
 
-    // We take a snapshot of the watches.
-    // we map over them and create polling futures.
-
-    // TODO: Real implementation should perhaps be more efficient, but this works for now.
-    let mut fds_and_events = {
+    // We take a snapshot of the watches. A concurrent epoll_ctl holding the
+    // watch list counts against the caller's timeout.
+    let watches = {
         let mut lock = epoll_file.lock().await;
         let ops = &mut lock.0;
         let epoll = ops.as_epoll().ok_or(KernelError::InvalidValue)?.get_epoll();
-        let watches = epoll.watches.lock().await;
-
-        let mut list = Vec::new();
-        for (watch_fd, (event, file)) in watches.iter() {
-            let poll_flags = PollFlags::from_bits_truncate(event.events as _);
-            list.push((*watch_fd, *event, file.clone(), poll_flags));
-        }
-        list
+        let watches = if timeout >= 0 {
+            match epoll
+                .watches
+                .read_timeout(Duration::from_millis(timeout as u64))
+                .await
+            {
+                Ok(watches) => watches,
+                Err(_) => {
+                    if mask.is_some() {
+                        task.sig_mask.store(old_sigmask);
+                    }
+                    return Ok(0);
+                }
+            }
+        } else {
+            epoll.watches.read().await
+        };
+
+        watches
+            .values()
+            .map(|(event, file)| (*event, file.clone()))
+            .collect::<Vec<_>>()
     };
 
-    loop {
-        // Evaluate all poll_futs for current events.
-        let mut ready_events: Vec<EpollEvent> = Vec::new();
-        let mut fds_to_poll = Vec::new();
+    // The readiness futures are made once, up front, so that the wakers they
+    // register stay registered while we sleep. A file whose readiness can't be
+    // polled is never reported.
+    let mut futs = Vec::new();
+    for (event, file) in watches.iter() {
+        let poll_flags = PollFlags::from_bits_truncate(event.events as _);
 
-        for (watch_fd, event, file, poll_flags) in fds_and_events.iter_mut() {
-            fds_to_poll.push((*watch_fd, *event, file.clone(), *poll_flags));
-        }
+        futs.push((*event, Some(Box::pin(file.poll(poll_flags).await))));
+    }
+
+    let mut ready_events: Vec<EpollEvent> = Vec::new();
 
-        // Wait a bit, or complete if timeout expires
-        let mut events_ready = false;
-        let yielded = poll_fn(|cx| {
-            let mut ready = false;
-            for (_watch_fd, event, file, poll_flags) in fds_to_poll.iter_mut() {
-                let poll_fut = file.poll(*poll_flags);
-                let mut pin_fut = Box::pin(poll_fut);
-                // First poll: get the inner future
-                if let Poll::Ready(inner_fut) = pin_fut.as_mut().poll(cx) {
-                    let mut inner_pin = Box::pin(inner_fut);
-                    // Second poll: actually poll the file readiness future with the context
-                    if let Poll::Ready(Ok(revents)) = inner_pin.as_mut().poll(cx)
-                        && (revents.intersects(*poll_flags) || !revents.is_empty())
-                    {
+    poll_fn(|cx| {
+        for (event, fut) in futs.iter_mut() {
+            let Some(poll_fut) = fut else {
+                continue;
+            };
+
+            match poll_fut.as_mut().poll(cx) {
+                Poll::Ready(Ok(revents)) => {
+                    *fut = None;
+
+                    if !revents.is_empty() {
                         let mut out_event = *event;
                         out_event.events = revents.bits() as u32;
                         ready_events.push(out_event);
-                        ready = true;
+
                         if ready_events.len() == maxevents as usize {
                             break;
                         }
                     }
                 }
+                Poll::Ready(Err(_)) => *fut = None,
+                Poll::Pending => {}
             }
-            if ready {
-                events_ready = true;
-                return Poll::Ready(true);
-            }
+        }
 
-            // TODO: handle timeouts better
-            if timeout == 0 {
-                return Poll::Ready(true);
-            }
+        if !ready_events.is_empty() || timeout == 0 {
+            return Poll::Ready(());
+        }
 
-            if let Some(ref mut t) = timeout_fut
-                && t.as_mut().poll(cx).is_ready()
-            {
-                return Poll::Ready(true);
-            }
+        match timeout_fut {
+            Some(ref mut t) => t.as_mut().poll(cx),
+            None => Poll::Pending,
+        }
+    })
+    .await;
 
-            Poll::Pending
-        })
-        .await;
+    drop(futs);
 
-        if events_ready && !ready_events.is_empty() {
-            copy_objs_to_user(&ready_events, events_ptr).await?;
-            if mask.is_some() {
-                task.sig_mask.store(old_sigmask);
-            }
-            return Ok(ready_events.len());
-        }
+    if mask.is_some() {
+        task.sig_mask.store(old_sigmask);
+    }
 
-        if timeout == 0 || (timeout > 0 && yielded) {
-            if mask.is_some() {
-                task.sig_mask.store(old_sigmask);
-            }
-            return Ok(0);
-        }
+    if !ready_events.is_empty() {
+        copy_objs_to_user(&ready_events, events_ptr).await?;
     }
+
+    Ok(ready_events.len())
 }
@@ -8,6 +8,7 @@ use crate::{
     memory::{
         page::ClaimedPage,
         uaccess::{copy_from_user, cstr::UserCStr},
+        vmalloc::vmalloc,
     },
     process::{ctx::Context, thread_group::signal::SignalActionState},
 };
@@ -27,7 +28,7 @@ use libkernel::{
         proc_vm::{
             ProcessVM,
             memory_map::MemoryMap,
-            vmarea::{VMAPermissions, VMArea, VMAreaKind},
+            vmarea::{VMAFlags, VMAPermissions, VMArea, VMAreaKind},
         },
         region::VirtMemoryRegion,
     },
@@ -46,6 +47,8 @@ const LINKER_BIAS: usize = 0x0000_7000_0000_0000;
 const PROG_BIAS: usize = 0x0000_5000_0000_0000;
 
 const STACK_END: usize = 0x0000_8000_0000_0000;
+/// The initial size of the stack VMA. It grows downwards on demand, bounded by
+/// `RLIMIT_STACK`.
 const STACK_SZ: usize = 0x2000 * 0x400;
 const STACK_START: usize = STACK_END - STACK_SZ;
 
@@ -98,12 +101,13 @@ async fn exec_elf(
         .map_err(|_| ExecError::InvalidElfFormat)?;
     let endian = elf.endian().unwrap();
 
-    // Read full program header table
+    // Read full program header table. Its size comes straight from the file,
+    // so it's read into vmalloc memory, which fails cleanly if it's absurd.
     let ph_table_size = elf.e_phnum.get(endian) as usize * elf.e_phentsize.get(endian) as usize
         + elf.e_phoff.get(endian) as usize;
-    let mut ph_buf = vec![0u8; ph_table_size];
+    let mut ph_buf = vmalloc(ph_table_size)?;
 
-    inode.read_at(0, &mut ph_buf).await?;
+    inode.read_at(0, ph_buf.as_slice_mut()).await?;
 
     let hdrs = elf
         .program_headers(endian, ph_buf.as_slice())
@@ -177,6 +181,7 @@ async fn exec_elf(
     );
 
     stack_vma.set_name("[stack]");
+    stack_vma.set_flags(VMAFlags::GROWSDOWN | VMAFlags::ACCOUNT);
 
     vmas.push(stack_vma);
 
@@ -410,10 +415,12 @@ async fn process_interp(
     let interp_ph_table_size = interp_elf.e_phnum.get(iendian) as usize
         * interp_elf.e_phentsize.get(iendian) as usize
         + interp_elf.e_phoff.get(iendian) as usize;
-    let mut interp_ph_buf = vec![0u8; interp_ph_table_size];
-    interp_inode.read_at(0, &mut interp_ph_buf).await?;
+    let mut interp_ph_buf = vmalloc(interp_ph_table_size)?;
+    interp_inode
+        .read_at(0, interp_ph_buf.as_slice_mut())
+        .await?;
     let interp_hdrs = interp_elf
-        .program_headers(iendian, &interp_ph_buf[..])
+        .program_headers(iendian, interp_ph_buf.as_slice())
         .map_err(|_| ExecError::InvalidPHdrFormat)?;
 
     // Build VMAs for interpreter
@@ -10,6 +10,7 @@ use crate::sched::syscall_ctx::ProcessCtx;
 use crate::sched::{self};
 use alloc::vec::Vec;
 use libkernel::error::Result;
+use libkernel::sync::condvar::WakeupType;
 use log::warn;
 use ringbuf::Arc;
 
@@ -36,6 +37,7 @@ pub fn do_exit_group(task: &Arc<Task>, exit_code: ChildState) {
             // We're already on our way out. Just kill this thread.
             drop(process_state);
             sched::current_work().state.finish();
+            mark_thread_exited(task);
             return;
         }
 
@@ -55,6 +57,7 @@ pub fn do_exit_group(task: &Arc<Task>, exit_code: ChildState) {
                 // again.
                 other_thread.state.finish();
             }
+            mark_thread_exited(&other_thread);
         }
     }
 
@@ -87,6 +90,11 @@ pub fn do_exit_group(task: &Arc<Task>, exit_code: ChildState) {
 
     parent.queue_signal(SigId::SIGCHLD);
 
+    process.exited.update(|exited| {
+        *exited = true;
+        WakeupType::All
+    });
+
     // 5. This thread is now finished.
     sched::current_work().state.finish();
 
@@ -94,6 +102,14 @@ pub fn do_exit_group(task: &Arc<Task>, exit_code: ChildState) {
     // state is set to Finished.
 }
 
+/// Wakes any `PIDFD_THREAD` pidfds waiting on `task`.
+fn mark_thread_exited(task: &Task) {
+    task.exited.update(|exited| {
+        *exited = true;
+        WakeupType::All
+    });
+}
+
 pub fn kernel_exit_with_signal(task: Arc<Task>, signal: SigId, core: bool) {
     do_exit_group(&task, ChildState::SignalExit { signal, core });
 }
@@ -159,6 +175,7 @@ pub async fn sys_exit(ctx: &mut ProcessCtx, exit_code: usize) -> Result<usize> {
     } else {
         // Mark our own state as finished.
         sched::current_work().state.finish();
+        mark_thread_exited(task);
 
         // Remove ourself from the process's thread list.
         tasks_lock.remove(&task.tid);
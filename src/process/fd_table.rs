@@ -1,4 +1,7 @@
-use crate::{fs::open_file::OpenFile, memory::uaccess::UserCopyable};
+use crate::{
+    fs::open_file::{OpenFile, file_max, nr_files},
+    memory::uaccess::UserCopyable,
+};
 use alloc::{sync::Arc, vec::Vec};
 use libkernel::error::{FsError, KernelError, Result};
 
@@ -82,6 +85,10 @@ impl FileDescriptorTable {
 
     /// Inserts a new file into the table with descriptor flags.
     pub fn insert_with_flags(&mut self, file: Arc<OpenFile>, flags: FdFlags) -> Result<Fd> {
+        if nr_files() > file_max() {
+            return Err(FsError::TooManyFilesInSystem.into());
+        }
+
         let fd = self.find_free_fd()?;
 
         let entry = FileDescriptorEntry { file, flags };
@@ -278,8 +278,8 @@ pub async fn sys_ppoll(
     ufds: TUA<PollFd>,
     nfds: u32,
     timeout: TUA<TimeSpec>,
-    _sigmask: TUA<SigSet>,
-    _sigset_len: usize,
+    sigmask: TUA<SigSet>,
+    sigset_len: usize,
 ) -> Result<usize> {
     let task = ctx.shared();
 
@@ -292,29 +292,66 @@ pub async fn sys_ppoll(
         Some(pin!(sleep(duration)))
     };
 
+    let mask = if sigmask.is_null() {
+        None
+    } else {
+        if sigset_len != core::mem::size_of::<SigSet>() {
+            return Err(KernelError::InvalidValue);
+        }
+
+        Some(copy_from_user(sigmask).await?)
+    };
+
+    // Negative fds are skipped, and fds which aren't open are reported with
+    // `POLLNVAL` rather than failing the call.
     let fds = {
         let fd_table = task.fd_table.lock_save_irq();
 
         poll_fds
             .iter()
-            .map(|poll_fd| fd_table.get(poll_fd.fd).ok_or(KernelError::BadFd))
-            .collect::<Result<Vec<_>>>()?
+            .map(|poll_fd| {
+                if poll_fd.fd.as_raw() < 0 {
+                    None
+                } else {
+                    Some(fd_table.get(poll_fd.fd))
+                }
+            })
+            .collect::<Vec<_>>()
     };
 
+    let old_sigmask = task.sig_mask.load();
+    if let Some(mask) = mask {
+        let mut new_sigmask = mask;
+        new_sigmask.remove(SigSet::UNMASKABLE_SIGNALS);
+        task.sig_mask.store(new_sigmask);
+    }
+
     let mut futs = Vec::new();
+    let mut num_invalid = 0;
 
     for (poll_fd, open_file) in poll_fds.iter_mut().zip(fds) {
-        let poll_fut = open_file.poll(poll_fd.events).await;
+        poll_fd.revents = PollFlags::empty();
 
-        futs.push(Box::pin(async {
-            poll_fd.revents = poll_fut.await?;
+        match open_file {
+            None => {}
+            Some(None) => {
+                poll_fd.revents = PollFlags::POLLNVAL;
+                num_invalid += 1;
+            }
+            Some(Some(open_file)) => {
+                let poll_fut = open_file.poll(poll_fd.events).await;
 
-            Ok(())
-        }));
+                futs.push(Box::pin(async {
+                    poll_fd.revents = poll_fut.await?;
+
+                    Ok(())
+                }));
+            }
+        }
     }
 
-    let num_ready = poll_fn(|cx| {
-        let mut num_ready = 0;
+    let result = poll_fn(|cx| {
+        let mut num_ready = num_invalid;
 
         for fut in futs.iter_mut() {
             match fut.as_mut().poll(cx) {
@@ -334,10 +371,16 @@ pub async fn sys_ppoll(
             Poll::Ready(Ok(num_ready))
         }
     })
-    .await?;
+    .await;
 
     drop(futs);
 
+    if mask.is_some() {
+        task.sig_mask.store(old_sigmask);
+    }
+
+    let num_ready = result?;
+
     copy_objs_to_user(&poll_fds, ufds).await?;
 
     Ok(num_ready)
@@ -3,12 +3,12 @@ use crate::sched::CPU_STAT;
 use crate::sched::sched_task::Work;
 use crate::{
     arch::ArchImpl,
-    kernel::cpu_id::CpuId,
+    kernel::{cpu_id::CpuId, hostname::UtsNamespace},
     memory::{
         PAGE_ALLOC,
-        fault::{FaultResolution, handle_demand_fault},
+        fault::{FaultResolution, handle_demand_fault, handle_protection_fault},
     },
-    sync::SpinLock,
+    sync::{CondVar, SpinLock},
 };
 use alloc::{
     boxed::Box,
@@ -32,7 +32,7 @@ use libkernel::{
 };
 use ptrace::PTrace;
 use thread_group::pid::PidT;
-use thread_group::signal::{AtomicSigSet, SigId};
+use thread_group::signal::{AtomicSigSet, FaultInfo, SigId};
 use thread_group::{Tgid, ThreadGroup};
 
 pub mod caps;
@@ -186,16 +186,20 @@ pub struct Task {
     pub vm: Arc<SpinLock<ProcVM>>,
     pub cwd: Arc<SpinLock<(Arc<dyn Inode>, PathBuf)>>,
     pub root: Arc<SpinLock<(Arc<dyn Inode>, PathBuf)>>,
+    pub uts_ns: Arc<UtsNamespace>,
     pub creds: SpinLock<Credentials>,
     pub i_timers: SpinLock<ITimers>,
     pub fd_table: Arc<SpinLock<FileDescriptorTable>>,
     pub ptrace: SpinLock<PTrace>,
     pub sig_mask: AtomicSigSet,
     pub pending_signals: AtomicSigSet,
+    pub fault_info: SpinLock<Option<FaultInfo>>,
     pub signal_notifier: SpinLock<WakerSet>,
     pub utime: AtomicUsize,
     pub stime: AtomicUsize,
     pub last_account: AtomicUsize,
+    /// Set once this thread has exited, for `PIDFD_THREAD` pidfds to wait on.
+    pub exited: CondVar<bool>,
 }
 
 impl Task {
@@ -217,6 +221,13 @@ impl Task {
         self.notify_signal_waiters();
     }
 
+    /// Raise a synchronous fault signal on this task, recording the details
+    /// of the fault for the signal handler.
+    pub fn raise_fault_signal(&self, info: FaultInfo) {
+        *self.fault_info.lock_save_irq() = Some(info);
+        self.raise_task_signal(info.signal);
+    }
+
     pub fn notify_signal_waiters(&self) {
         self.signal_notifier.lock_save_irq().wake_all();
     }
@@ -276,7 +287,7 @@ impl Task {
                 Box::into_pin(fut).await?;
             }
 
-            {
+            let resolution = {
                 let mut vm = self.vm.lock_save_irq();
 
                 if let Some(pa) = vm.mm_mut().address_space_mut().translate(va) {
@@ -298,11 +309,24 @@ impl Task {
 
                         return Ok(ret);
                     }
+
+                    // The page is resident, but the PTE doesn't permit the
+                    // access, e.g. a write to a CoW page.
+                    handle_protection_fault(&mut vm, va, access_kind, pa)?
+                } else {
+                    drop(vm);
+
+                    handle_demand_fault(
+                        self.vm.clone(),
+                        va,
+                        access_kind,
+                        self.process.stack_limit(),
+                    )?
                 }
-            }
+            };
 
             // Try to handle the fault.
-            match handle_demand_fault(self.vm.clone(), va, access_kind)? {
+            match resolution {
                 // Resolved the fault.   Try again
                 FaultResolution::Resolved => continue,
                 FaultResolution::Denied => return Err(KernelError::Fault),
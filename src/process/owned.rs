@@ -11,10 +11,15 @@ use super::{
     },
     threading::RobustListHead,
 };
-use crate::{arch::Arch, fs::DummyInode, sync::SpinLock};
+use crate::{
+    arch::Arch,
+    fs::DummyInode,
+    sync::{CondVar, SpinLock},
+};
 use crate::{
     arch::ArchImpl,
     drivers::timer::{Instant, now},
+    kernel::hostname::init_uts_ns,
 };
 use alloc::sync::Arc;
 use core::ops::Deref;
@@ -69,6 +74,7 @@ impl OwnedTask {
             process: thread_group_builder.build(),
             cwd: Arc::new(SpinLock::new((Arc::new(DummyInode {}), PathBuf::new()))),
             root: Arc::new(SpinLock::new((Arc::new(DummyInode {}), PathBuf::new()))),
+            uts_ns: init_uts_ns(),
             creds: SpinLock::new(Credentials::new_root()),
             vm: Arc::new(SpinLock::new(vm)),
             fd_table: Arc::new(SpinLock::new(FileDescriptorTable::new())),
@@ -78,8 +84,10 @@ impl OwnedTask {
             stime: AtomicUsize::new(0),
             last_account: AtomicUsize::new(0),
             pending_signals: AtomicSigSet::empty(),
+            fault_info: SpinLock::new(None),
             signal_notifier: SpinLock::new(WakerSet::new()),
             sig_mask: AtomicSigSet::empty(),
+            exited: CondVar::new(false),
         };
 
         Self {
@@ -99,6 +107,7 @@ impl OwnedTask {
             process: ThreadGroupBuilder::new(Tgid::init()).build(),
             cwd: Arc::new(SpinLock::new((Arc::new(DummyInode {}), PathBuf::new()))),
             root: Arc::new(SpinLock::new((Arc::new(DummyInode {}), PathBuf::new()))),
+            uts_ns: init_uts_ns(),
             creds: SpinLock::new(Credentials::new_root()),
             vm: Arc::new(SpinLock::new(
                 ProcessVM::empty().expect("Could not create init process's VM"),
@@ -110,8 +119,10 @@ impl OwnedTask {
             utime: AtomicUsize::new(0),
             stime: AtomicUsize::new(0),
             pending_signals: AtomicSigSet::empty(),
+            fault_info: SpinLock::new(None),
             signal_notifier: SpinLock::new(WakerSet::new()),
             sig_mask: AtomicSigSet::empty(),
+            exited: CondVar::new(false),
         };
 
         Self {
@@ -1,12 +1,15 @@
 use crate::fs::fops::FileOps;
 use crate::fs::open_file::OpenFile;
 use crate::process::thread_group::pid::PidT;
+use crate::process::thread_group::{Tgid, ThreadGroup};
 use crate::process::{Tid, find_task_by_tid};
 use crate::sched::syscall_ctx::ProcessCtx;
+use crate::sync::CondVar;
 use alloc::boxed::Box;
 use alloc::sync::Arc;
 use async_trait::async_trait;
 use bitflags::bitflags;
+use core::pin::Pin;
 use libkernel::error::{KernelError, Result};
 use libkernel::fs::OpenFlags;
 use libkernel::memory::address::UA;
@@ -22,18 +25,23 @@ bitflags! {
 pub struct PidFile {
     _pid: Tid,
     _flags: PidfdFlags,
+    /// The process's [`ThreadGroup::exited`], or for `PIDFD_THREAD` the
+    /// thread's [`Task::exited`](crate::process::Task::exited), which makes
+    /// the pidfd readable once it's set.
+    exited: CondVar<bool>,
 }
 
 impl PidFile {
-    pub fn new(pid: Tid, flags: PidfdFlags) -> Self {
+    pub fn new(pid: Tid, flags: PidfdFlags, exited: CondVar<bool>) -> Self {
         Self {
             _pid: pid,
             _flags: flags,
+            exited,
         }
     }
 
-    pub fn new_open_file(pid: Tid, flags: PidfdFlags) -> Arc<OpenFile> {
-        let file = PidFile::new(pid, flags);
+    pub fn new_open_file(pid: Tid, flags: PidfdFlags, exited: CondVar<bool>) -> Arc<OpenFile> {
+        let file = PidFile::new(pid, flags, exited);
         Arc::new(OpenFile::new(
             Box::new(file),
             OpenFlags::from_bits(flags.bits()).unwrap(),
@@ -50,17 +58,35 @@ impl FileOps for PidFile {
     async fn writeat(&mut self, _buf: UA, _count: usize, _offset: u64) -> Result<usize> {
         Err(KernelError::InvalidValue)
     }
+
+    /// A pidfd polls readable once its process, or for `PIDFD_THREAD` its
+    /// thread, has exited.
+    fn poll_read_ready(&self) -> Pin<Box<dyn Future<Output = Result<()>> + 'static + Send>> {
+        let exited = self.exited.clone();
+        Box::pin(async move {
+            exited.wait_until(|exited| exited.then_some(())).await;
+            Ok(())
+        })
+    }
 }
 
 pub async fn sys_pidfd_open(ctx: &ProcessCtx, pid: PidT, flags: u32) -> Result<usize> {
     let pid = Tid::from_pid_t(pid);
     let flags = PidfdFlags::from_bits(flags).ok_or(KernelError::InvalidValue)?;
-    if !flags.contains(PidfdFlags::PIDFD_THREAD) {
-        // Ensure the pid exists and is a thread group leader.
-        let _ = find_task_by_tid(pid).unwrap();
-    }
+    let exited = if flags.contains(PidfdFlags::PIDFD_THREAD) {
+        find_task_by_tid(pid)
+            .ok_or(KernelError::NoProcess)?
+            .exited
+            .clone()
+    } else {
+        // The pid must be a thread group leader.
+        ThreadGroup::get(Tgid(pid.value()))
+            .ok_or(KernelError::NoProcess)?
+            .exited
+            .clone()
+    };
 
-    let file = PidFile::new_open_file(pid, flags);
+    let file = PidFile::new_open_file(pid, flags, exited);
 
     let fd = ctx.task().fd_table.lock_save_irq().insert(file)?;
 
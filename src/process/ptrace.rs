@@ -4,20 +4,26 @@ use super::{
 };
 use crate::{
     arch::{Arch, ArchImpl},
-    fs::syscalls::iov::IoVec,
-    memory::uaccess::{copy_from_user, copy_to_user},
+    memory::{
+        PageOffsetTranslator,
+        uaccess::{copy_from_user, copy_to_user, copy_to_user_slice, iovec::IoVec},
+    },
     process::thread_group::signal::SigId,
     sched::syscall_ctx::ProcessCtx,
 };
 use alloc::sync::Arc;
 use bitflags::Flags;
 use core::{
+    cmp::min,
     future::poll_fn,
+    mem::size_of,
+    slice,
     task::{Poll, Waker},
 };
 use libkernel::{
     error::{KernelError, Result},
-    memory::address::UA,
+    memory::{PAGE_SIZE, address::UA, proc_vm::vmarea::AccessKind},
+    proc::caps::CapabilitiesFlags,
 };
 use log::warn;
 
@@ -32,6 +38,81 @@ const PTRACE_EVENT_EXIT: usize = 6;
 const PTRACE_EVENT_SECCOMP: usize = 7;
 const PTRACE_EVENT_STOP: usize = 128;
 
+const PTRACE_SYSCALL_INFO_NONE: u8 = 0;
+const PTRACE_SYSCALL_INFO_ENTRY: u8 = 1;
+const PTRACE_SYSCALL_INFO_EXIT: u8 = 2;
+
+/// Decodes the syscall a tracee was stopped in from its registers, for
+/// `PTRACE_GET_SYSCALL_INFO`.
+pub trait SyscallRegs {
+    /// The `AUDIT_ARCH_*` value identifying the architecture.
+    const AUDIT_ARCH: u32;
+
+    fn instruction_pointer(&self) -> u64;
+    fn stack_pointer(&self) -> u64;
+    fn syscall_nr(&self) -> u64;
+    fn syscall_args(&self) -> [u64; 6];
+    /// The syscall's return value. Only meaningful at a syscall exit stop.
+    fn syscall_ret(&self) -> u64;
+}
+
+/// `struct ptrace_syscall_info`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct PtraceSyscallInfo {
+    op: u8,
+    pad: [u8; 3],
+    arch: u32,
+    instruction_pointer: u64,
+    stack_pointer: u64,
+    /// `nr` followed by `args` for an entry stop, or `rval` followed by
+    /// `is_error` for an exit stop.
+    data: [u64; 7],
+}
+
+impl PtraceSyscallInfo {
+    /// Builds the info for a tracee stopped with `regs` at `hit_point`.
+    /// Returns it along with how much of it is valid.
+    fn new(regs: &GpRegs, hit_point: Option<TracePoint>) -> (Self, usize) {
+        let mut info = Self {
+            op: PTRACE_SYSCALL_INFO_NONE,
+            pad: [0; 3],
+            arch: GpRegs::AUDIT_ARCH,
+            instruction_pointer: regs.instruction_pointer(),
+            stack_pointer: regs.stack_pointer(),
+            data: [0; 7],
+        };
+
+        let header_len = size_of::<Self>() - size_of::<[u64; 7]>();
+
+        match hit_point {
+            Some(TracePoint::SyscallEntry) => {
+                info.op = PTRACE_SYSCALL_INFO_ENTRY;
+                info.data[0] = regs.syscall_nr();
+                info.data[1..].copy_from_slice(&regs.syscall_args());
+
+                (info, size_of::<Self>())
+            }
+            Some(TracePoint::SyscallExit) => {
+                let rval = regs.syscall_ret() as i64;
+
+                info.op = PTRACE_SYSCALL_INFO_EXIT;
+                info.data[0] = rval as u64;
+                info.data[1] = (-4095..0).contains(&rval) as u64;
+
+                // `rval`, then the single byte of `is_error`.
+                (info, header_len + size_of::<i64>() + 1)
+            }
+            _ => (info, header_len),
+        }
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        // SAFETY: The struct is repr(C), and its padding is explicit.
+        unsafe { slice::from_raw_parts((self as *const Self).cast::<u8>(), size_of::<Self>()) }
+    }
+}
+
 bitflags::bitflags! {
     #[derive(Clone, Copy, PartialEq)]
     pub struct PTraceOptions: usize {
@@ -200,6 +281,15 @@ impl PTrace {
         should_stop
     }
 
+    /// Returns the trace point the program was halted at, if it was halted at
+    /// one rather than by a signal.
+    fn hit_point(&self) -> Option<TracePoint> {
+        match self.state.as_ref()? {
+            PTraceState::TracePointHit { hit_point, .. } => Some(*hit_point),
+            _ => None,
+        }
+    }
+
     /// Returns the current GP regset when the program has been halted.
     pub fn regset(&self) -> Option<GpRegs> {
         match self.state.as_ref()? {
@@ -232,6 +322,7 @@ enum PtraceOperation {
     Syscall = 24,
     SetOptions = 0x4200,
     GetRegSet = 0x4204,
+    GetSyscallInfo = 0x420e,
 }
 
 impl TryFrom<i32> for PtraceOperation {
@@ -246,6 +337,7 @@ impl TryFrom<i32> for PtraceOperation {
             24 => Ok(PtraceOperation::Syscall),
             0x4200 => Ok(PtraceOperation::SetOptions),
             0x4204 => Ok(PtraceOperation::GetRegSet),
+            0x420e => Ok(PtraceOperation::GetSyscallInfo),
             // TODO: Should be EIO
             _ => Err(KernelError::InvalidValue),
         }
@@ -284,6 +376,37 @@ pub async fn ptrace_stop(ctx: &ProcessCtx, point: TracePoint) -> bool {
     .await
 }
 
+/// Check whether `tracer` may inspect and modify the state of `target`.
+///
+/// Access is granted within a thread group, when the tracer's real IDs match
+/// all of the target's real, effective and saved IDs, or when the tracer holds
+/// `CAP_SYS_PTRACE`.
+pub fn ptrace_may_access(tracer: &Task, target: &Task) -> Result<()> {
+    if tracer.process.tgid == target.process.tgid {
+        return Ok(());
+    }
+
+    let tracer_creds = tracer.creds.lock_save_irq().clone();
+    let target_creds = target.creds.lock_save_irq().clone();
+
+    let uid = tracer_creds.uid();
+    let gid = tracer_creds.gid();
+
+    if [target_creds.uid(), target_creds.euid(), target_creds.suid()]
+        .iter()
+        .all(|&x| x == uid)
+        && [target_creds.gid(), target_creds.egid(), target_creds.sgid()]
+            .iter()
+            .all(|&x| x == gid)
+    {
+        return Ok(());
+    }
+
+    tracer_creds
+        .caps()
+        .check_capable(CapabilitiesFlags::CAP_SYS_PTRACE)
+}
+
 pub async fn sys_ptrace(ctx: &ProcessCtx, op: i32, pid: PidT, addr: UA, data: UA) -> Result<usize> {
     let op = PtraceOperation::try_from(op)?;
 
@@ -338,6 +461,53 @@ pub async fn sys_ptrace(ctx: &ProcessCtx, op: i32, pid: PidT, addr: UA, data: UA
                 Err(KernelError::NoProcess)
             }
         }
+        PtraceOperation::GetSyscallInfo => {
+            let (regs, hit_point) = {
+                let ptrace = target_task.ptrace.lock_save_irq();
+
+                (ptrace.regset(), ptrace.hit_point())
+            };
+
+            let regs = regs.ok_or(KernelError::NoProcess)?;
+            let (info, len) = PtraceSyscallInfo::new(&regs, hit_point);
+
+            copy_to_user_slice(&info.as_bytes()[..min(len, addr.value())], data).await?;
+
+            Ok(len)
+        }
+        PtraceOperation::PeekText | PtraceOperation::PeekData => {
+            ptrace_may_access(ctx.shared(), &target_task)?;
+
+            let mut word = [0u8; size_of::<u64>()];
+            let mut copied = 0;
+
+            // The word may straddle two pages.
+            while copied < word.len() {
+                let va = addr.add_bytes(copied);
+                let len = min(PAGE_SIZE - va.page_offset(), word.len() - copied);
+
+                // SAFETY: The page is only read from.
+                let page = unsafe { target_task.get_page(va, AccessKind::Read).await? };
+
+                let src = page
+                    .region()
+                    .start_address()
+                    .to_va::<PageOffsetTranslator>()
+                    .cast::<u8>()
+                    .add_bytes(va.page_offset())
+                    .as_ptr();
+
+                // SAFETY: `len` doesn't run past the end of the page, which is
+                // pinned while `page` is held.
+                word[copied..copied + len]
+                    .copy_from_slice(unsafe { slice::from_raw_parts(src, len) });
+                copied += len;
+            }
+
+            copy_to_user(data.cast::<u64>(), u64::from_ne_bytes(word)).await?;
+
+            Ok(0)
+        }
         PtraceOperation::SetOptions => {
             let opts = PTraceOptions::from_bits_truncate(data.value());
             let mut ptrace = target_task.ptrace.lock_save_irq();
@@ -395,7 +565,5 @@ pub async fn sys_ptrace(ctx: &ProcessCtx, op: i32, pid: PidT, addr: UA, data: UA
 
             Ok(0)
         }
-        // TODO: Wrong error
-        _ => Err(KernelError::InvalidValue),
     }
 }
@@ -1,7 +1,7 @@
 use super::thread_group::signal::{InterruptResult, Interruptable};
 use crate::{
-    clock::timespec::TimeSpec,
-    drivers::timer::{now, sleep},
+    clock::{ClockId, realtime::date, timespec::TimeSpec},
+    drivers::timer::{now, sleep, uptime},
     memory::uaccess::copy_to_user,
 };
 use core::time::Duration;
@@ -27,10 +27,33 @@ pub async fn sys_nanosleep(rqtp: TUA<TimeSpec>, rmtp: TUA<TimeSpec>) -> Result<u
 }
 
 pub async fn sys_clock_nanosleep(
-    _clock_id: i32,
-    _flags: u32,
+    clock_id: i32,
+    flags: u32,
     rqtp: TUA<TimeSpec>,
     rmtp: TUA<TimeSpec>,
 ) -> Result<usize> {
-    sys_nanosleep(rqtp, rmtp).await
+    const TIMER_ABSTIME: u32 = 1;
+
+    let clock_now: fn() -> Duration =
+        match ClockId::try_from(clock_id).map_err(|_| KernelError::InvalidValue)? {
+            ClockId::Realtime => date,
+            ClockId::Monotonic | ClockId::BootTime => uptime,
+            _ => return Err(KernelError::NotSupported),
+        };
+
+    if flags & TIMER_ABSTIME == 0 {
+        return sys_nanosleep(rqtp, rmtp).await;
+    }
+
+    let deadline: Duration = TimeSpec::copy_from_user(rqtp).await?.into();
+
+    // There's no remaining time to report for an absolute sleep: restarting
+    // it waits for the same deadline.
+    match sleep(deadline.saturating_sub(clock_now()))
+        .interruptable()
+        .await
+    {
+        InterruptResult::Interrupted => Err(KernelError::Interrupted),
+        InterruptResult::Uninterrupted(()) => Ok(0),
+    }
 }
@@ -6,7 +6,7 @@ use crate::{
         sched_task::{Work, state::TaskState},
         waker::create_waker,
     },
-    sync::SpinLock,
+    sync::{CondVar, SpinLock},
 };
 use alloc::{
     collections::btree_map::BTreeMap,
@@ -18,7 +18,7 @@ use core::sync::atomic::AtomicUsize;
 use core::{fmt::Display, sync::atomic::Ordering};
 use libkernel::fs::pathbuf::PathBuf;
 use pid::PidT;
-use rsrc_lim::ResourceLimits;
+use rsrc_lim::{ResourceLimits, RlimitId};
 use signal::{SigId, SigSet, SignalActionState};
 use wait::Notifiers;
 
@@ -103,6 +103,8 @@ pub struct ThreadGroup {
     pub pgid: SpinLock<Pgid>,
     pub sid: SpinLock<Sid>,
     pub state: SpinLock<ProcessState>,
+    /// Set once the process has exited, for pidfds to wait on.
+    pub exited: CondVar<bool>,
     pub umask: SpinLock<u32>,
     pub parent: SpinLock<Option<Weak<ThreadGroup>>>,
     pub children: SpinLock<BTreeMap<Tgid, Arc<ThreadGroup>>>,
@@ -151,6 +153,11 @@ impl ThreadGroup {
         TG_LIST.lock_save_irq().get(&id).and_then(|x| x.upgrade())
     }
 
+    /// Returns the soft `RLIMIT_STACK`, which bounds how far the stack may grow.
+    pub fn stack_limit(&self) -> usize {
+        self.rsrc_lim.lock_save_irq().get(RlimitId::STACK).rlim_cur as usize
+    }
+
     pub fn notify_signal_waiters(&self) {
         let tasks: Vec<_> = self
             .tasks
@@ -2,7 +2,10 @@ use core::sync::atomic::AtomicUsize;
 
 use alloc::{collections::btree_map::BTreeMap, sync::Arc};
 
-use crate::{drivers::fs::cgroup, sync::SpinLock};
+use crate::{
+    drivers::fs::cgroup,
+    sync::{CondVar, SpinLock},
+};
 
 use super::{
     Pgid, ProcessState, Sid, TG_LIST, Tgid, ThreadGroup,
@@ -70,6 +73,7 @@ impl ThreadGroupBuilder {
             ),
             sid: SpinLock::new(Sid(self.tgid.value())),
             parent: SpinLock::new(self.parent.as_ref().map(Arc::downgrade)),
+            exited: CondVar::new(false),
             umask: SpinLock::new(self.umask.unwrap_or(0)),
             children: SpinLock::new(BTreeMap::new()),
             signals: self
@@ -215,6 +215,20 @@ impl Display for SigId {
     }
 }
 
+/// `si_code` for `SIGSEGV`: the address isn't mapped.
+pub const SEGV_MAPERR: i32 = 1;
+/// `si_code` for `SIGSEGV`: the mapping doesn't permit the access.
+pub const SEGV_ACCERR: i32 = 2;
+
+/// Details of a synchronous fault, reported to a `SA_SIGINFO` handler via
+/// `siginfo_t`.
+#[derive(Clone, Copy, Debug)]
+pub struct FaultInfo {
+    pub signal: SigId,
+    pub code: i32,
+    pub addr: UA,
+}
+
 #[derive(Clone, Copy, Debug)]
 pub enum SigActionState {
     Ignore,
@@ -18,6 +18,7 @@ use super::AltSigStack;
 pub struct UserSigAltStack {
     ss_sp: UA,
     ss_flags: SigAltStackFlags,
+    _pad: i32,
     ss_size: usize,
 }
 
@@ -60,12 +61,14 @@ impl From<Option<AltSigStack>> for UserSigAltStack {
             Self {
                 ss_sp: value.range.start_address(),
                 ss_flags: flags,
+                _pad: 0,
                 ss_size: value.range.size(),
             }
         } else {
             Self {
                 ss_sp: UA::null(),
                 ss_flags: SigAltStackFlags::empty(),
+                _pad: 0,
                 ss_size: 0,
             }
         }
@@ -1,16 +1,19 @@
 use crate::arch::{Arch, ArchImpl};
-use crate::drivers::timer::now;
+use crate::drivers::timer::{now, schedule_preempt};
 #[cfg(feature = "smp")]
 use crate::interrupts::cpu_messenger::{Message, message_cpu};
 use crate::kernel::cpu_id::CpuId;
+use crate::kernel::trace::{TraceEvent, trace_event};
 use crate::process::owned::OwnedTask;
 use crate::sched::sched_task::{CPU_MASK_SIZE, CpuMask};
+use crate::sync::RCU;
 use crate::{per_cpu_private, per_cpu_shared, process::TASK_LIST};
 use alloc::{boxed::Box, sync::Arc, vec::Vec};
 use core::fmt::Debug;
-use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering, fence};
 use core::task::Waker;
 use core::time::Duration;
+use libkernel::sync::executor::{Executor, Priority, TaskHandle};
 use log::warn;
 use runqueue::RunQueue;
 use sched_task::{RunnableTask, Work};
@@ -24,7 +27,9 @@ pub mod syscalls;
 pub mod uspc_ret;
 pub mod waker;
 
-pub static NUM_CONTEXT_SWITCHES: AtomicUsize = AtomicUsize::new(0);
+per_cpu_shared! {
+    pub static NUM_CONTEXT_SWITCHES: AtomicUsize = || AtomicUsize::new(0);
+}
 
 #[derive(Debug, Default)]
 pub struct CpuStat<T>
@@ -64,10 +69,6 @@ per_cpu_shared! {
     pub static CPU_STAT: CpuStat<AtomicUsize> = CpuStat::default;
 }
 
-pub fn get_cpu_stat(cpu_id: CpuId) -> CpuStat<usize> {
-    CPU_STAT.get_by_cpu(cpu_id.value()).to_usize()
-}
-
 per_cpu_private! {
     static SCHED_STATE: SchedState = SchedState::new;
 }
@@ -76,6 +77,23 @@ per_cpu_shared! {
     pub static SHARED_SCHED_STATE: SharedSchedState = SharedSchedState::new;
 }
 
+/// Kernel tasks which don't belong to any user task, such as I/O completion
+/// and writeback.
+static KERNEL_EXECUTOR: Executor<ArchImpl> = Executor::with_ready_hook(kick_idle_cpu);
+
+/// The most kernel task polls made on each pass through the dispatcher, so
+/// that a burst of kernel work can't hold up userspace indefinitely.
+const KERNEL_TASK_BUDGET: usize = 16;
+
+/// When kernel tasks are left over after a pass through the dispatcher, how
+/// soon the CPU comes back for them. There is no periodic tick to do so.
+const KERNEL_TASK_RETRY: Duration = Duration::from_millis(1);
+
+/// The most consecutive polls of a task's kernel work before it is made to
+/// yield, so that a future which is always woken again (e.g. a busy socket)
+/// can't monopolise its CPU.
+pub const POLL_BUDGET: usize = 64;
+
 /// Default time-slice assigned to runnable tasks.
 const DEFAULT_TIME_SLICE: Duration = Duration::from_millis(4);
 
@@ -129,12 +147,101 @@ fn schedule() {
     // called without SCHED_STATE borrowed, e.g. closeing the other end of a
     // pipe.
     drop(deferred);
+
+    // No read-side critical section spans a task switch.
+    RCU.quiescent_state(CpuId::this().value());
 }
 
 pub fn spawn_kernel_work(ctx: &mut ProcessCtx, fut: impl Future<Output = ()> + 'static + Send) {
     ctx.task_mut().ctx.put_kernel_work(Box::pin(fut));
 }
 
+/// Spawns a free-standing kernel task with the given priority.
+///
+/// Unlike [`spawn_kernel_work`], the task doesn't run on behalf of a user
+/// task. It is polled whenever a CPU passes through the dispatcher, before
+/// picking the next user task to run.
+///
+/// The returned handle can be used to abort the task, e.g. when whatever it
+/// serves is torn down.
+pub fn spawn_kernel_task(
+    priority: Priority,
+    fut: impl Future<Output = ()> + 'static + Send,
+) -> TaskHandle<ArchImpl> {
+    KERNEL_EXECUTOR.spawn(priority, fut)
+}
+
+/// Polls ready kernel tasks, up to [`KERNEL_TASK_BUDGET`].
+pub fn run_kernel_tasks() {
+    if KERNEL_EXECUTOR.run(KERNEL_TASK_BUDGET) == KERNEL_TASK_BUDGET
+        && let Some(now) = now()
+    {
+        // If this CPU goes idle, make sure it still comes back. Should a
+        // task be picked instead, its preemption deadline replaces this.
+        schedule_preempt(now + KERNEL_TASK_RETRY);
+    }
+}
+
+/// Wakes an idle CPU to poll newly ready kernel tasks.
+///
+/// There's no periodic tick, so an idle CPU otherwise sleeps until its next
+/// timer event, however long that is. Busy CPUs poll kernel tasks the next
+/// time they pass through the dispatcher.
+fn kick_idle_cpu() {
+    // Pairs with the fence in `idle_with_kernel_tasks`: either the idle CPU
+    // sees the newly ready task, or we see that it's idle.
+    fence(Ordering::SeqCst);
+
+    // An idle CPU only runs kernel code in an interrupt handler or the
+    // dispatcher, and polls ready tasks before going back to sleep.
+    if SHARED_SCHED_STATE.get().idle.load(Ordering::Relaxed) {
+        return;
+    }
+
+    #[cfg(feature = "smp")]
+    if let Some(cpu) = (0..ArchImpl::cpu_count()).find(|&cpu| {
+        SHARED_SCHED_STATE
+            .get_by_cpu(cpu)
+            .idle
+            .load(Ordering::Relaxed)
+    }) {
+        // Fails only before the messenger is up, when there's no idle CPU to
+        // wake anyway.
+        let _ = message_cpu(CpuId::from_value(cpu), Message::Kick);
+    }
+}
+
+/// Returns `true` if this CPU has picked its idle task while kernel tasks are
+/// ready, i.e. one was woken after the CPU last polled them but before it
+/// was marked idle. The CPU should poll them rather than sleep.
+pub fn idle_with_kernel_tasks() -> bool {
+    if !SHARED_SCHED_STATE.get().idle.load(Ordering::Relaxed) {
+        return false;
+    }
+
+    fence(Ordering::SeqCst);
+
+    KERNEL_EXECUTOR.has_ready()
+}
+
+/// Makes the current task yield for having exhausted its [`POLL_BUDGET`].
+///
+/// Repeat offenders are logged, at exponentially decreasing frequency.
+pub fn yield_exhausted_budget() {
+    let work = current_work();
+    let exhaustions = work.budget_exhaustions.fetch_add(1, Ordering::Relaxed) + 1;
+
+    if exhaustions.is_power_of_two() {
+        warn!(
+            "Task {} ({}) has exhausted its poll budget {exhaustions} times",
+            work.tid.value(),
+            work.comm.lock_save_irq().as_str()
+        );
+    }
+
+    SCHED_STATE.borrow_mut().run_q.yield_current();
+}
+
 #[cfg(feature = "smp")]
 fn get_best_cpu(cpu_mask: CpuMask) -> CpuId {
     let r = 0..ArchImpl::cpu_count();
@@ -162,6 +269,30 @@ fn get_best_cpu(cpu_mask: CpuMask) -> CpuId {
         })
 }
 
+/// Moves the current task to another CPU if its affinity no longer allows it
+/// to run on this one.
+///
+/// Must only be called between polls of the task's work, as the task may
+/// start running on its new CPU as soon as this returns.
+pub fn migrate_current() {
+    let work = SCHED_STATE.borrow_mut().run_q.take_disallowed_current();
+
+    if let Some(work) = work {
+        insert_work_cross_cpu(work);
+    }
+}
+
+/// Returns the CPU affinity of the current task.
+pub fn current_cpu_mask() -> CpuMask {
+    SCHED_STATE.borrow().run_q.current().cpu_mask
+}
+
+/// Sets the CPU affinity of the current task. Should it exclude this CPU,
+/// the task is moved the next time it passes through the scheduler.
+pub fn set_current_cpu_mask(mask: CpuMask) {
+    SCHED_STATE.borrow_mut().run_q.current_mut().cpu_mask = mask;
+}
+
 /// Insert the given task onto a CPU's run queue.
 pub fn insert_work(work: Arc<Work>) {
     SCHED_STATE.borrow_mut().run_q.add_work(work);
@@ -246,18 +377,45 @@ impl SchedState {
             current.work.reset_last_account(now_inst);
         }
 
-        self.run_q.schedule(now_inst)
+        // The idle tasks' tids are their CPU's id, so are reported as 0.
+        let traced_tid = |run_q: &RunQueue| {
+            let work = &run_q.current().work;
+
+            if work.task.is_idle_task() {
+                0
+            } else {
+                work.tid.value()
+            }
+        };
+
+        let prev = traced_tid(&self.run_q);
+        let deferred = self.run_q.schedule(now_inst);
+        let next = traced_tid(&self.run_q);
+
+        if prev != next {
+            trace_event(TraceEvent::SchedSwitch, [prev as u64, next as u64, 0, 0]);
+        }
+
+        SHARED_SCHED_STATE.get().idle.store(
+            self.run_q.current().work.task.is_idle_task(),
+            Ordering::Relaxed,
+        );
+
+        deferred
     }
 }
 
 pub struct SharedSchedState {
     pub total_runq_weight: AtomicU64,
+    /// Whether the CPU is running its idle task.
+    pub idle: AtomicBool,
 }
 
 impl SharedSchedState {
     pub fn new() -> Self {
         Self {
             total_runq_weight: AtomicU64::new(0),
+            idle: AtomicBool::new(false),
         }
     }
 }
@@ -278,6 +436,8 @@ pub fn sched_init() {
 
     insert_work(init_work);
 
+    RCU.cpu_online(CpuId::this().value());
+
     schedule();
 }
 
@@ -285,6 +445,8 @@ pub fn sched_init_secondary() {
     // Force update_global_least_tasked_cpu_info
     SCHED_STATE.borrow().update_global_least_tasked_cpu_info();
 
+    RCU.cpu_online(CpuId::this().value());
+
     schedule();
 }
 
@@ -292,6 +454,15 @@ pub fn current_work() -> Arc<Work> {
     SCHED_STATE.borrow().run_q.current().work.clone()
 }
 
+/// Like [`current_work`], but returns `None` rather than panicking if the
+/// scheduler state is already borrowed, e.g. when called from a fault taken
+/// inside the scheduler.
+pub fn try_current_work() -> Option<Arc<Work>> {
+    SCHED_STATE
+        .try_borrow()
+        .map(|state| state.run_q.current().work.clone())
+}
+
 pub fn current_work_waker() -> Waker {
     create_waker(current_work())
 }
@@ -72,6 +72,9 @@ pub struct RunQueue {
     pub(super) running_task: Option<RunnableTask>,
     v_clock: VClock,
     idle: RunnableTask,
+    /// Set by [`Self::yield_current`] to requeue the running task at the next
+    /// schedule, even if it has time left in its slice.
+    yield_requested: bool,
 }
 
 impl RunQueue {
@@ -85,6 +88,7 @@ impl RunQueue {
             running_task: None,
             v_clock: VClock::new(),
             idle,
+            yield_requested: false,
         }
     }
 
@@ -99,6 +103,7 @@ impl RunQueue {
         let mut prev_task = ptr::null();
         let mut next_task = None;
         let mut deferred_drops: Vec<RunnableTask> = Vec::new();
+        let yielded = core::mem::take(&mut self.yield_requested);
 
         if let Some(mut cur_task) = self.running_task.take() {
             prev_task = Arc::as_ptr(&cur_task.work);
@@ -108,6 +113,10 @@ impl RunQueue {
                     if cur_task.tick(now) {
                         // Deadline exceeded — requeue for the next time slice.
                         self.enqueue(cur_task);
+                    } else if yielded {
+                        // Asked to yield — requeue behind other eligible tasks.
+                        cur_task.yield_slice();
+                        self.enqueue(cur_task);
                     } else {
                         // Still has budget — keep running.
                         next_task = Some(cur_task);
@@ -140,7 +149,7 @@ impl RunQueue {
 
             if Arc::as_ptr(&next_task.work) != prev_task {
                 // If we scheduled a different task than before, context switch.
-                NUM_CONTEXT_SWITCHES.fetch_add(1, Ordering::Relaxed);
+                NUM_CONTEXT_SWITCHES.add(1);
 
                 next_task.switch_context();
 
@@ -225,6 +234,37 @@ impl RunQueue {
         self.enqueue(new_task);
     }
 
+    /// Takes the running task off this run-queue if its affinity no longer
+    /// includes this CPU, returning it so it can be placed on one that it
+    /// does.
+    pub fn take_disallowed_current(&mut self) -> Option<Arc<Work>> {
+        let cpu = ArchImpl::id();
+        let cur_task = self.running_task.as_ref()?;
+
+        if cur_task.cpu_mask[cpu / 8] & (1 << (cpu % 8)) != 0
+            || !matches!(
+                cur_task.work.state.load(Ordering::Acquire),
+                TaskState::Running | TaskState::Woken
+            )
+        {
+            return None;
+        }
+
+        let mut cur_task = self.running_task.take()?;
+        cur_task.sched_data.last_cpu = cpu;
+        self.total_weight = self.total_weight.saturating_sub(cur_task.weight() as u64);
+
+        // Dropping the runnable task puts its scheduler data back, ready for
+        // the run-queue it's moved to.
+        Some(cur_task.work.clone())
+    }
+
+    /// Requeues the running task at the next schedule, rather than letting it
+    /// run out its slice.
+    pub fn yield_current(&mut self) {
+        self.yield_requested = true;
+    }
+
     pub fn weight(&self) -> u64 {
         self.total_weight
     }
@@ -1,6 +1,7 @@
 use core::{
     cmp::Ordering,
     ops::{Deref, DerefMut},
+    sync::atomic::AtomicUsize,
 };
 
 use super::{DEFAULT_TIME_SLICE, SCHED_WEIGHT_BASE, VT_FIXED_SHIFT};
@@ -55,6 +56,9 @@ pub struct Work {
     pub task: Box<OwnedTask>,
     pub state: TaskStateMachine,
     pub sched_data: SpinLock<Option<SchedulerData>>,
+    /// The number of times this task has been made to yield for exhausting
+    /// its poll budget.
+    pub budget_exhaustions: AtomicUsize,
 }
 
 impl Deref for Work {
@@ -79,6 +83,7 @@ impl Work {
             task,
             state: TaskStateMachine::new(),
             sched_data: SpinLock::new(Some(sched_data)),
+            budget_exhaustions: AtomicUsize::new(0),
         })
     }
 
@@ -133,6 +138,12 @@ impl RunnableTask {
         self.v_deadline = self.v_eligible + v_delta;
     }
 
+    /// Give up the rest of the current slice, pushing the virtual deadline
+    /// back so that other eligible tasks run first.
+    pub fn yield_slice(&mut self) {
+        self.replenish_deadline();
+    }
+
     /// Update accounting info for this task given the latest time. Returns
     /// `true` when we should try to reschedule another task, `false` otherwise.
     pub fn tick(&mut self, now: Instant) -> bool {
@@ -3,7 +3,7 @@ use crate::memory::uaccess::{copy_from_user_slice, copy_to_user_slice};
 use crate::process::thread_group::pid::PidT;
 use crate::sched::sched_task::CPU_MASK_SIZE;
 use crate::sched::syscall_ctx::ProcessCtx;
-use crate::sched::{current_work, schedule};
+use crate::sched::{current_cpu_mask, schedule, set_current_cpu_mask};
 use alloc::vec;
 use libkernel::memory::address::UA;
 
@@ -18,16 +18,12 @@ pub async fn sys_sched_getaffinity(
     size: usize,
     mask: UA,
 ) -> libkernel::error::Result<usize> {
-    let task = if pid == 0 {
-        current_work()
+    let cpu_mask = if pid == 0 {
+        current_cpu_mask()
     } else {
         // TODO: Support getting affinity of other tasks if PERM_NICE
         return Err(libkernel::error::KernelError::InvalidValue);
     };
-    let cpu_mask = {
-        let sched_data = task.sched_data.lock_save_irq();
-        sched_data.as_ref().unwrap().cpu_mask
-    };
     let mut cpu_mask: &[u8] = &cpu_mask;
     if CPU_MASK_SIZE > size {
         cpu_mask = &cpu_mask[..size];
@@ -44,13 +40,10 @@ pub async fn sys_sched_setaffinity(
 ) -> libkernel::error::Result<usize> {
     let mut cpu_set = vec![0u8; size];
     copy_from_user_slice(mask, cpu_set.as_mut_slice()).await?;
-    let task = if pid == 0 {
-        current_work()
-    } else {
+    if pid != 0 {
         // TODO: Support setting affinity of other tasks if PERM_NICE
         return Err(libkernel::error::KernelError::InvalidValue);
-    };
-    let mut sched_data = task.sched_data.lock_save_irq();
+    }
     if CPU_MASK_SIZE > size {
         return Err(libkernel::error::KernelError::InvalidValue);
     }
@@ -68,7 +61,6 @@ pub async fn sys_sched_setaffinity(
     if !any_true {
         return Err(libkernel::error::KernelError::InvalidValue);
     }
-    sched_data.as_mut().unwrap().cpu_mask = cpu_set.try_into().unwrap();
-    // TODO: apply the new affinity immediately if the current CPU is no longer in the set
+    set_current_cpu_mask(cpu_set.try_into().unwrap());
     Ok(0)
 }
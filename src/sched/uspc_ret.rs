@@ -1,4 +1,7 @@
-use super::{current_work, current_work_waker, schedule};
+use super::{
+    POLL_BUDGET, current_work, current_work_waker, idle_with_kernel_tasks, migrate_current,
+    run_kernel_tasks, schedule, yield_exhausted_budget,
+};
 use crate::{
     arch::{Arch, ArchImpl},
     process::{
@@ -82,6 +85,8 @@ fn try_sleep_current() -> bool {
 /// the scheduler or task management.
 pub fn dispatch_userspace_task(frame: *mut UserCtx) {
     let mut state = State::PickNewTask;
+    // Consecutive polls of the current task's work, see `POLL_BUDGET`.
+    let mut polls = 0;
 
     // SAFETY: Access is exclusive since we're not polling any futures.
     let mut ctx = unsafe { ProcessCtx::from_current() };
@@ -89,13 +94,33 @@ pub fn dispatch_userspace_task(frame: *mut UserCtx) {
     'dispatch: loop {
         match state {
             State::PickNewTask => {
+                // Give free-standing kernel tasks a chance to run first.
+                run_kernel_tasks();
+
+                // Move the task elsewhere if it may no longer run here.
+                migrate_current();
+
                 // Pick a new task, potentially context switching to a new task.
                 schedule();
                 // SAFETY: As above.
                 ctx = unsafe { ProcessCtx::from_current() };
+
+                if idle_with_kernel_tasks() {
+                    continue;
+                }
+
+                polls = 0;
                 state = State::ProcessKernelWork;
             }
             State::ProcessKernelWork => {
+                if polls >= POLL_BUDGET {
+                    // The task keeps being woken. Its work stays put, so it
+                    // carries on from where it left off when next scheduled.
+                    yield_exhausted_budget();
+                    state = State::PickNewTask;
+                    continue;
+                }
+
                 // First, let's handle signals. If there is any scheduled signal
                 // work (this has to be async to handle faults, etc).
                 let signal_work = ctx.task_mut().ctx.take_signal_work();
@@ -106,6 +131,8 @@ pub fn dispatch_userspace_task(frame: *mut UserCtx) {
                         panic!("Signal processing for idle task");
                     }
 
+                    polls += 1;
+
                     match signal_work
                         .as_mut()
                         .poll(&mut core::task::Context::from_waker(&current_work_waker()))
@@ -145,6 +172,8 @@ pub fn dispatch_userspace_task(frame: *mut UserCtx) {
                         panic!("Idle process should never have kernel work");
                     }
 
+                    polls += 1;
+
                     match kern_work
                         .as_mut()
                         .poll(&mut core::task::Context::from_waker(&current_work_waker()))
@@ -0,0 +1,31 @@
+//! Lock-ordering validation, enabled with the `lockdep` feature. See
+//! [`libkernel::sync::lockdep`].
+//!
+//! Sleeping locks are attributed to the running [`Work`], identified by its
+//! address.
+//!
+//! [`Work`]: crate::sched::sched_task::Work
+
+use crate::{
+    arch::{Arch, ArchImpl},
+    sched::try_current_work,
+};
+use alloc::sync::Arc;
+use libkernel::sync::lockdep::{self, LockdepHooks};
+
+static HOOKS: LockdepHooks = LockdepHooks {
+    capture_backtrace: ArchImpl::capture_backtrace,
+    current_task,
+};
+
+fn current_task() -> Option<usize> {
+    try_current_work().map(|work| Arc::as_ptr(&work) as usize)
+}
+
+/// Starts validating lock usage.
+///
+/// This must be called after the scheduler has been initialised, so that the
+/// running task can be identified.
+pub fn init() {
+    lockdep::enable(&HOOKS);
+}
@@ -1,23 +1,101 @@
-use crate::arch::ArchImpl;
+use crate::{arch::ArchImpl, drivers::timer::sleep};
+use core::time::Duration;
+use libkernel::sync::{rcu::Rcu, timeout::Timeout};
 
+#[cfg(feature = "lockdep")]
+pub mod lockdep;
 pub mod per_cpu;
 
 pub type SpinLock<T> = libkernel::sync::spinlock::SpinLockIrq<T, ArchImpl>;
 pub type Mutex<T> = libkernel::sync::mutex::Mutex<T, ArchImpl>;
 pub type AsyncMutexGuard<'a, T> = libkernel::sync::mutex::AsyncMutexGuard<'a, T, ArchImpl>;
-#[expect(dead_code)]
 pub type Rwlock<T> = libkernel::sync::rwlock::Rwlock<T, ArchImpl>;
-#[expect(dead_code)]
 pub type AsyncRwlockReadGuard<'a, T> =
     libkernel::sync::rwlock::AsyncRwlockReadGuard<'a, T, ArchImpl>;
 #[expect(dead_code)]
 pub type AsyncRwlockWriteGuard<'a, T> =
     libkernel::sync::rwlock::AsyncRwlockWriteGuard<'a, T, ArchImpl>;
+pub type SeqLock<T> = libkernel::sync::seqlock::SeqLock<T, ArchImpl>;
 pub type OnceLock<T> = libkernel::sync::once_lock::OnceLock<T, ArchImpl>;
+pub type OnceCell<T> = libkernel::sync::once_cell::OnceCell<T, ArchImpl>;
 pub type CondVar<T> = libkernel::sync::condvar::CondVar<T, ArchImpl>;
+
+/// The kernel's RCU domain. The scheduler reports a quiescent state each time
+/// it switches tasks, and cpuidle marks a CPU idle while it sleeps.
+pub static RCU: Rcu<ArchImpl> = Rcu::new();
+
 // pub type Reciever<T> = libkernel::sync::mpsc::Reciever<T, ArchImpl>;
 // pub type Sender<T> = libkernel::sync::mpsc::Sender<T, ArchImpl>;
 
 // pub fn channel<T: Send>() -> (Sender<T>, Reciever<T>) {
 //     libkernel::sync::mpsc::channel()
 // }
+
+/// Mutex acquisition bounded by the system timer, so drivers needn't wait
+/// indefinitely on a contended resource.
+pub trait MutexTimeout<T: ?Sized> {
+    /// Acquires the lock, failing with [`Timeout`] if `duration` elapses
+    /// first.
+    async fn lock_timeout<'a>(
+        &'a self,
+        duration: Duration,
+    ) -> Result<AsyncMutexGuard<'a, T>, Timeout>
+    where
+        T: 'a;
+}
+
+impl<T: ?Sized> MutexTimeout<T> for Mutex<T> {
+    async fn lock_timeout<'a>(
+        &'a self,
+        duration: Duration,
+    ) -> Result<AsyncMutexGuard<'a, T>, Timeout>
+    where
+        T: 'a,
+    {
+        self.lock_before(sleep(duration)).await
+    }
+}
+
+/// Rwlock acquisition bounded by the system timer.
+pub trait RwlockTimeout<T: ?Sized> {
+    /// Acquires the lock for reading, failing with [`Timeout`] if `duration`
+    /// elapses first.
+    async fn read_timeout<'a>(
+        &'a self,
+        duration: Duration,
+    ) -> Result<AsyncRwlockReadGuard<'a, T>, Timeout>
+    where
+        T: 'a;
+
+    /// Acquires the lock for writing, failing with [`Timeout`] if `duration`
+    /// elapses first.
+    #[expect(dead_code)]
+    async fn write_timeout<'a>(
+        &'a self,
+        duration: Duration,
+    ) -> Result<AsyncRwlockWriteGuard<'a, T>, Timeout>
+    where
+        T: 'a;
+}
+
+impl<T: ?Sized> RwlockTimeout<T> for Rwlock<T> {
+    async fn read_timeout<'a>(
+        &'a self,
+        duration: Duration,
+    ) -> Result<AsyncRwlockReadGuard<'a, T>, Timeout>
+    where
+        T: 'a,
+    {
+        self.read_before(sleep(duration)).await
+    }
+
+    async fn write_timeout<'a>(
+        &'a self,
+        duration: Duration,
+    ) -> Result<AsyncRwlockWriteGuard<'a, T>, Timeout>
+    where
+        T: 'a,
+    {
+        self.write_before(sleep(duration)).await
+    }
+}
@@ -45,3 +45,35 @@ fn test_epoll_add_wait() {
     }
 }
 register_test!(test_epoll_add_wait);
+
+fn test_epoll_eventfd() {
+    unsafe {
+        let epfd = libc::epoll_create1(0);
+        assert!(epfd >= 0);
+
+        let efd = libc::eventfd(0, libc::EFD_NONBLOCK);
+        assert!(efd >= 0);
+
+        let mut ev = libc::epoll_event {
+            events: libc::EPOLLIN as u32,
+            u64: 7,
+        };
+        let res = libc::epoll_ctl(epfd, libc::EPOLL_CTL_ADD, efd, &mut ev);
+        assert_eq!(res, 0);
+
+        let mut events = [libc::epoll_event { events: 0, u64: 0 }; 1];
+        let res = libc::epoll_wait(epfd, events.as_mut_ptr(), 1, 0);
+        assert_eq!(res, 0);
+
+        let value = 1u64;
+        libc::write(efd, (&raw const value).cast(), 8);
+
+        let res = libc::epoll_wait(epfd, events.as_mut_ptr(), 1, 100);
+        assert_eq!(res, 1);
+        assert_eq!(events[0].u64, 7);
+
+        libc::close(efd);
+        libc::close(epfd);
+    }
+}
+register_test!(test_epoll_eventfd);
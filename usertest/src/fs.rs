@@ -254,6 +254,50 @@ fn test_write() {
 
 register_test!(test_write);
 
+fn test_read_write_partial_fault() {
+    let path = "/tmp/partial_fault_test";
+    let page_size = 4096;
+    fs::write(path, vec![0xaau8; 2 * page_size]).unwrap();
+    let c_path = CString::new(path).unwrap();
+
+    unsafe {
+        // A buffer whose last 100 bytes are followed by an unmapped page.
+        let map = libc::mmap(
+            std::ptr::null_mut(),
+            2 * page_size,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+            -1,
+            0,
+        );
+        assert_ne!(map, libc::MAP_FAILED, "mmap failed");
+        assert_eq!(libc::munmap(map.byte_add(page_size), page_size), 0);
+        let buf = map.byte_add(page_size - 100);
+
+        let fd = libc::open(c_path.as_ptr(), libc::O_RDWR);
+        assert!(fd >= 0, "open failed");
+
+        // Reads and writes that fault part-way through are short.
+        assert_eq!(libc::read(fd, buf, page_size), 100);
+        assert_eq!(*(buf as *const u8), 0xaa);
+        assert_eq!(libc::write(fd, buf, page_size), 100);
+
+        // Nothing can be copied from a completely unmapped buffer.
+        assert_eq!(libc::read(fd, map.byte_add(page_size), page_size), -1);
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::EFAULT)
+        );
+
+        libc::close(fd);
+        libc::munmap(map, page_size);
+    }
+
+    fs::remove_file(path).unwrap();
+}
+
+register_test!(test_read_write_partial_fault);
+
 fn test_link() {
     let path = "/tmp/link_test";
     let link = "/tmp/link_test_link";
@@ -284,13 +328,175 @@ fn test_link() {
         if stat_linkbuf.assume_init().st_ino != stat_targetbuf.assume_init().st_ino {
             panic!("link failed");
         }
+        assert_eq!(stat_linkbuf.assume_init().st_nlink, 2);
+
+        // Links can't reach into another filesystem.
+        let c_proc = CString::new("/proc/link_test").unwrap();
+        assert_eq!(libc::link(c_path.as_ptr(), c_proc.as_ptr()), -1);
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::EXDEV)
+        );
     }
     fs::remove_file(path).expect("Failed to delete file");
+    unsafe {
+        let ret = libc::stat(c_link.as_ptr(), stat_linkbuf.as_mut_ptr());
+        assert_eq!(ret, 0);
+        assert_eq!(stat_linkbuf.assume_init().st_nlink, 1);
+    }
     fs::remove_file(link).expect("Failed to delete link");
 }
 
 register_test!(test_link);
 
+fn test_tmpfile() {
+    let link = "/tmp/tmpfile_test_link";
+    let c_dir = CString::new("/tmp").unwrap();
+    let c_link = CString::new(link).unwrap();
+    let c_empty = CString::new("").unwrap();
+    let mut statbuf = MaybeUninit::uninit();
+
+    unsafe {
+        // It has to be opened for writing.
+        assert_eq!(
+            libc::open(c_dir.as_ptr(), libc::O_TMPFILE | libc::O_RDONLY, 0o600),
+            -1
+        );
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::EINVAL)
+        );
+
+        let fd = libc::open(c_dir.as_ptr(), libc::O_TMPFILE | libc::O_RDWR, 0o600);
+        if fd < 0 {
+            panic!("open failed");
+        }
+        assert_eq!(libc::write(fd, b"tmpfile".as_ptr().cast(), 7), 7);
+        assert_eq!(libc::fstat(fd, statbuf.as_mut_ptr()), 0);
+        assert_eq!(statbuf.assume_init().st_nlink, 0);
+
+        let ret = libc::linkat(
+            fd,
+            c_empty.as_ptr(),
+            libc::AT_FDCWD,
+            c_link.as_ptr(),
+            libc::AT_EMPTY_PATH,
+        );
+        if ret < 0 {
+            panic!("linkat failed");
+        }
+        assert_eq!(libc::fstat(fd, statbuf.as_mut_ptr()), 0);
+        assert_eq!(statbuf.assume_init().st_nlink, 1);
+        libc::close(fd);
+
+        // O_EXCL keeps it from ever being linked.
+        let fd = libc::open(
+            c_dir.as_ptr(),
+            libc::O_TMPFILE | libc::O_RDWR | libc::O_EXCL,
+            0o600,
+        );
+        if fd < 0 {
+            panic!("open failed");
+        }
+        let c_other = CString::new("/tmp/tmpfile_test_other").unwrap();
+        assert_eq!(
+            libc::linkat(
+                fd,
+                c_empty.as_ptr(),
+                libc::AT_FDCWD,
+                c_other.as_ptr(),
+                libc::AT_EMPTY_PATH,
+            ),
+            -1
+        );
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::ENOENT)
+        );
+        libc::close(fd);
+    }
+
+    assert_eq!(fs::read(link).unwrap(), b"tmpfile");
+    fs::remove_file(link).expect("Failed to delete link");
+}
+
+register_test!(test_tmpfile);
+
+fn test_statfs() {
+    let c_path = CString::new("/tmp").unwrap();
+    let mut statbuf = MaybeUninit::uninit();
+
+    unsafe {
+        assert_eq!(libc::statfs(c_path.as_ptr(), statbuf.as_mut_ptr()), 0);
+        let before = statbuf.assume_init();
+        assert_eq!(before.f_type, 0x01021994);
+        assert_eq!(before.f_bsize, 4096);
+        assert_eq!(before.f_namelen, 255);
+        assert!(before.f_blocks > 0);
+        assert!(before.f_bfree <= before.f_blocks);
+
+        // Filling a file takes blocks away from what's free.
+        let path = "/tmp/statfs_test";
+        fs::write(path, vec![1u8; 16 * 4096]).expect("Failed to write file");
+        let fd = libc::open(c_path.as_ptr(), libc::O_RDONLY | libc::O_DIRECTORY);
+        assert_eq!(libc::fstatfs(fd, statbuf.as_mut_ptr()), 0);
+        libc::close(fd);
+        let after = statbuf.assume_init();
+        assert!(after.f_blocks - after.f_bfree >= before.f_blocks - before.f_bfree + 16);
+
+        fs::remove_file(path).expect("Failed to delete file");
+    }
+}
+
+register_test!(test_statfs);
+
+fn test_block_device() {
+    const BLKGETSIZE64: u32 = 0x80081272;
+    const BLOCK_EXT_MAJOR: u32 = 259;
+
+    // The initrd holds the root filesystem.
+    let c_path = CString::new("/dev/ram0").unwrap();
+    let mut statbuf = MaybeUninit::uninit();
+    let mut size = 0u64;
+    let mut magic = [0u8; 2];
+
+    unsafe {
+        assert_eq!(libc::stat(c_path.as_ptr(), statbuf.as_mut_ptr()), 0);
+        let stat = statbuf.assume_init();
+        assert_eq!(stat.st_mode & libc::S_IFMT, libc::S_IFBLK);
+        assert_eq!(libc::major(stat.st_rdev), BLOCK_EXT_MAJOR);
+
+        let fd = libc::open(c_path.as_ptr(), libc::O_RDONLY);
+        if fd < 0 {
+            panic!("open failed");
+        }
+        assert_eq!(libc::ioctl(fd, BLKGETSIZE64 as _, &mut size), 0);
+        assert!(size > 0);
+        assert_eq!(libc::lseek(fd, 0, libc::SEEK_END), size as i64);
+
+        // The ext4 superblock's magic number.
+        assert_eq!(
+            libc::pread(fd, magic.as_mut_ptr().cast(), 2, 1024 + 0x38),
+            2
+        );
+        assert_eq!(u16::from_le_bytes(magic), 0xef53);
+
+        // Reading stops at the end of the device.
+        assert_eq!(
+            libc::pread(fd, magic.as_mut_ptr().cast(), 2, size as i64),
+            0
+        );
+        libc::close(fd);
+    }
+
+    // sysfs gives the size in 512-byte sectors.
+    let sectors =
+        std::fs::read_to_string("/sys/block/ram0/size").expect("read /sys/block/ram0/size");
+    assert_eq!(sectors.trim().parse::<u64>().unwrap() * 512, size);
+}
+
+register_test!(test_block_device);
+
 fn test_symlink() {
     use std::fs::{self, File};
     use std::io::{Read, Write};
@@ -332,6 +538,88 @@ fn test_symlink() {
 
 register_test!(test_symlink);
 
+fn test_symlink_resolution() {
+    let c = |s: &str| CString::new(s).unwrap();
+    let mut buffer = [0u8; 64];
+
+    fs::create_dir("/tmp/symlink_dir").expect("Failed to create directory");
+
+    unsafe {
+        // A relative symlink to the directory, and a dangling one in it.
+        assert_eq!(
+            libc::symlink(
+                c("symlink_dir").as_ptr(),
+                c("/tmp/symlink_dir_link").as_ptr()
+            ),
+            0
+        );
+        assert_eq!(
+            libc::symlink(
+                c("missing").as_ptr(),
+                c("/tmp/symlink_dir/dangling").as_ptr()
+            ),
+            0
+        );
+
+        // The directory's link is followed, but not the last one.
+        let ret = libc::readlink(
+            c("/tmp/symlink_dir_link/dangling").as_ptr(),
+            buffer.as_mut_ptr().cast(),
+            buffer.len(),
+        );
+        assert_eq!(ret, 7);
+        assert_eq!(&buffer[..7], b"missing");
+
+        // A dangling symlink still takes up its name.
+        assert_eq!(
+            libc::symlink(c("other").as_ptr(), c("/tmp/symlink_dir/dangling").as_ptr()),
+            -1
+        );
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::EEXIST)
+        );
+
+        // Only symlinks can be read.
+        assert_eq!(
+            libc::readlink(
+                c("/tmp/symlink_dir").as_ptr(),
+                buffer.as_mut_ptr().cast(),
+                buffer.len()
+            ),
+            -1
+        );
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::EINVAL)
+        );
+
+        // A symlink which points at itself can't be followed.
+        assert_eq!(
+            libc::symlink(
+                c("/tmp/symlink_loop").as_ptr(),
+                c("/tmp/symlink_loop").as_ptr()
+            ),
+            0
+        );
+        assert_eq!(
+            libc::open(c("/tmp/symlink_loop").as_ptr(), libc::O_RDONLY),
+            -1
+        );
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::ELOOP)
+        );
+    }
+
+    fs::remove_file("/tmp/symlink_loop").expect("Failed to delete link");
+    fs::remove_file("/tmp/symlink_dir/dangling").expect("Failed to delete link");
+    fs::remove_file("/tmp/symlink_dir_link").expect("Failed to delete link");
+    fs::remove_dir("/tmp/symlink_dir").expect("Failed to delete directory");
+}
+
+register_test!(test_symlink_resolution);
+
 fn test_rename() {
     use std::fs::{self, File};
     use std::io::{Read, Write};
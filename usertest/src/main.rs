@@ -61,6 +61,50 @@ fn test_clock_sleep() {
 
 register_test!(test_clock_sleep);
 
+fn test_clock_nanosleep_abstime() {
+    const SLEEP_NS: i64 = 20_000_000;
+
+    unsafe {
+        let mut res = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        assert_eq!(libc::clock_getres(libc::CLOCK_MONOTONIC, &mut res), 0);
+        assert!(res.tv_sec == 0 && res.tv_nsec > 0 && res.tv_nsec <= 1000);
+
+        let mut start = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut start);
+
+        let mut deadline = start;
+        deadline.tv_nsec += SLEEP_NS;
+        if deadline.tv_nsec >= 1_000_000_000 {
+            deadline.tv_sec += 1;
+            deadline.tv_nsec -= 1_000_000_000;
+        }
+
+        let ret = libc::clock_nanosleep(
+            libc::CLOCK_MONOTONIC,
+            libc::TIMER_ABSTIME,
+            &deadline,
+            std::ptr::null_mut(),
+        );
+        assert_eq!(ret, 0);
+
+        let mut end = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut end);
+
+        assert!((end.tv_sec, end.tv_nsec) >= (deadline.tv_sec, deadline.tv_nsec));
+    }
+}
+
+register_test!(test_clock_nanosleep_abstime);
+
 fn test_fork() {
     unsafe {
         let pid = libc::fork();
@@ -196,6 +240,382 @@ fn test_mincore() {
 
 register_test!(test_mincore);
 
+fn test_proc_smaps() {
+    let page_size = 4096;
+
+    unsafe {
+        let addr = libc::mmap(
+            std::ptr::null_mut(),
+            4 * page_size,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+            -1,
+            0,
+        );
+        assert_ne!(addr, libc::MAP_FAILED, "mmap failed");
+
+        // Fault in two of the four pages.
+        *(addr as *mut u8) = 1;
+        *(addr as *mut u8).add(2 * page_size) = 1;
+
+        let smaps = std::fs::read_to_string("/proc/self/smaps").expect("read smaps");
+        let header = format!("{:08x}-", addr as usize);
+        let mut lines = smaps.lines().skip_while(|l| !l.starts_with(&header));
+        assert!(lines.next().is_some(), "mapping missing from smaps");
+
+        // Collect the `Field: value kB` lines up to the next mapping's header.
+        let fields: Vec<(&str, usize)> = lines
+            .map_while(|l| {
+                let (name, value) = l.split_once(':')?;
+                let kb = value.trim().strip_suffix(" kB")?.parse().ok()?;
+                Some((name, kb))
+            })
+            .collect();
+        let field = |name| fields.iter().find(|(n, _)| *n == name).map(|(_, v)| *v);
+
+        assert_eq!(field("Size"), Some(16));
+        assert_eq!(field("Rss"), Some(8));
+        assert_eq!(field("Anonymous"), Some(8));
+
+        libc::munmap(addr, 4 * page_size);
+    }
+
+    let meminfo = std::fs::read_to_string("/proc/meminfo").expect("read meminfo");
+    for field in ["MemTotal:", "MemFree:", "Slab:", "VmallocUsed:"] {
+        assert!(meminfo.contains(field), "missing {field}");
+    }
+}
+
+register_test!(test_proc_smaps);
+
+fn test_proc_cpuidle() {
+    // Give the idle task a chance to run.
+    std::thread::sleep(std::time::Duration::from_millis(50));
+
+    let cpuidle = std::fs::read_to_string("/proc/cpuidle").expect("read cpuidle");
+    let wfi_usage: u64 = cpuidle
+        .lines()
+        .skip(1)
+        .map(|l| l.split_whitespace().collect::<Vec<_>>())
+        .filter(|fields| fields[2] == "WFI")
+        .map(|fields| fields[5].parse::<u64>().expect("parse usage"))
+        .sum();
+
+    assert!(wfi_usage > 0, "no CPU entered WFI:\n{cpuidle}");
+}
+
+register_test!(test_proc_cpuidle);
+
+fn test_syslog() {
+    unsafe {
+        let size = libc::klogctl(10, std::ptr::null_mut(), 0);
+        assert!(size > 0, "SYSLOG_ACTION_SIZE_BUFFER failed");
+
+        let mut buf = vec![0u8; size as usize];
+        let len = libc::klogctl(3, buf.as_mut_ptr().cast(), buf.len() as _);
+        assert!(len > 0, "SYSLOG_ACTION_READ_ALL failed");
+
+        let log = String::from_utf8_lossy(&buf[..len as usize]);
+        for line in log.lines() {
+            assert!(
+                line.starts_with('<') && line.contains("] "),
+                "malformed log line: {line}"
+            );
+        }
+
+        assert_eq!(libc::klogctl(8, std::ptr::null_mut(), 0), -1);
+    }
+}
+
+register_test!(test_syslog);
+
+fn test_tracepoints() {
+    let set_event = "/sys/kernel/tracing/set_event";
+
+    let available = std::fs::read_to_string("/sys/kernel/tracing/available_events")
+        .expect("read available_events");
+    assert!(available.lines().any(|l| l == "sys_enter"));
+
+    std::fs::write(set_event, "sys_enter").expect("enable sys_enter");
+    assert_eq!(std::fs::read_to_string(set_event).unwrap(), "sys_enter\n");
+
+    unsafe { libc::getppid() };
+
+    let trace = std::fs::read_to_string("/sys/kernel/tracing/trace").expect("read trace");
+
+    std::fs::write(set_event, "!*").expect("disable events");
+    assert_eq!(std::fs::read_to_string(set_event).unwrap(), "");
+
+    // getppid is syscall 0xad on aarch64.
+    assert!(
+        trace.lines().any(|l| l.contains("sys_enter: 0xad ")),
+        "getppid not traced:\n{trace}"
+    );
+    assert!(std::fs::write(set_event, "no_such_event").is_err());
+}
+
+register_test!(test_tracepoints);
+
+fn test_ptrace_syscall_info() {
+    const PTRACE_GET_SYSCALL_INFO: libc::c_uint = 0x420e;
+    static MAGIC: u64 = 0x1234_5678_9abc_def0;
+
+    let wait_stop = |pid| {
+        let mut status = 0;
+        assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+        assert!(libc::WIFSTOPPED(status), "tracee didn't stop: {status:#x}");
+    };
+
+    // struct ptrace_syscall_info: op, pad, arch, ip, sp, then nr/rval and args.
+    let syscall_info = |pid| {
+        let mut info = [0u64; 10];
+        let len = unsafe {
+            libc::ptrace(
+                PTRACE_GET_SYSCALL_INFO,
+                pid,
+                size_of_val(&info),
+                info.as_mut_ptr(),
+            )
+        };
+        assert!(len > 0, "PTRACE_GET_SYSCALL_INFO failed");
+        (info[0] as u8, info[3])
+    };
+
+    unsafe {
+        let pid = libc::fork();
+        if pid == 0 {
+            libc::ptrace(libc::PTRACE_TRACEME, 0, 0, 0);
+            libc::raise(libc::SIGSTOP);
+            libc::getppid();
+            libc::_exit(0);
+        }
+
+        wait_stop(pid);
+
+        let word = libc::ptrace(libc::PTRACE_PEEKDATA, pid, &raw const MAGIC, 0);
+        assert_eq!(word as u64, MAGIC);
+
+        libc::ptrace(libc::PTRACE_SYSCALL, pid, 0, 0);
+        wait_stop(pid);
+        // getppid is syscall 0xad on aarch64.
+        assert_eq!(syscall_info(pid), (1, 0xad));
+
+        libc::ptrace(libc::PTRACE_SYSCALL, pid, 0, 0);
+        wait_stop(pid);
+        assert_eq!(syscall_info(pid), (2, libc::getpid() as u64));
+
+        libc::ptrace(libc::PTRACE_CONT, pid, 0, 0);
+
+        let mut status = 0;
+        assert_eq!(libc::waitpid(pid, &mut status, 0), pid);
+        assert!(libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0);
+    }
+}
+
+register_test!(test_ptrace_syscall_info);
+
+fn test_commit_accounting() {
+    let committed_kb = || {
+        std::fs::read_to_string("/proc/meminfo")
+            .expect("read meminfo")
+            .lines()
+            .find_map(|l| l.strip_prefix("Committed_AS:"))
+            .and_then(|v| v.trim().strip_suffix("kB"))
+            .and_then(|v| v.trim().parse::<usize>().ok())
+            .expect("missing Committed_AS")
+    };
+
+    let len = 16 * 1024 * 1024;
+
+    unsafe {
+        let map = |flags| {
+            let addr = libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | flags,
+                -1,
+                0,
+            );
+            assert_ne!(addr, libc::MAP_FAILED, "mmap failed");
+            addr
+        };
+
+        // Private writable memory is committed when it's mapped.
+        let before = committed_kb();
+        let addr = map(0);
+        assert!(committed_kb() >= before + len / 1024);
+
+        libc::munmap(addr, len);
+        assert!(committed_kb() < before + len / 1024);
+
+        // Unless the caller asks for no reservation.
+        let before = committed_kb();
+        let addr = map(libc::MAP_NORESERVE);
+        assert!(committed_kb() < before + len / 1024);
+
+        libc::munmap(addr, len);
+    }
+}
+
+register_test!(test_commit_accounting);
+
+fn test_anon_zero_page() {
+    let page_size = 4096;
+
+    unsafe {
+        let addr = libc::mmap(
+            std::ptr::null_mut(),
+            3 * page_size,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+            -1,
+            0,
+        );
+        assert_ne!(addr, libc::MAP_FAILED, "mmap failed");
+        let page = |i: usize| (addr as *mut u8).add(i * page_size);
+
+        // Read faults map the shared zero page.
+        for i in 0..3 {
+            assert_eq!(std::ptr::read_volatile(page(i)), 0);
+        }
+
+        // Writing to one page must not affect the others.
+        std::ptr::write_volatile(page(0), 0xaa);
+        assert_eq!(std::ptr::read_volatile(page(0)), 0xaa);
+        assert_eq!(std::ptr::read_volatile(page(1)), 0);
+
+        // Making a zero page mapping read-only and then writable again must
+        // still give us a private copy on write.
+        assert_eq!(
+            libc::mprotect(page(1).cast(), page_size, libc::PROT_READ),
+            0
+        );
+        assert_eq!(
+            libc::mprotect(
+                page(1).cast(),
+                page_size,
+                libc::PROT_READ | libc::PROT_WRITE
+            ),
+            0
+        );
+        std::ptr::write_volatile(page(1), 0xbb);
+        assert_eq!(std::ptr::read_volatile(page(1)), 0xbb);
+        assert_eq!(std::ptr::read_volatile(page(2)), 0);
+
+        libc::munmap(addr, 3 * page_size);
+    }
+}
+
+register_test!(test_anon_zero_page);
+
+fn test_process_vm_rw() {
+    let mut buf = [0u8; 64];
+    buf[..5].copy_from_slice(b"hello");
+
+    unsafe {
+        let parent = libc::getpid();
+        let pid = libc::fork();
+        if pid < 0 {
+            panic!("fork failed");
+        } else if pid == 0 {
+            // Child process: overwrite the parent's (CoW-shared) buffer, then
+            // read it back.
+            let src = *b"world";
+            let local = libc::iovec {
+                iov_base: src.as_ptr() as *mut _,
+                iov_len: src.len(),
+            };
+            let remote = libc::iovec {
+                iov_base: buf.as_mut_ptr() as *mut _,
+                iov_len: src.len(),
+            };
+            if libc::process_vm_writev(parent, &local, 1, &remote, 1, 0) != src.len() as isize {
+                libc::_exit(1);
+            }
+
+            let mut dst = [0u8; 5];
+            let local = libc::iovec {
+                iov_base: dst.as_mut_ptr() as *mut _,
+                iov_len: dst.len(),
+            };
+            if libc::process_vm_readv(parent, &local, 1, &remote, 1, 0) != dst.len() as isize
+                || dst != src
+            {
+                libc::_exit(2);
+            }
+
+            libc::_exit(0);
+        } else {
+            // Parent process
+            let mut status = 0;
+            libc::waitpid(pid, &mut status, 0);
+            assert!(libc::WIFEXITED(status));
+            assert_eq!(libc::WEXITSTATUS(status), 0);
+            assert_eq!(&std::ptr::read_volatile(&buf)[..5], b"world");
+        }
+    }
+}
+
+register_test!(test_process_vm_rw);
+
+fn test_userfaultfd() {
+    const UFFDIO_API: u32 = 0xc018_aa3f;
+    const UFFDIO_REGISTER: u32 = 0xc020_aa00;
+    const UFFDIO_COPY: u32 = 0xc028_aa03;
+    const PAGE_SIZE: usize = 4096;
+
+    unsafe {
+        let uffd = libc::syscall(libc::SYS_userfaultfd, libc::O_CLOEXEC) as libc::c_int;
+        assert!(uffd >= 0, "userfaultfd failed");
+
+        // struct uffdio_api { api, features, ioctls }
+        let mut api = [0xaau64, 0, 0];
+        assert_eq!(libc::ioctl(uffd, UFFDIO_API as _, api.as_mut_ptr()), 0);
+
+        let addr = libc::mmap(
+            std::ptr::null_mut(),
+            PAGE_SIZE,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+            -1,
+            0,
+        );
+        assert_ne!(addr, libc::MAP_FAILED);
+
+        // struct uffdio_register { start, len, mode, ioctls }
+        let mut reg = [addr as u64, PAGE_SIZE as u64, 1, 0];
+        assert_eq!(libc::ioctl(uffd, UFFDIO_REGISTER as _, reg.as_mut_ptr()), 0);
+
+        let addr_val = addr as usize;
+        let reader = thread::spawn(move || std::ptr::read_volatile(addr_val as *const u64));
+
+        // Wait for the reader's fault to be reported.
+        let mut msg = [0u64; 4];
+        assert_eq!(
+            libc::read(uffd, msg.as_mut_ptr() as *mut _, 32),
+            32,
+            "failed to read uffd_msg"
+        );
+        assert_eq!(msg[0] & 0xff, 0x12, "expected a pagefault event");
+        assert_eq!(msg[2] as usize, addr_val);
+
+        // Resolve it by copying in a page of our own.
+        let src = vec![0x5au8; PAGE_SIZE];
+        // struct uffdio_copy { dst, src, len, mode, copy }
+        let mut copy = [addr_val as u64, src.as_ptr() as u64, PAGE_SIZE as u64, 0, 0];
+        assert_eq!(libc::ioctl(uffd, UFFDIO_COPY as _, copy.as_mut_ptr()), 0);
+        assert_eq!(copy[4], PAGE_SIZE as u64);
+
+        assert_eq!(reader.join().unwrap(), 0x5a5a_5a5a_5a5a_5a5a);
+
+        libc::close(uffd);
+        libc::munmap(addr, PAGE_SIZE);
+    }
+}
+
+register_test!(test_userfaultfd);
+
 fn test_itimer() {
     use libc::{ITIMER_REAL, itimerval};
     use std::mem::MaybeUninit;
@@ -249,6 +669,314 @@ fn test_itimer() {
 
 register_test!(test_itimer);
 
+fn test_getrandom() {
+    let mut a = [0u8; 64];
+    let mut b = [0u8; 64];
+
+    unsafe {
+        for buf in [&mut a, &mut b] {
+            let ret = libc::getrandom(buf.as_mut_ptr().cast(), buf.len(), 0);
+            assert_eq!(ret, buf.len() as isize);
+        }
+
+        // The pool is seeded by now, so these mustn't fail.
+        for flags in [libc::GRND_NONBLOCK, libc::GRND_INSECURE, libc::GRND_RANDOM] {
+            let ret = libc::getrandom(b.as_mut_ptr().cast(), b.len(), flags);
+            assert_eq!(ret, b.len() as isize);
+        }
+
+        let ret = libc::getrandom(
+            b.as_mut_ptr().cast(),
+            b.len(),
+            libc::GRND_RANDOM | libc::GRND_INSECURE,
+        );
+        assert_eq!(ret, -1);
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::EINVAL)
+        );
+    }
+
+    assert_ne!(a, [0; 64]);
+    assert_ne!(a, b);
+}
+
+register_test!(test_getrandom);
+
+fn test_dev_urandom() {
+    use std::io::Read;
+
+    let mut a = [0u8; 64];
+    let mut b = [0u8; 64];
+    let mut file = std::fs::File::open("/dev/urandom").expect("Failed to open /dev/urandom");
+
+    file.read_exact(&mut a).unwrap();
+    file.read_exact(&mut b).unwrap();
+
+    assert_ne!(a, [0; 64]);
+    assert_ne!(a, b);
+}
+
+register_test!(test_dev_urandom);
+
+fn test_sched_affinity() {
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        let size = std::mem::size_of::<libc::cpu_set_t>();
+
+        assert_eq!(libc::sched_getaffinity(0, size, &mut set), 0);
+        assert!(libc::CPU_ISSET(0, &set));
+
+        libc::CPU_ZERO(&mut set);
+        libc::CPU_SET(0, &mut set);
+        assert_eq!(libc::sched_setaffinity(0, size, &set), 0);
+
+        // Give the scheduler a chance to move us, should we be elsewhere.
+        for _ in 0..4 {
+            libc::sched_yield();
+        }
+
+        assert_eq!(libc::sched_getcpu(), 0);
+
+        let mut got: libc::cpu_set_t = std::mem::zeroed();
+        assert_eq!(libc::sched_getaffinity(0, size, &mut got), 0);
+        assert!(libc::CPU_ISSET(0, &got));
+        assert!(!libc::CPU_ISSET(1, &got));
+    }
+}
+
+register_test!(test_sched_affinity);
+
+fn test_kexec_load_checks() {
+    unsafe {
+        // Unloading, when nothing is loaded, is fine.
+        let ret = libc::syscall(libc::SYS_kexec_load, 0, 0, std::ptr::null::<u8>(), 0);
+        assert_eq!(ret, 0);
+
+        let ret = libc::syscall(libc::SYS_kexec_load, 0, 17, std::ptr::null::<u8>(), 0);
+        assert_eq!(ret, -1);
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::EINVAL)
+        );
+    }
+}
+
+register_test!(test_kexec_load_checks);
+
+fn test_sysctl() {
+    let overcommit = "/proc/sys/vm/overcommit_memory";
+
+    let original = std::fs::read_to_string(overcommit).expect("read overcommit_memory");
+
+    std::fs::write(overcommit, "1\n").expect("set overcommit_memory");
+    assert_eq!(std::fs::read_to_string(overcommit).unwrap(), "1\n");
+
+    assert!(std::fs::write(overcommit, "7").is_err());
+    assert!(std::fs::write(overcommit, "many").is_err());
+    assert_eq!(std::fs::read_to_string(overcommit).unwrap(), "1\n");
+
+    std::fs::write(overcommit, &original).expect("restore overcommit_memory");
+
+    let hostname = std::fs::read_to_string("/proc/sys/kernel/hostname").expect("read hostname");
+    assert!(hostname.ends_with('\n'));
+
+    let core = std::fs::read_dir("/proc/sys/net/core").expect("list net/core");
+    assert!(core.flatten().any(|e| e.file_name() == "somaxconn"));
+}
+
+register_test!(test_sysctl);
+
+fn test_uts_namespace() {
+    fn uname() -> (String, String, String) {
+        let field = |f: &[libc::c_char]| {
+            unsafe { std::ffi::CStr::from_ptr(f.as_ptr()) }
+                .to_string_lossy()
+                .into_owned()
+        };
+
+        let mut uts: libc::utsname = unsafe { std::mem::zeroed() };
+        assert_eq!(unsafe { libc::uname(&mut uts) }, 0);
+        assert_eq!(field(&uts.sysname), "Moss");
+        assert!(field(&uts.release).starts_with("4.2.3"));
+
+        (
+            field(&uts.nodename),
+            field(&uts.domainname),
+            field(&uts.machine),
+        )
+    }
+
+    let (hostname, domainname, machine) = uname();
+    assert_eq!(machine, "aarch64");
+
+    unsafe {
+        let pid = libc::syscall(
+            libc::SYS_clone,
+            libc::CLONE_NEWUTS | libc::SIGCHLD,
+            0,
+            0,
+            0,
+            0,
+        ) as libc::pid_t;
+
+        if pid == 0 {
+            let name = b"moss-ns";
+            let domain = b"example.org";
+            if libc::sethostname(name.as_ptr().cast(), name.len()) != 0
+                || libc::setdomainname(domain.as_ptr().cast(), domain.len()) != 0
+            {
+                libc::_exit(1);
+            }
+
+            let (hostname, domainname, _) = uname();
+            libc::_exit(if hostname == "moss-ns" && domainname == "example.org" {
+                0
+            } else {
+                2
+            });
+        }
+
+        let mut status = 0;
+        assert_eq!(libc::waitpid(pid, &mut status, 0), pid);
+        assert!(libc::WIFEXITED(status));
+        assert_eq!(libc::WEXITSTATUS(status), 0);
+    }
+
+    // The names changed only within the child's namespace.
+    assert_eq!(uname(), (hostname, domainname, machine));
+}
+
+register_test!(test_uts_namespace);
+
+fn test_eventfd() {
+    unsafe {
+        let read = |fd| {
+            let mut value = 0u64;
+            let ret = libc::read(fd, (&raw mut value).cast(), 8);
+            (ret, value)
+        };
+        let write = |fd, value: u64| libc::write(fd, (&raw const value).cast(), 8);
+
+        let fd = libc::eventfd(3, libc::EFD_NONBLOCK);
+        assert!(fd >= 0);
+
+        assert_eq!(write(fd, 4), 8);
+        assert_eq!(read(fd), (8, 7));
+
+        // The counter is now zero, so a read would block.
+        assert_eq!(read(fd).0, -1);
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::EAGAIN)
+        );
+
+        assert_eq!(write(fd, u64::MAX), -1);
+        assert_eq!(write(fd, u64::MAX - 1), 8);
+        assert_eq!(write(fd, 1), -1);
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::EAGAIN)
+        );
+        libc::close(fd);
+
+        let fd = libc::eventfd(2, libc::EFD_NONBLOCK | libc::EFD_SEMAPHORE);
+        assert!(fd >= 0);
+        assert_eq!(read(fd), (8, 1));
+        assert_eq!(read(fd), (8, 1));
+        assert_eq!(read(fd).0, -1);
+        libc::close(fd);
+    }
+}
+
+register_test!(test_eventfd);
+
+fn test_timerfd() {
+    unsafe {
+        let read = |fd| {
+            let mut expirations = 0u64;
+            let ret = libc::read(fd, (&raw mut expirations).cast(), 8);
+            (ret, expirations)
+        };
+        let spec = |interval_ns, value_ns| libc::itimerspec {
+            it_interval: libc::timespec {
+                tv_sec: 0,
+                tv_nsec: interval_ns,
+            },
+            it_value: libc::timespec {
+                tv_sec: 0,
+                tv_nsec: value_ns,
+            },
+        };
+
+        let fd = libc::timerfd_create(libc::CLOCK_MONOTONIC, libc::TFD_NONBLOCK);
+        assert!(fd >= 0);
+
+        // Disarmed, so there's nothing to read.
+        assert_eq!(read(fd).0, -1);
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::EAGAIN)
+        );
+
+        // A periodic timer, every 10ms starting in 10ms.
+        let new = spec(10_000_000, 10_000_000);
+        assert_eq!(libc::timerfd_settime(fd, 0, &new, std::ptr::null_mut()), 0);
+
+        let mut curr = spec(0, 0);
+        assert_eq!(libc::timerfd_gettime(fd, &mut curr), 0);
+        assert_eq!(curr.it_interval.tv_nsec, 10_000_000);
+        assert!(curr.it_value.tv_nsec > 0 && curr.it_value.tv_nsec <= 10_000_000);
+
+        let mut pfd = libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        assert_eq!(libc::poll(&mut pfd, 1, 1000), 1);
+        assert!(pfd.revents & libc::POLLIN != 0);
+
+        std::thread::sleep(std::time::Duration::from_millis(30));
+        let (ret, expirations) = read(fd);
+        assert_eq!(ret, 8);
+        assert!(expirations >= 3, "only {expirations} expirations");
+
+        // Disarming returns the old setting.
+        let mut old = spec(0, 0);
+        assert_eq!(libc::timerfd_settime(fd, 0, &spec(0, 0), &mut old), 0);
+        assert_eq!(old.it_interval.tv_nsec, 10_000_000);
+        assert_eq!(read(fd).0, -1);
+        libc::close(fd);
+
+        // An absolute one-shot timer, already in the past, expires at once.
+        let fd = libc::timerfd_create(libc::CLOCK_REALTIME, 0);
+        assert!(fd >= 0);
+        let mut now = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        libc::clock_gettime(libc::CLOCK_REALTIME, &mut now);
+        let past = libc::itimerspec {
+            it_interval: libc::timespec {
+                tv_sec: 0,
+                tv_nsec: 0,
+            },
+            it_value: libc::timespec {
+                tv_sec: now.tv_sec - 1,
+                tv_nsec: 0,
+            },
+        };
+        assert_eq!(
+            libc::timerfd_settime(fd, libc::TFD_TIMER_ABSTIME, &past, std::ptr::null_mut()),
+            0
+        );
+        assert_eq!(read(fd), (8, 1));
+        libc::close(fd);
+    }
+}
+
+register_test!(test_timerfd);
+
 fn run_test(test_fn: fn()) -> Result<(), i32> {
     // Fork a new process to run the test
     unsafe {
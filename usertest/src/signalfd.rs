@@ -311,3 +311,87 @@ fn test_signalfd_epoll_readiness() {
 }
 
 register_test!(test_signalfd_epoll_readiness);
+
+fn test_epoll_signalfd_and_pidfd() {
+    unsafe {
+        let mask = sigset(&[libc::SIGUSR1]);
+        let old_mask = block_signals(&mask);
+
+        let sfd = signalfd4(-1, &mask, libc::O_NONBLOCK);
+        assert!(sfd >= 0);
+
+        let pid = libc::fork();
+        assert!(pid >= 0);
+        if pid == 0 {
+            libc::usleep(20_000);
+            libc::_exit(3);
+        }
+
+        let pidfd = libc::syscall(libc::SYS_pidfd_open, pid, 0) as libc::c_int;
+        assert!(
+            pidfd >= 0,
+            "pidfd_open failed: {}",
+            std::io::Error::last_os_error()
+        );
+
+        let epfd = libc::epoll_create1(0);
+        assert!(epfd >= 0);
+        for (fd, data) in [(sfd, 1), (pidfd, 2)] {
+            let mut event = libc::epoll_event {
+                events: libc::EPOLLIN as u32,
+                u64: data,
+            };
+            assert_eq!(
+                libc::epoll_ctl(epfd, libc::EPOLL_CTL_ADD, fd, &mut event),
+                0
+            );
+        }
+
+        assert_eq!(libc::kill(libc::getpid(), libc::SIGUSR1), 0);
+
+        // Wait with no timeout for each in turn: the signal is already
+        // pending, while the child exits a little later.
+        let mut seen = BTreeSet::new();
+        while seen.len() < 2 {
+            let mut ready = [libc::epoll_event { events: 0, u64: 0 }; 2];
+            let n = libc::epoll_wait(epfd, ready.as_mut_ptr(), ready.len() as i32, -1);
+            assert!(
+                n > 0,
+                "epoll_wait failed: {}",
+                std::io::Error::last_os_error()
+            );
+
+            for event in &ready[..n as usize] {
+                match event.u64 {
+                    1 => assert_eq!(read_one(sfd).ssi_signo, libc::SIGUSR1 as u32),
+                    2 => {
+                        // Once readable, the pidfd stays that way until it's
+                        // closed, so stop watching it.
+                        libc::epoll_ctl(epfd, libc::EPOLL_CTL_DEL, pidfd, std::ptr::null_mut());
+                    }
+                    data => panic!("unexpected event {data}"),
+                }
+                seen.insert(event.u64);
+            }
+        }
+
+        let mut status = 0;
+        assert_eq!(libc::waitpid(pid, &mut status, 0), pid);
+        assert!(libc::WIFEXITED(status));
+        assert_eq!(libc::WEXITSTATUS(status), 3);
+
+        // A pid which doesn't exist can't be opened.
+        assert_eq!(libc::syscall(libc::SYS_pidfd_open, 0x7fff_fff0, 0), -1);
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::ESRCH)
+        );
+
+        libc::close(epfd);
+        libc::close(pidfd);
+        libc::close(sfd);
+        restore_sigmask(&old_mask);
+    }
+}
+
+register_test!(test_epoll_signalfd_and_pidfd);
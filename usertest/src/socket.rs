@@ -15,6 +15,1843 @@ pub fn test_tcp_socket_creation() {
 
 register_test!(test_tcp_socket_creation);
 
+fn loopback_addr(port: u16) -> libc::sockaddr_in {
+    libc::sockaddr_in {
+        sin_family: AF_INET as u16,
+        sin_port: port.to_be(),
+        sin_addr: libc::in_addr {
+            s_addr: u32::from_ne_bytes([127, 0, 0, 1]),
+        },
+        sin_zero: [0; 8],
+    }
+}
+
+pub fn test_tcp_loopback_connect_accept() {
+    let addrlen = std::mem::size_of::<libc::sockaddr_in>() as u32;
+
+    unsafe {
+        let server_fd = socket(AF_INET, SOCK_STREAM, 0);
+        assert!(server_fd >= 0, "Failed to create server TCP socket");
+        let server_addr = loopback_addr(5555);
+        assert_eq!(
+            bind(
+                server_fd,
+                &server_addr as *const libc::sockaddr_in as *const libc::sockaddr,
+                addrlen,
+            ),
+            0,
+            "Failed to bind TCP socket"
+        );
+        assert_eq!(listen(server_fd, 1), 0, "Failed to listen on TCP socket");
+
+        let client_fd = socket(AF_INET, SOCK_STREAM, 0);
+        assert!(client_fd >= 0, "Failed to create client TCP socket");
+        assert_eq!(
+            connect(
+                client_fd,
+                &server_addr as *const libc::sockaddr_in as *const libc::sockaddr,
+                addrlen,
+            ),
+            0,
+            "Failed to connect to listening TCP socket: {}",
+            std::io::Error::last_os_error()
+        );
+
+        let mut peer: libc::sockaddr_in = std::mem::zeroed();
+        let mut peerlen = addrlen;
+        let accepted_fd = accept(
+            server_fd,
+            &mut peer as *mut libc::sockaddr_in as *mut libc::sockaddr,
+            &mut peerlen,
+        );
+        assert!(accepted_fd >= 0, "Failed to accept TCP connection");
+        assert_eq!(peer.sin_family, AF_INET as u16);
+        assert_eq!(peer.sin_addr.s_addr, server_addr.sin_addr.s_addr);
+        assert_ne!(peer.sin_port, 0);
+
+        // Nothing's listening here, so the connection should be reset.
+        let refused_fd = socket(AF_INET, SOCK_STREAM, 0);
+        assert!(refused_fd >= 0, "Failed to create TCP socket");
+        let refused_addr = loopback_addr(5556);
+        assert_eq!(
+            connect(
+                refused_fd,
+                &refused_addr as *const libc::sockaddr_in as *const libc::sockaddr,
+                addrlen,
+            ),
+            -1
+        );
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::ECONNREFUSED)
+        );
+
+        libc::close(refused_fd);
+        libc::close(accepted_fd);
+        libc::close(client_fd);
+        libc::close(server_fd);
+    }
+}
+
+register_test!(test_tcp_loopback_connect_accept);
+
+pub fn test_accept4_flags() {
+    let addrlen = std::mem::size_of::<libc::sockaddr_in>() as u32;
+
+    unsafe {
+        let server_fd = socket(AF_INET, SOCK_STREAM, 0);
+        assert!(server_fd >= 0);
+        let server_addr = loopback_addr(5575);
+        assert_eq!(bind_addr(server_fd, &server_addr), 0);
+        assert_eq!(listen(server_fd, 1), 0);
+
+        let client_fd = socket(AF_INET, SOCK_STREAM, 0);
+        assert!(client_fd >= 0);
+        assert_eq!(
+            connect(
+                client_fd,
+                &server_addr as *const libc::sockaddr_in as *const libc::sockaddr,
+                addrlen,
+            ),
+            0
+        );
+
+        // Unknown flags are refused.
+        assert_errno(
+            libc::accept4(server_fd, std::ptr::null_mut(), std::ptr::null_mut(), 1) as isize,
+            libc::EINVAL,
+        );
+
+        let accepted_fd = libc::accept4(
+            server_fd,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+        );
+        assert!(accepted_fd >= 0, "accept4 failed");
+        assert_ne!(
+            libc::fcntl(accepted_fd, libc::F_GETFL) & libc::O_NONBLOCK,
+            0
+        );
+        assert_ne!(
+            libc::fcntl(accepted_fd, libc::F_GETFD) & libc::FD_CLOEXEC,
+            0
+        );
+
+        // Being nonblocking, a read with nothing to read fails straight away.
+        let mut buf = [0u8; 4];
+        assert_errno(
+            libc::read(accepted_fd, buf.as_mut_ptr().cast(), buf.len()),
+            libc::EAGAIN,
+        );
+
+        libc::close(accepted_fd);
+        libc::close(client_fd);
+        libc::close(server_fd);
+    }
+}
+
+register_test!(test_accept4_flags);
+
+pub fn test_tcp_shutdown() {
+    let addrlen = std::mem::size_of::<libc::sockaddr_in>() as u32;
+
+    unsafe {
+        // There's no connection to shut down.
+        let unconnected_fd = socket(AF_INET, SOCK_STREAM, 0);
+        assert!(unconnected_fd >= 0);
+        assert_errno(
+            shutdown(unconnected_fd, libc::SHUT_RDWR) as isize,
+            libc::ENOTCONN,
+        );
+        libc::close(unconnected_fd);
+
+        let server_fd = socket(AF_INET, SOCK_STREAM, 0);
+        assert!(server_fd >= 0);
+        let server_addr = loopback_addr(5576);
+        assert_eq!(bind_addr(server_fd, &server_addr), 0);
+        assert_eq!(listen(server_fd, 1), 0);
+
+        let client_fd = socket(AF_INET, SOCK_STREAM, 0);
+        assert!(client_fd >= 0);
+        assert_eq!(
+            connect(
+                client_fd,
+                &server_addr as *const libc::sockaddr_in as *const libc::sockaddr,
+                addrlen,
+            ),
+            0
+        );
+        let accepted_fd = accept(server_fd, std::ptr::null_mut(), std::ptr::null_mut());
+        assert!(accepted_fd >= 0);
+
+        // Shutting down the client's write side is seen as end of file, while
+        // the server can still send to it.
+        assert_eq!(shutdown(client_fd, libc::SHUT_WR), 0);
+        let mut buf = [0u8; 4];
+        assert_eq!(
+            libc::read(accepted_fd, buf.as_mut_ptr().cast(), buf.len()),
+            0
+        );
+
+        let msg = b"pong";
+        assert_eq!(
+            libc::write(accepted_fd, msg.as_ptr().cast(), msg.len()),
+            msg.len() as isize
+        );
+        assert_eq!(
+            libc::read(client_fd, buf.as_mut_ptr().cast(), buf.len()),
+            msg.len() as isize
+        );
+        assert_eq!(&buf, msg);
+
+        // The client can't send any more.
+        assert_errno(
+            libc::send(
+                client_fd,
+                msg.as_ptr().cast(),
+                msg.len(),
+                libc::MSG_NOSIGNAL,
+            ),
+            libc::EPIPE,
+        );
+
+        // Once the client's shut down for reading, its reads return straight
+        // away.
+        assert_eq!(shutdown(client_fd, libc::SHUT_RD), 0);
+        assert_eq!(
+            libc::write(accepted_fd, msg.as_ptr().cast(), msg.len()),
+            msg.len() as isize
+        );
+        assert_eq!(libc::read(client_fd, buf.as_mut_ptr().cast(), buf.len()), 0);
+
+        libc::close(accepted_fd);
+        libc::close(client_fd);
+        libc::close(server_fd);
+    }
+}
+
+register_test!(test_tcp_shutdown);
+
+fn sockname(
+    fd: i32,
+    get: unsafe extern "C" fn(i32, *mut libc::sockaddr, *mut u32) -> i32,
+) -> libc::sockaddr_in {
+    let mut addr: libc::sockaddr_in = unsafe { std::mem::zeroed() };
+    let mut addrlen = std::mem::size_of::<libc::sockaddr_in>() as u32;
+    let ret = unsafe {
+        get(
+            fd,
+            &mut addr as *mut libc::sockaddr_in as *mut libc::sockaddr,
+            &mut addrlen,
+        )
+    };
+    assert_eq!(ret, 0);
+    assert_eq!(addrlen as usize, std::mem::size_of::<libc::sockaddr_in>());
+    addr
+}
+
+pub fn test_getsockname_getpeername() {
+    let addrlen = std::mem::size_of::<libc::sockaddr_in>() as u32;
+
+    unsafe {
+        let server_fd = socket(AF_INET, SOCK_STREAM, 0);
+        assert!(server_fd >= 0);
+
+        // An unbound socket has no peer, and the unspecified address.
+        assert_errno(
+            libc::getpeername(server_fd, std::ptr::null_mut(), std::ptr::null_mut()) as isize,
+            libc::ENOTCONN,
+        );
+        let unbound = sockname(server_fd, libc::getsockname);
+        assert_eq!(unbound.sin_family, AF_INET as u16);
+        assert_eq!(unbound.sin_port, 0);
+
+        // Binding to port zero picks a port, which getsockname reports.
+        assert_eq!(bind_addr(server_fd, &loopback_addr(0)), 0);
+        let server_addr = sockname(server_fd, libc::getsockname);
+        assert_ne!(server_addr.sin_port, 0);
+        assert_eq!(
+            server_addr.sin_addr.s_addr,
+            loopback_addr(0).sin_addr.s_addr
+        );
+        assert_eq!(listen(server_fd, 1), 0);
+
+        let client_fd = socket(AF_INET, SOCK_STREAM, 0);
+        assert!(client_fd >= 0);
+        assert_eq!(
+            connect(
+                client_fd,
+                &server_addr as *const libc::sockaddr_in as *const libc::sockaddr,
+                addrlen,
+            ),
+            0
+        );
+        let accepted_fd = accept(server_fd, std::ptr::null_mut(), std::ptr::null_mut());
+        assert!(accepted_fd >= 0, "accept failed");
+
+        // Each end of the connection names the other as its peer.
+        let client_addr = sockname(client_fd, libc::getsockname);
+        assert_ne!(client_addr.sin_port, 0);
+        assert_eq!(
+            sockname(client_fd, libc::getpeername).sin_port,
+            server_addr.sin_port
+        );
+        assert_eq!(
+            sockname(accepted_fd, libc::getsockname).sin_port,
+            server_addr.sin_port
+        );
+        assert_eq!(
+            sockname(accepted_fd, libc::getpeername).sin_port,
+            client_addr.sin_port
+        );
+
+        // An address too long for the buffer is cut short, but its full
+        // length is still reported.
+        let mut short = [0u8; 4];
+        let mut short_len = short.len() as u32;
+        assert_eq!(
+            libc::getsockname(client_fd, short.as_mut_ptr().cast(), &mut short_len),
+            0
+        );
+        assert_eq!(short_len, addrlen);
+        assert_eq!(
+            u16::from_ne_bytes([short[2], short[3]]),
+            client_addr.sin_port
+        );
+
+        libc::close(accepted_fd);
+        libc::close(client_fd);
+        libc::close(server_fd);
+    }
+}
+
+register_test!(test_getsockname_getpeername);
+
+pub fn test_udp_loopback() {
+    let addrlen = std::mem::size_of::<libc::sockaddr_in>() as u32;
+
+    unsafe {
+        let server_fd = socket(AF_INET, SOCK_DGRAM, 0);
+        assert!(server_fd >= 0, "Failed to create UDP socket");
+        let server_addr = loopback_addr(5353);
+        assert_eq!(
+            bind(
+                server_fd,
+                &server_addr as *const libc::sockaddr_in as *const libc::sockaddr,
+                addrlen,
+            ),
+            0,
+            "Failed to bind UDP socket"
+        );
+
+        // The client isn't bound, so sending gives it an ephemeral port.
+        let client_fd = socket(AF_INET, SOCK_DGRAM, 0);
+        assert!(client_fd >= 0, "Failed to create UDP socket");
+        let msg = b"ping";
+        assert_eq!(
+            libc::sendto(
+                client_fd,
+                msg.as_ptr().cast(),
+                msg.len(),
+                0,
+                &server_addr as *const libc::sockaddr_in as *const libc::sockaddr,
+                addrlen,
+            ),
+            msg.len() as isize,
+            "sendto failed: {}",
+            std::io::Error::last_os_error()
+        );
+
+        let mut buf = [0u8; 16];
+
+        // Peeking into too small a buffer leaves the datagram queued, and
+        // MSG_TRUNC still reports its full length.
+        let n = libc::recv(
+            server_fd,
+            buf.as_mut_ptr().cast(),
+            2,
+            libc::MSG_PEEK | libc::MSG_TRUNC,
+        );
+        assert_eq!(n, msg.len() as isize, "recv with MSG_PEEK failed");
+        assert_eq!(&buf[..2], &msg[..2]);
+
+        let mut from: libc::sockaddr_in = std::mem::zeroed();
+        let mut fromlen = addrlen;
+        let n = libc::recvfrom(
+            server_fd,
+            buf.as_mut_ptr().cast(),
+            buf.len(),
+            0,
+            &mut from as *mut libc::sockaddr_in as *mut libc::sockaddr,
+            &mut fromlen,
+        );
+        assert_eq!(n, msg.len() as isize, "recvfrom failed");
+        assert_eq!(&buf[..msg.len()], msg);
+        assert_eq!(from.sin_family, AF_INET as u16);
+        assert_ne!(from.sin_port, 0);
+
+        // Reply to wherever it came from, over a connected socket this time.
+        assert_eq!(
+            connect(
+                server_fd,
+                &from as *const libc::sockaddr_in as *const libc::sockaddr,
+                addrlen,
+            ),
+            0
+        );
+        let reply = b"pong";
+        assert_eq!(
+            libc::send(server_fd, reply.as_ptr().cast(), reply.len(), 0),
+            reply.len() as isize
+        );
+        let n = libc::recv(client_fd, buf.as_mut_ptr().cast(), buf.len(), 0);
+        assert_eq!(n, reply.len() as isize);
+        assert_eq!(&buf[..reply.len()], reply);
+
+        // Nothing else has been sent.
+        assert_eq!(
+            libc::recv(
+                client_fd,
+                buf.as_mut_ptr().cast(),
+                buf.len(),
+                libc::MSG_DONTWAIT
+            ),
+            -1
+        );
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::EAGAIN)
+        );
+
+        libc::close(client_fd);
+        libc::close(server_fd);
+    }
+}
+
+register_test!(test_udp_loopback);
+
+pub fn test_udp_mmsg() {
+    unsafe {
+        let fd = socket(AF_INET, SOCK_DGRAM, 0);
+        assert!(fd >= 0);
+        let addr = loopback_addr(5585);
+        assert_eq!(bind_addr(fd, &addr), 0);
+        assert_eq!(
+            connect(
+                fd,
+                &addr as *const libc::sockaddr_in as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_in>() as u32,
+            ),
+            0
+        );
+
+        // Send three datagrams to ourselves in one go.
+        let msgs: [&[u8]; 3] = [b"one", b"two", b"three"];
+        let mut send_iovs: Vec<libc::iovec> = msgs
+            .iter()
+            .map(|msg| libc::iovec {
+                iov_base: msg.as_ptr() as *mut _,
+                iov_len: msg.len(),
+            })
+            .collect();
+        let mut send_hdrs: Vec<libc::mmsghdr> = send_iovs
+            .iter_mut()
+            .map(|iov| {
+                let mut hdr: libc::mmsghdr = std::mem::zeroed();
+                hdr.msg_hdr.msg_iov = iov;
+                hdr.msg_hdr.msg_iovlen = 1;
+                hdr
+            })
+            .collect();
+        assert_eq!(
+            libc::sendmmsg(fd, send_hdrs.as_mut_ptr(), send_hdrs.len() as _, 0 as _),
+            3
+        );
+        for (hdr, msg) in send_hdrs.iter().zip(msgs) {
+            assert_eq!(hdr.msg_len as usize, msg.len());
+        }
+
+        // Receive them in one go too, with room for more, which aren't
+        // waited for.
+        let mut bufs = [[0u8; 16]; 4];
+        let mut recv_iovs: Vec<libc::iovec> = bufs
+            .iter_mut()
+            .map(|buf| libc::iovec {
+                iov_base: buf.as_mut_ptr().cast(),
+                iov_len: buf.len(),
+            })
+            .collect();
+        let mut recv_hdrs: Vec<libc::mmsghdr> = recv_iovs
+            .iter_mut()
+            .map(|iov| {
+                let mut hdr: libc::mmsghdr = std::mem::zeroed();
+                hdr.msg_hdr.msg_iov = iov;
+                hdr.msg_hdr.msg_iovlen = 1;
+                hdr
+            })
+            .collect();
+        assert_eq!(
+            libc::recvmmsg(
+                fd,
+                recv_hdrs.as_mut_ptr(),
+                recv_hdrs.len() as _,
+                libc::MSG_WAITFORONE as _,
+                std::ptr::null_mut(),
+            ),
+            3
+        );
+        for ((hdr, buf), msg) in recv_hdrs.iter().zip(&bufs).zip(msgs) {
+            assert_eq!(&buf[..hdr.msg_len as usize], msg);
+        }
+
+        // With nothing left, a nonblocking batch fails outright.
+        assert_errno(
+            libc::recvmmsg(
+                fd,
+                recv_hdrs.as_mut_ptr(),
+                recv_hdrs.len() as _,
+                libc::MSG_DONTWAIT as _,
+                std::ptr::null_mut(),
+            ) as isize,
+            libc::EAGAIN,
+        );
+
+        libc::close(fd);
+    }
+}
+
+register_test!(test_udp_mmsg);
+
+fn set_nonblocking(fd: i32) {
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFL);
+        assert!(flags >= 0);
+        assert_eq!(libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK), 0);
+    }
+}
+
+fn assert_errno(ret: isize, errno: i32) {
+    assert_eq!(ret, -1);
+    assert_eq!(std::io::Error::last_os_error().raw_os_error(), Some(errno));
+}
+
+pub fn test_tcp_nonblocking() {
+    let addrlen = std::mem::size_of::<libc::sockaddr_in>() as u32;
+    let server_addr = loopback_addr(5557);
+    let server_sockaddr = &server_addr as *const libc::sockaddr_in as *const libc::sockaddr;
+
+    unsafe {
+        let server_fd = socket(AF_INET, SOCK_STREAM, 0);
+        assert!(server_fd >= 0);
+        assert_eq!(bind(server_fd, server_sockaddr, addrlen), 0);
+        assert_eq!(listen(server_fd, 4), 0);
+
+        // Nothing's connected yet.
+        set_nonblocking(server_fd);
+        assert_errno(
+            accept(server_fd, std::ptr::null_mut(), std::ptr::null_mut()) as isize,
+            libc::EAGAIN,
+        );
+
+        let client_fd = socket(AF_INET, SOCK_STREAM, 0);
+        assert!(client_fd >= 0);
+        assert_eq!(connect(client_fd, server_sockaddr, addrlen), 0);
+        let accepted_fd = accept(server_fd, std::ptr::null_mut(), std::ptr::null_mut());
+        assert!(accepted_fd >= 0, "accept failed");
+
+        let msg = b"hello";
+        assert_eq!(
+            libc::send(client_fd, msg.as_ptr().cast(), msg.len(), 0),
+            msg.len() as isize
+        );
+        let mut buf = [0u8; 16];
+        assert_eq!(
+            libc::recv(accepted_fd, buf.as_mut_ptr().cast(), buf.len(), 0),
+            msg.len() as isize
+        );
+        assert_eq!(&buf[..msg.len()], msg);
+
+        // Everything sent has been read, so there's nothing to wait for.
+        assert_errno(
+            libc::recv(
+                client_fd,
+                buf.as_mut_ptr().cast(),
+                buf.len(),
+                libc::MSG_DONTWAIT,
+            ),
+            libc::EAGAIN,
+        );
+        set_nonblocking(accepted_fd);
+        assert_errno(
+            libc::read(accepted_fd, buf.as_mut_ptr().cast(), buf.len()),
+            libc::EAGAIN,
+        );
+
+        // A nonblocking connect returns straight away, and a refused one is
+        // reported through SO_ERROR.
+        let pending_fd = socket(AF_INET, SOCK_STREAM, 0);
+        assert!(pending_fd >= 0);
+        set_nonblocking(pending_fd);
+        assert_errno(
+            connect(pending_fd, server_sockaddr, addrlen) as isize,
+            libc::EINPROGRESS,
+        );
+
+        let refused_fd = socket(AF_INET, SOCK_STREAM, 0);
+        assert!(refused_fd >= 0);
+        set_nonblocking(refused_fd);
+        let refused_addr = loopback_addr(5558);
+        assert_errno(
+            connect(
+                refused_fd,
+                &refused_addr as *const libc::sockaddr_in as *const libc::sockaddr,
+                addrlen,
+            ) as isize,
+            libc::EINPROGRESS,
+        );
+        assert_eq!(
+            get_int_sockopt(refused_fd, libc::SOL_SOCKET, libc::SO_ERROR),
+            libc::ECONNREFUSED
+        );
+        assert_eq!(
+            get_int_sockopt(refused_fd, libc::SOL_SOCKET, libc::SO_ERROR),
+            0
+        );
+
+        for fd in [refused_fd, pending_fd, accepted_fd, client_fd, server_fd] {
+            libc::close(fd);
+        }
+    }
+}
+
+register_test!(test_tcp_nonblocking);
+
+fn pollfd(fd: i32, events: i16) -> libc::pollfd {
+    libc::pollfd {
+        fd,
+        events,
+        revents: 0,
+    }
+}
+
+pub fn test_tcp_poll() {
+    let addrlen = std::mem::size_of::<libc::sockaddr_in>() as u32;
+    let server_addr = loopback_addr(5559);
+    let server_sockaddr = &server_addr as *const libc::sockaddr_in as *const libc::sockaddr;
+
+    unsafe {
+        let server_fd = socket(AF_INET, SOCK_STREAM, 0);
+        assert!(server_fd >= 0);
+        assert_eq!(bind(server_fd, server_sockaddr, addrlen), 0);
+        assert_eq!(listen(server_fd, 4), 0);
+
+        let client_fd = socket(AF_INET, SOCK_STREAM, 0);
+        assert!(client_fd >= 0);
+        assert_eq!(connect(client_fd, server_sockaddr, addrlen), 0);
+
+        // The listening socket is readable once there's a connection to
+        // accept.
+        let mut fds = [pollfd(server_fd, libc::POLLIN)];
+        assert_eq!(libc::poll(fds.as_mut_ptr(), 1, 0), 1);
+        assert_eq!(fds[0].revents, libc::POLLIN);
+
+        let accepted_fd = accept(server_fd, std::ptr::null_mut(), std::ptr::null_mut());
+        assert!(accepted_fd >= 0, "accept failed");
+
+        // Nothing's been sent yet, but there's room to send.
+        let mut fds = [
+            pollfd(accepted_fd, libc::POLLIN),
+            pollfd(client_fd, libc::POLLOUT),
+        ];
+        assert_eq!(libc::poll(fds.as_mut_ptr(), 2, 0), 1);
+        assert_eq!(fds[0].revents, 0);
+        assert_eq!(fds[1].revents, libc::POLLOUT);
+
+        let msg = b"hello";
+        assert_eq!(
+            libc::send(client_fd, msg.as_ptr().cast(), msg.len(), 0),
+            msg.len() as isize
+        );
+        let mut fds = [pollfd(accepted_fd, libc::POLLIN)];
+        assert_eq!(libc::poll(fds.as_mut_ptr(), 1, -1), 1);
+        assert_eq!(fds[0].revents, libc::POLLIN);
+
+        // Negative fds are skipped, and ones which aren't open are flagged.
+        let mut fds = [pollfd(-1, libc::POLLIN), pollfd(1000, libc::POLLIN)];
+        assert_eq!(libc::poll(fds.as_mut_ptr(), 2, 0), 1);
+        assert_eq!(fds[0].revents, 0);
+        assert_eq!(fds[1].revents, libc::POLLNVAL);
+
+        for fd in [accepted_fd, client_fd, server_fd] {
+            libc::close(fd);
+        }
+    }
+}
+
+register_test!(test_tcp_poll);
+
+pub fn test_tcp_msg_peek() {
+    let addrlen = std::mem::size_of::<libc::sockaddr_in>() as u32;
+
+    unsafe {
+        let server_fd = socket(AF_INET, SOCK_STREAM, 0);
+        assert!(server_fd >= 0);
+        let server_addr = loopback_addr(5573);
+        assert_eq!(bind_addr(server_fd, &server_addr), 0);
+        assert_eq!(listen(server_fd, 1), 0);
+
+        let client_fd = socket(AF_INET, SOCK_STREAM, 0);
+        assert!(client_fd >= 0);
+        assert_eq!(
+            connect(
+                client_fd,
+                &server_addr as *const libc::sockaddr_in as *const libc::sockaddr,
+                addrlen,
+            ),
+            0
+        );
+        let accepted_fd = accept(server_fd, std::ptr::null_mut(), std::ptr::null_mut());
+        assert!(accepted_fd >= 0);
+
+        let msg = b"hello";
+        assert_eq!(
+            libc::write(client_fd, msg.as_ptr().cast(), msg.len()),
+            msg.len() as isize
+        );
+
+        // Peeking leaves the data to be read again.
+        let mut buf = [0u8; 5];
+        for _ in 0..2 {
+            assert_eq!(
+                libc::recv(accepted_fd, buf.as_mut_ptr().cast(), 3, libc::MSG_PEEK),
+                3
+            );
+            assert_eq!(&buf[..3], b"hel");
+        }
+        assert_eq!(
+            libc::recv(accepted_fd, buf.as_mut_ptr().cast(), buf.len(), 0),
+            msg.len() as isize
+        );
+        assert_eq!(&buf, msg);
+
+        // Once it's all been read, there's nothing left to peek at.
+        assert_errno(
+            libc::recv(
+                accepted_fd,
+                buf.as_mut_ptr().cast(),
+                buf.len(),
+                libc::MSG_PEEK | libc::MSG_DONTWAIT,
+            ),
+            libc::EAGAIN,
+        );
+
+        libc::close(accepted_fd);
+        libc::close(client_fd);
+        libc::close(server_fd);
+    }
+}
+
+register_test!(test_tcp_msg_peek);
+
+pub fn test_tcp_msg_waitall() {
+    let addrlen = std::mem::size_of::<libc::sockaddr_in>() as u32;
+
+    unsafe {
+        let server_fd = socket(AF_INET, SOCK_STREAM, 0);
+        assert!(server_fd >= 0);
+        let server_addr = loopback_addr(5582);
+        assert_eq!(bind_addr(server_fd, &server_addr), 0);
+        assert_eq!(listen(server_fd, 1), 0);
+
+        let client_fd = socket(AF_INET, SOCK_STREAM, 0);
+        assert!(client_fd >= 0);
+        assert_eq!(
+            connect(
+                client_fd,
+                &server_addr as *const libc::sockaddr_in as *const libc::sockaddr,
+                addrlen,
+            ),
+            0
+        );
+        let accepted_fd = accept(server_fd, std::ptr::null_mut(), std::ptr::null_mut());
+        assert!(accepted_fd >= 0);
+
+        // The message arrives in two pieces, a while apart.
+        let writer = std::thread::spawn(move || {
+            for piece in [b"hello ", b"world!"] {
+                assert_eq!(
+                    libc::write(client_fd, piece.as_ptr().cast(), piece.len()),
+                    piece.len() as isize
+                );
+                std::thread::sleep(std::time::Duration::from_millis(50));
+            }
+            libc::close(client_fd);
+        });
+
+        let mut buf = [0u8; 12];
+        assert_eq!(
+            libc::recv(
+                accepted_fd,
+                buf.as_mut_ptr().cast(),
+                buf.len(),
+                libc::MSG_WAITALL
+            ),
+            buf.len() as isize
+        );
+        assert_eq!(&buf, b"hello world!");
+        writer.join().unwrap();
+
+        // The connection ending cuts the read short.
+        assert_eq!(
+            libc::recv(
+                accepted_fd,
+                buf.as_mut_ptr().cast(),
+                buf.len(),
+                libc::MSG_WAITALL
+            ),
+            0
+        );
+
+        libc::close(accepted_fd);
+        libc::close(server_fd);
+    }
+}
+
+register_test!(test_tcp_msg_waitall);
+
+pub fn test_tcp_listen_backlog() {
+    let addrlen = std::mem::size_of::<libc::sockaddr_in>() as u32;
+    let server_addr = loopback_addr(5583);
+    let connect_client = || unsafe {
+        let fd = socket(AF_INET, SOCK_STREAM, 0);
+        assert!(fd >= 0);
+        let ret = connect(
+            fd,
+            &server_addr as *const libc::sockaddr_in as *const libc::sockaddr,
+            addrlen,
+        );
+        (fd, ret)
+    };
+
+    unsafe {
+        let server_fd = socket(AF_INET, SOCK_STREAM, 0);
+        assert!(server_fd >= 0);
+        assert_eq!(bind_addr(server_fd, &server_addr), 0);
+        assert_eq!(listen(server_fd, 2), 0);
+
+        // As many connections as the backlog finish their handshake without
+        // being accepted, but then there's no room for more.
+        let mut clients = Vec::new();
+        for _ in 0..2 {
+            let (fd, ret) = connect_client();
+            assert_eq!(ret, 0);
+            clients.push(fd);
+        }
+        let (refused_fd, ret) = connect_client();
+        assert_errno(ret as isize, libc::ECONNREFUSED);
+        libc::close(refused_fd);
+
+        // Accepting one makes room again.
+        let accepted_fd = accept(server_fd, std::ptr::null_mut(), std::ptr::null_mut());
+        assert!(accepted_fd >= 0);
+        let (fd, ret) = connect_client();
+        assert_eq!(ret, 0);
+        clients.push(fd);
+
+        let mut accepted = vec![accepted_fd];
+        for _ in 0..2 {
+            let fd = accept(server_fd, std::ptr::null_mut(), std::ptr::null_mut());
+            assert!(fd >= 0);
+            accepted.push(fd);
+        }
+
+        for fd in accepted.into_iter().chain(clients) {
+            libc::close(fd);
+        }
+        libc::close(server_fd);
+    }
+}
+
+register_test!(test_tcp_listen_backlog);
+
+fn bind_addr(fd: i32, addr: &libc::sockaddr_in) -> isize {
+    unsafe {
+        bind(
+            fd,
+            addr as *const libc::sockaddr_in as *const libc::sockaddr,
+            std::mem::size_of::<libc::sockaddr_in>() as u32,
+        ) as isize
+    }
+}
+
+pub fn test_bind_conflicts() {
+    unsafe {
+        for ty in [SOCK_STREAM, SOCK_DGRAM] {
+            let first = socket(AF_INET, ty, 0);
+            let second = socket(AF_INET, ty, 0);
+            assert!(first >= 0 && second >= 0);
+
+            assert_eq!(bind_addr(first, &loopback_addr(5560)), 0);
+
+            // Neither the same address nor any address can take the port.
+            assert_errno(bind_addr(second, &loopback_addr(5560)), libc::EADDRINUSE);
+            let mut any = loopback_addr(5560);
+            any.sin_addr.s_addr = libc::INADDR_ANY;
+            assert_errno(bind_addr(second, &any), libc::EADDRINUSE);
+
+            // Closing the first socket gives the port back.
+            libc::close(first);
+            assert_eq!(bind_addr(second, &loopback_addr(5560)), 0);
+
+            // Port 0 picks a free port.
+            let third = socket(AF_INET, ty, 0);
+            assert!(third >= 0);
+            assert_eq!(bind_addr(third, &loopback_addr(0)), 0);
+
+            libc::close(third);
+            libc::close(second);
+        }
+    }
+}
+
+register_test!(test_bind_conflicts);
+
+pub fn test_bind_bad_sockaddr() {
+    unsafe {
+        let fd = socket(AF_INET, SOCK_DGRAM, 0);
+        assert!(fd >= 0);
+        let addr = loopback_addr(5584);
+        let addr_ptr = &addr as *const libc::sockaddr_in as *const libc::sockaddr;
+
+        // Too short for a sockaddr_in, or longer than any address can be.
+        assert_errno(bind(fd, addr_ptr, 8) as isize, libc::EINVAL);
+        let storage = [0u8; 256];
+        assert_errno(
+            bind(fd, storage.as_ptr().cast(), storage.len() as u32) as isize,
+            libc::EINVAL,
+        );
+
+        // A family nobody knows.
+        let mut unknown = addr;
+        unknown.sin_family = 0x7fff;
+        assert_errno(
+            bind(
+                fd,
+                &unknown as *const libc::sockaddr_in as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_in>() as u32,
+            ) as isize,
+            libc::EAFNOSUPPORT,
+        );
+
+        assert_errno(
+            bind(
+                fd,
+                std::ptr::null(),
+                std::mem::size_of::<libc::sockaddr_in>() as u32,
+            ) as isize,
+            libc::EFAULT,
+        );
+
+        assert_eq!(bind_addr(fd, &addr), 0);
+        libc::close(fd);
+    }
+}
+
+register_test!(test_bind_bad_sockaddr);
+
+fn loopback6_addr(port: u16) -> libc::sockaddr_in6 {
+    libc::sockaddr_in6 {
+        sin6_family: libc::AF_INET6 as u16,
+        sin6_port: port.to_be(),
+        sin6_flowinfo: 0,
+        sin6_addr: libc::in6_addr {
+            s6_addr: std::net::Ipv6Addr::LOCALHOST.octets(),
+        },
+        sin6_scope_id: 0,
+    }
+}
+
+fn bind6_addr(fd: i32, addr: &libc::sockaddr_in6) -> isize {
+    unsafe {
+        bind(
+            fd,
+            addr as *const libc::sockaddr_in6 as *const libc::sockaddr,
+            std::mem::size_of::<libc::sockaddr_in6>() as u32,
+        ) as isize
+    }
+}
+
+/// Accepts a connection on `fd`, returning the new socket and its peer.
+fn accept6(fd: i32) -> (i32, libc::sockaddr_in6) {
+    unsafe {
+        let mut peer: libc::sockaddr_in6 = std::mem::zeroed();
+        let mut peerlen = std::mem::size_of::<libc::sockaddr_in6>() as u32;
+        let accepted_fd = accept(
+            fd,
+            &mut peer as *mut libc::sockaddr_in6 as *mut libc::sockaddr,
+            &mut peerlen,
+        );
+        assert!(
+            accepted_fd >= 0,
+            "Failed to accept TCP connection: {}",
+            std::io::Error::last_os_error()
+        );
+        assert_eq!(peer.sin6_family, libc::AF_INET6 as u16);
+        (accepted_fd, peer)
+    }
+}
+
+pub fn test_ipv6_tcp() {
+    unsafe {
+        let server_fd = socket(libc::AF_INET6, SOCK_STREAM, 0);
+        assert!(server_fd >= 0, "Failed to create IPv6 TCP socket");
+        assert_eq!(
+            get_int_sockopt(server_fd, libc::SOL_SOCKET, libc::SO_DOMAIN),
+            libc::AF_INET6
+        );
+        let server_addr = loopback6_addr(5570);
+        assert_eq!(bind6_addr(server_fd, &server_addr), 0);
+        assert_eq!(listen(server_fd, 1), 0);
+
+        let client_fd = socket(libc::AF_INET6, SOCK_STREAM, 0);
+        assert!(client_fd >= 0);
+        assert_eq!(
+            connect(
+                client_fd,
+                &server_addr as *const libc::sockaddr_in6 as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_in6>() as u32,
+            ),
+            0,
+            "Failed to connect over IPv6: {}",
+            std::io::Error::last_os_error()
+        );
+
+        let (accepted_fd, peer) = accept6(server_fd);
+        assert_eq!(peer.sin6_addr.s6_addr, server_addr.sin6_addr.s6_addr);
+        assert_ne!(peer.sin6_port, 0);
+
+        let msg = b"ping";
+        assert_eq!(
+            libc::write(client_fd, msg.as_ptr().cast(), msg.len()),
+            msg.len() as isize
+        );
+        let mut buf = [0u8; 4];
+        assert_eq!(
+            libc::read(accepted_fd, buf.as_mut_ptr().cast(), buf.len()),
+            msg.len() as isize
+        );
+        assert_eq!(&buf, msg);
+
+        libc::close(accepted_fd);
+        libc::close(client_fd);
+        libc::close(server_fd);
+
+        // An IPv6 socket bound on any address hears from IPv4 peers too, which
+        // it sees as v4-mapped addresses.
+        let dual_fd = socket(libc::AF_INET6, SOCK_STREAM, 0);
+        assert!(dual_fd >= 0);
+        assert_eq!(
+            get_int_sockopt(dual_fd, libc::IPPROTO_IPV6, libc::IPV6_V6ONLY),
+            0
+        );
+        let mut any_addr = loopback6_addr(5571);
+        any_addr.sin6_addr = libc::in6_addr { s6_addr: [0; 16] };
+        assert_eq!(bind6_addr(dual_fd, &any_addr), 0);
+        assert_eq!(listen(dual_fd, 1), 0);
+
+        let v4_fd = socket(AF_INET, SOCK_STREAM, 0);
+        assert!(v4_fd >= 0);
+        let v4_addr = loopback_addr(5571);
+        assert_eq!(
+            connect(
+                v4_fd,
+                &v4_addr as *const libc::sockaddr_in as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_in>() as u32,
+            ),
+            0,
+            "Failed to connect to a dual-stack socket: {}",
+            std::io::Error::last_os_error()
+        );
+
+        let (accepted_fd, peer) = accept6(dual_fd);
+        assert_eq!(
+            peer.sin6_addr.s6_addr,
+            std::net::Ipv4Addr::LOCALHOST.to_ipv6_mapped().octets()
+        );
+
+        // The dual-stack socket holds the port for IPv4 as well.
+        let clash_fd = socket(AF_INET, SOCK_STREAM, 0);
+        assert!(clash_fd >= 0);
+        assert_errno(bind_addr(clash_fd, &loopback_addr(5571)), libc::EADDRINUSE);
+
+        libc::close(clash_fd);
+        libc::close(accepted_fd);
+        libc::close(v4_fd);
+        libc::close(dual_fd);
+
+        // An IPV6_V6ONLY socket leaves IPv4 to others.
+        let v6only_fd = socket(libc::AF_INET6, SOCK_STREAM, 0);
+        assert!(v6only_fd >= 0);
+        let one: i32 = 1;
+        assert_eq!(
+            libc::setsockopt(
+                v6only_fd,
+                libc::IPPROTO_IPV6,
+                libc::IPV6_V6ONLY,
+                &one as *const i32 as *const libc::c_void,
+                std::mem::size_of::<i32>() as u32,
+            ),
+            0
+        );
+        any_addr.sin6_port = 5572u16.to_be();
+        assert_eq!(bind6_addr(v6only_fd, &any_addr), 0);
+
+        let v4_fd = socket(AF_INET, SOCK_STREAM, 0);
+        assert!(v4_fd >= 0);
+        assert_eq!(bind_addr(v4_fd, &loopback_addr(5572)), 0);
+
+        libc::close(v4_fd);
+        libc::close(v6only_fd);
+    }
+}
+
+register_test!(test_ipv6_tcp);
+
+pub fn test_icmp_ping() {
+    let addr = loopback_addr(0);
+    let addrlen = std::mem::size_of::<libc::sockaddr_in>() as u32;
+
+    unsafe {
+        let fd = socket(AF_INET, SOCK_DGRAM, libc::IPPROTO_ICMP);
+        assert!(
+            fd >= 0,
+            "Failed to create ping socket: {}",
+            std::io::Error::last_os_error()
+        );
+
+        // An echo request, with sequence number 1 and a payload. The
+        // identifier and checksum are filled in for a ping socket.
+        let mut request = [0u8; 16];
+        request[0] = 8;
+        request[7] = 1;
+        request[8..].copy_from_slice(b"moss-png");
+        assert_eq!(
+            libc::sendto(
+                fd,
+                request.as_ptr().cast(),
+                request.len(),
+                0,
+                &addr as *const libc::sockaddr_in as *const libc::sockaddr,
+                addrlen,
+            ),
+            request.len() as isize,
+            "sendto failed: {}",
+            std::io::Error::last_os_error()
+        );
+
+        // The stack may hand back the request itself before the reply.
+        let mut reply = [0u8; 64];
+        let n = loop {
+            let n = libc::recv(fd, reply.as_mut_ptr().cast(), reply.len(), 0);
+            assert_eq!(n, request.len() as isize, "recv failed");
+            if reply[0] == 0 {
+                break n as usize;
+            }
+        };
+        assert_eq!(reply[7], 1);
+        assert_eq!(&reply[8..n], b"moss-png");
+
+        libc::close(fd);
+    }
+}
+
+register_test!(test_icmp_ping);
+
+pub fn test_packet_capture() {
+    let addrlen = std::mem::size_of::<libc::sockaddr_in>() as u32;
+
+    unsafe {
+        let capture_fd = socket(
+            libc::AF_PACKET,
+            SOCK_DGRAM,
+            (libc::ETH_P_ALL as u16).to_be() as i32,
+        );
+        assert!(
+            capture_fd >= 0,
+            "Failed to create packet socket: {}",
+            std::io::Error::last_os_error()
+        );
+
+        // Only watch loopback, which is always interface 1.
+        let mut sll: libc::sockaddr_ll = std::mem::zeroed();
+        sll.sll_family = libc::AF_PACKET as u16;
+        sll.sll_ifindex = 1;
+        assert_eq!(
+            bind(
+                capture_fd,
+                &sll as *const libc::sockaddr_ll as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_ll>() as u32,
+            ),
+            0
+        );
+
+        let udp_fd = socket(AF_INET, SOCK_DGRAM, 0);
+        assert!(udp_fd >= 0);
+        let dest = loopback_addr(5561);
+        let msg = b"captured";
+        assert_eq!(
+            libc::sendto(
+                udp_fd,
+                msg.as_ptr().cast(),
+                msg.len(),
+                0,
+                &dest as *const libc::sockaddr_in as *const libc::sockaddr,
+                addrlen,
+            ),
+            msg.len() as isize
+        );
+
+        // Look for the datagram among whatever else loopback has carried: an
+        // IPv4 packet, without a link-layer header, ending in the message.
+        let mut frame = [0u8; 256];
+        loop {
+            let mut from: libc::sockaddr_ll = std::mem::zeroed();
+            let mut fromlen = std::mem::size_of::<libc::sockaddr_ll>() as u32;
+            let n = libc::recvfrom(
+                capture_fd,
+                frame.as_mut_ptr().cast(),
+                frame.len(),
+                0,
+                &mut from as *mut libc::sockaddr_ll as *mut libc::sockaddr,
+                &mut fromlen,
+            );
+            assert!(n > 0, "recvfrom failed");
+            assert_eq!(from.sll_ifindex, 1);
+
+            let packet = &frame[..n as usize];
+            if from.sll_protocol == (libc::ETH_P_IP as u16).to_be() && packet.ends_with(msg) {
+                assert_eq!(packet[0] >> 4, 4);
+                assert_eq!(packet[9], libc::IPPROTO_UDP as u8);
+                break;
+            }
+        }
+
+        libc::close(udp_fd);
+        libc::close(capture_fd);
+    }
+}
+
+register_test!(test_packet_capture);
+
+pub fn test_netlink_route() {
+    unsafe {
+        let fd = socket(libc::AF_NETLINK, libc::SOCK_RAW, libc::NETLINK_ROUTE);
+        assert!(
+            fd >= 0,
+            "Failed to create netlink socket: {}",
+            std::io::Error::last_os_error()
+        );
+
+        let mut snl: libc::sockaddr_nl = std::mem::zeroed();
+        snl.nl_family = libc::AF_NETLINK as u16;
+        let snl_len = std::mem::size_of::<libc::sockaddr_nl>() as u32;
+        assert_eq!(
+            bind(
+                fd,
+                &snl as *const libc::sockaddr_nl as *const libc::sockaddr,
+                snl_len,
+            ),
+            0
+        );
+
+        // Binding to port zero picks one.
+        let mut bound: libc::sockaddr_nl = std::mem::zeroed();
+        let mut bound_len = snl_len;
+        assert_eq!(
+            libc::getsockname(
+                fd,
+                &mut bound as *mut libc::sockaddr_nl as *mut libc::sockaddr,
+                &mut bound_len,
+            ),
+            0
+        );
+        assert_eq!(bound.nl_family, libc::AF_NETLINK as u16);
+        assert_ne!(bound.nl_pid, 0);
+
+        // An RTM_GETLINK dump: a header followed by an empty ifinfomsg.
+        let mut request = [0u8; 32];
+        request[0..4].copy_from_slice(&32u32.to_ne_bytes());
+        request[4..6].copy_from_slice(&libc::RTM_GETLINK.to_ne_bytes());
+        request[6..8]
+            .copy_from_slice(&((libc::NLM_F_REQUEST | libc::NLM_F_DUMP) as u16).to_ne_bytes());
+        request[8..12].copy_from_slice(&1u32.to_ne_bytes());
+        assert_eq!(
+            libc::send(fd, request.as_ptr().cast(), request.len(), 0),
+            request.len() as isize
+        );
+
+        let mut reply = [0u8; 4096];
+        let n = libc::recv(fd, reply.as_mut_ptr().cast(), reply.len(), 0);
+        assert!(n > 0, "recv failed");
+
+        // Walk the messages, looking for loopback's name and the end of the
+        // dump.
+        let mut found_lo = false;
+        let mut rest = &reply[..n as usize];
+        loop {
+            assert!(rest.len() >= 16, "dump ended without NLMSG_DONE");
+            let len = u32::from_ne_bytes(rest[0..4].try_into().unwrap()) as usize;
+            let ty = u16::from_ne_bytes(rest[4..6].try_into().unwrap());
+            assert_eq!(u32::from_ne_bytes(rest[8..12].try_into().unwrap()), 1);
+            assert_eq!(
+                u32::from_ne_bytes(rest[12..16].try_into().unwrap()),
+                bound.nl_pid
+            );
+
+            if ty == libc::NLMSG_DONE as u16 {
+                break;
+            }
+            assert_eq!(ty, libc::RTM_NEWLINK);
+
+            let mut attrs = &rest[32..len];
+            while attrs.len() >= 4 {
+                let attr_len = u16::from_ne_bytes([attrs[0], attrs[1]]) as usize;
+                let attr_ty = u16::from_ne_bytes([attrs[2], attrs[3]]);
+                if attr_ty == libc::IFLA_IFNAME && &attrs[4..attr_len] == b"lo\0" {
+                    found_lo = true;
+                }
+                attrs = &attrs[((attr_len + 3) & !3).min(attrs.len())..];
+            }
+
+            rest = &rest[(len + 3) & !3..];
+        }
+        assert!(found_lo, "loopback wasn't listed");
+
+        // A request of a type the kernel doesn't know is answered with an
+        // error.
+        request[4..6].copy_from_slice(&0x7fffu16.to_ne_bytes());
+        request[6..8].copy_from_slice(&(libc::NLM_F_REQUEST as u16).to_ne_bytes());
+        assert_eq!(
+            libc::send(fd, request.as_ptr().cast(), request.len(), 0),
+            request.len() as isize
+        );
+        let n = libc::recv(fd, reply.as_mut_ptr().cast(), reply.len(), 0);
+        assert!(n >= 20, "recv failed");
+        assert_eq!(
+            u16::from_ne_bytes(reply[4..6].try_into().unwrap()),
+            libc::NLMSG_ERROR as u16
+        );
+        assert_eq!(
+            i32::from_ne_bytes(reply[16..20].try_into().unwrap()),
+            -libc::EOPNOTSUPP
+        );
+
+        libc::close(fd);
+    }
+}
+
+register_test!(test_netlink_route);
+
+/// Makes a `struct ifreq` naming `name`.
+fn ifreq(name: &str) -> [u8; 40] {
+    let mut req = [0u8; 40];
+    req[..name.len()].copy_from_slice(name.as_bytes());
+    req
+}
+
+pub fn test_interface_ioctls() {
+    unsafe {
+        let fd = socket(AF_INET, SOCK_DGRAM, 0);
+        assert!(fd >= 0);
+
+        let mut req = ifreq("lo");
+        assert_eq!(
+            libc::ioctl(fd, libc::SIOCGIFINDEX as _, req.as_mut_ptr()),
+            0
+        );
+        assert_eq!(i32::from_ne_bytes(req[16..20].try_into().unwrap()), 1);
+
+        let mut req = ifreq("lo");
+        assert_eq!(libc::ioctl(fd, libc::SIOCGIFADDR as _, req.as_mut_ptr()), 0);
+        assert_eq!(u16::from_ne_bytes([req[16], req[17]]), AF_INET as u16);
+        assert_eq!(req[20..24], [127, 0, 0, 1]);
+
+        let mut req = ifreq("lo");
+        assert_eq!(
+            libc::ioctl(fd, libc::SIOCGIFNETMASK as _, req.as_mut_ptr()),
+            0
+        );
+        assert_eq!(req[20..24], [255, 0, 0, 0]);
+
+        let mut req = ifreq("lo");
+        assert_eq!(
+            libc::ioctl(fd, libc::SIOCGIFFLAGS as _, req.as_mut_ptr()),
+            0
+        );
+        let flags = i16::from_ne_bytes([req[16], req[17]]) as i32;
+        assert_eq!(
+            flags & (libc::IFF_UP | libc::IFF_LOOPBACK),
+            libc::IFF_UP | libc::IFF_LOOPBACK
+        );
+
+        let mut req = ifreq("lo");
+        assert_eq!(libc::ioctl(fd, libc::SIOCGIFMTU as _, req.as_mut_ptr()), 0);
+        assert!(i32::from_ne_bytes(req[16..20].try_into().unwrap()) >= 68);
+
+        // Looking the name up from the index.
+        let mut req = [0u8; 40];
+        req[16..20].copy_from_slice(&1i32.to_ne_bytes());
+        assert_eq!(libc::ioctl(fd, libc::SIOCGIFNAME as _, req.as_mut_ptr()), 0);
+        assert_eq!(&req[..3], b"lo\0");
+
+        let mut req = ifreq("nonexistent0");
+        assert_eq!(
+            libc::ioctl(fd, libc::SIOCGIFINDEX as _, req.as_mut_ptr()),
+            -1
+        );
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::ENODEV)
+        );
+
+        libc::close(fd);
+    }
+}
+
+register_test!(test_interface_ioctls);
+
+pub fn test_proc_net() {
+    let dev = std::fs::read_to_string("/proc/net/dev").expect("read /proc/net/dev");
+    assert!(dev.starts_with("Inter-|"));
+    assert!(dev.lines().any(|line| line.trim_start().starts_with("lo:")));
+
+    unsafe {
+        let listen_fd = socket(AF_INET, SOCK_STREAM, 0);
+        assert!(listen_fd >= 0);
+        assert_eq!(bind_addr(listen_fd, &loopback_addr(5580)), 0);
+        assert_eq!(listen(listen_fd, 1), 0);
+
+        // 127.0.0.1:5580, listening.
+        let local = format!("{:08X}:{:04X}", u32::from_ne_bytes([127, 0, 0, 1]), 5580);
+        let tcp = std::fs::read_to_string("/proc/net/tcp").expect("read /proc/net/tcp");
+        assert!(
+            tcp.lines().skip(1).any(|line| {
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                fields[1] == local && fields[3] == "0A"
+            }),
+            "listener missing from /proc/net/tcp:\n{tcp}"
+        );
+
+        let udp_fd = socket(AF_INET, SOCK_DGRAM, 0);
+        assert!(udp_fd >= 0);
+        assert_eq!(bind_addr(udp_fd, &loopback_addr(5581)), 0);
+
+        let local = format!("{:08X}:{:04X}", u32::from_ne_bytes([127, 0, 0, 1]), 5581);
+        let udp = std::fs::read_to_string("/proc/net/udp").expect("read /proc/net/udp");
+        assert!(
+            udp.lines()
+                .skip(1)
+                .any(|line| line.split_whitespace().nth(1) == Some(local.as_str())),
+            "socket missing from /proc/net/udp:\n{udp}"
+        );
+
+        libc::close(udp_fd);
+        libc::close(listen_fd);
+    }
+}
+
+register_test!(test_proc_net);
+
+pub fn test_sysfs_net() {
+    let dir = "/sys/class/net/lo";
+    let read = |name: &str| {
+        std::fs::read_to_string(format!("{dir}/{name}"))
+            .unwrap_or_else(|e| panic!("read {dir}/{name}: {e}"))
+    };
+
+    assert!(
+        std::fs::read_dir("/sys/class/net")
+            .expect("read /sys/class/net")
+            .any(|entry| entry.unwrap().file_name() == "lo")
+    );
+    assert_eq!(read("ifindex"), "1\n");
+    assert_eq!(read("address"), "00:00:00:00:00:00\n");
+    assert_eq!(read("type"), "772\n");
+
+    let mtu = read("mtu");
+    std::fs::write(format!("{dir}/mtu"), "1500\n").expect("write mtu");
+    assert_eq!(read("mtu"), "1500\n");
+    std::fs::write(format!("{dir}/mtu"), &mtu).expect("restore mtu");
+    assert_eq!(read("mtu"), mtu);
+
+    assert!(std::fs::write(format!("{dir}/mtu"), "lots").is_err());
+    assert!(std::fs::write(format!("{dir}/ifindex"), "2").is_err());
+}
+
+register_test!(test_sysfs_net);
+
+fn get_int_sockopt(fd: i32, level: i32, optname: i32) -> i32 {
+    let mut value: i32 = -1;
+    let mut len = std::mem::size_of::<i32>() as u32;
+    let ret = unsafe {
+        libc::getsockopt(
+            fd,
+            level,
+            optname,
+            &mut value as *mut i32 as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    assert_eq!(
+        ret,
+        0,
+        "getsockopt({level}, {optname}) failed: {}",
+        std::io::Error::last_os_error()
+    );
+    assert_eq!(len, std::mem::size_of::<i32>() as u32);
+    value
+}
+
+pub fn test_sockopts() {
+    unsafe {
+        let tcp_fd = socket(AF_INET, SOCK_STREAM, 0);
+        let udp_fd = socket(AF_INET, SOCK_DGRAM, 0);
+        let unix_fd = socket(AF_UNIX, SOCK_DGRAM, 0);
+        assert!(tcp_fd >= 0 && udp_fd >= 0 && unix_fd >= 0);
+
+        assert_eq!(
+            get_int_sockopt(tcp_fd, libc::SOL_SOCKET, libc::SO_TYPE),
+            SOCK_STREAM
+        );
+        assert_eq!(
+            get_int_sockopt(udp_fd, libc::SOL_SOCKET, libc::SO_TYPE),
+            SOCK_DGRAM
+        );
+        assert_eq!(
+            get_int_sockopt(unix_fd, libc::SOL_SOCKET, libc::SO_TYPE),
+            SOCK_DGRAM
+        );
+        assert_eq!(
+            get_int_sockopt(unix_fd, libc::SOL_SOCKET, libc::SO_DOMAIN),
+            AF_UNIX
+        );
+        assert_eq!(get_int_sockopt(tcp_fd, libc::SOL_SOCKET, libc::SO_ERROR), 0);
+
+        let one: i32 = 1;
+        for (level, optname) in [
+            (libc::SOL_SOCKET, libc::SO_REUSEADDR),
+            (libc::IPPROTO_TCP, libc::TCP_NODELAY),
+        ] {
+            assert_eq!(get_int_sockopt(tcp_fd, level, optname), 0);
+            assert_eq!(
+                libc::setsockopt(
+                    tcp_fd,
+                    level,
+                    optname,
+                    &one as *const i32 as *const libc::c_void,
+                    std::mem::size_of::<i32>() as u32,
+                ),
+                0
+            );
+            assert_eq!(get_int_sockopt(tcp_fd, level, optname), 1);
+        }
+
+        // SO_TYPE can't be set, and there's no option 12345.
+        for (level, optname) in [(libc::SOL_SOCKET, libc::SO_TYPE), (libc::SOL_SOCKET, 12345)] {
+            assert_eq!(
+                libc::setsockopt(
+                    tcp_fd,
+                    level,
+                    optname,
+                    &one as *const i32 as *const libc::c_void,
+                    std::mem::size_of::<i32>() as u32,
+                ),
+                -1
+            );
+            assert_eq!(
+                std::io::Error::last_os_error().raw_os_error(),
+                Some(libc::ENOPROTOOPT)
+            );
+        }
+
+        // Buffers can be resized before the socket's used.
+        let size: i32 = 8192;
+        for fd in [tcp_fd, udp_fd] {
+            for optname in [libc::SO_SNDBUF, libc::SO_RCVBUF] {
+                assert_eq!(
+                    libc::setsockopt(
+                        fd,
+                        libc::SOL_SOCKET,
+                        optname,
+                        &size as *const i32 as *const libc::c_void,
+                        std::mem::size_of::<i32>() as u32,
+                    ),
+                    0
+                );
+                assert_eq!(get_int_sockopt(fd, libc::SOL_SOCKET, optname), size);
+            }
+        }
+
+        libc::close(unix_fd);
+        libc::close(udp_fd);
+        libc::close(tcp_fd);
+    }
+}
+
+register_test!(test_sockopts);
+
+fn set_int_sockopt(fd: i32, level: i32, optname: i32, value: i32) {
+    let ret = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            optname,
+            &value as *const i32 as *const libc::c_void,
+            std::mem::size_of::<i32>() as u32,
+        )
+    };
+    assert_eq!(
+        ret,
+        0,
+        "setsockopt({level}, {optname}) failed: {}",
+        std::io::Error::last_os_error()
+    );
+}
+
+/// Makes a TCP socket with `SO_REUSEADDR` and `SO_REUSEPORT` set as given.
+fn reuse_socket(reuse_addr: bool, reuse_port: bool) -> i32 {
+    let fd = unsafe { socket(AF_INET, SOCK_STREAM, 0) };
+    assert!(fd >= 0);
+    set_int_sockopt(fd, libc::SOL_SOCKET, libc::SO_REUSEADDR, reuse_addr as i32);
+    set_int_sockopt(fd, libc::SOL_SOCKET, libc::SO_REUSEPORT, reuse_port as i32);
+    fd
+}
+
+pub fn test_tcp_reuse_addr() {
+    let addrlen = std::mem::size_of::<libc::sockaddr_in>() as u32;
+
+    unsafe {
+        let server_fd = reuse_socket(true, false);
+        let server_addr = loopback_addr(5577);
+        assert_eq!(bind_addr(server_fd, &server_addr), 0);
+
+        // Another socket with SO_REUSEADDR can bind alongside, but not listen
+        // while the first one is.
+        let second_fd = reuse_socket(true, false);
+        assert_eq!(bind_addr(second_fd, &server_addr), 0);
+        assert_eq!(listen(server_fd, 1), 0);
+        assert_errno(listen(second_fd, 1) as isize, libc::EADDRINUSE);
+        libc::close(second_fd);
+
+        let client_fd = socket(AF_INET, SOCK_STREAM, 0);
+        assert!(client_fd >= 0);
+        assert_eq!(
+            connect(
+                client_fd,
+                &server_addr as *const libc::sockaddr_in as *const libc::sockaddr,
+                addrlen,
+            ),
+            0
+        );
+        let accepted_fd = accept(server_fd, std::ptr::null_mut(), std::ptr::null_mut());
+        assert!(accepted_fd >= 0, "accept failed");
+
+        // The server closes first, so its end of the connection lingers on the
+        // port after the listener's gone.
+        libc::close(accepted_fd);
+        libc::close(server_fd);
+
+        let plain_fd = reuse_socket(false, false);
+        assert_errno(bind_addr(plain_fd, &server_addr), libc::EADDRINUSE);
+        libc::close(plain_fd);
+
+        let restarted_fd = reuse_socket(true, false);
+        assert_eq!(bind_addr(restarted_fd, &server_addr), 0);
+        assert_eq!(listen(restarted_fd, 1), 0);
+
+        libc::close(restarted_fd);
+        libc::close(client_fd);
+    }
+}
+
+register_test!(test_tcp_reuse_addr);
+
+pub fn test_tcp_reuse_port() {
+    unsafe {
+        let addr = loopback_addr(5578);
+
+        // Sockets which all set SO_REUSEPORT can listen on the same port.
+        let first_fd = reuse_socket(false, true);
+        assert_eq!(bind_addr(first_fd, &addr), 0);
+        assert_eq!(listen(first_fd, 1), 0);
+        let second_fd = reuse_socket(false, true);
+        assert_eq!(bind_addr(second_fd, &addr), 0);
+        assert_eq!(listen(second_fd, 1), 0);
+        assert_eq!(
+            get_int_sockopt(second_fd, libc::SOL_SOCKET, libc::SO_REUSEPORT),
+            1
+        );
+
+        // One which doesn't can't join them.
+        let plain_fd = reuse_socket(false, false);
+        assert_errno(bind_addr(plain_fd, &addr), libc::EADDRINUSE);
+
+        libc::close(plain_fd);
+        libc::close(second_fd);
+        libc::close(first_fd);
+    }
+}
+
+register_test!(test_tcp_reuse_port);
+
+pub fn test_tcp_options() {
+    let addrlen = std::mem::size_of::<libc::sockaddr_in>() as u32;
+
+    unsafe {
+        let server_fd = socket(AF_INET, SOCK_STREAM, 0);
+        assert!(server_fd >= 0);
+
+        // The keepalive options start at Linux's defaults.
+        assert_eq!(
+            get_int_sockopt(server_fd, libc::IPPROTO_TCP, libc::TCP_KEEPIDLE),
+            7200
+        );
+        assert_eq!(
+            get_int_sockopt(server_fd, libc::IPPROTO_TCP, libc::TCP_KEEPINTVL),
+            75
+        );
+        assert_eq!(
+            get_int_sockopt(server_fd, libc::IPPROTO_TCP, libc::TCP_KEEPCNT),
+            9
+        );
+        assert_eq!(
+            get_int_sockopt(server_fd, libc::IPPROTO_TCP, libc::TCP_USER_TIMEOUT),
+            0
+        );
+
+        for (optname, value) in [
+            (libc::TCP_NODELAY, 1),
+            (libc::TCP_KEEPIDLE, 60),
+            (libc::TCP_KEEPINTVL, 10),
+            (libc::TCP_KEEPCNT, 3),
+            (libc::TCP_QUICKACK, 1),
+            (libc::TCP_USER_TIMEOUT, 30000),
+        ] {
+            set_int_sockopt(server_fd, libc::IPPROTO_TCP, optname, value);
+            assert_eq!(
+                get_int_sockopt(server_fd, libc::IPPROTO_TCP, optname),
+                value
+            );
+        }
+        set_int_sockopt(server_fd, libc::SOL_SOCKET, libc::SO_KEEPALIVE, 1);
+
+        // Out of range values are refused.
+        for (optname, value) in [
+            (libc::TCP_KEEPIDLE, 0),
+            (libc::TCP_KEEPINTVL, 32768),
+            (libc::TCP_KEEPCNT, 128),
+            (libc::TCP_USER_TIMEOUT, -1),
+        ] {
+            assert_errno(
+                libc::setsockopt(
+                    server_fd,
+                    libc::IPPROTO_TCP,
+                    optname,
+                    &value as *const i32 as *const libc::c_void,
+                    std::mem::size_of::<i32>() as u32,
+                ) as isize,
+                libc::EINVAL,
+            );
+        }
+
+        let server_addr = loopback_addr(5579);
+        assert_eq!(bind_addr(server_fd, &server_addr), 0);
+        assert_eq!(listen(server_fd, 1), 0);
+
+        let client_fd = socket(AF_INET, SOCK_STREAM, 0);
+        assert!(client_fd >= 0);
+        assert_eq!(
+            connect(
+                client_fd,
+                &server_addr as *const libc::sockaddr_in as *const libc::sockaddr,
+                addrlen,
+            ),
+            0
+        );
+        let accepted_fd = accept(server_fd, std::ptr::null_mut(), std::ptr::null_mut());
+        assert!(accepted_fd >= 0, "accept failed");
+
+        // The accepted socket takes after the listener.
+        for (level, optname, value) in [
+            (libc::SOL_SOCKET, libc::SO_KEEPALIVE, 1),
+            (libc::IPPROTO_TCP, libc::TCP_NODELAY, 1),
+            (libc::IPPROTO_TCP, libc::TCP_KEEPIDLE, 60),
+            (libc::IPPROTO_TCP, libc::TCP_KEEPCNT, 3),
+            (libc::IPPROTO_TCP, libc::TCP_USER_TIMEOUT, 30000),
+        ] {
+            assert_eq!(get_int_sockopt(accepted_fd, level, optname), value);
+        }
+
+        libc::close(accepted_fd);
+        libc::close(client_fd);
+        libc::close(server_fd);
+    }
+}
+
+register_test!(test_tcp_options);
+
+pub fn test_socket_timeouts() {
+    unsafe {
+        let fd = socket(AF_INET, SOCK_DGRAM, 0);
+        assert!(fd >= 0);
+        assert_eq!(bind_addr(fd, &loopback_addr(5574)), 0);
+
+        let timeout = libc::timeval {
+            tv_sec: 0,
+            tv_usec: 100_000,
+        };
+        assert_eq!(
+            libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_RCVTIMEO,
+                &timeout as *const libc::timeval as *const libc::c_void,
+                std::mem::size_of::<libc::timeval>() as u32,
+            ),
+            0
+        );
+
+        let mut read_back: libc::timeval = std::mem::zeroed();
+        let mut len = std::mem::size_of::<libc::timeval>() as u32;
+        assert_eq!(
+            libc::getsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_RCVTIMEO,
+                &mut read_back as *mut libc::timeval as *mut libc::c_void,
+                &mut len,
+            ),
+            0
+        );
+        assert_eq!(len, std::mem::size_of::<libc::timeval>() as u32);
+        assert_eq!((read_back.tv_sec, read_back.tv_usec), (0, 100_000));
+
+        // Nothing's coming, so the receive gives up once the timeout passes.
+        let start = std::time::Instant::now();
+        let mut buf = [0u8; 4];
+        assert_errno(
+            libc::recv(fd, buf.as_mut_ptr().cast(), buf.len(), 0),
+            libc::EAGAIN,
+        );
+        assert!(start.elapsed() >= std::time::Duration::from_millis(100));
+
+        libc::close(fd);
+    }
+}
+
+register_test!(test_socket_timeouts);
+
 pub fn test_unix_socket_creation() {
     unsafe {
         let sockfd = socket(AF_UNIX, SOCK_STREAM, 0);
@@ -70,6 +1907,187 @@ pub fn test_unix_socket_basic_functions() {
 
 register_test!(test_unix_socket_basic_functions);
 
+pub fn test_socket_type_flags() {
+    unsafe {
+        let fd = socket(
+            AF_INET,
+            SOCK_DGRAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+            0,
+        );
+        assert!(fd >= 0);
+        assert_ne!(libc::fcntl(fd, libc::F_GETFL) & libc::O_NONBLOCK, 0);
+        assert_ne!(libc::fcntl(fd, libc::F_GETFD) & libc::FD_CLOEXEC, 0);
+        let mut buf = [0u8; 4];
+        assert_errno(
+            libc::recv(fd, buf.as_mut_ptr().cast(), buf.len(), 0),
+            libc::EAGAIN,
+        );
+        libc::close(fd);
+
+        let fd = socket(AF_INET, SOCK_DGRAM, 0);
+        assert!(fd >= 0);
+        assert_eq!(libc::fcntl(fd, libc::F_GETFL) & libc::O_NONBLOCK, 0);
+        assert_eq!(libc::fcntl(fd, libc::F_GETFD) & libc::FD_CLOEXEC, 0);
+        libc::close(fd);
+
+        // Other bits above the type aren't flags.
+        assert_errno(
+            socket(AF_INET, SOCK_DGRAM | 0x100, 0) as isize,
+            libc::EINVAL,
+        );
+    }
+}
+
+register_test!(test_socket_type_flags);
+
+pub fn test_unix_socketpair() {
+    unsafe {
+        let mut sv = [-1; 2];
+        assert_eq!(
+            libc::socketpair(
+                AF_UNIX,
+                SOCK_STREAM | libc::SOCK_CLOEXEC,
+                0,
+                sv.as_mut_ptr()
+            ),
+            0
+        );
+        for fd in sv {
+            assert_ne!(libc::fcntl(fd, libc::F_GETFD) & libc::FD_CLOEXEC, 0);
+        }
+
+        // Each end reads what the other writes.
+        let mut buf = [0u8; 8];
+        for (from, to) in [(sv[0], sv[1]), (sv[1], sv[0])] {
+            assert_eq!(libc::write(from, b"hi".as_ptr().cast(), 2), 2);
+            assert_eq!(libc::read(to, buf.as_mut_ptr().cast(), buf.len()), 2);
+            assert_eq!(&buf[..2], b"hi");
+        }
+        libc::close(sv[0]);
+        libc::close(sv[1]);
+
+        // Datagram pairs keep message boundaries.
+        assert_eq!(
+            libc::socketpair(
+                AF_UNIX,
+                SOCK_DGRAM | libc::SOCK_NONBLOCK,
+                0,
+                sv.as_mut_ptr()
+            ),
+            0
+        );
+        assert_eq!(libc::send(sv[0], b"one".as_ptr().cast(), 3, 0), 3);
+        assert_eq!(libc::send(sv[0], b"two".as_ptr().cast(), 3, 0), 3);
+        assert_eq!(libc::recv(sv[1], buf.as_mut_ptr().cast(), buf.len(), 0), 3);
+        assert_eq!(&buf[..3], b"one");
+        assert_eq!(libc::recv(sv[1], buf.as_mut_ptr().cast(), buf.len(), 0), 3);
+        assert_eq!(&buf[..3], b"two");
+        assert_errno(
+            libc::recv(sv[1], buf.as_mut_ptr().cast(), buf.len(), 0),
+            libc::EAGAIN,
+        );
+        libc::close(sv[0]);
+        libc::close(sv[1]);
+
+        assert_errno(
+            libc::socketpair(AF_INET, SOCK_STREAM, 0, sv.as_mut_ptr()) as isize,
+            libc::EOPNOTSUPP,
+        );
+    }
+}
+
+register_test!(test_unix_socketpair);
+
+pub fn test_unix_scm_rights() {
+    let path = "/tmp/test_scm_rights";
+    let mut sockaddr: libc::sockaddr_un = unsafe { std::mem::zeroed() };
+    sockaddr.sun_family = AF_UNIX as u16;
+    for (dst, &b) in sockaddr.sun_path.iter_mut().zip(path.as_bytes()) {
+        *dst = b as _;
+    }
+    let addrlen = std::mem::size_of::<libc::sockaddr_un>() as u32;
+    let addr = &sockaddr as *const libc::sockaddr_un as *const libc::sockaddr;
+
+    unsafe {
+        let server_fd = socket(AF_UNIX, SOCK_STREAM, 0);
+        assert!(server_fd >= 0);
+        assert_eq!(bind(server_fd, addr, addrlen), 0);
+        assert_eq!(listen(server_fd, 1), 0);
+
+        let client_fd = socket(AF_UNIX, SOCK_STREAM, 0);
+        assert!(client_fd >= 0);
+        assert_eq!(connect(client_fd, addr, addrlen), 0);
+        let accepted_fd = accept(server_fd, std::ptr::null_mut(), std::ptr::null_mut());
+        assert!(accepted_fd >= 0, "accept failed");
+
+        let mut pipe_fds = [0; 2];
+        assert_eq!(libc::pipe(pipe_fds.as_mut_ptr()), 0);
+        let contents = b"passed";
+        assert_eq!(
+            libc::write(pipe_fds[1], contents.as_ptr().cast(), contents.len()),
+            contents.len() as isize
+        );
+
+        // Send the pipe's read end along with a byte of data.
+        let mut data = *b"x";
+        let mut iov = libc::iovec {
+            iov_base: data.as_mut_ptr().cast(),
+            iov_len: data.len(),
+        };
+        let space = libc::CMSG_SPACE(size_of::<i32>() as u32) as usize;
+        let mut control = vec![0u8; space];
+        let mut msg: libc::msghdr = std::mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = space as _;
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(size_of::<i32>() as u32) as _;
+        std::ptr::write_unaligned(libc::CMSG_DATA(cmsg).cast::<i32>(), pipe_fds[0]);
+        assert_eq!(libc::sendmsg(client_fd, &msg, 0), 1);
+
+        // The receiver's copy still works once the sender's is closed.
+        libc::close(pipe_fds[0]);
+
+        let mut buf = [0u8; 4];
+        let mut iov = libc::iovec {
+            iov_base: buf.as_mut_ptr().cast(),
+            iov_len: buf.len(),
+        };
+        let mut control = vec![0u8; space];
+        let mut msg: libc::msghdr = std::mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = space as _;
+        assert_eq!(libc::recvmsg(accepted_fd, &mut msg, 0), 1);
+        assert_eq!(buf[0], b'x');
+        assert_eq!(msg.msg_flags & libc::MSG_CTRUNC, 0);
+
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        assert!(!cmsg.is_null(), "no control message received");
+        assert_eq!((*cmsg).cmsg_level, libc::SOL_SOCKET);
+        assert_eq!((*cmsg).cmsg_type, libc::SCM_RIGHTS);
+        let received_fd = std::ptr::read_unaligned(libc::CMSG_DATA(cmsg).cast::<i32>());
+        assert!(received_fd >= 0);
+
+        let mut buf = [0u8; 16];
+        assert_eq!(
+            libc::read(received_fd, buf.as_mut_ptr().cast(), buf.len()),
+            contents.len() as isize
+        );
+        assert_eq!(&buf[..contents.len()], contents);
+
+        for fd in [received_fd, pipe_fds[1], accepted_fd, client_fd, server_fd] {
+            libc::close(fd);
+        }
+    }
+}
+
+register_test!(test_unix_scm_rights);
+
 pub fn test_unix_socket_fork_msg_passing() {
     use std::ptr;
 